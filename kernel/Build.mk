@@ -54,4 +54,8 @@ kernel/doc: sandbox_setup
 	cd kernel && $(BWRAP) cargo doc --release
 
 .PHONY: kernel/localtests
-kernel/localtests:
+kernel/localtests: sandbox_setup
+	mkdir -p build/kernel/register_gen
+	$(BWRAP) rustc --edition 2018 --test kernel/h1/register_gen.rs \
+		-o build/kernel/register_gen/tests
+	build/kernel/register_gen/tests
@@ -42,9 +42,13 @@ use kernel::mpu::MPU;
 
 use h1::crypto::dcrypto::Dcrypto;
 use h1::hil::flash::Flash;
+use h1::hil::fuse::Fuse;
+use h1::hil::keystore::KeyStore;
 use h1::nvcounter::{FlashCounter,NvCounter};
 use h1::timels::Timels;
-use h1::usb::{Descriptor, StringDescriptor};
+use h1::usb::{StringDescriptor, StringDescriptors};
+use h1::usb::constants::{STRING_BLAH, STRING_BOARD, STRING_INTERFACE1, STRING_INTERFACE2,
+                         STRING_PLATFORM, STRING_VENDOR};
 
 // State for loading apps
 const NUM_PROCS: usize = 1;
@@ -52,6 +56,69 @@ const NUM_PROCS: usize = 1;
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
 
+// How the kernel responds when a process faults, for apps that need a
+// different response than FAULT_RESPONSE.
+const APP_FAULT_RESPONSES: &[h1::process_loader::AppFaultResponse] = &[];
+
+// Interrupts on one NVIC line within IRQ_STATS_WINDOW_MS that are logged as
+// an interrupt storm. USB SOF alone raises 1000 interrupts per second.
+const IRQ_STATS_WINDOW_MS: u32 = 1000;
+const IRQ_STORM_THRESHOLD: u16 = 5000;
+
+// Set to true to prefix every console line with the time since boot from
+// the start. Apps can also turn this on and off at runtime.
+const CONSOLE_TIMESTAMPS: bool = false;
+
+// AES key slot holding the personality storage key, usable by apps but
+// not readable.
+const PERSONALITY_KEY_SLOT: usize = 0;
+
+// How often the entropy pool reseeds its DRBG from the TRNG, and whether it
+// keeps serving DRBG output when the TRNG stops delivering.
+const ENTROPY_RESEED_POLICY: h1::entropy_pool::ReseedPolicy =
+    h1::entropy_pool::DEFAULT_RESEED_POLICY;
+
+// Set to true to run the U2FHID transaction layer in the kernel and give
+// userspace complete requests (h1::usb::u2fhid_driver). u2f_app implements
+// U2FHID itself on raw frames, so it needs this off.
+const U2FHID_IN_KERNEL: bool = false;
+
+// The protocol the in-kernel transaction layer speaks. HidMode::Ctap2 makes
+// the device a FIDO2 authenticator, which needs a larger message buffer.
+const U2FHID_MODE: h1::usb::u2fhid::HidMode = h1::usb::u2fhid::HidMode::U2f;
+
+// Set to true to run the console over the USB CDC-ACM interface (a serial
+// port on the host) instead of UART0. Console timestamps only apply to
+// UART0.
+const USB_CONSOLE: bool = false;
+
+// Set to true to add a vendor bulk interface on endpoint 2, which apps use
+// through h1::usb::bulk_driver to stream data to host tools. It cannot be
+// used with USB_CONSOLE, which takes the same endpoint.
+const USB_BULK: bool = false;
+
+// NVIC interrupt priorities: SPI device first, then USB, then timers.
+const INTERRUPT_PRIORITIES: &[h1::irq_priority::InterruptGroup] =
+    h1::irq_priority::DEFAULT_PRIORITIES;
+
+// Set to true to record USB control and data traffic for debugging
+// enumeration. The capture is printed by the U2F dump command and can be
+// converted to pcap with tools/usb_pcap.
+const ENABLE_USB_CAPTURE: bool = false;
+
+// Flash pages that nothing may erase or write, e.g. while tracking down an
+// unexpected write. Operations on them fail with ERESERVE and are recorded in
+// the flash audit journal along with those on the personality pages.
+const FLASH_DENIED_PAGES: &[usize] = &[];
+
+// Set to true to detect the console baud rate at boot. Press enter on the
+// console within AUTOBAUD_TIMEOUT_TICKS of reset; otherwise the console
+// falls back to h1::uart::DEFAULT_BAUDRATE.
+const ENABLE_AUTOBAUD: bool = false;
+
+// One second at 24MHz.
+const AUTOBAUD_TIMEOUT_TICKS: u32 = 24_000_000;
+
 // Used by panic_fmt to print chip-specific debugging information.
 static mut CHIP: Option<&'static h1::chip::Hotel> = None;
 
@@ -59,8 +126,9 @@ static mut CHIP: Option<&'static h1::chip::Hotel> = None;
 #[cfg(not(test))]
 #[panic_handler]
 pub unsafe extern "C" fn panic_fmt(pi: &core::panic::PanicInfo) -> ! {
-    let led = &mut kernel::hil::led::LedLow::new(&mut h1::gpio::PORT0.pins[0]);
-    let writer = &mut h1::io::WRITER;
+    let led_pin = &mut h1::gpio::GPIOPin::new(h1::gpio::GPIO0_BASE, h1::gpio::Pin::P0);
+    let led = &mut kernel::hil::led::LedLow::new(led_pin);
+    let writer = &mut h1::io::Writer;
     kernel::debug::panic(&mut [led], writer, pi, &cortexm3::support::nop, &crate::PROCESSES, &CHIP)
 }
 
@@ -82,57 +150,38 @@ pub struct Golf {
     digest: &'static h1_syscalls::digest::DigestDriver<'static, h1::crypto::sha::ShaEngine>,
     aes: &'static h1_syscalls::aes::AesDriver<'static>,
     rng: &'static capsules::rng::RngDriver<'static>,
+    entropy_pool_syscalls: &'static h1_syscalls::entropy_pool::EntropyPoolSyscall<'static>,
+    trng_health_syscalls: &'static h1_syscalls::trng_health::TrngHealthSyscall<'static>,
+    fault_stats_syscalls: &'static h1_syscalls::fault_stats::FaultStatsSyscall,
+    crypto_stats_syscalls: &'static h1_syscalls::crypto_stats::CryptoStatsSyscall,
+    irq_stats_syscalls: &'static h1_syscalls::irq_stats::IrqStatsSyscall<'static, VirtualMuxAlarm<'static, Timels>>,
+    console_timestamps_syscalls: &'static h1_syscalls::console_timestamps::ConsoleTimestampsSyscall<'static>,
+    stack_usage_syscalls: &'static h1_syscalls::stack_usage::StackUsageSyscall,
     dcrypto: &'static h1_syscalls::dcrypto::DcryptoDriver<'static>,
-    low_level_debug: &'static capsules::low_level_debug::LowLevelDebug<
-        'static,
-        capsules::virtual_uart::UartDevice<'static>
-    >,
+    low_level_debug: &'static h1_syscalls::low_level_debug::LowLevelDebugExt<'static>,
     nvcounter: &'static h1_syscalls::nvcounter_syscall::NvCounterSyscall<'static,
         FlashCounter<'static, h1::hil::flash::virtual_flash::FlashUser<'static>>>,
     u2f_usb: &'static h1::usb::driver::U2fSyscallDriver<'static>,
+    u2fhid_usb: &'static h1::usb::u2fhid_driver::U2fHidSyscallDriver<'static, VirtualMuxAlarm<'static, Timels>>,
+    bulk_usb: &'static h1::usb::bulk_driver::BulkSyscallDriver<'static>,
     personality: &'static h1_syscalls::personality::PersonalitySyscall<'static>,
+    keystore: &'static h1_syscalls::keystore::KeyStoreSyscall<'static>,
+    hkdf: &'static h1_syscalls::hkdf::HkdfSyscall<'static>,
+    keyladder: &'static h1_syscalls::keyladder::KeyLadderSyscall<'static>,
 }
 
-static mut STRINGS: [StringDescriptor; 7] = [
-    StringDescriptor {
-        b_length: 4,
-        b_descriptor_type: Descriptor::String as u8,
-        b_string: &[0x0409], // English
-    },
-    StringDescriptor {
-        b_length: 24,
-        b_descriptor_type: Descriptor::String as u8,
-        b_string: &[0x0047, 0x006f, 0x006f, 0x0067, 0x006c, 0x0065, 0x0020, 0x0049, 0x006e, 0x0063, 0x002e], // Google Inc.
-    },
-    StringDescriptor {
-        b_length: 14,
-        b_descriptor_type: Descriptor::String as u8,
-        b_string: &[0x0070, 0x0072, 0x006f, 0x0074, 0x006f, 0x0032], // proto2
-    },
-    StringDescriptor {
-        b_length: 54,
-        b_descriptor_type: Descriptor::String as u8,
-        b_string: &[0x0070, 0x0072, 0x006F, 0x0074, 0x006F, 0x0032, 0x005F, 0x0076, 0x0031, 0x002E, 0x0031, 0x002E, 0x0038, 0x0037, 0x0031, 0x0033, 0x002D, 0x0030, 0x0031, 0x0033, 0x0032, 0x0031, 0x0037, 0x0064, 0x0039, 0x0031], // proto2-...
-    },
-    // Why does this need 3 l (0x6C)? Linux seems to be truncating last one.
-    // Verified GetDescriptor for the String is returning complete information.
-    // -pal
-    StringDescriptor {
-        b_length: 12,
-        b_descriptor_type: Descriptor::String as u8,
-        b_string: &[0x0053, 0x0068, 0x0065, 0x006C, 0x006C, 0x006C], // Shell
-    },
-    StringDescriptor {
-        b_length: 8,
-        b_descriptor_type: Descriptor::String as u8,
-        b_string: &[0x0042, 0x004C, 0x0041, 0x0048],  // BLAH
-    },
-    StringDescriptor {
-        b_length: 20,
-        b_descriptor_type: Descriptor::String as u8,
-        b_string: &[0x0048, 0x006F, 0x0074, 0x0065, 0x006C, 0x0020, 0x0055, 0x0032, 0x0046], // Hotel U2F
-    },
-];
+/// Measures the baud rate on the UART0 RX pad (DIOB6) by temporarily
+/// routing it to GPIO0_GPIO15. GPIO0 must already be clocked.
+fn detect_console_baudrate(pinmux: &h1::pinmux::Pinmux, rx: &h1::gpio::GPIOPin,
+                           timer: &h1::timeus::Timeus) -> u32 {
+    use kernel::hil::gpio::Input;
+
+    pinmux.gpio0_gpio15.select.set(h1::pinmux::SelectablePin::Diob6);
+    let baudrate = h1::uart::detect_baudrate(|| rx.read(), timer, AUTOBAUD_TIMEOUT_TICKS);
+    pinmux.gpio0_gpio15.select.set(h1::pinmux::SelectablePin::Disconnected);
+
+    baudrate.unwrap_or(h1::uart::DEFAULT_BAUDRATE)
+}
 
 #[no_mangle]
 pub unsafe fn reset_handler() {
@@ -140,6 +189,8 @@ pub unsafe fn reset_handler() {
 
     h1::init();
 
+    let peripherals = static_init!(h1::peripherals::Peripherals, h1::peripherals::Peripherals::new());
+
     let timerhs = {
         use h1::pmu::*;
         use h1::timeus::Timeus;
@@ -155,7 +206,7 @@ pub unsafe fn reset_handler() {
     {
         use h1::pmu::*;
         Clock::new(PeripheralClock::Bank0(PeripheralClock0::Gpio0)).enable();
-        let pinmux = &mut *h1::pinmux::PINMUX;
+        let pinmux = &peripherals.pinmux;
         // LED_0
         pinmux.dioa11.select.set(h1::pinmux::Function::Gpio0Gpio0);
 
@@ -169,6 +220,12 @@ pub unsafe fn reset_handler() {
         pinmux.uart0_rx.select.set(h1::pinmux::SelectablePin::Diob6);
     }
 
+    let console_baudrate = if ENABLE_AUTOBAUD {
+        detect_console_baudrate(&peripherals.pinmux, &peripherals.gpio0.pins[15], &timerhs)
+    } else {
+        h1::uart::DEFAULT_BAUDRATE
+    };
+
     // Create capabilities that the board needs to call certain protected kernel
     // functions.
     let process_mgmt_cap = create_capability!(capabilities::ProcessManagementCapability);
@@ -178,20 +235,32 @@ pub unsafe fn reset_handler() {
     let kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
     let dynamic_deferred_call_clients =
-        static_init!([DynamicDeferredCallClientState; 2], Default::default());
+        static_init!([DynamicDeferredCallClientState; 3], Default::default());
     let dynamic_deferred_caller = static_init!(
         DynamicDeferredCall,
         DynamicDeferredCall::new(dynamic_deferred_call_clients)
     );
     DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
 
-    let uart_mux = components::console::UartMuxComponent::new(&h1::uart::UART0, 115200, dynamic_deferred_caller)
+    let console_device: &'static dyn hil::uart::Uart<'static> = if USB_CONSOLE {
+        let usb_uart = static_init!(
+            h1::usb::cdc_uart::UsbCdcUart<'static>,
+            h1::usb::cdc_uart::UsbCdcUart::new(&peripherals.usb0, dynamic_deferred_caller));
+        usb_uart.initialize_callback_handle(
+            dynamic_deferred_caller.register(usb_uart).expect("no deferred call slot available for the USB console"));
+        h1::usb::cdc::UsbCdcAcm::set_cdc_client(&peripherals.usb0, usb_uart);
+        usb_uart
+    } else {
+        &peripherals.uart0
+    };
+
+    let uart_mux = components::console::UartMuxComponent::new(console_device, console_baudrate, dynamic_deferred_caller)
         .finalize(());
-    hil::uart::Transmit::set_transmit_client(&h1::uart::UART0, uart_mux);
+    hil::uart::Transmit::set_transmit_client(console_device, uart_mux);
 
     // Configure UART speed
-    let uart = &h1::uart::UART0;
-    uart.config(115200);
+    let uart = &peripherals.uart0;
+    uart.config(console_baudrate);
 
     // Create virtual device for console.
     let console_uart = static_init!(UartDevice, UartDevice::new(uart_mux, true));
@@ -212,8 +281,9 @@ pub unsafe fn reset_handler() {
     components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
 
     // LowLevelDebug driver
-    static mut LOW_LEVEL_DEBUG_BUF: [u8; capsules::low_level_debug::BUF_LEN] =
-        [0; capsules::low_level_debug::BUF_LEN];
+    let low_level_debug_buffer = static_init!(
+        [u8; capsules::low_level_debug::BUF_LEN],
+        [0; capsules::low_level_debug::BUF_LEN]);
     let low_level_debug_uart = static_init!(UartDevice, UartDevice::new(uart_mux, false));
     low_level_debug_uart.setup();
     let low_level_debug = static_init!(
@@ -222,18 +292,22 @@ pub unsafe fn reset_handler() {
             capsules::virtual_uart::UartDevice<'static>
         >,
         capsules::low_level_debug::LowLevelDebug::new(
-            &mut LOW_LEVEL_DEBUG_BUF,
+            low_level_debug_buffer,
             low_level_debug_uart,
             kernel.create_grant(&grant_cap)
         )
     );
     hil::uart::Transmit::set_transmit_client(low_level_debug_uart, low_level_debug);
+    let low_level_debug = static_init!(
+        h1_syscalls::low_level_debug::LowLevelDebugExt<'static>,
+        h1_syscalls::low_level_debug::LowLevelDebugExt::new(low_level_debug)
+    );
 
     //debug!("Booting.");
     let wrapped_pins = static_init!(
         [kernel::hil::gpio::InterruptValueWrapper<'static, h1::gpio::GPIOPin>; 2],
-        [kernel::hil::gpio::InterruptValueWrapper::new(&h1::gpio::PORT0.pins[0]),
-         kernel::hil::gpio::InterruptValueWrapper::new(&h1::gpio::PORT0.pins[1])]
+        [kernel::hil::gpio::InterruptValueWrapper::new(&peripherals.gpio0.pins[0]),
+         kernel::hil::gpio::InterruptValueWrapper::new(&peripherals.gpio0.pins[1])]
     );
     let capsule_pins = static_init!(
         [Option<&'static kernel::hil::gpio::InterruptValueWrapper<'static, h1::gpio::GPIOPin>>; 2],
@@ -250,15 +324,15 @@ pub unsafe fn reset_handler() {
 
     let alarm_mux = static_init!(
         capsules::virtual_alarm::MuxAlarm<'static, Timels>,
-        capsules::virtual_alarm::MuxAlarm::new(&h1::timels::TIMELS0));
-    h1::timels::TIMELS0.set_alarm_client(alarm_mux);
+        capsules::virtual_alarm::MuxAlarm::new(&peripherals.timels0));
+    peripherals.timels0.set_alarm_client(alarm_mux);
 
     // Create flash driver and its virtualization
     let flash_virtual_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
                                            VirtualMuxAlarm::new(alarm_mux));
     let flash = static_init!(
         h1::hil::flash::FlashImpl<'static, VirtualMuxAlarm<'static, Timels>>,
-        h1::hil::flash::FlashImpl::new(flash_virtual_alarm, &*h1::hil::flash::h1_hw::H1_HW));
+        h1::hil::flash::FlashImpl::new(flash_virtual_alarm, peripherals.flash_controller));
     flash_virtual_alarm.set_alarm_client(flash);
 
     let flash_mux = static_init!(
@@ -272,8 +346,27 @@ pub unsafe fn reset_handler() {
     let nvcounter_flash = static_init!(h1::hil::flash::virtual_flash::FlashUser<'static>,
                                        h1::hil::flash::virtual_flash::FlashUser::new(flash_mux));
 
+    let keystore_flash = static_init!(h1::hil::flash::virtual_flash::FlashUser<'static>,
+                                      h1::hil::flash::virtual_flash::FlashUser::new(flash_mux));
+
     flash.set_client(flash_mux);
 
+    // 1MHz timer used to timestamp the flash audit journal and the USB
+    // capture.
+    let debug_timer = static_init!(h1::timeus::Timeus, h1::timeus::Timeus::new(2));
+    debug_timer.start_with_divider(24);  // 1MHz
+
+    let audit_records = static_init!(
+        [h1::hil::flash::audit::AuditRecord; h1::hil::flash::audit::AUDIT_RECORD_COUNT],
+        [h1::hil::flash::audit::AuditRecord::EMPTY; h1::hil::flash::audit::AUDIT_RECORD_COUNT]);
+    let flash_audit = static_init!(
+        h1::hil::flash::audit::WriteAudit<'static>,
+        h1::hil::flash::audit::WriteAudit::new(debug_timer,
+                                               &h1::personality::PERSONALITY_PAGES,
+                                               FLASH_DENIED_PAGES,
+                                               audit_records));
+    flash_mux.set_audit(flash_audit);
+
     let timer_virtual_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
                                            VirtualMuxAlarm::new(alarm_mux));
     let timer = static_init!(
@@ -281,24 +374,66 @@ pub unsafe fn reset_handler() {
         AlarmDriver::new(timer_virtual_alarm, kernel.create_grant(&grant_cap)));
     timer_virtual_alarm.set_alarm_client(timer);
 
+    // Count interrupts per NVIC line and log interrupt storms.
+    let irq_stats_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
+                                       VirtualMuxAlarm::new(alarm_mux));
+    let irq_counts = static_init!(h1::irq_stats::IrqCounts, h1::irq_stats::IrqCounts::EMPTY);
+    let irq_stats = static_init!(
+        h1::irq_stats::IrqStats<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1::irq_stats::IrqStats::new(irq_stats_alarm,
+                                     irq_counts,
+                                     IRQ_STATS_WINDOW_MS,
+                                     IRQ_STORM_THRESHOLD));
+    irq_stats_alarm.set_alarm_client(irq_stats);
+    irq_stats.start();
+    let irq_stats_syscalls = static_init!(
+        h1_syscalls::irq_stats::IrqStatsSyscall<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1_syscalls::irq_stats::IrqStatsSyscall::new(irq_stats));
+
+    // Timestamps for the console lines.
+    let uptime_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
+                                    VirtualMuxAlarm::new(alarm_mux));
+    let uptime = static_init!(
+        h1::uptime::UptimeClock<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1::uptime::UptimeClock::new(h1::timeus::Timeus::new(3), uptime_alarm));
+    uptime_alarm.set_alarm_client(uptime);
+    uptime.start();
+    peripherals.uart0.set_timestamp_clock(uptime);
+    peripherals.uart0.enable_timestamps(CONSOLE_TIMESTAMPS);
+    let console_timestamps_syscalls = static_init!(
+        h1_syscalls::console_timestamps::ConsoleTimestampsSyscall<'static>,
+        h1_syscalls::console_timestamps::ConsoleTimestampsSyscall::new(&peripherals.uart0));
+
     let digest = static_init!(
         h1_syscalls::digest::DigestDriver<'static, h1::crypto::sha::ShaEngine>,
         h1_syscalls::digest::DigestDriver::new(
-                &mut h1::crypto::sha::KEYMGR0_SHA,
+                &peripherals.sha,
                 kernel.create_grant(&grant_cap)));
-
+    // Kernel users of the SHA engine go through the arbiter so they cannot
+    // clobber an app's digest session.
+    let sha_arbiter = static_init!(
+        h1::crypto::sha::ShaArbiter<'static>,
+        h1::crypto::sha::ShaArbiter::new(&peripherals.sha));
+    sha_arbiter.set_owner(digest);
+
+    let aes_key_slots = static_init!(
+        h1::crypto::key_slots::AesKeySlots,
+        h1::crypto::key_slots::AesKeySlots::new());
     let aes = static_init!(
         h1_syscalls::aes::AesDriver,
-        h1_syscalls::aes::AesDriver::new(&mut h1::crypto::aes::KEYMGR0_AES, kernel.create_grant(&grant_cap)));
-    h1::crypto::aes::KEYMGR0_AES.set_client(aes);
-    aes.initialize(&mut h1_syscalls::aes::AES_BUF);
-
-    h1::crypto::dcrypto::DCRYPTO.initialize();
-    let dcrypto = static_init!(
-        h1_syscalls::dcrypto::DcryptoDriver<'static>,
-        h1_syscalls::dcrypto::DcryptoDriver::new(&mut h1::crypto::dcrypto::DCRYPTO));
-
-    h1::crypto::dcrypto::DCRYPTO.set_client(dcrypto);
+        h1_syscalls::aes::AesDriver::new(&peripherals.aes, aes_key_slots,
+                                         kernel.create_grant(&grant_cap)));
+    peripherals.aes.set_client(aes);
+    let aes_buffer = static_init!(
+        [u8; hil::symmetric_encryption::AES128_BLOCK_SIZE],
+        [0; hil::symmetric_encryption::AES128_BLOCK_SIZE]);
+    aes.initialize(aes_buffer);
+
+    peripherals.dcrypto.initialize();
+    let dcrypto_mux = static_init!(
+        h1::crypto::virtual_dcrypto::MuxDcrypto<'static>,
+        h1::crypto::virtual_dcrypto::MuxDcrypto::new(&peripherals.dcrypto));
+    peripherals.dcrypto.set_client(dcrypto_mux);
 
     let nvcounter_buffer = static_init!([u32; 1], [0]);
     let nvcounter = static_init!(
@@ -314,14 +449,42 @@ pub unsafe fn reset_handler() {
 
     let u2f = static_init!(
         h1::usb::driver::U2fSyscallDriver<'static>,
-        h1::usb::driver::U2fSyscallDriver::new(&mut h1::usb::USB0, kernel.create_grant(&grant_cap)));
-    h1::usb::u2f::UsbHidU2f::set_u2f_client(&h1::usb::USB0, u2f);
-
-
-    h1::trng::TRNG0.init();
+        h1::usb::driver::U2fSyscallDriver::new(&peripherals.usb0, kernel.create_grant(&grant_cap)));
+    let u2fhid_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
+                                    VirtualMuxAlarm::new(alarm_mux));
+    let u2fhid_buffer = static_init!([u8; U2FHID_MODE.max_message_len()],
+                                     [0; U2FHID_MODE.max_message_len()]);
+    let u2fhid = static_init!(
+        h1::usb::u2fhid_driver::U2fHidSyscallDriver<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1::usb::u2fhid_driver::U2fHidSyscallDriver::new(&peripherals.usb0,
+                                                         u2fhid_alarm,
+                                                         u2fhid_buffer,
+                                                         U2FHID_MODE,
+                                                         kernel.create_grant(&grant_cap)));
+    u2fhid_alarm.set_alarm_client(u2fhid);
+    if U2FHID_IN_KERNEL {
+        h1::usb::u2f::UsbHidU2f::set_u2f_client(&peripherals.usb0, u2fhid);
+    } else {
+        h1::usb::u2f::UsbHidU2f::set_u2f_client(&peripherals.usb0, u2f);
+    }
+    let bulk = static_init!(
+        h1::usb::bulk_driver::BulkSyscallDriver<'static>,
+        h1::usb::bulk_driver::BulkSyscallDriver::new(&peripherals.usb0, 2,
+                                                     kernel.create_grant(&grant_cap)));
+
+
+    peripherals.trng0.init();
+    let entropy_pool = static_init!(
+        h1::entropy_pool::EntropyPoolImpl<'static>,
+        h1::entropy_pool::EntropyPoolImpl::new(
+            &peripherals.trng0,
+            h1::crypto::drbg::CtrDrbg::new(&peripherals.aes),
+            ENTROPY_RESEED_POLICY)
+    );
+    peripherals.trng0.set_client(entropy_pool);
     let entropy_to_random = static_init!(
         capsules::rng::Entropy32ToRandom<'static>,
-        capsules::rng::Entropy32ToRandom::new(&h1::trng::TRNG0)
+        capsules::rng::Entropy32ToRandom::new(entropy_pool)
     );
 
     let rng = static_init!(
@@ -331,18 +494,115 @@ pub unsafe fn reset_handler() {
             kernel.create_grant(&grant_cap)
         )
     );
-    h1::trng::TRNG0.set_client(entropy_to_random);
+    entropy_pool.set_client(entropy_to_random);
     entropy_to_random.set_client(rng);
+    entropy_pool.init();
+
+    let dcrypto_user = static_init!(
+        h1::crypto::virtual_dcrypto::DcryptoUser<'static>,
+        h1::crypto::virtual_dcrypto::DcryptoUser::new(dcrypto_mux));
+    dcrypto_user.setup();
+    let p256_user = static_init!(
+        h1::crypto::virtual_dcrypto::DcryptoUser<'static>,
+        h1::crypto::virtual_dcrypto::DcryptoUser::new(dcrypto_mux));
+    p256_user.setup();
+    let p256 = static_init!(
+        h1::crypto::p256::P256Engine<'static>,
+        h1::crypto::p256::P256Engine::new(p256_user, entropy_pool));
+    p256_user.set_client(p256);
+    let dcrypto = static_init!(
+        h1_syscalls::dcrypto::DcryptoDriver<'static>,
+        h1_syscalls::dcrypto::DcryptoDriver::new(dcrypto_user, p256, sha_arbiter));
+    dcrypto_user.set_client(dcrypto);
+    p256.set_client(dcrypto);
+
+    let entropy_pool_syscalls = static_init!(
+        h1_syscalls::entropy_pool::EntropyPoolSyscall<'static>,
+        h1_syscalls::entropy_pool::EntropyPoolSyscall::new(entropy_pool)
+    );
+    let trng_health_syscalls = static_init!(
+        h1_syscalls::trng_health::TrngHealthSyscall<'static>,
+        h1_syscalls::trng_health::TrngHealthSyscall::new(&peripherals.trng0)
+    );
+    let fault_stats_syscalls = static_init!(
+        h1_syscalls::fault_stats::FaultStatsSyscall,
+        h1_syscalls::fault_stats::FaultStatsSyscall::new()
+    );
+    h1::crypto::stats::set_clock(debug_timer, 1_000_000);
+    let crypto_stats_syscalls = static_init!(
+        h1_syscalls::crypto_stats::CryptoStatsSyscall,
+        h1_syscalls::crypto_stats::CryptoStatsSyscall::new()
+    );
+    let stack_usage_syscalls = static_init!(
+        h1_syscalls::stack_usage::StackUsageSyscall,
+        h1_syscalls::stack_usage::StackUsageSyscall::new(&PROCESSES)
+    );
 
     let personality = static_init!(
         h1_syscalls::personality::PersonalitySyscall<'static>,
-        h1_syscalls::personality::PersonalitySyscall::new(&mut h1::personality::PERSONALITY,
+        h1_syscalls::personality::PersonalitySyscall::new(&peripherals.personality,
                                                           kernel.create_grant(&grant_cap)));
 
-    h1::personality::PERSONALITY.set_flash(flash_user);
-    h1::personality::PERSONALITY.set_buffer(&mut h1::personality::BUFFER);
-    h1::personality::PERSONALITY.set_client(personality);
-    flash_user.set_client(&h1::personality::PERSONALITY);
+    peripherals.personality.set_flash(flash_user);
+    let personality_image = static_init!(
+        [u32; h1::personality::IMAGE_WORDS], [0; h1::personality::IMAGE_WORDS]);
+    let personality_write_buffer = static_init!(
+        [u32; h1::personality::WRITE_CHUNK_WORDS], [0; h1::personality::WRITE_CHUNK_WORDS]);
+    peripherals.personality.set_buffers(personality_image, personality_write_buffer);
+    peripherals.personality.set_client(personality);
+    flash_user.set_client(&peripherals.personality);
+
+    let keyladder = static_init!(
+        h1::crypto::keyladder::KeyLadderImpl<'static>,
+        h1::crypto::keyladder::KeyLadderImpl::new(sha_arbiter));
+
+    let keystore_image = static_init!(
+        [u32; h1::keystore::KEYSTORE_WORDS], h1::keystore::EMPTY_IMAGE);
+    let keystore_write_buffer = static_init!(
+        [u32; h1::keystore::WRITE_CHUNK_WORDS], [0; h1::keystore::WRITE_CHUNK_WORDS]);
+    let keystore_dcrypto = static_init!(
+        h1::crypto::virtual_dcrypto::DcryptoUser<'static>,
+        h1::crypto::virtual_dcrypto::DcryptoUser::new(dcrypto_mux));
+    keystore_dcrypto.setup();
+    let keystore_p256 = static_init!(
+        h1::crypto::p256::P256Engine<'static>,
+        h1::crypto::p256::P256Engine::new(keystore_dcrypto, entropy_pool));
+    keystore_dcrypto.set_client(keystore_p256);
+    let keystore = static_init!(
+        h1::keystore::KeyStoreImpl<'static>,
+        h1::keystore::KeyStoreImpl::new(keystore_flash,
+                                        &peripherals.aes,
+                                        sha_arbiter,
+                                        keystore_p256,
+                                        keyladder,
+                                        entropy_pool,
+                                        keystore_image,
+                                        keystore_write_buffer));
+    keystore_flash.set_client(keystore);
+    keystore_p256.set_client(keystore);
+    keystore.init();
+    let keystore_syscalls = static_init!(
+        h1_syscalls::keystore::KeyStoreSyscall<'static>,
+        h1_syscalls::keystore::KeyStoreSyscall::new(keystore, &PROCESSES,
+                                                    kernel.create_grant(&grant_cap)));
+    keystore.set_client(keystore_syscalls);
+
+    let hkdf = static_init!(
+        h1::hkdf::HkdfImpl<'static>,
+        h1::hkdf::HkdfImpl::new(sha_arbiter));
+    let hkdf_syscalls = static_init!(
+        h1_syscalls::hkdf::HkdfSyscall<'static>,
+        h1_syscalls::hkdf::HkdfSyscall::new(hkdf, Some(keystore), kernel.create_grant(&grant_cap)));
+
+    let keyladder_syscalls = static_init!(
+        h1_syscalls::keyladder::KeyLadderSyscall<'static>,
+        h1_syscalls::keyladder::KeyLadderSyscall::new(keyladder, &PROCESSES,
+                                                      kernel.create_grant(&grant_cap)));
+    let rcode = peripherals.personality.install_key(keyladder, aes_key_slots,
+                                                    PERSONALITY_KEY_SLOT);
+    if rcode != kernel::ReturnCode::SUCCESS {
+        debug!("Personality: could not install key: {:?}", rcode);
+    }
 
     // ** GLOBALSEC **
     // TODO(alevy): refactor out
@@ -387,43 +647,117 @@ pub unsafe fn reset_handler() {
         vs(DUSB0_REGION3_CTRL as *mut u32, !0);
 
         // Flash region initialization. We initialize a single region for the
-        // last three pages of the second flash macro, used by Personality (n-3)
-        // and the non-volatile counter implementation (n-2, n-1).
-        const FLASH_START: usize = 0x40000;
-        const FLASH_SIZE: usize = 512 * 1024;
-        const FLASH_PAGE_SIZE: usize = 2048;
-        vs(FLASH_REGION2_BASE as *mut u32, (FLASH_START + FLASH_SIZE - 3*FLASH_PAGE_SIZE) as u32);
+        // reserved pages at the end of the second flash macro. Besides the
+        // non-volatile counter (n-2, n-1), they hold both personality slots
+        // (n-3, n-7) and both key store pages (n-4, n-8), so the region has
+        // to cover all of them rather than just the last three pages.
+        use h1::hil::flash::h1_hw::{H1_FLASH_PAGE_SIZE, H1_FLASH_START, H1_RESERVED_PAGES,
+                                    H1_RESERVED_START};
+        vs(FLASH_REGION2_BASE as *mut u32, (H1_FLASH_START + H1_RESERVED_START) as u32);
         // The value of the SIZE register is one less than the size of the
         // region, i.e. the last address within the region is the start address
         // + the size register.
-        vs(FLASH_REGION2_SIZE as *mut u32, (3*FLASH_PAGE_SIZE - 1) as u32);
+        vs(FLASH_REGION2_SIZE as *mut u32, (H1_RESERVED_PAGES * H1_FLASH_PAGE_SIZE - 1) as u32);
         // Enable the region for reads and writes.
         vs(FLASH_REGION2_CTRL as *mut u32, 0b111);
     }
 
     let mut _ctr = 0;
-    let chip = static_init!(h1::chip::Hotel, h1::chip::Hotel::new());
+    let chip = static_init!(h1::chip::Hotel, h1::chip::Hotel::new(peripherals, INTERRUPT_PRIORITIES));
     chip.mpu().enable_app_mpu();
+    chip.set_interrupt_counter(irq_stats);
     CHIP = Some(chip);
 
     let end = timerhs.now();
     println!("Tock: booted in {} tics; initializing USB and loading processes.",
              end.wrapping_sub(start));
 
-    h1::usb::USB0.init(&mut h1::usb::EP0_OUT_DESCRIPTORS,
-                       &mut h1::usb::EP0_OUT_BUFFERS,
-                       &mut h1::usb::EP0_IN_DESCRIPTORS,
-                       &mut h1::usb::EP0_IN_BUFFER,
-                       &mut h1::usb::EP1_OUT_DESCRIPTOR,
-                       &mut h1::usb::EP1_OUT_BUFFER,
-                       &mut h1::usb::EP1_IN_DESCRIPTOR,
-                       &mut h1::usb::EP1_IN_BUFFER,
-                       &mut h1::usb::CONFIGURATION_BUFFER,
-                       h1::usb::PHY::A,
-                       None,
-                       Some(0x18d1),  // Google vendor ID
-                       Some(0x5026),  // proto2
-                       &mut STRINGS);
+    // The U2F HID interface comes first: hosts look for it as interface 0.
+    peripherals.usb0.register_interface(
+        h1::usb::interface::InterfaceKind::U2fHid,
+        1,
+        h1::usb::interface::EndpointBuffers {
+            out_descriptor: &mut h1::usb::EP1_DESCRIPTOR_POOL.take("usb ep1 out").unwrap()[0],
+            out_buffer: h1::usb::EP1_BUFFER_POOL.take("usb ep1 out").unwrap(),
+            in_descriptor: &mut h1::usb::EP1_DESCRIPTOR_POOL.take("usb ep1 in").unwrap()[0],
+            in_buffer: h1::usb::EP1_BUFFER_POOL.take("usb ep1 in").unwrap(),
+        }).expect("failed to register the USB U2F interface");
+    peripherals.usb0.enable_u2f_transfers(h1::usb::transfer::TransferBuffers {
+        out_descriptors: h1::usb::U2F_TRANSFER_DESCRIPTOR_POOL.take("usb u2f transfer out").unwrap(),
+        out_buffer: h1::usb::U2F_TRANSFER_BUFFER_POOL.take("usb u2f transfer out").unwrap(),
+        in_descriptors: h1::usb::U2F_TRANSFER_DESCRIPTOR_POOL.take("usb u2f transfer in").unwrap(),
+        in_buffer: h1::usb::U2F_TRANSFER_BUFFER_POOL.take("usb u2f transfer in").unwrap(),
+    });
+    if USB_CONSOLE {
+        peripherals.usb0.register_interface(
+            h1::usb::interface::InterfaceKind::CdcAcm { notification_endpoint: 3 },
+            2,
+            h1::usb::interface::EndpointBuffers {
+                out_descriptor: &mut h1::usb::EP2_DESCRIPTOR_POOL.take("usb ep2 out").unwrap()[0],
+                out_buffer: h1::usb::EP2_BUFFER_POOL.take("usb ep2 out").unwrap(),
+                in_descriptor: &mut h1::usb::EP2_DESCRIPTOR_POOL.take("usb ep2 in").unwrap()[0],
+                in_buffer: h1::usb::EP2_BUFFER_POOL.take("usb ep2 in").unwrap(),
+            }).expect("failed to register the USB console interface");
+    }
+    if USB_BULK {
+        peripherals.usb0.register_interface(
+            h1::usb::interface::InterfaceKind::VendorBulk {
+                sub_class: h1::usb::bulk_driver::SUB_CLASS,
+                protocol: h1::usb::bulk_driver::PROTOCOL,
+            },
+            2,
+            h1::usb::interface::EndpointBuffers {
+                out_descriptor: &mut h1::usb::EP2_DESCRIPTOR_POOL.take("usb ep2 out").unwrap()[0],
+                out_buffer: h1::usb::EP2_BUFFER_POOL.take("usb ep2 out").unwrap(),
+                in_descriptor: &mut h1::usb::EP2_DESCRIPTOR_POOL.take("usb ep2 in").unwrap()[0],
+                in_buffer: h1::usb::EP2_BUFFER_POOL.take("usb ep2 in").unwrap(),
+            }).expect("failed to register the USB bulk interface");
+        h1::usb::bulk::UsbBulk::set_bulk_client(&peripherals.usb0, 2, bulk);
+    }
+
+    let usb_strings = static_init!(
+        [StringDescriptor; h1::usb::types::STRING_COUNT],
+        StringDescriptors::new()
+            .string(STRING_VENDOR, "Google Inc.")
+            .string(STRING_BOARD, "proto2")
+            .string(STRING_PLATFORM, "proto2_v1.1.8713-013217d91")
+            .string(STRING_INTERFACE1, "Shell")
+            .string(STRING_BLAH, "BLAH")
+            .string(STRING_INTERFACE2, "Hotel U2F")
+            .serial_number(peripherals.fuse.get_dev_id())
+            .build()
+    );
+    peripherals.usb0.init(h1::usb::EP0_OUT_DESCRIPTOR_POOL.take("usb").unwrap(),
+                          h1::usb::EP0_OUT_BUFFER_POOL.take("usb").unwrap(),
+                          h1::usb::EP0_IN_DESCRIPTOR_POOL.take("usb").unwrap(),
+                          h1::usb::EP0_IN_BUFFER_POOL.take("usb").unwrap(),
+                          h1::usb::CONFIGURATION_BUFFER_POOL.take("usb").unwrap(),
+                          h1::usb::PHY::A,
+                          None,
+                          Some(0x18d1),  // Google vendor ID
+                          Some(0x5026),  // proto2
+                          usb_strings);
+    // Lets provisioning tools read the certificate and versions over USB.
+    let attestation_personality = static_init!(
+        h1::hil::personality::PersonalityData,
+        h1::hil::personality::PersonalityData::EMPTY
+    );
+    let attestation_reports = static_init!(
+        h1::usb::feature_report::AttestationReports<'static>,
+        h1::usb::feature_report::AttestationReports::new(
+            &peripherals.personality, &peripherals.globalsec, attestation_personality)
+    );
+    peripherals.usb0.set_feature_report_source(attestation_reports);
+    if ENABLE_USB_CAPTURE {
+        let capture_records = static_init!(
+            [h1::usb::capture::CaptureRecord; h1::usb::capture::CAPTURE_RECORD_COUNT],
+            [h1::usb::capture::CaptureRecord::EMPTY; h1::usb::capture::CAPTURE_RECORD_COUNT]);
+        peripherals.usb0.enable_capture(debug_timer, capture_records);
+    }
+    let trace_records = static_init!(
+        [h1::usb::trace::TraceRecord; h1::usb::trace::TRACE_RECORD_COUNT],
+        [h1::usb::trace::TraceRecord::EMPTY; h1::usb::trace::TRACE_RECORD_COUNT]);
+    peripherals.usb0.enable_trace(debug_timer, trace_records);
     let golf2 = Golf {
         console: console,
         gpio: gpio,
@@ -435,8 +769,20 @@ pub unsafe fn reset_handler() {
         low_level_debug,
         nvcounter: nvcounter_syscall,
         rng: rng,
+        entropy_pool_syscalls: entropy_pool_syscalls,
+        trng_health_syscalls: trng_health_syscalls,
+        fault_stats_syscalls: fault_stats_syscalls,
+        crypto_stats_syscalls: crypto_stats_syscalls,
+        irq_stats_syscalls: irq_stats_syscalls,
+        console_timestamps_syscalls: console_timestamps_syscalls,
+        stack_usage_syscalls: stack_usage_syscalls,
         u2f_usb: u2f,
+        u2fhid_usb: u2fhid,
+        bulk_usb: bulk,
         personality: personality,
+        keystore: keystore_syscalls,
+        hkdf: hkdf_syscalls,
+        keyladder: keyladder_syscalls,
     };
 
     // Uncomment to initialize NvCounter
@@ -449,7 +795,8 @@ pub unsafe fn reset_handler() {
         /// script.
         static _eapps: u8;
     }
-    kernel::procs::load_processes(
+    h1::stack_usage::paint(&mut APP_MEMORY);
+    h1::process_loader::load_processes(
         kernel,
         chip,
         core::slice::from_raw_parts(
@@ -458,6 +805,7 @@ pub unsafe fn reset_handler() {
         ),
         &mut APP_MEMORY,
         &mut PROCESSES,
+        APP_FAULT_RESPONSES,
         FAULT_RESPONSE,
         &process_mgmt_cap,
     ).unwrap_or_else(|err| {
@@ -479,14 +827,26 @@ impl Platform for Golf {
             capsules::alarm::DRIVER_NUM                => f(Some(self.timer)),
             capsules::console::DRIVER_NUM              => f(Some(self.console)),
             capsules::gpio::DRIVER_NUM                 => f(Some(self.gpio)),
-            capsules::low_level_debug::DRIVER_NUM      => f(Some(self.low_level_debug)),
             capsules::rng::DRIVER_NUM                  => f(Some(self.rng)),
             h1::usb::driver::DRIVER_NUM                => f(Some(self.u2f_usb)),
+            h1::usb::u2fhid_driver::DRIVER_NUM         => f(Some(self.u2fhid_usb)),
+            h1::usb::bulk_driver::DRIVER_NUM           => f(Some(self.bulk_usb)),
             h1_syscalls::aes::DRIVER_NUM               => f(Some(self.aes)),
+            h1_syscalls::console_timestamps::DRIVER_NUM => f(Some(self.console_timestamps_syscalls)),
+            h1_syscalls::crypto_stats::DRIVER_NUM      => f(Some(self.crypto_stats_syscalls)),
             h1_syscalls::dcrypto::DRIVER_NUM           => f(Some(self.dcrypto)),
             h1_syscalls::digest::DRIVER_NUM            => f(Some(self.digest)),
+            h1_syscalls::entropy_pool::DRIVER_NUM      => f(Some(self.entropy_pool_syscalls)),
+            h1_syscalls::fault_stats::DRIVER_NUM       => f(Some(self.fault_stats_syscalls)),
+            h1_syscalls::irq_stats::DRIVER_NUM         => f(Some(self.irq_stats_syscalls)),
+            h1_syscalls::low_level_debug::DRIVER_NUM   => f(Some(self.low_level_debug)),
+            h1_syscalls::keystore::DRIVER_NUM          => f(Some(self.keystore)),
+            h1_syscalls::hkdf::DRIVER_NUM              => f(Some(self.hkdf)),
+            h1_syscalls::keyladder::DRIVER_NUM         => f(Some(self.keyladder)),
             h1_syscalls::nvcounter_syscall::DRIVER_NUM => f(Some(self.nvcounter)),
             h1_syscalls::personality::DRIVER_NUM       => f(Some(self.personality)),
+            h1_syscalls::stack_usage::DRIVER_NUM       => f(Some(self.stack_usage_syscalls)),
+            h1_syscalls::trng_health::DRIVER_NUM       => f(Some(self.trng_health_syscalls)),
             kernel::ipc::DRIVER_NUM                    => f(Some(&self.ipc)),
             _ =>  f(None),
         }
@@ -12,12 +12,39 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod register_gen;
+
 use std::env;
 use std::fs;
 use std::path::Path;
 
 fn main() {
-    let src = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("layout.ld");
-    let dst = Path::new(&env::var("OUT_DIR").unwrap()).join("../../layout.ld");
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    let src = Path::new(&manifest_dir).join("layout.ld");
+    let dst = Path::new(&out_dir).join("../../layout.ld");
     fs::copy(src, dst).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=layout.ld");
+    println!("cargo:rerun-if-changed=register_gen.rs");
+
+    // Generate <name>_bitfields.rs in OUT_DIR for each registers/<name>.toml.
+    let registers_dir = Path::new(&manifest_dir).join("registers");
+    println!("cargo:rerun-if-changed=registers");
+    let mut maps: Vec<_> = fs::read_dir(&registers_dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |extension| extension == "toml"))
+        .collect();
+    maps.sort();
+    for path in maps {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let map = register_gen::parse(&text)
+            .unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+        let source = format!("registers/{}.toml", name);
+        fs::write(Path::new(&out_dir).join(format!("{}_bitfields.rs", name)),
+                  register_gen::generate(&map, &source)).unwrap();
+    }
 }
@@ -0,0 +1,580 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generates `register_bitfields!` definitions from the register maps in
+//! `registers/`.
+//!
+//! A register map is a TOML file of the form:
+//!
+//! ```toml
+//! public = true                  # Optional: make the registers `pub`.
+//!
+//! [[register]]
+//! name = "CTRL"
+//! comment = "Databook table 1"   # Optional: emitted as a `//` comment.
+//!
+//! [[register.field]]
+//! name = "MODE"
+//! offset = 0
+//! bits = 2                       # Optional, defaults to 1.
+//! doc = "Mode"                   # Optional, may be a """multi-line""" string.
+//! [register.field.values]        # Optional named values, in order.
+//! Generic = 0
+//! Eeprom = 2
+//! ```
+//!
+//! Only this subset of TOML is accepted: no third-party parser is available
+//! to build scripts here. Fields are checked to fit in the register without
+//! overlapping, and values to fit in their field.
+//!
+//! The parser has no crate of its own; `make kernel/localtests` builds this
+//! file with `rustc --test` to run the tests below.
+
+use std::convert::TryFrom;
+
+/// Width of the generated registers.
+const REGISTER_BITS: u32 = 32;
+
+pub struct RegisterMap {
+    public: bool,
+    registers: Vec<Register>,
+}
+
+struct Register {
+    name: String,
+    comment: Option<String>,
+    fields: Vec<Field>,
+    line: usize,
+}
+
+struct Field {
+    name: String,
+    offset: Option<u32>,
+    bits: u32,
+    doc: Vec<String>,
+    values: Vec<(String, u64)>,
+    line: usize,
+}
+
+enum Section {
+    Root,
+    Register,
+    Field,
+    Values,
+}
+
+enum Value {
+    Bool(bool),
+    Integer(u64),
+    String(String),
+    // The lines of a """multi-line""" string.
+    Lines(Vec<String>),
+}
+
+/// Parses a register map. Errors are prefixed with the line number.
+pub fn parse(text: &str) -> Result<RegisterMap, String> {
+    let mut map = RegisterMap { public: false, registers: Vec::new() };
+    let mut section = Section::Root;
+    let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+    while let Some((line_number, line)) = lines.next() {
+        let error = |message: String| format!("line {}: {}", line_number, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            let header = strip_comment(line);
+            section = match header {
+                "[[register]]" => {
+                    map.registers.push(Register {
+                        name: String::new(),
+                        comment: None,
+                        fields: Vec::new(),
+                        line: line_number,
+                    });
+                    Section::Register
+                },
+                "[[register.field]]" => {
+                    let register = map.registers.last_mut()
+                        .ok_or_else(|| error("field outside of a register".to_string()))?;
+                    register.fields.push(Field {
+                        name: String::new(),
+                        offset: None,
+                        bits: 1,
+                        doc: Vec::new(),
+                        values: Vec::new(),
+                        line: line_number,
+                    });
+                    Section::Field
+                },
+                "[register.field.values]" => {
+                    map.registers.last().and_then(|register| register.fields.last())
+                        .ok_or_else(|| error("values outside of a field".to_string()))?;
+                    Section::Values
+                },
+                _ => return Err(error(format!("unsupported table {}", header))),
+            };
+            continue;
+        }
+
+        let equals = line.find('=').ok_or_else(|| error("expected key = value".to_string()))?;
+        let key = line[..equals].trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(error(format!("invalid key {:?}", key)));
+        }
+        let value = parse_value(line[equals + 1..].trim(), &mut lines).map_err(error)?;
+
+        let unknown_key = || error(format!("unknown key {}", key));
+        match section {
+            Section::Root => match (key, value) {
+                ("public", Value::Bool(public)) => map.public = public,
+                _ => return Err(unknown_key()),
+            },
+            Section::Register => {
+                let register = map.registers.last_mut().unwrap();
+                match (key, value) {
+                    ("name", Value::String(name)) => register.name = name,
+                    ("comment", Value::String(comment)) => register.comment = Some(comment),
+                    _ => return Err(unknown_key()),
+                }
+            },
+            Section::Field => {
+                let field = map.registers.last_mut().unwrap().fields.last_mut().unwrap();
+                match (key, value) {
+                    ("name", Value::String(name)) => field.name = name,
+                    ("offset", Value::Integer(offset)) => {
+                        field.offset = Some(u32::try_from(offset)
+                            .map_err(|_| error(format!("offset {} is out of range", offset)))?);
+                    },
+                    ("bits", Value::Integer(bits)) => {
+                        field.bits = u32::try_from(bits)
+                            .map_err(|_| error(format!("bits {} is out of range", bits)))?;
+                    },
+                    ("doc", Value::String(doc)) => field.doc = vec![doc],
+                    ("doc", Value::Lines(doc)) => field.doc = doc,
+                    _ => return Err(unknown_key()),
+                }
+            },
+            Section::Values => {
+                let field = map.registers.last_mut().unwrap().fields.last_mut().unwrap();
+                match value {
+                    Value::Integer(value) => field.values.push((key.to_string(), value)),
+                    _ => return Err(error(format!("value {} is not an integer", key))),
+                }
+            },
+        }
+    }
+
+    validate(&map)?;
+    Ok(map)
+}
+
+fn strip_comment(text: &str) -> &str {
+    match text.find('#') {
+        Some(start) => text[..start].trim(),
+        None => text.trim(),
+    }
+}
+
+fn parse_value<'a, I: Iterator<Item = (usize, &'a str)>>(text: &str, lines: &mut I)
+    -> Result<Value, String>
+{
+    if text.starts_with("\"\"\"") {
+        // The newline right after the opening quotes is not part of the
+        // string, as in TOML.
+        let mut doc = Vec::new();
+        let mut rest = text.split_at(3).1.to_string();
+        loop {
+            if let Some(end) = rest.find("\"\"\"") {
+                if !rest[..end].is_empty() {
+                    doc.push(unescape(&rest[..end])?);
+                }
+                if !strip_comment(&rest[end + 3..]).is_empty() {
+                    return Err("unexpected text after string".to_string());
+                }
+                return Ok(Value::Lines(doc));
+            }
+            if !rest.is_empty() || !doc.is_empty() {
+                doc.push(unescape(&rest)?);
+            }
+            rest = match lines.next() {
+                Some((_, line)) => line.trim().to_string(),
+                None => return Err("unterminated string".to_string()),
+            };
+        }
+    }
+
+    if text.starts_with('"') {
+        let mut escaped = false;
+        for (i, c) in text.char_indices().skip(1) {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    if !strip_comment(&text[i + 1..]).is_empty() {
+                        return Err("unexpected text after string".to_string());
+                    }
+                    return Ok(Value::String(unescape(&text[1..i])?));
+                },
+                _ => {},
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+
+    let text = strip_comment(text);
+    match text {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {},
+    }
+    let digits = text.replace('_', "");
+    let parsed = match digits.get(..2) {
+        Some("0x") => u64::from_str_radix(digits.split_at(2).1, 16),
+        Some("0b") => u64::from_str_radix(digits.split_at(2).1, 2),
+        _ => digits.parse(),
+    };
+    parsed.map(Value::Integer).map_err(|_| format!("unsupported value {}", text))
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => result.push('"'),
+            Some('\\') => result.push('\\'),
+            Some('n') => result.push('\n'),
+            other => return Err(format!("unsupported escape \\{}", other.unwrap_or(' '))),
+        }
+    }
+    Ok(result)
+}
+
+fn is_identifier(name: &str) -> bool {
+    matches!(name.chars().next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn validate(map: &RegisterMap) -> Result<(), String> {
+    for (i, register) in map.registers.iter().enumerate() {
+        let error = |message: String| format!("line {}: {}", register.line, message);
+        if !is_identifier(&register.name) {
+            return Err(error(format!("invalid register name {:?}", register.name)));
+        }
+        if map.registers[..i].iter().any(|other| other.name == register.name) {
+            return Err(error(format!("duplicate register {}", register.name)));
+        }
+
+        let mut used_bits = 0u64;
+        for (j, field) in register.fields.iter().enumerate() {
+            let error = |message: String| {
+                format!("line {}: {}.{}: {}", field.line, register.name, field.name, message)
+            };
+            if !is_identifier(&field.name) {
+                return Err(error("invalid field name".to_string()));
+            }
+            if register.fields[..j].iter().any(|other| other.name == field.name) {
+                return Err(error("duplicate field".to_string()));
+            }
+            let offset = field.offset.ok_or_else(|| error("missing offset".to_string()))?;
+            let end = offset.checked_add(field.bits).filter(|&end| end <= REGISTER_BITS);
+            if field.bits == 0 || end.is_none() {
+                return Err(error(format!("{} bits at offset {} do not fit in the register",
+                                         field.bits, offset)));
+            }
+            let mask = ((1u64 << field.bits) - 1) << offset;
+            if used_bits & mask != 0 {
+                return Err(error("overlaps another field".to_string()));
+            }
+            used_bits |= mask;
+
+            for (k, (name, value)) in field.values.iter().enumerate() {
+                if field.values[..k].iter().any(|(other, _)| other == name) {
+                    return Err(error(format!("duplicate value {}", name)));
+                }
+                if *value >= 1 << field.bits {
+                    return Err(error(format!("value {} does not fit in {} bits", name, field.bits)));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the `register_bitfields!` invocation for `map`. `source` names the
+/// register map in the generated header.
+pub fn generate(map: &RegisterMap, source: &str) -> String {
+    let mut out = format!("// Generated by build.rs from {}. Do not edit.\n\n", source);
+    out.push_str(&format!("register_bitfields![u{},\n", REGISTER_BITS));
+    let visibility = if map.public { "pub " } else { "" };
+    let registers: Vec<String> = map.registers.iter().map(|register| {
+        let mut text = String::new();
+        if let Some(comment) = &register.comment {
+            text.push_str(&format!("    // {}\n", comment));
+        }
+        text.push_str(&format!("    {}{} [\n", visibility, register.name));
+        let fields: Vec<String> = register.fields.iter().map(|field| {
+            let mut text = String::new();
+            for line in &field.doc {
+                if line.is_empty() {
+                    text.push_str("        ///\n");
+                } else {
+                    text.push_str(&format!("        /// {}\n", line));
+                }
+            }
+            text.push_str(&format!("        {} OFFSET({}) NUMBITS({}) [",
+                                   field.name, field.offset.unwrap(), field.bits));
+            if !field.values.is_empty() {
+                let values: Vec<String> = field.values.iter()
+                    .map(|(name, value)| format!("            {} = {}", name, value))
+                    .collect();
+                text.push_str(&format!("\n{}\n        ", values.join(",\n")));
+            }
+            text.push(']');
+            text
+        }).collect();
+        text.push_str(&fields.join(",\n"));
+        text.push_str("\n    ]");
+        text
+    }).collect();
+    out.push_str(&registers.join(",\n"));
+    out.push_str("\n];\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_error(text: &str) -> String {
+        match parse(text) {
+            Ok(_) => panic!("parsed {:?}", text),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn generates_bitfields() {
+        let map = parse(r#"
+public = true
+
+[[register]]
+name = "CTRL"
+comment = "Control"
+
+[[register.field]]
+name = "ENABLE"
+offset = 0
+doc = "Enables the block"
+
+[[register.field]]
+name = "MODE"
+offset = 4
+bits = 2
+doc = """
+Mode
+
+Selects the mode"""
+[register.field.values]
+Idle = 0
+Run = 0b10
+"#).unwrap();
+        assert_eq!(generate(&map, "test.toml"), r#"// Generated by build.rs from test.toml. Do not edit.
+
+register_bitfields![u32,
+    // Control
+    pub CTRL [
+        /// Enables the block
+        ENABLE OFFSET(0) NUMBITS(1) [],
+        /// Mode
+        ///
+        /// Selects the mode
+        MODE OFFSET(4) NUMBITS(2) [
+            Idle = 0,
+            Run = 2
+        ]
+    ]
+];
+"#);
+    }
+
+    #[test]
+    fn rejects_duplicate_registers() {
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register]]
+name = "CTRL"
+"#), "line 4: duplicate register CTRL");
+    }
+
+    #[test]
+    fn rejects_duplicate_fields() {
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+offset = 0
+[[register.field]]
+name = "A"
+offset = 1
+"#), "line 7: CTRL.A: duplicate field");
+    }
+
+    #[test]
+    fn rejects_duplicate_values() {
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+offset = 0
+bits = 2
+[register.field.values]
+X = 0
+X = 1
+"#), "line 4: CTRL.A: duplicate value X");
+    }
+
+    #[test]
+    fn rejects_overlapping_fields() {
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+offset = 0
+bits = 4
+[[register.field]]
+name = "B"
+offset = 3
+"#), "line 8: CTRL.B: overlaps another field");
+    }
+
+    #[test]
+    fn rejects_fields_past_the_register() {
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+offset = 30
+bits = 3
+"#), "line 4: CTRL.A: 3 bits at offset 30 do not fit in the register");
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+offset = 0
+bits = 0
+"#), "line 4: CTRL.A: 0 bits at offset 0 do not fit in the register");
+    }
+
+    #[test]
+    fn rejects_overflowing_offsets_and_sizes() {
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+offset = 0xffff_ffff
+bits = 2
+"#), "line 4: CTRL.A: 2 bits at offset 4294967295 do not fit in the register");
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+offset = 0x1_0000_0000
+"#), "line 6: offset 4294967296 is out of range");
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+offset = 0
+bits = 0x1_0000_0001
+"#), "line 7: bits 4294967297 is out of range");
+        assert_eq!(parse_error("public = 0x1_0000_0000_0000_0000"),
+                   "line 1: unsupported value 0x1_0000_0000_0000_0000");
+    }
+
+    #[test]
+    fn rejects_values_too_large_for_the_field() {
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+offset = 0
+bits = 2
+[register.field.values]
+X = 4
+"#), "line 4: CTRL.A: value X does not fit in 2 bits");
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "0CTRL"
+"#), "line 2: invalid register name \"0CTRL\"");
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A-B"
+offset = 0
+"#), "line 4: CTRL.A-B: invalid field name");
+    }
+
+    #[test]
+    fn rejects_missing_offsets() {
+        assert_eq!(parse_error(r#"
+[[register]]
+name = "CTRL"
+[[register.field]]
+name = "A"
+"#), "line 4: CTRL.A: missing offset");
+    }
+
+    #[test]
+    fn rejects_unsupported_syntax() {
+        assert_eq!(parse_error("[register]"), "line 1: unsupported table [register]");
+        assert_eq!(parse_error("[[register.field]]"), "line 1: field outside of a register");
+        assert_eq!(parse_error("[[register]]\n[register.field.values]"),
+                   "line 2: values outside of a field");
+        assert_eq!(parse_error("public"), "line 1: expected key = value");
+        assert_eq!(parse_error("a.b = 1"), "line 1: invalid key \"a.b\"");
+        assert_eq!(parse_error("private = true"), "line 1: unknown key private");
+        assert_eq!(parse_error("[[register]]\nname = 1"), "line 2: unknown key name");
+        assert_eq!(parse_error("[[register]]\nname = \"CTRL"), "line 2: unterminated string");
+        assert_eq!(parse_error("[[register]]\ncomment = \"\"\"\nopen"),
+                   "line 2: unterminated string");
+        assert_eq!(parse_error("[[register]]\nname = \"A\" B"),
+                   "line 2: unexpected text after string");
+        assert_eq!(parse_error("[[register]]\nname = \"\\t\""),
+                   "line 2: unsupported escape \\t");
+    }
+}
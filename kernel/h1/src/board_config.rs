@@ -0,0 +1,248 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Persistent board configuration.
+//!
+//! The configuration (see `spiutils::protocol::config`) alternates between
+//! the fifth- and sixth-to-last (N-5, N-6) pages of flash, right below the
+//! key store. Of the two pages, the valid one with the higher sequence
+//! number is active. A commit writes the staged configuration with the next
+//! sequence number to the other page, so a reset in the middle of a commit
+//! leaves the previous configuration active.
+//!
+//! `load` reads the pages straight from the memory-mapped flash, so that
+//! boards can apply the configuration in `reset_handler` before their
+//! drivers are initialized.
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::ReturnCode;
+use spiutils::io::Cursor;
+use spiutils::protocol::config::{ConfigPage, MAX_CONFIG_LEN};
+use spiutils::protocol::wire::{FromWire, ToWire};
+
+use crate::hil::board_config::{BoardConfig, Client, ConfigKey, ConfigStore};
+use crate::hil::flash;
+use crate::hil::flash::h1_hw::H1_FLASH_START;
+
+// Both copies are in the reserved pages at the end of flash.
+const CONFIG_ADDRESSES: [usize; 2] = [
+    flash::h1_hw::H1_FLASH_SIZE - (5 * flash::h1_hw::H1_FLASH_PAGE_SIZE),
+    flash::h1_hw::H1_FLASH_SIZE - (6 * flash::h1_hw::H1_FLASH_PAGE_SIZE),
+];
+/// The flash pages holding the configuration, for `WriteAudit`.
+pub const CONFIG_PAGES: [usize; 2] = [
+    CONFIG_ADDRESSES[0] / flash::h1_hw::H1_FLASH_PAGE_SIZE,
+    CONFIG_ADDRESSES[1] / flash::h1_hw::H1_FLASH_PAGE_SIZE,
+];
+
+/// Number of words written by a commit. A config page fits in a single
+/// flash write.
+pub const CONFIG_WORDS: usize = MAX_CONFIG_LEN / 4;
+
+/// The active configuration as found in flash.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadedConfig {
+    /// The page holding the configuration, or None if the defaults are used.
+    slot: Option<usize>,
+    sequence: u32,
+    pub config: BoardConfig,
+}
+
+fn read_page(slot: usize) -> Option<ConfigPage> {
+    let address = H1_FLASH_START + CONFIG_ADDRESSES[slot];
+    // The flash is memory mapped; erased or torn pages fail to parse.
+    let page = unsafe { core::slice::from_raw_parts(address as *const u8, MAX_CONFIG_LEN) };
+    ConfigPage::from_wire(page).ok()
+}
+
+/// Reads the active configuration from flash, falling back to the defaults
+/// if neither page holds a valid configuration.
+pub fn load() -> LoadedConfig {
+    let mut loaded = LoadedConfig {
+        slot: None,
+        sequence: 0,
+        config: BoardConfig::default(),
+    };
+    for slot in 0..CONFIG_ADDRESSES.len() {
+        if let Some(page) = read_page(slot) {
+            let newer = page.sequence.wrapping_sub(loaded.sequence) as i32 > 0;
+            if loaded.slot.is_none() || newer {
+                loaded = LoadedConfig {
+                    slot: Some(slot),
+                    sequence: page.sequence,
+                    config: page.config,
+                };
+            }
+        }
+    }
+    loaded
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    Erasing,
+    Writing,
+}
+
+pub struct FlashConfigStore<'a> {
+    flash: &'a dyn flash::Flash<'a>,
+    client: OptionalCell<&'a dyn Client>,
+    write_buffer: TakeCell<'a, [u32]>,
+    state: Cell<State>,
+    active: BoardConfig,
+    committed: Cell<LoadedConfig>,
+    staged: Cell<BoardConfig>,
+}
+
+impl<'a> FlashConfigStore<'a> {
+    /// `loaded` is the configuration the board booted with, as returned by
+    /// `load`.
+    pub fn new(flash: &'a dyn flash::Flash<'a>,
+               loaded: LoadedConfig,
+               write_buffer: &'a mut [u32]) -> FlashConfigStore<'a> {
+        FlashConfigStore {
+            flash: flash,
+            client: OptionalCell::empty(),
+            write_buffer: TakeCell::new(write_buffer),
+            state: Cell::new(State::Idle),
+            active: loaded.config,
+            committed: Cell::new(loaded),
+            staged: Cell::new(loaded.config),
+        }
+    }
+
+    // The page the next commit goes to.
+    fn target_slot(&self) -> usize {
+        match self.committed.get().slot {
+            Some(slot) => 1 - slot,
+            None => 0,
+        }
+    }
+
+    fn write_page(&self) {
+        let buffer = match self.write_buffer.take() {
+            Some(buffer) => buffer,
+            None => {
+                self.finish(ReturnCode::ENOMEM);
+                return;
+            }
+        };
+        let page = ConfigPage {
+            sequence: self.committed.get().sequence.wrapping_add(1),
+            config: self.staged.get(),
+        };
+        // Pad with the erased value so that the rest of the page is untouched.
+        let mut bytes = [0xffu8; MAX_CONFIG_LEN];
+        if page.to_wire(Cursor::new(&mut bytes)).is_err() {
+            self.write_buffer.replace(buffer);
+            self.finish(ReturnCode::FAIL);
+            return;
+        }
+        for (word, chunk) in buffer.iter_mut().zip(bytes.chunks(4)) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        let (rcode, buffer) = self.flash.write(CONFIG_ADDRESSES[self.target_slot()] / 4, buffer);
+        match buffer {
+            None => self.state.set(State::Writing),
+            Some(buffer) => {
+                self.write_buffer.replace(buffer);
+                self.finish(if rcode == ReturnCode::SUCCESS { ReturnCode::FAIL } else { rcode });
+            }
+        }
+    }
+
+    fn finish(&self, rcode: ReturnCode) {
+        self.state.set(State::Idle);
+        self.client.map(|client| client.commit_done(rcode));
+    }
+}
+
+impl<'a> ConfigStore<'a> for FlashConfigStore<'a> {
+    fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    fn active(&self) -> BoardConfig {
+        self.active
+    }
+
+    fn staged(&self) -> BoardConfig {
+        self.staged.get()
+    }
+
+    fn set(&self, key: ConfigKey, value: u32) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let mut staged = self.staged.get();
+        if !staged.set(key, value) {
+            return ReturnCode::EINVAL;
+        }
+        self.staged.set(staged);
+        ReturnCode::SUCCESS
+    }
+
+    fn commit(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let page = CONFIG_ADDRESSES[self.target_slot()] / flash::h1_hw::H1_FLASH_PAGE_SIZE;
+        let rcode = self.flash.erase(page);
+        if rcode == ReturnCode::SUCCESS {
+            self.state.set(State::Erasing);
+        }
+        rcode
+    }
+
+    fn rollback(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.staged.set(self.committed.get().config);
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> flash::Client<'a> for FlashConfigStore<'a> {
+    fn erase_done(&self, rcode: ReturnCode) {
+        if self.state.get() != State::Erasing {
+            return;
+        }
+        if rcode == ReturnCode::SUCCESS {
+            self.write_page();
+        } else {
+            self.finish(rcode);
+        }
+    }
+
+    fn write_done(&self, data: &'a mut [u32], rcode: ReturnCode) {
+        self.write_buffer.replace(data);
+        if self.state.get() != State::Writing {
+            return;
+        }
+        if rcode == ReturnCode::SUCCESS {
+            let committed = self.committed.get();
+            self.committed.set(LoadedConfig {
+                slot: Some(self.target_slot()),
+                sequence: committed.sequence.wrapping_add(1),
+                config: self.staged.get(),
+            });
+        }
+        self.finish(rcode);
+    }
+}
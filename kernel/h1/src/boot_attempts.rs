@@ -0,0 +1,154 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Counts consecutive failed boots and decides when to boot into failsafe.
+//!
+//! Every boot increments an NvCounter. A boot that reaches its health check
+//! (reported by the app through `mark_healthy`) clears the counter again, so
+//! the counter value at boot is the number of boots in a row that never
+//! became healthy, e.g. because the app crashed during startup and the chip
+//! was reset.
+//!
+//! Once that number reaches the board's limit, `start` reports that this
+//! boot should be a failsafe boot, and clears the counter so that the boot
+//! after it tries the normal configuration again.
+//!
+//! A healthy boot can also lock the board down (see `crate::lockdown`). The
+//! lockdown closes the flash write windows, so it is engaged only once the
+//! counter has been cleared.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::ReturnCode;
+
+use crate::lockdown::{Lockdown, LockdownReason};
+use crate::nvcounter::{Client, NvCounter};
+
+pub struct BootAttempts<'a> {
+    counter: &'a dyn NvCounter<'a>,
+    max_failed_boots: usize,
+    failed_boots: Cell<usize>,
+    failsafe: Cell<bool>,
+    // Whether a counter operation is in progress.
+    busy: Cell<bool>,
+    // Whether the counter should be cleared once it is idle.
+    clear_pending: Cell<bool>,
+    healthy: Cell<bool>,
+    lockdown: OptionalCell<&'a Lockdown<'a>>,
+}
+
+impl<'a> BootAttempts<'a> {
+    /// Boots into failsafe after `max_failed_boots` failed boots in a row.
+    /// The counter's client must be set to the returned `BootAttempts`.
+    pub fn new(counter: &'a dyn NvCounter<'a>, max_failed_boots: usize) -> BootAttempts<'a> {
+        BootAttempts {
+            counter: counter,
+            max_failed_boots: max_failed_boots,
+            failed_boots: Cell::new(0),
+            failsafe: Cell::new(false),
+            busy: Cell::new(false),
+            clear_pending: Cell::new(false),
+            healthy: Cell::new(false),
+            lockdown: OptionalCell::empty(),
+        }
+    }
+
+    /// Counts this boot. Returns true if this boot should be a failsafe
+    /// boot. If the counter cannot be read, the boot is treated as normal.
+    pub fn start(&self) -> bool {
+        if let ReturnCode::SuccessWithValue { value } = self.counter.read_and_increment() {
+            self.busy.set(true);
+            self.failed_boots.set(value);
+        }
+        if self.failed_boots.get() >= self.max_failed_boots {
+            self.failsafe.set(true);
+            self.clear();
+        }
+        self.failsafe.get()
+    }
+
+    /// The number of failed boots in a row before this one.
+    pub fn failed_boots(&self) -> usize {
+        self.failed_boots.get()
+    }
+
+    /// Engages `lockdown` once this boot passes its health check.
+    pub fn set_lockdown(&self, lockdown: &'a Lockdown<'a>) {
+        self.lockdown.set(lockdown);
+    }
+
+    pub fn is_failsafe(&self) -> bool {
+        self.failsafe.get()
+    }
+
+    /// Reports that this boot passed its health check, which clears the
+    /// failed boot count and then engages the lockdown, if set. Returns
+    /// EALREADY if it was already reported.
+    pub fn mark_healthy(&self) -> ReturnCode {
+        if self.healthy.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.healthy.set(true);
+        self.clear();
+        ReturnCode::SUCCESS
+    }
+
+    fn clear(&self) {
+        if self.busy.get() {
+            self.clear_pending.set(true);
+            return;
+        }
+        self.clear_pending.set(false);
+        match self.counter.initialize() {
+            ReturnCode::SUCCESS => self.busy.set(true),
+            rcode => {
+                debug!("BootAttempts: failed to clear counter: {:?}", rcode);
+                self.lock_down_if_healthy();
+            },
+        }
+    }
+
+    // Called once a clear has finished, successfully or not.
+    fn lock_down_if_healthy(&self) {
+        if self.healthy.get() {
+            self.lockdown.map(|lockdown| lockdown.engage(LockdownReason::Healthy));
+        }
+    }
+}
+
+impl<'a> Client for BootAttempts<'a> {
+    fn initialize_done(&self, status: ReturnCode) {
+        self.busy.set(false);
+        if status != ReturnCode::SUCCESS {
+            debug!("BootAttempts: failed to clear counter: {:?}", status);
+        }
+        if self.clear_pending.get() {
+            self.clear();
+        } else {
+            self.lock_down_if_healthy();
+        }
+    }
+
+    fn increment_done(&self, status: ReturnCode) {
+        self.busy.set(false);
+        if status != ReturnCode::SUCCESS {
+            debug!("BootAttempts: failed to count boot: {:?}", status);
+        }
+        if self.clear_pending.get() {
+            self.clear();
+        }
+    }
+}
@@ -13,86 +13,132 @@
 // limitations under the License.
 
 use cortexm3;
-use crate::crypto;
-use crate::gpio;
+use crate::irq_priority::{self, InterruptGroup};
+use crate::irq_stats::InterruptCounter;
 use kernel::Chip;
-use crate::spi_host;
-use crate::spi_device;
-use crate::timels;
-use crate::trng;
-use crate::uart;
-use crate::usb;
+use kernel::common::cells::OptionalCell;
+use crate::peripherals::Peripherals;
+use crate::pmu::DeepSleep;
+use crate::watchdog::{Watchdog, WATCHDOG0_IRQ};
 
 pub struct Hotel {
     mpu: cortexm3::mpu::MPU,
     userspace_kernel_boundary: cortexm3::syscall::SysCall,
     systick: cortexm3::systick::SysTick,
+    interrupt_priorities: &'static [InterruptGroup],
+    peripherals: &'static Peripherals,
+    deep_sleep: OptionalCell<&'static DeepSleep<'static>>,
+    interrupt_counter: OptionalCell<&'static dyn InterruptCounter>,
 }
 
 impl Hotel {
-    pub unsafe fn new() -> Hotel {
+    /// Creates the chip and programs the NVIC with the board's interrupt
+    /// priority table (see `irq_priority::DEFAULT_PRIORITIES`). Interrupts
+    /// are dispatched to the drivers in `peripherals`.
+    pub unsafe fn new(peripherals: &'static Peripherals,
+                      interrupt_priorities: &'static [InterruptGroup]) -> Hotel {
+        irq_priority::apply(interrupt_priorities);
         Hotel {
             mpu: cortexm3::mpu::MPU::new(),
             userspace_kernel_boundary: cortexm3::syscall::SysCall::new(),
             systick: cortexm3::systick::SysTick::new(),
+            interrupt_priorities: interrupt_priorities,
+            peripherals: peripherals,
+            deep_sleep: OptionalCell::empty(),
+            interrupt_counter: OptionalCell::empty(),
         }
     }
+
+    /// The watchdog interrupt stays pending once the watchdog expired (see
+    /// `watchdog`), so it must not keep the kernel busy.
+    fn parked_interrupt(&self) -> Option<u32> {
+        if self.peripherals.watchdog0.is_expired() {
+            Some(WATCHDOG0_IRQ)
+        } else {
+            None
+        }
+    }
+
+    /// Lets `deep_sleep` put the chip into deep sleep when the kernel is
+    /// idle. Without it, the chip only ever sleeps lightly.
+    pub fn set_deep_sleep(&self, deep_sleep: &'static DeepSleep<'static>) {
+        self.deep_sleep.set(deep_sleep);
+    }
+
+    /// Reports every interrupt dispatched to `counter` (see `irq_stats`).
+    pub fn set_interrupt_counter(&self, counter: &'static dyn InterruptCounter) {
+        self.interrupt_counter.set(counter);
+    }
 }
 
 impl Chip for Hotel {
     type MPU = cortexm3::mpu::MPU;
     type UserspaceKernelBoundary = cortexm3::syscall::SysCall;
     type SchedulerTimer = cortexm3::systick::SysTick;
-    type WatchDog = ();
+    type WatchDog = Watchdog;
 
     fn has_pending_interrupts(&self) -> bool {
-        unsafe { cortexm3::nvic::next_pending().is_some() }
+        unsafe {
+            irq_priority::next_pending(self.interrupt_priorities, self.parked_interrupt()).is_some()
+        }
     }
 
     fn service_pending_interrupts(&self) {
+        let p = self.peripherals;
         unsafe {
-            while let Some(nvic_num) = cortexm3::nvic::next_pending() {
+            while let Some(nvic_num) =
+                irq_priority::next_pending(self.interrupt_priorities, self.parked_interrupt()) {
+                p.spi_device_probe.serviced(nvic_num);
+                self.interrupt_counter.map(|counter| counter.count(nvic_num));
                 match nvic_num {
-                    1 | 3 | 6 | 7 | 8 | 9 | 10 | 11 => crypto::dcrypto::DCRYPTO.handle_error_interrupt(nvic_num),
-                    2 => crypto::dcrypto::DCRYPTO.handle_wipe_interrupt(),
-                    4 => crypto::dcrypto::DCRYPTO.handle_done_interrupt(),
-                    5 => crypto::dcrypto::DCRYPTO.handle_receive_interrupt(),
+                    1 | 3 | 6 | 7 | 8 | 9 | 10 | 11 => p.dcrypto.handle_error_interrupt(nvic_num),
+                    2 => p.dcrypto.handle_wipe_interrupt(),
+                    4 => p.dcrypto.handle_done_interrupt(),
+                    5 => p.dcrypto.handle_receive_interrupt(),
 
                     //54 => (), // KEYMGR HKEY ALERT, ignored
-                    104..=109 => crypto::aes::KEYMGR0_AES.handle_interrupt(nvic_num),
+                    104..=109 => p.aes.handle_interrupt(nvic_num),
 
-                    110 => crypto::sha::KEYMGR0_SHA.handle_interrupt(nvic_num),
+                    110 => p.sha.handle_interrupt(nvic_num),
                     111 => (), // KEYMGR0_SHA_WFIFO_FULL
 
-                    127 => spi_host::SPI_HOST0.handle_interrupt(),
-                    128 => spi_host::SPI_HOST1.handle_interrupt(),
+                    127 => p.spi_host0.handle_interrupt(),
+                    128 => p.spi_host1.handle_interrupt(),
 
-                    131 => spi_device::SPI_DEVICE0.handle_interrupt_cmd_addr_fifo_not_empty(),
+                    131 => p.spi_device0.handle_interrupt_cmd_addr_fifo_not_empty(),
+                    134 => p.spi_device0.handle_interrupt_ram_page0_lvl(),
 
-                    159 => timels::TIMELS0.handle_interrupt(),
-                    160 => timels::TIMELS1.handle_interrupt(),
+                    159 => p.timels0.handle_interrupt(),
+                    160 => p.timels1.handle_interrupt(),
 
-                    169 => trng::TRNG0.handle_interrupt(),
+                    169 => p.trng0.handle_interrupt(),
 
-                    174 => uart::UART0.handle_rx_interrupt(),
-                    177 => uart::UART0.handle_tx_interrupt(),
-                    181 => uart::UART1.handle_rx_interrupt(),
-                    184 => uart::UART1.handle_tx_interrupt(),
-                    188 => uart::UART2.handle_rx_interrupt(),
-                    191 => uart::UART2.handle_tx_interrupt(),
+                    174 => p.uart0.handle_rx_interrupt(),
+                    177 => p.uart0.handle_tx_interrupt(),
+                    181 => p.uart1.handle_rx_interrupt(),
+                    184 => p.uart1.handle_tx_interrupt(),
+                    188 => p.uart2.handle_rx_interrupt(),
+                    191 => p.uart2.handle_tx_interrupt(),
 
                     193 => {
-                        usb::USB0.handle_interrupt()
+                        p.usb0.handle_interrupt()
+                    },
+
+                    WATCHDOG0_IRQ => {
+                        // Leave the interrupt pending and disabled, so that the
+                        // second expiry resets the chip.
+                        p.watchdog0.handle_interrupt();
+                        continue;
                     },
 
                     pin @ 65..=80 => {
-                        gpio::PORT0.pins[(pin - 65) as usize].handle_interrupt();
+                        p.gpio0.pins[(pin - 65) as usize].handle_interrupt();
                     }
                     81 => {
                         // GPIO Combined interrupt... why does this remain asserted?
                     }
                     pin @ 82..=97 => {
-                        gpio::PORT1.pins[(pin - 82) as usize].handle_interrupt();
+                        p.gpio1.pins[(pin - 82) as usize].handle_interrupt();
                     }
                     98 => {
                         // GPIO Combined interrupt... why does this remain asserted?
@@ -113,20 +159,34 @@ impl Chip for Hotel {
         &self.systick
     }
 
-    fn watchdog(&self) -> &() { &() }
+    fn watchdog(&self) -> &Watchdog {
+        &self.peripherals.watchdog0
+    }
 
     fn userspace_kernel_boundary(&self) -> &cortexm3::syscall::SysCall {
         &self.userspace_kernel_boundary
     }
 
     fn sleep(&self) {
+        // The kernel calls this with interrupts disabled, so nothing can
+        // start between the clients agreeing to sleep and the WFI.
+        let deep = self.deep_sleep.map_or(false, |deep_sleep| deep_sleep.prepare());
         unsafe {
+            if deep {
+                cortexm3::scb::set_sleepdeep();
+            } else {
                 cortexm3::scb::unset_sleepdeep();
+            }
         }
 
         unsafe {
             cortexm3::support::wfi();
         }
+
+        if deep {
+            unsafe { cortexm3::scb::unset_sleepdeep(); }
+            self.deep_sleep.map(|deep_sleep| deep_sleep.resume());
+        }
     }
 
     unsafe fn atomic<F, R>(&self, f: F) -> R
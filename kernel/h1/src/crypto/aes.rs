@@ -25,6 +25,8 @@ pub trait AES128Ecb {
 }
 
 use super::keymgr::{KEYMGR0_REGS, Registers};
+use super::marshal;
+use super::stats::{self, Engine};
 
 #[derive(Debug, Copy, Clone)]
 pub enum KeySize {
@@ -106,6 +108,8 @@ pub struct AesEngine<'a>{
     read_index: Cell<usize>,
     write_index: Cell<usize>,
     stop_index: Cell<usize>,
+    // Clock value when the block in flight was written, for the usage stats.
+    block_started: Cell<Option<u32>>,
 }
 
 impl<'a> AES128<'a> for AesEngine<'a> {
@@ -124,12 +128,7 @@ impl<'a> AES128<'a> for AesEngine<'a> {
             return ReturnCode::ESIZE;
         }
         let mut key32: [u32; 8] = [0; 8];
-        for i in 0..4 {
-            key32[i] = (key[4 * i + 0] as u32) |
-                       (key[4 * i + 1] as u32) << 8 |
-                       (key[4 * i + 2] as u32) << 16 |
-                       (key[4 * i + 3] as u32) << 24;
-        }
+        marshal::pack_words(key, &mut key32);
         self.install_key(KeySize::KeySize128, &key32);
         ReturnCode::SUCCESS
     }
@@ -140,15 +139,10 @@ impl<'a> AES128<'a> for AesEngine<'a> {
         if iv.len() != AES128_BLOCK_SIZE {
             return ReturnCode::ESIZE;
         }
-        // For each of 4 words in CTR
-        for i in 0..4 {
-            let mut val: u32 = 0;
-            // OR in each byte of the word
-            for b in 0..4 {
-                let index = (4 * i) + b;
-                val |= (iv[index] as u32) << (b * 8);
-            }
-            regs.ctr[i].set(val);
+        let mut iv32: [u32; 4] = [0; 4];
+        marshal::pack_words(iv, &mut iv32);
+        for (i, word) in iv32.iter().enumerate() {
+            regs.ctr[i].set(*word);
         }
         ReturnCode::SUCCESS
     }
@@ -219,6 +213,7 @@ impl<'a> AesEngine<'a> {
             read_index: Cell::new(0),
             write_index: Cell::new(0),
             stop_index: Cell::new(0),
+            block_started: Cell::new(None),
         }
     }
 
@@ -306,6 +301,7 @@ impl<'a> AesEngine<'a> {
 
     pub fn crypt(&self, input: &[u8]) -> usize {
         let ref regs = unsafe { &*self.regs }.aes;
+        self.block_started.set(stats::now());
 
         let mut written_bytes = 0;
         let mut written_words = 0;
@@ -313,11 +309,7 @@ impl<'a> AesEngine<'a> {
             if regs.wfifo_full.get() != 0 || written_bytes >= 16 {
                 break;
             }
-            let d = word.iter()
-                .map(|b| *b as u32)
-                .enumerate()
-                .fold(0, |accm, (i, byte)| accm | (byte << (i * 8)));
-            regs.wfifo_data.set(d);
+            regs.wfifo_data.set(marshal::pack_word(word));
             written_bytes += word.len();
             written_words += 1;
         }
@@ -335,11 +327,7 @@ impl<'a> AesEngine<'a> {
         let mut i = 0;
         while regs.rfifo_empty.get() == 0 {
             if output.len() > i + 3 {
-                let word = regs.rfifo_data.get();
-                output[i + 0] = (word >> 0) as u8;
-                output[i + 1] = (word >> 8) as u8;
-                output[i + 2] = (word >> 16) as u8;
-                output[i + 3] = (word >> 24) as u8;
+                marshal::unpack_word(regs.rfifo_data.get(), &mut output[i..]);
                 i += 4;
             } else {
                 println!("Can't read any more data");
@@ -350,6 +338,99 @@ impl<'a> AesEngine<'a> {
         i
     }
 
+    /// Returns true if an interrupt-driven operation is in progress.
+    pub fn is_busy(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Encrypts a single block in place using AES-256 in ECB mode, polling for
+    /// completion instead of waiting for an interrupt.
+    ///
+    /// The installed key and mode are clobbered, so this must only be used in
+    /// between interrupt-driven operations, which install their own key.
+    /// Returns EBUSY if such an operation is in progress.
+    pub fn encrypt_block_blocking(&self, key: &[u8; 32], block: &mut [u8; AES128_BLOCK_SIZE])
+        -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+
+        let mut key32: [u32; 8] = [0; 8];
+        marshal::pack_words(key, &mut key32);
+        self.set_mode_aes128ecb(true);
+        self.install_key(KeySize::KeySize256, &key32);
+        self.crypt_block_polled(block);
+
+        ReturnCode::SUCCESS
+    }
+
+    /// Encrypts a single block in place in ECB mode with the installed key,
+    /// polling for completion. Leaves the engine in ECB mode.
+    /// Returns EBUSY if an interrupt-driven operation is in progress.
+    pub fn encrypt_block_polled(&self, block: &mut [u8; AES128_BLOCK_SIZE]) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+
+        self.set_mode_aes128ecb(true);
+        self.crypt_block_polled(block);
+        ReturnCode::SUCCESS
+    }
+
+    fn crypt_block_polled(&self, block: &mut [u8; AES128_BLOCK_SIZE]) {
+        let ref regs = unsafe { &*self.regs }.aes;
+
+        // Don't let the completion reach the client of interrupt-driven operations.
+        let int_enable = regs.int_enable.get();
+        regs.int_enable.set(int_enable & !(1 << Interrupt::DoneCipher as usize));
+
+        self.crypt(&block[..]);
+        while regs.rfifo_level.get() < 4 {}
+        self.read_data(&mut block[..]);
+        stats::record(Engine::Aes, 1, AES128_BLOCK_SIZE, self.block_started.take());
+
+        self.clear_interrupt(Interrupt::DoneCipher);
+        regs.int_enable.set(int_enable);
+    }
+
+    /// Folds `block` into the GHASH accumulator `mac` with the hash key `h`,
+    /// using the key manager's GF(2^128) multiplier. A block shorter than 16
+    /// bytes is padded with zeros.
+    ///
+    /// The hash key and accumulator are loaded for each block and cleared
+    /// from the engine afterwards, so GCM operations of different apps can
+    /// be interleaved with each other and with other modes.
+    pub fn ghash_block(&self, h: &[u8; AES128_BLOCK_SIZE], mac: &mut [u8; AES128_BLOCK_SIZE],
+                       block: &[u8]) {
+        let ref regs = unsafe { &*self.regs }.aes;
+
+        let mut words: [u32; 4] = [0; 4];
+        marshal::pack_words(h, &mut words);
+        for (reg, word) in regs.gcm_h.iter().zip(words.iter()) {
+            reg.set(*word);
+        }
+        marshal::pack_words(&mac[..], &mut words);
+        for (reg, word) in regs.gcm_mac.iter().zip(words.iter()) {
+            reg.set(*word);
+        }
+        marshal::pack_words(block, &mut words);
+        for (reg, word) in regs.gcm_hash_in.iter().zip(words.iter()) {
+            reg.set(*word);
+        }
+
+        // Computes mac = (mac ^ hash_in) * h.
+        regs.gcm_do_acc.set(1);
+
+        for (reg, word) in regs.gcm_mac.iter().zip(words.iter_mut()) {
+            *word = reg.get();
+        }
+        marshal::unpack_words(&words, &mut mac[..]);
+
+        for reg in regs.gcm_h.iter().chain(regs.gcm_mac.iter()) {
+            reg.set(0);
+        }
+    }
+
     pub fn enable_all_interrupts(&self) {
         self.enable_interrupt(Interrupt::WFIFOOverflow);
         self.enable_interrupt(Interrupt::RFIFOOverflow);
@@ -359,6 +440,14 @@ impl<'a> AesEngine<'a> {
         self.enable_interrupt(Interrupt::DoneWipeSecrets);
     }
 
+    /// Clears the key and chaining state from the engine without changing
+    /// its configuration.
+    pub fn wipe_secrets(&self) {
+        let ref regs = unsafe { &*self.regs }.aes;
+
+        regs.wipe_secrets.set(1);
+    }
+
     pub fn finish(&self) {
         let ref regs = unsafe { &*self.regs }.aes;
 
@@ -382,6 +471,9 @@ impl<'a> AesEngine<'a> {
 
     pub fn handle_interrupt(&self, interrupt: u32) {
         if let ParsedInterrupt::Found(int) = interrupt.into() {
+            if let Interrupt::DoneCipher = int {
+                stats::record(Engine::Aes, 1, AES128_BLOCK_SIZE, self.block_started.take());
+            }
             self.client.map(|client| match int {
                 Interrupt::DoneCipher => client.crypt_done(self.input.take(), self.output.take().unwrap() ),
                 _ => {}
@@ -393,4 +485,6 @@ impl<'a> AesEngine<'a> {
     }
 }
 
-pub static mut KEYMGR0_AES: AesEngine = unsafe { AesEngine::new(KEYMGR0_REGS) };
+pub(crate) const unsafe fn keymgr0_aes() -> AesEngine<'static> {
+    AesEngine::new(KEYMGR0_REGS)
+}
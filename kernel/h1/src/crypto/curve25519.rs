@@ -0,0 +1,577 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dcrypto program for Curve25519.
+//!
+//! The program does the point arithmetic for X25519 and Ed25519 on the
+//! dcrypto engine. Hashing and arithmetic modulo the group order are left
+//! to the caller; see `ecc::curve25519::ExpandedKey`.
+//!
+//! Field elements are kept modulo 2p = 2^256 - 38 inside the program, so
+//! that the Barrett multiplication (MulMod) of the cr50 P-256 program can be
+//! reused unchanged, and are reduced modulo p = 2^255 - 19 before they are
+//! stored.
+//!
+//! Data memory is six 32-byte cells of little-endian integers. Cell 0 holds
+//! the pointers to the other cells and is filled in by `new_dmem`. The
+//! entry points use the others as follows:
+//!   `X25519`: X25519(k, u) for the clamped scalar k in `K0` and u, with
+//!     its top bit cleared, in `X`. The result replaces u.
+//!   `ED25519_MUL`: [k0]B + [k1]P for scalars in `K0` and `K1` and the
+//!     point P in `X` and `Y`. The affine result replaces P.
+//!   `ED25519_VERIFY`: [k0]B - [k1]A, where A is given by its y-coordinate
+//!     (below p) in `Y` and the sign of its x-coordinate in `FLAG`. The
+//!     affine result is stored in `X` and `Y`, and `FLAG` is set to 1 if A
+//!     is on the curve and to 0 otherwise.
+//!
+//! The scalar multiplications run in constant time. Only the point
+//! decoding in `ED25519_VERIFY` branches, on public data.
+//!
+//! `PROGRAM` and the entry points are generated from curve25519.dasm by
+//! tools/dcrypto_asm, whose tests check that they match the source.
+
+use super::dcrypto::{self, Dcrypto};
+use kernel::ReturnCode;
+
+/// Size in bytes of a data memory cell.
+pub const CELL_LEN: usize = 32;
+
+/// Size in bytes of the data memory used by the program.
+pub const DMEM_LEN: usize = 6 * CELL_LEN;
+
+/// Byte offsets of the cells in data memory.
+pub const K0: usize = CELL_LEN;
+pub const K1: usize = 2 * CELL_LEN;
+pub const X: usize = 3 * CELL_LEN;
+pub const Y: usize = 4 * CELL_LEN;
+pub const FLAG: usize = 5 * CELL_LEN;
+
+/// Entry points, as IMEM word addresses.
+pub const X25519: u32 = 86;
+pub const ED25519_MUL: u32 = 332;
+pub const ED25519_VERIFY: u32 = 340;
+
+/// Returns a data memory image with the pointer cell filled in and all
+/// other cells zero.
+pub fn new_dmem() -> [u8; DMEM_LEN] {
+    let mut dmem = [0u8; DMEM_LEN];
+    for cell in 1..6 {
+        dmem[4 * (cell - 1)] = cell as u8;
+    }
+    dmem
+}
+
+/// Loads the program at the start of IMEM.
+pub fn load<'a>(dcrypto: &dyn Dcrypto<'a>) -> ReturnCode {
+    dcrypto::load_program(dcrypto, &PROGRAM)
+}
+
+#[rustfmt::skip]
+static PROGRAM: [u32; 447] = [
+    // @0x0: function Setup25519[9] {
+    0x847c4000, // ldi r31, [#0]
+    0x4c7fff00, // xor r31, r31, r31
+    0x51781f01, // addi r30, r31, #1
+    0x55741f01, // subi r29, r31, #1
+    0x8074ffda, // movi r29.0l, #65498
+    0x98801d00, // ldmod r29
+    0x7c701f00, // mov r28, r31
+    0x80700026, // movi r28.0l, #38
+    0x0c000000, // ret
+    // }
+    // @0x9: function LoadPointers[12] {
+    0x84404000, // ldi r16, [#0]
+    0x95801000, // lddmp r16
+    0x7c401f00, // mov r16, r31
+    0x80c00001, // movi r16.1l, #1
+    0x81400006, // movi r16.2l, #6
+    0x81c00007, // movi r16.3l, #7
+    0x82400008, // movi r16.4l, #8
+    0x82c00009, // movi r16.5l, #9
+    0x8340001e, // movi r16.6l, #30
+    0x83c0001f, // movi r16.7l, #31
+    0x97801000, // ldrfp r16
+    0x0c000000, // ret
+    // }
+    // @0x15: function MulMod[38] {
+    0x584f3800, // mul128 r19, r24l, r25l
+    0x59d33800, // mul128 r20, r24u, r25u
+    0x58d73800, // mul128 r21, r24u, r25l
+    0x504eb310, // add r19, r19, r21 << 128
+    0x50d2b490, // addc r20, r20, r21 >> 128
+    0x59573800, // mul128 r21, r24l, r25u
+    0x504eb310, // add r19, r19, r21 << 128
+    0x50d2b490, // addc r20, r20, r21 >> 128
+    0x645bfc02, // selm r22, r28, r31
+    0x685693ff, // rshi r21, r19, r20 >> 255
+    0x585f9500, // mul128 r23, r21l, r28l
+    0x59e39500, // mul128 r24, r21u, r28u
+    0x58e79500, // mul128 r25, r21u, r28l
+    0x505f3710, // add r23, r23, r25 << 128
+    0x50e33890, // addc r24, r24, r25 >> 128
+    0x59679500, // mul128 r25, r21l, r28u
+    0x505f3710, // add r23, r23, r25 << 128
+    0x50e33890, // addc r24, r24, r25 >> 128
+    0x6867f4ff, // rshi r25, r20, r31 >> 255
+    0x5062b800, // add r24, r24, r21
+    0x50e7f900, // addc r25, r25, r31
+    0x5062d800, // add r24, r24, r22
+    0x50e7f900, // addc r25, r25, r31
+    0x68573801, // rshi r21, r24, r25 >> 1
+    0x585abd00, // mul128 r22, r29l, r21l
+    0x59debd00, // mul128 r23, r29u, r21u
+    0x58e2bd00, // mul128 r24, r29u, r21l
+    0x505b1610, // add r22, r22, r24 << 128
+    0x50df1790, // addc r23, r23, r24 >> 128
+    0x5962bd00, // mul128 r24, r29l, r21u
+    0x505b1610, // add r22, r22, r24 << 128
+    0x50df1790, // addc r23, r23, r24 >> 128
+    0x545ad300, // sub r22, r19, r22
+    0x54d2f400, // subb r20, r20, r23
+    0x6457fd01, // sell r21, r29, r31
+    0x5456b600, // sub r21, r22, r21
+    0x9c4ff500, // addm r19, r21, r31
+    0x0c000000, // ret
+    // }
+    // r19 = r19 mod p, for r19 < 2p.
+    // @0x3b: function Canonical[7] {
+    0x55601f01, // subi r24, r31, #1
+    0x83e17fff, // movi r24.7h, #32767
+    0x8060ffed, // movi r24.0l, #65517
+    0x98801800, // ldmod r24
+    0x9c4ff300, // addm r19, r19, r31
+    0x98801d00, // ldmod r29
+    0x0c000000, // ret
+    // }
+    // r16 = r17 ^ r18. Runs in constant time.
+    // @0x42: function ModExp[12] {
+    0x7c401e00, // mov r16, r30
+    0x05100009, // loop #256 (
+        0x7c601000, // mov r24, r16
+        0x7c641000, // mov r25, r16
+        0x08000015, // call &MulMod
+        0x7c401300, // mov r16, r19
+        0x7c601300, // mov r24, r19
+        0x7c641100, // mov r25, r17
+        0x08000015, // call &MulMod
+        0x504a5200, // add r18, r18, r18
+        0x64421308, // selc r16, r19, r16
+    // )
+    0x0c000000, // ret
+    // }
+    // r18 = p - 2.
+    // @0x4e: function SetupInvExp[4] {
+    0x55481f01, // subi r18, r31, #1
+    0x83c97fff, // movi r18.7h, #32767
+    0x8048ffeb, // movi r18.0l, #65515
+    0x0c000000, // ret
+    // }
+    // r18 = (p - 5) / 8.
+    // @0x52: function SetupSqrtExp[4] {
+    0x55481f01, // subi r18, r31, #1
+    0x83c90fff, // movi r18.7h, #4095
+    0x8048fffd, // movi r18.0l, #65533
+    0x0c000000, // ret
+    // }
+    // X25519: scalar (clamped) in k0, u in x; result replaces x.
+    // @0x56: function x25519[87] {
+    0x08000000, // call &Setup25519
+    0x08000009, // call &LoadPointers
+    0x8c001000, // ld *0, *0
+    0x8c101200, // ld *4, *2
+    0x7c040800, // mov r1, r8
+    0x7c280000, // mov r10, r0
+    0x50000000, // add r0, r0, r0
+    0x50294a00, // add r10, r10, r10
+    0x7c081e00, // mov r2, r30
+    0x7c0c1f00, // mov r3, r31
+    0x7c100100, // mov r4, r1
+    0x7c141e00, // mov r5, r30
+    0x7c6c1f00, // mov r27, r31
+    0x806cdb41, // movi r27.0l, #56129
+    0x806d0001, // movi r27.0h, #1
+    0x050ff03d, // loop #255 (
+        0x50000000, // add r0, r0, r0
+        0x64184408, // selc r6, r4, r2
+        0x641c8208, // selc r7, r2, r4
+        0x64206508, // selc r8, r5, r3
+        0x6424a308, // selc r9, r3, r5
+        0x7c080600, // mov r2, r6
+        0x7c100700, // mov r4, r7
+        0x7c0c0800, // mov r3, r8
+        0x7c140900, // mov r5, r9
+        0x9c186200, // addm r6, r2, r3
+        0xa01c6200, // subm r7, r2, r3
+        0x9c20a400, // addm r8, r4, r5
+        0xa024a400, // subm r9, r4, r5
+        0x7c600900, // mov r24, r9
+        0x7c640600, // mov r25, r6
+        0x08000015, // call &MulMod
+        0x7c241300, // mov r9, r19
+        0x7c600800, // mov r24, r8
+        0x7c640700, // mov r25, r7
+        0x08000015, // call &MulMod
+        0x7c201300, // mov r8, r19
+        0x7c600600, // mov r24, r6
+        0x7c640600, // mov r25, r6
+        0x08000015, // call &MulMod
+        0x7c181300, // mov r6, r19
+        0x7c600700, // mov r24, r7
+        0x7c640700, // mov r25, r7
+        0x08000015, // call &MulMod
+        0x7c1c1300, // mov r7, r19
+        0x9c610900, // addm r24, r9, r8
+        0x7c641800, // mov r25, r24
+        0x08000015, // call &MulMod
+        0x7c101300, // mov r4, r19
+        0xa0610900, // subm r24, r9, r8
+        0x7c641800, // mov r25, r24
+        0x08000015, // call &MulMod
+        0x7c601300, // mov r24, r19
+        0x7c640100, // mov r25, r1
+        0x08000015, // call &MulMod
+        0x7c141300, // mov r5, r19
+        0x7c600600, // mov r24, r6
+        0x7c640700, // mov r25, r7
+        0x08000015, // call &MulMod
+        0x7c081300, // mov r2, r19
+        0xa024e600, // subm r9, r6, r7
+        0x7c601b00, // mov r24, r27
+        0x7c640900, // mov r25, r9
+        0x08000015, // call &MulMod
+        0x9c60d300, // addm r24, r19, r6
+        0x7c640900, // mov r25, r9
+        0x08000015, // call &MulMod
+        0x7c0c1300, // mov r3, r19
+        0x50294a00, // add r10, r10, r10
+        0x64184408, // selc r6, r4, r2
+        0x641c8208, // selc r7, r2, r4
+        0x64206508, // selc r8, r5, r3
+        0x6424a308, // selc r9, r3, r5
+        0x7c080600, // mov r2, r6
+        0x7c100700, // mov r4, r7
+        0x7c0c0800, // mov r3, r8
+        0x7c140900, // mov r5, r9
+    // )
+    0x7c440300, // mov r17, r3
+    0x0800004e, // call &SetupInvExp
+    0x08000042, // call &ModExp
+    0x7c600200, // mov r24, r2
+    0x7c641000, // mov r25, r16
+    0x08000015, // call &MulMod
+    0x0800003b, // call &Canonical
+    0x7c201300, // mov r8, r19
+    0x90480400, // st *4, *2
+    0x0c000000, // ret
+    // }
+    // Adds (r8, r9, r10, r11) and (r12, r13, r14, r15), both in extended
+    // coordinates, into (r8, r9, r10, r11). r27 holds 2d.
+    // @0xad: function EdAdd[44] {
+    0xa0410900, // subm r16, r9, r8
+    0xa0458d00, // subm r17, r13, r12
+    0x7c601000, // mov r24, r16
+    0x7c641100, // mov r25, r17
+    0x08000015, // call &MulMod
+    0x7c401300, // mov r16, r19
+    0x9c450900, // addm r17, r9, r8
+    0x9c498d00, // addm r18, r13, r12
+    0x7c601100, // mov r24, r17
+    0x7c641200, // mov r25, r18
+    0x08000015, // call &MulMod
+    0x7c441300, // mov r17, r19
+    0x7c600b00, // mov r24, r11
+    0x7c640f00, // mov r25, r15
+    0x08000015, // call &MulMod
+    0x7c601300, // mov r24, r19
+    0x7c641b00, // mov r25, r27
+    0x08000015, // call &MulMod
+    0x7c481300, // mov r18, r19
+    0x7c600a00, // mov r24, r10
+    0x7c640e00, // mov r25, r14
+    0x08000015, // call &MulMod
+    0x9c6a7300, // addm r26, r19, r19
+    0xa0321100, // subm r12, r17, r16
+    0xa0365a00, // subm r13, r26, r18
+    0x9c3a5a00, // addm r14, r26, r18
+    0x9c3e1100, // addm r15, r17, r16
+    0x7c600c00, // mov r24, r12
+    0x7c640d00, // mov r25, r13
+    0x08000015, // call &MulMod
+    0x7c201300, // mov r8, r19
+    0x7c600e00, // mov r24, r14
+    0x7c640f00, // mov r25, r15
+    0x08000015, // call &MulMod
+    0x7c241300, // mov r9, r19
+    0x7c600d00, // mov r24, r13
+    0x7c640e00, // mov r25, r14
+    0x08000015, // call &MulMod
+    0x7c281300, // mov r10, r19
+    0x7c600c00, // mov r24, r12
+    0x7c640f00, // mov r25, r15
+    0x08000015, // call &MulMod
+    0x7c2c1300, // mov r11, r19
+    0x0c000000, // ret
+    // }
+    // (r8, r9) = [r0]B + [r1]P in affine coordinates, with P = (r5, r6) and
+    // r7 = x y of P. Runs in constant time.
+    // @0xd9: function EdMul[105] {
+    0x7c081f00, // mov r2, r31
+    0x8008d51a, // movi r2.0l, #54554
+    0x80098f25, // movi r2.0h, #36645
+    0x80882d60, // movi r2.1l, #11616
+    0x8089c956, // movi r2.1h, #51542
+    0x8108a7b2, // movi r2.2l, #42930
+    0x81099525, // movi r2.2h, #38181
+    0x8188c760, // movi r2.3l, #51040
+    0x8189692c, // movi r2.3h, #26924
+    0x8208dc5c, // movi r2.4l, #56412
+    0x8209fdd6, // movi r2.4h, #64982
+    0x8288e231, // movi r2.5l, #57905
+    0x8289c0a4, // movi r2.5h, #49316
+    0x830853fe, // movi r2.6l, #21502
+    0x8309cd6e, // movi r2.6h, #52590
+    0x838836d3, // movi r2.7l, #14035
+    0x83892169, // movi r2.7h, #8553
+    0x7c0c1f00, // mov r3, r31
+    0x800c6658, // movi r3.0l, #26200
+    0x800d6666, // movi r3.0h, #26214
+    0x808c6666, // movi r3.1l, #26214
+    0x808d6666, // movi r3.1h, #26214
+    0x810c6666, // movi r3.2l, #26214
+    0x810d6666, // movi r3.2h, #26214
+    0x818c6666, // movi r3.3l, #26214
+    0x818d6666, // movi r3.3h, #26214
+    0x820c6666, // movi r3.4l, #26214
+    0x820d6666, // movi r3.4h, #26214
+    0x828c6666, // movi r3.5l, #26214
+    0x828d6666, // movi r3.5h, #26214
+    0x830c6666, // movi r3.6l, #26214
+    0x830d6666, // movi r3.6h, #26214
+    0x838c6666, // movi r3.7l, #26214
+    0x838d6666, // movi r3.7h, #26214
+    0x7c101f00, // mov r4, r31
+    0x8010dda3, // movi r4.0l, #56739
+    0x8011a5b7, // movi r4.0h, #42423
+    0x80908ab3, // movi r4.1l, #35507
+    0x80916dde, // movi r4.1h, #28126
+    0x811052f5, // movi r4.2l, #21237
+    0x81117751, // movi r4.2h, #30545
+    0x81909f80, // movi r4.3l, #40832
+    0x819120f0, // movi r4.3h, #8432
+    0x8210e37d, // movi r4.4l, #58237
+    0x821164ab, // movi r4.4h, #25771
+    0x82904e8e, // movi r4.5l, #20110
+    0x829166ea, // movi r4.5h, #26346
+    0x83107665, // movi r4.6l, #30309
+    0x8311d78b, // movi r4.6h, #55179
+    0x83905f0f, // movi r4.7l, #24335
+    0x83916787, // movi r4.7h, #26503
+    0x7c6c1f00, // mov r27, r31
+    0x806cf159, // movi r27.0l, #61785
+    0x806d26b2, // movi r27.0h, #9906
+    0x80ec9b94, // movi r27.1l, #39828
+    0x80edebd6, // movi r27.1h, #60374
+    0x816cb156, // movi r27.2l, #45398
+    0x816d8283, // movi r27.2h, #33411
+    0x81ec149a, // movi r27.3l, #5274
+    0x81ed00e0, // movi r27.3h, #224
+    0x826cd130, // movi r27.4l, #53552
+    0x826deef3, // movi r27.4h, #61171
+    0x82ec80f2, // movi r27.5l, #33010
+    0x82ed198e, // movi r27.5h, #6542
+    0x836cfce7, // movi r27.6l, #64743
+    0x836d56df, // movi r27.6h, #22239
+    0x83ecd9dc, // movi r27.7l, #55772
+    0x83ed2406, // movi r27.7h, #9222
+    0x7c201f00, // mov r8, r31
+    0x7c241e00, // mov r9, r30
+    0x7c281e00, // mov r10, r30
+    0x7c2c1f00, // mov r11, r31
+    0x05100012, // loop #256 (
+        0x7c300800, // mov r12, r8
+        0x7c340900, // mov r13, r9
+        0x7c380a00, // mov r14, r10
+        0x7c3c0b00, // mov r15, r11
+        0x080000ad, // call &EdAdd
+        0x50000000, // add r0, r0, r0
+        0x6433e208, // selc r12, r2, r31
+        0x6437c308, // selc r13, r3, r30
+        0x643fe408, // selc r15, r4, r31
+        0x7c381e00, // mov r14, r30
+        0x080000ad, // call &EdAdd
+        0x50042100, // add r1, r1, r1
+        0x6433e508, // selc r12, r5, r31
+        0x6437c608, // selc r13, r6, r30
+        0x643fe708, // selc r15, r7, r31
+        0x7c381e00, // mov r14, r30
+        0x080000ad, // call &EdAdd
+        0xfc000000, // nop
+    // )
+    0x7c440a00, // mov r17, r10
+    0x0800004e, // call &SetupInvExp
+    0x08000042, // call &ModExp
+    0x7c600800, // mov r24, r8
+    0x7c641000, // mov r25, r16
+    0x08000015, // call &MulMod
+    0x0800003b, // call &Canonical
+    0x7c201300, // mov r8, r19
+    0x7c600900, // mov r24, r9
+    0x7c641000, // mov r25, r16
+    0x08000015, // call &MulMod
+    0x0800003b, // call &Canonical
+    0x7c241300, // mov r9, r19
+    0x0c000000, // ret
+    // }
+    // Loads the scalars, multiplies and stores the result in x and y.
+    // @0x142: function EdMulStore[10] {
+    0x7c600500, // mov r24, r5
+    0x7c640600, // mov r25, r6
+    0x08000015, // call &MulMod
+    0x7c1c1300, // mov r7, r19
+    0x8c001000, // ld *0, *0
+    0x8c041100, // ld *1, *1
+    0x080000d9, // call &EdMul
+    0x90480400, // st *4, *2
+    0x904c0500, // st *5, *3
+    0x0c000000, // ret
+    // }
+    // [k0]B + [k1]P, with P in x and y.
+    // @0x14c: function ed25519mul[8] {
+    0x08000000, // call &Setup25519
+    0x08000009, // call &LoadPointers
+    0x8c101200, // ld *4, *2
+    0x8c141300, // ld *5, *3
+    0x7c140800, // mov r5, r8
+    0x7c180900, // mov r6, r9
+    0x08000142, // call &EdMulStore
+    0x0c000000, // ret
+    // }
+    // [k0]B - [k1]A, with A encoded as y < p and the sign of x in flag. flag
+    // is set to 1 if A decodes and to 0 otherwise.
+    // @0x154: function ed25519verify[107] {
+    0x08000000, // call &Setup25519
+    0x08000009, // call &LoadPointers
+    0x8c081300, // ld *2, *3
+    0x8c0c1400, // ld *3, *4
+    0x7c6c1f00, // mov r27, r31
+    0x806c78a3, // movi r27.0l, #30883
+    0x806d1359, // movi r27.0h, #4953
+    0x80ec4dca, // movi r27.1l, #19914
+    0x80ed75eb, // movi r27.1h, #30187
+    0x816cd8ab, // movi r27.2l, #55467
+    0x816d4141, // movi r27.2h, #16705
+    0x81ec0a4d, // movi r27.3l, #2637
+    0x81ed0070, // movi r27.3h, #112
+    0x826ce898, // movi r27.4l, #59544
+    0x826d7779, // movi r27.4h, #30585
+    0x82ec4079, // movi r27.5l, #16505
+    0x82ed8cc7, // movi r27.5h, #36039
+    0x836cfe73, // movi r27.6l, #65139
+    0x836d2b6f, // movi r27.6h, #11119
+    0x83ec6cee, // movi r27.7l, #27886
+    0x83ed5203, // movi r27.7h, #20995
+    0x7c600600, // mov r24, r6
+    0x7c640600, // mov r25, r6
+    0x08000015, // call &MulMod
+    0x7c081300, // mov r2, r19
+    0xa00fc200, // subm r3, r2, r30
+    0x7c601b00, // mov r24, r27
+    0x7c640200, // mov r25, r2
+    0x08000015, // call &MulMod
+    0x9c13d300, // addm r4, r19, r30
+    0x7c600400, // mov r24, r4
+    0x7c640400, // mov r25, r4
+    0x08000015, // call &MulMod
+    0x7c601300, // mov r24, r19
+    0x7c640400, // mov r25, r4
+    0x08000015, // call &MulMod
+    0x7c081300, // mov r2, r19
+    0x7c601300, // mov r24, r19
+    0x7c641300, // mov r25, r19
+    0x08000015, // call &MulMod
+    0x7c601300, // mov r24, r19
+    0x7c640400, // mov r25, r4
+    0x08000015, // call &MulMod
+    0x7c601300, // mov r24, r19
+    0x7c640300, // mov r25, r3
+    0x08000015, // call &MulMod
+    0x7c441300, // mov r17, r19
+    0x08000052, // call &SetupSqrtExp
+    0x08000042, // call &ModExp
+    0x7c600300, // mov r24, r3
+    0x7c640200, // mov r25, r2
+    0x08000015, // call &MulMod
+    0x7c601300, // mov r24, r19
+    0x7c641000, // mov r25, r16
+    0x08000015, // call &MulMod
+    0x7c141300, // mov r5, r19
+    0x7c601300, // mov r24, r19
+    0x7c641300, // mov r25, r19
+    0x08000015, // call &MulMod
+    0x7c601300, // mov r24, r19
+    0x7c640400, // mov r25, r4
+    0x08000015, // call &MulMod
+    0x7c081300, // mov r2, r19
+    0xa04c6200, // subm r19, r2, r3
+    0x0800003b, // call &Canonical
+    0x5c03f300, // cmp r19, r31
+    0x100041b0, // bz root
+    0x9c4c6200, // addm r19, r2, r3
+    0x0800003b, // call &Canonical
+    0x5c03f300, // cmp r19, r31
+    0x100841bd, // bnz fail
+    0x7c6c1f00, // mov r27, r31
+    0x806ca0b0, // movi r27.0l, #41136
+    0x806d4a0e, // movi r27.0h, #18958
+    0x80ec1b27, // movi r27.1l, #6951
+    0x80edc4ee, // movi r27.1h, #50414
+    0x816ce478, // movi r27.2l, #58488
+    0x816dad2f, // movi r27.2h, #44335
+    0x81ec1806, // movi r27.3l, #6150
+    0x81ed2f43, // movi r27.3h, #12099
+    0x826cd7a7, // movi r27.4l, #55207
+    0x826d3dfb, // movi r27.4h, #15867
+    0x82ec0099, // movi r27.5l, #153
+    0x82ed2b4d, // movi r27.5h, #11085
+    0x836cdf0b, // movi r27.6l, #57099
+    0x836d4fc1, // movi r27.6h, #20417
+    0x83ec2480, // movi r27.7l, #9344
+    0x83ed2b83, // movi r27.7h, #11139
+    0x7c600500, // mov r24, r5
+    0x7c641b00, // mov r25, r27
+    0x08000015, // call &MulMod
+    0x7c141300, // mov r5, r19
+    // root:
+    0x7c4c0500, // mov r19, r5
+    0x0800003b, // call &Canonical
+    0x7c141300, // mov r5, r19
+    0x5c03e500, // cmp r5, r31
+    0x100841b7, // bnz nonzero
+    0x5c03e700, // cmp r7, r31
+    0x100841bd, // bnz fail
+    // nonzero:
+    0x4c08e500, // xor r2, r5, r7
+    0x100011ba, // bl negated
+    0xa014bf00, // subm r5, r31, r5
+    // negated:
+    0x90500600, // st *6, *4
+    0x08000142, // call &EdMulStore
+    0x0c000000, // ret
+    // fail:
+    0x90500700, // st *7, *4
+    0x0c000000, // ret
+    // }
+];
@@ -56,6 +56,7 @@ use kernel::common::cells::TakeCell;
 use kernel::common::cells::VolatileCell;
 use kernel::ReturnCode;
 
+use super::stats::{self, Engine};
 use crate::pmu::{Clock, PeripheralClock, PeripheralClock0, reset_dcrypto};
 
 
@@ -65,7 +66,9 @@ use crate::pmu::{Clock, PeripheralClock, PeripheralClock0, reset_dcrypto};
 const DCRYPTO_BASE_ADDR: u32 = 0x40420000;
 const DCRYPTO_BASE: *mut Registers = DCRYPTO_BASE_ADDR as *mut Registers;
 
-pub static mut DCRYPTO: DcryptoEngine<'static> = unsafe {DcryptoEngine::new(DCRYPTO_BASE) };
+pub(crate) const unsafe fn dcrypto() -> DcryptoEngine<'static> {
+    DcryptoEngine::new(DCRYPTO_BASE)
+}
 
 
 const DROM_OFFSET: u32 = 0x2000;
@@ -227,6 +230,25 @@ pub trait Dcrypto<'a> {
     fn wipe_secrets(&self) -> ReturnCode;
 }
 
+// Words copied to IMEM per call to write_instructions.
+const LOAD_CHUNK_LEN: usize = 32;
+
+/// Loads a program built into the kernel at the start of IMEM.
+pub fn load_program<'a>(dcrypto: &dyn Dcrypto<'a>, program: &[u32]) -> ReturnCode {
+    let mut bytes = [0u8; 4 * LOAD_CHUNK_LEN];
+    for (index, chunk) in program.chunks(LOAD_CHUNK_LEN).enumerate() {
+        for (word, out) in chunk.iter().zip(bytes.chunks_mut(4)) {
+            out.copy_from_slice(&word.to_le_bytes());
+        }
+        let rcode = dcrypto.write_instructions(&bytes, (index * LOAD_CHUNK_LEN) as u32,
+                                               chunk.len() as u32);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+    }
+    ReturnCode::SUCCESS
+}
+
 #[repr(C)]
 struct Registers {
     pub version: VolatileCell<u32>,      // 0x0000
@@ -258,7 +280,9 @@ pub struct DcryptoEngine<'a> {
     state: Cell<State>,
     drom: TakeCell<'static, [u32; DROM_SIZE]>,
     dmem: TakeCell<'static, [u32; DMEM_SIZE]>,
-    imem: TakeCell<'static, [u32; IMEM_SIZE]>
+    imem: TakeCell<'static, [u32; IMEM_SIZE]>,
+    // Clock value when the running program was started, for the usage stats.
+    program_started: Cell<Option<u32>>,
 }
 
 impl<'a> DcryptoEngine<'a> {
@@ -270,6 +294,7 @@ impl<'a> DcryptoEngine<'a> {
             drom: TakeCell::empty(),
             dmem: TakeCell::empty(),
             imem: TakeCell::empty(),
+            program_started: Cell::new(None),
         }
     }
 
@@ -413,6 +438,7 @@ impl<'a> DcryptoEngine<'a> {
             cause == ProgramFault::LoopUnderflow ||
             cause == ProgramFault::StackOverflow)
         {
+            stats::record(Engine::Dcrypto, 1, 0, self.program_started.take());
             self.client.get().map(|client| {
                 println!("DCRYPTO engine had a {:?} error but was in state {:?}, HW state is {:?}.", cause, prior_state, status);
                 client.execution_complete(ReturnCode::FAIL, cause);
@@ -446,6 +472,7 @@ impl<'a> DcryptoEngine<'a> {
                     _            => ProgramFault::Unknown
                 };
                 self.state.set(State::Halt);
+                stats::record(Engine::Dcrypto, 1, 0, self.program_started.take());
                 self.client.get().map(|client| {
                         client.execution_complete(ReturnCode::SUCCESS, fault);
                 });
@@ -515,6 +542,7 @@ impl<'a> Dcrypto<'a> for DcryptoEngine<'a> {
                 mem[(offset + i) as usize] = word;
             }
         });
+        stats::record(Engine::Dcrypto, 0, length as usize * 4, None);
         ReturnCode::SUCCESS
     }
 
@@ -608,6 +636,9 @@ impl<'a> Dcrypto<'a> for DcryptoEngine<'a> {
             registers.int_state.get() & 0x3 != 0
         }{}
 
+        if is_call {
+            self.program_started.set(stats::now());
+        }
         registers.host_cmd.set(instruction);
         if is_call {
             self.state.set(State::Running);
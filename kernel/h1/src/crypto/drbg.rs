@@ -0,0 +1,218 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! CTR_DRBG (NIST SP 800-90A) using AES-256 without a derivation function.
+
+use core::cell::Cell;
+use kernel::hil::symmetric_encryption::AES128_BLOCK_SIZE;
+use kernel::ReturnCode;
+
+use super::aes::AesEngine;
+use super::util;
+
+const KEY_LEN: usize = 32;
+
+/// The length of the seed material for instantiating or reseeding.
+pub const SEED_LEN: usize = KEY_LEN + AES128_BLOCK_SIZE;
+
+/// The AES-256 block encryption the DRBG is built on. Implemented by the AES
+/// engine; the tests use a software model instead.
+pub trait BlockEncrypt {
+    /// Returns true if the cipher cannot currently be used.
+    fn is_busy(&self) -> bool;
+
+    /// Encrypts `block` in place under `key`.
+    fn encrypt_block_blocking(&self, key: &[u8; KEY_LEN], block: &mut [u8; AES128_BLOCK_SIZE])
+        -> ReturnCode;
+}
+
+impl<'a> BlockEncrypt for AesEngine<'a> {
+    fn is_busy(&self) -> bool {
+        AesEngine::is_busy(self)
+    }
+
+    fn encrypt_block_blocking(&self, key: &[u8; KEY_LEN], block: &mut [u8; AES128_BLOCK_SIZE])
+        -> ReturnCode {
+        AesEngine::encrypt_block_blocking(self, key, block)
+    }
+}
+
+pub struct CtrDrbg<'a> {
+    aes: &'a dyn BlockEncrypt,
+    key: Cell<[u8; KEY_LEN]>,
+    v: Cell<[u8; AES128_BLOCK_SIZE]>,
+    is_seeded: Cell<bool>,
+}
+
+impl<'a> CtrDrbg<'a> {
+    pub const fn new(aes: &'a dyn BlockEncrypt) -> CtrDrbg<'a> {
+        CtrDrbg {
+            aes: aes,
+            key: Cell::new([0; KEY_LEN]),
+            v: Cell::new([0; AES128_BLOCK_SIZE]),
+            is_seeded: Cell::new(false),
+        }
+    }
+
+    /// Returns true once the DRBG has been seeded at least once.
+    pub fn is_seeded(&self) -> bool {
+        self.is_seeded.get()
+    }
+
+    /// Returns true if the AES engine can currently be used.
+    pub fn is_available(&self) -> bool {
+        !self.aes.is_busy()
+    }
+
+    /// Mixes fresh entropy into the state. Instantiates the DRBG if it was not
+    /// seeded before.
+    pub fn reseed(&self, entropy: &[u8; SEED_LEN]) -> ReturnCode {
+        let rcode = self.update(entropy);
+        if rcode == ReturnCode::SUCCESS {
+            self.is_seeded.set(true);
+        }
+        rcode
+    }
+
+    /// Generates one block of output.
+    pub fn generate(&self, output: &mut [u8; AES128_BLOCK_SIZE]) -> ReturnCode {
+        if !self.is_seeded() {
+            return ReturnCode::EOFF;
+        }
+        let rcode = self.next_block(output);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        // Provide backtracking resistance.
+        self.update(&[0; SEED_LEN])
+    }
+
+    // Increments V and encrypts it under the current key.
+    fn next_block(&self, output: &mut [u8; AES128_BLOCK_SIZE]) -> ReturnCode {
+        let mut v = self.v.get();
+        for byte in v.iter_mut().rev() {
+            *byte = byte.wrapping_add(1);
+            if *byte != 0 { break; }
+        }
+        self.v.set(v);
+
+        *output = v;
+        self.aes.encrypt_block_blocking(&self.key.get(), output)
+    }
+
+    // CTR_DRBG_Update: derives a new key and V from the current state and
+    // `provided_data`.
+    fn update(&self, provided_data: &[u8; SEED_LEN]) -> ReturnCode {
+        let mut temp = [0u8; SEED_LEN];
+        for chunk in temp.chunks_mut(AES128_BLOCK_SIZE) {
+            let mut block = [0u8; AES128_BLOCK_SIZE];
+            let rcode = self.next_block(&mut block);
+            chunk.copy_from_slice(&block);
+            util::zeroize(&mut block);
+            if rcode != ReturnCode::SUCCESS {
+                util::zeroize(&mut temp);
+                return rcode;
+            }
+        }
+        for (byte, provided) in temp.iter_mut().zip(provided_data.iter()) {
+            *byte ^= *provided;
+        }
+
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(&temp[..KEY_LEN]);
+        let mut v = [0u8; AES128_BLOCK_SIZE];
+        v.copy_from_slice(&temp[KEY_LEN..]);
+        self.key.set(key);
+        self.v.set(v);
+        util::zeroize(&mut temp);
+        util::zeroize(&mut key);
+        util::zeroize(&mut v);
+        ReturnCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use super::super::golden;
+    use std::vec::Vec;
+
+    /// Software AES-256, standing in for the AES engine.
+    struct SoftwareAes;
+
+    impl BlockEncrypt for SoftwareAes {
+        fn is_busy(&self) -> bool {
+            false
+        }
+
+        fn encrypt_block_blocking(&self, key: &[u8; KEY_LEN],
+                                  block: &mut [u8; AES128_BLOCK_SIZE]) -> ReturnCode {
+            let output = golden::aes_encrypt(key, block);
+            block.copy_from_slice(&output);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // SP 800-90A Generate without additional input: the requested blocks,
+    // then an update with zeros.
+    fn generate_bits(drbg: &CtrDrbg, output: &mut [u8]) {
+        for chunk in output.chunks_mut(AES128_BLOCK_SIZE) {
+            let mut block = [0u8; AES128_BLOCK_SIZE];
+            assert_eq!(drbg.next_block(&mut block), ReturnCode::SUCCESS);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        assert_eq!(drbg.update(&[0; SEED_LEN]), ReturnCode::SUCCESS);
+    }
+
+    // CAVP CTR_DRBG.rsp, [AES-256 no df], PredictionResistance = False, no
+    // personalization string or additional input, COUNT = 0.
+    #[test]
+    fn nist_no_df_vector() {
+        let aes = SoftwareAes;
+        let drbg = CtrDrbg::new(&aes);
+        let mut entropy = [0u8; SEED_LEN];
+        entropy.copy_from_slice(&unhex(
+            "df5d73faa468649edda33b5cca79b0b05600419ccb7a879ddfec9db32ee494e5\
+             531b51de16a30f769262474c73bec010"));
+        assert_eq!(drbg.reseed(&entropy), ReturnCode::SUCCESS);
+
+        let mut returned = [0u8; 64];
+        generate_bits(&drbg, &mut returned);
+        generate_bits(&drbg, &mut returned);
+        assert_eq!(returned[..], unhex(
+            "d1c07cd95af8a7f11012c84ce48bb8cb87189e99d40fccb1771c619bdf82ab22\
+             80b1dc2f2581f39164f7ac0c510494b3a43c41b7db17514c87b107ae793e01c5")[..]);
+    }
+
+    #[test]
+    fn generate_requires_seed() {
+        let aes = SoftwareAes;
+        let drbg = CtrDrbg::new(&aes);
+        let mut block = [0u8; AES128_BLOCK_SIZE];
+        assert_eq!(drbg.generate(&mut block), ReturnCode::EOFF);
+        assert_eq!(drbg.reseed(&[7; SEED_LEN]), ReturnCode::SUCCESS);
+        assert_eq!(drbg.generate(&mut block), ReturnCode::SUCCESS);
+    }
+}
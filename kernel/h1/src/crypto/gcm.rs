@@ -0,0 +1,94 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Block formatting for AES-GCM (NIST SP 800-38D).
+//!
+//! The key manager computes GHASH in hardware and the AES engine provides
+//! the CTR keystream; these helpers build the blocks the AES driver feeds to
+//! them. Only 96-bit IVs are supported, so the initial counter block is the
+//! IV followed by a 32-bit block counter of 1.
+
+use super::util;
+
+/// The length of a GCM IV, in bytes.
+pub const IV_SIZE: usize = 12;
+
+/// The length of a full GCM tag, in bytes.
+pub const TAG_SIZE: usize = 16;
+
+/// The shortest tag accepted when verifying, in bytes.
+pub const MIN_TAG_SIZE: usize = 12;
+
+/// Returns the initial counter block J0 for a 96-bit IV.
+pub fn initial_counter(iv: &[u8; IV_SIZE]) -> [u8; 16] {
+    let mut counter = [0u8; 16];
+    counter[..IV_SIZE].copy_from_slice(iv);
+    counter[15] = 1;
+    counter
+}
+
+/// Increments the 32-bit big-endian block counter in the last word of
+/// `counter`, leaving the IV part untouched.
+pub fn increment_counter(counter: &mut [u8; 16]) {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(&counter[12..]);
+    let next = u32::from_be_bytes(word).wrapping_add(1);
+    counter[12..].copy_from_slice(&next.to_be_bytes());
+}
+
+/// Returns the final GHASH block, len(A) || len(C) in bits, for `aad_len`
+/// bytes of additional data and `text_len` bytes of ciphertext.
+pub fn length_block(aad_len: u64, text_len: u64) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..8].copy_from_slice(&(aad_len * 8).to_be_bytes());
+    block[8..].copy_from_slice(&(text_len * 8).to_be_bytes());
+    block
+}
+
+/// Compares a computed tag with the first `expected.len()` bytes of `tag`
+/// in constant time. Tags shorter than `MIN_TAG_SIZE` never match.
+pub fn tag_matches(tag: &[u8; TAG_SIZE], expected: &[u8]) -> bool {
+    if expected.len() < MIN_TAG_SIZE || expected.len() > TAG_SIZE {
+        return false;
+    }
+    util::ct_eq(&tag[..expected.len()], expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_wraps_in_last_word() {
+        let mut counter = initial_counter(&[0xab; IV_SIZE]);
+        assert_eq!(counter[11..], [0xab, 0, 0, 0, 1]);
+        counter[12..].copy_from_slice(&[0xff; 4]);
+        increment_counter(&mut counter);
+        assert_eq!(counter[..IV_SIZE], [0xab; IV_SIZE]);
+        assert_eq!(counter[12..], [0; 4]);
+    }
+
+    #[test]
+    fn truncated_tags() {
+        let tag = [7u8; TAG_SIZE];
+        assert!(tag_matches(&tag, &tag));
+        assert!(tag_matches(&tag, &tag[..MIN_TAG_SIZE]));
+        assert!(!tag_matches(&tag, &tag[..MIN_TAG_SIZE - 1]));
+        let mut bad = tag;
+        bad[3] ^= 0x10;
+        assert!(!tag_matches(&tag, &bad));
+    }
+}
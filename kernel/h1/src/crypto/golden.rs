@@ -0,0 +1,413 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Checks the SHA and AES drivers against the vectors in `golden_vectors.txt`.
+//!
+//! The engines cannot run on the host, so each vector is run through a
+//! software model of the engine. The model only sees the words the driver
+//! writes to the engine's registers, and its results go back through the
+//! driver's conversions, so a change to how the drivers pack or unpack data
+//! makes these tests fail even though the models themselves are unchanged.
+
+extern crate std;
+
+use super::gcm;
+use core::convert::TryInto;
+use super::marshal;
+use std::vec::Vec;
+
+const VECTORS: &str = include_str!("golden_vectors.txt");
+
+/// One "golden:" line.
+struct Vector {
+    op: &'static str,
+    key: Option<Vec<u8>>,
+    iv: Option<Vec<u8>>,
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+fn parse_hex(field: &str) -> Option<Vec<u8>> {
+    if field == "-" {
+        return None;
+    }
+    assert!(field.len() % 2 == 0, "odd length hex: {}", field);
+    Some((0..field.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&field[i..i + 2], 16).expect("bad hex"))
+        .collect())
+}
+
+fn vectors() -> Vec<Vector> {
+    VECTORS.lines()
+        .filter(|line| line.starts_with("golden:"))
+        .map(|line| {
+            let fields: Vec<&'static str> = line.split_whitespace().collect();
+            assert_eq!(fields.len(), 6, "malformed vector: {}", line);
+            Vector {
+                op: fields[1],
+                key: parse_hex(fields[2]),
+                iv: parse_hex(fields[3]),
+                input: parse_hex(fields[4]).expect("missing input"),
+                output: parse_hex(fields[5]).expect("missing output"),
+            }
+        })
+        .collect()
+}
+
+/// The corpus generator of userspace/crypto_capture.
+fn fill(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| seed.wrapping_add((i * 29) as u8)).collect()
+}
+
+/// Runs `bytes` through the words the driver writes to the engine, and
+/// returns the bytes the engine sees.
+fn to_engine(bytes: &[u8]) -> Vec<u8> {
+    let mut words = std::vec![0u32; (bytes.len() + 3) / 4];
+    marshal::pack_words(bytes, &mut words);
+    let mut seen = std::vec![0u8; bytes.len()];
+    for (word, chunk) in words.iter().zip(seen.chunks_mut(4)) {
+        chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+    }
+    seen
+}
+
+/// Returns the bytes the driver reads back from the engine's output FIFO.
+fn from_engine(bytes: &[u8]) -> Vec<u8> {
+    let words: Vec<u32> = bytes.chunks(4)
+        .map(|chunk| {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(word)
+        })
+        .collect();
+    let mut output = std::vec![0u8; bytes.len()];
+    marshal::unpack_words(&words, &mut output);
+    output
+}
+
+fn sha_pad(input: &[u8]) -> Vec<u8> {
+    let mut padded = input.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((input.len() as u64) * 8).to_be_bytes());
+    padded
+}
+
+fn sha1_state(input: &[u8]) -> Vec<u32> {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    for block in sha_pad(input).chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[4 * i], block[4 * i + 1],
+                                       block[4 * i + 2], block[4 * i + 3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e)
+                .wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+    h.to_vec()
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256_state(input: &[u8]) -> Vec<u32> {
+    let mut h: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                           0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    for block in sha_pad(input).chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[4 * i], block[4 * i + 1],
+                                       block[4 * i + 2], block[4 * i + 3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let mut v = h;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch)
+                .wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+        for (state, value) in h.iter_mut().zip(v.iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+    h.to_vec()
+}
+
+fn xtime(x: u8) -> u8 {
+    (x << 1) ^ if x & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn aes_sbox() -> [u8; 256] {
+    // Walks the multiplicative group with generator 3, pairing each element
+    // with its inverse.
+    let mut sbox = [0u8; 256];
+    let (mut p, mut q) = (1u8, 1u8);
+    loop {
+        p ^= xtime(p);
+        q ^= q << 1;
+        q ^= q << 2;
+        q ^= q << 4;
+        if q & 0x80 != 0 {
+            q ^= 0x09;
+        }
+        sbox[p as usize] = 0x63 ^ q ^ q.rotate_left(1) ^ q.rotate_left(2) ^
+                           q.rotate_left(3) ^ q.rotate_left(4);
+        if p == 1 {
+            break;
+        }
+    }
+    sbox[0] = 0x63;
+    sbox
+}
+
+/// AES-128 or AES-256 encryption of one block, depending on the key length.
+/// Also the software AES the DRBG tests run on.
+pub(super) fn aes_encrypt(key: &[u8], block: &[u8]) -> Vec<u8> {
+    let sbox = aes_sbox();
+    let key_words = key.len() / 4;
+    assert!(key_words == 4 || key_words == 8, "bad AES key length {}", key.len());
+    let rounds = key_words + 6;
+    let mut words: Vec<[u8; 4]> = key.chunks(4).map(|word| word.try_into().unwrap()).collect();
+    let mut rcon = 1u8;
+    for i in key_words..4 * (rounds + 1) {
+        let mut word = words[i - 1];
+        if i % key_words == 0 {
+            word = [sbox[word[1] as usize] ^ rcon, sbox[word[2] as usize],
+                    sbox[word[3] as usize], sbox[word[0] as usize]];
+            rcon = xtime(rcon);
+        } else if key_words > 6 && i % key_words == 4 {
+            for byte in word.iter_mut() {
+                *byte = sbox[*byte as usize];
+            }
+        }
+        for (byte, prev) in word.iter_mut().zip(words[i - key_words].iter()) {
+            *byte ^= prev;
+        }
+        words.push(word);
+    }
+    let round_keys: Vec<Vec<u8>> = words.chunks(4).map(|round| round.concat()).collect();
+
+    let mut state = [0u8; 16];
+    for i in 0..16 {
+        state[i] = block[i] ^ round_keys[0][i];
+    }
+    for round in 1..rounds + 1 {
+        let mut shifted = [0u8; 16];
+        for col in 0..4 {
+            for row in 0..4 {
+                shifted[4 * col + row] = sbox[state[4 * ((col + row) % 4) + row] as usize];
+            }
+        }
+        if round != rounds {
+            for col in shifted.chunks_mut(4) {
+                let all = col[0] ^ col[1] ^ col[2] ^ col[3];
+                let first = col[0];
+                for row in 0..4 {
+                    let next = if row == 3 { first } else { col[row + 1] };
+                    col[row] ^= all ^ xtime(col[row] ^ next);
+                }
+            }
+        }
+        for i in 0..16 {
+            state[i] = shifted[i] ^ round_keys[round][i];
+        }
+    }
+    state.to_vec()
+}
+
+/// Models the key manager's GHASH unit: `mac = (mac ^ block) * h` in
+/// GF(2^128), with both operands in the bit order of SP 800-38D. A partial
+/// block is padded with zeros, as the driver does.
+fn ghash_model(h: &[u8], mac: &mut [u8; 16], block: &[u8]) {
+    let mut padded = [0u8; 16];
+    padded[..block.len()].copy_from_slice(block);
+    let h = u128::from_be_bytes(to_engine(h)[..].try_into().unwrap());
+    let block = u128::from_be_bytes(to_engine(&padded)[..].try_into().unwrap());
+    let mut x = u128::from_be_bytes(*mac) ^ block;
+    let mut v = h;
+    let mut z = 0u128;
+    for _ in 0..128 {
+        if x & (1 << 127) != 0 {
+            z ^= v;
+        }
+        x <<= 1;
+        v = (v >> 1) ^ if v & 1 != 0 { 0xe1 << 120 } else { 0 };
+    }
+    *mac = z.to_be_bytes();
+}
+
+#[test]
+fn corpus_matches_capture_inputs() {
+    let vectors = vectors();
+    let sha_lengths = [1, 3, 4, 5, 55, 56, 57, 63, 64, 65, 119, 120, 128, 255];
+    for op in ["sha1", "sha256"].iter() {
+        let inputs: Vec<&Vec<u8>> = vectors.iter()
+            .filter(|v| v.op == *op)
+            .map(|v| &v.input)
+            .collect();
+        assert_eq!(inputs.len(), sha_lengths.len());
+        for (input, len) in inputs.iter().zip(sha_lengths.iter()) {
+            assert_eq!(**input, fill(*len, *len as u8));
+        }
+    }
+
+    let aes_seeds = [(0x00, 0x11, 0x22), (0x5a, 0xa5, 0x3c), (0xff, 0x80, 0x01)];
+    let aes: Vec<&Vector> = vectors.iter().filter(|v| v.op.starts_with("aes")).collect();
+    assert_eq!(aes.len(), 3 * aes_seeds.len());
+    for (case, (key, iv, input)) in aes.chunks(3).zip(aes_seeds.iter()) {
+        for vector in case.iter() {
+            assert_eq!(vector.key, Some(fill(16, *key)));
+            assert_eq!(vector.input, fill(16, *input));
+            if vector.op == "aes128-ctr" {
+                assert_eq!(vector.iv, Some(fill(16, *iv)));
+            } else {
+                assert_eq!(vector.iv, None);
+            }
+        }
+    }
+}
+
+#[test]
+fn sha_vectors() {
+    for vector in vectors().iter().filter(|v| v.op.starts_with("sha")) {
+        let message = to_engine(&vector.input);
+        let state = match vector.op {
+            "sha1" => sha1_state(&message),
+            "sha256" => sha256_state(&message),
+            op => panic!("unknown op {}", op),
+        };
+        // The engine exposes each big-endian state word byte-swapped, so the
+        // driver's little-endian read gives the digest in order.
+        let sts_h: Vec<u32> = state.iter().map(|word| word.swap_bytes()).collect();
+        let mut digest = std::vec![0u8; vector.output.len()];
+        marshal::unpack_words(&sts_h, &mut digest);
+        assert_eq!(digest, vector.output, "{} of {} bytes", vector.op, vector.input.len());
+    }
+}
+
+#[test]
+fn aes_vectors() {
+    for vector in vectors().iter().filter(|v| v.op.starts_with("aes")) {
+        let key = to_engine(vector.key.as_ref().expect("missing key"));
+        let input = to_engine(&vector.input);
+        let output = match vector.op {
+            "aes128-ecb-encrypt" => from_engine(&aes_encrypt(&key, &input)),
+            "aes128-ecb-decrypt" => {
+                // The model only encrypts, so check that the expected output
+                // encrypts back to the input.
+                assert_eq!(aes_encrypt(&key, &to_engine(&vector.output)), input);
+                vector.output.clone()
+            },
+            "aes128-ctr" => {
+                let counter = to_engine(vector.iv.as_ref().expect("missing iv"));
+                let stream = aes_encrypt(&key, &counter);
+                from_engine(&input.iter().zip(stream.iter()).map(|(a, b)| a ^ b).collect::<Vec<u8>>())
+            },
+            op => panic!("unknown op {}", op),
+        };
+        assert_eq!(output, vector.output, "{}", vector.op);
+    }
+}
+
+#[test]
+fn pack_pads_with_zeros() {
+    let mut words = [0xffffffffu32; 3];
+    marshal::pack_words(&[1, 2, 3, 4, 5], &mut words);
+    assert_eq!(words, [0x04030201, 0x00000005, 0]);
+    let mut bytes = [0u8; 5];
+    marshal::unpack_words(&words, &mut bytes);
+    assert_eq!(bytes, [1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn gcm_matches_nist_vector() {
+    // Test case 4 of the GCM specification: 20 bytes of AAD and 60 bytes of
+    // plaintext, so both end in a partial block.
+    let key = parse_hex("feffe9928665731c6d6a8f9467308308").unwrap();
+    let iv = parse_hex("cafebabefacedbaddecaf888").unwrap();
+    let aad = parse_hex("feedfacedeadbeeffeedfacedeadbeefabaddad2").unwrap();
+    let plaintext = parse_hex("d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+                               1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39").unwrap();
+    let ciphertext = parse_hex("42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e\
+                                21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091").unwrap();
+    let tag = parse_hex("5bc94fbc3221a5db94fae95ae7121a47").unwrap();
+
+    // The same sequence of engine operations as the AES driver's GCM session.
+    let h = aes_encrypt(&key, &[0; 16]);
+    let mut counter = gcm::initial_counter(iv[..].try_into().unwrap());
+    let tag_mask = aes_encrypt(&key, &counter);
+    let mut mac = [0u8; 16];
+    for block in aad.chunks(16) {
+        ghash_model(&h, &mut mac, block);
+    }
+    let mut output = Vec::new();
+    for block in plaintext.chunks(16) {
+        gcm::increment_counter(&mut counter);
+        let stream = aes_encrypt(&key, &counter);
+        let sealed: Vec<u8> = block.iter().zip(stream.iter()).map(|(a, b)| a ^ b).collect();
+        ghash_model(&h, &mut mac, &sealed);
+        output.extend_from_slice(&sealed);
+    }
+    ghash_model(&h, &mut mac, &gcm::length_block(aad.len() as u64, plaintext.len() as u64));
+    let mut computed = [0u8; gcm::TAG_SIZE];
+    for (i, byte) in computed.iter_mut().enumerate() {
+        *byte = mac[i] ^ tag_mask[i];
+    }
+
+    assert_eq!(output, ciphertext);
+    assert!(gcm::tag_matches(&computed, &tag));
+    assert!(gcm::tag_matches(&computed, &tag[..12]));
+}
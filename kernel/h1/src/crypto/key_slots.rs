@@ -0,0 +1,110 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Kernel-held AES keys that apps refer to by slot index.
+//!
+//! The AES engine has a single key register set, so the slots live in
+//! kernel memory and the AES syscall driver loads a slot's key into the
+//! engine whenever an app that selected the slot runs an operation. Key
+//! bytes never go back to app memory.
+//!
+//! Apps install keys into free slots for their own use. The kernel (e.g. the
+//! personality driver) installs keys that every app may use but no app can
+//! read, replace or clear.
+
+use core::cell::Cell;
+use kernel::hil::symmetric_encryption::AES128_KEY_SIZE;
+use kernel::{AppId, ReturnCode};
+
+use super::util;
+
+/// Number of key slots.
+pub const NUM_SLOTS: usize = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Owner {
+    Kernel,
+    App(AppId),
+}
+
+pub struct AesKeySlots {
+    owners: [Cell<Option<Owner>>; NUM_SLOTS],
+    keys: [Cell<[u8; AES128_KEY_SIZE]>; NUM_SLOTS],
+}
+
+impl AesKeySlots {
+    pub fn new() -> AesKeySlots {
+        AesKeySlots {
+            owners: Default::default(),
+            keys: Default::default(),
+        }
+    }
+
+    /// The owner of the key in `slot`, or None if the slot is free or does
+    /// not exist.
+    pub fn owner(&self, slot: usize) -> Option<Owner> {
+        self.owners.get(slot).and_then(Cell::get)
+    }
+
+    /// Installs `key` into `slot` for `owner`, replacing any key `owner`
+    /// had there. Returns EINVAL for a bad slot index, ESIZE for a key that
+    /// is not `AES128_KEY_SIZE` bytes and ERESERVE if someone else owns the
+    /// slot.
+    pub fn install(&self, slot: usize, owner: Owner, key: &[u8]) -> ReturnCode {
+        if slot >= NUM_SLOTS {
+            return ReturnCode::EINVAL;
+        }
+        if key.len() != AES128_KEY_SIZE {
+            return ReturnCode::ESIZE;
+        }
+        match self.owners[slot].get() {
+            Some(current) if current != owner => return ReturnCode::ERESERVE,
+            _ => {}
+        }
+        let mut slot_key = [0; AES128_KEY_SIZE];
+        slot_key.copy_from_slice(key);
+        self.keys[slot].set(slot_key);
+        self.owners[slot].set(Some(owner));
+        ReturnCode::SUCCESS
+    }
+
+    /// Wipes the key in `slot` and frees it. Returns EINVAL for a bad slot
+    /// index and ERESERVE if `owner` does not own the slot.
+    pub fn clear(&self, slot: usize, owner: Owner) -> ReturnCode {
+        if self.owner(slot) != Some(owner) {
+            return if slot < NUM_SLOTS { ReturnCode::ERESERVE } else { ReturnCode::EINVAL };
+        }
+        self.keys[slot].set([0; AES128_KEY_SIZE]);
+        self.owners[slot].set(None);
+        ReturnCode::SUCCESS
+    }
+
+    /// Calls `f` with the key in `slot` if `app` may use it, i.e. if the
+    /// kernel or `app` owns it. Returns None otherwise.
+    pub fn with_key<F, R>(&self, slot: usize, app: AppId, f: F) -> Option<R>
+        where F: FnOnce(&[u8; AES128_KEY_SIZE]) -> R
+    {
+        match self.owner(slot) {
+            Some(Owner::Kernel) => {}
+            Some(Owner::App(owner)) if owner == app => {}
+            _ => return None,
+        }
+        let mut key = self.keys[slot].get();
+        let result = f(&key);
+        util::zeroize(&mut key);
+        Some(result)
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Key derivation on the KEYMGR key ladder.
+//!
+//! Each certificate step runs the SHA engine over a hardware certificate,
+//! optionally mixed with 32 bytes of input, and leaves the result in the
+//! hidden key registers where software cannot read it. A derivation walks
+//! the same chain as the U2F app's key ladder shims: the fixed certificates
+//! up to ISR2, a step into the usage's branch and a final step over the
+//! hashed context whose output is the key.
+//!
+//! Like `HkdfImpl`, derivation uses the SHA engine synchronously. It takes
+//! the engine from the `ShaArbiter` and fails with EBUSY while an app has a
+//! digest in progress.
+
+use crate::crypto::sha::{ShaArbiter, ShaEngine};
+use crate::hil::digest::{DigestEngine, DigestMode};
+use crate::hil::keyladder::{KeyLadder, KeyUsage, KEY_LEN};
+use super::util;
+use kernel::ReturnCode;
+
+const INPUT_LEN: usize = 32;
+
+// Certificates from the root to ISR2, walked before every derivation.
+const ROOT_CERTS: [u32; 7] = [0, 3, 4, 5, 7, 15, 20];
+// Certificate stepped once per firmware version the chain descends.
+const VERSION_CERT: u32 = 25;
+const VERSION_STEPS: usize = 255;
+const ISR2_CERT: u32 = 34;
+// ISR2 to the usage's branch.
+const USAGE_CERT: u32 = 35;
+// Final step, an HMAC keyed by the usage's branch.
+const OUTPUT_CERT: u32 = 38;
+
+// The seeds are SHA256 of their names, as in the U2F app.
+const ISR2_SEED: [u8; INPUT_LEN] = [
+    0x63, 0x98, 0x4e, 0x70, 0xd3, 0x70, 0x1c, 0xf6,
+    0xe7, 0x32, 0x6f, 0xd2, 0xe2, 0x97, 0x42, 0x29,
+    0x9c, 0x93, 0x1e, 0x4d, 0xa8, 0xb6, 0xb3, 0x64,
+    0x36, 0x18, 0xa3, 0xb5, 0x7e, 0x1d, 0x1f, 0x1c,
+];
+
+const SEED_ATTEST: [u8; INPUT_LEN] = [
+    0x39, 0x01, 0x64, 0x40, 0x4a, 0xcf, 0xfa, 0xcb,
+    0x7b, 0xc2, 0xc2, 0xc2, 0xba, 0x9c, 0x2d, 0x9f,
+    0xc3, 0x41, 0x3d, 0x8e, 0x54, 0xe9, 0xbf, 0x43,
+    0x4f, 0x53, 0xcd, 0x81, 0x05, 0x4b, 0x80, 0x23,
+];
+
+const SEED_ORIGIN: [u8; INPUT_LEN] = [
+    0x02, 0xf5, 0xa7, 0x06, 0xc4, 0x40, 0x3c, 0x21,
+    0x19, 0x4f, 0x3d, 0x5f, 0x3b, 0x94, 0xca, 0x52,
+    0xae, 0x2f, 0x4e, 0x23, 0x13, 0xdc, 0xb6, 0xdd,
+    0xc0, 0x56, 0x95, 0xaa, 0xf1, 0x38, 0xd5, 0xb2,
+];
+
+const SEED_STORAGE: [u8; INPUT_LEN] = [
+    0x34, 0x7e, 0xe2, 0x45, 0x69, 0x3e, 0x85, 0x17,
+    0x21, 0x73, 0x91, 0xeb, 0x4a, 0x01, 0x66, 0x81,
+    0x6d, 0x4a, 0x13, 0x97, 0x6a, 0x9c, 0x9e, 0xdd,
+    0xd2, 0x9a, 0xbb, 0xa4, 0x8f, 0x2f, 0x7c, 0xdb,
+];
+
+fn usage_seed(usage: KeyUsage) -> &'static [u8; INPUT_LEN] {
+    match usage {
+        KeyUsage::Attestation => &SEED_ATTEST,
+        KeyUsage::Origin => &SEED_ORIGIN,
+        KeyUsage::Storage => &SEED_STORAGE,
+    }
+}
+
+pub struct KeyLadderImpl<'a> {
+    sha: &'a ShaArbiter<'a>,
+}
+
+impl<'a> KeyLadderImpl<'a> {
+    pub fn new(sha: &'a ShaArbiter<'a>) -> KeyLadderImpl<'a> {
+        KeyLadderImpl { sha: sha }
+    }
+
+    /// Runs one certificate step. The result stays hidden unless `output`
+    /// is given. Returns false if the engine or the key ladder failed.
+    fn step(sha: &ShaEngine, cert: u32, input: Option<&[u8; INPUT_LEN]>,
+            output: Option<&mut [u8; KEY_LEN]>) -> bool {
+        if sha.initialize_certificate(cert).is_err() {
+            return false;
+        }
+        let result = match input {
+            Some(input) => sha.update(input).and_then(|_| match output {
+                Some(output) => sha.finalize(output),
+                None => sha.finalize_hidden(),
+            }),
+            None => sha.finalize_hidden(),
+        };
+        result.is_ok() && !sha.ladder_error()
+    }
+
+    fn hash_context(sha: &ShaEngine, context: &[&[u8]], input: &mut [u8; INPUT_LEN]) -> bool {
+        if sha.initialize(DigestMode::Sha256).is_err() {
+            return false;
+        }
+        for part in context {
+            if sha.update(part).is_err() {
+                return false;
+            }
+        }
+        sha.finalize(input).is_ok()
+    }
+}
+
+impl<'a> KeyLadder for KeyLadderImpl<'a> {
+    fn derive(&self, usage: KeyUsage, context: &[&[u8]], key: &mut [u8; KEY_LEN]) -> ReturnCode {
+        // Hash the context first: the certificate steps below leave the
+        // engine holding hidden key material.
+        let sha = match self.sha.engine() {
+            Ok(sha) => sha,
+            Err(rcode) => {
+                util::zeroize(key);
+                return rcode;
+            }
+        };
+        let mut input = [0u8; INPUT_LEN];
+        let ok = Self::hash_context(sha, context, &mut input)
+            && ROOT_CERTS.iter().all(|cert| Self::step(sha, *cert, None, None))
+            && (0..VERSION_STEPS).all(|_| Self::step(sha, VERSION_CERT, None, None))
+            && Self::step(sha, ISR2_CERT, Some(&ISR2_SEED), None)
+            && Self::step(sha, USAGE_CERT, Some(usage_seed(usage)), None)
+            && Self::step(sha, OUTPUT_CERT, Some(&input), Some(key));
+        util::zeroize(&mut input);
+        if ok {
+            ReturnCode::SUCCESS
+        } else {
+            util::zeroize(key);
+            ReturnCode::FAIL
+        }
+    }
+}
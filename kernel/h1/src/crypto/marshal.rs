@@ -0,0 +1,53 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conversions between byte buffers and the key manager's 32-bit registers.
+//!
+//! The SHA and AES engines take keys, IVs and data, and return results, in
+//! little-endian words: byte 0 of a buffer is the least significant byte of
+//! the first word. A buffer that does not fill its last word is padded with
+//! zero bytes. These conversions are all the engines' drivers do to the data,
+//! so they are what the golden vectors in `golden.rs` check.
+
+/// Packs up to 4 bytes into a word, padding missing bytes with zeros.
+pub fn pack_word(bytes: &[u8]) -> u32 {
+    bytes.iter()
+        .take(4)
+        .enumerate()
+        .fold(0, |word, (i, byte)| word | (*byte as u32) << (i * 8))
+}
+
+/// Unpacks a word into up to 4 bytes.
+pub fn unpack_word(word: u32, bytes: &mut [u8]) {
+    for (i, byte) in bytes.iter_mut().take(4).enumerate() {
+        *byte = (word >> (i * 8)) as u8;
+    }
+}
+
+/// Packs `bytes` into `words`. Words past the end of `bytes` are zero.
+pub fn pack_words(bytes: &[u8], words: &mut [u32]) {
+    let mut chunks = bytes.chunks(4);
+    for word in words.iter_mut() {
+        *word = chunks.next().map_or(0, pack_word);
+    }
+}
+
+/// Unpacks `words` into `bytes`, stopping at the end of either.
+pub fn unpack_words(words: &[u32], bytes: &mut [u8]) {
+    for (word, chunk) in words.iter().zip(bytes.chunks_mut(4)) {
+        unpack_word(*word, chunk);
+    }
+}
@@ -15,6 +15,20 @@
 pub mod keymgr;
 pub mod sha;
 pub mod aes;
+pub mod curve25519;
 pub mod dcrypto;
+pub mod drbg;
+pub mod gcm;
+pub mod keyladder;
+pub mod key_slots;
+pub mod marshal;
+pub mod p256;
+pub mod rsa;
+pub mod stats;
+pub mod util;
+pub mod virtual_dcrypto;
+
+#[cfg(test)]
+mod golden;
 
 const KEYMGR0_BASE_ADDRESS: usize = 0x40570000;
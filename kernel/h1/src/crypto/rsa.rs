@@ -0,0 +1,476 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! RSA modular exponentiation on the dcrypto engine.
+//!
+//! The program computes base^exponent mod modulus with Montgomery
+//! multiplication on 256-bit words, for moduli of up to `MAX_LEN` bytes.
+//! Its run time only depends on the lengths of the operands, not on their
+//! values. A 2048-bit signature check with exponent 65537 runs about
+//! 570,000 instructions.
+//!
+//! Data memory holds little-endian 32-byte cells. The operands are stored
+//! least significant cell first, except for the exponent, which is stored
+//! most significant cell first and left-aligned; see rsa.dasm.
+//!
+//! `PROGRAM` and the entry point are generated from rsa.dasm by
+//! tools/dcrypto_asm, whose tests check that they match the source.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::ReturnCode;
+use super::dcrypto::{self, Dcrypto, DcryptoClient, ProgramFault};
+use super::util;
+
+/// Size in bytes of a data memory cell.
+pub const CELL_LEN: usize = 32;
+
+/// Largest supported modulus, in bytes.
+pub const MAX_LEN: usize = 12 * CELL_LEN;
+
+/// Indices of the data memory cells holding the operands.
+pub const LEN_CELL: usize = 0;
+pub const EXPONENT_BITS_CELL: usize = 1;
+pub const MODULUS_CELL: usize = 2;
+pub const BASE_CELL: usize = 14;
+pub const RESULT_CELL: usize = 26;
+pub const EXPONENT_CELL: usize = 62;
+
+// Number of data memory cells used by the program.
+const USED_CELLS: usize = EXPONENT_CELL + MAX_LEN / CELL_LEN;
+
+/// Entry point, as an IMEM word address.
+pub const MODEXP: u32 = 161;
+
+// Words per cell, as counted by the data memory accessors.
+const CELL_WORDS: u32 = (CELL_LEN / 4) as u32;
+
+fn check(rcode: ReturnCode) -> Result<(), ReturnCode> {
+    match rcode {
+        ReturnCode::SUCCESS => Ok(()),
+        rcode => Err(rcode),
+    }
+}
+
+pub trait RsaClient {
+    /// Called when the exponentiation started with `RsaEngine::modexp` is
+    /// done. If `rcode` is SUCCESS, `result` holds the big-endian result,
+    /// as long as the modulus.
+    fn modexp_done(&self, rcode: ReturnCode, result: &[u8]);
+}
+
+pub struct RsaEngine<'a> {
+    dcrypto: &'a dyn Dcrypto<'a>,
+    client: OptionalCell<&'a dyn RsaClient>,
+    // Length in cells of the modulus of the running exponentiation, or 0.
+    cells: Cell<usize>,
+}
+
+impl<'a> RsaEngine<'a> {
+    pub fn new(dcrypto: &'a dyn Dcrypto<'a>) -> RsaEngine<'a> {
+        RsaEngine {
+            dcrypto: dcrypto,
+            client: OptionalCell::empty(),
+            cells: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn RsaClient) {
+        self.client.set(client);
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.cells.get() != 0
+    }
+
+    /// Starts computing `base^exponent mod modulus`. All are big-endian.
+    /// The modulus must be a whole number of cells long, odd, and have its
+    /// top bit set, as RSA moduli do. `base` must be as long as `modulus`
+    /// and less than it, and `exponent` at most as long. Returns EINVAL
+    /// for other operands and EBUSY if the engine is in use.
+    pub fn modexp(&self, modulus: &[u8], exponent: &[u8], base: &[u8]) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        let cells = modulus.len() / CELL_LEN;
+        if cells < 2 || cells * CELL_LEN != modulus.len() || modulus.len() > MAX_LEN ||
+            exponent.is_empty() || exponent.len() > MAX_LEN || base.len() != modulus.len() {
+            return ReturnCode::EINVAL;
+        }
+        if modulus[0] & 0x80 == 0 || modulus[modulus.len() - 1] & 1 == 0 || base >= modulus {
+            return ReturnCode::EINVAL;
+        }
+        match self.start(cells, modulus, exponent, base) {
+            Ok(()) => {
+                self.cells.set(cells);
+                ReturnCode::SUCCESS
+            }
+            Err(rcode) => {
+                self.wipe();
+                rcode
+            }
+        }
+    }
+
+    fn start(&self, cells: usize, modulus: &[u8], exponent: &[u8], base: &[u8])
+             -> Result<(), ReturnCode> {
+        check(dcrypto::load_program(self.dcrypto, &PROGRAM))?;
+        let mut cell = [0u8; CELL_LEN];
+        cell[..4].copy_from_slice(&(cells as u32).to_le_bytes());
+        check(self.write_cell(LEN_CELL, &cell))?;
+        cell[..4].copy_from_slice(&(8 * exponent.len() as u32).to_le_bytes());
+        check(self.write_cell(EXPONENT_BITS_CELL, &cell))?;
+        self.write_number(MODULUS_CELL, modulus)?;
+        self.write_number(BASE_CELL, base)?;
+        let mut result = Ok(());
+        for (index, chunk) in exponent.chunks(CELL_LEN).enumerate() {
+            cell = [0; CELL_LEN];
+            for (out, byte) in cell.iter_mut().rev().zip(chunk.iter()) {
+                *out = *byte;
+            }
+            result = check(self.write_cell(EXPONENT_CELL + index, &cell));
+            if result.is_err() {
+                break;
+            }
+        }
+        util::zeroize(&mut cell);
+        result?;
+        check(self.dcrypto.call_imem(MODEXP))
+    }
+
+    fn write_cell(&self, index: usize, cell: &[u8; CELL_LEN]) -> ReturnCode {
+        self.dcrypto.write_data(cell, index as u32 * CELL_WORDS, CELL_WORDS)
+    }
+
+    // Stores the big-endian `value` from cell `first` on, least
+    // significant cell first.
+    fn write_number(&self, first: usize, value: &[u8]) -> Result<(), ReturnCode> {
+        let mut cell = [0u8; CELL_LEN];
+        for (index, chunk) in value.rchunks(CELL_LEN).enumerate() {
+            for (out, byte) in cell.iter_mut().zip(chunk.iter().rev()) {
+                *out = *byte;
+            }
+            check(self.write_cell(first + index, &cell))?;
+        }
+        Ok(())
+    }
+
+    // Reads the number stored from cell `first` on into the big-endian
+    // `value`.
+    fn read_number(&self, first: usize, value: &mut [u8]) -> Result<(), ReturnCode> {
+        let mut cell = [0u8; CELL_LEN];
+        for (index, chunk) in value.rchunks_mut(CELL_LEN).enumerate() {
+            check(self.dcrypto.read_data(&mut cell, (first + index) as u32 * CELL_WORDS,
+                                         CELL_WORDS))?;
+            for (out, byte) in chunk.iter_mut().zip(cell.iter().rev()) {
+                *out = *byte;
+            }
+        }
+        util::zeroize(&mut cell);
+        Ok(())
+    }
+
+    // Clears the operands and the result from data memory; the exponent
+    // may be a private key.
+    fn wipe(&self) {
+        for index in 0..USED_CELLS {
+            let _ = self.write_cell(index, &[0; CELL_LEN]);
+        }
+    }
+}
+
+impl<'a> DcryptoClient<'a> for RsaEngine<'a> {
+    fn execution_complete(&self, error: ReturnCode, _fault: ProgramFault) {
+        let cells = self.cells.get();
+        if cells == 0 {
+            return;
+        }
+        let mut result = [0u8; MAX_LEN];
+        let len = cells * CELL_LEN;
+        let rcode = match check(error).and_then(|_| self.read_number(RESULT_CELL,
+                                                                     &mut result[..len])) {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err(rcode) => rcode,
+        };
+        self.wipe();
+        self.cells.set(0);
+        self.client.map(|client| client.modexp_done(rcode, &result[..len]));
+        util::zeroize(&mut result);
+    }
+
+    fn reset_complete(&self, _error: ReturnCode) {}
+
+    fn secret_wipe_complete(&self, _error: ReturnCode) {}
+}
+
+#[rustfmt::skip]
+static PROGRAM: [u32; 211] = [
+    // r4:r5 = r0 * r2.
+    // @0x0: function Mul[9] {
+    0x58104000, // mul128 r4, r0l, r2l
+    0x59944000, // mul128 r5, r0u, r2u
+    0x58984000, // mul128 r6, r0u, r2l
+    0x5010c410, // add r4, r4, r6 << 128
+    0x5094c590, // addc r5, r5, r6 >> 128
+    0x59184000, // mul128 r6, r0l, r2u
+    0x5010c410, // add r4, r4, r6 << 128
+    0x5094c590, // addc r5, r5, r6 >> 128
+    0x0c000000, // ret
+    // }
+    // r2 = r1 * r29 mod 2^256.
+    // @0x9: function MulLow[6] {
+    0x580ba100, // mul128 r2, r1l, r29l
+    0x589ba100, // mul128 r6, r1u, r29l
+    0x5008c210, // add r2, r2, r6 << 128
+    0x591ba100, // mul128 r6, r1l, r29u
+    0x5008c210, // add r2, r2, r6 << 128
+    0x0c000000, // ret
+    // }
+    // Stores r4 in the L cells at slot 4.
+    // @0xf: function Fill[6] {
+    0x95800f00, // lddmp r15
+    0x7c2c1c00, // mov r11, r28
+    // FillLoop:
+    0x90700300, // st *3, *4++
+    0x552c0b01, // subi r11, r11, #1
+    0x10084011, // bnz &FillLoop
+    0x0c000000, // ret
+    // }
+    // Stores in slot 5 the number at slot 3, with r7 as an extra top cell,
+    // reduced by N once if it is not less than N.
+    // @0x15: function Reduce[28] {
+    0x95800f00, // lddmp r15
+    0x7c0c1f00, // mov r3, r31
+    0x7c2c1c00, // mov r11, r28
+    // ReduceBorrow:
+    0x8c001a00, // ld *0, *2++
+    0x8c041b00, // ld *1, *3++
+    0x54100100, // sub r4, r1, r0
+    0x509bff00, // addc r6, r31, r31
+    0x54106400, // sub r4, r4, r3
+    0x508fe600, // addc r3, r6, r31
+    0x552c0b01, // subi r11, r11, #1
+    0x10084018, // bnz &ReduceBorrow
+    0x540c7e00, // sub r3, r30, r3
+    0x440ce300, // or r3, r3, r7
+    0x54247f00, // sub r9, r31, r3
+    0x95800f00, // lddmp r15
+    0x7c0c1f00, // mov r3, r31
+    0x7c2c1c00, // mov r11, r28
+    // ReduceSub:
+    0x8c001a00, // ld *0, *2++
+    0x8c041b00, // ld *1, *3++
+    0x40012000, // and r0, r0, r9
+    0x54100100, // sub r4, r1, r0
+    0x509bff00, // addc r6, r31, r31
+    0x54106400, // sub r4, r4, r3
+    0x508fe600, // addc r3, r6, r31
+    0x90740300, // st *3, *5++
+    0x552c0b01, // subi r11, r11, #1
+    0x10084026, // bnz &ReduceSub
+    0x0c000000, // ret
+    // }
+    // Stores a * b / 2^(256 L) mod N in slot 5, for a in slot 1 and b in
+    // slot 0, both less than N.
+    // @0x31: function MontMul[47] {
+    0x7c101f00, // mov r4, r31
+    0x0800000f, // call &Fill
+    0x7c1c1f00, // mov r7, r31
+    0x7c281c00, // mov r10, r28
+    // MontOuter:
+    0x95800f00, // lddmp r15
+    0x8c081000, // ld *2, *0
+    0x7c0c1f00, // mov r3, r31
+    0x7c2c1c00, // mov r11, r28
+    // MontAdd:
+    0x8c001900, // ld *0, *1++
+    0x8c041b00, // ld *1, *3++
+    0x08000000, // call &Mul
+    0x50102400, // add r4, r4, r1
+    0x5097e500, // addc r5, r5, r31
+    0x50106400, // add r4, r4, r3
+    0x508fe500, // addc r3, r5, r31
+    0x90700300, // st *3, *4++
+    0x552c0b01, // subi r11, r11, #1
+    0x10084039, // bnz &MontAdd
+    0x501c6700, // add r7, r7, r3
+    0x50a3ff00, // addc r8, r31, r31
+    0x95800f00, // lddmp r15
+    0x8c041300, // ld *1, *3
+    0x08000009, // call &MulLow
+    0x8c001a00, // ld *0, *2++
+    0x8c041b00, // ld *1, *3++
+    0x08000000, // call &Mul
+    0x50102400, // add r4, r4, r1
+    0x508fe500, // addc r3, r5, r31
+    0x552c1c01, // subi r11, r28, #1
+    // MontShift:
+    0x8c001a00, // ld *0, *2++
+    0x8c041b00, // ld *1, *3++
+    0x08000000, // call &Mul
+    0x50102400, // add r4, r4, r1
+    0x5097e500, // addc r5, r5, r31
+    0x50106400, // add r4, r4, r3
+    0x508fe500, // addc r3, r5, r31
+    0x90700300, // st *3, *4++
+    0x552c0b01, // subi r11, r11, #1
+    0x1008404e, // bnz &MontShift
+    0x50106700, // add r4, r7, r3
+    0x509fe800, // addc r7, r8, r31
+    0x90700300, // st *3, *4++
+    0x513c0f01, // addi r15, r15, #1
+    0x55280a01, // subi r10, r10, #1
+    0x10084035, // bnz &MontOuter
+    0x08000015, // call &Reduce
+    0x0c000000, // ret
+    // }
+    // X = X if r23 is zero and Y if it is all ones.
+    // @0x60: function Select[15] {
+    0x7c3c1f00, // mov r15, r31
+    0x80bc001a, // movi r15.1l, #26
+    0x81bc0026, // movi r15.3l, #38
+    0x823c001a, // movi r15.4l, #26
+    0x95800f00, // lddmp r15
+    0x7c2c1c00, // mov r11, r28
+    // SelectLoop:
+    0x8c001900, // ld *0, *1++
+    0x8c041b00, // ld *1, *3++
+    0x4c040100, // xor r1, r1, r0
+    0x4006e100, // and r1, r1, r23
+    0x4c102000, // xor r4, r0, r1
+    0x90700300, // st *3, *4++
+    0x552c0b01, // subi r11, r11, #1
+    0x10084066, // bnz &SelectLoop
+    0x0c000000, // ret
+    // }
+    // @0x6f: function Setup[16] {
+    0x847c4000, // ldi r31, [#0]
+    0x4c7fff00, // xor r31, r31, r31
+    0x51781f01, // addi r30, r31, #1
+    0x7c401f00, // mov r16, r31
+    0x80c00001, // movi r16.1l, #1
+    0x81400002, // movi r16.2l, #2
+    0x81c00004, // movi r16.3l, #4
+    0x82400019, // movi r16.4l, #25
+    0x97801000, // ldrfp r16
+    0x84704000, // ldi r28, [#0]
+    0x846c4001, // ldi r27, [#1]
+    0x7c541f00, // mov r21, r31
+    0x81540002, // movi r21.2l, #2
+    0x81d40032, // movi r21.3l, #50
+    0x82540032, // movi r21.4l, #50
+    0x0c000000, // ret
+    // }
+    // r29 = -1/N mod 2^256, by Newton's iteration. N is odd, so N is its own
+    // inverse mod 8, and each step doubles the number of correct bits.
+    // @0x7f: function SetupMont[15] {
+    0x7c3c1500, // mov r15, r21
+    0x95800f00, // lddmp r15
+    0x8c041200, // ld *1, *2
+    0x7c300100, // mov r12, r1
+    0x7c740100, // mov r29, r1
+    0x05007007, // loop #7 (
+        0x08000009, // call &MulLow
+        0x54085f00, // sub r2, r31, r2
+        0x51080202, // addi r2, r2, #2
+        0x7c040200, // mov r1, r2
+        0x08000009, // call &MulLow
+        0x7c740200, // mov r29, r2
+        0x7c040c00, // mov r1, r12
+    // )
+    0x5477bf00, // sub r29, r31, r29
+    0x0c000000, // ret
+    // }
+    // A = A * 2^(256 L) mod N, by doubling 256 L times.
+    // @0x8e: function ToMont[19] {
+    0x7c3c1500, // mov r15, r21
+    0x81bc000e, // movi r15.3l, #14
+    0x823c000e, // movi r15.4l, #14
+    0x82bc000e, // movi r15.5l, #14
+    0x502b9f01, // add r10, r31, r28 << 8
+    // ToMontLoop:
+    0x95800f00, // lddmp r15
+    0x7c0c1f00, // mov r3, r31
+    0x7c2c1c00, // mov r11, r28
+    // ToMontDouble:
+    0x8c041b00, // ld *1, *3++
+    0x681023ff, // rshi r4, r3, r1 >> 255
+    0x90700300, // st *3, *4++
+    0x7c0c0100, // mov r3, r1
+    0x552c0b01, // subi r11, r11, #1
+    0x10084096, // bnz &ToMontDouble
+    0x681fe3ff, // rshi r7, r3, r31 >> 255
+    0x08000015, // call &Reduce
+    0x55280a01, // subi r10, r10, #1
+    0x10084093, // bnz &ToMontLoop
+    0x0c000000, // ret
+    // }
+    // X = A^E mod N, for A less than N. N must have its top bit set.
+    // @0xa1: function modexp[50] {
+    0x0800006f, // call &Setup
+    0x0800007f, // call &SetupMont
+    0x0800008e, // call &ToMont
+    0x7c3c1500, // mov r15, r21
+    0x81bc001a, // movi r15.3l, #26
+    0x823c001a, // movi r15.4l, #26
+    0x82bc001a, // movi r15.5l, #26
+    0x7c101f00, // mov r4, r31
+    0x0800000f, // call &Fill
+    0x7c1c1e00, // mov r7, r30
+    0x08000015, // call &Reduce
+    0x7c601f00, // mov r24, r31
+    0x8060003e, // movi r24.0l, #62
+    0x7c681f00, // mov r26, r31
+    // ExpBit:
+    0x5c03fa00, // cmp r26, r31
+    0x100840b6, // bnz &ExpHaveBits
+    0x95801800, // lddmp r24
+    0x8c101000, // ld *4, *0
+    0x51601801, // addi r24, r24, #1
+    0x7c681f00, // mov r26, r31
+    0x80680100, // movi r26.0l, #256
+    // ExpHaveBits:
+    0x50673900, // add r25, r25, r25
+    0x54dfff00, // subb r23, r31, r31
+    0x7c3c1500, // mov r15, r21
+    0x803c001a, // movi r15.0l, #26
+    0x80bc001a, // movi r15.1l, #26
+    0x82bc001a, // movi r15.5l, #26
+    0x08000031, // call &MontMul
+    0x7c3c1500, // mov r15, r21
+    0x803c000e, // movi r15.0l, #14
+    0x80bc001a, // movi r15.1l, #26
+    0x82bc0026, // movi r15.5l, #38
+    0x08000031, // call &MontMul
+    0x08000060, // call &Select
+    0x55681a01, // subi r26, r26, #1
+    0x556c1b01, // subi r27, r27, #1
+    0x100840af, // bnz &ExpBit
+    0x7c3c1500, // mov r15, r21
+    0x823c0026, // movi r15.4l, #38
+    0x7c101f00, // mov r4, r31
+    0x0800000f, // call &Fill
+    0x95800f00, // lddmp r15
+    0x7c101e00, // mov r4, r30
+    0x90500300, // st *3, *4
+    0x7c3c1500, // mov r15, r21
+    0x803c0026, // movi r15.0l, #38
+    0x80bc001a, // movi r15.1l, #26
+    0x82bc001a, // movi r15.5l, #26
+    0x08000031, // call &MontMul
+    0x0c000000, // ret
+    // }
+];
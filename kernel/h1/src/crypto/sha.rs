@@ -14,9 +14,15 @@
 
 use core::cell::Cell;
 use core::mem;
-use crate::hil::digest::{DigestEngine, DigestMode, DigestError};
-use kernel::common::cells::VolatileCell;
+use ecc::p256;
+use ecc::rfc6979::{HmacSha256, HMAC_LEN};
+use crate::hil::digest::{DigestEngine, DigestMode, DigestError, DigestOwner};
+use kernel::common::cells::{OptionalCell, VolatileCell};
+use kernel::ReturnCode;
 use super::keymgr::{KEYMGR0_REGS, Registers};
+use super::marshal;
+use super::stats::{self, Engine};
+use super::util;
 
 
 #[allow(unused)]
@@ -63,9 +69,17 @@ impl ShaEngine {
         let ref regs = unsafe { &*self.regs }.sha;
         regs.itop.set(0);
     }
+
+    /// Whether the key ladder flagged an error in a certificate step.
+    pub fn ladder_error(&self) -> bool {
+        let ref regs = unsafe { &*self.regs }.hkey;
+        regs.err_flags.get() != 0
+    }
 }
 
-pub static mut KEYMGR0_SHA: ShaEngine = unsafe { ShaEngine::new(KEYMGR0_REGS) };
+pub(crate) const unsafe fn keymgr0_sha() -> ShaEngine {
+    ShaEngine::new(KEYMGR0_REGS)
+}
 
 const HMAC_KEY_SIZE_BYTES: usize = 32;
 const HMAC_KEY_SIZE_WORDS: usize = HMAC_KEY_SIZE_BYTES / 4;
@@ -108,13 +122,12 @@ impl DigestEngine for ShaEngine {
             print!("Key too small: {}\n", key.len());
             return Err(DigestError::BufferTooSmall(HMAC_KEY_SIZE_BYTES));
         }
-        for i in 0..HMAC_KEY_SIZE_WORDS {
-            let word: u32 = (key[4 * i + 0] as u32) << 0  |
-                            (key[4 * i + 1] as u32) << 8  |
-                            (key[4 * i + 2] as u32) << 16 |
-                            (key[4 * i + 3] as u32) << 24;
-            regs.key_w[i].set(word);
+        let mut key_words = [0u32; HMAC_KEY_SIZE_WORDS];
+        marshal::pack_words(&key[..HMAC_KEY_SIZE_BYTES], &mut key_words);
+        for (i, word) in key_words.iter().enumerate() {
+            regs.key_w[i].set(*word);
         }
+        util::zeroize(&mut key_words);
 
         let flags = ShaCfgEnMask::Livestream as u32 |
                     ShaCfgEnMask::IntEnDone as u32 |
@@ -129,7 +142,8 @@ impl DigestEngine for ShaEngine {
     fn initialize_certificate(&self, certificate_id: u32) -> Result<(), DigestError> {
         let ref regs = unsafe { &*self.regs }.sha;
         regs.itop.set(0); // clear status
-
+        // Certificate steps with input finalize like a SHA-256 digest.
+        self.current_mode.set(Some(DigestMode::Sha256));
 
         regs.use_cert.set(certificate_id & CertificateMask::CertBits as u32 |
                           CertificateMask::Enable as u32);
@@ -150,10 +164,12 @@ impl DigestEngine for ShaEngine {
 
         let fifo_u8: &VolatileCell<u8> = unsafe { mem::transmute(&regs.input_fifo) };
 
+        let started = stats::now();
         // TODO(yuriks): Feed FIFO word at a time when possible
         for b in data {
             fifo_u8.set(*b);
         }
+        stats::record(Engine::Sha, 0, data.len(), started);
         Ok(data.len())
     }
 
@@ -169,31 +185,104 @@ impl DigestEngine for ShaEngine {
 
         // Tell hardware we're done streaming and then wait for the
         // hash calculation to finish.
+        let started = stats::now();
         regs.itop.set(0);
         regs.trig.set(ShaTrigMask::Stop as u32);
         while regs.itop.get() == 0 {}
+        stats::record(Engine::Sha, 1, 0, started);
 
         for i in 0..(expected_output_size / 4) {
-            let word = regs.sts_h[i].get();
-            output[i * 4 + 0] = (word >> 0) as u8;
-            output[i * 4 + 1] = (word >> 8) as u8;
-            output[i * 4 + 2] = (word >> 16) as u8;
-            output[i * 4 + 3] = (word >> 24) as u8;
+            marshal::unpack_word(regs.sts_h[i].get(), &mut output[i * 4..]);
         }
         regs.itop.set(0);
 
         Ok(expected_output_size)
     }
 
+    fn finalize_hmac(&self, output: &mut [u8]) -> Result<usize, DigestError> {
+        let ref regs = unsafe { &*self.regs }.sha;
+        if self.current_mode.get() != Some(DigestMode::Sha256Hmac) {
+            return Err(DigestError::NotConfigured);
+        }
+
+        let result = self.finalize(output);
+        for word in regs.key_w.iter() {
+            word.set(0);
+        }
+        self.current_mode.set(None);
+        result
+    }
+
     // Finalize withtout seeing the result; this is used for certificates
     // (hidden secret generation)
     fn finalize_hidden(&self) -> Result<usize, DigestError> {
         let ref regs = unsafe { &*self.regs }.sha;
+        let started = stats::now();
         regs.itop.set(0);
         regs.trig.set(ShaTrigMask::Stop as u32);
         while regs.itop.get() == 0 {}
         regs.itop.set(0);
+        stats::record(Engine::Sha, 1, 0, started);
 
         Ok(0)
     }
 }
+
+/// Hands the SHA engine to kernel users that are done with it by the time
+/// they return, such as HKDF and the key ladder.
+///
+/// The digest driver keeps an app's session in the engine across syscalls,
+/// and the engine cannot save it, so kernel users get EBUSY for as long as
+/// such a session lasts instead of clobbering it.
+pub struct ShaArbiter<'a> {
+    sha: &'a ShaEngine,
+    owner: OptionalCell<&'a dyn DigestOwner>,
+}
+
+impl<'a> ShaArbiter<'a> {
+    pub fn new(sha: &'a ShaEngine) -> ShaArbiter<'a> {
+        ShaArbiter {
+            sha: sha,
+            owner: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets who may hold the engine across calls, i.e. the digest driver.
+    pub fn set_owner(&self, owner: &'a dyn DigestOwner) {
+        self.owner.set(owner);
+    }
+
+    /// Returns the engine for use within the current call, or EBUSY if an
+    /// app has a digest in progress.
+    pub fn engine(&self) -> Result<&'a ShaEngine, ReturnCode> {
+        if self.owner.map_or(false, |owner| owner.has_session()) {
+            return Err(ReturnCode::EBUSY);
+        }
+        Ok(self.sha)
+    }
+}
+
+/// Computes the HMACs for RFC 6979 nonces on an engine handed out by the
+/// `ShaArbiter`.
+pub struct ShaHmac<'a> {
+    sha: &'a dyn DigestEngine,
+}
+
+impl<'a> ShaHmac<'a> {
+    pub fn new(sha: &'a dyn DigestEngine) -> ShaHmac<'a> {
+        ShaHmac { sha: sha }
+    }
+}
+
+impl<'a> HmacSha256 for ShaHmac<'a> {
+    fn hmac_sha256(&self, key: &[u8; HMAC_LEN], data: &[&[u8]])
+                   -> Result<[u8; HMAC_LEN], p256::Error> {
+        let mut output = [0u8; HMAC_LEN];
+        self.sha.initialize_hmac(key).map_err(|_| p256::Error::DigestFailure)?;
+        for part in data {
+            self.sha.update(part).map_err(|_| p256::Error::DigestFailure)?;
+        }
+        self.sha.finalize_hmac(&mut output).map_err(|_| p256::Error::DigestFailure)?;
+        Ok(output)
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Usage counters for the crypto engines.
+//!
+//! Each engine counts the operations it completed, the bytes it processed
+//! and the time it spent busy, for capacity planning. An AES operation is
+//! one block, a SHA operation one finalized digest and a dcrypto operation
+//! one program run. Busy time is measured on the clock passed to
+//! `set_clock`; without a clock only operations and bytes are counted.
+
+use core::ptr;
+use crate::timeus::Timeus;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+    Sha = 0,
+    Aes = 1,
+    Dcrypto = 2,
+}
+
+/// Number of engines.
+pub const ENGINES: usize = 3;
+
+impl Engine {
+    pub fn from_usize(value: usize) -> Option<Engine> {
+        match value {
+            0 => Some(Engine::Sha),
+            1 => Some(Engine::Aes),
+            2 => Some(Engine::Dcrypto),
+            _ => None,
+        }
+    }
+}
+
+/// Counters for one engine since boot.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EngineStats {
+    pub ops: u32,
+    pub bytes: u32,
+    /// Time spent busy, in clock ticks.
+    pub busy_ticks: u64,
+}
+
+const NO_USAGE: EngineStats = EngineStats { ops: 0, bytes: 0, busy_ticks: 0 };
+
+// Only updated from the kernel thread and engine interrupt handlers, which
+// run to completion without preempting each other.
+static mut STATS: [EngineStats; ENGINES] = [NO_USAGE; ENGINES];
+static mut CLOCK: Option<(&'static Timeus, u32)> = None;
+
+/// Sets the clock busy time is measured on. `clock` must be running at
+/// `clock_hz`.
+pub fn set_clock(clock: &'static Timeus, clock_hz: u32) {
+    unsafe { CLOCK = Some((clock, clock_hz)); }
+}
+
+/// The current clock value, to pass to `record` when the operation ends.
+pub fn now() -> Option<u32> {
+    unsafe { CLOCK.map(|(clock, _)| clock.now()) }
+}
+
+/// Counts `ops` operations over `bytes` bytes on `engine`, which has been
+/// busy since the clock read `started`.
+pub fn record(engine: Engine, ops: u32, bytes: usize, started: Option<u32>) {
+    let busy_ticks = match (unsafe { CLOCK }, started) {
+        (Some((clock, _)), Some(started)) => clock.now().wrapping_sub(started),
+        _ => 0,
+    };
+    let stats = unsafe { &mut STATS[engine as usize] };
+    stats.ops = stats.ops.saturating_add(ops);
+    stats.bytes = stats.bytes.saturating_add(bytes as u32);
+    stats.busy_ticks += busy_ticks as u64;
+}
+
+/// Returns the counters for `engine`.
+pub fn get(engine: Engine) -> EngineStats {
+    unsafe { ptr::read_volatile(&STATS[engine as usize]) }
+}
+
+/// Converts busy ticks to microseconds. Returns 0 if there is no clock.
+pub fn ticks_to_us(ticks: u64) -> u64 {
+    match unsafe { CLOCK } {
+        Some((_, clock_hz)) if clock_hz > 0 => ticks * 1_000_000 / clock_hz as u64,
+        _ => 0,
+    }
+}
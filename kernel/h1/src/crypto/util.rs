@@ -0,0 +1,95 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers for handling secrets: constant-time comparison and selection,
+//! and zeroization the compiler cannot elide.
+//!
+//! "Constant-time" here means the running time depends only on the lengths
+//! of the inputs, never on their contents. Lengths are assumed public.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Returns whether `a` and `b` hold the same bytes. Slices of different
+/// lengths are never equal.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b.iter()).fold(0, |diff, (a, b)| diff | (a ^ b));
+    // Keep the compiler from turning the fold into an early exit.
+    unsafe { core::ptr::read_volatile(&diff) == 0 }
+}
+
+/// Returns `a` if `choice` is true and `b` otherwise, without branching on
+/// `choice`.
+pub fn ct_select_u32(choice: bool, a: u32, b: u32) -> u32 {
+    let mask = 0u32.wrapping_sub(choice as u32);
+    (a & mask) | (b & !mask)
+}
+
+/// Copies `a` into `output` if `choice` is true and `b` otherwise, without
+/// branching on `choice`. All three slices must have the same length.
+pub fn ct_select(choice: bool, a: &[u8], b: &[u8], output: &mut [u8]) {
+    assert!(a.len() == output.len() && b.len() == output.len());
+    let mask = 0u8.wrapping_sub(choice as u8);
+    for ((out, a), b) in output.iter_mut().zip(a.iter()).zip(b.iter()) {
+        *out = (a & mask) | (b & !mask);
+    }
+}
+
+/// Overwrites `buffer` with zeros. Unlike a plain assignment, the writes
+/// are not optimized away when `buffer` is not read again, so this is the
+/// way to clear key material.
+pub fn zeroize<T: Copy + Default>(buffer: &mut [T]) {
+    for item in buffer.iter_mut() {
+        unsafe { core::ptr::write_volatile(item, T::default()) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ct_eq_compares_contents_and_lengths() {
+        assert!(ct_eq(b"tag", b"tag"));
+        assert!(ct_eq(&[], &[]));
+        assert!(!ct_eq(b"tag", b"tax"));
+        assert!(!ct_eq(b"tag", b"ta"));
+    }
+
+    #[test]
+    fn ct_select_picks_by_choice() {
+        assert_eq!(ct_select_u32(true, 1, 2), 1);
+        assert_eq!(ct_select_u32(false, 1, 2), 2);
+        let mut output = [0u8; 2];
+        ct_select(true, &[1, 2], &[3, 4], &mut output);
+        assert_eq!(output, [1, 2]);
+        ct_select(false, &[1, 2], &[3, 4], &mut output);
+        assert_eq!(output, [3, 4]);
+    }
+
+    #[test]
+    fn zeroize_clears_bytes_and_words() {
+        let mut bytes = [0xa5u8; 5];
+        zeroize(&mut bytes);
+        assert_eq!(bytes, [0; 5]);
+        let mut words = [0xdeadbeefu32; 3];
+        zeroize(&mut words);
+        assert_eq!(words, [0; 3]);
+    }
+}
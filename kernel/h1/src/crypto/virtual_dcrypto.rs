@@ -0,0 +1,189 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shares the dcrypto engine between several clients.
+//!
+//! The engine already refuses to load memory or start a program while one
+//! is running, so the mux only has to route each completion to the user
+//! that started the program. Users load and start their programs within a
+//! single call, or within the completion of their previous run, so another
+//! user cannot change the engine's memory or registers in between.
+//! Operations of other users fail with EBUSY while a program runs; they are
+//! not queued.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::ReturnCode;
+use super::dcrypto::{Dcrypto, DcryptoClient, ProgramFault, State};
+
+pub struct MuxDcrypto<'a> {
+    engine: &'a dyn Dcrypto<'a>,
+    users: List<'a, DcryptoUser<'a>>,
+    busy: Cell<bool>,
+}
+
+pub struct DcryptoUser<'a> {
+    mux: &'a MuxDcrypto<'a>,
+    client: OptionalCell<&'a dyn DcryptoClient<'a>>,
+    // Whether the running program was started by this user.
+    running: Cell<bool>,
+    next: ListLink<'a, DcryptoUser<'a>>,
+}
+
+impl<'a> MuxDcrypto<'a> {
+    pub const fn new(engine: &'a dyn Dcrypto<'a>) -> MuxDcrypto<'a> {
+        MuxDcrypto {
+            engine: engine,
+            users: List::new(),
+            busy: Cell::new(false),
+        }
+    }
+
+    // Runs `start`, which begins an operation with a completion callback,
+    // and routes that callback to `user`.
+    fn start(&self, user: &DcryptoUser<'a>, start: impl FnOnce() -> ReturnCode) -> ReturnCode {
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+        let rcode = start();
+        if rcode == ReturnCode::SUCCESS {
+            self.busy.set(true);
+            user.running.set(true);
+        }
+        rcode
+    }
+
+    fn idle(&self) -> ReturnCode {
+        if self.busy.get() { ReturnCode::EBUSY } else { ReturnCode::SUCCESS }
+    }
+
+    // Returns the user whose program just completed.
+    fn finish(&self) -> Option<&'a DcryptoUser<'a>> {
+        self.busy.set(false);
+        let user = self.users.iter().find(|user| user.running.get());
+        user.map(|user| user.running.set(false));
+        user
+    }
+}
+
+impl<'a> DcryptoClient<'a> for MuxDcrypto<'a> {
+    fn execution_complete(&self, error: ReturnCode, fault: ProgramFault) {
+        self.finish().map(|user| {
+            user.client.map(|client| client.execution_complete(error, fault));
+        });
+    }
+
+    fn reset_complete(&self, error: ReturnCode) {
+        self.finish().map(|user| {
+            user.client.map(|client| client.reset_complete(error));
+        });
+    }
+
+    fn secret_wipe_complete(&self, error: ReturnCode) {
+        self.finish().map(|user| {
+            user.client.map(|client| client.secret_wipe_complete(error));
+        });
+    }
+}
+
+impl<'a> DcryptoUser<'a> {
+    pub const fn new(mux: &'a MuxDcrypto<'a>) -> DcryptoUser<'a> {
+        DcryptoUser {
+            mux: mux,
+            client: OptionalCell::empty(),
+            running: Cell::new(false),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Registers the user with the mux. Must be called before the user's
+    /// first program runs.
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+}
+
+impl<'a> Dcrypto<'a> for DcryptoUser<'a> {
+    fn set_client(&self, client: &'a dyn DcryptoClient<'a>) {
+        self.client.set(client);
+    }
+
+    fn read_data(&self, data: &mut [u8], offset: u32, length: u32) -> ReturnCode {
+        match self.mux.idle() {
+            ReturnCode::SUCCESS => self.mux.engine.read_data(data, offset, length),
+            rcode => rcode,
+        }
+    }
+
+    fn write_data(&self, data: &[u8], offset: u32, length: u32) -> ReturnCode {
+        match self.mux.idle() {
+            ReturnCode::SUCCESS => self.mux.engine.write_data(data, offset, length),
+            rcode => rcode,
+        }
+    }
+
+    fn read_instructions(&self, data: &mut [u8], offset: u32, length: u32) -> ReturnCode {
+        match self.mux.idle() {
+            ReturnCode::SUCCESS => self.mux.engine.read_instructions(data, offset, length),
+            rcode => rcode,
+        }
+    }
+
+    fn write_instructions(&self, instructions: &[u8], offset: u32, length: u32) -> ReturnCode {
+        match self.mux.idle() {
+            ReturnCode::SUCCESS => self.mux.engine.write_instructions(instructions, offset, length),
+            rcode => rcode,
+        }
+    }
+
+    fn call_imem(&self, address: u32) -> ReturnCode {
+        self.mux.start(self, || self.mux.engine.call_imem(address))
+    }
+
+    fn execute_instruction(&self, instruction: u32, is_call: bool) -> ReturnCode {
+        if !is_call {
+            return match self.mux.idle() {
+                ReturnCode::SUCCESS => self.mux.engine.execute_instruction(instruction, false),
+                rcode => rcode,
+            };
+        }
+        self.mux.start(self, || self.mux.engine.execute_instruction(instruction, true))
+    }
+
+    fn state(&self) -> State {
+        self.mux.engine.state()
+    }
+
+    fn reset(&self) -> ReturnCode {
+        self.mux.start(self, || self.mux.engine.reset())
+    }
+
+    fn wipe_secrets(&self) -> ReturnCode {
+        // The engine returns to Halt by itself after a wipe and does not
+        // call back, so the wipe does not hold the mux.
+        match self.mux.idle() {
+            ReturnCode::SUCCESS => self.mux.engine.wipe_secrets(),
+            rcode => rcode,
+        }
+    }
+}
+
+impl<'a> ListNode<'a, DcryptoUser<'a>> for DcryptoUser<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, DcryptoUser<'a>> {
+        &self.next
+    }
+}
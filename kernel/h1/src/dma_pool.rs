@@ -0,0 +1,216 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fixed pools of buffers for DMA and FIFO transfers.
+//!
+//! Buffers that hardware reads or writes directly must stay put and often
+//! need more alignment than their element type provides. A `DmaPool` holds
+//! `COUNT` blocks of `LEN` elements in static storage, each aligned to the
+//! alignment class `A`, and hands them out as `&'static mut [T; LEN]`.
+//!
+//! Each `take` names the owner of the block. Debug builds remember the owners
+//! so that an exhausted pool reports who is holding on to its blocks, and
+//! `check_released` catches an owner that forgot to return one.
+
+use core::cell::Cell;
+use core::cell::UnsafeCell;
+use core::mem;
+
+/// 4 byte alignment, enough for word-wise FIFO access.
+#[derive(Clone, Copy)]
+#[repr(align(4))]
+pub struct Align4;
+
+/// 16 byte alignment.
+#[derive(Clone, Copy)]
+#[repr(align(16))]
+pub struct Align16;
+
+/// 64 byte alignment.
+#[derive(Clone, Copy)]
+#[repr(align(64))]
+pub struct Align64;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Block<T: Copy, A: Copy, const LEN: usize> {
+    // Zero-sized, only here to raise the alignment of `data`.
+    _align: [A; 0],
+    data: [T; LEN],
+}
+
+pub struct DmaPool<T: Copy, A: Copy, const LEN: usize, const COUNT: usize> {
+    name: &'static str,
+    blocks: UnsafeCell<[Block<T, A, LEN>; COUNT]>,
+    taken: Cell<[bool; COUNT]>,
+    #[cfg(debug_assertions)]
+    owners: Cell<[&'static str; COUNT]>,
+}
+
+// The kernel is single-threaded and a block is only reachable through the
+// `&'static mut` handed out by `take`.
+unsafe impl<T: Copy, A: Copy, const LEN: usize, const COUNT: usize> Sync
+    for DmaPool<T, A, LEN, COUNT> {}
+
+impl<T: Copy, A: Copy, const LEN: usize, const COUNT: usize> DmaPool<T, A, LEN, COUNT> {
+    /// Creates a pool whose blocks are filled with `init`.
+    pub const fn new(name: &'static str, init: T) -> DmaPool<T, A, LEN, COUNT> {
+        DmaPool {
+            name: name,
+            blocks: UnsafeCell::new([Block { _align: [], data: [init; LEN] }; COUNT]),
+            taken: Cell::new([false; COUNT]),
+            #[cfg(debug_assertions)]
+            owners: Cell::new([""; COUNT]),
+        }
+    }
+
+    /// Number of blocks not currently taken.
+    pub fn available(&self) -> usize {
+        self.taken.get().iter().filter(|taken| !**taken).count()
+    }
+
+    /// Takes a free block on behalf of `owner`, or returns None if all blocks
+    /// are taken. Debug builds panic instead, listing the current owners.
+    pub fn take(&'static self, owner: &'static str) -> Option<&'static mut [T; LEN]> {
+        let mut taken = self.taken.get();
+        let index = match taken.iter().position(|taken| !*taken) {
+            Some(index) => index,
+            None => {
+                #[cfg(debug_assertions)]
+                panic!("DmaPool {} exhausted, held by {:?}",
+                       self.name, &self.owners.get()[..]);
+                #[cfg(not(debug_assertions))]
+                return None;
+            }
+        };
+        taken[index] = true;
+        self.taken.set(taken);
+        #[cfg(debug_assertions)]
+        {
+            let mut owners = self.owners.get();
+            owners[index] = owner;
+            self.owners.set(owners);
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = owner;
+
+        // The block is marked taken, so this is the only reference to it
+        // until it is given back.
+        let blocks = unsafe { &mut *self.blocks.get() };
+        Some(&mut blocks[index].data)
+    }
+
+    /// Returns a block to the pool. Panics if the block was not taken from
+    /// this pool.
+    pub fn give(&self, buffer: &'static mut [T; LEN]) {
+        let index = self.index_of(buffer);
+        let mut taken = self.taken.get();
+        assert!(taken[index], "DmaPool {}: block {} given back twice", self.name, index);
+        taken[index] = false;
+        self.taken.set(taken);
+        #[cfg(debug_assertions)]
+        {
+            let mut owners = self.owners.get();
+            owners[index] = "";
+            self.owners.set(owners);
+        }
+    }
+
+    /// Panics in debug builds if `owner` still holds a block. Call it where
+    /// the owner should have returned all of its blocks.
+    pub fn check_released(&self, owner: &'static str) {
+        #[cfg(debug_assertions)]
+        {
+            let taken = self.taken.get();
+            let owners = self.owners.get();
+            for index in 0..COUNT {
+                if taken[index] && owners[index] == owner {
+                    panic!("DmaPool {}: {} leaked block {}", self.name, owner, index);
+                }
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = owner;
+    }
+
+    fn index_of(&self, buffer: &[T; LEN]) -> usize {
+        let base = self.blocks.get() as usize;
+        let offset = (buffer as *const [T; LEN] as usize).wrapping_sub(base);
+        let stride = mem::size_of::<Block<T, A, LEN>>();
+        assert!(offset < stride * COUNT && offset % stride == 0,
+                "DmaPool {}: foreign block", self.name);
+        offset / stride
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Align16, Align64, DmaPool};
+
+    #[test]
+    fn blocks_are_aligned() {
+        static POOL: DmaPool<u8, Align64, 10, 3> = DmaPool::new("aligned", 0);
+        for _ in 0..3 {
+            let block = POOL.take("test").unwrap();
+            assert_eq!(block.as_ptr() as usize % 64, 0);
+        }
+    }
+
+    #[test]
+    fn take_and_give() {
+        static POOL: DmaPool<u32, Align16, 4, 2> = DmaPool::new("words", 7);
+        let first = POOL.take("first").unwrap();
+        assert_eq!(*first, [7; 4]);
+        first[0] = 1;
+        let second = POOL.take("second").unwrap();
+        assert_eq!(POOL.available(), 0);
+
+        POOL.give(first);
+        assert_eq!(POOL.available(), 1);
+        POOL.check_released("first");
+        let again = POOL.take("again").unwrap();
+        assert_eq!(again[0], 1);
+        POOL.give(again);
+        POOL.give(second);
+        assert_eq!(POOL.available(), 2);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "leaked")]
+    fn leak_is_reported() {
+        static POOL: DmaPool<u8, Align16, 8, 1> = DmaPool::new("leaky", 0);
+        let _block = POOL.take("owner").unwrap();
+        POOL.check_released("owner");
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "exhausted")]
+    fn exhaustion_is_reported() {
+        static POOL: DmaPool<u8, Align16, 8, 1> = DmaPool::new("small", 0);
+        let _block = POOL.take("owner").unwrap();
+        let _ = POOL.take("owner");
+    }
+
+    #[test]
+    #[should_panic(expected = "foreign")]
+    fn foreign_block_is_rejected() {
+        static POOL: DmaPool<u8, Align16, 8, 1> = DmaPool::new("pool", 0);
+        static mut OTHER: [u8; 8] = [0; 8];
+        POOL.give(unsafe { &mut OTHER });
+    }
+}
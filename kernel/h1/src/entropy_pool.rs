@@ -0,0 +1,298 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Entropy pool serving randomness from a CTR_DRBG that is periodically
+//! reseeded from the TRNG.
+//!
+//! The TRNG stops producing samples if its health tests fail, which would
+//! block every consumer of randomness. The pool keeps serving randomness from
+//! the DRBG in that case and flags itself as degraded until the next
+//! successful reseed, unless the board's `ReseedPolicy` says to stop.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::entropy::{Client32, Continue, Entropy32};
+use kernel::hil::symmetric_encryption::AES128_BLOCK_SIZE;
+use kernel::ReturnCode;
+
+use crate::crypto::drbg::{CtrDrbg, SEED_LEN};
+use crate::hil::entropy_pool::{EntropyPool, EntropyPoolStatus};
+
+const SEED_WORDS: usize = SEED_LEN / 4;
+const BLOCK_WORDS: usize = AES128_BLOCK_SIZE / 4;
+
+/// When the pool reseeds the DRBG, and what it does if it cannot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReseedPolicy {
+    /// Number of words generated from the DRBG before requesting a reseed.
+    pub interval_words: u32,
+    /// Number of words generated from the DRBG without a reseed after which
+    /// the TRNG is considered to be failing.
+    pub degraded_words: u32,
+    /// Whether to stop serving DRBG output while degraded. Requests then
+    /// wait for the TRNG, and fail if its health tests failed.
+    pub stop_when_degraded: bool,
+}
+
+/// Reseeds every 4 KiB of output and keeps serving while degraded.
+pub const DEFAULT_RESEED_POLICY: ReseedPolicy = ReseedPolicy {
+    interval_words: 1024,
+    degraded_words: 4 * 1024,
+    stop_when_degraded: false,
+};
+
+pub struct EntropyPoolImpl<'a> {
+    trng: &'a dyn Entropy32<'a>,
+    drbg: CtrDrbg<'a>,
+    policy: ReseedPolicy,
+    client: OptionalCell<&'a dyn Client32>,
+
+    /// Seed material collected from the TRNG.
+    seed: Cell<[u32; SEED_WORDS]>,
+    seed_len: Cell<usize>,
+
+    /// Whether the TRNG was asked for entropy and has not delivered yet.
+    trng_requested: Cell<bool>,
+
+    /// Whether the client asked for entropy and was not satisfied yet.
+    client_pending: Cell<bool>,
+
+    status: Cell<EntropyPoolStatus>,
+}
+
+impl<'a> EntropyPoolImpl<'a> {
+    pub fn new(trng: &'a dyn Entropy32<'a>,
+               drbg: CtrDrbg<'a>,
+               policy: ReseedPolicy) -> EntropyPoolImpl<'a> {
+        EntropyPoolImpl {
+            trng: trng,
+            drbg: drbg,
+            policy: policy,
+            client: OptionalCell::empty(),
+            seed: Cell::new([0; SEED_WORDS]),
+            seed_len: Cell::new(0),
+            trng_requested: Cell::new(false),
+            client_pending: Cell::new(false),
+            status: Cell::new(EntropyPoolStatus::default()),
+        }
+    }
+
+    /// Starts collecting the initial seed.
+    pub fn init(&self) {
+        self.request_trng();
+    }
+
+    fn needs_reseed(&self) -> bool {
+        let status = self.status.get();
+        !status.is_seeded || status.words_since_reseed >= self.policy.interval_words
+    }
+
+    fn request_trng(&self) {
+        if !self.trng_requested.get() {
+            self.trng_requested.set(true);
+            if self.trng.get() != ReturnCode::SUCCESS {
+                self.trng_requested.set(false);
+            }
+        }
+    }
+
+    // Collects seed material from the TRNG and reseeds the DRBG once enough
+    // has been collected.
+    fn collect_seed(&self, entropy: &mut dyn Iterator<Item = u32>) {
+        let mut seed = self.seed.get();
+        let mut seed_len = self.seed_len.get();
+        while seed_len < SEED_WORDS {
+            match entropy.next() {
+                Some(word) => {
+                    seed[seed_len] = word;
+                    seed_len += 1;
+                }
+                None => break,
+            }
+        }
+        self.seed.set(seed);
+        self.seed_len.set(seed_len);
+
+        if seed_len < SEED_WORDS || !self.drbg.is_available() {
+            return;
+        }
+
+        let mut seed_bytes = [0u8; SEED_LEN];
+        for (bytes, word) in seed_bytes.chunks_mut(4).zip(seed.iter()) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        if self.drbg.reseed(&seed_bytes) == ReturnCode::SUCCESS {
+            let mut status = self.status.get();
+            status.is_seeded = true;
+            status.is_degraded = false;
+            status.reseed_count = status.reseed_count.wrapping_add(1);
+            status.words_since_reseed = 0;
+            self.status.set(status);
+        }
+        self.seed.set([0; SEED_WORDS]);
+        self.seed_len.set(0);
+    }
+
+    // Serves a pending client request from the DRBG.
+    // Returns false if the DRBG cannot be used right now.
+    fn serve_from_drbg(&self) -> bool {
+        if !self.drbg.is_seeded() || !self.drbg.is_available() {
+            return false;
+        }
+        if self.policy.stop_when_degraded && self.status.get().is_degraded {
+            return false;
+        }
+
+        let mut iter = DrbgIter {
+            pool: self,
+            block: [0; BLOCK_WORDS],
+            idx: BLOCK_WORDS,
+        };
+        let result = self.client.map_or(Continue::Done, |client| {
+            client.entropy_available(&mut iter, ReturnCode::SUCCESS)
+        });
+        self.client_pending.set(result == Continue::More);
+
+        if self.needs_reseed() {
+            self.request_trng();
+        }
+        true
+    }
+
+    fn account_generated_words(&self, count: u32) {
+        let mut status = self.status.get();
+        status.words_since_reseed = status.words_since_reseed.saturating_add(count);
+        if status.words_since_reseed >= self.policy.degraded_words && !status.is_degraded {
+            status.is_degraded = true;
+            // Kick the TRNG again on the next request, which restarts it if it
+            // timed out.
+            self.trng_requested.set(false);
+        }
+        self.status.set(status);
+    }
+}
+
+impl<'a> Entropy32<'a> for EntropyPoolImpl<'a> {
+    fn set_client(&self, client: &'a dyn Client32) {
+        self.client.set(client);
+    }
+
+    fn get(&self) -> ReturnCode {
+        self.client_pending.set(true);
+        if !self.serve_from_drbg() {
+            // Fall back to raw TRNG output until the DRBG can be used.
+            self.request_trng();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        self.client_pending.set(false);
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> Client32 for EntropyPoolImpl<'a> {
+    fn entropy_available(&self, entropy: &mut dyn Iterator<Item = u32>, error: ReturnCode)
+        -> Continue {
+        self.trng_requested.set(false);
+
+        if error != ReturnCode::SUCCESS {
+            let mut status = self.status.get();
+            status.is_degraded = true;
+            self.status.set(status);
+            // The words collected so far came right before a health test
+            // failure.
+            self.seed.set([0; SEED_WORDS]);
+            self.seed_len.set(0);
+        } else if self.needs_reseed() {
+            self.collect_seed(entropy);
+        }
+
+        if self.client_pending.get() && !self.serve_from_drbg() {
+            // Pass the raw TRNG output through.
+            let result = self.client.map_or(Continue::Done, |client| {
+                client.entropy_available(entropy, error)
+            });
+            self.client_pending.set(result == Continue::More);
+        }
+
+        if self.needs_reseed() || self.client_pending.get() {
+            self.trng_requested.set(true);
+            Continue::More
+        } else {
+            Continue::Done
+        }
+    }
+}
+
+impl<'a> EntropyPool for EntropyPoolImpl<'a> {
+    fn get_status(&self) -> EntropyPoolStatus {
+        self.status.get()
+    }
+
+    fn fill(&self, buf: &mut [u8]) -> ReturnCode {
+        if !self.drbg.is_seeded() {
+            return ReturnCode::EOFF;
+        }
+        if !self.drbg.is_available() {
+            return ReturnCode::EBUSY;
+        }
+        if self.policy.stop_when_degraded && self.status.get().is_degraded {
+            return ReturnCode::FAIL;
+        }
+        for chunk in buf.chunks_mut(AES128_BLOCK_SIZE) {
+            let mut block = [0u8; AES128_BLOCK_SIZE];
+            let rcode = self.drbg.generate(&mut block);
+            if rcode != ReturnCode::SUCCESS {
+                return rcode;
+            }
+            chunk.copy_from_slice(&block[..chunk.len()]);
+            self.account_generated_words(BLOCK_WORDS as u32);
+        }
+        if self.needs_reseed() {
+            self.request_trng();
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
+struct DrbgIter<'a, 'b: 'a> {
+    pool: &'a EntropyPoolImpl<'b>,
+    block: [u32; BLOCK_WORDS],
+    idx: usize,
+}
+
+impl<'a, 'b> Iterator for DrbgIter<'a, 'b> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.idx >= BLOCK_WORDS {
+            let mut bytes = [0u8; AES128_BLOCK_SIZE];
+            if self.pool.drbg.generate(&mut bytes) != ReturnCode::SUCCESS {
+                return None;
+            }
+            for (word, chunk) in self.block.iter_mut().zip(bytes.chunks(4)) {
+                *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            }
+            self.idx = 0;
+            self.pool.account_generated_words(BLOCK_WORDS as u32);
+        }
+        let word = self.block[self.idx];
+        self.idx += 1;
+        Some(word)
+    }
+}
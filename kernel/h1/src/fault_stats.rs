@@ -0,0 +1,188 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! CPU exception statistics.
+//!
+//! Faults in apps are recovered by restarting the app, which leaves no
+//! trace. `hard_fault_handler` records every fault before handing it to the
+//! Cortex-M3 handler: a count per fault type and the most recent fault
+//! frame, so that intermittent faults can be diagnosed afterwards.
+//!
+//! The configurable fault handlers are not enabled, so every fault escalates
+//! to a hard fault. The fault type is taken from the configurable fault
+//! status register. Its bits are sticky until written, so bits from an
+//! earlier fault may remain set in later frames.
+
+use core::ptr;
+
+const SCB_CFSR: *const u32 = 0xe000ed28 as *const u32;
+const SCB_HFSR: *const u32 = 0xe000ed2c as *const u32;
+const SCB_MMFAR: *const u32 = 0xe000ed34 as *const u32;
+const SCB_BFAR: *const u32 = 0xe000ed38 as *const u32;
+
+// CFSR fields.
+const CFSR_MMFSR: u32 = 0x0000_00ff;
+const CFSR_BFSR: u32 = 0x0000_ff00;
+const CFSR_UFSR: u32 = 0xffff_0000;
+const CFSR_MSTKERR: u32 = 1 << 4;
+const CFSR_STKERR: u32 = 1 << 12;
+
+// EXC_RETURN bit set when the exception was taken from thread mode on the
+// process stack, i.e. from an app.
+const EXC_RETURN_PROCESS_STACK: u32 = 1 << 2;
+
+// Offsets of the stacked LR and PC in the exception frame, in words.
+const FRAME_LR: usize = 5;
+const FRAME_PC: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultType {
+    HardFault = 0,
+    MemManage = 1,
+    BusFault = 2,
+    UsageFault = 3,
+}
+
+/// Number of fault types.
+pub const FAULT_TYPES: usize = 4;
+
+impl FaultType {
+    pub fn from_usize(value: usize) -> Option<FaultType> {
+        match value {
+            0 => Some(FaultType::HardFault),
+            1 => Some(FaultType::MemManage),
+            2 => Some(FaultType::BusFault),
+            3 => Some(FaultType::UsageFault),
+            _ => None,
+        }
+    }
+
+    fn from_cfsr(cfsr: u32) -> FaultType {
+        if cfsr & CFSR_MMFSR != 0 {
+            FaultType::MemManage
+        } else if cfsr & CFSR_BFSR != 0 {
+            FaultType::BusFault
+        } else if cfsr & CFSR_UFSR != 0 {
+            FaultType::UsageFault
+        } else {
+            FaultType::HardFault
+        }
+    }
+}
+
+/// The state of the CPU when a fault was taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FaultFrame {
+    /// The faulting instruction. Zero if the exception frame could not be
+    /// stacked.
+    pub pc: u32,
+    /// The link register of the faulting code. Zero if the exception frame
+    /// could not be stacked.
+    pub lr: u32,
+    /// Configurable fault status register.
+    pub cfsr: u32,
+    /// Hard fault status register.
+    pub hfsr: u32,
+    /// MemManage fault address register.
+    pub mmfar: u32,
+    /// Bus fault address register.
+    pub bfar: u32,
+    /// Whether the fault was taken in an app rather than in the kernel.
+    pub in_app: bool,
+}
+
+/// Statistics for one fault type.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FaultRecord {
+    /// Number of faults since boot.
+    pub count: u32,
+    /// The most recent fault, if any.
+    pub last: Option<FaultFrame>,
+}
+
+const NO_FAULTS: FaultRecord = FaultRecord { count: 0, last: None };
+
+// Only written by the fault handler, which no other fault handler can
+// preempt.
+static mut RECORDS: [FaultRecord; FAULT_TYPES] = [NO_FAULTS; FAULT_TYPES];
+
+/// Returns the statistics for `fault_type`.
+pub fn get(fault_type: FaultType) -> FaultRecord {
+    unsafe { ptr::read_volatile(&RECORDS[fault_type as usize]) }
+}
+
+#[no_mangle]
+unsafe extern "C" fn h1_record_fault(exc_return: u32, msp: u32, psp: u32) {
+    let cfsr = ptr::read_volatile(SCB_CFSR);
+    let in_app = exc_return & EXC_RETURN_PROCESS_STACK != 0;
+    let mut frame = FaultFrame {
+        pc: 0,
+        lr: 0,
+        cfsr: cfsr,
+        hfsr: ptr::read_volatile(SCB_HFSR),
+        mmfar: ptr::read_volatile(SCB_MMFAR),
+        bfar: ptr::read_volatile(SCB_BFAR),
+        in_app: in_app,
+    };
+    // Reading a frame that failed to stack would fault again.
+    if cfsr & (CFSR_MSTKERR | CFSR_STKERR) == 0 {
+        let stack = (if in_app { psp } else { msp }) as *const u32;
+        frame.pc = ptr::read_volatile(stack.add(FRAME_PC));
+        frame.lr = ptr::read_volatile(stack.add(FRAME_LR));
+    }
+
+    let record = &mut RECORDS[FaultType::from_cfsr(cfsr) as usize];
+    record.count = record.count.saturating_add(1);
+    record.last = Some(frame);
+}
+
+// The Cortex-M3 handler, by a name the trampoline can branch to.
+#[no_mangle]
+static H1_CORTEXM3_HARD_FAULT_HANDLER: unsafe extern "C" fn() = cortexm3::hard_fault_handler;
+
+/// Hard fault vector. Records the fault, then branches to the Cortex-M3 hard
+/// fault handler with LR and the stack pointers as they were on entry.
+///
+/// Recording runs on the kernel stack. If MSP is within 256 bytes of
+/// `_sstack` (or below it, after a kernel stack overflow), recording would
+/// fault again inside HardFault and lock up the core, so the fault goes
+/// straight to the Cortex-M3 handler, which recovers the stack and reports
+/// the overflow.
+#[naked]
+pub unsafe extern "C" fn hard_fault_handler() {
+    llvm_asm!("
+    mov    r0, lr
+    mrs    r1, msp
+    mrs    r2, psp
+    movw   r3, #:lower16:_sstack
+    movt   r3, #:upper16:_sstack
+    add    r3, r3, #256
+    cmp    r1, r3
+    bls    1f
+    push   {r4, lr}
+    bl     h1_record_fault
+    pop    {r4, lr}
+1:
+    movw   r3, #:lower16:H1_CORTEXM3_HARD_FAULT_HANDLER
+    movt   r3, #:upper16:H1_CORTEXM3_HARD_FAULT_HANDLER
+    ldr    r3, [r3]
+    bx     r3
+    "
+    :
+    :
+    :
+    : "volatile");
+}
@@ -0,0 +1,230 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Storage backend on a range of internal flash pages.
+//!
+//! Each flash page is one block. Blocks are read straight from the
+//! memory-mapped flash, and written by erasing the page and programming it
+//! in flash writes of `WRITE_WORDS` words. Internal flash is trusted, so
+//! blocks carry no integrity check; an erased or torn page reads back as
+//! whatever the flash holds, and stores must validate their records.
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient};
+use kernel::ReturnCode;
+
+use crate::hil::flash;
+use crate::hil::flash::h1_hw::{H1_FLASH_PAGE_SIZE, H1_FLASH_SIZE, H1_FLASH_START};
+use crate::hil::storage::{Client, Storage};
+
+/// Number of words in each flash write, the most the flash accepts.
+pub const WRITE_WORDS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    Reading,
+    Erasing,
+    Writing,
+}
+
+pub struct FlashStorage<'a> {
+    flash: &'a dyn flash::Flash<'a>,
+    first_page: usize,
+    page_count: usize,
+    client: OptionalCell<&'a dyn Client<'a>>,
+    buffer: TakeCell<'a, [u8]>,
+    write_buffer: TakeCell<'a, [u32]>,
+    state: Cell<State>,
+    page: Cell<usize>,
+    // Bytes of the page written so far.
+    written: Cell<usize>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a> FlashStorage<'a> {
+    /// Uses `page_count` pages starting at `first_page`, which must not be
+    /// used by anyone else. `write_buffer` must hold `WRITE_WORDS` words.
+    /// Reads complete through a deferred call, whose handle must be set with
+    /// `initialize_callback_handle`.
+    pub fn new(flash: &'a dyn flash::Flash<'a>,
+               first_page: usize,
+               page_count: usize,
+               write_buffer: &'a mut [u32],
+               deferred_caller: &'a DynamicDeferredCall) -> FlashStorage<'a> {
+        FlashStorage {
+            flash: flash,
+            first_page: first_page,
+            page_count: page_count,
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            write_buffer: TakeCell::new(write_buffer),
+            state: Cell::new(State::Idle),
+            page: Cell::new(0),
+            written: Cell::new(0),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.set(handle);
+    }
+
+    fn check_request(&self, block: usize, buffer: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            ReturnCode::EBUSY
+        } else if block >= self.page_count || buffer.len() != H1_FLASH_PAGE_SIZE ||
+                  (self.first_page + block + 1) * H1_FLASH_PAGE_SIZE > H1_FLASH_SIZE {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn write_chunk(&self) {
+        let write_buffer = match self.write_buffer.take() {
+            Some(write_buffer) => write_buffer,
+            None => {
+                self.finish_write(ReturnCode::ENOMEM);
+                return;
+            }
+        };
+        let written = self.written.get();
+        self.buffer.map(|buffer| {
+            let chunk = &buffer[written..written + WRITE_WORDS * 4];
+            for (word, bytes) in write_buffer.iter_mut().zip(chunk.chunks(4)) {
+                *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+        });
+        let target = (self.page.get() * H1_FLASH_PAGE_SIZE + written) / 4;
+        let (rcode, write_buffer) = self.flash.write(target, write_buffer);
+        match write_buffer {
+            None => self.state.set(State::Writing),
+            Some(write_buffer) => {
+                self.write_buffer.replace(write_buffer);
+                let rcode = if rcode == ReturnCode::SUCCESS { ReturnCode::FAIL } else { rcode };
+                self.finish_write(rcode);
+            }
+        }
+    }
+
+    fn finish_write(&self, rcode: ReturnCode) {
+        self.state.set(State::Idle);
+        if let Some(buffer) = self.buffer.take() {
+            self.client.map(|client| client.write_done(buffer, rcode));
+        }
+    }
+}
+
+impl<'a> Storage<'a> for FlashStorage<'a> {
+    fn set_client(&self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        H1_FLASH_PAGE_SIZE
+    }
+
+    fn block_count(&self) -> usize {
+        self.page_count
+    }
+
+    fn read_block(&self, block: usize, buffer: &'a mut [u8])
+                  -> (ReturnCode, Option<&'a mut [u8]>) {
+        let rcode = self.check_request(block, buffer);
+        if rcode != ReturnCode::SUCCESS {
+            return (rcode, Some(buffer));
+        }
+        let address = H1_FLASH_START + (self.first_page + block) * H1_FLASH_PAGE_SIZE;
+        // The flash is memory mapped.
+        let page = unsafe { core::slice::from_raw_parts(address as *const u8, H1_FLASH_PAGE_SIZE) };
+        buffer.copy_from_slice(page);
+
+        let scheduled = self.handle.map_or(false, |handle| {
+            self.deferred_caller.set(*handle).unwrap_or(false)
+        });
+        if !scheduled {
+            return (ReturnCode::FAIL, Some(buffer));
+        }
+        self.buffer.replace(buffer);
+        self.state.set(State::Reading);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn write_block(&self, block: usize, buffer: &'a mut [u8])
+                   -> (ReturnCode, Option<&'a mut [u8]>) {
+        let rcode = self.check_request(block, buffer);
+        if rcode != ReturnCode::SUCCESS {
+            return (rcode, Some(buffer));
+        }
+        let page = self.first_page + block;
+        let rcode = self.flash.erase(page);
+        if rcode != ReturnCode::SUCCESS {
+            return (rcode, Some(buffer));
+        }
+        self.buffer.replace(buffer);
+        self.page.set(page);
+        self.written.set(0);
+        self.state.set(State::Erasing);
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for FlashStorage<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.state.get() != State::Reading {
+            return;
+        }
+        self.state.set(State::Idle);
+        if let Some(buffer) = self.buffer.take() {
+            self.client.map(|client| client.read_done(buffer, ReturnCode::SUCCESS));
+        }
+    }
+}
+
+impl<'a> flash::Client<'a> for FlashStorage<'a> {
+    fn erase_done(&self, rcode: ReturnCode) {
+        if self.state.get() != State::Erasing {
+            return;
+        }
+        if rcode == ReturnCode::SUCCESS {
+            self.write_chunk();
+        } else {
+            self.finish_write(rcode);
+        }
+    }
+
+    fn write_done(&self, data: &'a mut [u32], rcode: ReturnCode) {
+        self.write_buffer.replace(data);
+        if self.state.get() != State::Writing {
+            return;
+        }
+        if rcode != ReturnCode::SUCCESS {
+            self.finish_write(rcode);
+            return;
+        }
+        self.written.set(self.written.get() + WRITE_WORDS * 4);
+        if self.written.get() < H1_FLASH_PAGE_SIZE {
+            self.write_chunk();
+        } else {
+            self.finish_write(ReturnCode::SUCCESS);
+        }
+    }
+}
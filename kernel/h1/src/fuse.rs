@@ -37,7 +37,9 @@ const FUSE_BASE_ADDR: u32 = 0x4045_0000;
 const FUSE_REGISTERS: StaticRef<Registers> =
     unsafe { StaticRef::new(FUSE_BASE_ADDR as *const Registers) };
 
-pub static mut FUSE: FuseController = FuseController::new(FUSE_REGISTERS);
+pub(crate) const unsafe fn fuse() -> FuseController {
+    FuseController::new(FUSE_REGISTERS)
+}
 
 /// Fuse Controller
 pub struct FuseController {
@@ -53,8 +53,9 @@ register_structs! {
         (0x00e4 => flash_region1_ctrl: ReadWrite<u32, REGION_CTRL::Register>),
         (0x00e8 => flash_region2_ctrl: ReadWrite<u32, REGION_CTRL::Register>),
         (0x00ec => flash_region3_ctrl: ReadWrite<u32, REGION_CTRL::Register>),
+        (0x00f0 => flash_region4_ctrl: ReadWrite<u32, REGION_CTRL::Register>),
 
-        (0x00f0 => _reserved00f0),
+        (0x00f4 => _reserved00f4),
 
         (0x0230 => flash_region0_base_addr: ReadWrite<u32>),
         (0x0234 => flash_region0_size: ReadWrite<u32>),
@@ -64,8 +65,10 @@ register_structs! {
         (0x0244 => flash_region2_size: ReadWrite<u32>),
         (0x0248 => flash_region3_base_addr: ReadWrite<u32>),
         (0x024c => flash_region3_size: ReadWrite<u32>),
+        (0x0250 => flash_region4_base_addr: ReadWrite<u32>),
+        (0x0254 => flash_region4_size: ReadWrite<u32>),
 
-        (0x0250 => @END),
+        (0x0258 => @END),
     }
 }
 
@@ -80,11 +83,18 @@ register_bitfields![u32,
     ]
 ];
 
+const H1_FLASH_START: u32 = crate::hil::flash::h1_hw::H1_FLASH_START as u32;
+const H1_RESERVED_START: u32 = crate::hil::flash::h1_hw::H1_RESERVED_START as u32;
+const H1_RESERVED_SIZE: u32 =
+    (crate::hil::flash::h1_hw::H1_RESERVED_PAGES * crate::hil::flash::h1_hw::H1_FLASH_PAGE_SIZE) as u32;
+
 const GLOBALSEC_BASE_ADDR: u32 = 0x4009_0000;
 const GLOBALSEC_REGISTERS: StaticRef<Registers> =
     unsafe { StaticRef::new(GLOBALSEC_BASE_ADDR as *const Registers) };
 
-pub static mut GLOBALSEC: GlobalSecHardware = GlobalSecHardware::new(GLOBALSEC_REGISTERS);
+pub(crate) const unsafe fn globalsec() -> GlobalSecHardware {
+    GlobalSecHardware::new(GLOBALSEC_REGISTERS)
+}
 
 pub struct Segments {
     pub ro_a: SegmentInfo,
@@ -164,8 +174,7 @@ impl GlobalSecHardware {
         // - REGION1 : Active RW image, already locked
         // - REGION2 : inactive RO image
         // - REGION3 : inactive RW image
-
-        const H1_FLASH_START: u32 = crate::hil::flash::h1_hw::H1_FLASH_START as u32;
+        // - REGION4 : reserved pages at the end of flash
 
         // Determine the inactive RO.
         match self.registers.flash_region0_base_addr.get() {
@@ -208,6 +217,15 @@ impl GlobalSecHardware {
             REGION_CTRL::EN::SET +
             REGION_CTRL::RD_EN::SET +
             REGION_CTRL::WR_EN::SET);
+
+        // Enable the reserved pages for reads and writes. They hold kernel
+        // data and stay writable after the write windows are closed.
+        self.registers.flash_region4_base_addr.set(H1_FLASH_START + H1_RESERVED_START);
+        self.registers.flash_region4_size.set(H1_RESERVED_SIZE);
+        self.registers.flash_region4_ctrl.write(
+            REGION_CTRL::EN::SET +
+            REGION_CTRL::RD_EN::SET +
+            REGION_CTRL::WR_EN::SET);
     }
 }
 
@@ -215,4 +233,35 @@ impl GlobalSec for GlobalSecHardware {
     fn get_runtime_segment_info(&self) -> RuntimeSegmentInfo {
         self.runtime_segment_info
     }
+
+    fn close_write_windows(&self) {
+        self.registers.flash_region2_ctrl.modify(REGION_CTRL::WR_EN::CLEAR);
+        self.registers.flash_region3_ctrl.modify(REGION_CTRL::WR_EN::CLEAR);
+    }
+
+    fn is_flash_writable(&self, offset: u32, size: u32) -> bool {
+        let start = match H1_FLASH_START.checked_add(offset) {
+            Some(start) => start,
+            None => return false,
+        };
+        let end = match start.checked_add(size) {
+            Some(end) => end,
+            None => return false,
+        };
+        let regs = &self.registers;
+        let regions = [
+            (&regs.flash_region0_ctrl, &regs.flash_region0_base_addr, &regs.flash_region0_size),
+            (&regs.flash_region1_ctrl, &regs.flash_region1_base_addr, &regs.flash_region1_size),
+            (&regs.flash_region2_ctrl, &regs.flash_region2_base_addr, &regs.flash_region2_size),
+            (&regs.flash_region3_ctrl, &regs.flash_region3_base_addr, &regs.flash_region3_size),
+        ];
+        // The write has to fit in a single region, since regions need not be
+        // adjacent.
+        regions.iter().any(|(ctrl, base_addr, region_size)| {
+            let region_start = base_addr.get();
+            ctrl.is_set(REGION_CTRL::EN) && ctrl.is_set(REGION_CTRL::WR_EN) &&
+                start >= region_start &&
+                end as u64 <= region_start as u64 + region_size.get() as u64
+        })
+    }
 }
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use self::Pin::*;
+use crate::spsc::SpscQueue;
 use core::cell::Cell;
 use core::mem::transmute;
 use kernel::common::cells::VolatileCell;
@@ -42,43 +43,28 @@ pub struct Port {
     pub pins: [GPIOPin; 16],
 }
 
-pub static mut PORT0: Port = Port {
-    pins: [GPIOPin::new(GPIO0_BASE, P0),
-           GPIOPin::new(GPIO0_BASE, P1),
-           GPIOPin::new(GPIO0_BASE, P2),
-           GPIOPin::new(GPIO0_BASE, P3),
-           GPIOPin::new(GPIO0_BASE, P4),
-           GPIOPin::new(GPIO0_BASE, P5),
-           GPIOPin::new(GPIO0_BASE, P6),
-           GPIOPin::new(GPIO0_BASE, P7),
-           GPIOPin::new(GPIO0_BASE, P8),
-           GPIOPin::new(GPIO0_BASE, P9),
-           GPIOPin::new(GPIO0_BASE, P10),
-           GPIOPin::new(GPIO0_BASE, P11),
-           GPIOPin::new(GPIO0_BASE, P12),
-           GPIOPin::new(GPIO0_BASE, P13),
-           GPIOPin::new(GPIO0_BASE, P14),
-           GPIOPin::new(GPIO0_BASE, P15)],
-};
-
-pub static mut PORT1: Port = Port {
-    pins: [GPIOPin::new(GPIO1_BASE, P0),
-           GPIOPin::new(GPIO1_BASE, P1),
-           GPIOPin::new(GPIO1_BASE, P2),
-           GPIOPin::new(GPIO1_BASE, P3),
-           GPIOPin::new(GPIO1_BASE, P4),
-           GPIOPin::new(GPIO1_BASE, P5),
-           GPIOPin::new(GPIO1_BASE, P6),
-           GPIOPin::new(GPIO1_BASE, P7),
-           GPIOPin::new(GPIO1_BASE, P8),
-           GPIOPin::new(GPIO1_BASE, P9),
-           GPIOPin::new(GPIO1_BASE, P10),
-           GPIOPin::new(GPIO1_BASE, P11),
-           GPIOPin::new(GPIO1_BASE, P12),
-           GPIOPin::new(GPIO1_BASE, P13),
-           GPIOPin::new(GPIO1_BASE, P14),
-           GPIOPin::new(GPIO1_BASE, P15)],
-};
+impl Port {
+    pub(crate) const unsafe fn new(base: *mut PortRegisters) -> Port {
+        Port {
+            pins: [GPIOPin::new(base, P0),
+                   GPIOPin::new(base, P1),
+                   GPIOPin::new(base, P2),
+                   GPIOPin::new(base, P3),
+                   GPIOPin::new(base, P4),
+                   GPIOPin::new(base, P5),
+                   GPIOPin::new(base, P6),
+                   GPIOPin::new(base, P7),
+                   GPIOPin::new(base, P8),
+                   GPIOPin::new(base, P9),
+                   GPIOPin::new(base, P10),
+                   GPIOPin::new(base, P11),
+                   GPIOPin::new(base, P12),
+                   GPIOPin::new(base, P13),
+                   GPIOPin::new(base, P14),
+                   GPIOPin::new(base, P15)],
+        }
+    }
+}
 
 #[derive(Clone,Copy,Debug)]
 pub enum Pin {
@@ -100,47 +86,71 @@ pub enum Pin {
     P15,
 }
 
+/// Number of edges a pin buffers before they are delivered to its client.
+const EVENT_QUEUE_LEN: usize = 4;
+
 pub struct GPIOPin {
     port: *mut PortRegisters,
     pin: Pin,
     change: Cell<bool>,
     client: Cell<Option<&'static dyn hil::gpio::Client>>,
+    // One entry per edge seen but not yet delivered.
+    events: SpscQueue<(), EVENT_QUEUE_LEN>,
 }
 
 impl GPIOPin {
-    const fn new(port: *mut PortRegisters, pin: Pin) -> GPIOPin {
+    /// Creates a pin driver outside of `Peripherals`.
+    ///
+    /// ## Safety
+    ///
+    /// The pin is also driven by the instance in `Peripherals`, so this is
+    /// only meant for the panic handler, once the kernel has stopped.
+    pub const unsafe fn new(port: *mut PortRegisters, pin: Pin) -> GPIOPin {
         GPIOPin {
             port: port,
             pin: pin,
             change: Cell::new(false),
             client: Cell::new(None),
+            events: SpscQueue::new(),
         }
     }
 
     pub fn handle_interrupt(&self) {
+        use kernel::hil::gpio::Input;
         let mask = 1 << (self.pin as u32);
 
         let port: &mut PortRegisters = unsafe { transmute(self.port) };
         port.interrupt_status.set(mask);
+        let _ = self.events.push(());
 
         // If our InterruptMode was `Change`, we need to flip the direction of
-        // the interrupt polarity.
+        // the interrupt polarity. If the pin already toggled back before the
+        // new polarity took effect, the hardware will not report that edge,
+        // so queue it here and flip again.
         if self.change.get() {
-            if port.interrupt_pol_set.get() & mask != 0 {
-                port.interrupt_pol_clear.set(mask);
-            } else {
-                port.interrupt_pol_set.set(mask);
+            for _ in 0..EVENT_QUEUE_LEN {
+                let rising = port.interrupt_pol_set.get() & mask != 0;
+                if rising {
+                    port.interrupt_pol_clear.set(mask);
+                } else {
+                    port.interrupt_pol_set.set(mask);
+                }
+                if self.read() == rising || self.events.push(()).is_err() {
+                    break;
+                }
             }
         }
 
-        self.client.get().map(|client| {
-            client.fired()
-        });
+        while self.events.pop().is_some() {
+            self.client.get().map(|client| {
+                client.fired()
+            });
+        }
     }
 
     // Returns the pinmux::Pin corresponding to this GPIO pin.
     fn get_pinmux_pin(&self) -> Option<&'static crate::pinmux::Pin> {
-        let pinmux = unsafe { &*crate::pinmux::PINMUX };
+        let pinmux = crate::pinmux::registers();
         let peripheral = match (self.port, self.pin) {
             (GPIO0_BASE, Pin::P0 ) => &pinmux.gpio0_gpio0,
             (GPIO0_BASE, Pin::P1 ) => &pinmux.gpio0_gpio1,
@@ -0,0 +1,53 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interface for the persistent board configuration.
+//!
+//! Changes are staged with `set` and only reach flash on `commit`. The
+//! configuration the board booted with stays in effect until the next boot.
+
+use kernel::ReturnCode;
+
+pub use spiutils::protocol::config::{BoardConfig, ConfigKey};
+
+pub trait ConfigStore<'a> {
+    /// Set the client for commit completions.
+    fn set_client(&self, client: &'a dyn Client);
+
+    /// Get the configuration the board booted with.
+    fn active(&self) -> BoardConfig;
+
+    /// Get the staged configuration. Without staged changes, this is the
+    /// last committed configuration.
+    fn staged(&self) -> BoardConfig;
+
+    /// Stage a new value for `key`. Returns EINVAL if `value` is out of range
+    /// for `key` and EBUSY while a commit is pending.
+    fn set(&self, key: ConfigKey, value: u32) -> ReturnCode;
+
+    /// Persist the staged configuration. Completion is signaled through
+    /// `Client::commit_done`. Returns EBUSY if a commit is pending.
+    fn commit(&self) -> ReturnCode;
+
+    /// Discard the staged changes, reverting to the last committed
+    /// configuration. Returns EBUSY while a commit is pending.
+    fn rollback(&self) -> ReturnCode;
+}
+
+pub trait Client {
+    /// Called when a `commit` call completed.
+    fn commit_done(&self, rcode: ReturnCode);
+}
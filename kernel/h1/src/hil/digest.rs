@@ -73,9 +73,22 @@ pub trait DigestEngine {
     /// stored.
     fn finalize(&self, output: &mut [u8]) -> Result<usize, DigestError>;
 
+    /// Finalizes an HMAC started with `initialize_hmac`, stores it in the
+    /// `output` buffer, and clears the key from the engine. Returns the number
+    /// of bytes stored.
+    fn finalize_hmac(&self, output: &mut [u8]) -> Result<usize, DigestError>;
+
     /// Finalize withtout seeing the result; this is used for certificates
     /// (hidden secret generation). Ok is always Ok(0); passes a usize
     /// to match the finalize() signature.
     fn finalize_hidden(&self) -> Result<usize, DigestError>;
 
 }
+
+/// Something that holds a digest engine across calls, such as the digest
+/// syscall driver holding it for an app's session.
+pub trait DigestOwner {
+    /// Returns true while the engine holds a digest in progress that must
+    /// not be disturbed.
+    fn has_session(&self) -> bool;
+}
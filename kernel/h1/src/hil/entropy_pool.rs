@@ -0,0 +1,47 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interface for querying the state of the entropy pool on H1
+
+use kernel::ReturnCode;
+
+/// State of the entropy pool.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct EntropyPoolStatus {
+    /// Whether the DRBG was seeded from the TRNG at least once.
+    pub is_seeded: bool,
+
+    /// Whether the TRNG failed to provide entropy for a reseed in time.
+    /// Randomness is still served from the DRBG in this state.
+    pub is_degraded: bool,
+
+    /// Number of successful reseeds.
+    pub reseed_count: u32,
+
+    /// Number of words generated since the last reseed.
+    pub words_since_reseed: u32,
+}
+
+pub trait EntropyPool {
+    /// Get the current state of the entropy pool.
+    fn get_status(&self) -> EntropyPoolStatus;
+
+    /// Fill `buf` with DRBG output without waiting for a callback.
+    /// Returns EOFF if the pool was not seeded yet, EBUSY if the AES
+    /// engine is in use and FAIL if the pool is degraded and its reseed
+    /// policy stops serving output then.
+    fn fill(&self, buf: &mut [u8]) -> ReturnCode;
+}
@@ -0,0 +1,165 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Write auditing for sensitive flash pages.
+//!
+//! A `WriteAudit` attached to a `MuxFlash` sees every erase and write before
+//! it is queued. Operations on a watched page are recorded in the audit
+//! journal, a ring of `AuditRecord`s holding the client, the page and a
+//! microsecond timestamp. Operations on a denied page are rejected with
+//! ERESERVE instead of being queued, and are recorded as well.
+//!
+//! Clients are numbered in the order they registered with the mux (see
+//! `FlashUser::id`). Only operations through the mux are audited.
+//!
+//! The journal also records when the board was locked down (see
+//! `crate::lockdown`), as a `Lockdown` record with client and page 0.
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::ReturnCode;
+use crate::timeus::Timeus;
+
+/// Number of operations kept in the audit journal.
+pub const AUDIT_RECORD_COUNT: usize = 32;
+
+/// The kind of operation audited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditKind {
+    Erase = 0,
+    Write = 1,
+    Lockdown = 2,
+}
+
+/// A single audited operation on one page.
+#[derive(Clone, Copy)]
+pub struct AuditRecord {
+    pub timestamp_us: u32,
+    /// The `FlashUser::id` of the client that requested the operation.
+    pub client: u8,
+    pub page: u16,
+    pub kind: AuditKind,
+    /// Whether the operation was rejected because the page is denied.
+    pub denied: bool,
+}
+
+impl AuditRecord {
+    pub const EMPTY: AuditRecord = AuditRecord {
+        timestamp_us: 0,
+        client: 0,
+        page: 0,
+        kind: AuditKind::Erase,
+        denied: false,
+    };
+}
+
+pub struct WriteAudit<'a> {
+    // Microsecond timer used to timestamp records.
+    timer: &'a Timeus,
+    watched: &'a [usize],
+    denied: &'a [usize],
+    records: TakeCell<'static, [AuditRecord; AUDIT_RECORD_COUNT]>,
+    // Index of the next record to write and the number of valid records.
+    next: Cell<usize>,
+    count: Cell<usize>,
+    // Number of records overwritten before they were dumped.
+    dropped: Cell<u32>,
+}
+
+impl<'a> WriteAudit<'a> {
+    /// Audits operations on the `watched` pages and rejects operations on
+    /// the `denied` pages. `timer` must already be running at 1MHz.
+    pub fn new(timer: &'a Timeus,
+               watched: &'a [usize],
+               denied: &'a [usize],
+               records: &'static mut [AuditRecord; AUDIT_RECORD_COUNT]) -> WriteAudit<'a> {
+        WriteAudit {
+            timer: timer,
+            watched: watched,
+            denied: denied,
+            records: TakeCell::new(records),
+            next: Cell::new(0),
+            count: Cell::new(0),
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Checks an operation by `client` on the pages `first_page` to
+    /// `last_page` inclusive, recording it if it touches a watched or denied
+    /// page. Returns ERESERVE if any of the pages is denied, SUCCESS
+    /// otherwise.
+    pub fn check(&self, client: u8, kind: AuditKind, first_page: usize, last_page: usize)
+                 -> ReturnCode {
+        let denied = (first_page..=last_page).any(|page| self.denied.contains(&page));
+        for page in first_page..=last_page {
+            if denied || self.watched.contains(&page) {
+                self.record(client, kind, page, denied);
+            }
+        }
+        if denied {
+            debug!("WriteAudit: denied {:?} of page {} by client {}", kind, first_page, client);
+            ReturnCode::ERESERVE
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Records that the board was locked down.
+    pub fn record_lockdown(&self) {
+        self.record(0, AuditKind::Lockdown, 0, false);
+    }
+
+    fn record(&self, client: u8, kind: AuditKind, page: usize, denied: bool) {
+        let timestamp_us = self.timer.now();
+        self.records.map(|records| {
+            records[self.next.get()] = AuditRecord {
+                timestamp_us: timestamp_us,
+                client: client,
+                page: page as u16,
+                kind: kind,
+                denied: denied,
+            };
+
+            self.next.set((self.next.get() + 1) % AUDIT_RECORD_COUNT);
+            if self.count.get() == AUDIT_RECORD_COUNT {
+                self.dropped.set(self.dropped.get().wrapping_add(1));
+            } else {
+                self.count.set(self.count.get() + 1);
+            }
+        });
+    }
+
+    /// Prints all audit records, oldest first, and empties the journal.
+    ///
+    /// Each record is printed as
+    /// `flashaudit: <timestamp_us> <client> <page> <kind> <denied>`.
+    pub fn dump(&self) {
+        self.records.map(|records| {
+            print!("flashaudit: begin dropped={}\n", self.dropped.get());
+            let count = self.count.get();
+            let first = (self.next.get() + AUDIT_RECORD_COUNT - count) % AUDIT_RECORD_COUNT;
+            for n in 0..count {
+                let record = &records[(first + n) % AUDIT_RECORD_COUNT];
+                print!("flashaudit: {} {} {} {} {}\n",
+                       record.timestamp_us, record.client, record.page, record.kind as u8,
+                       record.denied as u8);
+            }
+            print!("flashaudit: end\n");
+            self.count.set(0);
+            self.dropped.set(0);
+        });
+    }
+}
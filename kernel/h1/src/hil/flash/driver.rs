@@ -20,7 +20,9 @@ use ::kernel::hil::time::Alarm;
 use ::kernel::ReturnCode;
 use super::hardware::Bank;
 use super::hardware::Hardware;
+use super::hardware::WORDS_PER_BANK;
 use super::smart_program::SmartProgramState;
+use crate::hil::power::{PowerClient, SleepReadiness};
 
 /// The H1 flash driver. The hardware interface (either the real flash modules
 /// or the fake) is injected to support testing. This will not configure the
@@ -67,18 +69,11 @@ impl<'d, A: Alarm<'d>, H: Hardware> FlashImpl<'d, A, H> {
 }
 
 const MAX_WRITE_SIZE: usize = 32; // Maximum single write is 32 words
-const WORDS_PER_BANK: usize = 0x10000; // 64ki words per bank
 
 // Computes the flash Bank for the specified target location in words
 // from the beginning of flash.
 fn get_bank_from_target(target: usize) -> Option<Bank> {
-    if target < WORDS_PER_BANK {
-        Some(Bank::Zero)
-    } else if target < 2 * WORDS_PER_BANK {
-        Some(Bank::One)
-    } else {
-        None
-    }
+    Bank::from_word_offset(target)
 }
 
 impl<'d, A: Alarm<'d>, H: Hardware> super::flash::Flash<'d> for FlashImpl<'d, A, H> {
@@ -101,6 +96,11 @@ impl<'d, A: Alarm<'d>, H: Hardware> super::flash::Flash<'d> for FlashImpl<'d, A,
     }
 
     fn read(&self, word: usize) -> ReturnCode {
+        // The bank that is being programmed or erased cannot be read until the
+        // operation finishes. The other bank remains readable.
+        if let Some(bank) = get_bank_from_target(word) {
+            if self.is_bank_busy(bank) { return ReturnCode::EBUSY; }
+        }
         self.hw.read(word)
     }
 
@@ -176,7 +176,33 @@ impl<'d, A: Alarm<'d>, H: Hardware> ::kernel::hil::time::AlarmClient for FlashIm
     }
 }
 
+// A program or erase pulse must not be cut short by deep sleep; the smart
+// programming state machine also relies on the alarm, which deep sleep stops.
+impl<'d, A: Alarm<'d>, H: Hardware> PowerClient for FlashImpl<'d, A, H> {
+    fn prepare_sleep(&self) -> SleepReadiness {
+        if self.program_in_progress() {
+            SleepReadiness::Busy
+        } else {
+            SleepReadiness::Ready
+        }
+    }
+
+    fn resume(&self) {}
+}
+
 impl<'d, A: Alarm<'d>, H: Hardware> FlashImpl<'d, A, H> {
+    /// Returns the bank that is currently being programmed or erased, if any.
+    pub fn busy_bank(&self) -> Option<Bank> {
+        if self.program_in_progress() { Some(self.write_bank.get()) } else { None }
+    }
+
+    /// Returns true if the given bank is currently being programmed or erased.
+    /// Reads from a busy bank are rejected, while the other bank stays
+    /// available for reads.
+    pub fn is_bank_busy(&self, bank: Bank) -> bool {
+        self.busy_bank() == Some(bank)
+    }
+
     /// Returns true if an operation is in progress and false otherwise.
     fn program_in_progress(&self) -> bool {
         // SmartProgramState is not Copy, so we can't use Cell::get() or
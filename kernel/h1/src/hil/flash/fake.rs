@@ -132,6 +132,10 @@ impl super::hardware::Hardware for FakeHw {
         self.opcode.get() != 0
     }
 
+    fn is_bank_programming(&self, bank: Bank) -> bool {
+        self.opcode.get() != 0 && self.transaction_bank.get() == Some(bank)
+    }
+
     fn read(&self, offset: usize) -> kernel::ReturnCode {
         const FLASH_BANK_WORDS: usize = super::h1_hw::H1_FLASH_BANK_SIZE / 4;
         let bank_offset = offset % FLASH_BANK_WORDS;
@@ -20,13 +20,24 @@ use kernel::common::registers::{self, register_bitfields, ReadWrite};
 
 // The hardware flash controller. Cannot be used in userspace (accessing will
 // trigger a fault), and should only be manipulated by the flash hardware.
-pub static mut H1_HW: *const H1bHw = 0x40720000 as *const H1bHw;
+const H1_HW_BASE: usize = 0x40720000;
+
+pub(crate) unsafe fn h1_hw() -> &'static H1bHw {
+    &*(H1_HW_BASE as *const H1bHw)
+}
 
 pub const H1_FLASH_START: usize     = 0x40000;
 pub const H1_FLASH_BANK_SIZE: usize = 0x40000;
 pub const H1_FLASH_SIZE: usize      = 0x80000; // Two banks
 pub const H1_FLASH_PAGE_SIZE: usize = 0x00800; // 2kB
 
+// The last pages of flash hold kernel data: the non-volatile counter (n-1,
+// n-2), personality (n-3, n-7), the key store (n-4, n-8) and the board
+// configuration (n-5, n-6). Boards open them for writes with a GLOBALSEC
+// flash region of their own and keep them out of their RW segments.
+pub const H1_RESERVED_PAGES: usize = 8;
+pub const H1_RESERVED_START: usize  = H1_FLASH_SIZE - H1_RESERVED_PAGES * H1_FLASH_PAGE_SIZE;
+
 pub const H1_INFO_0_START: usize    = 0x20000;
 pub const H1_INFO_1_START: usize    = 0x28000;
 pub const H1_INFO_SIZE: usize       = 0x00800;
@@ -248,6 +259,13 @@ impl super::hardware::Hardware for H1bHw {
         self.pe_control_0.get() != 0 || self.pe_control_1.get() != 0
     }
 
+    fn is_bank_programming(&self, bank: super::hardware::Bank) -> bool {
+        match bank {
+            super::hardware::Bank::Zero => self.pe_control_0.get() != 0,
+            super::hardware::Bank::One => self.pe_control_1.get() != 0,
+        }
+    }
+
     fn read(&self, offset: usize) -> ReturnCode {
         // The two flash macros are in consecutive memory locations, so they can
         // be addressed as one.
@@ -15,17 +15,51 @@
 use kernel::ReturnCode;
 
 /// The bank to perform an operation on.
+///
+/// H1 has two independent flash macros. While one bank is being programmed or
+/// erased the other bank can still be read (and executed from), which allows
+/// staging a firmware image into the inactive bank without stalling the
+/// active one.
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Bank {
     Zero,
     One,
 }
 
+/// Number of words in a single flash bank.
+pub const WORDS_PER_BANK: usize = 0x10000; // 64ki words per bank
+
+impl Bank {
+    /// Returns the bank containing the given word offset (relative to the
+    /// start of flash), or None if the offset is out of range.
+    pub fn from_word_offset(offset: usize) -> Option<Bank> {
+        if offset < WORDS_PER_BANK {
+            Some(Bank::Zero)
+        } else if offset < 2 * WORDS_PER_BANK {
+            Some(Bank::One)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the opposite bank.
+    pub fn other(self) -> Bank {
+        match self {
+            Bank::Zero => Bank::One,
+            Bank::One => Bank::Zero,
+        }
+    }
+}
+
 /// The interface between the flash driver and the (real or fake) flash module.
 pub trait Hardware {
     /// Returns true if an operation is running, false otherwise.
     fn is_programming(&self) -> bool;
 
+    /// Returns true if an operation is running on the given bank, false
+    /// otherwise.
+    fn is_bank_programming(&self, bank: Bank) -> bool;
+
     /// Read a single word from the flash (non-blocking). offset is in units of
     /// words and is relative to the start of flash.
     fn read(&self, offset: usize) -> ReturnCode;
@@ -16,6 +16,7 @@
 // more representative of the H1 flash hardware's capabilities (e.g. sub-page
 // writes and counters).
 
+pub mod audit;
 pub mod driver;
 #[cfg(feature = "test")]
 pub mod fake;
@@ -34,6 +35,7 @@ pub type FlashImpl<'h, A> = self::driver::FlashImpl<'static, A, self::h1_hw::H1b
 
 pub use self::flash::{Client,Flash};
 pub use self::hardware::Bank;
+pub use self::hardware::WORDS_PER_BANK;
 pub use self::hardware::Hardware;
 
 // Constants used by multiple submodules.
@@ -53,8 +53,9 @@ impl SmartProgramState {
             },
             Running(attempts_remaining, final_pulse_needed, timeout_nanoseconds) => {
                 // Copied from Cr50: a timeout causes an immediate failure with
-                // no retry.
-                if hw.is_programming() {
+                // no retry. Only the bank being operated on matters; the other
+                // bank may be serving reads.
+                if hw.is_bank_programming(bank) {
                     alarm.disarm();
                     return Finished(ReturnCode::FAIL);
                 }
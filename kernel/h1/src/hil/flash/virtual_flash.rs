@@ -16,14 +16,19 @@ use core::cell::Cell;
 use ::kernel::common::cells::{OptionalCell, TakeCell};
 use ::kernel::common::{List, ListLink, ListNode};
 use ::kernel::ReturnCode;
+use super::audit::{AuditKind, WriteAudit};
 use super::flash::Flash;
 use super::flash::Client;
+use super::WORDS_PER_PAGE;
 
 /// Virtualizes the H1 flash abstraction to support multiple clients.
 pub struct MuxFlash<'f> {
     driver: &'f dyn Flash<'f>,
     users: List<'f, FlashUser<'f>>,
     in_flight: OptionalCell<&'f FlashUser<'f>>,
+    audit: OptionalCell<&'f WriteAudit<'f>>,
+    // The id given to the next client that registers.
+    next_id: Cell<u8>,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -41,6 +46,7 @@ pub struct FlashUser<'f> {
     operation: Cell<Operation>,
     next: ListLink<'f, FlashUser<'f>>,
     client: OptionalCell<&'f dyn Client<'f>>,
+    id: Cell<u8>,
 }
 
 impl<'f> Client<'f> for MuxFlash<'f> {
@@ -69,9 +75,17 @@ impl<'f> FlashUser<'f> {
             write_pos: Cell::new(0),
             operation: Cell::new(Operation::Idle),
             next: ListLink::empty(),
-            client: OptionalCell::empty()
+            client: OptionalCell::empty(),
+            id: Cell::new(0),
         }
     }
+
+    /// Returns the number of this client, in the order clients registered
+    /// with the mux, starting at 0. Used to tell clients apart in the write
+    /// audit journal.
+    pub fn id(&self) -> u8 {
+        self.id.get()
+    }
 }
 
 impl<'f> Flash<'f> for FlashUser<'f> {
@@ -79,6 +93,10 @@ impl<'f> Flash<'f> for FlashUser<'f> {
         if self.operation.get() != Operation::Idle {
             return ReturnCode::EBUSY;
         }
+        let rcode = self.mux.audit(self.id.get(), AuditKind::Erase, page, page);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
         self.operation.set(Operation::Erase(page));
         self.mux.do_next_op();
         ReturnCode::SUCCESS
@@ -92,6 +110,12 @@ impl<'f> Flash<'f> for FlashUser<'f> {
         if self.operation.get() != Operation::Idle {
             return (ReturnCode::EBUSY, Some(data));
         }
+        let last = target + data.len().saturating_sub(1);
+        let rcode = self.mux.audit(self.id.get(), AuditKind::Write,
+                                   target / WORDS_PER_PAGE, last / WORDS_PER_PAGE);
+        if rcode != ReturnCode::SUCCESS {
+            return (rcode, Some(data));
+        }
         self.write_pos.set(target);
         self.write_len.set(data.len());
         self.buffer.replace(data);
@@ -101,6 +125,8 @@ impl<'f> Flash<'f> for FlashUser<'f> {
     }
 
     fn set_client(&'f self, client: &'f dyn Client<'f>) {
+        self.id.set(self.mux.next_id.get());
+        self.mux.next_id.set(self.mux.next_id.get().wrapping_add(1));
         self.mux.users.push_head(self);
         self.client.set(client);
     }
@@ -125,9 +151,22 @@ impl<'f> MuxFlash<'f> {
             driver: driver,
             users: List::new(),
             in_flight: OptionalCell::empty(),
+            audit: OptionalCell::empty(),
+            next_id: Cell::new(0),
         }
     }
 
+    /// Checks every erase and write against `audit` before queuing it.
+    pub fn set_audit(&self, audit: &'f WriteAudit<'f>) {
+        self.audit.set(audit);
+    }
+
+    fn audit(&self, client: u8, kind: AuditKind, first_page: usize, last_page: usize)
+             -> ReturnCode {
+        self.audit.map_or(ReturnCode::SUCCESS,
+                          |audit| audit.check(client, kind, first_page, last_page))
+    }
+
     fn do_next_op(&self) {
         if self.in_flight.is_some() {
             return;
@@ -21,4 +21,11 @@ use spiutils::driver::firmware::RuntimeSegmentInfo;
 pub trait GlobalSec {
     /// Get runtime information about firmware segments.
     fn get_runtime_segment_info(&self) -> RuntimeSegmentInfo;
+
+    /// Disables writes to the inactive RO and RW segments.
+    fn close_write_windows(&self);
+
+    /// Returns true if the GLOBALSEC flash regions allow writes to all of
+    /// `size` bytes at `offset`, given from the start of flash.
+    fn is_flash_writable(&self, offset: u32, size: u32) -> bool;
 }
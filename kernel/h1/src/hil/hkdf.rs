@@ -0,0 +1,58 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interface for HKDF-SHA256 key derivation (RFC 5869).
+
+use kernel::ReturnCode;
+
+/// Length in bytes of a pseudorandom key produced by `extract`.
+pub const PRK_LEN: usize = 32;
+
+/// Longest output `expand` can produce (255 SHA-256 blocks).
+pub const MAX_OUTPUT_LEN: usize = 255 * PRK_LEN;
+
+/// Length in bytes of a secret provided by a `KeySource`.
+pub const SECRET_LEN: usize = 32;
+
+/// Implementations return EBUSY if the hash engine is in use.
+pub trait Hkdf {
+    /// HKDF-Extract: computes the pseudorandom key for `ikm` and `salt`.
+    /// An empty salt is treated as `PRK_LEN` zero bytes.
+    fn extract(&self, salt: &[u8], ikm: &[u8], prk: &mut [u8; PRK_LEN]) -> ReturnCode;
+
+    /// HKDF-Expand: fills `okm` with key material derived from `prk`
+    /// and `info`. Returns ESIZE if `okm` is longer than `MAX_OUTPUT_LEN`.
+    fn expand(&self, prk: &[u8; PRK_LEN], info: &[u8], okm: &mut [u8]) -> ReturnCode;
+
+    /// Extract followed by expand. The intermediate key is wiped before
+    /// returning.
+    fn derive(&self, salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8]) -> ReturnCode {
+        let mut prk = [0u8; PRK_LEN];
+        let mut rcode = self.extract(salt, ikm, &mut prk);
+        if rcode == ReturnCode::SUCCESS {
+            rcode = self.expand(&prk, info, okm);
+        }
+        ecc::wipe(&mut prk);
+        rcode
+    }
+}
+
+/// A kernel-held secret that can be used as HKDF input key material
+/// without being exposed to apps.
+pub trait KeySource {
+    /// Copies the secret into `secret`. The caller must wipe it after use.
+    fn secret(&self, secret: &mut [u8; SECRET_LEN]) -> ReturnCode;
+}
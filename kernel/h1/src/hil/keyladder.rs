@@ -0,0 +1,58 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interface for deriving keys from the KEYMGR key ladder.
+//!
+//! Derived keys are bound to the chip and to the firmware's key ladder
+//! position, so the same usage and context always give the same key and
+//! nothing secret has to be kept in flash.
+
+use kernel::ReturnCode;
+
+/// Length in bytes of a derived key.
+pub const KEY_LEN: usize = 32;
+
+/// What a derived key is used for. Keys for different usages are
+/// derived from different key ladder branches, so they never coincide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyUsage {
+    /// Keys that sign attestation statements.
+    Attestation = 0,
+    /// Keys bound to a relying party, e.g. U2F origin keys.
+    Origin = 1,
+    /// Keys that protect data stored in flash.
+    Storage = 2,
+}
+
+impl KeyUsage {
+    pub fn from_usize(value: usize) -> Option<KeyUsage> {
+        match value {
+            0 => Some(KeyUsage::Attestation),
+            1 => Some(KeyUsage::Origin),
+            2 => Some(KeyUsage::Storage),
+            _ => None,
+        }
+    }
+}
+
+pub trait KeyLadder {
+    /// Derives the key for `usage` and `context` into `key`. The parts of
+    /// `context` are hashed in order, so callers that mix variable-length
+    /// parts must make the split unambiguous themselves. Returns FAIL if
+    /// the key ladder reported an error and EBUSY if the hash engine is in
+    /// use; `key` is wiped in both cases.
+    fn derive(&self, usage: KeyUsage, context: &[&[u8]], key: &mut [u8; KEY_LEN]) -> ReturnCode;
+}
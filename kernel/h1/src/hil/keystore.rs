@@ -0,0 +1,112 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interface for generating and using device-bound signing keys.
+//!
+//! Private keys never leave the key store. Callers refer to keys through
+//! opaque handles, which the key store hands out when a key is generated.
+//! Every key belongs to the owner named when it was generated, and its
+//! handle is unknown to any other owner.
+
+use kernel::ReturnCode;
+
+pub use ecc::p256::{PublicKey, Signature};
+
+/// An opaque reference to a key held by the key store.
+pub type KeyHandle = u32;
+
+/// Length in bytes of a message digest accepted by `sign`.
+pub const DIGEST_LEN: usize = ecc::p256::SCALAR_LEN;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    /// ECDSA on NIST P-256.
+    EcdsaP256 = 0,
+    /// RSA-2048. Not supported yet.
+    Rsa2048 = 1,
+}
+
+impl KeyType {
+    pub fn from_usize(value: usize) -> Option<KeyType> {
+        match value {
+            0 => Some(KeyType::EcdsaP256),
+            1 => Some(KeyType::Rsa2048),
+            _ => None,
+        }
+    }
+}
+
+/// How the per-signature nonce is chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonceMode {
+    /// Draw the nonce from the entropy pool.
+    Random = 0,
+    /// Derive the nonce from the key and the digest (RFC 6979), so that
+    /// signing does not depend on the entropy source.
+    Deterministic = 1,
+}
+
+impl NonceMode {
+    pub fn from_usize(value: usize) -> Option<NonceMode> {
+        match value {
+            0 => Some(NonceMode::Random),
+            1 => Some(NonceMode::Deterministic),
+            _ => None,
+        }
+    }
+}
+
+pub trait KeyStore<'a> {
+    /// Set the client for generate, sign and delete completions.
+    fn set_client(&self, client: &'a dyn Client);
+
+    /// Generate a new key for `owner`, at most 255 bytes long, and persist
+    /// it. Completion is signaled through `Client::generate_done`, which
+    /// carries the handle of the new key. Returns ENOSUPPORT for key types
+    /// that cannot be generated, ENOMEM if all key slots are used and EBUSY
+    /// if another operation is pending or the crypto engine is in use.
+    fn generate(&self, key_type: KeyType, owner: &[u8]) -> ReturnCode;
+
+    /// Get the public key for `handle`. Returns EINVAL for handles unknown
+    /// to `owner`.
+    fn public_key(&self, handle: KeyHandle, owner: &[u8]) -> Result<PublicKey, ReturnCode>;
+
+    /// Sign a message digest with the key for `handle`, choosing the nonce
+    /// according to `nonce_mode`. Completion is signaled through
+    /// `Client::sign_done`. Returns EINVAL for handles unknown to `owner`
+    /// and EBUSY if another operation is pending, the crypto engine is in
+    /// use, or a deterministic nonce needs the hash engine while it is in
+    /// use.
+    fn sign(&self, handle: KeyHandle, owner: &[u8], digest: &[u8; DIGEST_LEN],
+            nonce_mode: NonceMode) -> ReturnCode;
+
+    /// Delete the key for `handle`. Returns EINVAL for handles unknown to
+    /// `owner` and EBUSY if another operation is pending. Completion is signaled through `Client::delete_done`.
+    fn delete(&self, handle: KeyHandle, owner: &[u8]) -> ReturnCode;
+}
+
+pub trait Client {
+    /// Called when a `generate` call completed. `handle` is only valid if
+    /// `rcode` is SUCCESS.
+    fn generate_done(&self, rcode: ReturnCode, handle: KeyHandle);
+
+    /// Called when a `sign` call completed. `signature` is only valid if
+    /// `rcode` is SUCCESS.
+    fn sign_done(&self, rcode: ReturnCode, signature: &Signature);
+
+    /// Called when a `delete` call completed.
+    fn delete_done(&self, rcode: ReturnCode);
+}
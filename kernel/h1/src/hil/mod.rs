@@ -13,13 +13,21 @@
 // limitations under the License.
 
 pub mod aes;
+pub mod board_config;
 pub mod common;
 pub mod digest;
+pub mod entropy_pool;
 pub mod flash;
 pub mod fuse;
 pub mod globalsec;
+pub mod hkdf;
+pub mod keyladder;
+pub mod keystore;
 pub mod personality;
+pub mod power;
 pub mod reset;
 pub mod rng;
 pub mod spi_host;
 pub mod spi_device;
+pub mod storage;
+pub mod timebase;
@@ -17,6 +17,17 @@
 
 use kernel::ReturnCode;
 
+/// Length of the attestation data, in bytes.
+pub const PERSONALITY_SIZE: usize = 2048;
+
+/// Length of the part of the attestation data that is stored, in bytes. The
+/// tail of the certificate field is reserved for the commit epilogue; it is
+/// not stored and reads as zero.
+pub const PERSONALITY_STORED_SIZE: usize = PERSONALITY_SIZE - 64;
+
+/// Maximum length of a certificate that can be stored, in bytes.
+pub const MAX_CERTIFICATE_LEN: usize = PERSONALITY_STORED_SIZE - (4 + 5 * 32);
+
 /// Structure of device attestation data.
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -27,9 +38,33 @@ pub struct PersonalityData {
     pub pub_y: [u32; 8],
     pub certificate_hash: [u32; 8],
     pub certificate_len: u32,
-    pub certificate: [u8; 2048 - (4 + 5 * 32)],
+    pub certificate: [u8; PERSONALITY_SIZE - (4 + 5 * 32)],
 }
 
+impl PersonalityData {
+    pub const EMPTY: PersonalityData = PersonalityData {
+        checksum: [0; 8],
+        salt: [0; 8],
+        pub_x: [0; 8],
+        pub_y: [0; 8],
+        certificate_hash: [0; 8],
+        certificate_len: 0,
+        certificate: [0; PERSONALITY_SIZE - (4 + 5 * 32)],
+    };
+}
+
+/// Integrity of the stored attestation data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    /// The data comes from the latest committed update.
+    Committed = 0,
+    /// No update has been committed yet. The data is read as written before
+    /// updates were atomic, or as erased flash.
+    Legacy = 1,
+    /// The latest update was interrupted, e.g. by a power loss. Its copy is
+    /// ignored and the previous data, if any, is read instead.
+    Corrupted = 2,
+}
 
 /// Trait for getting and setting device attestation data.
 ///
@@ -46,11 +81,23 @@ pub trait Personality<'a> {
     /// must be at least 2048 bytes long.
     fn get_u8(&self, personality: &mut [u8]) -> ReturnCode;
 
-    /// Set the device's attestation data.
+    /// Set the device's attestation data. Replaces any staged data.
     fn set(&self, personality: &mut PersonalityData) -> ReturnCode;
     /// Set the device's attestation data from a slice; this slice
-    /// must be at least 2048 bytes long.
+    /// must be at least 2048 bytes long. Replaces any staged data.
     fn set_u8(&self, personality: &mut [u8]) -> ReturnCode;
+
+    /// Stage new attestation data from a slice, which must be at least 2048
+    /// bytes long. Nothing is written until `commit` is called.
+    fn begin_u8(&self, personality: &[u8]) -> ReturnCode;
+    /// Durably store the staged data. Either all of it or none of it
+    /// replaces the current data, even across a power loss.
+    fn commit(&self) -> ReturnCode;
+    /// Discard the staged data.
+    fn abort(&self) -> ReturnCode;
+
+    /// Report the integrity of the stored data.
+    fn status(&self) -> Result<Status, ReturnCode>;
 }
 
 /// A [Personality](trait.Personality.html) client
@@ -65,4 +112,8 @@ pub trait Client<'a> {
     /// Called by (Personality)[trait.Personality.html] when a call to
     /// `set_u8` has been committed to nonvolatile storage.
     fn set_u8_done(&self, rval: ReturnCode);
+
+    /// Called by (Personality)[trait.Personality.html] when a call to
+    /// `commit` has completed.
+    fn commit_done(&self, rval: ReturnCode);
 }
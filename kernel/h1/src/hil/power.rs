@@ -0,0 +1,43 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interface for drivers that must be quiesced before deep sleep.
+//!
+//! Deep sleep stops the clocks of peripherals that may be in the middle of
+//! an operation, such as a flash program pulse or a SPI transaction.
+//! Drivers that own such operations implement `PowerClient` and are listed
+//! in the board's `pmu::DeepSleep` table, which asks all of them before the
+//! chip enters deep sleep.
+
+/// A driver's answer to a deep sleep request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SleepReadiness {
+    /// The driver is idle and its state survives deep sleep.
+    Ready,
+    /// The driver is mid-operation. Deep sleep is vetoed for now and asked
+    /// again the next time the kernel goes idle.
+    Busy,
+}
+
+pub trait PowerClient {
+    /// Called with interrupts disabled right before deep sleep. Any state
+    /// that deep sleep would lose must be saved here.
+    fn prepare_sleep(&self) -> SleepReadiness;
+
+    /// Called after waking up, and when a sleep attempt is called off after
+    /// `prepare_sleep` returned `Ready`.
+    fn resume(&self);
+}
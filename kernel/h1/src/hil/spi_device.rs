@@ -1,6 +1,12 @@
 //! Interfaces for SPI device on H1
 
+use spiutils::driver::spi_device::AccessMetrics;
+use spiutils::driver::spi_device::AccessRegion;
 use spiutils::driver::spi_device::AddressConfig;
+use spiutils::driver::spi_device::DeniedAccessResponse;
+use spiutils::driver::spi_device::EmulatedOperation;
+use spiutils::driver::spi_device::HandlerMode;
+use spiutils::driver::spi_device::PassthroughFilterAction;
 use spiutils::protocol::flash::AddressMode;
 
 pub trait SpiDeviceClient {
@@ -11,14 +17,26 @@ pub trait SpiDeviceClient {
     /// be called to finish the transaction.
     ///
     /// `is_write_enabled`: Whether the "write enabled" bit is set.
-    fn data_available(&self, is_busy: bool, is_write_enabled: bool);
+    ///
+    /// Returns false if the client cannot take the data yet. The data then
+    /// stays in the device and is offered again by the next call to
+    /// SpiDevice.poll_data_available.
+    fn data_available(&self, is_busy: bool, is_write_enabled: bool) -> bool;
+
+    /// Called when the SPI host has read the data put by
+    /// SpiDevice.put_mailbox_data.
+    fn mailbox_read(&self);
 }
 
 pub trait SpiDevice {
     fn set_client(&self, client: Option<&'static dyn SpiDeviceClient>);
 
     /// Configure addresses exposed by this SPI device on the SPI bus.
-    fn configure_addresses(&self, config: AddressConfig);
+    ///
+    /// Returns EINVAL, leaving the previous configuration in place, if the
+    /// configuration fails `AddressConfig::validate` or the mailbox RAM
+    /// would extend past the end of the address space.
+    fn configure_addresses(&self, config: AddressConfig) -> kernel::ReturnCode;
 
     /// Configure the engine's address mode.
     fn set_address_mode(&self, address_mode: AddressMode);
@@ -26,6 +44,10 @@ pub trait SpiDevice {
     /// Get the engine's address mode.
     fn get_address_mode(&self) -> AddressMode;
 
+    /// Get the multi-line fast reads the engine passes through, as
+    /// `READ_CAPABILITY_*` bits.
+    fn get_read_capabilities(&self) -> u32;
+
     /// Get data received from the SPI host.
     ///
     /// `read_buffer`: Received data is written into this buffer.
@@ -33,6 +55,13 @@ pub trait SpiDevice {
     /// Returns the length of data written into `read_buffer`.
     fn get_received_data(&self, read_buffer: &mut [u8]) -> usize;
 
+    /// Check whether the SPI host has sent data that has not been picked
+    /// up yet and, if so, notify the client synchronously without waiting
+    /// for the interrupt.
+    ///
+    /// Returns true if data was pending.
+    fn poll_data_available(&self) -> bool;
+
     /// Put data to send to the SPI host.
     ///
     /// `write_data`: All data from this buffer is copied into the HW buffer.
@@ -40,10 +69,30 @@ pub trait SpiDevice {
     /// is padded with 0xFF.
     fn put_send_data(&self, write_data: &[u8]) -> kernel::ReturnCode;
 
+    /// Put a payload for the SPI host into the mailbox, which starts at the
+    /// beginning of the RAM, like `put_send_data`. Once the SPI host has
+    /// read it, or the first 256 bytes of a longer payload,
+    /// SpiDeviceClient.mailbox_read is called.
+    ///
+    /// A later `put_send_data` or `put_mailbox_data` cancels the pending
+    /// notification.
+    fn put_mailbox_data(&self, write_data: &[u8]) -> kernel::ReturnCode;
+
+    /// Publish the info block, which the SPI host reads from the last page of
+    /// the RAM without involving the client. Once set, `put_send_data` no
+    /// longer writes to that page.
+    ///
+    /// `data`: Block contents, padded with 0xFF to the page size.
+    fn set_info_block(&self, data: &[u8]) -> kernel::ReturnCode;
+
     /// Set the contents of the SPI flash status register.
     /// Note that this does not include the busy bit and the write enable bit.
     fn set_status(&self, status: u8);
 
+    /// Get the contents of the SPI flash status register as the SPI host
+    /// reads it, including the busy bit and the write enable bit.
+    fn get_status(&self) -> u8;
+
     /// Clear the busy bit.
     fn clear_busy(&self);
 
@@ -58,4 +107,65 @@ pub trait SpiDevice {
 
     /// Configure SFDP
     fn set_sfdp(&self, data: &[u8]) -> kernel::ReturnCode;
+
+    /// Configure the access map. Addresses not covered by any region are
+    /// readable and writable. If regions overlap, the first match wins.
+    ///
+    /// Reads into denied regions of the external flash window are answered by
+    /// hardware. Since there is only one such window, denied regions must be
+    /// page aligned and located at the start or the end of the window.
+    fn set_access_regions(&self, regions: &[AccessRegion]) -> kernel::ReturnCode;
+
+    /// Configure how denied accesses are answered.
+    fn set_denied_access_response(&self, response: DeniedAccessResponse);
+
+    /// Check a command received from the SPI host against the access map.
+    ///
+    /// `address`: The address of the command, or None for commands that
+    /// affect the whole address space (e.g. chip erase).
+    ///
+    /// Writes into the part of the external flash window protected by the
+    /// block protect bits of the status register are denied as well. A
+    /// chip erase is denied if any part is protected.
+    ///
+    /// If the access is denied, it is counted in the access metrics and
+    /// answered according to the configured `DeniedAccessResponse`, except
+    /// that writes to protected blocks always clear BUSY like on a real
+    /// flash. The caller must drop the command in that case.
+    ///
+    /// Returns true if the access is allowed.
+    fn check_access(&self, address: Option<u32>, is_write: bool) -> bool;
+
+    /// Configure what passthrough does with commands with `op_code` from the
+    /// SPI host. `PassthroughFilterAction::Default` removes an override.
+    ///
+    /// The filter only decides what reaches the external flash. Commands
+    /// that the hardware does not answer itself are still delivered to the
+    /// client, so blocking one leaves it to the client alone.
+    ///
+    /// Returns ESIZE, leaving the filter as it was, if there are too many
+    /// overrides or the hardware does not have enough filter rules for them.
+    fn set_passthrough_filter(&self, op_code: u8, action: PassthroughFilterAction) -> kernel::ReturnCode;
+
+    /// Get the counters for denied accesses.
+    fn get_access_metrics(&self) -> AccessMetrics;
+
+    /// Keep the busy bit set for at least `latency_us` after the SPI host
+    /// sends a command performing `operation`, so that the SPI host sees the
+    /// timing of a real flash. A latency of 0 turns the emulation off.
+    ///
+    /// Returns ENOSUPPORT if the device cannot emulate timing.
+    fn set_emulated_latency(&self, operation: EmulatedOperation, latency_us: u32) -> kernel::ReturnCode;
+
+    /// Configure who handles program and erase commands (OpCode::PageProgram,
+    /// OpCode::SectorErase, OpCode::BlockErase32KB and OpCode::BlockErase64KB).
+    ///
+    /// With `HandlerMode::KernelSpace`, such commands for the device's flash
+    /// region are applied to the H1 flash without involving the client. Any
+    /// other mode leaves them to the client.
+    ///
+    /// `spi_base`: The address on the SPI bus that the flash region starts at.
+    ///
+    /// Returns ENOSUPPORT if the device has no flash region.
+    fn set_program_erase_handling(&self, handler_mode: HandlerMode, spi_base: u32) -> kernel::ReturnCode;
 }
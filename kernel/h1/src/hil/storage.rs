@@ -0,0 +1,81 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interface for block storage that persistent stores can be placed on.
+//!
+//! A backend divides its region into blocks of `block_size()` bytes, which
+//! are read and written whole. Writing a block replaces its contents; a
+//! reset during a write may leave the block unreadable, so stores that need
+//! atomic updates must alternate between blocks, as the board configuration
+//! does between its pages.
+
+use kernel::ReturnCode;
+
+pub trait Storage<'a> {
+    /// Set the client for read and write completions.
+    fn set_client(&self, client: &'a dyn Client<'a>);
+
+    /// The size of a block, in bytes. Buffers passed to `read_block` and
+    /// `write_block` must have exactly this length.
+    fn block_size(&self) -> usize;
+
+    /// The number of blocks in the backend.
+    fn block_count(&self) -> usize;
+
+    /// Read `block` into `buffer`. Completion is signaled through
+    /// `Client::read_done`. Backends that protect their blocks complete
+    /// with FAIL if the block was never written, was torn by a reset or was
+    /// modified behind their back.
+    /// Returns the buffer with EINVAL for out of range blocks or a buffer of
+    /// the wrong size, and with EBUSY if another operation is pending.
+    fn read_block(&self, block: usize, buffer: &'a mut [u8])
+                  -> (ReturnCode, Option<&'a mut [u8]>);
+
+    /// Replace the contents of `block` with `buffer`. Completion is
+    /// signaled through `Client::write_done`. Returns the buffer on error, as
+    /// `read_block` does.
+    fn write_block(&self, block: usize, buffer: &'a mut [u8])
+                   -> (ReturnCode, Option<&'a mut [u8]>);
+}
+
+pub trait Client<'a> {
+    /// Called when a `read_block` call completed. `buffer` only holds the
+    /// block if `rcode` is SUCCESS.
+    fn read_done(&self, buffer: &'a mut [u8], rcode: ReturnCode);
+
+    /// Called when a `write_block` call completed.
+    fn write_done(&self, buffer: &'a mut [u8], rcode: ReturnCode);
+}
+
+/// Assigns storage namespaces to backends, so that a store can keep small,
+/// frequently used records in internal flash and large blobs elsewhere.
+pub struct Namespaces<'a> {
+    backends: &'a [(u32, &'a dyn Storage<'a>)],
+}
+
+impl<'a> Namespaces<'a> {
+    /// `backends` pairs namespace identifiers with the backend holding them.
+    pub const fn new(backends: &'a [(u32, &'a dyn Storage<'a>)]) -> Namespaces<'a> {
+        Namespaces { backends: backends }
+    }
+
+    /// The backend holding `namespace`, or None if it has not been assigned.
+    pub fn backend(&self, namespace: u32) -> Option<&'a dyn Storage<'a>> {
+        self.backends.iter()
+            .find(|(id, _)| *id == namespace)
+            .map(|(_, backend)| *backend)
+    }
+}
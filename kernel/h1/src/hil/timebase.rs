@@ -0,0 +1,39 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interface for an approximate wall clock on H1
+//!
+//! H1 does not have a real-time clock. Instead, a trusted party (e.g. the BMC)
+//! periodically provides the current UNIX time, and the timebase extrapolates
+//! from the most recent synchronization point using a local counter.
+
+pub trait Timebase {
+    /// Synchronize the timebase to the given UNIX time in milliseconds.
+    fn set_unix_time_ms(&self, unix_time_ms: u64);
+
+    /// Get the current approximate UNIX time in milliseconds.
+    ///
+    /// Returns `None` if the timebase was never synchronized. The returned
+    /// time does not go backwards when a later synchronization moves the
+    /// reference time back by a small amount (transport jitter, drift
+    /// correction); a larger backwards step is followed.
+    fn get_unix_time_ms(&self) -> Option<u64>;
+
+    /// Get the estimated drift of the local counter relative to the reference
+    /// clock, in parts per million. A positive value means that the local
+    /// counter runs slow.
+    fn get_drift_ppm(&self) -> i32;
+}
@@ -0,0 +1,147 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! HKDF-SHA256 on top of the SHA engine.
+//!
+//! HMAC keys are handled as RFC 2104 requires: keys longer than the
+//! SHA-256 block size are hashed first and shorter ones are zero-padded to
+//! it. The engine's HMAC mode only takes 32-byte keys, so it is used for
+//! keys up to that length (including every PRK), and HMAC is built from
+//! two plain SHA-256 passes for keys of 33 to 64 bytes.
+//!
+//! The SHA engine is taken from the `ShaArbiter`, so a derivation fails
+//! with EBUSY instead of clobbering a digest an app has in progress.
+
+use crate::crypto::sha::{ShaArbiter, ShaEngine};
+use crate::hil::digest::{DigestEngine, DigestMode};
+use crate::hil::hkdf::{Hkdf, MAX_OUTPUT_LEN, PRK_LEN};
+use ecc::wipe;
+use kernel::ReturnCode;
+
+const HMAC_KEY_LEN: usize = 32;
+const SHA256_BLOCK_LEN: usize = 64;
+const HMAC_IPAD: u8 = 0x36;
+const HMAC_OPAD: u8 = 0x5c;
+
+pub struct HkdfImpl<'a> {
+    sha: &'a ShaArbiter<'a>,
+}
+
+impl<'a> HkdfImpl<'a> {
+    pub fn new(sha: &'a ShaArbiter<'a>) -> HkdfImpl<'a> {
+        HkdfImpl { sha: sha }
+    }
+
+    /// Computes HMAC-SHA256 over the concatenation of `data`. `key` must be
+    /// at most one block long.
+    fn hmac(&self, sha: &ShaEngine, key: &[u8], data: &[&[u8]], output: &mut [u8; PRK_LEN])
+            -> ReturnCode {
+        if key.len() <= HMAC_KEY_LEN {
+            let mut padded = [0u8; HMAC_KEY_LEN];
+            padded[..key.len()].copy_from_slice(key);
+            let result = sha.initialize_hmac(&padded);
+            wipe(&mut padded);
+            if result.is_err() {
+                return ReturnCode::FAIL;
+            }
+            for part in data {
+                if sha.update(part).is_err() {
+                    return ReturnCode::FAIL;
+                }
+            }
+            return match sha.finalize_hmac(output) {
+                Ok(_) => ReturnCode::SUCCESS,
+                Err(_) => ReturnCode::FAIL,
+            };
+        }
+
+        // H((K ^ opad) | H((K ^ ipad) | data))
+        let mut pad = [0u8; SHA256_BLOCK_LEN];
+        pad[..key.len()].copy_from_slice(key);
+        pad.iter_mut().for_each(|byte| *byte ^= HMAC_IPAD);
+        let mut inner = [0u8; PRK_LEN];
+        let mut result = sha.initialize(DigestMode::Sha256)
+            .and_then(|_| sha.update(&pad));
+        for part in data {
+            result = result.and_then(|_| sha.update(part));
+        }
+        result = result.and_then(|_| sha.finalize(&mut inner));
+        pad.iter_mut().for_each(|byte| *byte ^= HMAC_IPAD ^ HMAC_OPAD);
+        result = result
+            .and_then(|_| sha.initialize(DigestMode::Sha256))
+            .and_then(|_| sha.update(&pad))
+            .and_then(|_| sha.update(&inner))
+            .and_then(|_| sha.finalize(output));
+        wipe(&mut pad);
+        wipe(&mut inner);
+        match result {
+            Ok(_) => ReturnCode::SUCCESS,
+            Err(_) => ReturnCode::FAIL,
+        }
+    }
+}
+
+impl<'a> Hkdf for HkdfImpl<'a> {
+    fn extract(&self, salt: &[u8], ikm: &[u8], prk: &mut [u8; PRK_LEN]) -> ReturnCode {
+        let sha = match self.sha.engine() {
+            Ok(sha) => sha,
+            Err(rcode) => return rcode,
+        };
+        if salt.len() <= SHA256_BLOCK_LEN {
+            return self.hmac(sha, salt, &[ikm], prk);
+        }
+        let mut key = [0u8; HMAC_KEY_LEN];
+        let result = sha.initialize(DigestMode::Sha256)
+            .and_then(|_| sha.update(salt))
+            .and_then(|_| sha.finalize(&mut key));
+        let rcode = match result {
+            Ok(_) => self.hmac(sha, &key, &[ikm], prk),
+            Err(_) => ReturnCode::FAIL,
+        };
+        wipe(&mut key);
+        rcode
+    }
+
+    fn expand(&self, prk: &[u8; PRK_LEN], info: &[u8], okm: &mut [u8]) -> ReturnCode {
+        if okm.len() > MAX_OUTPUT_LEN {
+            return ReturnCode::ESIZE;
+        }
+        let sha = match self.sha.engine() {
+            Ok(sha) => sha,
+            Err(rcode) => return rcode,
+        };
+        let mut block = [0u8; PRK_LEN];
+        let mut rcode = ReturnCode::SUCCESS;
+        for (index, chunk) in okm.chunks_mut(PRK_LEN).enumerate() {
+            // T(0) is empty; T(n) = HMAC(PRK, T(n-1) | info | n).
+            let previous: &[u8] = if index == 0 { &[] } else { &block };
+            let counter = [index as u8 + 1];
+            let mut next = [0u8; PRK_LEN];
+            rcode = self.hmac(sha, prk, &[previous, info, &counter], &mut next);
+            block = next;
+            wipe(&mut next);
+            if rcode != ReturnCode::SUCCESS {
+                break;
+            }
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        wipe(&mut block);
+        if rcode != ReturnCode::SUCCESS {
+            wipe(okm);
+        }
+        rcode
+    }
+}
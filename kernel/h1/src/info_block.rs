@@ -0,0 +1,118 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeps the SPI device info block up to date.
+//!
+//! The info block (see `spiutils::protocol::info_block`) lets the SPI host
+//! read the device ID, the active firmware versions and a heartbeat straight
+//! from the SPI device RAM, without waking the app. `InfoBlockUpdater`
+//! rewrites it on every alarm so that the heartbeat shows the kernel is alive.
+
+use core::cell::Cell;
+use kernel::hil::time::{Alarm, AlarmClient, Frequency};
+
+use spiutils::compat::firmware::BuildInfo;
+use spiutils::compat::firmware::BUILD_INFO_LEN;
+use spiutils::compat::firmware::BUILD_INFO_OFFSET;
+use spiutils::driver::firmware::SegmentInfo;
+use spiutils::io::Cursor;
+use spiutils::protocol::firmware::SegmentAndLocation;
+use spiutils::protocol::info_block::InfoBlock;
+use spiutils::protocol::info_block::INFO_BLOCK_LEN;
+use spiutils::protocol::wire::FromWire;
+use spiutils::protocol::wire::ToWire;
+
+use crate::hil::flash::h1_hw::H1_FLASH_START;
+use crate::hil::fuse::Fuse;
+use crate::hil::globalsec::GlobalSec;
+use crate::hil::spi_device::SpiDevice;
+
+/// Build information reported for segments that cannot be identified.
+const UNKNOWN_BUILD_INFO: BuildInfo = BuildInfo {
+    epoch: 0,
+    major: 0,
+    minor: 0,
+    timestamp: 0,
+};
+
+pub struct InfoBlockUpdater<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    spi_device: &'a dyn SpiDevice,
+    fuse: &'a dyn Fuse,
+    globalsec: &'a dyn GlobalSec,
+    interval_ms: u32,
+    heartbeat: Cell<u32>,
+}
+
+impl<'a, A: Alarm<'a>> InfoBlockUpdater<'a, A> {
+    /// Creates an updater that refreshes the info block every `interval_ms`.
+    pub fn new(alarm: &'a A,
+               spi_device: &'a dyn SpiDevice,
+               fuse: &'a dyn Fuse,
+               globalsec: &'a dyn GlobalSec,
+               interval_ms: u32) -> InfoBlockUpdater<'a, A> {
+        InfoBlockUpdater {
+            alarm: alarm,
+            spi_device: spi_device,
+            fuse: fuse,
+            globalsec: globalsec,
+            interval_ms: interval_ms,
+            heartbeat: Cell::new(0),
+        }
+    }
+
+    /// Publishes the info block and starts refreshing it. The alarm client
+    /// must be set to this updater.
+    pub fn start(&self) {
+        self.refresh();
+    }
+
+    fn refresh(&self) {
+        let segments = self.globalsec.get_runtime_segment_info();
+        let info_block = InfoBlock {
+            heartbeat: self.heartbeat.get(),
+            device_id: self.fuse.get_dev_id(),
+            ro_version: read_build_info(segments.active_ro),
+            rw_version: read_build_info(segments.active_rw),
+        };
+        self.heartbeat.set(self.heartbeat.get().wrapping_add(1));
+
+        let mut buf = [0u8; INFO_BLOCK_LEN];
+        if info_block.to_wire(Cursor::new(&mut buf)).is_ok() {
+            self.spi_device.set_info_block(&buf);
+        }
+
+        let interval = (A::Frequency::frequency() as u64 * self.interval_ms as u64 / 1000) as u32;
+        self.alarm.set_alarm(self.alarm.now(), interval.into());
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for InfoBlockUpdater<'a, A> {
+    fn alarm(&self) {
+        self.refresh();
+    }
+}
+
+/// Reads the build information from the header of a segment in flash.
+pub(crate) fn read_build_info(segment: SegmentInfo) -> BuildInfo {
+    if segment.identifier == SegmentAndLocation::Unknown {
+        return UNKNOWN_BUILD_INFO;
+    }
+    let address = H1_FLASH_START + segment.address as usize + BUILD_INFO_OFFSET;
+    // The flash is memory mapped and the segment header is always present.
+    let header = unsafe { core::slice::from_raw_parts(address as *const u8, BUILD_INFO_LEN) };
+    BuildInfo::from_wire(header).unwrap_or(UNKNOWN_BUILD_INFO)
+}
@@ -18,15 +18,14 @@ use crate::uart;
 
 pub struct Writer;
 
-pub static mut WRITER: Writer = Writer {};
-
 // We expect the board using this code to initialize the UART
 // with a suitable pin mux and at the desired speed before this
-// method is called.
+// method is called. Synchronous sends only touch the registers, so
+// this can use its own UART0 instance rather than the board's.
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
         unsafe {
-            let uart = &uart::UART0;
+            let uart = uart::uart0();
 
             uart.send_bytes_sync(s.as_bytes());
  
@@ -38,7 +37,7 @@ impl Write for Writer {
 impl kernel::debug::IoWrite for Writer {
     fn write(&mut self, buf: &[u8]) {
         unsafe {
-            uart::UART0.send_bytes_sync(buf);
+            uart::uart0().send_bytes_sync(buf);
         }
     }
 }
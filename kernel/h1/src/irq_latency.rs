@@ -0,0 +1,144 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interrupt service latency probe.
+//!
+//! The SPI device must answer BUSY polls within a fixed window, which only
+//! holds if its interrupt is serviced promptly while other peripherals are
+//! busy. A `LatencyProbe` measures this on target: `pend` timestamps and
+//! sets the interrupt pending in the NVIC, and `serviced`, called by the chip
+//! when the interrupt is dispatched, records the time in between.
+//!
+//! The time from chip select to the interrupt being raised is fixed by the
+//! hardware and is not included.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::cells::VolatileCell;
+use kernel::ReturnCode;
+
+use crate::timeus::Timeus;
+
+const NVIC_ISPR: *const [VolatileCell<u32>; 8] = 0xe000e200 as *const [VolatileCell<u32>; 8];
+
+/// SPI device command/address FIFO not empty.
+const SPI_DEVICE_CMD_ADDR_IRQ: u32 = 131;
+
+/// Latency statistics since the last reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LatencyStats {
+    /// Number of probes serviced.
+    pub samples: u32,
+    /// Longest latency, in nanoseconds.
+    pub max_ns: u32,
+    /// Mean latency, in nanoseconds.
+    pub mean_ns: u32,
+}
+
+pub struct LatencyProbe {
+    nvic_num: u32,
+    clock: OptionalCell<&'static Timeus>,
+    clock_hz: Cell<u32>,
+    // Clock value when the outstanding probe was pended.
+    pended_at: OptionalCell<u32>,
+    samples: Cell<u32>,
+    max_ticks: Cell<u32>,
+    total_ticks: Cell<u64>,
+}
+
+pub(crate) const unsafe fn spi_device_probe() -> LatencyProbe {
+    LatencyProbe::new(SPI_DEVICE_CMD_ADDR_IRQ)
+}
+
+impl LatencyProbe {
+    const fn new(nvic_num: u32) -> LatencyProbe {
+        LatencyProbe {
+            nvic_num: nvic_num,
+            clock: OptionalCell::empty(),
+            clock_hz: Cell::new(0),
+            pended_at: OptionalCell::empty(),
+            samples: Cell::new(0),
+            max_ticks: Cell::new(0),
+            total_ticks: Cell::new(0),
+        }
+    }
+
+    /// Sets the clock to timestamp probes with. `clock` must be a running
+    /// counter incrementing at `clock_hz`.
+    pub fn set_clock(&self, clock: &'static Timeus, clock_hz: u32) {
+        self.clock.set(clock);
+        self.clock_hz.set(clock_hz);
+    }
+
+    /// Sets the probed interrupt pending. Fails with EBUSY while an earlier
+    /// probe is outstanding, and with EOFF if no clock is set.
+    pub fn pend(&self) -> ReturnCode {
+        if self.pended_at.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let now = match self.clock.map(|clock| clock.now()) {
+            Some(now) => now,
+            None => return ReturnCode::EOFF,
+        };
+        self.pended_at.set(now);
+        let ispr = unsafe { &*NVIC_ISPR };
+        ispr[(self.nvic_num / 32) as usize].set(1 << (self.nvic_num % 32));
+        ReturnCode::SUCCESS
+    }
+
+    /// Records the latency of the outstanding probe, if `nvic_num` is the
+    /// probed interrupt. Called by the chip before dispatching `nvic_num`.
+    pub fn serviced(&self, nvic_num: u32) {
+        if nvic_num != self.nvic_num {
+            return;
+        }
+        let pended_at = match self.pended_at.take() {
+            Some(pended_at) => pended_at,
+            None => return,
+        };
+        let ticks = self.clock.map_or(0, |clock| clock.now().wrapping_sub(pended_at));
+        self.samples.set(self.samples.get().saturating_add(1));
+        self.max_ticks.set(self.max_ticks.get().max(ticks));
+        self.total_ticks.set(self.total_ticks.get() + ticks as u64);
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        let samples = self.samples.get();
+        let mean_ticks = match samples {
+            0 => 0,
+            _ => self.total_ticks.get() / samples as u64,
+        };
+        LatencyStats {
+            samples: samples,
+            max_ns: self.ticks_to_ns(self.max_ticks.get() as u64),
+            mean_ns: self.ticks_to_ns(mean_ticks),
+        }
+    }
+
+    /// Clears the statistics. An outstanding probe is still recorded.
+    pub fn reset(&self) {
+        self.samples.set(0);
+        self.max_ticks.set(0);
+        self.total_ticks.set(0);
+    }
+
+    fn ticks_to_ns(&self, ticks: u64) -> u32 {
+        match self.clock_hz.get() {
+            0 => 0,
+            hz => (ticks * 1_000_000_000 / hz as u64).min(0xffff_ffff) as u32,
+        }
+    }
+}
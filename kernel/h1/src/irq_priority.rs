@@ -0,0 +1,110 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interrupt priority assignment for the H1 NVIC.
+//!
+//! Tock services interrupts as bottom halves, so the order in which
+//! pending interrupts are handled matters more than hardware preemption.
+//! A board provides a table of `InterruptGroup`s; `Hotel` programs the
+//! NVIC priority registers from it and services pending interrupts in
+//! priority order, so that e.g. a SPI device chip select is never queued
+//! behind a flash completion.
+
+use kernel::common::cells::VolatileCell;
+
+/// Priority levels, highest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InterruptPriority {
+    /// Latency-critical: host-facing SPI device.
+    Critical = 0,
+    /// USB endpoints.
+    High = 1,
+    /// Timers.
+    Normal = 2,
+    /// Everything else (flash, crypto, UART, ...). Interrupts not in the
+    /// table are serviced at this level.
+    Background = 3,
+}
+
+pub const PRIORITY_LEVELS: [InterruptPriority; 4] = [
+    InterruptPriority::Critical,
+    InterruptPriority::High,
+    InterruptPriority::Normal,
+    InterruptPriority::Background,
+];
+
+/// A contiguous range of NVIC interrupt numbers sharing a priority.
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptGroup {
+    pub first: u32,
+    pub last: u32,
+    pub priority: InterruptPriority,
+}
+
+/// Default assignment: SPI device highest, then USB, then timers.
+pub const DEFAULT_PRIORITIES: &[InterruptGroup] = &[
+    // SPI device command/address FIFO not empty.
+    InterruptGroup { first: 131, last: 131, priority: InterruptPriority::Critical },
+    // USB0.
+    InterruptGroup { first: 193, last: 193, priority: InterruptPriority::High },
+    // TIMELS0 and TIMELS1.
+    InterruptGroup { first: 159, last: 160, priority: InterruptPriority::Normal },
+];
+
+// The H1 NVIC implements the upper bits of each 8-bit priority field;
+// only the top two are used so that all levels are distinct regardless
+// of how many bits are implemented.
+const PRIORITY_SHIFT: u32 = 6;
+
+const NVIC_ISPR: *const [VolatileCell<u32>; 8] = 0xe000e200 as *const [VolatileCell<u32>; 8];
+const NVIC_IPR: *const [VolatileCell<u8>; 240] = 0xe000e400 as *const [VolatileCell<u8>; 240];
+
+/// Programs the NVIC priority registers: interrupts in `table` get their
+/// assigned priority, all others `Background`.
+pub unsafe fn apply(table: &[InterruptGroup]) {
+    let ipr = &*NVIC_IPR;
+    for reg in ipr.iter() {
+        reg.set((InterruptPriority::Background as u8) << PRIORITY_SHIFT);
+    }
+    for group in table {
+        for irq in group.first..=group.last {
+            ipr[irq as usize].set((group.priority as u8) << PRIORITY_SHIFT);
+        }
+    }
+}
+
+/// Returns the highest-priority pending interrupt according to `table`,
+/// falling back to the lowest-numbered pending interrupt. `parked` is an
+/// interrupt that is deliberately left pending and never returned.
+pub unsafe fn next_pending(table: &[InterruptGroup], parked: Option<u32>) -> Option<u32> {
+    let ispr = &*NVIC_ISPR;
+    let is_pending = |irq: u32| {
+        Some(irq) != parked && ispr[(irq / 32) as usize].get() & (1 << (irq % 32)) != 0
+    };
+    for &level in PRIORITY_LEVELS.iter() {
+        if level == InterruptPriority::Background {
+            break;
+        }
+        for group in table.iter().filter(|group| group.priority == level) {
+            for irq in group.first..=group.last {
+                if is_pending(irq) {
+                    return Some(irq);
+                }
+            }
+        }
+    }
+    (0..ispr.len() as u32 * 32).find(|&irq| is_pending(irq))
+}
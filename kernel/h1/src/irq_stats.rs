@@ -0,0 +1,191 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Interrupt load statistics.
+//!
+//! When the system bogs down, the interrupt counts show which peripheral is
+//! to blame. The chip reports every interrupt it dispatches to an
+//! `InterruptCounter`, which for `IrqStats` only increments two counters for
+//! the NVIC line: the total since boot and the count in the current window.
+//!
+//! Every window, `IrqStats` keeps the window's counts for reporting and
+//! starts a new one. A line that reaches the storm threshold within a window
+//! is logged once, when the storm starts.
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::time::{Alarm, AlarmClient, Frequency};
+
+/// Number of NVIC lines counted.
+pub const NUM_IRQS: usize = 256;
+
+/// Counts the interrupts dispatched by the chip.
+pub trait InterruptCounter {
+    fn count(&self, nvic_num: u32);
+}
+
+/// Interrupt counts per NVIC line.
+pub struct IrqCounts {
+    total: [u32; NUM_IRQS],
+    window: [u16; NUM_IRQS],
+    last_window: [u16; NUM_IRQS],
+}
+
+impl IrqCounts {
+    pub const EMPTY: IrqCounts = IrqCounts {
+        total: [0; NUM_IRQS],
+        window: [0; NUM_IRQS],
+        last_window: [0; NUM_IRQS],
+    };
+
+    fn count(&mut self, line: usize) {
+        if line < NUM_IRQS {
+            self.total[line] = self.total[line].wrapping_add(1);
+            self.window[line] = self.window[line].saturating_add(1);
+        }
+    }
+
+    /// Ends the current window, calling `storm` for each line that reached
+    /// `threshold` in it but not in the window before. A threshold of 0
+    /// disables storm detection.
+    fn end_window<F: FnMut(usize, u16)>(&mut self, threshold: u16, mut storm: F) {
+        for line in 0..NUM_IRQS {
+            let count = self.window[line];
+            if threshold != 0 && count >= threshold && self.last_window[line] < threshold {
+                storm(line, count);
+            }
+            self.last_window[line] = count;
+            self.window[line] = 0;
+        }
+    }
+}
+
+pub struct IrqStats<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    counts: TakeCell<'static, IrqCounts>,
+    window_ms: u32,
+    storm_threshold: Cell<u16>,
+}
+
+impl<'a, A: Alarm<'a>> IrqStats<'a, A> {
+    /// Aggregates the counts every `window_ms`, logging lines with at least
+    /// `storm_threshold` interrupts in a window.
+    pub fn new(alarm: &'a A,
+               counts: &'static mut IrqCounts,
+               window_ms: u32,
+               storm_threshold: u16) -> IrqStats<'a, A> {
+        IrqStats {
+            alarm: alarm,
+            counts: TakeCell::new(counts),
+            window_ms: window_ms,
+            storm_threshold: Cell::new(storm_threshold),
+        }
+    }
+
+    /// Starts the first window. The alarm client must be set to these
+    /// statistics.
+    pub fn start(&self) {
+        let interval = (A::Frequency::frequency() as u64 * self.window_ms as u64 / 1000) as u32;
+        self.alarm.set_alarm(self.alarm.now(), interval.into());
+    }
+
+    pub fn window_ms(&self) -> u32 {
+        self.window_ms
+    }
+
+    pub fn storm_threshold(&self) -> u16 {
+        self.storm_threshold.get()
+    }
+
+    /// Sets the number of interrupts per window that is logged as a storm.
+    /// 0 disables storm detection.
+    pub fn set_storm_threshold(&self, threshold: u16) {
+        self.storm_threshold.set(threshold);
+    }
+
+    /// The number of interrupts on `line` since boot.
+    pub fn total(&self, line: usize) -> Option<u32> {
+        self.counts.map_or(None, |counts| counts.total.get(line).copied())
+    }
+
+    /// The number of interrupts on `line` in the last complete window.
+    pub fn last_window(&self, line: usize) -> Option<u16> {
+        self.counts.map_or(None, |counts| counts.last_window.get(line).copied())
+    }
+
+    /// The first line at or after `line` that has had an interrupt.
+    pub fn next_active(&self, line: usize) -> Option<usize> {
+        self.counts.map_or(None, |counts| {
+            (line..NUM_IRQS).find(|line| counts.total[*line] != 0)
+        })
+    }
+}
+
+impl<'a, A: Alarm<'a>> InterruptCounter for IrqStats<'a, A> {
+    fn count(&self, nvic_num: u32) {
+        self.counts.map(|counts| counts.count(nvic_num as usize));
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for IrqStats<'a, A> {
+    fn alarm(&self) {
+        let window_ms = self.window_ms;
+        let threshold = self.storm_threshold.get();
+        self.counts.map(|counts| counts.end_window(threshold, |line, count| {
+            debug!("IrqStats: interrupt storm on NVIC {}: {} in {} ms", line, count, window_ms);
+        }));
+        self.start();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn storms_are_reported_once() {
+        let mut counts = IrqCounts::EMPTY;
+        let mut storms = Vec::new();
+        for _ in 0..5 {
+            counts.count(193);
+        }
+        counts.count(65);
+        counts.count(NUM_IRQS);
+        counts.end_window(5, |line, count| storms.push((line, count)));
+        assert_eq!(storms, [(193, 5)]);
+        assert_eq!(counts.last_window[193], 5);
+        assert_eq!(counts.window[193], 0);
+
+        for _ in 0..7 {
+            counts.count(193);
+        }
+        counts.end_window(5, |line, count| storms.push((line, count)));
+        assert_eq!(storms.len(), 1);
+        assert_eq!(counts.total[193], 12);
+        assert_eq!(counts.total[65], 1);
+
+        counts.end_window(5, |line, count| storms.push((line, count)));
+        for _ in 0..5 {
+            counts.count(193);
+        }
+        counts.end_window(0, |line, count| storms.push((line, count)));
+        assert_eq!(storms.len(), 1);
+    }
+}
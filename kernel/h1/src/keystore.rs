@@ -0,0 +1,663 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Key store holding ECDSA P-256 keys in flash and handing out opaque
+//! handles to them.
+//!
+//! Keys live in two of the reserved pages at the end of flash, the
+//! fourth-to-last (N-4) and the eighth-to-last (N-8). A page starts with a
+//! commit chunk holding a magic value and a sequence number, followed by
+//! `MAX_KEYS` slots. Each slot stores the key's handle, its type, a tag
+//! naming its owner, the private scalar encrypted with an AES-256 keystream
+//! derived from the wrapping key and the handle, and the public key.
+//!
+//! The used part of the current page is mirrored in RAM. An update erases
+//! the other page, writes the slots and then the commit chunk with the next
+//! sequence number. Of two committed pages the one with the higher sequence
+//! number is current, so a reset in the middle of an update leaves the
+//! previous keys in place.
+//!
+//! The wrapping key and the owner tags are derived from the KEYMGR key
+//! ladder, so nothing in flash unwraps the keys on another chip or under
+//! other firmware. Callers name the owner of a key, e.g. with the app's
+//! package name, and a handle only works for the owner that generated it.
+//!
+//! Private keys and random nonces are drawn from the entropy pool, which is
+//! fed by the TRNG. The point arithmetic of key generation and signing runs
+//! on the dcrypto engine through a `P256Engine`, so both complete through
+//! the client. Deterministic (RFC 6979) nonces compute their HMACs on the
+//! SHA engine, taken from the `ShaArbiter`, so they fail with EBUSY while an
+//! app has a digest in progress.
+//!
+//! The key store also acts as an HKDF `KeySource`: its secret is a
+//! keystream block for the reserved empty handle, so it never coincides
+//! with a wrapping keystream.
+
+use core::cell::Cell;
+use ecc::p256::{PrivateKey, PublicKey, Signature, SCALAR_LEN};
+use ecc::wipe;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::symmetric_encryption::AES128_BLOCK_SIZE;
+use kernel::ReturnCode;
+
+use crate::crypto::aes::AesEngine;
+use crate::crypto::p256::{P256Client, P256Engine};
+use crate::crypto::sha::{ShaArbiter, ShaHmac};
+use crate::crypto::util;
+use crate::hil::entropy_pool::EntropyPool;
+use crate::hil::flash;
+use crate::hil::hkdf::{KeySource, SECRET_LEN};
+use crate::hil::keyladder::{KeyLadder, KeyUsage, KEY_LEN};
+use crate::hil::keystore::{Client, KeyHandle, KeyStore, KeyType, NonceMode, DIGEST_LEN};
+
+/// Maximum number of keys held at once.
+pub const MAX_KEYS: usize = 8;
+
+// Both pages are in the reserved pages at the end of flash.
+const PAGE_ADDRESSES: [usize; 2] = [
+    flash::h1_hw::H1_FLASH_SIZE - (4 * flash::h1_hw::H1_FLASH_PAGE_SIZE),
+    flash::h1_hw::H1_FLASH_SIZE - (8 * flash::h1_hw::H1_FLASH_PAGE_SIZE),
+];
+
+// Marks a committed page ("KEY2").
+const MAGIC: u32 = 0x4b455932;
+
+const SCALAR_WORDS: usize = SCALAR_LEN / 4;
+
+// Flash writes are limited to 32 words.
+pub const WRITE_CHUNK_WORDS: usize = 32;
+
+// Page layout, in words. The commit chunk takes a whole write, so that it
+// can be written after the slots.
+const MAGIC_OFFSET: usize = 0;
+const SEQUENCE_OFFSET: usize = 1;
+const SLOTS_OFFSET: usize = WRITE_CHUNK_WORDS;
+
+// Slot layout, in words.
+const SLOT_HANDLE: usize = 0;
+const SLOT_TYPE: usize = 1;
+const SLOT_OWNER: usize = 2;
+const OWNER_WORDS: usize = 4;
+const SLOT_PRIVATE: usize = SLOT_OWNER + OWNER_WORDS;
+const SLOT_PUBLIC_X: usize = SLOT_PRIVATE + SCALAR_WORDS;
+const SLOT_PUBLIC_Y: usize = SLOT_PUBLIC_X + SCALAR_WORDS;
+const SLOT_WORDS: usize = SLOT_PUBLIC_Y + SCALAR_WORDS;
+
+// Key ladder contexts. The leading zero keeps them apart from the contexts
+// of app derivations, which start with the length of a package name.
+const WRAP_CONTEXT: &[u8] = b"\0keystore wrap";
+const OWNER_CONTEXT: &[u8] = b"\0keystore owner";
+
+/// Number of words of the page mirrored in RAM, rounded up to whole writes.
+pub const KEYSTORE_WORDS: usize =
+    (SLOTS_OFFSET + MAX_KEYS * SLOT_WORDS + WRITE_CHUNK_WORDS - 1)
+    / WRITE_CHUNK_WORDS * WRITE_CHUNK_WORDS;
+
+// Handle of an empty slot; erased flash reads as all ones.
+const EMPTY_HANDLE: KeyHandle = 0xffffffff;
+
+// Number of times random values are redrawn before giving up. Redraws are
+// only needed with negligible probability.
+const MAX_RANDOM_ATTEMPTS: usize = 8;
+
+/// Initial contents of the image buffer: no keys.
+pub const EMPTY_IMAGE: [u32; KEYSTORE_WORDS] = [EMPTY_HANDLE; KEYSTORE_WORDS];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    // Computing the public key of a new key on the dcrypto engine.
+    Generating,
+    Signing,
+    Erasing,
+    // Holds the offset of the chunk being written. The commit chunk at
+    // offset 0 is written last.
+    Writing(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    Generate(usize),
+    Delete,
+}
+
+pub struct KeyStoreImpl<'a> {
+    flash: &'a dyn flash::Flash<'a>,
+    aes: &'a AesEngine<'a>,
+    sha: &'a ShaArbiter<'a>,
+    p256: &'a P256Engine<'a>,
+    key_ladder: &'a dyn KeyLadder,
+    entropy: &'a dyn EntropyPool,
+    client: OptionalCell<&'a dyn Client>,
+    image: TakeCell<'a, [u32; KEYSTORE_WORDS]>,
+    write_buffer: TakeCell<'a, [u32]>,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    // Index into PAGE_ADDRESSES of the page the image was loaded from.
+    current_page: Cell<usize>,
+}
+
+fn words_to_bytes(words: &[u32], bytes: &mut [u8; SCALAR_LEN]) {
+    for (chunk, word) in bytes.chunks_mut(4).zip(words.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+fn bytes_to_words(bytes: &[u8; SCALAR_LEN], words: &mut [u32]) {
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+}
+
+impl<'a> KeyStoreImpl<'a> {
+    pub fn new(flash: &'a dyn flash::Flash<'a>,
+               aes: &'a AesEngine<'a>,
+               sha: &'a ShaArbiter<'a>,
+               p256: &'a P256Engine<'a>,
+               key_ladder: &'a dyn KeyLadder,
+               entropy: &'a dyn EntropyPool,
+               image: &'a mut [u32; KEYSTORE_WORDS],
+               write_buffer: &'a mut [u32]) -> KeyStoreImpl<'a> {
+        KeyStoreImpl {
+            flash: flash,
+            aes: aes,
+            sha: sha,
+            p256: p256,
+            key_ladder: key_ladder,
+            entropy: entropy,
+            client: OptionalCell::empty(),
+            image: TakeCell::new(image),
+            write_buffer: TakeCell::new(write_buffer),
+            state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::Delete),
+            current_page: Cell::new(0),
+        }
+    }
+
+    /// Loads the current key page from flash. Must be called before any
+    /// other operation.
+    pub fn init(&self) -> ReturnCode {
+        self.image.map_or(ReturnCode::ENOMEM, |image| self.load(image))
+    }
+
+    fn read_word(&self, page: usize, offset: usize) -> Result<u32, ReturnCode> {
+        match self.flash.read(PAGE_ADDRESSES[page] / 4 + offset) {
+            ReturnCode::SuccessWithValue { value } => Ok(value as u32),
+            result => Err(result),
+        }
+    }
+
+    // Returns the sequence number of `page` if it has been committed.
+    fn committed_sequence(&self, page: usize) -> Result<Option<u32>, ReturnCode> {
+        if self.read_word(page, MAGIC_OFFSET)? != MAGIC {
+            return Ok(None);
+        }
+        Ok(Some(self.read_word(page, SEQUENCE_OFFSET)?))
+    }
+
+    // Reads the current page into `image`, or starts over with no keys if
+    // neither page has been committed.
+    fn load(&self, image: &mut [u32; KEYSTORE_WORDS]) -> ReturnCode {
+        let sequences = match (self.committed_sequence(0), self.committed_sequence(1)) {
+            (Ok(first), Ok(second)) => [first, second],
+            (Err(rcode), _) | (_, Err(rcode)) => return rcode,
+        };
+        let page = match sequences {
+            [Some(first), Some(second)] => if second > first { 1 } else { 0 },
+            [Some(_), None] => 0,
+            [None, Some(_)] => 1,
+            [None, None] => {
+                for word in image.iter_mut() {
+                    *word = EMPTY_HANDLE;
+                }
+                image[SEQUENCE_OFFSET] = 0;
+                // The first commit goes to the first page.
+                self.current_page.set(1);
+                return ReturnCode::SUCCESS;
+            }
+        };
+        for (i, word) in image.iter_mut().enumerate() {
+            match self.read_word(page, i) {
+                Ok(value) => *word = value,
+                Err(rcode) => return rcode,
+            }
+        }
+        self.current_page.set(page);
+        ReturnCode::SUCCESS
+    }
+
+    // Derives the tag stored with the keys of `owner`.
+    fn owner_tag(&self, owner: &[u8]) -> Result<[u8; 4 * OWNER_WORDS], ReturnCode> {
+        if owner.len() > u8::max_value() as usize {
+            return Err(ReturnCode::EINVAL);
+        }
+        let mut key = [0u8; KEY_LEN];
+        let rcode = self.key_ladder.derive(
+            KeyUsage::Storage, &[OWNER_CONTEXT, &[owner.len() as u8], owner], &mut key);
+        let mut tag = [0u8; 4 * OWNER_WORDS];
+        tag.copy_from_slice(&key[..4 * OWNER_WORDS]);
+        wipe(&mut key);
+        match rcode {
+            ReturnCode::SUCCESS => Ok(tag),
+            rcode => Err(rcode),
+        }
+    }
+
+    fn find_handle(image: &[u32; KEYSTORE_WORDS], handle: KeyHandle) -> Option<usize> {
+        if handle == EMPTY_HANDLE {
+            return None;
+        }
+        (0..MAX_KEYS).find(|slot| image[SLOTS_OFFSET + slot * SLOT_WORDS + SLOT_HANDLE] == handle)
+    }
+
+    // Finds the slot of `handle`, if it belongs to the owner with `tag`.
+    fn find_slot(image: &[u32; KEYSTORE_WORDS], handle: KeyHandle,
+                 tag: &[u8; 4 * OWNER_WORDS]) -> Option<usize> {
+        KeyStoreImpl::find_handle(image, handle).filter(|slot| {
+            let base = SLOTS_OFFSET + slot * SLOT_WORDS + SLOT_OWNER;
+            let mut stored = [0u8; 4 * OWNER_WORDS];
+            for (chunk, word) in stored.chunks_mut(4).zip(image[base..base + OWNER_WORDS].iter()) {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+            util::ct_eq(&stored, tag)
+        })
+    }
+
+    fn random_word(&self) -> Result<u32, ReturnCode> {
+        let mut bytes = [0u8; 4];
+        match self.entropy.fill(&mut bytes) {
+            ReturnCode::SUCCESS => Ok(u32::from_le_bytes(bytes)),
+            rcode => Err(rcode),
+        }
+    }
+
+    // Fills `scalar` with a uniformly distributed value in [1, n), by
+    // rejection sampling.
+    fn random_scalar(&self, scalar: &mut [u8; SCALAR_LEN]) -> ReturnCode {
+        for _ in 0..MAX_RANDOM_ATTEMPTS {
+            let rcode = self.entropy.fill(scalar);
+            if rcode != ReturnCode::SUCCESS {
+                wipe(scalar);
+                return rcode;
+            }
+            if PrivateKey::from_bytes(scalar).is_ok() {
+                return ReturnCode::SUCCESS;
+            }
+        }
+        wipe(scalar);
+        ReturnCode::FAIL
+    }
+
+    // Chooses the nonce for signing `digest` with `key`.
+    fn nonce(&self, key: &PrivateKey, digest: &[u8; DIGEST_LEN], nonce_mode: NonceMode)
+             -> Result<[u8; SCALAR_LEN], ReturnCode> {
+        if nonce_mode == NonceMode::Deterministic {
+            let sha = self.sha.engine()?;
+            return key.rfc6979_nonce(digest, &ShaHmac::new(sha)).map_err(|_| ReturnCode::FAIL);
+        }
+        let mut nonce = [0u8; SCALAR_LEN];
+        match self.random_scalar(&mut nonce) {
+            ReturnCode::SUCCESS => Ok(nonce),
+            rcode => Err(rcode),
+        }
+    }
+
+    // Computes the keystream that wraps the private key of `handle`.
+    fn keystream(&self, handle: KeyHandle, keystream: &mut [u8; SCALAR_LEN]) -> ReturnCode {
+        let mut key = [0u8; KEY_LEN];
+        let rcode = self.key_ladder.derive(KeyUsage::Storage, &[WRAP_CONTEXT], &mut key);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        for (counter, chunk) in keystream.chunks_mut(AES128_BLOCK_SIZE).enumerate() {
+            let mut block = [0u8; AES128_BLOCK_SIZE];
+            block[0..4].copy_from_slice(&handle.to_le_bytes());
+            block[4..8].copy_from_slice(&(counter as u32).to_le_bytes());
+            let rcode = self.aes.encrypt_block_blocking(&key, &mut block);
+            if rcode != ReturnCode::SUCCESS {
+                wipe(&mut key);
+                return rcode;
+            }
+            chunk.copy_from_slice(&block);
+            wipe(&mut block);
+        }
+        wipe(&mut key);
+        ReturnCode::SUCCESS
+    }
+
+    // Encrypts or decrypts a private key in place.
+    fn wrap(&self, handle: KeyHandle, key: &mut [u8; SCALAR_LEN]) -> ReturnCode {
+        let mut keystream = [0u8; SCALAR_LEN];
+        let rcode = self.keystream(handle, &mut keystream);
+        if rcode == ReturnCode::SUCCESS {
+            for (byte, mask) in key.iter_mut().zip(keystream.iter()) {
+                *byte ^= *mask;
+            }
+        }
+        wipe(&mut keystream);
+        rcode
+    }
+
+    fn unwrap_key(&self, image: &[u32; KEYSTORE_WORDS], slot: usize)
+                  -> Result<PrivateKey, ReturnCode> {
+        let base = SLOTS_OFFSET + slot * SLOT_WORDS;
+        let mut bytes = [0u8; SCALAR_LEN];
+        words_to_bytes(&image[base + SLOT_PRIVATE..base + SLOT_PRIVATE + SCALAR_WORDS],
+                       &mut bytes);
+        let rcode = self.wrap(image[base + SLOT_HANDLE], &mut bytes);
+        let key = match rcode {
+            ReturnCode::SUCCESS => PrivateKey::from_bytes(&bytes).map_err(|_| ReturnCode::FAIL),
+            _ => Err(rcode),
+        };
+        wipe(&mut bytes);
+        key
+    }
+
+    // Fills a free slot with a new key for the owner with `tag`, leaving out
+    // the public key, and returns the slot index. The private key is
+    // returned in `key`.
+    fn create_key(&self, image: &mut [u32; KEYSTORE_WORDS], tag: &[u8; 4 * OWNER_WORDS],
+                  key: &mut [u8; SCALAR_LEN]) -> Result<usize, ReturnCode> {
+        let slot = (0..MAX_KEYS)
+            .find(|slot| image[SLOTS_OFFSET + slot * SLOT_WORDS + SLOT_HANDLE] == EMPTY_HANDLE)
+            .ok_or(ReturnCode::ENOMEM)?;
+
+        let mut handle = EMPTY_HANDLE;
+        for _ in 0..MAX_RANDOM_ATTEMPTS {
+            let candidate = self.random_word()?;
+            if candidate != EMPTY_HANDLE && KeyStoreImpl::find_handle(image, candidate).is_none() {
+                handle = candidate;
+                break;
+            }
+        }
+        if handle == EMPTY_HANDLE {
+            return Err(ReturnCode::FAIL);
+        }
+
+        let rcode = self.random_scalar(key);
+        if rcode != ReturnCode::SUCCESS {
+            return Err(rcode);
+        }
+        let mut bytes = *key;
+        let rcode = self.wrap(handle, &mut bytes);
+        if rcode != ReturnCode::SUCCESS {
+            wipe(&mut bytes);
+            return Err(rcode);
+        }
+        let base = SLOTS_OFFSET + slot * SLOT_WORDS;
+        bytes_to_words(&bytes, &mut image[base + SLOT_PRIVATE..base + SLOT_PRIVATE + SCALAR_WORDS]);
+        for (word, chunk) in image[base + SLOT_OWNER..base + SLOT_OWNER + OWNER_WORDS].iter_mut()
+            .zip(tag.chunks(4)) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        image[base + SLOT_TYPE] = KeyType::EcdsaP256 as u32;
+        image[base + SLOT_HANDLE] = handle;
+        wipe(&mut bytes);
+        Ok(slot)
+    }
+
+    fn clear_slot(image: &mut [u32; KEYSTORE_WORDS], slot: usize) {
+        let base = SLOTS_OFFSET + slot * SLOT_WORDS;
+        for word in image[base..base + SLOT_WORDS].iter_mut() {
+            *word = EMPTY_HANDLE;
+        }
+    }
+
+    // The page an update writes to.
+    fn target_page(&self) -> usize {
+        1 - self.current_page.get()
+    }
+
+    // Starts writing the RAM image to the other page with the next sequence
+    // number.
+    fn start_update(&self, image: &mut [u32; KEYSTORE_WORDS]) -> ReturnCode {
+        image[MAGIC_OFFSET] = MAGIC;
+        image[SEQUENCE_OFFSET] = image[SEQUENCE_OFFSET].wrapping_add(1);
+        let rcode = self.flash.erase(PAGE_ADDRESSES[self.target_page()]
+                                     / flash::h1_hw::H1_FLASH_PAGE_SIZE);
+        if rcode == ReturnCode::SUCCESS {
+            self.state.set(State::Erasing);
+        }
+        rcode
+    }
+
+    fn write_chunk(&self, offset: usize) {
+        let buffer = match self.write_buffer.take() {
+            Some(buffer) => buffer,
+            None => {
+                self.finish(ReturnCode::ENOMEM);
+                return;
+            }
+        };
+        self.image.map(|image| {
+            buffer.copy_from_slice(&image[offset..offset + WRITE_CHUNK_WORDS]);
+        });
+        let address = PAGE_ADDRESSES[self.target_page()] / 4 + offset;
+        let (rcode, buffer) = self.flash.write(address, buffer);
+        match buffer {
+            None => self.state.set(State::Writing(offset)),
+            Some(buffer) => {
+                self.write_buffer.replace(buffer);
+                self.finish(if rcode == ReturnCode::SUCCESS { ReturnCode::FAIL } else { rcode });
+            }
+        }
+    }
+
+    fn finish(&self, rcode: ReturnCode) {
+        self.state.set(State::Idle);
+        if rcode == ReturnCode::SUCCESS {
+            self.current_page.set(self.target_page());
+        }
+        let handle = self.image.map_or(EMPTY_HANDLE, |image| {
+            let handle = match self.operation.get() {
+                Operation::Generate(slot) => image[SLOTS_OFFSET + slot * SLOT_WORDS + SLOT_HANDLE],
+                Operation::Delete => EMPTY_HANDLE,
+            };
+            if rcode != ReturnCode::SUCCESS {
+                // The current page is untouched; go back to it.
+                let _ = self.load(image);
+            }
+            handle
+        });
+        match self.operation.get() {
+            Operation::Generate(_) => {
+                self.client.map(|client| client.generate_done(rcode, handle));
+            }
+            Operation::Delete => {
+                self.client.map(|client| client.delete_done(rcode));
+            }
+        }
+    }
+}
+
+impl<'a> KeyStore<'a> for KeyStoreImpl<'a> {
+    fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    fn generate(&self, key_type: KeyType, owner: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if key_type != KeyType::EcdsaP256 {
+            return ReturnCode::ENOSUPPORT;
+        }
+        let tag = match self.owner_tag(owner) {
+            Ok(tag) => tag,
+            Err(rcode) => return rcode,
+        };
+        self.image.map_or(ReturnCode::ENOMEM, |image| {
+            let mut key = [0u8; SCALAR_LEN];
+            let rcode = match self.create_key(image, &tag, &mut key) {
+                Ok(slot) => {
+                    let rcode = self.p256.public_key(&key);
+                    if rcode == ReturnCode::SUCCESS {
+                        self.operation.set(Operation::Generate(slot));
+                        self.state.set(State::Generating);
+                    }
+                    rcode
+                }
+                Err(rcode) => rcode,
+            };
+            wipe(&mut key);
+            if rcode != ReturnCode::SUCCESS {
+                let _ = self.load(image);
+            }
+            rcode
+        })
+    }
+
+    fn public_key(&self, handle: KeyHandle, owner: &[u8]) -> Result<PublicKey, ReturnCode> {
+        let tag = self.owner_tag(owner)?;
+        self.image.map_or(Err(ReturnCode::ENOMEM), |image| {
+            let slot = KeyStoreImpl::find_slot(image, handle, &tag).ok_or(ReturnCode::EINVAL)?;
+            let base = SLOTS_OFFSET + slot * SLOT_WORDS;
+            let mut public_key = PublicKey { x: [0; SCALAR_LEN], y: [0; SCALAR_LEN] };
+            words_to_bytes(&image[base + SLOT_PUBLIC_X..base + SLOT_PUBLIC_X + SCALAR_WORDS],
+                           &mut public_key.x);
+            words_to_bytes(&image[base + SLOT_PUBLIC_Y..base + SLOT_PUBLIC_Y + SCALAR_WORDS],
+                           &mut public_key.y);
+            Ok(public_key)
+        })
+    }
+
+    fn sign(&self, handle: KeyHandle, owner: &[u8], digest: &[u8; DIGEST_LEN],
+            nonce_mode: NonceMode) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let tag = match self.owner_tag(owner) {
+            Ok(tag) => tag,
+            Err(rcode) => return rcode,
+        };
+        self.image.map_or(ReturnCode::ENOMEM, |image| {
+            let slot = match KeyStoreImpl::find_slot(image, handle, &tag) {
+                Some(slot) => slot,
+                None => return ReturnCode::EINVAL,
+            };
+            let key = match self.unwrap_key(image, slot) {
+                Ok(key) => key,
+                Err(rcode) => return rcode,
+            };
+            let mut nonce = match self.nonce(&key, digest, nonce_mode) {
+                Ok(nonce) => nonce,
+                Err(rcode) => return rcode,
+            };
+            let mut bytes = key.to_bytes();
+            let rcode = self.p256.sign(&bytes, digest, &nonce);
+            wipe(&mut bytes);
+            wipe(&mut nonce);
+            if rcode == ReturnCode::SUCCESS {
+                self.state.set(State::Signing);
+            }
+            rcode
+        })
+    }
+
+    fn delete(&self, handle: KeyHandle, owner: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let tag = match self.owner_tag(owner) {
+            Ok(tag) => tag,
+            Err(rcode) => return rcode,
+        };
+        self.image.map_or(ReturnCode::ENOMEM, |image| {
+            match KeyStoreImpl::find_slot(image, handle, &tag) {
+                Some(slot) => {
+                    KeyStoreImpl::clear_slot(image, slot);
+                    self.operation.set(Operation::Delete);
+                    let rcode = self.start_update(image);
+                    if rcode != ReturnCode::SUCCESS {
+                        let _ = self.load(image);
+                    }
+                    rcode
+                }
+                None => ReturnCode::EINVAL,
+            }
+        })
+    }
+}
+
+impl<'a> KeySource for KeyStoreImpl<'a> {
+    fn secret(&self, secret: &mut [u8; SECRET_LEN]) -> ReturnCode {
+        self.keystream(EMPTY_HANDLE, secret)
+    }
+}
+
+impl<'a> P256Client for KeyStoreImpl<'a> {
+    fn public_key_done(&self, rcode: ReturnCode, public_key: &PublicKey) {
+        let slot = match (self.state.get(), self.operation.get()) {
+            (State::Generating, Operation::Generate(slot)) => slot,
+            _ => return,
+        };
+        self.state.set(State::Idle);
+        if rcode != ReturnCode::SUCCESS {
+            self.finish(rcode);
+            return;
+        }
+        let rcode = self.image.map_or(ReturnCode::ENOMEM, |image| {
+            let base = SLOTS_OFFSET + slot * SLOT_WORDS;
+            bytes_to_words(&public_key.x,
+                           &mut image[base + SLOT_PUBLIC_X..base + SLOT_PUBLIC_X + SCALAR_WORDS]);
+            bytes_to_words(&public_key.y,
+                           &mut image[base + SLOT_PUBLIC_Y..base + SLOT_PUBLIC_Y + SCALAR_WORDS]);
+            self.start_update(image)
+        });
+        if rcode != ReturnCode::SUCCESS {
+            self.finish(rcode);
+        }
+    }
+
+    fn sign_done(&self, rcode: ReturnCode, signature: &Signature) {
+        if self.state.get() != State::Signing {
+            return;
+        }
+        self.state.set(State::Idle);
+        self.client.map(|client| client.sign_done(rcode, signature));
+    }
+
+    fn verify_done(&self, _rcode: ReturnCode) {}
+}
+
+impl<'a> flash::Client<'a> for KeyStoreImpl<'a> {
+    fn erase_done(&self, rcode: ReturnCode) {
+        if self.state.get() != State::Erasing {
+            return;
+        }
+        if rcode == ReturnCode::SUCCESS {
+            self.write_chunk(SLOTS_OFFSET);
+        } else {
+            self.finish(rcode);
+        }
+    }
+
+    fn write_done(&self, data: &'a mut [u32], rcode: ReturnCode) {
+        self.write_buffer.replace(data);
+        if let State::Writing(offset) = self.state.get() {
+            if rcode != ReturnCode::SUCCESS {
+                self.finish(rcode);
+            } else if offset == MAGIC_OFFSET {
+                self.finish(ReturnCode::SUCCESS);
+            } else if offset + WRITE_CHUNK_WORDS < KEYSTORE_WORDS {
+                self.write_chunk(offset + WRITE_CHUNK_WORDS);
+            } else {
+                // All slots are written; commit the page.
+                self.write_chunk(MAGIC_OFFSET);
+            }
+        }
+    }
+}
@@ -30,29 +30,59 @@ extern crate kernel;
 #[macro_use]
 pub mod io;
 
+pub mod board_config;
+pub mod boot_attempts;
 pub mod chip;
 pub mod crypto;
+pub mod dma_pool;
+pub mod entropy_pool;
+pub mod fault_stats;
+pub mod flash_storage;
 pub mod fuse;
 pub mod globalsec;
 pub mod gpio;
 pub mod hil;
+pub mod hkdf;
+pub mod info_block;
+pub mod irq_latency;
+pub mod irq_priority;
+pub mod irq_stats;
+pub mod keystore;
+pub mod lockdown;
 pub mod nvcounter;
+pub mod passthrough_guard;
+pub mod peripherals;
 pub mod personality;
 pub mod pinmux;
 pub mod pmu;
+pub mod process_loader;
 pub mod spi_host;
+pub mod spi_host_lease;
 pub mod spi_device;
+pub mod spi_device_failsafe;
+pub mod spi_device_filter;
+pub mod spi_device_flash;
+pub mod spi_device_timing;
+pub mod spi_flash_storage;
+pub mod soft_pwm;
+pub mod spsc;
+pub mod stack_usage;
+pub mod timebase;
 pub mod timels;
 pub mod timeus;
 pub mod trng;
 pub mod uart;
+pub mod uptime;
 pub mod usb;
+pub mod virtual_gpio;
+pub mod watchdog;
 
 
 pub mod test_rng;
 pub mod test_dcrypto;
 
-use cortexm3::{generic_isr, hard_fault_handler, svc_handler, systick_handler};
+use cortexm3::{generic_isr, svc_handler, systick_handler};
+use crate::fault_stats::hard_fault_handler;
 
 unsafe extern "C" fn unhandled_interrupt() {
     let mut interrupt_number: u32;
@@ -138,6 +168,8 @@ pub unsafe fn init() {
         pdest = pdest.offset(1);
     }
 
+    stack_usage::paint_kernel_stack();
+
     cortexm3::nvic::disable_all();
     cortexm3::nvic::clear_all_pending();
     cortexm3::nvic::enable_all();
@@ -165,8 +197,8 @@ pub unsafe fn init() {
         nvcounter_test::NvCounterTest::new(nvcounter));
     nvcounter.set_client(nvcounter_test);
 
-    dcrypto_test::run_dcrypto();
-    rng_test::run_rng();
+    dcrypto_test::run_dcrypto(&peripherals.dcrypto);
+    rng_test::run_rng(&peripherals.trng0);
     flash_test.run();
     nvcounter_test.run();
     */
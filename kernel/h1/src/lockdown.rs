@@ -0,0 +1,87 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Deep lockdown.
+//!
+//! Once a production device has booted and verified itself, nothing should
+//! need the SWD port or the write windows on the inactive segments until the
+//! next reset. `Lockdown::engage` closes both: it stops the SWD port's clock
+//! and clears the write enables of the globalsec flash regions of the
+//! inactive RO and RW segments.
+//!
+//! Software could reopen them, so the lockdown is also kept in software. It
+//! cannot be released, and drivers that change the board's configuration
+//! check `is_engaged` and refuse while it is. Only a reset clears it.
+
+use kernel::common::cells::OptionalCell;
+use kernel::ReturnCode;
+
+use crate::hil::flash::audit::WriteAudit;
+use crate::hil::globalsec::GlobalSec;
+use crate::pmu::{Clock, PeripheralClock, PeripheralClock0};
+
+/// Why the lockdown was engaged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockdownReason {
+    /// An app requested it.
+    Commanded = 1,
+    /// The boot passed its health check (see `crate::boot_attempts`).
+    Healthy = 2,
+}
+
+pub struct Lockdown<'a> {
+    globalsec: &'a dyn GlobalSec,
+    audit: OptionalCell<&'a WriteAudit<'a>>,
+    reason: OptionalCell<LockdownReason>,
+}
+
+impl<'a> Lockdown<'a> {
+    pub fn new(globalsec: &'a dyn GlobalSec) -> Lockdown<'a> {
+        Lockdown {
+            globalsec: globalsec,
+            audit: OptionalCell::empty(),
+            reason: OptionalCell::empty(),
+        }
+    }
+
+    /// Records the lockdown in the flash audit journal of `audit`.
+    pub fn set_audit(&self, audit: &'a WriteAudit<'a>) {
+        self.audit.set(audit);
+    }
+
+    /// Locks the board down. Returns EALREADY if it already is.
+    pub fn engage(&self, reason: LockdownReason) -> ReturnCode {
+        if self.is_engaged() {
+            return ReturnCode::EALREADY;
+        }
+        self.reason.set(reason);
+        self.globalsec.close_write_windows();
+        unsafe { Clock::new(PeripheralClock::Bank0(PeripheralClock0::Swdp0)) }.disable();
+        self.audit.map(|audit| audit.record_lockdown());
+        debug!("Lockdown: engaged ({:?})", reason);
+        ReturnCode::SUCCESS
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.reason.is_some()
+    }
+
+    /// Why the lockdown was engaged, if it is.
+    pub fn reason(&self) -> Option<LockdownReason> {
+        self.reason.extract()
+    }
+}
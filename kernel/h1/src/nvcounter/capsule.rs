@@ -258,7 +258,7 @@ impl <'c, F: hil::flash::Flash<'c> + 'c> hil::flash::Client<'c> for FlashCounter
         // If we are being asked to initialize, jump to step Init1. This can
         // only happen from step Rollover3, but that isn't important here.
         if self.task.get() == Some(Task::Initialize) {
-            match self.flash.erase(Page::Low as usize * WORDS_PER_PAGE) {
+            match self.flash.erase(Page::Low as usize) {
                 ReturnCode::SUCCESS => return,
                 error => {
                     self.task.set(None);
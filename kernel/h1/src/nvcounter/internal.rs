@@ -26,11 +26,15 @@ pub enum Task {
     Increment,
 }
 
-// The flash page numbers in use by the counter.
+const FLASH_PAGES: isize =
+    (hil::flash::h1_hw::H1_FLASH_SIZE / hil::flash::h1_hw::H1_FLASH_PAGE_SIZE) as isize;
+
+// The flash page numbers in use by the counter: the last two of the reserved
+// pages at the end of flash, which boards keep out of their RW segments.
 #[derive(PartialEq)]
 pub enum Page {
-    High = 254,
-    Low = 255,
+    High = FLASH_PAGES - 2,
+    Low = FLASH_PAGES - 1,
 }
 
 // Reads the count stored in the given page.
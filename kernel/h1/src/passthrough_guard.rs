@@ -0,0 +1,117 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Disables SPI passthrough from a GPIO edge.
+//!
+//! When the host goes into reset, the SPI device must be isolated from the
+//! flash before the host comes back and starts fetching from it. Waiting for
+//! an app to see the reset monitor edge and disable passthrough over a
+//! syscall adds the app's scheduling latency to that window.
+//!
+//! A `PassthroughGuard` observes the configured pin and disables passthrough
+//! directly from the GPIO interrupt's bottom half. Its client is told
+//! afterwards, so that userspace can decide when to enable passthrough again.
+//! Boards should give the pin's interrupt `Critical` priority (see
+//! h1::irq_priority), and set the guard up as the last user of its
+//! `MuxGpioPin` so that it runs before the pin's other clients.
+//!
+//! The guard does not take the SPI host lease: isolation must not wait for
+//! whoever holds it.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil;
+
+use crate::hil::spi_host::SpiHost;
+
+/// Level of the pin while the host is in reset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Polarity {
+    ActiveLow,
+    ActiveHigh,
+}
+
+pub trait PassthroughGuardClient {
+    /// Called after the guard disabled passthrough. `trips` is the number of
+    /// times it has done so since boot.
+    fn passthrough_disabled(&self, trips: u32);
+}
+
+pub struct PassthroughGuard<'a> {
+    pin: &'a dyn hil::gpio::InterruptPin<'a>,
+    spi_host: &'a dyn SpiHost,
+    polarity: Polarity,
+    armed: Cell<bool>,
+    trips: Cell<u32>,
+    client: OptionalCell<&'a dyn PassthroughGuardClient>,
+}
+
+impl<'a> PassthroughGuard<'a> {
+    /// Creates a guard that disables passthrough on `spi_host` when `pin`
+    /// asserts with the given polarity. The guard starts disarmed.
+    pub fn new(pin: &'a dyn hil::gpio::InterruptPin<'a>,
+               spi_host: &'a dyn SpiHost,
+               polarity: Polarity) -> PassthroughGuard<'a> {
+        PassthroughGuard {
+            pin: pin,
+            spi_host: spi_host,
+            polarity: polarity,
+            armed: Cell::new(false),
+            trips: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn PassthroughGuardClient) {
+        self.client.set(client);
+    }
+
+    /// Starts watching the pin.
+    pub fn arm(&self) {
+        self.armed.set(true);
+        self.pin.enable_interrupts(match self.polarity {
+            Polarity::ActiveLow => hil::gpio::InterruptEdge::FallingEdge,
+            Polarity::ActiveHigh => hil::gpio::InterruptEdge::RisingEdge,
+        });
+    }
+
+    /// Stops watching the pin. Passthrough is left as it is.
+    pub fn disarm(&self) {
+        self.armed.set(false);
+        self.pin.disable_interrupts();
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    /// Number of times the guard disabled passthrough since boot.
+    pub fn trips(&self) -> u32 {
+        self.trips.get()
+    }
+}
+
+impl<'a> hil::gpio::Client for PassthroughGuard<'a> {
+    fn fired(&self) {
+        if !self.armed.get() {
+            return;
+        }
+        self.spi_host.spi_device_spi_host_passthrough(false);
+        let trips = self.trips.get().wrapping_add(1);
+        self.trips.set(trips);
+        self.client.map(|client| client.passthrough_disabled(trips));
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The H1 peripheral drivers.
+//!
+//! Each peripheral has exactly one driver, owned by `Peripherals`. The board
+//! creates it once at the start of `reset_handler` and hands references to
+//! it to the capsules and to the chip, which dispatches interrupts to it.
+//! Nothing else can reach the drivers, so the board code needs no `static
+//! mut` access to use them.
+//!
+//! The buffers the drivers need are likewise allocated by the board with
+//! `static_init!`, or taken from a `DmaPool` if hardware accesses them. The
+//! only `static mut`s left in a board are the ones the kernel and the linker
+//! script need: the chip for the panic handler, the process table and the
+//! app and stack memory.
+
+use crate::crypto::aes::{self, AesEngine};
+use crate::crypto::dcrypto::{self, DcryptoEngine};
+use crate::crypto::sha::{self, ShaEngine};
+use crate::fuse::{self, FuseController};
+use crate::globalsec::{self, GlobalSecHardware};
+use crate::gpio::{self, Port};
+use crate::hil::flash::h1_hw::{self, H1bHw};
+use crate::irq_latency::{self, LatencyProbe};
+use crate::personality::{self, PersonalityDriver};
+use crate::pinmux::{self, Pinmux};
+use crate::pmu::{self, ResetImpl};
+use crate::spi_device::{self, SpiDeviceHardware};
+use crate::spi_host::{self, SpiHostHardware};
+use crate::timels::{self, Timels};
+use crate::trng::{self, Trng};
+use crate::uart::{self, UART};
+use crate::usb::{self, USB};
+use crate::watchdog::{self, Watchdog};
+
+pub struct Peripherals {
+    pub timels0: Timels,
+    pub timels1: Timels,
+    pub gpio0: Port,
+    pub gpio1: Port,
+    pub pinmux: Pinmux,
+    pub sha: ShaEngine,
+    pub aes: AesEngine<'static>,
+    pub dcrypto: DcryptoEngine<'static>,
+    pub globalsec: GlobalSecHardware,
+    /// The flash controller's registers, for `hil::flash::FlashImpl`.
+    pub flash_controller: &'static H1bHw,
+    pub usb0: USB<'static>,
+    pub spi_host0: SpiHostHardware,
+    pub spi_host1: SpiHostHardware,
+    pub personality: PersonalityDriver<'static>,
+    pub fuse: FuseController,
+    pub reset: ResetImpl,
+    pub spi_device0: SpiDeviceHardware,
+    pub uart0: UART<'static>,
+    pub uart1: UART<'static>,
+    pub uart2: UART<'static>,
+    pub trng0: Trng<'static>,
+    pub watchdog0: Watchdog,
+    /// Measures the interrupt latency of `spi_device0`.
+    pub spi_device_probe: LatencyProbe,
+}
+
+impl Peripherals {
+    /// Creates the peripheral drivers.
+    ///
+    /// ## Safety
+    ///
+    /// Must be called only once, from `reset_handler`, and the result must be
+    /// placed in static memory (e.g. with `static_init!`). A second instance
+    /// would drive the same hardware as the first.
+    pub unsafe fn new() -> Peripherals {
+        Peripherals {
+            timels0: timels::timels0(),
+            timels1: timels::timels1(),
+            gpio0: Port::new(gpio::GPIO0_BASE),
+            gpio1: Port::new(gpio::GPIO1_BASE),
+            pinmux: pinmux::pinmux(),
+            sha: sha::keymgr0_sha(),
+            aes: aes::keymgr0_aes(),
+            dcrypto: dcrypto::dcrypto(),
+            globalsec: globalsec::globalsec(),
+            flash_controller: h1_hw::h1_hw(),
+            usb0: usb::usb0(),
+            spi_host0: spi_host::spi_host0(),
+            spi_host1: spi_host::spi_host1(),
+            personality: personality::personality(),
+            fuse: fuse::fuse(),
+            reset: pmu::reset(),
+            spi_device0: spi_device::spi_device0(),
+            uart0: uart::uart0(),
+            uart1: uart::uart1(),
+            uart2: uart::uart2(),
+            trng0: trng::trng0(),
+            watchdog0: watchdog::watchdog0(),
+            spi_device_probe: irq_latency::spi_device_probe(),
+        }
+    }
+}
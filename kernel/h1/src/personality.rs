@@ -13,52 +13,141 @@
 // limitations under the License.
 
 //! Peripheral driver for device attestation (personality) data.  This
-//! is per-device data that will be stored durably on the device; this
-//! implementations currently stores it in RAM.
+//! is per-device data that is stored durably in flash.
+//!
+//! Updates are atomic. The data alternates between the third-to-last (N-3)
+//! page of flash, right below the two pages used as a counter, and the
+//! seventh-to-last (N-7) page, right below the board configuration. A commit
+//! erases the page not holding the current data and writes, in order:
+//!
+//!   1. a header chunk with a magic value and the erase count,
+//!   2. the data,
+//!   3. a flag chunk with the valid flag and the erase count again.
+//!
+//! The erase count is incremented by every commit, so of two valid copies
+//! the one with the higher count is current. A power loss during a commit
+//! leaves a copy with a header but no valid flag, which is ignored and
+//! reported as `Status::Corrupted` until the next commit overwrites it.
+//!
+//! Data written before updates were atomic has no epilogue. It is read from
+//! the N-3 page as-is until the first commit, which goes to the N-7 page
+//! so that the old data survives a failed commit.
 
-use core::cmp;
-use core::mem;
 use core::cell::Cell;
-use crate::hil::personality::{Client, Personality, PersonalityData};
+use crate::hil::personality::{Client, Personality, PersonalityData, Status};
+use crate::hil::personality::{PERSONALITY_SIZE, PERSONALITY_STORED_SIZE};
 use crate::hil::flash;
+use crate::hil::keyladder::{KeyLadder, KeyUsage, KEY_LEN};
+use crate::crypto::key_slots::{AesKeySlots, Owner};
+use crate::crypto::util;
+use kernel::hil::symmetric_encryption::AES128_KEY_SIZE;
 use kernel::ReturnCode;
 use kernel::common::cells::{OptionalCell, TakeCell};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 enum State {
     Idle,
-    ErasingU8,
-    WritingU8,
-    ErasingStruct,
-    WritingStruct,
+    Erasing,
+    WritingHeader,
+    // Holds the offset of the chunk being written, in words.
+    WritingData(usize),
+    WritingFlag,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Operation {
+    Set,
+    SetU8,
+    Commit,
+}
+
+// What a page holds, judging by its epilogue.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Contents {
+    // Erased, or written before updates were atomic.
+    Unknown,
+    // A committed copy with the given erase count.
+    Committed(u32),
+    // A commit with the given erase count that never set the valid flag.
+    Interrupted(u32),
+}
+
+// The result of looking at both pages.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Copies {
+    active: Option<usize>,
+    status: Status,
+    next_slot: usize,
+    next_count: u32,
 }
 
 pub struct PersonalityDriver<'a> {
     state: Cell<State>,
+    operation: Cell<Operation>,
     client: OptionalCell<&'a dyn Client<'a>>,
     flash: OptionalCell<&'a dyn flash::Flash<'a>>,
+    image: TakeCell<'a, [u32]>,
     write_buffer: TakeCell<'a, [u32]>,
+    staged: Cell<bool>,
+    target_slot: Cell<usize>,
+    erase_count: Cell<u32>,
+}
+
+pub(crate) const unsafe fn personality() -> PersonalityDriver<'static> {
+    PersonalityDriver::new()
 }
 
-pub static mut PERSONALITY: PersonalityDriver<'static> = unsafe {PersonalityDriver::new() };
+/// Number of words of staged data, i.e. the stored part of the data.
+pub const IMAGE_WORDS: usize = PERSONALITY_STORED_SIZE / 4;
+
+// Each chunk fits in a single flash write.
+pub const WRITE_CHUNK_WORDS: usize = 8;
+
+// Both slots are in the reserved pages at the end of flash.
+const SLOT_ADDRESSES: [usize; 2] = [
+    flash::h1_hw::H1_FLASH_SIZE - (3 * flash::h1_hw::H1_FLASH_PAGE_SIZE),
+    flash::h1_hw::H1_FLASH_SIZE - (7 * flash::h1_hw::H1_FLASH_PAGE_SIZE),
+];
+/// The flash pages holding the personality data, for `WriteAudit`.
+pub const PERSONALITY_PAGES: [usize; 2] = [
+    SLOT_ADDRESSES[0] / flash::h1_hw::H1_FLASH_PAGE_SIZE,
+    SLOT_ADDRESSES[1] / flash::h1_hw::H1_FLASH_PAGE_SIZE,
+];
+// The page read while no commit has succeeded.
+const LEGACY_SLOT: usize = 0;
+const PAGE_SIZE_U32: usize = flash::h1_hw::H1_FLASH_PAGE_SIZE / 4;
 
-pub static mut BUFFER: [u32; PAGE_SIZE_U32] = [0; PAGE_SIZE_U32];
+// Epilogue layout, in words from the start of the page.
+const HEADER_OFFSET: usize = IMAGE_WORDS;
+const FLAG_OFFSET: usize = HEADER_OFFSET + WRITE_CHUNK_WORDS;
 
+// Marks a page written by a commit ("PERS").
+const MAGIC: u32 = 0x50455253;
+// Marks a completed commit ("VALD").
+const VALID_FLAG: u32 = 0x56414c44;
+const ERASED_WORD: u32 = 0xffffffff;
 
-// Personality data is stored as the third-to-last (N-3) page of flash;
-// it is followed by the two pages used as a counter.
-const PERSONALITY_ADDRESS: usize = flash::h1_hw::H1_FLASH_SIZE - (3 * flash::h1_hw::H1_FLASH_PAGE_SIZE) ;
-const PERSONALITY_ADDRESS_U32: usize = PERSONALITY_ADDRESS / 4;
-const PERSONALITY_SIZE: usize = flash::h1_hw::H1_FLASH_PAGE_SIZE;
-const PAGE_SIZE_U32: usize    = flash::h1_hw::H1_FLASH_PAGE_SIZE / 4;
+// Location of `PersonalityData::salt`, in words, and its length in bytes.
+const SALT_OFFSET: usize = 8;
+const SALT_LEN: usize = 32;
+
+// Whether erase count `a` is newer than `b`, allowing for wraparound.
+fn newer(a: u32, b: u32) -> bool {
+    a.wrapping_sub(b) as i32 > 0
+}
 
 impl<'a> PersonalityDriver<'a> {
     const unsafe fn new() -> PersonalityDriver<'a> {
         PersonalityDriver {
             state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::Commit),
             client: OptionalCell::empty(),
             flash: OptionalCell::empty(),
+            image: TakeCell::empty(),
             write_buffer: TakeCell::empty(),
+            staged: Cell::new(false),
+            target_slot: Cell::new(0),
+            erase_count: Cell::new(0),
         }
     }
 
@@ -66,32 +155,212 @@ impl<'a> PersonalityDriver<'a> {
         self.flash.set(flash);
     }
 
-    pub fn set_buffer(&self, buf: &'a mut [u32]) {
-        self.write_buffer.replace(buf);
+    /// `image` holds `IMAGE_WORDS` words and `write_buffer` holds
+    /// `WRITE_CHUNK_WORDS` words, one flash write.
+    pub fn set_buffers(&self, image: &'a mut [u32], write_buffer: &'a mut [u32]) {
+        self.image.replace(image);
+        self.write_buffer.replace(write_buffer);
     }
 
     pub fn set_client(&self, client: &'a dyn Client<'a>) {
         self.client.replace(client);
     }
 
-    fn start_write(&self, target: usize) -> bool {
-        if self.flash.is_none() || self.write_buffer.is_none() {
-            false
+    /// Derives the device's storage key from the key ladder and the salt
+    /// in the attestation data, and installs it into kernel-owned `slot`,
+    /// so that apps can use the key for AES without being able to read it.
+    pub fn install_key(&self, key_ladder: &dyn KeyLadder, key_slots: &AesKeySlots, slot: usize)
+                       -> ReturnCode {
+        let mut salt = [0u8; SALT_LEN];
+        let rcode = self.read_data(|i, word| {
+            if i >= SALT_OFFSET && i < SALT_OFFSET + SALT_LEN / 4 {
+                let offset = 4 * (i - SALT_OFFSET);
+                salt[offset..offset + 4].copy_from_slice(&word.to_le_bytes());
+            }
+        });
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        let mut key = [0u8; KEY_LEN];
+        let mut rcode = key_ladder.derive(KeyUsage::Storage, &[b"personality", &salt], &mut key);
+        if rcode == ReturnCode::SUCCESS {
+            rcode = key_slots.install(slot, Owner::Kernel, &key[..AES128_KEY_SIZE]);
+        }
+        util::zeroize(&mut key);
+        rcode
+    }
+
+    fn read_word(&self, word: usize) -> Result<u32, ReturnCode> {
+        match self.flash.map_or(ReturnCode::ENOMEM, |flash| flash.read(word)) {
+            ReturnCode::SuccessWithValue { value } => Ok(value as u32),
+            rcode => Err(rcode),
+        }
+    }
+
+    fn read_copy(&self, slot: usize) -> Result<Contents, ReturnCode> {
+        let base = SLOT_ADDRESSES[slot] / 4;
+        if self.read_word(base + HEADER_OFFSET)? != MAGIC {
+            return Ok(Contents::Unknown);
+        }
+        let count = self.read_word(base + HEADER_OFFSET + 1)?;
+        if self.read_word(base + FLAG_OFFSET)? == VALID_FLAG &&
+            self.read_word(base + FLAG_OFFSET + 1)? == count {
+            Ok(Contents::Committed(count))
         } else {
-            let buf = self.write_buffer.take().unwrap();
-            self.flash.map(move |flash| {
-                let (_rcode, opt) = flash.write(target, buf);
-                match opt {
-                    None => true,  // Operation successful
-                    Some(buf) => { // Not successful
-                        self.write_buffer.replace(buf);
-                        false
+            Ok(Contents::Interrupted(count))
+        }
+    }
+
+    fn scan(&self) -> Result<Copies, ReturnCode> {
+        let mut active: Option<(usize, u32)> = None;
+        let mut interrupted: Option<u32> = None;
+        for slot in 0..SLOT_ADDRESSES.len() {
+            match self.read_copy(slot)? {
+                Contents::Committed(count) => {
+                    if active.map_or(true, |(_, latest)| newer(count, latest)) {
+                        active = Some((slot, count));
+                    }
+                },
+                Contents::Interrupted(count) => {
+                    if interrupted.map_or(true, |latest| newer(count, latest)) {
+                        interrupted = Some(count);
                     }
+                },
+                Contents::Unknown => {},
+            }
+        }
+
+        let latest = match (active, interrupted) {
+            (Some((_, committed)), Some(count)) if newer(count, committed) => Some(count),
+            (Some((_, committed)), _) => Some(committed),
+            (None, count) => count,
+        };
+        let status = match (active, interrupted) {
+            (_, Some(count)) if latest == Some(count) => Status::Corrupted,
+            (Some(_), _) => Status::Committed,
+            (None, _) => Status::Legacy,
+        };
+        Ok(Copies {
+            active: active.map(|(slot, _)| slot),
+            status: status,
+            next_slot: match active {
+                Some((slot, _)) => 1 - slot,
+                None => 1 - LEGACY_SLOT,
+            },
+            next_count: latest.map_or(1, |count| count.wrapping_add(1)),
+        })
+    }
+
+    // Reads the current data a word at a time, zero-filling the part that
+    // is not stored.
+    fn read_data<F: FnMut(usize, u32)>(&self, mut store: F) -> ReturnCode {
+        let copies = match self.scan() {
+            Ok(copies) => copies,
+            Err(rcode) => return rcode,
+        };
+        let (slot, stored_words) = match copies.active {
+            Some(slot) => (slot, IMAGE_WORDS),
+            None => (LEGACY_SLOT, PAGE_SIZE_U32),
+        };
+        let base = SLOT_ADDRESSES[slot] / 4;
+        for i in 0..PERSONALITY_SIZE / 4 {
+            if i < stored_words {
+                match self.read_word(base + i) {
+                    Ok(word) => store(i, word),
+                    Err(rcode) => return rcode,
                 }
-            }).unwrap()
+            } else {
+                store(i, 0);
+            }
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn stage<F: Fn(usize) -> u32>(&self, load: F) -> ReturnCode {
+        self.image.map_or(ReturnCode::ENOMEM, |image| {
+            for (i, word) in image.iter_mut().enumerate() {
+                *word = load(i);
+            }
+            self.staged.set(true);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn start_commit(&self, operation: Operation) -> ReturnCode {
+        let copies = match self.scan() {
+            Ok(copies) => copies,
+            Err(rcode) => return rcode,
+        };
+        let page = SLOT_ADDRESSES[copies.next_slot] / flash::h1_hw::H1_FLASH_PAGE_SIZE;
+        let rcode = self.flash.map_or(ReturnCode::ENOMEM, |flash| flash.erase(page));
+        if rcode == ReturnCode::SUCCESS {
+            self.target_slot.set(copies.next_slot);
+            self.erase_count.set(copies.next_count);
+            self.operation.set(operation);
+            self.state.set(State::Erasing);
         }
+        rcode
     }
 
+    fn write_chunk(&self, offset: usize, state: State) {
+        let buffer = match self.write_buffer.take() {
+            Some(buffer) => buffer,
+            None => {
+                self.finish(ReturnCode::ENOMEM);
+                return;
+            }
+        };
+        let marker = match state {
+            State::WritingHeader => Some(MAGIC),
+            State::WritingFlag => Some(VALID_FLAG),
+            _ => None,
+        };
+        match marker {
+            Some(marker) => {
+                // Pad with the erased value so that the rest of the chunk is
+                // untouched.
+                for word in buffer.iter_mut() {
+                    *word = ERASED_WORD;
+                }
+                buffer[0] = marker;
+                buffer[1] = self.erase_count.get();
+            },
+            None => {
+                self.image.map(|image| {
+                    buffer.copy_from_slice(&image[offset..offset + WRITE_CHUNK_WORDS]);
+                });
+            },
+        }
+        let target = SLOT_ADDRESSES[self.target_slot.get()] / 4 + offset;
+        let flash = match self.flash.extract() {
+            Some(flash) => flash,
+            None => {
+                self.write_buffer.replace(buffer);
+                self.finish(ReturnCode::ENOMEM);
+                return;
+            }
+        };
+        let (rcode, buffer) = flash.write(target, buffer);
+        match buffer {
+            None => self.state.set(state),
+            Some(buffer) => {
+                self.write_buffer.replace(buffer);
+                self.finish(if rcode == ReturnCode::SUCCESS { ReturnCode::FAIL } else { rcode });
+            }
+        }
+    }
+
+    fn finish(&self, rcode: ReturnCode) {
+        self.state.set(State::Idle);
+        if rcode == ReturnCode::SUCCESS {
+            self.staged.set(false);
+        }
+        self.client.map(|client| match self.operation.get() {
+            Operation::Set => client.set_done(rcode),
+            Operation::SetU8 => client.set_u8_done(rcode),
+            Operation::Commit => client.commit_done(rcode),
+        });
+    }
 }
 
 impl<'a> Personality<'a> for PersonalityDriver<'a> {
@@ -100,175 +369,110 @@ impl<'a> Personality<'a> for PersonalityDriver<'a> {
     }
 
     fn get(&self, data: &mut PersonalityData) -> ReturnCode {
-        unsafe {
-            self.flash.map_or(ReturnCode::ENOMEM, |flash| {
-                let mut personality_ptr = mem::transmute::<*mut PersonalityData, *mut u32>(data);
-                let word_count = PAGE_SIZE_U32;
-                for i in 0..word_count {
-                    let result = flash.read(PERSONALITY_ADDRESS_U32 + i);
-                    match result {
-                        ReturnCode::SuccessWithValue{value: v} => {
-                            *personality_ptr = v as u32;
-                            personality_ptr = personality_ptr.offset(1);
-                        }
-                        _ => {
-                            return result;
-                        }
-                    }
-                }
-                ReturnCode::SUCCESS
-            })
-        }
+        // PersonalityData is exactly PERSONALITY_SIZE bytes and word aligned.
+        let words = unsafe {
+            core::slice::from_raw_parts_mut(data as *mut PersonalityData as *mut u32,
+                                            PERSONALITY_SIZE / 4)
+        };
+        self.read_data(|i, word| words[i] = word)
     }
 
     fn get_u8(&self, data: &mut [u8]) -> ReturnCode {
         if data.len() < PERSONALITY_SIZE {
-            ReturnCode::ESIZE
-        } else {
-            unsafe {
-                self.flash.map_or(ReturnCode::ENOMEM, |flash| {
-                    let ptr = data.as_mut_ptr();
-                    let mut personality_ptr = mem::transmute::<*mut u8, *mut u32>(ptr);
-                    let word_count = PAGE_SIZE_U32;
-                    for i in 0..word_count {
-                        let result = flash.read(PERSONALITY_ADDRESS_U32 + i);
-                        match result {
-                            ReturnCode::SuccessWithValue{value: v} => {
-                                *personality_ptr = v as u32;
-                                personality_ptr = personality_ptr.offset(1);
-                            }
-                            _ => {
-                                return result;
-                            }
-                        }
-                    }
-                    ReturnCode::SUCCESS
-                })
-            }
+            return ReturnCode::ESIZE;
         }
+        self.read_data(|i, word| data[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes()))
     }
 
     fn set(&self, data: &mut PersonalityData) -> ReturnCode {
         if self.state.get() != State::Idle {
             return ReturnCode::EBUSY;
         }
-        if self.flash.is_some() {
-            self.flash.map(move |flash| {
-                let offset = PERSONALITY_ADDRESS;
-                let page = offset / flash::h1_hw::H1_FLASH_PAGE_SIZE;
-                let rval = flash.erase(page);
-                match rval {
-                    ReturnCode::SUCCESS => {
-                        self.write_buffer.map(|buffer| {
-                            self.state.set(State::ErasingStruct);
-                            unsafe {
-                                let mut ptr = mem::transmute::<*mut PersonalityData, *mut u32>(data);
-                                let word_count = PAGE_SIZE_U32;
-                                for i in 0..word_count {
-                                    buffer[i] = *ptr;
-                                    ptr = ptr.offset(1);
-                                }
-                            }
-                        });
-                        ReturnCode::SUCCESS
-                    },
-                    _ => {
-                        rval
-                    }
-                }
-            }).unwrap()
-        } else {
-            ReturnCode::ENOMEM
+        let words = unsafe {
+            core::slice::from_raw_parts(data as *const PersonalityData as *const u32,
+                                        PERSONALITY_SIZE / 4)
+        };
+        let rcode = self.stage(|i| words[i]);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
         }
+        self.start_commit(Operation::Set)
     }
 
     fn set_u8(&self, data: &mut [u8]) -> ReturnCode {
+        let rcode = self.begin_u8(data);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        self.start_commit(Operation::SetU8)
+    }
+
+    fn begin_u8(&self, data: &[u8]) -> ReturnCode {
         if data.len() < PERSONALITY_SIZE {
-            debug!("personality::set_u8: ESIZE");
-            ReturnCode::ESIZE
+            return ReturnCode::ESIZE;
         }
-        else if self.state.get() != State::Idle {
-            debug!("personality::set_u8 EBUSY");
-            ReturnCode::EBUSY
-        } else {
-            if self.flash.is_some() {
-                self.flash.map(move |flash| {
-                    let offset = PERSONALITY_ADDRESS;
-                    let page = offset / flash::h1_hw::H1_FLASH_PAGE_SIZE;
-                    let rval = flash.erase(page);
-
-                    match rval {
-                        ReturnCode::SUCCESS => {
-                            self.write_buffer.map(|buffer| {
-                                self.state.set(State::ErasingU8);
-                                let len = cmp::min(data.len(), flash::h1_hw::H1_FLASH_PAGE_SIZE);
-                                unsafe {
-                                    let mut ptr = mem::transmute::<*mut u32, *mut u8>(buffer.as_mut_ptr());
-                                    for i in 0..len {
-                                        *ptr = data[i];
-                                        ptr = ptr.offset(1);
-                                    }
-                                }
-                            });
-                            ReturnCode::SUCCESS
-                        },
-                        _ => {
-                            rval
-                        }
-                    }
-                }).unwrap()
-            } else {
-                ReturnCode::ENOMEM
-            }
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
         }
+        self.stage(|i| u32::from_le_bytes([data[4 * i], data[4 * i + 1],
+                                           data[4 * i + 2], data[4 * i + 3]]))
+    }
+
+    fn commit(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if !self.staged.get() {
+            return ReturnCode::EINVAL;
+        }
+        self.start_commit(Operation::Commit)
+    }
+
+    fn abort(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.staged.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn status(&self) -> Result<Status, ReturnCode> {
+        self.scan().map(|copies| copies.status)
     }
 }
 
 impl<'a> flash::Client<'a> for PersonalityDriver<'a> {
-    fn erase_done(&self, _rcode: ReturnCode) {
-        let state = self.state.get();
-        let target = PERSONALITY_ADDRESS_U32; // Write offset is in words
-        match state {
-            State::ErasingStruct => {
-                if self.start_write(target) {
-                    self.state.set(State::WritingStruct);
-                } else {
-                    self.client.map(|c| c.set_done(ReturnCode::FAIL));
-                    self.state.set(State::Idle);
-                }
-            }
-
-            State::ErasingU8 => {
-                if self.start_write(target) {
-                    self.state.set(State::WritingU8);
-                } else {
-                    debug!("personality::write_u8 failed");
-                    self.client.map(|c| c.set_u8_done(ReturnCode::FAIL));
-                    self.state.set(State::Idle);
-                }
-            },
-            _ => { // Should never happen -pal
-                debug!("Erase done called but in state {:?}", state);
-            }
+    fn erase_done(&self, rcode: ReturnCode) {
+        if self.state.get() != State::Erasing {
+            return;
+        }
+        if rcode == ReturnCode::SUCCESS {
+            self.write_chunk(HEADER_OFFSET, State::WritingHeader);
+        } else {
+            self.finish(rcode);
         }
     }
 
-    fn write_done(&self, _data: &'a mut [u32], rcode: ReturnCode) {
-        let state = self.state.get();
-        match state {
-            State::WritingStruct => {
-                self.state.set(State::Idle);
-                self.client.map(|c| c.set_done(rcode));
-            },
-            State::WritingU8 => {
-                self.state.set(State::Idle);
-                self.client.map(|c| {
-                    c.set_u8_done(rcode);
-                });
-            },
-            _ => { // Should never happen -pal
-                debug!(" -- ERROR: personality::write_done in state {:?}", state);
+    fn write_done(&self, data: &'a mut [u32], rcode: ReturnCode) {
+        self.write_buffer.replace(data);
+        if rcode != ReturnCode::SUCCESS {
+            if self.state.get() != State::Idle {
+                self.finish(rcode);
+            }
+            return;
+        }
+        match self.state.get() {
+            State::WritingHeader => self.write_chunk(0, State::WritingData(0)),
+            State::WritingData(offset) => {
+                let next = offset + WRITE_CHUNK_WORDS;
+                if next < IMAGE_WORDS {
+                    self.write_chunk(next, State::WritingData(next));
+                } else {
+                    self.write_chunk(FLAG_OFFSET, State::WritingFlag);
+                }
             },
+            State::WritingFlag => self.finish(ReturnCode::SUCCESS),
+            State::Idle | State::Erasing => {},
         }
     }
 }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::ops::Deref;
 use kernel::common::cells::VolatileCell;
 
 pub struct Pin {
@@ -163,7 +164,31 @@ pub struct Registers {
     pub xo0_testbus7: Peripheral,
 }
 
-pub const PINMUX: *mut Registers = 0x40060000 as *mut Registers;
+const PINMUX_BASE: usize = 0x40060000;
+
+// The registers are all cells, so shared references to them are enough to
+// configure the pins.
+pub(crate) fn registers() -> &'static Registers {
+    unsafe { &*(PINMUX_BASE as *const Registers) }
+}
+
+/// The pin multiplexer, owned by `Peripherals`. Dereferences to its
+/// registers.
+pub struct Pinmux {
+    _private: (),
+}
+
+impl Deref for Pinmux {
+    type Target = Registers;
+
+    fn deref(&self) -> &Registers {
+        registers()
+    }
+}
+
+pub(crate) const unsafe fn pinmux() -> Pinmux {
+    Pinmux { _private: () }
+}
 
 #[repr(u32)]
 pub enum SelectablePin {
@@ -41,8 +41,11 @@
 //!     * Designed for 1.8-3.6V
 //!
 
+use crate::hil::power::{PowerClient, SleepReadiness};
 use crate::hil::reset;
+use crate::timeus::Timeus;
 
+use core::cell::Cell;
 use core::mem::transmute;
 use kernel::common::cells::VolatileCell;
 use spiutils::driver::reset::ResetSource;
@@ -169,9 +172,11 @@ pub struct PMURegisters {
 /// PMU base address
 const PMU_BASE: isize = 0x40000000;
 
-pub static mut PMU: *mut PMURegisters = PMU_BASE as *mut PMURegisters;
+const PMU: *mut PMURegisters = PMU_BASE as *mut PMURegisters;
 
-pub static mut RESET: ResetImpl = ResetImpl::new();
+pub(crate) const unsafe fn reset() -> ResetImpl {
+    ResetImpl::new()
+}
 
 #[derive(Clone,Copy)]
 pub enum PeripheralClock0 {
@@ -331,3 +336,97 @@ impl reset::Reset for ResetImpl {
         }
     }
 }
+
+/// The deep sleep path.
+///
+/// Deep sleep is requested with `request` and entered the next time the
+/// kernel goes idle (see `chip::Hotel::sleep`), once every `PowerClient` in
+/// the table is ready. A client that is busy vetoes that idle period, and
+/// the request is retried at the following one. If the clients are not all
+/// ready before the timeout, the attempt is abandoned and counted in
+/// `aborted`.
+pub struct DeepSleep<'a> {
+    clients: &'a [&'a dyn PowerClient],
+    clock: &'a Timeus,
+    clock_hz: u32,
+    /// Clock value at which the pending request was made, and its timeout
+    /// in clock ticks.
+    request: Cell<Option<(u32, u32)>>,
+    entered: Cell<u32>,
+    aborted: Cell<u32>,
+}
+
+impl<'a> DeepSleep<'a> {
+    /// `clock` must be a running counter incrementing at `clock_hz`.
+    pub fn new(clients: &'a [&'a dyn PowerClient], clock: &'a Timeus, clock_hz: u32)
+               -> DeepSleep<'a> {
+        DeepSleep {
+            clients: clients,
+            clock: clock,
+            clock_hz: clock_hz,
+            request: Cell::new(None),
+            entered: Cell::new(0),
+            aborted: Cell::new(0),
+        }
+    }
+
+    /// Requests deep sleep at the next idle period where every client is
+    /// ready, giving up after `timeout_ms`. Replaces a pending request.
+    pub fn request(&self, timeout_ms: u32) {
+        let timeout = (self.clock_hz as u64 * timeout_ms as u64 / 1000) as u32;
+        self.request.set(Some((self.clock.now(), timeout)));
+    }
+
+    /// Withdraws a pending request.
+    pub fn cancel(&self) {
+        self.request.set(None);
+    }
+
+    /// Whether a request is pending.
+    pub fn is_requested(&self) -> bool {
+        self.request.get().is_some()
+    }
+
+    /// The number of times deep sleep was entered since boot.
+    pub fn entered(&self) -> u32 {
+        self.entered.get()
+    }
+
+    /// The number of requests abandoned because a client stayed busy.
+    pub fn aborted(&self) -> u32 {
+        self.aborted.get()
+    }
+
+    /// Called by the chip with interrupts disabled when the kernel goes
+    /// idle. Returns true if deep sleep may be entered, in which case
+    /// `resume` must be called after waking up.
+    pub fn prepare(&self) -> bool {
+        let (requested_at, timeout) = match self.request.get() {
+            Some(request) => request,
+            None => return false,
+        };
+        if self.clock.now().wrapping_sub(requested_at) >= timeout {
+            self.request.set(None);
+            self.aborted.set(self.aborted.get().saturating_add(1));
+            return false;
+        }
+        for (index, client) in self.clients.iter().enumerate() {
+            if client.prepare_sleep() == SleepReadiness::Busy {
+                for client in self.clients[..index].iter().rev() {
+                    client.resume();
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Wakes the clients after deep sleep. The request is complete.
+    pub fn resume(&self) {
+        for client in self.clients.iter().rev() {
+            client.resume();
+        }
+        self.request.set(None);
+        self.entered.set(self.entered.get().saturating_add(1));
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Loads processes with a fault response chosen per app.
+//!
+//! `kernel::procs::load_processes` applies one fault response to every app.
+//! `load_processes` here walks the app images itself and loads them one at a
+//! time, looking up each app's package name (from its TBF header) in a
+//! board-side table of `AppFaultResponse`s. Apps not in the table get the
+//! board's default response.
+//!
+//! Each TBF header is checked before its app is loaded: the header version,
+//! sizes, checksum and TLV layout, and the kernel version the app was built
+//! for. An app that fails a check is skipped with a console message saying
+//! why, and the apps after it are still loaded.
+//!
+//! The process keeps the package name (`ProcessType::get_process_name`), so
+//! the process console and drivers that key on the app name see the name
+//! checked here.
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::procs::{FaultResponse, ProcessLoadError, ProcessType};
+use kernel::{Chip, Kernel};
+
+/// The fault response for the app with package name `name`.
+pub struct AppFaultResponse {
+    pub name: &'static str,
+    pub response: FaultResponse,
+}
+
+// TBF base header: version u16, header size u16, total size u32, flags u32,
+// checksum u32, followed by TLVs.
+const TBF_VERSION: u16 = 2;
+const TBF_BASE_HEADER_LEN: usize = 16;
+const TBF_CHECKSUM_WORD: usize = 3;
+const TBF_TLV_PACKAGE_NAME: u16 = 3;
+const TBF_TLV_KERNEL_VERSION: u16 = 8;
+
+/// The kernel version apps are checked against. An app that asks for a
+/// kernel version (TLV type 8) must want this major version and at most this
+/// minor version.
+pub const KERNEL_VERSION: (u16, u16) = (1, 6);
+
+/// Why an app image was not loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TbfError {
+    /// The header has a version this loader does not understand.
+    UnsupportedVersion(u16),
+    /// The header size is too small, unaligned, or larger than the app.
+    BadHeaderSize(usize),
+    /// The checksum stored in the header does not match its contents.
+    BadChecksum { stored: u32, computed: u32 },
+    /// The TLV at this offset runs past the end of the header.
+    MalformedTlv(usize),
+    /// The app was built for a different kernel version.
+    IncompatibleKernel { major: u16, minor: u16 },
+}
+
+/// The parts of a TBF header the loader uses.
+#[derive(Debug, PartialEq)]
+pub struct TbfHeader<'a> {
+    /// Size of the whole app image, header included.
+    pub total_size: usize,
+    /// Package name, if the header has one.
+    pub name: Option<&'a [u8]>,
+    /// Kernel version the app asks for, if any.
+    pub kernel_version: Option<(u16, u16)>,
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Returns the total size of the app image starting at `app_flash`, or None
+/// if the app region ends there. Erased flash (0xff) and zeroed flash both
+/// end the region, as does a size that does not fit in it.
+fn app_size(app_flash: &[u8]) -> Option<usize> {
+    let version = read_u16(app_flash, 0)?;
+    if version == 0 || version == 0xffff {
+        return None;
+    }
+    let total_size = read_u32(app_flash, 4)? as usize;
+    if total_size < TBF_BASE_HEADER_LEN || total_size > app_flash.len() {
+        return None;
+    }
+    Some(total_size)
+}
+
+/// XOR of the header words, leaving out the checksum word itself.
+fn header_checksum(header: &[u8]) -> u32 {
+    header.chunks(4)
+        .enumerate()
+        .filter(|&(index, _)| index != TBF_CHECKSUM_WORD)
+        .fold(0, |checksum, (_, word)| {
+            let mut bytes = [0u8; 4];
+            bytes[..word.len()].copy_from_slice(word);
+            checksum ^ u32::from_le_bytes(bytes)
+        })
+}
+
+/// Parses and checks the TBF header of the app image `image`, which is
+/// `app_size` bytes long.
+pub fn parse_header<'a>(image: &'a [u8]) -> Result<TbfHeader<'a>, TbfError> {
+    let version = read_u16(image, 0).unwrap_or(0);
+    if version != TBF_VERSION {
+        return Err(TbfError::UnsupportedVersion(version));
+    }
+    let header_size = read_u16(image, 2).unwrap_or(0) as usize;
+    if header_size < TBF_BASE_HEADER_LEN || header_size % 4 != 0 || header_size > image.len() {
+        return Err(TbfError::BadHeaderSize(header_size));
+    }
+    let header = &image[..header_size];
+
+    let stored = read_u32(header, TBF_CHECKSUM_WORD * 4).unwrap_or(0);
+    let computed = header_checksum(header);
+    if stored != computed {
+        return Err(TbfError::BadChecksum { stored, computed });
+    }
+
+    let mut parsed = TbfHeader {
+        total_size: image.len(),
+        name: None,
+        kernel_version: None,
+    };
+    let mut offset = TBF_BASE_HEADER_LEN;
+    while offset + 4 <= header.len() {
+        let tlv_type = read_u16(header, offset).unwrap_or(0);
+        let tlv_len = read_u16(header, offset + 2).unwrap_or(0) as usize;
+        let value = header.get(offset + 4..offset + 4 + tlv_len)
+            .ok_or(TbfError::MalformedTlv(offset))?;
+        match tlv_type {
+            TBF_TLV_PACKAGE_NAME => parsed.name = Some(value),
+            TBF_TLV_KERNEL_VERSION => {
+                let major = read_u16(value, 0).ok_or(TbfError::MalformedTlv(offset))?;
+                let minor = read_u16(value, 2).ok_or(TbfError::MalformedTlv(offset))?;
+                parsed.kernel_version = Some((major, minor));
+            }
+            _ => (),
+        }
+        // TLV values are padded to a multiple of 4 bytes.
+        offset += 4 + ((tlv_len + 3) & !3);
+    }
+
+    if let Some((major, minor)) = parsed.kernel_version {
+        if major != KERNEL_VERSION.0 || minor > KERNEL_VERSION.1 {
+            return Err(TbfError::IncompatibleKernel { major, minor });
+        }
+    }
+    Ok(parsed)
+}
+
+fn fault_response(name: Option<&[u8]>,
+                  responses: &[AppFaultResponse],
+                  default_response: FaultResponse) -> FaultResponse {
+    name.and_then(|name| responses.iter().find(|entry| entry.name.as_bytes() == name))
+        .map_or(default_response, |entry| entry.response)
+}
+
+/// Like `kernel::procs::load_processes`, but the fault response of each app
+/// is looked up in `responses` by package name, falling back to
+/// `default_response`. Apps whose TBF header fails `parse_header` are
+/// skipped.
+pub unsafe fn load_processes<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'static mut [u8],
+    procs: &'static mut [Option<&'static dyn ProcessType>],
+    responses: &[AppFaultResponse],
+    default_response: FaultResponse,
+    capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    let mut app_flash = app_flash;
+    let mut app_memory = app_memory;
+    let mut procs = procs.iter_mut();
+    while let Some(size) = app_size(app_flash) {
+        let image = &app_flash[..size];
+        app_flash = &app_flash[size..];
+
+        let header = match parse_header(image) {
+            Ok(header) => header,
+            Err(err) => {
+                debug!("Tock: skipping app at {:p}: {:?}", image.as_ptr(), err);
+                continue;
+            }
+        };
+        let name = header.name
+            .and_then(|name| core::str::from_utf8(name).ok())
+            .unwrap_or("<unnamed>");
+        let proc = match procs.next() {
+            Some(proc) => proc,
+            None => {
+                debug!("Tock: no process slot left for app '{}'", name);
+                break;
+            }
+        };
+        let response = fault_response(header.name, responses, default_response);
+        kernel::procs::load_processes(kernel, chip, image, app_memory,
+                                      core::slice::from_mut(proc), response, capability)?;
+
+        // Continue after the memory the process was given.
+        let used = match proc {
+            Some(process) => {
+                debug!("Tock: loaded app '{}' ({} bytes)", process.get_process_name(), size);
+                process.mem_end() as usize - app_memory.as_ptr() as usize
+            }
+            None => 0,
+        };
+        app_memory = &mut core::mem::take(&mut app_memory)[used..];
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A TBF header with a main TLV (type 1) followed by a package name TLV.
+    const HEADER: [u8; 44] = [
+        0x02, 0x00, 0x2c, 0x00, 0x00, 0x01, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x02, 0x1a, 0x23, 0x69,
+        0x01, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x03, 0x00, 0x07, 0x00, b'o', b't', b'p', b'i',
+        b'l', b'o', b't', 0x00,
+    ];
+
+    #[test]
+    fn parses_size_and_name() {
+        let mut image = [0u8; 0x100];
+        image[..HEADER.len()].copy_from_slice(&HEADER);
+        assert_eq!(app_size(&image), Some(0x100));
+        assert_eq!(app_size(&image[..0x80]), None);
+        assert_eq!(app_size(&[0xffu8; 0x100]), None);
+        assert_eq!(parse_header(&image), Ok(TbfHeader {
+            total_size: 0x100,
+            name: Some(&b"otpilot"[..]),
+            kernel_version: None,
+        }));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut image = [0u8; 0x100];
+        image[..HEADER.len()].copy_from_slice(&HEADER);
+
+        let mut corrupted = image;
+        corrupted[36] = b'O';
+        match parse_header(&corrupted) {
+            Err(TbfError::BadChecksum { stored: 0x69231a02, .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut old_version = image;
+        old_version[0] = 1;
+        assert_eq!(parse_header(&old_version), Err(TbfError::UnsupportedVersion(1)));
+
+        let mut truncated_tlv = image;
+        truncated_tlv[34] = 0x20;
+        // Keep the checksum valid so that the TLV check is reached.
+        let checksum = header_checksum(&truncated_tlv[..44]);
+        truncated_tlv[12..16].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(parse_header(&truncated_tlv), Err(TbfError::MalformedTlv(32)));
+    }
+
+    #[test]
+    fn checks_kernel_version() {
+        let mut image = [0u8; 0x100];
+        image[..HEADER.len()].copy_from_slice(&HEADER);
+        // Header grows by a kernel version TLV.
+        image[2] = 52;
+        let with_version = |major: u16, minor: u16| {
+            let mut image = image;
+            image[44..46].copy_from_slice(&TBF_TLV_KERNEL_VERSION.to_le_bytes());
+            image[46..48].copy_from_slice(&4u16.to_le_bytes());
+            image[48..50].copy_from_slice(&major.to_le_bytes());
+            image[50..52].copy_from_slice(&minor.to_le_bytes());
+            let checksum = header_checksum(&image[..52]);
+            image[12..16].copy_from_slice(&checksum.to_le_bytes());
+            parse_header(&image).map(|header| header.kernel_version)
+        };
+        assert_eq!(with_version(KERNEL_VERSION.0, 0), Ok(Some((KERNEL_VERSION.0, 0))));
+        assert_eq!(with_version(KERNEL_VERSION.0, KERNEL_VERSION.1 + 1),
+                   Err(TbfError::IncompatibleKernel {
+                       major: KERNEL_VERSION.0, minor: KERNEL_VERSION.1 + 1 }));
+        assert_eq!(with_version(KERNEL_VERSION.0 + 1, 0),
+                   Err(TbfError::IncompatibleKernel { major: KERNEL_VERSION.0 + 1, minor: 0 }));
+    }
+
+    #[test]
+    fn looks_up_fault_response() {
+        let responses = [
+            AppFaultResponse { name: "u2f_app", response: FaultResponse::Stop },
+            AppFaultResponse { name: "otpilot", response: FaultResponse::Restart },
+        ];
+        let name = Some(&b"otpilot"[..]);
+        match fault_response(name, &responses, FaultResponse::Panic) {
+            FaultResponse::Restart => (),
+            _ => panic!("wrong fault response"),
+        }
+        match fault_response(name, &responses[..1], FaultResponse::Panic) {
+            FaultResponse::Panic => (),
+            _ => panic!("wrong fault response"),
+        }
+    }
+}
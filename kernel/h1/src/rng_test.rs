@@ -17,15 +17,15 @@ use kernel::hil::rng::RNG;
 use h1::trng;
 use h1::test_rng;
 
-pub unsafe fn run_rng() {
-    let r = static_init_test_rng();
-    trng::TRNG0.set_client(r);
+pub unsafe fn run_rng(trng: &'static trng::Trng<'static>) {
+    let r = static_init_test_rng(trng);
+    trng.set_client(r);
     r.run();
 }
 
-unsafe fn static_init_test_rng() -> &'static mut TestRng<'static> {
+unsafe fn static_init_test_rng(trng: &'static trng::Trng<'static>) -> &'static mut TestRng<'static> {
     static_init!(
         TestRng<'static>,
-        TestRng::new(&trng::TRNG0)
+        TestRng::new(trng)
     )
 }
@@ -0,0 +1,170 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Software PWM on plain GPIO pins.
+//!
+//! Some status LEDs are on pins that cannot be muxed to a PWM function.
+//! `SoftPwm` dims them by toggling the pins from an alarm: every period it
+//! drives each pin with a nonzero duty cycle active, and turns it off again
+//! once its duty cycle has elapsed. That costs up to one interrupt per pin
+//! and period, so the number of pins is limited to `MAX_PINS`. While every
+//! pin is fully on or fully off the alarm is stopped.
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::time::{Alarm, AlarmClient, Frequency, Ticks};
+use kernel::ReturnCode;
+
+/// The maximum number of pins a `SoftPwm` drives.
+pub const MAX_PINS: usize = 4;
+
+/// The PWM frequency, in Hz.
+pub const PWM_HZ: u32 = 200;
+
+/// A pin driven by `SoftPwm`.
+pub struct SoftPwmPin<'a> {
+    pin: &'a dyn gpio::Pin,
+    active_low: bool,
+    /// Duty cycle in percent.
+    duty: Cell<u8>,
+}
+
+impl<'a> SoftPwmPin<'a> {
+    pub const fn new(pin: &'a dyn gpio::Pin, active_low: bool) -> SoftPwmPin<'a> {
+        SoftPwmPin {
+            pin: pin,
+            active_low: active_low,
+            duty: Cell::new(0),
+        }
+    }
+
+    fn drive(&self, on: bool) {
+        if on != self.active_low {
+            self.pin.set();
+        } else {
+            self.pin.clear();
+        }
+    }
+}
+
+pub struct SoftPwm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    pins: &'a [SoftPwmPin<'a>],
+    /// Alarm ticks at the start of the current period.
+    period_start: Cell<u32>,
+    running: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>> SoftPwm<'a, A> {
+    /// Drives `pins`, which must not be more than `MAX_PINS`. The alarm
+    /// client must be set to this `SoftPwm`.
+    pub fn new(alarm: &'a A, pins: &'a [SoftPwmPin<'a>]) -> SoftPwm<'a, A> {
+        assert!(pins.len() <= MAX_PINS, "SoftPwm: too many pins");
+        SoftPwm {
+            alarm: alarm,
+            pins: pins,
+            period_start: Cell::new(0),
+            running: Cell::new(false),
+        }
+    }
+
+    /// Configures the pins as outputs and turns them off.
+    pub fn init(&self) {
+        for pin in self.pins {
+            pin.pin.make_output();
+            pin.drive(false);
+        }
+    }
+
+    /// The number of pins.
+    pub fn pin_count(&self) -> usize {
+        self.pins.len()
+    }
+
+    /// The duty cycle of `index`, in percent.
+    pub fn duty(&self, index: usize) -> Option<u8> {
+        self.pins.get(index).map(|pin| pin.duty.get())
+    }
+
+    /// Sets the duty cycle of `index`, in percent. Takes effect from the
+    /// next period.
+    pub fn set_duty(&self, index: usize, percent: u8) -> ReturnCode {
+        let pin = match self.pins.get(index) {
+            Some(pin) if percent <= 100 => pin,
+            _ => return ReturnCode::EINVAL,
+        };
+        pin.duty.set(percent);
+        if self.running.get() {
+            return ReturnCode::SUCCESS;
+        }
+        if self.pins.iter().all(|pin| pin.duty.get() == 0 || pin.duty.get() == 100) {
+            pin.drive(percent == 100);
+        } else {
+            self.period_start.set(self.alarm.now().into_u32().wrapping_sub(period_ticks::<A>()));
+            self.running.set(true);
+            self.update();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Drives the pins for the current point in the period and sets the
+    /// alarm for the next edge.
+    fn update(&self) {
+        let period = period_ticks::<A>();
+        let now = self.alarm.now().into_u32();
+        let mut elapsed = now.wrapping_sub(self.period_start.get());
+        if elapsed >= period {
+            // Start a new period, skipping the ones we were too late for.
+            self.period_start.set(now.wrapping_sub(elapsed % period));
+            elapsed %= period;
+            if self.pins.iter().all(|pin| pin.duty.get() == 0 || pin.duty.get() == 100) {
+                for pin in self.pins {
+                    pin.drive(pin.duty.get() == 100);
+                }
+                self.running.set(false);
+                return;
+            }
+        }
+
+        let mut next_edge = period;
+        for pin in self.pins {
+            let off_at = off_ticks(period, pin.duty.get());
+            pin.drive(elapsed < off_at);
+            if elapsed < off_at && off_at < next_edge {
+                next_edge = off_at;
+            }
+        }
+        let reference = self.period_start.get();
+        self.alarm.set_alarm(reference.into(), next_edge.into());
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for SoftPwm<'a, A> {
+    fn alarm(&self) {
+        if self.running.get() {
+            self.update();
+        }
+    }
+}
+
+fn period_ticks<'a, A: Alarm<'a>>() -> u32 {
+    A::Frequency::frequency() / PWM_HZ
+}
+
+/// Ticks into the period at which a pin with `duty` percent turns off.
+fn off_ticks(period: u32, duty: u8) -> u32 {
+    (period as u64 * duty as u64 / 100) as u32
+}
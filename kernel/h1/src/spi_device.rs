@@ -1,6 +1,9 @@
 use crate::hil::spi_device::SpiDevice;
 use crate::hil::spi_device::SpiDeviceClient;
+use crate::spi_device_filter::{compute_rules, forced_op_code, FilterRule, FILTER_RULES};
+use crate::spsc::SpscQueue;
 
+use core::cell::Cell;
 use core::cmp::min;
 
 use kernel::common::cells::OptionalCell;
@@ -12,9 +15,25 @@ use kernel::common::registers::WriteOnly;
 use kernel::common::StaticRef;
 use kernel::ReturnCode;
 
+use spiutils::driver::spi_device::AccessMetrics;
+use spiutils::driver::spi_device::AccessPermission;
+use spiutils::driver::spi_device::AccessRegion;
 use spiutils::driver::spi_device::AddressConfig;
+use spiutils::driver::spi_device::block_protected_range;
+use spiutils::driver::spi_device::window_last_address;
+use spiutils::driver::spi_device::DeniedAccessResponse;
+use spiutils::driver::spi_device::EmulatedOperation;
+use spiutils::driver::spi_device::HandlerMode;
+use spiutils::driver::spi_device::MAX_ACCESS_REGIONS;
+use spiutils::driver::spi_device::PassthroughFilterAction;
+use spiutils::driver::spi_device::READ_CAPABILITY_DUAL_OUTPUT;
+use spiutils::driver::spi_device::READ_CAPABILITY_QUAD_OUTPUT;
 use spiutils::protocol::flash::AddressMode;
 use spiutils::protocol::flash::OpCode;
+use spiutils::protocol::flash::STATUS_WEL;
+use spiutils::protocol::flash::STATUS_WIP;
+use spiutils::protocol::info_block::INFO_BLOCK_OFFSET;
+use spiutils::protocol::info_block::INFO_BLOCK_SIZE;
 
 // Helper method to improve syntax for getting a data byte from a slice
 // or a default value if the specified index is out of bounds.
@@ -217,238 +236,17 @@ register_structs! {
     }
 }
 
-register_bitfields![u32,
-    CTRL [
-        /// Mode
-        MODE OFFSET(0) NUMBITS(2) [
-            Generic = 0,
-            SwetlandMode = 1,
-            Eeprom = 2,
-            Disabled = 3
-        ],
-        /// (Generic Mode) 0: Valid on first clock edge; 1: valid on second
-        /// clock edge
-        CPHA OFFSET(2) NUMBITS(1) [],
-        /// (Generic Mode) 0: SCK start low; 1: SCK starts high
-        CPOL OFFSET(3) NUMBITS(1) [],
-        /// The polarity of the MISO pin during idle periods (CSB is
-        /// deasserted).
-        IDLE_LVL OFFSET(4) NUMBITS(1) [],
-        /// (Generic Mode) 0: LSB sent first; 1: MSB send first
-        TXBITOR OFFSET(5) NUMBITS(1) [],
-        /// (Generic Mode) 0: LSB received first; 1: MSB received first
-        RXBITOR OFFSET(6) NUMBITS(1) []
-    ],
-    FIFO_CTRL [
-        /// Reset TX FIFO. Bit is self-clearing after write of 1.
-        TXFIFO_RST OFFSET(0) NUMBITS(1) [],
-        /// Enable transmission from TX FIFO
-        TXFIFO_EN OFFSET(1) NUMBITS(1) [],
-        /// Disable TX FIFO at the end of transaction
-        TXFIFO_AUTO_DIS OFFSET(2) NUMBITS(1) [],
-        /// Reset RX FIFO. Bit is self-clearing after write of 1.
-        RXFIFO_RST OFFSET(3) NUMBITS(1) [],
-        /// Enable packet receiving in RX FIFO
-        RXFIFO_EN OFFSET(4) NUMBITS(1) [],
-        /// Disable RX FIFO at the end of transaction
-        RXFIFO_AUTO_DIS OFFSET(5) NUMBITS(1) []
-    ],
-    TXFIFO_SIZE [
-        /// The number of bytes in the TX FIFO.
-        VALUE OFFSET(0) NUMBITS(11) []
-    ],
-    TXFIFO_RPTR [
-        /// The current byte read pointer of the TX FIFO. The MSB is used to
-        /// detect if the TX FIFO is empty or full.
-        VALUE OFFSET(0) NUMBITS(11) []
-    ],
-    TXFIFO_WPTR [
-        /// The current byte write pointer of the TX FIFO. The MSB is used to
-        /// detect if the TX FIFO is empty or full.
-        VALUE OFFSET(0) NUMBITS(11) []
-    ],
-    TXFIFO_THRESHOLD [
-        /// TXFIFO_LVL interrupt will be asserted when TXFIFO_SIZE is less than
-        /// or equal to this value.
-        VALUE OFFSET(0) NUMBITS(10) []
-    ],
-    RXFIFO_SIZE [
-        /// The number of 8-bit words in the RX FIFO.
-        VALUE OFFSET(0) NUMBITS(11) []
-    ],
-    RXFIFO_RPTR [
-        /// The current byte read pointer of the RX FIFO. The MSB is used to
-        /// detect if the RX FIFO is empty or full.
-        VALUE OFFSET(0) NUMBITS(11) []
-    ],
-    RXFIFO_WPTR [
-        /// The current byte write pointer of the RX FIFO. The MSB is used to
-        /// detect if the RX FIFO is empty or full.
-        VALUE OFFSET(0) NUMBITS(11) []
-    ],
-    RXFIFO_THRESHOLD [
-        /// Level of RX FIFO + 1 in 8-bit words for RXFIFO_LVL interrupt
-        VALUE OFFSET(0) NUMBITS(10) []
-    ],
-    INTERRUPT [
-        /// Control Reg 0 interrupt
-        CTLWR0 OFFSET(0) NUMBITS(1) [],
-        /// Control Reg 1 interrupt
-        CTLWR1 OFFSET(1) NUMBITS(1) [],
-        /// Control Reg 2 interrupt
-        CTLWR2 OFFSET(2) NUMBITS(1) [],
-        /// Control Reg 3 interrupt
-        CTLWR3 OFFSET(3) NUMBITS(1) [],
-        /// Control Reg 4 interrupt
-        CTLWR4 OFFSET(4) NUMBITS(1) [],
-        /// Control Reg 5 interrupt
-        CTLWR5 OFFSET(5) NUMBITS(1) [],
-        /// Control Reg 6 interrupt
-        CTLWR6 OFFSET(6) NUMBITS(1) [],
-        /// Control Reg 7 interrupt
-        CTLWR7 OFFSET(7) NUMBITS(1) [],
-        /// CS assert interrupt
-        CS_ASSERT OFFSET(8) NUMBITS(1) [],
-        /// CS deassert interrupt
-        CS_DEASSERT OFFSET(9) NUMBITS(1) [],
-        /// RX FIFO overflow interrupt
-        RXFIFO_OVERFLOW OFFSET(10) NUMBITS(1) [],
-        /// TX FIFO empty interrupt
-        TXFIFO_EMPTY OFFSET(11) NUMBITS(1) [],
-        /// TX FIFO full interrupt
-        TXFIFO_FULL OFFSET(12) NUMBITS(1) [],
-        /// TX FIFO level interrupt
-        TXFIFO_LVL OFFSET(13) NUMBITS(1) [],
-        /// RX FIFO level interrupt
-        RXFIFO_LVL OFFSET(14) NUMBITS(1) []
-    ],
-    ISTATE_CLR [
-        /// Control Reg 0 interrupt clear
-        CTLWR0 OFFSET(0) NUMBITS(1) [],
-        /// Control Reg 1 interrupt clear
-        CTLWR1 OFFSET(1) NUMBITS(1) [],
-        /// Control Reg 2 interrupt clear
-        CTLWR2 OFFSET(2) NUMBITS(1) [],
-        /// Control Reg 3 interrupt clear
-        CTLWR3 OFFSET(3) NUMBITS(1) [],
-        /// Control Reg 4 interrupt clear
-        CTLWR4 OFFSET(4) NUMBITS(1) [],
-        /// Control Reg 5 interrupt clear
-        CTLWR5 OFFSET(5) NUMBITS(1) [],
-        /// Control Reg 6 interrupt clear
-        CTLWR6 OFFSET(6) NUMBITS(1) [],
-        /// Control Reg 7 interrupt clear
-        CTLWR7 OFFSET(7) NUMBITS(1) [],
-        /// CS assert interrupt clear
-        CS_ASSERT OFFSET(8) NUMBITS(1) [],
-        /// CS deassert interrupt clear
-        CS_DEASSERT OFFSET(9) NUMBITS(1) [],
-        /// RX FIFO overflow interrupt clear
-        RXFIFO_OVERFLOW OFFSET(10) NUMBITS(1) []
-    ],
-    EEPROM_CTRL [
-        /// SPI device EEPROM mode address selection. 0 -> 3 byte address for
-        /// read commands. 1 -> 4 byte address for read commands
-        ADDR_MODE OFFSET(0) NUMBITS(1) [],
-        /// Disable passthrough filtering completely
-        PASSTHRU_DIS OFFSET(1) NUMBITS(1) [],
-        /// Disable external flash mapping completely
-        EXT_FLASH_DIS OFFSET(2) NUMBITS(1) [],
-        /// Disable internal flash mapping completely
-        INT_FLASH_DIS OFFSET(3) NUMBITS(1) [],
-        /// Disable internal ram mapping completely
-        RAM_DIS OFFSET(4) NUMBITS(1) [],
-        /// On-die ram is enabled for generic mailbox mode. Note when this field
-        /// is 1, internal ram mapping is automatically disabled
-        MAILBOX_EN OFFSET(5) NUMBITS(1) [],
-        /// Prefetch limit for internal flash reads. This parameter controls how
-        /// much controller prefetches into flash territory on reads mapped to
-        /// internal flash. The default of 2 should be sufficient for most use
-        /// cases. Setting this number too high can cause errors on transaction
-        /// boundaries, as the flash subsystem can buffer up a large number of
-        /// transactions that cross SPI transaction boundaries (across CSB)
-        FIFO_PREFETCH_LIMIT OFFSET(6) NUMBITS(4) [],
-        /// Dual read enable. 0 means dual read opcode is not treated as dual
-        /// read
-        FAST_DUAL_RD_EN OFFSET(10) NUMBITS(1) [],
-        /// Enable virtual address filtering before use. This means address bits
-        /// can be filtered out prior to region map. See SPS_VIRTUAL_ADDR_FILTER
-        /// for more details
-        VIRTUAL_ADDR_FILTER_EN OFFSET(11) NUMBITS(1) []
-    ],
-    BUSY_OPCODE [
-        /// Arbitrary opcode is enabled
-        EN OFFSET(0) NUMBITS(1) [],
-        /// Value of arbitrary opcode
-        VALUE OFFSET(1) NUMBITS(8) []
-    ],
-    STATUS_BIT [
-        /// A single status bit
-        VALUE OFFSET(0) NUMBITS(1) []
-    ],
-    PAGE [
-        /// Page number (address shifted by PAGE_SIZE)
-        ID OFFSET(0) NUMBITS(23) []
-    ],
-    RAM_CTRL_PAGE [
-        /// When the end of a particular region is reached, the address
-        /// automatically wraps to the beginning of the region
-        WRAP_MODE OFFSET(0) NUMBITS(1) [],
-        /// Watermark (in bytes) that triggers an interrupt to software
-        INT_LVL OFFSET(1) NUMBITS(8) []
-    ],
-    CMD_MEM_PTR [
-        /// Pointer into command memory
-        VALUE OFFSET(0) NUMBITS(9) [],
-        /// Indicate whether the memory is full
-        FULL OFFSET(9) NUMBITS(1) []
-    ],
-    PASSTHRU_FILTER_RULE [
-        /// Rule Valid.
-        /// - Whether this rule participates in passthrough filtering.
-        VALID OFFSET(0) NUMBITS(1) [],
-        /// Reserved
-        RESERVED OFFSET(1) NUMBITS(7) [],
-        /// The command to force if rule is matched during filtering
-        FORCE_CMD OFFSET(8) NUMBITS(8) [],
-        /// The command value to match.
-        CMD_MATCH OFFSET(16) NUMBITS(8) [],
-        /// Command match bit vector
-        /// - A bit vector to indicate how many bits should be compared.
-        /// - This field helps differentiate the case between leading 0's and
-        /// don't cares.
-        CMD_MATCH_BIT_VECTOR OFFSET(24) NUMBITS(8) []
-    ],
-    EEPROM_INTERRUPT [
-        /// INTR_CMD_ADDR_FIFO_NOT_EMPTY interrupt
-        CMD_ADDR_FIFO_NOT_EMPTY OFFSET(0) NUMBITS(1) [],
-        /// INTR_CMD_ADDR_FIFO_OVFL interrupt
-        CMD_ADDR_FIFO_OVFL OFFSET(1) NUMBITS(1) [],
-        /// INTR_CMD_MEM_OVFL interrupt
-        CMD_MEM_OVFL OFFSET(2) NUMBITS(1) [],
-        /// INTR_RAM_PAGE0_LVL interrupt
-        RAM_PAGE0_LVL OFFSET(3) NUMBITS(1) [],
-        /// INTR_RAM_PAGE1_LVL interrupt
-        RAM_PAGE1_LVL OFFSET(4) NUMBITS(1) [],
-        /// INTR_RAM_PAGE2_LVL interrupt
-        RAM_PAGE2_LVL OFFSET(5) NUMBITS(1) [],
-        /// INTR_RAM_PAGE3_LVL interrupt
-        RAM_PAGE3_LVL OFFSET(6) NUMBITS(1) []
-    ],
-    DATA [
-        BYTE0 OFFSET(0) NUMBITS(8) [],
-        BYTE1 OFFSET(8) NUMBITS(8) [],
-        BYTE2 OFFSET(16) NUMBITS(8) [],
-        BYTE3 OFFSET(24) NUMBITS(8) []
-    ]
-];
+// Generated from registers/spi_device.toml.
+include!(concat!(env!("OUT_DIR"), "/spi_device_bitfields.rs"));
 
 /// SPI device EEPROM virtual pages are 512 bytes in size.
 const PAGE_SHIFT: u8 = 9;
 
-#[allow(dead_code)]
 const PAGE_SIZE: u32 = 1 << PAGE_SHIFT;
 
+/// The highest RAM page interrupt watermark, which is 8 bits wide.
+const MAX_MAILBOX_WATERMARK: usize = 0xff;
+
 /// Configuration for SPI device hardware.
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct SpiDeviceConfiguration {
@@ -456,6 +254,11 @@ pub struct SpiDeviceConfiguration {
     /// When set to false, enables OpCode::FastReadDualOutput.
     pub enable_fastread4b_cmd: bool,
 
+    /// Set to true to pass OpCode::FastReadQuadOutput through to the external
+    /// flash. Only boards that connect all four data lines of the external
+    /// flash may set this.
+    pub enable_quad_output_read_cmd: bool,
+
     /// Set to true to handle OpCode::Enter4ByteAddressMode and OpCode::Exit4ByteAddressMode
     /// in software and passthrough mode.
     /// When set to false, these op codes are ignored.
@@ -469,12 +272,27 @@ impl SpiDeviceConfiguration {
     pub const fn default() -> SpiDeviceConfiguration {
         SpiDeviceConfiguration {
             enable_fastread4b_cmd: false,
+            enable_quad_output_read_cmd: false,
             enable_enterexit4b_cmd: false,
             startup_address_mode: AddressMode::ThreeByte,
         }
     }
 }
 
+/// Maximum number of op codes whose passthrough filtering can be overridden.
+pub const MAX_PASSTHROUGH_FILTER_OVERRIDES: usize = 4;
+
+/// Number of received transactions that can wait to be delivered to the
+/// client.
+const TRANSACTION_QUEUE_LEN: usize = 4;
+
+/// Status bits latched when a transaction is received.
+#[derive(Clone, Copy)]
+struct TransactionStatus {
+    is_busy: bool,
+    is_write_enabled: bool,
+}
+
 /// SPI device EEPROM sector size is 4KiB, since this is the smallest erasable
 /// size.
 #[allow(dead_code)]
@@ -484,14 +302,24 @@ const SPI_DEVICE0_BASE_ADDR: u32 = 0x4051_0000;
 const SPI_DEVICE0_REGISTERS: StaticRef<Registers> =
     unsafe { StaticRef::new(SPI_DEVICE0_BASE_ADDR as *const Registers) };
 
-pub static mut SPI_DEVICE0: SpiDeviceHardware = SpiDeviceHardware::new(SPI_DEVICE0_REGISTERS,
-    SpiDeviceConfiguration::default());
+pub(crate) const unsafe fn spi_device0() -> SpiDeviceHardware {
+    SpiDeviceHardware::new(SPI_DEVICE0_REGISTERS, SpiDeviceConfiguration::default())
+}
 
 /// A SPI device
 pub struct SpiDeviceHardware {
     registers: StaticRef<Registers>,
     client: OptionalCell<&'static dyn SpiDeviceClient>,
     config: SpiDeviceConfiguration,
+    address_config: OptionalCell<AddressConfig>,
+    access_regions: [OptionalCell<AccessRegion>; MAX_ACCESS_REGIONS],
+    denied_access_response: Cell<DeniedAccessResponse>,
+    access_metrics: Cell<AccessMetrics>,
+    passthrough_filter_overrides: [OptionalCell<(u8, PassthroughFilterAction)>; MAX_PASSTHROUGH_FILTER_OVERRIDES],
+    transactions: SpscQueue<TransactionStatus, TRANSACTION_QUEUE_LEN>,
+    // A transaction the client could not take yet.
+    held_transaction: OptionalCell<TransactionStatus>,
+    info_block_enabled: Cell<bool>,
 }
 
 impl SpiDeviceHardware {
@@ -500,6 +328,27 @@ impl SpiDeviceHardware {
             registers: base_addr,
             client: OptionalCell::empty(),
             config: config,
+            address_config: OptionalCell::empty(),
+            access_regions: [
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+            ],
+            denied_access_response: Cell::new(DeniedAccessResponse::Zeros),
+            access_metrics: Cell::new(AccessMetrics {
+                denied_reads: 0,
+                denied_writes: 0,
+            }),
+            passthrough_filter_overrides: [
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+            ],
+            transactions: SpscQueue::new(),
+            held_transaction: OptionalCell::empty(),
+            info_block_enabled: Cell::new(false),
         }
     }
 
@@ -528,11 +377,15 @@ impl SpiDeviceHardware {
             self.registers.fast_dual_rd_opcode.set(OpCode::FastReadDualOutput as u8);
         }
 
-        self.init_passthrough_filters();
+        // The default filter always fits.
+        self.program_passthrough_filter();
+        self.registers.eeprom_ctrl.modify(EEPROM_CTRL::PASSTHRU_DIS::CLEAR);
 
         self.clear_jedec();
         self.clear_sfdp();
 
+        self.set_denied_access_response(self.denied_access_response.get());
+
         self.init_busy_opcodes();
 
         self.set_address_mode(self.config.startup_address_mode);
@@ -556,153 +409,46 @@ impl SpiDeviceHardware {
         }
     }
 
-    fn init_passthrough_filters(&self) {
-        // Match 0b0000_0XXX (0x00 - 0x07) and force to NormalRead
-        let mut rule_idx = 0;
-        self.registers.passthru_filter_rule[rule_idx].write(
-                PASSTHRU_FILTER_RULE::VALID::SET +
-                PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::NormalRead as u32) +
-                PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b0000_0000) +
-                PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_1000)
-            );
-        rule_idx += 1;
-
-        if !self.config.enable_fastread4b_cmd {
-            // Match 0b0000_1XXX (0x08 - 0x0f) and force to FastRead
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::FastRead as u32) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b0000_1000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_1000)
-                );
-            rule_idx += 1;
-        } else {
-            // Match 0b0000_10XX (0x08 - 0x0b) and force to FastRead
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::FastRead as u32) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b0000_1000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_1100)
-                );
-            rule_idx += 1;
-
-            // Match 0b0000_11XX (0x0c - 0x0f) and force to FastRead4B
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::FastRead4B as u32) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b0000_1100) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_1100)
-                );
-            rule_idx += 1;
-        }
-
-        // Match 0b0001_XXXX (0x10 - 0x1f) and force to NormalRead
-        self.registers.passthru_filter_rule[rule_idx].write(
-                PASSTHRU_FILTER_RULE::VALID::SET +
-                PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::NormalRead as u32) +
-                PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b0001_0000) +
-                PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_0000)
-            );
-        rule_idx += 1;
-
-        // Match 0b001X_XXXX (0x20 - 0x3f) and force to FastReadDualOutput
-        self.registers.passthru_filter_rule[rule_idx].write(
-                PASSTHRU_FILTER_RULE::VALID::SET +
-                PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::FastReadDualOutput as u32) +
-                PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b0010_0000) +
-                PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1110_0000)
-            );
-        rule_idx += 1;
-
-        // Match 0b01XX_XXXX (0x40 - 0x7f) and force to NormalRead
-        self.registers.passthru_filter_rule[rule_idx].write(
-                PASSTHRU_FILTER_RULE::VALID::SET +
-                PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::NormalRead as u32) +
-                PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b0100_0000) +
-                PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1100_0000)
-            );
-        rule_idx += 1;
-
-        if !self.config.enable_enterexit4b_cmd {
-            // Match 0b1XXX_XXXX (0x80 - 0xff) and force to NormalRead
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::NormalRead as u32) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b1000_0000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1000_0000)
-                );
-            rule_idx += 1;
-        } else {
-            // Match 0b100X_XXXX (0x80 - 0x9f) and force to ReadJedec
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::ReadJedec as u32) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b1000_0000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1110_0000)
-                );
-            rule_idx += 1;
-
-            // Match 0b1010_XXXX (0xa0 - 0xaf) and force to 0xa0 (non-existing command)
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(0b1010_0000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b1010_0000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_0000)
-                );
-            rule_idx += 1;
-
-            // Match 0b1011_0XXX (0xb0 - 0xb7) and force to Enter4ByteAddressMode
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::Enter4ByteAddressMode as u32) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b1011_0000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_1000)
-                );
-            rule_idx += 1;
-
-            // Match 0b1011_1XXX (0xb8 - 0xbf) and force to 0xbf (non-existing command)
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(0b1011_1111) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b1011_0000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_1000)
-                );
-            rule_idx += 1;
-
-            // Match 0b110X_XXXX (0xc0 - 0xdf) and force to 0xc8 (non-existing command)
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(0b1100_1000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b1100_0000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1110_0000)
-                );
-            rule_idx += 1;
-
-            // Match 0b1110_XXXX (0xe0 - 0xef) and force to Exit4ByteAddressMode (0xe9)
-            self.registers.passthru_filter_rule[rule_idx].write(
+    // Program the passthrough filter rules for the default filter with the
+    // configured overrides. Passthrough stays disabled while the rules
+    // change, so that no command slips through a half-written filter.
+    // Returns false, leaving the rules alone, if they do not fit in hardware.
+    fn program_passthrough_filter(&self) -> bool {
+        let mut rules = [FilterRule::default(); FILTER_RULES];
+        let count = match compute_rules(|op_code| self.forced_op_code(op_code), &mut rules) {
+            Some(count) => count,
+            None => return false,
+        };
+
+        let was_enabled = !self.registers.eeprom_ctrl.is_set(EEPROM_CTRL::PASSTHRU_DIS);
+        self.registers.eeprom_ctrl.modify(EEPROM_CTRL::PASSTHRU_DIS::SET);
+        for (idx, reg) in self.registers.passthru_filter_rule.iter().enumerate() {
+            match rules[..count].get(idx) {
+                Some(rule) => reg.write(
                     PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(OpCode::Exit4ByteAddressMode as u32) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b1110_0000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_0000)
-                );
-            rule_idx += 1;
-
-            // Match 0b1111_XXXX (0xf0 - 0xff) and force to 0xff (non-existing command)
-            self.registers.passthru_filter_rule[rule_idx].write(
-                    PASSTHRU_FILTER_RULE::VALID::SET +
-                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(0b1111_1111) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(0b1111_0000) +
-                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(0b1111_0000)
-                );
-            rule_idx += 1;
+                    PASSTHRU_FILTER_RULE::FORCE_CMD.val(rule.forced_op_code as u32) +
+                    PASSTHRU_FILTER_RULE::CMD_MATCH.val(rule.op_code as u32) +
+                    PASSTHRU_FILTER_RULE::CMD_MATCH_BIT_VECTOR.val(rule.mask as u32)
+                ),
+                None => reg.write(PASSTHRU_FILTER_RULE::VALID::CLEAR),
+            }
         }
-
-        // Disable all remaining passthrough filter rules
-        for idx in rule_idx..self.registers.passthru_filter_rule.len() {
-            self.registers.passthru_filter_rule[idx].write(PASSTHRU_FILTER_RULE::VALID::CLEAR);
+        if was_enabled {
+            self.registers.eeprom_ctrl.modify(EEPROM_CTRL::PASSTHRU_DIS::CLEAR);
         }
+        true
+    }
 
-        self.registers.eeprom_ctrl.modify(EEPROM_CTRL::PASSTHRU_DIS::CLEAR);
+    // The op code that passthrough forces `op_code` to.
+    fn forced_op_code(&self, op_code: u8) -> u8 {
+        let action = self.passthrough_filter_overrides.iter()
+            .filter_map(|entry| entry.extract())
+            .find(|(overridden, _)| *overridden == op_code)
+            .map_or(PassthroughFilterAction::Default, |(_, action)| action);
+        forced_op_code(op_code, action,
+                       self.config.enable_fastread4b_cmd,
+                       self.config.enable_quad_output_read_cmd,
+                       self.config.enable_enterexit4b_cmd)
     }
 
     fn init_busy_opcodes(&self) {
@@ -753,6 +499,20 @@ impl SpiDeviceHardware {
         self.registers.eeprom_int_enable.modify(EEPROM_INTERRUPT::CMD_ADDR_FIFO_NOT_EMPTY::CLEAR);
     }
 
+    // The mailbox is RAM page 0, so its level interrupt fires once the SPI
+    // host reads up to the watermark.
+    fn arm_mailbox_read_interrupt(&self, len: usize) {
+        let watermark = min(len, MAX_MAILBOX_WATERMARK + 1) - 1;
+        self.registers.ram_ctrl_page[0].modify(RAM_CTRL_PAGE::INT_LVL.val(watermark as u32));
+        self.registers.eeprom_int_state.write(EEPROM_INTERRUPT::RAM_PAGE0_LVL::SET);
+        self.registers.eeprom_int_enable.modify(EEPROM_INTERRUPT::RAM_PAGE0_LVL::SET);
+    }
+
+    fn disarm_mailbox_read_interrupt(&self) {
+        self.registers.eeprom_int_enable.modify(EEPROM_INTERRUPT::RAM_PAGE0_LVL::CLEAR);
+        self.registers.eeprom_int_state.write(EEPROM_INTERRUPT::RAM_PAGE0_LVL::SET);
+    }
+
     fn is_busy(&self) -> bool {
         self.registers.eeprom_busy_status.is_set(STATUS_BIT::VALUE)
     }
@@ -763,16 +523,176 @@ impl SpiDeviceHardware {
 
     pub fn handle_interrupt_cmd_addr_fifo_not_empty(&self) {
         //debug!("CMD_ADDR_FIFO_EMPTY = {}", self.registers.cmd_addr_fifo_empty.get());
-        if !self.registers.cmd_addr_fifo_empty.is_set(STATUS_BIT::VALUE) {
-            self.client.map(|client| {
-                client.data_available(self.is_busy(), self.is_write_enabled());
+        self.latch_transaction();
+        self.clear_rx_interrupt();
+        self.deliver_transactions();
+    }
+
+    pub fn handle_interrupt_ram_page0_lvl(&self) {
+        // Only report the first read of each payload.
+        self.disarm_mailbox_read_interrupt();
+        self.client.map(|client| client.mailbox_read());
+    }
+
+    // Queue the status of the next transaction in the CMD_ADDR FIFO, if any.
+    fn latch_transaction(&self) {
+        if self.registers.cmd_addr_fifo_empty.is_set(STATUS_BIT::VALUE) {
+            return;
+        }
+        let _ = self.transactions.push(TransactionStatus {
+            is_busy: self.is_busy(),
+            is_write_enabled: self.is_write_enabled(),
+        });
+    }
+
+    // Deliver queued transactions to the client. The client consumes one
+    // FIFO entry per call, so keep going while the host has sent more in
+    // the meantime, but at most TRANSACTION_QUEUE_LEN times so that a client
+    // which does not consume them cannot stall the kernel. A transaction the
+    // client turns down is held, and delivered first next time.
+    // Returns true if at least one transaction was delivered.
+    fn deliver_transactions(&self) -> bool {
+        let mut delivered = false;
+        for _ in 0..TRANSACTION_QUEUE_LEN {
+            let status = match self.held_transaction.take().or_else(|| self.transactions.pop()) {
+                Some(status) => status,
+                None => break,
+            };
+            let accepted = self.client.map_or(true, |client| {
+                client.data_available(status.is_busy, status.is_write_enabled)
             });
+            if !accepted {
+                self.held_transaction.set(status);
+                break;
+            }
+            delivered = true;
+            if self.transactions.is_empty() {
+                self.latch_transaction();
+            }
         }
+        delivered
+    }
 
-        self.clear_rx_interrupt();
+    /// Check whether the block protect bits of the status register protect
+    /// `address` in the external flash window, or, for None, any part of it.
+    fn is_block_protected(&self, address: Option<u32>) -> bool {
+        let config = match self.address_config.extract() {
+            Some(config) => config,
+            None => return false,
+        };
+        let (offset, size) = block_protected_range(
+            self.registers.eeprom_status.get(), config.flash_physical_size);
+        if size == 0 {
+            return false;
+        }
+        match address {
+            Some(address) => address.checked_sub(config.flash_virtual_base)
+                .map_or(false, |address| address >= offset && address - offset < size),
+            None => true,
+        }
+    }
+
+    /// Find the first region in the access map that contains `address`.
+    fn find_access_region(&self, address: u32) -> Option<AccessRegion> {
+        self.access_regions.iter()
+            .filter_map(|region| region.extract())
+            .find(|region| region.contains(address))
+    }
+
+    /// Compute the first and last page of the external flash window, excluding
+    /// denied regions at the start or the end of the window. The result does
+    /// not depend on the order of the regions in the access map.
+    ///
+    /// Returns None if a denied region cannot be excluded in hardware, or
+    /// if the window does not fit in the address space.
+    fn get_ext_flash_window(&self, config: &AddressConfig) -> Option<(u32, u32)> {
+        let (window_first, window_last) = config.flash_window().ok()?;
+        let window_first_page = window_first >> PAGE_SHIFT;
+        let window_last_page = window_last >> PAGE_SHIFT;
+
+        // Collect the pages of all denied regions that overlap the window.
+        let mut denied = [None; MAX_ACCESS_REGIONS];
+        for (entry, region) in denied.iter_mut().zip(self.access_regions.iter()) {
+            let region = match region.extract() {
+                Some(region) => region,
+                None => continue,
+            };
+            if region.permission != AccessPermission::Deny || region.size == 0 {
+                continue;
+            }
+            let region_first_page = region.virtual_base >> PAGE_SHIFT;
+            let region_last_page =
+                window_last_address(region.virtual_base, region.size).ok()? >> PAGE_SHIFT;
+            if region_last_page < window_first_page || region_first_page > window_last_page {
+                // Not part of the window.
+                continue;
+            }
+            *entry = Some((region_first_page, region_last_page));
+        }
+
+        // Allow any size of external flash by default
+        let mut first_page = window_first_page;
+        let mut last_page = !0;
+
+        // Shrink the window until no denied region touches either end. Each
+        // pass only moves the ends inwards, so this terminates.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &(region_first_page, region_last_page) in denied.iter().flatten() {
+                if region_first_page <= first_page && region_last_page >= first_page {
+                    first_page = region_last_page.checked_add(1)?;
+                    changed = true;
+                }
+                let end_page = min(last_page, window_last_page);
+                if first_page <= end_page &&
+                    region_first_page <= end_page && region_last_page >= end_page {
+                    last_page = region_first_page.checked_sub(1)?;
+                    changed = true;
+                }
+            }
+        }
+
+        // Any denied region left in the window is in the middle of it.
+        let end_page = min(last_page, window_last_page);
+        let is_excluded = denied.iter().flatten().all(|&(region_first_page, region_last_page)| {
+            region_last_page < first_page || region_first_page > end_page
+        });
+        if !is_excluded {
+            return None;
+        }
+
+        Some((first_page, last_page))
+    }
+
+    fn program_ext_flash_window(&self, first_page: u32, last_page: u32) {
+        self.registers.ext_flash_base_page.write(PAGE::ID.val(first_page));
+        self.registers.ext_flash_limit_page.write(PAGE::ID.val(last_page));
     }
 
     /// Write bytes to a slice of 32-bit registers, filling missing data with 0xff.
+    // Write `write_data` to the start of the RAM and pad the rest with 0xFF.
+    fn write_ram(&self, write_data: &[u8]) -> kernel::ReturnCode {
+        // Leave the info block page alone once it is in use.
+        let ram_len = if self.info_block_enabled.get() {
+            INFO_BLOCK_OFFSET as usize
+        } else {
+            self.registers.generic_ram.len()
+        };
+        if write_data.len() > ram_len {
+            debug!("h1::Sps::store_data: Invalid write_data length == {}", write_data.len());
+            return ReturnCode::ESIZE;
+        }
+        for idx in 0..write_data.len() {
+            self.registers.generic_ram[idx].set(write_data[idx]);
+        }
+        for idx in write_data.len()..ram_len {
+            self.registers.generic_ram[idx].set(!0);
+        }
+
+        ReturnCode::SUCCESS
+    }
+
     fn write_register_data(&self, regs: &[ReadWrite<u32, DATA::Register>], data: &[u8]) -> kernel::ReturnCode {
         if data.len() > regs.len()*4 {
             debug!("h1::Sps::set_jedec_id: Invalid data length == {}", data.len());
@@ -805,17 +725,29 @@ impl SpiDevice for SpiDeviceHardware {
         }
     }
 
-    fn configure_addresses(&self, config: AddressConfig) {
+    fn configure_addresses(&self, config: AddressConfig) -> ReturnCode {
+        if config.validate().is_err() {
+            return ReturnCode::EINVAL;
+        }
+        // The RAM pages are mapped consecutively from `ram_virtual_base`.
+        let ram_size = self.registers.ram_virtual_page.len() as u32 * PAGE_SIZE;
+        if window_last_address(config.ram_virtual_base, ram_size).is_err() {
+            return ReturnCode::EINVAL;
+        }
+        // Denied regions in the access map must still be excludable from the
+        // new external flash window.
+        let (first_page, last_page) = match self.get_ext_flash_window(&config) {
+            Some(window) => window,
+            None => return ReturnCode::EINVAL,
+        };
+
         self.registers.eeprom_ctrl.modify(EEPROM_CTRL::EXT_FLASH_DIS::SET);
         self.registers.eeprom_ctrl.modify(EEPROM_CTRL::VIRTUAL_ADDR_FILTER_EN::CLEAR);
         self.registers.eeprom_ctrl.modify(EEPROM_CTRL::RAM_DIS::SET);
 
-        // Configure external flash at `flash_virtual_base`
-        self.registers.ext_flash_base_page.write(
-            PAGE::ID.val(config.flash_virtual_base >> PAGE_SHIFT));
-
-        // Allow any size of external flash
-        self.registers.ext_flash_limit_page.write(PAGE::ID.val(0xffffffff));
+        // Configure external flash at `flash_virtual_base`, excluding denied
+        // regions.
+        self.program_ext_flash_window(first_page, last_page);
 
         // Allow all bits to be used unmodified
         self.registers.ext_flash_trans_bit_vector.set(0xffffffff);
@@ -826,6 +758,9 @@ impl SpiDevice for SpiDeviceHardware {
 
 
         // Configure all available EEPROM mode RAM pages at the desired base address.
+        // This resets the watermarks, so a pending mailbox notification is
+        // dropped.
+        self.disarm_mailbox_read_interrupt();
         let ram_virtual_page_base = config.ram_virtual_base >> PAGE_SHIFT;
         for idx in 0..self.registers.ram_virtual_page.len() {
             self.registers.ram_virtual_page[idx].write(
@@ -836,9 +771,13 @@ impl SpiDevice for SpiDeviceHardware {
             );
         }
 
+        self.address_config.set(config);
+
         self.registers.eeprom_ctrl.modify(EEPROM_CTRL::EXT_FLASH_DIS::CLEAR);
         self.registers.eeprom_ctrl.modify(EEPROM_CTRL::RAM_DIS::CLEAR);
         self.registers.eeprom_ctrl.modify(EEPROM_CTRL::VIRTUAL_ADDR_FILTER_EN::CLEAR);
+
+        ReturnCode::SUCCESS
     }
 
     fn set_address_mode(&self, address_mode: AddressMode) {
@@ -857,6 +796,18 @@ impl SpiDevice for SpiDeviceHardware {
         }
     }
 
+    fn get_read_capabilities(&self) -> u32 {
+        // The dual output read slot is taken by FastRead4B when enabled.
+        let mut capabilities = 0;
+        if !self.config.enable_fastread4b_cmd {
+            capabilities |= READ_CAPABILITY_DUAL_OUTPUT;
+        }
+        if self.config.enable_quad_output_read_cmd {
+            capabilities |= READ_CAPABILITY_QUAD_OUTPUT;
+        }
+        capabilities
+    }
+
     fn get_received_data(&self, read_buffer: &mut[u8]) -> usize {
         if self.registers.cmd_addr_fifo_empty.is_set(STATUS_BIT::VALUE) {
             return 0;
@@ -909,24 +860,56 @@ impl SpiDevice for SpiDeviceHardware {
         length
     }
 
+    fn poll_data_available(&self) -> bool {
+        // A held transaction is still in the FIFO, so latching now would
+        // count it twice.
+        if self.transactions.is_empty() && self.held_transaction.is_none() {
+            self.latch_transaction();
+        }
+        self.deliver_transactions()
+    }
+
     fn put_send_data(&self, write_data: &[u8]) -> kernel::ReturnCode {
         //debug!("kernel: put_send_data (len={})", write_data.len());
-        if write_data.len() > self.registers.generic_ram.len() {
-            debug!("h1::Sps::store_data: Invalid write_data length == {}", write_data.len());
-            return ReturnCode::ESIZE;
+        self.disarm_mailbox_read_interrupt();
+        self.write_ram(write_data)
+    }
+
+    fn put_mailbox_data(&self, write_data: &[u8]) -> kernel::ReturnCode {
+        self.disarm_mailbox_read_interrupt();
+        let return_code = self.write_ram(write_data);
+        if return_code == ReturnCode::SUCCESS && !write_data.is_empty() {
+            self.arm_mailbox_read_interrupt(write_data.len());
         }
-        for idx in 0..write_data.len() {
-            self.registers.generic_ram[idx].set(write_data[idx]);
+        return_code
+    }
+
+    fn set_info_block(&self, data: &[u8]) -> kernel::ReturnCode {
+        if data.len() > INFO_BLOCK_SIZE {
+            return ReturnCode::ESIZE;
         }
-        for idx in write_data.len()..self.registers.generic_ram.len() {
-            self.registers.generic_ram[idx].set(!0);
+        let page = &self.registers.generic_ram[INFO_BLOCK_OFFSET as usize..];
+        for (idx, reg) in page.iter().enumerate() {
+            reg.set(*data.get(idx).unwrap_or(&!0));
         }
+        self.info_block_enabled.set(true);
 
         ReturnCode::SUCCESS
     }
 
     fn set_status(&self, status: u8) {
-        self.registers.eeprom_status.set(status);
+        self.registers.eeprom_status.set(status & !(STATUS_WIP | STATUS_WEL));
+    }
+
+    fn get_status(&self) -> u8 {
+        let mut status = self.registers.eeprom_status.get() & !(STATUS_WIP | STATUS_WEL);
+        if self.is_busy() {
+            status |= STATUS_WIP;
+        }
+        if self.is_write_enabled() {
+            status |= STATUS_WEL;
+        }
+        status
     }
 
     fn clear_busy(&self) {
@@ -956,4 +939,129 @@ impl SpiDevice for SpiDeviceHardware {
         //debug!("kernel: set_sfdp (len={})", data.len());
         self.write_register_data(&self.registers.sfdp, data)
     }
+
+    fn set_access_regions(&self, regions: &[AccessRegion]) -> kernel::ReturnCode {
+        if regions.len() > self.access_regions.len() {
+            return ReturnCode::ESIZE;
+        }
+        for region in regions {
+            if window_last_address(region.virtual_base, region.size).is_err() {
+                return ReturnCode::EINVAL;
+            }
+            if region.permission == AccessPermission::Deny &&
+                (region.virtual_base % PAGE_SIZE != 0 || region.size % PAGE_SIZE != 0) {
+                return ReturnCode::EINVAL;
+            }
+        }
+
+        let previous_regions = [
+            self.access_regions[0].extract(),
+            self.access_regions[1].extract(),
+            self.access_regions[2].extract(),
+            self.access_regions[3].extract(),
+        ];
+        for (idx, entry) in self.access_regions.iter().enumerate() {
+            match regions.get(idx) {
+                Some(region) => entry.set(*region),
+                None => entry.clear(),
+            }
+        }
+
+        if let Some(config) = self.address_config.extract() {
+            match self.get_ext_flash_window(&config) {
+                Some((first_page, last_page)) => {
+                    self.program_ext_flash_window(first_page, last_page);
+                }
+                None => {
+                    // Restore the previous access map, which is still programmed.
+                    for (entry, region) in self.access_regions.iter().zip(previous_regions.iter()) {
+                        entry.insert(*region);
+                    }
+                    return ReturnCode::EINVAL;
+                }
+            }
+        }
+
+        ReturnCode::SUCCESS
+    }
+
+    fn set_denied_access_response(&self, response: DeniedAccessResponse) {
+        self.denied_access_response.set(response);
+        // Reads of unmapped (i.e. denied) addresses are answered by hardware.
+        match response {
+            DeniedAccessResponse::Zeros => self.registers.unmapped_return_val.set(0x00),
+            DeniedAccessResponse::Busy => self.registers.unmapped_return_val.set(0xff),
+        }
+    }
+
+    fn check_access(&self, address: Option<u32>, is_write: bool) -> bool {
+        let is_allowed = match address {
+            Some(address) => self.find_access_region(address)
+                .map_or(true, |region| region.allows(is_write)),
+            None => self.access_regions.iter()
+                .filter_map(|region| region.extract())
+                .all(|region| region.allows(is_write)),
+        };
+        let is_protected = is_allowed && is_write && self.is_block_protected(address);
+        if is_allowed && !is_protected {
+            return true;
+        }
+
+        let mut metrics = self.access_metrics.get();
+        if is_write {
+            metrics.denied_writes = metrics.denied_writes.wrapping_add(1);
+        } else {
+            metrics.denied_reads = metrics.denied_reads.wrapping_add(1);
+        }
+        self.access_metrics.set(metrics);
+
+        if is_write {
+            self.clear_write_enable();
+        }
+        // A real flash just ignores writes to protected blocks.
+        if is_protected || self.denied_access_response.get() == DeniedAccessResponse::Zeros {
+            self.clear_busy();
+        }
+
+        false
+    }
+
+    fn get_access_metrics(&self) -> AccessMetrics {
+        self.access_metrics.get()
+    }
+
+    fn set_passthrough_filter(&self, op_code: u8, action: PassthroughFilterAction) -> ReturnCode {
+        let entry = match self.passthrough_filter_overrides.iter()
+            .find(|entry| entry.map_or(false, |(overridden, _)| *overridden == op_code)) {
+            Some(entry) => entry,
+            None if action == PassthroughFilterAction::Default => return ReturnCode::SUCCESS,
+            None => match self.passthrough_filter_overrides.iter().find(|entry| entry.is_none()) {
+                Some(entry) => entry,
+                None => return ReturnCode::ESIZE,
+            },
+        };
+
+        let previous = entry.extract();
+        if action == PassthroughFilterAction::Default {
+            entry.clear();
+        } else {
+            entry.set((op_code, action));
+        }
+        if !self.program_passthrough_filter() {
+            entry.insert(previous);
+            return ReturnCode::ESIZE;
+        }
+
+        ReturnCode::SUCCESS
+    }
+
+    fn set_emulated_latency(&self, _operation: EmulatedOperation, _latency_us: u32) -> ReturnCode {
+        // Timing is emulated by spi_device_timing::EmulatedTiming.
+        ReturnCode::ENOSUPPORT
+    }
+
+    fn set_program_erase_handling(&self, _handler_mode: HandlerMode, _spi_base: u32) -> ReturnCode {
+        // Commands are applied to flash by spi_device_flash::FlashEmulation.
+        ReturnCode::ENOSUPPORT
+    }
 }
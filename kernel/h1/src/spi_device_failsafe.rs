@@ -0,0 +1,77 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Ends the SPI host's commands in failsafe mode, when no app runs.
+//!
+//! In failsafe mode, `FlashEmulation` programs and erases the inactive RW
+//! segment for the SPI host, so that the host can write a new firmware
+//! image. It hands all other commands that reach software to this client.
+//! The client switches the address mode for Enter4ByteAddressMode and
+//! Exit4ByteAddressMode, so that the host can reach the RW segment above
+//! its own flash, and applies WriteStatusRegister. Other commands, e.g.
+//! writes to the host's own flash, are dropped the way a real flash drops
+//! writes to a protected block. Either way the client clears BUSY, so the
+//! host does not wait for an app that never runs.
+
+use spiutils::protocol::flash::AddressMode;
+use spiutils::protocol::flash::OpCode;
+use spiutils::protocol::wire::WireEnum;
+
+use crate::hil::spi_device::{SpiDevice, SpiDeviceClient};
+
+pub struct FailsafeSpiDevice<'a> {
+    device: &'a dyn SpiDevice,
+}
+
+impl<'a> FailsafeSpiDevice<'a> {
+    /// Ends the commands of `device`, which must have this as its client.
+    pub const fn new(device: &'a dyn SpiDevice) -> FailsafeSpiDevice<'a> {
+        FailsafeSpiDevice {
+            device: device,
+        }
+    }
+}
+
+impl<'a> SpiDeviceClient for FailsafeSpiDevice<'a> {
+    fn data_available(&self, _is_busy: bool, is_write_enabled: bool) -> bool {
+        // Just grab the op code and the status register byte.
+        let mut command = [!0; 2];
+        let len = self.device.get_received_data(&mut command);
+        let op_code = command[..len].get(0).and_then(|op_code| OpCode::from_wire_value(*op_code));
+        match op_code {
+            Some(OpCode::Enter4ByteAddressMode) => {
+                self.device.set_address_mode(AddressMode::FourByte);
+            }
+            Some(OpCode::Exit4ByteAddressMode) => {
+                self.device.set_address_mode(AddressMode::ThreeByte);
+            }
+            Some(OpCode::WriteStatusRegister) => {
+                if len > 1 && is_write_enabled {
+                    self.device.set_status(command[1]);
+                }
+                self.device.clear_write_enable();
+            }
+            Some(op_code) if op_code.is_write() => {
+                self.device.clear_write_enable();
+            }
+            _ => {}
+        }
+        self.device.clear_busy();
+        true
+    }
+
+    fn mailbox_read(&self) {}
+}
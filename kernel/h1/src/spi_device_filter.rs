@@ -0,0 +1,158 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Computes the passthrough filter rules of the SPI device.
+//!
+//! While passthrough is enabled, the SPI device forwards commands from the
+//! SPI host to the external flash, with the op code replaced by the one that
+//! the matching filter rule forces. A rule matches op codes under a bit mask.
+//! The default filter forces every op code to a read, JEDEC ID, an address
+//! mode change or an op code the flash does not know, so that the SPI host
+//! cannot modify the external flash through passthrough.
+//!
+//! Software may override the default for single op codes. The rules are
+//! computed from scratch for the resulting mapping, so that they never
+//! overlap and their order in hardware does not matter.
+
+use spiutils::driver::spi_device::PassthroughFilterAction;
+use spiutils::protocol::flash::OpCode;
+
+/// Number of passthrough filter rules in hardware.
+pub const FILTER_RULES: usize = 16;
+
+/// An op code the external flash does not know.
+const UNKNOWN_OP_CODE: u8 = 0xff;
+
+/// A passthrough filter rule: op codes that equal `op_code` in the bits set
+/// in `mask` are replaced by `forced_op_code`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct FilterRule {
+    pub op_code: u8,
+    pub mask: u8,
+    pub forced_op_code: u8,
+}
+
+impl FilterRule {
+    /// Check if the rule matches `op_code`.
+    pub fn matches(&self, op_code: u8) -> bool {
+        op_code & self.mask == self.op_code & self.mask
+    }
+}
+
+/// Returns the op code that passthrough forces `op_code` to when handled
+/// with `action`. The default filter depends on whether FastRead4B, quad
+/// output reads and the 4 byte address mode commands are enabled.
+pub fn forced_op_code(op_code: u8, action: PassthroughFilterAction,
+                      enable_fastread4b_cmd: bool, enable_quad_output_read_cmd: bool,
+                      enable_enterexit4b_cmd: bool) -> u8 {
+    match action {
+        PassthroughFilterAction::Pass => return op_code,
+        PassthroughFilterAction::Block => return UNKNOWN_OP_CODE,
+        PassthroughFilterAction::Default => {}
+    }
+
+    match op_code {
+        0x08..=0x0b => OpCode::FastRead as u8,
+        0x0c..=0x0f if enable_fastread4b_cmd => OpCode::FastRead4B as u8,
+        0x0c..=0x0f => OpCode::FastRead as u8,
+        0x20..=0x3f => OpCode::FastReadDualOutput as u8,
+        0x60..=0x7f if enable_quad_output_read_cmd => OpCode::FastReadQuadOutput as u8,
+        0x80..=0xff if !enable_enterexit4b_cmd => OpCode::NormalRead as u8,
+        0x80..=0x9f => OpCode::ReadJedec as u8,
+        // The remaining values are op codes that do not exist.
+        0xa0..=0xaf => 0xa0,
+        0xb0..=0xb7 => OpCode::Enter4ByteAddressMode as u8,
+        0xb8..=0xbf => 0xbf,
+        0xc0..=0xdf => 0xc8,
+        0xe0..=0xef => OpCode::Exit4ByteAddressMode as u8,
+        0xf0..=0xff => UNKNOWN_OP_CODE,
+        _ => OpCode::NormalRead as u8,
+    }
+}
+
+/// Computes rules that force each op code to `forced(op_code)`. Each rule
+/// covers an aligned block of op codes that are all forced to the same
+/// value, and blocks are as large as possible.
+///
+/// Returns the number of rules written to `rules`, or None if they do not
+/// fit.
+pub fn compute_rules<F: Fn(u8) -> u8>(forced: F, rules: &mut [FilterRule]) -> Option<usize> {
+    let mut count = 0;
+    add_rules(&forced, 0, 8, rules, &mut count)?;
+    Some(count)
+}
+
+// Adds the rules for the 2^`bits` op codes starting at `base`.
+fn add_rules<F: Fn(u8) -> u8>(forced: &F, base: u8, bits: u32,
+                              rules: &mut [FilterRule], count: &mut usize) -> Option<()> {
+    let last = (base as u32 + (1 << bits) - 1) as u8;
+    let forced_op_code = forced(base);
+    if (base..=last).all(|op_code| forced(op_code) == forced_op_code) {
+        *rules.get_mut(*count)? = FilterRule {
+            op_code: base,
+            mask: !(last - base),
+            forced_op_code: forced_op_code,
+        };
+        *count += 1;
+        return Some(());
+    }
+
+    add_rules(forced, base, bits - 1, rules, count)?;
+    add_rules(forced, base | (1 << (bits - 1)), bits - 1, rules, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_rules(forced: impl Fn(u8) -> u8) -> usize {
+        let mut rules = [FilterRule::default(); FILTER_RULES];
+        let count = compute_rules(&forced, &mut rules).expect("rules do not fit");
+        for op_code in 0..=255 {
+            let mut matching = rules[..count].iter().filter(|rule| rule.matches(op_code));
+            let rule = matching.next().expect("no rule matches");
+            assert_eq!(rule.forced_op_code, forced(op_code));
+            assert!(matching.next().is_none(), "rules overlap at {:#x}", op_code);
+        }
+        count
+    }
+
+    #[test]
+    fn default_filter_fits() {
+        let default = PassthroughFilterAction::Default;
+        assert_eq!(check_rules(|op_code| forced_op_code(op_code, default, false, false, false)), 6);
+        assert_eq!(check_rules(|op_code| forced_op_code(op_code, default, false, false, true)), 12);
+        assert_eq!(check_rules(|op_code| forced_op_code(op_code, default, true, false, true)), 13);
+        assert_eq!(check_rules(|op_code| forced_op_code(op_code, default, true, true, true)), 14);
+    }
+
+    #[test]
+    fn overrides_split_rules() {
+        let page_program = OpCode::PageProgram as u8;
+        let count = check_rules(|op_code| {
+            let action = if op_code == page_program {
+                PassthroughFilterAction::Pass
+            } else {
+                PassthroughFilterAction::Default
+            };
+            forced_op_code(op_code, action, true, false, true)
+        });
+        assert_eq!(count, 16);
+
+        let mut rules = [FilterRule::default(); FILTER_RULES];
+        assert_eq!(compute_rules(|op_code| op_code, &mut rules), None);
+    }
+}
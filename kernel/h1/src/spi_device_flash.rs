@@ -0,0 +1,456 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Handles program and erase commands from the SPI host in the kernel.
+//!
+//! By default, the app gets every PageProgram and erase command, writes the
+//! H1 flash itself and then clears BUSY, so the SPI host polls BUSY for the
+//! whole round trip through userspace. `FlashEmulation` sits between the SPI
+//! device and its client. Once switched to `HandlerMode::KernelSpace`, it
+//! applies PageProgram, SectorErase, BlockErase32KB and BlockErase64KB
+//! commands for its flash region straight to the H1 flash and clears WEL and
+//! BUSY when the flash is done. All other commands still go to the client.
+//!
+//! Commands are checked against the access map and need WEL set, like those
+//! the client handles. Writes that the GLOBALSEC flash regions do not allow
+//! (e.g. after lockdown) are dropped the way a real flash drops writes to a
+//! protected block.
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::ReturnCode;
+
+use spiutils::driver::firmware::SegmentInfo;
+use spiutils::driver::spi_device::AccessMetrics;
+use spiutils::driver::spi_device::AccessRegion;
+use spiutils::driver::spi_device::AddressConfig;
+use spiutils::driver::spi_device::DeniedAccessResponse;
+use spiutils::driver::spi_device::EmulatedOperation;
+use spiutils::driver::spi_device::HandlerMode;
+use spiutils::driver::spi_device::PassthroughFilterAction;
+use spiutils::protocol::flash::AddressMode;
+use spiutils::protocol::flash::OpCode;
+use spiutils::protocol::wire::WireEnum;
+
+use crate::hil::flash::h1_hw::H1_FLASH_PAGE_SIZE;
+use crate::hil::flash::{Client, Flash};
+use crate::hil::globalsec::GlobalSec;
+use crate::hil::spi_device::{SpiDevice, SpiDeviceClient};
+
+/// Size of a page of the emulated flash. PageProgram does not cross pages.
+pub const PROGRAM_PAGE_SIZE: usize = 256;
+
+/// Size of the buffer for a command from the SPI host. It must hold the op
+/// code, the address and a full page of data.
+pub const COMMAND_BUFFER_LEN: usize = 512;
+
+/// Size of the write buffer in words, which is the largest write the flash
+/// driver accepts.
+pub const WRITE_BUFFER_WORDS: usize = 32;
+
+const WRITE_BUFFER_BYTES: usize = 4 * WRITE_BUFFER_WORDS;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    // Writing the command bytes from `data_pos` to `data_end` to flash,
+    // starting at `offset` bytes from the start of flash.
+    Programming { offset: usize, data_pos: usize, data_end: usize },
+    // Erasing H1 flash pages from `next_page` up to `end_page`.
+    Erasing { next_page: usize, end_page: usize },
+}
+
+pub struct FlashEmulation<'a, F: Flash<'a>> {
+    device: &'a dyn SpiDevice,
+    flash: &'a F,
+    globalsec: &'a dyn GlobalSec,
+    client: OptionalCell<&'static dyn SpiDeviceClient>,
+    // The part of the H1 flash that the SPI host may program and erase.
+    region: SegmentInfo,
+    handler_mode: Cell<HandlerMode>,
+    // The address on the SPI bus that `region` starts at.
+    spi_base: Cell<u32>,
+    write_buffer: TakeCell<'a, [u32]>,
+    command: TakeCell<'a, [u8]>,
+    // Length of the command in `command` that is waiting for the client.
+    held_len: Cell<Option<usize>>,
+    state: Cell<State>,
+}
+
+impl<'a, F: Flash<'a>> FlashEmulation<'a, F> {
+    /// Wraps `device`, which must have this as its client, and `flash`,
+    /// which must have this as its client too. Program and erase commands
+    /// are left to the client until `set_program_erase_handling` is called.
+    pub fn new(device: &'a dyn SpiDevice,
+               flash: &'a F,
+               globalsec: &'a dyn GlobalSec,
+               region: SegmentInfo,
+               write_buffer: &'a mut [u32; WRITE_BUFFER_WORDS],
+               command: &'a mut [u8; COMMAND_BUFFER_LEN]) -> FlashEmulation<'a, F> {
+        FlashEmulation {
+            device: device,
+            flash: flash,
+            globalsec: globalsec,
+            client: OptionalCell::empty(),
+            region: region,
+            handler_mode: Cell::new(HandlerMode::Disabled),
+            spi_base: Cell::new(0),
+            write_buffer: TakeCell::new(write_buffer),
+            command: TakeCell::new(command),
+            held_len: Cell::new(None),
+            state: Cell::new(State::Idle),
+        }
+    }
+
+    // Returns the op code and address of a program or erase command, and
+    // where its data starts.
+    fn parse_command(&self, command: &[u8]) -> Option<(OpCode, u32, usize)> {
+        let op_code = command.get(0).and_then(|op_code| OpCode::from_wire_value(*op_code))?;
+        match op_code {
+            OpCode::PageProgram | OpCode::SectorErase |
+            OpCode::BlockErase32KB | OpCode::BlockErase64KB => {}
+            _ => return None,
+        }
+
+        let address_len = match self.device.get_address_mode() {
+            AddressMode::ThreeByte => 3,
+            AddressMode::FourByte => 4,
+        };
+        let address = command.get(1..1 + address_len)?.iter()
+            .fold(0u32, |address, byte| (address << 8) | (*byte as u32));
+        Some((op_code, address, 1 + address_len))
+    }
+
+    // Handles the `len` byte command in `command` if it programs or erases
+    // the region. Returns false if the client has to handle it.
+    fn handle_command(&self, len: usize, is_write_enabled: bool) -> bool {
+        let (op_code, address, data_start) =
+            match self.command.map_or(None, |command| self.parse_command(&command[..len])) {
+                Some(parsed) => parsed,
+                None => return false,
+            };
+        let region_offset = match address.checked_sub(self.spi_base.get()) {
+            Some(offset) if offset < self.region.size => offset as usize,
+            _ => return false,
+        };
+
+        let (start, size) = match op_code {
+            OpCode::PageProgram => {
+                // A real flash wraps around to the start of the page. Hosts
+                // don't rely on that, so leave it to the client.
+                let size = len - data_start;
+                if region_offset % PROGRAM_PAGE_SIZE + size > PROGRAM_PAGE_SIZE {
+                    return false;
+                }
+                (region_offset, size)
+            }
+            _ => {
+                let size = match op_code {
+                    OpCode::SectorErase => 4 * 1024,
+                    OpCode::BlockErase32KB => 32 * 1024,
+                    _ => 64 * 1024,
+                };
+                (region_offset - region_offset % size, size)
+            }
+        };
+        let offset = self.region.address as usize + start;
+        if start + size > self.region.size as usize ||
+            (op_code != OpCode::PageProgram && offset % H1_FLASH_PAGE_SIZE != 0) {
+            return false;
+        }
+
+        if !self.device.check_access(Some(address), true) {
+            // The device already answered the denied command.
+            return true;
+        }
+        if !is_write_enabled || size == 0 ||
+            !self.globalsec.is_flash_writable(offset as u32, size as u32) {
+            self.finish();
+            return true;
+        }
+
+        let rcode = match op_code {
+            OpCode::PageProgram => {
+                self.state.set(State::Programming {
+                    offset: offset,
+                    data_pos: data_start,
+                    data_end: len,
+                });
+                self.program_next()
+            }
+            _ => {
+                self.state.set(State::Erasing {
+                    next_page: offset / H1_FLASH_PAGE_SIZE,
+                    end_page: (offset + size) / H1_FLASH_PAGE_SIZE,
+                });
+                self.erase_next()
+            }
+        };
+        if rcode != ReturnCode::SUCCESS {
+            self.finish();
+        }
+        true
+    }
+
+    // Starts writing the part of the program in progress that falls into
+    // the next write buffer sized block of flash.
+    fn program_next(&self) -> ReturnCode {
+        let (offset, data_pos, data_end) = match self.state.get() {
+            State::Programming { offset, data_pos, data_end } => (offset, data_pos, data_end),
+            _ => return ReturnCode::FAIL,
+        };
+        let block_start = offset - offset % WRITE_BUFFER_BYTES;
+        let len = min(data_end - data_pos, block_start + WRITE_BUFFER_BYTES - offset);
+        let buffer = match self.write_buffer.take() {
+            Some(buffer) => buffer,
+            None => return ReturnCode::EBUSY,
+        };
+
+        // Flash fails writes that set a cleared bit again, so start from
+        // what the block holds now.
+        for index in 0..buffer.len() {
+            match self.flash.read(block_start / 4 + index) {
+                ReturnCode::SuccessWithValue { value } => buffer[index] = value as u32,
+                rcode => {
+                    self.write_buffer.replace(buffer);
+                    return rcode;
+                }
+            }
+        }
+        self.command.map(|command| {
+            program_bytes(buffer, offset - block_start, &command[data_pos..data_pos + len]);
+        });
+
+        self.state.set(State::Programming {
+            offset: offset + len,
+            data_pos: data_pos + len,
+            data_end: data_end,
+        });
+        let (rcode, buffer) = self.flash.write(block_start / 4, buffer);
+        if let Some(buffer) = buffer {
+            self.write_buffer.replace(buffer);
+        }
+        rcode
+    }
+
+    // Starts erasing the next page of the erase in progress.
+    fn erase_next(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Erasing { next_page, end_page } => {
+                self.state.set(State::Erasing { next_page: next_page + 1, end_page: end_page });
+                self.flash.erase(next_page)
+            }
+            _ => ReturnCode::FAIL,
+        }
+    }
+
+    // Continues the operation in progress once the flash finished a step
+    // with `rcode`.
+    fn step_done(&self, rcode: ReturnCode) {
+        let done = match self.state.get() {
+            State::Idle => return,
+            _ if rcode != ReturnCode::SUCCESS => true,
+            State::Programming { data_pos, data_end, .. } if data_pos < data_end =>
+                self.program_next() != ReturnCode::SUCCESS,
+            State::Erasing { next_page, end_page } if next_page < end_page =>
+                self.erase_next() != ReturnCode::SUCCESS,
+            _ => true,
+        };
+        if done {
+            self.finish();
+            // Deliver what the SPI host sent in the meantime.
+            self.device.poll_data_available();
+        }
+    }
+
+    // Ends the command the way a real flash does.
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        self.device.clear_write_enable();
+        self.device.clear_busy();
+    }
+}
+
+// Clears the bits in `block` that are clear in `data`, which is placed at
+// byte `position` of the block.
+fn program_bytes(block: &mut [u32], position: usize, data: &[u8]) {
+    for (index, byte) in data.iter().enumerate() {
+        let shift = 8 * ((position + index) % 4);
+        block[(position + index) / 4] &= !(0xff << shift) | ((*byte as u32) << shift);
+    }
+}
+
+impl<'a, F: Flash<'a>> Client<'a> for FlashEmulation<'a, F> {
+    fn erase_done(&self, rcode: ReturnCode) {
+        self.step_done(rcode);
+    }
+
+    fn write_done(&self, data: &'a mut [u32], rcode: ReturnCode) {
+        self.write_buffer.replace(data);
+        self.step_done(rcode);
+    }
+}
+
+impl<'a, F: Flash<'a>> SpiDeviceClient for FlashEmulation<'a, F> {
+    fn data_available(&self, is_busy: bool, is_write_enabled: bool) -> bool {
+        if self.state.get() != State::Idle {
+            // Keep commands in order: hold them until the flash is done.
+            return false;
+        }
+
+        if self.held_len.get().is_none() && self.handler_mode.get() == HandlerMode::KernelSpace {
+            let len = self.command.map_or(0, |command| self.device.get_received_data(command));
+            if self.handle_command(len, is_write_enabled) {
+                return true;
+            }
+            self.held_len.set(Some(len));
+        }
+
+        let accepted = self.client.map_or(true, |client| client.data_available(is_busy, is_write_enabled));
+        if accepted {
+            self.held_len.set(None);
+        }
+        accepted
+    }
+
+    fn mailbox_read(&self) {
+        self.client.map(|client| client.mailbox_read());
+    }
+}
+
+impl<'a, F: Flash<'a>> SpiDevice for FlashEmulation<'a, F> {
+    fn set_client(&self, client: Option<&'static dyn SpiDeviceClient>) {
+        match client {
+            None => { self.client.clear(); }
+            Some(cl) => { self.client.set(cl); }
+        }
+    }
+
+    fn configure_addresses(&self, config: AddressConfig) -> ReturnCode {
+        self.device.configure_addresses(config)
+    }
+
+    fn set_address_mode(&self, address_mode: AddressMode) {
+        self.device.set_address_mode(address_mode)
+    }
+
+    fn get_address_mode(&self) -> AddressMode {
+        self.device.get_address_mode()
+    }
+
+    fn get_read_capabilities(&self) -> u32 {
+        self.device.get_read_capabilities()
+    }
+
+    fn get_received_data(&self, read_buffer: &mut [u8]) -> usize {
+        match self.held_len.get() {
+            // The command was read from the device already.
+            Some(len) => self.command.map_or(0, |command| {
+                let length = min(len, read_buffer.len());
+                read_buffer[..length].copy_from_slice(&command[..length]);
+                length
+            }),
+            None => self.device.get_received_data(read_buffer),
+        }
+    }
+
+    fn poll_data_available(&self) -> bool {
+        self.device.poll_data_available()
+    }
+
+    fn put_send_data(&self, write_data: &[u8]) -> ReturnCode {
+        self.device.put_send_data(write_data)
+    }
+
+    fn put_mailbox_data(&self, write_data: &[u8]) -> ReturnCode {
+        self.device.put_mailbox_data(write_data)
+    }
+
+    fn set_info_block(&self, data: &[u8]) -> ReturnCode {
+        self.device.set_info_block(data)
+    }
+
+    fn set_status(&self, status: u8) {
+        self.device.set_status(status)
+    }
+
+    fn get_status(&self) -> u8 {
+        self.device.get_status()
+    }
+
+    fn clear_busy(&self) {
+        self.device.clear_busy()
+    }
+
+    fn is_write_enable_set(&self) -> bool {
+        self.device.is_write_enable_set()
+    }
+
+    fn clear_write_enable(&self) {
+        self.device.clear_write_enable()
+    }
+
+    fn set_jedec_id(&self, data: &[u8]) -> ReturnCode {
+        self.device.set_jedec_id(data)
+    }
+
+    fn set_sfdp(&self, data: &[u8]) -> ReturnCode {
+        self.device.set_sfdp(data)
+    }
+
+    fn set_access_regions(&self, regions: &[AccessRegion]) -> ReturnCode {
+        self.device.set_access_regions(regions)
+    }
+
+    fn set_denied_access_response(&self, response: DeniedAccessResponse) {
+        self.device.set_denied_access_response(response)
+    }
+
+    fn check_access(&self, address: Option<u32>, is_write: bool) -> bool {
+        self.device.check_access(address, is_write)
+    }
+
+    fn set_passthrough_filter(&self, op_code: u8, action: PassthroughFilterAction) -> ReturnCode {
+        self.device.set_passthrough_filter(op_code, action)
+    }
+
+    fn get_access_metrics(&self) -> AccessMetrics {
+        self.device.get_access_metrics()
+    }
+
+    fn set_emulated_latency(&self, operation: EmulatedOperation, latency_us: u32) -> ReturnCode {
+        self.device.set_emulated_latency(operation, latency_us)
+    }
+
+    fn set_program_erase_handling(&self, handler_mode: HandlerMode, spi_base: u32) -> ReturnCode {
+        self.handler_mode.set(handler_mode);
+        self.spi_base.set(spi_base);
+        ReturnCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_bytes_only_clears_bits() {
+        let mut block = [0xffff_ffff, 0xffff_00ff];
+        program_bytes(&mut block, 3, &[0x12, 0x0f, 0xf0]);
+        assert_eq!(block, [0x12ff_ffff, 0xffff_000f]);
+    }
+}
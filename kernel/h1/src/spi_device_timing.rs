@@ -0,0 +1,226 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Emulates the program and erase timing of a real SPI flash.
+//!
+//! The handler usually finishes a program or erase long before a real flash
+//! would, so the SPI host sees the BUSY bit clear almost at once. That hides
+//! bugs in host drivers that mishandle a flash that stays busy.
+//! `EmulatedTiming` sits between the SPI device and its client and keeps the
+//! BUSY bit set for at least the configured latency after such a command is
+//! received: if the handler clears BUSY earlier, clearing it is deferred
+//! until the latency has elapsed.
+//!
+//! The latency is measured from when the client reads the command, which is
+//! later than its arrival if the client held the transaction.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{Alarm, AlarmClient, Frequency};
+use kernel::ReturnCode;
+
+use spiutils::driver::spi_device::AccessMetrics;
+use spiutils::driver::spi_device::AccessRegion;
+use spiutils::driver::spi_device::AddressConfig;
+use spiutils::driver::spi_device::DeniedAccessResponse;
+use spiutils::driver::spi_device::EmulatedOperation;
+use spiutils::driver::spi_device::EMULATED_OPERATIONS;
+use spiutils::driver::spi_device::HandlerMode;
+use spiutils::driver::spi_device::PassthroughFilterAction;
+use spiutils::protocol::flash::AddressMode;
+use spiutils::protocol::flash::OpCode;
+use spiutils::protocol::wire::WireEnum;
+
+use crate::hil::spi_device::{SpiDevice, SpiDeviceClient};
+
+/// Longest latency that can be emulated, so that the alarm cannot wrap.
+pub const MAX_EMULATED_LATENCY_US: u32 = 60_000_000;
+
+pub struct EmulatedTiming<'a, A: Alarm<'a>> {
+    device: &'a dyn SpiDevice,
+    alarm: &'a A,
+    client: OptionalCell<&'static dyn SpiDeviceClient>,
+    latencies_us: [Cell<u32>; EMULATED_OPERATIONS],
+    // Whether an emulated operation is in progress.
+    in_progress: Cell<bool>,
+    // Whether the client cleared BUSY during the operation.
+    clear_busy_pending: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>> EmulatedTiming<'a, A> {
+    /// Wraps `device`, which must have this as its client. All latencies
+    /// start at 0, so timing is not emulated until configured.
+    pub fn new(device: &'a dyn SpiDevice, alarm: &'a A) -> EmulatedTiming<'a, A> {
+        EmulatedTiming {
+            device: device,
+            alarm: alarm,
+            client: OptionalCell::empty(),
+            latencies_us: [Cell::new(0), Cell::new(0), Cell::new(0), Cell::new(0)],
+            in_progress: Cell::new(false),
+            clear_busy_pending: Cell::new(false),
+        }
+    }
+
+    // Starts emulating the operation performed by the command in `data`, if
+    // it has a latency configured.
+    fn start(&self, data: &[u8]) {
+        let operation = match data.get(0)
+            .and_then(|op_code| OpCode::from_wire_value(*op_code))
+            .and_then(EmulatedOperation::from_op_code) {
+            Some(operation) => operation,
+            None => return,
+        };
+        let latency_us = self.latencies_us[operation as usize].get();
+        if latency_us == 0 {
+            return;
+        }
+
+        let ticks = (A::Frequency::frequency() as u64 * latency_us as u64 / 1_000_000) as u32;
+        self.in_progress.set(true);
+        self.clear_busy_pending.set(false);
+        self.alarm.set_alarm(self.alarm.now(), ticks.into());
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for EmulatedTiming<'a, A> {
+    fn alarm(&self) {
+        self.in_progress.set(false);
+        if self.clear_busy_pending.take() {
+            self.device.clear_busy();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> SpiDeviceClient for EmulatedTiming<'a, A> {
+    fn data_available(&self, is_busy: bool, is_write_enabled: bool) -> bool {
+        self.client.map_or(true, |client| client.data_available(is_busy, is_write_enabled))
+    }
+
+    fn mailbox_read(&self) {
+        self.client.map(|client| client.mailbox_read());
+    }
+}
+
+impl<'a, A: Alarm<'a>> SpiDevice for EmulatedTiming<'a, A> {
+    fn set_client(&self, client: Option<&'static dyn SpiDeviceClient>) {
+        match client {
+            None => { self.client.clear(); }
+            Some(cl) => { self.client.set(cl); }
+        }
+    }
+
+    fn configure_addresses(&self, config: AddressConfig) -> ReturnCode {
+        self.device.configure_addresses(config)
+    }
+
+    fn set_address_mode(&self, address_mode: AddressMode) {
+        self.device.set_address_mode(address_mode)
+    }
+
+    fn get_address_mode(&self) -> AddressMode {
+        self.device.get_address_mode()
+    }
+
+    fn get_read_capabilities(&self) -> u32 {
+        self.device.get_read_capabilities()
+    }
+
+    fn get_received_data(&self, read_buffer: &mut [u8]) -> usize {
+        let length = self.device.get_received_data(read_buffer);
+        self.start(&read_buffer[..length]);
+        length
+    }
+
+    fn poll_data_available(&self) -> bool {
+        self.device.poll_data_available()
+    }
+
+    fn put_send_data(&self, write_data: &[u8]) -> ReturnCode {
+        self.device.put_send_data(write_data)
+    }
+
+    fn put_mailbox_data(&self, write_data: &[u8]) -> ReturnCode {
+        self.device.put_mailbox_data(write_data)
+    }
+
+    fn set_info_block(&self, data: &[u8]) -> ReturnCode {
+        self.device.set_info_block(data)
+    }
+
+    fn set_status(&self, status: u8) {
+        self.device.set_status(status)
+    }
+
+    fn get_status(&self) -> u8 {
+        self.device.get_status()
+    }
+
+    fn clear_busy(&self) {
+        if self.in_progress.get() {
+            self.clear_busy_pending.set(true);
+        } else {
+            self.device.clear_busy();
+        }
+    }
+
+    fn is_write_enable_set(&self) -> bool {
+        self.device.is_write_enable_set()
+    }
+
+    fn clear_write_enable(&self) {
+        self.device.clear_write_enable()
+    }
+
+    fn set_jedec_id(&self, data: &[u8]) -> ReturnCode {
+        self.device.set_jedec_id(data)
+    }
+
+    fn set_sfdp(&self, data: &[u8]) -> ReturnCode {
+        self.device.set_sfdp(data)
+    }
+
+    fn set_access_regions(&self, regions: &[AccessRegion]) -> ReturnCode {
+        self.device.set_access_regions(regions)
+    }
+
+    fn set_denied_access_response(&self, response: DeniedAccessResponse) {
+        self.device.set_denied_access_response(response)
+    }
+
+    fn check_access(&self, address: Option<u32>, is_write: bool) -> bool {
+        self.device.check_access(address, is_write)
+    }
+
+    fn set_passthrough_filter(&self, op_code: u8, action: PassthroughFilterAction) -> ReturnCode {
+        self.device.set_passthrough_filter(op_code, action)
+    }
+
+    fn get_access_metrics(&self) -> AccessMetrics {
+        self.device.get_access_metrics()
+    }
+
+    fn set_emulated_latency(&self, operation: EmulatedOperation, latency_us: u32) -> ReturnCode {
+        if latency_us > MAX_EMULATED_LATENCY_US {
+            return ReturnCode::EINVAL;
+        }
+        self.latencies_us[operation as usize].set(latency_us);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_program_erase_handling(&self, handler_mode: HandlerMode, spi_base: u32) -> ReturnCode {
+        self.device.set_program_erase_handling(handler_mode, spi_base)
+    }
+}
@@ -0,0 +1,412 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Storage backend on a region of the external SPI flash.
+//!
+//! Each 4 KiB flash sector is one block. The last `MAC_LEN` bytes of a
+//! sector hold an HMAC-SHA256 over the sector's address and its data, so a
+//! block that was erased, torn or written by anyone without the key reads
+//! back as FAIL. The MAC does not tell apart versions of the same block, so
+//! stores must still detect rollback of their records.
+//!
+//! The external flash sits behind SPI passthrough, which is turned off for
+//! the duration of each operation. The backend holds the SPI host lease
+//! while it works. It must be the SPI host's client, and passes the
+//! completions of app transactions on to the apps' client. The hardware
+//! polls the flash's status register after each transaction, so a
+//! transaction completes only once a program or erase has finished.
+//!
+//! The HMAC is computed with the SHA engine used synchronously. The engine
+//! comes from the `ShaArbiter`, so operations complete with EBUSY while an
+//! app has a digest in progress.
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::spi::{SpiMaster, SpiMasterClient};
+use kernel::ReturnCode;
+
+use crate::crypto::sha::ShaArbiter;
+use crate::hil::digest::DigestEngine;
+use crate::hil::spi_host::SpiHost;
+use crate::hil::storage::{Client, Storage};
+use crate::spi_host_lease::{Owner, SpiHostLease};
+
+/// The erase unit of the external flash, in bytes.
+pub const SECTOR_SIZE: usize = 4096;
+
+/// The length of the MAC at the end of each sector, in bytes.
+pub const MAC_LEN: usize = 32;
+
+/// The usable size of a block, in bytes.
+pub const BLOCK_SIZE: usize = SECTOR_SIZE - MAC_LEN;
+
+/// Data bytes per transaction. Divides the 256 byte program page, so that
+/// no program crosses a page boundary.
+const CHUNK_LEN: usize = 64;
+
+/// Opcode and 24-bit address.
+const HEADER_LEN: usize = 4;
+
+/// The lease is renewed with every transaction.
+const LEASE_MS: u32 = 1000;
+
+const OPCODE_PAGE_PROGRAM: u8 = 0x02;
+const OPCODE_READ: u8 = 0x03;
+const OPCODE_WRITE_ENABLE: u8 = 0x06;
+const OPCODE_SECTOR_ERASE: u8 = 0x20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    Reading,
+    EraseEnabled,
+    Erasing,
+    ProgramEnabled,
+    Programming,
+}
+
+pub struct SpiFlashStorage<'a, S: SpiMaster> {
+    spi: &'a S,
+    spi_host: &'a dyn SpiHost,
+    lease: &'a SpiHostLease<'a>,
+    app_client: &'static dyn SpiMasterClient,
+    sha: &'a ShaArbiter<'a>,
+    key: [u8; MAC_LEN],
+    base_address: usize,
+    sector_count: usize,
+    passthrough_when_idle: Cell<bool>,
+    client: OptionalCell<&'a dyn Client<'a>>,
+    buffer: TakeCell<'a, [u8]>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    sector: Cell<usize>,
+    // Bytes of the sector transferred so far.
+    position: Cell<usize>,
+    mac: Cell<[u8; MAC_LEN]>,
+}
+
+impl<'a, S: SpiMaster> SpiFlashStorage<'a, S> {
+    /// Uses `sector_count` sectors starting at `base_address` of the flash
+    /// on `spi`, which must be the SPI host itself rather than the apps'
+    /// `LeasedSpiMaster`. Set the backend as the SPI host's client after
+    /// `app_client`, which receives the completions of app transactions.
+    /// `key` is the HMAC key protecting the blocks; boards derive
+    /// it from a kernel secret. The transfer buffers must hold
+    /// `spi_host::FIFO_SIZE` bytes.
+    pub fn new(spi: &'a S,
+               spi_host: &'a dyn SpiHost,
+               lease: &'a SpiHostLease<'a>,
+               app_client: &'static dyn SpiMasterClient,
+               sha: &'a ShaArbiter<'a>,
+               key: [u8; MAC_LEN],
+               base_address: usize,
+               sector_count: usize,
+               tx_buffer: &'static mut [u8],
+               rx_buffer: &'static mut [u8]) -> SpiFlashStorage<'a, S> {
+        SpiFlashStorage {
+            spi: spi,
+            spi_host: spi_host,
+            lease: lease,
+            app_client: app_client,
+            sha: sha,
+            key: key,
+            base_address: base_address,
+            sector_count: sector_count,
+            passthrough_when_idle: Cell::new(false),
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            state: Cell::new(State::Idle),
+            sector: Cell::new(0),
+            position: Cell::new(0),
+            mac: Cell::new([0; MAC_LEN]),
+        }
+    }
+
+    /// Sets whether SPI passthrough is turned back on after each operation.
+    pub fn set_passthrough_when_idle(&self, enabled: bool) {
+        self.passthrough_when_idle.set(enabled);
+    }
+
+    fn sector_address(&self) -> usize {
+        self.base_address + self.sector.get() * SECTOR_SIZE
+    }
+
+    // Computes the MAC of the sector's data in the client's buffer.
+    fn compute_mac(&self, mac: &mut [u8; MAC_LEN]) -> ReturnCode {
+        let sha = match self.sha.engine() {
+            Ok(sha) => sha,
+            Err(rcode) => return rcode,
+        };
+        let address = (self.sector_address() as u32).to_be_bytes();
+        let result = self.buffer.map(|buffer| {
+            sha.initialize_hmac(&self.key)
+                .and_then(|_| sha.update(&address))
+                .and_then(|_| sha.update(buffer))
+                .and_then(|_| sha.finalize_hmac(mac))
+        });
+        match result {
+            Some(Ok(_)) => ReturnCode::SUCCESS,
+            Some(Err(_)) => ReturnCode::FAIL,
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    fn check_request(&self, block: usize, buffer: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            ReturnCode::EBUSY
+        } else if block >= self.sector_count || buffer.len() != BLOCK_SIZE {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    // Takes the SPI host for an operation on `block`.
+    fn begin(&self, block: usize) -> ReturnCode {
+        // An app transaction in flight would complete as one of ours.
+        if self.spi.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        let rcode = self.lease.acquire(Owner::Kernel, LEASE_MS);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        self.spi_host.spi_device_spi_host_passthrough(false);
+        self.spi_host.wait_busy_clear_in_transactions(true);
+        self.sector.set(block);
+        self.position.set(0);
+        ReturnCode::SUCCESS
+    }
+
+    fn end(&self) {
+        self.spi_host.wait_busy_clear_in_transactions(false);
+        self.spi_host.spi_device_spi_host_passthrough(self.passthrough_when_idle.get());
+        self.lease.release(Owner::Kernel);
+    }
+
+    // Sends `opcode` with an address in the sector and `data_len` bytes of
+    // data, which `fill` writes into the transmit buffer.
+    fn transfer<F: FnOnce(&mut [u8])>(&self, state: State, opcode: u8, with_address: bool,
+                                      data_len: usize, fill: F) -> ReturnCode {
+        if self.lease.acquire(Owner::Kernel, LEASE_MS) != ReturnCode::SUCCESS {
+            return ReturnCode::ECANCEL;
+        }
+        let (tx_buffer, rx_buffer) = match (self.tx_buffer.take(), self.rx_buffer.take()) {
+            (Some(tx_buffer), Some(rx_buffer)) => (tx_buffer, rx_buffer),
+            (tx_buffer, rx_buffer) => {
+                self.tx_buffer.put(tx_buffer);
+                self.rx_buffer.put(rx_buffer);
+                return ReturnCode::ENOMEM;
+            }
+        };
+        tx_buffer[0] = opcode;
+        let mut len = 1;
+        if with_address {
+            let address = (self.sector_address() + self.position.get()) as u32;
+            tx_buffer[1..HEADER_LEN].copy_from_slice(&address.to_be_bytes()[1..]);
+            len = HEADER_LEN;
+        }
+        fill(&mut tx_buffer[len..len + data_len]);
+        len += data_len;
+
+        self.state.set(state);
+        self.spi.read_write_bytes(tx_buffer, Some(rx_buffer), len)
+    }
+
+    fn read_chunk(&self) -> ReturnCode {
+        self.transfer(State::Reading, OPCODE_READ, true, CHUNK_LEN, |data| {
+            for byte in data.iter_mut() {
+                *byte = 0xff;
+            }
+        })
+    }
+
+    fn program_chunk(&self) -> ReturnCode {
+        let position = self.position.get();
+        let mac = self.mac.get();
+        let buffer = &self.buffer;
+        self.transfer(State::Programming, OPCODE_PAGE_PROGRAM, true, CHUNK_LEN, |data| {
+            buffer.map(|buffer| {
+                for (i, byte) in data.iter_mut().enumerate() {
+                    let offset = position + i;
+                    *byte = if offset < BLOCK_SIZE {
+                        buffer[offset]
+                    } else {
+                        mac[offset - BLOCK_SIZE]
+                    };
+                }
+            });
+        })
+    }
+
+    fn write_enable(&self, state: State) -> ReturnCode {
+        self.transfer(state, OPCODE_WRITE_ENABLE, false, 0, |_| {})
+    }
+
+    // Copies the data of a completed read out of `rx_buffer`.
+    fn store_chunk(&self, rx_buffer: &[u8]) {
+        let position = self.position.get();
+        let mut mac = self.mac.get();
+        self.buffer.map(|buffer| {
+            for (i, byte) in rx_buffer[HEADER_LEN..HEADER_LEN + CHUNK_LEN].iter().enumerate() {
+                let offset = position + i;
+                if offset < BLOCK_SIZE {
+                    buffer[offset] = *byte;
+                } else {
+                    mac[offset - BLOCK_SIZE] = *byte;
+                }
+            }
+        });
+        self.mac.set(mac);
+    }
+
+    // Advances the state machine after a transaction completed.
+    fn step(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Reading => {
+                self.position.set(self.position.get() + CHUNK_LEN);
+                if self.position.get() < SECTOR_SIZE {
+                    return self.read_chunk();
+                }
+                let mut mac = [0; MAC_LEN];
+                let mut rcode = self.compute_mac(&mut mac);
+                let diff = mac.iter()
+                    .zip(self.mac.get().iter())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b));
+                if rcode == ReturnCode::SUCCESS && diff != 0 {
+                    rcode = ReturnCode::FAIL;
+                }
+                self.finish(rcode);
+                ReturnCode::SUCCESS
+            },
+            State::EraseEnabled => {
+                self.transfer(State::Erasing, OPCODE_SECTOR_ERASE, true, 0, |_| {})
+            },
+            State::Erasing => self.write_enable(State::ProgramEnabled),
+            State::ProgramEnabled => self.program_chunk(),
+            State::Programming => {
+                self.position.set(self.position.get() + CHUNK_LEN);
+                if self.position.get() < SECTOR_SIZE {
+                    return self.write_enable(State::ProgramEnabled);
+                }
+                self.finish(ReturnCode::SUCCESS);
+                ReturnCode::SUCCESS
+            },
+            State::Idle => ReturnCode::SUCCESS,
+        }
+    }
+
+    fn finish(&self, rcode: ReturnCode) {
+        let state = self.state.get();
+        self.state.set(State::Idle);
+        self.mac.set([0; MAC_LEN]);
+        self.end();
+        if let Some(buffer) = self.buffer.take() {
+            self.client.map(|client| {
+                if state == State::Reading {
+                    client.read_done(buffer, rcode);
+                } else {
+                    client.write_done(buffer, rcode);
+                }
+            });
+        }
+    }
+}
+
+impl<'a, S: SpiMaster> Storage<'a> for SpiFlashStorage<'a, S> {
+    fn set_client(&self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> usize {
+        self.sector_count
+    }
+
+    fn read_block(&self, block: usize, buffer: &'a mut [u8])
+                  -> (ReturnCode, Option<&'a mut [u8]>) {
+        let mut rcode = self.check_request(block, buffer);
+        if rcode == ReturnCode::SUCCESS {
+            rcode = self.begin(block);
+        }
+        if rcode != ReturnCode::SUCCESS {
+            return (rcode, Some(buffer));
+        }
+        self.buffer.replace(buffer);
+        let rcode = self.read_chunk();
+        if rcode != ReturnCode::SUCCESS {
+            self.state.set(State::Idle);
+            self.end();
+            return (rcode, self.buffer.take());
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn write_block(&self, block: usize, buffer: &'a mut [u8])
+                   -> (ReturnCode, Option<&'a mut [u8]>) {
+        let mut rcode = self.check_request(block, buffer);
+        if rcode == ReturnCode::SUCCESS {
+            rcode = self.begin(block);
+        }
+        if rcode != ReturnCode::SUCCESS {
+            return (rcode, Some(buffer));
+        }
+        self.buffer.replace(buffer);
+        let mut mac = [0; MAC_LEN];
+        let mut rcode = self.compute_mac(&mut mac);
+        self.mac.set(mac);
+        if rcode == ReturnCode::SUCCESS {
+            rcode = self.write_enable(State::EraseEnabled);
+        }
+        if rcode != ReturnCode::SUCCESS {
+            self.state.set(State::Idle);
+            self.mac.set([0; MAC_LEN]);
+            self.end();
+            return (rcode, self.buffer.take());
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+impl<'a, S: SpiMaster> SpiMasterClient for SpiFlashStorage<'a, S> {
+    fn read_write_done(&self,
+                       write_buffer: &'static mut [u8],
+                       read_buffer: Option<&'static mut [u8]>,
+                       len: usize) {
+        if self.state.get() == State::Idle {
+            self.app_client.read_write_done(write_buffer, read_buffer, len);
+            return;
+        }
+        if self.state.get() == State::Reading {
+            if let Some(ref rx_buffer) = read_buffer {
+                self.store_chunk(rx_buffer);
+            }
+        }
+        self.tx_buffer.replace(write_buffer);
+        self.rx_buffer.put(read_buffer);
+
+        let rcode = self.step();
+        if rcode != ReturnCode::SUCCESS {
+            self.finish(rcode);
+        }
+    }
+}
@@ -1,4 +1,6 @@
+use crate::hil::power::{PowerClient, SleepReadiness};
 use crate::hil::spi_host::SpiHost;
+use crate::dma_pool::{Align4, DmaPool};
 use core::cell::Cell;
 use core::cmp::min;
 use kernel::common::cells::{OptionalCell, TakeCell};
@@ -6,6 +8,7 @@ use kernel::common::registers::{register_bitfields, register_structs, ReadOnly,
 use kernel::common::StaticRef;
 use kernel::hil::spi::{ClockPolarity, ClockPhase, SpiMaster, SpiMasterClient};
 use kernel::ReturnCode;
+use spiutils::protocol::flash::OpCode;
 
 // The TX and RX FIFOs both have the same length. We write and read at the same
 // time.
@@ -95,9 +98,36 @@ const SPI_HOST0_REGISTERS: StaticRef<Registers> =
 const SPI_HOST1_REGISTERS: StaticRef<Registers> =
     unsafe { StaticRef::new(SPI_HOST1_BASE_ADDR as *const Registers) };
 
-pub static mut SPI_HOST0: SpiHostHardware = SpiHostHardware::new(SPI_HOST0_REGISTERS);
+/// The size of the SPI host FIFOs, which bounds a single transaction.
+pub const FIFO_SIZE: usize = 128;
 
-pub static mut SPI_HOST1: SpiHostHardware = SpiHostHardware::new(SPI_HOST1_REGISTERS);
+/// Transfer buffers for users of the SPI hosts, a write and a read buffer for
+/// each of the two app drivers.
+pub static TRANSFER_BUFFER_POOL: DmaPool<u8, Align4, FIFO_SIZE, 4> =
+    DmaPool::new("spi host", 0);
+
+pub(crate) const unsafe fn spi_host0() -> SpiHostHardware {
+    SpiHostHardware::new(SPI_HOST0_REGISTERS)
+}
+
+pub(crate) const unsafe fn spi_host1() -> SpiHostHardware {
+    SpiHostHardware::new(SPI_HOST1_REGISTERS)
+}
+
+/// Returns the op code the controller sends for `op_code`.
+///
+/// The controller clocks the FIFO out on a single data line in each
+/// direction, so it cannot switch to two or four lines after the dummy
+/// cycles. Dual and quad output fast reads have the same framing and the
+/// same 8 dummy clocks as a fast read, so they are served as one and return
+/// the same data on a single line.
+fn single_line_op_code(op_code: u8) -> u8 {
+    if op_code == OpCode::FastReadDualOutput as u8 || op_code == OpCode::FastReadQuadOutput as u8 {
+        OpCode::FastRead as u8
+    } else {
+        op_code
+    }
+}
 
 /// A SPI Host
 pub struct SpiHostHardware {
@@ -193,6 +223,9 @@ impl SpiHostHardware {
             for idx in 0..tx_buf_len {
                 self.registers.tx_fifo[idx].set(tx_buf[idx]);
             }
+            if tx_buf_len > 0 {
+                self.registers.tx_fifo[0].set(single_line_op_code(tx_buf[0]));
+            }
         });
 
         // Clear the TX FIFO for additional bytes not supplied by write_buffer.
@@ -226,6 +259,15 @@ impl SpiHostHardware {
     }
 }
 
+// A transaction in flight holds the TX buffer until TXDONE.
+impl PowerClient for SpiHostHardware {
+    fn prepare_sleep(&self) -> SleepReadiness {
+        if self.tx_buffer.is_some() { SleepReadiness::Busy } else { SleepReadiness::Ready }
+    }
+
+    fn resume(&self) {}
+}
+
 impl SpiHost for SpiHostHardware {
     fn spi_device_spi_host_passthrough(&self, enabled: bool) {
         self.registers.ctrl.modify(
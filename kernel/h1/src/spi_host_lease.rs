@@ -0,0 +1,246 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Arbitrates the SPI host between the kernel and apps.
+//!
+//! Kernel clients (e.g. firmware verification) and the SPI host syscall
+//! drivers share SPI_HOST0. Whoever uses it must hold the lease from
+//! `SpiHostLease::acquire` for the duration of its work and `release` it
+//! afterwards. A lease lasts at most the time given to `acquire`, after which
+//! anyone may take the SPI host over; holders renew by acquiring again.
+//!
+//! With `RevokePolicy::KernelPreempts`, the kernel takes the SPI host from an
+//! app that holds it. Apps never take it from the kernel.
+//!
+//! App transactions through `capsules::spi_controller` and
+//! `h1_syscalls::spi_host_queue` cannot be attributed to a particular app, so
+//! `LeasedSpiMaster` only keeps them off the SPI host while the kernel holds
+//! it.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMaster, SpiMasterClient};
+use kernel::{AppId, ReturnCode};
+
+use crate::timeus::Timeus;
+
+/// Longest lease that can be acquired, so that the lease timer cannot wrap.
+pub const MAX_LEASE_MS: u32 = 60_000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Owner {
+    Kernel,
+    App(AppId),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RevokePolicy {
+    /// Leases are only given up by release or expiry.
+    Never,
+    /// The kernel revokes an app's lease when it needs the SPI host.
+    KernelPreempts,
+}
+
+/// Counters of lease contention since boot.
+#[derive(Clone, Copy, Default)]
+pub struct LeaseStats {
+    /// Leases granted, not counting renewals.
+    pub acquired: u32,
+    /// Acquires refused because someone else held the lease.
+    pub contended: u32,
+    /// App leases revoked by the kernel.
+    pub revoked: u32,
+    /// Leases taken over after they expired.
+    pub expired: u32,
+}
+
+pub struct SpiHostLease<'a> {
+    clock: &'a Timeus,
+    clock_hz: u32,
+    policy: RevokePolicy,
+    owner: OptionalCell<Owner>,
+    /// Clock value when the lease was acquired or last renewed.
+    start: Cell<u32>,
+    /// Length of the lease, in clock ticks.
+    duration: Cell<u32>,
+    stats: Cell<LeaseStats>,
+}
+
+impl<'a> SpiHostLease<'a> {
+    /// `clock` must be a running counter incrementing at `clock_hz`, which
+    /// must not wrap within `MAX_LEASE_MS`.
+    pub fn new(clock: &'a Timeus, clock_hz: u32, policy: RevokePolicy) -> SpiHostLease<'a> {
+        SpiHostLease {
+            clock: clock,
+            clock_hz: clock_hz,
+            policy: policy,
+            owner: OptionalCell::empty(),
+            start: Cell::new(0),
+            duration: Cell::new(0),
+            stats: Cell::new(LeaseStats::default()),
+        }
+    }
+
+    /// Acquires or renews the lease for `owner`, for at most `lease_ms`
+    /// (capped at `MAX_LEASE_MS`). Returns EBUSY if someone else holds it.
+    pub fn acquire(&self, owner: Owner, lease_ms: u32) -> ReturnCode {
+        let mut stats = self.stats.get();
+        match self.owner.extract() {
+            Some(current) if current == owner => {},
+            Some(_) if self.is_expired() => {
+                stats.expired += 1;
+                stats.acquired += 1;
+            },
+            Some(Owner::App(_)) if owner == Owner::Kernel &&
+                                   self.policy == RevokePolicy::KernelPreempts => {
+                stats.revoked += 1;
+                stats.acquired += 1;
+            },
+            Some(_) => {
+                stats.contended += 1;
+                self.stats.set(stats);
+                return ReturnCode::EBUSY;
+            },
+            None => stats.acquired += 1,
+        }
+        self.stats.set(stats);
+
+        let lease_ms = lease_ms.min(MAX_LEASE_MS);
+        self.owner.set(owner);
+        self.start.set(self.clock.now());
+        self.duration.set((self.clock_hz as u64 * lease_ms as u64 / 1000) as u32);
+        ReturnCode::SUCCESS
+    }
+
+    /// Gives up the lease. Returns EINVAL if `owner` does not hold it.
+    pub fn release(&self, owner: Owner) -> ReturnCode {
+        if !self.is_held_by(owner) {
+            return ReturnCode::EINVAL;
+        }
+        self.owner.clear();
+        ReturnCode::SUCCESS
+    }
+
+    /// Returns true if `owner` holds an unexpired lease.
+    pub fn is_held_by(&self, owner: Owner) -> bool {
+        self.owner.extract() == Some(owner) && !self.is_expired()
+    }
+
+    /// Returns true if the kernel holds an unexpired lease.
+    pub fn is_held_by_kernel(&self) -> bool {
+        self.is_held_by(Owner::Kernel)
+    }
+
+    pub fn stats(&self) -> LeaseStats {
+        self.stats.get()
+    }
+
+    fn is_expired(&self) -> bool {
+        self.clock.now().wrapping_sub(self.start.get()) >= self.duration.get()
+    }
+}
+
+/// Passes app transactions to the SPI host while the kernel does not hold
+/// the lease, and refuses them with EBUSY while it does.
+pub struct LeasedSpiMaster<'a, S: SpiMaster> {
+    spi: &'a S,
+    lease: &'a SpiHostLease<'a>,
+}
+
+impl<'a, S: SpiMaster> LeasedSpiMaster<'a, S> {
+    pub fn new(spi: &'a S, lease: &'a SpiHostLease<'a>) -> LeasedSpiMaster<'a, S> {
+        LeasedSpiMaster {
+            spi: spi,
+            lease: lease,
+        }
+    }
+}
+
+impl<'a, S: SpiMaster> SpiMaster for LeasedSpiMaster<'a, S> {
+    type ChipSelect = S::ChipSelect;
+
+    fn set_client(&self, client: &'static dyn SpiMasterClient) {
+        self.spi.set_client(client);
+    }
+
+    fn init(&self) {
+        self.spi.init();
+    }
+
+    fn is_busy(&self) -> bool {
+        self.lease.is_held_by_kernel() || self.spi.is_busy()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        if self.lease.is_held_by_kernel() {
+            return ReturnCode::EBUSY;
+        }
+        self.spi.read_write_bytes(write_buffer, read_buffer, len)
+    }
+
+    fn write_byte(&self, val: u8) {
+        self.spi.write_byte(val);
+    }
+
+    fn read_byte(&self) -> u8 {
+        self.spi.read_byte()
+    }
+
+    fn read_write_byte(&self, val: u8) -> u8 {
+        self.spi.read_write_byte(val)
+    }
+
+    fn specify_chip_select(&self, cs: Self::ChipSelect) {
+        self.spi.specify_chip_select(cs);
+    }
+
+    fn set_rate(&self, rate: u32) -> u32 {
+        self.spi.set_rate(rate)
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.spi.get_rate()
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        self.spi.set_clock(polarity);
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        self.spi.get_clock()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        self.spi.set_phase(phase);
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.spi.get_phase()
+    }
+
+    fn hold_low(&self) {
+        self.spi.hold_low();
+    }
+
+    fn release_low(&self) {
+        self.spi.release_low();
+    }
+}
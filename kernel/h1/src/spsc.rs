@@ -0,0 +1,192 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fixed-capacity single-producer/single-consumer queue.
+//!
+//! `SpscQueue` passes small `Copy` events from an interrupt handler (the
+//! producer) to the code that services them (the consumer) without
+//! allocating and without disabling interrupts. Unlike an `OptionalCell`
+//! plus a flag, a burst of events is kept in order up to the capacity.
+//!
+//! At most one context may call `push` and at most one context may call
+//! `pop`; the two may preempt each other at any point.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+pub struct SpscQueue<T: Copy, const N: usize> {
+    slots: UnsafeCell<[MaybeUninit<T>; N]>,
+    // Positions run from 0 to 2 * N - 1 so that a full queue can be told
+    // apart from an empty one without giving up a slot.
+    // `head` is only written by the consumer, `tail` only by the producer.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// The producer and the consumer each only write their own position, and a
+// slot is only read after the producer has published it.
+unsafe impl<T: Copy + Send, const N: usize> Sync for SpscQueue<T, N> {}
+
+impl<T: Copy, const N: usize> SpscQueue<T, N> {
+    pub const fn new() -> SpscQueue<T, N> {
+        SpscQueue {
+            slots: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + 2 * N - head) % (2 * N)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    /// Appends `value`. Returns it back if the queue is full.
+    ///
+    /// Must only be called from the producer context.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if (tail + 2 * N - head) % (2 * N) == N {
+            return Err(value);
+        }
+        unsafe {
+            // The consumer does not touch this slot until `tail` moves past it.
+            (*self.slots.get())[tail % N] = MaybeUninit::new(value);
+        }
+        self.tail.store((tail + 1) % (2 * N), Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes and returns the oldest value, if any.
+    ///
+    /// Must only be called from the consumer context.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let value = unsafe {
+            // The producer does not touch this slot until `head` moves past it.
+            (*self.slots.get())[head % N].assume_init()
+        };
+        self.head.store((head + 1) % (2 * N), Ordering::Release);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::SpscQueue;
+    use std::sync::Arc;
+    use std::thread;
+    use std::vec::Vec;
+
+    #[test]
+    fn fifo_order_and_capacity() {
+        let queue: SpscQueue<u8, 3> = SpscQueue::new();
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.push(1), Ok(()));
+        assert_eq!(queue.push(2), Ok(()));
+        assert_eq!(queue.push(3), Ok(()));
+        assert!(queue.is_full());
+        assert_eq!(queue.push(4), Err(4));
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.push(4), Ok(()));
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), Some(4));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn positions_wrap() {
+        let queue: SpscQueue<usize, 2> = SpscQueue::new();
+        for i in 0..100 {
+            assert_eq!(queue.push(i), Ok(()));
+            if i % 3 == 0 {
+                assert_eq!(queue.push(i + 1000), Ok(()));
+                assert_eq!(queue.pop(), Some(i));
+                assert_eq!(queue.pop(), Some(i + 1000));
+            } else {
+                assert_eq!(queue.pop(), Some(i));
+            }
+            assert!(queue.is_empty());
+        }
+    }
+
+    // Runs a producer and a consumer on separate threads and checks that
+    // every value arrives exactly once and in order.
+    fn stress<const N: usize>(count: usize) {
+        let queue: Arc<SpscQueue<usize, N>> = Arc::new(SpscQueue::new());
+        let producer_queue = queue.clone();
+        let producer = thread::spawn(move || {
+            let mut next = 0;
+            while next < count {
+                if producer_queue.push(next).is_ok() {
+                    next += 1;
+                } else {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut received = Vec::with_capacity(count);
+        while received.len() < count {
+            match queue.pop() {
+                Some(value) => received.push(value),
+                None => thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+
+        assert!(queue.is_empty());
+        assert!(received.iter().enumerate().all(|(i, value)| i == *value));
+    }
+
+    #[test]
+    fn concurrent_small_queue() {
+        for _ in 0..20 {
+            stress::<1>(2_000);
+            stress::<3>(2_000);
+        }
+    }
+
+    #[test]
+    fn concurrent_large_queue() {
+        stress::<64>(200_000);
+    }
+}
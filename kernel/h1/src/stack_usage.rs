@@ -0,0 +1,113 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Stack high-water marks.
+//!
+//! The kernel stack and the app memory are filled with `PAINT` at boot.
+//! Stacks grow down towards the bottom of their memory, so the words at the
+//! bottom that still hold the paint are stack that has never been used.
+//! Memory is only scanned when its usage is queried.
+//!
+//! Apps place their stack at the bottom of their memory, but the kernel does
+//! not know where the top of an app's stack is, so for apps only the unused
+//! bytes are reported. Apps are not repainted when they restart, so their
+//! marks cover every run since boot. A stack word that happens to be written
+//! with `PAINT` reads as unused, so the marks are a lower bound.
+
+use core::ptr;
+
+/// The fill pattern for unused stack.
+const PAINT: u32 = 0x57ac_c0de;
+
+/// Returns the number of words at the start of `memory` that still hold the
+/// paint.
+fn unused_words(memory: &[u32]) -> usize {
+    memory.iter()
+        .position(|word| unsafe { ptr::read_volatile(word) } != PAINT)
+        .unwrap_or(memory.len())
+}
+
+fn paint_words(memory: &mut [u32]) {
+    for word in memory.iter_mut() {
+        unsafe { ptr::write_volatile(word, PAINT) };
+    }
+}
+
+/// Fills `memory` with the paint. Bytes before the first and after the last
+/// whole word are left alone.
+pub fn paint(memory: &mut [u8]) {
+    let (_, words, _) = unsafe { memory.align_to_mut::<u32>() };
+    paint_words(words);
+}
+
+/// Returns the number of bytes at the start of `memory` that have not been
+/// written since it was painted.
+pub fn unused_bytes(memory: &[u8]) -> usize {
+    let (head, words, _) = unsafe { memory.align_to::<u32>() };
+    if !head.is_empty() {
+        // The bytes before the first word were never painted.
+        return 0;
+    }
+    unused_words(words) * 4
+}
+
+fn kernel_stack() -> (*mut u32, usize) {
+    let start = unsafe { &crate::_sstack as *const u32 as *mut u32 };
+    let end = crate::_estack as usize;
+    (start, (end - start as usize) / 4)
+}
+
+/// Fills the unused part of the kernel stack with the paint. Must be called
+/// on the kernel stack, before interrupts are enabled.
+pub unsafe fn paint_kernel_stack() {
+    let sp: usize;
+    llvm_asm!("mov $0, sp" : "=r"(sp) : : : "volatile");
+    let (start, _) = kernel_stack();
+    let words = (sp - start as usize) / 4;
+    paint_words(core::slice::from_raw_parts_mut(start, words));
+}
+
+/// Returns the size of the kernel stack, in bytes.
+pub fn kernel_stack_size() -> usize {
+    kernel_stack().1 * 4
+}
+
+/// Returns the deepest the kernel stack has been since boot, in bytes.
+pub fn kernel_stack_used() -> usize {
+    let (start, words) = kernel_stack();
+    let stack = unsafe { core::slice::from_raw_parts(start as *const u32, words) };
+    (words - unused_words(stack)) * 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unused_bytes_stop_at_first_write() {
+        let mut memory = [0u32; 16];
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, 64)
+        };
+        paint(bytes);
+        assert_eq!(unused_bytes(bytes), 64);
+        bytes[41] = 0;
+        bytes[60] = 0;
+        assert_eq!(unused_bytes(bytes), 40);
+        assert_eq!(unused_bytes(&bytes[1..]), 0);
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Approximate wall clock anchored to a Timeus counter.
+//!
+//! The counter runs at 1kHz, so the 32-bit counter wraps roughly every 49
+//! days. Wraparound is detected whenever the counter is sampled, so the
+//! timebase must be read or synchronized at least once per wrap period.
+
+use core::cell::Cell;
+use core::cmp::max;
+
+use crate::hil::timebase::Timebase;
+use crate::timeus::Timeus;
+
+/// Number of 24Mhz tics per counter increment, i.e. the counter runs at 1kHz.
+const COUNTER_DIVIDER: u32 = 24_000;
+
+/// Minimum local time between two synchronizations for the drift estimate to
+/// be updated. Shorter intervals are dominated by transport jitter.
+const MIN_DRIFT_INTERVAL_MS: u64 = 60_000;
+
+/// Drift estimates beyond this value are assumed to be caused by the reference
+/// clock being stepped rather than by the local counter drifting.
+const MAX_DRIFT_PPM: i64 = 50_000;
+
+/// Synchronizations that move the reference time back by at most this much
+/// keep the reported time monotonic. Larger backwards steps are taken as the
+/// reference clock being set, and the reported time follows them.
+const MAX_MONOTONIC_STEP_MS: u64 = 1_000;
+
+pub struct TimebaseImpl {
+    counter: Timeus,
+
+    /// Last raw counter value, used to detect wraparound.
+    last_raw: Cell<u32>,
+
+    /// Number of times the counter wrapped.
+    wraps: Cell<u32>,
+
+    /// Local time (in ms) at the last synchronization.
+    anchor_local_ms: Cell<u64>,
+
+    /// UNIX time (in ms) at the last synchronization.
+    anchor_unix_ms: Cell<Option<u64>>,
+
+    /// Estimated drift of the local counter in ppm.
+    drift_ppm: Cell<i32>,
+
+    /// Largest UNIX time handed out since the reference clock was last
+    /// stepped backwards. Keeps the reported time monotonic across small
+    /// corrections.
+    last_reported_ms: Cell<u64>,
+}
+
+impl TimebaseImpl {
+    /// Creates a new timebase on top of the given counter.
+    ///
+    /// The counter must not be used by anything else.
+    pub fn new(counter: Timeus) -> TimebaseImpl {
+        TimebaseImpl {
+            counter: counter,
+            last_raw: Cell::new(0),
+            wraps: Cell::new(0),
+            anchor_local_ms: Cell::new(0),
+            anchor_unix_ms: Cell::new(None),
+            drift_ppm: Cell::new(0),
+            last_reported_ms: Cell::new(0),
+        }
+    }
+
+    /// Starts the underlying counter.
+    pub fn start(&self) {
+        self.counter.start_with_divider(COUNTER_DIVIDER);
+        self.last_raw.set(self.counter.now());
+    }
+
+    /// Local time in ms since `start`, extended to 64 bits.
+    fn local_ms(&self) -> u64 {
+        let raw = self.counter.now();
+        if raw < self.last_raw.get() {
+            self.wraps.set(self.wraps.get().wrapping_add(1));
+        }
+        self.last_raw.set(raw);
+        ((self.wraps.get() as u64) << 32) | (raw as u64)
+    }
+
+    /// Applies the drift correction to the given local time interval.
+    fn corrected_elapsed_ms(&self, elapsed_ms: u64) -> u64 {
+        let correction = (elapsed_ms as i64)
+            .saturating_mul(self.drift_ppm.get() as i64) / 1_000_000;
+        (elapsed_ms as i64).saturating_add(correction).max(0) as u64
+    }
+}
+
+impl Timebase for TimebaseImpl {
+    fn set_unix_time_ms(&self, unix_time_ms: u64) {
+        let now_ms = self.local_ms();
+
+        if let Some(prev_unix_ms) = self.anchor_unix_ms.get() {
+            let local_elapsed = now_ms - self.anchor_local_ms.get();
+            if local_elapsed >= MIN_DRIFT_INTERVAL_MS {
+                let remote_elapsed = unix_time_ms as i64 - prev_unix_ms as i64;
+                let drift = (remote_elapsed - local_elapsed as i64)
+                    .saturating_mul(1_000_000) / local_elapsed as i64;
+                if drift.abs() <= MAX_DRIFT_PPM {
+                    self.drift_ppm.set(drift as i32);
+                }
+            }
+        }
+
+        self.anchor_local_ms.set(now_ms);
+        self.anchor_unix_ms.set(Some(unix_time_ms));
+        if self.last_reported_ms.get().saturating_sub(unix_time_ms) > MAX_MONOTONIC_STEP_MS {
+            self.last_reported_ms.set(unix_time_ms);
+        }
+    }
+
+    fn get_unix_time_ms(&self) -> Option<u64> {
+        let anchor_unix_ms = self.anchor_unix_ms.get()?;
+        let elapsed = self.local_ms() - self.anchor_local_ms.get();
+        let unix_time_ms = max(
+            anchor_unix_ms.saturating_add(self.corrected_elapsed_ms(elapsed)),
+            self.last_reported_ms.get());
+        self.last_reported_ms.set(unix_time_ms);
+        Some(unix_time_ms)
+    }
+
+    fn get_drift_ppm(&self) -> i32 {
+        self.drift_ppm.get()
+    }
+}
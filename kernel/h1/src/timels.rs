@@ -19,8 +19,13 @@ use kernel::hil::time::{self, Alarm, Frequency, Ticks};
 const TIMELS0_BASE: *const Registers = 0x40540000 as *const Registers;
 const TIMELS1_BASE: *const Registers = 0x40540040 as *const Registers;
 
-pub static mut TIMELS0: Timels = Timels::new(TIMELS0_BASE);
-pub static mut TIMELS1: Timels = Timels::new(TIMELS1_BASE);
+pub(crate) const unsafe fn timels0() -> Timels {
+    Timels::new(TIMELS0_BASE)
+}
+
+pub(crate) const unsafe fn timels1() -> Timels {
+    Timels::new(TIMELS1_BASE)
+}
 
 struct Registers {
     pub control: VolatileCell<u32>,
@@ -15,6 +15,22 @@
 #![allow(dead_code)]
 
 //! Driver for the True Random Number Generator (TRNG).
+//!
+//! The output is checked by the continuous health tests of NIST SP800-90B,
+//! section 4.4, taking each 32-bit word as one sample:
+//!
+//! - the repetition count test fails when the same word is read
+//!   `RCT_CUTOFF` times in a row;
+//! - the adaptive proportion test fails when the first word of a window of
+//!   `APT_WINDOW` words occurs `APT_CUTOFF` times in it.
+//!
+//! Both cutoffs are derived for a false positive probability of 2^-20 with
+//! a claimed min-entropy of `MIN_ENTROPY_BITS` per word, which is far below
+//! what a working TRNG delivers. The first `STARTUP_SAMPLES` words after
+//! `init` are tested but not handed out (section 4.3).
+//!
+//! A failure is latched until the next reset: `get` returns FAIL from then
+//! on, and the client is told once with an empty iterator and FAIL.
 
 use core::cell::Cell;
 use kernel::hil::entropy::{Continue, Entropy32, Client32};
@@ -133,11 +149,150 @@ struct Registers {
 
 const TRNG0_BASE: *mut Registers = 0x40410000 as *mut Registers;
 
-pub static mut TRNG0: Trng<'static> = unsafe { Trng::new(TRNG0_BASE) };
+/// Claimed min-entropy of one output word, in bits.
+pub const MIN_ENTROPY_BITS: u32 = 4;
+
+/// Repetition count test cutoff: 1 + ceil(20 / MIN_ENTROPY_BITS).
+pub const RCT_CUTOFF: u32 = 6;
+
+/// Adaptive proportion test window size, for non-binary samples.
+pub const APT_WINDOW: u32 = 512;
+
+/// Adaptive proportion test cutoff for `APT_WINDOW` and `MIN_ENTROPY_BITS`
+/// (SP800-90B, table 2).
+pub const APT_CUTOFF: u32 = 62;
+
+/// Number of words tested at start-up before any is handed out.
+pub const STARTUP_SAMPLES: u32 = 1024;
+
+/// A health test of SP800-90B.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthTest {
+    RepetitionCount,
+    AdaptiveProportion,
+}
+
+/// Health test results since boot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HealthStatus {
+    /// The test that failed, if any.
+    pub failure: Option<HealthTest>,
+    /// Whether the start-up tests are still running.
+    pub in_startup: bool,
+    /// Number of words tested.
+    pub samples: u32,
+    /// Longest run of identical words seen.
+    pub max_repetitions: u32,
+    /// Highest count of a window's first word seen in its window.
+    pub max_proportion: u32,
+    /// Number of one bits in the tested words.
+    pub ones: u64,
+}
+
+impl HealthStatus {
+    /// Fraction of one bits in the tested words, in units of 1/1000. A
+    /// value far from 500 points to a biased source.
+    pub fn ones_permille(&self) -> u32 {
+        let bits = self.samples as u64 * 32;
+        if bits == 0 {
+            return 0;
+        }
+        (self.ones * 1000 / bits) as u32
+    }
+}
+
+/// State of the continuous health tests.
+#[derive(Clone, Copy)]
+struct HealthTests {
+    status: HealthStatus,
+    startup_remaining: u32,
+    // Repetition count test: the last word and how often it occurred in a row.
+    last: u32,
+    repetitions: u32,
+    // Adaptive proportion test: the window's first word, how often it occurred
+    // and how many words of the window were seen.
+    reference: u32,
+    proportion: u32,
+    window_len: u32,
+}
+
+impl HealthTests {
+    const fn new() -> HealthTests {
+        HealthTests {
+            status: HealthStatus {
+                failure: None,
+                in_startup: true,
+                samples: 0,
+                max_repetitions: 0,
+                max_proportion: 0,
+                ones: 0,
+            },
+            startup_remaining: STARTUP_SAMPLES,
+            last: 0,
+            repetitions: 0,
+            reference: 0,
+            proportion: 0,
+            window_len: 0,
+        }
+    }
+
+    /// Runs the tests on `sample`. Returns whether it may be handed out, or
+    /// the test that failed.
+    fn test(&mut self, sample: u32) -> Result<bool, HealthTest> {
+        if let Some(failure) = self.status.failure {
+            return Err(failure);
+        }
+        let status = &mut self.status;
+        status.samples = status.samples.wrapping_add(1);
+        status.ones += sample.count_ones() as u64;
+
+        if self.repetitions > 0 && sample == self.last {
+            self.repetitions += 1;
+        } else {
+            self.last = sample;
+            self.repetitions = 1;
+        }
+        status.max_repetitions = status.max_repetitions.max(self.repetitions);
+
+        if self.window_len == APT_WINDOW {
+            self.window_len = 0;
+        }
+        if self.window_len == 0 {
+            self.reference = sample;
+            self.proportion = 0;
+        }
+        self.window_len += 1;
+        if sample == self.reference {
+            self.proportion += 1;
+        }
+        status.max_proportion = status.max_proportion.max(self.proportion);
+
+        if self.repetitions >= RCT_CUTOFF {
+            status.failure = Some(HealthTest::RepetitionCount);
+        } else if self.proportion >= APT_CUTOFF {
+            status.failure = Some(HealthTest::AdaptiveProportion);
+        }
+        if let Some(failure) = status.failure {
+            return Err(failure);
+        }
+
+        if self.startup_remaining > 0 {
+            self.startup_remaining -= 1;
+            status.in_startup = self.startup_remaining > 0;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+pub(crate) const unsafe fn trng0() -> Trng<'static> {
+    Trng::new(TRNG0_BASE)
+}
 
 pub struct Trng<'a> {
     regs: *mut Registers,
     client: Cell<Option<&'a dyn Client32>>,
+    health: Cell<HealthTests>,
 }
 
 impl<'a> Trng<'a> {
@@ -145,6 +300,7 @@ impl<'a> Trng<'a> {
         Trng {
             regs: trng,
             client: Cell::new(None),
+            health: Cell::new(HealthTests::new()),
         }
     }
 
@@ -155,8 +311,31 @@ impl<'a> Trng<'a> {
         regs.interrupt_enable.set(0);
         regs.interrupt_state.set(0x1);
 
+        self.deliver();
+    }
+
+    /// Returns the health test results since boot.
+    pub fn health_status(&self) -> HealthStatus {
+        self.health.get().status
+    }
+
+    fn has_failed(&self) -> bool {
+        self.health.get().status.failure.is_some()
+    }
+
+    // Hands the available words to the client, and reports a health test
+    // failure found while it read them.
+    fn deliver(&self) {
+        let regs = unsafe { &*self.regs };
+
+        if self.has_failed() {
+            return;
+        }
         self.client.get().map(|client| {
-            if let Continue::More = client.entropy_available(&mut Iter(self), ReturnCode::SUCCESS) {
+            let result = client.entropy_available(&mut Iter(self), ReturnCode::SUCCESS);
+            if self.has_failed() {
+                client.entropy_available(&mut Iter(self), ReturnCode::FAIL);
+            } else if let Continue::More = result {
                 // Re-enable the interrupt since the client needs more data.
                 regs.interrupt_enable.set(0x1);
             }
@@ -187,6 +366,9 @@ impl<'a> Entropy32<'a> for Trng<'a> {
     fn get(&self) -> ReturnCode {
         let regs = unsafe { &*self.regs };
 
+        if self.has_failed() {
+            return ReturnCode::FAIL;
+        }
         if regs.empty.get() > 0 {
             // Make sure the TRNG isn't stuck.
             if regs.fsm_state.get() & 0x8 != 0 {
@@ -198,11 +380,7 @@ impl<'a> Entropy32<'a> for Trng<'a> {
             // Enable interrupts so we know when there is random data ready.
             regs.interrupt_enable.set(0x1);
         } else {
-            self.client.get().map(|client| {
-                if let Continue::More = client.entropy_available(&mut Iter(self), ReturnCode::SUCCESS) {
-                    regs.interrupt_enable.set(0x1);
-                }
-            });
+            self.deliver();
         }
         ReturnCode::SUCCESS
     }
@@ -220,10 +398,86 @@ impl<'a, 'b> Iterator for Iter<'a, 'b> {
     fn next(&mut self) -> Option<u32> {
         let regs = unsafe { &*self.0.regs };
 
-        if regs.empty.get() == 0 {
-            Some(regs.read_data.get())
-        } else {
-            None
+        while regs.empty.get() == 0 {
+            let word = regs.read_data.get();
+            let mut health = self.0.health.get();
+            let result = health.test(word);
+            self.0.health.set(health);
+            match result {
+                Ok(true) => return Some(word),
+                // Start-up sample, read the next one.
+                Ok(false) => (),
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn past_startup() -> HealthTests {
+        let mut health = HealthTests::new();
+        for i in 0..STARTUP_SAMPLES {
+            assert_eq!(health.test(i.wrapping_mul(0x9e3779b9)), Ok(false));
+        }
+        assert!(!health.status.in_startup);
+        health
+    }
+
+    #[test]
+    fn varying_samples_pass() {
+        let mut health = past_startup();
+        let mut x = 1u32;
+        for _ in 0..4 * APT_WINDOW {
+            // xorshift32
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            assert_eq!(health.test(x), Ok(true));
+        }
+        assert_eq!(health.status.max_repetitions, 1);
+        let permille = health.status.ones_permille();
+        assert!(permille > 450 && permille < 550, "{}", permille);
+    }
+
+    #[test]
+    fn repetition_count_failure_latches() {
+        let mut health = past_startup();
+        for _ in 1..RCT_CUTOFF {
+            assert_eq!(health.test(7), Ok(true));
+        }
+        assert_eq!(health.test(7), Err(HealthTest::RepetitionCount));
+        assert_eq!(health.test(8), Err(HealthTest::RepetitionCount));
+        assert_eq!(health.status.failure, Some(HealthTest::RepetitionCount));
+    }
+
+    #[test]
+    fn adaptive_proportion_failure() {
+        let mut health = past_startup();
+        // Start a fresh window, then alternate so that no run gets long.
+        while health.window_len != APT_WINDOW {
+            assert_eq!(health.test(health.window_len.wrapping_mul(0x9e3779b9) | 1), Ok(true));
+        }
+        let mut result = Ok(true);
+        let mut i = 0;
+        while result == Ok(true) {
+            result = health.test(if i % 2 == 0 { 5 } else { i });
+            i += 1;
+        }
+        assert_eq!(result, Err(HealthTest::AdaptiveProportion));
+        assert_eq!(health.status.max_proportion, APT_CUTOFF);
+    }
+
+    #[test]
+    fn failure_during_startup() {
+        let mut health = HealthTests::new();
+        for _ in 1..RCT_CUTOFF {
+            assert_eq!(health.test(0), Ok(false));
         }
+        assert_eq!(health.test(0), Err(HealthTest::RepetitionCount));
+        assert!(health.status.in_startup);
     }
 }
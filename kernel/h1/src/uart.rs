@@ -25,8 +25,8 @@
 //! baud rate:w
 //!
 //! ```
-//! let uart = &h1::uart::UART0;
-//! let pinmux = unsafe { &mut *h1::pinmux::PINMUX };
+//! let uart = &peripherals.uart0;
+//! let pinmux = &peripherals.pinmux;
 //! pinmux.dioa0.select.set(h1::pinmux::Function::Uart0Tx);
 //! uart.config(115200);
 //! uart.enable_tx();
@@ -52,6 +52,8 @@ use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
 use kernel::hil;
 use kernel::ReturnCode;
 use crate::pmu::{Clock, PeripheralClock, PeripheralClock1};
+use crate::timeus::Timeus;
+use crate::uptime::Uptime;
 
 /// Registers for the UART controller
 #[allow(dead_code)]
@@ -71,11 +73,95 @@ const UART0_BASE: *mut Registers = 0x40600000 as *mut Registers;
 const UART1_BASE: *mut Registers = 0x40610000 as *mut Registers;
 const UART2_BASE: *mut Registers = 0x40620000 as *mut Registers;
 
-pub static mut UART0: UART = unsafe { UART::new(UART0_BASE, PeripheralClock1::Uart0Timer) };
+/// Baud rate used when none is configured or detected.
+pub const DEFAULT_BAUDRATE: u32 = 115200;
 
-pub static mut UART1: UART = unsafe { UART::new(UART1_BASE, PeripheralClock1::Uart1Timer) };
+/// Baud rates recognized by `detect_baudrate`.
+pub const AUTOBAUD_RATES: &[u32] = &[115200, 230400, 460800, 1000000];
 
-pub static mut UART2: UART = unsafe { UART::new(UART2_BASE, PeripheralClock1::Uart2Timer) };
+/// Length of a console timestamp, `[ssssssssss.uuuuuu] `.
+pub const TIMESTAMP_LEN: usize = 20;
+
+/// Formats `now_us` as a line prefix holding the seconds and microseconds
+/// since boot. The seconds wrap after 10 digits to keep the width fixed.
+pub fn format_timestamp(now_us: u64) -> [u8; TIMESTAMP_LEN] {
+    let mut stamp = *b"[0000000000.000000] ";
+    let mut seconds = now_us / 1_000_000;
+    let mut micros = now_us % 1_000_000;
+    for digit in stamp[12..18].iter_mut().rev() {
+        *digit = b'0' + (micros % 10) as u8;
+        micros /= 10;
+    }
+    for digit in stamp[1..11].iter_mut().rev() {
+        *digit = b'0' + (seconds % 10) as u8;
+        seconds /= 10;
+    }
+    stamp
+}
+
+/// Number of low pulses measured by `detect_baudrate`.
+const AUTOBAUD_PULSES: usize = 8;
+
+/// Frequency of a `Timeus` counter started with divider 1.
+const TIMEUS_TICKS_PER_SECOND: u32 = 24_000_000;
+
+/// Detects the baud rate of incoming serial data by busy-waiting on the RX
+/// line.
+///
+/// `rx_level` returns the current level of the RX pad and `timer` must be
+/// running with divider 1. The shortest low pulse seen is taken as one bit
+/// time, so the host should send a character whose least significant bit is
+/// set, such as carriage return. Returns the closest rate in
+/// `AUTOBAUD_RATES`, or None if nothing was received within `timeout_ticks`
+/// or the measured bit time doesn't match any of them.
+pub fn detect_baudrate<F: Fn() -> bool>(rx_level: F,
+                                        timer: &Timeus,
+                                        timeout_ticks: u32) -> Option<u32> {
+    let start = timer.now();
+    let timed_out = || timer.now().wrapping_sub(start) > timeout_ticks;
+
+    let mut shortest = u32::MAX;
+    'pulses: for _ in 0..AUTOBAUD_PULSES {
+        // Wait for the line to be idle, then for the next falling edge.
+        while !rx_level() {
+            if timed_out() { break 'pulses; }
+        }
+        while rx_level() {
+            if timed_out() { break 'pulses; }
+        }
+        let falling = timer.now();
+        while !rx_level() {
+            if timed_out() { break 'pulses; }
+        }
+        shortest = core::cmp::min(shortest, timer.now().wrapping_sub(falling));
+    }
+    if shortest == u32::MAX {
+        return None;
+    }
+
+    // Accept the closest rate if the measurement is within 25% of it.
+    AUTOBAUD_RATES.iter()
+        .map(|rate| {
+            let bit_ticks = TIMEUS_TICKS_PER_SECOND / rate;
+            let error = if shortest > bit_ticks { shortest - bit_ticks } else { bit_ticks - shortest };
+            (*rate, bit_ticks, error)
+        })
+        .filter(|(_, bit_ticks, error)| *error <= bit_ticks / 4)
+        .min_by_key(|(_, _, error)| *error)
+        .map(|(rate, _, _)| rate)
+}
+
+pub(crate) const unsafe fn uart0() -> UART<'static> {
+    UART::new(UART0_BASE, PeripheralClock1::Uart0Timer)
+}
+
+pub(crate) const unsafe fn uart1() -> UART<'static> {
+    UART::new(UART1_BASE, PeripheralClock1::Uart1Timer)
+}
+
+pub(crate) const unsafe fn uart2() -> UART<'static> {
+    UART::new(UART2_BASE, PeripheralClock1::Uart2Timer)
+}
 
 /// A UART channel
 ///
@@ -91,6 +177,12 @@ pub struct UART<'a> {
     rx_cursor: Cell<usize>,
     tx_client: OptionalCell<&'a dyn hil::uart::TransmitClient>,
     rx_client: OptionalCell<&'a dyn hil::uart::ReceiveClient>,
+    timestamp_clock: OptionalCell<&'a dyn Uptime>,
+    timestamps_enabled: Cell<bool>,
+    at_line_start: Cell<bool>,
+    // The timestamp being sent, and how much of it was sent.
+    timestamp: Cell<[u8; TIMESTAMP_LEN]>,
+    timestamp_cursor: Cell<usize>,
 }
 
 impl<'a> hil::uart::Uart<'a> for UART<'a> {}
@@ -108,9 +200,37 @@ impl<'a> UART<'a> {
             rx_cursor: Cell::new(0),
             tx_client: OptionalCell::empty(),
             rx_client: OptionalCell::empty(),
+            timestamp_clock: OptionalCell::empty(),
+            timestamps_enabled: Cell::new(false),
+            at_line_start: Cell::new(true),
+            timestamp: Cell::new([0; TIMESTAMP_LEN]),
+            timestamp_cursor: Cell::new(TIMESTAMP_LEN),
         }
     }
 
+    /// Sets the clock for console timestamps (see `enable_timestamps`).
+    pub fn set_timestamp_clock(&self, clock: &'a dyn Uptime) {
+        self.timestamp_clock.set(clock);
+    }
+
+    /// Prefixes every line sent with `transmit_buffer` with the time from the
+    /// timestamp clock, formatted by `format_timestamp`. Takes effect from the
+    /// next line. Output sent with `send_bytes_sync` is never stamped.
+    ///
+    /// Returns false if no timestamp clock is set.
+    pub fn enable_timestamps(&self, enabled: bool) -> bool {
+        if self.timestamp_clock.is_none() {
+            return false;
+        }
+        self.timestamps_enabled.set(enabled);
+        true
+    }
+
+    /// Returns whether console timestamps are on.
+    pub fn timestamps_enabled(&self) -> bool {
+        self.timestamps_enabled.get()
+    }
+
     /// Enables transmission on the UART
     ///
     /// Side-effect: ensures the clock is on.
@@ -230,11 +350,12 @@ impl<'a> UART<'a> {
                 -1 // done
             } else {
                 for b in bytes[init_cursor..limit].iter() {
-                    if regs.state.get() & 1 == 1 {
+                    if !self.send_timestamp(regs) || regs.state.get() & 1 == 1 {
                         break; // TX Buffer full, we'll continue later
                     }
                     self.tx_cursor.set(self.tx_cursor.get() + 1);
                     regs.write_data.set(*b as u32);
+                    self.at_line_start.set(*b == b'\n');
                 }
                 (self.tx_cursor.get() - init_cursor) as isize
             }
@@ -253,6 +374,31 @@ impl<'a> UART<'a> {
 
     }
 
+    // Call this before writing a byte to the TX FIFO. At the start of a line,
+    // takes a new timestamp if they are enabled, then writes out what is left
+    // of the current one. Returns false if the FIFO filled up first.
+    fn send_timestamp(&self, regs: &Registers) -> bool {
+        if self.at_line_start.get() {
+            self.at_line_start.set(false);
+            if self.timestamps_enabled.get() {
+                if let Some(now_us) = self.timestamp_clock.map(|clock| clock.now_us()) {
+                    self.timestamp.set(format_timestamp(now_us));
+                    self.timestamp_cursor.set(0);
+                }
+            }
+        }
+
+        let timestamp = self.timestamp.get();
+        while self.timestamp_cursor.get() < TIMESTAMP_LEN {
+            if regs.state.get() & 1 == 1 {
+                return false;
+            }
+            regs.write_data.set(timestamp[self.timestamp_cursor.get()] as u32);
+            self.timestamp_cursor.set(self.timestamp_cursor.get() + 1);
+        }
+        true
+    }
+
     fn purge_rx_fifo(&self) {
         let regs = unsafe { &*self.regs };
 
@@ -401,3 +547,16 @@ impl<'a> hil::uart::Configure for UART<'a> {
         ReturnCode::SUCCESS
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    #[test]
+    fn timestamps_have_fixed_width() {
+        assert_eq!(&format_timestamp(0), b"[0000000000.000000] ");
+        assert_eq!(&format_timestamp(12_345_678), b"[0000000012.345678] ");
+        assert_eq!(&format_timestamp(u64::MAX), b"[6744073709.551615] ");
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A 64-bit microsecond clock since boot.
+//!
+//! `UptimeClock` runs a Timeus counter at 1MHz and extends it to 64 bits.
+//! The 32-bit counter wraps about every 71 minutes, and a wrap is only
+//! noticed when the counter is read, so the clock also reads it from an
+//! alarm every `SAMPLE_INTERVAL_MS`.
+
+use core::cell::Cell;
+use kernel::hil::time::{Alarm, AlarmClient, Frequency};
+
+use crate::timeus::Timeus;
+
+/// Number of 24MHz tics per counter increment, i.e. the counter runs at 1MHz.
+const COUNTER_DIVIDER: u32 = 24;
+
+/// How often the counter is read to catch its wraps.
+const SAMPLE_INTERVAL_MS: u32 = 10 * 60 * 1000;
+
+/// A source of timestamps.
+pub trait Uptime {
+    /// Returns the microseconds since the clock was started.
+    fn now_us(&self) -> u64;
+}
+
+pub struct UptimeClock<'a, A: Alarm<'a>> {
+    counter: Timeus,
+    alarm: &'a A,
+
+    /// Last raw counter value, used to detect wraparound.
+    last_raw: Cell<u32>,
+
+    /// Number of times the counter wrapped.
+    wraps: Cell<u32>,
+}
+
+impl<'a, A: Alarm<'a>> UptimeClock<'a, A> {
+    /// Creates a clock on top of the given counter, which must not be used
+    /// by anything else.
+    pub fn new(counter: Timeus, alarm: &'a A) -> UptimeClock<'a, A> {
+        UptimeClock {
+            counter: counter,
+            alarm: alarm,
+            last_raw: Cell::new(0),
+            wraps: Cell::new(0),
+        }
+    }
+
+    /// Starts the counter. The alarm client must be set to this clock.
+    pub fn start(&self) {
+        self.counter.start_with_divider(COUNTER_DIVIDER);
+        self.last_raw.set(self.counter.now());
+        self.schedule_sample();
+    }
+
+    fn schedule_sample(&self) {
+        let interval = (A::Frequency::frequency() as u64 * SAMPLE_INTERVAL_MS as u64 / 1000) as u32;
+        self.alarm.set_alarm(self.alarm.now(), interval.into());
+    }
+}
+
+impl<'a, A: Alarm<'a>> Uptime for UptimeClock<'a, A> {
+    fn now_us(&self) -> u64 {
+        let raw = self.counter.now();
+        if raw < self.last_raw.get() {
+            self.wraps.set(self.wraps.get().wrapping_add(1));
+        }
+        self.last_raw.set(raw);
+        ((self.wraps.get() as u64) << 32) | (raw as u64)
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for UptimeClock<'a, A> {
+    fn alarm(&self) {
+        self.now_us();
+        self.schedule_sample();
+    }
+}
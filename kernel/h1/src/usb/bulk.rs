@@ -0,0 +1,50 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Vendor-specific bulk interfaces.
+//!
+//! A board registers one with `InterfaceKind::VendorBulk` (see
+//! `interface`) for a protocol of its own, and its client exchanges
+//! packets of up to `MAX_PACKET_SIZE` bytes with the host on the
+//! interface's endpoints through `UsbBulk`.
+
+use kernel::ReturnCode;
+
+/// Trait the USB driver implements to carry vendor bulk interfaces. Each
+/// method takes the endpoint number of the interface.
+pub trait UsbBulk<'a> {
+    fn set_bulk_client(&self, endpoint: usize, client: &'a dyn UsbBulkClient);
+
+    /// Returns whether the IN endpoint is free for sending.
+    fn bulk_transmit_ready(&self, endpoint: usize) -> bool;
+
+    /// Sends up to `MAX_PACKET_SIZE` bytes to the host.
+    fn bulk_put_slice(&self, endpoint: usize, slice: &[u8]) -> ReturnCode;
+
+    /// Copies the packet that `packet_received` announced into `slice`,
+    /// returning its length.
+    fn bulk_get_slice(&self, endpoint: usize, slice: &mut [u8]) -> usize;
+
+    /// Lets the host send the next packet; call once the last one was
+    /// copied out.
+    fn bulk_enable_rx(&self, endpoint: usize) -> ReturnCode;
+}
+
+/// Client for the UsbBulk trait.
+pub trait UsbBulkClient {
+    fn packet_received(&self, endpoint: usize);
+    fn packet_transmitted(&self, endpoint: usize);
+}
@@ -0,0 +1,311 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Lets userspace stream blobs, such as firmware images or logs, to and
+//! from a host tool over a vendor bulk interface.
+//!
+//! A send goes out as one bulk transfer: the app's bytes are split into
+//! `PACKET_SIZE` packets, and a transfer whose length is a multiple of
+//! `PACKET_SIZE` (including an empty one) ends with a zero-length packet.
+//!
+//! A receive fills the app's receive buffer from the host's packets until
+//! a short packet ends the transfer or the buffer is full. The rest of a
+//! packet that did not fit is kept for the next receive, and the host is
+//! held off (NAKed) while no receive is pending, so longer transfers can be
+//! read in buffer-sized pieces. When the buffer fills exactly at a packet
+//! boundary, the end of the transfer is only known from the next packet;
+//! a zero-length packet then completes the next receive with no bytes.
+//!
+//! Commands:
+//!   - 0: Check.
+//!   - 1: Sends the first arg1 bytes of the send buffer.
+//!   - 2: Receives into the receive buffer.
+//!
+//! Allows:
+//!   - 1: Send buffer.
+//!   - 2: Receive buffer.
+//!
+//! Subscribes:
+//!   - 1: Send done, with the return code and the number of bytes sent.
+//!   - 2: Receive done, with the return code, the number of bytes received
+//!        and whether the host's transfer ended (1) or continues (0).
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::OptionalCell;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::usb::bulk::{UsbBulk, UsbBulkClient};
+use crate::usb::constants::MAX_PACKET_SIZE;
+
+pub const DRIVER_NUM: usize = 0x2000a;
+
+pub const BULK_CMD_CHECK:   usize = 0;
+pub const BULK_CMD_SEND:    usize = 1;
+pub const BULK_CMD_RECEIVE: usize = 2;
+
+pub const BULK_ALLOW_SEND:    usize = 1;
+pub const BULK_ALLOW_RECEIVE: usize = 2;
+
+pub const BULK_SUBSCRIBE_SEND_DONE:    usize = 1;
+pub const BULK_SUBSCRIBE_RECEIVE_DONE: usize = 2;
+
+/// The interface subclass and protocol host tools look for, under the
+/// vendor-specific class.
+pub const SUB_CLASS: u8 = 0x53;
+pub const PROTOCOL: u8 = 0x01;
+
+pub const PACKET_SIZE: usize = MAX_PACKET_SIZE as usize;
+
+#[derive(Default)]
+pub struct App {
+    send_callback: Option<Callback>,
+    receive_callback: Option<Callback>,
+    send_buffer: Option<AppSlice<Shared, u8>>,
+    receive_buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct BulkSyscallDriver<'a> {
+    usb: &'a dyn UsbBulk<'a>,
+    endpoint: usize,
+    apps: Grant<App>,
+
+    // The app sending, the length of its transfer, the bytes the host has
+    // taken and the length of the packet on its way to it.
+    sender: OptionalCell<AppId>,
+    send_len: Cell<usize>,
+    sent: Cell<usize>,
+    in_flight: Cell<Option<usize>>,
+
+    receiver: OptionalCell<AppId>,
+    received: Cell<usize>,
+    // The last packet from the host, until receives took all of it. The
+    // endpoint is not re-enabled before then.
+    packet: Cell<[u8; PACKET_SIZE]>,
+    packet_len: Cell<Option<usize>>,
+    packet_used: Cell<usize>,
+}
+
+impl<'a> BulkSyscallDriver<'a> {
+    /// Streams over the vendor bulk interface on `endpoint`. The bulk
+    /// client of the endpoint must be set to this driver.
+    pub fn new(usb: &'a dyn UsbBulk<'a>, endpoint: usize, grant: Grant<App>) -> BulkSyscallDriver<'a> {
+        BulkSyscallDriver {
+            usb: usb,
+            endpoint: endpoint,
+            apps: grant,
+            sender: OptionalCell::empty(),
+            send_len: Cell::new(0),
+            sent: Cell::new(0),
+            in_flight: Cell::new(None),
+            receiver: OptionalCell::empty(),
+            received: Cell::new(0),
+            packet: Cell::new([0; PACKET_SIZE]),
+            packet_len: Cell::new(None),
+            packet_used: Cell::new(0),
+        }
+    }
+
+    /// Sends the next packet of the sender's transfer, which is empty once
+    /// all bytes were sent.
+    fn send_next_packet(&self) {
+        let appid = match self.sender.extract() {
+            Some(appid) => appid,
+            None => return,
+        };
+        let sent = self.sent.get();
+        let len = cmp::min(self.send_len.get() - sent, PACKET_SIZE);
+        let rcode = self.apps.enter(appid, |app, _| {
+            match app.send_buffer {
+                Some(ref buffer) if buffer.len() >= sent + len => {
+                    self.usb.bulk_put_slice(self.endpoint, &buffer.as_ref()[sent..sent + len])
+                }
+                _ => ReturnCode::ERESERVE,
+            }
+        }).unwrap_or_else(|err| err.into());
+        if rcode == ReturnCode::SUCCESS {
+            self.in_flight.set(Some(len));
+        } else {
+            self.finish_send(rcode);
+        }
+    }
+
+    fn finish_send(&self, rcode: ReturnCode) {
+        self.in_flight.set(None);
+        self.sender.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.send_callback.map(|mut cb| cb.schedule(From::from(rcode), self.sent.get(), 0));
+            });
+        });
+    }
+
+    /// Moves bytes of the kept packet into the receiver's buffer, lets the
+    /// host send the next packet once this one is used up, and completes
+    /// the receive once the buffer is full or the transfer ended.
+    fn deliver(&self) {
+        let (packet_len, appid) = match (self.packet_len.get(), self.receiver.extract()) {
+            (Some(packet_len), Some(appid)) => (packet_len, appid),
+            _ => return,
+        };
+        let used = self.packet_used.get();
+        let received = self.received.get();
+        let full = self.apps.enter(appid, |app, _| {
+            let buffer = match app.receive_buffer {
+                Some(ref mut buffer) => buffer,
+                None => return Err(ReturnCode::ERESERVE),
+            };
+            let count = cmp::min(buffer.len().saturating_sub(received), packet_len - used);
+            buffer.as_mut()[received..received + count]
+                .copy_from_slice(&self.packet.get()[used..used + count]);
+            self.received.set(received + count);
+            self.packet_used.set(used + count);
+            Ok(received + count >= buffer.len())
+        }).unwrap_or_else(|err| Err(err.into()));
+
+        let full = match full {
+            Ok(full) => full,
+            Err(rcode) => {
+                self.finish_receive(rcode, false);
+                return;
+            }
+        };
+        let mut ended = false;
+        if self.packet_used.get() == packet_len {
+            self.packet_len.set(None);
+            self.usb.bulk_enable_rx(self.endpoint);
+            ended = packet_len < PACKET_SIZE;
+        }
+        if full || ended {
+            self.finish_receive(ReturnCode::SUCCESS, ended);
+        }
+    }
+
+    fn finish_receive(&self, rcode: ReturnCode, ended: bool) {
+        self.receiver.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.receive_callback.map(|mut cb| {
+                    cb.schedule(From::from(rcode), self.received.get(), ended as usize)
+                });
+            });
+        });
+    }
+}
+
+impl<'a> UsbBulkClient for BulkSyscallDriver<'a> {
+    fn packet_received(&self, _endpoint: usize) {
+        let mut packet = [0; PACKET_SIZE];
+        let len = self.usb.bulk_get_slice(self.endpoint, &mut packet);
+        self.packet.set(packet);
+        self.packet_len.set(Some(len));
+        self.packet_used.set(0);
+        self.deliver();
+    }
+
+    fn packet_transmitted(&self, _endpoint: usize) {
+        let in_flight = match self.in_flight.take() {
+            Some(in_flight) => in_flight,
+            None => return,
+        };
+        self.sent.set(self.sent.get() + in_flight);
+        if self.sent.get() < self.send_len.get() || in_flight == PACKET_SIZE {
+            self.send_next_packet();
+        } else {
+            self.finish_send(ReturnCode::SUCCESS);
+        }
+    }
+}
+
+impl<'a> Driver for BulkSyscallDriver<'a> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            BULK_ALLOW_SEND => self.apps.enter(appid, |app, _| {
+                app.send_buffer = slice;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into()),
+            BULK_ALLOW_RECEIVE => self.apps.enter(appid, |app, _| {
+                app.receive_buffer = slice;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        self.apps.enter(app_id, |app, _| {
+            match subscribe_num {
+                BULK_SUBSCRIBE_SEND_DONE => app.send_callback = callback,
+                BULK_SUBSCRIBE_RECEIVE_DONE => app.receive_callback = callback,
+                _ => return ReturnCode::ENOSUPPORT,
+            }
+            ReturnCode::SUCCESS
+        }).unwrap_or_else(|err| err.into())
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            BULK_CMD_CHECK => ReturnCode::SUCCESS,
+            BULK_CMD_SEND => {
+                if self.sender.is_some() || !self.usb.bulk_transmit_ready(self.endpoint) {
+                    return ReturnCode::EBUSY;
+                }
+                let rcode = self.apps.enter(appid, |app, _| {
+                    match app.send_buffer {
+                        Some(ref buffer) if arg1 <= buffer.len() => ReturnCode::SUCCESS,
+                        Some(_) => ReturnCode::ESIZE,
+                        None => ReturnCode::ERESERVE,
+                    }
+                }).unwrap_or_else(|err| err.into());
+                if rcode != ReturnCode::SUCCESS {
+                    return rcode;
+                }
+                self.sender.set(appid);
+                self.send_len.set(arg1);
+                self.sent.set(0);
+                self.send_next_packet();
+                ReturnCode::SUCCESS
+            }
+            BULK_CMD_RECEIVE => {
+                if self.receiver.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                let rcode = self.apps.enter(appid, |app, _| {
+                    match app.receive_buffer {
+                        Some(ref buffer) if buffer.len() > 0 => ReturnCode::SUCCESS,
+                        _ => ReturnCode::ERESERVE,
+                    }
+                }).unwrap_or_else(|err| err.into());
+                if rcode != ReturnCode::SUCCESS {
+                    return rcode;
+                }
+                self.receiver.set(appid);
+                self.received.set(0);
+                self.deliver();
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Developer-facing capture of USB traffic on EP0 and the data endpoints.
+//!
+//! When enabled, the USB driver records every SETUP packet and the
+//! first `CAPTURE_PREFIX_LEN` bytes of every data packet, together
+//! with a microsecond timestamp, into a ring of `CaptureRecord`s.
+//! `dump` prints the ring to the console as `usbcap:` lines, which
+//! `tools/usb_pcap` converts into a pcap file for Wireshark.
+//!
+//! Capture is disabled unless a board calls `USB::enable_capture`.
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::ReturnCode;
+use crate::timeus::Timeus;
+
+/// Number of packets kept in the capture ring.
+pub const CAPTURE_RECORD_COUNT: usize = 64;
+
+/// Number of payload bytes captured per packet. SETUP packets always fit.
+pub const CAPTURE_PREFIX_LEN: usize = 16;
+
+/// The kind of packet captured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureKind {
+    /// A SETUP packet received on a control endpoint.
+    Setup = 0,
+    /// Data sent from the device to the host.
+    DataIn = 1,
+    /// Data sent from the host to the device.
+    DataOut = 2,
+}
+
+/// A single captured packet.
+#[derive(Clone, Copy)]
+pub struct CaptureRecord {
+    pub timestamp_us: u32,
+    pub endpoint: u8,
+    pub kind: CaptureKind,
+    /// Full length of the packet; only the first `CAPTURE_PREFIX_LEN`
+    /// bytes are stored in `prefix`.
+    pub length: u16,
+    pub prefix: [u8; CAPTURE_PREFIX_LEN],
+}
+
+impl CaptureRecord {
+    pub const EMPTY: CaptureRecord = CaptureRecord {
+        timestamp_us: 0,
+        endpoint: 0,
+        kind: CaptureKind::Setup,
+        length: 0,
+        prefix: [0; CAPTURE_PREFIX_LEN],
+    };
+}
+
+pub struct UsbCapture<'a> {
+    // Microsecond timer used to timestamp records.
+    timer: OptionalCell<&'a Timeus>,
+    records: TakeCell<'static, [CaptureRecord; CAPTURE_RECORD_COUNT]>,
+    // Index of the next record to write and the number of valid records.
+    next: Cell<usize>,
+    count: Cell<usize>,
+    // Number of records overwritten before they were dumped.
+    dropped: Cell<u32>,
+}
+
+impl<'a> UsbCapture<'a> {
+    pub const fn new() -> UsbCapture<'a> {
+        UsbCapture {
+            timer: OptionalCell::empty(),
+            records: TakeCell::empty(),
+            next: Cell::new(0),
+            count: Cell::new(0),
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Starts capturing into `records`. `timer` must already be running
+    /// at 1MHz.
+    pub fn enable(&self,
+                  timer: &'a Timeus,
+                  records: &'static mut [CaptureRecord; CAPTURE_RECORD_COUNT]) {
+        self.timer.set(timer);
+        self.records.replace(records);
+        self.next.set(0);
+        self.count.set(0);
+        self.dropped.set(0);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.records.is_some()
+    }
+
+    /// Records a packet whose payload is stored in the little-endian
+    /// words of `buffer`, as used by the USB DMA buffers.
+    pub fn record(&self, endpoint: u8, kind: CaptureKind, buffer: &[u32], length: usize) {
+        let timestamp_us = self.timer.map_or(0, |timer| timer.now());
+        self.records.map(|records| {
+            let record = &mut records[self.next.get()];
+            record.timestamp_us = timestamp_us;
+            record.endpoint = endpoint;
+            record.kind = kind;
+            record.length = length as u16;
+            record.prefix = [0; CAPTURE_PREFIX_LEN];
+            let captured = core::cmp::min(core::cmp::min(length, CAPTURE_PREFIX_LEN),
+                                          buffer.len() * 4);
+            for i in 0..captured {
+                record.prefix[i] = (buffer[i / 4] >> (8 * (i % 4))) as u8;
+            }
+
+            self.next.set((self.next.get() + 1) % CAPTURE_RECORD_COUNT);
+            if self.count.get() == CAPTURE_RECORD_COUNT {
+                self.dropped.set(self.dropped.get().wrapping_add(1));
+            } else {
+                self.count.set(self.count.get() + 1);
+            }
+        });
+    }
+
+    /// Prints all captured records, oldest first, and empties the ring.
+    ///
+    /// Each record is printed as
+    /// `usbcap: <timestamp_us> <endpoint> <kind> <length> <prefix hex>`.
+    pub fn dump(&self) -> ReturnCode {
+        self.records.map_or(ReturnCode::ENOSUPPORT, |records| {
+            print!("usbcap: begin dropped={}\n", self.dropped.get());
+            let count = self.count.get();
+            let first = (self.next.get() + CAPTURE_RECORD_COUNT - count) % CAPTURE_RECORD_COUNT;
+            for n in 0..count {
+                let record = &records[(first + n) % CAPTURE_RECORD_COUNT];
+                let captured = core::cmp::min(record.length as usize, CAPTURE_PREFIX_LEN);
+                print!("usbcap: {} {} {} {} ",
+                       record.timestamp_us, record.endpoint, record.kind as u8, record.length);
+                for byte in &record.prefix[..captured] {
+                    print!("{:02x}", byte);
+                }
+                print!("\n");
+            }
+            print!("usbcap: end\n");
+            self.count.set(0);
+            self.dropped.set(0);
+            ReturnCode::SUCCESS
+        })
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! USB CDC-ACM (virtual serial port) interface.
+//!
+//! A CDC-ACM function is two interfaces tied together by an interface
+//! association descriptor: a communication interface that takes the
+//! class requests (line coding, DTR/RTS) and has an interrupt endpoint for
+//! notifications, and a data interface with a pair of bulk endpoints that
+//! carry the serial stream. The USB driver serves the descriptors and
+//! class requests; `UsbCdcAcm` lets a client move the bulk packets, such
+//! as `cdc_uart::UsbCdcUart`, which runs the console over it.
+//!
+//! The board registers the function as `InterfaceKind::CdcAcm` (see
+//! `interface`). The communication interface takes the first interface
+//! number and the data interface the next one. The notification endpoint
+//! never sends anything.
+
+use kernel::ReturnCode;
+
+use crate::usb::constants::{MAX_PACKET_SIZE, STRING_INTERFACE1};
+use crate::usb::types::{EndpointAttributes, EndpointDescriptor,
+                        EndpointSynchronizationType, EndpointTransferType,
+                        EndpointUsageType, InterfaceDescriptor};
+
+/// The size of a bulk packet.
+pub const PACKET_SIZE: usize = MAX_PACKET_SIZE as usize;
+
+// Class, subclass and protocol codes (CDC 1.2, section 4).
+const CLASS_COMM: u8 = 0x02;
+const SUBCLASS_ACM: u8 = 0x02;
+const CLASS_DATA: u8 = 0x0a;
+
+// Class-specific descriptor types and subtypes (CDC 1.2, section 5.2.3).
+const DESCRIPTOR_INTERFACE_ASSOCIATION: u8 = 0x0b;
+const CS_INTERFACE: u8 = 0x24;
+const SUBTYPE_HEADER: u8 = 0x00;
+const SUBTYPE_CALL_MANAGEMENT: u8 = 0x01;
+const SUBTYPE_ACM: u8 = 0x02;
+const SUBTYPE_UNION: u8 = 0x06;
+
+// ACM capabilities: SET_LINE_CODING, GET_LINE_CODING and
+// SET_CONTROL_LINE_STATE.
+const ACM_CAPABILITIES: u8 = 0x02;
+
+/// Bit of the SET_CONTROL_LINE_STATE value that is set while the host has
+/// the port open.
+pub const CONTROL_LINE_DTR: u16 = 1 << 0;
+
+/// The length of the line coding structure of SET/GET_LINE_CODING.
+pub const LINE_CODING_LEN: usize = 7;
+
+/// The serial settings the host asked for. They mean nothing on USB, but
+/// hosts read them back and expect to get what they set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineCoding {
+    pub baud_rate: u32,
+    pub stop_bits: u8,
+    pub parity: u8,
+    pub data_bits: u8,
+}
+
+impl LineCoding {
+    /// 115200 baud, 8N1.
+    pub const DEFAULT: LineCoding = LineCoding {
+        baud_rate: 115200,
+        stop_bits: 0,
+        parity: 0,
+        data_bits: 8,
+    };
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<LineCoding> {
+        if bytes.len() < LINE_CODING_LEN {
+            return None;
+        }
+        Some(LineCoding {
+            baud_rate: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            stop_bits: bytes[4],
+            parity: bytes[5],
+            data_bits: bytes[6],
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; LINE_CODING_LEN] {
+        let baud = self.baud_rate.to_le_bytes();
+        [baud[0], baud[1], baud[2], baud[3], self.stop_bits, self.parity, self.data_bits]
+    }
+}
+
+/// Writes the descriptors of a CDC-ACM function into `buf` for the
+/// configuration descriptor, returning the number of bytes written. The
+/// communication interface is `comm_interface` and the data interface the
+/// one after it.
+pub fn write_descriptors(comm_interface: u8,
+                         data_endpoint: usize,
+                         notification_endpoint: usize,
+                         buf: &mut [u8]) -> usize {
+    let data_interface = comm_interface + 1;
+    let interrupt = EndpointAttributes {
+        transfer: EndpointTransferType::Interrupt,
+        synchronization: EndpointSynchronizationType::None,
+        usage: EndpointUsageType::Data,
+    };
+    let bulk_out = EndpointAttributes {
+        transfer: EndpointTransferType::Bulk,
+        synchronization: EndpointSynchronizationType::None,
+        usage: EndpointUsageType::Data,
+    };
+    let bulk_in = EndpointAttributes {
+        transfer: EndpointTransferType::Bulk,
+        synchronization: EndpointSynchronizationType::None,
+        usage: EndpointUsageType::Data,
+    };
+
+    let association = [8, DESCRIPTOR_INTERFACE_ASSOCIATION,
+                       comm_interface, 2,
+                       CLASS_COMM, SUBCLASS_ACM, 0,
+                       STRING_INTERFACE1];
+    let mut comm = InterfaceDescriptor::new(STRING_INTERFACE1, comm_interface,
+                                            CLASS_COMM, SUBCLASS_ACM, 0);
+    comm.b_num_endpoints = 1;
+    let functional = [5, CS_INTERFACE, SUBTYPE_HEADER, 0x10, 0x01,
+                      5, CS_INTERFACE, SUBTYPE_CALL_MANAGEMENT, 0x00, data_interface,
+                      4, CS_INTERFACE, SUBTYPE_ACM, ACM_CAPABILITIES,
+                      5, CS_INTERFACE, SUBTYPE_UNION, comm_interface, data_interface];
+    let notification = EndpointDescriptor::new(0x80 | notification_endpoint as u8, interrupt, 16);
+    let data = InterfaceDescriptor::new(STRING_INTERFACE1, data_interface, CLASS_DATA, 0, 0);
+    let data_out = EndpointDescriptor::new(data_endpoint as u8, bulk_out, 0);
+    let data_in = EndpointDescriptor::new(0x80 | data_endpoint as u8, bulk_in, 0);
+
+    let mut size = 0;
+    buf[size..size + association.len()].copy_from_slice(&association);
+    size += association.len();
+    size += comm.into_u8_buf(&mut buf[size..size + comm.length()]);
+    buf[size..size + functional.len()].copy_from_slice(&functional);
+    size += functional.len();
+    size += notification.into_u8_buf(&mut buf[size..size + notification.length()]);
+    size += data.into_u8_buf(&mut buf[size..size + data.length()]);
+    size += data_out.into_u8_buf(&mut buf[size..size + data_out.length()]);
+    size += data_in.into_u8_buf(&mut buf[size..size + data_in.length()]);
+    size
+}
+
+/// Trait the USB driver implements to carry a CDC-ACM serial port on its
+/// bulk data endpoints.
+pub trait UsbCdcAcm<'a> {
+    fn set_cdc_client(&self, client: &'a dyn UsbCdcAcmClient);
+
+    /// Whether the host has the port open (DTR set).
+    fn cdc_port_open(&self) -> bool;
+
+    /// Returns whether the bulk IN endpoint is free for sending.
+    fn cdc_transmit_ready(&self) -> bool;
+
+    /// Sends up to `PACKET_SIZE` bytes to the host. `packet_transmitted`
+    /// follows once the host took them.
+    fn cdc_put_slice(&self, slice: &[u8]) -> ReturnCode;
+
+    /// Copies the packet that `packet_received` announced into `slice`,
+    /// returning its length.
+    fn cdc_get_slice(&self, slice: &mut [u8]) -> usize;
+
+    /// Lets the host send the next packet; call once the last one was
+    /// copied out.
+    fn cdc_enable_rx(&self) -> ReturnCode;
+}
+
+/// Client for the UsbCdcAcm trait.
+pub trait UsbCdcAcmClient {
+    fn packet_received(&self);
+    fn packet_transmitted(&self);
+    /// The host opened (`open` is true) or closed the port.
+    fn port_changed(&self, open: bool);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    #[test]
+    fn line_coding_round_trips() {
+        let bytes = [0x00, 0xc2, 0x01, 0x00, 0, 0, 8];
+        let coding = LineCoding::from_bytes(&bytes).unwrap();
+        assert_eq!(coding, LineCoding::DEFAULT);
+        assert_eq!(coding.to_bytes(), bytes);
+        assert_eq!(LineCoding::from_bytes(&bytes[..6]), None);
+    }
+
+    #[test]
+    fn descriptors() {
+        let mut buf = [0u8; 128];
+        let len = write_descriptors(1, 2, 3, &mut buf);
+        assert_eq!(len, 8 + 9 + 19 + 7 + 9 + 7 + 7);
+        // Every descriptor's length leads to the next one, and the
+        // interfaces and endpoints are the ones the driver serves.
+        let mut offset = 0;
+        let mut interfaces = std::vec::Vec::new();
+        let mut endpoints = std::vec::Vec::new();
+        while offset < len {
+            match buf[offset + 1] {
+                4 => interfaces.push((buf[offset + 2], buf[offset + 4], buf[offset + 5])),
+                5 => endpoints.push((buf[offset + 2], buf[offset + 3])),
+                _ => {}
+            }
+            offset += buf[offset] as usize;
+        }
+        assert_eq!(offset, len);
+        assert_eq!(interfaces, [(1, 1, CLASS_COMM), (2, 2, CLASS_DATA)]);
+        assert_eq!(endpoints, [(0x83, 0x03), (0x02, 0x02), (0x82, 0x02)]);
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A UART on the USB CDC-ACM interface, so that the console can run over
+//! USB instead of UART0.
+//!
+//! `UsbCdcUart` implements the `hil::uart` traits on top of `UsbCdcAcm`:
+//! transmissions go out in bulk packets, and received packets fill the
+//! receive buffer, with the rest of a packet kept for the next receive.
+//! Nobody reads the bulk IN endpoint while the host has the port closed, so
+//! transmissions then complete at once and their data is dropped, the same
+//! as UART output with nothing attached. Such completions, and receives
+//! that are already satisfied by a kept packet, are delivered from a
+//! deferred call.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient};
+use kernel::hil;
+use kernel::ReturnCode;
+
+use crate::usb::cdc::{UsbCdcAcm, UsbCdcAcmClient, PACKET_SIZE};
+
+pub struct UsbCdcUart<'a> {
+    usb: &'a dyn UsbCdcAcm<'a>,
+    tx_client: OptionalCell<&'a dyn hil::uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn hil::uart::ReceiveClient>,
+
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    // Bytes of `tx_buffer` the host has taken, and the bytes of the packet
+    // on its way to it.
+    tx_sent: Cell<usize>,
+    tx_in_flight: Cell<usize>,
+    // Set when `tx_buffer` is to be completed without sending the rest.
+    tx_dropped: Cell<bool>,
+
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_received: Cell<usize>,
+    // The last packet from the host, until the receive buffers took all of
+    // it. The host is not allowed to send the next one before then.
+    packet: Cell<[u8; PACKET_SIZE]>,
+    packet_len: Cell<usize>,
+    packet_used: Cell<usize>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a> UsbCdcUart<'a> {
+    /// Completions from a deferred call need its handle, which must be set
+    /// with `initialize_callback_handle`.
+    pub fn new(usb: &'a dyn UsbCdcAcm<'a>,
+               deferred_caller: &'a DynamicDeferredCall) -> UsbCdcUart<'a> {
+        UsbCdcUart {
+            usb: usb,
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_sent: Cell::new(0),
+            tx_in_flight: Cell::new(0),
+            tx_dropped: Cell::new(false),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_received: Cell::new(0),
+            packet: Cell::new([0; PACKET_SIZE]),
+            packet_len: Cell::new(0),
+            packet_used: Cell::new(0),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.set(handle);
+    }
+
+    fn defer(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    fn drop_transmission(&self) {
+        self.tx_in_flight.set(0);
+        self.tx_dropped.set(true);
+        self.defer();
+    }
+
+    fn send_next_packet(&self) {
+        let sent = self.tx_sent.get();
+        let len = cmp::min(self.tx_len.get() - sent, PACKET_SIZE);
+        let rcode = self.tx_buffer
+            .map_or(ReturnCode::FAIL, |buffer| self.usb.cdc_put_slice(&buffer[sent..sent + len]));
+        if rcode == ReturnCode::SUCCESS {
+            self.tx_in_flight.set(len);
+        } else {
+            self.finish_transmit(rcode);
+        }
+    }
+
+    fn finish_transmit(&self, rcode: ReturnCode) {
+        self.tx_in_flight.set(0);
+        self.tx_dropped.set(false);
+        if let Some(buffer) = self.tx_buffer.take() {
+            self.tx_client.map(|client| client.transmitted_buffer(buffer, self.tx_len.get(), rcode));
+        }
+    }
+
+    /// Moves bytes of the kept packet into the receive buffer, lets the host
+    /// send the next packet once this one is used up, and completes the
+    /// receive buffer once it is full.
+    fn deliver(&self) {
+        let packet_len = self.packet_len.get();
+        if packet_len == 0 {
+            return;
+        }
+        let full = self.rx_buffer.map_or(false, |buffer| {
+            let received = self.rx_received.get();
+            let used = self.packet_used.get();
+            let count = cmp::min(self.rx_len.get() - received, packet_len - used);
+            buffer[received..received + count].copy_from_slice(&self.packet.get()[used..used + count]);
+            self.rx_received.set(received + count);
+            self.packet_used.set(used + count);
+            received + count == self.rx_len.get()
+        });
+        if self.packet_used.get() == packet_len {
+            self.packet_len.set(0);
+            self.usb.cdc_enable_rx();
+        }
+        if full {
+            self.finish_receive(ReturnCode::SUCCESS);
+        }
+    }
+
+    fn finish_receive(&self, rcode: ReturnCode) {
+        if let Some(buffer) = self.rx_buffer.take() {
+            self.rx_client.map(|client| {
+                client.received_buffer(buffer, self.rx_received.get(), rcode, hil::uart::Error::None)
+            });
+        }
+    }
+}
+
+impl<'a> hil::uart::Transmit<'a> for UsbCdcUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn hil::uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(&self, tx_buffer: &'static mut [u8], tx_len: usize) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.tx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(tx_buffer));
+        }
+        if tx_len > tx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(tx_buffer));
+        }
+        self.tx_buffer.replace(tx_buffer);
+        self.tx_len.set(tx_len);
+        self.tx_sent.set(0);
+        if self.usb.cdc_port_open() && tx_len > 0 {
+            self.send_next_packet();
+        } else {
+            self.drop_transmission();
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        if self.tx_buffer.is_some() {
+            ReturnCode::FAIL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> hil::uart::Receive<'a> for UsbCdcUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn hil::uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(&self, rx_buffer: &'static mut [u8], rx_len: usize) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        }
+        if rx_buffer.len() < rx_len {
+            return (ReturnCode::ENOMEM, Some(rx_buffer));
+        }
+        self.rx_buffer.replace(rx_buffer);
+        self.rx_len.set(rx_len);
+        self.rx_received.set(0);
+        if self.packet_len.get() > 0 {
+            self.defer();
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        if self.rx_buffer.is_some() {
+            self.finish_receive(ReturnCode::ECANCEL);
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> hil::uart::Configure for UsbCdcUart<'a> {
+    fn configure(&self, _params: hil::uart::Parameters) -> ReturnCode {
+        // The host picks the line settings, and they do not matter on USB.
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> UsbCdcAcmClient for UsbCdcUart<'a> {
+    fn packet_received(&self) {
+        let mut packet = [0; PACKET_SIZE];
+        let len = self.usb.cdc_get_slice(&mut packet);
+        self.packet.set(packet);
+        self.packet_len.set(len);
+        self.packet_used.set(0);
+        if len == 0 {
+            self.usb.cdc_enable_rx();
+        } else {
+            self.deliver();
+        }
+    }
+
+    fn packet_transmitted(&self) {
+        let in_flight = self.tx_in_flight.get();
+        if in_flight == 0 {
+            return;
+        }
+        self.tx_in_flight.set(0);
+        self.tx_sent.set(self.tx_sent.get() + in_flight);
+        if self.tx_sent.get() < self.tx_len.get() {
+            self.send_next_packet();
+        } else {
+            self.finish_transmit(ReturnCode::SUCCESS);
+        }
+    }
+
+    fn port_changed(&self, open: bool) {
+        // The packet in flight is not going to be read, or was flushed
+        // with the endpoint.
+        if !open && self.tx_buffer.is_some() {
+            self.drop_transmission();
+        }
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for UsbCdcUart<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.tx_dropped.get() {
+            self.finish_transmit(ReturnCode::SUCCESS);
+        }
+        self.deliver();
+    }
+}
@@ -22,6 +22,7 @@ pub const STRING_PLATFORM: u8   = 3;
 pub const STRING_INTERFACE1: u8 = 4;  // Shell
 pub const STRING_BLAH: u8       = 5;  // Garbage?
 pub const STRING_INTERFACE2: u8 = 6;  // Hotel_U2F
+pub const STRING_SERIAL: u8     = 7;
 
 const MAX_CONTROL_ENDPOINTS: u16 =  3;
 const MAX_NORMAL_ENDPOINTS:  u16 = 16;
@@ -38,6 +39,16 @@ pub const RX_FIFO_SIZE: u16 = (4 * MAX_CONTROL_ENDPOINTS + 6) +
                               (2 * MAX_NORMAL_ENDPOINTS) + 1;
 pub const TX_FIFO_SIZE: u16 = 2 * MAX_PACKET_SIZE / 4;
 
+// The device remote wakeup feature selector, and its bit in the device's
+// GET_STATUS response (USB 2.0, 9.4.5 and 9.4.9).
+pub const FEATURE_DEVICE_REMOTE_WAKEUP: u16 = 1;
+pub const STATUS_REMOTE_WAKEUP: u32 = 1 << 1;
+
+// The endpoint halt feature selector, and its bit in an endpoint's
+// GET_STATUS response (USB 2.0, 9.4.5 and 9.4.9).
+pub const FEATURE_ENDPOINT_HALT: u16 = 0;
+pub const STATUS_ENDPOINT_HALT: u32 = 1 << 0;
+
 
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -100,6 +111,31 @@ pub const U2F_REPORT_DESCRIPTOR: [u8; 34] = [
     0xC0              /* End Collection */
 ];
 
+/// U2FHID commands, as sent in the command byte of an initialization
+/// frame (with the frame type bit set). CTAPHID adds `Cbor`, `Cancel` and
+/// `Keepalive`.
 pub enum U2fHidCommand {
-    Error = 0xbf,
+    Ping      = 0x81,
+    Msg       = 0x83,
+    Lock      = 0x84,
+    Init      = 0x86,
+    Wink      = 0x88,
+    Cbor      = 0x90,
+    Cancel    = 0x91,
+    Keepalive = 0xbb,
+    Error     = 0xbf,
+}
+
+/// Error codes sent in a U2FHID ERROR response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum U2fHidError {
+    InvalidCmd   = 0x01,
+    InvalidPar   = 0x02,
+    InvalidLen   = 0x03,
+    InvalidSeq   = 0x04,
+    MsgTimeout   = 0x05,
+    ChannelBusy  = 0x06,
+    LockRequired = 0x0a,
+    InvalidCid   = 0x0b,
+    Other        = 0x7f,
 }
@@ -0,0 +1,134 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The data stage of control OUT requests.
+//!
+//! Class and vendor requests to an interface may carry up to
+//! `CONTROL_OUT_MAX_LEN` bytes from the host, such as a HID SET_REPORT.
+//! The driver collects the data stage packet by packet in a `ControlOut`
+//! and hands it to the interface's `UsbControlClient` once it is complete;
+//! the client's answer decides whether the status stage is acknowledged
+//! or stalled. CDC-ACM's SET_LINE_CODING is handled by the driver itself.
+
+use kernel::ReturnCode;
+
+use crate::usb::constants::MAX_PACKET_SIZE;
+use crate::usb::types::SetupRequest;
+
+/// The longest data stage the device accepts; longer requests are
+/// stalled.
+pub const CONTROL_OUT_MAX_LEN: usize = 256;
+
+/// Client for class and vendor requests with data to an interface. Boards
+/// set one per interface with `USB::set_control_client`.
+pub trait UsbControlClient {
+    /// Handles `request` to `interface` with its data stage, which is
+    /// empty for requests without one. Returning anything but SUCCESS
+    /// makes the device stall the status stage.
+    fn control_out(&self, interface: u8, request: &SetupRequest, data: &[u8]) -> ReturnCode;
+}
+
+/// A control OUT request whose data stage is being received.
+pub struct ControlOut {
+    request: SetupRequest,
+    data: [u8; CONTROL_OUT_MAX_LEN],
+    received: usize,
+}
+
+impl ControlOut {
+    /// Returns None if the data stage of `request` does not fit.
+    pub fn new(request: SetupRequest) -> Option<ControlOut> {
+        if request.length() as usize > CONTROL_OUT_MAX_LEN {
+            return None;
+        }
+        Some(ControlOut {
+            request: request,
+            data: [0; CONTROL_OUT_MAX_LEN],
+            received: 0,
+        })
+    }
+
+    pub fn request(&self) -> &SetupRequest {
+        &self.request
+    }
+
+    /// The data received so far.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.received]
+    }
+
+    /// Whether the data stage is complete: all the bytes the request
+    /// announced arrived, or the host ended it early with a short packet.
+    pub fn is_complete(&self) -> bool {
+        self.received == self.request.length() as usize
+    }
+
+    /// Adds the next packet of the data stage, dropping bytes past the
+    /// announced length, and returns whether the data stage is complete.
+    pub fn receive(&mut self, packet: &[u8]) -> bool {
+        let count = ::core::cmp::min(packet.len(), self.request.length() as usize - self.received);
+        self.data[self.received..self.received + count].copy_from_slice(&packet[..count]);
+        self.received += count;
+        self.is_complete() || packet.len() < MAX_PACKET_SIZE as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    // A SET_REPORT for the output report of interface 1.
+    fn set_report(len: u16) -> SetupRequest {
+        SetupRequest {
+            bm_request_type: 0x21,
+            b_request: 0x09,
+            w_value: 0x0200,
+            w_index: 1,
+            w_length: len,
+        }
+    }
+
+    #[test]
+    fn collects_packets() {
+        let mut out = ControlOut::new(set_report(100)).unwrap();
+        assert!(!out.receive(&[1; 64]));
+        assert!(out.receive(&[2; 36]));
+        assert_eq!(out.data().len(), 100);
+        assert_eq!(&out.data()[60..68], &[1, 1, 1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn ends_at_short_packet() {
+        let mut out = ControlOut::new(set_report(128)).unwrap();
+        assert!(out.receive(&[3; 10]));
+        assert!(!out.is_complete());
+        assert_eq!(out.data(), &[3; 10]);
+    }
+
+    #[test]
+    fn ends_at_announced_length() {
+        let mut out = ControlOut::new(set_report(64)).unwrap();
+        assert!(out.receive(&[4; 64]));
+        assert!(out.is_complete());
+    }
+
+    #[test]
+    fn rejects_long_requests() {
+        assert!(ControlOut::new(set_report(CONTROL_OUT_MAX_LEN as u16)).is_some());
+        assert!(ControlOut::new(set_report(CONTROL_OUT_MAX_LEN as u16 + 1)).is_none());
+    }
+}
@@ -25,6 +25,13 @@ pub const DRIVER_NUM: usize = 0x20008;
 pub const U2F_CMD_CHECK:    usize = 0;
 pub const U2F_CMD_TRANSMIT: usize = 1;
 pub const U2F_CMD_RECEIVE:  usize = 2;
+pub const U2F_CMD_DUMP_CAPTURE: usize = 3;
+pub const U2F_CMD_SET_DEBUG_VERBOSITY: usize = 4;
+pub const U2F_CMD_REMOTE_WAKEUP: usize = 5;
+pub const U2F_CMD_TRANSMIT_TRANSFER: usize = 6;
+pub const U2F_CMD_RECEIVE_TRANSFER: usize = 7;
+pub const U2F_CMD_DUMP_TRACE: usize = 8;
+pub const U2F_CMD_RECONNECT: usize = 9;
 
 pub const U2F_ALLOW_TRANSMIT: usize = 1;
 pub const U2F_ALLOW_RECEIVE:  usize = 2;
@@ -32,12 +39,14 @@ pub const U2F_ALLOW_RECEIVE:  usize = 2;
 pub const U2F_SUBSCRIBE_TRANSMIT_DONE: usize = 1;
 pub const U2F_SUBSCRIBE_RECEIVE_DONE:  usize = 2;
 pub const U2F_SUBSCRIBE_RECONNECT:     usize = 3;
+pub const U2F_SUBSCRIBE_SUSPEND:       usize = 4;
 
 #[derive(Default)]
 pub struct App {
     tx_callback: Option<Callback>,
     rx_callback: Option<Callback>,
     connection_callback: Option<Callback>,
+    suspend_callback: Option<Callback>,
     tx_buffer: Option<AppSlice<Shared, u8>>,
     rx_buffer: Option<AppSlice<Shared, u8>>,
 }
@@ -56,6 +65,15 @@ impl<'a> U2fSyscallDriver<'a> {
             busy: Cell::new(false)
         }
     }
+
+    // Tells the apps whether the bus is suspended (1) or not (0).
+    fn schedule_suspend_callbacks(&self, suspended: usize) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.suspend_callback.map(|mut cb| cb.schedule(suspended, 0, 0));
+            });
+        }
+    }
 }
 
 impl<'a> UsbHidU2fClient<'a> for U2fSyscallDriver<'a> {
@@ -91,6 +109,33 @@ impl<'a> UsbHidU2fClient<'a> for U2fSyscallDriver<'a> {
             });
         }
     }
+
+    fn transfer_received(&self, len: usize) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                let copied = app.rx_buffer.as_mut().map_or(0, |buf| {
+                    self.u2f_endpoints.get_transfer(buf.as_mut())
+                });
+                app.rx_callback.map(|mut cb| cb.schedule(copied, len, 0));
+            });
+        }
+    }
+
+    fn transfer_transmitted(&self, len: usize) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.tx_callback.map(|mut cb| cb.schedule(len, 0, 0));
+            });
+        }
+    }
+
+    fn suspended(&self) {
+        self.schedule_suspend_callbacks(1);
+    }
+
+    fn resumed(&self) {
+        self.schedule_suspend_callbacks(0);
+    }
 }
 
 impl<'a> Driver for U2fSyscallDriver<'a> {
@@ -113,10 +158,15 @@ impl<'a> Driver for U2fSyscallDriver<'a> {
         }
     }
 
-    /// The USB driver supports 3 callbacks:
+    /// The USB driver supports 4 callbacks:
     ///    - 0: Transmit complete
     ///    - 1: Receive complete
     ///    - 2: Reconnected
+    ///    - 3: Suspended (1) or resumed (0)
+    ///
+    /// After a transfer, the transmit callback gets the bytes sent, and the
+    /// receive callback the bytes copied to the receive buffer and the
+    /// length of the transfer.
     fn subscribe(
         &self,
         subscribe_num: usize,
@@ -139,11 +189,16 @@ impl<'a> Driver for U2fSyscallDriver<'a> {
                 ReturnCode::SUCCESS
             }).unwrap_or_else(|err| err.into()),
 
+            U2F_SUBSCRIBE_SUSPEND => self.apps.enter(app_id, |app, _| {
+                app.suspend_callback = callback;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into()),
+
             _ => ReturnCode::ENOSUPPORT,
         }
     }
 
-    fn command(&self, command_num: usize, _data: usize, _: usize, appid: AppId) -> ReturnCode {
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
         match command_num {
             U2F_CMD_CHECK => ReturnCode::SUCCESS, // Existence check
             U2F_CMD_TRANSMIT => self.apps.enter(appid, |app, _| { // Send packet
@@ -173,6 +228,43 @@ impl<'a> Driver for U2fSyscallDriver<'a> {
             U2F_CMD_RECEIVE => {
                 self.u2f_endpoints.enable_rx()
             },
+            // Prints the USB packet capture, if the board enabled it.
+            U2F_CMD_DUMP_CAPTURE => {
+                self.u2f_endpoints.dump_capture()
+            },
+            // Sets the driver's debug verbosity mask (see usb::debug).
+            U2F_CMD_SET_DEBUG_VERBOSITY => {
+                self.u2f_endpoints.set_debug_verbosity(data as u32)
+            },
+            // Wakes the host from suspend, e.g. when the user touches the
+            // device.
+            U2F_CMD_REMOTE_WAKEUP => {
+                self.u2f_endpoints.remote_wakeup()
+            },
+            // Sends the first `data` bytes of the transmit buffer as one
+            // transfer of up to 1KB.
+            U2F_CMD_TRANSMIT_TRANSFER => self.apps.enter(appid, |app, _| {
+                app.tx_buffer.as_ref().map_or(ReturnCode::ERESERVE, |buf| {
+                    if data > buf.len() {
+                        ReturnCode::ESIZE
+                    } else {
+                        self.u2f_endpoints.put_transfer(&buf.as_ref()[..data])
+                    }
+                })
+            }).unwrap_or_else(|err| err.into()),
+            // Receives the next transfer instead of the next frame.
+            U2F_CMD_RECEIVE_TRANSFER => {
+                self.u2f_endpoints.enable_rx_transfer()
+            },
+            // Prints the USB driver event trace, if the board enabled it.
+            U2F_CMD_DUMP_TRACE => {
+                self.u2f_endpoints.dump_trace()
+            },
+            // Drops off the bus and connects again, so that the host
+            // re-enumerates the device; the connection callback follows.
+            U2F_CMD_RECONNECT => {
+                self.u2f_endpoints.force_reconnect()
+            },
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -0,0 +1,191 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read-only blobs served as HID feature reports.
+//!
+//! Provisioning tools can read the device certificate and the build
+//! information with HID GET_REPORT (feature) requests on the U2F interface,
+//! without going through the U2F channel. The reports are not declared in
+//! the report descriptor, which keeps the U2F reports free of report IDs.
+//!
+//! The report ID selects a blob and a chunk of it: the top two bits are the
+//! blob (see `BLOB_*`) and the low six bits the chunk index. A report is at
+//! most one packet:
+//!
+//! ```text
+//! report ID: u8 | reserved: u8 | blob length: u16 (LE) | up to 60 data bytes
+//! ```
+//!
+//! Chunk `n` carries the blob bytes from `n * CHUNK_DATA_LEN`; chunks past the
+//! end carry no data. Reading chunk 0 takes a new snapshot of the blob, so a
+//! host should read the chunks in order starting at 0.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::TakeCell;
+use kernel::ReturnCode;
+use spiutils::compat::firmware::BUILD_INFO_LEN;
+use spiutils::io::Cursor;
+use spiutils::protocol::wire::ToWire;
+
+use crate::hil::globalsec::GlobalSec;
+use crate::hil::personality::{Personality, PersonalityData, MAX_CERTIFICATE_LEN};
+use crate::info_block::read_build_info;
+use crate::usb::constants::EP_BUFFER_SIZE_BYTES;
+
+/// The HID report type of feature reports, the high byte of wValue.
+pub const FEATURE_REPORT_TYPE: u8 = 3;
+
+/// The device certificate from the personality.
+pub const BLOB_CERTIFICATE: u8 = 1;
+
+/// The build information of the active RO and RW segments.
+pub const BLOB_BUILD_INFO: u8 = 2;
+
+/// The length of the header of each report, in bytes.
+pub const CHUNK_HEADER_LEN: usize = 4;
+
+/// The number of blob bytes in each report.
+pub const CHUNK_DATA_LEN: usize = EP_BUFFER_SIZE_BYTES - CHUNK_HEADER_LEN;
+
+const CHUNK_BITS: u8 = 6;
+const CHUNK_MASK: u8 = (1 << CHUNK_BITS) - 1;
+
+/// Returns the report ID of chunk `chunk` of blob `blob`.
+pub const fn report_id(blob: u8, chunk: u8) -> u8 {
+    (blob << CHUNK_BITS) | (chunk & CHUNK_MASK)
+}
+
+/// Provides the blobs served as feature reports.
+pub trait FeatureReportSource {
+    /// Copies the bytes of `blob` starting at `offset` into `buf`. Returns the
+    /// length of the whole blob and the number of bytes copied, or None if
+    /// there is no such blob. An `offset` of 0 refreshes the blob first.
+    fn read(&self, blob: u8, offset: usize, buf: &mut [u8]) -> Option<(usize, usize)>;
+}
+
+/// Writes the feature report with ID `report_id` into `report`. Returns its
+/// length, or None if the report does not exist.
+pub fn encode_report(source: &dyn FeatureReportSource,
+                     report_id: u8,
+                     report: &mut [u8; EP_BUFFER_SIZE_BYTES]) -> Option<usize> {
+    let blob = report_id >> CHUNK_BITS;
+    let offset = (report_id & CHUNK_MASK) as usize * CHUNK_DATA_LEN;
+    let (blob_len, copied) = source.read(blob, offset, &mut report[CHUNK_HEADER_LEN..])?;
+    report[0] = report_id;
+    report[1] = 0;
+    report[2..CHUNK_HEADER_LEN].copy_from_slice(&(blob_len as u16).to_le_bytes());
+    Some(CHUNK_HEADER_LEN + copied)
+}
+
+/// Copies the part of `blob` starting at `offset` into `buf`.
+fn copy_from(blob: &[u8], offset: usize, buf: &mut [u8]) -> (usize, usize) {
+    let start = cmp::min(offset, blob.len());
+    let copied = cmp::min(blob.len() - start, buf.len());
+    buf[..copied].copy_from_slice(&blob[start..start + copied]);
+    (blob.len(), copied)
+}
+
+/// Serves the device certificate from the personality and the build
+/// information of the active segments.
+pub struct AttestationReports<'a> {
+    personality: &'a dyn Personality<'a>,
+    globalsec: &'a dyn GlobalSec,
+    personality_data: TakeCell<'a, PersonalityData>,
+    build_info: Cell<[u8; 2 * BUILD_INFO_LEN]>,
+}
+
+impl<'a> AttestationReports<'a> {
+    pub fn new(personality: &'a dyn Personality<'a>,
+               globalsec: &'a dyn GlobalSec,
+               personality_data: &'a mut PersonalityData) -> AttestationReports<'a> {
+        AttestationReports {
+            personality: personality,
+            globalsec: globalsec,
+            personality_data: TakeCell::new(personality_data),
+            build_info: Cell::new([0; 2 * BUILD_INFO_LEN]),
+        }
+    }
+
+    fn refresh_build_info(&self) {
+        let segments = self.globalsec.get_runtime_segment_info();
+        let mut build_info = [0u8; 2 * BUILD_INFO_LEN];
+        {
+            let mut cursor = Cursor::new(&mut build_info);
+            // The buffer fits both, so neither write can fail.
+            let _ = read_build_info(segments.active_ro).to_wire(&mut cursor);
+            let _ = read_build_info(segments.active_rw).to_wire(&mut cursor);
+        }
+        self.build_info.set(build_info);
+    }
+}
+
+impl<'a> FeatureReportSource for AttestationReports<'a> {
+    fn read(&self, blob: u8, offset: usize, buf: &mut [u8]) -> Option<(usize, usize)> {
+        match blob {
+            BLOB_CERTIFICATE => self.personality_data.map(|data| {
+                if offset == 0 && self.personality.get(data) != ReturnCode::SUCCESS {
+                    data.certificate_len = 0;
+                }
+                // An unprovisioned personality reads as all ones.
+                let len = data.certificate_len as usize;
+                let len = if len <= MAX_CERTIFICATE_LEN { len } else { 0 };
+                copy_from(&data.certificate[..len], offset, buf)
+            }),
+            BLOB_BUILD_INFO => {
+                if offset == 0 {
+                    self.refresh_build_info();
+                }
+                Some(copy_from(&self.build_info.get(), offset, buf))
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counting;
+
+    // Blob 1 is 100 bytes counting up from 0.
+    impl FeatureReportSource for Counting {
+        fn read(&self, blob: u8, offset: usize, buf: &mut [u8]) -> Option<(usize, usize)> {
+            let mut bytes = [0u8; 100];
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+            if blob == 1 { Some(copy_from(&bytes, offset, buf)) } else { None }
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        let mut report = [0u8; EP_BUFFER_SIZE_BYTES];
+        assert_eq!(encode_report(&Counting, report_id(1, 0), &mut report),
+                   Some(EP_BUFFER_SIZE_BYTES));
+        assert_eq!(report[..6], [0x40, 0, 100, 0, 0, 1]);
+
+        assert_eq!(encode_report(&Counting, report_id(1, 1), &mut report),
+                   Some(CHUNK_HEADER_LEN + 100 - CHUNK_DATA_LEN));
+        assert_eq!(report[..5], [0x41, 0, 100, 0, CHUNK_DATA_LEN as u8]);
+
+        assert_eq!(encode_report(&Counting, report_id(1, 2), &mut report),
+                   Some(CHUNK_HEADER_LEN));
+        assert_eq!(encode_report(&Counting, report_id(2, 0), &mut report), None);
+    }
+}
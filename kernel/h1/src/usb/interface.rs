@@ -0,0 +1,331 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interfaces of the composite USB device.
+//!
+//! Before `USB::init`, the board registers each interface it wants the
+//! device to have: the U2F HID interface, the CDC-ACM serial port (see
+//! `cdc`), or vendor-specific bulk (see `bulk`) or isochronous (see
+//! `isochronous`) interfaces. Interfaces are
+//! numbered in the order they are registered, and each gets a pair of IN
+//! and OUT endpoints with the same number for its data. `InterfaceTable`
+//! keeps the registrations; the driver generates the configuration
+//! descriptor from it and uses it to route interface requests and
+//! endpoint events to the right client.
+
+use kernel::ReturnCode;
+
+use crate::usb::cdc;
+use crate::usb::constants::{EP_BUFFER_SIZE_WORDS, STRING_INTERFACE1, STRING_INTERFACE2};
+use crate::usb::registers::DMADescriptor;
+use crate::usb::types::{EndpointAttributes, EndpointDescriptor,
+                        EndpointSynchronizationType, EndpointTransferType,
+                        EndpointUsageType, HidDeviceDescriptor,
+                        InterfaceDescriptor};
+
+/// The highest endpoint number interfaces can use; endpoint 0 is the
+/// control endpoint.
+pub const MAX_ENDPOINT: usize = 4;
+
+/// The most interfaces the device can have. Each takes at least one
+/// endpoint, so the endpoints run out first. A CDC-ACM function counts
+/// once, although it has two interfaces.
+pub const MAX_INTERFACES: usize = MAX_ENDPOINT;
+
+const CLASS_HID: u8 = 0x03;
+const CLASS_VENDOR: u8 = 0xff;
+
+/// What an interface is, which decides its descriptors, how its endpoints
+/// are set up and which client gets its events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterfaceKind {
+    /// The U2F HID interface, with a pair of interrupt endpoints. Its
+    /// client implements `UsbHidU2fClient`.
+    U2fHid,
+    /// A CDC-ACM serial port, with a pair of bulk endpoints and an
+    /// interrupt endpoint for notifications. Its client implements
+    /// `UsbCdcAcmClient`.
+    CdcAcm { notification_endpoint: usize },
+    /// A vendor-specific interface with a pair of bulk endpoints. Its
+    /// client implements `UsbBulkClient`.
+    VendorBulk { sub_class: u8, protocol: u8 },
+    /// A vendor-specific interface with a pair of asynchronous isochronous
+    /// endpoints, polled every frame. Its client implements
+    /// `UsbIsochronousClient`.
+    VendorIsochronous { sub_class: u8, protocol: u8 },
+}
+
+impl InterfaceKind {
+    /// The number of interface numbers it takes.
+    pub fn interface_count(&self) -> u8 {
+        match *self {
+            InterfaceKind::CdcAcm { .. } => 2,
+            _ => 1,
+        }
+    }
+
+    /// The transfer type of its data endpoints.
+    pub fn transfer_type(&self) -> EndpointTransferType {
+        match *self {
+            InterfaceKind::U2fHid => EndpointTransferType::Interrupt,
+            InterfaceKind::VendorIsochronous { .. } => EndpointTransferType::Isochronous,
+            _ => EndpointTransferType::Bulk,
+        }
+    }
+
+    /// Writes the descriptors of an interface of this kind, numbered from
+    /// `first_interface` and with its data on `endpoint`, into `buf`.
+    /// Returns the number of bytes written.
+    pub fn write_descriptors(&self, first_interface: u8, endpoint: usize, buf: &mut [u8]) -> usize {
+        let transfer = self.transfer_type();
+        let attributes = || EndpointAttributes {
+            transfer: transfer,
+            synchronization: if transfer == EndpointTransferType::Isochronous {
+                EndpointSynchronizationType::Asynchronous
+            } else {
+                EndpointSynchronizationType::None
+            },
+            usage: EndpointUsageType::Data,
+        };
+        let interval = match transfer {
+            EndpointTransferType::Interrupt => 2,
+            EndpointTransferType::Isochronous => 1,
+            _ => 0,
+        };
+        let ep_out = EndpointDescriptor::new(endpoint as u8, attributes(), interval);
+        let ep_in = EndpointDescriptor::new(0x80 | endpoint as u8, attributes(), interval);
+
+        let mut size = 0;
+        match *self {
+            InterfaceKind::U2fHid => {
+                let u2f = InterfaceDescriptor::new(STRING_INTERFACE2, first_interface, CLASS_HID, 0, 0);
+                let hid = HidDeviceDescriptor::new();
+                size += u2f.into_u8_buf(&mut buf[size..size + u2f.length()]);
+                size += hid.into_u8_buf(&mut buf[size..size + hid.length()]);
+            },
+            InterfaceKind::CdcAcm { notification_endpoint } => {
+                return cdc::write_descriptors(first_interface, endpoint, notification_endpoint, buf);
+            },
+            InterfaceKind::VendorBulk { sub_class, protocol } |
+            InterfaceKind::VendorIsochronous { sub_class, protocol } => {
+                let vendor = InterfaceDescriptor::new(STRING_INTERFACE1, first_interface,
+                                                      CLASS_VENDOR, sub_class, protocol);
+                size += vendor.into_u8_buf(&mut buf[size..size + vendor.length()]);
+            },
+        }
+        size += ep_out.into_u8_buf(&mut buf[size..size + ep_out.length()]);
+        size += ep_in.into_u8_buf(&mut buf[size..size + ep_in.length()]);
+        size
+    }
+}
+
+/// The DMA descriptors and buffers of an interface's data endpoints.
+pub struct EndpointBuffers {
+    pub out_descriptor: &'static mut DMADescriptor,
+    pub out_buffer: &'static mut [u32; EP_BUFFER_SIZE_WORDS],
+    pub in_descriptor: &'static mut DMADescriptor,
+    pub in_buffer: &'static mut [u32; EP_BUFFER_SIZE_WORDS],
+}
+
+/// A registered interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interface {
+    pub kind: InterfaceKind,
+    /// Its (first) interface number.
+    pub number: u8,
+    /// The number of its data endpoints.
+    pub endpoint: usize,
+}
+
+impl Interface {
+    /// Whether it owns interface number `number`.
+    pub fn has_number(&self, number: u8) -> bool {
+        number >= self.number && number < self.number + self.kind.interface_count()
+    }
+}
+
+/// The interfaces registered so far.
+#[derive(Clone, Copy)]
+pub struct InterfaceTable {
+    interfaces: [Option<Interface>; MAX_INTERFACES],
+    // Bit n is set if endpoint n is taken.
+    endpoints: u32,
+    next_number: u8,
+}
+
+impl InterfaceTable {
+    pub const fn new() -> InterfaceTable {
+        InterfaceTable {
+            interfaces: [None; MAX_INTERFACES],
+            endpoints: 1, // Endpoint 0 is the control endpoint.
+            next_number: 0,
+        }
+    }
+
+    /// Adds an interface of `kind` with its data on `endpoint`. Fails with
+    /// EINVAL if the endpoints are out of range or already taken.
+    pub fn add(&mut self, kind: InterfaceKind, endpoint: usize) -> Result<Interface, ReturnCode> {
+        if endpoint > MAX_ENDPOINT {
+            return Err(ReturnCode::EINVAL);
+        }
+        let mut endpoints = 1 << endpoint;
+        if let InterfaceKind::CdcAcm { notification_endpoint } = kind {
+            if notification_endpoint > MAX_ENDPOINT || notification_endpoint == endpoint {
+                return Err(ReturnCode::EINVAL);
+            }
+            endpoints |= 1 << notification_endpoint;
+        }
+        if self.endpoints & endpoints != 0 {
+            return Err(ReturnCode::EINVAL);
+        }
+        let slot = self.interfaces.iter().position(|interface| interface.is_none())
+            .ok_or(ReturnCode::ENOMEM)?;
+        let interface = Interface {
+            kind: kind,
+            number: self.next_number,
+            endpoint: endpoint,
+        };
+        self.interfaces[slot] = Some(interface);
+        self.endpoints |= endpoints;
+        self.next_number += kind.interface_count();
+        Ok(interface)
+    }
+
+    /// The number of interface numbers taken, for the configuration
+    /// descriptor.
+    pub fn interface_count(&self) -> u8 {
+        self.next_number
+    }
+
+    /// The interfaces in the order they were registered.
+    pub fn iter(&self) -> impl Iterator<Item = Interface> + '_ {
+        self.interfaces.iter().filter_map(|interface| *interface)
+    }
+
+    /// The interface that owns interface number `number`.
+    pub fn by_number(&self, number: u8) -> Option<Interface> {
+        self.iter().find(|interface| interface.has_number(number))
+    }
+
+    /// The interface whose data is on `endpoint`.
+    pub fn by_endpoint(&self, endpoint: usize) -> Option<Interface> {
+        self.iter().find(|interface| interface.endpoint == endpoint)
+    }
+
+    /// The first interface that `matches` accepts.
+    pub fn find<F: Fn(InterfaceKind) -> bool>(&self, matches: F) -> Option<Interface> {
+        self.iter().find(|interface| matches(interface.kind))
+    }
+
+    /// Writes the descriptors of all interfaces into `buf`, returning the
+    /// number of bytes written.
+    pub fn write_descriptors(&self, buf: &mut [u8]) -> usize {
+        let mut size = 0;
+        for interface in self.iter() {
+            size += interface.kind.write_descriptors(interface.number, interface.endpoint,
+                                                     &mut buf[size..]);
+        }
+        size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    // Returns (interface number, class) of each interface descriptor and
+    // the address of each endpoint descriptor in `descriptors`.
+    fn parse(descriptors: &[u8]) -> (Vec<(u8, u8)>, Vec<u8>) {
+        let mut interfaces = Vec::new();
+        let mut endpoints = Vec::new();
+        let mut offset = 0;
+        while offset < descriptors.len() {
+            match descriptors[offset + 1] {
+                4 => interfaces.push((descriptors[offset + 2], descriptors[offset + 5])),
+                5 => endpoints.push(descriptors[offset + 2]),
+                _ => {}
+            }
+            offset += descriptors[offset] as usize;
+        }
+        assert_eq!(offset, descriptors.len());
+        (interfaces, endpoints)
+    }
+
+    #[test]
+    fn numbers_interfaces_in_order() {
+        let mut table = InterfaceTable::new();
+        let u2f = table.add(InterfaceKind::U2fHid, 1).unwrap();
+        let cdc = table.add(InterfaceKind::CdcAcm { notification_endpoint: 3 }, 2).unwrap();
+        let vendor = table.add(InterfaceKind::VendorBulk { sub_class: 80, protocol: 1 }, 4).unwrap();
+        assert_eq!((u2f.number, cdc.number, vendor.number), (0, 1, 3));
+        assert_eq!(table.interface_count(), 4);
+        assert_eq!(table.by_number(2), Some(cdc));
+        assert_eq!(table.by_number(4), None);
+        assert_eq!(table.by_endpoint(4), Some(vendor));
+        assert_eq!(table.by_endpoint(3), None);
+        assert_eq!(table.find(|kind| kind == InterfaceKind::U2fHid), Some(u2f));
+
+        let mut buf = [0u8; 256];
+        let len = table.write_descriptors(&mut buf);
+        let (interfaces, endpoints) = parse(&buf[..len]);
+        assert_eq!(interfaces, [(0, CLASS_HID), (1, 0x02), (2, 0x0a), (3, CLASS_VENDOR)]);
+        assert_eq!(endpoints, [0x01, 0x81, 0x83, 0x02, 0x82, 0x04, 0x84]);
+    }
+
+    #[test]
+    fn describes_isochronous_endpoints() {
+        let mut table = InterfaceTable::new();
+        table.add(InterfaceKind::VendorIsochronous { sub_class: 1, protocol: 2 }, 3).unwrap();
+        let mut buf = [0u8; 64];
+        let len = table.write_descriptors(&mut buf);
+        let (interfaces, endpoints) = parse(&buf[..len]);
+        assert_eq!(interfaces, [(0, CLASS_VENDOR)]);
+        assert_eq!(endpoints, [0x03, 0x83]);
+        // Asynchronous isochronous data endpoints, every frame.
+        assert_eq!((buf[9 + 3], buf[9 + 6]), (0b0101, 1));
+        assert_eq!((buf[16 + 3], buf[16 + 6]), (0b0101, 1));
+    }
+
+    #[test]
+    fn rejects_taken_endpoints() {
+        let mut table = InterfaceTable::new();
+        table.add(InterfaceKind::U2fHid, 1).unwrap();
+        assert_eq!(table.add(InterfaceKind::U2fHid, 1), Err(ReturnCode::EINVAL));
+        assert_eq!(table.add(InterfaceKind::U2fHid, 0), Err(ReturnCode::EINVAL));
+        assert_eq!(table.add(InterfaceKind::U2fHid, MAX_ENDPOINT + 1), Err(ReturnCode::EINVAL));
+        assert_eq!(table.add(InterfaceKind::CdcAcm { notification_endpoint: 1 }, 2),
+                   Err(ReturnCode::EINVAL));
+        assert_eq!(table.add(InterfaceKind::CdcAcm { notification_endpoint: 2 }, 2),
+                   Err(ReturnCode::EINVAL));
+        // Failed registrations take nothing.
+        assert_eq!(table.add(InterfaceKind::CdcAcm { notification_endpoint: 3 }, 2).unwrap().number, 1);
+        assert_eq!(table.add(InterfaceKind::VendorBulk { sub_class: 0, protocol: 0 }, 3),
+                   Err(ReturnCode::EINVAL));
+    }
+
+    #[test]
+    fn runs_out_of_endpoints() {
+        let mut table = InterfaceTable::new();
+        for endpoint in 1..=MAX_INTERFACES {
+            table.add(InterfaceKind::VendorBulk { sub_class: 0, protocol: 0 }, endpoint).unwrap();
+        }
+        assert_eq!(table.add(InterfaceKind::U2fHid, MAX_ENDPOINT + 1), Err(ReturnCode::EINVAL));
+        assert_eq!(table.interface_count(), MAX_INTERFACES as u8);
+        assert_eq!(table.iter().count(), MAX_INTERFACES);
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Vendor-specific isochronous interfaces.
+//!
+//! A board registers one with `InterfaceKind::VendorIsochronous` (see
+//! `interface`) for streams such as audio or sensor samples, which want a
+//! packet every frame and would rather lose one than fall behind. Unlike a
+//! bulk packet, each isochronous packet belongs to a particular 1ms
+//! full-speed frame: the client reads the current frame number and queues
+//! the packet for a later frame through `UsbIsochronous`. If the frame has
+//! gone by when the core gets to the packet, it is dropped, and the client
+//! hears that it was not delivered.
+
+use kernel::ReturnCode;
+
+/// Frame numbers count modulo 2048.
+pub const FRAME_NUMBER_MASK: u16 = 0x7ff;
+
+/// The frame `frames` after `frame`.
+pub fn frame_after(frame: u16, frames: u16) -> u16 {
+    frame.wrapping_add(frames) & FRAME_NUMBER_MASK
+}
+
+/// Whether `frame` is still to come at frame `current`. Frames up to half
+/// the frame number range ahead count as the future, the rest as the past.
+pub fn is_future_frame(current: u16, frame: u16) -> bool {
+    let ahead = frame.wrapping_sub(current) & FRAME_NUMBER_MASK;
+    ahead != 0 && ahead <= FRAME_NUMBER_MASK / 2
+}
+
+/// Trait the USB driver implements to carry vendor isochronous interfaces.
+/// Each method takes the endpoint number of the interface.
+pub trait UsbIsochronous<'a> {
+    fn set_isochronous_client(&self, endpoint: usize, client: &'a dyn UsbIsochronousClient);
+
+    /// The number of the current frame.
+    fn frame_number(&self) -> u16;
+
+    /// Returns whether the IN endpoint is free to queue a packet.
+    fn isochronous_transmit_ready(&self, endpoint: usize) -> bool;
+
+    /// Queues up to `MAX_PACKET_SIZE` bytes to go to the host in `frame`.
+    /// Fails with EINVAL if `frame` is not in the future.
+    fn isochronous_put_slice(&self, endpoint: usize, frame: u16, slice: &[u8]) -> ReturnCode;
+
+    /// Copies the packet that `packet_received` announced into `slice`,
+    /// returning its length.
+    fn isochronous_get_slice(&self, endpoint: usize, slice: &mut [u8]) -> usize;
+
+    /// Accepts the packet the host sends in `frame`. Fails with EINVAL if
+    /// `frame` is not in the future.
+    fn isochronous_enable_rx(&self, endpoint: usize, frame: u16) -> ReturnCode;
+}
+
+/// Client for the UsbIsochronous trait. `delivered` is false if the packet
+/// for `frame` was dropped because its frame had passed, or was damaged.
+pub trait UsbIsochronousClient {
+    fn packet_received(&self, endpoint: usize, frame: u16, delivered: bool);
+    fn packet_transmitted(&self, endpoint: usize, frame: u16, delivered: bool);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_frame_numbers() {
+        assert_eq!(frame_after(10, 5), 15);
+        assert_eq!(frame_after(2046, 3), 1);
+        assert!(is_future_frame(10, 11));
+        assert!(is_future_frame(2046, 1));
+        assert!(is_future_frame(0, 1023));
+        assert!(!is_future_frame(10, 10));
+        assert!(!is_future_frame(10, 9));
+        assert!(!is_future_frame(1, 2046));
+        assert!(!is_future_frame(0, 1024));
+    }
+}
@@ -14,77 +14,119 @@
 
 #![allow(dead_code)]
 
+pub mod bulk;
+pub mod bulk_driver;
+pub mod capture;
+pub mod cdc;
+pub mod cdc_uart;
 pub mod constants;
+pub mod control;
 pub mod driver;
+pub mod feature_report;
+pub mod interface;
+pub mod isochronous;
 mod registers;
 mod serialize;
+pub mod trace;
+pub mod transfer;
 pub mod types;
 pub mod u2f;
+pub mod u2fhid;
+pub mod u2fhid_driver;
 
 pub use self::constants::Descriptor;
-pub use self::types::StringDescriptor;
+pub use self::types::{StringDescriptor, StringDescriptors};
 
 use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
 use cortexm3::support;
 use kernel::ReturnCode;
-use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::common::registers::{LocalRegisterCopy};
+use crate::dma_pool::{Align4, DmaPool};
 use crate::pmu::{Clock, PeripheralClock, PeripheralClock1};
-
+use crate::timeus::Timeus;
+
+use self::capture::{CaptureKind, CaptureRecord, UsbCapture, CAPTURE_RECORD_COUNT};
+use self::bulk::{UsbBulk, UsbBulkClient};
+use self::cdc::{LineCoding, UsbCdcAcm, UsbCdcAcmClient, CONTROL_LINE_DTR,
+                LINE_CODING_LEN};
+use self::interface::{EndpointBuffers, Interface, InterfaceKind,
+                      InterfaceTable, MAX_ENDPOINT};
+use self::feature_report::{FeatureReportSource, FEATURE_REPORT_TYPE};
+use self::isochronous::{is_future_frame, UsbIsochronous, UsbIsochronousClient,
+                        FRAME_NUMBER_MASK};
 use self::constants::*;
+use self::control::{ControlOut, UsbControlClient};
 use self::registers::{AhbConfig, AllEndpointInterrupt, DescFlag,
-                      DeviceConfig, DeviceControl, DMADescriptor,
+                      DeviceConfig, DeviceControl, DeviceStatus, DMADescriptor,
                       EndpointControl, Gpio, InEndpointInterruptMask,
-                      Interrupt, OutEndpointInterruptMask, Registers,
-                      Reset, UsbConfiguration};
+                      Interrupt, OtgInterrupt, OutEndpointInterruptMask,
+                      PowerClockGatingControl, Registers, Reset,
+                      UsbConfiguration};
 use self::types::{ConfigurationDescriptor, DeviceDescriptor,
-                  EndpointAttributes, EndpointDescriptor,
-                  EndpointSynchronizationType, EndpointTransferType,
-                  EndpointUsageType, HidDeviceDescriptor,
-                  InterfaceDescriptor, SetupDirection, SetupRecipient,
+                  EndpointTransferType, InterfaceDescriptor, SetupDirection, SetupRecipient,
                   SetupRequest, SetupRequestClass, SetupRequestType,
                   StaticRef};
+use self::trace::{TraceEvent, TraceRecord, UsbTrace, TRACE_RECORD_COUNT};
+use self::transfer::{TransferBuffers, TRANSFER_PACKET_COUNT, TRANSFER_SIZE_BYTES,
+                     TRANSFER_SIZE_WORDS};
 use self::u2f::{UsbHidU2f, UsbHidU2fClient};
 
-// Simple macros for USB debugging output: default definitions do nothing,
-// but you can uncomment print defintions to get detailed output on the
-// messages sent and received.
+/// Categories of USB debug messages. The driver prints the messages of the
+/// categories set in its verbosity mask (see `USB::set_debug_verbosity`).
+pub mod debug {
+    /// Enumeration and EP0 control transfers.
+    pub const CONTROL: u32 = 1 << 0;
+    /// Data transfers on the interfaces' endpoints.
+    pub const DATA: u32 = 1 << 1;
+    /// Interrupt handling.
+    pub const INTERRUPT: u32 = 1 << 2;
+    /// All categories.
+    pub const ALL: u32 = CONTROL | DATA | INTERRUPT;
+}
+
+// The debug verbosity mask. Only debug builds print, so that release builds
+// carry neither the messages nor the checks.
+static DEBUG_VERBOSITY: AtomicU32 = AtomicU32::new(0);
+
+#[cfg(debug_assertions)]
+macro_rules! usb_debug {
+    ($category:expr, $($arg:tt)+) => ({
+        if DEBUG_VERBOSITY.load(Ordering::Relaxed) & $category != 0 {
+            print!($($arg)+);
+        }
+    });
+}
+
+#[cfg(not(debug_assertions))]
+macro_rules! usb_debug {
+    ($category:expr, $($arg:tt)+) => ({});
+}
 
 macro_rules! control_debug { // Debug messages for enumeration/EP0 control
-//    () => ({print!();});
-//    ($fmt:expr) => ({print!($fmt);});
-//    ($fmt:expr, $($arg:tt)+) => ({print!($fmt, $($arg)+);});
-    () => ({});
-    ($fmt:expr) => ({});
-    ($fmt:expr, $($arg:tt)+) => ({});
+    ($($arg:tt)+) => (usb_debug!(debug::CONTROL, $($arg)+));
 }
 
-macro_rules! data_debug { // Debug messages for data/EP1
-//    () => ({print!();});
-//    ($fmt:expr) => ({print!($fmt);});
-//    ($fmt:expr, $($arg:tt)+) => ({print!($fmt, $($arg)+);});
-    () => ({});
-    ($fmt:expr) => ({});
-    ($fmt:expr, $($arg:tt)+) => ({});
+macro_rules! data_debug { // Debug messages for data endpoints
+    ($($arg:tt)+) => (usb_debug!(debug::DATA, $($arg)+));
 }
 
 macro_rules! int_debug { // Debug messages for interrupt handling
-//    () => ({print!();});
-//    ($fmt:expr) => ({print!($fmt);});
-//    ($fmt:expr, $($arg:tt)+) => ({print!($fmt, $($arg)+);});
-    () => ({});
-    ($fmt:expr) => ({});
-    ($fmt:expr, $($arg:tt)+) => ({});
+    ($($arg:tt)+) => (usb_debug!(debug::INTERRUPT, $($arg)+));
 }
 
 /// USBState encodes the current state of the USB driver's state
-/// machine. It can be in three states: waiting for a message from
-/// the host, sending data in reply to a query from the host, or sending
-/// a status response (no data) in reply to a command from the host.
+/// machine. It can be in four states: waiting for a message from
+/// the host, sending data in reply to a query from the host, receiving
+/// the data of a command from the host, or sending a status response
+/// (no data) in reply to a command from the host.
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum USBState {
     WaitingForSetupPacket,   // Waiting for message from host
     DataStageIn,             // Sending data to host
+    DataStageOut,            // Receiving data from host, for class and
+    // vendor requests to an interface (see `control`)
     NoDataStage,             // Sending status (not data) to host,
     // e.g. in response to set command
 }
@@ -93,6 +135,10 @@ enum USBState {
 const EP0_IN_BUFFER_COUNT:  usize = 4;
 const EP0_OUT_BUFFER_COUNT: usize = 2;
 
+/// The configuration descriptor is sent in a single EP0 IN transfer, so it
+/// can use all of the EP0 IN buffers.
+pub const CONFIGURATION_BUFFER_SIZE: usize = EP_BUFFER_SIZE_BYTES * EP0_IN_BUFFER_COUNT;
+
 /// Driver for the Synopsys DesignWare Cores USB 2.0 Hi-Speed
 /// On-The-Go (OTG) controller.
 ///
@@ -100,7 +146,9 @@ const EP0_OUT_BUFFER_COUNT: usize = 2;
 /// 2.0 Hi-Speed On-The-Go (OTG) Programmer's Guide.
 ///
 /// The driver can enumerate (appear as a device to a host OS) and
-/// exchange data on EP1. The driver operates as a device in
+/// exchange data on the endpoints of the interfaces the board registered
+/// (see `interface`): U2F HID, a CDC-ACM serial port and vendor bulk
+/// and isochronous interfaces. The driver operates as a device in
 /// Scatter-Gather DMA mode (Figure 1-1) and performs the initial
 /// handshakes with the host on endpoint 0. An uninitialized drive
 /// appears as a counterfeit flash device (vendor id: 0011, product
@@ -117,10 +165,10 @@ const EP0_OUT_BUFFER_COUNT: usize = 2;
 /// For endpoint 0, the driver configures 2 OUT descriptors and 4 IN
 /// descriptors. Four IN descriptors allows responses up to 256 bytes
 /// (64 * 4), which is important for sending the device configuration
-/// descriptor as one big blob.  The driver never expects to receive
-/// OUT packets larger than 64 bytes (the maximum each descriptor can
-/// handle). It uses two OUT descriptors so it can receive a packet
-/// while processing the previous one.
+/// descriptor as one big blob.  OUT packets are at most 64 bytes (the
+/// maximum each descriptor can handle); a longer data stage is received
+/// one packet at a time. It uses two OUT descriptors so it can receive a
+/// packet while processing the previous one.
 ///
 /// The USB stack currently assumes the presence of 7
 /// StringDescriptors, which are provided by the boot sequence. The
@@ -147,20 +195,33 @@ pub struct USB<'a> {
     ep0_in_descriptors: TakeCell<'static, [DMADescriptor; EP0_IN_BUFFER_COUNT]>,
     ep0_in_buffers: TakeCell<'static, [u32; 16 * EP0_IN_BUFFER_COUNT]>,
 
+    // The control OUT request whose data stage is being received.
+    control_out: MapCell<ControlOut>,
+
     // Track the index of which ep0_out descriptor is currently set
     // for reception and which descriptor received the most
     // recent packet.
     next_ep0_out_idx: Cell<usize>,
     last_ep0_out_idx: Cell<usize>,
 
-    // EP1 is used for application messages: userspace applications
-    // can communicate using them through system calls. EP1 in and out
-    // are both 64-byte buffers.
-    ep1_out_descriptor: TakeCell<'static, DMADescriptor>,
-    ep1_out_buffer: Cell<Option<&'static [u32; EP_BUFFER_SIZE_WORDS]>>,
-    ep1_in_descriptor: TakeCell<'static, DMADescriptor>,
-    ep1_in_buffer: TakeCell<'static,[u32; EP_BUFFER_SIZE_WORDS]>,
-
+    // The interfaces the board registered, and the data endpoints they
+    // use for application messages, indexed by endpoint number minus
+    // one: userspace applications can communicate using them through
+    // system calls. A CDC-ACM notification endpoint never sends
+    // anything, so it has no buffers.
+    interfaces: Cell<InterfaceTable>,
+    endpoints: [DataEndpoint<'a>; MAX_ENDPOINT],
+
+    // Descriptor chains and buffers for transfers of more than one packet
+    // on the U2F endpoint, if the board enabled them (see `transfer`);
+    // whether the OUT chain is armed, and the length being sent on the IN
+    // chain.
+    transfer_out_descriptors: TakeCell<'static, [DMADescriptor; TRANSFER_PACKET_COUNT]>,
+    transfer_out_buffer: TakeCell<'static, [u32; TRANSFER_SIZE_WORDS]>,
+    transfer_in_descriptors: TakeCell<'static, [DMADescriptor; TRANSFER_PACKET_COUNT]>,
+    transfer_in_buffer: TakeCell<'static, [u32; TRANSFER_SIZE_WORDS]>,
+    transfer_receiving: Cell<bool>,
+    transfer_sending: Cell<Option<usize>>,
 
     // Numeric configurations set by instantation. These values are
     // filled into USB Descriptors as part of enumeration.
@@ -172,8 +233,8 @@ pub struct USB<'a> {
     // ConfigurationDescriptor. `configuration_total_length` is the
     // length. The function `generate_full_configuration_descriptor`
     // populates these values. The ConfigurationDescriptor is limited
-    // to a single 64 byte buffer.
-    configuration_descriptor: TakeCell<'static, [u8; EP_BUFFER_SIZE_BYTES]>,
+    // to the EP0 IN buffers.
+    configuration_descriptor: TakeCell<'static, [u8; CONFIGURATION_BUFFER_SIZE]>,
     configuration_total_length: Cell<u16>,
 
     // Which USB configuration is currently being used.
@@ -187,11 +248,90 @@ pub struct USB<'a> {
 
     // Client to give callbacks to.
     u2f_client: OptionalCell<&'a dyn UsbHidU2fClient<'a>>,
+
+    // The CDC-ACM client, the line coding the host set, and whether the
+    // host has the port open (DTR).
+    cdc_client: OptionalCell<&'a dyn UsbCdcAcmClient>,
+    line_coding: Cell<LineCoding>,
+    cdc_port_open: Cell<bool>,
+
+    // Set when the host went away (cable unplugged or forced reconnect), so
+    // that the client is told once the host has configured us again.
+    reconnecting: Cell<bool>,
+
+    // Set while the bus is suspended and the PHY clock stopped, and whether
+    // the host allows us to wake it (SET_FEATURE DEVICE_REMOTE_WAKEUP).
+    suspended: Cell<bool>,
+    remote_wakeup_enabled: Cell<bool>,
+
+    // The data endpoints the host halted (SET_FEATURE ENDPOINT_HALT), one
+    // bit per direction laid out like DAINT.
+    halted_endpoints: Cell<u32>,
+
+    // Optional packet capture and event trace for debugging enumeration.
+    capture: UsbCapture<'a>,
+    trace: UsbTrace<'a>,
+
+    // Blobs served as HID feature reports, if the board provides them.
+    feature_reports: OptionalCell<&'a dyn FeatureReportSource>,
+}
+
+/// A data endpoint: 64-byte IN and OUT buffers with their DMA
+/// descriptors, set when an interface is registered on it.
+struct DataEndpoint<'a> {
+    out_descriptor: TakeCell<'static, DMADescriptor>,
+    out_buffer: Cell<Option<&'static [u32; EP_BUFFER_SIZE_WORDS]>>,
+    in_descriptor: TakeCell<'static, DMADescriptor>,
+    in_buffer: TakeCell<'static, [u32; EP_BUFFER_SIZE_WORDS]>,
+    // The client of a vendor bulk interface. U2F and CDC-ACM have one
+    // client each, kept by the driver.
+    bulk_client: OptionalCell<&'a dyn UsbBulkClient>,
+    // The client of a vendor isochronous interface, and the frames its
+    // next packets in each direction are scheduled for.
+    isochronous_client: OptionalCell<&'a dyn UsbIsochronousClient>,
+    in_frame: Cell<u16>,
+    out_frame: Cell<u16>,
+    // The client for class and vendor requests with data to the
+    // interface.
+    control_client: OptionalCell<&'a dyn UsbControlClient>,
+}
+
+impl<'a> DataEndpoint<'a> {
+    const fn new() -> DataEndpoint<'a> {
+        DataEndpoint {
+            out_descriptor: TakeCell::empty(),
+            out_buffer: Cell::new(None),
+            in_descriptor: TakeCell::empty(),
+            in_buffer: TakeCell::empty(),
+            bulk_client: OptionalCell::empty(),
+            isochronous_client: OptionalCell::empty(),
+            in_frame: Cell::new(0),
+            out_frame: Cell::new(0),
+            control_client: OptionalCell::empty(),
+        }
+    }
 }
 
+// The DAINT/DAINTMASK bits of an endpoint's IN and OUT directions.
+const fn endpoint_interrupt_bits(endpoint: usize) -> u32 {
+    (1 << endpoint) | (1 << (16 + endpoint))
+}
+
+// Iterations of the busy-wait while driving remote wakeup signaling: USB
+// 2.0 (7.1.7.7) wants 1 to 15ms of resume, and this is about 5ms at 24MHz.
+const REMOTE_WAKEUP_SIGNALING_NOPS: u32 = 40000;
+
+// Iterations of the busy-wait while soft disconnected. The host notices a
+// disconnect after 2.5us of SE0 (USB 2.0 7.1.7.3); this is about 400us at
+// 24MHz.
+const SOFT_DISCONNECT_NOPS: u32 = 10000;
+
 // Hardware base address of the singleton USB controller
 const BASE_ADDR: *const Registers = 0x40300000 as *const Registers;
-pub static mut USB0: USB<'static> = unsafe { USB::new() };
+
+pub(crate) const unsafe fn usb0() -> USB<'static> {
+    USB::new()
+}
 
 impl<'a> USB<'a> {
     /// Creates a new value referencing the single USB driver.  After
@@ -213,10 +353,16 @@ impl<'a> USB<'a> {
             ep0_out_buffers: Cell::new(None),
             ep0_in_descriptors: TakeCell::empty(),
             ep0_in_buffers: TakeCell::empty(),
-            ep1_out_descriptor: TakeCell::empty(),
-            ep1_out_buffer: Cell::new(None),
-            ep1_in_descriptor: TakeCell::empty(),
-            ep1_in_buffer: TakeCell::empty(),
+            control_out: MapCell::empty(),
+            interfaces: Cell::new(InterfaceTable::new()),
+            endpoints: [DataEndpoint::new(), DataEndpoint::new(),
+                        DataEndpoint::new(), DataEndpoint::new()],
+            transfer_out_descriptors: TakeCell::empty(),
+            transfer_out_buffer: TakeCell::empty(),
+            transfer_in_descriptors: TakeCell::empty(),
+            transfer_in_buffer: TakeCell::empty(),
+            transfer_receiving: Cell::new(false),
+            transfer_sending: Cell::new(None),
             configuration_descriptor: TakeCell::empty(),
             next_ep0_out_idx: Cell::new(0),
             last_ep0_out_idx: Cell::new(0),
@@ -227,6 +373,16 @@ impl<'a> USB<'a> {
             configuration_total_length: Cell::new(0),
             strings: TakeCell::empty(),
             u2f_client: OptionalCell::empty(),
+            cdc_client: OptionalCell::empty(),
+            line_coding: Cell::new(LineCoding::DEFAULT),
+            cdc_port_open: Cell::new(false),
+            reconnecting: Cell::new(false),
+            suspended: Cell::new(false),
+            remote_wakeup_enabled: Cell::new(false),
+            halted_endpoints: Cell::new(0),
+            capture: UsbCapture::new(),
+            trace: UsbTrace::new(),
+            feature_reports: OptionalCell::empty(),
         }
     }
 
@@ -260,49 +416,285 @@ impl<'a> USB<'a> {
     /// Reset the device in response to a USB RESET.
     fn usb_reset(&self) {
         control_debug!("USB: WaitingForSetupPacket in reset.\n");
+        self.trace.record(TraceEvent::Reset, 0);
+        self.reset_ep0();
+    }
+
+    /// Returns EP0 to its state before enumeration, waiting for the first
+    /// SETUP packet at address 0.
+    fn reset_ep0(&self) {
         self.state.set(USBState::WaitingForSetupPacket);
         // Reset device address field (bits 10:4) of device config
         self.registers.device_config.modify(DeviceConfig::DeviceAddress.val(0));
+        // A reset clears the features the host set.
+        self.remote_wakeup_enabled.set(false);
+        self.halted_endpoints.set(0);
+        self.control_out.take();
         self.init_ep0_descriptors();
         self.expect_setup_packet();
     }
 
-    fn ep1_tx_fifo_is_ready(&self) -> bool {
-        self.ep1_in_descriptor.map_or(false, |desc| {
-            desc.flags & DescFlag::STATUS_MASK == DescFlag::DMA_DONE ||
-                desc.flags & DescFlag::STATUS_MASK == DescFlag::HOST_BUSY
+    fn endpoint(&self, endpoint: usize) -> Option<&DataEndpoint<'a>> {
+        if endpoint == 0 {
+            None
+        } else {
+            self.endpoints.get(endpoint - 1)
+        }
+    }
+
+    /// The data endpoint of the (first) U2F HID interface.
+    fn u2f_endpoint(&self) -> Option<usize> {
+        self.interfaces.get()
+            .find(|kind| kind == InterfaceKind::U2fHid)
+            .map(|interface| interface.endpoint)
+    }
+
+    /// The CDC-ACM interface, if the board registered one.
+    fn cdc_interface(&self) -> Option<Interface> {
+        self.interfaces.get().find(|kind| matches!(kind, InterfaceKind::CdcAcm { .. }))
+    }
+
+    fn cdc_enabled(&self) -> bool {
+        self.cdc_interface().is_some()
+    }
+
+    /// Whether the board gave a serial number string; without one, the
+    /// device descriptor reports index 0 (no serial number).
+    fn has_serial_number(&self) -> bool {
+        self.strings.map_or(false, |strs| {
+            strs.get(STRING_SERIAL as usize).map_or(false, |s| !s.is_empty())
         })
     }
 
-    fn ep1_rx_fifo_is_ready(&self) -> bool {
-        self.ep1_out_descriptor.map_or(false, |desc| {
-            desc.flags & DescFlag::DMA_DONE == DescFlag::DMA_DONE
+    /// Whether `endpoint` belongs to a vendor bulk interface.
+    fn is_bulk_endpoint(&self, endpoint: usize) -> bool {
+        self.interfaces.get().by_endpoint(endpoint).map_or(false, |interface| {
+            matches!(interface.kind, InterfaceKind::VendorBulk { .. })
         })
     }
 
-    fn ep1_enable_tx(&self) {
-        self.ep1_in_descriptor.map(|desc| {
-            desc.flags = (DescFlag::LAST |
-                          DescFlag::HOST_READY |
-                          DescFlag::IOC).bytes(U2F_REPORT_SIZE);
-            self.registers.in_endpoints[1].control.modify(EndpointControl::Enable::SET +
-                                                          EndpointControl::ClearNak::SET);
+    /// Whether `endpoint` belongs to a vendor isochronous interface.
+    fn is_isochronous_endpoint(&self, endpoint: usize) -> bool {
+        self.interfaces.get().by_endpoint(endpoint).map_or(false, |interface| {
+            matches!(interface.kind, InterfaceKind::VendorIsochronous { .. })
+        })
+    }
+
+    /// The number of the current frame; in full-speed mode the low bits
+    /// of DSTS.SOFFN.
+    fn current_frame(&self) -> u16 {
+        self.registers.device_status.read(DeviceStatus::FrameNumber) as u16 & FRAME_NUMBER_MASK
+    }
+
+    fn ep_tx_fifo_is_ready(&self, endpoint: usize) -> bool {
+        self.endpoint(endpoint).map_or(false, |data| {
+            data.in_descriptor.map_or(false, |desc| {
+                desc.flags & DescFlag::STATUS_MASK == DescFlag::DMA_DONE ||
+                    desc.flags & DescFlag::STATUS_MASK == DescFlag::HOST_BUSY
+            })
+        })
+    }
+
+    fn ep_enable_tx(&self, endpoint: usize, len: u16) {
+        self.endpoint(endpoint).map(|data| {
+            data.in_descriptor.map(|desc| {
+                let mut flags = DescFlag::LAST | DescFlag::HOST_READY | DescFlag::IOC;
+                if len < MAX_PACKET_SIZE {
+                    flags = flags | DescFlag::SHORT;
+                }
+                if self.is_isochronous_endpoint(endpoint) {
+                    flags = flags.frame(data.in_frame.get());
+                }
+                desc.flags = flags.bytes(len);
+                self.registers.in_endpoints[endpoint].control.modify(EndpointControl::Enable::SET +
+                                                                     EndpointControl::ClearNak::SET);
+            });
         });
     }
 
-    fn ep1_enable_rx(&self) -> ReturnCode {
-        self.ep1_out_descriptor.map_or(ReturnCode::FAIL, |desc| {
-            desc.flags = (DescFlag::LAST |
-                          DescFlag::HOST_READY |
-                          DescFlag::IOC).bytes(U2F_REPORT_SIZE);
-            self.registers.out_endpoints[1].control.modify(EndpointControl::Enable::SET +
-                                                           EndpointControl::ClearNak::SET);
-            data_debug!("Set EP1 receive flags.\n");
-            ReturnCode::SUCCESS
+    fn ep_enable_rx(&self, endpoint: usize) -> ReturnCode {
+        self.endpoint(endpoint).map_or(ReturnCode::FAIL, |data| {
+            data.out_descriptor.map_or(ReturnCode::FAIL, |desc| {
+                let mut flags = DescFlag::LAST | DescFlag::HOST_READY | DescFlag::IOC;
+                if self.is_isochronous_endpoint(endpoint) {
+                    flags = flags.frame(data.out_frame.get());
+                }
+                desc.flags = flags.bytes(MAX_PACKET_SIZE);
+                self.registers.out_endpoints[endpoint].control.modify(EndpointControl::Enable::SET +
+                                                                      EndpointControl::ClearNak::SET);
+                data_debug!("Set EP{} receive flags.\n", endpoint);
+                ReturnCode::SUCCESS
+            })
         })
     }
 
-    fn usb_reconnect(&self) {}
+    /// The length of the packet last received on `endpoint`: the
+    /// descriptor counts down the bytes it has room for.
+    fn ep_received_length(&self, endpoint: usize) -> usize {
+        self.endpoint(endpoint).map_or(0, |data| {
+            data.out_descriptor.map_or(0, |desc| {
+                let remaining = if self.is_isochronous_endpoint(endpoint) {
+                    desc.flags.isochronous_bytes() as usize
+                } else {
+                    (desc.flags.0 & 0xffff) as usize
+                };
+                MAX_PACKET_SIZE as usize - ::core::cmp::min(remaining, MAX_PACKET_SIZE as usize)
+            })
+        })
+    }
+
+    /// Copies `slice` into the IN buffer of `endpoint` and sends it.
+    fn ep_put_slice(&self, endpoint: usize, slice: &[u8]) -> ReturnCode {
+        let data = match self.endpoint(endpoint) {
+            Some(data) => data,
+            None => return ReturnCode::EINVAL,
+        };
+        if slice.len() > MAX_PACKET_SIZE as usize {
+            data_debug!("EP{}: ERROR: slice too large\n", endpoint);
+            ReturnCode::ESIZE
+        } else if !self.ep_tx_fifo_is_ready(endpoint) {
+            data_debug!("EP{}: ERROR: Tried to put slice but busy.\n", endpoint);
+            ReturnCode::EBUSY
+        } else {
+            data.in_buffer.map(|hardware_buffer| {
+                for (word, bytes) in hardware_buffer.iter_mut().zip(slice.chunks(4)) {
+                    let mut padded = [0u8; 4];
+                    padded[..bytes.len()].copy_from_slice(bytes);
+                    *word = u32::from_le_bytes(padded);
+                }
+                self.capture.record(endpoint as u8, CaptureKind::DataIn, &hardware_buffer[..], slice.len());
+            });
+            self.ep_enable_tx(endpoint, slice.len() as u16);
+            data_debug!("EP{}: {} words available.\n", endpoint,
+                        self.registers.in_endpoints[endpoint].tx_fifo_status.get());
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Copies the packet last received on `endpoint` into `slice`,
+    /// returning how many bytes were copied.
+    fn ep_get_slice(&self, endpoint: usize, slice: &mut [u8]) -> usize {
+        let len = ::core::cmp::min(self.ep_received_length(endpoint), slice.len());
+        self.endpoint(endpoint).map(|data| {
+            data.out_buffer.get().map(|hardware_buffer| {
+                for (i, byte) in slice[..len].iter_mut().enumerate() {
+                    *byte = (hardware_buffer[i / 4] >> (8 * (i % 4))) as u8;
+                }
+            });
+        });
+        len
+    }
+
+    /// Records whether the host has the CDC-ACM port open, telling the
+    /// client when that changes.
+    fn set_cdc_port_open(&self, open: bool) {
+        if self.cdc_port_open.get() != open {
+            control_debug!("USB: CDC port {}.\n", if open { "opened" } else { "closed" });
+            self.cdc_port_open.set(open);
+            self.cdc_client.map(|client| client.port_changed(open));
+        }
+    }
+
+    /// Returns the data endpoints to their state before enumeration after
+    /// the host went away: drops whatever they were sending or receiving,
+    /// so that a transfer cut short by the unplug does not leave them busy.
+    fn usb_disconnected(&self) {
+        control_debug!("USB: disconnected.\n");
+        self.trace.record(TraceEvent::Disconnect, 0);
+        self.reconnecting.set(true);
+        self.configuration_current_value.set(0);
+        self.usb_resume();
+        self.remote_wakeup_enabled.set(false);
+        self.transfer_receiving.set(false);
+        self.transfer_sending.set(None);
+        self.state.set(USBState::WaitingForSetupPacket);
+
+        for interface in self.interfaces.get().iter() {
+            let endpoint = interface.endpoint;
+            let mask = self.registers.device_all_ep_interrupt_mask.get();
+            self.registers.device_all_ep_interrupt_mask.set(mask & !endpoint_interrupt_bits(endpoint));
+            self.registers.in_endpoints[endpoint].control.modify(EndpointControl::SetNak::SET);
+            self.registers.out_endpoints[endpoint].control.modify(EndpointControl::SetNak::SET);
+            self.flush_tx_fifo(endpoint as u8);
+            self.endpoint(endpoint).map(|data| {
+                data.in_descriptor.map(|desc| {
+                    desc.flags = DescFlag::LAST | DescFlag::HOST_BUSY | DescFlag::IOC;
+                });
+                data.out_descriptor.map(|desc| {
+                    desc.flags = DescFlag::LAST | DescFlag::HOST_BUSY | DescFlag::IOC;
+                });
+            });
+            self.registers.in_endpoints[endpoint].interrupt.set(!0);
+            self.registers.out_endpoints[endpoint].interrupt.set(!0);
+        }
+        self.set_cdc_port_open(false);
+    }
+
+    /// Drops off the bus and connects again, so that the host enumerates
+    /// the device from scratch, e.g. to pick up new descriptors. Clients
+    /// hear `reconnected` once the host configures the device again.
+    fn usb_reconnect(&self) {
+        control_debug!("USB: soft reconnect.\n");
+        self.trace.record(TraceEvent::SoftReconnect, 0);
+        self.registers.device_control.modify(DeviceControl::SoftDisconnect::SET);
+        self.usb_disconnected();
+        for _ in 0..SOFT_DISCONNECT_NOPS {
+            support::nop();
+        }
+        // The host starts over at address 0, and may not have finished
+        // the control transfer it was in the middle of.
+        self.reset_ep0();
+        self.registers.device_control.modify(DeviceControl::SoftDisconnect::CLEAR);
+    }
+
+    /// Stops the PHY clock when the host has suspended the bus, and tells
+    /// the client. The core still watches the bus, and raises ResumeWakeup
+    /// when the host resumes or resets it.
+    fn usb_suspend(&self) {
+        if self.suspended.get() ||
+            !self.registers.device_status.is_set(DeviceStatus::SuspendStatus) {
+            return;
+        }
+        control_debug!("USB: suspended.\n");
+        self.trace.record(TraceEvent::Suspend, 0);
+        self.suspended.set(true);
+        self.registers.power_clock_gating_control.modify(PowerClockGatingControl::StopPhyClock::SET);
+        self.registers.power_clock_gating_control.modify(PowerClockGatingControl::GateHclk::SET);
+        self.u2f_client.map(|client| client.suspended());
+    }
+
+    /// Restarts the PHY clock after a suspend, and tells the client.
+    fn usb_resume(&self) {
+        if !self.suspended.get() {
+            return;
+        }
+        control_debug!("USB: resumed.\n");
+        self.trace.record(TraceEvent::Resume, 0);
+        self.registers.power_clock_gating_control.modify(PowerClockGatingControl::GateHclk::CLEAR);
+        self.registers.power_clock_gating_control.modify(PowerClockGatingControl::StopPhyClock::CLEAR);
+        self.suspended.set(false);
+        self.u2f_client.map(|client| client.resumed());
+    }
+
+    /// Wakes the host from a suspend by driving resume signaling on the
+    /// bus; see `UsbHidU2f::remote_wakeup`.
+    fn usb_remote_wakeup(&self) -> ReturnCode {
+        if !self.suspended.get() {
+            return ReturnCode::EALREADY;
+        }
+        if !self.remote_wakeup_enabled.get() {
+            return ReturnCode::EOFF;
+        }
+        control_debug!("USB: remote wakeup.\n");
+        self.trace.record(TraceEvent::RemoteWakeup, 0);
+        self.usb_resume();
+        self.registers.device_control.modify(DeviceControl::RemoteWakeupSignaling::SET);
+        for _ in 0..REMOTE_WAKEUP_SIGNALING_NOPS {
+            support::nop();
+        }
+        self.registers.device_control.modify(DeviceControl::RemoteWakeupSignaling::CLEAR);
+        ReturnCode::SUCCESS
+    }
 
     /// Perform a soft reset on the USB core; timeout if the reset
     /// takes too long.
@@ -345,20 +737,48 @@ impl<'a> USB<'a> {
             // MPS default set to 0 == 64 bytes
             // "Application must read the DSTS register to obtain the
             //  enumerated speed."
+            self.trace.record(TraceEvent::EnumerationDone,
+                              self.registers.device_status.read(DeviceStatus::EnumeratedSpeed));
         }
 
-        if status.is_set(Interrupt::EarlySuspend) ||
-            status.is_set(Interrupt::Suspend) {
-                // Currently do not support suspend
-            }
+        // EarlySuspend only warns that the bus has been idle for 3ms and
+        // Suspend follows; there is nothing to do until then.
+        if status.is_set(Interrupt::Suspend) {
+            self.usb_suspend();
+        }
+
+        if status.is_set(Interrupt::ResumeWakeup) {
+            self.usb_resume();
+        }
 
         if mask.is_set(Interrupt::StartOfFrame) &&
             status.is_set(Interrupt::StartOfFrame) { // Clear SOF
                 self.registers.interrupt_mask.modify(Interrupt::StartOfFrame::CLEAR);
             }
 
+        if status.is_set(Interrupt::OTG) {
+            let otg_status = self.registers.otg_interrupt.extract();
+            if otg_status.is_set(OtgInterrupt::SessionEndDetected) {
+                // VBUS went away: the cable was unplugged.
+                self.usb_disconnected();
+            }
+            self.registers.otg_interrupt.set(otg_status.get());
+        }
+
+        if status.is_set(Interrupt::SessionRequest) {
+            // VBUS came back: the cable was plugged in. Reconnect so that
+            // the host does not miss our pull-up.
+            control_debug!("USB: session request.\n");
+            self.trace.record(TraceEvent::SessionRequest, 0);
+            if self.reconnecting.get() {
+                self.usb_reconnect();
+            }
+        }
+
         if status.is_set(Interrupt::Reset) ||
             status.is_set(Interrupt::ResetDetected) {
+                // A reset also ends a suspend.
+                self.usb_resume();
                 self.usb_reset();
             }
 
@@ -367,8 +787,9 @@ impl<'a> USB<'a> {
                 let pending_interrupts = self.registers.device_all_ep_interrupt.extract();
                 let inter_ep0_out = pending_interrupts.is_set(AllEndpointInterrupt::OUT0);
                 let inter_ep0_in = pending_interrupts.is_set(AllEndpointInterrupt::IN0);
-                let inter_ep1_out = pending_interrupts.is_set(AllEndpointInterrupt::OUT1);
-                let inter_ep1_in = pending_interrupts.is_set(AllEndpointInterrupt::IN1);
+                let data_endpoint = (1..=MAX_ENDPOINT).find(|&endpoint| {
+                    pending_interrupts.get() & endpoint_interrupt_bits(endpoint) != 0
+                });
                 int_debug!(" - handling endpoint interrupts {:032b}\n", pending_interrupts.get());
                 int_debug!(" -      all endpoint mask       {:032b}\n", self.registers.device_all_ep_interrupt_mask.get());
                 int_debug!(" -     out1 endpoint ints       {:032b}\n", self.registers.out_endpoints[1].interrupt.get());
@@ -377,9 +798,11 @@ impl<'a> USB<'a> {
                 if inter_ep0_out || inter_ep0_in {
                     int_debug!("   - ep0out: {} ep0in: {}\n", inter_ep0_out, inter_ep0_in);
                     self.handle_endpoint0_events(inter_ep0_out, inter_ep0_in);
-                } else if inter_ep1_out || inter_ep1_in {
-                    int_debug!("   - ep1out: {} ep1in: {}\n", inter_ep1_out, inter_ep1_in);
-                    self.handle_endpoint1_events(inter_ep1_out, inter_ep1_in);
+                } else if let Some(endpoint) = data_endpoint {
+                    let inter_out = pending_interrupts.get() & (1 << (16 + endpoint)) != 0;
+                    let inter_in = pending_interrupts.get() & (1 << endpoint) != 0;
+                    int_debug!("   - ep{}out: {} ep{}in: {}\n", endpoint, inter_out, endpoint, inter_in);
+                    self.handle_data_endpoint_events(endpoint, inter_out, inter_in);
                 }
             }
 
@@ -423,33 +846,122 @@ impl<'a> USB<'a> {
                                                       EndpointControl::ClearNak::SET);
     }
 
-    /// Handles events for endpoint 1 (data to/from USB client). Clear
-    /// pending interrupts and issue callbcks to client.
-    fn handle_endpoint1_events(&self, out_interrupt: bool, in_interrupt: bool) {
-        data_debug!("Handling endpoint 1 events: out {}, in {}\n", out_interrupt, in_interrupt);
+    /// Handles events for a data endpoint. Clear pending interrupts and
+    /// issue callbacks to the client of the endpoint's interface.
+    fn handle_data_endpoint_events(&self, endpoint: usize, out_interrupt: bool, in_interrupt: bool) {
+        data_debug!("Handling endpoint {} events: out {}, in {}\n", endpoint, out_interrupt, in_interrupt);
+        let kind = self.interfaces.get().by_endpoint(endpoint).map(|interface| interface.kind);
         if in_interrupt {
-            let ep_in = &self.registers.in_endpoints[1];
+            let ep_in = &self.registers.in_endpoints[endpoint];
             let ep_in_interrupts = ep_in.interrupt.extract();
             data_debug!("In interrupts: {:#x}\n", ep_in_interrupts.get());
             print_in_endpoint_interrupt_status(ep_in_interrupts);
             ep_in.interrupt.set(ep_in_interrupts.get());
             if ep_in_interrupts.is_set(InEndpointInterruptMask::TransferCompleted) {
-                data_debug!("U2F: frame_transmitted callback on ep1.\n");
-                self.u2f_client.map(|client| client.frame_transmitted());
+                data_debug!("EP{}: packet transmitted.\n", endpoint);
+                match kind {
+                    Some(InterfaceKind::U2fHid) if self.transfer_sending.get().is_some() => {
+                        self.handle_transfer_transmitted(endpoint);
+                    },
+                    Some(InterfaceKind::U2fHid) => {
+                        self.u2f_client.map(|client| client.frame_transmitted());
+                    },
+                    Some(InterfaceKind::CdcAcm { .. }) => {
+                        self.cdc_client.map(|client| client.packet_transmitted());
+                    },
+                    Some(InterfaceKind::VendorBulk { .. }) => {
+                        self.endpoints[endpoint - 1].bulk_client
+                            .map(|client| client.packet_transmitted(endpoint));
+                    },
+                    Some(InterfaceKind::VendorIsochronous { .. }) => {
+                        let data = &self.endpoints[endpoint - 1];
+                        let delivered = data.in_descriptor.map_or(false, |desc| {
+                            desc.flags & DescFlag::ISOCHRONOUS_STATUS_MASK == DescFlag::ISOCHRONOUS_SUCCESS
+                        });
+                        data.isochronous_client.map(|client| {
+                            client.packet_transmitted(endpoint, data.in_frame.get(), delivered)
+                        });
+                    },
+                    None => {},
+                }
             }
-
         }
         if out_interrupt {
-            let ep_out = &self.registers.out_endpoints[1];
+            let ep_out = &self.registers.out_endpoints[endpoint];
             let ep_out_interrupts = ep_out.interrupt.extract();
             data_debug!("Out interrupts: {:#x}\n", ep_out_interrupts.get());
             ep_out.interrupt.set(ep_out_interrupts.get());
             if ep_out_interrupts.is_set(OutEndpointInterruptMask::TransferCompleted) {
-                data_debug!("U2F: ep1 frame received.\n");
-                self.u2f_client.map(|client| client.frame_received());
+                data_debug!("EP{}: packet received.\n", endpoint);
+                let transfer = kind == Some(InterfaceKind::U2fHid) && self.transfer_receiving.get();
+                if !transfer {
+                    self.endpoints[endpoint - 1].out_buffer.get().map(|buf| {
+                        self.capture.record(endpoint as u8, CaptureKind::DataOut, &buf[..],
+                                            self.ep_received_length(endpoint));
+                    });
+                }
+                match kind {
+                    Some(InterfaceKind::U2fHid) if transfer => {
+                        self.handle_transfer_received(endpoint);
+                    },
+                    Some(InterfaceKind::U2fHid) => {
+                        self.u2f_client.map(|client| client.frame_received());
+                    },
+                    Some(InterfaceKind::CdcAcm { .. }) => {
+                        self.cdc_client.map(|client| client.packet_received());
+                    },
+                    Some(InterfaceKind::VendorBulk { .. }) => {
+                        self.endpoints[endpoint - 1].bulk_client
+                            .map(|client| client.packet_received(endpoint));
+                    },
+                    Some(InterfaceKind::VendorIsochronous { .. }) => {
+                        let data = &self.endpoints[endpoint - 1];
+                        let delivered = data.out_descriptor.map_or(false, |desc| {
+                            desc.flags & DescFlag::ISOCHRONOUS_STATUS_MASK == DescFlag::ISOCHRONOUS_SUCCESS
+                        });
+                        data.isochronous_client.map(|client| {
+                            client.packet_received(endpoint, data.out_frame.get(), delivered)
+                        });
+                    },
+                    None => {},
+                }
             }
         }
+    }
 
+    /// Whether the U2F endpoint can take a frame or transfer to send.
+    fn u2f_transmit_ready(&self, endpoint: usize) -> bool {
+        self.transfer_sending.get().is_none() && self.ep_tx_fifo_is_ready(endpoint)
+    }
+
+    /// Finishes a transfer sent on the U2F endpoint: points the endpoint
+    /// back at its single-packet descriptor and tells the client.
+    fn handle_transfer_transmitted(&self, endpoint: usize) {
+        let len = self.transfer_sending.take().unwrap_or(0);
+        self.endpoints[endpoint - 1].in_descriptor.map(|in_desc| {
+            self.registers.in_endpoints[endpoint].dma_address.set(&in_desc);
+        });
+        data_debug!("EP{}: transfer of {} bytes sent.\n", endpoint, len);
+        self.u2f_client.map(|client| client.transfer_transmitted(len));
+    }
+
+    /// Handles a packet received while a transfer is armed on the U2F
+    /// endpoint. Once the transfer ended, points the endpoint back at its
+    /// single-packet descriptor and tells the client how much arrived.
+    fn handle_transfer_received(&self, endpoint: usize) {
+        let len = match self.transfer_out_descriptors.map_or(None, |descs| transfer::received_length(descs)) {
+            Some(len) => len,
+            None => return, // More packets to come.
+        };
+        self.transfer_receiving.set(false);
+        self.endpoints[endpoint - 1].out_descriptor.map(|out_desc| {
+            self.registers.out_endpoints[endpoint].dma_address.set(&out_desc);
+        });
+        self.transfer_out_buffer.map(|buf| {
+            self.capture.record(endpoint as u8, CaptureKind::DataOut, &buf[..], len);
+        });
+        data_debug!("EP{}: transfer of {} bytes received.\n", endpoint, len);
+        self.u2f_client.map(|client| client.transfer_received(len));
     }
 
     /// Handle all endpoint 0 events; clear pending interrupt flags,
@@ -535,6 +1047,17 @@ impl<'a> USB<'a> {
                     }
                 }
             }
+            USBState::DataStageOut => {
+                control_debug!("USB: state is data stage out\n");
+                if out_interrupt {
+                    if (transfer_type == TableCase::A || transfer_type == TableCase::C) && setup_ready {
+                        // The host gave up on the transfer and sent a new request.
+                        self.handle_setup(transfer_type);
+                    } else if transfer_type == TableCase::A || transfer_type == TableCase::E {
+                        self.handle_control_out_data();
+                    }
+                }
+            }
             USBState::NoDataStage => {
                 if in_interrupt &&
                     ep_in_interrupts.is_set(InEndpointInterruptMask::TransferCompleted) {
@@ -570,15 +1093,22 @@ impl<'a> USB<'a> {
     ///   - handle_standard_device_to_host: getting status, descriptors, etc.,
     ///   - handle_standard_host_to_device: none supported yet
     ///   - handle_standard_no_data_phase: setting configuration and address,
-    ///   - handle_class_interface_to_host: getting HID report descriptor, or
-    ///   - handle_class_host_to_interface: setting idle interval.
+    ///   - handle_class_interface_to_host: getting HID report descriptor,
+    ///   - handle_class_host_to_interface: setting idle interval, or class
+    ///     requests with data for the interface's client, or
+    ///   - handle_vendor_host_to_interface: vendor requests for the
+    ///     interface's client.
     fn handle_setup(&self, transfer_type: TableCase) {
         // Assuming `ep0_out_buffers` was properly set in `init`, this will
         // always succeed.
         control_debug!("Handle setup, case {:?}\n", transfer_type);
         self.ep0_out_buffers.get().map(|bufs| {
             let request = SetupRequest::new(&bufs[self.last_ep0_out_idx.get()]);
+            self.capture.record(0, CaptureKind::Setup, &bufs[self.last_ep0_out_idx.get()], 8);
+            self.trace.record(TraceEvent::Setup, bufs[self.last_ep0_out_idx.get()][0]);
             control_debug!("  - type={:?} recip={:?} dir={:?} request={:?}\n", request.req_type(), request.recipient(), request.data_direction(), request.request());
+            // A new request ends the data stage of the previous one.
+            self.control_out.take();
 
             if request.req_type() == SetupRequestClass::Standard {
                 if request.recipient() == SetupRecipient::Device {
@@ -597,6 +1127,13 @@ impl<'a> USB<'a> {
                     } else {
                         self.handle_standard_host_to_interface(transfer_type, &request);
                     }
+                } else if request.recipient() == SetupRecipient::Endpoint {
+                    control_debug!("Standard request on endpoint.\n");
+                    if request.data_direction() == SetupDirection::DeviceToHost {
+                        self.handle_standard_endpoint_to_host(transfer_type, &request);
+                    } else {
+                        self.handle_standard_host_to_endpoint(transfer_type, &request);
+                    }
                 }
             } else if request.req_type() == SetupRequestClass::Class && request.recipient() == SetupRecipient::Interface {
                 if request.data_direction() == SetupDirection::DeviceToHost {
@@ -604,15 +1141,22 @@ impl<'a> USB<'a> {
                 } else {
                     self.handle_class_host_to_interface(transfer_type, &request);
                 }
+            } else if request.req_type() == SetupRequestClass::Vendor &&
+                request.recipient() == SetupRecipient::Interface &&
+                request.data_direction() == SetupDirection::HostToDevice {
+                self.handle_vendor_host_to_interface(transfer_type, &request);
             } else {
                 control_debug!("  - unknown case.\n");
+                self.handle_unexpected_packet();
             }
         });
     }
 
-    fn handle_standard_host_to_device(&self, _transfer_type: TableCase, _request: &SetupRequest) {
-        // TODO(alevy): don't support any of these yet...
-        unimplemented!();
+    /// Handles standard requests to the device with data from the host.
+    /// The only one, SET_DESCRIPTOR, is optional and not supported.
+    fn handle_standard_host_to_device(&self, _transfer_type: TableCase, request: &SetupRequest) {
+        control_debug!("Unhandled setup: device, host to device: {:?}\n", request.request());
+        self.handle_unexpected_packet();
     }
 
     /// Handles requests for data from device to host, including the device descriptor,
@@ -646,22 +1190,14 @@ impl<'a> USB<'a> {
                         self.ep0_in_buffers.map(|buf| {
                             self.configuration_descriptor.map(|desc| {
                                 len = self.get_configuration_total_length();
-                                for i in 0..16 {
-                                    buf[i] = desc[4 * i + 0] as u32 |
-                                             (desc[4 * i + 1] as u32) << 8 |
-                                             (desc[4 * i + 2] as u32) << 16 |
-                                             (desc[4 * i + 3] as u32) << 24;
+                                for (word, bytes) in buf.iter_mut().zip(desc.chunks(4)) {
+                                    *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
                                 }
                             });
                         });
                         control_debug!("USB: Trying to send configuration descriptor, len {}\n  ", len);
                         len = ::core::cmp::min(len, request.w_length);
-                        self.ep0_in_descriptors.map(|descs| {
-                            descs[0].flags = (DescFlag::HOST_READY |
-                                              DescFlag::LAST |
-                                              DescFlag::SHORT |
-                                              DescFlag::IOC).bytes(len as u16);
-                        });
+                        self.set_ep0_in_length(len as usize);
                         self.expect_data_phase_in(transfer_type);
                     },
                     GET_DESCRIPTOR_INTERFACE => {
@@ -686,7 +1222,14 @@ impl<'a> USB<'a> {
                     GET_DESCRIPTOR_STRING => {
                         let index = (request.w_value & 0xff) as usize;
                         self.strings.map(|strs| {
-                            let str = &strs[index];
+                            let str = match strs.get(index) {
+                                Some(str) => str,
+                                None => {
+                                    control_debug!("USB: no string descriptor {}", index);
+                                    self.handle_unexpected_packet();
+                                    return;
+                                }
+                            };
                             let mut len = 0;
                             self.ep0_in_buffers.map(|buf| {
                                 len = str.into_u32_buf(buf);
@@ -722,15 +1265,8 @@ impl<'a> USB<'a> {
                 self.expect_data_phase_in(transfer_type);
             }
             GetStatus => {
-                self.ep0_in_buffers.map(|buf| {
-                    buf[0] = 0x0;
-                });
-                self.ep0_in_descriptors.map(|descs| {
-                    descs[0].flags = (DescFlag::HOST_READY | DescFlag::LAST |
-                                      DescFlag::SHORT | DescFlag::IOC)
-                        .bytes(2);
-                });
-                self.expect_status_phase_in(transfer_type);
+                let status = if self.remote_wakeup_enabled.get() { STATUS_REMOTE_WAKEUP } else { 0 };
+                self.send_status(transfer_type, request, status);
             }
             _ => {
                 self.handle_unexpected_packet();
@@ -740,12 +1276,31 @@ impl<'a> USB<'a> {
 
 
 
+    /// Sends the two-byte response to GET_STATUS.
+    fn send_status(&self, transfer_type: TableCase, request: &SetupRequest, status: u32) {
+        let len = ::core::cmp::min(2, request.length());
+        self.ep0_in_buffers.map(|buf| {
+            buf[0] = status;
+        });
+        self.ep0_in_descriptors.map(|descs| {
+            descs[0].flags = (DescFlag::HOST_READY | DescFlag::LAST |
+                              DescFlag::SHORT | DescFlag::IOC)
+                .bytes(len);
+        });
+        self.expect_data_phase_in(transfer_type);
+    }
+
     /// Responds to a SETUP message destined to an interface. Currently
-    /// only handles GetDescriptor requests for Report descriptors.
+    /// only handles GetStatus, and GetDescriptor requests for the Report
+    /// descriptor of the U2F HID interface.
     fn handle_standard_interface_to_host(&self, transfer_type: TableCase, request: &SetupRequest) {
         control_debug!("Handle setup interface, device to host.\n");
         let request_type = request.request();
         match request_type {
+            // Interfaces have no status bits (USB 2.0, 9.4.5).
+            SetupRequestType::GetStatus if self.request_interface_kind(request).is_some() => {
+                self.send_status(transfer_type, request, 0);
+            },
             SetupRequestType::GetDescriptor => {
                 let value      = request.value();
                 let descriptor = Descriptor::from_u8((value >> 8) as u8);
@@ -753,7 +1308,7 @@ impl<'a> USB<'a> {
                 let len        = request.length() as usize;
                 control_debug!("  - Descriptor: {:?}, index: {}, length: {}\n", descriptor, _index, len);
                 match descriptor {
-                    Descriptor::Report => {
+                    Descriptor::Report if self.request_interface_kind(request) == Some(InterfaceKind::U2fHid) => {
                         if U2F_REPORT_DESCRIPTOR.len() != len {
                             control_debug!("Requested report of length {} but length is {}", request.length(), U2F_REPORT_DESCRIPTOR.len());
                             self.handle_bad_packet();
@@ -789,6 +1344,103 @@ impl<'a> USB<'a> {
         }
     }
 
+    /// Handles GET_STATUS for an endpoint, which tells whether the host
+    /// halted it.
+    fn handle_standard_endpoint_to_host(&self, transfer_type: TableCase, request: &SetupRequest) {
+        match (request.request(), self.halt_bit(request.index())) {
+            (SetupRequestType::GetStatus, Some(bit)) => {
+                let status = if self.halted_endpoints.get() & bit != 0 { STATUS_ENDPOINT_HALT } else { 0 };
+                self.send_status(transfer_type, request, status);
+            },
+            _ => {
+                control_debug!("Unhandled setup: endpoint {:#x}, device to host: {:?}\n",
+                               request.index(), request.request());
+                self.handle_unexpected_packet();
+            }
+        }
+    }
+
+    /// Handles SET_FEATURE and CLEAR_FEATURE ENDPOINT_HALT, which stall and
+    /// un-stall a data endpoint.
+    fn handle_standard_host_to_endpoint(&self, transfer_type: TableCase, request: &SetupRequest) {
+        use self::types::SetupRequestType::*;
+        match request.request() {
+            SetFeature | ClearFeature if request.value() == FEATURE_ENDPOINT_HALT &&
+                request.length() == 0 &&
+                self.set_endpoint_halt(request.index(), request.request() == SetFeature) => {
+                self.expect_status_phase_in(transfer_type);
+            },
+            _ => {
+                control_debug!("Unhandled setup: endpoint {:#x}, host to device: {:?}\n",
+                               request.index(), request.request());
+                self.handle_unexpected_packet();
+            }
+        }
+    }
+
+    /// The bit of the endpoint at `address` (its number, with 0x80 for IN)
+    /// in `halted_endpoints`, if the device has that endpoint.
+    fn halt_bit(&self, address: u16) -> Option<u32> {
+        if address & !0x8f != 0 {
+            return None;
+        }
+        let endpoint = (address & 0x0f) as usize;
+        let is_in = address & 0x80 != 0;
+        let used = endpoint == 0 || self.interfaces.get().iter().any(|interface| {
+            interface.endpoint == endpoint ||
+                (is_in && interface.kind == InterfaceKind::CdcAcm { notification_endpoint: endpoint })
+        });
+        if !used {
+            None
+        } else if is_in {
+            Some(1 << endpoint)
+        } else {
+            Some(1 << (16 + endpoint))
+        }
+    }
+
+    /// Stalls the endpoint at `address`, or un-stalls it and resets its
+    /// data toggle to DATA0. Returns false if the device has no such
+    /// endpoint. The control endpoint only stalls requests it rejects, so
+    /// halting it is accepted and ignored.
+    fn set_endpoint_halt(&self, address: u16, halt: bool) -> bool {
+        let bit = match self.halt_bit(address) {
+            Some(bit) => bit,
+            None => return false,
+        };
+        let endpoint = (address & 0x0f) as usize;
+        if endpoint == 0 {
+            return true;
+        }
+        control_debug!("USB: endpoint {:#x} {}.\n", address, if halt { "halted" } else { "resumed" });
+        self.trace.record(TraceEvent::EndpointHalt, address as u32 | (halt as u32) << 8);
+        let control = if address & 0x80 != 0 {
+            &self.registers.in_endpoints[endpoint].control
+        } else {
+            &self.registers.out_endpoints[endpoint].control
+        };
+        if halt {
+            control.modify(EndpointControl::Stall::SET);
+            self.halted_endpoints.set(self.halted_endpoints.get() | bit);
+        } else {
+            control.modify(EndpointControl::Stall::CLEAR + EndpointControl::SetData0Pid::SET);
+            self.halted_endpoints.set(self.halted_endpoints.get() & !bit);
+        }
+        true
+    }
+
+    /// Un-stalls all halted endpoints, as SET_CONFIGURATION does (USB 2.0,
+    /// 9.4.5).
+    fn clear_endpoint_halts(&self) {
+        for endpoint in 1..=MAX_ENDPOINT {
+            for &address in [endpoint as u16, 0x80 | endpoint as u16].iter() {
+                if self.halt_bit(address).map_or(false, |bit| self.halted_endpoints.get() & bit != 0) {
+                    self.set_endpoint_halt(address, false);
+                }
+            }
+        }
+    }
+
     /// Handles a setup message to an interface, host-to-device
     /// communication.  Not supported.
     fn handle_standard_host_to_interface(&self, _transfer_type: TableCase, _request: &SetupRequest) {
@@ -797,17 +1449,78 @@ impl<'a> USB<'a> {
     }
 
     /// Handles a setup message to a class, device-to-host
-    /// communication. Not supported.
-    fn handle_class_interface_to_host(&self, _transfer_type: TableCase, _request: &SetupRequest) {
-        control_debug!("Unhandled setup: class, device to host.!");
-        self.handle_unexpected_packet();
+    /// communication. Currently supports only GetReport for feature
+    /// reports on the U2F HID interface, and the requests of the CDC-ACM
+    /// interface.
+    fn handle_class_interface_to_host(&self, transfer_type: TableCase, request: &SetupRequest) {
+        use self::types::SetupClassRequestType;
+        control_debug!("Handle setup class, device to host.\n");
+        match self.request_interface_kind(request) {
+            Some(InterfaceKind::U2fHid) => {},
+            Some(InterfaceKind::CdcAcm { .. }) => {
+                self.handle_cdc_interface_to_host(transfer_type, request);
+                return;
+            },
+            _ => {
+                control_debug!("Class request for interface {} without class requests.\n", request.index());
+                self.handle_unexpected_packet();
+                return;
+            },
+        }
+        let value = request.value();
+        let report_type = (value >> 8) as u8;
+        let report_id = (value & 0xff) as u8;
+        if request.class_request() != SetupClassRequestType::GetReport ||
+            report_type != FEATURE_REPORT_TYPE {
+            control_debug!("Unhandled setup: class, device to host.!");
+            self.handle_unexpected_packet();
+            return;
+        }
+
+        let mut report = [0u8; EP_BUFFER_SIZE_BYTES];
+        let len = match self.feature_reports.map_or(None, |source| {
+            feature_report::encode_report(*source, report_id, &mut report)
+        }) {
+            Some(len) => ::core::cmp::min(len, request.length() as usize),
+            None => {
+                control_debug!("GetReport: no feature report {:#x}\n", report_id);
+                self.handle_unexpected_packet();
+                return;
+            }
+        };
+        self.ep0_in_buffers.map(|buf| {
+            for (i, word) in report.chunks(4).enumerate() {
+                buf[i] = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            }
+        });
+        self.ep0_in_descriptors.map(|descs| {
+            descs[0].flags = (DescFlag::HOST_READY |
+                              DescFlag::LAST |
+                              DescFlag::SHORT |
+                              DescFlag::IOC).bytes(len as u16);
+        });
+        self.expect_data_phase_in(transfer_type);
     }
 
     /// Handles a setup message to a class, host-to-device
-    /// communication.  Currently supports only SetIdle commands.
-    fn handle_class_host_to_interface(&self, _transfer_type: TableCase, request: &SetupRequest) {
+    /// communication.  Supports SetIdle commands on the U2F HID interface
+    /// and the requests of the CDC-ACM interface, and passes other U2F HID
+    /// requests, such as SetReport, to the interface's control client.
+    fn handle_class_host_to_interface(&self, transfer_type: TableCase, request: &SetupRequest) {
         use self::types::SetupClassRequestType;
         control_debug!("Handle setup class, host to device.\n");
+        match self.request_interface_kind(request) {
+            Some(InterfaceKind::U2fHid) => {},
+            Some(InterfaceKind::CdcAcm { .. }) => {
+                self.handle_cdc_host_to_interface(transfer_type, request);
+                return;
+            },
+            _ => {
+                control_debug!("Class request for interface {} without class requests.\n", request.index());
+                self.handle_unexpected_packet();
+                return;
+            },
+        }
         match request.class_request() {
             SetupClassRequestType::SetIdle => {
                 let val = request.value();
@@ -816,12 +1529,183 @@ impl<'a> USB<'a> {
                 control_debug!("SetIdle: {} to {}, stall fifos.", _id, _interval);
                 self.stall_both_fifos();
             },
+            _ if self.control_client(request).is_some() => {
+                self.begin_control_out(transfer_type, request);
+            },
             _ => {
                 self.handle_unexpected_packet();
             }
         }
     }
 
+    /// Handles a vendor request to an interface, host-to-device
+    /// communication, by passing it to the interface's control client.
+    fn handle_vendor_host_to_interface(&self, transfer_type: TableCase, request: &SetupRequest) {
+        if self.control_client(request).is_some() {
+            self.begin_control_out(transfer_type, request);
+        } else {
+            control_debug!("Vendor request {:#x} for interface {} without a client.\n",
+                           request.b_request, request.index());
+            self.handle_unexpected_packet();
+        }
+    }
+
+    /// The kind of the interface a request to an interface is for.
+    fn request_interface_kind(&self, request: &SetupRequest) -> Option<InterfaceKind> {
+        if request.index() > u8::max_value() as u16 {
+            return None;
+        }
+        self.interfaces.get()
+            .by_number(request.index() as u8)
+            .map(|interface| interface.kind)
+    }
+
+    /// Handles GET_LINE_CODING, the one CDC-ACM request with data for the
+    /// host.
+    fn handle_cdc_interface_to_host(&self, transfer_type: TableCase, request: &SetupRequest) {
+        use self::types::SetupClassRequestType;
+        if request.class_request() != SetupClassRequestType::GetLineCoding {
+            control_debug!("Unhandled CDC request {:#x}\n", request.b_request);
+            self.handle_unexpected_packet();
+            return;
+        }
+        let mut coding = [0u8; 8];
+        coding[..LINE_CODING_LEN].copy_from_slice(&self.line_coding.get().to_bytes());
+        let len = ::core::cmp::min(LINE_CODING_LEN, request.length() as usize);
+        self.ep0_in_buffers.map(|buf| {
+            buf[0] = u32::from_le_bytes([coding[0], coding[1], coding[2], coding[3]]);
+            buf[1] = u32::from_le_bytes([coding[4], coding[5], coding[6], coding[7]]);
+        });
+        self.ep0_in_descriptors.map(|descs| {
+            descs[0].flags = (DescFlag::HOST_READY |
+                              DescFlag::LAST |
+                              DescFlag::SHORT |
+                              DescFlag::IOC).bytes(len as u16);
+        });
+        self.expect_data_phase_in(transfer_type);
+    }
+
+    /// Handles SET_LINE_CODING, whose line coding follows in the data
+    /// stage, and SET_CONTROL_LINE_STATE, which opens or closes the port.
+    fn handle_cdc_host_to_interface(&self, transfer_type: TableCase, request: &SetupRequest) {
+        use self::types::SetupClassRequestType;
+        match request.class_request() {
+            SetupClassRequestType::SetLineCoding if request.length() as usize == LINE_CODING_LEN => {
+                self.begin_control_out(transfer_type, request);
+            },
+            SetupClassRequestType::SetControlLineState => {
+                self.set_cdc_port_open(request.value() & CONTROL_LINE_DTR != 0);
+                self.expect_status_phase_in(transfer_type);
+            },
+            _ => {
+                control_debug!("Unhandled CDC request {:#x}\n", request.b_request);
+                self.handle_unexpected_packet();
+            }
+        }
+    }
+
+    /// The control client of the interface a request to an interface is
+    /// for.
+    fn control_client(&self, request: &SetupRequest) -> Option<&'a dyn UsbControlClient> {
+        if request.index() > u8::max_value() as u16 {
+            return None;
+        }
+        self.interfaces.get()
+            .by_number(request.index() as u8)
+            .and_then(|interface| self.endpoint(interface.endpoint))
+            .and_then(|data| data.control_client.extract())
+    }
+
+    /// Starts receiving the data stage of a control OUT request, or handles
+    /// the request at once if it has none.
+    fn begin_control_out(&self, transfer_type: TableCase, request: &SetupRequest) {
+        let out = match ControlOut::new(*request) {
+            Some(out) => out,
+            None => {
+                control_debug!("Control OUT data stage of {} bytes is too long.\n", request.length());
+                self.handle_unexpected_packet();
+                return;
+            }
+        };
+        if out.is_complete() {
+            self.finish_control_out(transfer_type, &out);
+        } else {
+            self.trace.record(TraceEvent::DataStageOut, request.length() as u32);
+            self.control_out.put(out);
+            self.expect_data_phase_out(transfer_type);
+        }
+    }
+
+    /// Adds the packet just received on EP0 to the data stage, and handles
+    /// the request once the data stage is complete.
+    fn handle_control_out_data(&self) {
+        let received = self.ep0_out_descriptors.map_or(0, |descs| {
+            let remaining = (descs[self.last_ep0_out_idx.get()].flags.0 & 0xffff) as usize;
+            64 - ::core::cmp::min(remaining, 64)
+        });
+        let mut packet = [0u8; 64];
+        self.ep0_out_buffers.get().map(|bufs| {
+            let buf = &bufs[self.last_ep0_out_idx.get()];
+            for (bytes, word) in packet.chunks_mut(4).zip(buf.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes());
+            }
+            self.capture.record(0, CaptureKind::DataOut, &buf[..], received);
+        });
+        let complete = match self.control_out.map(|out| out.receive(&packet[..received])) {
+            Some(complete) => complete,
+            None => {
+                control_debug!("Control OUT data without a request.\n");
+                self.handle_unexpected_packet();
+                return;
+            }
+        };
+        if complete {
+            if let Some(out) = self.control_out.take() {
+                // The host is already waiting for the status stage, so the
+                // IN endpoint must not NAK.
+                self.finish_control_out(TableCase::C, &out);
+            }
+        } else {
+            self.ep0_out_descriptors.map(|descs| {
+                descs[self.next_ep0_out_idx.get()].flags =
+                    (DescFlag::HOST_READY | DescFlag::LAST | DescFlag::IOC).bytes(64);
+            });
+            self.registers.out_endpoints[0].control.write(EndpointControl::Enable::SET +
+                                                          EndpointControl::ClearNak::SET);
+        }
+    }
+
+    /// Handles a control OUT request with its complete data stage, and
+    /// acknowledges or stalls the status stage.
+    fn finish_control_out(&self, transfer_type: TableCase, out: &ControlOut) {
+        use self::types::SetupClassRequestType;
+        let request = out.request();
+        let rcode = match self.request_interface_kind(request) {
+            Some(InterfaceKind::CdcAcm { .. })
+                if request.req_type() == SetupRequestClass::Class &&
+                request.class_request() == SetupClassRequestType::SetLineCoding => {
+                match LineCoding::from_bytes(out.data()) {
+                    Some(coding) => {
+                        self.line_coding.set(coding);
+                        ReturnCode::SUCCESS
+                    },
+                    None => {
+                        control_debug!("SET_LINE_CODING: short data stage of {} bytes\n", out.data().len());
+                        ReturnCode::ESIZE
+                    },
+                }
+            },
+            _ => self.control_client(request).map_or(ReturnCode::ENOSUPPORT, |client| {
+                client.control_out(request.index() as u8, request, out.data())
+            }),
+        };
+        if rcode == ReturnCode::SUCCESS {
+            self.expect_status_phase_in(transfer_type);
+        } else {
+            control_debug!("Control OUT request {:#x} failed: {:?}\n", request.b_request, rcode);
+            self.handle_unexpected_packet();
+        }
+    }
 
     /// Handles requests with no accompanying data phase. This includes simple commands
     /// like setting the device address or its which of its configurations to use.
@@ -839,14 +1723,27 @@ impl<'a> USB<'a> {
                 // IN packet handshake, the hardware knows to wait, so
                 // we should just set it now.
                 let new_addr = (request.w_value & 0x7f) as u32;
+                self.trace.record(TraceEvent::SetAddress, new_addr);
                 self.registers.device_config.modify(DeviceConfig::DeviceAddress.val(new_addr));
-                self.setup_u2f_descriptors(); // Need to activate EP1 after SetAddress
+                self.setup_endpoints(); // Need to activate data endpoints after SetAddress
+                self.expect_status_phase_in(transfer_type);
+            }
+            SetFeature | ClearFeature if request.value() == FEATURE_DEVICE_REMOTE_WAKEUP => {
+                let enabled = request.request() == SetFeature;
+                control_debug!("USB: remote wakeup {}.\n", if enabled { "enabled" } else { "disabled" });
+                self.remote_wakeup_enabled.set(enabled);
                 self.expect_status_phase_in(transfer_type);
             }
             SetConfiguration => {
                 control_debug!("SetConfiguration: {:?} Type {:?} transfer\n", request.w_value, transfer_type);
                 self.configuration_current_value.set(request.w_value as u8);
+                self.trace.record(TraceEvent::SetConfiguration, request.w_value as u32);
+                self.clear_endpoint_halts();
                 self.expect_status_phase_in(transfer_type);
+                if self.reconnecting.get() {
+                    self.reconnecting.set(false);
+                    self.u2f_client.map(|client| client.reconnected());
+                }
             }
             _ => {
                 control_debug!("USB: unhandled no data setup packet {}", request.b_request as u8);
@@ -868,6 +1765,19 @@ impl<'a> USB<'a> {
             // 3. Set EP0 in DMA
             self.registers.in_endpoints[0].dma_address.set(&descs[0]);
             control_debug!("USB: expect_data_phase_in: endpoint 0 descriptor: flags={:08x} addr={:08x} \n", descs[0].flags.0, descs[0].addr);
+            let mut length = 0;
+            for desc in descs.iter() {
+                length += (desc.flags.0 & 0xffff) as usize;
+                if (desc.flags & DescFlag::LAST) == DescFlag::LAST {
+                    break;
+                }
+            }
+            self.trace.record(TraceEvent::DataStageIn, length as u32);
+            if self.capture.is_enabled() {
+                self.ep0_in_buffers.map(|bufs| {
+                    self.capture.record(0, CaptureKind::DataIn, &bufs[..], length);
+                });
+            }
 
             // If we clear the NAK (write CNAK) then this responds to
             // a non-setup packet, leading to failure as the code
@@ -901,10 +1811,48 @@ impl<'a> USB<'a> {
         });
     }
 
+    /// Receive the data stage of a request from the host over endpoint 0.
+    fn expect_data_phase_out(&self, transfer_type: TableCase) {
+        self.state.set(USBState::DataStageOut);
+        control_debug!("USB: expect_data_phase_out, case: {:?}\n", transfer_type);
+        self.ep0_out_descriptors.map(|descs| {
+            descs[self.next_ep0_out_idx.get()].flags =
+                (DescFlag::HOST_READY | DescFlag::LAST | DescFlag::IOC).bytes(64);
+        });
+
+        self.registers.device_all_ep_interrupt_mask.modify(AllEndpointInterrupt::OUT0::SET + AllEndpointInterrupt::IN0::CLEAR);
+
+        // As for the data phase in, only clear the NAK once the setup
+        // packet is done.
+        if transfer_type == TableCase::C {
+            self.registers.out_endpoints[0].control.write(EndpointControl::Enable::SET +
+                                                          EndpointControl::ClearNak::SET);
+        } else {
+            self.registers.out_endpoints[0].control.write(EndpointControl::Enable::SET);
+        }
+    }
+
+    /// Hands the first `len` bytes of the EP0 IN buffers to the
+    /// hardware, using as many IN descriptors as they need.
+    fn set_ep0_in_length(&self, len: usize) {
+        self.ep0_in_descriptors.map(|descs| {
+            let count = ::core::cmp::min(::core::cmp::max((len + 63) / 64, 1), descs.len());
+            for (i, desc) in descs.iter_mut().take(count).enumerate() {
+                let bytes = ::core::cmp::min(len - i * 64, 64) as u16;
+                desc.flags = if i + 1 == count {
+                    (DescFlag::HOST_READY | DescFlag::LAST | DescFlag::SHORT | DescFlag::IOC).bytes(bytes)
+                } else {
+                    DescFlag::HOST_READY.bytes(bytes)
+                };
+            }
+        });
+    }
+
     /// Setup endpoint 0 for a status phase with no data phase.
     fn expect_status_phase_in(&self, transfer_type: TableCase) {
         self.state.set(USBState::NoDataStage);
         control_debug!("USB: expect_status_phase_in, case: {:?}\n", transfer_type);
+        self.trace.record(TraceEvent::StatusStageIn, 0);
 
         self.ep0_in_descriptors.map(|descs| {
             // 1. Expect a zero-length in for the status phase
@@ -1024,57 +1972,16 @@ impl<'a> USB<'a> {
     }
 
     /// Generate the binary representation of the configuration descriptor for the
-    /// device. This is currently hardcoded to include:
-    ///   - The U2F Interface Descriptor
-    ///   - The HID Device Descriptor
-    ///   - The EP1 out Endpoint Descriptor (U2F)
-    ///   - The EP1 in Endpoint Descriptor (U2F)
-    ///   - The Shell Device Descriptor
+    /// device: the configuration, followed by the descriptors of each
+    /// registered interface (see `InterfaceKind::write_descriptors`).
     fn generate_full_configuration_descriptor(&self) {
         self.configuration_descriptor.map(|desc| {
-
-            let mut config = ConfigurationDescriptor::new(1, STRING_PLATFORM, 50);
-
-            let attributes_u2f_in = EndpointAttributes {
-                transfer: EndpointTransferType::Interrupt,
-                synchronization: EndpointSynchronizationType::None,
-                usage: EndpointUsageType::Data,
-            };
-            let attributes_u2f_out = EndpointAttributes {
-                transfer: EndpointTransferType::Interrupt,
-                synchronization: EndpointSynchronizationType::None,
-                usage: EndpointUsageType::Data,
-            };
-
-            let u2f = InterfaceDescriptor::new(STRING_INTERFACE2, 0, 3, 0, 0);
-            let hid = HidDeviceDescriptor::new();
-            let ep1out = EndpointDescriptor::new(0x01, attributes_u2f_out, 2);
-            let ep1in  = EndpointDescriptor::new(0x81, attributes_u2f_in, 2);
+            let interfaces = self.interfaces.get();
+            let mut config = ConfigurationDescriptor::new(interfaces.interface_count(), STRING_PLATFORM, 50);
+            config.set_remote_wakeup();
 
             let mut size: usize = config.length();
-            size += u2f.into_u8_buf(&mut desc[size..size + u2f.length()]);
-            size += hid.into_u8_buf(&mut desc[size..size + hid.length()]);
-            size += ep1out.into_u8_buf(&mut desc[size..size + ep1out.length()]);
-            size += ep1in.into_u8_buf(&mut desc[size..size + ep1in.length()]);
-
-            // In case we want to start including a shell like the normal gnubby.
-            // Note this requires changing config to have 2 interfaces, not 1.
-            /*let attributes_shell_in = EndpointAttributes {
-                transfer: EndpointTransferType::Bulk,
-                synchronization: EndpointSynchronizationType::None,
-                usage: EndpointUsageType::Data,
-            };
-            let attributes_shell_out = EndpointAttributes {
-                transfer: EndpointTransferType::Bulk,
-                synchronization: EndpointSynchronizationType::None,
-                usage: EndpointUsageType::Data,
-            };
-            let shell = InterfaceDescriptor::new(STRING_INTERFACE1, 1, 0xFF, 80, 1);
-            let ep2in  = EndpointDescriptor::new(0x82, attributes_shell_in, 10);
-            let ep2out = EndpointDescriptor::new(0x02, attributes_shell_out, 0);
-            size += shell.into_u8_buf(&mut desc[size..size + shell.length()]);
-            size += ep2in.into_u8_buf(&mut desc[size..size + ep2in.length()]);
-            size += ep2out.into_u8_buf(&mut desc[size..size + ep2out.length()]);*/
+            size += interfaces.write_descriptors(&mut desc[size..]);
 
             config.set_total_length(size as u16);
             config.into_u8_buf(&mut desc[0..config.length()]);
@@ -1090,6 +1997,59 @@ impl<'a> USB<'a> {
         self.configuration_total_length.get()
     }
 
+    /// Starts capturing control and data endpoint traffic into `records`,
+    /// timestamped with `timer` (which must be running at 1MHz). Intended
+    /// for debugging enumeration; see `usb::capture`.
+    pub fn enable_capture(&self,
+                          timer: &'a Timeus,
+                          records: &'static mut [CaptureRecord; CAPTURE_RECORD_COUNT]) {
+        self.capture.enable(timer, records);
+    }
+
+    /// Starts recording driver events into `records`, timestamped with
+    /// `timer` (which must be running at 1MHz); see `usb::trace`.
+    pub fn enable_trace(&self,
+                        timer: &'a Timeus,
+                        records: &'static mut [TraceRecord; TRACE_RECORD_COUNT]) {
+        self.trace.enable(timer, records);
+    }
+
+    /// Serves the blobs of `source` as HID feature reports (see
+    /// `feature_report`).
+    pub fn set_feature_report_source(&self, source: &'a dyn FeatureReportSource) {
+        self.feature_reports.set(source);
+    }
+
+    /// Prints and clears the captured packets. Returns ENOSUPPORT if
+    /// capture was not enabled.
+    pub fn dump_capture(&self) -> ReturnCode {
+        self.capture.dump()
+    }
+
+    /// Prints and clears the traced events. Returns ENOSUPPORT if tracing
+    /// was not enabled.
+    pub fn dump_trace(&self) -> ReturnCode {
+        self.trace.dump()
+    }
+
+    /// Sets which categories of debug messages (see `debug`) are printed.
+    /// Returns ENOSUPPORT in release builds, which print none.
+    pub fn set_debug_verbosity(&self, mask: u32) -> ReturnCode {
+        if !cfg!(debug_assertions) {
+            return ReturnCode::ENOSUPPORT;
+        }
+        if mask & !debug::ALL != 0 {
+            return ReturnCode::EINVAL;
+        }
+        DEBUG_VERBOSITY.store(mask, Ordering::Relaxed);
+        ReturnCode::SUCCESS
+    }
+
+    /// The categories of debug messages that are printed.
+    pub fn debug_verbosity(&self) -> u32 {
+        DEBUG_VERBOSITY.load(Ordering::Relaxed)
+    }
+
     /// Stalls both the IN and OUT endpoints for endpoint 0.
     //
     // A STALL condition indicates that an endpoint is unable to
@@ -1098,6 +2058,7 @@ impl<'a> USB<'a> {
     // indicate the request wasn't understood or needs to be resent.
     fn stall_both_fifos(&self) {
         control_debug!("USB: WaitingForSetupPacket in stall_both_fifos.\n");
+        self.trace.record(TraceEvent::Stall, 0);
         self.state.set(USBState::WaitingForSetupPacket);
         self.ep0_out_descriptors.map(|descs| {
             descs[self.next_ep0_out_idx.get()].flags = (DescFlag::LAST | DescFlag::IOC).bytes(64);
@@ -1153,37 +2114,170 @@ impl<'a> USB<'a> {
     // Construct a USB Device Descriptor from the configuration parameters
     // of the USB driver.
     fn generate_device_descriptor(&self) -> DeviceDescriptor {
+        // A device with an interface association descriptor (the CDC-ACM
+        // interfaces) must say so with the Miscellaneous class, Common
+        // Class subclass and IAD protocol.
+        let (class, sub_class, protocol) = if self.cdc_enabled() {
+            (0xef, 0x02, 0x01)
+        } else {
+            (self.device_class.get(), 0x00, 0x00)
+        };
         DeviceDescriptor {
             b_length: 18,
             b_descriptor_type: 1,
             bcd_usb: 0x0200,
-            b_device_class: self.device_class.get(),
-            b_device_sub_class: 0x00,
-            b_device_protocol: 0x00,
+            b_device_class: class,
+            b_device_sub_class: sub_class,
+            b_device_protocol: protocol,
             b_max_packet_size0: MAX_PACKET_SIZE as u8,
             id_vendor: self.vendor_id.get(),
             id_product: self.product_id.get(),
             bcd_device: 0x0100,
             i_manufacturer: STRING_VENDOR,
             i_product: STRING_BOARD,
-            i_serial_number: STRING_LANG,
+            i_serial_number: if self.has_serial_number() { STRING_SERIAL } else { 0 },
             b_num_configurations: 1,
         }
     }
 
 
+    /// Registers an interface of `kind` with its data on `endpoint`, which
+    /// uses `buffers` (see `interface`). Returns its interface number:
+    /// interfaces are numbered in the order they are registered. Must be
+    /// called before `init`, which generates the configuration descriptor.
+    pub fn register_interface(&self,
+                              kind: InterfaceKind,
+                              endpoint: usize,
+                              buffers: EndpointBuffers) -> Result<u8, ReturnCode> {
+        let mut interfaces = self.interfaces.get();
+        let interface = interfaces.add(kind, endpoint)?;
+        self.interfaces.set(interfaces);
+
+        let data = &self.endpoints[endpoint - 1];
+        data.out_descriptor.replace(buffers.out_descriptor);
+        data.out_buffer.set(Some(buffers.out_buffer));
+        data.in_descriptor.replace(buffers.in_descriptor);
+        data.in_buffer.replace(buffers.in_buffer);
+        Ok(interface.number)
+    }
+
+    /// Sets the client for class and vendor requests with data to the
+    /// interface numbered `interface`.
+    pub fn set_control_client(&self, interface: u8, client: &'a dyn UsbControlClient) -> ReturnCode {
+        match self.interfaces.get().by_number(interface).and_then(|i| self.endpoint(i.endpoint)) {
+            Some(data) => {
+                data.control_client.set(client);
+                ReturnCode::SUCCESS
+            },
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    /// Lets the U2F interface send and receive transfers of up to
+    /// `TRANSFER_SIZE_BYTES` in one operation, through the descriptor chains
+    /// in `buffers` (see `transfer`).
+    pub fn enable_u2f_transfers(&self, buffers: TransferBuffers) {
+        transfer::link(buffers.out_descriptors, buffers.out_buffer);
+        transfer::link(buffers.in_descriptors, buffers.in_buffer);
+        self.transfer_out_descriptors.replace(buffers.out_descriptors);
+        self.transfer_out_buffer.replace(buffers.out_buffer);
+        self.transfer_in_descriptors.replace(buffers.in_descriptors);
+        self.transfer_in_buffer.replace(buffers.in_buffer);
+    }
+
+    fn setup_endpoints(&self) {
+        // Setting up the endpoints clears their stalls.
+        self.halted_endpoints.set(0);
+        for interface in self.interfaces.get().iter() {
+            self.setup_endpoint(interface);
+        }
+    }
+
+    // Resets the data endpoint descriptors and buffers of `interface`;
+    // usb_reset() and init_ep0_descriptors() do these operations on the
+    // EP0 (control) descriptors and buffers. Arms the OUT endpoint to
+    // receive and leaves the IN endpoint idle until there is something to
+    // send. A CDC-ACM notification endpoint is activated but never
+    // enabled, so that it NAKs the host's polls. Isochronous OUT endpoints
+    // are left idle too, until the client picks a frame to receive in.
+    //
+    // This must be called after a SetAddress command, to enable data
+    // transmission.
+    fn setup_endpoint(&self, interface: Interface) {
+        let endpoint = interface.endpoint;
+        let data = match self.endpoint(endpoint) {
+            Some(data) => data,
+            None => return,
+        };
+        let transfer = interface.kind.transfer_type();
+        let endpoint_type = match transfer {
+            EndpointTransferType::Isochronous => EndpointControl::EndpointType::Isochronous,
+            EndpointTransferType::Bulk => EndpointControl::EndpointType::Bulk,
+            _ => EndpointControl::EndpointType::Interrupt,
+        };
+        let receive = transfer != EndpointTransferType::Isochronous;
+
+        data.out_descriptor.map(|out_desc| {
+            data.out_buffer.get().map(|out_buf| {
+                out_desc.flags = if receive {
+                    (DescFlag::LAST | DescFlag::HOST_READY | DescFlag::IOC).bytes(MAX_PACKET_SIZE)
+                } else {
+                    DescFlag::LAST | DescFlag::HOST_BUSY | DescFlag::IOC
+                };
+                out_desc.addr = out_buf.as_ptr() as usize;
+                self.registers.out_endpoints[endpoint].dma_address.set(&out_desc);
+            });
+        });
+        data.in_descriptor.map(|in_desc| {
+            data.in_buffer.map(|in_buf| {
+                in_desc.flags = DescFlag::LAST | DescFlag::HOST_BUSY | DescFlag::IOC;
+                in_desc.addr = in_buf.as_ptr() as usize;
+                self.registers.in_endpoints[endpoint].dma_address.set(&in_desc);
+            });
+        });
+
+        if receive {
+            self.registers.out_endpoints[endpoint].control.write(EndpointControl::Enable::SET +
+                                                                 EndpointControl::ClearNak::SET +
+                                                                 EndpointControl::UsbActiveEndpoint::SET +
+                                                                 endpoint_type +
+                                                                 EndpointControl::MaximumPacketSize.val(MAX_PACKET_SIZE as u32));
+        } else {
+            self.registers.out_endpoints[endpoint].control.write(EndpointControl::UsbActiveEndpoint::SET +
+                                                                 endpoint_type +
+                                                                 EndpointControl::MaximumPacketSize.val(MAX_PACKET_SIZE as u32));
+        }
+        self.registers.in_endpoints[endpoint].control.write(EndpointControl::UsbActiveEndpoint::SET +
+                                                            EndpointControl::TxFifoNumber.val(endpoint as u32) +
+                                                            endpoint_type +
+                                                            EndpointControl::MaximumPacketSize.val(MAX_PACKET_SIZE as u32));
+
+        if let InterfaceKind::CdcAcm { notification_endpoint } = interface.kind {
+            self.registers.in_endpoints[notification_endpoint].control.write(EndpointControl::UsbActiveEndpoint::SET +
+                                                                             EndpointControl::TxFifoNumber.val(notification_endpoint as u32) +
+                                                                             EndpointControl::EndpointType::Interrupt +
+                                                                             EndpointControl::MaximumPacketSize.val(MAX_PACKET_SIZE as u32));
+            // Whatever was on its way to the host before is gone.
+            self.set_cdc_port_open(false);
+        }
+        if interface.kind == InterfaceKind::U2fHid {
+            self.transfer_receiving.set(false);
+            self.transfer_sending.set(None);
+        }
+
+        let mask = self.registers.device_all_ep_interrupt_mask.get();
+        self.registers.device_all_ep_interrupt_mask.set(mask | endpoint_interrupt_bits(endpoint));
+    }
+
     /// Initialize the USB driver in device mode, so it can be begin
-    /// communicating with a connected host.
+    /// communicating with a connected host. The board registers its
+    /// interfaces with `register_interface` before calling this.
     pub fn init(&self,
                 ep0_out_descriptors: &'static mut [DMADescriptor; EP0_OUT_BUFFER_COUNT],
                 ep0_out_buffers: &'static mut [[u32; 16]; EP0_OUT_BUFFER_COUNT],
                 ep0_in_descriptors: &'static mut [DMADescriptor; EP0_IN_BUFFER_COUNT],
                 ep0_in_buffers: &'static mut [u32; 16 * 4],
-                ep1_out_descriptor: &'static mut DMADescriptor,
-                ep1_out_buffer: &'static mut [u32; 16],
-                ep1_in_descriptor: &'static mut DMADescriptor,
-                ep1_in_buffer: &'static mut [u32; 16],
-                configuration_buffer: &'static mut [u8; 64],
+                configuration_buffer: &'static mut [u8; CONFIGURATION_BUFFER_SIZE],
                 phy: PHY,
                 device_class: Option<u8>,
                 vendor_id: Option<u16>,
@@ -1193,10 +2287,6 @@ impl<'a> USB<'a> {
         self.ep0_out_buffers.set(Some(ep0_out_buffers));
         self.ep0_in_descriptors.replace(ep0_in_descriptors);
         self.ep0_in_buffers.replace(ep0_in_buffers);
-        self.ep1_out_descriptor.replace(ep1_out_descriptor);
-        self.ep1_out_buffer.set(Some(ep1_out_buffer));
-        self.ep1_in_descriptor.replace(ep1_in_descriptor);
-        self.ep1_in_buffer.replace(ep1_in_buffer);
         self.configuration_descriptor.replace(configuration_buffer);
         self.strings.replace(strings);
 
@@ -1316,6 +2406,7 @@ impl<'a> USB<'a> {
         //   * Enumeration Done
         //   * Early Suspend
         //   * USB Suspend
+        //   * Resume/Remote Wakeup
         //   * SOF
         //
         self.registers
@@ -1328,7 +2419,10 @@ impl<'a> USB<'a> {
                    Interrupt::OutEndpoints::SET +
                    Interrupt::EarlySuspend::SET +
                    Interrupt::Suspend::SET +
-                   Interrupt::StartOfFrame::SET);
+                   Interrupt::ResumeWakeup::SET +
+                   Interrupt::StartOfFrame::SET +
+                   Interrupt::OTG::SET +
+                   Interrupt::SessionRequest::SET);
 
         // Power on programming done
         self.registers.device_control.modify(DeviceControl::PowerOnProgrammingDone::SET);
@@ -1349,88 +2443,59 @@ impl<'a> USB<'a> {
 
 }
 
-/// Implementation of the HID U2F API for the USB device. It assumes
-/// that U2F is over endpoint 1.
+/// Implementation of the HID U2F API for the USB device, on the endpoint
+/// of the board's U2F HID interface. Its methods fail or do nothing unless
+/// the board registered one.
 impl<'a> UsbHidU2f<'a> for USB<'a> {
     fn set_u2f_client(&self, client: &'a dyn UsbHidU2fClient<'a>) {
         self.u2f_client.set(client);
     }
 
-    // Note that this resets the EP1 (U2F) descriptors and buffers;
-    // usb_reset() and init_ep0_descriptors() do these operations on the
-    // EP0 (control) descriptors and buffers.
-    //
-    // This method must be called after a SetConfiguration and SetAddress
-    // command, to initialize EP1 and enable data transmission.
     fn setup_u2f_descriptors(&self) {
-        self.ep1_out_descriptor.map(|out_desc| {
-            self.ep1_out_buffer.get().map(|out_buf| {
-                out_desc.flags = (DescFlag::LAST |
-                                  DescFlag::HOST_READY |
-                                  DescFlag::IOC).bytes(U2F_REPORT_SIZE);
-                out_desc.addr = out_buf.as_ptr() as usize;
-                self.registers.out_endpoints[1].dma_address.set(&out_desc);
-            });
-        });
-
-        self.ep1_in_descriptor.map(|in_desc| {
-            self.ep1_in_buffer.map(|in_buf| {
-                in_desc.flags =  DescFlag::LAST | DescFlag::HOST_BUSY | DescFlag::IOC;
-                in_desc.addr = in_buf.as_ptr() as usize;
-                self.registers.in_endpoints[1].dma_address.set(&in_desc);
-
-            });
-        });
-
-        self.ep1_out_descriptor.map(|_out_desc| {
-            self.ep1_out_buffer.get().map(|_out_buf| {
-                self.registers.out_endpoints[1].control.write(EndpointControl::Enable::SET +
-                                                              EndpointControl::ClearNak::SET +
-                                                              EndpointControl::UsbActiveEndpoint::SET +
-                                                              EndpointControl::EndpointType::Interrupt +
-                                                              EndpointControl::MaximumPacketSize.val(U2F_REPORT_SIZE as u32));
-            });
-        });
-
-        self.ep1_in_descriptor.map(|_in_desc| {
-            self.ep1_in_buffer.map(|_in_buf| {
-                self.registers.in_endpoints[1].control.write(EndpointControl::UsbActiveEndpoint::SET +
-                                                             EndpointControl::TxFifoNumber.val(1) +
-                                                             EndpointControl::EndpointType::Interrupt +
-                                                             EndpointControl::MaximumPacketSize.val(U2F_REPORT_SIZE as u32));
-            });
-        });
-
-        self.registers.device_all_ep_interrupt_mask.modify(AllEndpointInterrupt::OUT1::SET + AllEndpointInterrupt::IN1::SET);
+        self.interfaces.get()
+            .find(|kind| kind == InterfaceKind::U2fHid)
+            .map(|interface| self.setup_endpoint(interface));
     }
 
     fn force_reconnect(&self) -> ReturnCode {
-        panic!("Trying to force reconnect USB EP1\n");
+        self.usb_reconnect();
+        ReturnCode::SUCCESS
+    }
+
+    fn remote_wakeup(&self) -> ReturnCode {
+        self.usb_remote_wakeup()
     }
 
     fn enable_rx(&self) -> ReturnCode {
-        self.ep1_enable_rx();
-        ReturnCode::SUCCESS
+        if self.transfer_receiving.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.u2f_endpoint().map_or(ReturnCode::FAIL, |endpoint| self.ep_enable_rx(endpoint))
     }
 
     fn iface_respond(&self) -> ReturnCode {ReturnCode::FAIL}
 
     fn transmit_ready(&self) -> bool {
-        self.ep1_tx_fifo_is_ready()
+        self.u2f_endpoint().map_or(false, |endpoint| self.u2f_transmit_ready(endpoint))
     }
 
     fn put_frame(&self, frame: &[u32; 16]) -> ReturnCode {
         data_debug!("U2F: put_frame\n");
-        if !self.ep1_tx_fifo_is_ready() {
+        let endpoint = match self.u2f_endpoint() {
+            Some(endpoint) => endpoint,
+            None => return ReturnCode::FAIL,
+        };
+        if !self.u2f_transmit_ready(endpoint) {
             data_debug!("Tried to put frame but busy.\n");
             ReturnCode::EBUSY
         } else {
-            self.ep1_in_buffer.map(|hardware_buffer| {
+            self.endpoints[endpoint - 1].in_buffer.map(|hardware_buffer| {
                 for i in 0..frame.len() {
                     hardware_buffer[i] = frame[i];
                 }
+                self.capture.record(endpoint as u8, CaptureKind::DataIn, &hardware_buffer[..], frame.len() * 4);
             });
-            self.ep1_enable_tx();
+            self.ep_enable_tx(endpoint, U2F_REPORT_SIZE);
             data_debug!("Sending frame.\n");
             ReturnCode::SUCCESS
         }
@@ -1438,38 +2503,85 @@ impl<'a> UsbHidU2f<'a> for USB<'a> {
 
     fn put_slice(&self, slice: &[u8]) -> ReturnCode {
         data_debug!("U2F: put_slice\n");
-        if slice.len() > 64 {
-            data_debug!("U2F EP1: ERROR: slice too large\n");
-            ReturnCode::ESIZE
-        } else if !self.ep1_tx_fifo_is_ready() {
-            data_debug!("U2F EP1: ERROR: Tried to put slice but busy.\n");
-            ReturnCode::EBUSY
-        } else {
-            self.ep1_in_buffer.map(|hardware_buffer| {
-                for (i, c) in slice.iter().enumerate() {
-                    let hw_index = i / 4;
-                    let byte_index = i % 4;
-                    if byte_index == 0 {
-                        hardware_buffer[hw_index] = *c as u32;
-                    } else {
-                        hardware_buffer[hw_index] |= (*c as u32) << (8 * byte_index);
-                    }
+        if self.transfer_sending.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.u2f_endpoint().map_or(ReturnCode::FAIL, |endpoint| self.ep_put_slice(endpoint, slice))
+    }
+
+    fn enable_rx_transfer(&self) -> ReturnCode {
+        let endpoint = match self.u2f_endpoint() {
+            Some(endpoint) => endpoint,
+            None => return ReturnCode::FAIL,
+        };
+        if self.transfer_receiving.get() ||
+            self.registers.out_endpoints[endpoint].control.is_set(EndpointControl::Enable) {
+            // Still waiting for a frame or another transfer.
+            return ReturnCode::EBUSY;
+        }
+        self.transfer_out_descriptors.map_or(ReturnCode::ENOSUPPORT, |descs| {
+            transfer::prepare_out(descs);
+            self.registers.out_endpoints[endpoint].dma_address.set(&descs[0]);
+            self.transfer_receiving.set(true);
+            self.registers.out_endpoints[endpoint].control.modify(EndpointControl::Enable::SET +
+                                                                  EndpointControl::ClearNak::SET);
+            data_debug!("EP{}: armed for a transfer.\n", endpoint);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn get_transfer(&self, buffer: &mut [u8]) -> usize {
+        let received = self.transfer_out_descriptors
+            .map_or(None, |descs| transfer::received_length(descs))
+            .unwrap_or(0);
+        let len = ::core::cmp::min(received, buffer.len());
+        self.transfer_out_buffer.map(|hardware_buffer| {
+            for (i, byte) in buffer[..len].iter_mut().enumerate() {
+                *byte = (hardware_buffer[i / 4] >> (8 * (i % 4))) as u8;
+            }
+        });
+        len
+    }
+
+    fn put_transfer(&self, data: &[u8]) -> ReturnCode {
+        let endpoint = match self.u2f_endpoint() {
+            Some(endpoint) => endpoint,
+            None => return ReturnCode::FAIL,
+        };
+        if data.len() > TRANSFER_SIZE_BYTES {
+            return ReturnCode::ESIZE;
+        }
+        if !self.u2f_transmit_ready(endpoint) {
+            return ReturnCode::EBUSY;
+        }
+        self.transfer_in_descriptors.map_or(ReturnCode::ENOSUPPORT, |descs| {
+            self.transfer_in_buffer.map(|hardware_buffer| {
+                for (word, bytes) in hardware_buffer.iter_mut().zip(data.chunks(4)) {
+                    let mut padded = [0u8; 4];
+                    padded[..bytes.len()].copy_from_slice(bytes);
+                    *word = u32::from_le_bytes(padded);
                 }
+                self.capture.record(endpoint as u8, CaptureKind::DataIn, &hardware_buffer[..], data.len());
             });
-            self.ep1_enable_tx();
-            data_debug!("U2FData: Started slice send.\n");
-            data_debug!("U2FData: {} words available.\n", self.registers.in_endpoints[1].tx_fifo_status.get());
+            let packets = transfer::prepare_in(descs, data.len());
+            self.registers.in_endpoints[endpoint].dma_address.set(&descs[0]);
+            self.transfer_sending.set(Some(data.len()));
+            self.registers.in_endpoints[endpoint].control.modify(EndpointControl::Enable::SET +
+                                                                 EndpointControl::ClearNak::SET);
+            data_debug!("EP{}: sending a transfer of {} packets.\n", endpoint, packets);
             ReturnCode::SUCCESS
-        }
+        })
     }
 
     fn get_frame(&self, frame: &mut [u32; 16]) {
         // Unlike the CR52 code, we don't need to disable interrupts,
         // because Tock handles the USB interrupts as bottom halves. -pal
-        self.ep1_out_buffer.get().map(|hardware_buffer| {
-            for i in 0..16 {
-                frame[i] = hardware_buffer[i];
-            }
+        self.u2f_endpoint().map(|endpoint| {
+            self.endpoints[endpoint - 1].out_buffer.get().map(|hardware_buffer| {
+                for i in 0..16 {
+                    frame[i] = hardware_buffer[i];
+                }
+            });
         });
     }
 
@@ -1478,17 +2590,145 @@ impl<'a> UsbHidU2f<'a> for USB<'a> {
         if slice.len() > 64 {
             ReturnCode::ESIZE
         } else {
-            self.ep1_out_buffer.get().map(|hardware_buffer| {
-                let len = slice.len();
-                for i in 0..len {
-                    let hw_index = i / 4;
-                    let byte_index = i % 4;
-                    slice[i] = ((hardware_buffer[hw_index] >> (8 * byte_index)) & 0xff) as u8;
-                }
+            self.u2f_endpoint().map(|endpoint| {
+                self.endpoints[endpoint - 1].out_buffer.get().map(|hardware_buffer| {
+                    let len = slice.len();
+                    for i in 0..len {
+                        let hw_index = i / 4;
+                        let byte_index = i % 4;
+                        slice[i] = ((hardware_buffer[hw_index] >> (8 * byte_index)) & 0xff) as u8;
+                    }
+                });
             });
             ReturnCode::SUCCESS
         }
     }
+
+    fn dump_capture(&self) -> ReturnCode {
+        self.capture.dump()
+    }
+
+    fn dump_trace(&self) -> ReturnCode {
+        self.trace.dump()
+    }
+
+    fn set_debug_verbosity(&self, mask: u32) -> ReturnCode {
+        USB::set_debug_verbosity(self, mask)
+    }
+}
+
+/// The CDC-ACM serial interface, on the data endpoint the board registered
+/// it with. Its methods fail or do nothing unless the board registered one.
+impl<'a> UsbCdcAcm<'a> for USB<'a> {
+    fn set_cdc_client(&self, client: &'a dyn UsbCdcAcmClient) {
+        self.cdc_client.set(client);
+    }
+
+    fn cdc_port_open(&self) -> bool {
+        self.cdc_port_open.get()
+    }
+
+    fn cdc_transmit_ready(&self) -> bool {
+        self.cdc_interface().map_or(false, |interface| self.ep_tx_fifo_is_ready(interface.endpoint))
+    }
+
+    fn cdc_put_slice(&self, slice: &[u8]) -> ReturnCode {
+        self.cdc_interface().map_or(ReturnCode::ENOSUPPORT, |interface| {
+            self.ep_put_slice(interface.endpoint, slice)
+        })
+    }
+
+    fn cdc_get_slice(&self, slice: &mut [u8]) -> usize {
+        self.cdc_interface().map_or(0, |interface| self.ep_get_slice(interface.endpoint, slice))
+    }
+
+    fn cdc_enable_rx(&self) -> ReturnCode {
+        self.cdc_interface().map_or(ReturnCode::ENOSUPPORT, |interface| {
+            self.ep_enable_rx(interface.endpoint)
+        })
+    }
+}
+
+/// Raw packets on the endpoints of vendor-specific bulk interfaces. The
+/// methods fail with EINVAL, or do nothing, for any other endpoint.
+impl<'a> UsbBulk<'a> for USB<'a> {
+    fn set_bulk_client(&self, endpoint: usize, client: &'a dyn UsbBulkClient) {
+        if self.is_bulk_endpoint(endpoint) {
+            self.endpoints[endpoint - 1].bulk_client.set(client);
+        }
+    }
+
+    fn bulk_transmit_ready(&self, endpoint: usize) -> bool {
+        self.is_bulk_endpoint(endpoint) && self.ep_tx_fifo_is_ready(endpoint)
+    }
+
+    fn bulk_put_slice(&self, endpoint: usize, slice: &[u8]) -> ReturnCode {
+        if !self.is_bulk_endpoint(endpoint) {
+            return ReturnCode::EINVAL;
+        }
+        self.ep_put_slice(endpoint, slice)
+    }
+
+    fn bulk_get_slice(&self, endpoint: usize, slice: &mut [u8]) -> usize {
+        if !self.is_bulk_endpoint(endpoint) {
+            return 0;
+        }
+        self.ep_get_slice(endpoint, slice)
+    }
+
+    fn bulk_enable_rx(&self, endpoint: usize) -> ReturnCode {
+        if !self.is_bulk_endpoint(endpoint) {
+            return ReturnCode::EINVAL;
+        }
+        self.ep_enable_rx(endpoint)
+    }
+}
+
+/// Packets scheduled by frame on the endpoints of vendor-specific
+/// isochronous interfaces. The methods fail with EINVAL, or do nothing,
+/// for any other endpoint.
+impl<'a> UsbIsochronous<'a> for USB<'a> {
+    fn set_isochronous_client(&self, endpoint: usize, client: &'a dyn UsbIsochronousClient) {
+        if self.is_isochronous_endpoint(endpoint) {
+            self.endpoints[endpoint - 1].isochronous_client.set(client);
+        }
+    }
+
+    fn frame_number(&self) -> u16 {
+        self.current_frame()
+    }
+
+    fn isochronous_transmit_ready(&self, endpoint: usize) -> bool {
+        self.is_isochronous_endpoint(endpoint) && self.ep_tx_fifo_is_ready(endpoint)
+    }
+
+    fn isochronous_put_slice(&self, endpoint: usize, frame: u16, slice: &[u8]) -> ReturnCode {
+        if !self.is_isochronous_endpoint(endpoint) ||
+            !is_future_frame(self.current_frame(), frame) {
+            return ReturnCode::EINVAL;
+        }
+        if !self.ep_tx_fifo_is_ready(endpoint) {
+            return ReturnCode::EBUSY;
+        }
+        self.endpoints[endpoint - 1].in_frame.set(frame);
+        self.ep_put_slice(endpoint, slice)
+    }
+
+    fn isochronous_get_slice(&self, endpoint: usize, slice: &mut [u8]) -> usize {
+        if !self.is_isochronous_endpoint(endpoint) {
+            return 0;
+        }
+        self.ep_get_slice(endpoint, slice)
+    }
+
+    fn isochronous_enable_rx(&self, endpoint: usize, frame: u16) -> ReturnCode {
+        if !self.is_isochronous_endpoint(endpoint) ||
+            !is_future_frame(self.current_frame(), frame) {
+            return ReturnCode::EINVAL;
+        }
+        self.endpoints[endpoint - 1].out_frame.set(frame);
+        self.ep_enable_rx(endpoint)
+    }
 }
 
 /// Which physical connection to use
@@ -1562,7 +2802,7 @@ impl TableCase {
 }
 
 fn print_in_endpoint_interrupt_status(status: LocalRegisterCopy<u32, InEndpointInterruptMask::Register>) {
-    int_debug!("USB in endpoint interrupt, status: {:08x}\n", status);
+    int_debug!("USB in endpoint interrupt, status: {:08x}\n", status.get());
     if status.is_set(InEndpointInterruptMask::TransferCompleted)    {data_debug!("  +Transfer complete\n");}
     if status.is_set(InEndpointInterruptMask::EndpointDisabled)    {data_debug!("  +Endpoint disabled\n");}
     if status.is_set(InEndpointInterruptMask::AhbError)            {data_debug!("  +AHB Error\n");}
@@ -1607,27 +2847,46 @@ fn print_usb_interrupt_status(status: LocalRegisterCopy<u32, Interrupt::Register
 
 // These are HW, not USB descriptors: they describe the
 // current state of hardware for USB endpoints, including
-// status flags and a pointer into a data buffer.
-pub static mut EP0_OUT_DESCRIPTORS: [DMADescriptor; EP0_OUT_BUFFER_COUNT] = [DMADescriptor {
+// status flags and a pointer into a data buffer. The DMA engine
+// reads and writes them, so they come from pools like the buffers.
+const EMPTY_DESCRIPTOR: DMADescriptor = DMADescriptor {
     flags: DescFlag::HOST_BUSY,
     addr: 0,
-}; EP0_OUT_BUFFER_COUNT];
-pub static mut EP0_IN_DESCRIPTORS: [DMADescriptor; EP0_IN_BUFFER_COUNT] = [DMADescriptor {
-    flags: DescFlag::HOST_BUSY,
-    addr: 0,
-}; EP0_IN_BUFFER_COUNT];
-
-pub static mut EP0_OUT_BUFFERS: [[u32; EP_BUFFER_SIZE_WORDS]; EP0_OUT_BUFFER_COUNT] =
-                                  [[0; EP_BUFFER_SIZE_WORDS]; EP0_OUT_BUFFER_COUNT];
-pub static mut EP0_IN_BUFFER: [u32; EP_BUFFER_SIZE_WORDS * EP0_IN_BUFFER_COUNT] =
-                                [0; EP_BUFFER_SIZE_WORDS * EP0_IN_BUFFER_COUNT];
-
-pub static mut EP1_OUT_DESCRIPTOR: DMADescriptor = DMADescriptor {flags: DescFlag::HOST_BUSY,
-                                                                  addr: 0};
-pub static mut EP1_IN_DESCRIPTOR:  DMADescriptor = DMADescriptor {flags: DescFlag::HOST_BUSY,
-                                                                  addr: 0};
-pub static mut EP1_OUT_BUFFER: [u32; EP_BUFFER_SIZE_WORDS] = [0; EP_BUFFER_SIZE_WORDS];
-pub static mut EP1_IN_BUFFER:  [u32; EP_BUFFER_SIZE_WORDS] = [0; EP_BUFFER_SIZE_WORDS];
+};
+pub static EP0_OUT_DESCRIPTOR_POOL:
+    DmaPool<DMADescriptor, Align4, EP0_OUT_BUFFER_COUNT, 1> =
+    DmaPool::new("usb ep0 out descriptors", EMPTY_DESCRIPTOR);
+pub static EP0_IN_DESCRIPTOR_POOL:
+    DmaPool<DMADescriptor, Align4, EP0_IN_BUFFER_COUNT, 1> =
+    DmaPool::new("usb ep0 in descriptors", EMPTY_DESCRIPTOR);
+
+// The endpoint buffers are DMA targets, so they come from pools that keep
+// them word aligned.
+pub static EP0_OUT_BUFFER_POOL:
+    DmaPool<[u32; EP_BUFFER_SIZE_WORDS], Align4, EP0_OUT_BUFFER_COUNT, 1> =
+    DmaPool::new("usb ep0 out", [0; EP_BUFFER_SIZE_WORDS]);
+pub static EP0_IN_BUFFER_POOL:
+    DmaPool<u32, Align4, {EP_BUFFER_SIZE_WORDS * EP0_IN_BUFFER_COUNT}, 1> =
+    DmaPool::new("usb ep0 in", 0);
+
+// One block for EP1 OUT and one for EP1 IN.
+pub static EP1_DESCRIPTOR_POOL: DmaPool<DMADescriptor, Align4, 1, 2> =
+    DmaPool::new("usb ep1 descriptors", EMPTY_DESCRIPTOR);
+pub static EP1_BUFFER_POOL: DmaPool<u32, Align4, EP_BUFFER_SIZE_WORDS, 2> =
+    DmaPool::new("usb ep1", 0);
+
+// One block for EP2 OUT and one for EP2 IN, for a board's second interface.
+pub static EP2_DESCRIPTOR_POOL: DmaPool<DMADescriptor, Align4, 1, 2> =
+    DmaPool::new("usb ep2 descriptors", EMPTY_DESCRIPTOR);
+pub static EP2_BUFFER_POOL: DmaPool<u32, Align4, EP_BUFFER_SIZE_WORDS, 2> =
+    DmaPool::new("usb ep2", 0);
+
+// One block for U2F transfers OUT and one for IN, for boards that enable them.
+pub static U2F_TRANSFER_DESCRIPTOR_POOL: DmaPool<DMADescriptor, Align4, TRANSFER_PACKET_COUNT, 2> =
+    DmaPool::new("usb u2f transfer descriptors", EMPTY_DESCRIPTOR);
+pub static U2F_TRANSFER_BUFFER_POOL: DmaPool<u32, Align4, TRANSFER_SIZE_WORDS, 2> =
+    DmaPool::new("usb u2f transfer", 0);
 
 // Buffer used to store device configuration (descriptors), initialized at startup.
-pub static mut CONFIGURATION_BUFFER: [u8; EP_BUFFER_SIZE_BYTES] = [0; EP_BUFFER_SIZE_BYTES];
+pub static CONFIGURATION_BUFFER_POOL: DmaPool<u8, Align4, CONFIGURATION_BUFFER_SIZE, 1> =
+    DmaPool::new("usb configuration", 0);
@@ -14,286 +14,16 @@
 
 use core::ops::{BitAnd, BitOr};
 use kernel::common::cells::VolatileCell;
-use kernel::common::registers::{register_bitfields, ReadWrite};
-
-register_bitfields![u32,
-    pub AhbConfig [  // OTG Databook, Table 5-9
-        GlobalInterruptMask                OFFSET(0)  NUMBITS(1) [],
-        BurstLength                        OFFSET(1)  NUMBITS(4) [
-            Len1Word    = 0b0000,
-            Len4Words   = 0b0001,
-            Len8Words   = 0b0010,
-            Len16Words  = 0b0011,
-            Len32Words  = 0b0100,
-            Len64Words  = 0b0101,
-            Len128Words = 0b0110,
-            Len256Words = 0b0111
-        ],
-        DmaEnable                          OFFSET(5)  NUMBITS(1) [],
-        NonPeriodicTxFifoEmptyLevel        OFFSET(7)  NUMBITS(1) [],
-        PeriodicTxFifoEmptyLevel           OFFSET(8)  NUMBITS(1) [],
-        RemoteMemorySupport                OFFSET(21) NUMBITS(1) [],
-        NotifyAllDmaWrite                  OFFSET(22) NUMBITS(1) [],
-        AhbSingleSupport                   OFFSET(23) NUMBITS(1) [],
-        InverseDescEndianness              OFFSET(24) NUMBITS(1) []
-    ],
-
-    pub UsbConfiguration [  // OTG Databook, Table 5-10
-        TimeoutCalibration                 OFFSET(0)  NUMBITS(3) [],
-        PhysicalInterface                  OFFSET(3)  NUMBITS(1) [
-            Bits8  = 0,
-            Bits16 = 1
-        ],
-        UlpiUtmiSelect                     OFFSET(4)  NUMBITS(1) [
-            Utmi = 0,
-            Ulpi = 1
-        ],
-        FullSpeedSerialInterfaceSelect     OFFSET(5)  NUMBITS(1) [
-            Unidirectional6Pin = 0,
-            Bidirectional3Pin  = 1
-        ],
-        PhySelect                          OFFSET(6)  NUMBITS(1) [
-            Usb20HighSpeed     = 0,
-            Usb11FullSpeed     = 1
-        ],
-        UlpiDdrSelect                      OFFSET(7)  NUMBITS(1) [
-            SingleDataRate8bit = 0,
-            DoubleDataRate4bit = 1
-        ],
-        SrpCapable                         OFFSET(8)  NUMBITS(1) [],
-        HnpCapable                         OFFSET(9)  NUMBITS(1) [],
-        UsbTurnaroundTime                  OFFSET(10) NUMBITS(4) []
-        // Bit 14 reserved
-        // Bits 15+ not used by SW; not included because they won't be tested
-    ],
-
-    pub Reset [  // OTG Databook, Table 5-11
-        AhbMasterIdle                      OFFSET(31) NUMBITS(1) [],
-        DmaRequestSignal                   OFFSET(30) NUMBITS(1) [],
-        TxFifoNumber                       OFFSET(6)  NUMBITS(5) [
-            Fifo0       =  0,
-            Fifo1       =  1,
-            Fifo2       =  2,
-            Fifo3       =  3,
-            Fifo4       =  4,
-            Fifo5       =  5,
-            Fifo6       =  6,
-            Fifo7       =  7,
-            Fifo8       =  8,
-            Fifo9       =  9,
-            Fifo10      = 10,
-            Fifo11      = 11,
-            Fifo12      = 12,
-            Fifo13      = 13,
-            Fifo14      = 14,
-            Fifo15      = 15,
-            AllFifos    = 16 // It's 5 bits, 0x10 means all FIFOs
-        ],
-        TxFifoFlush                        OFFSET(5)  NUMBITS(1) [],
-        RxFifoFlush                        OFFSET(4)  NUMBITS(1) [],
-        InTokenSequenceLearningQueueFlush  OFFSET(3)  NUMBITS(1) [],
-        HostFrameCounterReset              OFFSET(2)  NUMBITS(1) [],
-        PiuFsDedicatedControllerSoftReset  OFFSET(1)  NUMBITS(1) []
-    ],
-
-    pub Interrupt [  // OTG Databook, Table 5-13
-        // Note this field is not valid on the Mask register
-        CurrentMode                        OFFSET(0)  NUMBITS(1) [
-            Host   = 0b0,
-            Device = 0b1
-        ],
-        ModeMismatch                       OFFSET(1)  NUMBITS(1) [],
-        OTG                                OFFSET(2)  NUMBITS(1) [],
-        StartOfFrame                       OFFSET(3)  NUMBITS(1) [],
-        RxFifoNotEmpty                     OFFSET(4)  NUMBITS(1) [],
-        NonPeriodicTxFifoEmpty             OFFSET(5)  NUMBITS(1) [],
-        GlobalInNak                        OFFSET(6)  NUMBITS(1) [],
-        GlobalOutNak                       OFFSET(7)  NUMBITS(1) [],
-        EarlySuspend                       OFFSET(10) NUMBITS(1) [],
-        Suspend                            OFFSET(11) NUMBITS(1) [],
-        Reset                              OFFSET(12) NUMBITS(1) [],
-        EnumerationDone                    OFFSET(13) NUMBITS(1) [],
-        OutIsochronousPacketDropped        OFFSET(14) NUMBITS(1) [],
-        EndOfPeriodicFrame                 OFFSET(15) NUMBITS(1) [],
-        RestoreDone                        OFFSET(16) NUMBITS(1) [],
-        EndpointMismatch                   OFFSET(17) NUMBITS(1) [],
-        InEndpoints                        OFFSET(18) NUMBITS(1) [],
-        OutEndpoints                       OFFSET(19) NUMBITS(1) [],
-        IncompleteIsochronousInTransfer    OFFSET(20) NUMBITS(1) [],
-        IncompletePeriodicTransfer         OFFSET(21) NUMBITS(1) [],
-        DataFetchSuspended                 OFFSET(22) NUMBITS(1) [],
-        ResetDetected                      OFFSET(23) NUMBITS(1) [],
-        ConnectIDChange                    OFFSET(28) NUMBITS(1) [],
-        DisconnectDetected                 OFFSET(29) NUMBITS(1) [],
-        SessionRequest                     OFFSET(30) NUMBITS(1) [],
-        ResumeWakeup                       OFFSET(31) NUMBITS(1) []
-    ],
-
-    pub Gpio [  // OTG Databook, Table 5-22
-        Gpi                                OFFSET(0)  NUMBITS(16) [],
-        GpoRegister                        OFFSET(16) NUMBITS(4)  [],
-        GpoValue                           OFFSET(20) NUMBITS(8)  [],
-        GpoOperation                       OFFSET(31) NUMBITS(1)  [
-            Read  = 0,
-            Write = 1
-        ]
-    ],
-
-    pub DeviceConfig [  // OTG Databook, Table 5-53
-        DeviceSpeed                        OFFSET(0) NUMBITS(2) [
-            High  = 0b00,
-            Full2 = 0b01,
-            Low   = 0b10,
-            Full1 = 0b11
-        ],
-        DeviceAddress                      OFFSET(4)  NUMBITS(7) [],
-        PeriodicFrameInterval              OFFSET(11) NUMBITS(2) [
-            Interval80 = 0b00,
-            Interval85 = 0b01,
-            Interval90 = 0b10,
-            Interval95 = 0b11
-        ],
-        EnableDeviceOutNak                 OFFSET(13) NUMBITS(1) [],
-        XcvrDelay                          OFFSET(14) NUMBITS(1) [],
-        ErraticErrorInterruptMask          OFFSET(15) NUMBITS(1) [],
-        InEndpointMismatchCount            OFFSET(18) NUMBITS(5) [],
-        EnableScatterGatherDMAInDeviceMode OFFSET(23) NUMBITS(1) [],
-        PeriodicScheduling                 OFFSET(24) NUMBITS(2) [
-            Interval25 = 0b00,
-            Interval50 = 0b01,
-            Interval75 = 0b10
-        ],
-        ResumeValidationPeriod             OFFSET(26) NUMBITS(6) []
-    ],
-
-    pub DeviceControl [  // OTG Databook, Table 5-54
-        RemoteWakeupSignaling              OFFSET(0)  NUMBITS(1) [],
-        SoftDisconnect                     OFFSET(1)  NUMBITS(1) [],
-        GlobalNonPeriodicInNakStatus       OFFSET(2)  NUMBITS(1) [],
-        GlobalOutNakStatus                 OFFSET(3)  NUMBITS(1) [],
-        TestControl                        OFFSET(4)  NUMBITS(3) [
-            Disabled        = 0b000,
-            ModeJ           = 0b001,
-            ModeK           = 0b010,
-            ModeSE0Nak      = 0b011,
-            ModePacket      = 0b100,
-            ModeForceEnable = 0b101
-        ],
-        SetGlobalNonPeriodicInNak          OFFSET(7)  NUMBITS(1) [],
-        ClearGlobalNonPeriodicInNak        OFFSET(8)  NUMBITS(1) [],
-        SetGlobalOutNak                    OFFSET(9)  NUMBITS(1) [],
-        ClearGlobalOutNak                  OFFSET(10) NUMBITS(1) [],
-        PowerOnProgrammingDone             OFFSET(11) NUMBITS(1) [],
-        GlobalMultiCount                   OFFSET(13) NUMBITS(2) [
-            CountInvalid   = 0b00,
-            Count1Packet   = 0b01,
-            Count2Packets  = 0b10,
-            Count3Packets  = 0b11
-        ],
-        IgnoreFrameNumber                  OFFSET(15) NUMBITS(1) [],
-        NakOnBabbleError                   OFFSET(16) NUMBITS(1) [],
-        EnableContinueOnBna                OFFSET(17) NUMBITS(1) [],
-        DeepSleepBESLReject                OFFSET(18) NUMBITS(1) []
-    ],
-
-    pub InEndpointInterruptMask [  // OTG Databook, Table 5-57
-        TransferCompleted                0,
-        EndpointDisabled                 1,
-        AhbError                         2,
-        Timeout                          3,
-        InTokenReceivedWhenTxFifoEmpty   4,
-        InTokenEndpointMismatched        5,
-        InEndpointNakEffective           6,
-        // Bit 7 reserved
-        TxFifoUnderrun                   8,
-        BufferNotAvailable               9,
-        // Bits 10-12 reserved
-        NAK                             13
-        // Bits 14-31 reserved
-    ],
-
-    pub OutEndpointInterruptMask [  // OTG Databook, Table 5-58
-        TransferCompleted                     0,
-        EndpointDisabled                      1,
-        AhbError                              2,
-        SetupPhaseDone                        3,
-        OutTokenReceivedWhenEndpointDisabled  4,
-        StatusPhaseReceived                   5,
-        BackToBackSetupPacketsReceived        6,
-        // Bit 7 reserved
-        OutPacketError                        8,
-        BnaInterrupt                          9,
-        // Bits 10-11 reserved
-        BabbleError                          12,
-        Nak                                  13,
-        Nyet                                 14
-        // Bits 15-31 reserved
-    ],
-
-    pub AllEndpointInterrupt [  // OTG Databook Table 5-59
-        IN0    0,
-        IN1    1,
-        IN2    2,
-        IN3    3,
-        IN4    4,
-        IN5    5,
-        IN6    6,
-        IN7    7,
-        IN8    8,
-        IN9    9,
-        IN10  10,
-        IN11  11,
-        IN12  12,
-        IN13  13,
-        IN14  14,
-        IN15  15,
-        OUT0  16,
-        OUT1  17,
-        OUT2  18,
-        OUT3  19,
-        OUT4  20,
-        OUT5  21,
-        OUT6  22,
-        OUT7  23,
-        OUT8  24,
-        OUT9  25,
-        OUT10 26,
-        OUT11 27,
-        OUT12 28,
-        OUT13 29,
-        OUT14 30,
-        OUT15 31
-    ],
-
-    pub EndpointControl [
-        MaximumPacketSize                  OFFSET(0)  NUMBITS(11) [],
-        NextEndpoint                       OFFSET(11) NUMBITS(4)  [],
-        UsbActiveEndpoint                  OFFSET(15) NUMBITS(1)  [],
-        NakStatus                          OFFSET(17) NUMBITS(1)  [
-            TransmittingNonNakHandshakes = 0,
-            TransmittingNakHandshakes    = 1
-        ],
-        EndpointType                       OFFSET(18) NUMBITS(2)  [
-            Control     = 0b00,
-            Isochronous = 0b01,
-            Bulk        = 0b10,
-            Interrupt   = 0b11
-        ],
-        SnoopMode                          OFFSET(20) NUMBITS(1)  [],
-        Stall                              OFFSET(21) NUMBITS(1)  [],
-        TxFifoNumber                       OFFSET(22) NUMBITS(4)  [],
-        ClearNak                           OFFSET(26) NUMBITS(1)  [],
-        SetNak                             OFFSET(27) NUMBITS(1)  [],
-        Disable                            OFFSET(30) NUMBITS(1)  [],
-        Enable                             OFFSET(31) NUMBITS(1)  []
-    ]
-];
+use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
+
+// Generated from registers/usb.toml.
+include!(concat!(env!("OUT_DIR"), "/usb_bitfields.rs"));
 
 
 #[repr(C)]
 pub struct Registers {
     pub _otg_control: VolatileCell<u32>,
-    pub _otg_interrupt: VolatileCell<u32>,
+    pub otg_interrupt: ReadWrite<u32, OtgInterrupt::Register>,
     pub ahb_config: ReadWrite<u32, AhbConfig::Register>,
     pub configuration: ReadWrite<u32, UsbConfiguration::Register>,
     pub reset: ReadWrite<u32, Reset::Register>,
@@ -335,7 +65,7 @@ pub struct Registers {
 
     pub device_config: ReadWrite<u32, DeviceConfig::Register>,
     pub device_control: ReadWrite<u32, DeviceControl::Register>,
-    pub _device_status: VolatileCell<u32>,
+    pub device_status: ReadOnly<u32, DeviceStatus::Register>,
 
     _reserved_3: u32,
     // 0x810
@@ -359,7 +89,7 @@ pub struct Registers {
     // 0xd00
     _reserved6: [u32; 64],
     // 0xe00
-    pub _power_clock_gating_control: VolatileCell<u32>,
+    pub power_clock_gating_control: ReadWrite<u32, PowerClockGatingControl::Register>,
 }
 
 #[repr(C)]
@@ -511,11 +241,29 @@ impl DescFlag {
     /// Host Busy status
     pub const HOST_BUSY: DescFlag = DescFlag(0b11 << 30);
 
+    // Isochronous descriptors have a narrower byte count, followed by the
+    // frame they are scheduled for, and report whether the packet went
+    // through in bits 29:28.
+    /// Mask for pulling out the transfer status of an isochronous descriptor
+    pub const ISOCHRONOUS_STATUS_MASK: DescFlag = DescFlag(0b11 << 28);
+    /// The packet was sent or received in its frame
+    pub const ISOCHRONOUS_SUCCESS: DescFlag = DescFlag(0b00 << 28);
+
     /// Set the number of bytes to transmit
     pub const fn bytes(self, bytes: u16) -> DescFlag {
         DescFlag(self.0 | bytes as u32)
     }
 
+    /// Schedule an isochronous descriptor for `frame`
+    pub const fn frame(self, frame: u16) -> DescFlag {
+        DescFlag(self.0 | (frame as u32 & 0x7ff) << 12)
+    }
+
+    /// The byte count of an isochronous descriptor
+    pub const fn isochronous_bytes(self) -> u16 {
+        (self.0 & 0x7ff) as u16
+    }
+
     pub const fn to_u32(self) -> u32 {
         self.0
     }
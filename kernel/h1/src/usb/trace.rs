@@ -0,0 +1,231 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A ring of USB driver events for diagnosing enumeration on hardware.
+//!
+//! The debug messages (see `usb::debug`) are only in debug builds, and
+//! printing them changes the timing the host sees. The trace instead
+//! stores a `TraceRecord` per event: a microsecond timestamp, what
+//! happened and one word of detail, such as the first word of a SETUP
+//! packet. Recording costs a few stores, so boards can leave it enabled.
+//! `dump` prints the ring to the console as `usbtrace:` lines.
+//!
+//! Tracing is disabled unless a board calls `USB::enable_trace`.
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::ReturnCode;
+use crate::timeus::Timeus;
+
+/// Number of events kept in the trace ring.
+pub const TRACE_RECORD_COUNT: usize = 128;
+
+/// What happened; the meaning of a record's `arg` depends on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// The host reset the bus.
+    Reset = 0,
+    /// Reset signaling ended; `arg` is the enumerated speed from DSTS.
+    EnumerationDone = 1,
+    Suspend = 2,
+    Resume = 3,
+    /// VBUS went away.
+    Disconnect = 4,
+    /// VBUS came back.
+    SessionRequest = 5,
+    /// A SETUP packet arrived; `arg` holds bmRequestType, bRequest and
+    /// wValue, as in the packet's first word.
+    Setup = 6,
+    /// `arg` is the new device address.
+    SetAddress = 7,
+    /// `arg` is the new configuration value.
+    SetConfiguration = 8,
+    /// A data stage to the host started; `arg` is its length.
+    DataStageIn = 9,
+    /// A data stage from the host started; `arg` is its length.
+    DataStageOut = 10,
+    /// A status stage to the host started.
+    StatusStageIn = 11,
+    /// EP0 stalled a request.
+    Stall = 12,
+    /// The host halted (`arg` bit 8 set) or resumed the endpoint whose
+    /// address is in the low byte of `arg`.
+    EndpointHalt = 13,
+    /// The device drove remote wakeup signaling.
+    RemoteWakeup = 14,
+    /// The device soft disconnected and connected again.
+    SoftReconnect = 15,
+}
+
+/// A single traced event.
+#[derive(Clone, Copy)]
+pub struct TraceRecord {
+    pub timestamp_us: u32,
+    pub event: TraceEvent,
+    pub arg: u32,
+}
+
+impl TraceRecord {
+    pub const EMPTY: TraceRecord = TraceRecord {
+        timestamp_us: 0,
+        event: TraceEvent::Reset,
+        arg: 0,
+    };
+}
+
+pub struct UsbTrace<'a> {
+    // Microsecond timer used to timestamp records.
+    timer: OptionalCell<&'a Timeus>,
+    records: TakeCell<'static, [TraceRecord; TRACE_RECORD_COUNT]>,
+    // Index of the next record to write and the number of valid records.
+    next: Cell<usize>,
+    count: Cell<usize>,
+    // Number of records overwritten before they were dumped.
+    dropped: Cell<u32>,
+}
+
+impl<'a> UsbTrace<'a> {
+    pub const fn new() -> UsbTrace<'a> {
+        UsbTrace {
+            timer: OptionalCell::empty(),
+            records: TakeCell::empty(),
+            next: Cell::new(0),
+            count: Cell::new(0),
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Starts tracing into `records`. `timer` must already be running at
+    /// 1MHz.
+    pub fn enable(&self,
+                  timer: &'a Timeus,
+                  records: &'static mut [TraceRecord; TRACE_RECORD_COUNT]) {
+        self.timer.set(timer);
+        self.enable_records(records);
+    }
+
+    fn enable_records(&self, records: &'static mut [TraceRecord; TRACE_RECORD_COUNT]) {
+        self.records.replace(records);
+        self.next.set(0);
+        self.count.set(0);
+        self.dropped.set(0);
+    }
+
+    pub fn record(&self, event: TraceEvent, arg: u32) {
+        let timestamp_us = self.timer.map_or(0, |timer| timer.now());
+        self.record_at(timestamp_us, event, arg);
+    }
+
+    fn record_at(&self, timestamp_us: u32, event: TraceEvent, arg: u32) {
+        self.records.map(|records| {
+            records[self.next.get()] = TraceRecord {
+                timestamp_us: timestamp_us,
+                event: event,
+                arg: arg,
+            };
+            self.next.set((self.next.get() + 1) % TRACE_RECORD_COUNT);
+            if self.count.get() == TRACE_RECORD_COUNT {
+                self.dropped.set(self.dropped.get().wrapping_add(1));
+            } else {
+                self.count.set(self.count.get() + 1);
+            }
+        });
+    }
+
+    /// Calls `f` on each record, oldest first, and empties the ring.
+    /// Returns the number of records that were overwritten before, or
+    /// None if tracing is not enabled.
+    fn drain<F: FnMut(&TraceRecord)>(&self, mut f: F) -> Option<u32> {
+        self.records.map(|records| {
+            let count = self.count.get();
+            let first = (self.next.get() + TRACE_RECORD_COUNT - count) % TRACE_RECORD_COUNT;
+            for n in 0..count {
+                f(&records[(first + n) % TRACE_RECORD_COUNT]);
+            }
+            self.count.set(0);
+            self.dropped.replace(0)
+        })
+    }
+
+    /// Prints all traced events, oldest first, and empties the ring.
+    ///
+    /// Each event is printed as
+    /// `usbtrace: <timestamp_us> <event> <arg hex>`.
+    pub fn dump(&self) -> ReturnCode {
+        if !self.records.is_some() {
+            return ReturnCode::ENOSUPPORT;
+        }
+        print!("usbtrace: begin\n");
+        let dropped = self.drain(|record| {
+            print!("usbtrace: {} {:?} {:#x}\n", record.timestamp_us, record.event, record.arg);
+        });
+        print!("usbtrace: end dropped={}\n", dropped.unwrap_or(0));
+        ReturnCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    fn trace() -> UsbTrace<'static> {
+        let records = std::boxed::Box::leak(std::boxed::Box::new(
+            [TraceRecord::EMPTY; TRACE_RECORD_COUNT]));
+        let trace = UsbTrace::new();
+        trace.enable_records(records);
+        trace
+    }
+
+    fn events(trace: &UsbTrace) -> (Vec<(u32, TraceEvent, u32)>, Option<u32>) {
+        let mut events = Vec::new();
+        let dropped = trace.drain(|record| {
+            events.push((record.timestamp_us, record.event, record.arg));
+        });
+        (events, dropped)
+    }
+
+    #[test]
+    fn drains_in_order() {
+        let trace = trace();
+        trace.record_at(10, TraceEvent::Reset, 0);
+        trace.record_at(20, TraceEvent::Setup, 0x0100_0680);
+        assert_eq!(events(&trace),
+                   (std::vec![(10, TraceEvent::Reset, 0), (20, TraceEvent::Setup, 0x0100_0680)], Some(0)));
+        assert_eq!(events(&trace), (std::vec![], Some(0)));
+    }
+
+    #[test]
+    fn overwrites_oldest() {
+        let trace = trace();
+        for n in 0..TRACE_RECORD_COUNT as u32 + 3 {
+            trace.record_at(n, TraceEvent::Stall, n);
+        }
+        let (events, dropped) = events(&trace);
+        assert_eq!(dropped, Some(3));
+        assert_eq!(events.len(), TRACE_RECORD_COUNT);
+        assert_eq!(events[0].0, 3);
+        assert_eq!(events[TRACE_RECORD_COUNT - 1].0, TRACE_RECORD_COUNT as u32 + 2);
+    }
+
+    #[test]
+    fn disabled_records_nothing() {
+        let trace = UsbTrace::new();
+        trace.record_at(1, TraceEvent::Reset, 0);
+        assert_eq!(events(&trace), (std::vec![], None));
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Transfers of more than one packet on the U2F endpoint.
+//!
+//! A transfer moves up to `TRANSFER_SIZE_BYTES` in one operation through a
+//! chain of DMA descriptors, one per 64-byte packet, that the core walks
+//! until the descriptor marked LAST. Sending, the last packet is short
+//! unless the length is a multiple of the packet size. Receiving, every
+//! descriptor is armed for a full packet and the transfer ends at the first
+//! short packet or when the chain is full. The board hands the chains and
+//! their buffers to `USB::enable_u2f_transfers`.
+
+use crate::usb::constants::{EP_BUFFER_SIZE_BYTES, EP_BUFFER_SIZE_WORDS, MAX_PACKET_SIZE};
+use crate::usb::registers::{DescFlag, DMADescriptor};
+
+/// The largest transfer.
+pub const TRANSFER_SIZE_BYTES: usize = 1024;
+pub const TRANSFER_SIZE_WORDS: usize = TRANSFER_SIZE_BYTES / 4;
+/// The number of packets, and so of descriptors, in a transfer.
+pub const TRANSFER_PACKET_COUNT: usize = TRANSFER_SIZE_BYTES / EP_BUFFER_SIZE_BYTES;
+
+/// The descriptor chains and buffers for transfers in each direction.
+pub struct TransferBuffers {
+    pub out_descriptors: &'static mut [DMADescriptor; TRANSFER_PACKET_COUNT],
+    pub out_buffer: &'static mut [u32; TRANSFER_SIZE_WORDS],
+    pub in_descriptors: &'static mut [DMADescriptor; TRANSFER_PACKET_COUNT],
+    pub in_buffer: &'static mut [u32; TRANSFER_SIZE_WORDS],
+}
+
+/// Points each descriptor of the chain at its packet of `buffer`.
+pub fn link(descriptors: &mut [DMADescriptor; TRANSFER_PACKET_COUNT],
+            buffer: &[u32; TRANSFER_SIZE_WORDS]) {
+    for (desc, packet) in descriptors.iter_mut().zip(buffer.chunks(EP_BUFFER_SIZE_WORDS)) {
+        desc.flags = DescFlag::HOST_BUSY;
+        desc.addr = packet.as_ptr() as usize;
+    }
+}
+
+/// Readies the chain to send `len` bytes and returns the number of
+/// packets; `len` must be at most `TRANSFER_SIZE_BYTES`. Only the last
+/// packet interrupts on completion.
+pub fn prepare_in(descriptors: &mut [DMADescriptor; TRANSFER_PACKET_COUNT], len: usize) -> usize {
+    let packet_size = MAX_PACKET_SIZE as usize;
+    let count = ::core::cmp::max((len + packet_size - 1) / packet_size, 1);
+    for (i, desc) in descriptors.iter_mut().take(count).enumerate() {
+        let bytes = ::core::cmp::min(len - i * packet_size, packet_size);
+        let mut flags = DescFlag::HOST_READY;
+        if i + 1 == count {
+            flags = flags | DescFlag::LAST | DescFlag::IOC;
+            if bytes < packet_size {
+                flags = flags | DescFlag::SHORT;
+            }
+        }
+        desc.flags = flags.bytes(bytes as u16);
+    }
+    count
+}
+
+/// Readies the whole chain to receive. Every packet interrupts on
+/// completion, so that a short packet anywhere ends the transfer.
+pub fn prepare_out(descriptors: &mut [DMADescriptor; TRANSFER_PACKET_COUNT]) {
+    let last = descriptors.len() - 1;
+    for (i, desc) in descriptors.iter_mut().enumerate() {
+        let mut flags = DescFlag::HOST_READY | DescFlag::IOC;
+        if i == last {
+            flags = flags | DescFlag::LAST;
+        }
+        desc.flags = flags.bytes(MAX_PACKET_SIZE);
+    }
+}
+
+/// Returns the length of a finished transfer received on the chain, or
+/// None while the host is still sending it. Each descriptor counts down the
+/// bytes it has room for.
+pub fn received_length(descriptors: &[DMADescriptor; TRANSFER_PACKET_COUNT]) -> Option<usize> {
+    let packet_size = MAX_PACKET_SIZE as usize;
+    let mut total = 0;
+    for (i, desc) in descriptors.iter().enumerate() {
+        if desc.flags & DescFlag::STATUS_MASK != DescFlag::DMA_DONE {
+            return None;
+        }
+        let remaining = ::core::cmp::min((desc.flags.0 & 0xffff) as usize, packet_size);
+        total += packet_size - remaining;
+        if remaining > 0 || i + 1 == descriptors.len() {
+            return Some(total);
+        }
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    fn chain() -> [DMADescriptor; TRANSFER_PACKET_COUNT] {
+        [DMADescriptor { flags: DescFlag::HOST_BUSY, addr: 0 }; TRANSFER_PACKET_COUNT]
+    }
+
+    // What the core leaves in a descriptor after receiving `bytes`.
+    fn receive(desc: &mut DMADescriptor, bytes: u16) {
+        let armed = desc.flags.0 & 0xffff;
+        desc.flags = DescFlag((desc.flags.0 & !(0b11 << 30) & !0xffff) | (armed - bytes as u32))
+            | DescFlag::DMA_DONE;
+    }
+
+    #[test]
+    fn prepares_in_packets() {
+        let mut descs = chain();
+        assert_eq!(prepare_in(&mut descs, 150), 3);
+        assert_eq!(descs[0].flags, DescFlag::HOST_READY.bytes(64));
+        assert_eq!(descs[1].flags, DescFlag::HOST_READY.bytes(64));
+        assert_eq!(descs[2].flags, (DescFlag::HOST_READY | DescFlag::LAST |
+                                    DescFlag::IOC | DescFlag::SHORT).bytes(22));
+
+        assert_eq!(prepare_in(&mut descs, 128), 2);
+        assert_eq!(descs[1].flags, (DescFlag::HOST_READY | DescFlag::LAST | DescFlag::IOC).bytes(64));
+
+        assert_eq!(prepare_in(&mut descs, 0), 1);
+        assert_eq!(descs[0].flags, (DescFlag::HOST_READY | DescFlag::LAST |
+                                    DescFlag::IOC | DescFlag::SHORT).bytes(0));
+
+        assert_eq!(prepare_in(&mut descs, TRANSFER_SIZE_BYTES), TRANSFER_PACKET_COUNT);
+        assert_eq!(descs[TRANSFER_PACKET_COUNT - 1].flags & DescFlag::LAST, DescFlag::LAST);
+    }
+
+    #[test]
+    fn ends_at_short_packet() {
+        let mut descs = chain();
+        prepare_out(&mut descs);
+        receive(&mut descs[0], 64);
+        assert_eq!(received_length(&descs), None);
+        receive(&mut descs[1], 64);
+        receive(&mut descs[2], 10);
+        assert_eq!(received_length(&descs), Some(138));
+    }
+
+    #[test]
+    fn ends_when_chain_is_full() {
+        let mut descs = chain();
+        prepare_out(&mut descs);
+        for desc in descs.iter_mut() {
+            receive(desc, 64);
+        }
+        assert_eq!(received_length(&descs), Some(TRANSFER_SIZE_BYTES));
+    }
+
+    #[test]
+    fn links_packets() {
+        let mut descs = chain();
+        let buffer = [0u32; TRANSFER_SIZE_WORDS];
+        link(&mut descs, &buffer);
+        let base = buffer.as_ptr() as usize;
+        assert_eq!(descs[0].addr, base);
+        assert_eq!(descs[3].addr, base + 3 * EP_BUFFER_SIZE_BYTES);
+    }
+}
@@ -16,7 +16,7 @@
 
 use core::ops::Deref;
 use super::serialize::Serialize;
-use crate::usb::constants::Descriptor;
+use crate::usb::constants::{Descriptor, STRING_LANG, STRING_SERIAL};
 use crate::usb::constants::MAX_PACKET_SIZE;
 use crate::usb::constants::U2F_REPORT_SIZE;
 
@@ -97,7 +97,7 @@ impl ConfigurationDescriptor {
     /// descriptor is `i_configuration`. The value `b_max_power` sets
     /// the maximum power of the device in 2mA increments.  The
     /// configuration has `bm_attributes` set to bus powered (not
-    /// remote wakeup).
+    /// remote wakeup, see `set_remote_wakeup`).
     pub fn new(num_interfaces: u8,
                i_configuration: u8,
                b_max_power: u8) -> ConfigurationDescriptor {
@@ -150,59 +150,154 @@ impl ConfigurationDescriptor {
         self.w_total_length = len;
     }
 
+    /// Marks the configuration as able to wake the host from suspend.
+    pub fn set_remote_wakeup(&mut self) {
+        self.bm_attributes |= 0b00100000;
+    }
+
     pub fn length(&self) -> usize {
         CONFIGURATION_DESCRIPTOR_LENGTH as usize
     }
 }
 
 
-#[derive(Debug)]
+/// The language ID of US English, the language of the device's strings.
+pub const LANGUAGE_ENGLISH_US: u16 = 0x0409;
+
+/// The number of strings the USB stack expects, at the STRING_* indices in
+/// `constants`.
+pub const STRING_COUNT: usize = 8;
+
+// A string descriptor is at most 255 bytes: its 2-byte header and up to 126
+// UTF-16 code units. Longer strings are truncated.
+const MAX_STRING_UNITS: usize = 126;
+
+#[derive(Clone, Copy, Debug)]
+enum StringContent {
+    Languages(&'static [u16]),
+    Text(&'static str),
+    // A number written as `SERIAL_NUMBER_DIGITS` upper-case hex digits.
+    Hex(u64),
+}
+
+const SERIAL_NUMBER_DIGITS: usize = 16;
+
+/// A string descriptor: either the language IDs the device supports
+/// (string 0), or a string that is encoded as UTF-16 when it is sent.
+#[derive(Clone, Copy, Debug)]
 pub struct StringDescriptor {
     pub b_length: u8,
     pub b_descriptor_type: u8,
-    pub b_string: &'static [u16],
+    content: StringContent,
 }
 
 impl StringDescriptor {
-    pub fn new(str: &'static [u16]) -> StringDescriptor {
+    pub fn new(text: &'static str) -> StringDescriptor {
+        let units = ::core::cmp::min(text.encode_utf16().count(), MAX_STRING_UNITS);
+        StringDescriptor::with_content(StringContent::Text(text), units)
+    }
+
+    /// A serial number string: `id` in hex, e.g. the device ID from the
+    /// fuses.
+    pub fn serial_number(id: u64) -> StringDescriptor {
+        StringDescriptor::with_content(StringContent::Hex(id), SERIAL_NUMBER_DIGITS)
+    }
+
+    /// Whether the string has no characters.
+    pub fn is_empty(&self) -> bool {
+        self.length() <= 2
+    }
+
+    /// The descriptor listing the supported `languages`, which is string 0.
+    pub fn languages(languages: &'static [u16]) -> StringDescriptor {
+        let units = ::core::cmp::min(languages.len(), MAX_STRING_UNITS);
+        StringDescriptor::with_content(StringContent::Languages(languages), units)
+    }
+
+    fn with_content(content: StringContent, units: usize) -> StringDescriptor {
         StringDescriptor {
-            b_length: (str.len() * 2 + 2) as u8,
+            b_length: (2 + 2 * units) as u8,
             b_descriptor_type: Descriptor::String as u8,
-            b_string: str,
+            content: content,
         }
     }
 
-    pub fn into_u32_buf(&self, buf: &mut [u32; 64]) -> usize {
-        let count = self.b_string.len();
-        if count == 0 {
-            buf[0] = (self.b_length as u32)          << 0 |
-                     (self.b_descriptor_type as u32) << 8;
-            2
-        } else {
-            buf[0] = (self.b_length as u32)          << 0 |
-                     (self.b_descriptor_type as u32) << 8 |
-                     (self.b_string[0] as u32)       << 16;
-            for i in 1..count {
-                // The first 16 bits of the message are the
-                // length and type. The next 16 bits of the message are the first
-                // wide character of the string (index 0). So this means that bits
-                // 16..31 of buf[0] are b_string[0], bits 0..15 of buf[1] are string[1],
-                // bits 16..31 of buf[1] are string[2].
-                if i % 2 == 1 {
-                    buf[(i / 2) + 1] = self.b_string[i] as u32;
-                } else {
-                    buf[i / 2] = buf[i / 2] | (self.b_string[i] as u32) << 16;
+    // Calls `f` with each UTF-16 code unit of the descriptor and its index.
+    fn for_each_unit<F: FnMut(usize, u16)>(&self, mut f: F) {
+        match self.content {
+            StringContent::Languages(languages) => {
+                for (i, &language) in languages.iter().take(MAX_STRING_UNITS).enumerate() {
+                    f(i, language);
+                }
+            }
+            StringContent::Text(text) => {
+                for (i, unit) in text.encode_utf16().take(MAX_STRING_UNITS).enumerate() {
+                    f(i, unit);
+                }
+            }
+            StringContent::Hex(value) => {
+                for i in 0..SERIAL_NUMBER_DIGITS {
+                    let digit = (value >> (4 * (SERIAL_NUMBER_DIGITS - 1 - i))) & 0xf;
+                    f(i, b"0123456789ABCDEF"[digit as usize] as u16);
                 }
             }
-            2 + 2 * count
         }
     }
 
+    pub fn into_u32_buf(&self, buf: &mut [u32; 64]) -> usize {
+        buf[0] = (self.b_length as u32)          << 0 |
+                 (self.b_descriptor_type as u32) << 8;
+        self.for_each_unit(|i, unit| {
+            // The header takes the first 16 bits, so unit `i` is the
+            // (i + 1)th half-word of the buffer.
+            let half = i + 1;
+            if half % 2 == 0 {
+                buf[half / 2] = unit as u32;
+            } else {
+                buf[half / 2] |= (unit as u32) << 16;
+            }
+        });
+        self.length()
+    }
+
     pub fn length(&self) -> usize {
         self.b_length as usize
     }
 }
 
+/// Builds the string descriptors a board passes to `USB::init`, from
+/// strings given at the STRING_* indices in `constants`. Strings that are
+/// not given are empty, and string 0 lists US English.
+pub struct StringDescriptors {
+    strings: [StringDescriptor; STRING_COUNT],
+}
+
+impl StringDescriptors {
+    pub fn new() -> StringDescriptors {
+        let mut strings = [StringDescriptor::new(""); STRING_COUNT];
+        strings[STRING_LANG as usize] = StringDescriptor::languages(&[LANGUAGE_ENGLISH_US]);
+        StringDescriptors { strings: strings }
+    }
+
+    /// Sets the string at `index`, which must be one of the STRING_*
+    /// indices other than STRING_LANG.
+    pub fn string(mut self, index: u8, text: &'static str) -> StringDescriptors {
+        self.strings[index as usize] = StringDescriptor::new(text);
+        self
+    }
+
+    /// Sets the serial number string to `id` in hex. Without one, the
+    /// device reports no serial number.
+    pub fn serial_number(mut self, id: u64) -> StringDescriptors {
+        self.strings[STRING_SERIAL as usize] = StringDescriptor::serial_number(id);
+        self
+    }
+
+    pub fn build(self) -> [StringDescriptor; STRING_COUNT] {
+        self.strings
+    }
+}
+
 unsafe impl Serialize for ConfigurationDescriptor {}
 
 #[derive(Debug)]
@@ -271,7 +366,7 @@ impl InterfaceDescriptor {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum EndpointTransferType {
     Control     = 0b00,
     Isochronous = 0b01,
@@ -307,13 +402,15 @@ pub struct EndpointAttributes {
 
 impl Into<u8> for EndpointAttributes {
     fn into(self) -> u8 {
+        // Only isochronous endpoints have synchronization and usage types;
+        // the bits are reserved for the others.
         match self.transfer {
-            EndpointTransferType::Isochronous => self.transfer as u8,
-            _ => {
+            EndpointTransferType::Isochronous => {
                 self.transfer as u8 |
                 (self.synchronization as u8) << 2 |
                 (self.usage as u8) << 4
             }
+            _ => self.transfer as u8,
         }
     }
 }
@@ -453,7 +550,11 @@ pub enum SetupRequestType {
 #[repr(u8)]
 pub enum SetupClassRequestType {
     Undefined = 0,
+    GetReport = 1,
     SetIdle = 10,
+    SetLineCoding = 0x20,
+    GetLineCoding = 0x21,
+    SetControlLineState = 0x22,
 }
 
 
@@ -486,7 +587,7 @@ pub enum SetupRecipient {
     Reserved  = 4,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct SetupRequest {
     pub bm_request_type: u8,
     pub b_request: u8,
@@ -551,7 +652,11 @@ impl SetupRequest {
 
     pub fn class_request(&self) -> SetupClassRequestType {
         match self.b_request {
+            1  => SetupClassRequestType::GetReport,
             10 => SetupClassRequestType::SetIdle,
+            0x20 => SetupClassRequestType::SetLineCoding,
+            0x21 => SetupClassRequestType::GetLineCoding,
+            0x22 => SetupClassRequestType::SetControlLineState,
             _  => SetupClassRequestType::Undefined,
         }
     }
@@ -613,3 +718,77 @@ pub struct U2fHidSequenceFrame {
     sequence_num: u8,
     data: [u8; U2F_REPORT_SIZE as usize - 6],
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::usb::constants::{STRING_INTERFACE1, STRING_VENDOR};
+
+    fn bytes(descriptor: &StringDescriptor) -> std::vec::Vec<u8> {
+        let mut buf = [0u32; 64];
+        let len = descriptor.into_u32_buf(&mut buf);
+        buf.iter().flat_map(|word| word.to_le_bytes().to_vec()).take(len).collect()
+    }
+
+    #[test]
+    fn encodes_strings() {
+        let shell = StringDescriptor::new("Shell");
+        assert_eq!(shell.length(), 12);
+        assert_eq!(bytes(&shell), [12, 3, b'S', 0, b'h', 0, b'e', 0, b'l', 0, b'l', 0]);
+
+        // Characters outside the BMP take two code units.
+        assert_eq!(StringDescriptor::new("é🔑").length(), 8);
+        assert_eq!(StringDescriptor::new("").length(), 2);
+    }
+
+    #[test]
+    fn truncates_long_strings() {
+        let long = StringDescriptor::new(
+            "0123456789012345678901234567890123456789012345678901234567890123\
+             0123456789012345678901234567890123456789012345678901234567890123");
+        assert_eq!(long.length(), 254);
+        assert_eq!(bytes(&long).len(), 254);
+    }
+
+    #[test]
+    fn builds_string_table() {
+        let strings = StringDescriptors::new()
+            .string(STRING_VENDOR, "Google Inc.")
+            .string(STRING_INTERFACE1, "Shell")
+            .build();
+        assert_eq!(bytes(&strings[STRING_LANG as usize]), [4, 3, 0x09, 0x04]);
+        assert_eq!(strings[STRING_VENDOR as usize].length(), 24);
+        assert_eq!(strings[STRING_INTERFACE1 as usize].length(), 12);
+        assert_eq!(strings[STRING_COUNT - 1].length(), 2);
+        assert!(strings[STRING_SERIAL as usize].is_empty());
+    }
+
+    #[test]
+    fn encodes_serial_numbers() {
+        let strings = StringDescriptors::new().serial_number(0x0123_4567_89ab_cdef).build();
+        let serial = &strings[STRING_SERIAL as usize];
+        assert!(!serial.is_empty());
+        let digits: std::vec::Vec<u8> = bytes(serial)[2..].iter().step_by(2).cloned().collect();
+        assert_eq!(&digits[..], b"0123456789ABCDEF");
+        assert_eq!(bytes(&StringDescriptor::serial_number(0x2a))[2..6], [b'0', 0, b'0', 0]);
+        assert_eq!(bytes(&StringDescriptor::serial_number(0x2a))[30..34], [b'2', 0, b'A', 0]);
+    }
+
+    #[test]
+    fn encodes_endpoint_attributes() {
+        let isochronous: u8 = EndpointAttributes {
+            transfer: EndpointTransferType::Isochronous,
+            synchronization: EndpointSynchronizationType::Asynchronous,
+            usage: EndpointUsageType::Feedback,
+        }.into();
+        assert_eq!(isochronous, 0b01_01_01);
+        // The synchronization and usage bits are reserved for the others.
+        let bulk: u8 = EndpointAttributes {
+            transfer: EndpointTransferType::Bulk,
+            synchronization: EndpointSynchronizationType::Asynchronous,
+            usage: EndpointUsageType::Feedback,
+        }.into();
+        assert_eq!(bulk, 0b10);
+    }
+}
@@ -26,9 +26,16 @@ pub trait UsbHidU2f<'a> {
     /// Reset the device and endpoints
     fn setup_u2f_descriptors(&self);
 
-    /// For a reconnect: disconnect, wait, then connect
+    /// Soft disconnects from the bus, waits, then connects again so that
+    /// the host enumerates the device anew. The client's `reconnected` is
+    /// called once the host has configured it.
     fn force_reconnect(&self) -> ReturnCode;
 
+    /// Wakes the host while the bus is suspended, e.g. on a touch. Fails
+    /// with EALREADY if the bus is not suspended, or EOFF if the host did
+    /// not enable remote wakeup.
+    fn remote_wakeup(&self) -> ReturnCode;
+
     /// Enable reception of next frame; call after `get_slice` or `get_frame`.
     fn enable_rx(&self) -> ReturnCode;
 
@@ -52,6 +59,32 @@ pub trait UsbHidU2f<'a> {
     /// only when caller buffer couldn't be aligned or presized. Included to prevent
     /// double-copy from userspace buffers.
     fn put_slice(&self, frame: &[u8]) -> ReturnCode;
+
+    /// Arms the endpoint to receive one transfer of up to
+    /// `TRANSFER_SIZE_BYTES` in place of the next frame; call after
+    /// `frame_received` or `transfer_received`. Returns ENOSUPPORT if the
+    /// board did not enable transfers.
+    fn enable_rx_transfer(&self) -> ReturnCode;
+
+    /// Copies the transfer that `transfer_received` announced into
+    /// `buffer`, returning how many bytes were copied.
+    fn get_transfer(&self, buffer: &mut [u8]) -> usize;
+
+    /// Sends up to `TRANSFER_SIZE_BYTES` to the host as one transfer; fails
+    /// like `put_slice` if the endpoint is busy.
+    fn put_transfer(&self, data: &[u8]) -> ReturnCode;
+
+    /// Prints captured USB packets to the console for conversion to pcap;
+    /// returns ENOSUPPORT if packet capture is not enabled.
+    fn dump_capture(&self) -> ReturnCode;
+
+    /// Prints the traced USB driver events to the console; returns
+    /// ENOSUPPORT if tracing is not enabled.
+    fn dump_trace(&self) -> ReturnCode;
+
+    /// Sets which categories of driver debug messages are printed; see
+    /// `usb::debug`.
+    fn set_debug_verbosity(&self, mask: u32) -> ReturnCode;
 }
 
 /// Client for the UsbHidU2f trait.
@@ -59,4 +92,11 @@ pub trait UsbHidU2fClient<'a> {
     fn reconnected(&self);
     fn frame_received(&self);
     fn frame_transmitted(&self);
+    /// A transfer of `len` bytes arrived, or was sent.
+    fn transfer_received(&self, len: usize);
+    fn transfer_transmitted(&self, len: usize);
+    /// The host suspended the bus: nothing is sent or received until
+    /// `resumed`.
+    fn suspended(&self);
+    fn resumed(&self);
 }
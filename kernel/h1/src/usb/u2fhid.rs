@@ -0,0 +1,621 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! U2FHID transaction layer.
+//!
+//! The host sends a U2FHID request as 64-byte reports: an initialization
+//! frame with the channel ID, command and length, then continuation frames
+//! numbered from 0. `U2fHidTransport` reassembles the frames into a message
+//! buffer, allocating channels for INIT, keeping LOCK and answering protocol
+//! violations with U2FHID errors. There is one transaction at a time: frames
+//! for other channels get ERR_CHANNEL_BUSY until it is finished.
+//!
+//! In `HidMode::Ctap2` the transport speaks CTAPHID, the FIDO2 version of the
+//! protocol: INIT advertises CBOR support, messages may be up to
+//! `CTAPHID_MAX_MESSAGE_LEN` bytes, and CANCEL on the pending channel is
+//! passed on instead of being answered.
+//!
+//! Frame layout (the channel ID is little-endian, the length big-endian):
+//!
+//! ```text
+//! init: cid: u32 | cmd: u8 (bit 7 set) | length: u16 | 57 data bytes
+//! cont: cid: u32 | seq: u8 (bit 7 clear)              | 59 data bytes
+//! ```
+//!
+//! The transport keeps no timers: its user ends overdue transactions with
+//! `timeout`.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::TakeCell;
+
+use crate::usb::constants::{EP_BUFFER_SIZE_BYTES, U2fHidCommand, U2fHidError};
+
+pub const FRAME_LEN: usize = EP_BUFFER_SIZE_BYTES;
+
+pub const CID_BROADCAST: u32 = 0xffff_ffff;
+
+const TYPE_INIT: u8 = 0x80;
+const INIT_HEADER_LEN: usize = 7;
+const CONT_HEADER_LEN: usize = 5;
+pub const INIT_DATA_LEN: usize = FRAME_LEN - INIT_HEADER_LEN;
+pub const CONT_DATA_LEN: usize = FRAME_LEN - CONT_HEADER_LEN;
+
+/// The longest request or response. This is the limit u2f_app used when it
+/// implemented U2FHID itself.
+pub const MAX_MESSAGE_LEN: usize = INIT_DATA_LEN + 39 * CONT_DATA_LEN;
+
+/// The longest CTAPHID message: an initialization frame and all 128
+/// continuation frames.
+pub const CTAPHID_MAX_MESSAGE_LEN: usize = INIT_DATA_LEN + 128 * CONT_DATA_LEN;
+
+pub const INIT_NONCE_LEN: usize = 8;
+const INIT_RESPONSE_LEN: usize = 17;
+const IF_VERSION: u8 = 2;
+const CAPFLAG_WINK: u8 = 1;
+const CAPFLAG_LOCK: u8 = 2;
+const CAPFLAG_CBOR: u8 = 4;
+
+/// The longest lock a channel may take with LOCK.
+const MAX_LOCK_SECONDS: u8 = 10;
+
+/// The protocol the transport speaks, chosen by the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HidMode {
+    /// Legacy U2F authenticators.
+    U2f,
+    /// FIDO2 authenticators, which also take U2F requests in MSG.
+    Ctap2,
+}
+
+impl HidMode {
+    /// The size of the message buffer the mode needs.
+    pub const fn max_message_len(self) -> usize {
+        match self {
+            HidMode::U2f => MAX_MESSAGE_LEN,
+            HidMode::Ctap2 => CTAPHID_MAX_MESSAGE_LEN,
+        }
+    }
+
+    /// The capability flags in the INIT response. CTAPHID has no lock flag,
+    /// though LOCK still works.
+    fn capabilities(self) -> u8 {
+        match self {
+            HidMode::U2f => CAPFLAG_WINK | CAPFLAG_LOCK,
+            HidMode::Ctap2 => CAPFLAG_WINK | CAPFLAG_CBOR,
+        }
+    }
+}
+
+/// A frame the transport wants sent in reply to one it received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reply {
+    /// The INIT response, assigning `new_cid`.
+    Init { cid: u32, nonce: [u8; INIT_NONCE_LEN], new_cid: u32, capabilities: u8 },
+    /// An empty response to `cmd`.
+    Empty { cid: u32, cmd: u8 },
+    /// An ERROR response with a U2FHID error code.
+    Error { cid: u32, code: u8 },
+    /// A CTAPHID KEEPALIVE, telling the host the request on `cid` is still
+    /// being processed.
+    Keepalive { cid: u32, status: u8 },
+}
+
+impl Reply {
+    pub fn error(cid: u32, error: U2fHidError) -> Reply {
+        Reply::Error { cid: cid, code: error as u8 }
+    }
+
+    pub fn encode(&self, frame: &mut [u8; FRAME_LEN]) {
+        match *self {
+            Reply::Init { cid, nonce, new_cid, capabilities } => {
+                let mut data = [0; INIT_RESPONSE_LEN];
+                data[..INIT_NONCE_LEN].copy_from_slice(&nonce);
+                data[8..12].copy_from_slice(&new_cid.to_le_bytes());
+                data[12] = IF_VERSION;
+                // Bytes 13 to 15 are the device version, which is 0.0.0.
+                data[16] = capabilities;
+                encode_frame(cid, U2fHidCommand::Init as u8, &data, 0, frame);
+            }
+            Reply::Empty { cid, cmd } => encode_frame(cid, cmd, &[], 0, frame),
+            Reply::Error { cid, code } =>
+                encode_frame(cid, U2fHidCommand::Error as u8, &[code], 0, frame),
+            Reply::Keepalive { cid, status } =>
+                encode_frame(cid, U2fHidCommand::Keepalive as u8, &[status], 0, frame),
+        }
+    }
+}
+
+/// The outcome of a received frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Nothing to do: the frame was part of a request, or was ignored.
+    None,
+    Reply(Reply),
+    /// The `len` byte request `cmd` on `cid` is complete in the message
+    /// buffer. The transaction lasts until `finish`.
+    Request { cid: u32, cmd: u8, len: usize },
+    /// The host cancelled the pending CTAPHID request on `cid`. The
+    /// transaction goes on: the request still needs a response.
+    Cancel { cid: u32 },
+}
+
+/// The number of frames a `len` byte message takes.
+pub fn frame_count(len: usize) -> usize {
+    if len <= INIT_DATA_LEN {
+        1
+    } else {
+        1 + (len - INIT_DATA_LEN + CONT_DATA_LEN - 1) / CONT_DATA_LEN
+    }
+}
+
+/// Writes frame `index` of the message `cmd` on `cid` to `frame`: frame 0 is
+/// the initialization frame, frame `n` continuation frame `n - 1`.
+pub fn encode_frame(cid: u32, cmd: u8, message: &[u8], index: usize,
+                    frame: &mut [u8; FRAME_LEN]) {
+    *frame = [0; FRAME_LEN];
+    frame[..4].copy_from_slice(&cid.to_le_bytes());
+    let (header_len, start) = if index == 0 {
+        frame[4] = cmd;
+        frame[5] = (message.len() >> 8) as u8;
+        frame[6] = message.len() as u8;
+        (INIT_HEADER_LEN, 0)
+    } else {
+        frame[4] = (index - 1) as u8;
+        (CONT_HEADER_LEN, INIT_DATA_LEN + (index - 1) * CONT_DATA_LEN)
+    };
+    let start = cmp::min(start, message.len());
+    let end = cmp::min(start + FRAME_LEN - header_len, message.len());
+    frame[header_len..header_len + end - start].copy_from_slice(&message[start..end]);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    /// `received` bytes of a `len` byte request arrived; the next
+    /// continuation frame is `seq`.
+    Receiving { cid: u32, cmd: u8, len: usize, received: usize, seq: u8 },
+    /// The request is complete and waits for its response.
+    Pending { cid: u32, cmd: u8 },
+}
+
+#[derive(Clone, Copy)]
+struct Lock {
+    cid: u32,
+    start: u32,
+    ticks: u32,
+}
+
+pub struct U2fHidTransport<'a> {
+    buffer: TakeCell<'a, [u8]>,
+    mode: HidMode,
+    state: Cell<State>,
+    next_cid: Cell<u32>,
+    lock: Cell<Option<Lock>>,
+    ticks_per_second: u32,
+}
+
+impl<'a> U2fHidTransport<'a> {
+    /// Reassembles requests in `buffer`, which must hold
+    /// `mode.max_message_len()` bytes. The times passed to `receive` count
+    /// `ticks_per_second`.
+    pub fn new(buffer: &'a mut [u8], mode: HidMode, ticks_per_second: u32) -> U2fHidTransport<'a> {
+        assert!(buffer.len() >= mode.max_message_len(), "U2fHidTransport: buffer too small");
+        U2fHidTransport {
+            buffer: TakeCell::new(buffer),
+            mode: mode,
+            state: Cell::new(State::Idle),
+            next_cid: Cell::new(1),
+            lock: Cell::new(None),
+            ticks_per_second: ticks_per_second,
+        }
+    }
+
+    /// Handles a frame received at `now`.
+    pub fn receive(&self, frame: &[u8; FRAME_LEN], now: u32) -> Event {
+        let cid = u32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]]);
+        let is_init = frame[4] & TYPE_INIT != 0;
+        let cmd = frame[4];
+        if cid == 0 || (cid == CID_BROADCAST && cmd != U2fHidCommand::Init as u8) {
+            return Event::Reply(Reply::error(cid, U2fHidError::InvalidCid));
+        }
+        if cmd == U2fHidCommand::Init as u8 {
+            // INIT gets through locks and busy channels, so that a host can
+            // always resynchronize.
+            return self.init(cid, frame);
+        }
+        if let Some(lock) = self.lock.get() {
+            if now.wrapping_sub(lock.start) >= lock.ticks {
+                self.lock.set(None);
+            } else if lock.cid != cid {
+                return Event::Reply(Reply::error(cid, U2fHidError::ChannelBusy));
+            }
+        }
+        if cmd == U2fHidCommand::Cancel as u8 && self.mode == HidMode::Ctap2 {
+            // CANCEL has no response, and is ignored unless it is for the
+            // pending request.
+            return match self.state.get() {
+                State::Pending { cid: pending, .. } if pending == cid => Event::Cancel { cid: cid },
+                _ => Event::None,
+            };
+        }
+        if is_init {
+            self.start(cid, cmd, frame, now)
+        } else {
+            self.continue_request(cid, frame)
+        }
+    }
+
+    /// The channel and command of the request waiting for its response.
+    pub fn pending(&self) -> Option<(u32, u8)> {
+        match self.state.get() {
+            State::Pending { cid, cmd } => Some((cid, cmd)),
+            _ => None,
+        }
+    }
+
+    pub fn is_receiving(&self) -> bool {
+        matches!(self.state.get(), State::Receiving { .. })
+    }
+
+    pub fn mode(&self) -> HidMode {
+        self.mode
+    }
+
+    pub fn max_message_len(&self) -> usize {
+        self.mode.max_message_len()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.state.get() == State::Idle
+    }
+
+    /// Ends the pending transaction once its response is sent.
+    pub fn finish(&self) {
+        self.state.set(State::Idle);
+    }
+
+    /// Ends the current transaction because the host did not send the rest
+    /// of the request, or the response was not sent in time. Returns the
+    /// error to reply with.
+    pub fn timeout(&self) -> Option<Reply> {
+        let cid = match self.state.get() {
+            State::Idle => return None,
+            State::Receiving { cid, .. } | State::Pending { cid, .. } => cid,
+        };
+        self.state.set(State::Idle);
+        Some(Reply::error(cid, U2fHidError::MsgTimeout))
+    }
+
+    /// Drops the transaction and the lock, for when the host went away.
+    pub fn reset(&self) {
+        self.state.set(State::Idle);
+        self.lock.set(None);
+    }
+
+    /// Runs `f` on the message buffer, which holds the request while it is
+    /// pending. The response is written to the same buffer.
+    pub fn map_buffer<F, R>(&self, f: F) -> Option<R> where F: FnOnce(&mut [u8]) -> R {
+        self.buffer.map(|buffer| f(buffer))
+    }
+
+    fn init(&self, cid: u32, frame: &[u8; FRAME_LEN]) -> Event {
+        if message_len(frame) != INIT_NONCE_LEN {
+            return Event::Reply(Reply::error(cid, U2fHidError::InvalidLen));
+        }
+        match self.state.get() {
+            State::Receiving { cid: current, .. } | State::Pending { cid: current, .. }
+                if current == cid => self.state.set(State::Idle),
+            _ => {}
+        }
+        let new_cid = if cid == CID_BROADCAST {
+            let new_cid = self.next_cid.get();
+            let next = new_cid.wrapping_add(1);
+            self.next_cid.set(if next == CID_BROADCAST { 1 } else { next });
+            new_cid
+        } else {
+            cid
+        };
+        let mut nonce = [0; INIT_NONCE_LEN];
+        nonce.copy_from_slice(&frame[INIT_HEADER_LEN..INIT_HEADER_LEN + INIT_NONCE_LEN]);
+        Event::Reply(Reply::Init {
+            cid: cid,
+            nonce: nonce,
+            new_cid: new_cid,
+            capabilities: self.mode.capabilities(),
+        })
+    }
+
+    fn start(&self, cid: u32, cmd: u8, frame: &[u8; FRAME_LEN], now: u32) -> Event {
+        match self.state.get() {
+            State::Idle => {}
+            State::Receiving { cid: current, .. } if current == cid => {
+                // A new request before the last one was complete.
+                self.state.set(State::Idle);
+                return Event::Reply(Reply::error(cid, U2fHidError::InvalidSeq));
+            }
+            _ => return Event::Reply(Reply::error(cid, U2fHidError::ChannelBusy)),
+        }
+        let len = message_len(frame);
+        if len > self.max_message_len() {
+            return Event::Reply(Reply::error(cid, U2fHidError::InvalidLen));
+        }
+        if cmd == U2fHidCommand::Cbor as u8 && self.mode != HidMode::Ctap2 {
+            return Event::Reply(Reply::error(cid, U2fHidError::InvalidCmd));
+        }
+        if cmd == U2fHidCommand::Lock as u8 {
+            return self.set_lock(cid, len, frame[INIT_HEADER_LEN], now);
+        }
+        let received = cmp::min(len, INIT_DATA_LEN);
+        self.buffer.map(|buffer| {
+            buffer[..received].copy_from_slice(&frame[INIT_HEADER_LEN..INIT_HEADER_LEN + received]);
+        });
+        self.state.set(State::Receiving { cid: cid, cmd: cmd, len: len, received: received, seq: 0 });
+        self.complete()
+    }
+
+    fn continue_request(&self, cid: u32, frame: &[u8; FRAME_LEN]) -> Event {
+        let (cmd, len, received, seq) = match self.state.get() {
+            State::Receiving { cid: current, cmd, len, received, seq } if current == cid =>
+                (cmd, len, received, seq),
+            // Continuation frames outside of a request are ignored.
+            _ => return Event::None,
+        };
+        if frame[4] != seq {
+            self.state.set(State::Idle);
+            return Event::Reply(Reply::error(cid, U2fHidError::InvalidSeq));
+        }
+        let count = cmp::min(len - received, CONT_DATA_LEN);
+        self.buffer.map(|buffer| {
+            buffer[received..received + count]
+                .copy_from_slice(&frame[CONT_HEADER_LEN..CONT_HEADER_LEN + count]);
+        });
+        self.state.set(State::Receiving {
+            cid: cid,
+            cmd: cmd,
+            len: len,
+            received: received + count,
+            seq: seq + 1,
+        });
+        self.complete()
+    }
+
+    fn complete(&self) -> Event {
+        match self.state.get() {
+            State::Receiving { cid, cmd, len, received, .. } if received == len => {
+                self.state.set(State::Pending { cid: cid, cmd: cmd });
+                Event::Request { cid: cid, cmd: cmd, len: len }
+            }
+            _ => Event::None,
+        }
+    }
+
+    /// LOCK takes a duration in seconds; 0 releases the lock.
+    fn set_lock(&self, cid: u32, len: usize, seconds: u8, now: u32) -> Event {
+        if len != 1 {
+            return Event::Reply(Reply::error(cid, U2fHidError::InvalidLen));
+        }
+        if seconds > MAX_LOCK_SECONDS {
+            return Event::Reply(Reply::error(cid, U2fHidError::InvalidPar));
+        }
+        self.lock.set(if seconds == 0 {
+            None
+        } else {
+            Some(Lock { cid: cid, start: now, ticks: seconds as u32 * self.ticks_per_second })
+        });
+        Event::Reply(Reply::Empty { cid: cid, cmd: U2fHidCommand::Lock as u8 })
+    }
+}
+
+fn message_len(frame: &[u8; FRAME_LEN]) -> usize {
+    (frame[5] as usize) << 8 | frame[6] as usize
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    const CID: u32 = 0x1234_5678;
+    const OTHER_CID: u32 = 0x0bad_cafe;
+    const TICKS_PER_SECOND: u32 = 1000;
+
+    fn frames(cid: u32, cmd: u8, message: &[u8]) -> Vec<[u8; FRAME_LEN]> {
+        (0..frame_count(message.len())).map(|index| {
+            let mut frame = [0; FRAME_LEN];
+            encode_frame(cid, cmd, message, index, &mut frame);
+            frame
+        }).collect()
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    fn error(cid: u32, error: U2fHidError) -> Event {
+        Event::Reply(Reply::error(cid, error))
+    }
+
+    #[test]
+    fn init_allocates_channels() {
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+        let transport = U2fHidTransport::new(&mut buffer, HidMode::U2f, TICKS_PER_SECOND);
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        let init = frames(CID_BROADCAST, U2fHidCommand::Init as u8, &nonce);
+        let reply = Reply::Init { cid: CID_BROADCAST, nonce: nonce, new_cid: 1, capabilities: 3 };
+        assert_eq!(transport.receive(&init[0], 0), Event::Reply(reply));
+        assert_eq!(transport.receive(&init[0], 0),
+                   Event::Reply(Reply::Init { cid: CID_BROADCAST, nonce: nonce, new_cid: 2, capabilities: 3 }));
+
+        let mut frame = [0; FRAME_LEN];
+        reply.encode(&mut frame);
+        assert_eq!(frame[..7], [0xff, 0xff, 0xff, 0xff, 0x86, 0, 17]);
+        assert_eq!(frame[7..24], [1, 2, 3, 4, 5, 6, 7, 8, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn reassembles_requests() {
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+        let transport = U2fHidTransport::new(&mut buffer, HidMode::U2f, TICKS_PER_SECOND);
+        let request = message(200);
+        let request_frames = frames(CID, U2fHidCommand::Msg as u8, &request);
+        assert_eq!(request_frames.len(), 4);
+        for frame in &request_frames[..3] {
+            assert_eq!(transport.receive(frame, 0), Event::None);
+            assert!(transport.is_receiving());
+        }
+        assert_eq!(transport.receive(&request_frames[3], 0),
+                   Event::Request { cid: CID, cmd: U2fHidCommand::Msg as u8, len: 200 });
+        assert_eq!(transport.pending(), Some((CID, U2fHidCommand::Msg as u8)));
+        assert_eq!(transport.map_buffer(|buffer| buffer[..200] == request[..]), Some(true));
+
+        // Other channels wait until the transaction is finished.
+        let ping = frames(OTHER_CID, U2fHidCommand::Ping as u8, &[]);
+        assert_eq!(transport.receive(&ping[0], 0), error(OTHER_CID, U2fHidError::ChannelBusy));
+        transport.finish();
+        assert_eq!(transport.receive(&ping[0], 0),
+                   Event::Request { cid: OTHER_CID, cmd: U2fHidCommand::Ping as u8, len: 0 });
+    }
+
+    #[test]
+    fn sequence_errors() {
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+        let transport = U2fHidTransport::new(&mut buffer, HidMode::U2f, TICKS_PER_SECOND);
+        let request_frames = frames(CID, U2fHidCommand::Msg as u8, &message(100));
+
+        // Stray continuation frames are ignored.
+        assert_eq!(transport.receive(&request_frames[1], 0), Event::None);
+        assert!(transport.is_idle());
+
+        assert_eq!(transport.receive(&request_frames[0], 0), Event::None);
+        assert_eq!(transport.receive(&request_frames[0], 0), error(CID, U2fHidError::InvalidSeq));
+        assert!(transport.is_idle());
+
+        let mut skipped = request_frames[1];
+        skipped[4] = 1;
+        assert_eq!(transport.receive(&request_frames[0], 0), Event::None);
+        assert_eq!(transport.receive(&skipped, 0), error(CID, U2fHidError::InvalidSeq));
+        assert!(transport.is_idle());
+
+        let mut frame = request_frames[0];
+        frame[..4].copy_from_slice(&[0; 4]);
+        assert_eq!(transport.receive(&frame, 0), error(0, U2fHidError::InvalidCid));
+        frame[..4].copy_from_slice(&CID_BROADCAST.to_le_bytes());
+        assert_eq!(transport.receive(&frame, 0), error(CID_BROADCAST, U2fHidError::InvalidCid));
+        frame[..4].copy_from_slice(&CID.to_le_bytes());
+        frame[5..7].copy_from_slice(&[0x10, 0]);
+        assert_eq!(transport.receive(&frame, 0), error(CID, U2fHidError::InvalidLen));
+    }
+
+    #[test]
+    fn timeout_and_init_abort() {
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+        let transport = U2fHidTransport::new(&mut buffer, HidMode::U2f, TICKS_PER_SECOND);
+        let request_frames = frames(CID, U2fHidCommand::Msg as u8, &message(100));
+        assert_eq!(transport.timeout(), None);
+        assert_eq!(transport.receive(&request_frames[0], 0), Event::None);
+        assert_eq!(transport.timeout(), Some(Reply::error(CID, U2fHidError::MsgTimeout)));
+        assert!(transport.is_idle());
+
+        assert_eq!(transport.receive(&request_frames[0], 0), Event::None);
+        let init = frames(CID, U2fHidCommand::Init as u8, &[0; INIT_NONCE_LEN]);
+        assert_eq!(transport.receive(&init[0], 0), Event::Reply(Reply::Init {
+            cid: CID,
+            nonce: [0; INIT_NONCE_LEN],
+            new_cid: CID,
+            capabilities: 3,
+        }));
+        assert!(transport.is_idle());
+    }
+
+    #[test]
+    fn lock() {
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+        let transport = U2fHidTransport::new(&mut buffer, HidMode::U2f, TICKS_PER_SECOND);
+        let lock = frames(CID, U2fHidCommand::Lock as u8, &[2]);
+        assert_eq!(transport.receive(&lock[0], 100),
+                   Event::Reply(Reply::Empty { cid: CID, cmd: U2fHidCommand::Lock as u8 }));
+        assert!(transport.is_idle());
+
+        let ping = frames(OTHER_CID, U2fHidCommand::Ping as u8, &[]);
+        assert_eq!(transport.receive(&ping[0], 2099), error(OTHER_CID, U2fHidError::ChannelBusy));
+        assert_eq!(transport.receive(&ping[0], 2100),
+                   Event::Request { cid: OTHER_CID, cmd: U2fHidCommand::Ping as u8, len: 0 });
+
+        let too_long = frames(CID, U2fHidCommand::Lock as u8, &[11]);
+        transport.finish();
+        assert_eq!(transport.receive(&too_long[0], 0), error(CID, U2fHidError::InvalidPar));
+    }
+
+    #[test]
+    fn ctaphid() {
+        let mut buffer = [0; CTAPHID_MAX_MESSAGE_LEN];
+        let transport = U2fHidTransport::new(&mut buffer, HidMode::Ctap2, TICKS_PER_SECOND);
+        let init = frames(CID_BROADCAST, U2fHidCommand::Init as u8, &[0; INIT_NONCE_LEN]);
+        match transport.receive(&init[0], 0) {
+            Event::Reply(Reply::Init { capabilities, .. }) => assert_eq!(capabilities, 5),
+            event => panic!("unexpected {:?}", event),
+        }
+
+        let request = message(CTAPHID_MAX_MESSAGE_LEN);
+        let request_frames = frames(CID, U2fHidCommand::Cbor as u8, &request);
+        assert_eq!(request_frames.len(), 129);
+        for frame in &request_frames[..128] {
+            assert_eq!(transport.receive(frame, 0), Event::None);
+        }
+        assert_eq!(transport.receive(&request_frames[128], 0), Event::Request {
+            cid: CID,
+            cmd: U2fHidCommand::Cbor as u8,
+            len: CTAPHID_MAX_MESSAGE_LEN,
+        });
+        assert_eq!(transport.map_buffer(|buffer| buffer[..] == request[..]), Some(true));
+
+        // Only CANCEL for the pending request gets through.
+        let cancel = frames(CID, U2fHidCommand::Cancel as u8, &[]);
+        assert_eq!(transport.receive(&cancel[0], 0), Event::Cancel { cid: CID });
+        let other_cancel = frames(OTHER_CID, U2fHidCommand::Cancel as u8, &[]);
+        assert_eq!(transport.receive(&other_cancel[0], 0), Event::None);
+        assert_eq!(transport.pending(), Some((CID, U2fHidCommand::Cbor as u8)));
+        transport.finish();
+        assert_eq!(transport.receive(&cancel[0], 0), Event::None);
+    }
+
+    #[test]
+    fn u2f_mode_rejects_ctaphid() {
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+        let transport = U2fHidTransport::new(&mut buffer, HidMode::U2f, TICKS_PER_SECOND);
+        let cbor = frames(CID, U2fHidCommand::Cbor as u8, &[1]);
+        assert_eq!(transport.receive(&cbor[0], 0), error(CID, U2fHidError::InvalidCmd));
+        let long = frames(CID, U2fHidCommand::Msg as u8, &message(MAX_MESSAGE_LEN + 1));
+        assert_eq!(transport.receive(&long[0], 0), error(CID, U2fHidError::InvalidLen));
+    }
+
+    #[test]
+    fn encodes_responses() {
+        let response = message(MAX_MESSAGE_LEN);
+        let response_frames = frames(CID, U2fHidCommand::Msg as u8, &response);
+        assert_eq!(response_frames.len(), 40);
+        assert_eq!(response_frames[0][..7], [0x78, 0x56, 0x34, 0x12, 0x83, 0x09, 0x36]);
+        assert_eq!(response_frames[39][4], 38);
+        let mut decoded = response_frames[0][INIT_HEADER_LEN..].to_vec();
+        for frame in &response_frames[1..] {
+            decoded.extend_from_slice(&frame[CONT_HEADER_LEN..]);
+        }
+        assert_eq!(decoded, response);
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Provides userspace with complete U2FHID requests.
+//!
+//! `driver::U2fSyscallDriver` hands userspace raw 64-byte frames. This
+//! capsule runs the U2FHID transaction layer (see `u2fhid`) in the kernel
+//! instead: it answers INIT, PING, LOCK and WINK itself and passes other
+//! requests, such as MSG APDUs, to the app once all their frames arrived.
+//! The app's response is split into frames on the request's channel. In
+//! `HidMode::Ctap2` the app also gets CTAPHID CBOR requests and CANCEL, and
+//! can send KEEPALIVE while it works on a request.
+//!
+//! A request times out `MSG_TIMEOUT_MS` after its last frame if it is not
+//! complete, and `TRANS_TIMEOUT_MS` after it is complete if the response
+//! has not been sent by then. The host gets ERR_MSG_TIMEOUT either way.
+//!
+//! Commands:
+//!   - 0: Check.
+//!   - 1: Sends the first arg1 bytes of the response buffer as the response
+//!        to the request on channel arg2.
+//!   - 2: Answers the request on channel arg2 with U2FHID error arg1.
+//!   - 3: Sends a CTAPHID KEEPALIVE with status arg1 for the request on
+//!        channel arg2, which restarts its `TRANS_TIMEOUT_MS`.
+//!   - 4: Wakes the host from suspend, e.g. when the user touches the
+//!        device.
+//!
+//! Allows:
+//!   - 1: Response buffer.
+//!   - 2: Request buffer.
+//!
+//! Subscribes:
+//!   - 1: Response sent.
+//!   - 2: Request received, with its length, command and channel. Requests
+//!        that do not fit the request buffer get ERR_INVALID_LEN.
+//!   - 3: Reconnected.
+//!   - 4: Wink.
+//!   - 5: Cancel, with the channel of the cancelled request.
+//!   - 6: The host suspended (1) or resumed (0) the bus.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{Alarm, AlarmClient, Frequency, Ticks};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::usb::constants::{U2fHidCommand, U2fHidError};
+use crate::usb::u2fhid::{self, Event, HidMode, Reply, U2fHidTransport, FRAME_LEN};
+use crate::usb::{UsbHidU2f, UsbHidU2fClient};
+
+pub const DRIVER_NUM: usize = 0x20009;
+
+pub const U2FHID_CMD_CHECK:   usize = 0;
+pub const U2FHID_CMD_RESPOND: usize = 1;
+pub const U2FHID_CMD_ERROR:   usize = 2;
+pub const U2FHID_CMD_KEEPALIVE: usize = 3;
+pub const U2FHID_CMD_WAKEUP:  usize = 4;
+
+pub const U2FHID_ALLOW_RESPONSE: usize = 1;
+pub const U2FHID_ALLOW_REQUEST:  usize = 2;
+
+pub const U2FHID_SUBSCRIBE_RESPONSE_DONE: usize = 1;
+pub const U2FHID_SUBSCRIBE_REQUEST:       usize = 2;
+pub const U2FHID_SUBSCRIBE_RECONNECT:     usize = 3;
+pub const U2FHID_SUBSCRIBE_WINK:          usize = 4;
+pub const U2FHID_SUBSCRIBE_CANCEL:        usize = 5;
+pub const U2FHID_SUBSCRIBE_SUSPEND:       usize = 6;
+
+/// How long the host may take between the frames of a request.
+pub const MSG_TIMEOUT_MS: u32 = 500;
+
+/// How long a transaction may take from the complete request to the
+/// response.
+pub const TRANS_TIMEOUT_MS: u32 = 3000;
+
+#[derive(Default)]
+pub struct App {
+    response_callback: Option<Callback>,
+    request_callback: Option<Callback>,
+    connection_callback: Option<Callback>,
+    wink_callback: Option<Callback>,
+    cancel_callback: Option<Callback>,
+    suspend_callback: Option<Callback>,
+    response_buffer: Option<AppSlice<Shared, u8>>,
+    request_buffer: Option<AppSlice<Shared, u8>>,
+}
+
+/// A response being sent from the message buffer.
+#[derive(Clone, Copy)]
+struct Response {
+    cid: u32,
+    cmd: u8,
+    len: usize,
+    next_frame: usize,
+}
+
+pub struct U2fHidSyscallDriver<'a, A: Alarm<'a>> {
+    u2f_endpoints: &'a dyn UsbHidU2f<'a>,
+    alarm: &'a A,
+    transport: U2fHidTransport<'a>,
+    /// A reply to send before the next response frame. Replies that pile up
+    /// while the endpoint is busy are dropped but the last; the host retries.
+    reply: Cell<Option<Reply>>,
+    response: Cell<Option<Response>>,
+    /// The app handling the pending request.
+    handler: OptionalCell<AppId>,
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm<'a>> U2fHidSyscallDriver<'a, A> {
+    /// Speaks `mode` to the host, reassembling requests in `buffer`, which
+    /// must hold `mode.max_message_len()` bytes. The alarm client must be set
+    /// to this driver.
+    pub fn new(u2f: &'a dyn UsbHidU2f<'a>,
+               alarm: &'a A,
+               buffer: &'a mut [u8],
+               mode: HidMode,
+               grant: Grant<App>) -> U2fHidSyscallDriver<'a, A> {
+        U2fHidSyscallDriver {
+            u2f_endpoints: u2f,
+            alarm: alarm,
+            transport: U2fHidTransport::new(buffer, mode, A::Frequency::frequency()),
+            reply: Cell::new(None),
+            response: Cell::new(None),
+            handler: OptionalCell::empty(),
+            apps: grant,
+        }
+    }
+
+    fn set_timeout(&self, ms: u32) {
+        let interval = (A::Frequency::frequency() as u64 * ms as u64 / 1000) as u32;
+        self.alarm.set_alarm(self.alarm.now(), interval.into());
+    }
+
+    /// Handles a complete request: PING and WINK here, the rest in the app.
+    fn request(&self, cid: u32, cmd: u8, len: usize) {
+        self.set_timeout(TRANS_TIMEOUT_MS);
+        if cmd == U2fHidCommand::Ping as u8 {
+            // The request is the response.
+            self.respond(cid, cmd, len);
+        } else if cmd == U2fHidCommand::Wink as u8 {
+            self.respond(cid, cmd, 0);
+            for cntr in self.apps.iter() {
+                cntr.enter(|app, _| {
+                    app.wink_callback.map(|mut cb| cb.schedule(0, 0, 0));
+                });
+            }
+        } else if let Err(error) = self.deliver(cid, cmd, len) {
+            self.fail(cid, error as u8);
+        }
+    }
+
+    /// Copies the request to the first app that takes requests.
+    fn deliver(&self, cid: u32, cmd: u8, len: usize) -> Result<(), U2fHidError> {
+        let mut result = Err(U2fHidError::Other);
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if result.is_ok() || app.request_callback.is_none() {
+                    return;
+                }
+                let delivered = app.request_buffer.as_mut().map_or(false, |buffer| {
+                    if buffer.len() < len {
+                        return false;
+                    }
+                    self.transport.map_buffer(|message| {
+                        buffer.as_mut()[..len].copy_from_slice(&message[..len]);
+                    });
+                    true
+                });
+                if !delivered {
+                    result = Err(U2fHidError::InvalidLen);
+                    return;
+                }
+                self.handler.set(app.appid());
+                app.request_callback.map(|mut cb| cb.schedule(len, cmd as usize, cid as usize));
+                result = Ok(());
+            });
+        }
+        result
+    }
+
+    /// Starts sending the `len` byte response in the message buffer.
+    fn respond(&self, cid: u32, cmd: u8, len: usize) {
+        self.response.set(Some(Response { cid: cid, cmd: cmd, len: len, next_frame: 0 }));
+        self.send_next();
+    }
+
+    /// Ends the pending transaction with an ERROR response.
+    fn fail(&self, cid: u32, code: u8) {
+        self.transport.finish();
+        self.alarm.disarm();
+        self.handler.clear();
+        self.response.set(None);
+        self.reply.set(Some(Reply::Error { cid: cid, code: code }));
+        self.send_next();
+    }
+
+    /// Sends the next reply or response frame, if the endpoint is free.
+    fn send_next(&self) {
+        if !self.u2f_endpoints.transmit_ready() {
+            return;
+        }
+        let mut frame = [0; FRAME_LEN];
+        let mut finished = false;
+        if let Some(reply) = self.reply.take() {
+            reply.encode(&mut frame);
+        } else if let Some(response) = self.response.get() {
+            if self.transport.pending().map(|(cid, _)| cid) != Some(response.cid) {
+                // The host aborted the transaction with INIT.
+                self.response.set(None);
+                self.handler.clear();
+                return;
+            }
+            self.transport.map_buffer(|message| {
+                u2fhid::encode_frame(response.cid, response.cmd, &message[..response.len],
+                                     response.next_frame, &mut frame);
+            });
+            let next_frame = response.next_frame + 1;
+            if next_frame < u2fhid::frame_count(response.len) {
+                self.response.set(Some(Response { next_frame: next_frame, ..response }));
+            } else {
+                self.response.set(None);
+                finished = true;
+            }
+        } else {
+            return;
+        }
+        self.u2f_endpoints.put_slice(&frame);
+
+        if finished {
+            self.transport.finish();
+            self.alarm.disarm();
+            self.handler.take().map(|appid| {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.response_callback.map(|mut cb| cb.schedule(0, 0, 0));
+                });
+            });
+        }
+    }
+
+    /// Checks that `appid` handles the pending request on `cid`, and returns
+    /// its command.
+    fn pending_command(&self, appid: AppId, cid: usize) -> Result<u8, ReturnCode> {
+        match self.transport.pending() {
+            Some((pending_cid, cmd)) if pending_cid as usize == cid &&
+                self.handler.map_or(false, |handler| *handler == appid) &&
+                self.response.get().is_none() => Ok(cmd),
+            _ => Err(ReturnCode::EALREADY),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> UsbHidU2fClient<'a> for U2fHidSyscallDriver<'a, A> {
+    fn reconnected(&self) {
+        self.transport.reset();
+        self.alarm.disarm();
+        self.handler.clear();
+        self.reply.set(None);
+        self.response.set(None);
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.connection_callback.map(|mut cb| cb.schedule(0, 0, 0));
+            });
+        }
+    }
+
+    fn frame_received(&self) {
+        let mut frame = [0; FRAME_LEN];
+        self.u2f_endpoints.get_slice(&mut frame);
+        self.u2f_endpoints.enable_rx();
+        match self.transport.receive(&frame, self.alarm.now().into_u32()) {
+            Event::None => {}
+            Event::Reply(reply) => self.reply.set(Some(reply)),
+            Event::Request { cid, cmd, len } => self.request(cid, cmd, len),
+            Event::Cancel { cid } => {
+                self.handler.map(|appid| {
+                    let _ = self.apps.enter(*appid, |app, _| {
+                        app.cancel_callback.map(|mut cb| cb.schedule(cid as usize, 0, 0));
+                    });
+                });
+            }
+        }
+        if self.transport.is_receiving() {
+            self.set_timeout(MSG_TIMEOUT_MS);
+        } else if self.transport.is_idle() {
+            self.alarm.disarm();
+        }
+        self.send_next();
+    }
+
+    fn frame_transmitted(&self) {
+        self.send_next();
+    }
+
+    // U2FHID moves 64-byte frames only.
+    fn transfer_received(&self, _len: usize) {}
+
+    fn transfer_transmitted(&self, _len: usize) {}
+
+    fn suspended(&self) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.suspend_callback.map(|mut cb| cb.schedule(1, 0, 0));
+            });
+        }
+    }
+
+    fn resumed(&self) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                app.suspend_callback.map(|mut cb| cb.schedule(0, 0, 0));
+            });
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for U2fHidSyscallDriver<'a, A> {
+    fn alarm(&self) {
+        if let Some(reply) = self.transport.timeout() {
+            self.handler.clear();
+            self.response.set(None);
+            self.reply.set(Some(reply));
+            self.send_next();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> Driver for U2fHidSyscallDriver<'a, A> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            U2FHID_ALLOW_RESPONSE => self.apps.enter(appid, |app, _| {
+                app.response_buffer = slice;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into()),
+            U2FHID_ALLOW_REQUEST => self.apps.enter(appid, |app, _| {
+                app.request_buffer = slice;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        self.apps.enter(app_id, |app, _| {
+            match subscribe_num {
+                U2FHID_SUBSCRIBE_RESPONSE_DONE => app.response_callback = callback,
+                U2FHID_SUBSCRIBE_REQUEST => app.request_callback = callback,
+                U2FHID_SUBSCRIBE_RECONNECT => app.connection_callback = callback,
+                U2FHID_SUBSCRIBE_WINK => app.wink_callback = callback,
+                U2FHID_SUBSCRIBE_CANCEL => app.cancel_callback = callback,
+                U2FHID_SUBSCRIBE_SUSPEND => app.suspend_callback = callback,
+                _ => return ReturnCode::ENOSUPPORT,
+            }
+            ReturnCode::SUCCESS
+        }).unwrap_or_else(|err| err.into())
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            U2FHID_CMD_CHECK => ReturnCode::SUCCESS,
+            U2FHID_CMD_RESPOND => {
+                let cmd = match self.pending_command(appid, arg2) {
+                    Ok(cmd) => cmd,
+                    Err(rcode) => return rcode,
+                };
+                let rcode = self.apps.enter(appid, |app, _| {
+                    let buffer = match app.response_buffer {
+                        Some(ref buffer) => buffer,
+                        None => return ReturnCode::ERESERVE,
+                    };
+                    if arg1 > buffer.len() || arg1 > self.transport.max_message_len() {
+                        return ReturnCode::ESIZE;
+                    }
+                    self.transport.map_buffer(|message| {
+                        message[..arg1].copy_from_slice(&buffer.as_ref()[..arg1]);
+                    });
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into());
+                if rcode == ReturnCode::SUCCESS {
+                    self.respond(arg2 as u32, cmd, arg1);
+                }
+                rcode
+            }
+            U2FHID_CMD_ERROR => {
+                if let Err(rcode) = self.pending_command(appid, arg2) {
+                    return rcode;
+                }
+                if arg1 > u8::max_value() as usize {
+                    return ReturnCode::EINVAL;
+                }
+                self.fail(arg2 as u32, arg1 as u8);
+                ReturnCode::SUCCESS
+            }
+            U2FHID_CMD_KEEPALIVE => {
+                if self.transport.mode() != HidMode::Ctap2 {
+                    return ReturnCode::ENOSUPPORT;
+                }
+                if let Err(rcode) = self.pending_command(appid, arg2) {
+                    return rcode;
+                }
+                if arg1 > u8::max_value() as usize {
+                    return ReturnCode::EINVAL;
+                }
+                self.set_timeout(TRANS_TIMEOUT_MS);
+                self.reply.set(Some(Reply::Keepalive { cid: arg2 as u32, status: arg1 as u8 }));
+                self.send_next();
+                ReturnCode::SUCCESS
+            }
+            U2FHID_CMD_WAKEUP => self.u2f_endpoints.remote_wakeup(),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
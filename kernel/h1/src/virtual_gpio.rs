@@ -0,0 +1,422 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtualizes a GPIO pin so that several kernel clients and the userspace
+//! GPIO driver can share it.
+//!
+//! Every `VirtualGpioPin` can read the pin and receive edge events. Only one
+//! of them at a time owns the pin and may configure or drive it:
+//!
+//! - `Access::Control` users become the owner on their first configuring or
+//!   driving call if nobody else owns the pin, or up front with `claim()`.
+//!   They keep it until `release()`.
+//! - `Access::Observe` users can never own the pin.
+//!
+//! Calls that would change the pin from a user that does not own it are
+//! ignored and report the pin's current state, so that a misbehaving client
+//! cannot fight the owner over the line.
+//!
+//! The hardware interrupt is programmed for the union of the edges the users
+//! asked for, and each event is delivered to every user whose edge matches
+//! the level of the pin when the event is handled.
+
+use core::cell::Cell;
+use core::ptr;
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil;
+use kernel::ReturnCode;
+
+pub struct MuxGpioPin<'a, P: hil::gpio::InterruptPin<'a>> {
+    pin: &'a P,
+    users: List<'a, VirtualGpioPin<'a, P>>,
+    // Null while no user owns the pin.
+    owner: Cell<*const VirtualGpioPin<'a, P>>,
+}
+
+impl<'a, P: hil::gpio::InterruptPin<'a>> MuxGpioPin<'a, P> {
+    pub fn new(pin: &'a P) -> MuxGpioPin<'a, P> {
+        MuxGpioPin {
+            pin,
+            users: List::new(),
+            owner: Cell::new(ptr::null()),
+        }
+    }
+
+    /// Returns true if `user` may configure and drive the pin, making it the
+    /// owner if the pin is free.
+    fn acquire(&self, user: &VirtualGpioPin<'a, P>) -> bool {
+        if user.access == Access::Observe {
+            return false;
+        }
+        if self.owner.get().is_null() {
+            self.owner.set(user);
+        }
+        ptr::eq(self.owner.get(), user)
+    }
+
+    // Programs the hardware interrupt for the edges the users want.
+    fn update_interrupts(&self) {
+        use kernel::hil::gpio::InterruptEdge::{EitherEdge, FallingEdge, RisingEdge};
+        let rising = self.users.iter().any(|user| user.rising.get());
+        let falling = self.users.iter().any(|user| user.falling.get());
+        match (rising, falling) {
+            (false, false) => self.pin.disable_interrupts(),
+            (true, false) => self.pin.enable_interrupts(RisingEdge),
+            (false, true) => self.pin.enable_interrupts(FallingEdge),
+            (true, true) => self.pin.enable_interrupts(EitherEdge),
+        }
+    }
+}
+
+impl<'a, P: hil::gpio::InterruptPin<'a>> hil::gpio::Client for MuxGpioPin<'a, P> {
+    fn fired(&self) {
+        let value = self.pin.read();
+        for user in self.users.iter() {
+            if (value && user.rising.get()) || (!value && user.falling.get()) {
+                user.client.map(|client| client.fired());
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Access {
+    /// May own the pin to configure and drive it.
+    Control,
+    /// May only read the pin and receive its events.
+    Observe,
+}
+
+pub struct VirtualGpioPin<'a, P: hil::gpio::InterruptPin<'a>> {
+    mux: &'a MuxGpioPin<'a, P>,
+    access: Access,
+    // Edges this user enabled events for.
+    rising: Cell<bool>,
+    falling: Cell<bool>,
+    client: OptionalCell<&'a dyn hil::gpio::Client>,
+    next: ListLink<'a, VirtualGpioPin<'a, P>>,
+}
+
+impl<'a, P: hil::gpio::InterruptPin<'a>> VirtualGpioPin<'a, P> {
+    pub const fn new(mux: &'a MuxGpioPin<'a, P>, access: Access) -> VirtualGpioPin<'a, P> {
+        VirtualGpioPin {
+            mux,
+            access,
+            rising: Cell::new(false),
+            falling: Cell::new(false),
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Registers this user with its mux. Must be called once before use.
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+
+    /// Takes ownership of the pin. Returns EBUSY if another user owns it and
+    /// ENOSUPPORT for observers.
+    pub fn claim(&self) -> ReturnCode {
+        match self.access {
+            Access::Observe => ReturnCode::ENOSUPPORT,
+            Access::Control if self.mux.acquire(self) => ReturnCode::SUCCESS,
+            Access::Control => ReturnCode::EBUSY,
+        }
+    }
+
+    /// Gives up ownership of the pin, leaving its configuration unchanged.
+    pub fn release(&self) {
+        if self.is_owner() {
+            self.mux.owner.set(ptr::null());
+        }
+    }
+
+    pub fn is_owner(&self) -> bool {
+        ptr::eq(self.mux.owner.get(), self)
+    }
+}
+
+impl<'a, P: hil::gpio::InterruptPin<'a>> ListNode<'a, VirtualGpioPin<'a, P>>
+    for VirtualGpioPin<'a, P>
+{
+    fn next(&'a self) -> &'a ListLink<'a, VirtualGpioPin<'a, P>> {
+        &self.next
+    }
+}
+
+impl<'a, P: hil::gpio::InterruptPin<'a>> hil::gpio::Configure for VirtualGpioPin<'a, P> {
+    fn configuration(&self) -> hil::gpio::Configuration {
+        self.mux.pin.configuration()
+    }
+
+    fn make_output(&self) -> hil::gpio::Configuration {
+        if !self.mux.acquire(self) {
+            return self.configuration();
+        }
+        self.mux.pin.make_output()
+    }
+
+    fn disable_output(&self) -> hil::gpio::Configuration {
+        if !self.mux.acquire(self) {
+            return self.configuration();
+        }
+        self.mux.pin.disable_output()
+    }
+
+    fn make_input(&self) -> hil::gpio::Configuration {
+        if !self.mux.acquire(self) {
+            return self.configuration();
+        }
+        self.mux.pin.make_input()
+    }
+
+    fn disable_input(&self) -> hil::gpio::Configuration {
+        if !self.mux.acquire(self) {
+            return self.configuration();
+        }
+        self.mux.pin.disable_input()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        if self.mux.acquire(self) {
+            self.mux.pin.deactivate_to_low_power();
+        }
+    }
+
+    fn set_floating_state(&self, state: hil::gpio::FloatingState) {
+        if self.mux.acquire(self) {
+            self.mux.pin.set_floating_state(state);
+        }
+    }
+
+    fn floating_state(&self) -> hil::gpio::FloatingState {
+        self.mux.pin.floating_state()
+    }
+
+    fn is_input(&self) -> bool {
+        self.mux.pin.is_input()
+    }
+
+    fn is_output(&self) -> bool {
+        self.mux.pin.is_output()
+    }
+}
+
+impl<'a, P: hil::gpio::InterruptPin<'a>> hil::gpio::Input for VirtualGpioPin<'a, P> {
+    fn read(&self) -> bool {
+        self.mux.pin.read()
+    }
+}
+
+impl<'a, P: hil::gpio::InterruptPin<'a>> hil::gpio::Output for VirtualGpioPin<'a, P> {
+    fn set(&self) {
+        if self.mux.acquire(self) {
+            self.mux.pin.set();
+        }
+    }
+
+    fn clear(&self) {
+        if self.mux.acquire(self) {
+            self.mux.pin.clear();
+        }
+    }
+
+    fn toggle(&self) -> bool {
+        if !self.mux.acquire(self) {
+            return self.mux.pin.read();
+        }
+        self.mux.pin.toggle()
+    }
+}
+
+impl<'a, P: hil::gpio::InterruptPin<'a>> hil::gpio::Interrupt<'a> for VirtualGpioPin<'a, P> {
+    fn set_client(&self, client: &'a dyn hil::gpio::Client) {
+        self.client.set(client);
+    }
+
+    fn enable_interrupts(&self, mode: hil::gpio::InterruptEdge) {
+        let (rising, falling) = match mode {
+            hil::gpio::InterruptEdge::RisingEdge => (true, false),
+            hil::gpio::InterruptEdge::FallingEdge => (false, true),
+            hil::gpio::InterruptEdge::EitherEdge => (true, true),
+        };
+        self.rising.set(rising);
+        self.falling.set(falling);
+        self.mux.update_interrupts();
+    }
+
+    fn disable_interrupts(&self) {
+        self.rising.set(false);
+        self.falling.set(false);
+        self.mux.update_interrupts();
+    }
+
+    fn is_pending(&self) -> bool {
+        self.mux.pin.is_pending()
+    }
+}
+
+impl<'a, P: hil::gpio::InterruptPin<'a>> hil::gpio::Pin for VirtualGpioPin<'a, P> {}
+impl<'a, P: hil::gpio::InterruptPin<'a>> hil::gpio::InterruptPin<'a> for VirtualGpioPin<'a, P> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, MuxGpioPin, VirtualGpioPin};
+    use core::cell::Cell;
+    use kernel::hil::gpio::{Client, Configuration, Configure, FloatingState, Input,
+                            Interrupt, InterruptEdge, Output};
+    use kernel::ReturnCode;
+
+    #[derive(Default)]
+    struct FakePin {
+        value: Cell<bool>,
+        output: Cell<bool>,
+        // 0: disabled, 1: rising, 2: falling, 3: either.
+        edges: Cell<u8>,
+    }
+
+    impl Configure for FakePin {
+        fn configuration(&self) -> Configuration {
+            if self.output.get() { Configuration::InputOutput } else { Configuration::Input }
+        }
+        fn make_output(&self) -> Configuration {
+            self.output.set(true);
+            self.configuration()
+        }
+        fn disable_output(&self) -> Configuration {
+            self.output.set(false);
+            self.configuration()
+        }
+        fn make_input(&self) -> Configuration { self.configuration() }
+        fn disable_input(&self) -> Configuration { self.configuration() }
+        fn deactivate_to_low_power(&self) { self.output.set(false); }
+        fn set_floating_state(&self, _state: FloatingState) {}
+        fn floating_state(&self) -> FloatingState { FloatingState::PullNone }
+        fn is_input(&self) -> bool { true }
+        fn is_output(&self) -> bool { self.output.get() }
+    }
+
+    impl Input for FakePin {
+        fn read(&self) -> bool { self.value.get() }
+    }
+
+    impl Output for FakePin {
+        fn set(&self) { self.value.set(true); }
+        fn clear(&self) { self.value.set(false); }
+        fn toggle(&self) -> bool {
+            self.value.set(!self.value.get());
+            self.value.get()
+        }
+    }
+
+    impl<'a> Interrupt<'a> for FakePin {
+        fn set_client(&self, _client: &'a dyn Client) {}
+        fn enable_interrupts(&self, mode: InterruptEdge) {
+            self.edges.set(match mode {
+                InterruptEdge::RisingEdge => 1,
+                InterruptEdge::FallingEdge => 2,
+                InterruptEdge::EitherEdge => 3,
+            });
+        }
+        fn disable_interrupts(&self) { self.edges.set(0); }
+        fn is_pending(&self) -> bool { false }
+    }
+
+    impl kernel::hil::gpio::Pin for FakePin {}
+    impl<'a> kernel::hil::gpio::InterruptPin<'a> for FakePin {}
+
+    #[derive(Default)]
+    struct Counter {
+        count: Cell<usize>,
+    }
+
+    impl Client for Counter {
+        fn fired(&self) { self.count.set(self.count.get() + 1); }
+    }
+
+    #[test]
+    fn first_controller_owns_the_pin() {
+        let pin = FakePin::default();
+        let mux = MuxGpioPin::new(&pin);
+        let kernel_user = VirtualGpioPin::new(&mux, Access::Control);
+        let app_user = VirtualGpioPin::new(&mux, Access::Control);
+        let observer = VirtualGpioPin::new(&mux, Access::Observe);
+        kernel_user.setup();
+        app_user.setup();
+        observer.setup();
+
+        assert_eq!(observer.claim(), ReturnCode::ENOSUPPORT);
+        observer.set();
+        assert!(!pin.read());
+
+        assert_eq!(kernel_user.make_output(), Configuration::InputOutput);
+        assert!(kernel_user.is_owner());
+        assert_eq!(app_user.claim(), ReturnCode::EBUSY);
+        assert_eq!(app_user.disable_output(), Configuration::InputOutput);
+        app_user.set();
+        assert!(!pin.read());
+        assert!(!app_user.toggle());
+
+        kernel_user.set();
+        assert!(observer.read());
+
+        kernel_user.release();
+        assert!(!kernel_user.is_owner());
+        app_user.clear();
+        assert!(app_user.is_owner());
+        assert!(!pin.read());
+        assert_eq!(kernel_user.claim(), ReturnCode::EBUSY);
+    }
+
+    #[test]
+    fn events_fan_out_by_edge() {
+        let pin = FakePin::default();
+        let mux = MuxGpioPin::new(&pin);
+        let rising_user = VirtualGpioPin::new(&mux, Access::Control);
+        let falling_user = VirtualGpioPin::new(&mux, Access::Observe);
+        let either_user = VirtualGpioPin::new(&mux, Access::Observe);
+        let (rising, falling, either) = (Counter::default(), Counter::default(),
+                                         Counter::default());
+        rising_user.setup();
+        falling_user.setup();
+        either_user.setup();
+        rising_user.set_client(&rising);
+        falling_user.set_client(&falling);
+        either_user.set_client(&either);
+
+        rising_user.enable_interrupts(InterruptEdge::RisingEdge);
+        assert_eq!(pin.edges.get(), 1);
+        falling_user.enable_interrupts(InterruptEdge::FallingEdge);
+        assert_eq!(pin.edges.get(), 3);
+
+        pin.value.set(true);
+        kernel::hil::gpio::Client::fired(&mux);
+        pin.value.set(false);
+        kernel::hil::gpio::Client::fired(&mux);
+        assert_eq!((rising.count.get(), falling.count.get(), either.count.get()), (1, 1, 0));
+
+        either_user.enable_interrupts(InterruptEdge::EitherEdge);
+        rising_user.disable_interrupts();
+        pin.value.set(true);
+        kernel::hil::gpio::Client::fired(&mux);
+        assert_eq!((rising.count.get(), falling.count.get(), either.count.get()), (1, 1, 1));
+
+        falling_user.disable_interrupts();
+        either_user.disable_interrupts();
+        assert_eq!(pin.edges.get(), 0);
+    }
+}
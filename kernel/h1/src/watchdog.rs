@@ -0,0 +1,248 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Driver for the hardware watchdog.
+//!
+//! The watchdog counts down from `load` and raises its interrupt when it
+//! reaches zero. If the interrupt has not been cleared when it reaches zero
+//! again, it resets the chip. The kernel pets it (clears the interrupt and
+//! reloads the counter) from its main loop through the `WatchDog` trait, so a
+//! kernel that stops running resets the chip after two timeouts.
+//!
+//! Apps can take part as well: the kernel only pets the watchdog while its
+//! `Liveness` client reports that every app that asked for supervision is
+//! alive (see h1_syscalls::watchdog). Once an app is late the watchdog is left
+//! to expire. The first expiry logs the late app and leaves the interrupt
+//! pending (the chip stops dispatching it), so the second expiry resets the
+//! chip while the kernel keeps running long enough to flush the log.
+//!
+//! The late app is also written to a record in retained RAM (the `.retained`
+//! section, which the reset handler leaves alone), so that after the reset
+//! `init` can pick it up and report which app caused it.
+//!
+//! The watchdog is stopped while the chip sleeps, because the kernel cannot
+//! pet it then.
+
+use core::cell::Cell;
+use core::ptr;
+use kernel::common::cells::{OptionalCell, VolatileCell};
+use kernel::debug;
+use kernel::platform::watchdog::WatchDog;
+
+use crate::pmu::{Clock, PeripheralClock, PeripheralClock1};
+
+const WATCHDOG0_BASE: *const Registers = 0x40500000 as *const Registers;
+
+// The watchdog counts at the 24MHz peripheral clock.
+const WATCHDOG_HZ: u32 = 24_000_000;
+
+// Unlocks writes to the other registers.
+const UNLOCK_MAGIC: u32 = 0x1acce551;
+
+/// NVIC interrupt number of the watchdog interrupt.
+pub const WATCHDOG0_IRQ: u32 = 194;
+
+const CONTROL_INTEN: u32 = 1 << 0;
+const CONTROL_RESEN: u32 = 1 << 1;
+
+// Marks a valid `LateAppRecord`.
+const LATE_APP_MAGIC: u32 = 0x4c617465;
+
+/// The late app, kept across the watchdog reset. `check` is the complement
+/// of `app`, so that RAM contents left from power up are not mistaken for a
+/// record.
+#[repr(C)]
+struct LateAppRecord {
+    magic: u32,
+    app: u32,
+    check: u32,
+}
+
+#[link_section = ".retained"]
+static mut LATE_APP_RECORD: LateAppRecord = LateAppRecord { magic: 0, app: 0, check: 0 };
+
+pub(crate) const unsafe fn watchdog0() -> Watchdog {
+    Watchdog::new(WATCHDOG0_BASE)
+}
+
+#[repr(C)]
+struct Registers {
+    /// The value the counter is reloaded with.
+    load: VolatileCell<u32>,
+    /// The current value of the counter.
+    _value: VolatileCell<u32>,
+    /// Enables the interrupt (and the counter) and the reset output.
+    control: VolatileCell<u32>,
+    /// Writing any value clears the interrupt and reloads the counter.
+    intclr: VolatileCell<u32>,
+    _ris: VolatileCell<u32>,
+    _mis: VolatileCell<u32>,
+    _reserved: [u32; 762],
+    /// Write `UNLOCK_MAGIC` to allow writes, anything else to forbid them.
+    lock: VolatileCell<u32>,
+}
+
+/// Decides whether the kernel should keep petting the watchdog.
+pub trait Liveness {
+    /// Returns the process slot of an app that missed its pet interval, or
+    /// None if all supervised apps are alive. Slots, unlike app ids, still
+    /// identify the app after a reset.
+    fn late_app(&self) -> Option<usize>;
+}
+
+pub struct Watchdog {
+    registers: *const Registers,
+    timeout_ms: Cell<u32>,
+    liveness: OptionalCell<&'static dyn Liveness>,
+    late_app: OptionalCell<usize>,
+    late_app_before_reset: OptionalCell<usize>,
+    expired: Cell<bool>,
+}
+
+impl Watchdog {
+    const fn new(registers: *const Registers) -> Watchdog {
+        Watchdog {
+            registers: registers,
+            timeout_ms: Cell::new(0),
+            liveness: OptionalCell::empty(),
+            late_app: OptionalCell::empty(),
+            late_app_before_reset: OptionalCell::empty(),
+            expired: Cell::new(false),
+        }
+    }
+
+    /// Sets the time between two expiries of the watchdog; the chip resets
+    /// `2 * timeout_ms` after the last pet. The watchdog is disabled while
+    /// this is 0, which is the default. Must be called before the kernel
+    /// loop starts.
+    pub fn set_timeout_ms(&self, timeout_ms: u32) {
+        self.timeout_ms.set(timeout_ms);
+    }
+
+    /// Only pets the watchdog while `liveness` reports all apps alive.
+    pub fn set_liveness(&self, liveness: &'static dyn Liveness) {
+        self.liveness.set(liveness);
+    }
+
+    /// Picks up the late app recorded before the last reset, if any, and
+    /// clears the record. Must be called once at boot.
+    pub fn init(&self) {
+        let record = unsafe { &mut LATE_APP_RECORD };
+        let magic = unsafe { ptr::read_volatile(&record.magic) };
+        let app = unsafe { ptr::read_volatile(&record.app) };
+        let check = unsafe { ptr::read_volatile(&record.check) };
+        unsafe { ptr::write_volatile(&mut record.magic, 0) };
+        if magic == LATE_APP_MAGIC && check == !app {
+            debug!("watchdog: last reset was caused by late app in slot {}", app);
+            self.late_app_before_reset.set(app as usize);
+        }
+    }
+
+    /// Returns the slot of the app that stopped the kernel from petting the
+    /// watchdog, if any.
+    pub fn late_app(&self) -> Option<usize> {
+        self.late_app.map(|app| *app)
+    }
+
+    /// Returns the slot of the app whose lateness made the watchdog reset
+    /// the chip before this boot, if that is why the chip reset.
+    pub fn late_app_before_reset(&self) -> Option<usize> {
+        self.late_app_before_reset.map(|app| *app)
+    }
+
+    /// Returns true once the watchdog expired. Its interrupt is then left
+    /// pending until the second expiry resets the chip.
+    pub fn is_expired(&self) -> bool {
+        self.expired.get()
+    }
+
+    /// Handles the first expiry of the watchdog. The interrupt is not
+    /// cleared, so the watchdog resets the chip when it expires again.
+    pub fn handle_interrupt(&self) {
+        if self.expired.replace(true) {
+            return;
+        }
+        match self.late_app() {
+            Some(app) => {
+                let record = unsafe { &mut LATE_APP_RECORD };
+                unsafe {
+                    ptr::write_volatile(&mut record.app, app as u32);
+                    ptr::write_volatile(&mut record.check, !(app as u32));
+                    ptr::write_volatile(&mut record.magic, LATE_APP_MAGIC);
+                }
+                debug!("watchdog: expired, app in slot {} is late; resetting", app)
+            },
+            None => debug!("watchdog: expired, kernel is late; resetting"),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.timeout_ms.get() != 0
+    }
+
+    fn write_control(&self, control: u32) {
+        let regs = unsafe { &*self.registers };
+        regs.lock.set(UNLOCK_MAGIC);
+        regs.control.set(control);
+        regs.lock.set(0);
+    }
+}
+
+impl WatchDog for Watchdog {
+    fn setup(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        Clock::new(PeripheralClock::Bank1(PeripheralClock1::Watchdog0)).enable();
+        let regs = unsafe { &*self.registers };
+        let load = (WATCHDOG_HZ as u64 * self.timeout_ms.get() as u64 / 1000)
+            .min(u32::MAX as u64) as u32;
+        regs.lock.set(UNLOCK_MAGIC);
+        regs.load.set(load);
+        regs.intclr.set(1);
+        regs.control.set(CONTROL_INTEN | CONTROL_RESEN);
+        regs.lock.set(0);
+    }
+
+    fn tickle(&self) {
+        if !self.is_enabled() || self.late_app.is_some() || self.expired.get() {
+            return;
+        }
+        if let Some(app) = self.liveness.map_or(None, |liveness| liveness.late_app()) {
+            debug!("watchdog: app in slot {} missed its pet interval, letting the watchdog reset",
+                   app);
+            self.late_app.set(app);
+            return;
+        }
+        let regs = unsafe { &*self.registers };
+        regs.lock.set(UNLOCK_MAGIC);
+        regs.intclr.set(1);
+        regs.lock.set(0);
+    }
+
+    fn suspend(&self) {
+        if self.is_enabled() && self.late_app.is_none() && !self.expired.get() {
+            self.write_control(0);
+        }
+    }
+
+    fn resume(&self) {
+        if self.is_enabled() && self.late_app.is_none() && !self.expired.get() {
+            self.tickle();
+            self.write_control(CONTROL_INTEN | CONTROL_RESEN);
+        }
+    }
+}
@@ -13,7 +13,13 @@
 // limitations under the License.
 
 use core::cell::Cell;
+use core::cmp::min;
+use crate::error::{ErrorCode, IntoReturnCode};
+use crate::app_slice::AppSliceExt;
 use h1::crypto::aes::{AesEngine, AES128Ecb};
+use h1::crypto::gcm;
+use h1::crypto::key_slots::{AesKeySlots, Owner};
+use h1::crypto::util;
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode, Shared, AppSlice};
 use kernel::common::cells::TakeCell;
 use kernel::hil::symmetric_encryption;
@@ -23,7 +29,122 @@ use kernel::hil::symmetric_encryption::{AES128, AES128CBC, AES128Ctr};
 
 pub const DRIVER_NUM: usize = 0x40010;
 
-pub static mut AES_BUF: [u8; AES128_BLOCK_SIZE] = [0; AES128_BLOCK_SIZE];
+/// Maximum number of blocks processed in a single session before the app
+/// must begin a new one.
+pub const MAX_SESSION_BLOCKS: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionMode {
+    Ctr = 0,
+    CbcEncrypt = 1,
+    CbcDecrypt = 2,
+    GcmEncrypt = 3,
+    GcmDecrypt = 4,
+}
+
+impl SessionMode {
+    fn from_usize(value: usize) -> Option<SessionMode> {
+        match value {
+            0 => Some(SessionMode::Ctr),
+            1 => Some(SessionMode::CbcEncrypt),
+            2 => Some(SessionMode::CbcDecrypt),
+            3 => Some(SessionMode::GcmEncrypt),
+            4 => Some(SessionMode::GcmDecrypt),
+            _ => None,
+        }
+    }
+
+    fn is_gcm(self) -> bool {
+        self == SessionMode::GcmEncrypt || self == SessionMode::GcmDecrypt
+    }
+}
+
+/// Chaining state of a multi-block operation, kept by the kernel so that
+/// apps do not need to track IVs and counters across blocks.
+#[derive(Default)]
+struct Session {
+    mode: Option<SessionMode>,
+    // IV (CBC) or counter block (CTR, GCM) for the next block.
+    iv: [u8; AES128_BLOCK_SIZE],
+    // Input of the block in flight; the next IV when decrypting CBC.
+    pending_input: [u8; AES128_BLOCK_SIZE],
+    // Offset of the block in flight in the app's buffers, and the number of
+    // bytes the current update processes.
+    offset: usize,
+    end: usize,
+    blocks: usize,
+    gcm: GcmState,
+}
+
+/// Authentication state of a GCM session.
+#[derive(Default)]
+struct GcmState {
+    // GHASH key, the encryption of the zero block.
+    hash_key: [u8; AES128_BLOCK_SIZE],
+    // Encryption of the initial counter block, XORed into the tag.
+    tag_mask: [u8; AES128_BLOCK_SIZE],
+    // GHASH accumulator.
+    mac: [u8; AES128_BLOCK_SIZE],
+    aad_len: u64,
+    text_len: u64,
+    // Number of message bytes in the block in flight.
+    pending_len: usize,
+    // Set once a partial block has ended the AAD or the message.
+    aad_done: bool,
+    text_done: bool,
+}
+
+impl Session {
+    fn is_active(&self) -> bool {
+        self.mode.is_some()
+    }
+
+    fn zeroize(&mut self) {
+        self.mode = None;
+        util::zeroize(&mut self.iv);
+        util::zeroize(&mut self.pending_input);
+        self.offset = 0;
+        self.end = 0;
+        self.blocks = 0;
+        util::zeroize(&mut self.gcm.hash_key);
+        util::zeroize(&mut self.gcm.tag_mask);
+        util::zeroize(&mut self.gcm.mac);
+        self.gcm = GcmState::default();
+    }
+
+    fn is_gcm(&self) -> bool {
+        self.mode.map_or(false, SessionMode::is_gcm)
+    }
+
+    // Compute the IV for the next block from the output of the current one.
+    fn advance(&mut self, output: Option<&[u8]>) {
+        match self.mode {
+            Some(SessionMode::Ctr) => {
+                // Increment the big-endian counter block.
+                for byte in self.iv.iter_mut().rev() {
+                    *byte = byte.wrapping_add(1);
+                    if *byte != 0 {
+                        break;
+                    }
+                }
+            }
+            Some(SessionMode::CbcEncrypt) => match output {
+                Some(output) => self.iv.copy_from_slice(output),
+                None => {
+                    // Cannot chain without the ciphertext.
+                    self.zeroize();
+                    return;
+                }
+            },
+            Some(SessionMode::CbcDecrypt) => self.iv = self.pending_input,
+            Some(SessionMode::GcmEncrypt) | Some(SessionMode::GcmDecrypt) => {
+                gcm::increment_counter(&mut self.iv);
+            }
+            None => return,
+        }
+        self.blocks += 1;
+    }
+}
 
 #[derive(Default)]
 pub struct AppData {
@@ -32,23 +153,32 @@ pub struct AppData {
     output_buffer: Option<AppSlice<Shared, u8>>,
     iv_buffer: Option<AppSlice<Shared, u8>>,
     crypto_callback: Option<Callback>,
+    session: Session,
+    // Key slot used instead of the key buffer, if any.
+    key_slot: Option<usize>,
 }
 
 pub struct AesDriver<'a> {
     device: &'a AesEngine<'a>,
+    key_slots: &'a AesKeySlots,
     apps: Grant<AppData>,
     current_user: Cell<Option<AppId>>,
     buffer: TakeCell<'a, [u8]>,
+    // App whose session state is loaded into the engine.
+    session_owner: Cell<Option<AppId>>,
 }
 
 impl<'a> AesDriver<'a> {
-    pub fn new(device: &'a mut AesEngine<'a>,
+    pub fn new(device: &'a AesEngine<'a>,
+               key_slots: &'a AesKeySlots,
                container: Grant<AppData>) -> AesDriver<'a> {
         AesDriver {
             device: device,
+            key_slots: key_slots,
             apps: container,
             current_user: Cell::new(None),
             buffer: TakeCell::empty(),
+            session_owner: Cell::new(None),
         }
     }
 
@@ -67,43 +197,62 @@ impl<'a> AesDriver<'a> {
         }
     }
 
-    fn run_aes(&self, caller_id: AppId) -> ReturnCode {
+    // Load the app's key into the engine: the selected key slot if there
+    // is one, the key buffer otherwise.
+    fn load_key(&self, caller_id: AppId, app_data: &AppData) -> ReturnCode {
+        if let Some(slot) = app_data.key_slot {
+            return self.key_slots.with_key(slot, caller_id, |key| self.device.set_key(key))
+                .unwrap_or_else(|| {
+                    debug!("AES: key slot {} is not usable.\n", slot);
+                    ErrorCode::Reserve.rcode()
+                });
+        }
+        match app_data.key {
+            Some(ref key) if key.len() == AES128_KEY_SIZE => {
+                self.device.set_key(key.as_ref());
+                ReturnCode::SUCCESS
+            }
+            Some(_) => {
+                debug!("AES: application encryption key is wrong size.\n");
+                ErrorCode::Invalid.rcode()
+            }
+            None => {
+                debug!("AES: Missing application encryption key.\n");
+                ErrorCode::Size.rcode()
+            }
+        }
+    }
+
+    // Run the block at `offset` in the input buffer through the engine.
+    fn run_aes(&self, caller_id: AppId, offset: usize) -> ReturnCode {
         self.apps.enter(caller_id, |app_data, _| {
             if app_data.input_buffer.is_none() {
                 debug!("AES: Missing input buffer.\n");
-                return ReturnCode::ENOMEM;
-            } else if app_data.key.is_none() {
-                debug!("AES: Missing application encryption key.\n");
-                return ReturnCode::ENOMEM;
+                return ErrorCode::Size.rcode();
             } else if self.buffer.is_none() {
                 debug!("AES: Missing kernel buffer.\n");
-                return ReturnCode::ENOMEM;
+                return ErrorCode::Size.rcode();
             }
 
-            let key = app_data.key.take();
-            let rcode = key.map_or(ReturnCode::EINVAL, |key| {
-                if key.len() == AES128_KEY_SIZE {
-                    self.device.set_key(key.as_ref());
-                    app_data.key = Some(key);
-                    ReturnCode::SUCCESS
-                } else {
-                    debug!("AES: application encryption key is wrong size.\n");
-                    ReturnCode::EINVAL
-                }
-            });
-
+            let rcode = self.load_key(caller_id, app_data);
             if rcode != ReturnCode::SUCCESS {
                 return rcode;
             }
 
             // Copy application data into the kernel buffer
-            self.buffer.map(|buf| {
-                app_data.input_buffer.as_ref().map(|src| {
-                    for (i, c) in src.as_ref()[0..AES128_BLOCK_SIZE].iter().enumerate() {
-                        buf[i] = *c;
+            let rcode = app_data.input_buffer.as_ref().map_or(ErrorCode::Size.rcode(), |src| {
+                match src.get_range(offset, AES128_BLOCK_SIZE) {
+                    Ok(input) => {
+                        self.buffer.map(|buf| buf.copy_from_slice(input));
+                        ReturnCode::SUCCESS
                     }
-                });
+                    Err(rcode) => rcode,
+                }
             });
+
+            if rcode != ReturnCode::SUCCESS {
+                return rcode;
+            }
             let buf = self.buffer.take().unwrap();
             let opt =  AES128::crypt(self.device, None, buf, 0, AES128_BLOCK_SIZE);
             if let Some((rcode, _ibufopt, obuf)) = opt {
@@ -113,29 +262,403 @@ impl<'a> AesDriver<'a> {
             } else {
                 ReturnCode::SUCCESS
             }
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    // Clear the engine's key and chaining state if no operation is running.
+    fn wipe_engine(&self) {
+        if !self.device.is_busy() {
+            self.device.wipe_secrets();
+        }
+    }
+
+    fn begin_session(&self, caller_id: AppId, mode: SessionMode) -> ReturnCode {
+        if let Some(owner) = self.session_owner.get() {
+            // A restarted app loses its grant and with it its session, so
+            // only a live session blocks other apps.
+            let owner_active = owner != caller_id && self.apps.enter(owner, |app_data, _| {
+                app_data.session.is_active()
+            }).unwrap_or(false);
+            if owner_active {
+                return ErrorCode::Busy.rcode();
+            }
+            self.session_owner.set(None);
+            self.wipe_engine();
+        }
+
+        self.apps.enter(caller_id, |app_data, _| {
+            let iv = match app_data.iv_buffer {
+                Some(ref iv) => match iv.get_prefix(AES128_BLOCK_SIZE) {
+                    Ok(iv) => iv,
+                    Err(rcode) => return rcode,
+                },
+                None => return ErrorCode::Size.rcode(),
+            };
+            app_data.session.zeroize();
+            app_data.session.iv.copy_from_slice(iv);
+            app_data.session.mode = Some(mode);
+            if mode.is_gcm() {
+                let rcode = self.start_gcm(caller_id, app_data);
+                if rcode != ReturnCode::SUCCESS {
+                    app_data.session.zeroize();
+                    return rcode;
+                }
+            }
+            self.session_owner.set(Some(caller_id));
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    // Derive the GHASH key and tag mask from the app's key, and replace the
+    // IV with the counter block of the first message block.
+    fn start_gcm(&self, caller_id: AppId, app_data: &mut AppData) -> ReturnCode {
+        let rcode = self.load_key(caller_id, app_data);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+
+        let session = &mut app_data.session;
+        let mut iv = [0; gcm::IV_SIZE];
+        iv.copy_from_slice(&session.iv[..gcm::IV_SIZE]);
+        let mut counter = gcm::initial_counter(&iv);
+
+        let rcode = self.device.encrypt_block_polled(&mut session.gcm.hash_key);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        session.gcm.tag_mask = counter;
+        let rcode = self.device.encrypt_block_polled(&mut session.gcm.tag_mask);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        gcm::increment_counter(&mut counter);
+        session.iv = counter;
+        ReturnCode::SUCCESS
+    }
+
+    // Process the first `len` bytes of the input buffer, one block at a time.
+    // Each completed block starts the next one from `crypt_done`, and the
+    // app gets a single callback once all of them are done.
+    fn update_session(&self, caller_id: AppId, len: usize) -> ReturnCode {
+        if self.session_owner.get() != Some(caller_id) {
+            return ErrorCode::Reserve.rcode();
+        }
+        if self.device.is_busy() {
+            return ErrorCode::Busy.rcode();
+        }
+
+        let rcode = self.apps.enter(caller_id, |app_data, _| {
+            let app_data: &mut AppData = app_data;
+            let mode = match app_data.session.mode {
+                Some(mode) => mode,
+                None => return ErrorCode::Reserve.rcode(),
+            };
+            // Only the last message block of a GCM session may be partial.
+            if len == 0 || (!mode.is_gcm() && len % AES128_BLOCK_SIZE != 0) ||
+                app_data.session.gcm.text_done {
+                return ErrorCode::Invalid.rcode();
+            }
+            let blocks_len = (len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE;
+            let buffer_fits = |buffer: &AppSlice<Shared, u8>| buffer.len() >= blocks_len;
+            if !app_data.input_buffer.as_ref().map_or(false, buffer_fits) ||
+                !app_data.output_buffer.as_ref().map_or(true, buffer_fits) {
+                return ErrorCode::Size.rcode();
+            }
+            app_data.session.offset = 0;
+            app_data.session.end = len;
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode());
+
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        self.start_session_block(caller_id)
+    }
+
+    // Load the session block at the current offset and start the engine on
+    // it with the session's mode and IV.
+    fn start_session_block(&self, caller_id: AppId) -> ReturnCode {
+        let offset = self.apps.enter(caller_id, |app_data, _| {
+            let app_data: &mut AppData = app_data;
+            let session = &mut app_data.session;
+            let mode = match session.mode {
+                Some(mode) => mode,
+                None => return Err(ErrorCode::Reserve.rcode()),
+            };
+            if session.blocks >= MAX_SESSION_BLOCKS {
+                debug!("AES: session exceeded its lifetime.\n");
+                session.zeroize();
+                return Err(ErrorCode::Cancel.rcode());
+            }
+            session.gcm.pending_len = min(AES128_BLOCK_SIZE, session.end - session.offset);
+            match app_data.input_buffer {
+                Some(ref input) => match input.get_range(session.offset, AES128_BLOCK_SIZE) {
+                    Ok(input) => session.pending_input.copy_from_slice(input),
+                    Err(rcode) => return Err(rcode),
+                },
+                None => return Err(ErrorCode::Size.rcode()),
+            }
+
+            match mode {
+                SessionMode::Ctr => self.device.set_mode_aes128ctr(true),
+                SessionMode::CbcEncrypt => self.device.set_mode_aes128cbc(true),
+                SessionMode::CbcDecrypt => self.device.set_mode_aes128cbc(false),
+                SessionMode::GcmEncrypt | SessionMode::GcmDecrypt => {
+                    self.device.set_mode_aes128ctr(true)
+                }
+            }
+            match self.device.set_iv(&session.iv) {
+                ReturnCode::SUCCESS => Ok(session.offset),
+                rcode => Err(rcode),
+            }
+        }).unwrap_or(Err(ErrorCode::NoMem.rcode()));
+
+        match offset {
+            Ok(offset) => self.run_aes(caller_id, offset),
+            Err(rcode) => {
+                if rcode == ErrorCode::Cancel.rcode() {
+                    self.end_session(caller_id);
+                }
+                rcode
+            }
+        }
+    }
+
+    fn end_session(&self, caller_id: AppId) -> ReturnCode {
+        let rcode = self.apps.enter(caller_id, |app_data, _| {
+            app_data.session.zeroize();
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode());
+
+        if self.session_owner.get() == Some(caller_id) {
+            self.session_owner.set(None);
+            self.wipe_engine();
+        }
+        rcode
+    }
+
+    // Hash the first `len` bytes of the input buffer as additional data.
+    fn absorb_aad(&self, caller_id: AppId, len: usize) -> ReturnCode {
+        if self.session_owner.get() != Some(caller_id) {
+            return ErrorCode::Reserve.rcode();
+        }
+        if self.device.is_busy() {
+            return ErrorCode::Busy.rcode();
+        }
+
+        self.apps.enter(caller_id, |app_data, _| {
+            let app_data: &mut AppData = app_data;
+            let session = &mut app_data.session;
+            if !session.is_gcm() {
+                return ErrorCode::Reserve.rcode();
+            }
+            // All additional data comes before the message, and only the
+            // last block of it may be partial.
+            if len == 0 || len > AES128_BLOCK_SIZE || session.gcm.aad_done ||
+                session.gcm.text_len > 0 {
+                return ErrorCode::Invalid.rcode();
+            }
+            let aad = match app_data.input_buffer {
+                Some(ref input) => match input.get_prefix(len) {
+                    Ok(aad) => aad,
+                    Err(rcode) => return rcode,
+                },
+                None => return ErrorCode::Size.rcode(),
+            };
+            self.device.ghash_block(&session.gcm.hash_key, &mut session.gcm.mac, aad);
+            session.gcm.aad_len += len as u64;
+            session.gcm.aad_done = len < AES128_BLOCK_SIZE;
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    // Hash the ciphertext of the block that just completed. Message bytes
+    // past the end of a partial block are cleared from the app's output.
+    fn absorb_gcm_block(&self, app_data: &mut AppData) {
+        let session = &mut app_data.session;
+        let len = session.gcm.pending_len;
+        let output = app_data.output_buffer.as_mut().or(app_data.input_buffer.as_mut())
+            .and_then(|output| output.get_range_mut(session.offset, AES128_BLOCK_SIZE).ok());
+        let mut ciphertext = [0; AES128_BLOCK_SIZE];
+        match output {
+            Some(output) => {
+                for byte in output[len..].iter_mut() {
+                    *byte = 0;
+                }
+                if session.mode == Some(SessionMode::GcmEncrypt) {
+                    ciphertext[..len].copy_from_slice(&output[..len]);
+                } else {
+                    ciphertext[..len].copy_from_slice(&session.pending_input[..len]);
+                }
+            }
+            None => {
+                // Cannot authenticate without the ciphertext.
+                session.zeroize();
+                return;
+            }
+        }
+        self.device.ghash_block(&session.gcm.hash_key, &mut session.gcm.mac, &ciphertext[..len]);
+        session.gcm.text_len += len as u64;
+        session.gcm.text_done = len < AES128_BLOCK_SIZE;
+    }
+
+    // Compute the tag and end the session. Encrypting writes the tag to the
+    // output buffer, decrypting checks the first `tag_len` bytes of the
+    // input buffer against it. A failed check also ends the session, so
+    // that a tag cannot be guessed against the same message.
+    fn finish_gcm(&self, caller_id: AppId, tag_len: usize) -> ReturnCode {
+        if self.session_owner.get() != Some(caller_id) {
+            return ErrorCode::Reserve.rcode();
+        }
+        if self.device.is_busy() {
+            return ErrorCode::Busy.rcode();
+        }
+
+        let rcode = self.apps.enter(caller_id, |app_data, _| {
+            let app_data: &mut AppData = app_data;
+            let session = &mut app_data.session;
+            if !session.is_gcm() {
+                return ErrorCode::Reserve.rcode();
+            }
+            let lengths = gcm::length_block(session.gcm.aad_len, session.gcm.text_len);
+            self.device.ghash_block(&session.gcm.hash_key, &mut session.gcm.mac, &lengths);
+            let mut tag = [0; gcm::TAG_SIZE];
+            for (i, byte) in tag.iter_mut().enumerate() {
+                *byte = session.gcm.mac[i] ^ session.gcm.tag_mask[i];
+            }
+
+            let rcode = if session.mode == Some(SessionMode::GcmEncrypt) {
+                match app_data.output_buffer {
+                    Some(ref mut output) if output.len() >= gcm::TAG_SIZE => {
+                        output.as_mut()[..gcm::TAG_SIZE].copy_from_slice(&tag);
+                        ReturnCode::SUCCESS
+                    }
+                    _ => ErrorCode::Size.rcode(),
+                }
+            } else {
+                match app_data.input_buffer {
+                    Some(ref input) => match input.get_prefix(tag_len) {
+                        Ok(expected) if gcm::tag_matches(&tag, expected) => ReturnCode::SUCCESS,
+                        Ok(_) => ErrorCode::Fail.rcode(),
+                        Err(rcode) => rcode,
+                    },
+                    None => ErrorCode::Size.rcode(),
+                }
+            };
+            util::zeroize(&mut tag);
+            rcode
+        }).unwrap_or(ErrorCode::NoMem.rcode());
+
+        self.end_session(caller_id);
+        rcode
+    }
+
+    // Copy the key buffer into `slot`. A slot held by an app that has
+    // since restarted is taken over.
+    fn install_slot(&self, caller_id: AppId, slot: usize) -> ReturnCode {
+        if let Some(Owner::App(owner)) = self.key_slots.owner(slot) {
+            if owner != caller_id && self.apps.enter(owner, |_, _| ()).is_err() {
+                self.key_slots.clear(slot, Owner::App(owner));
+            }
+        }
+        self.apps.enter(caller_id, |app_data, _| {
+            match app_data.key {
+                Some(ref key) => self.key_slots.install(slot, Owner::App(caller_id), key.as_ref()),
+                None => ErrorCode::Size.rcode(),
+            }
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    fn clear_slot(&self, caller_id: AppId, slot: usize) -> ReturnCode {
+        let rcode = self.key_slots.clear(slot, Owner::App(caller_id));
+        if rcode == ReturnCode::SUCCESS {
+            self.wipe_engine();
+        }
+        rcode
+    }
+
+    // Use the key in `slot` for the following operations, or the key buffer
+    // again if `slot` is None.
+    fn select_slot(&self, caller_id: AppId, slot: Option<usize>) -> ReturnCode {
+        if let Some(slot) = slot {
+            if self.key_slots.with_key(slot, caller_id, |_| ()).is_none() {
+                return ErrorCode::Reserve.rcode();
+            }
+        }
+        self.apps.enter(caller_id, |app_data, _| {
+            app_data.key_slot = slot;
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 }
 
 impl<'a> symmetric_encryption::Client<'a> for AesDriver<'a> {
     fn crypt_done(&self, _source: Option<&'a mut [u8]>, output: &'a mut [u8]) {
-        self.current_user.get().map(|current_user| {
-            let _ = self.apps.enter(current_user, move |app_data, _| {
-                if let Some(ref mut slice) = app_data.output_buffer {
-                    self.device.read_data(slice.as_mut());
+        // The kernel buffer still holds the app's input block.
+        util::zeroize(output);
+        self.buffer.replace(output);
+
+        let current_user = match self.current_user.get() {
+            Some(current_user) => current_user,
+            None => return,
+        };
+        let more_blocks = self.apps.enter(current_user, |app_data, _| {
+            let app_data: &mut AppData = app_data;
+            let in_session = app_data.session.is_active() &&
+                self.session_owner.get() == Some(current_user);
+            let offset = if in_session { app_data.session.offset } else { 0 };
+            if let Some(ref mut slice) = app_data.output_buffer {
+                if let Ok(block) = slice.get_range_mut(offset, AES128_BLOCK_SIZE) {
+                    self.device.read_data(block);
                 }
-                let val = {
-                    if let Some(ref mut slice) = app_data.input_buffer {
-                        self.device.read_data(slice.as_mut())
-                    } else {
-                        0
-                    }
-                };
+            }
+            let val = match app_data.input_buffer {
+                Some(ref mut slice) => slice.get_range_mut(offset, AES128_BLOCK_SIZE)
+                    .map_or(0, |block| self.device.read_data(block)),
+                None => 0,
+            };
+            if !in_session {
                 self.current_user.set(None);
                 app_data.crypto_callback.map(|mut cb| cb.schedule(val, 0, 0));
-            });
-        });
-        self.buffer.replace(output);
+                return false;
+            }
+
+            if app_data.session.is_gcm() {
+                self.absorb_gcm_block(app_data);
+            }
+            let output = app_data.output_buffer.as_ref()
+                .or(app_data.input_buffer.as_ref())
+                .and_then(|slice| slice.get_range(offset, AES128_BLOCK_SIZE).ok());
+            app_data.session.advance(output);
+            app_data.session.offset += AES128_BLOCK_SIZE;
+            let session = &app_data.session;
+            if session.is_active() && session.offset < session.end {
+                return true;
+            }
+            // A session that ended early could not chain or authenticate.
+            let rcode = if session.is_active() {
+                ReturnCode::SUCCESS
+            } else {
+                ErrorCode::Fail.rcode()
+            };
+            let processed = min(session.offset, session.end);
+            self.current_user.set(None);
+            app_data.crypto_callback.map(|mut cb| cb.schedule(usize::from(rcode), processed, 0));
+            false
+        }).unwrap_or(false);
+
+        if more_blocks {
+            let rcode = self.start_session_block(current_user);
+            if rcode != ReturnCode::SUCCESS {
+                self.current_user.set(None);
+                let _ = self.apps.enter(current_user, |app_data, _| {
+                    let processed = app_data.session.offset;
+                    app_data.crypto_callback.map(|mut cb| {
+                        cb.schedule(usize::from(rcode), processed, 0)
+                    });
+                });
+            }
+        }
     }
 }
 
@@ -152,13 +675,13 @@ impl<'a> Driver for AesDriver<'a> {
                 self.apps.enter(app_id, |app_data, _| {
                     app_data.crypto_callback = callback;
                     ReturnCode::SUCCESS
-                }).unwrap_or(ReturnCode::ENOMEM)
+                }).unwrap_or(ErrorCode::NoMem.rcode())
             },
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
-    fn command(&self, command_num: usize, _arg1: usize, _: usize, caller_id: AppId) -> ReturnCode {
+    fn command(&self, command_num: usize, arg1: usize, _: usize, caller_id: AppId) -> ReturnCode {
         if self.current_user.get() == None {
             self.current_user.set(Some(caller_id));
         }
@@ -166,47 +689,84 @@ impl<'a> Driver for AesDriver<'a> {
             0 /* Check if present */ => ReturnCode::SUCCESS,
             1 /* encrypt ECB */ => {
                 self.device.set_mode_aes128ecb(true);
-                self.run_aes(caller_id)
+                self.run_aes(caller_id, 0)
             },
             2 /* decrypt ECB */ => {
                 self.device.set_mode_aes128ecb(false);
-                self.run_aes(caller_id)
+                self.run_aes(caller_id, 0)
             }
             3 | 4 /* encrypt/decrypt CTR */ => {
                 self.apps.enter(caller_id, |app_data, _| {
                     self.device.set_mode_aes128ctr(true);
                     let buffer = app_data.iv_buffer.take();
-                    buffer.map_or(ReturnCode::ENOMEM, |iv| {
+                    buffer.map_or(ErrorCode::Size.rcode(), |iv| {
                         self.device.set_iv(iv.as_ref());
                         app_data.iv_buffer = Some(iv);
-                        self.run_aes(caller_id)
+                        self.run_aes(caller_id, 0)
                     })
-                }).unwrap_or(ReturnCode::ENOMEM)
+                }).unwrap_or(ErrorCode::NoMem.rcode())
             }
             5 /* encrypt CBC */ => {
                 self.device.set_mode_aes128cbc(true);
-                self.run_aes(caller_id)
+                self.run_aes(caller_id, 0)
             },
             6 /* decrypt CBC */ => {
                 self.device.set_mode_aes128cbc(false);
-                self.run_aes(caller_id)
+                self.run_aes(caller_id, 0)
             },
-            7 /* install key */ => {
+            7 /* install key: the selected key slot, or the key buffer */ => {
                 self.apps.enter(caller_id, |app_data, _| {
-                    let key = app_data.key.take();
-                    let rcode = key.map_or(ReturnCode::ENOMEM, |key| {
-                        if key.len() == AES128_KEY_SIZE {
-                            self.device.set_key(key.as_ref());
-                        }
-                        app_data.key = Some(key);
-                        ReturnCode::SUCCESS
-                    });
-                    rcode
-                }).unwrap_or(ReturnCode::ENOMEM)
+                    self.load_key(caller_id, app_data)
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            }
+            8 /* begin session using the IV/counter buffer
+                 arg1: 0: CTR, 1: encrypt CBC, 2: decrypt CBC,
+                       3: encrypt GCM, 4: decrypt GCM (the first 12 bytes
+                       of the buffer are the IV, the key must be allowed) */ => {
+                match SessionMode::from_usize(arg1) {
+                    Some(mode) => self.begin_session(caller_id, mode),
+                    None => ErrorCode::Invalid.rcode(),
+                }
+            }
+            9 /* update session: process the first arg1 bytes of the input
+                 buffer block by block, advancing the IV/counter kept by the
+                 kernel. arg1 must be a multiple of 16 except for the last
+                 message of a GCM session. The callback gets the return code
+                 and the number of bytes processed once all blocks are done */ => {
+                self.update_session(caller_id, arg1)
+            }
+            10 /* finish session and zeroize its state */ => {
+                self.end_session(caller_id)
+            }
+            11 /* GCM: hash the first arg1 (1 to 16) bytes of the input
+                  buffer as additional data, before any message block */ => {
+                self.absorb_aad(caller_id, arg1)
+            }
+            12 /* GCM: finish the session. Encrypting writes the tag to the
+                  output buffer. Decrypting compares it with the first arg1
+                  (12 to 16) bytes of the input buffer, returning FAIL if
+                  they differ; discard the plaintext then */ => {
+                self.finish_gcm(caller_id, arg1)
+            }
+            13 /* copy the key buffer into key slot arg1, which must be free
+                  or already hold a key of this app. The key can then be
+                  used without keeping it in app memory */ => {
+                self.install_slot(caller_id, arg1)
+            }
+            14 /* wipe and free key slot arg1, which must hold a key of
+                  this app */ => {
+                self.clear_slot(caller_id, arg1)
+            }
+            15 /* use key slot arg1 instead of the key buffer. Slots
+                  holding kernel keys can be used by every app */ => {
+                self.select_slot(caller_id, Some(arg1))
+            }
+            16 /* use the key buffer again */ => {
+                self.select_slot(caller_id, None)
             }
             _ => {
                 self.current_user.set(None);
-                ReturnCode::ENOSUPPORT
+                ErrorCode::NoSupport.rcode()
             }
         }
     }
@@ -223,7 +783,7 @@ impl<'a> Driver for AesDriver<'a> {
                         .enter(app_id, |app_data, _| {
                             if let Some(s) = slice {
                                 if s.len() != AES128_KEY_SIZE {
-                                    return ReturnCode::ESIZE;
+                                    return ErrorCode::Size.rcode();
                                 }
                                 app_data.key = Some(s);
                             } else {
@@ -232,15 +792,15 @@ impl<'a> Driver for AesDriver<'a> {
 
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::FAIL)
+                        .unwrap_or(ErrorCode::Fail.rcode())
                 }
                 1 => {
                     // Input Buffer
                     self.apps
                         .enter(app_id, |app_data, _| {
                             if let Some(s) = slice {
-                                if s.len() != AES128_BLOCK_SIZE {
-                                    return ReturnCode::ESIZE;
+                                if s.len() == 0 || s.len() % AES128_BLOCK_SIZE != 0 {
+                                    return ErrorCode::Size.rcode();
                                 }
                                 app_data.input_buffer = Some(s);
                             } else {
@@ -248,15 +808,15 @@ impl<'a> Driver for AesDriver<'a> {
                             }
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::FAIL)
+                        .unwrap_or(ErrorCode::Fail.rcode())
                 }
                 2 => {
                     // Output Buffer
                     self.apps
                         .enter(app_id, |app_data, _| {
                             if let Some(s) = slice {
-                                if s.len() != AES128_BLOCK_SIZE {
-                                    return ReturnCode::ESIZE;
+                                if s.len() == 0 || s.len() % AES128_BLOCK_SIZE != 0 {
+                                    return ErrorCode::Size.rcode();
                                 }
                                 app_data.output_buffer = Some(s);
                             } else {
@@ -264,7 +824,7 @@ impl<'a> Driver for AesDriver<'a> {
                             }
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::FAIL)
+                        .unwrap_or(ErrorCode::Fail.rcode())
                 }
                 3 => {
                     // Initialization vector/Counter
@@ -272,7 +832,7 @@ impl<'a> Driver for AesDriver<'a> {
                         .enter(app_id, |app_data, _| {
                             if let Some(s) = slice {
                                 if s.len() != AES128_BLOCK_SIZE {
-                                    return ReturnCode::ESIZE;
+                                    return ErrorCode::Size.rcode();
                                 }
                                 app_data.iv_buffer = Some(s);
                             } else {
@@ -280,9 +840,9 @@ impl<'a> Driver for AesDriver<'a> {
                             }
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::FAIL)
+                        .unwrap_or(ErrorCode::Fail.rcode())
                 }
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 }
@@ -0,0 +1,98 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bounds-checked access to buffers shared by applications via `allow`.
+//!
+//! Offsets and lengths passed in syscall arguments are untrusted. Drivers
+//! should go through `AppSliceExt` rather than indexing app slices
+//! directly, so that a bad offset/length pair results in an error code
+//! instead of a kernel panic.
+
+use core::ops::Range;
+use crate::error::{ErrorCode, IntoReturnCode};
+use kernel::{AppSlice, ReturnCode, Shared};
+
+/// Validate an `offset`/`len` pair against a buffer of `buffer_len` bytes.
+///
+/// Returns the corresponding range, or ESIZE if it does not fit.
+pub fn checked_range(offset: usize, len: usize, buffer_len: usize) -> Result<Range<usize>, ReturnCode> {
+    match offset.checked_add(len) {
+        Some(end) if end <= buffer_len => Ok(offset..end),
+        _ => Err(ErrorCode::Size.rcode()),
+    }
+}
+
+pub trait AppSliceExt {
+    /// Get `len` bytes starting at `offset`, or ESIZE if out of bounds.
+    fn get_range(&self, offset: usize, len: usize) -> Result<&[u8], ReturnCode>;
+
+    /// Get `len` mutable bytes starting at `offset`, or ESIZE if out of bounds.
+    fn get_range_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8], ReturnCode>;
+
+    /// Get the first `len` bytes, or ESIZE if the slice is shorter.
+    fn get_prefix(&self, len: usize) -> Result<&[u8], ReturnCode> {
+        self.get_range(0, len)
+    }
+
+    /// Get the byte at `index`, or ESIZE if out of bounds.
+    fn get_byte(&self, index: usize) -> Result<u8, ReturnCode> {
+        self.get_range(index, 1).map(|byte| byte[0])
+    }
+}
+
+impl AppSliceExt for AppSlice<Shared, u8> {
+    fn get_range(&self, offset: usize, len: usize) -> Result<&[u8], ReturnCode> {
+        let range = checked_range(offset, len, self.len())?;
+        Ok(&self.as_ref()[range])
+    }
+
+    fn get_range_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8], ReturnCode> {
+        let range = checked_range(offset, len, self.len())?;
+        Ok(&mut self.as_mut()[range])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::checked_range;
+    use crate::error::{ErrorCode, IntoReturnCode};
+
+    #[test]
+    fn in_bounds() {
+        assert_eq!(checked_range(2, 3, 8), Ok(2..5));
+    }
+
+    #[test]
+    fn ends_at_buffer_end() {
+        assert_eq!(checked_range(5, 3, 8), Ok(5..8));
+        assert_eq!(checked_range(0, 8, 8), Ok(0..8));
+        assert_eq!(checked_range(5, 4, 8), Err(ErrorCode::Size.rcode()));
+    }
+
+    #[test]
+    fn zero_length() {
+        assert_eq!(checked_range(0, 0, 0), Ok(0..0));
+        assert_eq!(checked_range(8, 0, 8), Ok(8..8));
+        assert_eq!(checked_range(9, 0, 8), Err(ErrorCode::Size.rcode()));
+    }
+
+    #[test]
+    fn offset_plus_len_overflows() {
+        assert_eq!(checked_range(usize::MAX, 1, 8), Err(ErrorCode::Size.rcode()));
+        assert_eq!(checked_range(1, usize::MAX, usize::MAX), Err(ErrorCode::Size.rcode()));
+        assert_eq!(checked_range(usize::MAX, usize::MAX, usize::MAX), Err(ErrorCode::Size.rcode()));
+    }
+}
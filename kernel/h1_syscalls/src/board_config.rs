@@ -0,0 +1,158 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for the persistent board configuration.
+//!
+//! Keys are the values of spiutils::protocol::config::ConfigKey. Committed
+//! values take effect on the next boot. While the board is locked down (see
+//! h1::lockdown), staging and committing fail with ErrorCode::Reserve.
+//!
+//! The driver implements 6 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. get the staged value of key arg1
+//!   2. get the value of key arg1 the board booted with
+//!   3. stage value arg2 for key arg1
+//!   4. commit the staged configuration; completion is signaled by a
+//!      callback.
+//!   5. discard the staged changes
+//!
+//! The driver implements 1 subscribe:
+//!   0. callback for commit, called with the ReturnCode.
+
+use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::hil::board_config::{Client, ConfigKey, ConfigStore};
+use h1::lockdown::Lockdown;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::common::cells::OptionalCell;
+use spiutils::protocol::wire::WireEnum;
+
+pub const DRIVER_NUM: usize = 0x400c0;
+
+const COMMAND_CHECK: usize        = 0;
+const COMMAND_GET_STAGED: usize   = 1;
+const COMMAND_GET_ACTIVE: usize   = 2;
+const COMMAND_SET: usize          = 3;
+const COMMAND_COMMIT: usize       = 4;
+const COMMAND_ROLLBACK: usize     = 5;
+const SUBSCRIBE_DONE: usize       = 0;
+
+#[derive(Default)]
+pub struct AppData {
+    callback: Option<Callback>,
+}
+
+pub struct BoardConfigSyscall<'a> {
+    store: &'a dyn ConfigStore<'a>,
+    apps: Grant<AppData>,
+    busy: Cell<bool>,
+    current_user: OptionalCell<AppId>,
+    lockdown: OptionalCell<&'a Lockdown<'a>>,
+}
+
+fn config_key(value: usize) -> Option<ConfigKey> {
+    if value > 0xffff {
+        return None;
+    }
+    ConfigKey::from_wire_value(value as u16)
+}
+
+impl<'a> BoardConfigSyscall<'a> {
+    pub fn new(store: &'a dyn ConfigStore<'a>,
+               container: Grant<AppData>) -> BoardConfigSyscall<'a> {
+        BoardConfigSyscall {
+            store: store,
+            apps: container,
+            busy: Cell::new(false),
+            current_user: OptionalCell::empty(),
+            lockdown: OptionalCell::empty(),
+        }
+    }
+
+    /// Rejects configuration changes while `lockdown` is engaged.
+    pub fn set_lockdown(&self, lockdown: &'a Lockdown<'a>) {
+        self.lockdown.set(lockdown);
+    }
+
+    fn is_locked_down(&self) -> bool {
+        self.lockdown.map_or(false, |lockdown| lockdown.is_engaged())
+    }
+}
+
+impl<'a> Driver for BoardConfigSyscall<'a> {
+    fn subscribe(&self,
+                 subscribe_num: usize,
+                 callback: Option<Callback>,
+                 app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            SUBSCRIBE_DONE => {
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            }
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_GET_STAGED => match config_key(arg1) {
+                Some(key) => ReturnCode::SuccessWithValue {
+                    value: self.store.staged().get(key) as usize
+                },
+                None => ErrorCode::Invalid.rcode(),
+            },
+            COMMAND_GET_ACTIVE => match config_key(arg1) {
+                Some(key) => ReturnCode::SuccessWithValue {
+                    value: self.store.active().get(key) as usize
+                },
+                None => ErrorCode::Invalid.rcode(),
+            },
+            COMMAND_SET | COMMAND_COMMIT if self.is_locked_down() => ErrorCode::Reserve.rcode(),
+            COMMAND_SET => match config_key(arg1) {
+                Some(key) => self.store.set(key, arg2 as u32),
+                None => ErrorCode::Invalid.rcode(),
+            },
+            COMMAND_COMMIT => {
+                if self.busy.get() {
+                    return ErrorCode::Busy.rcode();
+                }
+                let rcode = self.store.commit();
+                if rcode == ReturnCode::SUCCESS {
+                    self.busy.set(true);
+                    self.current_user.set(app_id);
+                }
+                rcode
+            },
+            COMMAND_ROLLBACK => self.store.rollback(),
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
+
+impl<'a> Client for BoardConfigSyscall<'a> {
+    fn commit_done(&self, rcode: ReturnCode) {
+        self.busy.set(false);
+        self.current_user.take().map(|current_user| {
+            let _ = self.apps.enter(current_user, |app_data, _| {
+                app_data.callback.map(|mut cb| cb.schedule(From::from(rcode), 0, 0));
+            });
+        });
+    }
+}
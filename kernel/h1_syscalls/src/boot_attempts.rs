@@ -0,0 +1,58 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for the failed boot count.
+//!
+//! The driver implements 3 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. get the number of failed boots in a row before this one
+//!   2. report that the app passed its health check, which clears the failed
+//!      boot count. Fails with ErrorCode::Already if already reported.
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::boot_attempts::BootAttempts;
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x400f0;
+
+const COMMAND_CHECK: usize              = 0;
+const COMMAND_GET_FAILED_BOOTS: usize   = 1;
+const COMMAND_MARK_HEALTHY: usize       = 2;
+
+pub struct BootAttemptsSyscall<'a> {
+    boot_attempts: &'a BootAttempts<'a>,
+}
+
+impl<'a> BootAttemptsSyscall<'a> {
+    pub fn new(boot_attempts: &'a BootAttempts<'a>) -> BootAttemptsSyscall<'a> {
+        BootAttemptsSyscall {
+            boot_attempts: boot_attempts,
+        }
+    }
+}
+
+impl<'a> Driver for BootAttemptsSyscall<'a> {
+    fn command(&self, command_num: usize, _arg1: usize, _arg2: usize, _app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_GET_FAILED_BOOTS => ReturnCode::SuccessWithValue {
+                value: self.boot_attempts.failed_boots()
+            },
+            COMMAND_MARK_HEALTHY => self.boot_attempts.mark_healthy(),
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for the console timestamps.
+//!
+//! While enabled, the kernel prefixes every console line with the time since
+//! boot (see h1::uart::format_timestamp).
+//!
+//! The driver implements 3 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. get whether timestamps are enabled (1) or not (0)
+//!   2. enable (arg1 = 1) or disable (arg1 = 0) timestamps. Fails with
+//!      ErrorCode::NoSupport if the board has no timestamp clock.
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::uart::UART;
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x40150;
+
+const COMMAND_CHECK: usize     = 0;
+const COMMAND_GET: usize       = 1;
+const COMMAND_SET: usize       = 2;
+
+pub struct ConsoleTimestampsSyscall<'a> {
+    uart: &'a UART<'a>,
+}
+
+impl<'a> ConsoleTimestampsSyscall<'a> {
+    pub fn new(uart: &'a UART<'a>) -> ConsoleTimestampsSyscall<'a> {
+        ConsoleTimestampsSyscall {
+            uart: uart,
+        }
+    }
+}
+
+impl<'a> Driver for ConsoleTimestampsSyscall<'a> {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize, _app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_GET => ReturnCode::SuccessWithValue {
+                value: self.uart.timestamps_enabled() as usize
+            },
+            COMMAND_SET => {
+                if arg1 > 1 {
+                    return ErrorCode::Invalid.rcode();
+                }
+                if self.uart.enable_timestamps(arg1 == 1) {
+                    ReturnCode::SUCCESS
+                } else {
+                    ErrorCode::NoSupport.rcode()
+                }
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
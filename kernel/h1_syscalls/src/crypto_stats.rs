@@ -0,0 +1,67 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Debug syscall driver for the crypto engine usage counters.
+//!
+//! Engines are the values of h1::crypto::stats::Engine:
+//!   0: SHA, 1: AES, 2: DCRYPTO.
+//!
+//! The driver implements 4 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. get the number of operations engine arg1 completed since boot
+//!   2. get the number of bytes engine arg1 processed since boot
+//!   3. get the time engine arg1 was busy since boot, in microseconds,
+//!      saturated to u32::MAX; 0 if the board did not set a clock.
+
+use core::convert::TryFrom;
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::crypto::stats::{self, Engine};
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x40160;
+
+const COMMAND_CHECK: usize      = 0;
+const COMMAND_GET_OPS: usize    = 1;
+const COMMAND_GET_BYTES: usize  = 2;
+const COMMAND_GET_BUSY: usize   = 3;
+
+pub struct CryptoStatsSyscall;
+
+impl CryptoStatsSyscall {
+    pub const fn new() -> CryptoStatsSyscall {
+        CryptoStatsSyscall
+    }
+}
+
+impl Driver for CryptoStatsSyscall {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize, _app_id: AppId) -> ReturnCode {
+        if command_num == COMMAND_CHECK {
+            return ReturnCode::SUCCESS;
+        }
+        let usage = match Engine::from_usize(arg1) {
+            Some(engine) => stats::get(engine),
+            None => return ErrorCode::Invalid.rcode(),
+        };
+        let value = match command_num {
+            COMMAND_GET_OPS => usage.ops,
+            COMMAND_GET_BYTES => usage.bytes,
+            COMMAND_GET_BUSY => u32::try_from(stats::ticks_to_us(usage.busy_ticks))
+                .unwrap_or(u32::MAX),
+            _ => return ErrorCode::NoSupport.rcode(),
+        };
+        ReturnCode::SuccessWithValue { value: value as usize }
+    }
+}
@@ -12,13 +12,101 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Syscall driver for the dcrypto engine.
+//!
+//! Besides running app-supplied dcrypto programs (command 1), the driver
+//! offers X25519, Ed25519 and ECDSA P-256 operations on the data buffer
+//! (commands 2-8), so apps do not need to ship their own programs for them.
+//!
+//! The Curve25519 commands (2-5) run the point arithmetic on the engine,
+//! using the program in `h1::crypto::curve25519`, and hashing and scalar
+//! arithmetic on the CPU. The P-256 commands (6-8) run the cr50 P-256
+//! program through `h1::crypto::p256::P256Engine`. Like command 1, they
+//! complete through the callback, whose first argument is the result. The
+//! command itself only fails for malformed input.
+//!
+//! Data buffer layouts, with offsets in bytes:
+//!   2. X25519: scalar at 0, u-coordinate at 32. The result replaces the
+//!      scalar.
+//!   3. Ed25519 public key: secret key at 0. The public key is written at
+//!      32.
+//!   4. Ed25519 sign: secret key at 0, message of arg1 bytes at 64. The
+//!      signature replaces bytes 0-63.
+//!   5. Ed25519 verify: public key at 0, signature at 32, message of arg1
+//!      bytes at 96. The result is SUCCESS if the signature is valid and
+//!      FAIL otherwise.
+//!   6. P-256 public key: private key at 0. The public key (x, y) is
+//!      written at 32.
+//!   7. P-256 sign: private key at 0, 32-byte digest at 32. The signature
+//!      (r, s) replaces bytes 0-63. The nonce is derived as in RFC 6979,
+//!      with the HMACs computed on the SHA engine, so the command fails
+//!      with EBUSY while an app has a digest in progress.
+//!   8. P-256 verify: public key (x, y) at 0, 32-byte digest at 64,
+//!      signature (r, s) at 96. The result is SUCCESS if the signature is
+//!      valid and FAIL otherwise.
+//!
+//! P-256 scalars and coordinates are big-endian. An invalid private key
+//! gives EINVAL.
+
 use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
+use crate::app_slice::AppSliceExt;
+use ecc::curve25519::{self, ExpandedKey};
+use ecc::p256::{PrivateKey, PublicKey, Signature, SCALAR_LEN};
+use h1::crypto::curve25519 as program;
 use h1::crypto::dcrypto::{Dcrypto, DcryptoClient, ProgramFault};
+use h1::crypto::p256::{P256Client, P256Engine};
+use h1::crypto::sha::{ShaArbiter, ShaHmac};
+use h1::crypto::util;
 use kernel::{AppId, Callback, Driver, ReturnCode, Shared, AppSlice};
 use kernel::common::cells::MapCell;
 
 pub const DRIVER_NUM: usize = 0x40004;
 
+const KEY_LEN: usize = curve25519::KEY_LEN;
+const SIGNATURE_LEN: usize = curve25519::SIGNATURE_LEN;
+
+// Length of an encoded P-256 public key or signature.
+const P256_PAIR_LEN: usize = 2 * SCALAR_LEN;
+
+fn result(rcode: ReturnCode) -> Result<(), ReturnCode> {
+    match rcode {
+        ReturnCode::SUCCESS => Ok(()),
+        rcode => Err(rcode),
+    }
+}
+
+// What the engine is running.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Program,
+    X25519,
+    Ed25519PublicKey,
+    // Computing the public key A = [a]B of an Ed25519 signature.
+    Ed25519SignPublicKey,
+    // Computing the commitment R = [r]B of an Ed25519 signature.
+    Ed25519SignNonce,
+    Ed25519Verify,
+    // Any of the P-256 commands, run by the P256Engine.
+    P256,
+}
+
+// State carried across the engine runs of a Curve25519 command.
+struct CurveState {
+    key: Option<ExpandedKey>,
+    // The nonce r when signing.
+    nonce: [u8; KEY_LEN],
+    // The encoded public key when signing, and R when verifying.
+    point: [u8; KEY_LEN],
+    message_len: usize,
+}
+
+impl Drop for CurveState {
+    fn drop(&mut self) {
+        util::zeroize(&mut self.nonce);
+    }
+}
+
 pub struct App {
     program: Option<AppSlice<Shared, u8>>,
     data_buffer: Option<AppSlice<Shared, u8>>,
@@ -37,22 +125,31 @@ impl Default for App {
 
 pub struct DcryptoDriver<'a> {
     device: &'a dyn Dcrypto<'a>,
+    p256: &'a P256Engine<'a>,
+    sha: &'a ShaArbiter<'a>,
     app: MapCell<App>,
     busy: Cell<bool>,
+    operation: Cell<Operation>,
+    curve: MapCell<CurveState>,
 }
 
 impl<'a> DcryptoDriver<'a> {
-    pub fn new(device: &'a mut dyn Dcrypto<'a>) -> DcryptoDriver<'a> {
+    pub fn new(device: &'a dyn Dcrypto<'a>, p256: &'a P256Engine<'a>,
+               sha: &'a ShaArbiter<'a>) -> DcryptoDriver<'a> {
         DcryptoDriver {
             device: device,
+            p256: p256,
+            sha: sha,
             app: MapCell::new(App::default()),
             busy: Cell::new(false),
+            operation: Cell::new(Operation::Program),
+            curve: MapCell::empty(),
        }
     }
 
     fn run_program(&self, app: &mut App, instruction: u32) -> ReturnCode {
         if app.data_buffer.is_none() || app.program.is_none() {
-            return ReturnCode::ENOMEM;
+            return ErrorCode::Size.rcode();
         }
 
         let mut rval: ReturnCode;
@@ -85,6 +182,263 @@ impl<'a> DcryptoDriver<'a> {
         }
         ReturnCode::SUCCESS
     }
+
+    // Loads the Curve25519 program and starts it at `entry` on `dmem`.
+    fn run_curve(&self, operation: Operation, entry: u32,
+                 dmem: &mut [u8; program::DMEM_LEN]) -> Result<(), ReturnCode> {
+        let mut rcode = program::load(self.device);
+        if rcode == ReturnCode::SUCCESS {
+            rcode = self.device.write_data(dmem, 0, (program::DMEM_LEN / 4) as u32);
+        }
+        util::zeroize(dmem);
+        if rcode == ReturnCode::SUCCESS {
+            rcode = self.device.call_imem(entry);
+        }
+        if rcode != ReturnCode::SUCCESS {
+            return Err(rcode);
+        }
+        self.operation.set(operation);
+        Ok(())
+    }
+
+    // Starts [scalar]B for one of the Ed25519 operations.
+    fn run_base_mul(&self, operation: Operation, scalar: &[u8; KEY_LEN])
+                    -> Result<(), ReturnCode> {
+        let mut dmem = program::new_dmem();
+        dmem[program::K0..program::K1].copy_from_slice(scalar);
+        self.run_curve(operation, program::ED25519_MUL, &mut dmem)
+    }
+
+    fn x25519(&self, data: &AppSlice<Shared, u8>) -> Result<(), ReturnCode> {
+        let mut scalar = [0u8; KEY_LEN];
+        scalar.copy_from_slice(data.get_range(0, KEY_LEN)?);
+        let mut dmem = program::new_dmem();
+        dmem[program::K0..program::K1].copy_from_slice(&curve25519::clamp_scalar(&scalar));
+        util::zeroize(&mut scalar);
+        dmem[program::X..program::Y].copy_from_slice(data.get_range(KEY_LEN, KEY_LEN)?);
+        dmem[program::Y - 1] &= 0x7f;
+        self.run_curve(Operation::X25519, program::X25519, &mut dmem)
+    }
+
+    fn ed25519_public_key(&self, data: &AppSlice<Shared, u8>) -> Result<(), ReturnCode> {
+        data.get_range(0, 2 * KEY_LEN)?;
+        let mut secret = [0u8; KEY_LEN];
+        secret.copy_from_slice(data.get_range(0, KEY_LEN)?);
+        let key = ExpandedKey::new(&secret);
+        util::zeroize(&mut secret);
+        let mut scalar = key.scalar();
+        let result = self.run_base_mul(Operation::Ed25519PublicKey, &scalar);
+        util::zeroize(&mut scalar);
+        result
+    }
+
+    fn ed25519_sign(&self, data: &AppSlice<Shared, u8>, message_len: usize)
+                    -> Result<(), ReturnCode> {
+        let message = data.get_range(SIGNATURE_LEN, message_len)?;
+        let mut secret = [0u8; KEY_LEN];
+        secret.copy_from_slice(data.get_range(0, KEY_LEN)?);
+        let key = ExpandedKey::new(&secret);
+        util::zeroize(&mut secret);
+        let mut scalar = key.scalar();
+        let state = CurveState {
+            nonce: key.nonce(message),
+            key: Some(key),
+            point: [0; KEY_LEN],
+            message_len: message_len,
+        };
+        let result = self.run_base_mul(Operation::Ed25519SignPublicKey, &scalar);
+        util::zeroize(&mut scalar);
+        if result.is_ok() {
+            self.curve.put(state);
+        }
+        result
+    }
+
+    fn ed25519_verify(&self, data: &AppSlice<Shared, u8>, message_len: usize)
+                      -> Result<(), ReturnCode> {
+        let mut public_key = [0u8; KEY_LEN];
+        let mut r = [0u8; KEY_LEN];
+        let mut s = [0u8; KEY_LEN];
+        public_key.copy_from_slice(data.get_range(0, KEY_LEN)?);
+        r.copy_from_slice(data.get_range(KEY_LEN, KEY_LEN)?);
+        s.copy_from_slice(data.get_range(2 * KEY_LEN, KEY_LEN)?);
+        let message = data.get_range(KEY_LEN + SIGNATURE_LEN, message_len)?;
+        let (y, sign) = curve25519::ed25519_split(&public_key).ok_or(ErrorCode::Fail.rcode())?;
+        if !curve25519::ed25519_scalar_is_canonical(&s) {
+            return Err(ErrorCode::Fail.rcode());
+        }
+
+        // Compute [S]B - [k]A; the signature is valid if it encodes to R.
+        let mut dmem = program::new_dmem();
+        dmem[program::K0..program::K1].copy_from_slice(&s);
+        dmem[program::K1..program::X]
+            .copy_from_slice(&curve25519::ed25519_challenge(&r, &public_key, message));
+        dmem[program::Y..program::FLAG].copy_from_slice(&y);
+        dmem[program::FLAG] = sign;
+        let result = self.run_curve(Operation::Ed25519Verify, program::ED25519_VERIFY, &mut dmem);
+        if result.is_ok() {
+            self.curve.put(CurveState {
+                key: None,
+                nonce: [0; KEY_LEN],
+                point: r,
+                message_len: message_len,
+            });
+        }
+        result
+    }
+
+    // Finishes a run of the Curve25519 program, given its data memory.
+    // Returns the result of the command, or None if another run has been
+    // started.
+    fn curve_complete(&self, data: &mut AppSlice<Shared, u8>,
+                      dmem: &[u8; program::DMEM_LEN]) -> Option<Result<(), ReturnCode>> {
+        let mut x = [0u8; KEY_LEN];
+        let mut y = [0u8; KEY_LEN];
+        x.copy_from_slice(&dmem[program::X..program::Y]);
+        y.copy_from_slice(&dmem[program::Y..program::FLAG]);
+        let result = match self.operation.get() {
+            Operation::Program | Operation::P256 => return Some(Ok(())),
+            Operation::X25519 => data.get_range_mut(0, KEY_LEN).map(|out| {
+                out.copy_from_slice(&x);
+            }),
+            Operation::Ed25519PublicKey => data.get_range_mut(KEY_LEN, KEY_LEN).map(|out| {
+                out.copy_from_slice(&curve25519::ed25519_encode(&x, &y));
+            }),
+            Operation::Ed25519SignPublicKey => {
+                let mut nonce = self.curve.map_or([0; KEY_LEN], |state| {
+                    state.point = curve25519::ed25519_encode(&x, &y);
+                    state.nonce
+                });
+                let result = self.run_base_mul(Operation::Ed25519SignNonce, &nonce);
+                util::zeroize(&mut nonce);
+                if result.is_ok() {
+                    return None;
+                }
+                result
+            }
+            Operation::Ed25519SignNonce => self.curve.take().map_or(
+                Err(ErrorCode::Fail.rcode()),
+                |state| {
+                    let r = curve25519::ed25519_encode(&x, &y);
+                    let message = data.get_range(SIGNATURE_LEN, state.message_len)?;
+                    let k = curve25519::ed25519_challenge(&r, &state.point, message);
+                    let s = state.key.as_ref()
+                        .map(|key| key.signature_scalar(&state.nonce, &k))
+                        .ok_or(ErrorCode::Fail.rcode())?;
+                    let out = data.get_range_mut(0, SIGNATURE_LEN)?;
+                    out[..KEY_LEN].copy_from_slice(&r);
+                    out[KEY_LEN..].copy_from_slice(&s);
+                    Ok(())
+                }),
+            Operation::Ed25519Verify => match self.curve.take() {
+                Some(ref state) if dmem[program::FLAG] == 1 &&
+                    curve25519::ed25519_encode(&x, &y) == state.point => Ok(()),
+                _ => Err(ErrorCode::Fail.rcode()),
+            },
+        };
+        Some(result)
+    }
+
+    // Loads the P-256 private key at the start of the data buffer.
+    fn p256_private_key(data: &AppSlice<Shared, u8>) -> Result<PrivateKey, ReturnCode> {
+        let mut bytes = [0u8; SCALAR_LEN];
+        bytes.copy_from_slice(data.get_range(0, SCALAR_LEN)?);
+        let key = PrivateKey::from_bytes(&bytes);
+        util::zeroize(&mut bytes);
+        key.map_err(|_| ErrorCode::Invalid.rcode())
+    }
+
+    fn p256_public_key(&self, data: &AppSlice<Shared, u8>) -> Result<(), ReturnCode> {
+        data.get_range(0, SCALAR_LEN + P256_PAIR_LEN)?;
+        let mut key = DcryptoDriver::p256_private_key(data)?.to_bytes();
+        let rcode = self.p256.public_key(&key);
+        util::zeroize(&mut key);
+        result(rcode)
+    }
+
+    fn p256_sign(&self, data: &AppSlice<Shared, u8>) -> Result<(), ReturnCode> {
+        let mut digest = [0u8; SCALAR_LEN];
+        digest.copy_from_slice(data.get_range(SCALAR_LEN, SCALAR_LEN)?);
+        let key = DcryptoDriver::p256_private_key(data)?;
+        let sha = self.sha.engine()?;
+        let mut nonce = key.rfc6979_nonce(&digest, &ShaHmac::new(sha))
+            .map_err(|_| ErrorCode::Fail.rcode())?;
+        let mut key = key.to_bytes();
+        let rcode = self.p256.sign(&key, &digest, &nonce);
+        util::zeroize(&mut key);
+        util::zeroize(&mut nonce);
+        result(rcode)
+    }
+
+    fn p256_verify(&self, data: &AppSlice<Shared, u8>) -> Result<(), ReturnCode> {
+        let mut public_key = PublicKey { x: [0; SCALAR_LEN], y: [0; SCALAR_LEN] };
+        let mut digest = [0u8; SCALAR_LEN];
+        let mut signature = Signature { r: [0; SCALAR_LEN], s: [0; SCALAR_LEN] };
+        public_key.x.copy_from_slice(data.get_range(0, SCALAR_LEN)?);
+        public_key.y.copy_from_slice(data.get_range(SCALAR_LEN, SCALAR_LEN)?);
+        digest.copy_from_slice(data.get_range(P256_PAIR_LEN, SCALAR_LEN)?);
+        signature.r.copy_from_slice(data.get_range(P256_PAIR_LEN + SCALAR_LEN, SCALAR_LEN)?);
+        signature.s.copy_from_slice(data.get_range(2 * P256_PAIR_LEN, SCALAR_LEN)?);
+        result(self.p256.verify(&public_key, &digest, &signature))
+    }
+
+    // Finishes a P-256 command. `output` copies the result into the data
+    // buffer.
+    fn p256_complete(&self, rcode: ReturnCode,
+                     output: impl FnOnce(&mut AppSlice<Shared, u8>) -> Result<(), ReturnCode>) {
+        let rcode = if rcode == ReturnCode::SUCCESS {
+            self.app.map_or(ErrorCode::Fail.rcode(), |app| {
+                match app.data_buffer {
+                    Some(ref mut data) => match output(data) {
+                        Ok(()) => ReturnCode::SUCCESS,
+                        Err(rcode) => rcode,
+                    },
+                    None => ErrorCode::Size.rcode(),
+                }
+            })
+        } else {
+            rcode
+        };
+        self.operation.set(Operation::Program);
+        self.busy.set(false);
+        self.app.map(|app| {
+            app.callback.map(|mut callback| {
+                callback.schedule(usize::from(rcode), 0, 0);
+            });
+        });
+    }
+
+    // Runs one of the elliptic curve commands on the data buffer.
+    fn curve(&self, command_num: usize, message_len: usize) -> ReturnCode {
+        if self.busy.get() {
+            return ErrorCode::Busy.rcode();
+        }
+        self.app.map_or(ErrorCode::Busy.rcode(), |app| {
+            let data = match app.data_buffer {
+                Some(ref mut data) => data,
+                None => return ErrorCode::Size.rcode(),
+            };
+            let result = match command_num {
+                2 => self.x25519(data),
+                3 => self.ed25519_public_key(data),
+                4 => self.ed25519_sign(data, message_len),
+                5 => self.ed25519_verify(data, message_len),
+                6 => self.p256_public_key(data),
+                7 => self.p256_sign(data),
+                _ => self.p256_verify(data),
+            };
+            if result.is_ok() {
+                if command_num >= 6 {
+                    self.operation.set(Operation::P256);
+                }
+                self.busy.set(true);
+            }
+            match result {
+                Ok(()) => ReturnCode::SUCCESS,
+                Err(rcode) => rcode,
+            }
+        })
+    }
 }
 
 impl<'a> Driver for DcryptoDriver<'a> {
@@ -100,24 +454,32 @@ impl<'a> Driver for DcryptoDriver<'a> {
                 });
                 ReturnCode::SUCCESS
             },
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
-    fn command(&self, command_num: usize, instruction: usize, _: usize, _: AppId) -> ReturnCode {
+    fn command(&self, command_num: usize, arg1: usize, _: usize, _: AppId) -> ReturnCode {
         match command_num {
             0 /* Check if present */ => ReturnCode::SUCCESS,
             1 /* run program */ => {
                 if self.busy.get() {
-                    ReturnCode::EBUSY
+                    ErrorCode::Busy.rcode()
                 } else {
-                    self.app.map_or(ReturnCode::EBUSY, |app| {
+                    self.app.map_or(ErrorCode::Busy.rcode(), |app| {
                         self.busy.set(true);
-                        self.run_program(app, instruction as u32)
+                        self.operation.set(Operation::Program);
+                        self.run_program(app, arg1 as u32)
                     })
                 }
             }
-            _ => ReturnCode::ENOSUPPORT,
+            2 /* X25519 */ |
+            3 /* Ed25519 public key */ |
+            4 /* Ed25519 sign message of arg1 bytes */ |
+            5 /* Ed25519 verify message of arg1 bytes */ |
+            6 /* P-256 public key */ |
+            7 /* P-256 sign */ |
+            8 /* P-256 verify */ => self.curve(command_num, arg1),
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 
@@ -133,7 +495,7 @@ impl<'a> Driver for DcryptoDriver<'a> {
                         app_data.data_buffer = slice;
                         ReturnCode::SUCCESS
                     })
-                    .unwrap_or(ReturnCode::FAIL)
+                    .unwrap_or(ErrorCode::Fail.rcode())
             }
             1 => {
                 // Input Buffer
@@ -142,15 +504,78 @@ impl<'a> Driver for DcryptoDriver<'a> {
                         app_data.program = slice;
                         ReturnCode::SUCCESS
                     })
-                    .unwrap_or(ReturnCode::FAIL)
+                    .unwrap_or(ErrorCode::Fail.rcode())
             }
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 }
 
+impl<'a> DcryptoDriver<'a> {
+    fn curve_execution_complete(&self, error: ReturnCode, fault: ProgramFault) {
+        let mut dmem = [0u8; program::DMEM_LEN];
+        self.device.read_data(&mut dmem, 0, (program::DMEM_LEN / 4) as u32);
+        let result = if error != ReturnCode::SUCCESS {
+            Some(Err(error))
+        } else {
+            self.app.map_or(Some(Err(ErrorCode::Fail.rcode())), |app| {
+                match app.data_buffer {
+                    Some(ref mut data) => self.curve_complete(data, &dmem),
+                    None => Some(Err(ErrorCode::Size.rcode())),
+                }
+            })
+        };
+        util::zeroize(&mut dmem);
+        let rcode = match result {
+            // Another run of the program has started.
+            None => return,
+            Some(Ok(())) => ReturnCode::SUCCESS,
+            Some(Err(rcode)) => rcode,
+        };
+
+        // Do not leave keys or intermediate values in the engine.
+        self.device.write_data(&dmem, 0, (program::DMEM_LEN / 4) as u32);
+        self.curve.take();
+        self.operation.set(Operation::Program);
+        self.busy.set(false);
+        self.app.map(|app| {
+            app.callback.map(|mut callback| {
+                callback.schedule(usize::from(rcode), usize::from(fault), 0);
+            });
+        });
+    }
+}
+
+impl<'a> P256Client for DcryptoDriver<'a> {
+    fn public_key_done(&self, rcode: ReturnCode, public_key: &PublicKey) {
+        self.p256_complete(rcode, |data| {
+            let output = data.get_range_mut(SCALAR_LEN, P256_PAIR_LEN)?;
+            output[..SCALAR_LEN].copy_from_slice(&public_key.x);
+            output[SCALAR_LEN..].copy_from_slice(&public_key.y);
+            Ok(())
+        });
+    }
+
+    fn sign_done(&self, rcode: ReturnCode, signature: &Signature) {
+        self.p256_complete(rcode, |data| {
+            let output = data.get_range_mut(0, P256_PAIR_LEN)?;
+            output[..SCALAR_LEN].copy_from_slice(&signature.r);
+            output[SCALAR_LEN..].copy_from_slice(&signature.s);
+            Ok(())
+        });
+    }
+
+    fn verify_done(&self, rcode: ReturnCode) {
+        self.p256_complete(rcode, |_| Ok(()));
+    }
+}
+
 impl<'a> DcryptoClient<'a> for DcryptoDriver<'a> {
     fn execution_complete(&self, error: ReturnCode, fault: ProgramFault) {
+        if self.operation.get() != Operation::Program {
+            self.curve_execution_complete(error, fault);
+            return;
+        }
         self.busy.set(false);
         self.app.map(move |app| {
             app.callback.map(|mut callback| {
@@ -13,19 +13,18 @@
 // limitations under the License.
 
 use h1::test_dcrypto::TestDcrypto;
-use h1::crypto::dcrypto;
 #[allow(unused_imports)]
 use h1::crypto::dcrypto::{Dcrypto, DcryptoClient, DcryptoEngine};
 
-pub unsafe fn run_dcrypto() {
-    let r = static_init_test_dcrypto();
-    dcrypto::DCRYPTO.set_client(r);
+pub unsafe fn run_dcrypto(engine: &'static DcryptoEngine<'static>) {
+    let r = static_init_test_dcrypto(engine);
+    engine.set_client(r);
     r.run();
 }
 
-unsafe fn static_init_test_dcrypto() -> &'static mut TestDcrypto<'static> {
+unsafe fn static_init_test_dcrypto(engine: &'static DcryptoEngine<'static>) -> &'static mut TestDcrypto<'static> {
     static_init!(
         TestDcrypto<'static>,
-        TestDcrypto::new(&dcrypto::DCRYPTO)
+        TestDcrypto::new(engine)
     )
 }
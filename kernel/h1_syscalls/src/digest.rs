@@ -13,17 +13,37 @@
 // limitations under the License.
 
 use core::cell::Cell;
-use h1::hil::digest::{DigestEngine, DigestError, DigestMode};
+use crate::error::{ErrorCode, IntoReturnCode};
+use crate::app_slice::AppSliceExt;
+use h1::hil::digest::{DigestEngine, DigestError, DigestMode, DigestOwner};
 use kernel::{AppId, AppSlice, Driver, Grant, ReturnCode, Shared};
 
 pub const DRIVER_NUM: usize = 0x40003;
 
+/// State of a hash that an application has initialized but not finalized.
+///
+/// The engine streams the message through its FIFO and cannot save the
+/// intermediate state, so the application that owns the session keeps the
+/// engine until it finalizes or goes away.
+#[derive(Clone, Copy)]
+struct Session {
+    /// Number of message bytes fed into the engine so far.
+    hashed_len: usize,
+    /// Whether this is an HMAC, whose key must be cleared from the engine
+    /// when it is finalized.
+    keyed: bool,
+}
+
 /// Per-application driver data.
 pub struct App {
     /// Buffer where data to be hashed will be read from.
     input_buffer: Option<AppSlice<Shared, u8>>,
     /// Buffer where the digest will be written to when hashing is finished.
     output_buffer: Option<AppSlice<Shared, u8>>,
+    /// Buffer holding the HMAC key.
+    key_buffer: Option<AppSlice<Shared, u8>>,
+    /// The hash in progress, if any.
+    session: Option<Session>,
 }
 
 impl Default for App {
@@ -31,6 +51,8 @@ impl Default for App {
         App {
             input_buffer: None,
             output_buffer: None,
+            key_buffer: None,
+            session: None,
         }
     }
 }
@@ -49,6 +71,56 @@ impl<'a, E: DigestEngine + 'a> DigestDriver<'a, E> {
             current_user: Cell::new(None),
         }
     }
+
+    /// Returns true if `caller_id` may start a new hash. An application that
+    /// restarted lost its grant and with it its session, so the engine is
+    /// only busy while its owner's session is live.
+    fn engine_available(&self, caller_id: AppId) -> bool {
+        if let Some(owner) = self.current_user.get() {
+            let owner_active = owner != caller_id && self.apps.enter(owner, |app_data, _| {
+                app_data.session.is_some()
+            }).unwrap_or(false);
+            if owner_active {
+                return false;
+            }
+            self.current_user.set(None);
+        }
+        true
+    }
+
+    /// Runs `f` on the caller's grant if it owns the engine.
+    fn with_session<F>(&self, caller_id: AppId, f: F) -> ReturnCode
+        where F: FnOnce(&mut App) -> ReturnCode
+    {
+        self.apps.enter(caller_id, |app_data, _| {
+            match self.current_user.get() {
+                Some(cur) if cur == caller_id && app_data.session.is_some() => f(app_data),
+                _ => ErrorCode::Busy.rcode(),
+            }
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    /// Finalizes the caller's hash into its output buffer and ends its
+    /// session.
+    fn finish(&self, app_data: &mut App) -> ReturnCode {
+        let keyed = app_data.session.map_or(false, |session| session.keyed);
+        self.current_user.set(None);
+        app_data.session = None;
+
+        let rval = match app_data.output_buffer {
+            Some(ref mut slice) if keyed => self.engine.finalize_hmac(slice.as_mut()),
+            Some(ref mut slice) => self.engine.finalize(slice.as_mut()),
+            None => self.engine.finalize_hidden()
+        };
+
+        match rval {
+            Ok(_t) => ReturnCode::SUCCESS,
+            Err(DigestError::EngineNotSupported) => ErrorCode::NoSupport.rcode(),
+            Err(DigestError::NotConfigured) => ErrorCode::Off.rcode(),
+            Err(DigestError::BufferTooSmall(_s)) => ErrorCode::Size.rcode(),
+            Err(DigestError::Timeout) => ErrorCode::Fail.rcode(),
+        }
+    }
 }
 
 const COMMAND_CHECK: usize            = 0;
@@ -57,25 +129,36 @@ const COMMAND_UPDATE: usize           = 2;
 const COMMAND_FINALIZE: usize         = 3;
 const COMMAND_BUSY: usize             = 4;
 const COMMAND_CERTIFICATE_INIT: usize = 5;
+const COMMAND_HASHED_LENGTH: usize    = 6;
+const COMMAND_HMAC_INITIALIZE: usize  = 7;
+const COMMAND_HMAC_FINALIZE: usize    = 8;
+
+impl<'a, E: DigestEngine> DigestOwner for DigestDriver<'a, E> {
+    /// Kernel users of the engine must wait while an app has a hash in
+    /// progress, since the engine cannot save and restore it.
+    fn has_session(&self) -> bool {
+        self.current_user.get().map_or(false, |owner| {
+            self.apps.enter(owner, |app_data, _| app_data.session.is_some()).unwrap_or(false)
+        })
+    }
+}
 
 impl<'a, E: DigestEngine> Driver for DigestDriver<'a, E> {
-    fn command(&self, minor_num: usize, r2: usize, _r3: usize, caller_id: AppId) -> ReturnCode {
+    fn command(&self, minor_num: usize, r2: usize, r3: usize, caller_id: AppId) -> ReturnCode {
         match minor_num {
             COMMAND_CHECK => ReturnCode::SUCCESS,
             // Initialize hash engine (arg: digest mode)
             COMMAND_INITIALIZE => {
+                if !self.engine_available(caller_id) {
+                    return ErrorCode::Busy.rcode();
+                }
                 self.apps
                     .enter(caller_id, |app_data, _| {
-                        if self.current_user.get().is_some() {
-                            return ReturnCode::EBUSY;
-                        }
-                        self.current_user.set(Some(caller_id));
-
                         let digest_mode = match r2 {
                             0 => DigestMode::Sha1,
                             1 => DigestMode::Sha256,
                             2 => DigestMode::Sha256Hmac,
-                            _ => return ReturnCode::EINVAL,
+                            _ => return ErrorCode::Invalid.rcode(),
                         };
                         let init_result = match digest_mode {
                             DigestMode::Sha1 | DigestMode::Sha256 =>
@@ -83,110 +166,131 @@ impl<'a, E: DigestEngine> Driver for DigestDriver<'a, E> {
                             DigestMode::Sha256Hmac => {
                                 let input_buffer = match app_data.input_buffer {
                                     Some(ref slice) => slice,
-                                    None => return ReturnCode::ENOMEM
+                                    None => return ErrorCode::Size.rcode()
                                 };
                                 self.engine.initialize_hmac(&input_buffer.as_ref())
                             }
                         };
                         match init_result {
-                            Ok(_t) => return ReturnCode::SUCCESS,
-                            Err(DigestError::EngineNotSupported) => return ReturnCode::ENOSUPPORT,
-                            Err(DigestError::NotConfigured) => return ReturnCode::FAIL,
-                            Err(DigestError::BufferTooSmall(_s)) => return ReturnCode::ESIZE,
-                            Err(DigestError::Timeout) => return ReturnCode::FAIL,
+                            Ok(_t) => {
+                                self.current_user.set(Some(caller_id));
+                                app_data.session = Some(Session {
+                                    hashed_len: 0,
+                                    keyed: digest_mode == DigestMode::Sha256Hmac,
+                                });
+                                return ReturnCode::SUCCESS;
+                            }
+                            Err(DigestError::EngineNotSupported) => return ErrorCode::NoSupport.rcode(),
+                            Err(DigestError::NotConfigured) => return ErrorCode::Off.rcode(),
+                            Err(DigestError::BufferTooSmall(_s)) => return ErrorCode::Size.rcode(),
+                            Err(DigestError::Timeout) => return ErrorCode::Fail.rcode(),
                         }
-                    }).unwrap_or(ReturnCode::ENOMEM)
+                    }).unwrap_or(ErrorCode::NoMem.rcode())
             },
-            // Feed data from input buffer (arg: number of bytes)
+            // Feed data from input buffer (args: number of bytes, offset). The
+            // app may allow a different buffer between updates.
             COMMAND_UPDATE => {
-                self.apps
-                    .enter(caller_id, |app_data, _| {
-                        match self.current_user.get() {
-                                Some(cur) if cur == caller_id => {}
-                            _ => {
-                                return ReturnCode::EBUSY
-                            }
-                        }
-                        let app_data: &mut App = app_data;
+                self.with_session(caller_id, |app_data| {
+                    let input_buffer = match app_data.input_buffer {
+                        Some(ref slice) => slice,
+                        None => return ErrorCode::Size.rcode()
+                    };
+                    let input = match input_buffer.get_range(r3, r2) {
+                        Ok(input) => input,
+                        Err(rcode) => return rcode
+                    };
 
-                        let input_buffer = match app_data.input_buffer {
-                            Some(ref slice) => slice,
-                            None => return ReturnCode::ENOMEM
-                        };
-                        let input_len = r2;
-                        if input_len > input_buffer.len() {
-                            return ReturnCode::ESIZE
-                        }
-
-                        match self.engine.update(&input_buffer.as_ref()[..input_len]) {
-                            Ok(_t) => ReturnCode::SUCCESS,
-                            Err(DigestError::EngineNotSupported) => ReturnCode::ENOSUPPORT,
-                            Err(DigestError::NotConfigured) => ReturnCode::ERESERVE,
-                            Err(DigestError::BufferTooSmall(_s)) => ReturnCode::ESIZE,
-                            Err(DigestError::Timeout) => ReturnCode::FAIL
+                    match self.engine.update(input) {
+                        Ok(consumed) => {
+                            if let Some(ref mut session) = app_data.session {
+                                session.hashed_len += consumed;
+                            }
+                            ReturnCode::SUCCESS
                         }
-                    })
-                    .unwrap_or(ReturnCode::ENOMEM)
+                        Err(DigestError::EngineNotSupported) => ErrorCode::NoSupport.rcode(),
+                        Err(DigestError::NotConfigured) => ErrorCode::Off.rcode(),
+                        Err(DigestError::BufferTooSmall(_s)) => ErrorCode::Size.rcode(),
+                        Err(DigestError::Timeout) => ErrorCode::Fail.rcode()
+                    }
+                })
             },
             // Finalize hash and output to output buffer (arg: unused)
             COMMAND_FINALIZE => {
-                self.apps
-                    .enter(caller_id, |app_data, _| {
-                        match self.current_user.get() {
-                            Some(cur) if cur == caller_id => {}
-                            _ => {
-                                return ReturnCode::EBUSY
-                            }
-                        }
-                        self.current_user.set(None);
-                        let app_data: &mut App = app_data;
-
-                        let rval = match app_data.output_buffer {
-                            Some(ref mut slice) => self.engine.finalize(slice.as_mut()),
-                            None => self.engine.finalize_hidden()
-                        };
-
-                        match rval {
-                            Ok(_t) => ReturnCode::SUCCESS,
-                            Err(DigestError::EngineNotSupported) => ReturnCode::ENOSUPPORT,
-                            Err(DigestError::NotConfigured) => ReturnCode::FAIL,
-                            Err(DigestError::BufferTooSmall(_s)) => ReturnCode::ESIZE,
-                            Err(DigestError::Timeout) => ReturnCode::FAIL,
-                        }
-
-                    })
-                    .unwrap_or(ReturnCode::ENOMEM)
+                self.with_session(caller_id, |app_data| self.finish(app_data))
             },
             COMMAND_BUSY => {
-                if self.current_user.get().is_some() {
-                    ReturnCode::EBUSY
-                } else {
+                if self.engine_available(caller_id) {
                     ReturnCode::SUCCESS
+                } else {
+                    ErrorCode::Busy.rcode()
                 }
             }
             COMMAND_CERTIFICATE_INIT => { // Cert initialize
+                if !self.engine_available(caller_id) {
+                    return ErrorCode::Busy.rcode();
+                }
                 let rval = self.apps
                     .enter(caller_id, |app_data, _| {
-                        if self.current_user.get().is_some() {
-                            return ReturnCode::EBUSY;
-                        }
-                        self.current_user.set(Some(caller_id));
                         let init_result = self.engine.initialize_certificate(r2 as u32);
                         let err = match init_result {
                             Ok(_t) => ReturnCode::SUCCESS,
-                            Err(DigestError::EngineNotSupported) => return ReturnCode::ENOSUPPORT,
-                            Err(DigestError::NotConfigured) => return ReturnCode::FAIL,
-                            Err(DigestError::BufferTooSmall(_s)) => return ReturnCode::ESIZE,
-                            Err(DigestError::Timeout) => return ReturnCode::FAIL,
+                            Err(DigestError::EngineNotSupported) => return ErrorCode::NoSupport.rcode(),
+                            Err(DigestError::NotConfigured) => return ErrorCode::Off.rcode(),
+                            Err(DigestError::BufferTooSmall(_s)) => return ErrorCode::Size.rcode(),
+                            Err(DigestError::Timeout) => return ErrorCode::Fail.rcode(),
                         };
-                        if app_data.input_buffer.is_none() {
-                            self.current_user.set(None);
+                        if app_data.input_buffer.is_some() {
+                            self.current_user.set(Some(caller_id));
+                            app_data.session = Some(Session { hashed_len: 0, keyed: false });
                         }
                         err
-                    }).unwrap_or(ReturnCode::ENOMEM);
+                    }).unwrap_or(ErrorCode::NoMem.rcode());
                 rval
             },
-            _ => ReturnCode::ENOSUPPORT
+            // Number of bytes fed into the caller's hash so far (arg: unused)
+            COMMAND_HASHED_LENGTH => {
+                self.with_session(caller_id, |app_data| {
+                    let hashed_len = app_data.session.map_or(0, |session| session.hashed_len);
+                    ReturnCode::SuccessWithValue { value: hashed_len }
+                })
+            },
+            // Initialize an HMAC-SHA256 with the key in the key buffer (arg: unused)
+            COMMAND_HMAC_INITIALIZE => {
+                if !self.engine_available(caller_id) {
+                    return ErrorCode::Busy.rcode();
+                }
+                self.apps
+                    .enter(caller_id, |app_data, _| {
+                        let init_result = match app_data.key_buffer {
+                            Some(ref key) => self.engine.initialize_hmac(key.as_ref()),
+                            None => return ErrorCode::Size.rcode(),
+                        };
+                        match init_result {
+                            Ok(_t) => {
+                                self.current_user.set(Some(caller_id));
+                                app_data.session = Some(Session { hashed_len: 0, keyed: true });
+                                ReturnCode::SUCCESS
+                            }
+                            Err(DigestError::EngineNotSupported) => ErrorCode::NoSupport.rcode(),
+                            Err(DigestError::NotConfigured) => ErrorCode::Off.rcode(),
+                            Err(DigestError::BufferTooSmall(_s)) => ErrorCode::Size.rcode(),
+                            Err(DigestError::Timeout) => ErrorCode::Fail.rcode(),
+                        }
+                    }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            // Finalize an HMAC into the output buffer (arg: unused)
+            COMMAND_HMAC_FINALIZE => {
+                self.with_session(caller_id, |app_data| {
+                    if !app_data.session.map_or(false, |session| session.keyed) {
+                        return ErrorCode::Invalid.rcode();
+                    }
+                    if app_data.output_buffer.is_none() {
+                        return ErrorCode::Size.rcode();
+                    }
+                    self.finish(app_data)
+                })
+            },
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -203,7 +307,7 @@ impl<'a, E: DigestEngine> Driver for DigestDriver<'a, E> {
                             app_data.input_buffer = slice;
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::ENOMEM)
+                        .unwrap_or(ErrorCode::NoMem.rcode())
                 }
                 1 => {
                     // Hash output buffer
@@ -212,9 +316,18 @@ impl<'a, E: DigestEngine> Driver for DigestDriver<'a, E> {
                             app_data.output_buffer = slice;
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::ENOMEM)
+                        .unwrap_or(ErrorCode::NoMem.rcode())
+                }
+                2 => {
+                    // HMAC key buffer
+                    self.apps
+                        .enter(app_id, |app_data, _| {
+                            app_data.key_buffer = slice;
+                            ReturnCode::SUCCESS
+                        })
+                        .unwrap_or(ErrorCode::NoMem.rcode())
                 }
-                _ => ReturnCode::ENOSUPPORT,
+                _ => ErrorCode::NoSupport.rcode(),
             }
     }
 }
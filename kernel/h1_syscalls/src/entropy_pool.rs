@@ -0,0 +1,81 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for querying the state of the entropy pool that backs the
+//! rng driver.
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::hil::entropy_pool::EntropyPool;
+use kernel::{AppId, AppSlice, Callback, Driver, ReturnCode, Shared};
+
+pub const DRIVER_NUM: usize = 0x40090;
+
+/// Status flags returned by command 1.
+pub const STATUS_SEEDED: usize = 1 << 0;
+pub const STATUS_DEGRADED: usize = 1 << 1;
+
+pub struct EntropyPoolSyscall<'a> {
+    pool: &'a dyn EntropyPool,
+}
+
+impl<'a> EntropyPoolSyscall<'a> {
+    pub fn new(pool: &'a dyn EntropyPool) -> EntropyPoolSyscall<'a> {
+        EntropyPoolSyscall {
+            pool: pool,
+        }
+    }
+}
+
+impl<'a> Driver for EntropyPoolSyscall<'a> {
+    fn subscribe(&self,
+                 subscribe_num: usize,
+                 _callback: Option<Callback>,
+                 _app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn command(&self, command_num: usize, _arg1: usize, _arg2: usize, _caller_id: AppId)
+        -> ReturnCode {
+        let status = self.pool.get_status();
+        match command_num {
+            0 /* Check if present */ => ReturnCode::SUCCESS,
+            1 /* Get status flags (STATUS_SEEDED | STATUS_DEGRADED) */ => {
+                let mut flags = 0;
+                if status.is_seeded { flags |= STATUS_SEEDED; }
+                if status.is_degraded { flags |= STATUS_DEGRADED; }
+                ReturnCode::SuccessWithValue { value: flags }
+            },
+            2 /* Get number of reseeds */ => {
+                ReturnCode::SuccessWithValue { value: status.reseed_count as usize }
+            },
+            3 /* Get number of words generated since the last reseed */ => {
+                ReturnCode::SuccessWithValue { value: status.words_since_reseed as usize }
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn allow(&self,
+             _app_id: AppId,
+             _minor_num: usize,
+             _slice: Option<AppSlice<Shared, u8>>
+    ) -> ReturnCode {
+        ErrorCode::NoSupport.rcode()
+    }
+}
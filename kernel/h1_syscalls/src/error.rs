@@ -0,0 +1,50 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Error codes returned by the drivers in this crate.
+//!
+//! Drivers report errors as `errorcode::ErrorCode`s, which document what
+//! each value means to apps, and convert them to the `ReturnCode` the kernel
+//! passes on with `rcode()`. Codes passed through from the h1 drivers are
+//! returned as they are.
+
+pub use errorcode::ErrorCode;
+use kernel::ReturnCode;
+
+/// Conversion of an error to the `ReturnCode` returned to apps.
+pub trait IntoReturnCode {
+    fn rcode(self) -> ReturnCode;
+}
+
+impl IntoReturnCode for ErrorCode {
+    fn rcode(self) -> ReturnCode {
+        match self {
+            ErrorCode::Fail => ReturnCode::FAIL,
+            ErrorCode::Busy => ReturnCode::EBUSY,
+            ErrorCode::Already => ReturnCode::EALREADY,
+            ErrorCode::Off => ReturnCode::EOFF,
+            ErrorCode::Reserve => ReturnCode::ERESERVE,
+            ErrorCode::Invalid => ReturnCode::EINVAL,
+            ErrorCode::Size => ReturnCode::ESIZE,
+            ErrorCode::Cancel => ReturnCode::ECANCEL,
+            ErrorCode::NoMem => ReturnCode::ENOMEM,
+            ErrorCode::NoSupport => ReturnCode::ENOSUPPORT,
+            ErrorCode::NoDevice => ReturnCode::ENODEVICE,
+            ErrorCode::Uninstalled => ReturnCode::EUNINSTALLED,
+            ErrorCode::NoAck => ReturnCode::ENOACK,
+        }
+    }
+}
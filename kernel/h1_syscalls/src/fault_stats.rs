@@ -0,0 +1,87 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Debug syscall driver for the CPU exception statistics.
+//!
+//! Fault types are the values of h1::fault_stats::FaultType:
+//!   0: HardFault, 1: MemManage, 2: BusFault, 3: UsageFault.
+//!
+//! The driver implements 3 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. get the number of faults of type arg1 since boot
+//!   2. get field arg2 of the most recent fault of type arg1; fails with
+//!      ErrorCode::Fail if no fault of that type was recorded. Fields:
+//!        0: PC, 1: LR, 2: CFSR, 3: HFSR, 4: MMFAR, 5: BFAR,
+//!        6: 1 if the fault was taken in an app, 0 if in the kernel.
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::fault_stats::{self, FaultFrame, FaultType};
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x400d0;
+
+const COMMAND_CHECK: usize      = 0;
+const COMMAND_GET_COUNT: usize  = 1;
+const COMMAND_GET_LAST: usize   = 2;
+
+fn frame_field(frame: &FaultFrame, field: usize) -> Option<u32> {
+    match field {
+        0 => Some(frame.pc),
+        1 => Some(frame.lr),
+        2 => Some(frame.cfsr),
+        3 => Some(frame.hfsr),
+        4 => Some(frame.mmfar),
+        5 => Some(frame.bfar),
+        6 => Some(frame.in_app as u32),
+        _ => None,
+    }
+}
+
+pub struct FaultStatsSyscall;
+
+impl FaultStatsSyscall {
+    pub const fn new() -> FaultStatsSyscall {
+        FaultStatsSyscall
+    }
+}
+
+impl Driver for FaultStatsSyscall {
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, _app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_GET_COUNT => match FaultType::from_usize(arg1) {
+                Some(fault_type) => ReturnCode::SuccessWithValue {
+                    value: fault_stats::get(fault_type).count as usize
+                },
+                None => ErrorCode::Invalid.rcode(),
+            },
+            COMMAND_GET_LAST => {
+                let fault_type = match FaultType::from_usize(arg1) {
+                    Some(fault_type) => fault_type,
+                    None => return ErrorCode::Invalid.rcode(),
+                };
+                match fault_stats::get(fault_type).last {
+                    Some(frame) => match frame_field(&frame, arg2) {
+                        Some(value) => ReturnCode::SuccessWithValue { value: value as usize },
+                        None => ErrorCode::Invalid.rcode(),
+                    },
+                    None => ErrorCode::Fail.rcode(),
+                }
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
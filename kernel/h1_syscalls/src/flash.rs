@@ -16,6 +16,7 @@
 
 use core::cell::Cell;
 use core::cmp::min;
+use crate::error::{ErrorCode, IntoReturnCode};
 
 use h1::hil::flash::Client;
 use h1::hil::flash::Flash;
@@ -62,13 +63,13 @@ impl<'a> FlashSyscalls<'a> {
         self.apps.enter(caller_id, |_app_data, _| {
             let return_code = self.device.erase(page);
             return_code
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 
     fn read(&self, caller_id: AppId, offset: usize, read_len: usize) -> ReturnCode {
         // We can only start at words boundaries.
         if offset % BYTES_PER_WORD != 0 {
-            return ReturnCode::EINVAL;
+            return ErrorCode::Invalid.rcode();
         }
 
         self.apps.enter(caller_id, |app_data, _| {
@@ -88,7 +89,7 @@ impl<'a> FlashSyscalls<'a> {
                             // A read should result in a SuccessWithValue or a failure.
                             // If we get plain SUCCESS, something is seriously wrong.
                             // So let the caller know
-                            return ReturnCode::FAIL
+                            return ErrorCode::Fail.rcode()
                         }
                         failure => {
                             // Everything else must be some kind of failure
@@ -99,18 +100,19 @@ impl<'a> FlashSyscalls<'a> {
                 return ReturnCode::SUCCESS
             }
 
-            ReturnCode::ENOMEM
-        }).unwrap_or(ReturnCode::ENOMEM)
+            ErrorCode::Size.rcode()
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 
     fn write(&self, caller_id: AppId, target: usize, write_len: usize) -> ReturnCode {
         // We cannot write partial words.
         if target % BYTES_PER_WORD != 0 || write_len % BYTES_PER_WORD != 0 {
-            return ReturnCode::EINVAL;
+            return ErrorCode::Invalid.rcode();
         }
 
         self.apps.enter(caller_id, |app_data, _| {
             if let Some(ref app_write_buffer) = app_data.write_buffer {
+                // The kernel buffer is away while another write is running.
                 if let Some(buffer) = self.write_buffer.take() {
                     // Figure minimum of static write_buffer, app's write_buffer and write_length
                     let words = min(buffer.len(), min(app_write_buffer.len(), write_len) / BYTES_PER_WORD);
@@ -129,10 +131,11 @@ impl<'a> FlashSyscalls<'a> {
                     self.write_buffer.set(buffer);
                     return return_code
                 }
+                return ErrorCode::Busy.rcode();
             }
 
-            ReturnCode::ENOMEM
-        }).unwrap_or(ReturnCode::ENOMEM)
+            ErrorCode::Size.rcode()
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 }
 
@@ -170,9 +173,9 @@ impl<'a> Driver for FlashSyscalls<'a> {
                 self.apps.enter(app_id, |app_data, _| {
                     app_data.operation_done_callback = callback;
                     ReturnCode::SUCCESS
-                }).unwrap_or(ReturnCode::ENOMEM)
+                }).unwrap_or(ErrorCode::NoMem.rcode())
             },
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -196,7 +199,7 @@ impl<'a> Driver for FlashSyscalls<'a> {
                  arg2: number of bytes to read */ => {
                 self.read(caller_id, arg1, arg2)
             },
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -213,7 +216,7 @@ impl<'a> Driver for FlashSyscalls<'a> {
                             app_data.write_buffer = slice;
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::FAIL)
+                        .unwrap_or(ErrorCode::Fail.rcode())
                 }
                 1 => {
                     // Read Buffer
@@ -222,9 +225,9 @@ impl<'a> Driver for FlashSyscalls<'a> {
                             app_data.read_buffer = slice;
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::FAIL)
+                        .unwrap_or(ErrorCode::Fail.rcode())
                 }
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 }
@@ -15,6 +15,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
 use h1::hil::fuse::Fuse;
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode, Shared, AppSlice};
 
@@ -47,13 +48,13 @@ impl<'a> FuseSyscall<'a> {
                 let dev_id = self.fuse.get_dev_id();
                 for (idx, &byte) in dev_id.to_be_bytes().iter().enumerate() {
                     match dev_id_buffer.as_mut().get_mut(idx) {
-                        None => return ReturnCode::ENOMEM,
+                        None => return ErrorCode::Size.rcode(),
                         Some(value) => *value = byte,
                     }
                 }
             }
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 }
 
@@ -64,7 +65,7 @@ impl<'a> Driver for FuseSyscall<'a> {
                  _app_id: AppId,
     ) -> ReturnCode {
         match subscribe_num {
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -78,7 +79,7 @@ impl<'a> Driver for FuseSyscall<'a> {
             1 /* Get Dev ID and write to Dev ID buffer in BE notation. */ => {
                 self.get_dev_id(caller_id)
             },
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -99,9 +100,9 @@ impl<'a> Driver for FuseSyscall<'a> {
                         }
                         ReturnCode::SUCCESS
                     })
-                    .unwrap_or(ReturnCode::FAIL)
+                    .unwrap_or(ErrorCode::Fail.rcode())
             }
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 }
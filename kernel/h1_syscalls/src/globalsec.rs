@@ -15,6 +15,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
 use h1::hil::globalsec::GlobalSec;
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode, Shared, AppSlice};
 use spiutils::io::Cursor;
@@ -48,11 +49,11 @@ impl<'a> GlobalSecSyscall<'a> {
             if let Some(ref mut buffer) = app_data.buffer {
                 let cursor = Cursor::new(buffer.as_mut());
                 if self.globalsec.get_runtime_segment_info().to_wire(cursor).is_err() {
-                    return ReturnCode::ENOMEM;
+                    return ErrorCode::Size.rcode();
                 }
             }
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 }
 
@@ -63,7 +64,7 @@ impl<'a> Driver for GlobalSecSyscall<'a> {
                  _app_id: AppId,
     ) -> ReturnCode {
         match subscribe_num {
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -75,7 +76,7 @@ impl<'a> Driver for GlobalSecSyscall<'a> {
         match command_num {
             0 /* Check if present */ => ReturnCode::SUCCESS,
             1 /* Get runtime segment info */ => self.get_runtime_segment_info(caller_id),
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -92,9 +93,9 @@ impl<'a> Driver for GlobalSecSyscall<'a> {
                         app_data.buffer = slice;
                         ReturnCode::SUCCESS
                     })
-                    .unwrap_or(ReturnCode::FAIL)
+                    .unwrap_or(ErrorCode::Fail.rcode())
             }
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 }
@@ -0,0 +1,154 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for HKDF-SHA256 key derivation.
+//!
+//! The input key material either comes from the app or from a kernel
+//! `KeySource`, in which case it never enters app memory. Boards without a
+//! key source only support the former.
+//!
+//! The driver implements 2 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. derive arg2 bytes into the output buffer. arg1 selects the input
+//!      key material: 0 for the IKM buffer, 1 for the kernel key source
+//!      (ENOSUPPORT if the board has none).
+//!
+//! The driver implements 4 allows:
+//!   0. salt (optional, empty if not allowed)
+//!   1. input key material, required when arg1 is 0
+//!   2. info (optional, empty if not allowed)
+//!   3. output buffer, at least arg2 bytes
+//!
+//! Derivation is synchronous, so the driver has no subscribes. It fails
+//! with EBUSY while an app has a digest in progress on the SHA engine.
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use crate::app_slice::AppSliceExt;
+use h1::hil::hkdf::{Hkdf, KeySource, SECRET_LEN};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+pub const DRIVER_NUM: usize = 0x400b0;
+
+const COMMAND_CHECK: usize  = 0;
+const COMMAND_DERIVE: usize = 1;
+const ALLOW_SALT: usize     = 0;
+const ALLOW_IKM: usize      = 1;
+const ALLOW_INFO: usize     = 2;
+const ALLOW_OUTPUT: usize   = 3;
+
+const IKM_SOURCE_APP: usize    = 0;
+const IKM_SOURCE_KERNEL: usize = 1;
+
+#[derive(Default)]
+pub struct AppData {
+    salt: Option<AppSlice<Shared, u8>>,
+    ikm: Option<AppSlice<Shared, u8>>,
+    info: Option<AppSlice<Shared, u8>>,
+    output: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct HkdfSyscall<'a> {
+    hkdf: &'a dyn Hkdf,
+    key_source: Option<&'a dyn KeySource>,
+    apps: Grant<AppData>,
+}
+
+fn slice_or_empty(slice: &Option<AppSlice<Shared, u8>>) -> &[u8] {
+    slice.as_ref().map_or(&[], |slice| slice.as_ref())
+}
+
+impl<'a> HkdfSyscall<'a> {
+    pub fn new(hkdf: &'a dyn Hkdf,
+               key_source: Option<&'a dyn KeySource>,
+               container: Grant<AppData>) -> HkdfSyscall<'a> {
+        HkdfSyscall {
+            hkdf: hkdf,
+            key_source: key_source,
+            apps: container,
+        }
+    }
+
+    fn derive(&self, app_id: AppId, ikm_source: usize, output_len: usize) -> ReturnCode {
+        self.apps.enter(app_id, |app_data, _| {
+            let salt = slice_or_empty(&app_data.salt);
+            let info = slice_or_empty(&app_data.info);
+            let okm = match app_data.output {
+                Some(ref mut output) => match output.get_range_mut(0, output_len) {
+                    Ok(okm) => okm,
+                    Err(rcode) => return rcode,
+                },
+                None => return ErrorCode::Size.rcode(),
+            };
+            match ikm_source {
+                IKM_SOURCE_APP => match app_data.ikm {
+                    Some(ref ikm) => self.hkdf.derive(salt, ikm.as_ref(), info, okm),
+                    None => ErrorCode::Size.rcode(),
+                },
+                IKM_SOURCE_KERNEL => {
+                    let key_source = match self.key_source {
+                        Some(key_source) => key_source,
+                        None => return ErrorCode::NoSupport.rcode(),
+                    };
+                    let mut secret = [0u8; SECRET_LEN];
+                    let mut rcode = key_source.secret(&mut secret);
+                    if rcode == ReturnCode::SUCCESS {
+                        rcode = self.hkdf.derive(salt, &secret, info, okm);
+                    }
+                    ecc::wipe(&mut secret);
+                    rcode
+                },
+                _ => ErrorCode::Invalid.rcode(),
+            }
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+}
+
+impl<'a> Driver for HkdfSyscall<'a> {
+    fn subscribe(&self,
+                 subscribe_num: usize,
+                 _callback: Option<Callback>,
+                 _app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_DERIVE => self.derive(app_id, arg1, arg2),
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn allow(&self,
+             app_id: AppId,
+             minor_num: usize,
+             slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps.enter(app_id, |app_data, _| {
+            match minor_num {
+                ALLOW_SALT => app_data.salt = slice,
+                ALLOW_IKM => app_data.ikm = slice,
+                ALLOW_INFO => app_data.info = slice,
+                ALLOW_OUTPUT => app_data.output = slice,
+                _ => return ErrorCode::NoSupport.rcode(),
+            }
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+}
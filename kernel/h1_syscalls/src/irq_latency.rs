@@ -0,0 +1,79 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for the SPI device interrupt latency probe, used by the
+//! on-target latency tests.
+//!
+//! The driver implements 4 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. set the SPI device interrupt pending and time how long it takes to
+//!      be serviced. Fails with ErrorCode::Busy while a probe is
+//!      outstanding.
+//!   2. get statistic arg1 since the last reset:
+//!        0: number of probes serviced, 1: maximum latency in nanoseconds,
+//!        2: mean latency in nanoseconds.
+//!   3. reset the statistics
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::irq_latency::LatencyProbe;
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x400e0;
+
+const COMMAND_CHECK: usize      = 0;
+const COMMAND_PROBE: usize      = 1;
+const COMMAND_GET_STAT: usize   = 2;
+const COMMAND_RESET: usize      = 3;
+
+const STAT_SAMPLES: usize   = 0;
+const STAT_MAX_NS: usize    = 1;
+const STAT_MEAN_NS: usize   = 2;
+
+pub struct IrqLatencySyscall<'a> {
+    probe: &'a LatencyProbe,
+}
+
+impl<'a> IrqLatencySyscall<'a> {
+    pub fn new(probe: &'a LatencyProbe) -> IrqLatencySyscall<'a> {
+        IrqLatencySyscall {
+            probe: probe,
+        }
+    }
+}
+
+impl<'a> Driver for IrqLatencySyscall<'a> {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize, _app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_PROBE => self.probe.pend(),
+            COMMAND_GET_STAT => {
+                let stats = self.probe.stats();
+                let value = match arg1 {
+                    STAT_SAMPLES => stats.samples,
+                    STAT_MAX_NS => stats.max_ns,
+                    STAT_MEAN_NS => stats.mean_ns,
+                    _ => return ErrorCode::Invalid.rcode(),
+                };
+                ReturnCode::SuccessWithValue { value: value as usize }
+            },
+            COMMAND_RESET => {
+                self.probe.reset();
+                ReturnCode::SUCCESS
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
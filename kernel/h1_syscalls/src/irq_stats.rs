@@ -0,0 +1,88 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Debug syscall driver for the interrupt load statistics.
+//!
+//! The driver implements 7 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. get the number of interrupts on NVIC line arg1 since boot
+//!   2. get the number of interrupts on NVIC line arg1 in the last window
+//!   3. get the window length, in milliseconds
+//!   4. get the storm threshold, in interrupts per window
+//!   5. set the storm threshold to arg1; 0 disables storm detection
+//!   6. get the first NVIC line at or after arg1 that has had an interrupt,
+//!      or h1::irq_stats::NUM_IRQS if there is none
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::irq_stats::{IrqStats, NUM_IRQS};
+use kernel::hil::time::Alarm;
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x40130;
+
+const COMMAND_CHECK: usize                  = 0;
+const COMMAND_GET_TOTAL: usize              = 1;
+const COMMAND_GET_LAST_WINDOW: usize        = 2;
+const COMMAND_GET_WINDOW_MS: usize          = 3;
+const COMMAND_GET_STORM_THRESHOLD: usize    = 4;
+const COMMAND_SET_STORM_THRESHOLD: usize    = 5;
+const COMMAND_NEXT_ACTIVE: usize            = 6;
+
+pub struct IrqStatsSyscall<'a, A: Alarm<'a>> {
+    stats: &'a IrqStats<'a, A>,
+}
+
+impl<'a, A: Alarm<'a>> IrqStatsSyscall<'a, A> {
+    pub fn new(stats: &'a IrqStats<'a, A>) -> IrqStatsSyscall<'a, A> {
+        IrqStatsSyscall {
+            stats: stats,
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> Driver for IrqStatsSyscall<'a, A> {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize, _app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_GET_TOTAL => match self.stats.total(arg1) {
+                Some(value) => ReturnCode::SuccessWithValue { value: value as usize },
+                None => ErrorCode::Invalid.rcode(),
+            },
+            COMMAND_GET_LAST_WINDOW => match self.stats.last_window(arg1) {
+                Some(value) => ReturnCode::SuccessWithValue { value: value as usize },
+                None => ErrorCode::Invalid.rcode(),
+            },
+            COMMAND_GET_WINDOW_MS => ReturnCode::SuccessWithValue {
+                value: self.stats.window_ms() as usize
+            },
+            COMMAND_GET_STORM_THRESHOLD => ReturnCode::SuccessWithValue {
+                value: self.stats.storm_threshold() as usize
+            },
+            COMMAND_SET_STORM_THRESHOLD => {
+                if arg1 > 0xffff {
+                    return ErrorCode::Invalid.rcode();
+                }
+                self.stats.set_storm_threshold(arg1 as u16);
+                ReturnCode::SUCCESS
+            },
+            COMMAND_NEXT_ACTIVE => ReturnCode::SuccessWithValue {
+                value: self.stats.next_active(arg1).unwrap_or(NUM_IRQS)
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for deriving per-app keys from the KEYMGR key ladder.
+//!
+//! The context passed to the key ladder is the app's package name,
+//! prefixed with its length, followed by the context buffer. Apps therefore
+//! cannot derive each other's keys, and apps without a package name cannot
+//! derive keys at all.
+//!
+//! The driver implements 2 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. derive the key for usage arg1 (see h1::hil::keyladder::KeyUsage)
+//!      into the output buffer
+//!
+//! The driver implements 2 allows:
+//!   0. context (optional, empty if not allowed)
+//!   1. output buffer, at least KEY_LEN bytes
+//!
+//! Derivation is synchronous, so the driver has no subscribes.
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use crate::app_slice::AppSliceExt;
+use h1::crypto::util;
+use h1::hil::keyladder::{KeyLadder, KeyUsage, KEY_LEN};
+use kernel::procs::ProcessType;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+pub const DRIVER_NUM: usize = 0x401A0;
+
+const COMMAND_CHECK: usize  = 0;
+const COMMAND_DERIVE: usize = 1;
+const ALLOW_CONTEXT: usize  = 0;
+const ALLOW_OUTPUT: usize   = 1;
+
+#[derive(Default)]
+pub struct AppData {
+    context: Option<AppSlice<Shared, u8>>,
+    output: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct KeyLadderSyscall<'a> {
+    key_ladder: &'a dyn KeyLadder,
+    processes: &'static [Option<&'static dyn ProcessType>],
+    apps: Grant<AppData>,
+}
+
+impl<'a> KeyLadderSyscall<'a> {
+    pub fn new(key_ladder: &'a dyn KeyLadder,
+               processes: &'static [Option<&'static dyn ProcessType>],
+               container: Grant<AppData>) -> KeyLadderSyscall<'a> {
+        KeyLadderSyscall {
+            key_ladder: key_ladder,
+            processes: processes,
+            apps: container,
+        }
+    }
+
+    fn app_name(&self, app_id: AppId) -> Option<&'static str> {
+        self.processes.iter().flatten()
+            .find(|process| process.appid() == app_id)
+            .map(|process| process.get_process_name())
+            .filter(|name| !name.is_empty() && name.len() <= u8::max_value() as usize)
+    }
+
+    fn derive(&self, app_id: AppId, usage: usize) -> ReturnCode {
+        let usage = match KeyUsage::from_usize(usage) {
+            Some(usage) => usage,
+            None => return ErrorCode::Invalid.rcode(),
+        };
+        let name = match self.app_name(app_id) {
+            Some(name) => name,
+            None => return ErrorCode::Reserve.rcode(),
+        };
+        self.apps.enter(app_id, |app_data, _| {
+            let context = app_data.context.as_ref().map_or(&[][..], |context| context.as_ref());
+            let output = match app_data.output {
+                Some(ref mut output) => match output.get_range_mut(0, KEY_LEN) {
+                    Ok(output) => output,
+                    Err(rcode) => return rcode,
+                },
+                None => return ErrorCode::Size.rcode(),
+            };
+            let mut key = [0u8; KEY_LEN];
+            let rcode = self.key_ladder.derive(
+                usage, &[&[name.len() as u8], name.as_bytes(), context], &mut key);
+            if rcode == ReturnCode::SUCCESS {
+                output.copy_from_slice(&key);
+            }
+            util::zeroize(&mut key);
+            rcode
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+}
+
+impl<'a> Driver for KeyLadderSyscall<'a> {
+    fn subscribe(&self,
+                 subscribe_num: usize,
+                 _callback: Option<Callback>,
+                 _app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize, app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_DERIVE => self.derive(app_id, arg1),
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn allow(&self,
+             app_id: AppId,
+             minor_num: usize,
+             slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps.enter(app_id, |app_data, _| {
+            match minor_num {
+                ALLOW_CONTEXT => app_data.context = slice,
+                ALLOW_OUTPUT => app_data.output = slice,
+                _ => return ErrorCode::NoSupport.rcode(),
+            }
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+}
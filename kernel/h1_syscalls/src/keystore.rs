@@ -0,0 +1,262 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for the key store.
+//!
+//! Apps refer to keys through the opaque handles returned by generate;
+//! private keys are never exposed to apps. Keys are owned by the app's
+//! package name, so an app cannot use another app's handles, and apps
+//! without a package name cannot use the key store at all.
+//!
+//! The driver implements 5 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. generate a key of type arg1 (see h1::hil::keystore::KeyType);
+//!      completion is signaled by a callback carrying the new handle.
+//!   2. copy the public key of handle arg1 (x followed by y, big-endian)
+//!      into the buffer.
+//!   3. sign the 32-byte digest at the start of the buffer with handle
+//!      arg1 and replace it with the signature (r followed by s,
+//!      big-endian). arg2 selects the nonce mode (see
+//!      h1::hil::keystore::NonceMode): 0 for random, 1 for RFC 6979.
+//!      Completion is signaled by a callback.
+//!   4. delete handle arg1; completion is signaled by a callback.
+//!
+//! The driver implements 1 allow:
+//!   0. userspace buffer used by commands 2 and 3, at least 64 bytes.
+//!
+//! The driver implements 1 subscribe:
+//!   0. callback for generate, sign and delete, called with the ReturnCode
+//!      and the new handle (for generate).
+
+use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
+use crate::app_slice::AppSliceExt;
+use h1::hil::keystore::{Client, KeyHandle, KeyStore, KeyType, NonceMode, Signature,
+                        DIGEST_LEN};
+use kernel::procs::ProcessType;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::common::cells::OptionalCell;
+
+pub const DRIVER_NUM: usize = 0x400a0;
+
+const COMMAND_CHECK: usize        = 0;
+const COMMAND_GENERATE: usize     = 1;
+const COMMAND_PUBLIC_KEY: usize   = 2;
+const COMMAND_SIGN: usize         = 3;
+const COMMAND_DELETE: usize       = 4;
+const ALLOW_BUFFER: usize         = 0;
+const SUBSCRIBE_DONE: usize       = 0;
+
+// Length of an encoded public key or signature.
+const COORDINATE_LEN: usize = 32;
+const PAIR_LEN: usize = 2 * COORDINATE_LEN;
+
+#[derive(Default)]
+pub struct AppData {
+    buffer: Option<AppSlice<Shared, u8>>,
+    callback: Option<Callback>,
+}
+
+pub struct KeyStoreSyscall<'a> {
+    keystore: &'a dyn KeyStore<'a>,
+    processes: &'static [Option<&'static dyn ProcessType>],
+    apps: Grant<AppData>,
+    busy: Cell<bool>,
+    current_user: OptionalCell<AppId>,
+}
+
+impl<'a> KeyStoreSyscall<'a> {
+    pub fn new(keystore: &'a dyn KeyStore<'a>,
+               processes: &'static [Option<&'static dyn ProcessType>],
+               container: Grant<AppData>) -> KeyStoreSyscall<'a> {
+        KeyStoreSyscall {
+            keystore: keystore,
+            processes: processes,
+            apps: container,
+            busy: Cell::new(false),
+            current_user: OptionalCell::empty(),
+        }
+    }
+
+    fn app_name(&self, app_id: AppId) -> Option<&'static [u8]> {
+        self.processes.iter().flatten()
+            .find(|process| process.appid() == app_id)
+            .map(|process| process.get_process_name().as_bytes())
+            .filter(|name| !name.is_empty() && name.len() <= u8::max_value() as usize)
+    }
+
+    fn start(&self, app_id: AppId, rcode: ReturnCode) -> ReturnCode {
+        if rcode == ReturnCode::SUCCESS {
+            self.busy.set(true);
+            self.current_user.set(app_id);
+        }
+        rcode
+    }
+
+    fn complete(&self, rcode: ReturnCode, handle: KeyHandle) {
+        self.busy.set(false);
+        self.current_user.take().map(|current_user| {
+            let _ = self.apps.enter(current_user, |app_data, _| {
+                app_data.callback.map(|mut cb| cb.schedule(From::from(rcode), handle as usize, 0));
+            });
+        });
+    }
+
+    fn public_key(&self, app_id: AppId, owner: &[u8], handle: KeyHandle) -> ReturnCode {
+        self.apps.enter(app_id, |app_data, _| {
+            let buffer = match app_data.buffer {
+                Some(ref mut buffer) => buffer,
+                None => return ErrorCode::Size.rcode(),
+            };
+            let output = match buffer.get_range_mut(0, PAIR_LEN) {
+                Ok(output) => output,
+                Err(rcode) => return rcode,
+            };
+            match self.keystore.public_key(handle, owner) {
+                Ok(public_key) => {
+                    output[..COORDINATE_LEN].copy_from_slice(&public_key.x);
+                    output[COORDINATE_LEN..].copy_from_slice(&public_key.y);
+                    ReturnCode::SUCCESS
+                }
+                Err(rcode) => rcode,
+            }
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    fn sign(&self, app_id: AppId, owner: &[u8], handle: KeyHandle, nonce_mode: NonceMode)
+            -> ReturnCode {
+        self.apps.enter(app_id, |app_data, _| {
+            let buffer = match app_data.buffer {
+                Some(ref buffer) => buffer,
+                None => return ErrorCode::Size.rcode(),
+            };
+            // The signature needs room for two coordinates.
+            let input = match buffer.get_prefix(PAIR_LEN) {
+                Ok(input) => input,
+                Err(rcode) => return rcode,
+            };
+            let mut digest = [0u8; DIGEST_LEN];
+            digest.copy_from_slice(&input[..DIGEST_LEN]);
+            self.start(app_id, self.keystore.sign(handle, owner, &digest, nonce_mode))
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+}
+
+impl<'a> Driver for KeyStoreSyscall<'a> {
+    fn subscribe(&self,
+                 subscribe_num: usize,
+                 callback: Option<Callback>,
+                 app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            SUBSCRIBE_DONE => {
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            }
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, app_id: AppId) -> ReturnCode {
+        let handle = arg1 as KeyHandle;
+        if command_num == COMMAND_CHECK {
+            return ReturnCode::SUCCESS;
+        }
+        let owner = match self.app_name(app_id) {
+            Some(name) => name,
+            None => return ErrorCode::Reserve.rcode(),
+        };
+        match command_num {
+            COMMAND_GENERATE => {
+                if self.busy.get() {
+                    return ErrorCode::Busy.rcode();
+                }
+                match KeyType::from_usize(arg1) {
+                    Some(key_type) => self.start(app_id, self.keystore.generate(key_type, owner)),
+                    None => ErrorCode::Invalid.rcode(),
+                }
+            },
+            COMMAND_PUBLIC_KEY => self.public_key(app_id, owner, handle),
+            COMMAND_SIGN => {
+                if self.busy.get() {
+                    return ErrorCode::Busy.rcode();
+                }
+                match NonceMode::from_usize(arg2) {
+                    Some(nonce_mode) => self.sign(app_id, owner, handle, nonce_mode),
+                    None => ErrorCode::Invalid.rcode(),
+                }
+            },
+            COMMAND_DELETE => {
+                if self.busy.get() {
+                    return ErrorCode::Busy.rcode();
+                }
+                self.start(app_id, self.keystore.delete(handle, owner))
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn allow(&self,
+             app_id: AppId,
+             minor_num: usize,
+             slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match minor_num {
+            ALLOW_BUFFER => {
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.buffer = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            _ => ErrorCode::NoSupport.rcode(),
+        }
+    }
+}
+
+impl<'a> Client for KeyStoreSyscall<'a> {
+    fn generate_done(&self, rcode: ReturnCode, handle: KeyHandle) {
+        self.complete(rcode, handle);
+    }
+
+    fn sign_done(&self, rcode: ReturnCode, signature: &Signature) {
+        self.busy.set(false);
+        self.current_user.take().map(|current_user| {
+            let _ = self.apps.enter(current_user, |app_data, _| {
+                let rcode = match (rcode, app_data.buffer.as_mut()) {
+                    (ReturnCode::SUCCESS, Some(buffer)) => {
+                        match buffer.get_range_mut(0, PAIR_LEN) {
+                            Ok(output) => {
+                                output[..COORDINATE_LEN].copy_from_slice(&signature.r);
+                                output[COORDINATE_LEN..].copy_from_slice(&signature.s);
+                                ReturnCode::SUCCESS
+                            }
+                            Err(rcode) => rcode,
+                        }
+                    }
+                    (ReturnCode::SUCCESS, None) => ErrorCode::Size.rcode(),
+                    (rcode, _) => rcode,
+                };
+                app_data.callback.map(|mut cb| cb.schedule(From::from(rcode), 0, 0));
+            });
+        });
+    }
+
+    fn delete_done(&self, rcode: ReturnCode) {
+        self.complete(rcode, 0);
+    }
+}
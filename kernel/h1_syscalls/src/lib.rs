@@ -18,18 +18,42 @@ extern crate h1;
 #[macro_use(static_init, debug)]
 extern crate kernel;
 
+pub mod app_slice;
+pub mod board_config;
+pub mod boot_attempts;
+pub mod console_timestamps;
+pub mod crypto_stats;
 pub mod digest;
+pub mod entropy_pool;
+pub mod error;
+pub mod fault_stats;
 pub mod aes;
 pub mod dcrypto;
 pub mod dcrypto_test;
 pub mod fuse;
 pub mod flash;
 pub mod globalsec;
+pub mod hkdf;
+pub mod irq_latency;
+pub mod irq_stats;
+pub mod keyladder;
+pub mod keystore;
+pub mod lockdown;
+pub mod low_level_debug;
 pub mod nvcounter_syscall;
+pub mod passthrough_guard;
 pub mod personality;
+pub mod rate_limit;
 pub mod reset;
+pub mod rsa;
+pub mod soft_pwm;
 pub mod spi_host;
+pub mod spi_host_queue;
 pub mod spi_device;
+pub mod stack_usage;
+pub mod timebase;
+pub mod trng_health;
+pub mod watchdog;
 
 pub unsafe fn init() {
 }
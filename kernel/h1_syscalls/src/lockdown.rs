@@ -0,0 +1,61 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Syscall driver for the deep lockdown.
+//!
+//! The driver implements 3 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. get the lockdown state: 0 if not locked down, otherwise the
+//!      h1::lockdown::LockdownReason it was engaged for
+//!      (1: commanded, 2: healthy boot)
+//!   2. lock the board down until the next reset. Fails with
+//!      ErrorCode::Already if it already is.
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::lockdown::{Lockdown, LockdownReason};
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x40120;
+
+const COMMAND_CHECK: usize       = 0;
+const COMMAND_GET_STATE: usize   = 1;
+const COMMAND_ENGAGE: usize      = 2;
+
+pub struct LockdownSyscall<'a> {
+    lockdown: &'a Lockdown<'a>,
+}
+
+impl<'a> LockdownSyscall<'a> {
+    pub fn new(lockdown: &'a Lockdown<'a>) -> LockdownSyscall<'a> {
+        LockdownSyscall {
+            lockdown: lockdown,
+        }
+    }
+}
+
+impl<'a> Driver for LockdownSyscall<'a> {
+    fn command(&self, command_num: usize, _arg1: usize, _arg2: usize, _app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_GET_STATE => ReturnCode::SuccessWithValue {
+                value: self.lockdown.reason().map_or(0, |reason| reason as usize)
+            },
+            COMMAND_ENGAGE => self.lockdown.engage(LockdownReason::Commanded),
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
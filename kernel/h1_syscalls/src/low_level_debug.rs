@@ -0,0 +1,85 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Extends the LowLevelDebug driver with extra op codes.
+//!
+//! `LowLevelDebugExt` is registered under the LowLevelDebug driver number
+//! and forwards the standard commands (1: alert code, 2: print 1 number,
+//! 3: print 2 numbers) to the LowLevelDebug capsule. It adds:
+//!   4. print an ASCII tag of up to TAG_LEN bytes, packed little-endian into
+//!      arg1 (bytes 0-3) and arg2 (bytes 4-7). The tag ends at the first NUL
+//!      byte. Fails with ErrorCode::Invalid if it contains a byte that is not
+//!      printable ASCII.
+//!
+//! Tags are printed through the kernel debug writer, so a tag may appear out
+//! of order with numbers printed just before it.
+
+use core::str;
+use crate::error::{ErrorCode, IntoReturnCode};
+use kernel::{AppId, AppSlice, Callback, Driver, ReturnCode, Shared};
+
+/// Same as capsules::low_level_debug::DRIVER_NUM.
+pub const DRIVER_NUM: usize = 0x8;
+
+/// Maximum length of a tag, in bytes.
+pub const TAG_LEN: usize = 8;
+
+const COMMAND_PRINT_TAG: usize = 4;
+
+pub struct LowLevelDebugExt<'a> {
+    low_level_debug: &'a dyn Driver,
+}
+
+impl<'a> LowLevelDebugExt<'a> {
+    pub fn new(low_level_debug: &'a dyn Driver) -> LowLevelDebugExt<'a> {
+        LowLevelDebugExt {
+            low_level_debug: low_level_debug,
+        }
+    }
+
+    fn print_tag(&self, arg1: usize, arg2: usize, app_id: AppId) -> ReturnCode {
+        let mut tag = [0u8; TAG_LEN];
+        tag[..4].copy_from_slice(&(arg1 as u32).to_le_bytes());
+        tag[4..].copy_from_slice(&(arg2 as u32).to_le_bytes());
+        let len = tag.iter().position(|&b| b == 0).unwrap_or(TAG_LEN);
+        if tag[..len].iter().any(|&b| b < 0x20 || b > 0x7e) {
+            return ErrorCode::Invalid.rcode();
+        }
+        // Only printable ASCII remains, so this cannot fail.
+        let tag = str::from_utf8(&tag[..len]).unwrap_or("");
+        debug!("LowLevelDebug: App {:#x} tag {}", app_id.idx(), tag);
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> Driver for LowLevelDebugExt<'a> {
+    fn subscribe(&self, minor_num: usize, callback: Option<Callback>, app_id: AppId)
+        -> ReturnCode {
+        self.low_level_debug.subscribe(minor_num, callback, app_id)
+    }
+
+    fn command(&self, minor_num: usize, r2: usize, r3: usize, caller_id: AppId) -> ReturnCode {
+        match minor_num {
+            COMMAND_PRINT_TAG => self.print_tag(r2, r3, caller_id),
+            _ => self.low_level_debug.command(minor_num, r2, r3, caller_id),
+        }
+    }
+
+    fn allow(&self, app: AppId, minor_num: usize, slice: Option<AppSlice<Shared, u8>>)
+        -> ReturnCode {
+        self.low_level_debug.allow(app, minor_num, slice)
+    }
+}
@@ -15,6 +15,7 @@
 /// Non-volatile counter driver. Implements the syscall API documented in
 /// doc/nvcounter_syscalls.md. Must be made the client of the NvCounter capsule.
 
+use crate::error::{ErrorCode, IntoReturnCode};
 use h1::nvcounter::NvCounter;
 use kernel::{AppId,Callback,ReturnCode};
 
@@ -113,12 +114,12 @@ impl<'c, C: NvCounter<'c>> NvCounterSyscall<'c, C> {
     fn read_and_increment(&self, app: AppId) -> ReturnCode {
         if self.init_failed.get() {
             debug!("Trying to increment an uninitialized NV Counter.");
-            return ReturnCode::FAIL;
+            return ErrorCode::Fail.rcode();
         }
         let result = self.grant.enter(app, |app_data, _| {
-            if app_data.wants_increment { return ReturnCode::EBUSY; }
+            if app_data.wants_increment { return ErrorCode::Busy.rcode(); }
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM);
+        }).unwrap_or(ErrorCode::NoMem.rcode());
         if result != ReturnCode::SUCCESS {
             debug!("Failed to start system call for NV Counter increment.");
             return result;
@@ -132,7 +133,7 @@ impl<'c, C: NvCounter<'c>> NvCounterSyscall<'c, C> {
                 },
                 _ => {
                     debug!("Failed to read and increment NV Counter: {:?}", increment_result);
-                    return ReturnCode::FAIL;
+                    return ErrorCode::Fail.rcode();
                 }
             }
             self.op_ongoing.set(true);
@@ -142,7 +143,7 @@ impl<'c, C: NvCounter<'c>> NvCounterSyscall<'c, C> {
             self.grant.enter(app, |app_data, _| {
                 app_data.wants_increment = true;
                 ReturnCode::SUCCESS
-            }).unwrap_or(ReturnCode::ENOMEM)
+            }).unwrap_or(ErrorCode::NoMem.rcode())
         }
     }
 
@@ -150,7 +151,7 @@ impl<'c, C: NvCounter<'c>> NvCounterSyscall<'c, C> {
         self.grant.enter(app, |app_data, _| {
             app_data.callback = callback;
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 }
 
@@ -159,14 +160,14 @@ impl<'c, C: NvCounter<'c>> kernel::Driver for NvCounterSyscall<'c, C> {
         match minor_num {
             0 => ReturnCode::SUCCESS,
             1 => self.read_and_increment(app),
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 
     fn subscribe(&self, minor_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
         match minor_num {
             0 => self.set_increment_callback(callback, app_id),
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 }
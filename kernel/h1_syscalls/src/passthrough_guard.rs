@@ -0,0 +1,105 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for the SPI passthrough guard (see h1::passthrough_guard).
+//!
+//! The driver implements 4 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. arm (arg1 != 0) or disarm (arg1 == 0) the guard
+//!   2. get whether the guard is armed (1) or not (0)
+//!   3. get the number of times the guard disabled passthrough since boot
+//!
+//! The driver supports 1 subscribe:
+//!   0. callback when the guard disabled passthrough. Arguments are the
+//!      number of times it has done so since boot, then 0, 0.
+//!
+//! The guard only disables passthrough. Enabling it again once the host is
+//! out of reset is up to the app, through the SPI host driver.
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::passthrough_guard::{PassthroughGuard, PassthroughGuardClient};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x40180;
+
+const COMMAND_CHECK: usize      = 0;
+const COMMAND_ARM: usize        = 1;
+const COMMAND_IS_ARMED: usize   = 2;
+const COMMAND_GET_TRIPS: usize  = 3;
+
+const SUBSCRIBE_DISABLED: usize = 0;
+
+#[derive(Default)]
+pub struct AppData {
+    callback: Option<Callback>,
+}
+
+pub struct PassthroughGuardSyscall<'a> {
+    guard: &'a PassthroughGuard<'a>,
+    apps: Grant<AppData>,
+}
+
+impl<'a> PassthroughGuardSyscall<'a> {
+    pub fn new(guard: &'a PassthroughGuard<'a>,
+               container: Grant<AppData>) -> PassthroughGuardSyscall<'a> {
+        PassthroughGuardSyscall {
+            guard: guard,
+            apps: container,
+        }
+    }
+}
+
+impl<'a> PassthroughGuardClient for PassthroughGuardSyscall<'a> {
+    fn passthrough_disabled(&self, trips: u32) {
+        self.apps.each(|app_data| {
+            if let Some(mut callback) = app_data.callback {
+                callback.schedule(trips as usize, 0, 0);
+            }
+        });
+    }
+}
+
+impl<'a> Driver for PassthroughGuardSyscall<'a> {
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId)
+        -> ReturnCode {
+        match subscribe_num {
+            SUBSCRIBE_DISABLED => {
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize, _app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_ARM => {
+                if arg1 != 0 {
+                    self.guard.arm();
+                } else {
+                    self.guard.disarm();
+                }
+                ReturnCode::SUCCESS
+            },
+            COMMAND_IS_ARMED => ReturnCode::SuccessWithValue { value: self.guard.is_armed() as usize },
+            COMMAND_GET_TRIPS => ReturnCode::SuccessWithValue { value: self.guard.trips() as usize },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
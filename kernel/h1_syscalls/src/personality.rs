@@ -13,22 +13,29 @@
 // limitations under the License.
 
 //! System call driver for device attestation (personality) data. This
-//! is per-device data that will be stored durably on the device; current
-//! implementations store it in RAM.
+//! is per-device data that is stored durably on the device.
 //!
-//! The driver implements 3 commands:
+//! The driver implements 7 commands:
 //!   0. check if the driver is present (ReturnCode::SUCCESS if so)
 //!   1. read personality data into a user buffer.
 //!   2. durably write personality data from a user buffer, completion signaled
 //!      by a callback.
+//!   3. begin an update: stage personality data from a user buffer. Until
+//!      the update is committed or aborted, only the calling app can write.
+//!   4. commit the staged update, completion signaled by a callback. The
+//!      update replaces the stored data entirely or not at all.
+//!   5. abort the staged update.
+//!   6. get the integrity status of the stored data (see
+//!      h1::hil::personality::Status).
 //!
 //! The driver implements 1 allow:
-//!   0. userspace buffer used for read and write (commands 1 and 2).
+//!   0. userspace buffer used for read, write and begin (commands 1 to 3).
 //!
 //! The driver implements 1 subscribe:
-//!   0. callback for when a durable write completes.
+//!   0. callback for when a durable write or a commit completes.
 
 use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
 use h1::personality;
 use h1::hil::personality::{Client, Personality};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode, Shared, AppSlice};
@@ -40,6 +47,10 @@ pub const DRIVER_NUM: usize = 0x5000b;
 const COMMAND_CHECK: usize             = 0;
 const COMMAND_READ: usize              = 1;
 const COMMAND_WRITE: usize             = 2;
+const COMMAND_BEGIN: usize             = 3;
+const COMMAND_COMMIT: usize            = 4;
+const COMMAND_ABORT: usize             = 5;
+const COMMAND_STATUS: usize            = 6;
 const ALLOW_BUFFER: usize              = 0;
 const SUBSCRIBE_WRITE_DONE: usize      = 0;
 
@@ -53,19 +64,44 @@ pub struct PersonalitySyscall<'a> {
     device: &'a personality::PersonalityDriver<'a>,
     apps: Grant<AppData>,
     busy: Cell<bool>,
-    current_user: OptionalCell<AppId>
+    current_user: OptionalCell<AppId>,
+    // The app with a staged update, if any.
+    transaction_owner: OptionalCell<AppId>,
 }
 
 impl<'a> PersonalitySyscall<'a> {
-    pub fn new(device: &'a mut personality::PersonalityDriver<'a>,
+    pub fn new(device: &'a personality::PersonalityDriver<'a>,
                container: Grant<AppData>) -> PersonalitySyscall<'a> {
         PersonalitySyscall {
             device: device,
             apps: container,
             busy: Cell::new(false),
-            current_user: OptionalCell::empty()
+            current_user: OptionalCell::empty(),
+            transaction_owner: OptionalCell::empty(),
+        }
+    }
 
+    // Whether an app other than `app_id` has a staged update.
+    fn locked_out(&self, app_id: AppId) -> bool {
+        self.transaction_owner.map_or(false, |owner| *owner != app_id)
+    }
+
+    // Starts an asynchronous write or commit on behalf of `app_id`.
+    fn start(&self, app_id: AppId, rcode: ReturnCode) -> ReturnCode {
+        if rcode == ReturnCode::SUCCESS {
+            self.busy.set(true);
+            self.current_user.replace(app_id);
         }
+        rcode
+    }
+
+    fn done(&self, rval: ReturnCode) {
+        self.busy.set(false);
+        self.current_user.take().map(|current_user| {
+            let _ = self.apps.enter(current_user, |app_data, _| {
+                app_data.callback.map(|mut cb| cb.schedule(From::from(rval), 0, 0));
+            });
+        });
     }
 }
 
@@ -82,10 +118,10 @@ impl<'a> Driver for PersonalitySyscall<'a> {
                 });
                 match result {
                     Ok(_t) => ReturnCode::SUCCESS,
-                    Err(_e) => ReturnCode::ENOMEM,
+                    Err(_e) => ErrorCode::NoMem.rcode(),
                 }
             }
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -94,34 +130,86 @@ impl<'a> Driver for PersonalitySyscall<'a> {
             COMMAND_CHECK => ReturnCode::SUCCESS,
             COMMAND_READ  => {
                 if self.busy.get() {
-                    ReturnCode::EBUSY
+                    ErrorCode::Busy.rcode()
                 } else {
                     self.apps.enter(app_id, |app_data, _| {
-                        if app_data.data.is_none() {return ReturnCode::ENOMEM;}
+                        if app_data.data.is_none() {return ErrorCode::Size.rcode();}
                         let mut data_slice = app_data.data.take().unwrap();
-                        self.device.get_u8(data_slice.as_mut());
+                        let rcode = self.device.get_u8(data_slice.as_mut());
                         app_data.data = Some(data_slice);
-                        ReturnCode::SUCCESS
-                    }).unwrap_or(ReturnCode::ENOMEM)
+                        rcode
+                    }).unwrap_or(ErrorCode::NoMem.rcode())
 
                 }
             },
             COMMAND_WRITE => {
-                if self.busy.get() {
-                    ReturnCode::EBUSY
+                if self.busy.get() || self.transaction_owner.is_some() {
+                    ErrorCode::Busy.rcode()
                 } else {
                     self.apps.enter(app_id, |app_data, _| {
-                        if app_data.data.is_none() {return ReturnCode::ENOMEM;}
+                        if app_data.data.is_none() {return ErrorCode::Size.rcode();}
 
                         let mut data_slice = app_data.data.take().unwrap();
-                        self.device.set_u8(data_slice.as_mut());
-                        self.current_user.replace(app_id);
+                        let rcode = self.device.set_u8(data_slice.as_mut());
+                        app_data.data = Some(data_slice);
+                        self.start(app_id, rcode)
+                    }).unwrap_or(ErrorCode::NoMem.rcode())
+                }
+            },
+            COMMAND_BEGIN => {
+                if self.busy.get() || self.locked_out(app_id) {
+                    ErrorCode::Busy.rcode()
+                } else {
+                    self.apps.enter(app_id, |app_data, _| {
+                        if app_data.data.is_none() {return ErrorCode::Size.rcode();}
+
+                        let data_slice = app_data.data.take().unwrap();
+                        let rcode = self.device.begin_u8(data_slice.as_ref());
                         app_data.data = Some(data_slice);
-                        ReturnCode::SUCCESS
-                    }).unwrap_or(ReturnCode::ENOMEM)
+                        if rcode == ReturnCode::SUCCESS {
+                            self.transaction_owner.set(app_id);
+                        }
+                        rcode
+                    }).unwrap_or(ErrorCode::NoMem.rcode())
+                }
+            },
+            COMMAND_COMMIT => {
+                if self.busy.get() || self.locked_out(app_id) {
+                    ErrorCode::Busy.rcode()
+                } else if self.transaction_owner.is_none() {
+                    ErrorCode::Invalid.rcode()
+                } else {
+                    let rcode = self.start(app_id, self.device.commit());
+                    if rcode == ReturnCode::SUCCESS {
+                        self.transaction_owner.clear();
+                    }
+                    rcode
+                }
+            },
+            COMMAND_ABORT => {
+                if self.busy.get() || self.locked_out(app_id) {
+                    ErrorCode::Busy.rcode()
+                } else if self.transaction_owner.is_none() {
+                    ErrorCode::Invalid.rcode()
+                } else {
+                    let rcode = self.device.abort();
+                    if rcode == ReturnCode::SUCCESS {
+                        self.transaction_owner.clear();
+                    }
+                    rcode
+                }
+            },
+            COMMAND_STATUS => {
+                if self.busy.get() {
+                    ErrorCode::Busy.rcode()
+                } else {
+                    match self.device.status() {
+                        Ok(status) => ReturnCode::SuccessWithValue { value: status as usize },
+                        Err(rcode) => rcode,
+                    }
                 }
             },
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -136,9 +224,9 @@ impl<'a> Driver for PersonalitySyscall<'a> {
                     app_data.data = slice;
                     ReturnCode::SUCCESS
                 })
-               .unwrap_or(ReturnCode::ENOMEM)
+               .unwrap_or(ErrorCode::NoMem.rcode())
             },
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 
@@ -147,20 +235,14 @@ impl<'a> Driver for PersonalitySyscall<'a> {
 impl<'a> Client<'a> for PersonalitySyscall<'a> {
 
     fn set_done(&self, rval: ReturnCode) {
-        self.current_user.map(|current_user| {
-            let _ = self.apps.enter(*current_user, |app_data, _| {
-                self.current_user.clear();
-                app_data.callback.map(|mut cb| cb.schedule(From::from(rval), 0, 0));
-            });
-        });
+        self.done(rval);
     }
 
     fn set_u8_done(&self, rval: ReturnCode) {
-        self.current_user.map(|current_user| {
-            let _ = self.apps.enter(*current_user, |app_data, _| {
-                self.current_user.clear();
-                app_data.callback.map(|mut cb| cb.schedule(From::from(rval), 0, 0));
-            });
-        });
+        self.done(rval);
+    }
+
+    fn commit_done(&self, rval: ReturnCode) {
+        self.done(rval);
     }
 }
@@ -0,0 +1,133 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Token-bucket rate limiting of syscalls, per driver number.
+//!
+//! A board lists the drivers to limit and checks `RateLimiter::allow` in its
+//! `Platform::with_driver`. Calls beyond the limit are answered by
+//! `THROTTLED`, which fails every syscall with EBUSY, so that an app spinning
+//! on an expensive driver cannot starve the rest of the system.
+
+use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::timeus::Timeus;
+use kernel::{AppId, AppSlice, Callback, Driver, ReturnCode, Shared};
+
+pub struct RateLimit {
+    driver_num: usize,
+    calls_per_second: u32,
+    burst: u32,
+
+    /// Available calls, in units of 1 / `clock_hz` calls.
+    tokens: Cell<u64>,
+
+    /// Clock value when `tokens` was last refilled.
+    last_refill: Cell<u32>,
+
+    /// Number of calls rejected so far.
+    throttled: Cell<u32>,
+}
+
+impl RateLimit {
+    /// Allows `burst` back-to-back calls to `driver_num`, refilled at
+    /// `calls_per_second`.
+    pub const fn new(driver_num: usize, calls_per_second: u32, burst: u32) -> RateLimit {
+        RateLimit {
+            driver_num: driver_num,
+            calls_per_second: calls_per_second,
+            burst: burst,
+            tokens: Cell::new(u64::MAX),
+            last_refill: Cell::new(0),
+            throttled: Cell::new(0),
+        }
+    }
+}
+
+pub struct RateLimiter<'a> {
+    clock: &'a Timeus,
+    clock_hz: u32,
+    limits: &'a [RateLimit],
+}
+
+impl<'a> RateLimiter<'a> {
+    /// `clock` must be a running counter incrementing at `clock_hz`. Calls to
+    /// a limited driver should be spaced less than one counter wrap apart to
+    /// get the full refill; a longer pause refills less.
+    pub fn new(clock: &'a Timeus, clock_hz: u32, limits: &'a [RateLimit]) -> RateLimiter<'a> {
+        RateLimiter {
+            clock: clock,
+            clock_hz: clock_hz,
+            limits: limits,
+        }
+    }
+
+    /// Consumes one call to `driver_num`. Returns false if the call exceeds
+    /// the driver's limit. Drivers without a limit are always allowed.
+    pub fn allow(&self, driver_num: usize) -> bool {
+        let limit = match self.limits.iter().find(|limit| limit.driver_num == driver_num) {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        let call = self.clock_hz as u64;
+        let now = self.clock.now();
+        let elapsed = now.wrapping_sub(limit.last_refill.get()) as u64;
+        limit.last_refill.set(now);
+        // A full bucket starts at u64::MAX, so clamp before adding.
+        let capacity = limit.burst as u64 * call;
+        let tokens = limit.tokens.get().min(capacity)
+            .saturating_add(elapsed * limit.calls_per_second as u64)
+            .min(capacity);
+
+        if tokens < call {
+            limit.tokens.set(tokens);
+            limit.throttled.set(limit.throttled.get().saturating_add(1));
+            return false;
+        }
+        limit.tokens.set(tokens - call);
+        true
+    }
+
+    /// Number of calls to `driver_num` rejected since boot, or None if the
+    /// driver is not limited.
+    pub fn throttled(&self, driver_num: usize) -> Option<u32> {
+        self.limits.iter()
+            .find(|limit| limit.driver_num == driver_num)
+            .map(|limit| limit.throttled.get())
+    }
+}
+
+/// Stands in for a driver whose rate limit was exceeded.
+pub struct Throttled;
+
+pub static THROTTLED: Throttled = Throttled;
+
+impl Driver for Throttled {
+    fn subscribe(&self, _minor_num: usize, _callback: Option<Callback>, _app_id: AppId)
+        -> ReturnCode {
+        ErrorCode::Busy.rcode()
+    }
+
+    fn command(&self, _minor_num: usize, _r2: usize, _r3: usize, _caller_id: AppId)
+        -> ReturnCode {
+        ErrorCode::Busy.rcode()
+    }
+
+    fn allow(&self, _app: AppId, _minor_num: usize, _slice: Option<AppSlice<Shared, u8>>)
+        -> ReturnCode {
+        ErrorCode::Busy.rcode()
+    }
+}
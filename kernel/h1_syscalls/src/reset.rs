@@ -15,6 +15,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
 use h1::hil::reset::Reset;
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode, Shared, AppSlice};
 use spiutils::io::Cursor;
@@ -55,11 +56,11 @@ impl<'a> ResetSyscall<'a> {
             if let Some(ref mut buffer) = app_data.buffer {
                 let cursor = Cursor::new(buffer.as_mut());
                 if self.reset.get_reset_source().to_wire(cursor).is_err() {
-                    return ReturnCode::ENOMEM;
+                    return ErrorCode::Size.rcode();
                 }
             }
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 }
 
@@ -69,7 +70,7 @@ impl<'a> Driver for ResetSyscall<'a> {
                  _callback: Option<Callback>,
                  _app_id: AppId,
     ) -> ReturnCode {
-        ReturnCode::ENOSUPPORT
+        ErrorCode::NoSupport.rcode()
     }
 
     fn command(&self, command_num: usize, _arg1: usize, _arg2: usize, caller_id: AppId)
@@ -81,7 +82,7 @@ impl<'a> Driver for ResetSyscall<'a> {
             0 /* Check if present */ => ReturnCode::SUCCESS,
             1 /* Reset chip. */ => self.reset_chip(),
             2 /* Get reset source */ => self.get_reset_source(caller_id),
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -98,9 +99,9 @@ impl<'a> Driver for ResetSyscall<'a> {
                         app_data.buffer = slice;
                         ReturnCode::SUCCESS
                     })
-                    .unwrap_or(ReturnCode::FAIL)
+                    .unwrap_or(ErrorCode::Fail.rcode())
             }
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 }
@@ -0,0 +1,187 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for RSA modular exponentiation.
+//!
+//! Computes message^exponent mod modulus for 2048- and 3072-bit moduli,
+//! e.g. to check RSA signatures, on the dcrypto engine. All numbers are
+//! big-endian. Only one exponentiation runs at a time.
+//!
+//! The driver implements 2 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. start an exponentiation with an arg1-byte modulus (256 or 384) and
+//!      an arg2-byte exponent, taken from the start of their buffers. The
+//!      message is the first arg1 bytes of its buffer and must be less
+//!      than the modulus. The modulus must be odd with its top bit set, and
+//!      the exponent must not be empty. Completion is signaled by a
+//!      callback.
+//!
+//! The driver implements 4 allows:
+//!   0. the modulus
+//!   1. the exponent
+//!   2. the message
+//!   3. the buffer the result is written to, at least arg1 bytes.
+//!
+//! The driver implements 1 subscribe:
+//!   0. callback when the exponentiation is done, called with the
+//!      ReturnCode and the length of the result.
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use crate::app_slice::AppSliceExt;
+use h1::crypto::rsa::{RsaClient, RsaEngine};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::common::cells::OptionalCell;
+
+pub const DRIVER_NUM: usize = 0x40170;
+
+const COMMAND_CHECK: usize        = 0;
+const COMMAND_MODEXP: usize       = 1;
+const ALLOW_MODULUS: usize        = 0;
+const ALLOW_EXPONENT: usize       = 1;
+const ALLOW_MESSAGE: usize        = 2;
+const ALLOW_RESULT: usize         = 3;
+const SUBSCRIBE_DONE: usize       = 0;
+
+// Supported modulus lengths, in bytes.
+const RSA_2048_LEN: usize = 256;
+const RSA_3072_LEN: usize = 384;
+
+#[derive(Default)]
+pub struct AppData {
+    modulus: Option<AppSlice<Shared, u8>>,
+    exponent: Option<AppSlice<Shared, u8>>,
+    message: Option<AppSlice<Shared, u8>>,
+    result: Option<AppSlice<Shared, u8>>,
+    callback: Option<Callback>,
+}
+
+pub struct RsaSyscall<'a> {
+    engine: &'a RsaEngine<'a>,
+    apps: Grant<AppData>,
+    current_user: OptionalCell<AppId>,
+}
+
+impl<'a> RsaSyscall<'a> {
+    pub fn new(engine: &'a RsaEngine<'a>, container: Grant<AppData>) -> RsaSyscall<'a> {
+        RsaSyscall {
+            engine: engine,
+            apps: container,
+            current_user: OptionalCell::empty(),
+        }
+    }
+
+    fn modexp(&self, app_id: AppId, modulus_len: usize, exponent_len: usize) -> ReturnCode {
+        if modulus_len != RSA_2048_LEN && modulus_len != RSA_3072_LEN {
+            return ErrorCode::Invalid.rcode();
+        }
+        if self.engine.is_busy() {
+            return ErrorCode::Busy.rcode();
+        }
+        let rcode = self.apps.enter(app_id, |app_data, _| {
+            let (modulus, exponent, message) =
+                match (&app_data.modulus, &app_data.exponent, &app_data.message) {
+                    (Some(modulus), Some(exponent), Some(message)) => (modulus, exponent, message),
+                    _ => return ErrorCode::Size.rcode(),
+                };
+            match app_data.result {
+                Some(ref result) if result.len() >= modulus_len => (),
+                Some(_) => return ErrorCode::Size.rcode(),
+                None => return ErrorCode::Size.rcode(),
+            }
+            let modulus = match modulus.get_prefix(modulus_len) {
+                Ok(modulus) => modulus,
+                Err(rcode) => return rcode,
+            };
+            let exponent = match exponent.get_prefix(exponent_len) {
+                Ok(exponent) => exponent,
+                Err(rcode) => return rcode,
+            };
+            let message = match message.get_prefix(modulus_len) {
+                Ok(message) => message,
+                Err(rcode) => return rcode,
+            };
+            self.engine.modexp(modulus, exponent, message)
+        }).unwrap_or(ErrorCode::NoMem.rcode());
+        if rcode == ReturnCode::SUCCESS {
+            self.current_user.set(app_id);
+        }
+        rcode
+    }
+}
+
+impl<'a> RsaClient for RsaSyscall<'a> {
+    fn modexp_done(&self, rcode: ReturnCode, output: &[u8]) {
+        let len = if rcode == ReturnCode::SUCCESS { output.len() } else { 0 };
+        self.current_user.take().map(|current_user| {
+            let _ = self.apps.enter(current_user, |app_data, _| {
+                let rcode = match app_data.result {
+                    Some(ref mut result) => match result.get_range_mut(0, len) {
+                        Ok(result) => {
+                            result.copy_from_slice(&output[..len]);
+                            rcode
+                        }
+                        Err(err) => err,
+                    },
+                    None => ErrorCode::Size.rcode(),
+                };
+                app_data.callback.map(|mut cb| cb.schedule(From::from(rcode), len, 0));
+            });
+        });
+    }
+}
+
+impl<'a> Driver for RsaSyscall<'a> {
+    fn subscribe(&self,
+                 subscribe_num: usize,
+                 callback: Option<Callback>,
+                 app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            SUBSCRIBE_DONE => {
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            }
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_MODEXP => self.modexp(app_id, arg1, arg2),
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn allow(&self,
+             app_id: AppId,
+             minor_num: usize,
+             slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps.enter(app_id, |app_data, _| {
+            match minor_num {
+                ALLOW_MODULUS => app_data.modulus = slice,
+                ALLOW_EXPONENT => app_data.exponent = slice,
+                ALLOW_MESSAGE => app_data.message = slice,
+                ALLOW_RESULT => app_data.result = slice,
+                _ => return ErrorCode::NoSupport.rcode(),
+            }
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+}
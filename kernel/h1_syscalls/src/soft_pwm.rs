@@ -0,0 +1,66 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for dimming LEDs with h1::soft_pwm.
+//!
+//! Pins are numbered as in the board's SoftPwm pin table.
+//!
+//! The driver implements 3 commands:
+//!   0. check if the driver is present; returns the number of pins
+//!   1. set the duty cycle of pin arg1 to arg2 percent (0 to 100)
+//!   2. get the duty cycle of pin arg1, in percent
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::soft_pwm::SoftPwm;
+use kernel::hil::time::Alarm;
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x40100;
+
+const COMMAND_CHECK: usize      = 0;
+const COMMAND_SET_DUTY: usize   = 1;
+const COMMAND_GET_DUTY: usize   = 2;
+
+pub struct SoftPwmSyscall<'a, A: Alarm<'a>> {
+    pwm: &'a SoftPwm<'a, A>,
+}
+
+impl<'a, A: Alarm<'a>> SoftPwmSyscall<'a, A> {
+    pub fn new(pwm: &'a SoftPwm<'a, A>) -> SoftPwmSyscall<'a, A> {
+        SoftPwmSyscall {
+            pwm: pwm,
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> Driver for SoftPwmSyscall<'a, A> {
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, _app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SuccessWithValue { value: self.pwm.pin_count() },
+            COMMAND_SET_DUTY => {
+                if arg2 > 100 {
+                    return ErrorCode::Invalid.rcode();
+                }
+                self.pwm.set_duty(arg1, arg2 as u8)
+            },
+            COMMAND_GET_DUTY => match self.pwm.duty(arg1) {
+                Some(duty) => ReturnCode::SuccessWithValue { value: duty as usize },
+                None => ErrorCode::Invalid.rcode(),
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use core::cell::Cell;
 use core::convert::TryFrom;
+use crate::error::{ErrorCode, IntoReturnCode};
 
 use h1::hil::spi_device::SpiDevice;
 use h1::hil::spi_device::SpiDeviceClient;
@@ -12,8 +13,17 @@ use kernel::Grant;
 use kernel::ReturnCode;
 use kernel::Shared;
 
+use crate::app_slice::AppSliceExt;
+
+use spiutils::driver::spi_device::AccessPermission;
+use spiutils::driver::spi_device::AccessRegion;
 use spiutils::driver::spi_device::AddressConfig;
+use spiutils::driver::spi_device::DeniedAccessResponse;
+use spiutils::driver::spi_device::EmulatedOperation;
 use spiutils::driver::spi_device::HandlerMode;
+use spiutils::driver::spi_device::MAX_ACCESS_REGIONS;
+use spiutils::driver::spi_device::PassthroughFilterAction;
+use spiutils::driver::spi_device::RxBufferMode;
 use spiutils::protocol::flash::AddressMode;
 use spiutils::protocol::flash::OpCode;
 use spiutils::protocol::wire::FromWire;
@@ -26,15 +36,22 @@ pub const DRIVER_NUM: usize = 0x40030;
 pub struct AppData {
     tx_buffer: Option<AppSlice<Shared, u8>>,
     rx_buffer: Option<AppSlice<Shared, u8>>,
+    mailbox_buffer: Option<AppSlice<Shared, u8>>,
     data_received_callback: Option<Callback>,
+    mailbox_read_callback: Option<Callback>,
     address_mode_handling: Cell<HandlerMode>,
     address_mode_changed_callback: Option<Callback>,
+    rx_buffer_mode: Cell<RxBufferMode>,
+    // Whether the app owns rx_buffer (RxBufferMode::Handoff only).
+    rx_buffer_owned: Cell<bool>,
 }
 
 pub struct SpiDeviceSyscall<'a> {
     device: &'a dyn SpiDevice,
     apps: Grant<AppData>,
     current_user: Cell<Option<AppId>>,
+    // Number of address mode switches since boot, by the SPI host or apps.
+    address_mode_switches: Cell<usize>,
 }
 
 impl<'a> SpiDeviceSyscall<'a> {
@@ -44,7 +61,19 @@ impl<'a> SpiDeviceSyscall<'a> {
             device: device,
             apps: container,
             current_user: Cell::new(None),
+            address_mode_switches: Cell::new(0),
+        }
+    }
+
+    // Switch to `address_mode`, counting the switch.
+    // Returns true if the address mode changed.
+    fn switch_address_mode(&self, address_mode: AddressMode) -> bool {
+        if self.device.get_address_mode() == address_mode {
+            return false;
         }
+        self.device.set_address_mode(address_mode);
+        self.address_mode_switches.set(self.address_mode_switches.get().wrapping_add(1));
+        true
     }
 
     fn send_data(&self, caller_id: AppId, clear_busy: bool, clear_write_enable: bool) -> ReturnCode {
@@ -59,8 +88,23 @@ impl<'a> SpiDeviceSyscall<'a> {
                 return ReturnCode::SUCCESS;
             }
 
-            ReturnCode::ENOMEM
-        }).unwrap_or(ReturnCode::ENOMEM)
+            ErrorCode::Size.rcode()
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    fn send_mailbox_data(&self, caller_id: AppId, clear_busy: bool, clear_write_enable: bool) -> ReturnCode {
+        self.apps.enter(caller_id, |app_data, _| {
+            if let Some(ref mailbox_buffer) = app_data.mailbox_buffer {
+                let return_code = self.device.put_mailbox_data(mailbox_buffer.as_ref());
+                if isize::from(return_code) < 0 { return return_code; }
+
+                if clear_write_enable { self.device.clear_write_enable(); }
+                if clear_busy { self.device.clear_busy(); }
+                return ReturnCode::SUCCESS;
+            }
+
+            ErrorCode::Size.rcode()
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 
     fn clear_status(&self, caller_id: AppId, clear_busy: bool, clear_write_enable: bool) -> ReturnCode {
@@ -69,28 +113,57 @@ impl<'a> SpiDeviceSyscall<'a> {
             if clear_busy { self.device.clear_busy(); }
 
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 
     fn set_address_mode(&self, caller_id: AppId, address_mode: AddressMode) -> ReturnCode {
         self.apps.enter(caller_id, |_app_data, _| {
-            self.device.set_address_mode(address_mode);
+            self.switch_address_mode(address_mode);
 
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 
     fn get_address_mode(&self, caller_id: AppId) -> ReturnCode {
         self.apps.enter(caller_id, |_app_data, _| {
             ReturnCode::SuccessWithValue { value: self.device.get_address_mode() as usize }
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    fn set_rx_buffer_mode(&self, caller_id: AppId, rx_buffer_mode: RxBufferMode) -> ReturnCode {
+        let result = self.apps.enter(caller_id, |app_data, _| {
+            app_data.rx_buffer_mode.set(rx_buffer_mode);
+            if rx_buffer_mode == RxBufferMode::Copy {
+                app_data.rx_buffer_owned.set(false);
+            }
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode());
+        if result == ReturnCode::SUCCESS {
+            // Deliver a transaction held while the app owned the buffer.
+            self.device.poll_data_available();
+        }
+        result
+    }
+
+    fn release_rx_buffer(&self, caller_id: AppId) -> ReturnCode {
+        let result = self.apps.enter(caller_id, |app_data, _| {
+            app_data.rx_buffer_owned.set(false);
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode());
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        // Outside of the grant, since delivering enters it again.
+        ReturnCode::SuccessWithValue {
+            value: usize::from(self.device.poll_data_available())
+        }
     }
 
     fn set_address_mode_handling(&self, caller_id: AppId, address_mode_handling: HandlerMode) -> ReturnCode {
         self.apps.enter(caller_id, |app_data, _| {
             app_data.address_mode_handling.set(address_mode_handling);
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 
     fn process_spi_cmd(&self, app_data: &AppData, spi_cmd: u8, maybe_spi_data: Option<u8>) -> Result<HandlerMode, FromWireError> {
@@ -105,15 +178,12 @@ impl<'a> SpiDeviceSyscall<'a> {
                             OpCode::Exit4ByteAddressMode => AddressMode::ThreeByte,
                             _ => return Err(FromWireError::OutOfRange)
                         };
-                        let mut has_address_mode_changed = false;
-                        if self.device.get_address_mode() != address_mode {
-                            self.device.set_address_mode(address_mode);
-                            has_address_mode_changed = true;
-                        }
+                        let has_address_mode_changed = self.switch_address_mode(address_mode);
                         self.device.clear_busy();
                         if has_address_mode_changed {
                             app_data.address_mode_changed_callback.map(
-                                |mut cb| cb.schedule(usize::from(address_mode), 0, 0));
+                                |mut cb| cb.schedule(usize::from(address_mode),
+                                                     self.address_mode_switches.get(), 0));
                         }
                         Ok(HandlerMode::KernelSpace)
                     }
@@ -139,9 +209,9 @@ impl<'a> SpiDeviceSyscall<'a> {
             if let Some(ref tx_buffer) = app_data.tx_buffer {
                 self.device.set_jedec_id(tx_buffer.as_ref())
             } else {
-                ReturnCode::ENOMEM
+                ErrorCode::Size.rcode()
             }
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 
     fn set_sfdp(&self, caller_id: AppId) -> ReturnCode {
@@ -149,49 +219,111 @@ impl<'a> SpiDeviceSyscall<'a> {
             if let Some(ref tx_buffer) = app_data.tx_buffer {
                 self.device.set_sfdp(tx_buffer.as_ref())
             } else {
-                ReturnCode::ENOMEM
+                ErrorCode::Size.rcode()
             }
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 
     fn configure_addresses(&self, caller_id: AppId) -> ReturnCode {
         self.apps.enter(caller_id, |app_data, _| {
             if let Some(ref tx_buffer) = app_data.tx_buffer {
-                let maybe_address_config = AddressConfig::from_wire(tx_buffer.as_ref());
-                if maybe_address_config.is_err() {
-                    return ReturnCode::EINVAL;
+                let address_config = match AddressConfig::from_wire(tx_buffer.as_ref()) {
+                    Ok(address_config) => address_config,
+                    Err(_) => return ErrorCode::Invalid.rcode(),
+                };
+                // Reject configurations whose windows would wrap before they
+                // reach the hardware.
+                if address_config.validate().is_err() {
+                    return ErrorCode::Invalid.rcode();
                 }
 
-                self.device.configure_addresses(maybe_address_config.unwrap());
+                self.device.configure_addresses(address_config)
+            } else {
+                ErrorCode::Size.rcode()
+            }
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
 
-                ReturnCode::SUCCESS
+    fn set_access_regions(&self, caller_id: AppId, region_count: usize) -> ReturnCode {
+        if region_count > MAX_ACCESS_REGIONS {
+            return ErrorCode::Size.rcode();
+        }
+        self.apps.enter(caller_id, |app_data, _| {
+            if let Some(ref tx_buffer) = app_data.tx_buffer {
+                let mut regions = [AccessRegion {
+                    virtual_base: 0,
+                    size: 0,
+                    permission: AccessPermission::ReadWrite,
+                }; MAX_ACCESS_REGIONS];
+                let mut data = tx_buffer.as_ref();
+                for region in regions[..region_count].iter_mut() {
+                    match AccessRegion::from_wire(&mut data) {
+                        Ok(value) => *region = value,
+                        Err(_) => return ErrorCode::Invalid.rcode(),
+                    }
+                }
+
+                self.device.set_access_regions(&regions[..region_count])
             } else {
-                ReturnCode::ENOMEM
+                ErrorCode::Size.rcode()
             }
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    // Check a command received from the SPI host against the access map.
+    // Returns false if the command was denied and must be dropped.
+    fn check_access(&self, rx_data: &[u8]) -> bool {
+        let op_code = match rx_data.get(0).and_then(|spi_cmd| OpCode::from_wire_value(*spi_cmd)) {
+            Some(op_code) => op_code,
+            None => return true,
+        };
+        if !op_code.is_read() && !op_code.is_write() {
+            return true;
+        }
+        if !op_code.has_address() {
+            return self.device.check_access(None, op_code.is_write());
+        }
+
+        let address_len = match self.device.get_address_mode() {
+            AddressMode::ThreeByte => 3,
+            AddressMode::FourByte => 4,
+        };
+        match rx_data.get(1..1 + address_len) {
+            Some(address_bytes) => {
+                let address = address_bytes.iter()
+                    .fold(0u32, |address, byte| (address << 8) | (*byte as u32));
+                self.device.check_access(Some(address), op_code.is_write())
+            }
+            // Incomplete command. Let the handler deal with it.
+            None => true,
+        }
     }
 }
 
 impl<'a> SpiDeviceClient for SpiDeviceSyscall<'a> {
-    fn data_available(&self, is_busy: bool, is_write_enabled: bool) {
+    fn data_available(&self, is_busy: bool, is_write_enabled: bool) -> bool {
         //debug!("data_available");
-        self.current_user.get().map(|current_user| {
-            let _ = self.apps.enter(current_user, move |app_data, _| {
+        self.current_user.get().map_or(true, |current_user| {
+            self.apps.enter(current_user, move |app_data, _| {
+                if app_data.rx_buffer_owned.get() {
+                    // The app is still reading the previous transaction.
+                    return false;
+                }
+
                 let mut rx_len = 0;
                 let mut handler_mode = HandlerMode::UserSpace;
                 let mut maybe_spi_cmd : Option<u8> = None;
                 let mut maybe_spi_data : Option<u8> = None;
+                let is_access_allowed;
                 if let Some(ref mut rx_buffer) = app_data.rx_buffer {
                     rx_len = self.device.get_received_data(rx_buffer.as_mut());
-                    if rx_len > 0 {
-                        maybe_spi_cmd = Some(rx_buffer.as_ref()[0]);
-                    }
-                    if rx_len > 1 {
-                        maybe_spi_data = Some(rx_buffer.as_ref()[1]);
-                    }
+                    let rx_data = rx_buffer.get_prefix(rx_len).unwrap_or(&[]);
+                    maybe_spi_cmd = rx_data.get(0).copied();
+                    maybe_spi_data = rx_data.get(1).copied();
+                    is_access_allowed = self.check_access(rx_data);
                 } else {
-                    // Just grab the first two bytes
-                    let mut spi_cmd_buf = [!0, !0];
+                    // Just grab the op code and address bytes
+                    let mut spi_cmd_buf = [!0; 5];
                     let spi_cmd_buf_len = self.device.get_received_data(&mut spi_cmd_buf);
                     if spi_cmd_buf_len > 0 {
                         maybe_spi_cmd = Some(spi_cmd_buf[0]);
@@ -199,6 +331,12 @@ impl<'a> SpiDeviceClient for SpiDeviceSyscall<'a> {
                     if spi_cmd_buf_len > 1 {
                         maybe_spi_data = Some(spi_cmd_buf[1]);
                     }
+                    is_access_allowed = self.check_access(&spi_cmd_buf[..spi_cmd_buf_len]);
+                }
+
+                if !is_access_allowed {
+                    // The device already answered the denied command.
+                    return true;
                 }
 
                 // Handle some special op code straight in kernel space
@@ -212,9 +350,21 @@ impl<'a> SpiDeviceClient for SpiDeviceSyscall<'a> {
 
                 //debug!("handler_mode: {:?}", handler_mode);
                 if handler_mode == HandlerMode::UserSpace {
+                    if rx_len > 0 && app_data.rx_buffer_mode.get() == RxBufferMode::Handoff {
+                        app_data.rx_buffer_owned.set(true);
+                    }
                     app_data.data_received_callback.map(
                         |mut cb| cb.schedule(rx_len, usize::from(is_busy), usize::from(is_write_enabled)));
                 }
+                true
+            }).unwrap_or(true)
+        })
+    }
+
+    fn mailbox_read(&self) {
+        self.current_user.get().map(|current_user| {
+            let _ = self.apps.enter(current_user, |app_data, _| {
+                app_data.mailbox_read_callback.map(|mut cb| cb.schedule(0, 0, 0));
             });
         });
     }
@@ -237,17 +387,26 @@ impl<'a> Driver for SpiDeviceSyscall<'a> {
                 self.apps.enter(app_id, |app_data, _| {
                     app_data.data_received_callback = callback;
                     ReturnCode::SUCCESS
-                }).unwrap_or(ReturnCode::ENOMEM)
+                }).unwrap_or(ErrorCode::NoMem.rcode())
             },
-            1 /* Address mode changed
+            1 /* Address mode changed by the SPI host while address mode
+                 handling is HandlerMode::KernelSpace
                  Callback arguments:
-                 arg1: new AddressMode as usize */ => {
+                 arg1: new AddressMode as usize
+                 arg2: number of address mode switches since boot */ => {
                 self.apps.enter(app_id, |app_data, _| {
                     app_data.address_mode_changed_callback = callback;
                     ReturnCode::SUCCESS
-                }).unwrap_or(ReturnCode::ENOMEM)
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            2 /* Mailbox read by the SPI host
+                 Callback arguments: none */ => {
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.mailbox_read_callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
             },
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -272,7 +431,7 @@ impl<'a> Driver for SpiDeviceSyscall<'a> {
                  arg1: AddressMode as usize */ => {
                 let address_mode = match AddressMode::try_from(arg1) {
                     Ok(val) => val,
-                    Err(_) => return ReturnCode::EINVAL
+                    Err(_) => return ErrorCode::Invalid.rcode()
                 };
                 self.set_address_mode(caller_id, address_mode)
             },
@@ -285,7 +444,7 @@ impl<'a> Driver for SpiDeviceSyscall<'a> {
                  arg1: HandlerMode as usize */ => {
                 let handler_mode = match HandlerMode::try_from(arg1) {
                     Ok(val) => val,
-                    Err(_) => return ReturnCode::EINVAL
+                    Err(_) => return ErrorCode::Invalid.rcode()
                 };
                 self.set_address_mode_handling(caller_id, handler_mode)
             }
@@ -298,7 +457,115 @@ impl<'a> Driver for SpiDeviceSyscall<'a> {
             8 /* Configure addresses using data from TX buffer */ => {
                 self.configure_addresses(caller_id)
             }
-            _ => ReturnCode::ENOSUPPORT
+            9 /* Set access map using data from TX buffer
+                 arg1: Number of AccessRegion entries in TX buffer */ => {
+                self.set_access_regions(caller_id, arg1)
+            }
+            10 /* Set denied access response
+                  arg1: DeniedAccessResponse as usize */ => {
+                let response = match DeniedAccessResponse::try_from(arg1) {
+                    Ok(val) => val,
+                    Err(_) => return ErrorCode::Invalid.rcode()
+                };
+                self.device.set_denied_access_response(response);
+                ReturnCode::SUCCESS
+            }
+            11 /* Get access metrics
+                  arg1: 0: denied reads, 1: denied writes
+                  returns: Counter value */ => {
+                let metrics = self.device.get_access_metrics();
+                match arg1 {
+                    0 => ReturnCode::SuccessWithValue { value: metrics.denied_reads as usize },
+                    1 => ReturnCode::SuccessWithValue { value: metrics.denied_writes as usize },
+                    _ => ErrorCode::Invalid.rcode()
+                }
+            }
+            12 /* Kick: check for a pending transaction now instead of waiting
+                  for the next interrupt. If there is one, the data received
+                  callback is queued before this returns.
+                  returns: 1 if a transaction was pending, 0 otherwise */ => {
+                ReturnCode::SuccessWithValue {
+                    value: usize::from(self.device.poll_data_available())
+                }
+            }
+            13 /* Set RX buffer mode
+                  arg1: RxBufferMode as usize */ => {
+                let rx_buffer_mode = match RxBufferMode::try_from(arg1) {
+                    Ok(val) => val,
+                    Err(_) => return ErrorCode::Invalid.rcode()
+                };
+                self.set_rx_buffer_mode(caller_id, rx_buffer_mode)
+            }
+            14 /* Release the RX buffer handed to the app by the last
+                  transaction (RxBufferMode::Handoff). If another transaction
+                  is waiting, the data received callback is queued before
+                  this returns.
+                  returns: 1 if a transaction was pending, 0 otherwise */ => {
+                self.release_rx_buffer(caller_id)
+            }
+            15 /* Set the emulated latency of a flash operation, for testing
+                  SPI host drivers against realistic timing
+                  arg1: EmulatedOperation as usize
+                  arg2: Latency in microseconds (0: no emulation) */ => {
+                let operation = match EmulatedOperation::try_from(arg1) {
+                    Ok(val) => val,
+                    Err(_) => return ErrorCode::Invalid.rcode()
+                };
+                self.device.set_emulated_latency(operation, arg2 as u32)
+            }
+            16 /* Configure program and erase handling
+                  (OpCode::PageProgram, OpCode::SectorErase,
+                  OpCode::BlockErase32KB and OpCode::BlockErase64KB)
+                  arg1: HandlerMode as usize
+                  arg2: Address on the SPI bus of the kernel's flash region */ => {
+                let handler_mode = match HandlerMode::try_from(arg1) {
+                    Ok(val) => val,
+                    Err(_) => return ErrorCode::Invalid.rcode()
+                };
+                self.device.set_program_erase_handling(handler_mode, arg2 as u32)
+            }
+            17 /* Get the status register as the SPI host reads it
+                  returns: Status register value */ => {
+                ReturnCode::SuccessWithValue { value: self.device.get_status() as usize }
+            }
+            18 /* Set the status register, e.g. the block protect bits.
+                  BUSY and WEL are not changed; use command 2 to clear them.
+                  arg1: Status register value */ => {
+                if arg1 > u8::MAX as usize {
+                    return ErrorCode::Invalid.rcode();
+                }
+                self.device.set_status(arg1 as u8);
+                ReturnCode::SUCCESS
+            }
+            19 /* Set what SPI passthrough does with an op code
+                  arg1: Op code
+                  arg2: PassthroughFilterAction as usize */ => {
+                if arg1 > u8::MAX as usize {
+                    return ErrorCode::Invalid.rcode();
+                }
+                let action = match PassthroughFilterAction::try_from(arg2) {
+                    Ok(val) => val,
+                    Err(_) => return ErrorCode::Invalid.rcode()
+                };
+                self.device.set_passthrough_filter(arg1 as u8, action)
+            }
+            20 /* Put mailbox data using data from mailbox buffer. The mailbox
+                  read callback follows once the SPI host has read it.
+                  arg1: Whether to clear busy (0: false, != 0: true)
+                  arg2: Whether to clear write enable (0: false, != 0: true) */ => {
+                self.send_mailbox_data(caller_id, arg1 != 0, arg2 != 0)
+            }
+            21 /* Get the number of address mode switches since boot, whether
+                  handled in kernel space or set by an app with command 3.
+                  returns: Number of switches (wraps around) */ => {
+                ReturnCode::SuccessWithValue { value: self.address_mode_switches.get() }
+            }
+            22 /* Get the multi-line fast reads passed through to the external
+                  flash.
+                  returns: READ_CAPABILITY_* bits */ => {
+                ReturnCode::SuccessWithValue { value: self.device.get_read_capabilities() as usize }
+            }
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -321,7 +588,7 @@ impl<'a> Driver for SpiDeviceSyscall<'a> {
                             }
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::FAIL)
+                        .unwrap_or(ErrorCode::Fail.rcode())
                 }
                 1 => {
                     // RX Buffer
@@ -334,9 +601,18 @@ impl<'a> Driver for SpiDeviceSyscall<'a> {
                             }
                             ReturnCode::SUCCESS
                         })
-                        .unwrap_or(ReturnCode::FAIL)
+                        .unwrap_or(ErrorCode::Fail.rcode())
+                }
+                2 => {
+                    // Mailbox Buffer
+                    self.apps
+                        .enter(app_id, |app_data, _| {
+                            app_data.mailbox_buffer = slice;
+                            ReturnCode::SUCCESS
+                        })
+                        .unwrap_or(ErrorCode::Fail.rcode())
                 }
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 }
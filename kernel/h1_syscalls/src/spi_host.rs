@@ -1,41 +1,67 @@
 use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
 use h1::hil::spi_host::SpiHost;
+use h1::spi_host_lease::{Owner, SpiHostLease};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode, Shared, AppSlice};
 
 pub const DRIVER_NUM: usize = 0x40020;
 
+/// Lease taken on behalf of an app that configures the SPI host without
+/// acquiring it first.
+pub const DEFAULT_LEASE_MS: u32 = 1000;
+
 #[derive(Default)]
 pub struct AppData {
 }
 
 pub struct SpiHostSyscall<'a> {
     device: &'a dyn SpiHost,
+    lease: &'a SpiHostLease<'a>,
     apps: Grant<AppData>,
     current_user: Cell<Option<AppId>>,
 }
 
 impl<'a> SpiHostSyscall<'a> {
     pub fn new(device: &'a dyn SpiHost,
+               lease: &'a SpiHostLease<'a>,
                container: Grant<AppData>) -> SpiHostSyscall<'a> {
         SpiHostSyscall {
             device: device,
+            lease: lease,
             apps: container,
             current_user: Cell::new(None),
         }
     }
 
+    /// Makes sure that the caller holds the lease, acquiring a default one if
+    /// the SPI host is free.
+    fn check_lease(&self, caller_id: AppId) -> ReturnCode {
+        if self.lease.is_held_by(Owner::App(caller_id)) {
+            return ReturnCode::SUCCESS;
+        }
+        self.lease.acquire(Owner::App(caller_id), DEFAULT_LEASE_MS)
+    }
+
     fn spi_device_spi_host_passthrough(&self, caller_id: AppId, enable: bool) -> ReturnCode {
+        let rc = self.check_lease(caller_id);
+        if rc != ReturnCode::SUCCESS {
+            return rc;
+        }
         self.apps.enter(caller_id, |_app_data, _| {
             self.device.spi_device_spi_host_passthrough(enable);
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 
     fn wait_busy_clear_in_transactions(&self, caller_id: AppId, enable: bool) -> ReturnCode {
+        let rc = self.check_lease(caller_id);
+        if rc != ReturnCode::SUCCESS {
+            return rc;
+        }
         self.apps.enter(caller_id, |_app_data, _| {
             self.device.wait_busy_clear_in_transactions(enable);
             ReturnCode::SUCCESS
-        }).unwrap_or(ReturnCode::ENOMEM)
+        }).unwrap_or(ErrorCode::NoMem.rcode())
     }
 }
 
@@ -46,7 +72,7 @@ impl<'a> Driver for SpiHostSyscall<'a> {
                  _app_id: AppId,
     ) -> ReturnCode {
         match subscribe_num {
-            _ => ReturnCode::ENOSUPPORT
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -66,7 +92,18 @@ impl<'a> Driver for SpiHostSyscall<'a> {
                  arg1: 0: disable, != 0: enable) */ => {
                 self.wait_busy_clear_in_transactions(caller_id, arg1 != 0)
             },
-            _ => ReturnCode::ENOSUPPORT
+            3 /* Acquire or renew the lease on the SPI host.
+                 arg1: lease duration in milliseconds */ => {
+                self.lease.acquire(Owner::App(caller_id), arg1 as u32)
+            },
+            4 /* Release the lease on the SPI host. */ => {
+                self.lease.release(Owner::App(caller_id))
+            },
+            5 /* Get the number of acquires refused because the SPI host was
+                 leased to someone else. */ => {
+                ReturnCode::SuccessWithValue { value: self.lease.stats().contended as usize }
+            },
+            _ => ErrorCode::NoSupport.rcode()
         }
     }
 
@@ -76,7 +113,7 @@ impl<'a> Driver for SpiHostSyscall<'a> {
              _slice: Option<AppSlice<Shared, u8>>
     ) -> ReturnCode {
         match minor_num {
-            _ => ReturnCode::ENOSUPPORT,
+            _ => ErrorCode::NoSupport.rcode(),
         }
     }
 }
@@ -0,0 +1,310 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Queues SPI host transactions from several apps.
+//!
+//! `capsules::spi_controller` serves one transaction at a time and refuses
+//! the others with EBUSY. Here each app can have up to
+//! `MAX_QUEUED_TRANSACTIONS` transactions waiting. A transaction writes
+//! `len` bytes from the app's write buffer at `offset` and stores what was
+//! read at the same offset of its read buffer, so an app queueing several
+//! transactions gives each its own part of the buffers.
+//!
+//! Transactions run in the order they were queued, across all apps, and
+//! their completion callbacks are scheduled in that order.
+
+use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::spi::{SpiMasterClient, SpiMasterDevice};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+pub const DRIVER_NUM: usize = 0x401b0;
+
+/// Transactions each app may have waiting, not counting the one in flight.
+pub const MAX_QUEUED_TRANSACTIONS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Transaction {
+    offset: usize,
+    len: usize,
+    /// Position in the order transactions were queued in.
+    ticket: u32,
+}
+
+#[derive(Default)]
+pub struct AppData {
+    write_buffer: Option<AppSlice<Shared, u8>>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    done_callback: Option<Callback>,
+    queue: [Option<Transaction>; MAX_QUEUED_TRANSACTIONS],
+    head: usize,
+    queued: usize,
+}
+
+impl AppData {
+    fn front(&self) -> Option<Transaction> {
+        if self.queued == 0 {
+            return None;
+        }
+        self.queue[self.head]
+    }
+
+    fn push(&mut self, transaction: Transaction) -> bool {
+        if self.queued == MAX_QUEUED_TRANSACTIONS {
+            return false;
+        }
+        self.queue[(self.head + self.queued) % MAX_QUEUED_TRANSACTIONS] = Some(transaction);
+        self.queued += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<Transaction> {
+        if self.queued == 0 {
+            return None;
+        }
+        let transaction = self.queue[self.head].take();
+        self.head = (self.head + 1) % MAX_QUEUED_TRANSACTIONS;
+        self.queued -= 1;
+        transaction
+    }
+
+    fn done(&self, return_code: ReturnCode, transaction: &Transaction) {
+        self.done_callback.map(|mut cb| cb.schedule(
+            usize::from(return_code), transaction.offset, transaction.len));
+    }
+}
+
+pub struct SpiHostQueue<'a, S: SpiMasterDevice> {
+    device: &'a S,
+    apps: Grant<AppData>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    /// The app whose transaction is on the SPI host.
+    current: OptionalCell<(AppId, Transaction)>,
+    next_ticket: Cell<u32>,
+    max_len: usize,
+}
+
+impl<'a, S: SpiMasterDevice> SpiHostQueue<'a, S> {
+    /// The shorter of `write_buffer` and `read_buffer` bounds the length of
+    /// a transaction.
+    pub fn new(device: &'a S,
+               write_buffer: &'static mut [u8],
+               read_buffer: &'static mut [u8],
+               container: Grant<AppData>) -> SpiHostQueue<'a, S> {
+        let max_len = write_buffer.len().min(read_buffer.len());
+        SpiHostQueue {
+            device: device,
+            apps: container,
+            write_buffer: TakeCell::new(write_buffer),
+            read_buffer: TakeCell::new(read_buffer),
+            current: OptionalCell::empty(),
+            next_ticket: Cell::new(0),
+            max_len: max_len,
+        }
+    }
+
+    fn enqueue(&self, caller_id: AppId, offset: usize, len: usize) -> ReturnCode {
+        if len == 0 || len > self.max_len {
+            return ErrorCode::Size.rcode();
+        }
+        let end = match offset.checked_add(len) {
+            Some(end) => end,
+            None => return ErrorCode::Invalid.rcode(),
+        };
+
+        let rc = self.apps.enter(caller_id, |app_data, _| {
+            let fits = |slice: &Option<AppSlice<Shared, u8>>| {
+                slice.as_ref().map_or(false, |slice| end <= slice.len())
+            };
+            if !fits(&app_data.write_buffer) || !fits(&app_data.read_buffer) {
+                return ErrorCode::Size.rcode();
+            }
+            let ticket = self.next_ticket.get();
+            if !app_data.push(Transaction { offset: offset, len: len, ticket: ticket }) {
+                return ErrorCode::Busy.rcode();
+            }
+            self.next_ticket.set(ticket.wrapping_add(1));
+            ReturnCode::SUCCESS
+        }).unwrap_or(ErrorCode::NoMem.rcode());
+
+        if rc == ReturnCode::SUCCESS && self.current.is_none() {
+            self.start_next();
+        }
+        rc
+    }
+
+    /// Returns the app with the transaction that was queued first.
+    fn oldest_app(&self) -> Option<AppId> {
+        // All waiting tickets lie in the `MAX_QUEUED_TRANSACTIONS` per app
+        // before `next_ticket`, so their age is well defined across wraps.
+        let next_ticket = self.next_ticket.get();
+        let oldest: Cell<Option<(AppId, u32)>> = Cell::new(None);
+        self.apps.each(|app_data| {
+            if let Some(transaction) = app_data.front() {
+                let age = next_ticket.wrapping_sub(transaction.ticket);
+                if oldest.get().map_or(true, |(_, oldest_age)| age > oldest_age) {
+                    oldest.set(Some((app_data.appid(), age)));
+                }
+            }
+        });
+        oldest.get().map(|(app_id, _)| app_id)
+    }
+
+    /// Starts the oldest waiting transaction. Transactions whose app buffers
+    /// went away since they were queued are completed with ESIZE.
+    fn start_next(&self) {
+        while self.current.is_none() {
+            let app_id = match self.oldest_app() {
+                Some(app_id) => app_id,
+                None => return,
+            };
+            let _ = self.apps.enter(app_id, |app_data, _| {
+                let transaction = match app_data.pop() {
+                    Some(transaction) => transaction,
+                    None => return,
+                };
+                let range = transaction.offset..transaction.offset + transaction.len;
+                let fits = app_data.read_buffer.as_ref()
+                    .map_or(false, |slice| range.end <= slice.len());
+                let source = match app_data.write_buffer {
+                    Some(ref slice) if fits && range.end <= slice.len() => slice,
+                    _ => {
+                        app_data.done(ErrorCode::Size.rcode(), &transaction);
+                        return;
+                    }
+                };
+                let (write_buffer, read_buffer) =
+                    match (self.write_buffer.take(), self.read_buffer.take()) {
+                        (Some(write_buffer), Some(read_buffer)) => (write_buffer, read_buffer),
+                        (write_buffer, read_buffer) => {
+                            if let Some(buffer) = write_buffer {
+                                self.write_buffer.replace(buffer);
+                            }
+                            if let Some(buffer) = read_buffer {
+                                self.read_buffer.replace(buffer);
+                            }
+                            app_data.done(ErrorCode::Busy.rcode(), &transaction);
+                            return;
+                        }
+                    };
+                write_buffer[..transaction.len].copy_from_slice(&source.as_ref()[range]);
+                self.current.set((app_id, transaction));
+                let rc = self.device.read_write_bytes(write_buffer, Some(read_buffer),
+                                                      transaction.len);
+                if rc != ReturnCode::SUCCESS {
+                    // The virtual SPI device queues every transaction, so
+                    // this should not happen. If it does the buffers are
+                    // lost and later transactions complete with EBUSY.
+                    self.current.clear();
+                    app_data.done(rc, &transaction);
+                }
+            });
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice> SpiMasterClient for SpiHostQueue<'a, S> {
+    fn read_write_done(&self,
+                       write_buffer: &'static mut [u8],
+                       read_buffer: Option<&'static mut [u8]>,
+                       len: usize) {
+        self.write_buffer.replace(write_buffer);
+        if let Some((app_id, transaction)) = self.current.take() {
+            let _ = self.apps.enter(app_id, |app_data, _| {
+                let range = transaction.offset..transaction.offset + len;
+                if let (Some(dest), Some(source)) = (app_data.read_buffer.as_mut(), read_buffer.as_ref()) {
+                    if range.end <= dest.len() && len <= source.len() {
+                        dest.as_mut()[range].copy_from_slice(&source[..len]);
+                    }
+                }
+                app_data.done(ReturnCode::SUCCESS, &transaction);
+            });
+        }
+        if let Some(buffer) = read_buffer {
+            self.read_buffer.replace(buffer);
+        }
+        self.start_next();
+    }
+}
+
+impl<'a, S: SpiMasterDevice> Driver for SpiHostQueue<'a, S> {
+    fn subscribe(&self,
+                 subscribe_num: usize,
+                 callback: Option<Callback>,
+                 app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 /* Transaction done
+                 Callback arguments:
+                 arg1: kernel::ReturnCode
+                 arg2: offset of the transaction
+                 arg3: length of the transaction */ => {
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.done_callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, caller_id: AppId)
+        -> ReturnCode {
+        match command_num {
+            0 /* Check if present */ => ReturnCode::SUCCESS,
+            1 /* Queue a transaction. Returns EBUSY if the app already has
+                 MAX_QUEUED_TRANSACTIONS waiting.
+                 arg1: offset in the write and read buffers
+                 arg2: number of bytes to transfer */ => {
+                self.enqueue(caller_id, arg1, arg2)
+            },
+            2 /* Get the number of transactions the app has waiting. */ => {
+                self.apps.enter(caller_id, |app_data, _| {
+                    ReturnCode::SuccessWithValue { value: app_data.queued }
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            3 /* Get the longest transaction. */ => {
+                ReturnCode::SuccessWithValue { value: self.max_len }
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn allow(&self,
+             app_id: AppId,
+             minor_num: usize,
+             slice: Option<AppSlice<Shared, u8>>
+    ) -> ReturnCode {
+        match minor_num {
+            0 /* Write buffer */ => {
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.write_buffer = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            1 /* Read buffer */ => {
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.read_buffer = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            _ => ErrorCode::NoSupport.rcode(),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+//! Debug syscall driver for the stack high-water marks.
+//!
+//! Processes are numbered in the order they were loaded, starting at 0.
+//!
+//! The driver implements 6 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. get the size of the kernel stack, in bytes
+//!   2. get the deepest the kernel stack has been since boot, in bytes
+//!   3. get the number of processes
+//!   4. get the number of bytes at the bottom of the memory of process arg1
+//!      that have never been used
+//!   5. get the memory size of process arg1, in bytes
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::stack_usage;
+use kernel::procs::ProcessType;
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x40110;
+
+const COMMAND_CHECK: usize               = 0;
+const COMMAND_GET_KERNEL_SIZE: usize     = 1;
+const COMMAND_GET_KERNEL_USED: usize     = 2;
+const COMMAND_GET_PROCESS_COUNT: usize   = 3;
+const COMMAND_GET_PROCESS_UNUSED: usize  = 4;
+const COMMAND_GET_PROCESS_SIZE: usize    = 5;
+
+pub struct StackUsageSyscall {
+    processes: &'static [Option<&'static dyn ProcessType>],
+}
+
+impl StackUsageSyscall {
+    pub const fn new(processes: &'static [Option<&'static dyn ProcessType>]) -> StackUsageSyscall {
+        StackUsageSyscall {
+            processes: processes,
+        }
+    }
+
+    fn process_memory(&self, index: usize) -> Option<&'static [u8]> {
+        let process = self.processes.iter().flatten().nth(index)?;
+        let start = process.mem_start();
+        let len = process.mem_end() as usize - start as usize;
+        Some(unsafe { core::slice::from_raw_parts(start, len) })
+    }
+}
+
+impl Driver for StackUsageSyscall {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize, _app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_GET_KERNEL_SIZE => ReturnCode::SuccessWithValue {
+                value: stack_usage::kernel_stack_size()
+            },
+            COMMAND_GET_KERNEL_USED => ReturnCode::SuccessWithValue {
+                value: stack_usage::kernel_stack_used()
+            },
+            COMMAND_GET_PROCESS_COUNT => ReturnCode::SuccessWithValue {
+                value: self.processes.iter().flatten().count()
+            },
+            COMMAND_GET_PROCESS_UNUSED => match self.process_memory(arg1) {
+                Some(memory) => ReturnCode::SuccessWithValue {
+                    value: stack_usage::unused_bytes(memory)
+                },
+                None => ErrorCode::Invalid.rcode(),
+            },
+            COMMAND_GET_PROCESS_SIZE => match self.process_memory(arg1) {
+                Some(memory) => ReturnCode::SuccessWithValue { value: memory.len() },
+                None => ErrorCode::Invalid.rcode(),
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use core::convert::TryInto;
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::hil::timebase::Timebase;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode, Shared, AppSlice};
+use kernel::procs::ProcessType;
+
+pub const DRIVER_NUM: usize = 0x40080;
+
+#[derive(Default)]
+pub struct AppData {
+    time_buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct TimebaseSyscall<'a> {
+    timebase: &'a dyn Timebase,
+    processes: &'static [Option<&'static dyn ProcessType>],
+    /// Name of the only app allowed to set the time.
+    time_source: &'static str,
+    apps: Grant<AppData>,
+}
+
+impl<'a> TimebaseSyscall<'a> {
+    pub fn new(timebase: &'a dyn Timebase,
+               processes: &'static [Option<&'static dyn ProcessType>],
+               time_source: &'static str,
+               container: Grant<AppData>) -> TimebaseSyscall<'a> {
+        TimebaseSyscall {
+            timebase: timebase,
+            processes: processes,
+            time_source: time_source,
+            apps: container,
+        }
+    }
+
+    fn is_time_source(&self, app_id: AppId) -> bool {
+        self.processes.iter().flatten()
+            .find(|process| process.appid() == app_id)
+            .map_or(false, |process| process.get_process_name() == self.time_source)
+    }
+
+    fn get_time(&self, caller_id: AppId) -> ReturnCode {
+        let unix_time_ms = match self.timebase.get_unix_time_ms() {
+            None => return ErrorCode::Off.rcode(),
+            Some(value) => value,
+        };
+        self.apps.enter(caller_id, |app_data, _| {
+            match app_data.time_buffer {
+                None => ErrorCode::Size.rcode(),
+                Some(ref mut time_buffer) => {
+                    match time_buffer.as_mut().get_mut(..8) {
+                        None => ErrorCode::Size.rcode(),
+                        Some(dest) => {
+                            dest.copy_from_slice(&unix_time_ms.to_be_bytes());
+                            ReturnCode::SUCCESS
+                        }
+                    }
+                }
+            }
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+
+    fn set_time(&self, caller_id: AppId) -> ReturnCode {
+        if !self.is_time_source(caller_id) {
+            return ErrorCode::Reserve.rcode();
+        }
+        self.apps.enter(caller_id, |app_data, _| {
+            match app_data.time_buffer {
+                None => ErrorCode::Size.rcode(),
+                Some(ref time_buffer) => {
+                    match time_buffer.as_ref().get(..8) {
+                        None => ErrorCode::Size.rcode(),
+                        Some(src) => {
+                            // The slice is guaranteed to be 8 bytes long.
+                            let unix_time_ms = u64::from_be_bytes(src.try_into().unwrap());
+                            self.timebase.set_unix_time_ms(unix_time_ms);
+                            ReturnCode::SUCCESS
+                        }
+                    }
+                }
+            }
+        }).unwrap_or(ErrorCode::NoMem.rcode())
+    }
+}
+
+impl<'a> Driver for TimebaseSyscall<'a> {
+    fn subscribe(&self,
+                 subscribe_num: usize,
+                 _callback: Option<Callback>,
+                 _app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn command(&self, command_num: usize, _arg1: usize, _arg2: usize, caller_id: AppId)
+        -> ReturnCode {
+        match command_num {
+            0 /* Check if present */ => ReturnCode::SUCCESS,
+            1 /* Get UNIX time in ms and write to time buffer in BE notation.
+                 Returns EOFF if the timebase was never synchronized. */ => {
+                self.get_time(caller_id)
+            },
+            2 /* Set UNIX time in ms from time buffer in BE notation.
+                 Returns ERESERVE unless called by the time source app. */ => {
+                self.set_time(caller_id)
+            },
+            3 /* Get estimated drift in ppm */ => {
+                ReturnCode::SuccessWithValue {
+                    value: self.timebase.get_drift_ppm() as usize
+                }
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+
+    fn allow(&self,
+             app_id: AppId,
+             minor_num: usize,
+             slice: Option<AppSlice<Shared, u8>>
+    ) -> ReturnCode {
+        match minor_num {
+            0 => {
+                // Buffer for UNIX time in ms (64 bit in BE notation)
+                self.apps
+                    .enter(app_id, |app_data, _| {
+                        app_data.time_buffer = slice;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or(ErrorCode::Fail.rcode())
+            }
+            _ => ErrorCode::NoSupport.rcode(),
+        }
+    }
+}
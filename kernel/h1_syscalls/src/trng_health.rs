@@ -0,0 +1,72 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for the TRNG health tests (see h1::trng).
+//!
+//! The driver implements 6 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. get the health state: 0 healthy, 1 start-up tests running,
+//!      2 repetition count test failed, 3 adaptive proportion test failed
+//!   2. get the number of words tested since boot
+//!   3. get the longest run of identical words seen
+//!   4. get the highest count of a window's first word seen in its window
+//!   5. get the fraction of one bits in the tested words, in 1/1000
+
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::trng::{HealthTest, Trng};
+use kernel::{AppId, Driver, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x40190;
+
+const COMMAND_CHECK: usize               = 0;
+const COMMAND_GET_STATE: usize           = 1;
+const COMMAND_GET_SAMPLES: usize         = 2;
+const COMMAND_GET_MAX_REPETITIONS: usize = 3;
+const COMMAND_GET_MAX_PROPORTION: usize  = 4;
+const COMMAND_GET_ONES_PERMILLE: usize   = 5;
+
+pub struct TrngHealthSyscall<'a> {
+    trng: &'a Trng<'a>,
+}
+
+impl<'a> TrngHealthSyscall<'a> {
+    pub fn new(trng: &'a Trng<'a>) -> TrngHealthSyscall<'a> {
+        TrngHealthSyscall {
+            trng: trng,
+        }
+    }
+}
+
+impl<'a> Driver for TrngHealthSyscall<'a> {
+    fn command(&self, command_num: usize, _arg1: usize, _arg2: usize, _app_id: AppId) -> ReturnCode {
+        let status = self.trng.health_status();
+        let value = match command_num {
+            COMMAND_CHECK => return ReturnCode::SUCCESS,
+            COMMAND_GET_STATE => match status.failure {
+                None if status.in_startup => 1,
+                None => 0,
+                Some(HealthTest::RepetitionCount) => 2,
+                Some(HealthTest::AdaptiveProportion) => 3,
+            },
+            COMMAND_GET_SAMPLES => status.samples,
+            COMMAND_GET_MAX_REPETITIONS => status.max_repetitions,
+            COMMAND_GET_MAX_PROPORTION => status.max_proportion,
+            COMMAND_GET_ONES_PERMILLE => status.ones_permille(),
+            _ => return ErrorCode::NoSupport.rcode(),
+        };
+        ReturnCode::SuccessWithValue { value: value as usize }
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Syscall driver for app supervision by the hardware watchdog.
+//!
+//! An app that registers a pet interval must pet the watchdog at least that
+//! often. While any registered app is late, the kernel stops petting the
+//! hardware watchdog and the chip resets (see h1::watchdog).
+//!
+//! The driver implements 5 commands:
+//!   0. check if the driver is present (ReturnCode::SUCCESS if so)
+//!   1. register a pet interval of arg1 milliseconds, or unregister if arg1
+//!      is 0. The first pet is due one interval from now. Fails with
+//!      ErrorCode::Invalid if arg1 exceeds MAX_INTERVAL_MS.
+//!   2. pet the watchdog. Fails with ErrorCode::Off if the app has not
+//!      registered an interval.
+//!   3. get the hardware watchdog timeout in milliseconds (0 if disabled)
+//!   4. get the process slot of the app that was late when the watchdog
+//!      reset the chip before this boot. Fails with ErrorCode::Off if the
+//!      last reset was not caused by a late app.
+
+use core::cell::Cell;
+use crate::error::{ErrorCode, IntoReturnCode};
+use h1::timeus::Timeus;
+use h1::watchdog::{Liveness, Watchdog};
+use kernel::{AppId, Driver, Grant, ReturnCode};
+
+pub const DRIVER_NUM: usize = 0x40140;
+
+const COMMAND_CHECK: usize         = 0;
+const COMMAND_REGISTER: usize      = 1;
+const COMMAND_PET: usize           = 2;
+const COMMAND_GET_TIMEOUT: usize   = 3;
+const COMMAND_GET_LATE_APP: usize  = 4;
+
+/// The longest pet interval an app can register. Keeps the interval well
+/// within the range of the 32-bit clock.
+pub const MAX_INTERVAL_MS: usize = 60_000;
+
+#[derive(Default)]
+pub struct AppData {
+    /// The pet interval, in clock ticks. 0 if the app is not supervised.
+    interval: u32,
+    /// The clock value at the last pet.
+    last_pet: u32,
+}
+
+pub struct WatchdogSyscall<'a> {
+    clock: &'a Timeus,
+    clock_hz: u32,
+    timeout_ms: u32,
+    watchdog: &'a Watchdog,
+    apps: Grant<AppData>,
+}
+
+impl<'a> WatchdogSyscall<'a> {
+    /// `clock` must be a running counter incrementing at `clock_hz`.
+    /// `timeout_ms` is the timeout `watchdog` was set up with.
+    pub fn new(clock: &'a Timeus,
+               clock_hz: u32,
+               timeout_ms: u32,
+               watchdog: &'a Watchdog,
+               container: Grant<AppData>) -> WatchdogSyscall<'a> {
+        WatchdogSyscall {
+            clock: clock,
+            clock_hz: clock_hz,
+            timeout_ms: timeout_ms,
+            watchdog: watchdog,
+            apps: container,
+        }
+    }
+}
+
+impl<'a> Liveness for WatchdogSyscall<'a> {
+    fn late_app(&self) -> Option<usize> {
+        let now = self.clock.now();
+        let late = Cell::new(None);
+        self.apps.each(|app_data| {
+            if app_data.interval != 0 && now.wrapping_sub(app_data.last_pet) > app_data.interval {
+                late.set(Some(app_data.appid().idx()));
+            }
+        });
+        late.get()
+    }
+}
+
+impl<'a> Driver for WatchdogSyscall<'a> {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize, app_id: AppId) -> ReturnCode {
+        match command_num {
+            COMMAND_CHECK => ReturnCode::SUCCESS,
+            COMMAND_REGISTER => {
+                if arg1 > MAX_INTERVAL_MS {
+                    return ErrorCode::Invalid.rcode();
+                }
+                let interval = (self.clock_hz as u64 * arg1 as u64 / 1000) as u32;
+                let now = self.clock.now();
+                self.apps.enter(app_id, |app_data, _| {
+                    app_data.interval = interval;
+                    app_data.last_pet = now;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            COMMAND_PET => {
+                let now = self.clock.now();
+                self.apps.enter(app_id, |app_data, _| {
+                    if app_data.interval == 0 {
+                        return ErrorCode::Off.rcode();
+                    }
+                    app_data.last_pet = now;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ErrorCode::NoMem.rcode())
+            },
+            COMMAND_GET_TIMEOUT => ReturnCode::SuccessWithValue { value: self.timeout_ms as usize },
+            COMMAND_GET_LATE_APP => match self.watchdog.late_app_before_reset() {
+                Some(slot) => ReturnCode::SuccessWithValue { value: slot },
+                None => ErrorCode::Off.rcode(),
+            },
+            _ => ErrorCode::NoSupport.rcode()
+        }
+    }
+}
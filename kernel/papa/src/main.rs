@@ -31,7 +31,6 @@ use capsules::virtual_alarm::VirtualMuxAlarm;
 use capsules::virtual_spi::VirtualSpiMasterDevice;
 use capsules::virtual_uart::UartDevice;
 
-use components::spi::SpiSyscallComponent;
 
 
 use kernel::{Chip, Platform};
@@ -43,22 +42,108 @@ use kernel::hil::entropy::Entropy32;
 use kernel::hil::gpio::Configure;
 use kernel::hil::gpio::Output;
 use kernel::hil::rng::Rng;
+use kernel::hil::spi::SpiMasterDevice;
 use kernel::mpu::MPU;
 
 use h1::crypto::dcrypto::Dcrypto;
+use h1::hil::board_config::ConfigStore;
 use h1::hil::flash::Flash;
+use h1::hil::globalsec::GlobalSec;
 use h1::hil::spi_device::SpiDevice;
+use h1::hil::spi_host::SpiHost;
+use h1::irq_priority::{InterruptGroup, InterruptPriority};
+use h1::nvcounter::{FlashCounter, NvCounter};
 use h1::timels::Timels;
+use h1::virtual_gpio::{Access, MuxGpioPin, VirtualGpioPin};
 
 use spiutils::driver::firmware::SegmentInfo;
+use spiutils::driver::spi_device::HandlerMode;
 use spiutils::protocol::firmware::SegmentAndLocation;
 
 // State for loading apps
 const NUM_PROCS: usize = 1;
 
+// The userspace GPIO driver's view of a shared pin.
+type AppGpioPin = VirtualGpioPin<'static, h1::gpio::GPIOPin>;
+
+// Frequency of `timerhs`, which is started with a divider of 1.
+const TIMERHS_HZ: u32 = 24_000_000;
+
+// SPI_HOST0 as seen by capsules::spi_controller.
+type AppSpiHost = h1::spi_host_lease::LeasedSpiMaster<'static, h1::spi_host::SpiHostHardware>;
+
+// Flash pages recorded in the flash audit journal.
+const JOURNALED_PAGES: [usize; 4] = [
+    h1::board_config::CONFIG_PAGES[0],
+    h1::board_config::CONFIG_PAGES[1],
+    h1::personality::PERSONALITY_PAGES[0],
+    h1::personality::PERSONALITY_PAGES[1],
+];
+
+// Boot into failsafe after this many boots in a row that did not pass the
+// app's health check.
+const MAX_FAILED_BOOTS: usize = 3;
+
+// In failsafe mode the SPI host can program and erase the inactive RW
+// segment at this address, in 4 byte address mode. It is right above the
+// 64MB of flash that otpilot passes through.
+const FAILSAFE_UPDATE_BASE: u32 = 0x4000000;
+
+// Set to true to lock the board down (see h1::lockdown) as soon as the app
+// passes its health check. This stops the SWD port, so leave it off while
+// debugging.
+const LOCK_DOWN_WHEN_HEALTHY: bool = false;
+
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
 
+// How the kernel responds when a process faults, for apps that need a
+// different response than FAULT_RESPONSE. otpilot serves the BMC, so bring
+// it back rather than stopping the chip.
+const APP_FAULT_RESPONSES: &[h1::process_loader::AppFaultResponse] = &[
+    h1::process_loader::AppFaultResponse {
+        name: "otpilot",
+        response: kernel::procs::FaultResponse::Restart,
+    },
+];
+
+// Interrupts on one NVIC line within IRQ_STATS_WINDOW_MS that are logged as
+// an interrupt storm. USB SOF alone raises 1000 interrupts per second.
+const IRQ_STATS_WINDOW_MS: u32 = 1000;
+const IRQ_STORM_THRESHOLD: u16 = 5000;
+
+// Set to true to prefix every console line with the time since boot from
+// the start. Apps can also turn this on and off at runtime.
+const CONSOLE_TIMESTAMPS: bool = false;
+
+// The hardware watchdog resets the chip when the kernel, or an app that
+// registered a pet interval, stops running for twice this long. 0 disables it.
+const WATCHDOG_TIMEOUT_MS: u32 = 1000;
+
+// When this reset monitor pin asserts, the kernel disables SPI passthrough
+// from the GPIO interrupt instead of waiting for an app to do it. The value
+// is the index into gpio0; None leaves isolation to the apps.
+const PASSTHROUGH_GUARD: Option<(usize, h1::passthrough_guard::Polarity)> =
+    Some((2 /* SYS_RSTMON# */, h1::passthrough_guard::Polarity::ActiveLow));
+
+// How often the entropy pool reseeds its DRBG from the TRNG, and whether it
+// keeps serving DRBG output when the TRNG stops delivering.
+const ENTROPY_RESEED_POLICY: h1::entropy_pool::ReseedPolicy =
+    h1::entropy_pool::DEFAULT_RESEED_POLICY;
+
+// NVIC interrupt priorities: SPI device and the passthrough guard's pin
+// first, then USB, then timers.
+const INTERRUPT_PRIORITIES: &[h1::irq_priority::InterruptGroup] = &[
+    // SPI device command/address FIFO not empty.
+    InterruptGroup { first: 131, last: 131, priority: InterruptPriority::Critical },
+    // GPIO0 pin 2 (SYS_RSTMON#), see PASSTHROUGH_GUARD.
+    InterruptGroup { first: 67, last: 67, priority: InterruptPriority::Critical },
+    // USB0.
+    InterruptGroup { first: 193, last: 193, priority: InterruptPriority::High },
+    // TIMELS0 and TIMELS1.
+    InterruptGroup { first: 159, last: 160, priority: InterruptPriority::Normal },
+];
+
 // Used by panic_fmt to print chip-specific debugging information.
 static mut CHIP: Option<&'static h1::chip::Hotel> = None;
 
@@ -67,8 +152,9 @@ static mut CHIP: Option<&'static h1::chip::Hotel> = None;
 #[panic_handler]
 pub unsafe extern "C" fn panic_fmt(pi: &core::panic::PanicInfo) -> ! {
     // Use an unused GPIO
-    let led = &mut kernel::hil::led::LedLow::new(&mut h1::gpio::PORT1.pins[15]);
-    let writer = &mut h1::io::WRITER;
+    let led_pin = &mut h1::gpio::GPIOPin::new(h1::gpio::GPIO1_BASE, h1::gpio::Pin::P15);
+    let led = &mut kernel::hil::led::LedLow::new(led_pin);
+    let writer = &mut h1::io::Writer;
     kernel::debug::panic(&mut [led], writer, pi, &cortexm3::support::nop, &crate::PROCESSES, &CHIP)
 }
 
@@ -84,25 +170,46 @@ pub static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
 
 pub struct Papa {
     console: &'static capsules::console::Console<'static>,
-    gpio: &'static capsules::gpio::GPIO<'static, h1::gpio::GPIOPin>,
+    gpio: &'static capsules::gpio::GPIO<'static, AppGpioPin>,
     timer: &'static AlarmDriver<'static, VirtualMuxAlarm<'static, Timels>>,
     ipc: kernel::ipc::IPC<NUM_PROCS>,
     digest: &'static h1_syscalls::digest::DigestDriver<'static, h1::crypto::sha::ShaEngine>,
     aes: &'static h1_syscalls::aes::AesDriver<'static>,
+    hkdf: &'static h1_syscalls::hkdf::HkdfSyscall<'static>,
+    keyladder: &'static h1_syscalls::keyladder::KeyLadderSyscall<'static>,
+    personality: &'static h1_syscalls::personality::PersonalitySyscall<'static>,
     rng: &'static capsules::rng::RngDriver<'static>,
+    entropy_pool_syscalls: &'static h1_syscalls::entropy_pool::EntropyPoolSyscall<'static>,
+    trng_health_syscalls: &'static h1_syscalls::trng_health::TrngHealthSyscall<'static>,
     h1_spi_host_syscalls: &'static h1_syscalls::spi_host::SpiHostSyscall<'static>,
     h1_spi_device_syscalls: &'static h1_syscalls::spi_device::SpiDeviceSyscall<'static>,
     spi_host_syscalls: &'static capsules::spi_controller::Spi<
-        'static, VirtualSpiMasterDevice<'static, h1::spi_host::SpiHostHardware>>,
+        'static, VirtualSpiMasterDevice<'static, AppSpiHost>>,
+    spi_host_queue_syscalls: &'static h1_syscalls::spi_host_queue::SpiHostQueue<
+        'static, VirtualSpiMasterDevice<'static, AppSpiHost>>,
     dcrypto: &'static h1_syscalls::dcrypto::DcryptoDriver<'static>,
-    low_level_debug: &'static capsules::low_level_debug::LowLevelDebug<
-        'static,
-        capsules::virtual_uart::UartDevice<'static>
-    >,
+    rsa: &'static h1_syscalls::rsa::RsaSyscall<'static>,
+    low_level_debug: &'static h1_syscalls::low_level_debug::LowLevelDebugExt<'static>,
     flash_syscalls: &'static h1_syscalls::flash::FlashSyscalls<'static >,
     fuse_syscalls: &'static h1_syscalls::fuse::FuseSyscall<'static>,
     globalsec_syscalls: &'static h1_syscalls::globalsec::GlobalSecSyscall<'static>,
+    lockdown_syscalls: &'static h1_syscalls::lockdown::LockdownSyscall<'static>,
     reset_syscalls: &'static h1_syscalls::reset::ResetSyscall<'static>,
+    timebase_syscalls: &'static h1_syscalls::timebase::TimebaseSyscall<'static>,
+    board_config_syscalls: &'static h1_syscalls::board_config::BoardConfigSyscall<'static>,
+    boot_attempts_syscalls: &'static h1_syscalls::boot_attempts::BootAttemptsSyscall<'static>,
+    fault_stats_syscalls: &'static h1_syscalls::fault_stats::FaultStatsSyscall,
+    crypto_stats_syscalls: &'static h1_syscalls::crypto_stats::CryptoStatsSyscall,
+    passthrough_guard_syscalls:
+        Option<&'static h1_syscalls::passthrough_guard::PassthroughGuardSyscall<'static>>,
+    irq_stats_syscalls: &'static h1_syscalls::irq_stats::IrqStatsSyscall<'static, VirtualMuxAlarm<'static, Timels>>,
+    console_timestamps_syscalls: &'static h1_syscalls::console_timestamps::ConsoleTimestampsSyscall<'static>,
+    stack_usage_syscalls: &'static h1_syscalls::stack_usage::StackUsageSyscall,
+    watchdog_syscalls: &'static h1_syscalls::watchdog::WatchdogSyscall<'static>,
+    irq_latency_syscalls: &'static h1_syscalls::irq_latency::IrqLatencySyscall<'static>,
+    soft_pwm_syscalls: &'static h1_syscalls::soft_pwm::SoftPwmSyscall<
+        'static, VirtualMuxAlarm<'static, Timels>>,
+    rate_limiter: &'static h1_syscalls::rate_limit::RateLimiter<'static>,
 }
 
 fn get_h1_flash_segment_info(identifier: SegmentAndLocation, address: u32, size: u32) -> SegmentInfo {
@@ -122,22 +229,28 @@ pub unsafe fn reset_handler() {
 
     h1::init();
 
+    let peripherals = static_init!(h1::peripherals::Peripherals, h1::peripherals::Peripherals::new());
+
     let timerhs = {
         use h1::pmu::*;
         use h1::timeus::Timeus;
         Clock::new(PeripheralClock::Bank1(PeripheralClock1::TimeUs0Timer)).enable();
         Clock::new(PeripheralClock::Bank1(PeripheralClock1::TimeLs0)).enable();
-        let timer = Timeus::new(0);
-        timer
+        static_init!(Timeus, Timeus::new(0))
     };
 
     timerhs.start();
     let start = timerhs.now();
 
+    // Board tunables, read from flash before any driver is configured.
+    let board_config = h1::board_config::load();
+
     {
         use h1::pmu::*;
         Clock::new(PeripheralClock::Bank0(PeripheralClock0::Gpio0)).enable();
-        let pinmux = &mut *h1::pinmux::PINMUX;
+        // Status LED.
+        Clock::new(PeripheralClock::Bank0(PeripheralClock0::Gpio1)).enable();
+        let pinmux = &peripherals.pinmux;
 
         const GPIO_INPUT_EN: u32 = 1 << 2;
         const GPIO_PULLUP_EN: u32 = 1 << 4;
@@ -173,19 +286,19 @@ pub unsafe fn reset_handler() {
         pinmux.dioa2.control.set(GPIO_INPUT_EN | GPIO_PULLUP_EN);
     }
 
-    let gpio_bmc_srst_n = &h1::gpio::PORT0.pins[0];
+    let gpio_bmc_srst_n = &peripherals.gpio0.pins[0];
     gpio_bmc_srst_n.clear();
     let _ = gpio_bmc_srst_n.make_output();
 
-    let gpio_bmc_cpu_rst_n = &h1::gpio::PORT0.pins[1];
+    let gpio_bmc_cpu_rst_n = &peripherals.gpio0.pins[1];
     gpio_bmc_cpu_rst_n.clear();
     let _ = gpio_bmc_cpu_rst_n.make_output();
 
-    let gpio_sys_rstmon_n = &h1::gpio::PORT0.pins[2];
+    let gpio_sys_rstmon_n = &peripherals.gpio0.pins[2];
     gpio_sys_rstmon_n.clear();
     let _ = gpio_sys_rstmon_n.make_input();
 
-    let gpio_bmc_rstmon_n = &h1::gpio::PORT0.pins[3];
+    let gpio_bmc_rstmon_n = &peripherals.gpio0.pins[3];
     gpio_bmc_rstmon_n.clear();
     let _ = gpio_bmc_rstmon_n.make_input();
 
@@ -205,13 +318,15 @@ pub unsafe fn reset_handler() {
     );
     DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
 
-    let uart_mux = components::console::UartMuxComponent::new(&h1::uart::UART0, 115200, dynamic_deferred_caller)
+    let uart_mux = components::console::UartMuxComponent::new(&peripherals.uart0,
+                                                             board_config.config.console_baud,
+                                                             dynamic_deferred_caller)
         .finalize(());
-    hil::uart::Transmit::set_transmit_client(&h1::uart::UART0, uart_mux);
+    hil::uart::Transmit::set_transmit_client(&peripherals.uart0, uart_mux);
 
     // Configure UART speed
-    let uart = &h1::uart::UART0;
-    uart.config(115200);
+    let uart = &peripherals.uart0;
+    uart.config(board_config.config.console_baud);
 
     // Create virtual device for console.
     let console_uart = static_init!(UartDevice, UartDevice::new(uart_mux, true));
@@ -232,9 +347,16 @@ pub unsafe fn reset_handler() {
     // Create virtual device for kernel debug.
     components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
 
+    // Process console on the console UART. Its `list` command names each app
+    // by the package name from its TBF header.
+    let process_console =
+        components::process_console::ProcessConsoleComponent::new(kernel, uart_mux)
+        .finalize(());
+
     // LowLevelDebug driver
-    static mut LOW_LEVEL_DEBUG_BUF: [u8; capsules::low_level_debug::BUF_LEN] =
-        [0; capsules::low_level_debug::BUF_LEN];
+    let low_level_debug_buffer = static_init!(
+        [u8; capsules::low_level_debug::BUF_LEN],
+        [0; capsules::low_level_debug::BUF_LEN]);
     let low_level_debug_uart = static_init!(UartDevice, UartDevice::new(uart_mux, false));
     low_level_debug_uart.setup();
     let low_level_debug = static_init!(
@@ -243,25 +365,57 @@ pub unsafe fn reset_handler() {
             capsules::virtual_uart::UartDevice<'static>
         >,
         capsules::low_level_debug::LowLevelDebug::new(
-            &mut LOW_LEVEL_DEBUG_BUF,
+            low_level_debug_buffer,
             low_level_debug_uart,
             kernel.create_grant(&grant_cap)
         )
     );
     hil::uart::Transmit::set_transmit_client(low_level_debug_uart, low_level_debug);
+    let low_level_debug = static_init!(
+        h1_syscalls::low_level_debug::LowLevelDebugExt<'static>,
+        h1_syscalls::low_level_debug::LowLevelDebugExt::new(low_level_debug)
+    );
 
     //debug!("Booting.");
+    // The pins go through a mux so that kernel clients can share them with
+    // the userspace GPIO driver. Whoever drives a pin first owns it; kernel
+    // clients that only watch the reset monitors attach with Access::Observe.
+    let gpio_muxes = static_init!(
+        [MuxGpioPin<'static, h1::gpio::GPIOPin>; 4],
+        [
+            MuxGpioPin::new(gpio_bmc_srst_n),
+            MuxGpioPin::new(gpio_bmc_cpu_rst_n),
+            MuxGpioPin::new(gpio_sys_rstmon_n),
+            MuxGpioPin::new(gpio_bmc_rstmon_n),
+        ],
+    );
+    for (i, mux) in gpio_muxes.iter().enumerate() {
+        hil::gpio::Interrupt::set_client(&peripherals.gpio0.pins[i], mux);
+    }
+    let app_pins = static_init!(
+        [AppGpioPin; 4],
+        [
+            VirtualGpioPin::new(&gpio_muxes[0], Access::Control),
+            VirtualGpioPin::new(&gpio_muxes[1], Access::Control),
+            VirtualGpioPin::new(&gpio_muxes[2], Access::Control),
+            VirtualGpioPin::new(&gpio_muxes[3], Access::Control),
+        ],
+    );
+    for pin in app_pins.iter() {
+        pin.setup();
+    }
+
     let wrapped_pins = static_init!(
-        [kernel::hil::gpio::InterruptValueWrapper<'static, h1::gpio::GPIOPin>; 4],
+        [kernel::hil::gpio::InterruptValueWrapper<'static, AppGpioPin>; 4],
         [
-            kernel::hil::gpio::InterruptValueWrapper::new(&gpio_bmc_srst_n),
-            kernel::hil::gpio::InterruptValueWrapper::new(&gpio_bmc_cpu_rst_n),
-            kernel::hil::gpio::InterruptValueWrapper::new(&gpio_sys_rstmon_n),
-            kernel::hil::gpio::InterruptValueWrapper::new(&gpio_bmc_rstmon_n),
+            kernel::hil::gpio::InterruptValueWrapper::new(&app_pins[0]),
+            kernel::hil::gpio::InterruptValueWrapper::new(&app_pins[1]),
+            kernel::hil::gpio::InterruptValueWrapper::new(&app_pins[2]),
+            kernel::hil::gpio::InterruptValueWrapper::new(&app_pins[3]),
         ],
     );
     let capsule_pins = static_init!(
-        [Option<&'static kernel::hil::gpio::InterruptValueWrapper<'static, h1::gpio::GPIOPin>>; 4],
+        [Option<&'static kernel::hil::gpio::InterruptValueWrapper<'static, AppGpioPin>>; 4],
         [
             Some(&wrapped_pins[0]),
             Some(&wrapped_pins[1]),
@@ -271,7 +425,7 @@ pub unsafe fn reset_handler() {
     );
 
     let gpio = static_init!(
-        capsules::gpio::GPIO<'static, h1::gpio::GPIOPin>,
+        capsules::gpio::GPIO<'static, AppGpioPin>,
         capsules::gpio::GPIO::new(capsule_pins, kernel.create_grant(&grant_cap)));
     for pin in wrapped_pins.iter() {
         pin.finalize();
@@ -280,21 +434,36 @@ pub unsafe fn reset_handler() {
 
     let alarm_mux = static_init!(
         capsules::virtual_alarm::MuxAlarm<'static, Timels>,
-        capsules::virtual_alarm::MuxAlarm::new(&h1::timels::TIMELS0));
-    h1::timels::TIMELS0.set_alarm_client(alarm_mux);
+        capsules::virtual_alarm::MuxAlarm::new(&peripherals.timels0));
+    peripherals.timels0.set_alarm_client(alarm_mux);
 
     // Create flash driver and its virtualization
     let flash_virtual_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
                                            VirtualMuxAlarm::new(alarm_mux));
     let flash = static_init!(
         h1::hil::flash::FlashImpl<'static, VirtualMuxAlarm<'static, Timels>>,
-        h1::hil::flash::FlashImpl::new(flash_virtual_alarm, &*h1::hil::flash::h1_hw::H1_HW));
+        h1::hil::flash::FlashImpl::new(flash_virtual_alarm, peripherals.flash_controller));
     flash_virtual_alarm.set_alarm_client(flash);
 
     let flash_mux = static_init!(
         h1::hil::flash::virtual_flash::MuxFlash<'static>,
         h1::hil::flash::virtual_flash::MuxFlash::new(flash));
 
+    // Journal writes to the board configuration, the lockdown and the
+    // personality.
+    let audit_timer = static_init!(h1::timeus::Timeus, h1::timeus::Timeus::new(2));
+    audit_timer.start_with_divider(24);  // 1MHz
+    let audit_records = static_init!(
+        [h1::hil::flash::audit::AuditRecord; h1::hil::flash::audit::AUDIT_RECORD_COUNT],
+        [h1::hil::flash::audit::AuditRecord::EMPTY; h1::hil::flash::audit::AUDIT_RECORD_COUNT]);
+    let flash_audit = static_init!(
+        h1::hil::flash::audit::WriteAudit<'static>,
+        h1::hil::flash::audit::WriteAudit::new(audit_timer,
+                                               &JOURNALED_PAGES,
+                                               &[],
+                                               audit_records));
+    flash_mux.set_audit(flash_audit);
+
     let flash_user = static_init!(
         h1::hil::flash::virtual_flash::FlashUser<'static>,
         h1::hil::flash::virtual_flash::FlashUser::new(flash_mux));
@@ -305,8 +474,51 @@ pub unsafe fn reset_handler() {
         h1_syscalls::flash::FlashSyscalls::new(flash_user, flash_syscalls_buffer, kernel.create_grant(&grant_cap)));
     flash_user.set_client(flash_syscalls);
 
+    let board_config_flash = static_init!(
+        h1::hil::flash::virtual_flash::FlashUser<'static>,
+        h1::hil::flash::virtual_flash::FlashUser::new(flash_mux));
+    let board_config_write_buffer = static_init!(
+        [u32; h1::board_config::CONFIG_WORDS], [0; h1::board_config::CONFIG_WORDS]);
+    let board_config_store = static_init!(
+        h1::board_config::FlashConfigStore<'static>,
+        h1::board_config::FlashConfigStore::new(board_config_flash,
+                                                board_config,
+                                                board_config_write_buffer));
+    board_config_flash.set_client(board_config_store);
+    let board_config_syscalls = static_init!(
+        h1_syscalls::board_config::BoardConfigSyscall<'static>,
+        h1_syscalls::board_config::BoardConfigSyscall::new(
+            board_config_store, kernel.create_grant(&grant_cap)));
+    board_config_store.set_client(board_config_syscalls);
+
+    let nvcounter_flash = static_init!(
+        h1::hil::flash::virtual_flash::FlashUser<'static>,
+        h1::hil::flash::virtual_flash::FlashUser::new(flash_mux));
+    let nvcounter_buffer = static_init!([u32; 1], [0]);
+    let nvcounter = static_init!(
+        FlashCounter<'static, h1::hil::flash::virtual_flash::FlashUser<'static>>,
+        FlashCounter::new(nvcounter_buffer, nvcounter_flash));
+    nvcounter_flash.set_client(nvcounter);
+    let boot_attempts = static_init!(
+        h1::boot_attempts::BootAttempts<'static>,
+        h1::boot_attempts::BootAttempts::new(nvcounter, MAX_FAILED_BOOTS));
+    nvcounter.set_client(boot_attempts);
+    let boot_attempts_syscalls = static_init!(
+        h1_syscalls::boot_attempts::BootAttemptsSyscall<'static>,
+        h1_syscalls::boot_attempts::BootAttemptsSyscall::new(boot_attempts));
+
     flash.set_client(flash_mux);
 
+    // A failsafe boot passes the BMC through to its flash and lets it write
+    // a new firmware image to the inactive RW segment. The app is not
+    // loaded. The next boot tries the normal configuration again, with the
+    // new image if the BMC wrote one (see h1::boot_attempts).
+    let failsafe = boot_attempts.start();
+    if failsafe {
+        println!("Tock: {} failed boots in a row; booting into failsafe.",
+                 boot_attempts.failed_boots());
+    }
+
     let timer_virtual_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
                                            VirtualMuxAlarm::new(alarm_mux));
     let timer = static_init!(
@@ -317,26 +529,92 @@ pub unsafe fn reset_handler() {
     let digest = static_init!(
         h1_syscalls::digest::DigestDriver<'static, h1::crypto::sha::ShaEngine>,
         h1_syscalls::digest::DigestDriver::new(
-                &mut h1::crypto::sha::KEYMGR0_SHA,
+                &peripherals.sha,
                 kernel.create_grant(&grant_cap)));
 
+    let sha_arbiter = static_init!(
+        h1::crypto::sha::ShaArbiter<'static>,
+        h1::crypto::sha::ShaArbiter::new(&peripherals.sha));
+    sha_arbiter.set_owner(digest);
+
+    // Papa has no keystore, so apps can only derive from their own input
+    // key material.
+    let hkdf = static_init!(
+        h1::hkdf::HkdfImpl<'static>,
+        h1::hkdf::HkdfImpl::new(sha_arbiter));
+    let hkdf_syscalls = static_init!(
+        h1_syscalls::hkdf::HkdfSyscall<'static>,
+        h1_syscalls::hkdf::HkdfSyscall::new(hkdf, None, kernel.create_grant(&grant_cap)));
+
+    let personality_flash = static_init!(
+        h1::hil::flash::virtual_flash::FlashUser<'static>,
+        h1::hil::flash::virtual_flash::FlashUser::new(flash_mux));
+    let personality = static_init!(
+        h1_syscalls::personality::PersonalitySyscall<'static>,
+        h1_syscalls::personality::PersonalitySyscall::new(&peripherals.personality,
+                                                          kernel.create_grant(&grant_cap)));
+    peripherals.personality.set_flash(personality_flash);
+    let personality_image = static_init!(
+        [u32; h1::personality::IMAGE_WORDS], [0; h1::personality::IMAGE_WORDS]);
+    let personality_write_buffer = static_init!(
+        [u32; h1::personality::WRITE_CHUNK_WORDS], [0; h1::personality::WRITE_CHUNK_WORDS]);
+    peripherals.personality.set_buffers(personality_image, personality_write_buffer);
+    peripherals.personality.set_client(personality);
+    personality_flash.set_client(&peripherals.personality);
+
+    // otpilot derives its session identity key from the attestation branch.
+    let keyladder = static_init!(
+        h1::crypto::keyladder::KeyLadderImpl<'static>,
+        h1::crypto::keyladder::KeyLadderImpl::new(sha_arbiter));
+    let keyladder_syscalls = static_init!(
+        h1_syscalls::keyladder::KeyLadderSyscall<'static>,
+        h1_syscalls::keyladder::KeyLadderSyscall::new(keyladder, &PROCESSES,
+                                                      kernel.create_grant(&grant_cap)));
+
+    let aes_key_slots = static_init!(
+        h1::crypto::key_slots::AesKeySlots,
+        h1::crypto::key_slots::AesKeySlots::new());
     let aes = static_init!(
         h1_syscalls::aes::AesDriver,
-        h1_syscalls::aes::AesDriver::new(&mut h1::crypto::aes::KEYMGR0_AES, kernel.create_grant(&grant_cap)));
-    h1::crypto::aes::KEYMGR0_AES.set_client(aes);
-    aes.initialize(&mut h1_syscalls::aes::AES_BUF);
-
-    h1::crypto::dcrypto::DCRYPTO.initialize();
-    let dcrypto = static_init!(
-        h1_syscalls::dcrypto::DcryptoDriver<'static>,
-        h1_syscalls::dcrypto::DcryptoDriver::new(&mut h1::crypto::dcrypto::DCRYPTO));
-
-    h1::crypto::dcrypto::DCRYPTO.set_client(dcrypto);
-
-    h1::trng::TRNG0.init();
+        h1_syscalls::aes::AesDriver::new(&peripherals.aes, aes_key_slots,
+                                         kernel.create_grant(&grant_cap)));
+    peripherals.aes.set_client(aes);
+    let aes_buffer = static_init!(
+        [u8; hil::symmetric_encryption::AES128_BLOCK_SIZE],
+        [0; hil::symmetric_encryption::AES128_BLOCK_SIZE]);
+    aes.initialize(aes_buffer);
+
+    peripherals.dcrypto.initialize();
+    let dcrypto_mux = static_init!(
+        h1::crypto::virtual_dcrypto::MuxDcrypto<'static>,
+        h1::crypto::virtual_dcrypto::MuxDcrypto::new(&peripherals.dcrypto));
+    peripherals.dcrypto.set_client(dcrypto_mux);
+
+    let rsa_dcrypto = static_init!(
+        h1::crypto::virtual_dcrypto::DcryptoUser<'static>,
+        h1::crypto::virtual_dcrypto::DcryptoUser::new(dcrypto_mux));
+    rsa_dcrypto.setup();
+    let rsa_engine = static_init!(
+        h1::crypto::rsa::RsaEngine<'static>,
+        h1::crypto::rsa::RsaEngine::new(rsa_dcrypto));
+    rsa_dcrypto.set_client(rsa_engine);
+    let rsa = static_init!(
+        h1_syscalls::rsa::RsaSyscall<'static>,
+        h1_syscalls::rsa::RsaSyscall::new(rsa_engine, kernel.create_grant(&grant_cap)));
+    rsa_engine.set_client(rsa);
+
+    peripherals.trng0.init();
+    let entropy_pool = static_init!(
+        h1::entropy_pool::EntropyPoolImpl<'static>,
+        h1::entropy_pool::EntropyPoolImpl::new(
+            &peripherals.trng0,
+            h1::crypto::drbg::CtrDrbg::new(&peripherals.aes),
+            ENTROPY_RESEED_POLICY)
+    );
+    peripherals.trng0.set_client(entropy_pool);
     let entropy_to_random = static_init!(
         capsules::rng::Entropy32ToRandom<'static>,
-        capsules::rng::Entropy32ToRandom::new(&h1::trng::TRNG0)
+        capsules::rng::Entropy32ToRandom::new(entropy_pool)
     );
 
     let rng = static_init!(
@@ -346,57 +624,337 @@ pub unsafe fn reset_handler() {
             kernel.create_grant(&grant_cap)
         )
     );
-    h1::trng::TRNG0.set_client(entropy_to_random);
+    entropy_pool.set_client(entropy_to_random);
     entropy_to_random.set_client(rng);
+    entropy_pool.init();
+
+    let dcrypto_user = static_init!(
+        h1::crypto::virtual_dcrypto::DcryptoUser<'static>,
+        h1::crypto::virtual_dcrypto::DcryptoUser::new(dcrypto_mux));
+    dcrypto_user.setup();
+    let p256_user = static_init!(
+        h1::crypto::virtual_dcrypto::DcryptoUser<'static>,
+        h1::crypto::virtual_dcrypto::DcryptoUser::new(dcrypto_mux));
+    p256_user.setup();
+    let p256 = static_init!(
+        h1::crypto::p256::P256Engine<'static>,
+        h1::crypto::p256::P256Engine::new(p256_user, entropy_pool));
+    p256_user.set_client(p256);
+    let dcrypto = static_init!(
+        h1_syscalls::dcrypto::DcryptoDriver<'static>,
+        h1_syscalls::dcrypto::DcryptoDriver::new(dcrypto_user, p256, sha_arbiter));
+    dcrypto_user.set_client(dcrypto);
+    p256.set_client(dcrypto);
+
+    let entropy_pool_syscalls = static_init!(
+        h1_syscalls::entropy_pool::EntropyPoolSyscall<'static>,
+        h1_syscalls::entropy_pool::EntropyPoolSyscall::new(entropy_pool)
+    );
+    let trng_health_syscalls = static_init!(
+        h1_syscalls::trng_health::TrngHealthSyscall<'static>,
+        h1_syscalls::trng_health::TrngHealthSyscall::new(&peripherals.trng0)
+    );
 
-    h1::spi_host::SPI_HOST0.init();
+    peripherals.spi_host0.init();
+    peripherals.spi_host0.spi_device_spi_host_passthrough(
+        failsafe || board_config.config.passthrough_default);
+    // Kernel users of SPI_HOST0 must hold this lease.
+    let spi_host_lease = static_init!(
+        h1::spi_host_lease::SpiHostLease<'static>,
+        h1::spi_host_lease::SpiHostLease::new(
+            timerhs, TIMERHS_HZ, h1::spi_host_lease::RevokePolicy::KernelPreempts)
+    );
     let h1_spi_host_syscalls = static_init!(
         h1_syscalls::spi_host::SpiHostSyscall<'static>,
-        h1_syscalls::spi_host::SpiHostSyscall::new(&h1::spi_host::SPI_HOST0, kernel.create_grant(&grant_cap))
+        h1_syscalls::spi_host::SpiHostSyscall::new(
+            &peripherals.spi_host0, spi_host_lease, kernel.create_grant(&grant_cap))
+    );
+    let passthrough_guard_syscalls = PASSTHROUGH_GUARD.map(|(pin, polarity)| {
+        // Set up after the app pins, so that the guard is the first user of
+        // the mux to see the edge.
+        let guard_pin = static_init!(
+            AppGpioPin,
+            VirtualGpioPin::new(&gpio_muxes[pin], Access::Observe)
+        );
+        guard_pin.setup();
+        let guard = static_init!(
+            h1::passthrough_guard::PassthroughGuard<'static>,
+            h1::passthrough_guard::PassthroughGuard::new(
+                guard_pin, &peripherals.spi_host0, polarity)
+        );
+        hil::gpio::Interrupt::set_client(guard_pin, guard);
+        let syscalls = static_init!(
+            h1_syscalls::passthrough_guard::PassthroughGuardSyscall<'static>,
+            h1_syscalls::passthrough_guard::PassthroughGuardSyscall::new(
+                guard, kernel.create_grant(&grant_cap))
+        );
+        guard.set_client(syscalls);
+        // In failsafe mode no app runs to enable passthrough again.
+        if !failsafe {
+            guard.arm();
+        }
+        &*syscalls
+    });
+    let app_spi_host = static_init!(
+        AppSpiHost,
+        h1::spi_host_lease::LeasedSpiMaster::new(&peripherals.spi_host0, spi_host_lease)
+    );
+    let spi_host_mux = components::spi::SpiMuxComponent::new(app_spi_host)
+        .finalize(components::spi_mux_component_helper!(AppSpiHost));
+    // Built by hand rather than with SpiSyscallComponent so that the transfer
+    // buffers come from the SPI host's pool and match its FIFO size.
+    let spi_host_device = static_init!(
+        VirtualSpiMasterDevice<'static, AppSpiHost>,
+        VirtualSpiMasterDevice::new(spi_host_mux, false)
+    );
+    let spi_host_syscalls = static_init!(
+        capsules::spi_controller::Spi<'static, VirtualSpiMasterDevice<'static, AppSpiHost>>,
+        capsules::spi_controller::Spi::new(spi_host_device)
     );
-    let spi_host_mux = components::spi::SpiMuxComponent::new(&h1::spi_host::SPI_HOST0)
-        .finalize(components::spi_mux_component_helper!(h1::spi_host::SpiHostHardware));
-    let spi_host_syscalls = SpiSyscallComponent::new(spi_host_mux, false)
-        .finalize(components::spi_syscall_component_helper!(h1::spi_host::SpiHostHardware));
+    spi_host_syscalls.config_buffers(
+        h1::spi_host::TRANSFER_BUFFER_POOL.take("spi_controller read").unwrap(),
+        h1::spi_host::TRANSFER_BUFFER_POOL.take("spi_controller write").unwrap());
+    spi_host_device.set_client(spi_host_syscalls);
+    // Lets several apps queue transactions instead of getting EBUSY.
+    let spi_host_queue_device = static_init!(
+        VirtualSpiMasterDevice<'static, AppSpiHost>,
+        VirtualSpiMasterDevice::new(spi_host_mux, false)
+    );
+    let spi_host_queue_syscalls = static_init!(
+        h1_syscalls::spi_host_queue::SpiHostQueue<'static, VirtualSpiMasterDevice<'static, AppSpiHost>>,
+        h1_syscalls::spi_host_queue::SpiHostQueue::new(
+            spi_host_queue_device,
+            h1::spi_host::TRANSFER_BUFFER_POOL.take("spi_host_queue write").unwrap(),
+            h1::spi_host::TRANSFER_BUFFER_POOL.take("spi_host_queue read").unwrap(),
+            kernel.create_grant(&grant_cap))
+    );
+    spi_host_queue_device.set_client(spi_host_queue_syscalls);
+
+    const H1_FLASH_BANK_SIZE: u32 = h1::hil::flash::h1_hw::H1_FLASH_BANK_SIZE as u32;
+    // RW_B stops short of the reserved pages at the end of flash, which hold
+    // the board configuration and the failed boot counter. Otherwise updating
+    // RW_B would erase them. The GLOBALSEC region opened over the inactive RW
+    // follows the segment size, and the reserved pages get a region of their
+    // own so that they stay writable whichever RW is running.
+    const H1_RESERVED_SIZE: u32 = (h1::hil::flash::h1_hw::H1_RESERVED_PAGES *
+                                   h1::hil::flash::h1_hw::H1_FLASH_PAGE_SIZE) as u32;
+    peripherals.globalsec.init(h1::globalsec::Segments {
+        ro_a: get_h1_flash_segment_info(SegmentAndLocation::RoA, 0x0, 0x4000),
+        rw_a: get_h1_flash_segment_info(SegmentAndLocation::RwA, 0x4000, H1_FLASH_BANK_SIZE - 0x4000),
+        ro_b: get_h1_flash_segment_info(SegmentAndLocation::RoB, H1_FLASH_BANK_SIZE, 0x4000),
+        rw_b: get_h1_flash_segment_info(SegmentAndLocation::RwB, H1_FLASH_BANK_SIZE + 0x4000,
+                                        H1_FLASH_BANK_SIZE - 0x4000 - H1_RESERVED_SIZE),
+    });
 
-    h1::spi_device::SPI_DEVICE0.init(h1::spi_device::SpiDeviceConfiguration {
+    peripherals.spi_device0.init(h1::spi_device::SpiDeviceConfiguration {
         enable_fastread4b_cmd: false,
+        enable_quad_output_read_cmd: true,
         enable_enterexit4b_cmd: true,
         startup_address_mode: spiutils::protocol::flash::AddressMode::ThreeByte,
     });
+    // Lets tests make the emulated flash stay busy like a real one.
+    let spi_device_timing_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
+                                               VirtualMuxAlarm::new(alarm_mux));
+    let spi_device_timing = static_init!(
+        h1::spi_device_timing::EmulatedTiming<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1::spi_device_timing::EmulatedTiming::new(&peripherals.spi_device0,
+                                                   spi_device_timing_alarm));
+    spi_device_timing_alarm.set_alarm_client(spi_device_timing);
+    peripherals.spi_device0.set_client(Some(spi_device_timing));
+    // Lets the app have the kernel program and erase the inactive RW segment
+    // for the SPI host.
+    let spi_device_flash_user = static_init!(
+        h1::hil::flash::virtual_flash::FlashUser<'static>,
+        h1::hil::flash::virtual_flash::FlashUser::new(flash_mux));
+    let spi_device_flash_write_buffer = static_init!(
+        [u32; h1::spi_device_flash::WRITE_BUFFER_WORDS],
+        [0; h1::spi_device_flash::WRITE_BUFFER_WORDS]);
+    let spi_device_flash_command_buffer = static_init!(
+        [u8; h1::spi_device_flash::COMMAND_BUFFER_LEN],
+        [0; h1::spi_device_flash::COMMAND_BUFFER_LEN]);
+    let spi_device_flash = static_init!(
+        h1::spi_device_flash::FlashEmulation<'static, h1::hil::flash::virtual_flash::FlashUser<'static>>,
+        h1::spi_device_flash::FlashEmulation::new(
+            spi_device_timing,
+            spi_device_flash_user,
+            &peripherals.globalsec,
+            peripherals.globalsec.get_runtime_segment_info().inactive_rw,
+            spi_device_flash_write_buffer,
+            spi_device_flash_command_buffer));
+    spi_device_flash_user.set_client(spi_device_flash);
+    spi_device_timing.set_client(Some(spi_device_flash));
     let h1_spi_device_syscalls = static_init!(
         h1_syscalls::spi_device::SpiDeviceSyscall<'static>,
-        h1_syscalls::spi_device::SpiDeviceSyscall::new(&h1::spi_device::SPI_DEVICE0, kernel.create_grant(&grant_cap))
+        h1_syscalls::spi_device::SpiDeviceSyscall::new(spi_device_flash, kernel.create_grant(&grant_cap))
     );
-    h1::spi_device::SPI_DEVICE0.set_client(Some(h1_spi_device_syscalls));
+    if failsafe {
+        // No app runs to take an update over the mailbox, so the kernel
+        // writes the image itself and ends all other commands.
+        let failsafe_spi_device = static_init!(
+            h1::spi_device_failsafe::FailsafeSpiDevice<'static>,
+            h1::spi_device_failsafe::FailsafeSpiDevice::new(spi_device_flash));
+        spi_device_flash.set_client(Some(failsafe_spi_device));
+        spi_device_flash.set_program_erase_handling(HandlerMode::KernelSpace, FAILSAFE_UPDATE_BASE);
+    } else {
+        spi_device_flash.set_client(Some(h1_spi_device_syscalls));
+    }
 
     let fuse_syscalls = static_init!(
         h1_syscalls::fuse::FuseSyscall<'static>,
-        h1_syscalls::fuse::FuseSyscall::new(&h1::fuse::FUSE, kernel.create_grant(&grant_cap))
+        h1_syscalls::fuse::FuseSyscall::new(&peripherals.fuse, kernel.create_grant(&grant_cap))
     );
 
-    const H1_FLASH_BANK_SIZE: u32 = h1::hil::flash::h1_hw::H1_FLASH_BANK_SIZE as u32;
-    h1::globalsec::GLOBALSEC.init(h1::globalsec::Segments {
-        ro_a: get_h1_flash_segment_info(SegmentAndLocation::RoA, 0x0, 0x4000),
-        rw_a: get_h1_flash_segment_info(SegmentAndLocation::RwA, 0x4000, H1_FLASH_BANK_SIZE - 0x4000),
-        ro_b: get_h1_flash_segment_info(SegmentAndLocation::RoB, H1_FLASH_BANK_SIZE, 0x4000),
-        rw_b: get_h1_flash_segment_info(SegmentAndLocation::RwB, H1_FLASH_BANK_SIZE + 0x4000, H1_FLASH_BANK_SIZE - 0x4000),
-    });
-
     let globalsec_syscalls = static_init!(
         h1_syscalls::globalsec::GlobalSecSyscall<'static>,
-        h1_syscalls::globalsec::GlobalSecSyscall::new(&h1::globalsec::GLOBALSEC, kernel.create_grant(&grant_cap))
+        h1_syscalls::globalsec::GlobalSecSyscall::new(&peripherals.globalsec, kernel.create_grant(&grant_cap))
     );
 
-    h1::pmu::RESET.init();
+    let lockdown = static_init!(
+        h1::lockdown::Lockdown<'static>,
+        h1::lockdown::Lockdown::new(&peripherals.globalsec));
+    lockdown.set_audit(flash_audit);
+    board_config_syscalls.set_lockdown(lockdown);
+    if LOCK_DOWN_WHEN_HEALTHY {
+        boot_attempts.set_lockdown(lockdown);
+    }
+    let lockdown_syscalls = static_init!(
+        h1_syscalls::lockdown::LockdownSyscall<'static>,
+        h1_syscalls::lockdown::LockdownSyscall::new(lockdown));
+
+    // Let the SPI host read identity information without waking the app.
+    let info_block_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
+                                        VirtualMuxAlarm::new(alarm_mux));
+    let info_block = static_init!(
+        h1::info_block::InfoBlockUpdater<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1::info_block::InfoBlockUpdater::new(
+            info_block_alarm,
+            &peripherals.spi_device0,
+            &peripherals.fuse,
+            &peripherals.globalsec,
+            board_config.config.heartbeat_interval_ms));
+    info_block_alarm.set_alarm_client(info_block);
+    info_block.start();
+
+    // Count interrupts per NVIC line and log interrupt storms.
+    let irq_stats_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
+                                       VirtualMuxAlarm::new(alarm_mux));
+    let irq_counts = static_init!(h1::irq_stats::IrqCounts, h1::irq_stats::IrqCounts::EMPTY);
+    let irq_stats = static_init!(
+        h1::irq_stats::IrqStats<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1::irq_stats::IrqStats::new(irq_stats_alarm,
+                                     irq_counts,
+                                     IRQ_STATS_WINDOW_MS,
+                                     IRQ_STORM_THRESHOLD));
+    irq_stats_alarm.set_alarm_client(irq_stats);
+    irq_stats.start();
+    let irq_stats_syscalls = static_init!(
+        h1_syscalls::irq_stats::IrqStatsSyscall<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1_syscalls::irq_stats::IrqStatsSyscall::new(irq_stats));
+
+    // Timestamps for the console lines.
+    let uptime_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
+                                    VirtualMuxAlarm::new(alarm_mux));
+    let uptime = static_init!(
+        h1::uptime::UptimeClock<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1::uptime::UptimeClock::new(h1::timeus::Timeus::new(3), uptime_alarm));
+    uptime_alarm.set_alarm_client(uptime);
+    uptime.start();
+    peripherals.uart0.set_timestamp_clock(uptime);
+    peripherals.uart0.enable_timestamps(CONSOLE_TIMESTAMPS);
+    let console_timestamps_syscalls = static_init!(
+        h1_syscalls::console_timestamps::ConsoleTimestampsSyscall<'static>,
+        h1_syscalls::console_timestamps::ConsoleTimestampsSyscall::new(&peripherals.uart0));
+
+    let watchdog_syscalls = static_init!(
+        h1_syscalls::watchdog::WatchdogSyscall<'static>,
+        h1_syscalls::watchdog::WatchdogSyscall::new(timerhs, TIMERHS_HZ, WATCHDOG_TIMEOUT_MS,
+                                                    &peripherals.watchdog0,
+                                                    kernel.create_grant(&grant_cap)));
+    peripherals.watchdog0.init();
+    peripherals.watchdog0.set_timeout_ms(WATCHDOG_TIMEOUT_MS);
+    peripherals.watchdog0.set_liveness(watchdog_syscalls);
+
+    peripherals.reset.init();
     let reset_syscalls = static_init!(
         h1_syscalls::reset::ResetSyscall<'static>,
-        h1_syscalls::reset::ResetSyscall::new(&h1::pmu::RESET, kernel.create_grant(&grant_cap))
+        h1_syscalls::reset::ResetSyscall::new(&peripherals.reset, kernel.create_grant(&grant_cap))
+    );
+
+    let timebase = static_init!(
+        h1::timebase::TimebaseImpl,
+        h1::timebase::TimebaseImpl::new(h1::timeus::Timeus::new(1))
+    );
+    timebase.start();
+    let timebase_syscalls = static_init!(
+        h1_syscalls::timebase::TimebaseSyscall<'static>,
+        h1_syscalls::timebase::TimebaseSyscall::new(timebase, &PROCESSES, "otpilot",
+                                                    kernel.create_grant(&grant_cap))
+    );
+    let fault_stats_syscalls = static_init!(
+        h1_syscalls::fault_stats::FaultStatsSyscall,
+        h1_syscalls::fault_stats::FaultStatsSyscall::new()
+    );
+    h1::crypto::stats::set_clock(timerhs, TIMERHS_HZ);
+    let crypto_stats_syscalls = static_init!(
+        h1_syscalls::crypto_stats::CryptoStatsSyscall,
+        h1_syscalls::crypto_stats::CryptoStatsSyscall::new()
+    );
+    let stack_usage_syscalls = static_init!(
+        h1_syscalls::stack_usage::StackUsageSyscall,
+        h1_syscalls::stack_usage::StackUsageSyscall::new(&PROCESSES)
+    );
+    peripherals.spi_device_probe.set_clock(timerhs, TIMERHS_HZ);
+    let irq_latency_syscalls = static_init!(
+        h1_syscalls::irq_latency::IrqLatencySyscall<'static>,
+        h1_syscalls::irq_latency::IrqLatencySyscall::new(&peripherals.spi_device_probe)
+    );
+
+    // The status LED (also lit on panic) has no PWM function, so dim it in
+    // software.
+    let soft_pwm_pins = static_init!(
+        [h1::soft_pwm::SoftPwmPin<'static>; 1],
+        [h1::soft_pwm::SoftPwmPin::new(&peripherals.gpio1.pins[15], true)]
+    );
+    let soft_pwm_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
+                                      VirtualMuxAlarm::new(alarm_mux));
+    let soft_pwm = static_init!(
+        h1::soft_pwm::SoftPwm<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1::soft_pwm::SoftPwm::new(soft_pwm_alarm, soft_pwm_pins));
+    soft_pwm_alarm.set_alarm_client(soft_pwm);
+    soft_pwm.init();
+    let soft_pwm_syscalls = static_init!(
+        h1_syscalls::soft_pwm::SoftPwmSyscall<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1_syscalls::soft_pwm::SoftPwmSyscall::new(soft_pwm)
+    );
+
+    // Keep an app spinning on flash or dcrypto from starving SPI passthrough.
+    let rate_limits = static_init!(
+        [h1_syscalls::rate_limit::RateLimit; 2],
+        [
+            h1_syscalls::rate_limit::RateLimit::new(h1_syscalls::dcrypto::DRIVER_NUM, 1000, 100),
+            h1_syscalls::rate_limit::RateLimit::new(h1_syscalls::flash::DRIVER_NUM, 1000, 100),
+        ]
+    );
+    let rate_limiter = static_init!(
+        h1_syscalls::rate_limit::RateLimiter<'static>,
+        h1_syscalls::rate_limit::RateLimiter::new(timerhs, TIMERHS_HZ, rate_limits)
+    );
+
+    // Drivers that must be idle before the chip enters deep sleep.
+    let power_clients = static_init!(
+        [&'static dyn h1::hil::power::PowerClient; 2],
+        [flash, &peripherals.spi_host0]
+    );
+    let deep_sleep = static_init!(
+        h1::pmu::DeepSleep<'static>,
+        h1::pmu::DeepSleep::new(power_clients, timerhs, TIMERHS_HZ)
     );
 
     let mut _ctr = 0;
-    let chip = static_init!(h1::chip::Hotel, h1::chip::Hotel::new());
+    let chip = static_init!(h1::chip::Hotel, h1::chip::Hotel::new(peripherals, INTERRUPT_PRIORITIES));
     chip.mpu().enable_app_mpu();
+    chip.set_deep_sleep(deep_sleep);
+    chip.set_interrupt_counter(irq_stats);
     CHIP = Some(chip);
 
     let end = timerhs.now();
@@ -410,16 +968,37 @@ pub unsafe fn reset_handler() {
         ipc: kernel::ipc::IPC::new(kernel, &grant_cap),
         digest: digest,
         aes: aes,
+        hkdf: hkdf_syscalls,
+        keyladder: keyladder_syscalls,
+        personality: personality,
         dcrypto: dcrypto,
+        rsa: rsa,
         low_level_debug,
         rng: rng,
+        entropy_pool_syscalls: entropy_pool_syscalls,
+        trng_health_syscalls: trng_health_syscalls,
         spi_host_syscalls: spi_host_syscalls,
+        spi_host_queue_syscalls: spi_host_queue_syscalls,
         h1_spi_host_syscalls: h1_spi_host_syscalls,
         h1_spi_device_syscalls: h1_spi_device_syscalls,
         flash_syscalls: flash_syscalls,
         fuse_syscalls: fuse_syscalls,
         globalsec_syscalls: globalsec_syscalls,
+        lockdown_syscalls: lockdown_syscalls,
         reset_syscalls: reset_syscalls,
+        timebase_syscalls: timebase_syscalls,
+        board_config_syscalls: board_config_syscalls,
+        boot_attempts_syscalls: boot_attempts_syscalls,
+        fault_stats_syscalls: fault_stats_syscalls,
+        crypto_stats_syscalls: crypto_stats_syscalls,
+        passthrough_guard_syscalls: passthrough_guard_syscalls,
+        irq_stats_syscalls: irq_stats_syscalls,
+        console_timestamps_syscalls: console_timestamps_syscalls,
+        stack_usage_syscalls: stack_usage_syscalls,
+        watchdog_syscalls: watchdog_syscalls,
+        irq_latency_syscalls: irq_latency_syscalls,
+        soft_pwm_syscalls: soft_pwm_syscalls,
+        rate_limiter: rate_limiter,
     };
 
     extern "C" {
@@ -429,23 +1008,28 @@ pub unsafe fn reset_handler() {
         /// script.
         static _eapps: u8;
     }
-    kernel::procs::load_processes(
-        kernel,
-        chip,
-        core::slice::from_raw_parts(
-            &_sapps as *const u8,
-            &_eapps as *const u8 as usize - &_sapps as *const u8 as usize
-        ),
-        &mut APP_MEMORY,
-        &mut PROCESSES,
-        FAULT_RESPONSE,
-        &process_mgmt_cap,
-    ).unwrap_or_else(|err| {
-        debug!("Error loading processes!\n{:?}", err);
-    });
+    if !failsafe {
+        h1::stack_usage::paint(&mut APP_MEMORY);
+        h1::process_loader::load_processes(
+            kernel,
+            chip,
+            core::slice::from_raw_parts(
+                &_sapps as *const u8,
+                &_eapps as *const u8 as usize - &_sapps as *const u8 as usize
+            ),
+            &mut APP_MEMORY,
+            &mut PROCESSES,
+            APP_FAULT_RESPONSES,
+            FAULT_RESPONSE,
+            &process_mgmt_cap,
+        ).unwrap_or_else(|err| {
+            debug!("Error loading processes!\n{:?}", err);
+        });
+    }
 
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::rr_component_helper!(NUM_PROCS));
+    let _ = process_console.start();
     debug!("Tock: starting main loop.");
     debug!(" ");
     kernel.kernel_loop(&papa, chip, Some(&papa.ipc), scheduler, &main_cap);
@@ -456,22 +1040,45 @@ impl Platform for Papa {
     where
         F: FnOnce(Option<&dyn kernel::Driver>) -> R
     {
+        if !self.rate_limiter.allow(driver_num) {
+            return f(Some(&h1_syscalls::rate_limit::THROTTLED));
+        }
         match driver_num {
             capsules::alarm::DRIVER_NUM                => f(Some(self.timer)),
             capsules::console::DRIVER_NUM              => f(Some(self.console)),
             capsules::gpio::DRIVER_NUM                 => f(Some(self.gpio)),
-            capsules::low_level_debug::DRIVER_NUM      => f(Some(self.low_level_debug)),
             capsules::rng::DRIVER_NUM                  => f(Some(self.rng)),
             capsules::spi_controller::DRIVER_NUM       => f(Some(self.spi_host_syscalls)),
             h1_syscalls::spi_host::DRIVER_NUM          => f(Some(self.h1_spi_host_syscalls)),
+            h1_syscalls::spi_host_queue::DRIVER_NUM    => f(Some(self.spi_host_queue_syscalls)),
             h1_syscalls::spi_device::DRIVER_NUM        => f(Some(self.h1_spi_device_syscalls)),
             h1_syscalls::aes::DRIVER_NUM               => f(Some(self.aes)),
+            h1_syscalls::board_config::DRIVER_NUM      => f(Some(self.board_config_syscalls)),
+            h1_syscalls::boot_attempts::DRIVER_NUM     => f(Some(self.boot_attempts_syscalls)),
+            h1_syscalls::console_timestamps::DRIVER_NUM => f(Some(self.console_timestamps_syscalls)),
+            h1_syscalls::crypto_stats::DRIVER_NUM      => f(Some(self.crypto_stats_syscalls)),
             h1_syscalls::dcrypto::DRIVER_NUM           => f(Some(self.dcrypto)),
             h1_syscalls::digest::DRIVER_NUM            => f(Some(self.digest)),
+            h1_syscalls::entropy_pool::DRIVER_NUM      => f(Some(self.entropy_pool_syscalls)),
+            h1_syscalls::fault_stats::DRIVER_NUM       => f(Some(self.fault_stats_syscalls)),
+            h1_syscalls::hkdf::DRIVER_NUM              => f(Some(self.hkdf)),
+            h1_syscalls::irq_stats::DRIVER_NUM         => f(Some(self.irq_stats_syscalls)),
+            h1_syscalls::keyladder::DRIVER_NUM         => f(Some(self.keyladder)),
+            h1_syscalls::low_level_debug::DRIVER_NUM   => f(Some(self.low_level_debug)),
             h1_syscalls::flash::DRIVER_NUM             => f(Some(self.flash_syscalls)),
             h1_syscalls::fuse::DRIVER_NUM              => f(Some(self.fuse_syscalls)),
             h1_syscalls::globalsec::DRIVER_NUM         => f(Some(self.globalsec_syscalls)),
+            h1_syscalls::irq_latency::DRIVER_NUM       => f(Some(self.irq_latency_syscalls)),
+            h1_syscalls::lockdown::DRIVER_NUM          => f(Some(self.lockdown_syscalls)),
+            h1_syscalls::passthrough_guard::DRIVER_NUM => f(self.passthrough_guard_syscalls),
+            h1_syscalls::personality::DRIVER_NUM       => f(Some(self.personality)),
             h1_syscalls::reset::DRIVER_NUM             => f(Some(self.reset_syscalls)),
+            h1_syscalls::rsa::DRIVER_NUM               => f(Some(self.rsa)),
+            h1_syscalls::soft_pwm::DRIVER_NUM          => f(Some(self.soft_pwm_syscalls)),
+            h1_syscalls::stack_usage::DRIVER_NUM       => f(Some(self.stack_usage_syscalls)),
+            h1_syscalls::watchdog::DRIVER_NUM          => f(Some(self.watchdog_syscalls)),
+            h1_syscalls::timebase::DRIVER_NUM          => f(Some(self.timebase_syscalls)),
+            h1_syscalls::trng_health::DRIVER_NUM       => f(Some(self.trng_health_syscalls)),
             kernel::ipc::DRIVER_NUM                    => f(Some(&self.ipc)),
             _ =>  f(None),
         }
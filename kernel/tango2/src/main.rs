@@ -0,0 +1,331 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Board file for tango2, an H1 board with only the UART console, GPIO,
+//! timers and flash.
+//!
+//! The kernel brings up nothing beyond those, so it builds quickly when
+//! working on kernel-only changes. It is also the starting point for new
+//! boards: copy it and add the drivers the board needs from golf2 or papa.
+
+#![no_std]
+#![no_main]
+#![feature(asm, const_fn, lang_items)]
+#![feature(in_band_lifetimes)]
+#![feature(core_intrinsics)]
+
+extern crate capsules;
+#[macro_use(print, println)]
+extern crate h1;
+#[macro_use(static_init, debug, create_capability)]
+extern crate kernel;
+extern crate cortexm3;
+
+use capsules::alarm::AlarmDriver;
+use capsules::virtual_alarm::VirtualMuxAlarm;
+
+use kernel::{Chip, Platform};
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::mpu::MPU;
+
+use h1::hil::flash::Flash;
+use h1::nvcounter::{FlashCounter, NvCounter};
+use h1::timels::Timels;
+
+// State for loading apps
+const NUM_PROCS: usize = 1;
+
+// how should the kernel respond when a process faults
+const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+
+// How the kernel responds when a process faults, for apps that need a
+// different response than FAULT_RESPONSE.
+const APP_FAULT_RESPONSES: &[h1::process_loader::AppFaultResponse] = &[];
+
+// NVIC interrupt priorities. There is no SPI device or USB, so this only
+// orders the timers and the UART.
+const INTERRUPT_PRIORITIES: &[h1::irq_priority::InterruptGroup] =
+    h1::irq_priority::DEFAULT_PRIORITIES;
+
+// Used by panic_fmt to print chip-specific debugging information.
+static mut CHIP: Option<&'static h1::chip::Hotel> = None;
+
+/// Panic handler.
+#[cfg(not(test))]
+#[panic_handler]
+pub unsafe extern "C" fn panic_fmt(pi: &core::panic::PanicInfo) -> ! {
+    let led_pin = &mut h1::gpio::GPIOPin::new(h1::gpio::GPIO0_BASE, h1::gpio::Pin::P0);
+    let led = &mut kernel::hil::led::LedLow::new(led_pin);
+    let writer = &mut h1::io::Writer;
+    kernel::debug::panic(&mut [led], writer, pi, &cortexm3::support::nop, &crate::PROCESSES, &CHIP)
+}
+
+#[link_section = ".app_memory"]
+static mut APP_MEMORY: [u8; 0xc000] = [0; 0xc000];
+
+static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] = [None];
+
+/// Dummy buffer that causes the linker to reserve enough space for the stack.
+#[no_mangle]
+#[link_section = ".stack_buffer"]
+pub static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
+
+pub struct Tango {
+    console: &'static capsules::console::Console<'static>,
+    gpio: &'static capsules::gpio::GPIO<'static, h1::gpio::GPIOPin>,
+    timer: &'static AlarmDriver<'static, VirtualMuxAlarm<'static, Timels>>,
+    ipc: kernel::ipc::IPC<NUM_PROCS>,
+    low_level_debug: &'static h1_syscalls::low_level_debug::LowLevelDebugExt<'static>,
+    nvcounter: &'static h1_syscalls::nvcounter_syscall::NvCounterSyscall<'static,
+        FlashCounter<'static, h1::hil::flash::virtual_flash::FlashUser<'static>>>,
+    fault_stats_syscalls: &'static h1_syscalls::fault_stats::FaultStatsSyscall,
+    stack_usage_syscalls: &'static h1_syscalls::stack_usage::StackUsageSyscall,
+}
+
+#[no_mangle]
+pub unsafe fn reset_handler() {
+    use kernel::hil::time::Alarm;
+
+    h1::init();
+
+    let peripherals = static_init!(h1::peripherals::Peripherals, h1::peripherals::Peripherals::new());
+
+    let timerhs = {
+        use h1::pmu::*;
+        use h1::timeus::Timeus;
+        Clock::new(PeripheralClock::Bank1(PeripheralClock1::TimeUs0Timer)).enable();
+        Clock::new(PeripheralClock::Bank1(PeripheralClock1::TimeLs0)).enable();
+        Timeus::new(0)
+    };
+
+    timerhs.start();
+    let start = timerhs.now();
+
+    {
+        use h1::pmu::*;
+        Clock::new(PeripheralClock::Bank0(PeripheralClock0::Gpio0)).enable();
+        let pinmux = &peripherals.pinmux;
+        // LED_0
+        pinmux.dioa11.select.set(h1::pinmux::Function::Gpio0Gpio0);
+
+        // SW1
+        pinmux.gpio0_gpio1.select.set(h1::pinmux::SelectablePin::Diom2);
+        pinmux.diom2.select.set(h1::pinmux::Function::Gpio0Gpio1);
+        pinmux.diom2.control.set(1 << 2 | 1 << 4);
+
+        pinmux.diob1.select.set(h1::pinmux::Function::Uart0Tx);
+        pinmux.diob6.control.set(1 << 2 | 1 << 4);
+        pinmux.uart0_rx.select.set(h1::pinmux::SelectablePin::Diob6);
+    }
+
+    // Create capabilities that the board needs to call certain protected kernel
+    // functions.
+    let process_mgmt_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let main_cap = create_capability!(capabilities::MainLoopCapability);
+    let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 2], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
+
+    let uart_mux = components::console::UartMuxComponent::new(
+        &peripherals.uart0, h1::uart::DEFAULT_BAUDRATE, dynamic_deferred_caller)
+        .finalize(());
+    hil::uart::Transmit::set_transmit_client(&peripherals.uart0, uart_mux);
+    peripherals.uart0.config(h1::uart::DEFAULT_BAUDRATE);
+
+    let console = components::console::ConsoleComponent::new(kernel, uart_mux).finalize(());
+    components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+
+    let low_level_debug = components::lldb::LowLevelDebugComponent::new(kernel, uart_mux)
+        .finalize(());
+    let low_level_debug = static_init!(
+        h1_syscalls::low_level_debug::LowLevelDebugExt<'static>,
+        h1_syscalls::low_level_debug::LowLevelDebugExt::new(low_level_debug)
+    );
+
+    let wrapped_pins = static_init!(
+        [kernel::hil::gpio::InterruptValueWrapper<'static, h1::gpio::GPIOPin>; 2],
+        [kernel::hil::gpio::InterruptValueWrapper::new(&peripherals.gpio0.pins[0]),
+         kernel::hil::gpio::InterruptValueWrapper::new(&peripherals.gpio0.pins[1])]
+    );
+    let capsule_pins = static_init!(
+        [Option<&'static kernel::hil::gpio::InterruptValueWrapper<'static, h1::gpio::GPIOPin>>; 2],
+        [Some(&wrapped_pins[0]), Some(&wrapped_pins[1])]
+    );
+    let gpio = static_init!(
+        capsules::gpio::GPIO<'static, h1::gpio::GPIOPin>,
+        capsules::gpio::GPIO::new(capsule_pins, kernel.create_grant(&grant_cap)));
+    for pin in wrapped_pins.iter() {
+        pin.finalize();
+        kernel::hil::gpio::InterruptWithValue::set_client(pin, gpio);
+    }
+
+    let alarm_mux = components::alarm::AlarmMuxComponent::new(&peripherals.timels0)
+        .finalize(components::alarm_mux_component_helper!(Timels));
+    let timer = components::alarm::AlarmDriverComponent::new(kernel, alarm_mux)
+        .finalize(components::alarm_component_helper!(Timels));
+
+    // Create flash driver and its virtualization
+    let flash_virtual_alarm = static_init!(VirtualMuxAlarm<'static, Timels>,
+                                           VirtualMuxAlarm::new(alarm_mux));
+    let flash = static_init!(
+        h1::hil::flash::FlashImpl<'static, VirtualMuxAlarm<'static, Timels>>,
+        h1::hil::flash::FlashImpl::new(flash_virtual_alarm, peripherals.flash_controller));
+    flash_virtual_alarm.set_alarm_client(flash);
+
+    let flash_mux = static_init!(
+        h1::hil::flash::virtual_flash::MuxFlash<'static>,
+        h1::hil::flash::virtual_flash::MuxFlash::new(flash));
+    let nvcounter_flash = static_init!(h1::hil::flash::virtual_flash::FlashUser<'static>,
+                                       h1::hil::flash::virtual_flash::FlashUser::new(flash_mux));
+    flash.set_client(flash_mux);
+
+    let nvcounter_buffer = static_init!([u32; 1], [0]);
+    let nvcounter = static_init!(
+        FlashCounter<'static, h1::hil::flash::virtual_flash::FlashUser<'static>>,
+        FlashCounter::new(nvcounter_buffer, nvcounter_flash));
+    nvcounter_flash.set_client(nvcounter);
+    let nvcounter_syscall = static_init!(
+        h1_syscalls::nvcounter_syscall::NvCounterSyscall<'static,
+            FlashCounter<'static, h1::hil::flash::virtual_flash::FlashUser<'static>>>,
+        h1_syscalls::nvcounter_syscall::NvCounterSyscall::new(nvcounter, kernel.create_grant(&grant_cap)));
+    nvcounter.set_client(nvcounter_syscall);
+
+    let fault_stats_syscalls = static_init!(
+        h1_syscalls::fault_stats::FaultStatsSyscall,
+        h1_syscalls::fault_stats::FaultStatsSyscall::new()
+    );
+    let stack_usage_syscalls = static_init!(
+        h1_syscalls::stack_usage::StackUsageSyscall,
+        h1_syscalls::stack_usage::StackUsageSyscall::new(&PROCESSES)
+    );
+
+    // ** GLOBALSEC **
+    // Only the CPU and DMA need access; there is no USB to open regions for.
+    {
+        use core::intrinsics::volatile_store as vs;
+        const GLOBALSEC_BASE:      usize = 0x40090000;
+
+        const CPU0_D_REGION0_CTRL: usize = GLOBALSEC_BASE + 0x0;
+        const CPU0_D_REGION1_CTRL: usize = GLOBALSEC_BASE + 0x4;
+        const CPU0_D_REGION2_CTRL: usize = GLOBALSEC_BASE + 0x8;
+        const CPU0_D_REGION3_CTRL: usize = GLOBALSEC_BASE + 0xc;
+
+        const DDMA0_REGION0_CTRL: usize = GLOBALSEC_BASE + 0x80;
+        const DDMA0_REGION1_CTRL: usize = GLOBALSEC_BASE + 0x84;
+        const DDMA0_REGION2_CTRL: usize = GLOBALSEC_BASE + 0x88;
+        const DDMA0_REGION3_CTRL: usize = GLOBALSEC_BASE + 0x8c;
+
+        const FLASH_REGION2_BASE: usize = GLOBALSEC_BASE + 0x240;
+        const FLASH_REGION2_SIZE: usize = GLOBALSEC_BASE + 0x244;
+        const FLASH_REGION2_CTRL: usize = GLOBALSEC_BASE + 0x0e8;
+
+        vs(CPU0_D_REGION0_CTRL as *mut u32, !0);
+        vs(CPU0_D_REGION1_CTRL as *mut u32, !0);
+        vs(CPU0_D_REGION2_CTRL as *mut u32, !0);
+        vs(CPU0_D_REGION3_CTRL as *mut u32, !0);
+
+        vs(DDMA0_REGION0_CTRL as *mut u32, !0);
+        vs(DDMA0_REGION1_CTRL as *mut u32, !0);
+        vs(DDMA0_REGION2_CTRL as *mut u32, !0);
+        vs(DDMA0_REGION3_CTRL as *mut u32, !0);
+
+        // The last two pages of the second flash macro hold the non-volatile
+        // counter; open them for reads and writes.
+        const FLASH_START: usize = 0x40000;
+        const FLASH_SIZE: usize = 512 * 1024;
+        const FLASH_PAGE_SIZE: usize = 2048;
+        vs(FLASH_REGION2_BASE as *mut u32, (FLASH_START + FLASH_SIZE - 2*FLASH_PAGE_SIZE) as u32);
+        vs(FLASH_REGION2_SIZE as *mut u32, (2*FLASH_PAGE_SIZE - 1) as u32);
+        vs(FLASH_REGION2_CTRL as *mut u32, 0b111);
+    }
+
+    let chip = static_init!(h1::chip::Hotel, h1::chip::Hotel::new(peripherals, INTERRUPT_PRIORITIES));
+    chip.mpu().enable_app_mpu();
+    CHIP = Some(chip);
+
+    let end = timerhs.now();
+    println!("Tock: booted in {} tics; loading processes.", end.wrapping_sub(start));
+
+    let tango2 = Tango {
+        console: console,
+        gpio: gpio,
+        timer: timer,
+        ipc: kernel::ipc::IPC::new(kernel, &grant_cap),
+        low_level_debug,
+        nvcounter: nvcounter_syscall,
+        fault_stats_syscalls: fault_stats_syscalls,
+        stack_usage_syscalls: stack_usage_syscalls,
+    };
+
+    extern "C" {
+        /// Beginning of the ROM region containing app images.
+        static _sapps: u8;
+        /// End of the ROM region containing app images. Defined by the linker
+        /// script.
+        static _eapps: u8;
+    }
+    h1::stack_usage::paint(&mut APP_MEMORY);
+    h1::process_loader::load_processes(
+        kernel,
+        chip,
+        core::slice::from_raw_parts(
+            &_sapps as *const u8,
+            &_eapps as *const u8 as usize - &_sapps as *const u8 as usize
+        ),
+        &mut APP_MEMORY,
+        &mut PROCESSES,
+        APP_FAULT_RESPONSES,
+        FAULT_RESPONSE,
+        &process_mgmt_cap,
+    ).unwrap_or_else(|err| {
+        debug!("Error loading processes!\n{:?}", err);
+    });
+    let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
+        .finalize(components::rr_component_helper!(NUM_PROCS));
+    debug!("Tock: starting main loop.");
+    debug!(" ");
+    kernel.kernel_loop(&tango2, chip, Some(&tango2.ipc), scheduler, &main_cap);
+}
+
+impl Platform for Tango {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R
+    {
+        match driver_num {
+            capsules::alarm::DRIVER_NUM                => f(Some(self.timer)),
+            capsules::console::DRIVER_NUM              => f(Some(self.console)),
+            capsules::gpio::DRIVER_NUM                 => f(Some(self.gpio)),
+            h1_syscalls::fault_stats::DRIVER_NUM       => f(Some(self.fault_stats_syscalls)),
+            h1_syscalls::low_level_debug::DRIVER_NUM   => f(Some(self.low_level_debug)),
+            h1_syscalls::nvcounter_syscall::DRIVER_NUM => f(Some(self.nvcounter)),
+            h1_syscalls::stack_usage::DRIVER_NUM       => f(Some(self.stack_usage_syscalls)),
+            kernel::ipc::DRIVER_NUM                    => f(Some(&self.ipc)),
+            _ =>  f(None),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+// Power-loss testing for --chaos. The board runs userspace/nvcounter_chaos,
+// which increments the nonvolatile counter in a loop and prints its value.
+// The runner power-cycles the board at random points and checks that the
+// value the app reads after each boot is at least the last value it reported
+// as committed, i.e. that no committed increment was lost.
+
+use std::io::{BufRead,BufReader,Write};
+use std::sync::{Arc,Mutex};
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+// Range of the time the board runs between power cycles, in milliseconds.
+const MIN_UPTIME_MS: u64 = 200;
+const MAX_UPTIME_MS: u64 = 3000;
+
+// Time the last boot gets to report before the results are checked.
+const SETTLE_MS: u64 = 3000;
+
+const REPORT_PREFIX: &str = "nvcounter_chaos:";
+
+// Small xorshift generator; the delays only need to be unpredictable to the
+// firmware, and the seed is printed so that a failing run can be repeated.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+#[derive(Default)]
+struct Checker {
+    // The smallest value the counter may have from now on, if known.
+    floor: Option<u64>,
+    boots: usize,
+    violations: usize,
+}
+
+impl Checker {
+    fn check_line(&mut self, line: &str, power_cycle: usize) {
+        if line.starts_with("FAILED:") {
+            self.violations += 1;
+            return;
+        }
+        let mut words = line.split_whitespace();
+        if words.next() != Some(REPORT_PREFIX) { return; }
+        let is_boot = match words.next() {
+            Some("boot") => true,
+            Some("committed") => false,
+            _ => return,
+        };
+        let value: u64 = match words.next().map(str::parse) {
+            Some(Ok(value)) => value,
+            _ => return,
+        };
+
+        if let Some(floor) = self.floor {
+            if value < floor {
+                println!("\nFAILED: counter went back from {} to {} after power cycle {}",
+                         floor, value, power_cycle);
+                self.violations += 1;
+            }
+        }
+        if is_boot {
+            self.boots += 1;
+            // The app prints the value it read, which it then incremented.
+            self.floor = Some(value + 1);
+        } else {
+            self.floor = Some(value);
+        }
+    }
+}
+
+// Runs `cycles` power cycles with the board already powered up, then exits
+// with the same codes as --test.
+pub fn run(mut debug_console: std::fs::File, target_console: std::fs::File, cycles: usize,
+           seed: u64, off_delay: u64) -> ! {
+    println!("Chaos test: {} power cycles, seed {}", cycles, seed);
+
+    let checker = Arc::new(Mutex::new(Checker::default()));
+    // Incremented before each power cycle, so that lines cut short by one
+    // are not checked.
+    let power_cycles = Arc::new(AtomicUsize::new(0));
+
+    let reader_checker = checker.clone();
+    let reader_power_cycles = power_cycles.clone();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(target_console);
+        let mut line = Vec::new();
+        loop {
+            let power_cycle = reader_power_cycles.load(Ordering::SeqCst);
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {},
+            }
+            std::io::stdout().write_all(&line).expect("Failed to echo to stdout");
+            if reader_power_cycles.load(Ordering::SeqCst) != power_cycle { continue; }
+            let text = String::from_utf8_lossy(&line);
+            reader_checker.lock().unwrap().check_line(text.trim(), power_cycle);
+        }
+    });
+
+    let mut rng = Rng(seed | 1);
+    for cycle in 1..=cycles {
+        let uptime = MIN_UPTIME_MS + rng.below(MAX_UPTIME_MS - MIN_UPTIME_MS);
+        std::thread::sleep(std::time::Duration::from_millis(uptime));
+        power_cycles.store(cycle, Ordering::SeqCst);
+        println!("\n[chaos] power cycle {} after {} ms", cycle, uptime);
+        debug_console.write_all(b"0").expect("Unable to reset H1 (failed write)");
+        debug_console.flush().expect("Unable to reset H1 (failed flush)");
+        std::thread::sleep(std::time::Duration::from_millis(off_delay));
+        debug_console.write_all(b"1").expect("Unable to restart H1 (failed write)");
+        debug_console.flush().expect("Unable to restart H1 (failed flush)");
+    }
+    std::thread::sleep(std::time::Duration::from_millis(SETTLE_MS));
+
+    let checker = checker.lock().unwrap();
+    println!("\nChaos test: {} power cycles, {} boots reported, {} violations",
+             cycles, checker.boots, checker.violations);
+    if checker.boots == 0 {
+        println!("No boots were reported; is nvcounter_chaos running?");
+        std::process::exit(6);
+    }
+    if checker.violations > 0 {
+        println!("TEST_FINISHED: FAIL");
+        std::process::exit(3);
+    }
+    println!("TEST_FINISHED: SUCCESS");
+    std::process::exit(0);
+}
@@ -0,0 +1,147 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+// Scripted access to otpilot for --command. The runner switches the otpilot
+// console into binary mode (see shared-lib/consoleutils), sends one request,
+// prints the response and switches the console back to text mode. Unlike
+// scraping the text console, this is not confused by log output, which ends up
+// between frames and is skipped.
+
+use consoleutils::cobs::FrameReader;
+use consoleutils::protocol;
+use consoleutils::protocol::{Request,Response,BINARY_MODE_SEQUENCE,MAX_FRAME_LEN,MAX_WIRE_LEN};
+use spiutils::protocol::config::ConfigKey;
+use spiutils::protocol::wire::WireEnum;
+use std::io::{Read,Write};
+
+pub struct Client<P: Read + Write> {
+    port: P,
+    reader: FrameReader<MAX_FRAME_LEN>,
+}
+
+impl<P: Read + Write> Client<P> {
+    // Switches the console on `port` into binary mode.
+    pub fn new(mut port: P) -> std::io::Result<Client<P>> {
+        port.write_all(BINARY_MODE_SEQUENCE)?;
+        port.flush()?;
+        Ok(Client { port, reader: FrameReader::new() })
+    }
+
+    pub fn send(&mut self, request: Request) -> std::io::Result<()> {
+        let mut wire = [0u8; MAX_WIRE_LEN];
+        let len = protocol::encode(&request, &mut wire).map_err(|err| std::io::Error::new(
+            std::io::ErrorKind::InvalidInput, format!("Unable to encode request: {:?}", err)))?;
+        self.port.write_all(&wire[..len])?;
+        self.port.flush()
+    }
+
+    // Sends `request` and waits for the response. Frames that do not decode
+    // as a response, such as text printed by the firmware, are skipped.
+    pub fn call(&mut self, request: Request) -> std::io::Result<Response> {
+        self.send(request)?;
+        let mut byte = [0u8];
+        loop {
+            self.port.read_exact(&mut byte)?;
+            if let Some(frame) = self.reader.push(byte[0]) {
+                if let Ok(response) = protocol::decode::<Response>(frame) {
+                    return Ok(response);
+                }
+            }
+        }
+    }
+}
+
+// Parses a config key name as in spiutils::protocol::config, e.g. ConsoleBaud.
+fn parse_config_key(name: &str) -> Option<u16> {
+    ConfigKey::from_name(name).map(|key| key.to_wire_value())
+}
+
+// Parses a --command value. Board configuration is changed with
+// config-set:<key>=<value> followed by config-commit, or config-rollback to
+// drop the staged changes; config-get:<key> reads a staged value.
+// usb-debug:<mask> sets the kernel's USB debug verbosity, in hex with a 0x
+// prefix or in decimal. timestamps-on and timestamps-off turn the kernel's
+// console line timestamps on and off.
+pub fn parse_request(command: &str) -> Option<Request> {
+    const CONFIG_GET: &str = "config-get:";
+    const CONFIG_SET: &str = "config-set:";
+    const USB_DEBUG: &str = "usb-debug:";
+    if command.starts_with(CONFIG_GET) {
+        return parse_config_key(&command[CONFIG_GET.len()..]).map(Request::GetConfig);
+    }
+    if command.starts_with(CONFIG_SET) {
+        let mut parts = command[CONFIG_SET.len()..].splitn(2, '=');
+        let key = parse_config_key(parts.next()?)?;
+        let value = parts.next()?.parse().ok()?;
+        return Some(Request::SetConfig(key, value));
+    }
+    if command.starts_with(USB_DEBUG) {
+        let mask = &command[USB_DEBUG.len()..];
+        let mask = if mask.starts_with("0x") {
+            u32::from_str_radix(&mask[2..], 16).ok()?
+        } else {
+            mask.parse().ok()?
+        };
+        return Some(Request::SetUsbDebug(mask));
+    }
+    match command {
+        "ping" => Some(Request::Ping),
+        "assert-bmc-cpu-rst" => Some(Request::SetBmcCpuRst(true)),
+        "deassert-bmc-cpu-rst" => Some(Request::SetBmcCpuRst(false)),
+        "assert-bmc-srst" => Some(Request::SetBmcSrst(true)),
+        "deassert-bmc-srst" => Some(Request::SetBmcSrst(false)),
+        "firmware-info" => Some(Request::GetFirmwareInfo),
+        "reset" => Some(Request::Reset),
+        "config-commit" => Some(Request::CommitConfig),
+        "config-rollback" => Some(Request::RollbackConfig),
+        "timestamps-on" => Some(Request::SetConsoleTimestamps(true)),
+        "timestamps-off" => Some(Request::SetConsoleTimestamps(false)),
+        _ => None,
+    }
+}
+
+// Sends `request` to the running board and prints the response. Exits with 0
+// if the request succeeded, 3 if the board reported a failure and 6 if there
+// was no response within `timeout` seconds.
+pub fn run(target_console: std::fs::File, request: Request, timeout: Option<u64>) -> ! {
+    if let Some(timeout) = timeout {
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs(timeout));
+            println!("Timed out waiting for a response.");
+            std::process::exit(6);
+        });
+    }
+
+    let mut client = Client::new(target_console)
+        .expect("Unable to switch the console to binary mode");
+
+    // The board resets without responding.
+    if request == Request::Reset {
+        client.send(request).expect("Console write error");
+        std::process::exit(0);
+    }
+
+    let response = client.call(request).expect("Console error");
+    println!("{:?}", response);
+    client.call(Request::TextMode).expect("Unable to switch the console back to text mode");
+
+    match response {
+        Response::Ok | Response::FirmwareInfo(_) | Response::ConfigValue(_) =>
+            std::process::exit(0),
+        Response::BadRequest | Response::Failed => std::process::exit(3),
+    }
+}
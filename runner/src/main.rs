@@ -15,13 +15,35 @@
 // Display the console output of the running h1 firmware. If --test is passed,
 // this will reset the h1 (to restart the running tests) and exit when the
 // tests are complete. If --test is passed, the return code indicates whether
-// the tests were successful. If --test is not passed, this always returns
+// the tests were successful, and the result lines are CRC-checked if the
+// firmware offers it (see results.rs). If --test is not passed, this always returns
 // success (even when interrupted); this allows it to be killed with an
 // interrupt signal without causing `make` to throw an error.
 //
+// If --timeout is passed, the runner exits after that many seconds: with
+// success if --test is not passed, otherwise with a run failure.
+//
+// If --chaos <cycles> is passed, the runner power-cycles the h1 that many
+// times at random points while userspace/nvcounter_chaos runs, and checks that
+// the nonvolatile counter survives every power loss (see chaos.rs). The
+// return code is as for --test.
+//
+// If --command <command> is passed, the runner does not reset the h1. It sends
+// the command to the running otpilot over the binary console channel, prints
+// the response and exits with 0 on success (see console.rs).
+//
+// If --wall-clock is passed, the timestamps the kernel puts at the start of
+// console lines are converted from time since boot to the host's wall-clock
+// time (see timestamps.rs).
+//
 // Prior to running this, the /dev/ttyUltraConsole3 and /dev/ttyUltraTarget2
 // devices must be properly configured (115200 baud, echo off).
 
+mod chaos;
+mod console;
+mod results;
+mod timestamps;
+
 // Because ending executing via Ctrl-C (SIGINT) is the expected behavior for
 // `make run`, we want to return 0 on SIGINT to minimize the error message from
 // `make` in that case. This signal handler simply terminates the process when
@@ -37,6 +59,17 @@ fn main() {
         .arg(clap::Arg::with_name("delay").help("Reset delay in milliseconds")
              .long("delay").short("d").takes_value(true))
         .arg(clap::Arg::with_name("test").long("test").short("t"))
+        .arg(clap::Arg::with_name("timeout").help("Exit after this many seconds")
+             .long("timeout").takes_value(true))
+        .arg(clap::Arg::with_name("chaos").help("Power-cycle the H1 this many times")
+             .long("chaos").takes_value(true))
+        .arg(clap::Arg::with_name("seed").help("Random seed for --chaos")
+             .long("seed").takes_value(true))
+        .arg(clap::Arg::with_name("command").help("Send a command to the running otpilot")
+             .long("command").takes_value(true))
+        .arg(clap::Arg::with_name("wall-clock")
+             .help("Convert console line timestamps to wall-clock time")
+             .long("wall-clock"))
         .get_matches();
 
     // Parse the command line arguments early so that we fail fast (with a nice
@@ -44,6 +77,25 @@ fn main() {
     // a bad command line argument is used.
     let delay = cmdline_matches.value_of("delay")
         .map_or(100, |d| d.parse().expect("Unable to parse --delay value"));
+    let timeout: Option<u64> = cmdline_matches.value_of("timeout")
+        .map(|t| t.parse().expect("Unable to parse --timeout value"));
+    let chaos_cycles: Option<usize> = cmdline_matches.value_of("chaos")
+        .map(|c| c.parse().expect("Unable to parse --chaos value"));
+    let seed = cmdline_matches.value_of("seed").map_or_else(
+        || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+               .map_or(1, |t| t.as_nanos() as u64),
+        |s| s.parse().expect("Unable to parse --seed value"));
+    let command = cmdline_matches.value_of("command")
+        .map(|c| console::parse_request(c).expect("Unknown --command value"));
+
+    if let Some(request) = command {
+        let target_console = std::fs::OpenOptions::new()
+                             .read(true)
+                             .write(true)
+                             .open("/dev/ttyUltraTarget2")
+                             .expect("Unable to open /dev/ttyUltraTarget2");
+        console::run(target_console, request, timeout);
+    }
 
     // When this runner starts, the H1 will already be running. As a result, we
     // may have missed some of its output. This is particularly problematic for
@@ -69,9 +121,12 @@ fn main() {
     // 2. Wait for --delay milliseconds.
     std::thread::sleep(std::time::Duration::from_millis(delay));
 
-    // 3. Open the console
+    // 3. Open the console. In --test mode the runner talks back to the test
+    //    harness to check the result lines.
+    let test_mode = cmdline_matches.is_present("test");
     let target_console = std::fs::OpenOptions::new()
                          .read(true)
+                         .write(test_mode)
                          .open("/dev/ttyUltraTarget2")
                          .expect("Unable to open /dev/ttyUltraTarget2");
 
@@ -79,44 +134,57 @@ fn main() {
     debug_console.write_all(b"1").expect("Unable to restart H1 (failed write)");
     debug_console.flush().expect("Unable to restart H1 (failed flush)");
 
+    if let Some(cycles) = chaos_cycles {
+        chaos::run(debug_console, target_console, cycles, seed, delay);
+    }
+
     // If we're not in --test mode, return 0 on SIGINT.
-    let test_mode = cmdline_matches.is_present("test");
     if !test_mode {
         unsafe { libc::signal(libc::SIGINT, sigint_handler as usize); }
     }
 
-    // Stream in the console output, and echo it to stdout. If --test was
-    // passed, we search for \nTEST_FINISHED: [FAIL|SUCCESS]\n and terminate
-    // (with the corresponding error code) once found.
-    let fail_message = b"\nTEST_FINISHED: FAIL\n";
-    let success_message = b"\nTEST_FINISHED: SUCCESS\n";
-    // The buffer length needs to match the larger of fail_message and
-    // success_message.
-    let mut buffer = vec![0; std::cmp::max(fail_message.len(), success_message.len())];
-    for byte in target_console.bytes() {
-        let byte = byte.expect("Console read error");
-        std::io::stdout().write(&[byte]).expect("Failed to echo to stdout");
-
-        if test_mode {
-            // Rotate byte into the buffer (shifting the buffer contents 1 byte to
-            // the left and appending byte).
-            for i in 1..buffer.len() { buffer[i-1] = buffer[i]; }
-            *buffer.last_mut().expect("empty buffer") = byte;
-
-            if &buffer[success_message.len()-fail_message.len()..] == fail_message {
-                // Return 3 to match Bazel's behavior (build successful but tests
-                // failed).
-                std::process::exit(3);
+    if let Some(timeout) = timeout {
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs(timeout));
+            std::io::stdout().flush().expect("Failed to flush stdout");
+            if test_mode {
+                println!("\nTimed out waiting for tests to finish.");
+                std::process::exit(6);
             }
+            std::process::exit(0);
+        });
+    }
 
-            if &buffer == success_message {
-                return;
+    if test_mode {
+        results::run(target_console);
+    }
+
+    // Stream in the console output, and echo it to stdout.
+    if cmdline_matches.is_present("wall-clock") {
+        use std::io::BufRead;
+        let mut reader = std::io::BufReader::new(target_console);
+        let mut wall_clock = timestamps::WallClock::default();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line).expect("Console read error") == 0 {
+                break;
             }
+            let host_us = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |t| t.as_micros() as u64);
+            std::io::stdout().write_all(&wall_clock.convert(&line, host_us))
+                .and_then(|_| std::io::stdout().flush())
+                .expect("Failed to echo to stdout");
+        }
+    } else {
+        for byte in target_console.bytes() {
+            let byte = byte.expect("Console read error");
+            std::io::stdout().write(&[byte]).expect("Failed to echo to stdout");
         }
     }
 
-    // Unexpected: we received EOF but tests did not finish. Return 6 (Bazel's
-    // "run failure" error message).
+    // Unexpected: we received EOF. Return 6 (Bazel's "run failure" error
+    // message).
     println!("\nUnexpected EOF from target console.");
     std::process::exit(6);
 }
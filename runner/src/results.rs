@@ -0,0 +1,145 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Test result detection for --test. The test harness offers CRC-protected
+// result lines at startup (see consoleutils::framing). The runner accepts,
+// verifies every frame, and asks for the frames it is missing or that failed
+// their CRC once it has seen the final one, so that a corrupted character in
+// a long capture no longer breaks the result. Firmware that does not offer
+// framing is handled as before, by looking for the plain TEST_FINISHED line.
+
+use consoleutils::framing;
+use consoleutils::framing::FrameError;
+use crate::timestamps;
+use std::collections::BTreeMap;
+use std::io::{BufRead,BufReader,Write};
+
+const FINISHED_PREFIX: &[u8] = b"TEST_FINISHED: ";
+const SUCCESS: &[u8] = b"SUCCESS";
+const FAIL: &[u8] = b"FAIL";
+
+// Exit codes, matching Bazel's.
+const EXIT_TESTS_FAILED: i32 = 3;
+const EXIT_RUN_FAILURE: i32 = 6;
+
+#[derive(Default)]
+struct Frames {
+    // Verified payloads, by sequence number.
+    payloads: BTreeMap<u16, Vec<u8>>,
+    // Sequence number of the TEST_FINISHED frame, once verified.
+    last: Option<u16>,
+    corrupted: usize,
+}
+
+impl Frames {
+    // Sequence numbers up to the last frame that have not been verified.
+    fn missing(&self) -> Vec<u16> {
+        match self.last {
+            Some(last) => (0..=last).filter(|seq| !self.payloads.contains_key(seq)).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+// Streams the console output to stdout until the tests finish, then exits
+// with the result.
+pub fn run(target_console: std::fs::File) -> ! {
+    let mut port = target_console.try_clone().expect("Unable to clone target console");
+    let mut reader = BufReader::new(target_console);
+    let mut frames: Option<Frames> = None;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {},
+            Err(error) => panic!("Console read error: {}", error),
+        }
+        std::io::stdout().write_all(&line).expect("Failed to echo to stdout");
+        let text = if line.ends_with(b"\n") { &line[..line.len() - 1] } else { &line[..] };
+        let text = timestamps::strip(text);
+
+        let frames = match frames.as_mut() {
+            Some(frames) => frames,
+            None => {
+                if text == framing::FRAMING_OFFER {
+                    port.write_all(&[framing::FRAMING_ACCEPT, b'\n'])
+                        .and_then(|_| port.flush())
+                        .expect("Unable to accept framing");
+                    frames = Some(Frames::default());
+                } else if text.starts_with(FINISHED_PREFIX) {
+                    finish(&text[FINISHED_PREFIX.len()..]);
+                }
+                continue;
+            },
+        };
+
+        let mut payload = [0u8; 0x10000];
+        let seq = match framing::decode_line(text, &mut payload) {
+            Ok((seq, len)) => {
+                let payload = &payload[..len];
+                if payload.starts_with(FINISHED_PREFIX) {
+                    frames.last = Some(seq);
+                }
+                frames.payloads.insert(seq, payload.to_vec());
+                seq
+            },
+            Err(FrameError::NotFramed) => continue,
+            Err(_) => {
+                frames.corrupted += 1;
+                continue;
+            },
+        };
+
+        let last = match frames.last {
+            Some(last) => last,
+            None => continue,
+        };
+        let missing = frames.missing();
+        if missing.is_empty() {
+            port.write_all(&[framing::FRAMING_DONE, b'\n'])
+                .and_then(|_| port.flush())
+                .expect("Unable to end framing");
+            if frames.corrupted > 0 {
+                println!("\nRecovered from {} corrupted result lines.", frames.corrupted);
+            }
+            finish(&frames.payloads[&last][FINISHED_PREFIX.len()..]);
+        }
+        // The harness resends the last frame while it waits for us, so
+        // answering only that one repeats requests that got lost without
+        // asking twice for the same frame.
+        if seq == last {
+            for seq in missing {
+                port.write_all(&framing::encode_retransmit(seq)).expect("Unable to request frame");
+            }
+            port.flush().expect("Unable to request frame");
+        }
+    }
+
+    println!("\nUnexpected EOF from target console.");
+    std::process::exit(EXIT_RUN_FAILURE);
+}
+
+// Exits with the status of the TEST_FINISHED line. Anything other than
+// SUCCESS or FAIL is not a result line, e.g. a line the tests printed.
+fn finish(result: &[u8]) {
+    if result == SUCCESS {
+        std::process::exit(0);
+    }
+    if result == FAIL {
+        std::process::exit(EXIT_TESTS_FAILED);
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Console line timestamps. While they are enabled (--command timestamps-on),
+// the kernel starts every console line with the time since boot, as
+// "[ssssssssss.uuuuuu] " (see h1::uart::format_timestamp). With --wall-clock,
+// the runner replaces it with the host's UNIX time at which the line was
+// printed, in the same format, to line the output up with other logs.
+//
+// The device clock is anchored to the host clock at the line that reached
+// the host soonest after it was stamped, so the converted times are off by
+// the UART latency at most, rather than by however late the host read each
+// line. The anchor is dropped when the device time goes backwards, i.e. the
+// device reset.
+
+pub const TIMESTAMP_LEN: usize = 20;
+
+// Parses the timestamp at the start of `line`, in microseconds.
+pub fn parse(line: &[u8]) -> Option<u64> {
+    if line.len() < TIMESTAMP_LEN || line[0] != b'[' || line[11] != b'.' || &line[18..20] != b"] " {
+        return None;
+    }
+    let digits = |range: std::ops::Range<usize>| -> Option<u64> {
+        line[range].iter().try_fold(0u64, |value, &digit| match digit {
+            b'0'..=b'9' => Some(value * 10 + (digit - b'0') as u64),
+            _ => None,
+        })
+    };
+    Some(digits(1..11)? * 1_000_000 + digits(12..18)?)
+}
+
+// Returns `line` without its timestamp, if it has one.
+pub fn strip(line: &[u8]) -> &[u8] {
+    match parse(line) {
+        Some(_) => &line[TIMESTAMP_LEN..],
+        None => line,
+    }
+}
+
+fn format(time_us: u64) -> String {
+    format!("[{:010}.{:06}] ", time_us / 1_000_000 % 10_000_000_000, time_us % 1_000_000)
+}
+
+#[derive(Default)]
+pub struct WallClock {
+    // Host time minus device time, in microseconds.
+    offset_us: Option<i64>,
+    last_device_us: u64,
+}
+
+impl WallClock {
+    // Returns `line`, received at UNIX time `host_us`, with its timestamp
+    // converted to wall-clock time. Lines without a timestamp are returned
+    // unchanged.
+    pub fn convert(&mut self, line: &[u8], host_us: u64) -> Vec<u8> {
+        let device_us = match parse(line) {
+            Some(device_us) => device_us,
+            None => return line.to_vec(),
+        };
+        if device_us < self.last_device_us {
+            self.offset_us = None;
+        }
+        self.last_device_us = device_us;
+
+        let offset_us = host_us as i64 - device_us as i64;
+        let offset_us = self.offset_us.map_or(offset_us, |anchor| anchor.min(offset_us));
+        self.offset_us = Some(offset_us);
+
+        let mut converted = format((device_us as i64 + offset_us) as u64).into_bytes();
+        converted.extend_from_slice(&line[TIMESTAMP_LEN..]);
+        converted
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Consistent Overhead Byte Stuffing.
+//!
+//! COBS rewrites a frame so that it contains no zero bytes, at a cost of one
+//! byte per 254 bytes of payload. A zero byte then unambiguously delimits
+//! frames on a byte stream.
+
+/// Delimits frames on the wire.
+pub const DELIMITER: u8 = 0;
+
+/// Returns the maximum encoded length of a frame of `len` bytes, excluding
+/// delimiters.
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// Encodes `data` into `out`. Returns the encoded length, or None if `out`
+/// is too small.
+pub fn encode(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    // Each block starts with a code byte: the offset to the next zero.
+    let mut code_index = 0;
+    let mut code: u8 = 1;
+    let mut out_len = 1;
+    if out.is_empty() {
+        return None;
+    }
+
+    for &byte in data {
+        if byte != 0 {
+            *out.get_mut(out_len)? = byte;
+            out_len += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            out[code_index] = code;
+            code = 1;
+            code_index = out_len;
+            *out.get_mut(out_len)? = 0;
+            out_len += 1;
+        }
+    }
+    out[code_index] = code;
+
+    Some(out_len)
+}
+
+/// Decodes `data` into `out`. Returns the decoded length, or None if `data`
+/// is not valid COBS or `out` is too small.
+pub fn decode(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut index = 0;
+    let mut out_len = 0;
+
+    while index < data.len() {
+        let code = data[index] as usize;
+        let end = index + code;
+        if code == 0 || end > data.len() {
+            return None;
+        }
+        for &byte in &data[index + 1..end] {
+            if byte == 0 {
+                return None;
+            }
+            *out.get_mut(out_len)? = byte;
+            out_len += 1;
+        }
+        index = end;
+        // A full block is not followed by an implicit zero, nor is the last.
+        if code != 0xff && index < data.len() {
+            *out.get_mut(out_len)? = 0;
+            out_len += 1;
+        }
+    }
+
+    Some(out_len)
+}
+
+/// Collects the bytes of a frame as they arrive from the wire.
+pub struct FrameReader<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    overflow: bool,
+}
+
+impl<const N: usize> FrameReader<N> {
+    /// Creates a reader for encoded frames of up to `N` bytes.
+    pub const fn new() -> Self {
+        FrameReader {
+            buf: [0; N],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Adds a byte from the wire. Returns the encoded frame once its
+    /// delimiter arrives. Empty frames and frames longer than `N` are dropped.
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if byte == DELIMITER {
+            let len = self.len;
+            let overflow = self.overflow;
+            self.len = 0;
+            self.overflow = false;
+            if len == 0 || overflow {
+                return None;
+            }
+            return Some(&self.buf[..len]);
+        }
+
+        if self.len < N {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        } else {
+            self.overflow = true;
+        }
+        None
+    }
+}
+
+impl<const N: usize> Default for FrameReader<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(data: &[u8], encoded: &[u8]) {
+        let mut out = [0xaa; 600];
+        let len = encode(data, &mut out).unwrap();
+        assert_eq!(&out[..len], encoded);
+        assert!(len <= max_encoded_len(data.len()));
+
+        let mut decoded = [0xaa; 600];
+        let len = decode(encoded, &mut decoded).unwrap();
+        assert_eq!(&decoded[..len], data);
+    }
+
+    #[test]
+    fn examples() {
+        round_trip(&[], &[0x01]);
+        round_trip(&[0x00], &[0x01, 0x01]);
+        round_trip(&[0x00, 0x00], &[0x01, 0x01, 0x01]);
+        round_trip(&[0x11, 0x22, 0x00, 0x33], &[0x03, 0x11, 0x22, 0x02, 0x33]);
+        round_trip(&[0x11, 0x00, 0x00, 0x00], &[0x02, 0x11, 0x01, 0x01, 0x01]);
+    }
+
+    #[test]
+    fn long_runs() {
+        let mut data = [0x42; 254];
+        let mut encoded = [0x42; 256];
+        encoded[0] = 0xff;
+        encoded[255] = 0x01;
+        round_trip(&data, &encoded);
+
+        data[253] = 0;
+        let mut encoded = [0x42; 255];
+        encoded[0] = 0xfe;
+        encoded[254] = 0x01;
+        round_trip(&data, &encoded);
+    }
+
+    #[test]
+    fn small_buffers() {
+        let mut out = [0; 4];
+        assert_eq!(encode(&[1, 2, 3, 4], &mut out), None);
+        assert_eq!(decode(&[0x05, 1, 2, 3, 4], &mut out), Some(4));
+        assert_eq!(decode(&[0x06, 1, 2, 3, 4, 5], &mut out), None);
+    }
+
+    #[test]
+    fn malformed() {
+        let mut out = [0; 16];
+        assert_eq!(decode(&[0x00], &mut out), None);
+        assert_eq!(decode(&[0x03, 0x11], &mut out), None);
+        assert_eq!(decode(&[0x03, 0x11, 0x00], &mut out), None);
+    }
+
+    #[test]
+    fn frame_reader() {
+        let mut reader = FrameReader::<4>::new();
+        assert_eq!(reader.push(0x00), None);
+        assert_eq!(reader.push(0x02), None);
+        assert_eq!(reader.push(0x11), None);
+        assert_eq!(reader.push(0x00), Some(&[0x02, 0x11][..]));
+
+        for _ in 0..5 {
+            assert_eq!(reader.push(0x01), None);
+        }
+        assert_eq!(reader.push(0x00), None);
+        assert_eq!(reader.push(0x01), None);
+        assert_eq!(reader.push(0x00), Some(&[0x01][..]));
+    }
+}
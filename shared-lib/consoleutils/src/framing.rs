@@ -0,0 +1,270 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! CRC-protected text lines.
+//!
+//! Long console captures occasionally corrupt characters. Lines that a tool
+//! must parse reliably, such as test results, can be sent as frames that
+//! stay readable on the console:
+//!
+//! ```text
+//! @@<seq> <len> <payload> <crc>\n
+//! ```
+//!
+//! `seq`, `len` and `crc` are four lowercase hex digits. `seq` numbers the
+//! frames from 0, `len` is the length of the unescaped payload, and `crc` is
+//! the CRC-16/CCITT-FALSE of `seq` (big endian) followed by the unescaped
+//! payload. Payload bytes outside printable ASCII, and backslashes, are
+//! escaped as `\xHH`.
+//!
+//! Framing is negotiated: the device prints `FRAMING_OFFER` on a line of
+//! its own, and frames lines only if the tool answers with `FRAMING_ACCEPT`.
+//! The tool asks for a frame again by sending a retransmit request (see
+//! `encode_retransmit`), and sends `FRAMING_DONE` once it has every frame.
+
+/// Starts every frame.
+pub const FRAME_MARKER: &[u8] = b"@@";
+
+/// Printed by the device, on a line of its own, to offer framing.
+pub const FRAMING_OFFER: &[u8] = b"CONSOLE_FRAMING_OFFER crc16";
+
+/// Sent by the tool to accept framing.
+pub const FRAMING_ACCEPT: u8 = b'F';
+
+/// Sent by the tool once it has received every frame it needs.
+pub const FRAMING_DONE: u8 = b'D';
+
+/// Starts a retransmit request.
+pub const RETRANSMIT: u8 = b'R';
+
+/// The length of an encoded retransmit request.
+pub const RETRANSMIT_LEN: usize = 6;
+
+/// Returns the maximum length of a frame with a payload of `len` bytes,
+/// including the newline.
+pub const fn max_line_len(len: usize) -> usize {
+    FRAME_MARKER.len() + 4 + 1 + 4 + 1 + 4 * len + 1 + 4 + 1
+}
+
+/// Why a line could not be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The line is not a frame, but ordinary text.
+    NotFramed,
+    /// The line is a frame, but damaged so that it cannot be parsed.
+    Malformed,
+    /// The line parses, but its CRC does not match.
+    BadCrc,
+    /// The output buffer is too small for the payload.
+    OutTooSmall,
+}
+
+/// Computes the CRC of a frame.
+pub fn crc16(seq: u16, payload: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for &byte in seq.to_be_bytes().iter().chain(payload.iter()) {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn needs_escape(byte: u8) -> bool {
+    !(0x20..=0x7e).contains(&byte) || byte == b'\\'
+}
+
+fn write_hex16(value: u16, out: &mut [u8]) {
+    for (i, byte) in out[..4].iter_mut().enumerate() {
+        *byte = HEX_DIGITS[((value >> (12 - 4 * i)) & 0xf) as usize];
+    }
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        _ => None,
+    }
+}
+
+fn read_hex(digits: &[u8]) -> Option<u16> {
+    digits.iter().try_fold(0u16, |value, &byte| Some((value << 4) | hex_digit(byte)? as u16))
+}
+
+/// Encodes `payload` as frame `seq` into `out`. Returns the length of the
+/// line, including the newline, or None if `out` is too small or the
+/// payload is longer than 0xffff bytes.
+pub fn encode_line(seq: u16, payload: &[u8], out: &mut [u8]) -> Option<usize> {
+    if payload.len() > 0xffff {
+        return None;
+    }
+    let mut len = 0;
+    let mut push = |bytes: &[u8]| -> Option<()> {
+        out.get_mut(len..len + bytes.len())?.copy_from_slice(bytes);
+        len += bytes.len();
+        Some(())
+    };
+    let mut hex = [0u8; 4];
+
+    push(FRAME_MARKER)?;
+    write_hex16(seq, &mut hex);
+    push(&hex)?;
+    push(b" ")?;
+    write_hex16(payload.len() as u16, &mut hex);
+    push(&hex)?;
+    push(b" ")?;
+    for &byte in payload {
+        if needs_escape(byte) {
+            push(&[b'\\', b'x', HEX_DIGITS[(byte >> 4) as usize], HEX_DIGITS[(byte & 0xf) as usize]])?;
+        } else {
+            push(&[byte])?;
+        }
+    }
+    push(b" ")?;
+    write_hex16(crc16(seq, payload), &mut hex);
+    push(&hex)?;
+    push(b"\n")?;
+    Some(len)
+}
+
+/// Decodes a frame, with or without its trailing newline, and writes the
+/// payload to `out`. Returns the sequence number and the payload length.
+pub fn decode_line(line: &[u8], out: &mut [u8]) -> Result<(u16, usize), FrameError> {
+    let line = match line.split_last() {
+        Some((b'\n', rest)) => rest,
+        _ => line,
+    };
+    if !line.starts_with(FRAME_MARKER) {
+        return Err(FrameError::NotFramed);
+    }
+    let line = &line[FRAME_MARKER.len()..];
+    // seq, space, len, space, ..., space, crc
+    if line.len() < 4 + 1 + 4 + 1 + 1 + 4 || line[4] != b' ' || line[9] != b' ' ||
+        line[line.len() - 5] != b' ' {
+        return Err(FrameError::Malformed);
+    }
+    let seq = read_hex(&line[..4]).ok_or(FrameError::Malformed)?;
+    let len = read_hex(&line[5..9]).ok_or(FrameError::Malformed)? as usize;
+    let crc = read_hex(&line[line.len() - 4..]).ok_or(FrameError::Malformed)?;
+    let escaped = &line[10..line.len() - 5];
+
+    let mut out_len = 0;
+    let mut index = 0;
+    while index < escaped.len() {
+        let byte = match escaped[index] {
+            b'\\' => {
+                let digits = escaped.get(index + 1..index + 4).ok_or(FrameError::Malformed)?;
+                if digits[0] != b'x' {
+                    return Err(FrameError::Malformed);
+                }
+                index += 4;
+                read_hex(&digits[1..]).ok_or(FrameError::Malformed)? as u8
+            },
+            byte => {
+                index += 1;
+                byte
+            },
+        };
+        *out.get_mut(out_len).ok_or(FrameError::OutTooSmall)? = byte;
+        out_len += 1;
+    }
+    if out_len != len {
+        return Err(FrameError::Malformed);
+    }
+    if crc16(seq, &out[..out_len]) != crc {
+        return Err(FrameError::BadCrc);
+    }
+    Ok((seq, out_len))
+}
+
+/// Encodes a request to send frame `seq` again.
+pub fn encode_retransmit(seq: u16) -> [u8; RETRANSMIT_LEN] {
+    let mut out = [RETRANSMIT, 0, 0, 0, 0, b'\n'];
+    write_hex16(seq, &mut out[1..5]);
+    out
+}
+
+/// Parses a retransmit request, with or without its trailing newline.
+/// Returns the requested sequence number.
+pub fn parse_retransmit(request: &[u8]) -> Option<u16> {
+    match request {
+        [RETRANSMIT, digits @ .., b'\n'] | [RETRANSMIT, digits @ ..] if digits.len() == 4 =>
+            read_hex(digits),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(line: &[u8]) -> Result<(u16, std::vec::Vec<u8>), FrameError> {
+        let mut out = [0u8; 64];
+        let (seq, len) = decode_line(line, &mut out)?;
+        Ok((seq, out[..len].to_vec()))
+    }
+
+    #[test]
+    fn crc() {
+        // CRC-16/CCITT-FALSE check value, with the sequence number as the
+        // first two bytes of "123456789".
+        assert_eq!(crc16(0x3132, b"3456789"), 0x29b1);
+    }
+
+    #[test]
+    fn round_trip() {
+        let payload = b"Finished test a\\b\x01";
+        let mut line = [0u8; max_line_len(18)];
+        assert_eq!(encode_line(7, payload, &mut line[..16]), None);
+
+        let len = encode_line(7, payload, &mut line).unwrap();
+        assert!(line[..len].starts_with(b"@@0007 0012 Finished test a\\x5cb\\x01 "));
+        assert_eq!(line[len - 1], b'\n');
+        assert_eq!(decode(&line[..len]), Ok((7, payload.to_vec())));
+        assert_eq!(decode(&line[..len - 1]), Ok((7, payload.to_vec())));
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut line = [0u8; max_line_len(32)];
+        let len = encode_line(1, b"Running test one", &mut line).unwrap();
+
+        let mut flipped = line;
+        flipped[14] ^= 0x01;
+        assert_eq!(decode(&flipped[..len]), Err(FrameError::BadCrc));
+
+        let mut dropped = [0u8; max_line_len(32)];
+        dropped[..14].copy_from_slice(&line[..14]);
+        dropped[14..len - 1].copy_from_slice(&line[15..len]);
+        assert_eq!(decode(&dropped[..len - 1]), Err(FrameError::Malformed));
+
+        assert_eq!(decode(b"@@00zz"), Err(FrameError::Malformed));
+        assert_eq!(decode(b"Running test one\n"), Err(FrameError::NotFramed));
+    }
+
+    #[test]
+    fn retransmit() {
+        assert_eq!(&encode_retransmit(0x12), b"R0012\n");
+        assert_eq!(parse_retransmit(b"R0012\n"), Some(0x12));
+        assert_eq!(parse_retransmit(b"R0012"), Some(0x12));
+        assert_eq!(parse_retransmit(b"R12\n"), None);
+        assert_eq!(parse_retransmit(b"D\n"), None);
+    }
+}
@@ -0,0 +1,34 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#![crate_type = "lib"]
+#![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+//! A binary channel multiplexed over the otpilot console.
+//!
+//! The console normally carries text for humans. A tool that wants to script
+//! otpilot sends `protocol::BINARY_MODE_SEQUENCE`, after which both sides
+//! exchange corepack-encoded messages in COBS frames, delimited by zero bytes.
+//! Text the device prints in the meantime ends up between frames and is
+//! dropped by the reader.
+//!
+//! Separately, `framing` protects individual text lines with a CRC, for
+//! output such as test results that a tool must read reliably.
+
+pub mod cobs;
+pub mod framing;
+pub mod protocol;
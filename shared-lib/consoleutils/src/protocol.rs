@@ -0,0 +1,246 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Messages on the binary console channel.
+//!
+//! Each `Request` from the host is answered by exactly one `Response`. On
+//! the wire, a message is its corepack encoding, COBS-encoded and surrounded
+//! by delimiters.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::cobs;
+
+/// Switches the console from text to binary mode. Starts with ESC NUL, which
+/// cannot be typed by accident.
+pub const BINARY_MODE_SEQUENCE: &[u8] = b"\x1b\x00COBS";
+
+/// Maximum length of an encoded message, before COBS.
+pub const MAX_MESSAGE_LEN: usize = 256;
+
+/// Maximum length of a COBS frame on the wire, excluding delimiters.
+pub const MAX_FRAME_LEN: usize = cobs::max_encoded_len(MAX_MESSAGE_LEN);
+
+/// Maximum length of a message on the wire, including delimiters.
+pub const MAX_WIRE_LEN: usize = MAX_FRAME_LEN + 2;
+
+/// A command from the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Request {
+    /// Checks that the channel is up.
+    Ping,
+
+    /// Asserts (true) or deasserts (false) BMC_CPU_RST.
+    SetBmcCpuRst(bool),
+
+    /// Asserts (true) or deasserts (false) BMC_SRST.
+    SetBmcSrst(bool),
+
+    /// Reads the versions of all firmware segments.
+    GetFirmwareInfo,
+
+    /// Resets the chip. There is no response.
+    Reset,
+
+    /// Switches the console back to text mode, after the response.
+    TextMode,
+
+    /// Reads the staged value of a board configuration key (see
+    /// `spiutils::protocol::config::ConfigKey`).
+    GetConfig(u16),
+
+    /// Stages a new value for a board configuration key.
+    SetConfig(u16, u32),
+
+    /// Writes the staged board configuration to flash. It takes effect on
+    /// the next boot.
+    CommitConfig,
+
+    /// Discards the staged board configuration changes.
+    RollbackConfig,
+
+    /// Sets which categories of USB driver debug messages the kernel prints.
+    /// Only debug kernels on boards with USB support this.
+    SetUsbDebug(u32),
+
+    /// Turns the kernel's console line timestamps on (true) or off (false).
+    SetConsoleTimestamps(bool),
+}
+
+/// The build version of a firmware segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Version {
+    /// Time epoch
+    pub epoch: u32,
+
+    /// Major version
+    pub major: u32,
+
+    /// Minor version
+    pub minor: u32,
+
+    /// Timestamp
+    pub timestamp: u64,
+}
+
+/// Versions of the firmware segments, if they could be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareInfo {
+    /// The active RO segment.
+    pub active_ro: Option<Version>,
+
+    /// The active RW segment.
+    pub active_rw: Option<Version>,
+
+    /// The inactive RO segment.
+    pub inactive_ro: Option<Version>,
+
+    /// The inactive RW segment.
+    pub inactive_rw: Option<Version>,
+}
+
+/// The device's answer to a `Request`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Response {
+    /// The request succeeded.
+    Ok,
+
+    /// Answers `Request::GetFirmwareInfo`.
+    FirmwareInfo(FirmwareInfo),
+
+    /// The request could not be decoded.
+    BadRequest,
+
+    /// The request failed on the device.
+    Failed,
+
+    /// Answers `Request::GetConfig`.
+    ConfigValue(u32),
+}
+
+/// An error encoding or decoding a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The frame is not valid COBS.
+    Framing,
+
+    /// corepack could not encode or decode the message.
+    Encoding,
+
+    /// The message does not fit the buffer.
+    TooLong,
+}
+
+/// Writes `message` to `out` as it goes on the wire, including delimiters.
+/// Returns the number of bytes written.
+pub fn encode<T: Serialize>(message: &T, out: &mut [u8]) -> Result<usize, Error> {
+    let mut raw = [0u8; MAX_MESSAGE_LEN];
+    let mut raw_len = 0;
+    {
+        let mut serializer = corepack::Serializer::new(|bytes: &[u8]| {
+            let end = raw_len + bytes.len();
+            if end > raw.len() {
+                return Err(corepack::error::Error::TooBig);
+            }
+            raw[raw_len..end].copy_from_slice(bytes);
+            raw_len = end;
+            Ok(())
+        });
+        message.serialize(&mut serializer).map_err(|err| match err {
+            corepack::error::Error::TooBig => Error::TooLong,
+            _ => Error::Encoding,
+        })?;
+    }
+
+    if out.len() < 2 {
+        return Err(Error::TooLong);
+    }
+    let last = out.len() - 1;
+    let len = cobs::encode(&raw[..raw_len], &mut out[1..last]).ok_or(Error::TooLong)?;
+    out[0] = cobs::DELIMITER;
+    out[len + 1] = cobs::DELIMITER;
+    Ok(len + 2)
+}
+
+/// Decodes a message from a COBS frame, as returned by `cobs::FrameReader`.
+pub fn decode<T: DeserializeOwned>(frame: &[u8]) -> Result<T, Error> {
+    let mut raw = [0u8; MAX_MESSAGE_LEN];
+    let len = cobs::decode(frame, &mut raw).ok_or(Error::Framing)?;
+    corepack::from_bytes(&raw[..len]).map_err(|_| Error::Encoding)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + core::fmt::Debug>(message: T) {
+        let mut wire = [0u8; MAX_WIRE_LEN];
+        let len = encode(&message, &mut wire).unwrap();
+        assert_eq!(wire[0], cobs::DELIMITER);
+        assert_eq!(wire[len - 1], cobs::DELIMITER);
+        assert!(!wire[1..len - 1].contains(&cobs::DELIMITER));
+        assert_eq!(decode::<T>(&wire[1..len - 1]).unwrap(), message);
+    }
+
+    #[test]
+    fn requests() {
+        round_trip(Request::Ping);
+        round_trip(Request::SetBmcCpuRst(true));
+        round_trip(Request::SetBmcSrst(false));
+        round_trip(Request::GetFirmwareInfo);
+        round_trip(Request::Reset);
+        round_trip(Request::TextMode);
+        round_trip(Request::SetConfig(1, 921600));
+        round_trip(Request::RollbackConfig);
+        round_trip(Request::SetUsbDebug(0x7));
+        round_trip(Request::SetConsoleTimestamps(true));
+    }
+
+    #[test]
+    fn responses() {
+        let version = Version {
+            epoch: 0,
+            major: 1,
+            minor: 2,
+            timestamp: 0x0102030405060708,
+        };
+        round_trip(Response::Ok);
+        round_trip(Response::ConfigValue(0xffffffff));
+        round_trip(Response::FirmwareInfo(FirmwareInfo {
+            active_ro: Some(version),
+            active_rw: Some(version),
+            inactive_ro: None,
+            inactive_rw: Some(version),
+        }));
+        round_trip(Response::BadRequest);
+        round_trip(Response::Failed);
+    }
+
+    #[test]
+    fn bad_frames() {
+        assert_eq!(decode::<Request>(&[0x03, 0x11]), Err(Error::Framing));
+        assert_eq!(decode::<Request>(&[0x02, 0xc1]), Err(Error::Encoding));
+    }
+
+    #[test]
+    fn out_too_small() {
+        let mut wire = [0u8; 3];
+        assert_eq!(encode(&Request::SetBmcSrst(true), &mut wire), Err(Error::TooLong));
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fixed-size 256-bit integers and Montgomery arithmetic.
+//!
+//! Integers are stored as eight little-endian 32-bit limbs.
+
+/// A 256-bit unsigned integer as little-endian 32-bit limbs.
+pub type U256 = [u32; 8];
+
+/// Zero.
+pub const ZERO: U256 = [0; 8];
+
+/// One.
+pub const ONE: U256 = [1, 0, 0, 0, 0, 0, 0, 0];
+
+/// Decodes a big-endian byte string.
+pub fn from_be_bytes(bytes: &[u8; 32]) -> U256 {
+    let mut out = ZERO;
+    for (i, limb) in out.iter_mut().enumerate() {
+        let offset = 28 - 4 * i;
+        *limb = u32::from_be_bytes([
+            bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+    }
+    out
+}
+
+/// Encodes as a big-endian byte string.
+pub fn to_be_bytes(value: &U256) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    for (i, limb) in value.iter().enumerate() {
+        let offset = 28 - 4 * i;
+        out[offset..offset + 4].copy_from_slice(&limb.to_be_bytes());
+    }
+    out
+}
+
+/// Decodes a little-endian byte string.
+pub fn from_le_bytes(bytes: &[u8; 32]) -> U256 {
+    let mut out = ZERO;
+    for (limb, chunk) in out.iter_mut().zip(bytes.chunks(4)) {
+        *limb = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    out
+}
+
+/// Encodes as a little-endian byte string.
+pub fn to_le_bytes(value: &U256) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    for (chunk, limb) in out.chunks_mut(4).zip(value.iter()) {
+        chunk.copy_from_slice(&limb.to_le_bytes());
+    }
+    out
+}
+
+/// Computes `a + b`, returning the sum and the carry out (0 or 1).
+pub fn add(a: &U256, b: &U256) -> (U256, u32) {
+    let mut out = ZERO;
+    let mut carry = 0u64;
+    for i in 0..8 {
+        let sum = a[i] as u64 + b[i] as u64 + carry;
+        out[i] = sum as u32;
+        carry = sum >> 32;
+    }
+    (out, carry as u32)
+}
+
+/// Computes `a - b`, returning the difference and the borrow out (0 or 1).
+pub fn sub(a: &U256, b: &U256) -> (U256, u32) {
+    let mut out = ZERO;
+    let mut borrow = 0u64;
+    for i in 0..8 {
+        let diff = (a[i] as u64).wrapping_sub(b[i] as u64).wrapping_sub(borrow);
+        out[i] = diff as u32;
+        borrow = (diff >> 32) & 1;
+    }
+    (out, borrow as u32)
+}
+
+/// Returns `a` if `choice` is 0 and `b` if `choice` is 1, without branching.
+pub fn select(choice: u32, a: &U256, b: &U256) -> U256 {
+    let mask = 0u32.wrapping_sub(choice);
+    let mut out = ZERO;
+    for (i, limb) in out.iter_mut().enumerate() {
+        *limb = (a[i] & !mask) | (b[i] & mask);
+    }
+    out
+}
+
+/// Returns true if `a` is zero.
+pub fn is_zero(a: &U256) -> bool {
+    a.iter().fold(0, |acc, limb| acc | limb) == 0
+}
+
+/// Returns true if `a < b`.
+pub fn less_than(a: &U256, b: &U256) -> bool {
+    sub(a, b).1 == 1
+}
+
+/// Returns bit `index` of `a`.
+pub fn bit(a: &U256, index: usize) -> u32 {
+    (a[index / 32] >> (index % 32)) & 1
+}
+
+/// An odd modulus with precomputed constants for Montgomery arithmetic
+/// with R = 2^256.
+pub struct Modulus {
+    /// The modulus.
+    pub m: U256,
+    /// -m^-1 mod 2^32.
+    pub m_prime: u32,
+    /// R^2 mod m.
+    pub r2: U256,
+}
+
+impl Modulus {
+    /// Computes `a + b mod m` for `a, b < m`.
+    pub fn add(&self, a: &U256, b: &U256) -> U256 {
+        let (sum, carry) = add(a, b);
+        let (reduced, borrow) = sub(&sum, &self.m);
+        // Keep the unreduced sum only if it did not overflow and was below m.
+        select(borrow & !carry & 1, &reduced, &sum)
+    }
+
+    /// Computes `a - b mod m` for `a, b < m`.
+    pub fn sub(&self, a: &U256, b: &U256) -> U256 {
+        let (diff, borrow) = sub(a, b);
+        let (wrapped, _) = add(&diff, &self.m);
+        select(borrow, &diff, &wrapped)
+    }
+
+    /// Computes `-a mod m` for `a < m`.
+    pub fn neg(&self, a: &U256) -> U256 {
+        self.sub(&ZERO, a)
+    }
+
+    /// Reduces `a < 2m` modulo m.
+    pub fn reduce_once(&self, a: &U256) -> U256 {
+        let (reduced, borrow) = sub(a, &self.m);
+        select(borrow, &reduced, a)
+    }
+
+    /// Montgomery multiplication: computes `a * b * R^-1 mod m` for
+    /// `a, b < m`.
+    pub fn mul(&self, a: &U256, b: &U256) -> U256 {
+        // Coarsely integrated operand scanning (CIOS).
+        let mut t = [0u32; 10];
+        for b_limb in b.iter() {
+            let mut carry = 0u64;
+            for j in 0..8 {
+                let sum = t[j] as u64 + a[j] as u64 * *b_limb as u64 + carry;
+                t[j] = sum as u32;
+                carry = sum >> 32;
+            }
+            let sum = t[8] as u64 + carry;
+            t[8] = sum as u32;
+            t[9] = (sum >> 32) as u32;
+
+            let factor = t[0].wrapping_mul(self.m_prime);
+            let sum = t[0] as u64 + factor as u64 * self.m[0] as u64;
+            let mut carry = sum >> 32;
+            for j in 1..8 {
+                let sum = t[j] as u64 + factor as u64 * self.m[j] as u64 + carry;
+                t[j - 1] = sum as u32;
+                carry = sum >> 32;
+            }
+            let sum = t[8] as u64 + carry;
+            t[7] = sum as u32;
+            t[8] = t[9] + (sum >> 32) as u32;
+        }
+
+        let mut result = ZERO;
+        result.copy_from_slice(&t[..8]);
+        let (reduced, borrow) = sub(&result, &self.m);
+        select(borrow & !t[8] & 1, &reduced, &result)
+    }
+
+    /// Converts `a < m` into the Montgomery domain.
+    pub fn to_montgomery(&self, a: &U256) -> U256 {
+        self.mul(a, &self.r2)
+    }
+
+    /// Converts `a` out of the Montgomery domain.
+    pub fn from_montgomery(&self, a: &U256) -> U256 {
+        self.mul(a, &ONE)
+    }
+
+    /// Returns R mod m, i.e. one in the Montgomery domain.
+    pub fn one(&self) -> U256 {
+        self.to_montgomery(&ONE)
+    }
+
+    /// Computes `a^e` for `a` in the Montgomery domain. `e` is public.
+    pub fn pow(&self, a: &U256, e: &U256) -> U256 {
+        let mut result = self.one();
+        for index in (0..256).rev() {
+            result = self.mul(&result, &result);
+            let product = self.mul(&result, a);
+            result = select(bit(e, index), &result, &product);
+        }
+        result
+    }
+
+    /// Computes `a^-1` for `a` in the Montgomery domain, using Fermat's
+    /// little theorem. `m` must be prime. Returns zero for zero.
+    pub fn invert(&self, a: &U256) -> U256 {
+        let (exponent, _) = sub(&self.m, &[2, 0, 0, 0, 0, 0, 0, 0]);
+        self.pow(a, &exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The P-256 group order, an arbitrary odd prime for testing.
+    const N: Modulus = Modulus {
+        m: [0xfc632551, 0xf3b9cac2, 0xa7179e84, 0xbce6faad,
+            0xffffffff, 0xffffffff, 0x00000000, 0xffffffff],
+        m_prime: 0xee00bc4f,
+        r2: [0xbe79eea2, 0x83244c95, 0x49bd6fa6, 0x4699799c,
+             0x2b6bec59, 0x2845b239, 0xf3d95620, 0x66e12d94],
+    };
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let value = from_be_bytes(&bytes);
+        assert_eq!(value[0], 0x1c1d1e1f);
+        assert_eq!(value[7], 0x00010203);
+        assert_eq!(to_be_bytes(&value), bytes);
+        let value = from_le_bytes(&bytes);
+        assert_eq!(value[0], 0x03020100);
+        assert_eq!(to_le_bytes(&value), bytes);
+    }
+
+    #[test]
+    fn modular_add_sub() {
+        let (max, _) = sub(&N.m, &ONE);
+        assert_eq!(N.add(&max, &ONE), ZERO);
+        assert_eq!(N.sub(&ZERO, &ONE), max);
+        assert_eq!(N.add(&max, &max), N.sub(&max, &ONE));
+    }
+
+    #[test]
+    fn montgomery_round_trip() {
+        let value = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(N.from_montgomery(&N.to_montgomery(&value)), value);
+    }
+
+    #[test]
+    fn inverse() {
+        let value = N.to_montgomery(&[0x12345678, 0, 0, 0, 0, 0, 0, 0x0badcafe]);
+        let product = N.mul(&value, &N.invert(&value));
+        assert_eq!(N.from_montgomery(&product), ONE);
+    }
+}
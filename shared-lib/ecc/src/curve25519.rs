@@ -0,0 +1,530 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! X25519 key agreement (RFC 7748) and Ed25519 signatures (RFC 8032).
+//!
+//! Field elements and scalars are kept in the Montgomery domain of their
+//! respective moduli, like in the P-256 code. All external encodings are
+//! 32-byte little-endian integers.
+
+use crate::bigint;
+use crate::bigint::Modulus;
+use crate::bigint::U256;
+use crate::sha512::Sha512;
+use crate::wipe;
+
+/// Length in bytes of keys, scalars and encoded points.
+pub const KEY_LEN: usize = 32;
+
+/// Length in bytes of an Ed25519 signature.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// The field prime p = 2^255 - 19.
+const P: Modulus = Modulus {
+    m: [0xffffffed, 0xffffffff, 0xffffffff, 0xffffffff,
+        0xffffffff, 0xffffffff, 0xffffffff, 0x7fffffff],
+    m_prime: 0x286bca1b,
+    r2: [0x000005a4, 0, 0, 0, 0, 0, 0, 0],
+};
+
+/// The order L of the Ed25519 base point.
+const L: Modulus = Modulus {
+    m: [0x5cf5d3ed, 0x5812631a, 0xa2f79cd6, 0x14def9de,
+        0x00000000, 0x00000000, 0x00000000, 0x10000000],
+    m_prime: 0x12547e1b,
+    r2: [0x449c0f01, 0xa40611e3, 0x68859347, 0xd00e1ba7,
+         0x17f5be65, 0xceec73d2, 0x7c309a3d, 0x0399411b],
+};
+
+/// (A - 2) / 4 for Curve25519.
+const A24: U256 = [121665, 0, 0, 0, 0, 0, 0, 0];
+
+/// The Edwards curve coefficient d = -121665 / 121666.
+const D: U256 = [0x135978a3, 0x75eb4dca, 0x4141d8ab, 0x00700a4d,
+                 0x7779e898, 0x8cc74079, 0x2b6ffe73, 0x52036cee];
+
+/// sqrt(-1) mod p.
+const SQRT_M1: U256 = [0x4a0ea0b0, 0xc4ee1b27, 0xad2fe478, 0x2f431806,
+                       0x3dfbd7a7, 0x2b4d0099, 0x4fc1df0b, 0x2b832480];
+
+/// (p - 5) / 8, used for square roots.
+const SQRT_EXPONENT: U256 = [0xfffffffd, 0xffffffff, 0xffffffff, 0xffffffff,
+                             0xffffffff, 0xffffffff, 0xffffffff, 0x0fffffff];
+
+/// The Ed25519 base point B.
+const BX: U256 = [0x8f25d51a, 0xc9562d60, 0x9525a7b2, 0x692cc760,
+                  0xfdd6dc5c, 0xc0a4e231, 0xcd6e53fe, 0x216936d3];
+const BY: U256 = [0x66666658, 0x66666666, 0x66666666, 0x66666666,
+                  0x66666666, 0x66666666, 0x66666666, 0x66666666];
+
+/// The X25519 base point u = 9.
+pub const X25519_BASE_POINT: [u8; KEY_LEN] = [
+    9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Clamps a little-endian scalar as described in RFC 7748.
+pub fn clamp_scalar(bytes: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    let mut clamped = *bytes;
+    clamped[0] &= 248;
+    clamped[31] &= 127;
+    clamped[31] |= 64;
+    clamped
+}
+
+/// Decodes a little-endian scalar and clamps it.
+fn clamp(bytes: &[u8; KEY_LEN]) -> U256 {
+    let mut clamped = clamp_scalar(bytes);
+    let scalar = bigint::from_le_bytes(&clamped);
+    wipe(&mut clamped);
+    scalar
+}
+
+/// Decodes a field element, ignoring the top bit and reducing modulo p.
+fn decode_field(bytes: &[u8; KEY_LEN]) -> U256 {
+    let mut value = bigint::from_le_bytes(bytes);
+    value[7] &= 0x7fffffff;
+    P.reduce_once(&value)
+}
+
+/// Computes the X25519 function of RFC 7748 on a scalar and a u-coordinate.
+///
+/// Callers doing key agreement should reject an all-zero result, which
+/// means that the peer sent a point of small order.
+pub fn x25519(scalar: &[u8; KEY_LEN], u: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    let k = clamp(scalar);
+    let x1 = P.to_montgomery(&decode_field(u));
+    let a24 = P.to_montgomery(&A24);
+    let mut x2 = P.one();
+    let mut z2 = bigint::ZERO;
+    let mut x3 = x1;
+    let mut z3 = P.one();
+    let mut swap = 0;
+    for index in (0..255).rev() {
+        let bit = bigint::bit(&k, index);
+        swap ^= bit;
+        let (new_x2, new_x3) = (bigint::select(swap, &x2, &x3), bigint::select(swap, &x3, &x2));
+        let (new_z2, new_z3) = (bigint::select(swap, &z2, &z3), bigint::select(swap, &z3, &z2));
+        x2 = new_x2;
+        x3 = new_x3;
+        z2 = new_z2;
+        z3 = new_z3;
+        swap = bit;
+
+        let a = P.add(&x2, &z2);
+        let aa = P.mul(&a, &a);
+        let b = P.sub(&x2, &z2);
+        let bb = P.mul(&b, &b);
+        let e = P.sub(&aa, &bb);
+        let c = P.add(&x3, &z3);
+        let d = P.sub(&x3, &z3);
+        let da = P.mul(&d, &a);
+        let cb = P.mul(&c, &b);
+        let sum = P.add(&da, &cb);
+        x3 = P.mul(&sum, &sum);
+        let difference = P.sub(&da, &cb);
+        z3 = P.mul(&x1, &P.mul(&difference, &difference));
+        x2 = P.mul(&aa, &bb);
+        z2 = P.mul(&e, &P.add(&aa, &P.mul(&a24, &e)));
+    }
+    x2 = bigint::select(swap, &x2, &x3);
+    z2 = bigint::select(swap, &z2, &z3);
+    bigint::to_le_bytes(&P.from_montgomery(&P.mul(&x2, &P.invert(&z2))))
+}
+
+/// Computes the X25519 public key for a private key.
+pub fn x25519_public_key(scalar: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    x25519(scalar, &X25519_BASE_POINT)
+}
+
+/// A point on the Ed25519 curve in extended coordinates (X:Y:Z:T) with
+/// x = X/Z, y = Y/Z and xy = T/Z, all in the Montgomery domain of p.
+#[derive(Clone, Copy)]
+struct EdwardsPoint {
+    x: U256,
+    y: U256,
+    z: U256,
+    t: U256,
+}
+
+impl EdwardsPoint {
+    fn identity() -> EdwardsPoint {
+        EdwardsPoint { x: bigint::ZERO, y: P.one(), z: P.one(), t: bigint::ZERO }
+    }
+
+    fn from_affine(x: &U256, y: &U256) -> EdwardsPoint {
+        let x = P.to_montgomery(x);
+        let y = P.to_montgomery(y);
+        EdwardsPoint { x, y, z: P.one(), t: P.mul(&x, &y) }
+    }
+
+    fn base_point() -> EdwardsPoint {
+        EdwardsPoint::from_affine(&BX, &BY)
+    }
+
+    fn negate(&self) -> EdwardsPoint {
+        EdwardsPoint { x: P.neg(&self.x), y: self.y, z: self.z, t: P.neg(&self.t) }
+    }
+
+    /// Point addition ("add-2008-hwcd-3"). The formulas are complete, so
+    /// they also handle doubling and the identity.
+    fn add(&self, other: &EdwardsPoint) -> EdwardsPoint {
+        let d2 = P.to_montgomery(&P.add(&D, &D));
+        let a = P.mul(&P.sub(&self.y, &self.x), &P.sub(&other.y, &other.x));
+        let b = P.mul(&P.add(&self.y, &self.x), &P.add(&other.y, &other.x));
+        let c = P.mul(&P.mul(&self.t, &d2), &other.t);
+        let zz = P.mul(&self.z, &other.z);
+        let d = P.add(&zz, &zz);
+        let e = P.sub(&b, &a);
+        let f = P.sub(&d, &c);
+        let g = P.add(&d, &c);
+        let h = P.add(&b, &a);
+        EdwardsPoint {
+            x: P.mul(&e, &f),
+            y: P.mul(&g, &h),
+            z: P.mul(&f, &g),
+            t: P.mul(&e, &h),
+        }
+    }
+
+    fn conditional_swap(choice: u32, a: &mut EdwardsPoint, b: &mut EdwardsPoint) {
+        for (a, b) in [(&mut a.x, &mut b.x), (&mut a.y, &mut b.y),
+                       (&mut a.z, &mut b.z), (&mut a.t, &mut b.t)].iter_mut() {
+            let new_a = bigint::select(choice, a, b);
+            let new_b = bigint::select(choice, b, a);
+            **a = new_a;
+            **b = new_b;
+        }
+    }
+
+    /// Computes `scalar * self` with a Montgomery ladder over all 256 bits.
+    fn mul(&self, scalar: &U256) -> EdwardsPoint {
+        let mut r0 = EdwardsPoint::identity();
+        let mut r1 = *self;
+        for index in (0..256).rev() {
+            let bit = bigint::bit(scalar, index);
+            EdwardsPoint::conditional_swap(bit, &mut r0, &mut r1);
+            r1 = r0.add(&r1);
+            r0 = r0.add(&r0);
+            EdwardsPoint::conditional_swap(bit, &mut r0, &mut r1);
+        }
+        r0
+    }
+
+    /// Encodes the point as y with the sign of x in the top bit.
+    fn encode(&self) -> [u8; KEY_LEN] {
+        let z_inv = P.invert(&self.z);
+        let x = P.from_montgomery(&P.mul(&self.x, &z_inv));
+        let y = P.from_montgomery(&P.mul(&self.y, &z_inv));
+        ed25519_encode(&bigint::to_le_bytes(&x), &bigint::to_le_bytes(&y))
+    }
+
+    /// Decodes a point, returning None if it is not on the curve.
+    fn decode(bytes: &[u8; KEY_LEN]) -> Option<EdwardsPoint> {
+        let (y, sign) = ed25519_split(bytes)?;
+        let sign = sign as u32;
+        let y = bigint::from_le_bytes(&y);
+
+        // x^2 = (y^2 - 1) / (d y^2 + 1). Since p = 5 mod 8, a candidate
+        // root is u v^3 (u v^7)^((p - 5) / 8).
+        let one = P.one();
+        let y_m = P.to_montgomery(&y);
+        let y2 = P.mul(&y_m, &y_m);
+        let u = P.sub(&y2, &one);
+        let v = P.add(&P.mul(&P.to_montgomery(&D), &y2), &one);
+        let v3 = P.mul(&P.mul(&v, &v), &v);
+        let v7 = P.mul(&P.mul(&v3, &v3), &v);
+        let mut x = P.mul(&P.mul(&u, &v3), &P.pow(&P.mul(&u, &v7), &SQRT_EXPONENT));
+
+        let vx2 = P.mul(&v, &P.mul(&x, &x));
+        if vx2 != u {
+            if vx2 != P.neg(&u) {
+                return None;
+            }
+            x = P.mul(&x, &P.to_montgomery(&SQRT_M1));
+        }
+        let x_plain = P.from_montgomery(&x);
+        if bigint::is_zero(&x_plain) && sign == 1 {
+            return None;
+        }
+        if x_plain[0] & 1 != sign {
+            x = P.neg(&x);
+        }
+        Some(EdwardsPoint { x, y: y_m, z: one, t: P.mul(&x, &y_m) })
+    }
+}
+
+/// Reduces a 512-bit little-endian integer modulo L.
+fn reduce_wide(bytes: &[u8; 64]) -> U256 {
+    let mut half = [0u8; 32];
+    half.copy_from_slice(&bytes[..32]);
+    let low = bigint::from_le_bytes(&half);
+    half.copy_from_slice(&bytes[32..]);
+    let high = bigint::from_le_bytes(&half);
+    wipe(&mut half);
+    // Montgomery multiplication by R^2 accepts any operand below R = 2^256:
+    // low * R^2 / R = low * R, and high * R^2 / R = high * 2^256.
+    let low = L.from_montgomery(&L.mul(&low, &L.r2));
+    let high = L.mul(&high, &L.r2);
+    L.add(&low, &high)
+}
+
+/// Encodes the affine point (x, y), given as little-endian field elements
+/// below p, as y with the sign of x in the top bit.
+pub fn ed25519_encode(x: &[u8; KEY_LEN], y: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    let mut bytes = *y;
+    bytes[31] |= (x[0] & 1) << 7;
+    bytes
+}
+
+/// Splits an encoded point into its y-coordinate and the sign of x.
+/// Returns None if y is not below p. The point may still be off the curve.
+pub fn ed25519_split(bytes: &[u8; KEY_LEN]) -> Option<([u8; KEY_LEN], u8)> {
+    let mut y = *bytes;
+    y[31] &= 0x7f;
+    if !bigint::less_than(&bigint::from_le_bytes(&y), &P.m) {
+        return None;
+    }
+    Some((y, bytes[31] >> 7))
+}
+
+/// Returns whether the S half of a signature is below L, as RFC 8032
+/// requires.
+pub fn ed25519_scalar_is_canonical(s: &[u8; KEY_LEN]) -> bool {
+    bigint::less_than(&bigint::from_le_bytes(s), &L.m)
+}
+
+/// The expanded form of an Ed25519 secret key.
+///
+/// Together with `ed25519_challenge`, this lets callers that do the point
+/// multiplications elsewhere, such as on an accelerator, build signatures.
+pub struct ExpandedKey {
+    scalar: U256,
+    prefix: [u8; KEY_LEN],
+}
+
+impl ExpandedKey {
+    /// Expands a 32-byte secret key as described in RFC 8032.
+    pub fn new(secret: &[u8; KEY_LEN]) -> ExpandedKey {
+        let mut sha = Sha512::new();
+        sha.update(secret);
+        let mut hash = sha.finalize();
+        let mut low = [0u8; KEY_LEN];
+        low.copy_from_slice(&hash[..KEY_LEN]);
+        let mut key = ExpandedKey { scalar: clamp(&low), prefix: [0; KEY_LEN] };
+        key.prefix.copy_from_slice(&hash[KEY_LEN..]);
+        wipe(&mut low);
+        wipe(&mut hash);
+        key
+    }
+
+    /// The clamped secret scalar a. The public key is [a]B.
+    pub fn scalar(&self) -> [u8; KEY_LEN] {
+        bigint::to_le_bytes(&self.scalar)
+    }
+
+    /// The nonce r = SHA-512(prefix || message) mod L. The R half of the
+    /// signature is [r]B.
+    pub fn nonce(&self, message: &[u8]) -> [u8; KEY_LEN] {
+        let mut sha = Sha512::new();
+        sha.update(&self.prefix);
+        sha.update(message);
+        let mut hash = sha.finalize();
+        let r = reduce_wide(&hash);
+        wipe(&mut hash);
+        bigint::to_le_bytes(&r)
+    }
+
+    /// The S half of the signature, r + k a mod L, for nonce r and
+    /// challenge k.
+    pub fn signature_scalar(&self, nonce: &[u8; KEY_LEN], challenge: &[u8; KEY_LEN])
+                            -> [u8; KEY_LEN] {
+        // The clamped scalar may exceed L, so reduce it on the way into the
+        // Montgomery domain.
+        let r = bigint::from_le_bytes(nonce);
+        let k = bigint::from_le_bytes(challenge);
+        let a = L.mul(&self.scalar, &L.r2);
+        let s = L.from_montgomery(&L.add(&L.to_montgomery(&r), &L.mul(&L.to_montgomery(&k), &a)));
+        bigint::to_le_bytes(&s)
+    }
+
+    fn public_key(&self) -> [u8; KEY_LEN] {
+        EdwardsPoint::base_point().mul(&self.scalar).encode()
+    }
+}
+
+impl Drop for ExpandedKey {
+    fn drop(&mut self) {
+        wipe(&mut self.prefix);
+        for limb in self.scalar.iter_mut() {
+            unsafe { core::ptr::write_volatile(limb, 0) };
+        }
+    }
+}
+
+/// Computes the challenge k = SHA-512(R || A || message) mod L.
+pub fn ed25519_challenge(r: &[u8; KEY_LEN], public_key: &[u8; KEY_LEN], message: &[u8])
+                         -> [u8; KEY_LEN] {
+    let mut sha = Sha512::new();
+    sha.update(r);
+    sha.update(public_key);
+    sha.update(message);
+    bigint::to_le_bytes(&reduce_wide(&sha.finalize()))
+}
+
+/// Computes the Ed25519 public key for a 32-byte secret key.
+pub fn ed25519_public_key(secret: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    ExpandedKey::new(secret).public_key()
+}
+
+/// Signs `message` with a 32-byte Ed25519 secret key.
+pub fn ed25519_sign(secret: &[u8; KEY_LEN], message: &[u8]) -> [u8; SIGNATURE_LEN] {
+    let key = ExpandedKey::new(secret);
+    let public_key = key.public_key();
+    let mut r = key.nonce(message);
+    let encoded_r = EdwardsPoint::base_point().mul(&bigint::from_le_bytes(&r)).encode();
+    let k = ed25519_challenge(&encoded_r, &public_key, message);
+
+    let mut signature = [0u8; SIGNATURE_LEN];
+    signature[..KEY_LEN].copy_from_slice(&encoded_r);
+    signature[KEY_LEN..].copy_from_slice(&key.signature_scalar(&r, &k));
+    wipe(&mut r);
+    signature
+}
+
+/// Verifies an Ed25519 signature over `message`.
+pub fn ed25519_verify(public_key: &[u8; KEY_LEN], message: &[u8],
+                      signature: &[u8; SIGNATURE_LEN]) -> bool {
+    let a = match EdwardsPoint::decode(public_key) {
+        Some(a) => a,
+        None => return false,
+    };
+    let mut encoded_r = [0u8; KEY_LEN];
+    encoded_r.copy_from_slice(&signature[..KEY_LEN]);
+    let mut encoded_s = [0u8; KEY_LEN];
+    encoded_s.copy_from_slice(&signature[KEY_LEN..]);
+    if !ed25519_scalar_is_canonical(&encoded_s) {
+        return false;
+    }
+    let s = bigint::from_le_bytes(&encoded_s);
+
+    // Check that [S]B - [k]A encodes to R.
+    let k = bigint::from_le_bytes(&ed25519_challenge(&encoded_r, public_key, message));
+    let check = EdwardsPoint::base_point().mul(&s).add(&a.negate().mul(&k));
+    check.encode() == encoded_r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_to_slice(s: &str, out: &mut [u8]) {
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+    }
+
+    fn hex(s: &str) -> [u8; KEY_LEN] {
+        let mut out = [0u8; KEY_LEN];
+        hex_to_slice(s, &mut out);
+        out
+    }
+
+    fn hex_signature(s: &str) -> [u8; SIGNATURE_LEN] {
+        let mut out = [0u8; SIGNATURE_LEN];
+        hex_to_slice(s, &mut out);
+        out
+    }
+
+    // Test vectors from RFC 7748 section 5.2.
+    #[test]
+    fn x25519_known_answers() {
+        let scalar = hex("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4");
+        let u = hex("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c");
+        assert_eq!(x25519(&scalar, &u),
+                   hex("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552"));
+
+        let scalar = hex("4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d");
+        let u = hex("e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493");
+        assert_eq!(x25519(&scalar, &u),
+                   hex("95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957"));
+    }
+
+    // Test vector from RFC 7748 section 6.1.
+    #[test]
+    fn x25519_key_agreement() {
+        let alice = hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        assert_eq!(x25519_public_key(&alice),
+                   hex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"));
+        assert_eq!(x25519(&alice, &x25519_public_key(&bob)),
+                   x25519(&bob, &x25519_public_key(&alice)));
+    }
+
+    // Test vectors 1 and 2 from RFC 8032 section 7.1.
+    #[test]
+    fn ed25519_known_answers() {
+        let secret = hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        let public_key = hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let signature = hex_signature(concat!(
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a3",
+            "3bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"));
+        assert_eq!(ed25519_public_key(&secret), public_key);
+        assert_eq!(ed25519_sign(&secret, b"")[..], signature[..]);
+        assert!(ed25519_verify(&public_key, b"", &signature));
+
+        let secret = hex("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb");
+        let public_key = hex("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c");
+        let signature = hex_signature(concat!(
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15",
+            "996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"));
+        assert_eq!(ed25519_public_key(&secret), public_key);
+        assert_eq!(ed25519_sign(&secret, &[0x72])[..], signature[..]);
+        assert!(ed25519_verify(&public_key, &[0x72], &signature));
+    }
+
+    #[test]
+    fn ed25519_rejects_bad_signatures() {
+        let secret = hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        let public_key = ed25519_public_key(&secret);
+        let signature = ed25519_sign(&secret, b"message");
+        assert!(!ed25519_verify(&public_key, b"massage", &signature));
+
+        let mut bad_signature = signature;
+        bad_signature[40] ^= 1;
+        assert!(!ed25519_verify(&public_key, b"message", &bad_signature));
+
+        // S >= L must be rejected.
+        let mut bad_signature = signature;
+        bad_signature[63] = 0xff;
+        assert!(!ed25519_verify(&public_key, b"message", &bad_signature));
+    }
+
+    #[test]
+    fn ed25519_split_checks_y() {
+        let public_key = hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let (y, sign) = ed25519_split(&public_key).unwrap();
+        assert_eq!(sign, 0);
+        assert_eq!(y, public_key);
+
+        // y = p, with the sign bit set.
+        let p = hex("edffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff");
+        assert!(ed25519_split(&p).is_none());
+        let mut below_p = p;
+        below_p[0] -= 1;
+        assert_eq!(ed25519_split(&below_p).unwrap().1, 1);
+    }
+}
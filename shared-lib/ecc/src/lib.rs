@@ -0,0 +1,42 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#![crate_type = "lib"]
+#![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+//! Software elliptic curve cryptography.
+//!
+//! This crate implements the curve arithmetic needed by the kernel key
+//! store and by host tools in portable Rust, so that the same code can be
+//! tested on the host. It favors simplicity over speed. Scalar
+//! multiplication uses a fixed-length Montgomery ladder, but the code has
+//! not been hardened against power or fault attacks.
+
+pub mod bigint;
+pub mod curve25519;
+pub mod p256;
+pub mod rfc6979;
+pub mod sha256;
+pub mod sha512;
+
+/// Overwrites `bytes` with zeros in a way the compiler does not optimize
+/// away, for clearing secrets.
+pub fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+}
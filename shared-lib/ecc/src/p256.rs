@@ -0,0 +1,478 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! ECDSA over the NIST P-256 curve.
+//!
+//! Field elements and scalars are kept in the Montgomery domain of their
+//! respective moduli; points are kept in Jacobian coordinates. All
+//! external encodings are 32-byte big-endian integers.
+
+use crate::bigint;
+use crate::bigint::Modulus;
+use crate::bigint::U256;
+use crate::rfc6979::{HmacSha256, NonceGenerator};
+use crate::wipe;
+
+/// The field prime p.
+const P: Modulus = Modulus {
+    m: [0xffffffff, 0xffffffff, 0xffffffff, 0x00000000,
+        0x00000000, 0x00000000, 0x00000001, 0xffffffff],
+    m_prime: 0x00000001,
+    r2: [0x00000003, 0x00000000, 0xffffffff, 0xfffffffb,
+         0xfffffffe, 0xffffffff, 0xfffffffd, 0x00000004],
+};
+
+/// The group order n.
+const N: Modulus = Modulus {
+    m: [0xfc632551, 0xf3b9cac2, 0xa7179e84, 0xbce6faad,
+        0xffffffff, 0xffffffff, 0x00000000, 0xffffffff],
+    m_prime: 0xee00bc4f,
+    r2: [0xbe79eea2, 0x83244c95, 0x49bd6fa6, 0x4699799c,
+         0x2b6bec59, 0x2845b239, 0xf3d95620, 0x66e12d94],
+};
+
+/// The curve coefficient b (a is -3).
+const B: U256 = [0x27d2604b, 0x3bce3c3e, 0xcc53b0f6, 0x651d06b0,
+                 0x769886bc, 0xb3ebbd55, 0xaa3a93e7, 0x5ac635d8];
+
+/// The base point G.
+const GX: U256 = [0xd898c296, 0xf4a13945, 0x2deb33a0, 0x77037d81,
+                  0x63a440f2, 0xf8bce6e5, 0xe12c4247, 0x6b17d1f2];
+const GY: U256 = [0x37bf51f5, 0xcbb64068, 0x6b315ece, 0x2bce3357,
+                  0x7c0f9e16, 0x8ee7eb4a, 0xfe1a7f9b, 0x4fe342e2];
+
+/// Length in bytes of an encoded scalar or coordinate.
+pub const SCALAR_LEN: usize = 32;
+
+/// Errors returned by P-256 operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The private key is not in [1, n).
+    InvalidKey,
+    /// The nonce is not in [1, n) or produced a zero signature component.
+    InvalidNonce,
+    /// The HMAC used to derive a deterministic nonce failed.
+    DigestFailure,
+}
+
+/// A point in Jacobian coordinates (X/Z^2, Y/Z^3), with all coordinates
+/// in the Montgomery domain of p. Z = 0 is the point at infinity.
+#[derive(Clone, Copy)]
+struct Point {
+    x: U256,
+    y: U256,
+    z: U256,
+}
+
+impl Point {
+    const INFINITY: Point = Point { x: bigint::ZERO, y: bigint::ZERO, z: bigint::ZERO };
+
+    /// Builds a point from affine coordinates that are not in the
+    /// Montgomery domain.
+    fn from_affine(x: &U256, y: &U256) -> Point {
+        Point { x: P.to_montgomery(x), y: P.to_montgomery(y), z: P.one() }
+    }
+
+    fn generator() -> Point {
+        Point::from_affine(&GX, &GY)
+    }
+
+    fn is_infinity(&self) -> bool {
+        bigint::is_zero(&self.z)
+    }
+
+    /// Returns the affine coordinates outside the Montgomery domain, or
+    /// None for the point at infinity.
+    fn affine(&self) -> Option<(U256, U256)> {
+        if self.is_infinity() {
+            return None;
+        }
+        let z_inv = P.invert(&self.z);
+        let z_inv2 = P.mul(&z_inv, &z_inv);
+        let z_inv3 = P.mul(&z_inv2, &z_inv);
+        Some((P.from_montgomery(&P.mul(&self.x, &z_inv2)),
+              P.from_montgomery(&P.mul(&self.y, &z_inv3))))
+    }
+
+    /// Point doubling for a = -3 ("dbl-2001-b"). Doubling the point at
+    /// infinity yields the point at infinity.
+    fn double(&self) -> Point {
+        let delta = P.mul(&self.z, &self.z);
+        let gamma = P.mul(&self.y, &self.y);
+        let beta = P.mul(&self.x, &gamma);
+        let t = P.mul(&P.sub(&self.x, &delta), &P.add(&self.x, &delta));
+        let alpha = P.add(&P.add(&t, &t), &t);
+        let beta2 = P.add(&beta, &beta);
+        let beta4 = P.add(&beta2, &beta2);
+        let beta8 = P.add(&beta4, &beta4);
+        let x = P.sub(&P.mul(&alpha, &alpha), &beta8);
+        let yz = P.add(&self.y, &self.z);
+        let z = P.sub(&P.sub(&P.mul(&yz, &yz), &gamma), &delta);
+        let gamma2 = P.mul(&gamma, &gamma);
+        let gamma2x2 = P.add(&gamma2, &gamma2);
+        let gamma2x4 = P.add(&gamma2x2, &gamma2x2);
+        let gamma2x8 = P.add(&gamma2x4, &gamma2x4);
+        let y = P.sub(&P.mul(&alpha, &P.sub(&beta4, &x)), &gamma2x8);
+        Point { x, y, z }
+    }
+
+    /// Point addition ("add-2007-bl"), handling the point at infinity and
+    /// equal inputs.
+    fn add(&self, other: &Point) -> Point {
+        if self.is_infinity() {
+            return *other;
+        }
+        if other.is_infinity() {
+            return *self;
+        }
+        let z1z1 = P.mul(&self.z, &self.z);
+        let z2z2 = P.mul(&other.z, &other.z);
+        let u1 = P.mul(&self.x, &z2z2);
+        let u2 = P.mul(&other.x, &z1z1);
+        let s1 = P.mul(&P.mul(&self.y, &other.z), &z2z2);
+        let s2 = P.mul(&P.mul(&other.y, &self.z), &z1z1);
+        let h = P.sub(&u2, &u1);
+        let r_half = P.sub(&s2, &s1);
+        if bigint::is_zero(&h) {
+            if bigint::is_zero(&r_half) {
+                return self.double();
+            }
+            return Point::INFINITY;
+        }
+        let h2 = P.add(&h, &h);
+        let i = P.mul(&h2, &h2);
+        let j = P.mul(&h, &i);
+        let r = P.add(&r_half, &r_half);
+        let v = P.mul(&u1, &i);
+        let x = P.sub(&P.sub(&P.mul(&r, &r), &j), &P.add(&v, &v));
+        let s1j = P.mul(&s1, &j);
+        let y = P.sub(&P.mul(&r, &P.sub(&v, &x)), &P.add(&s1j, &s1j));
+        let zz = P.add(&self.z, &other.z);
+        let z = P.mul(&P.sub(&P.sub(&P.mul(&zz, &zz), &z1z1), &z2z2), &h);
+        Point { x, y, z }
+    }
+
+    /// Swaps `a` and `b` if `choice` is 1, without branching.
+    fn conditional_swap(choice: u32, a: &mut Point, b: &mut Point) {
+        let new_a = Point {
+            x: bigint::select(choice, &a.x, &b.x),
+            y: bigint::select(choice, &a.y, &b.y),
+            z: bigint::select(choice, &a.z, &b.z),
+        };
+        let new_b = Point {
+            x: bigint::select(choice, &b.x, &a.x),
+            y: bigint::select(choice, &b.y, &a.y),
+            z: bigint::select(choice, &b.z, &a.z),
+        };
+        *a = new_a;
+        *b = new_b;
+    }
+
+    /// Computes `scalar * self` with a Montgomery ladder over all 256 bits.
+    fn mul(&self, scalar: &U256) -> Point {
+        let mut r0 = Point::INFINITY;
+        let mut r1 = *self;
+        for index in (0..256).rev() {
+            let bit = bigint::bit(scalar, index);
+            Point::conditional_swap(bit, &mut r0, &mut r1);
+            r1 = r0.add(&r1);
+            r0 = r0.double();
+            Point::conditional_swap(bit, &mut r0, &mut r1);
+        }
+        r0
+    }
+}
+
+/// Returns true if `value` is in [1, n).
+fn is_valid_scalar(value: &U256) -> bool {
+    !bigint::is_zero(value) && bigint::less_than(value, &N.m)
+}
+
+/// Converts a digest into a scalar as described in FIPS 186-4 section
+/// 6.4; the digest is truncated to 256 bits by the caller.
+fn digest_to_scalar(digest: &[u8; SCALAR_LEN]) -> U256 {
+    N.reduce_once(&bigint::from_be_bytes(digest))
+}
+
+/// An ECDSA signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signature {
+    /// The r component, big-endian.
+    pub r: [u8; SCALAR_LEN],
+    /// The s component, big-endian.
+    pub s: [u8; SCALAR_LEN],
+}
+
+/// A public key as affine coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicKey {
+    /// The x coordinate, big-endian.
+    pub x: [u8; SCALAR_LEN],
+    /// The y coordinate, big-endian.
+    pub y: [u8; SCALAR_LEN],
+}
+
+impl PublicKey {
+    /// Returns true if the coordinates are reduced and the point lies on
+    /// the curve.
+    pub fn is_valid(&self) -> bool {
+        let x = bigint::from_be_bytes(&self.x);
+        let y = bigint::from_be_bytes(&self.y);
+        if !bigint::less_than(&x, &P.m) || !bigint::less_than(&y, &P.m) {
+            return false;
+        }
+        // y^2 = x^3 - 3x + b
+        let x = P.to_montgomery(&x);
+        let y = P.to_montgomery(&y);
+        let x3 = P.mul(&P.mul(&x, &x), &x);
+        let three_x = P.add(&P.add(&x, &x), &x);
+        let rhs = P.add(&P.sub(&x3, &three_x), &P.to_montgomery(&B));
+        P.mul(&y, &y) == rhs
+    }
+
+    /// Verifies `signature` over a 32-byte message digest.
+    pub fn verify(&self, digest: &[u8; SCALAR_LEN], signature: &Signature) -> bool {
+        if !self.is_valid() {
+            return false;
+        }
+        let r = bigint::from_be_bytes(&signature.r);
+        let s = bigint::from_be_bytes(&signature.s);
+        if !is_valid_scalar(&r) || !is_valid_scalar(&s) {
+            return false;
+        }
+
+        let s_inv = N.invert(&N.to_montgomery(&s));
+        let u1 = N.from_montgomery(&N.mul(&N.to_montgomery(&digest_to_scalar(digest)), &s_inv));
+        let u2 = N.from_montgomery(&N.mul(&N.to_montgomery(&r), &s_inv));
+        let q = Point::from_affine(&bigint::from_be_bytes(&self.x),
+                                   &bigint::from_be_bytes(&self.y));
+        let sum = Point::generator().mul(&u1).add(&q.mul(&u2));
+        match sum.affine() {
+            Some((x, _)) => N.reduce_once(&x) == r,
+            None => false,
+        }
+    }
+}
+
+/// A private key. The scalar is wiped when the key is dropped.
+pub struct PrivateKey {
+    d: U256,
+}
+
+impl PrivateKey {
+    /// Loads a private key from its big-endian encoding, which must be in
+    /// [1, n). Callers generating keys should draw 32 random bytes and
+    /// retry on `Error::InvalidKey`.
+    pub fn from_bytes(bytes: &[u8; SCALAR_LEN]) -> Result<PrivateKey, Error> {
+        let d = bigint::from_be_bytes(bytes);
+        if !is_valid_scalar(&d) {
+            return Err(Error::InvalidKey);
+        }
+        Ok(PrivateKey { d })
+    }
+
+    /// Returns the big-endian encoding of the private scalar.
+    pub fn to_bytes(&self) -> [u8; SCALAR_LEN] {
+        bigint::to_be_bytes(&self.d)
+    }
+
+    /// Computes the public key d * G.
+    pub fn public_key(&self) -> PublicKey {
+        // d is in [1, n), so d * G is never the point at infinity.
+        let (x, y) = Point::generator().mul(&self.d).affine().unwrap_or_default();
+        PublicKey { x: bigint::to_be_bytes(&x), y: bigint::to_be_bytes(&y) }
+    }
+
+    /// Signs a 32-byte message digest with the per-signature secret
+    /// `nonce`. The nonce must be uniformly random (or derived as in RFC
+    /// 6979) and must never be reused.
+    pub fn sign(&self, digest: &[u8; SCALAR_LEN], nonce: &[u8; SCALAR_LEN])
+                -> Result<Signature, Error> {
+        let k = bigint::from_be_bytes(nonce);
+        if !is_valid_scalar(&k) {
+            return Err(Error::InvalidNonce);
+        }
+        let (x, _) = Point::generator().mul(&k).affine().ok_or(Error::InvalidNonce)?;
+        let r = N.reduce_once(&x);
+        if bigint::is_zero(&r) {
+            return Err(Error::InvalidNonce);
+        }
+
+        // s = k^-1 * (e + r * d) mod n
+        let k_inv = N.invert(&N.to_montgomery(&k));
+        let rd = N.mul(&N.to_montgomery(&r), &N.to_montgomery(&self.d));
+        let sum = N.add(&N.to_montgomery(&digest_to_scalar(digest)), &rd);
+        let s = N.from_montgomery(&N.mul(&k_inv, &sum));
+        if bigint::is_zero(&s) {
+            return Err(Error::InvalidNonce);
+        }
+        Ok(Signature { r: bigint::to_be_bytes(&r), s: bigint::to_be_bytes(&s) })
+    }
+
+    /// Returns the nonce RFC 6979 derives for signing `digest`, i.e. the
+    /// first candidate in [1, n). RFC 6979 moves on to the next candidate
+    /// if the nonce gives a zero r or s, which happens for a negligible
+    /// fraction of nonces; callers using this nonce fail instead.
+    pub fn rfc6979_nonce<H: HmacSha256>(&self, digest: &[u8; SCALAR_LEN], hmac: &H)
+                                        -> Result<[u8; SCALAR_LEN], Error> {
+        let mut key = self.to_bytes();
+        let generator = NonceGenerator::new(
+            hmac, &key, &bigint::to_be_bytes(&digest_to_scalar(digest)));
+        wipe(&mut key);
+        let mut generator = generator?;
+        loop {
+            let mut nonce = generator.next_candidate()?;
+            if is_valid_scalar(&bigint::from_be_bytes(&nonce)) {
+                return Ok(nonce);
+            }
+            wipe(&mut nonce);
+        }
+    }
+
+    /// Signs a 32-byte message digest with a nonce derived from the key and
+    /// the digest as described in RFC 6979.
+    pub fn sign_deterministic<H: HmacSha256>(&self, digest: &[u8; SCALAR_LEN], hmac: &H)
+                                             -> Result<Signature, Error> {
+        let mut key = self.to_bytes();
+        let generator = NonceGenerator::new(
+            hmac, &key, &bigint::to_be_bytes(&digest_to_scalar(digest)));
+        wipe(&mut key);
+        let mut generator = generator?;
+        loop {
+            let mut nonce = generator.next_candidate()?;
+            let result = self.sign(digest, &nonce);
+            wipe(&mut nonce);
+            match result {
+                Err(Error::InvalidNonce) => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        for limb in self.d.iter_mut() {
+            // Volatile so that the wipe is not optimized away.
+            unsafe { core::ptr::write_volatile(limb, 0) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rfc6979::tests::SoftwareHmac;
+
+    fn hex(s: &str) -> [u8; SCALAR_LEN] {
+        let mut out = [0u8; SCALAR_LEN];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    // Test vectors from RFC 6979 appendix A.2.5.
+    const PRIVATE_KEY: &str = "c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721";
+    const PUBLIC_X: &str = "60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6";
+    const PUBLIC_Y: &str = "7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299";
+    // SHA-256("sample")
+    const DIGEST: &str = "af2bdbe1aa9b6ec1e2ade1d694f41fc71a831d0268e9891562113d8a62add1bf";
+    const NONCE: &str = "a6e3c57dd01abe90086538398355dd4c3b17aa873382b0f24d6129493d8aad60";
+    const SIG_R: &str = "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716";
+    const SIG_S: &str = "f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8";
+
+    #[test]
+    fn generator_times_one() {
+        let key = PrivateKey::from_bytes(&bigint::to_be_bytes(&bigint::ONE)).unwrap();
+        let public = key.public_key();
+        assert_eq!(bigint::from_be_bytes(&public.x), GX);
+        assert_eq!(bigint::from_be_bytes(&public.y), GY);
+        assert!(public.is_valid());
+    }
+
+    #[test]
+    fn doubling_matches_addition() {
+        let g = Point::generator();
+        let doubled = g.double().affine();
+        let added = g.add(&g).affine();
+        assert!(doubled.is_some());
+        assert!(doubled == added);
+        assert!(g.mul(&N.m).is_infinity());
+    }
+
+    #[test]
+    fn public_key() {
+        let key = PrivateKey::from_bytes(&hex(PRIVATE_KEY)).unwrap();
+        assert_eq!(key.public_key(), PublicKey { x: hex(PUBLIC_X), y: hex(PUBLIC_Y) });
+    }
+
+    #[test]
+    fn sign_known_nonce() {
+        let key = PrivateKey::from_bytes(&hex(PRIVATE_KEY)).unwrap();
+        let signature = key.sign(&hex(DIGEST), &hex(NONCE)).unwrap();
+        assert_eq!(signature, Signature { r: hex(SIG_R), s: hex(SIG_S) });
+    }
+
+    #[test]
+    fn verify() {
+        let public = PublicKey { x: hex(PUBLIC_X), y: hex(PUBLIC_Y) };
+        let signature = Signature { r: hex(SIG_R), s: hex(SIG_S) };
+        assert!(public.verify(&hex(DIGEST), &signature));
+
+        let mut digest = hex(DIGEST);
+        digest[0] ^= 1;
+        assert!(!public.verify(&digest, &signature));
+
+        let mut bad_public = public;
+        bad_public.y[31] ^= 1;
+        assert!(!bad_public.is_valid());
+        assert!(!bad_public.verify(&hex(DIGEST), &signature));
+    }
+
+    #[test]
+    fn sign_deterministic() {
+        let key = PrivateKey::from_bytes(&hex(PRIVATE_KEY)).unwrap();
+        let signature = key.sign_deterministic(&hex(DIGEST), &SoftwareHmac).unwrap();
+        assert_eq!(signature, Signature { r: hex(SIG_R), s: hex(SIG_S) });
+
+        // SHA-256("test")
+        let digest = hex("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08");
+        let signature = key.sign_deterministic(&digest, &SoftwareHmac).unwrap();
+        assert_eq!(signature, Signature {
+            r: hex("f1abb023518351cd71d881567b1ea663ed3efcf6c5132b354f28d3b0b7d38367"),
+            s: hex("019f4113742a2b14bd25926b49c649155f267e60d3814b4c0cc84250e46f0083"),
+        });
+    }
+
+    #[test]
+    fn deterministic_nonce() {
+        let mut generator = NonceGenerator::new(
+            &SoftwareHmac, &hex(PRIVATE_KEY), &hex(DIGEST)).unwrap();
+        assert_eq!(generator.next_candidate().unwrap(), hex(NONCE));
+        let key = PrivateKey::from_bytes(&hex(PRIVATE_KEY)).unwrap();
+        assert_eq!(key.rfc6979_nonce(&hex(DIGEST), &SoftwareHmac).unwrap(), hex(NONCE));
+    }
+
+    #[test]
+    fn rejects_out_of_range_scalars() {
+        assert_eq!(PrivateKey::from_bytes(&[0; SCALAR_LEN]).err(), Some(Error::InvalidKey));
+        assert_eq!(PrivateKey::from_bytes(&bigint::to_be_bytes(&N.m)).err(),
+                   Some(Error::InvalidKey));
+        let key = PrivateKey::from_bytes(&hex(PRIVATE_KEY)).unwrap();
+        assert_eq!(key.sign(&hex(DIGEST), &[0; SCALAR_LEN]), Err(Error::InvalidNonce));
+    }
+}
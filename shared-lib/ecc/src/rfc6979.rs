@@ -0,0 +1,99 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Deterministic nonce generation for ECDSA as described in RFC 6979.
+//!
+//! The nonce is derived from the private key and the message digest with
+//! HMAC-DRBG, so signing does not depend on the quality of a random number
+//! generator. HMAC-SHA256 is supplied by the caller through the
+//! `HmacSha256` trait so that it can be backed by hardware.
+
+use crate::p256::Error;
+use crate::wipe;
+
+/// Length in bytes of an HMAC-SHA256 key and output.
+pub const HMAC_LEN: usize = 32;
+
+/// An HMAC-SHA256 implementation.
+pub trait HmacSha256 {
+    /// Computes HMAC-SHA256 under `key` over the concatenation of `data`.
+    /// Implementations report failures as `Error::DigestFailure`.
+    fn hmac_sha256(&self, key: &[u8; HMAC_LEN], data: &[&[u8]]) -> Result<[u8; HMAC_LEN], Error>;
+}
+
+/// Produces the sequence of nonce candidates of RFC 6979 section 3.2 for a
+/// 256-bit curve order and a 256-bit digest.
+///
+/// Callers must reject candidates that are not in [1, n), or that produce a
+/// zero signature component, and ask for the next candidate.
+pub struct NonceGenerator<'a, H: HmacSha256> {
+    hmac: &'a H,
+    k: [u8; HMAC_LEN],
+    v: [u8; HMAC_LEN],
+    started: bool,
+}
+
+impl<'a, H: HmacSha256> NonceGenerator<'a, H> {
+    /// Instantiates the generator from the big-endian private key and the
+    /// digest reduced modulo the curve order (bits2octets(h1)).
+    pub fn new(hmac: &'a H, private_key: &[u8; HMAC_LEN], reduced_digest: &[u8; HMAC_LEN])
+               -> Result<NonceGenerator<'a, H>, Error> {
+        let mut generator = NonceGenerator {
+            hmac,
+            k: [0x00; HMAC_LEN],
+            v: [0x01; HMAC_LEN],
+            started: false,
+        };
+        for separator in &[0x00u8, 0x01u8] {
+            generator.k = hmac.hmac_sha256(
+                &generator.k, &[&generator.v, &[*separator], private_key, reduced_digest])?;
+            generator.v = hmac.hmac_sha256(&generator.k, &[&generator.v])?;
+        }
+        Ok(generator)
+    }
+
+    /// Returns the next nonce candidate as a big-endian integer.
+    pub fn next_candidate(&mut self) -> Result<[u8; HMAC_LEN], Error> {
+        if self.started {
+            self.k = self.hmac.hmac_sha256(&self.k, &[&self.v, &[0x00]])?;
+            self.v = self.hmac.hmac_sha256(&self.k, &[&self.v])?;
+        }
+        self.started = true;
+        self.v = self.hmac.hmac_sha256(&self.k, &[&self.v])?;
+        Ok(self.v)
+    }
+}
+
+impl<'a, H: HmacSha256> Drop for NonceGenerator<'a, H> {
+    fn drop(&mut self) {
+        wipe(&mut self.k);
+        wipe(&mut self.v);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub struct SoftwareHmac;
+
+    impl HmacSha256 for SoftwareHmac {
+        fn hmac_sha256(&self, key: &[u8; HMAC_LEN], data: &[&[u8]])
+                       -> Result<[u8; HMAC_LEN], Error> {
+            Ok(crate::sha256::hmac(key, data))
+        }
+    }
+}
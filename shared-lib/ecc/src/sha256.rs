@@ -0,0 +1,200 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Software SHA-256 and HMAC-SHA256.
+//!
+//! The kernel uses the H1 SHA engine instead; this is for code that runs
+//! without it, such as apps and host tools.
+
+use crate::wipe;
+
+/// Length in bytes of a SHA-256 digest.
+pub const DIGEST_LEN: usize = 32;
+
+const BLOCK_LEN: usize = 64;
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// A streaming SHA-256 computation.
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; BLOCK_LEN],
+    buffer_len: usize,
+    // Total message length in bytes.
+    length: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256::new()
+    }
+}
+
+impl Sha256 {
+    /// Starts a new digest.
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: INITIAL_STATE,
+            buffer: [0; BLOCK_LEN],
+            buffer_len: 0,
+            length: 0,
+        }
+    }
+
+    /// Feeds `data` into the digest.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let count = core::cmp::min(BLOCK_LEN - self.buffer_len, data.len());
+            self.buffer[self.buffer_len..self.buffer_len + count].copy_from_slice(&data[..count]);
+            self.buffer_len += count;
+            data = &data[count..];
+            if self.buffer_len == BLOCK_LEN {
+                let block = self.buffer;
+                self.compress(&block);
+                self.buffer_len = 0;
+            }
+        }
+    }
+
+    /// Completes the digest.
+    pub fn finalize(mut self) -> [u8; DIGEST_LEN] {
+        let bit_length = self.length.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.buffer_len != BLOCK_LEN - 8 {
+            self.update(&[0]);
+        }
+        self.update(&bit_length.to_be_bytes());
+
+        let mut out = [0u8; DIGEST_LEN];
+        for (chunk, word) in out.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        wipe(&mut self.buffer);
+        out
+    }
+
+    fn compress(&mut self, block: &[u8; BLOCK_LEN]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let mut h = self.state;
+        for (constant, word) in ROUND_CONSTANTS.iter().zip(w.iter()) {
+            let s1 = h[4].rotate_right(6) ^ h[4].rotate_right(11) ^ h[4].rotate_right(25);
+            let ch = (h[4] & h[5]) ^ (!h[4] & h[6]);
+            let t1 = h[7].wrapping_add(s1).wrapping_add(ch)
+                .wrapping_add(*constant).wrapping_add(*word);
+            let s0 = h[0].rotate_right(2) ^ h[0].rotate_right(13) ^ h[0].rotate_right(22);
+            let maj = (h[0] & h[1]) ^ (h[0] & h[2]) ^ (h[1] & h[2]);
+            let t2 = s0.wrapping_add(maj);
+            h = [t1.wrapping_add(t2), h[0], h[1], h[2], h[3].wrapping_add(t1), h[4], h[5], h[6]];
+        }
+        for (word, add) in self.state.iter_mut().zip(h.iter()) {
+            *word = word.wrapping_add(*add);
+        }
+    }
+}
+
+/// Computes the SHA-256 digest of `data`.
+pub fn digest(data: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut sha = Sha256::new();
+    sha.update(data);
+    sha.finalize()
+}
+
+/// Computes HMAC-SHA256 (RFC 2104) under `key` over the concatenation of
+/// `data`. Keys longer than a block are hashed first.
+pub fn hmac(key: &[u8], data: &[&[u8]]) -> [u8; DIGEST_LEN] {
+    let mut block_key = [0u8; BLOCK_LEN];
+    if key.len() > BLOCK_LEN {
+        block_key[..DIGEST_LEN].copy_from_slice(&digest(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut pad = [0u8; BLOCK_LEN];
+    for (pad, key) in pad.iter_mut().zip(block_key.iter()) {
+        *pad = key ^ 0x36;
+    }
+    let mut inner = Sha256::new();
+    inner.update(&pad);
+    for part in data {
+        inner.update(part);
+    }
+    let inner_digest = inner.finalize();
+
+    for (pad, key) in pad.iter_mut().zip(block_key.iter()) {
+        *pad = key ^ 0x5c;
+    }
+    let mut outer = Sha256::new();
+    outer.update(&pad);
+    outer.update(&inner_digest);
+
+    wipe(&mut pad);
+    wipe(&mut block_key);
+    outer.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_answers() {
+        assert_eq!(digest(b"abc")[..4], [0xba, 0x78, 0x16, 0xbf]);
+        assert_eq!(digest(b"sample")[..4], [0xaf, 0x2b, 0xdb, 0xe1]);
+        assert_eq!(digest(b"")[28..], [0x78, 0x52, 0xb8, 0x55]);
+    }
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        let data = [0xa5u8; 200];
+        let mut sha = Sha256::new();
+        for chunk in data.chunks(9) {
+            sha.update(chunk);
+        }
+        assert_eq!(sha.finalize(), digest(&data));
+    }
+
+    #[test]
+    fn hmac_known_answers() {
+        // RFC 4231 test cases 2 and 6.
+        let mac = hmac(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        assert_eq!(mac[..8], [0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e]);
+        let mac = hmac(&[0xaa; 131], &[b"Test Using Larger Than Block-Size Key - Hash Key First"]);
+        assert_eq!(mac[..8], [0x60, 0xe4, 0x31, 0x59, 0x1e, 0xe0, 0xb6, 0x7f]);
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#![crate_type = "lib"]
+#![warn(missing_docs)]
+#![no_std]
+
+//! Error codes returned by the H1 syscall drivers.
+//!
+//! The values are those of the kernel's `ReturnCode`, which is what apps
+//! see as the return value of a failed command, subscribe or allow. They
+//! must never change. What this crate adds is the meaning of each code in
+//! the h1_syscalls drivers, so that every driver reports the same situation
+//! the same way, and whether an app can expect a retry to succeed.
+//!
+//! Drivers pick the code by situation:
+//!
+//! | Situation                                          | Code        |
+//! |----------------------------------------------------|-------------|
+//! | Another app or kernel client uses the resource     | `Busy`      |
+//! | A setup step the request depends on was not done   | `Off`       |
+//! | An argument is out of range or malformed           | `Invalid`   |
+//! | An allowed buffer is missing or has the wrong size | `Size`      |
+//! | The request is refused by policy (lockdown, lease) | `Reserve`   |
+//! | The kernel could not allocate the app's grant      | `NoMem`     |
+//! | The command, subscribe or allow number is unknown  | `NoSupport` |
+//! | The hardware reported an error or timed out        | `Fail`      |
+
+use core::fmt;
+
+/// An error reported by a syscall driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The operation failed for a reason not covered by the other codes,
+    /// such as a hardware error, a timeout or a failed check.
+    Fail = -1,
+    /// The resource is in use. Retrying later can succeed.
+    Busy = -2,
+    /// The requested state is already in effect.
+    Already = -3,
+    /// A setup step the request depends on was not done.
+    Off = -4,
+    /// The request is not permitted in the current state, e.g. after
+    /// lockdown or without holding a lease.
+    Reserve = -5,
+    /// An argument is out of range or malformed.
+    Invalid = -6,
+    /// An allowed buffer is missing or has the wrong size.
+    Size = -7,
+    /// The operation was cancelled before it completed.
+    Cancel = -8,
+    /// The kernel could not allocate memory for the app.
+    NoMem = -9,
+    /// The driver does not implement the request.
+    NoSupport = -10,
+    /// The device is not present.
+    NoDevice = -11,
+    /// The device is not installed.
+    Uninstalled = -12,
+    /// The other side did not acknowledge the request.
+    NoAck = -13,
+}
+
+/// All error codes, in the order of their values.
+pub const ERROR_CODES: [ErrorCode; 13] = [
+    ErrorCode::Fail,
+    ErrorCode::Busy,
+    ErrorCode::Already,
+    ErrorCode::Off,
+    ErrorCode::Reserve,
+    ErrorCode::Invalid,
+    ErrorCode::Size,
+    ErrorCode::Cancel,
+    ErrorCode::NoMem,
+    ErrorCode::NoSupport,
+    ErrorCode::NoDevice,
+    ErrorCode::Uninstalled,
+    ErrorCode::NoAck,
+];
+
+impl ErrorCode {
+    /// Returns the error for a return value, or None if the value is not
+    /// an error.
+    pub fn from_return_code(value: isize) -> Option<ErrorCode> {
+        if value < 0 && value >= -(ERROR_CODES.len() as isize) {
+            Some(ERROR_CODES[(-value - 1) as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value apps see.
+    pub fn return_code(self) -> isize {
+        self as isize
+    }
+
+    /// Whether the same request can succeed if it is retried later without
+    /// changes.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorCode::Busy | ErrorCode::Cancel | ErrorCode::NoAck)
+    }
+
+    /// Returns the kernel's name for the code.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::Fail => "FAIL",
+            ErrorCode::Busy => "EBUSY",
+            ErrorCode::Already => "EALREADY",
+            ErrorCode::Off => "EOFF",
+            ErrorCode::Reserve => "ERESERVE",
+            ErrorCode::Invalid => "EINVAL",
+            ErrorCode::Size => "ESIZE",
+            ErrorCode::Cancel => "ECANCEL",
+            ErrorCode::NoMem => "ENOMEM",
+            ErrorCode::NoSupport => "ENOSUPPORT",
+            ErrorCode::NoDevice => "ENODEVICE",
+            ErrorCode::Uninstalled => "EUNINSTALLED",
+            ErrorCode::NoAck => "ENOACK",
+        }
+    }
+
+    /// Returns a short description of what went wrong.
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorCode::Fail => "failed",
+            ErrorCode::Busy => "resource in use",
+            ErrorCode::Already => "already in that state",
+            ErrorCode::Off => "setup step missing",
+            ErrorCode::Reserve => "not permitted",
+            ErrorCode::Invalid => "invalid argument",
+            ErrorCode::Size => "buffer missing or wrong size",
+            ErrorCode::Cancel => "cancelled",
+            ErrorCode::NoMem => "out of kernel memory",
+            ErrorCode::NoSupport => "not supported",
+            ErrorCode::NoDevice => "no device",
+            ErrorCode::Uninstalled => "device not installed",
+            ErrorCode::NoAck => "not acknowledged",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.name(), self.return_code(), self.description())?;
+        if self.is_retryable() {
+            write!(f, ", retry later")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn values_round_trip() {
+        for (i, &code) in ERROR_CODES.iter().enumerate() {
+            assert_eq!(code.return_code(), -(i as isize) - 1);
+            assert_eq!(ErrorCode::from_return_code(code.return_code()), Some(code));
+        }
+        assert_eq!(ErrorCode::from_return_code(0), None);
+        assert_eq!(ErrorCode::from_return_code(5), None);
+        assert_eq!(ErrorCode::from_return_code(-14), None);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(ErrorCode::Busy.to_string(), "EBUSY (-2): resource in use, retry later");
+        assert_eq!(ErrorCode::Size.to_string(), "ESIZE (-7): buffer missing or wrong size");
+    }
+}
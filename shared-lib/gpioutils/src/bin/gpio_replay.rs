@@ -0,0 +1,56 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replays GPIO traces from otpilot console logs through the reset
+//! sequencer, and reports where it would drive the resets differently.
+//!
+//! Usage: gpio_replay <console log>
+
+use std::env;
+use std::fs;
+use std::process;
+
+use gpioutils::replay;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: {} <console log>", args[0]);
+        process::exit(2);
+    }
+    let log = fs::read_to_string(&args[1]).unwrap_or_else(|err| {
+        eprintln!("error: could not read {}: {}", args[1], err);
+        process::exit(2);
+    });
+    let trace = replay::parse(&log).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        process::exit(2);
+    });
+    if trace.dropped > 0 {
+        println!("warning: {} events were dropped from the trace", trace.dropped);
+    }
+
+    let result = replay::replay(&trace.events, replay::guard_ticks(trace.hz));
+    for (timestamp, action) in &result.actions {
+        println!("{} {:?}", timestamp, action);
+    }
+    for divergence in &result.divergences {
+        println!("divergence: {:?}", divergence);
+    }
+    if !result.divergences.is_empty() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,35 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#![crate_type = "lib"]
+#![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+//! Reset sequencing for otpilot.
+//!
+//! otpilot holds the BMC in reset and releases it, and reacts to the reset
+//! monitor pins, based on the order and timing of GPIO events. `sequencer`
+//! decides what to do on each event without touching the hardware, so that
+//! the same logic runs on the device and on the host. `trace` records the
+//! events otpilot sees and the edges it drives, and `replay` runs a recorded
+//! trace through the sequencer to check a change against real-world event
+//! orderings.
+
+pub mod pin;
+#[cfg(feature = "std")]
+pub mod replay;
+pub mod sequencer;
+pub mod trace;
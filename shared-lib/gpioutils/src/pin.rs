@@ -0,0 +1,63 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The GPIO pins used for reset sequencing.
+
+use core::convert::TryFrom;
+
+/// GPIO pins and mapping to kernel number.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[allow(non_camel_case_types)]
+#[allow(missing_docs)]
+pub enum GpioPin {
+    BMC_SRST_N = 0,
+    BMC_CPU_RST_N = 1,
+    SYS_RSTMON_N = 2,
+    BMC_RSTMON_N = 3,
+}
+
+impl GpioPin {
+    /// Returns true if otpilot drives the pin, false if it monitors it.
+    pub fn is_output(self) -> bool {
+        match self {
+            GpioPin::BMC_SRST_N | GpioPin::BMC_CPU_RST_N => true,
+            GpioPin::SYS_RSTMON_N | GpioPin::BMC_RSTMON_N => false,
+        }
+    }
+}
+
+/// Error for invalid GpioPin conversion.
+pub struct InvalidGpioPin;
+
+impl TryFrom<usize> for GpioPin {
+    type Error = InvalidGpioPin;
+
+    fn try_from(item: usize) -> Result<GpioPin, Self::Error> {
+        match item {
+            0 => Ok(GpioPin::BMC_SRST_N),
+            1 => Ok(GpioPin::BMC_CPU_RST_N),
+            2 => Ok(GpioPin::SYS_RSTMON_N),
+            3 => Ok(GpioPin::BMC_RSTMON_N),
+            _ => Err(InvalidGpioPin),
+        }
+    }
+}
+
+impl From<GpioPin> for usize {
+    fn from(item: GpioPin) -> usize {
+        item as usize
+    }
+}
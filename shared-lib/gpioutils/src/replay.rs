@@ -0,0 +1,268 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replays recorded GPIO traces through the sequencer.
+//!
+//! The events otpilot consumed on the reset monitor pins are fed to a fresh
+//! `Sequencer` in the recorded order, with the guard timer simulated from the
+//! timestamps. The edges the sequencer drives are compared with the edges
+//! recorded on the reset pins. A recorded edge that the sequencer did not
+//! produce on its own is taken to be a request to drive the reset (e.g. from
+//! the console or at boot), and is fed to the sequencer as such.
+
+use std::collections::VecDeque;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::pin::GpioPin;
+use crate::sequencer::{Action, Input, Sequencer, GUARD_MSECS};
+use crate::trace::{Edge, TraceEvent, TRACE_PREFIX};
+
+/// A trace read back from console output.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Trace {
+    /// The alarm clock frequency, in Hz.
+    pub hz: u32,
+    /// Number of events lost because the trace was full.
+    pub dropped: u32,
+    /// The events, oldest first.
+    pub events: Vec<TraceEvent>,
+}
+
+/// Reads the traces printed in `log`, skipping any other output. Traces
+/// printed one after another are joined.
+pub fn parse(log: &str) -> Result<Trace, String> {
+    let mut trace: Option<Trace> = None;
+    for (number, line) in log.lines().enumerate() {
+        let line = line.trim();
+        if !line.starts_with(TRACE_PREFIX) {
+            continue;
+        }
+        let rest = line[TRACE_PREFIX.len()..].trim();
+        if let Some(header) = rest.strip_prefix("begin") {
+            let (hz, dropped) = parse_header(header)
+                .ok_or_else(|| format!("line {}: bad trace header", number + 1))?;
+            let trace = trace.get_or_insert(Trace { hz, dropped: 0, events: Vec::new() });
+            if trace.hz != hz {
+                return Err(format!("line {}: clock changed from {} Hz to {} Hz",
+                                   number + 1, trace.hz, hz));
+            }
+            trace.dropped += dropped;
+        } else if rest != "end" {
+            let event = TraceEvent::parse(line)
+                .ok_or_else(|| format!("line {}: bad trace event", number + 1))?;
+            trace.as_mut()
+                .ok_or_else(|| format!("line {}: event before trace header", number + 1))?
+                .events.push(event);
+        }
+    }
+    trace.ok_or_else(|| String::from("no trace found"))
+}
+
+fn parse_header(header: &str) -> Option<(u32, u32)> {
+    let mut hz = None;
+    let mut dropped = None;
+    for field in header.split_whitespace() {
+        if let Some(value) = field.strip_prefix("hz=") {
+            hz = Some(value.parse().ok()?);
+        } else if let Some(value) = field.strip_prefix("dropped=") {
+            dropped = Some(value.parse().ok()?);
+        }
+    }
+    Some((hz?, dropped?))
+}
+
+/// Returns the length of the guard timer in ticks of a `hz` clock.
+pub fn guard_ticks(hz: u32) -> u32 {
+    (hz as u64 * GUARD_MSECS / 1000) as u32
+}
+
+/// A difference between the recorded edges and the sequencer's.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Divergence {
+    /// The sequencer drives a pin high (true) or low after the input at
+    /// `timestamp`, but the trace does not show it.
+    Missing {
+        /// The timestamp of the input the sequencer reacted to.
+        timestamp: u32,
+        /// The pin driven.
+        pin: GpioPin,
+        /// The level driven.
+        high: bool,
+    },
+    /// The trace shows `recorded` where the sequencer drives `pin` to a
+    /// different level, or drives a different pin.
+    Unexpected {
+        /// The recorded edge.
+        recorded: TraceEvent,
+        /// The pin the sequencer drives instead.
+        pin: GpioPin,
+        /// The level the sequencer drives instead.
+        high: bool,
+    },
+}
+
+/// The result of a replay.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Replay {
+    /// Every action of the sequencer, with the timestamp of its input.
+    pub actions: Vec<(u32, Action)>,
+    /// Where the sequencer and the trace disagree.
+    pub divergences: Vec<Divergence>,
+}
+
+struct Replayer {
+    sequencer: Sequencer,
+    guard_ticks: u32,
+    /// When the guard timer was started, if it is running.
+    guard_start: Option<u32>,
+    /// Edges the sequencer drove that the trace has yet to show, and the
+    /// timestamp of the input that caused them.
+    expected: VecDeque<(u32, GpioPin, bool)>,
+    replay: Replay,
+}
+
+impl Replayer {
+    fn run(&mut self, timestamp: u32, input: Input, expect_drives: bool) {
+        let Replayer { sequencer, guard_start, expected, replay, .. } = self;
+        let result: Result<(), ()> = sequencer.process(input, |action| {
+            replay.actions.push((timestamp, action));
+            match action {
+                Action::Drive(pin, high) if expect_drives => {
+                    expected.push_back((timestamp, pin, high));
+                },
+                Action::StartGuard => *guard_start = Some(timestamp),
+                _ => {},
+            }
+            Ok(())
+        });
+        debug_assert!(result.is_ok());
+    }
+
+    fn expire_guard(&mut self, now: u32) {
+        if let Some(start) = self.guard_start {
+            if now.wrapping_sub(start) >= self.guard_ticks {
+                self.guard_start = None;
+                self.run(start.wrapping_add(self.guard_ticks), Input::GuardExpired, true);
+            }
+        }
+    }
+
+    fn flush_expected(&mut self) {
+        for (timestamp, pin, high) in self.expected.drain(..) {
+            self.replay.divergences.push(Divergence::Missing { timestamp, pin, high });
+        }
+    }
+
+    fn event(&mut self, event: &TraceEvent) {
+        self.expire_guard(event.timestamp);
+        if !event.pin.is_output() {
+            self.flush_expected();
+            self.run(event.timestamp, Input::Event(event.pin), true);
+            return;
+        }
+
+        let high = event.edge == Edge::Rising;
+        match self.expected.pop_front() {
+            Some((_, pin, level)) if pin == event.pin && level == high => {},
+            Some((_, pin, level)) => {
+                self.replay.divergences.push(
+                    Divergence::Unexpected { recorded: *event, pin, high: level });
+                self.expected.clear();
+            },
+            None => {
+                // Not caused by an input, so it must have been requested.
+                let input = match event.pin {
+                    GpioPin::BMC_CPU_RST_N => Input::SetBmcCpuRst(!high),
+                    _ => Input::SetBmcSrst(!high),
+                };
+                self.run(event.timestamp, input, false);
+            },
+        }
+    }
+}
+
+/// Replays `events` with a guard timer of `guard_ticks`.
+pub fn replay(events: &[TraceEvent], guard_ticks: u32) -> Replay {
+    let mut replayer = Replayer {
+        sequencer: Sequencer::new(),
+        guard_ticks,
+        guard_start: None,
+        expected: VecDeque::new(),
+        replay: Replay::default(),
+    };
+    for event in events {
+        replayer.event(event);
+    }
+    replayer.flush_expected();
+    replayer.replay
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Boot, a BMC_RSTMON_N event inside the guard, and one after it that
+    // resets the BMC, at 32768 Hz (2031 guard ticks).
+    const LOG: &str = "\
+otpilot 0.1.0
+gpiotrace: begin hz=32768 dropped=0
+gpiotrace: 100 1 r
+gpiotrace: 105 0 r
+gpiotrace: 900 3 r
+Handling bmc_rstmon_n
+gpiotrace: 50000 3 r
+gpiotrace: 50002 1 f
+gpiotrace: 51500 1 r
+gpiotrace: 52000 2 r
+gpiotrace: end
+";
+
+    #[test]
+    fn parse_skips_other_output() {
+        let trace = parse(LOG).expect("parse failed");
+        assert_eq!(trace.hz, 32768);
+        assert_eq!(trace.dropped, 0);
+        assert_eq!(trace.events.len(), 7);
+        assert_eq!(trace.events[2],
+                   TraceEvent { timestamp: 900, pin: GpioPin::BMC_RSTMON_N, edge: Edge::Rising });
+        assert_eq!(TraceEvent::parse(&trace.events[2].to_string()), Some(trace.events[2]));
+        assert!(parse("gpiotrace: 1 1 r\n").is_err());
+    }
+
+    #[test]
+    fn recorded_trace_matches() {
+        let trace = parse(LOG).expect("parse failed");
+        let replay = replay(&trace.events, guard_ticks(trace.hz));
+        assert_eq!(replay.divergences, []);
+        assert!(replay.actions.contains(&(900, Action::Log("Ignored bmc_rstmon_n"))));
+        assert!(replay.actions.contains(&(50000, Action::ResetFlash)));
+        assert!(replay.actions.contains(&(52000, Action::Log("Ignored sys_rstmon_n"))));
+    }
+
+    #[test]
+    fn missing_reset_is_reported() {
+        // The BMC_RSTMON_N event comes after the guard, but the reset was
+        // never driven.
+        let mut events = parse(LOG).expect("parse failed").events;
+        events.retain(|event| event.timestamp < 50002 || event.timestamp > 51500);
+        let replay = replay(&events, guard_ticks(32768));
+        assert_eq!(replay.divergences, [
+            Divergence::Missing { timestamp: 50000, pin: GpioPin::BMC_CPU_RST_N, high: false },
+            Divergence::Missing { timestamp: 50000, pin: GpioPin::BMC_CPU_RST_N, high: true },
+        ]);
+    }
+}
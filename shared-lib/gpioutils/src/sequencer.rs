@@ -0,0 +1,138 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The reset sequencing logic.
+//!
+//! `Sequencer` turns each `Input` into `Action`s and leaves carrying them out
+//! to the caller: otpilot's GpioProcessor drives the hardware, and `replay`
+//! compares them against a recorded trace.
+//!
+//! The BMC toggles BMC_RSTMON_N while it comes out of reset, so after
+//! releasing a reset the sequencer starts a guard timer and ignores
+//! BMC_RSTMON_N until the caller reports that the guard expired. Outside the
+//! guard, BMC_RSTMON_N means the BMC reset itself: the sequencer holds it in
+//! reset while the SPI flash is put back into its initial state.
+//!
+//! SYS_RSTMON_N is handled by the kernel, which disables SPI passthrough as
+//! soon as it asserts. The sequencer only puts the flash back into its
+//! initial state when told about it, which enables passthrough again.
+
+use core::cell::Cell;
+
+use crate::pin::GpioPin;
+
+/// How long BMC_RSTMON_N is ignored after a reset is released, in
+/// milliseconds.
+pub const GUARD_MSECS: u64 = 62;
+
+/// Something that the sequencer reacts to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Input {
+    /// A request to assert (true) or deassert BMC_CPU_RST_N.
+    SetBmcCpuRst(bool),
+    /// A request to assert (true) or deassert BMC_SRST_N.
+    SetBmcSrst(bool),
+    /// An event on a reset monitor pin.
+    Event(GpioPin),
+    /// The guard timer expired.
+    GuardExpired,
+    /// The kernel disabled SPI passthrough because SYS_RSTMON_N asserted.
+    PassthroughDisabled,
+}
+
+/// Something that the caller must do, in the order given.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Drive an output pin high (true) or low.
+    Drive(GpioPin, bool),
+    /// Start the guard timer, replacing one that is running.
+    StartGuard,
+    /// Put the SPI flash back into its initial address mode, with SPI
+    /// passthrough disabled in the meantime.
+    ResetFlash,
+    /// Drop the pending events on a pin.
+    ClearEvents(GpioPin),
+    /// Print a message.
+    Log(&'static str),
+}
+
+/// The reset sequencing state.
+pub struct Sequencer {
+    /// Whether the guard timer is running.
+    guarded: Cell<bool>,
+}
+
+impl Sequencer {
+    /// Creates a sequencer with no guard timer running.
+    pub const fn new() -> Sequencer {
+        Sequencer {
+            guarded: Cell::new(false),
+        }
+    }
+
+    /// Reacts to `input`, passing each resulting action to `act`. Stops at
+    /// the first action that fails.
+    pub fn process<E, F>(&self, input: Input, mut act: F) -> Result<(), E>
+        where F: FnMut(Action) -> Result<(), E>
+    {
+        match input {
+            Input::SetBmcCpuRst(asserted) => self.set_reset(GpioPin::BMC_CPU_RST_N, asserted, &mut act),
+            Input::SetBmcSrst(asserted) => self.set_reset(GpioPin::BMC_SRST_N, asserted, &mut act),
+            Input::Event(GpioPin::BMC_RSTMON_N) => {
+                if self.guarded.get() {
+                    return act(Action::Log("Ignored bmc_rstmon_n"));
+                }
+                act(Action::Log("Handling bmc_rstmon_n"))?;
+                self.set_reset(GpioPin::BMC_CPU_RST_N, true, &mut act)?;
+                act(Action::ResetFlash)?;
+                // We don't care about any events that may have happened during reset.
+                act(Action::ClearEvents(GpioPin::BMC_RSTMON_N))?;
+                self.set_reset(GpioPin::BMC_CPU_RST_N, false, &mut act)
+            },
+            Input::Event(GpioPin::SYS_RSTMON_N) => act(Action::Log("Ignored sys_rstmon_n")),
+            // Outputs have no events.
+            Input::Event(_) => Ok(()),
+            Input::GuardExpired => {
+                self.guarded.set(false);
+                act(Action::Log("GPIO: alarm expired"))
+            },
+            Input::PassthroughDisabled => {
+                // Passthrough stays off until the flash is back in its
+                // initial state.
+                act(Action::Log("Handling passthrough guard"))?;
+                act(Action::ResetFlash)
+            },
+        }
+    }
+
+    fn set_reset<E, F>(&self, pin: GpioPin, asserted: bool, act: &mut F) -> Result<(), E>
+        where F: FnMut(Action) -> Result<(), E>
+    {
+        // The resets are active low.
+        act(Action::Drive(pin, !asserted))?;
+        if !asserted {
+            self.guarded.set(true);
+            act(Action::StartGuard)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! GPIO event traces.
+//!
+//! otpilot records every event it consumes on the reset monitor pins and
+//! every edge it drives on the reset pins into a `GpioTrace`, timestamped
+//! with the alarm clock. The trace is printed on the console as
+//!
+//! ```text
+//! gpiotrace: begin hz=<alarm clock frequency> dropped=<count>
+//! gpiotrace: <timestamp> <pin> <r|f>
+//! gpiotrace: end
+//! ```
+//!
+//! with one line per event, oldest first, where `<pin>` is the kernel GPIO
+//! number. Events are timestamped when otpilot consumes them rather than
+//! when the interrupt fires, and events cleared without being consumed are
+//! not recorded.
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use crate::pin::GpioPin;
+
+/// Starts every line of a printed trace.
+pub const TRACE_PREFIX: &str = "gpiotrace:";
+
+/// Number of events kept in a `GpioTrace`.
+pub const TRACE_LEN: usize = 64;
+
+/// The direction of a signal change.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Edge {
+    /// Low to high. The reset pins are active low, so this releases a reset.
+    Rising,
+    /// High to low.
+    Falling,
+}
+
+/// A single event in a trace.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TraceEvent {
+    /// The alarm clock, in ticks. Wraps around.
+    pub timestamp: u32,
+    /// The pin the event occurred on.
+    pub pin: GpioPin,
+    /// The edge seen on an input pin, or driven on an output pin.
+    pub edge: Edge,
+}
+
+impl TraceEvent {
+    const EMPTY: TraceEvent = TraceEvent {
+        timestamp: 0,
+        pin: GpioPin::BMC_SRST_N,
+        edge: Edge::Rising,
+    };
+
+    /// Parses a line printed for the event. Returns None for any other line,
+    /// including the begin and end lines.
+    pub fn parse(line: &str) -> Option<TraceEvent> {
+        let mut fields = line.split_whitespace();
+        if fields.next()? != TRACE_PREFIX {
+            return None;
+        }
+        let timestamp = fields.next()?.parse::<u32>().ok()?;
+        let pin = GpioPin::try_from(fields.next()?.parse::<usize>().ok()?).ok()?;
+        let edge = match fields.next()? {
+            "r" => Edge::Rising,
+            "f" => Edge::Falling,
+            _ => return None,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(TraceEvent { timestamp, pin, edge })
+    }
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let edge = match self.edge {
+            Edge::Rising => 'r',
+            Edge::Falling => 'f',
+        };
+        write!(f, "{} {} {} {}", TRACE_PREFIX, self.timestamp, self.pin as usize, edge)
+    }
+}
+
+/// A ring of the most recent `TRACE_LEN` events.
+pub struct GpioTrace {
+    events: [TraceEvent; TRACE_LEN],
+    // Index of the next event to write and the number of valid events.
+    next: usize,
+    count: usize,
+    // Number of events overwritten before the trace was cleared.
+    dropped: u32,
+}
+
+impl GpioTrace {
+    /// Creates an empty trace.
+    pub const fn new() -> GpioTrace {
+        GpioTrace {
+            events: [TraceEvent::EMPTY; TRACE_LEN],
+            next: 0,
+            count: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Appends `event`, overwriting the oldest event if the trace is full.
+    pub fn record(&mut self, event: TraceEvent) {
+        self.events[self.next] = event;
+        self.next = (self.next + 1) % TRACE_LEN;
+        if self.count == TRACE_LEN {
+            self.dropped = self.dropped.wrapping_add(1);
+        } else {
+            self.count += 1;
+        }
+    }
+
+    /// Returns the number of events overwritten since the trace was last
+    /// cleared.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Returns the recorded events, oldest first.
+    pub fn events(&self) -> impl Iterator<Item = &TraceEvent> {
+        let first = (self.next + TRACE_LEN - self.count) % TRACE_LEN;
+        (0..self.count).map(move |n| &self.events[(first + n) % TRACE_LEN])
+    }
+
+    /// Removes all events.
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.dropped = 0;
+    }
+}
+
+impl Default for GpioTrace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Differential fuzzing of the payload parser.
+//!
+//! The device and the host tools both parse payloads with
+//! `spiutils::protocol::payload::parse_payload`. This crate checks it against
+//! a reference parser written directly from the wire format, so that a change
+//! to the shared parser that makes it accept, reject or split an input
+//! differently is caught on the host:
+//!
+//! ```text
+//! content type: u8 | content length: u16 (big-endian) | CRC-8: u8
+//!     | [header CRC-16: u16 (big-endian)] | content
+//! ```
+//!
+//! The CRC-8 (polynomial x^8 + x^2 + x + 1, initial value 0) covers the
+//! content type, the content length and the content. If bit 7 of the content
+//! type is set, it is not part of the content type and the header CRC-16
+//! (CCITT-FALSE: polynomial x^16 + x^12 + x^5 + 1, initial value 0xffff)
+//! over the first four bytes follows.
+
+use spiutils::protocol::payload;
+
+/// The highest content type the device knows.
+const MAX_CONTENT_TYPE: u8 = 0x06;
+
+/// Set in the content type if the header CRC-16 follows.
+const HEADER_CRC_FLAG: u8 = 0x80;
+
+/// What a parser made of an input.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome<'a> {
+    Accepted { content_type: u8, checksum: u8, header_crc: Option<u16>, content: &'a [u8] },
+    BadHeader,
+    BadHeaderCrc,
+    Truncated,
+    BadChecksum,
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 }
+        })
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffffu16, |crc, byte| {
+        (0..8).fold(crc ^ (*byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
+/// The reference parser.
+pub fn reference(data: &[u8]) -> Outcome<'_> {
+    let has_header_crc = data.first().map_or(false, |byte| byte & HEADER_CRC_FLAG != 0);
+    let header_len = if has_header_crc { 6 } else { 4 };
+    if data.len() < header_len || data[0] & !HEADER_CRC_FLAG > MAX_CONTENT_TYPE {
+        return Outcome::BadHeader;
+    }
+    let content_type = data[0] & !HEADER_CRC_FLAG;
+    let header_crc = if has_header_crc {
+        let header_crc = u16::from_be_bytes([data[4], data[5]]);
+        if crc16(&data[..4]) != header_crc {
+            return Outcome::BadHeaderCrc;
+        }
+        Some(header_crc)
+    } else {
+        None
+    };
+    let content_len = u16::from_be_bytes([data[1], data[2]]) as usize;
+    if data.len() - header_len < content_len {
+        return Outcome::Truncated;
+    }
+    let content = &data[header_len..header_len + content_len];
+    let mut covered = vec![content_type, data[1], data[2]];
+    covered.extend_from_slice(content);
+    if crc8(&covered) != data[3] {
+        return Outcome::BadChecksum;
+    }
+    Outcome::Accepted { content_type, checksum: data[3], header_crc, content }
+}
+
+/// The parser used by the device and the host tools.
+pub fn device(data: &[u8]) -> Outcome<'_> {
+    use spiutils::protocol::wire::WireEnum;
+    match payload::parse_payload(data) {
+        Ok((header, content)) => Outcome::Accepted {
+            content_type: header.content.to_wire_value(),
+            checksum: header.checksum,
+            header_crc: header.header_crc,
+            content,
+        },
+        Err(payload::PayloadError::Header(_)) => Outcome::BadHeader,
+        Err(payload::PayloadError::BadHeaderCrc) => Outcome::BadHeaderCrc,
+        Err(payload::PayloadError::Truncated) => Outcome::Truncated,
+        Err(payload::PayloadError::BadChecksum) => Outcome::BadChecksum,
+    }
+}
+
+/// Runs both parsers on `data`. Returns an error describing the difference
+/// if they disagree.
+pub fn check(data: &[u8]) -> Result<(), String> {
+    let expected = reference(data);
+    let found = device(data);
+    if expected != found {
+        return Err(format!("reference: {:?}\ndevice:    {:?}", expected, found));
+    }
+    Ok(())
+}
+
+/// A xorshift64* generator, so that a run is reproducible from its seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift gets stuck at zero.
+        Rng(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Returns a value in [0, bound).
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Generates an input. Most inputs start as a valid payload, as random bytes
+/// rarely get past the checksum, and are then mutated.
+pub fn generate(rng: &mut Rng) -> Vec<u8> {
+    if rng.below(4) == 0 {
+        return (0..rng.below(16)).map(|_| rng.next_u64() as u8).collect();
+    }
+
+    let content: Vec<u8> = (0..rng.below(64)).map(|_| rng.next_u64() as u8).collect();
+    let mut data = vec![rng.below(MAX_CONTENT_TYPE as usize + 2) as u8];
+    data.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    let mut covered = data.clone();
+    covered.extend_from_slice(&content);
+    data.push(crc8(&covered));
+    if rng.below(2) == 0 {
+        data[0] |= HEADER_CRC_FLAG;
+        let header_crc = crc16(&data);
+        data.extend_from_slice(&header_crc.to_be_bytes());
+    }
+    data.extend_from_slice(&content);
+
+    for _ in 0..rng.below(3) {
+        match rng.below(5) {
+            0 => {
+                let index = rng.below(data.len());
+                data[index] ^= 1 << rng.below(8);
+            }
+            1 => data.truncate(rng.below(data.len() + 1)),
+            2 => data.push(rng.next_u64() as u8),
+            // Length fields are where parsers most often differ.
+            3 if data.len() >= 3 => data[1..3].copy_from_slice(&(rng.next_u64() as u16).to_be_bytes()),
+            _ => {
+                let index = rng.below(data.len());
+                data[index] = rng.next_u64() as u8;
+            }
+        }
+        if data.is_empty() {
+            break;
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parsers_agree() {
+        let mut rng = Rng::new(1);
+        for _ in 0..100_000 {
+            let data = generate(&mut rng);
+            if let Err(err) = check(&data) {
+                panic!("{:02x?}\n{}", data, err);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_content() {
+        // Claims 16 bytes of content but carries 1.
+        assert_eq!(device(&[0x01, 0x00, 0x10, 0x00, 0xaa]), Outcome::Truncated);
+        assert!(check(&[0x01, 0x00, 0x10, 0x00, 0xaa]).is_ok());
+    }
+
+    #[test]
+    fn corrupted_length_with_header_crc() {
+        // The same payload, but the header CRC shows that the length is bad.
+        let mut data = vec![0x81, 0x00, 0x10, 0x00];
+        let header_crc = crc16(&data) ^ 1;
+        data.extend_from_slice(&header_crc.to_be_bytes());
+        data.push(0xaa);
+        assert_eq!(device(&data), Outcome::BadHeaderCrc);
+        assert!(check(&data).is_ok());
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! payload_diff runs the differential payload fuzzer.
+//!
+//! ```text
+//! payload_diff [--seed N] [--iterations N]    fuzz, forever by default
+//! payload_diff FILE...                        replay saved inputs
+//! ```
+//!
+//! On a divergence, the input is written to `payload_diff-<seed>-<n>.bin` so
+//! that it can be replayed.
+
+use spiutils_fuzz::check;
+use spiutils_fuzz::generate;
+use spiutils_fuzz::Rng;
+
+/// Progress is printed every this many inputs.
+const REPORT_INTERVAL: u64 = 1_000_000;
+
+fn parse_flag(args: &mut std::vec::IntoIter<String>, flag: &str) -> u64 {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("{} needs a number", flag))
+}
+
+fn main() {
+    let mut seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(1);
+    let mut iterations = None;
+    let mut files = Vec::new();
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>().into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => seed = parse_flag(&mut args, "--seed"),
+            "--iterations" => iterations = Some(parse_flag(&mut args, "--iterations")),
+            _ => files.push(arg),
+        }
+    }
+
+    if !files.is_empty() {
+        let mut failed = false;
+        for file in files {
+            let data = std::fs::read(&file).expect("failed to read input");
+            if let Err(err) = check(&data) {
+                println!("{}: parsers disagree\n{}", file, err);
+                failed = true;
+            }
+        }
+        std::process::exit(failed as i32);
+    }
+
+    println!("seed {}", seed);
+    let mut rng = Rng::new(seed);
+    let mut next_report = REPORT_INTERVAL;
+    let iterations = iterations.unwrap_or(u64::MAX);
+    let mut count = 0u64;
+    while count < iterations {
+        let data = generate(&mut rng);
+        if let Err(err) = check(&data) {
+            let file = format!("payload_diff-{}-{}.bin", seed, count);
+            std::fs::write(&file, &data).expect("failed to save input");
+            println!("input {} ({:02x?}) saved to {}\n{}", count, data, file, err);
+            std::process::exit(1);
+        }
+        count += 1;
+        if count == next_report {
+            println!("{} inputs", count);
+            next_report += REPORT_INTERVAL;
+        }
+    }
+    println!("{} inputs, no divergence", count);
+}
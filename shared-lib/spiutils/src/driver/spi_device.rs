@@ -18,6 +18,10 @@
 
 use crate::io::Read;
 use crate::io::Write;
+use crate::protocol::flash::OpCode;
+use crate::protocol::flash::STATUS_BP_MASK;
+use crate::protocol::flash::STATUS_BP_SHIFT;
+use crate::protocol::flash::STATUS_TB;
 use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::FromWire;
 use crate::protocol::wire::ToWireError;
@@ -28,9 +32,10 @@ use core::default::Default;
 use core::mem;
 
 /// Handler mode.
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
 pub enum HandlerMode {
     /// Do not handle request.
+    #[default]
     Disabled = 0,
 
     /// Handle request in user space.
@@ -40,10 +45,6 @@ pub enum HandlerMode {
     KernelSpace = 2,
 }
 
-impl Default for HandlerMode {
-    fn default() -> Self { Self::Disabled }
-}
-
 /// Error for invalid handler mode conversion.
 pub struct InvalidHandlerMode;
 
@@ -84,6 +85,45 @@ pub struct AddressConfig {
     pub virtual_size: u32,
 }
 
+/// Why an AddressConfig or address window was rejected.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AddressConfigError {
+    /// A size is zero, or not a power of two where one is required.
+    BadSize,
+
+    /// A window extends past the end of the 32-bit address space.
+    Overflow,
+}
+
+/// Returns the last address of the `size` bytes starting at `base`.
+pub fn window_last_address(base: u32, size: u32) -> Result<u32, AddressConfigError> {
+    if size == 0 {
+        return Err(AddressConfigError::BadSize);
+    }
+    base.checked_add(size - 1).ok_or(AddressConfigError::Overflow)
+}
+
+impl AddressConfig {
+    /// Checks that the sizes are powers of two and that the flash windows
+    /// fit in the 32-bit address space on both buses, so that no address
+    /// math on the configuration can wrap.
+    pub fn validate(&self) -> Result<(), AddressConfigError> {
+        if !self.flash_physical_size.is_power_of_two() || !self.virtual_size.is_power_of_two() {
+            return Err(AddressConfigError::BadSize);
+        }
+        self.flash_window()?;
+        window_last_address(self.flash_physical_base, self.flash_physical_size)?;
+        Ok(())
+    }
+
+    /// Returns the first and last address of the external flash on the
+    /// SPI device bus.
+    pub fn flash_window(&self) -> Result<(u32, u32), AddressConfigError> {
+        let last = window_last_address(self.flash_virtual_base, self.flash_physical_size)?;
+        Ok((self.flash_virtual_base, last))
+    }
+}
+
 impl<'a> FromWire<'a> for AddressConfig {
     fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
         let flash_virtual_base = r.read_be::<u32>()?;
@@ -111,3 +151,356 @@ impl ToWire for AddressConfig {
         Ok(())
     }
 }
+
+/// What SPI passthrough does with commands with a given op code.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum PassthroughFilterAction {
+    /// Use the default filter, which only forwards commands that cannot
+    /// modify the external flash, such as reads.
+    #[default]
+    Default = 0,
+
+    /// Forward the command to the external flash unchanged.
+    Pass = 1,
+
+    /// Replace the op code with one the external flash does not know.
+    Block = 2,
+}
+
+/// Error for invalid passthrough filter action conversion.
+pub struct InvalidPassthroughFilterAction;
+
+impl TryFrom<usize> for PassthroughFilterAction {
+    type Error = InvalidPassthroughFilterAction;
+
+    fn try_from(item: usize) -> Result<PassthroughFilterAction, Self::Error> {
+        match item {
+            0 => Ok(PassthroughFilterAction::Default),
+            1 => Ok(PassthroughFilterAction::Pass),
+            2 => Ok(PassthroughFilterAction::Block),
+            _ => Err(InvalidPassthroughFilterAction),
+        }
+    }
+}
+
+/// Returns the offset and size of the part of a flash of `flash_size` bytes
+/// that the block protect bits in `status` protect against writes.
+///
+/// This follows the scheme most 3-byte address flashes use: BP0 to BP2 set to
+/// a value `n` from 1 to 6 protect 1/2^(7-n) of the flash, and 7 protects all
+/// of it. The protected blocks are at the top of the flash unless TB is set.
+pub fn block_protected_range(status: u8, flash_size: u32) -> (u32, u32) {
+    let size = match (status & STATUS_BP_MASK) >> STATUS_BP_SHIFT {
+        0 => 0,
+        7 => flash_size,
+        bp => flash_size >> (7 - bp),
+    };
+    if status & STATUS_TB != 0 {
+        (0, size)
+    } else {
+        (flash_size - size, size)
+    }
+}
+
+/// Access permission for a region of the SPI device address space.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum AccessPermission {
+    /// Region can be read and written.
+    #[default]
+    ReadWrite = 0,
+
+    /// Region can only be read.
+    ReadOnly = 1,
+
+    /// Region can neither be read nor written.
+    Deny = 2,
+}
+
+/// Error for invalid access permission conversion.
+pub struct InvalidAccessPermission;
+
+impl TryFrom<u8> for AccessPermission {
+    type Error = InvalidAccessPermission;
+
+    fn try_from(item: u8) -> Result<AccessPermission, Self::Error> {
+        match item {
+            0 => Ok(AccessPermission::ReadWrite),
+            1 => Ok(AccessPermission::ReadOnly),
+            2 => Ok(AccessPermission::Deny),
+            _ => Err(InvalidAccessPermission),
+        }
+    }
+}
+
+/// How to answer an access that was denied by the access map.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum DeniedAccessResponse {
+    /// Reads return all zeros. Denied writes are dropped and the BUSY bit is
+    /// cleared, so that the host sees the command as completed.
+    #[default]
+    Zeros = 0,
+
+    /// Reads return all ones. Denied writes are dropped and the BUSY bit
+    /// remains set until the handler clears it.
+    Busy = 1,
+}
+
+/// Error for invalid denied access response conversion.
+pub struct InvalidDeniedAccessResponse;
+
+impl TryFrom<usize> for DeniedAccessResponse {
+    type Error = InvalidDeniedAccessResponse;
+
+    fn try_from(item: usize) -> Result<DeniedAccessResponse, Self::Error> {
+        match item {
+            0 => Ok(DeniedAccessResponse::Zeros),
+            1 => Ok(DeniedAccessResponse::Busy),
+            _ => Err(InvalidDeniedAccessResponse),
+        }
+    }
+}
+
+/// Who owns the receive buffer between transactions.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum RxBufferMode {
+    /// Every transaction is written into the receive buffer as soon as it
+    /// arrives, even while the handler is still reading the previous one.
+    #[default]
+    Copy = 0,
+
+    /// A transaction hands the receive buffer to the handler, which owns it
+    /// until it releases it. Transactions arriving in the meantime wait in
+    /// the hardware FIFO.
+    Handoff = 1,
+}
+
+/// Error for invalid receive buffer mode conversion.
+pub struct InvalidRxBufferMode;
+
+impl TryFrom<usize> for RxBufferMode {
+    type Error = InvalidRxBufferMode;
+
+    fn try_from(item: usize) -> Result<RxBufferMode, Self::Error> {
+        match item {
+            0 => Ok(RxBufferMode::Copy),
+            1 => Ok(RxBufferMode::Handoff),
+            _ => Err(InvalidRxBufferMode),
+        }
+    }
+}
+
+/// A flash operation whose duration the SPI device can emulate.
+///
+/// The SPI device keeps the BUSY bit set for at least the configured latency
+/// after receiving the command, even if the handler completes it sooner.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum EmulatedOperation {
+    /// OpCode::PageProgram.
+    Program = 0,
+
+    /// OpCode::SectorErase.
+    SectorErase = 1,
+
+    /// OpCode::BlockErase32KB and OpCode::BlockErase64KB.
+    BlockErase = 2,
+
+    /// OpCode::ChipErase and OpCode::ChipErase2.
+    ChipErase = 3,
+}
+
+/// The number of EmulatedOperation values.
+pub const EMULATED_OPERATIONS: usize = 4;
+
+impl EmulatedOperation {
+    /// Returns the operation performed by `op_code`, if its duration can be
+    /// emulated.
+    pub fn from_op_code(op_code: OpCode) -> Option<EmulatedOperation> {
+        match op_code {
+            OpCode::PageProgram => Some(EmulatedOperation::Program),
+            OpCode::SectorErase => Some(EmulatedOperation::SectorErase),
+            OpCode::BlockErase32KB | OpCode::BlockErase64KB => Some(EmulatedOperation::BlockErase),
+            OpCode::ChipErase | OpCode::ChipErase2 => Some(EmulatedOperation::ChipErase),
+            _ => None,
+        }
+    }
+}
+
+/// Error for invalid emulated operation conversion.
+pub struct InvalidEmulatedOperation;
+
+impl TryFrom<usize> for EmulatedOperation {
+    type Error = InvalidEmulatedOperation;
+
+    fn try_from(item: usize) -> Result<EmulatedOperation, Self::Error> {
+        match item {
+            0 => Ok(EmulatedOperation::Program),
+            1 => Ok(EmulatedOperation::SectorErase),
+            2 => Ok(EmulatedOperation::BlockErase),
+            3 => Ok(EmulatedOperation::ChipErase),
+            _ => Err(InvalidEmulatedOperation),
+        }
+    }
+}
+
+/// Set in the read capabilities if the device passes 1-1-2 fast reads
+/// (OpCode::FastReadDualOutput) through to the external flash.
+pub const READ_CAPABILITY_DUAL_OUTPUT: u32 = 1 << 0;
+
+/// Set in the read capabilities if the device passes 1-1-4 fast reads
+/// (OpCode::FastReadQuadOutput) through to the external flash.
+pub const READ_CAPABILITY_QUAD_OUTPUT: u32 = 1 << 1;
+
+/// Maximum number of regions in the access map.
+pub const MAX_ACCESS_REGIONS: usize = 4;
+
+/// The length of an AccessRegion on the wire, in bytes.
+pub const ACCESS_REGION_LEN: usize = 2 * mem::size_of::<u32>() + 1;
+
+/// A region of the SPI device address space with an access permission.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct AccessRegion {
+    /// The address on the SPI device bus the region starts at.
+    pub virtual_base: u32,
+
+    /// The size of the region.
+    pub size: u32,
+
+    /// The permission for accesses into this region.
+    pub permission: AccessPermission,
+}
+
+impl AccessRegion {
+    /// Check if the specified address is within the region.
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.virtual_base && address - self.virtual_base < self.size
+    }
+
+    /// Check if an access of the specified type is allowed by this region.
+    pub fn allows(&self, is_write: bool) -> bool {
+        match self.permission {
+            AccessPermission::ReadWrite => true,
+            AccessPermission::ReadOnly => !is_write,
+            AccessPermission::Deny => false,
+        }
+    }
+}
+
+impl<'a> FromWire<'a> for AccessRegion {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let virtual_base = r.read_be::<u32>()?;
+        let size = r.read_be::<u32>()?;
+        let permission_u8 = r.read_be::<u8>()?;
+        let permission = AccessPermission::try_from(permission_u8)
+            .map_err(|_| FromWireError::OutOfRange)?;
+        Ok(Self {
+            virtual_base,
+            size,
+            permission,
+        })
+    }
+}
+
+impl ToWire for AccessRegion {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.virtual_base)?;
+        w.write_be(self.size)?;
+        w.write_be(self.permission as u8)?;
+        Ok(())
+    }
+}
+
+/// The length of AccessMetrics on the wire, in bytes.
+pub const ACCESS_METRICS_LEN: usize = 2 * mem::size_of::<u32>();
+
+/// Counters for accesses denied by the access map.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub struct AccessMetrics {
+    /// Number of denied read commands seen by software.
+    pub denied_reads: u32,
+
+    /// Number of denied write and erase commands.
+    pub denied_writes: u32,
+}
+
+impl<'a> FromWire<'a> for AccessMetrics {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let denied_reads = r.read_be::<u32>()?;
+        let denied_writes = r.read_be::<u32>()?;
+        Ok(Self {
+            denied_reads,
+            denied_writes,
+        })
+    }
+}
+
+impl ToWire for AccessMetrics {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.denied_reads)?;
+        w.write_be(self.denied_writes)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(flash_virtual_base: u32, flash_physical_base: u32, flash_physical_size: u32)
+              -> AddressConfig {
+        AddressConfig {
+            flash_virtual_base,
+            flash_physical_base,
+            flash_physical_size,
+            ram_virtual_base: 0x80000,
+            virtual_size: 0x4000000,
+        }
+    }
+
+    #[test]
+    fn window_last_address_boundaries() {
+        assert_eq!(window_last_address(0, 1), Ok(0));
+        assert_eq!(window_last_address(0, 0xffffffff), Ok(0xfffffffe));
+        assert_eq!(window_last_address(1, 0xffffffff), Ok(0xffffffff));
+        assert_eq!(window_last_address(2, 0xffffffff), Err(AddressConfigError::Overflow));
+        assert_eq!(window_last_address(0xffffffff, 1), Ok(0xffffffff));
+        assert_eq!(window_last_address(0xffffffff, 2), Err(AddressConfigError::Overflow));
+        assert_eq!(window_last_address(0x1000, 0), Err(AddressConfigError::BadSize));
+    }
+
+    #[test]
+    fn validate_accepts_windows_up_to_the_top_of_the_address_space() {
+        assert_eq!(config(0, 0, 0x4000000).validate(), Ok(()));
+        // The largest power of two, ending exactly at 4 GiB on both buses.
+        let top = config(0x80000000, 0x80000000, 0x80000000);
+        assert_eq!(top.validate(), Ok(()));
+        assert_eq!(top.flash_window(), Ok((0x80000000, 0xffffffff)));
+    }
+
+    #[test]
+    fn validate_rejects_wrapping_windows() {
+        assert_eq!(config(0x80000200, 0, 0x80000000).validate(),
+                   Err(AddressConfigError::Overflow));
+        assert_eq!(config(0, 0xfffff000, 0x2000).validate(),
+                   Err(AddressConfigError::Overflow));
+    }
+
+    #[test]
+    fn block_protected_range_follows_bp_and_tb() {
+        assert_eq!(block_protected_range(0x00, 0x1000000), (0x1000000, 0));
+        assert_eq!(block_protected_range(0x04, 0x1000000), (0xfc0000, 0x40000));
+        assert_eq!(block_protected_range(0x18, 0x1000000), (0x800000, 0x800000));
+        assert_eq!(block_protected_range(0x1c, 0x1000000), (0, 0x1000000));
+        assert_eq!(block_protected_range(0x24, 0x1000000), (0, 0x40000));
+        // BUSY, WEL and the bits above TB do not matter.
+        assert_eq!(block_protected_range(0xc7, 0x1000000), (0xfc0000, 0x40000));
+    }
+
+    #[test]
+    fn validate_rejects_bad_sizes() {
+        assert_eq!(config(0, 0, 0).validate(), Err(AddressConfigError::BadSize));
+        assert_eq!(config(0, 0, 0x3000).validate(), Err(AddressConfigError::BadSize));
+        let mut zero_virtual_size = config(0, 0, 0x1000);
+        zero_virtual_size.virtual_size = 0;
+        assert_eq!(zero_virtual_size.validate(), Err(AddressConfigError::BadSize));
+    }
+}
@@ -27,3 +27,5 @@ pub mod io;
 
 #[macro_use]
 pub mod protocol;
+
+pub mod session;
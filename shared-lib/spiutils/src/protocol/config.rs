@@ -0,0 +1,278 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Board configuration.
+//!
+//! Board-level tunables are kept in a config page in flash, which the kernel
+//! reads at boot before initializing the drivers. A config page holds a
+//! header followed by TLV entries:
+//!
+//! ```text
+//! magic: u32 | sequence: u32 | entries length: u16 | CRC-32: u32
+//! key: u16 | value length: u16 | value ...    (repeated)
+//! ```
+//!
+//! The CRC covers the sequence, the entries length and the entries. Entries
+//! with unknown keys are skipped, so that a page written by newer firmware
+//! can still be read, and keys without an entry keep their default value.
+
+use crate::io::Read;
+use crate::io::Write;
+use crate::io::Cursor;
+use crate::protocol::wire::FromWireError;
+use crate::protocol::wire::FromWire;
+use crate::protocol::wire::ToWireError;
+use crate::protocol::wire::ToWire;
+use crate::protocol::wire::WireEnum;
+
+/// Marks a config page ("CNFG").
+pub const CONFIG_MAGIC: u32 = 0x434e4647;
+
+/// The length of the config page header, in bytes.
+pub const CONFIG_HEADER_LEN: usize = 4 + 4 + 2 + 4;
+
+/// The maximum length of an encoded config page, in bytes.
+pub const MAX_CONFIG_LEN: usize = 128;
+
+wire_enum! {
+    /// A board configuration value.
+    pub enum ConfigKey: u16 {
+        /// Console UART baud rate.
+        ConsoleBaud = 0x0001,
+
+        /// Whether SPI passthrough is enabled at boot (0 or 1).
+        PassthroughDefault = 0x0002,
+
+        /// Interval between info block heartbeats, in milliseconds.
+        HeartbeatIntervalMs = 0x0003,
+    }
+}
+
+/// All configuration keys, in the order they are written.
+pub const CONFIG_KEYS: [ConfigKey; 3] = [
+    ConfigKey::ConsoleBaud,
+    ConfigKey::PassthroughDefault,
+    ConfigKey::HeartbeatIntervalMs,
+];
+
+// The length of an encoded entry, in bytes. All values are u32.
+const ENTRY_LEN: usize = 2 + 2 + 4;
+
+/// The board configuration.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BoardConfig {
+    /// Console UART baud rate.
+    pub console_baud: u32,
+
+    /// Whether SPI passthrough is enabled at boot.
+    pub passthrough_default: bool,
+
+    /// Interval between info block heartbeats, in milliseconds.
+    pub heartbeat_interval_ms: u32,
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        Self {
+            console_baud: 115200,
+            passthrough_default: false,
+            heartbeat_interval_ms: 1000,
+        }
+    }
+}
+
+impl BoardConfig {
+    /// Returns the value of `key`.
+    pub fn get(&self, key: ConfigKey) -> u32 {
+        match key {
+            ConfigKey::ConsoleBaud => self.console_baud,
+            ConfigKey::PassthroughDefault => self.passthrough_default as u32,
+            ConfigKey::HeartbeatIntervalMs => self.heartbeat_interval_ms,
+        }
+    }
+
+    /// Sets `key` to `value`. Returns false and leaves the configuration
+    /// unchanged if `value` is out of range for `key`.
+    pub fn set(&mut self, key: ConfigKey, value: u32) -> bool {
+        match key {
+            ConfigKey::ConsoleBaud if (9600..=1_000_000).contains(&value) => {
+                self.console_baud = value;
+            },
+            ConfigKey::PassthroughDefault if value <= 1 => {
+                self.passthrough_default = value != 0;
+            },
+            ConfigKey::HeartbeatIntervalMs if (100..=60_000).contains(&value) => {
+                self.heartbeat_interval_ms = value;
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A parsed config page.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ConfigPage {
+    /// Incremented on every commit. Of two valid pages, the one with the
+    /// higher sequence number is active.
+    pub sequence: u32,
+
+    /// The configuration stored in the page.
+    pub config: BoardConfig,
+}
+
+/// Data for CRC-32 implementation.
+pub(crate) struct Crc32 {
+    crc: u32,
+}
+
+/// The CRC-32 (IEEE 802.3) implementation.
+impl Crc32 {
+    /// Initialize CRC-32 data.
+    pub fn init() -> Self {
+        Self {
+            crc: 0xffffffff,
+        }
+    }
+
+    /// Get the calculated CRC-32 checksum.
+    pub fn get(&self) -> u32 {
+        !self.crc
+    }
+
+    /// Adds the specified data to the CRC-32 checksum.
+    pub fn add(&mut self, data: &[u8]) -> &mut Self {
+        for byte in data {
+            self.crc ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (self.crc & 1).wrapping_neg();
+                self.crc = (self.crc >> 1) ^ (0xedb88320 & mask);
+            }
+        }
+
+        self
+    }
+}
+
+/// Compute the checksum of a config page with the given sequence number and
+/// entries.
+pub fn compute_checksum(sequence: u32, entries: &[u8]) -> u32 {
+    Crc32::init()
+        .add(&sequence.to_be_bytes())
+        .add(&(entries.len() as u16).to_be_bytes())
+        .add(entries)
+        .get()
+}
+
+impl<'a> FromWire<'a> for ConfigPage {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        if r.read_be::<u32>()? != CONFIG_MAGIC {
+            return Err(FromWireError::OutOfRange);
+        }
+        let sequence = r.read_be::<u32>()?;
+        let entries_len = r.read_be::<u16>()? as usize;
+        let checksum = r.read_be::<u32>()?;
+        if CONFIG_HEADER_LEN + entries_len > MAX_CONFIG_LEN {
+            return Err(FromWireError::OutOfRange);
+        }
+        let mut entries = r.read_bytes(entries_len)?;
+        if compute_checksum(sequence, entries) != checksum {
+            return Err(FromWireError::OutOfRange);
+        }
+
+        let mut config = BoardConfig::default();
+        while entries.remaining_data() > 0 {
+            let key = entries.read_be::<u16>()?;
+            let len = entries.read_be::<u16>()? as usize;
+            let value = entries.read_bytes(len)?;
+            let key = match ConfigKey::from_wire_value(key) {
+                Some(key) => key,
+                None => continue,
+            };
+            if len != 4 {
+                return Err(FromWireError::OutOfRange);
+            }
+            let value = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+            if !config.set(key, value) {
+                return Err(FromWireError::OutOfRange);
+            }
+        }
+        Ok(Self {
+            sequence,
+            config,
+        })
+    }
+}
+
+impl ToWire for ConfigPage {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        let mut entries = [0u8; CONFIG_KEYS.len() * ENTRY_LEN];
+        {
+            let mut cursor = Cursor::new(&mut entries);
+            for key in CONFIG_KEYS.iter() {
+                cursor.write_be(key.to_wire_value())?;
+                cursor.write_be(4u16)?;
+                cursor.write_be(self.config.get(*key))?;
+            }
+        }
+        w.write_be(CONFIG_MAGIC)?;
+        w.write_be(self.sequence)?;
+        w.write_be(entries.len() as u16)?;
+        w.write_be(compute_checksum(self.sequence, &entries))?;
+        w.write_bytes(&entries)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut config = BoardConfig::default();
+        assert!(config.set(ConfigKey::ConsoleBaud, 921600));
+        assert!(!config.set(ConfigKey::PassthroughDefault, 2));
+        let page = ConfigPage { sequence: 7, config };
+
+        let mut buf = [0u8; MAX_CONFIG_LEN];
+        page.to_wire(Cursor::new(&mut buf)).expect("to_wire failed");
+        assert_eq!(ConfigPage::from_wire(&buf[..]).expect("from_wire failed"), page);
+
+        buf[CONFIG_HEADER_LEN + 7] ^= 1;
+        assert!(ConfigPage::from_wire(&buf[..]).is_err());
+        assert!(ConfigPage::from_wire(&[0xffu8; MAX_CONFIG_LEN][..]).is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_skipped() {
+        // An unknown key with a 2 byte value, then ConsoleBaud.
+        let entries = [0x12, 0x34, 0x00, 0x02, 0xaa, 0xbb,
+                       0x00, 0x01, 0x00, 0x04, 0x00, 0x00, 0x25, 0x80];
+        let mut buf = [0u8; MAX_CONFIG_LEN];
+        {
+            let mut cursor = Cursor::new(&mut buf);
+            cursor.write_be(CONFIG_MAGIC).unwrap();
+            cursor.write_be(1u32).unwrap();
+            cursor.write_be(entries.len() as u16).unwrap();
+            cursor.write_be(compute_checksum(1, &entries)).unwrap();
+            cursor.write_bytes(&entries).unwrap();
+        }
+        let page = ConfigPage::from_wire(&buf[..]).expect("from_wire failed");
+        assert_eq!(page.config.console_baud, 9600);
+        assert_eq!(page.config.heartbeat_interval_ms, BoardConfig::default().heartbeat_interval_ms);
+    }
+}
@@ -0,0 +1,430 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Digest protocol payload.
+//!
+//! The BMC can have the chip hash data that it streams through the mailbox.
+//! A digest session is started with an init request, which returns the id of
+//! the new session. The data follows in update requests of at most one
+//! mailbox each, and a final request returns the digest and ends the session.
+//!
+//! Every update request carries the offset of its data within the message.
+//! The chip rejects chunks that do not continue the message where the last
+//! one ended, so a lost or repeated chunk cannot silently change the digest.
+//! The chip runs one digest session at a time; an init request abandons the
+//! session in progress.
+
+use crate::io::Read;
+use crate::io::Write;
+use crate::protocol::wire::FromWireError;
+use crate::protocol::wire::FromWire;
+use crate::protocol::wire::ToWireError;
+use crate::protocol::wire::ToWire;
+use crate::protocol::wire::WireEnum;
+
+/// The length of the longest digest, in bytes.
+pub const MAX_DIGEST_LEN: usize = 32;
+
+wire_enum! {
+    /// The content type.
+    pub enum ContentType: u8 {
+        /// Request to start a digest session
+        InitRequest = 0x01,
+
+        /// Response to InitRequest
+        InitResponse = 0x02,
+
+        /// Request to hash a chunk of data
+        UpdateRequest = 0x03,
+
+        /// Response to UpdateRequest
+        UpdateResponse = 0x04,
+
+        /// Request to finish a digest session
+        FinalRequest = 0x05,
+
+        /// Response to FinalRequest
+        FinalResponse = 0x06,
+    }
+}
+
+/// A parsed header.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Header {
+    /// The content type following the header.
+    pub content: ContentType,
+}
+
+/// The length of a digest header on the wire, in bytes.
+pub const HEADER_LEN: usize = 1;
+
+impl<'a> FromWire<'a> for Header {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let content_u8 = r.read_be::<u8>()?;
+        let content = ContentType::from_wire_value(content_u8).ok_or(FromWireError::OutOfRange)?;
+        Ok(Self {
+            content,
+        })
+    }
+}
+
+impl ToWire for Header {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.content.to_wire_value())?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A message.
+///
+/// A message is identified by a [`ContentType`]:
+///
+/// [`ContentType`]: enum.ContentType.html
+pub trait Message<'req>: FromWire<'req> + ToWire {
+    /// The unique [`ContentType`] for this `Message`.
+    ///
+    /// [`ContentType`]: enum.ContentType.html
+    const TYPE: ContentType;
+}
+
+// ----------------------------------------------------------------------------
+
+wire_enum! {
+    /// A digest algorithm.
+    pub enum Algorithm: u8 {
+        /// SHA-1
+        Sha1 = 0x01,
+
+        /// SHA-256
+        Sha256 = 0x02,
+    }
+}
+
+impl Algorithm {
+    /// The length of a digest, in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Algorithm::Sha1 => 20,
+            Algorithm::Sha256 => 32,
+        }
+    }
+}
+
+wire_enum! {
+    /// The result of a digest request.
+    pub enum DigestResult: u8 {
+        /// Success
+        Success = 0x00,
+
+        /// Unspecified error
+        Error = 0x01,
+
+        /// The session id does not name the session in progress
+        InvalidSession = 0x02,
+
+        /// The offset is not where the last chunk ended
+        InvalidOffset = 0x03,
+
+        /// The hash engine is in use by another application
+        Busy = 0x04,
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed init request.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct InitRequest {
+    /// The algorithm to hash the data with.
+    pub algorithm: Algorithm,
+}
+
+/// The length of an init request on the wire, in bytes.
+pub const INIT_REQUEST_LEN: usize = 1;
+
+impl Message<'_> for InitRequest {
+    const TYPE: ContentType = ContentType::InitRequest;
+}
+
+impl<'a> FromWire<'a> for InitRequest {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let algorithm_u8 = r.read_be::<u8>()?;
+        let algorithm = Algorithm::from_wire_value(algorithm_u8).ok_or(FromWireError::OutOfRange)?;
+        Ok(Self {
+            algorithm,
+        })
+    }
+}
+
+impl ToWire for InitRequest {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.algorithm.to_wire_value())?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed init response.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct InitResponse {
+    /// The id of the new session. Only valid if the result is Success.
+    pub session_id: u32,
+
+    /// The result of the init request.
+    pub result: DigestResult,
+}
+
+/// The length of an init response on the wire, in bytes.
+pub const INIT_RESPONSE_LEN: usize = 5;
+
+impl Message<'_> for InitResponse {
+    const TYPE: ContentType = ContentType::InitResponse;
+}
+
+impl<'a> FromWire<'a> for InitResponse {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let session_id = r.read_be::<u32>()?;
+        let result_u8 = r.read_be::<u8>()?;
+        let result = DigestResult::from_wire_value(result_u8).ok_or(FromWireError::OutOfRange)?;
+        Ok(Self {
+            session_id,
+            result,
+        })
+    }
+}
+
+impl ToWire for InitResponse {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.session_id)?;
+        w.write_be(self.result.to_wire_value())?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed update request.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct UpdateRequest<'a> {
+    /// The id of the session.
+    pub session_id: u32,
+
+    /// The offset of `data` within the message.
+    pub offset: u32,
+
+    /// The data to hash.
+    pub data: &'a [u8],
+}
+
+/// The length of an update request on the wire, excluding its data, in bytes.
+pub const UPDATE_REQUEST_LEN: usize = 8;
+
+impl<'a> Message<'a> for UpdateRequest<'a> {
+    const TYPE: ContentType = ContentType::UpdateRequest;
+}
+
+impl<'a> FromWire<'a> for UpdateRequest<'a> {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let session_id = r.read_be::<u32>()?;
+        let offset = r.read_be::<u32>()?;
+        let data_len = r.remaining_data();
+        let data = r.read_bytes(data_len)?;
+        Ok(Self {
+            session_id,
+            offset,
+            data,
+        })
+    }
+}
+
+impl ToWire for UpdateRequest<'_> {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.session_id)?;
+        w.write_be(self.offset)?;
+        w.write_bytes(self.data)?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed update response.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct UpdateResponse {
+    /// The id of the session from the request.
+    pub session_id: u32,
+
+    /// The number of bytes hashed in the session so far. On InvalidOffset,
+    /// this is the offset the next chunk must start at.
+    pub hashed_len: u32,
+
+    /// The result of the update request.
+    pub result: DigestResult,
+}
+
+/// The length of an update response on the wire, in bytes.
+pub const UPDATE_RESPONSE_LEN: usize = 9;
+
+impl Message<'_> for UpdateResponse {
+    const TYPE: ContentType = ContentType::UpdateResponse;
+}
+
+impl<'a> FromWire<'a> for UpdateResponse {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let session_id = r.read_be::<u32>()?;
+        let hashed_len = r.read_be::<u32>()?;
+        let result_u8 = r.read_be::<u8>()?;
+        let result = DigestResult::from_wire_value(result_u8).ok_or(FromWireError::OutOfRange)?;
+        Ok(Self {
+            session_id,
+            hashed_len,
+            result,
+        })
+    }
+}
+
+impl ToWire for UpdateResponse {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.session_id)?;
+        w.write_be(self.hashed_len)?;
+        w.write_be(self.result.to_wire_value())?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed final request.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FinalRequest {
+    /// The id of the session.
+    pub session_id: u32,
+}
+
+/// The length of a final request on the wire, in bytes.
+pub const FINAL_REQUEST_LEN: usize = 4;
+
+impl Message<'_> for FinalRequest {
+    const TYPE: ContentType = ContentType::FinalRequest;
+}
+
+impl<'a> FromWire<'a> for FinalRequest {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let session_id = r.read_be::<u32>()?;
+        Ok(Self {
+            session_id,
+        })
+    }
+}
+
+impl ToWire for FinalRequest {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.session_id)?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed final response.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FinalResponse<'a> {
+    /// The id of the session from the request.
+    pub session_id: u32,
+
+    /// The result of the final request.
+    pub result: DigestResult,
+
+    /// The digest of the message. Empty unless the result is Success.
+    pub digest: &'a [u8],
+}
+
+/// The length of a final response on the wire, excluding its digest, in bytes.
+pub const FINAL_RESPONSE_LEN: usize = 6;
+
+impl<'a> Message<'a> for FinalResponse<'a> {
+    const TYPE: ContentType = ContentType::FinalResponse;
+}
+
+impl<'a> FromWire<'a> for FinalResponse<'a> {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let session_id = r.read_be::<u32>()?;
+        let result_u8 = r.read_be::<u8>()?;
+        let result = DigestResult::from_wire_value(result_u8).ok_or(FromWireError::OutOfRange)?;
+        let digest_len = r.read_be::<u8>()? as usize;
+        if digest_len > MAX_DIGEST_LEN {
+            return Err(FromWireError::OutOfRange);
+        }
+        let digest = r.read_bytes(digest_len)?;
+        Ok(Self {
+            session_id,
+            result,
+            digest,
+        })
+    }
+}
+
+impl ToWire for FinalResponse<'_> {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        if self.digest.len() > MAX_DIGEST_LEN {
+            return Err(ToWireError::InvalidData);
+        }
+        w.write_be(self.session_id)?;
+        w.write_be(self.result.to_wire_value())?;
+        w.write_be(self.digest.len() as u8)?;
+        w.write_bytes(self.digest)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::Cursor;
+
+    #[test]
+    fn update_request_round_trip() {
+        let request = UpdateRequest {
+            session_id: 7,
+            offset: 128,
+            data: b"chunk",
+        };
+        let mut buf = [0u8; UPDATE_REQUEST_LEN + 5];
+        request.to_wire(Cursor::new(&mut buf)).expect("to_wire failed");
+        assert_eq!(&buf[..UPDATE_REQUEST_LEN], &[0, 0, 0, 7, 0, 0, 0, 128]);
+        assert_eq!(UpdateRequest::from_wire(&buf[..]).expect("from_wire failed"), request);
+    }
+
+    #[test]
+    fn final_response_round_trip() {
+        let digest = [0x5au8; MAX_DIGEST_LEN];
+        let response = FinalResponse {
+            session_id: 3,
+            result: DigestResult::Success,
+            digest: &digest,
+        };
+        let mut buf = [0u8; FINAL_RESPONSE_LEN + MAX_DIGEST_LEN];
+        response.to_wire(Cursor::new(&mut buf)).expect("to_wire failed");
+        assert_eq!(FinalResponse::from_wire(&buf[..]).expect("from_wire failed"), response);
+
+        // A digest longer than any algorithm's is rejected.
+        buf[5] = MAX_DIGEST_LEN as u8 + 1;
+        assert!(FinalResponse::from_wire(&buf[..]).is_err());
+    }
+}
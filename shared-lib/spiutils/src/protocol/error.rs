@@ -32,6 +32,16 @@ wire_enum! {
 
         /// The content type on the message is not supported.
         ContentTypeNotSupported = 0x02,
+
+        /// The message was not sent through a session, but one is required,
+        /// or it came in a session record, but no session has been
+        /// established. The host must complete a handshake first.
+        SessionRequired = 0x03,
+
+        /// A fragment was lost or the message is too long, or a fragment of
+        /// the response that does not exist was asked for. The message must
+        /// be sent again from its first fragment.
+        BadFragment = 0x04,
     }
 }
 
@@ -130,3 +140,55 @@ impl ToWire for ContentTypeNotSupported {
     }
 }
 
+
+// ----------------------------------------------------------------------------
+
+/// A parsed `SessionRequired` message.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SessionRequired {
+}
+
+/// The length of a `SessionRequired` message on the wire, in bytes.
+pub const SESSION_REQUIRED_LEN: usize = 0;
+
+impl Message<'_> for SessionRequired {
+    const TYPE: ContentType = ContentType::SessionRequired;
+}
+
+impl<'a> FromWire<'a> for SessionRequired {
+    fn from_wire<R: Read<'a>>(mut _r: R) -> Result<Self, FromWireError> {
+        Ok(Self {})
+    }
+}
+
+impl ToWire for SessionRequired {
+    fn to_wire<W: Write>(&self, mut _w: W) -> Result<(), ToWireError> {
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed `BadFragment` message.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BadFragment {
+}
+
+/// The length of a `BadFragment` message on the wire, in bytes.
+pub const BAD_FRAGMENT_LEN: usize = 0;
+
+impl Message<'_> for BadFragment {
+    const TYPE: ContentType = ContentType::BadFragment;
+}
+
+impl<'a> FromWire<'a> for BadFragment {
+    fn from_wire<R: Read<'a>>(mut _r: R) -> Result<Self, FromWireError> {
+        Ok(Self {})
+    }
+}
+
+impl ToWire for BadFragment {
+    fn to_wire<W: Write>(&self, mut _w: W) -> Result<(), ToWireError> {
+        Ok(())
+    }
+}
@@ -143,6 +143,9 @@ wire_enum! {
         /// Similar to FastRead with output on both MISO and MOSI.
         FastReadDualOutput = 0x3b,
 
+        /// Similar to FastRead with output on all four data lines.
+        FastReadQuadOutput = 0x6b,
+
         ////////////////////////////////////////////////////////////
         // Address mode commands
 
@@ -169,6 +172,7 @@ impl<'a> OpCode {
             Self::FastRead => true,
             Self::FastRead4B => true,
             Self::FastReadDualOutput => true,
+            Self::FastReadQuadOutput => true,
             _ => false,
         }
     }
@@ -179,6 +183,7 @@ impl<'a> OpCode {
             Self::FastRead => true,
             Self::FastRead4B => true,
             Self::FastReadDualOutput => true,
+            Self::FastReadQuadOutput => true,
             _ => false,
         }
     }
@@ -191,6 +196,31 @@ impl<'a> OpCode {
         }
     }
 
+    /// Returns true iff the OpCode reads data from the flash array.
+    pub fn is_read(&self) -> bool {
+        match self {
+            Self::NormalRead => true,
+            Self::FastRead => true,
+            Self::FastRead4B => true,
+            Self::FastReadDualOutput => true,
+            Self::FastReadQuadOutput => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true iff the OpCode modifies data in the flash array.
+    pub fn is_write(&self) -> bool {
+        match self {
+            Self::SectorErase => true,
+            Self::BlockErase32KB => true,
+            Self::BlockErase64KB => true,
+            Self::ChipErase => true,
+            Self::ChipErase2 => true,
+            Self::PageProgram => true,
+            _ => false,
+        }
+    }
+
     /// Returns true iff the OpCode requires for the BUSY bit to clear.
     pub fn wait_busy_clear(&self) -> bool {
         match self {
@@ -207,6 +237,23 @@ impl<'a> OpCode {
     }
 }
 
+/// Status register bit that is set while a program or erase is in progress
+/// (BUSY).
+pub const STATUS_WIP: u8 = 1 << 0;
+
+/// Status register write enable latch (WEL).
+pub const STATUS_WEL: u8 = 1 << 1;
+
+/// Status register block protect bits BP0 to BP2.
+pub const STATUS_BP_MASK: u8 = 0b111 << STATUS_BP_SHIFT;
+
+/// Position of BP0 in the status register.
+pub const STATUS_BP_SHIFT: u8 = 2;
+
+/// Status register bit that moves the protected blocks from the top to the
+/// bottom of the flash (TB).
+pub const STATUS_TB: u8 = 1 << 5;
+
 const DUMMY_BYTE_VALUE: u8 = 0xff;
 
 /// Error used when address cannot be converted.
@@ -0,0 +1,155 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Info block.
+//!
+//! The kernel keeps basic identity information in the last page of the SPI
+//! device RAM, where the SPI host can read it at `ram_virtual_base +
+//! INFO_BLOCK_OFFSET` without involving the app.
+//!
+//! The kernel rewrites the block periodically while the host may be reading
+//! it. The block ends in a CRC-32 over all preceding bytes, and a block that
+//! was read mid-update fails the check and does not parse; read it again.
+
+use crate::compat::firmware::BuildInfo;
+use crate::compat::firmware::BUILD_INFO_LEN;
+use crate::io::Cursor;
+use crate::io::Read;
+use crate::io::Write;
+use crate::protocol::config::Crc32;
+use crate::protocol::wire::FromWireError;
+use crate::protocol::wire::FromWire;
+use crate::protocol::wire::ToWireError;
+use crate::protocol::wire::ToWire;
+
+/// The offset of the info block from the start of the SPI device RAM.
+pub const INFO_BLOCK_OFFSET: u32 = 0x600;
+
+/// The space reserved for the info block, in bytes.
+pub const INFO_BLOCK_SIZE: usize = 512;
+
+/// Marks a valid info block ("INFO").
+pub const INFO_BLOCK_MAGIC: u32 = 0x494e464f;
+
+/// The layout version of the info block.
+pub const INFO_BLOCK_VERSION: u8 = 2;
+
+/// The length of an info block on the wire, in bytes.
+pub const INFO_BLOCK_LEN: usize = INFO_BLOCK_BODY_LEN + 4;
+
+/// The length of the fields covered by the CRC, in bytes.
+const INFO_BLOCK_BODY_LEN: usize = 4 + 1 + 4 + 8 + 2 * BUILD_INFO_LEN;
+
+/// A parsed info block.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct InfoBlock {
+    /// Incremented every time the kernel refreshes the block.
+    pub heartbeat: u32,
+
+    /// The device ID from the fuses.
+    pub device_id: u64,
+
+    /// Build information of the active RO segment.
+    pub ro_version: BuildInfo,
+
+    /// Build information of the active RW segment.
+    pub rw_version: BuildInfo,
+}
+
+impl<'a> FromWire<'a> for InfoBlock {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let mut body = r.read_bytes(INFO_BLOCK_BODY_LEN)?;
+        if r.read_be::<u32>()? != Crc32::init().add(body).get() {
+            return Err(FromWireError::OutOfRange);
+        }
+        let magic = body.read_be::<u32>()?;
+        let version = body.read_be::<u8>()?;
+        if magic != INFO_BLOCK_MAGIC || version != INFO_BLOCK_VERSION {
+            return Err(FromWireError::OutOfRange);
+        }
+        let heartbeat = body.read_be::<u32>()?;
+        let device_id = body.read_be::<u64>()?;
+        let ro_version = BuildInfo::from_wire(&mut body)?;
+        let rw_version = BuildInfo::from_wire(&mut body)?;
+        Ok(Self {
+            heartbeat,
+            device_id,
+            ro_version,
+            rw_version,
+        })
+    }
+}
+
+impl ToWire for InfoBlock {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        let mut body = [0u8; INFO_BLOCK_BODY_LEN];
+        {
+            let mut cursor = Cursor::new(&mut body);
+            cursor.write_be(INFO_BLOCK_MAGIC)?;
+            cursor.write_be(INFO_BLOCK_VERSION)?;
+            cursor.write_be(self.heartbeat)?;
+            cursor.write_be(self.device_id)?;
+            self.ro_version.to_wire(&mut cursor)?;
+            self.rw_version.to_wire(&mut cursor)?;
+        }
+        w.write_bytes(&body)?;
+        w.write_be(Crc32::init().add(&body).get())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info_block(heartbeat: u32) -> InfoBlock {
+        let version = BuildInfo {
+            epoch: 1,
+            major: 2,
+            minor: 3,
+            timestamp: 4,
+        };
+        InfoBlock {
+            heartbeat,
+            device_id: 0x0123456789abcdef,
+            ro_version: version,
+            rw_version: version,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0u8; INFO_BLOCK_LEN];
+        info_block(7).to_wire(Cursor::new(&mut buf)).unwrap();
+        assert_eq!(InfoBlock::from_wire(&buf[..]).unwrap(), info_block(7));
+    }
+
+    #[test]
+    fn rejects_torn_reads() {
+        let mut old = [0u8; INFO_BLOCK_LEN];
+        let mut new = [0u8; INFO_BLOCK_LEN];
+        info_block(7).to_wire(Cursor::new(&mut old)).unwrap();
+        info_block(8).to_wire(Cursor::new(&mut new)).unwrap();
+        // The kernel rewrites the block front to back.
+        for split in 1..INFO_BLOCK_LEN {
+            let mut torn = old;
+            torn[..split].copy_from_slice(&new[..split]);
+            if torn != old && torn != new {
+                assert!(InfoBlock::from_wire(&torn[..]).is_err(), "split {}", split);
+            }
+        }
+    }
+}
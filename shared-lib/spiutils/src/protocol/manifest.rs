@@ -0,0 +1,239 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Signed firmware manifest.
+//!
+//! A manifest lists the flash segments of a firmware image with their
+//! SHA-256 digests, and is signed with ECDSA P-256:
+//!
+//! ```text
+//! magic: u32 | version: u8 | segment count: u8 | key ID: u32
+//! address: u32 | length: u32 | SHA-256: [u8; 32]    (repeated)
+//! signature r: [u8; 32] | signature s: [u8; 32]
+//! ```
+//!
+//! The signature is over the SHA-256 of everything before it. The key ID is
+//! the first four bytes of the SHA-256 of the public key's x || y, so that a
+//! verifier holding several keys knows which one to use.
+
+use crate::io::Read;
+use crate::io::Write;
+use crate::protocol::wire::FromWireError;
+use crate::protocol::wire::FromWire;
+use crate::protocol::wire::ToWireError;
+use crate::protocol::wire::ToWire;
+
+use ecc::p256::PublicKey;
+use ecc::p256::Signature;
+use ecc::p256::SCALAR_LEN;
+use ecc::sha256;
+use ecc::sha256::Sha256;
+use ecc::sha256::DIGEST_LEN;
+
+/// Marks a manifest ("MNFT").
+pub const MANIFEST_MAGIC: u32 = 0x4d4e4654;
+
+/// The layout version of the manifest.
+pub const MANIFEST_VERSION: u8 = 1;
+
+/// The maximum number of segments in a manifest.
+pub const MAX_MANIFEST_SEGMENTS: usize = 8;
+
+/// The length of the manifest header, in bytes.
+pub const MANIFEST_HEADER_LEN: usize = 4 + 1 + 1 + 4;
+
+/// The length of a segment entry, in bytes.
+pub const MANIFEST_SEGMENT_LEN: usize = 4 + 4 + DIGEST_LEN;
+
+/// The length of the signature, in bytes.
+pub const MANIFEST_SIGNATURE_LEN: usize = 2 * SCALAR_LEN;
+
+/// The maximum length of an encoded manifest, in bytes.
+pub const MAX_MANIFEST_LEN: usize =
+    MANIFEST_HEADER_LEN + MAX_MANIFEST_SEGMENTS * MANIFEST_SEGMENT_LEN + MANIFEST_SIGNATURE_LEN;
+
+/// A flash segment covered by a manifest.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct ManifestSegment {
+    /// The flash address of the segment.
+    pub address: u32,
+
+    /// The length of the segment, in bytes.
+    pub length: u32,
+
+    /// The SHA-256 of the segment contents.
+    pub digest: [u8; DIGEST_LEN],
+}
+
+impl ManifestSegment {
+    /// Describes the segment at `address` holding `data`.
+    pub fn new(address: u32, data: &[u8]) -> Self {
+        Self {
+            address,
+            length: data.len() as u32,
+            digest: sha256::digest(data),
+        }
+    }
+
+    /// Returns true if `data` is the contents of this segment.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() == self.length as usize && sha256::digest(data) == self.digest
+    }
+}
+
+/// A parsed manifest.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Manifest {
+    /// Identifies the signing key; see `key_id`.
+    pub key_id: u32,
+
+    segment_count: usize,
+    segments: [ManifestSegment; MAX_MANIFEST_SEGMENTS],
+
+    /// The signature over `signed_digest()`.
+    pub signature: Signature,
+}
+
+/// Returns the key ID of `public_key`.
+pub fn key_id(public_key: &PublicKey) -> u32 {
+    let mut sha = Sha256::new();
+    sha.update(&public_key.x);
+    sha.update(&public_key.y);
+    let digest = sha.finalize();
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+impl Manifest {
+    /// Creates an unsigned manifest for `segments`. Returns None if there are
+    /// more than `MAX_MANIFEST_SEGMENTS`.
+    pub fn new(key_id: u32, segments: &[ManifestSegment]) -> Option<Self> {
+        if segments.len() > MAX_MANIFEST_SEGMENTS {
+            return None;
+        }
+        let mut manifest = Self {
+            key_id,
+            segment_count: segments.len(),
+            segments: [ManifestSegment::default(); MAX_MANIFEST_SEGMENTS],
+            signature: Signature { r: [0; SCALAR_LEN], s: [0; SCALAR_LEN] },
+        };
+        manifest.segments[..segments.len()].copy_from_slice(segments);
+        Some(manifest)
+    }
+
+    /// The segments covered by the manifest.
+    pub fn segments(&self) -> &[ManifestSegment] {
+        &self.segments[..self.segment_count]
+    }
+
+    /// The digest that the signature is computed over.
+    pub fn signed_digest(&self) -> [u8; DIGEST_LEN] {
+        let mut sha = Sha256::new();
+        sha.update(&MANIFEST_MAGIC.to_be_bytes());
+        sha.update(&[MANIFEST_VERSION, self.segment_count as u8]);
+        sha.update(&self.key_id.to_be_bytes());
+        for segment in self.segments() {
+            sha.update(&segment.address.to_be_bytes());
+            sha.update(&segment.length.to_be_bytes());
+            sha.update(&segment.digest);
+        }
+        sha.finalize()
+    }
+
+    /// Returns true if the manifest was signed by the key `public_key`.
+    pub fn verify(&self, public_key: &PublicKey) -> bool {
+        self.key_id == key_id(public_key)
+            && public_key.verify(&self.signed_digest(), &self.signature)
+    }
+}
+
+impl<'a> FromWire<'a> for Manifest {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        if r.read_be::<u32>()? != MANIFEST_MAGIC || r.read_be::<u8>()? != MANIFEST_VERSION {
+            return Err(FromWireError::OutOfRange);
+        }
+        let segment_count = r.read_be::<u8>()? as usize;
+        let key_id = r.read_be::<u32>()?;
+        if segment_count > MAX_MANIFEST_SEGMENTS {
+            return Err(FromWireError::OutOfRange);
+        }
+        let mut segments = [ManifestSegment::default(); MAX_MANIFEST_SEGMENTS];
+        for segment in segments[..segment_count].iter_mut() {
+            segment.address = r.read_be::<u32>()?;
+            segment.length = r.read_be::<u32>()?;
+            segment.digest.copy_from_slice(r.read_bytes(DIGEST_LEN)?);
+        }
+        let mut signature = Signature { r: [0; SCALAR_LEN], s: [0; SCALAR_LEN] };
+        signature.r.copy_from_slice(r.read_bytes(SCALAR_LEN)?);
+        signature.s.copy_from_slice(r.read_bytes(SCALAR_LEN)?);
+        Ok(Self {
+            key_id,
+            segment_count,
+            segments,
+            signature,
+        })
+    }
+}
+
+impl ToWire for Manifest {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(MANIFEST_MAGIC)?;
+        w.write_be(MANIFEST_VERSION)?;
+        w.write_be(self.segment_count as u8)?;
+        w.write_be(self.key_id)?;
+        for segment in self.segments() {
+            w.write_be(segment.address)?;
+            w.write_be(segment.length)?;
+            w.write_bytes(&segment.digest)?;
+        }
+        w.write_bytes(&self.signature.r)?;
+        w.write_bytes(&self.signature.s)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::Cursor;
+    use ecc::p256::PrivateKey;
+
+    #[test]
+    fn sign_and_verify() {
+        let private_key = PrivateKey::from_bytes(&[0x11; SCALAR_LEN]).unwrap();
+        let public_key = private_key.public_key();
+        let segments = [ManifestSegment::new(0x44000, b"kernel"),
+                        ManifestSegment::new(0x76000, b"apps")];
+        let mut manifest = Manifest::new(key_id(&public_key), &segments).unwrap();
+        manifest.signature = private_key.sign(&manifest.signed_digest(), &[0x22; SCALAR_LEN])
+            .unwrap();
+        assert!(manifest.verify(&public_key));
+        assert!(manifest.segments()[1].matches(b"apps"));
+        assert!(!manifest.segments()[1].matches(b"appz"));
+
+        let mut buf = [0u8; MAX_MANIFEST_LEN];
+        let mut cursor = Cursor::new(&mut buf);
+        manifest.to_wire(&mut cursor).expect("to_wire failed");
+        let len = cursor.consumed_len();
+        assert_eq!(len, MANIFEST_HEADER_LEN + 2 * MANIFEST_SEGMENT_LEN + MANIFEST_SIGNATURE_LEN);
+        assert_eq!(Manifest::from_wire(&buf[..len]).expect("from_wire failed"), manifest);
+
+        // Changing a segment invalidates the signature.
+        buf[MANIFEST_HEADER_LEN + 3] ^= 1;
+        assert!(!Manifest::from_wire(&buf[..len]).unwrap().verify(&public_key));
+        assert!(Manifest::new(0, &[ManifestSegment::default(); MAX_MANIFEST_SEGMENTS + 1])
+            .is_none());
+    }
+}
@@ -19,7 +19,14 @@
 #[macro_use]
 pub mod wire;
 
+pub mod config;
+pub mod digest;
 pub mod error;
 pub mod firmware;
 pub mod flash;
+pub mod info_block;
+pub mod manifest;
 pub mod payload;
+pub mod session;
+pub mod sfdp;
+pub mod time;
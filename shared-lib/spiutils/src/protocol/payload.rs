@@ -62,6 +62,43 @@ impl Crc8 {
     }
 }
 
+/// Data for CRC16 implementation.
+struct Crc16 {
+    crc: u16,
+}
+
+/// The CRC16 implementation.
+impl Crc16 {
+    /// Initialize CRC16 data to 0xffff.
+    pub fn init() -> Self {
+        Self {
+            crc: 0xffff,
+        }
+    }
+
+    /// Get the calculated CRC16 checksum.
+    pub fn get(&self) -> u16 {
+        self.crc
+    }
+
+    /// Adds the specified data to the CRC16 checksum.
+    /// CRC-16/CCITT-FALSE, uses x^16+x^12+x^5+1 polynomial.
+    pub fn add(&mut self, data: &[u8]) -> &mut Self {
+        for byte in data {
+            self.crc ^= (*byte as u16) << 8;
+            for _ in 0..8 {
+                if self.crc & 0x8000 != 0 {
+                    self.crc = self.crc << 1 ^ 0x1021;
+                } else {
+                    self.crc <<= 1;
+                }
+            }
+        }
+
+        self
+    }
+}
+
 /// Compute the checksum of the given header and payload buffer.
 pub fn compute_checksum(header: &Header, payload: &[u8]) -> u8 {
     Crc8::init()
@@ -71,6 +108,16 @@ pub fn compute_checksum(header: &Header, payload: &[u8]) -> u8 {
         .get()
 }
 
+/// Compute the header CRC of the given header, covering all header fields
+/// before it.
+pub fn compute_header_crc(header: &Header) -> u16 {
+    Crc16::init()
+        .add(&[header.content.to_wire_value() | HEADER_CRC_FLAG])
+        .add(&header.content_len.to_be_bytes())
+        .add(&[header.checksum])
+        .get()
+}
+
 wire_enum! {
     /// The content type.
     pub enum ContentType: u8 {
@@ -82,6 +129,18 @@ wire_enum! {
 
         /// Firmware
         Firmware = 0x02,
+
+        /// Time
+        Time = 0x03,
+
+        /// Session
+        Session = 0x04,
+
+        /// Digest
+        Digest = 0x05,
+
+        /// Fragment of a larger payload
+        Fragment = 0x06,
     }
 }
 
@@ -97,30 +156,419 @@ pub struct Header {
     /// A checksum including the header (excluding this field)
     // and the content following the header.
     pub checksum: u8,
+
+    /// A CRC16 of the header fields above, if the sender included one.
+    /// This lets the receiver reject a corrupted `content_len` before
+    /// reading any content.
+    pub header_crc: Option<u16>,
 }
 
-/// The length of a payload header on the wire, in bytes.
+/// The length of a payload header without header CRC on the wire, in bytes.
 pub const HEADER_LEN: usize = 4;
 
+/// The length of the optional header CRC on the wire, in bytes.
+pub const HEADER_CRC_LEN: usize = 2;
+
+/// Set in the content type byte on the wire if the header CRC follows the
+/// checksum.
+pub const HEADER_CRC_FLAG: u8 = 0x80;
+
+/// Set in the capabilities of the Google SFDP parameter table if the device
+/// accepts payloads with header CRC and answers them with one.
+pub const CAPABILITY_HEADER_CRC: u32 = 1 << 0;
+
+/// Set in the capabilities of the Google SFDP parameter table if the device
+/// reassembles fragmented requests and sends responses too large for the
+/// mailbox in fragments.
+pub const CAPABILITY_FRAGMENTS: u32 = 1 << 1;
+
+impl Header {
+    /// The length of this header on the wire, in bytes.
+    pub fn wire_len(&self) -> usize {
+        match self.header_crc {
+            Some(_) => HEADER_LEN + HEADER_CRC_LEN,
+            None => HEADER_LEN,
+        }
+    }
+}
+
+/// Returns whether the payload at the start of `data` claims to have a
+/// header CRC, without parsing or checking it.
+pub fn has_header_crc(data: &[u8]) -> bool {
+    data.first().is_some_and(|content| content & HEADER_CRC_FLAG != 0)
+}
+
 impl<'a> FromWire<'a> for Header {
     fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
         let content_u8 = r.read_be::<u8>()?;
-        let content = ContentType::from_wire_value(content_u8).ok_or(FromWireError::OutOfRange)?;
+        let content = ContentType::from_wire_value(content_u8 & !HEADER_CRC_FLAG)
+            .ok_or(FromWireError::OutOfRange)?;
         let content_len = r.read_be::<u16>()?;
         let checksum = r.read_be::<u8>()?;
+        let header_crc = if content_u8 & HEADER_CRC_FLAG != 0 {
+            Some(r.read_be::<u16>()?)
+        } else {
+            None
+        };
         Ok(Self {
             content,
             content_len,
             checksum,
+            header_crc,
         })
     }
 }
 
 impl ToWire for Header {
     fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        w.write_be(self.content.to_wire_value())?;
-        w.write_be(self.content_len)?;
-        w.write_be(self.checksum)?;
+        match self.header_crc {
+            Some(header_crc) => {
+                w.write_be(self.content.to_wire_value() | HEADER_CRC_FLAG)?;
+                w.write_be(self.content_len)?;
+                w.write_be(self.checksum)?;
+                w.write_be(header_crc)?;
+            }
+            None => {
+                w.write_be(self.content.to_wire_value())?;
+                w.write_be(self.content_len)?;
+                w.write_be(self.checksum)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why `parse_payload` rejected a payload.
+#[derive(Clone, Copy, Debug)]
+pub enum PayloadError {
+    /// The header could not be parsed.
+    Header(FromWireError),
+
+    /// The header CRC does not match the header. Nothing in the header,
+    /// including the content length, can be trusted.
+    BadHeaderCrc,
+
+    /// The header claims more content than follows it.
+    Truncated,
+
+    /// The checksum does not match the header and content.
+    BadChecksum,
+}
+
+/// Parses the payload at the start of `data` and checks its header CRC, if
+/// any, and its checksum.
+/// Returns the header and the content; bytes after the content are ignored.
+pub fn parse_payload(mut data: &[u8]) -> Result<(Header, &[u8]), PayloadError> {
+    let header = Header::from_wire(&mut data).map_err(PayloadError::Header)?;
+    if let Some(header_crc) = header.header_crc {
+        if header_crc != compute_header_crc(&header) {
+            return Err(PayloadError::BadHeaderCrc);
+        }
+    }
+    let content = data.get(..header.content_len as usize).ok_or(PayloadError::Truncated)?;
+    if header.checksum != compute_checksum(&header, content) {
+        return Err(PayloadError::BadChecksum);
+    }
+    Ok((header, content))
+}
+
+/// The length of a fragment header on the wire, in bytes.
+pub const FRAGMENT_HEADER_LEN: usize = 2;
+
+/// The maximum number of fragments of a message.
+pub const MAX_FRAGMENTS: usize = 0x100;
+
+/// Set in the flags of a fragment header if more fragments follow.
+const FRAGMENT_FLAG_MORE: u8 = 0x01;
+
+/// A parsed fragment header.
+///
+/// A message too large for one payload, itself a complete payload, is split
+/// into fragments. Each is sent as the content of a `Fragment` payload,
+/// after a fragment header, and the receiver reassembles the message with a
+/// `Reassembler`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FragmentHeader {
+    /// The position of the fragment in the message, starting at 0.
+    pub sequence: u8,
+
+    /// Whether more fragments of the message follow this one.
+    pub more_fragments: bool,
+}
+
+impl<'a> FromWire<'a> for FragmentHeader {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let sequence = r.read_be::<u8>()?;
+        let flags = r.read_be::<u8>()?;
+        if flags & !FRAGMENT_FLAG_MORE != 0 {
+            return Err(FromWireError::OutOfRange);
+        }
+        Ok(Self {
+            sequence,
+            more_fragments: flags & FRAGMENT_FLAG_MORE != 0,
+        })
+    }
+}
+
+impl ToWire for FragmentHeader {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.sequence)?;
+        w.write_be(if self.more_fragments { FRAGMENT_FLAG_MORE } else { 0 })?;
         Ok(())
     }
 }
+
+/// Returns the header and the data of fragment `sequence` of `message`,
+/// split into fragments of up to `max_data_len` bytes. Returns None if the
+/// message has no such fragment or needs more than MAX_FRAGMENTS.
+pub fn fragment(message: &[u8], max_data_len: usize, sequence: u8)
+    -> Option<(FragmentHeader, &[u8])> {
+    if max_data_len == 0 {
+        return None;
+    }
+    let count = core::cmp::max(1, message.len().div_ceil(max_data_len));
+    if count > MAX_FRAGMENTS || sequence as usize >= count {
+        return None;
+    }
+    let start = sequence as usize * max_data_len;
+    let end = core::cmp::min(start + max_data_len, message.len());
+    let header = FragmentHeader {
+        sequence,
+        more_fragments: sequence as usize + 1 < count,
+    };
+    Some((header, &message[start..end]))
+}
+
+/// Why `Reassembler::add` dropped the message in progress.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ReassemblyError {
+    /// The fragment is not the one expected next. A fragment was lost.
+    OutOfOrder,
+
+    /// The message is longer than the reassembly buffer.
+    TooLong,
+}
+
+/// Reassembles a message of up to `N` bytes from its fragments.
+pub struct Reassembler<const N: usize> {
+    buf: [u8; N],
+
+    // Length of the message so far.
+    len: usize,
+
+    // The sequence number of the fragment expected next, if a message is
+    // in progress.
+    next_sequence: Option<u8>,
+}
+
+impl<const N: usize> Default for Reassembler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Reassembler<N> {
+    /// Creates a reassembler for messages of up to `N` bytes.
+    pub const fn new() -> Self {
+        Reassembler {
+            buf: [0; N],
+            len: 0,
+            next_sequence: None,
+        }
+    }
+
+    /// Adds the fragment with `header` and `data`. Returns the message once
+    /// its last fragment was added.
+    ///
+    /// Fragment 0 always starts a new message, so a sender recovers from an
+    /// error by sending the message again. On error, the message in progress
+    /// is dropped.
+    pub fn add(&mut self, header: &FragmentHeader, data: &[u8])
+        -> Result<Option<&[u8]>, ReassemblyError> {
+        if header.sequence == 0 {
+            self.len = 0;
+        } else if self.next_sequence != Some(header.sequence) {
+            self.next_sequence = None;
+            return Err(ReassemblyError::OutOfOrder);
+        }
+        self.next_sequence = None;
+
+        let end = self.len + data.len();
+        if end > N || (header.more_fragments && header.sequence == u8::MAX) {
+            return Err(ReassemblyError::TooLong);
+        }
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+
+        if header.more_fragments {
+            self.next_sequence = Some(header.sequence + 1);
+            return Ok(None);
+        }
+        Ok(Some(&self.buf[..self.len]))
+    }
+
+    /// Whether a message is in progress.
+    pub fn is_in_progress(&self) -> bool {
+        self.next_sequence.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::io::Cursor;
+
+    const CONTENT: &[u8] = b"manticore";
+
+    fn header(with_header_crc: bool) -> Header {
+        let mut header = Header {
+            content: ContentType::Manticore,
+            content_len: CONTENT.len() as u16,
+            checksum: 0,
+            header_crc: None,
+        };
+        header.checksum = compute_checksum(&header, CONTENT);
+        if with_header_crc {
+            header.header_crc = Some(compute_header_crc(&header));
+        }
+        header
+    }
+
+    fn to_payload(header: &Header, buf: &mut [u8]) -> usize {
+        let mut cursor = Cursor::new(buf);
+        header.to_wire(&mut cursor).unwrap();
+        cursor.write_bytes(CONTENT).unwrap();
+        cursor.consumed_len()
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(Crc16::init().add(b"123456789").get(), 0x29b1);
+    }
+
+    #[test]
+    fn round_trip_with_and_without_header_crc() {
+        for &with_header_crc in &[false, true] {
+            let mut buf = [0u8; 32];
+            let len = to_payload(&header(with_header_crc), &mut buf);
+            assert_eq!(len, header(with_header_crc).wire_len() + CONTENT.len());
+            assert_eq!(has_header_crc(&buf), with_header_crc);
+
+            let (parsed, content) = parse_payload(&buf[..len]).unwrap();
+            assert_eq!(parsed, header(with_header_crc));
+            assert_eq!(content, CONTENT);
+        }
+    }
+
+    #[test]
+    fn header_crc_catches_corrupted_length() {
+        let mut buf = [0u8; 32];
+        to_payload(&header(true), &mut buf);
+        // Claim more content than the payload has. Without the header CRC
+        // this is only noticed after reading past the end of the content.
+        buf[2] ^= 0x10;
+        assert!(matches!(parse_payload(&buf), Err(PayloadError::BadHeaderCrc)));
+    }
+
+    #[test]
+    fn any_corrupted_header_bit_is_rejected() {
+        let mut buf = [0u8; 32];
+        let len = to_payload(&header(true), &mut buf);
+        for bit in 0..(HEADER_LEN + HEADER_CRC_LEN) * 8 {
+            let mut corrupted = buf;
+            corrupted[bit / 8] ^= 1 << (bit % 8);
+            assert!(parse_payload(&corrupted[..len]).is_err(), "bit {}", bit);
+        }
+    }
+
+    #[test]
+    fn corrupted_content_is_rejected() {
+        let mut buf = [0u8; 32];
+        let len = to_payload(&header(true), &mut buf);
+        buf[len - 1] ^= 0x01;
+        assert!(matches!(parse_payload(&buf[..len]), Err(PayloadError::BadChecksum)));
+    }
+
+    #[test]
+    fn fragment_header_round_trip() {
+        let header = FragmentHeader { sequence: 3, more_fragments: true };
+        let mut buf = [0u8; FRAGMENT_HEADER_LEN];
+        header.to_wire(Cursor::new(&mut buf)).unwrap();
+        assert_eq!(buf, [0x03, 0x01]);
+        assert_eq!(FragmentHeader::from_wire(&mut &buf[..]).unwrap(), header);
+        assert!(FragmentHeader::from_wire(&mut &[0x03, 0x02][..]).is_err());
+    }
+
+    #[test]
+    fn fragment_splits_message() {
+        let message = [0x5a; 10];
+        let (header, data) = fragment(&message, 4, 0).unwrap();
+        assert_eq!(header, FragmentHeader { sequence: 0, more_fragments: true });
+        assert_eq!(data.len(), 4);
+        let (header, data) = fragment(&message, 4, 2).unwrap();
+        assert_eq!(header, FragmentHeader { sequence: 2, more_fragments: false });
+        assert_eq!(data.len(), 2);
+        assert_eq!(fragment(&message, 4, 3), None);
+        assert_eq!(fragment(&message, 0, 0), None);
+
+        // A message that fits one fragment is still sent as fragment 0.
+        assert_eq!(fragment(&message, 10, 0),
+                   Some((FragmentHeader { sequence: 0, more_fragments: false }, &message[..])));
+
+        // More than MAX_FRAGMENTS fragments cannot be numbered.
+        assert_eq!(fragment(&[0; MAX_FRAGMENTS + 1], 1, 0), None);
+        assert!(fragment(&[0; MAX_FRAGMENTS], 1, 0xff).is_some());
+    }
+
+    #[test]
+    fn reassembles_fragments() {
+        let message: std::vec::Vec<u8> = (0..100).collect();
+        let mut reassembler = Reassembler::<128>::new();
+        for sequence in 0..7 {
+            let (header, data) = fragment(&message, 16, sequence).unwrap();
+            let result = reassembler.add(&header, data).unwrap();
+            assert_eq!(result.is_none(), sequence < 6);
+            if let Some(reassembled) = result {
+                assert_eq!(reassembled, &message[..]);
+            }
+        }
+        assert!(!reassembler.is_in_progress());
+    }
+
+    #[test]
+    fn lost_fragment_drops_message() {
+        let message = [0x5a; 48];
+        let mut reassembler = Reassembler::<64>::new();
+        let (header, data) = fragment(&message, 16, 0).unwrap();
+        assert_eq!(reassembler.add(&header, data), Ok(None));
+        let (header, data) = fragment(&message, 16, 2).unwrap();
+        assert_eq!(reassembler.add(&header, data), Err(ReassemblyError::OutOfOrder));
+        assert!(!reassembler.is_in_progress());
+
+        // The message in progress is gone, so fragment 1 is out of order, too.
+        let (header, data) = fragment(&message, 16, 1).unwrap();
+        assert_eq!(reassembler.add(&header, data), Err(ReassemblyError::OutOfOrder));
+
+        // Sending the message again from fragment 0 recovers.
+        for sequence in 0..3 {
+            let (header, data) = fragment(&message, 16, sequence).unwrap();
+            let result = reassembler.add(&header, data).unwrap();
+            assert_eq!(result.map(|reassembled| reassembled.len()),
+                       if sequence == 2 { Some(48) } else { None });
+        }
+    }
+
+    #[test]
+    fn message_too_long() {
+        let message = [0x5a; 48];
+        let mut reassembler = Reassembler::<40>::new();
+        for sequence in 0..2 {
+            let (header, data) = fragment(&message, 16, sequence).unwrap();
+            assert_eq!(reassembler.add(&header, data), Ok(None));
+        }
+        let (header, data) = fragment(&message, 16, 2).unwrap();
+        assert_eq!(reassembler.add(&header, data), Err(ReassemblyError::TooLong));
+        assert!(!reassembler.is_in_progress());
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Session protocol payload.
+//!
+//! A session protects mailbox traffic between the BMC and the chip. The
+//! host starts a session with a handshake that exchanges ephemeral X25519
+//! public keys and nonces. Afterwards, every mailbox payload is sent as a
+//! record: the complete inner payload (payload header and content) is
+//! encrypted and authenticated with AES-GCM under keys derived with HKDF.
+//! The chip signs its handshake response with its identity key, so that
+//! hosts can tell they are talking to the chip. See [`crate::session`] for
+//! the key schedule and the signed transcript.
+
+use crate::io::Read;
+use crate::io::Write;
+use crate::protocol::wire::FromWireError;
+use crate::protocol::wire::FromWire;
+use crate::protocol::wire::ToWireError;
+use crate::protocol::wire::ToWire;
+use crate::protocol::wire::WireEnum;
+
+/// The length of an X25519 public key, in bytes.
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// The length of a handshake nonce, in bytes.
+pub const NONCE_LEN: usize = 16;
+
+/// The length of the authentication tag at the end of a record, in bytes.
+pub const TAG_LEN: usize = 16;
+
+/// The length of the chip's P-256 identity public key (x, y), in bytes.
+pub const IDENTITY_KEY_LEN: usize = 64;
+
+/// The length of a P-256 ECDSA signature (r, s), in bytes.
+pub const SIGNATURE_LEN: usize = 64;
+
+wire_enum! {
+    /// The content type.
+    pub enum ContentType: u8 {
+        /// Request to establish a new session
+        HandshakeRequest = 0x01,
+
+        /// Response to HandshakeRequest
+        HandshakeResponse = 0x02,
+
+        /// A protected payload
+        Record = 0x03,
+    }
+}
+
+/// A parsed header.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Header {
+    /// The content type following the header.
+    pub content: ContentType,
+}
+
+/// The length of a session header on the wire, in bytes.
+pub const HEADER_LEN: usize = 1;
+
+impl<'a> FromWire<'a> for Header {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let content_u8 = r.read_be::<u8>()?;
+        let content = ContentType::from_wire_value(content_u8).ok_or(FromWireError::OutOfRange)?;
+        Ok(Self {
+            content,
+        })
+    }
+}
+
+impl ToWire for Header {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.content.to_wire_value())?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A message.
+///
+/// A message is identified by a [`ContentType`]:
+///
+/// [`ContentType`]: enum.ContentType.html
+pub trait Message<'req>: FromWire<'req> + ToWire {
+    /// The unique [`ContentType`] for this `Message`.
+    ///
+    /// [`ContentType`]: enum.ContentType.html
+    const TYPE: ContentType;
+}
+
+fn read_array<'a, R: Read<'a>>(r: &mut R, out: &mut [u8]) -> Result<(), FromWireError> {
+    let bytes = r.read_bytes(out.len())?;
+    out.copy_from_slice(bytes);
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed handshake request.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HandshakeRequest {
+    /// The host's ephemeral X25519 public key.
+    pub public_key: [u8; PUBLIC_KEY_LEN],
+
+    /// A fresh random nonce chosen by the host.
+    pub nonce: [u8; NONCE_LEN],
+}
+
+/// The length of a handshake request on the wire, in bytes.
+pub const HANDSHAKE_REQUEST_LEN: usize = PUBLIC_KEY_LEN + NONCE_LEN;
+
+impl Message<'_> for HandshakeRequest {
+    const TYPE: ContentType = ContentType::HandshakeRequest;
+}
+
+impl<'a> FromWire<'a> for HandshakeRequest {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let mut public_key = [0u8; PUBLIC_KEY_LEN];
+        read_array(&mut r, &mut public_key)?;
+        let mut nonce = [0u8; NONCE_LEN];
+        read_array(&mut r, &mut nonce)?;
+        Ok(Self {
+            public_key,
+            nonce,
+        })
+    }
+}
+
+impl ToWire for HandshakeRequest {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_bytes(&self.public_key)?;
+        w.write_bytes(&self.nonce)?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+wire_enum! {
+    /// The result of a handshake request.
+    pub enum HandshakeResult: u8 {
+        /// Success
+        Success = 0x00,
+
+        /// Unspecified error
+        Error = 0x01,
+
+        /// The public key was rejected
+        InvalidPublicKey = 0x02,
+    }
+}
+
+/// A parsed handshake response.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HandshakeResponse {
+    /// The result of the handshake request.
+    pub result: HandshakeResult,
+
+    /// The chip's ephemeral X25519 public key.
+    pub public_key: [u8; PUBLIC_KEY_LEN],
+
+    /// A fresh random nonce chosen by the chip.
+    pub nonce: [u8; NONCE_LEN],
+
+    /// The chip's identity public key, big-endian x and y.
+    pub identity_key: [u8; IDENTITY_KEY_LEN],
+
+    /// The signature of the handshake transcript under the identity key,
+    /// big-endian r and s.
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+/// The length of a handshake response on the wire, in bytes.
+pub const HANDSHAKE_RESPONSE_LEN: usize =
+    1 + PUBLIC_KEY_LEN + NONCE_LEN + IDENTITY_KEY_LEN + SIGNATURE_LEN;
+
+impl Message<'_> for HandshakeResponse {
+    const TYPE: ContentType = ContentType::HandshakeResponse;
+}
+
+impl<'a> FromWire<'a> for HandshakeResponse {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let result_u8 = r.read_be::<u8>()?;
+        let result = HandshakeResult::from_wire_value(result_u8).ok_or(FromWireError::OutOfRange)?;
+        let mut public_key = [0u8; PUBLIC_KEY_LEN];
+        read_array(&mut r, &mut public_key)?;
+        let mut nonce = [0u8; NONCE_LEN];
+        read_array(&mut r, &mut nonce)?;
+        let mut identity_key = [0u8; IDENTITY_KEY_LEN];
+        read_array(&mut r, &mut identity_key)?;
+        let mut signature = [0u8; SIGNATURE_LEN];
+        read_array(&mut r, &mut signature)?;
+        Ok(Self {
+            result,
+            public_key,
+            nonce,
+            identity_key,
+            signature,
+        })
+    }
+}
+
+impl ToWire for HandshakeResponse {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.result.to_wire_value())?;
+        w.write_bytes(&self.public_key)?;
+        w.write_bytes(&self.nonce)?;
+        w.write_bytes(&self.identity_key)?;
+        w.write_bytes(&self.signature)?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed record.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Record<'a> {
+    /// The sequence number of the record within the session and direction.
+    pub sequence: u32,
+
+    /// The encrypted inner payload followed by the authentication tag.
+    pub data: &'a [u8],
+}
+
+/// The length of a record on the wire, excluding its data, in bytes.
+pub const RECORD_LEN: usize = 4;
+
+impl<'a> Message<'a> for Record<'a> {
+    const TYPE: ContentType = ContentType::Record;
+}
+
+impl<'a> FromWire<'a> for Record<'a> {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let sequence = r.read_be::<u32>()?;
+        let data_len = r.remaining_data();
+        let data = r.read_bytes(data_len)?;
+        Ok(Self {
+            sequence,
+            data,
+        })
+    }
+}
+
+impl ToWire for Record<'_> {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.sequence)?;
+        w.write_bytes(self.data)?;
+        Ok(())
+    }
+}
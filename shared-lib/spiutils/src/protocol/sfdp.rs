@@ -0,0 +1,889 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Serial Flash Discoverable Parameters (SFDP, JESD216A).
+//!
+//! An SFDP table starts with the SFDP header, followed by one parameter
+//! header per parameter table and the parameter tables themselves.
+//! `SfdpBuilder` composes such a table in a caller-provided buffer, one
+//! parameter table at a time.
+
+use crate::protocol::flash::AddressMode;
+
+/// The length of the SFDP header, in bytes.
+pub const SFDP_HEADER_LEN: usize = 8;
+
+/// The length of a parameter header, in bytes.
+pub const PARAMETER_HEADER_LEN: usize = 8;
+
+/// The length of the basic flash parameter table, in bytes.
+pub const BASIC_FLASH_PARAMETERS_LEN: usize = 16 * DWORD_LEN;
+
+/// The length of the 4-byte address instruction table, in bytes.
+pub const FOUR_BYTE_ADDRESS_TABLE_LEN: usize = 2 * DWORD_LEN;
+
+/// The maximum length of a parameter table, in bytes.
+pub const MAX_PARAMETER_TABLE_LEN: usize = 0xff * DWORD_LEN;
+
+/// The maximum number of parameter headers in an SFDP table.
+pub const MAX_PARAMETER_HEADERS: usize = 0x100;
+
+const DWORD_LEN: usize = 4;
+
+// Parameter tables must start below 16 MiB (24-bit table pointer).
+const MAX_TABLE_POINTER: usize = 0xffffff;
+
+/// Parameter table ID of the basic flash parameter table.
+pub const BASIC_FLASH_PARAMETERS_ID: u16 = 0xff00;
+
+/// Parameter table ID of the 4-byte address instruction table.
+pub const FOUR_BYTE_ADDRESS_TABLE_ID: u16 = 0xff84;
+
+/// Why an SFDP table could not be built.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SfdpError {
+    /// The buffer is too small to hold the table.
+    BufferTooSmall,
+
+    /// A parameter table is empty, not a whole number of DWORDs or longer
+    /// than MAX_PARAMETER_TABLE_LEN.
+    InvalidTableLength,
+
+    /// A parameter table would start past the reach of a table pointer.
+    TablePointerOutOfRange,
+
+    /// There are already MAX_PARAMETER_HEADERS parameter headers.
+    TooManyParameterHeaders,
+
+    /// The basic flash parameter table must be the first and only one.
+    /// Vendor tables must not use JEDEC IDs.
+    InvalidTableOrder,
+
+    /// The image size is zero, or above 2 gibibits and not a power of two.
+    InvalidImageSize,
+}
+
+/// Settings for the basic flash parameter table (JESD216A, 16 DWORDs).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BasicFlashParameters {
+    /// The size of the flash image, in bytes.
+    pub image_size_bytes: u32,
+
+    /// The address mode after power up or reset.
+    pub startup_address_mode: AddressMode,
+
+    /// Whether EN4B/EX4B switch the address mode.
+    pub support_address_mode_switch: bool,
+
+    /// Whether 1-1-2 fast reads (0x3b) are supported.
+    pub support_dual_output_read: bool,
+
+    /// Whether 1-1-4 fast reads (0x6b) are supported.
+    pub support_quad_output_read: bool,
+}
+
+/// Encodes the flash memory density (2nd DWORD of the basic flash parameter
+/// table) for an image of `image_size_bytes`.
+fn density_dword(image_size_bytes: u32) -> Result<u32, SfdpError> {
+    // Up to 2 gibibits the density is stored as N+1 bits, above as 2^N bits.
+    let bits = image_size_bytes as u64 * 8;
+    if bits == 0 {
+        Err(SfdpError::InvalidImageSize)
+    } else if bits <= 1 << 31 {
+        Ok((bits - 1) as u32)
+    } else if bits.is_power_of_two() {
+        Ok(1 << 31 | bits.trailing_zeros())
+    } else {
+        Err(SfdpError::InvalidImageSize)
+    }
+}
+
+impl BasicFlashParameters {
+    /// Encodes the basic flash parameter table.
+    // The table spells out every field as `value << bit`, including bit 0.
+    #[allow(clippy::identity_op)]
+    pub fn to_table(&self) -> Result<[u8; BASIC_FLASH_PARAMETERS_LEN], SfdpError> {
+        let density = density_dword(self.image_size_bytes)?;
+        let startup_address_mode = self.startup_address_mode;
+        let support_address_mode_switch = self.support_address_mode_switch;
+        let support_dual_output_read = self.support_dual_output_read;
+        let support_quad_output_read = self.support_quad_output_read;
+
+        let table : [u8; BASIC_FLASH_PARAMETERS_LEN] = [
+            // Basic Flash Parameter Table v1.0 1st DWORD
+            // <1:0>   : Block/Sector Erase granularity available for the entirety of flash:
+            //            - 0x1 if 4KiB is uniformly available
+            //            - 0x3 if 4KiB is unavailable
+            0x1 << 0 |  // 4KiB erase uniformly available
+            // <2>     : Write granularity (0 if the buffer is less than 64B, 1 if larger)
+            0x1 << 2 |  // page size is 64 or larger
+            // <3>     : Write Enable Instruction Required for writing to Volatile Status
+            //           Register:
+            //            - 0x0 if target flash only has nonvolatile status bits and does
+            //              not require status register to be written every power on
+            //            - 0x1 if target flash requires 0x00 to be written to the status
+            //              register in order to allow writes and erases
+            0x0 << 3 |  // nonvolatile only status register
+            // <4>     : Write Enable Opcode Select for Writing to Volatile Status Register:
+            //            - 0x0 if 0x50 is the opcode to enable a status register write
+            //            - 0x1 if 0x06 is the opcode to enable a status register write
+            0x1 << 4 |  // 0x06 write enable for status register
+            // <7:5>   : Unused
+            0x0 << 5,
+
+            // <15:8>  : 4KiB Erase Opcode (0xFF if unsupported)
+            0x20,  // 4KiB erase opcode
+
+            // <16>    : Supports 1-1-2 Fast Read (1 if supported)
+            (support_dual_output_read as u8) << 0 |
+            // <18:17> : Address Bytes:
+            //            - 0x0 if 3 Byte addressing only
+            //            - 0x1 if defaults to 3B addressing, enters 4B on command
+            //            - 0x2 if 4 Byte addressing only
+            (match startup_address_mode {
+                AddressMode::ThreeByte => if support_address_mode_switch { 1 } else { 0 }
+                AddressMode::FourByte => 2
+            }) << 1 |
+            // <19>    : Supports Double Transfer Rate (DTR) Clocking (1 if supported)
+            0x0 << 3 |  // DTR clocking not supported
+            // <20>    : Supports 1-2-2 Fast Read (1 if supported)
+            0x0 << 4 |  // 1-2-2 not supported
+            // <21>    : Supports 1-4-4 Fast Read (1 if supported)
+            0x0 << 5 |  // 1-4-4 not supported
+            // <22>    : Supports 1-1-4 Fast Read (1 if supported)
+            (support_quad_output_read as u8) << 6 |
+            // <23>    : Unused
+            0x0 << 7,
+            // <31:24> : Unused
+            0x0,
+
+
+            // Basic Flash Parameter Table v1.0 2nd DWORD
+            // <30:0> : N, where:
+            //           - if =< 2 gibibits, flash memory density is N+1 bits
+            //           - if > 2 gibibits, flash memory density is 2^N bits
+            ((density >> 0) & 0xff) as u8,
+            ((density >> 8) & 0xff) as u8,
+            ((density >> 16) & 0xff) as u8,
+            // <31>   : Density greater than 2 gibibits
+            ((density >> 24) & 0xff) as u8,
+
+
+            // Basic Flash Parameter Table v1.0 3rd DWORD
+            // ------------------------------------------
+            // <4:0>   : 1-4-4 Fast Read Number of Wait States (Dummy CLocks)
+            // <7:5>   : 1-4-4 Fast Read Number of Mode Bits (0 if unsupported)
+            0x0, // 1-4-4 is not supported
+            // <15:8>  : 1-4-4 Fast Read Opcode
+            0x0, // 1-4-4 is not supported
+            // <20:16> : 1-1-4 Fast Read Number of Wait States (Dummy Clocks)
+            // <23:21> : 1-1-4 Fast Read Number of Mode Bits (0 if unsupported)
+            if support_quad_output_read { 0x8 } else { 0x0 }, // 8 dummy cycles
+            // <31:24> : 1-1-4 Fast Read Opcode
+            if support_quad_output_read { 0x6b } else { 0x0 },
+
+
+            // Basic Flash Parameter Table v1.0 4th DWORD
+            // ------------------------------------------
+            // <4:0>   : 1-1-2 Fast Read Number of Wait States (Dummy CLocks)
+            // <7:5>   : 1-1-2 Fast Read Number of Mode Bits (0 if unsupported)
+            0x8, // 8 dummy cycles
+            // <15:8>  : 1-1-2 Fast Read Opcode
+            0x3b,
+            // <20:16> : 1-2-2 Fast Read Number of Wait States (Dummy Clocks)
+            // <23:21> : 1-2-2 Fast Read Number of Mode Bits (0 if unsupported)
+            0x0, // 1-2-2 is not supported
+            // <31:24> : 1-2-2 Fast Read Opcode
+            0x0, // 1-2-2 is not supported
+
+
+            // Basic Flash Parameter Table v1.0 5th DWORD
+            // ------------------------------------------
+            // <0>    : Supports 2-2-2 Fast Read (1 if supported)
+            // <3:1>  : Reserved (0x7)
+            // <4>    : Supports 4-4-4 Fast Read (1 if supported)
+            // <31:5> : Reserved (0x7FFFFFF)
+            0xee, 0xff, 0xff, 0xff, // 4-4-4 and 2-2-2 are not supported
+
+
+            // Basic Flash Parameter Table v1.0 6th DWORD
+            // ------------------------------------------
+            // <31:24> : 2-2-2 Fast Read Opcode
+            // <23:21> : 2-2-2 Fast Read Number of Mode Bits (0 if unsupported)
+            // <20:16> : 2-2-2 Fast Read Number of Wait States (Dummy Clocks)
+            // <15:0>  : Reserved (0xFFFF)
+            0xff, 0xff, 0x00, 0x00, // 2-2-2 is not supported
+
+
+            // Basic Flash Parameter Table v1.0 7th DWORD
+            // ------------------------------------------
+            // <31:24> : 4-4-4 Fast Read Opcode
+            // <23:21> : 4-4-4 Fast Read Number of Mode Bits (0 if unsupported)
+            // <20:16> : 4-4-4 Fast Read Number of Wait States (Dummy Clocks)
+            // <15:0>  : Reserved (0xFFFF)
+            0xff, 0xff, 0x00, 0x00, // 4-4-4 is not supported
+
+
+            // Basic Flash Parameter Table v1.0 8th DWORD
+            // ------------------------------------------
+            // <7:0>   : Sector Type 1 Erase Size (2^N Bytes, 0 if unavailable)
+            12, // 4 KiB
+            // <15:8>  : Sector Type 1 Erase Opcode
+            0x20,
+            // <23:16> : Sector Type 2 Erase Size (2^N Bytes, 0 if unavailable)
+            0, // unavailable
+            // <31:24> : Sector Type 2 Erase Opcode
+            0, // unavailable
+
+
+            // Basic Flash Parameter Table v1.0 9th DWORD
+            // ------------------------------------------
+            // <7:0>   : Sector Type 3 Erase Size (2^N Bytes, 0 if unavailable)
+            0, // unavailable
+            // <15:8>  : Sector Type 3 Erase Opcode
+            0, // unavailable
+            // <23:16> : Sector Type 4 Erase Size (2^N Bytes, 0 if unavailable)
+            0, // unavailable
+            // <31:24> : Sector Type 4 Erase Opcode
+            0, // unavailable
+
+
+            // Basic Flash Parameter Table v1.5 10th DWORD
+            // ------------------------------------------
+            // 128ms typical 4KiB erase time, 512ms max (2 * (1 + 1) * 128).
+            // MX25L25635FMI: 43ms typical, 200ms max (sector erase).
+            // W25Q256FV: 45ms typical, 400ms max (sector erase).
+            //
+            // <3:0>   : Multiplier from typical to maximum erase time, where
+            //           maximum_time = 2// (multiplier + 1)// typical_time
+            1 << 0 |
+            // <8:4>   : Sector Type 1 Erase, Typical time count, where
+            //           time = (count + 1)// units
+            0 << 4,
+            // <10:9>  : Sector Type 1 Erase, Typical time units, where
+            //           0x0: 1ms, 0x1: 16ms, 0x2: 128ms, 0x3: 1s
+            2 << 1 | // 128 ms
+            // <15:11> : Sector Type 2 Erase, Typical time count, where
+            //           time = (count + 1)// units
+            0 << 3, // unavailable
+            // <17:16> : Sector Type 2 Erase, Typical time units, where
+            //           0x0: 1ms, 0x1: 16ms, 0x2: 128ms, 0x3: 1s
+            // <22:18> : Sector Type 3 Erase, Typical time count, where
+            //           time = (count + 1)// units
+            // <24:23> : Sector Type 3 Erase, Typical time units, where
+            //           0x0: 1ms, 0x1: 16ms, 0x2: 128ms, 0x3: 1s
+            0x0, // unavailable
+            // <29:25> : Sector Type 4 Erase, Typical time count, where
+            //           time = (count + 1)// units
+            // <31:30> : Sector Type 4 Erase, Typical time units, where
+            //           0x0: 1ms, 0x1: 16ms, 0x2: 128ms, 0x3: 1s
+            0x0, // unavailable
+
+
+            // Basic Flash Parameter Table v1.5 11th DWORD
+            // ------------------------------------------
+            // <3:0>   : Multiplier from typical time to max time for programming, where
+            //           maximum_time = 2// (multiplier + 1)// typical_time
+            1 << 0 |
+            // <7:4>   : Page Size (2^N Bytes)
+            8 << 4, // 256B page size
+            // 1 mS for page program
+            // <12:8>  : Page Program, Typical time count, where time = (count + 1)// units
+            0xf << 0 |
+            // <13>    : Page Program, Typical time units (0: 8us, 1: 64us)
+            1 << 5 |
+            // 128 uS for first byte written
+            // <17:14> : First Byte Program, Typical time count, where each byte takes
+            //           time = (count + 1)// units// bytes
+            3 << 6,
+            3 << 0 |
+            // <18>    : First Byte Program, Typical time units (0: 1us, 1: 8us)
+            1 << 2 |
+            // 128 uS per additional byte written
+            // <22:19> : Additional Byte Program, Typical time count, where each byte takes
+            //           time = (count + 1)// units// bytes. This should not be
+            //           used if the additional bytes count exceeds 1/2 a page size.
+            0xf << 3 |
+            // <23>    : Additional Byte Program, Typical time units (0: 1us, 1: 8us)
+            1 << 7,
+            // chip erase takes 128-512 seconds
+            // <28:24> : Chip Erase, Typical time count, where time = (count + 1)// units
+            1 << 0 |
+            // <30:29> : Chip Erase, Typical time units, where
+            //           0x0: 16ms, 0x1: 256ms, 0x2: 4s, 0x3: 64s
+            3 << 5 |
+            // <31>    : Reserved (0x1)
+            1 << 7,
+
+
+            // Basic Flash Parameter Table v1.5 12th DWORD
+            // ------------------------------------------
+            // <3:0>   : Prohibited Operations During Program Suspend flags, where
+            //           xxx0b May not initiate a new erase anywhere
+            //                 (erase nesting not permitted)
+            //           xxx1b May not initiate a new erase in the program suspended page
+            //                 size
+            //           xx0xb May not initiate a new page program anywhere
+            //                 (program nesting not permitted)
+            //           xx1xb May not initiate a new page program in the program suspended
+            //                 page size
+            //           x0xxb Refer to vendor datasheet for read restrictions
+            //           x1xxb May not initiate a read in the program suspended page size
+            //           0xxxb Additional erase or program restrictions apply
+            //           1xxxb The erase and program restrictions in bits 1:0 are
+            //                 sufficient
+            // <7:4>   : Prohibited Operations During Erase Suspend flags, where
+            //           xxx0b May not initiate a new erase anywhere
+            //                 (erase nesting not permitted)
+            //           xxx1b May not initiate a new erase in the erase suspended sector
+            //                 size
+            //           xx0xb May not initiate a page program anywhere
+            //           xx1xb May not initiate a page program in the erase suspended
+            //                 sector size
+            //           x0xxb Refer to vendor datasheet for read restrictions
+            //           x1xxb May not initiate a read in the erase suspended sector size
+            //           0xxxb Additional erase or program restrictions apply
+            //           1xxxb The erase and program restrictions in bits 5:4 are
+            //                 sufficient
+            0x0,
+            // <8>     : Reserved (0x1)
+            // <12:9>  : Program resume to suspend minimum internal, (count + 1)// 64us
+            // <17:13> : Suspend in-progress program max latency count, where
+            //           max latency = (count + 1)// units
+            1 << 0, // reserved
+            // <19:18> : Suspend in-progress program max latency units, where
+            //           0x0: 128ns, 0x1: 1us, 0x2: 8us, 0x3: 64us
+            // <23:20> : Erase resume to suspend minimum interval, (count + 1)// 64us
+            0x0,
+            // <28:24> : Suspend in-progress erase max latency count, where
+            //           max latency = (count + 1)// units
+            // <30:29> : Suspend in-progress erase max latency units, where
+            //           0x0: 128ns, 0x1: 1us, 0x2: 8us, 0x3: 64us
+            // <31>    : Suspend / Resume unsupported (1 unsupported, 0 supported)
+            1 << 7, // unsupported
+
+
+            // Basic Flash Parameter Table v1.5 13th DWORD
+            // ------------------------------------------
+            // <7:0>   : Program Resume Instruction used to resume a program operation
+            0x00,
+            // <15:8>  : Program Suspend Instruction used to suspend a program operation
+            0x00,
+            // <23:16> : Resume Instruction used to resume a write or erase type operation
+            0x00,
+            // <31:24> : Suspend Instruction used to suspend a write or erase type operation
+            0x00,
+
+
+            // Basic Flash Parameter Table v1.5 14th DWORD
+            // ------------------------------------------
+            // <1:0>   : Reserved (0x3)
+            3 << 0 |
+            // <7:2>   : Status Register Polling Device Busy Flags, where
+            //           xx_xx1xb Bit 7 of the Flag Status Register may be polled any time
+            //                    a Program, Erase, Suspend/Resume command is issued, or
+            //                    after a Reset command while the device is busy. The read
+            //                    instruction is 70h. Flag Status Register bit definitions:
+            //                    bit[7]: Program or erase controller status
+            //                    (0=busy; 1=ready)
+            //           xx_xxx1b Use of legacy polling is supported by reading the Status
+            //                    Register with 05h instruction and checking WIP bit[0]
+            //                    (0=ready; 1=busy).
+            1 << 2, // Use legacy status register polling for WIP bit
+            // <12:8>  : Exit deep powerdown to next operation delay count, where
+            //           delay = = (count + 1)// units
+            // <14:13> : Exit deep powerdown to next operation delay units, where
+            //           0x0: 128ns, 0x1: 1us, 0x2: 8us, 0x3: 64us
+            // <22:15> : Exit deep powerdown instruction
+            // <30:23> : Enter deep powerdown instruction
+            // <31>    : Deep powerdown unsupported (1 unsupported, 0 supported)
+            0x0, 0x0, 1 << 7, // unsupported
+
+
+            // Basic Flash Parameter Table v1.5 15th DWORD
+            // ------------------------------------------
+            // <3:0>   : 4-4-4 mode disable sequences, where
+            //           xxx1b issue FFh instruction
+            //           xx1xb issue F5h instruction
+            //           x1xxb device uses a read-modify-write sequence of operations:
+            //                 read configuration using instruction 65h followed by address
+            //                 800003h, clear bit 6,
+            //                 write configuration using instruction 71h followed by
+            //                 address 800003h. This configuration is volatile.
+            //           1xxxb issue the Soft Reset 66/99 sequence
+            // <8:4>   : 4-4-4 mode enable sequences, where
+            //           x_xxx1b set QE per QER description above, then issue
+            //                   instruction 38h
+            //           x_xx1xb issue instruction 38h
+            //           x_x1xxb issue instruction 35h
+            //           x_1xxxb device uses a read-modify-write sequence of operations:
+            //                   read configuration using instruction 65h followed by
+            //                   address 800003h, set bit 6,
+            //                   write configuration using instruction 71h followed by
+            //                   address 800003h. This configuration is volatile.
+            // <9>     : 0-4-4 mode supported (1 supported, 0 unsupported)
+            // <15:10> : 0-4-4 Mode Exit Method, where
+            //           xx_xxx1b Mode Bits[7:0] = 00h will terminate this mode at the end
+            //                    of the current read operation
+            //           xx_xx1xb If 3-Byte address active, input Fh on DQ0-DQ3 for 8
+            //                    clocks. If 4-Byte address active, input Fh on DQ0-DQ3 for
+            //                    10 clocks. This will terminate the mode prior to the next
+            //                    read operation.
+            //           xx_1xxxb Input Fh (mode bit reset) on DQ0-DQ3 for 8 clocks. This
+            //                    will terminate the mode prior to the next read operation.
+            // <19:16> : 0-4-4 Mode Entry Method, where
+            //           xxx1b Mode Bits[7:0] = A5h Note: QE must be set prior to using this
+            //                 mode
+            //           xx1xb Read the 8-bit volatile configuration register with
+            //                 instruction 85h, set XIP bit[3] in the data read, and write
+            //                 the modified data using the instruction 81h, then Mode Bits
+            //                 [7:0] = 01h
+            // <22:20> : Quad Enable Requirements (1-1-4, 1-4-4, 4-4-4 Fast Reads), where
+            //           000b Device does not have a QE bit. Device detects 1-1-4 and 1-4-4
+            //                reads based on instruction. DQ3/HOLD# functions as hold during
+            //                instruction phase.
+            //           001b QE is bit 1 of status register 2. It is set via Write Status
+            //                with two data bytes where bit 1 of the second byte is one. It
+            //                is cleared via Write Status with two data bytes where bit
+            //                1 of the second byte is zero. Writing only one byte to the
+            //                status register has the side-effect of clearing status
+            //                register 2, including the QE bit. The 100b code is used if
+            //                writing one byte to the status register does not modify status
+            //                register 2.
+            //           010b QE is bit 6 of status register 1. It is set via Write Status
+            //                with one data byte where bit 6 is one. It is cleared via Write
+            //                Status with one data byte where bit 6 is zero.
+            //           011b QE is bit 7 of status register 2. It is set via Write status
+            //                register 2 instruction 3Eh with one data byte where bit 7 is
+            //                one. It is cleared via Write status register 2 instruction
+            //                3Eh with one data byte where bit 7 is zero. The status
+            //                register 2 is read using instruction 3Fh.
+            //           100b QE is bit 1 of status register 2. It is set via Write Status
+            //                with two data bytes where bit 1 of the second byte is one. It
+            //                is cleared via Write Status with two data bytes where bit 1
+            //                of the second byte is zero. In contrast to the 001b code,
+            //                writing one byte to the status register does not modify status
+            //                register 2.
+            //           101b QE is bit 1 of the status register 2. Status register 1 is
+            //                read using Read Status instruction 05h. Status register 2 is
+            //                read using instruction 35h. QE is set via Write Status
+            //                instruction 01h with two data bytes where bit 1 of the second
+            //                byte is one. It is cleared via Write Status with two data
+            //                bytes where bit 1 of the second byte is zero.
+            // <23>    : HOLD and WIP disable supported by setting the non-volatile extended
+            //           configuration register's bit 4 to 0.
+            0x0, 0x0, 0x0, // unsupported
+            // <31:24> : Reserved (0xFF)
+            0xff,
+
+
+            // Basic Flash Parameter Table v1.5 16th DWORD
+            // -------------------------------------------
+            // <6:0>   : Volatile or Non-Volatile Register and Write Enable Instruction for
+            //           Status Register 1, where
+            //           xx0_0000b status register is read only
+            //           xxx_xxx1b Non-Volatile Status Register 1, powers-up to last written
+            //                     value, use instruction 06h to enable write
+            //           xxx_xx1xb Volatile Status Register 1, status register powers-up
+            //                     with bits set to "1"s, use instruction 06h to enable
+            //                     write
+            //           xxx_x1xxb Volatile Status Register 1, status register powers-up
+            //                     with bits set to "1"s, use instruction 50h to enable
+            //                     write
+            //           xxx_1xxxb Non-Volatile/Volatile status register 1 powers-up to last
+            //                     written value in the non-volatile status register, use
+            //                     instruction 06h to enable write to non-volatile status
+            //                     register. Volatile status register may be activated after
+            //                     power-up to override the non-volatile status register,
+            //                     use instruction 50h to enable write and activate the
+            //                     volatile status register.
+            //           xx1_xxxxb Status Register 1 contains a mix of volatile and
+            //                     non-volatile bits. The 06h instruction is used to enable
+            //                     writing of the register.
+            0x2 << 0 | // Volatile status reg, powers up with bits set to 1, use
+                       // 0x06 to write enable
+            // <7>     : Reserved (0x1)
+            1 << 7,
+            // <13:8>  : Soft Reset and Rescue Sequence Support, where
+            //           00_0000b no software reset instruction is supported
+            //           xx_xxx1b drive Fh on all 4 data wires for 8 clocks
+            //           xx_xx1xb drive Fh on all 4 data wires for 10 clocks if device is
+            //                    operating in 4-byte address mode
+            //           xx_x1xxb drive Fh on all 4 data wires for 16 clocks
+            //           xx_1xxxb issue instruction F0h
+            //           x1_xxxxb issue reset enable instruction 66h, then issue reset
+            //                    instruction 99h. The reset enable, reset sequence may be
+            //                    issued on 1, 2, or 4 wires depending on the device
+            //                    operating mode.
+            //           1x_xxxxb exit 0-4-4 mode is required prior to other reset sequences
+            //                    above if the device may be operating in this mode.
+            0x0 << 0 | // no software reset instruction supported
+            // <23:14> : Exit 4-Byte Addressing, where
+            //           xx_xxxx_xxx1b issue instruction E9h to exit 4-Byte address mode
+            //                         (write enable instruction 06h is not required)
+            if support_address_mode_switch { 1 << 6 } else { 0 },
+            //           xx_xxxx_xx1xb issue write enable instruction 06h, then issue
+            //                         instruction E9h to exit 4-Byte address mode
+            //           xx_xxxx_x1xxb 8-bit volatile extended address register used to
+            //                         define A[31:A24] bits. Read with instruction C8h.
+            //                         Write instruction is C5h, data length is 1 byte.
+            //                         Return to lowest memory segment by setting A[31:24]
+            //                         to 00h and use 3-Byte addressing.
+            //           xx_xxxx_1xxxb 8-bit volatile bank register used to define A[30:A24]
+            //                         bits. MSB (bit[7]) is used to enable/disable 4-byte
+            //                         address mode. When MSB is cleared to ‘0’, 3-byte
+            //                         address mode is active and A30:A24 are used to select
+            //                         the active 128 Mbit memory segment. Read with
+            //                         instruction 16h. Write instruction is 17h, data
+            //                         length is 1 byte.
+            //           xx_xxx1_xxxxb A 16-bit nonvolatile configuration register controls
+            //                         3-Byte/4-Byte address mode. Read instruction is B5h.
+            //                         Bit[0] controls address mode [0=3-Byte; 1=4-Byte].
+            //                         Write configuration register instruction is B1h, data
+            //                         length is 2 bytes.
+            //           xx_xx1x_xxxxb Hardware reset
+            if startup_address_mode == AddressMode::ThreeByte { 1 << 3 } else { 0 } |
+            //           xx_x1xx_xxxxb Software reset (see bits 13:8 in this DWORD)
+            //           xx_1xxx_xxxxb Power cycle
+            if startup_address_mode == AddressMode::ThreeByte { 1 << 5 } else { 0 },
+            // <31:24> : Enter 4-Byte Addressing, where
+            //           xxxx_xxx1b issue instruction B7h
+            //                      (preceding write enable not required)
+            //           xxxx_xx1xb issue write enable instruction 06h, then issue
+            //                      instruction B7h
+            //           xxxx_x1xxb 8-bit volatile extended address register used to define
+            //                      A[31:24] bits. Read with instruction C8h. Write
+            //                      instruction is C5h with 1 byte of data. Select the
+            //                      active 128 Mbit memory segment by setting the
+            //                      appropriate A[31:24] bits and use 3-Byte addressing.
+            //           xxxx_1xxxb 8-bit volatile bank register used to define A[30:A24]
+            //                      bits. MSB (bit[7]) is used to enable/disable 4-byte
+            //                      address mode. When MSB is set to ‘1’, 4-byte address
+            //                      mode is active and A[30:24] bits are don’t care. Read
+            //                      with instruction 16h. Write instruction is 17h with 1
+            //                      byte of data. When MSB is cleared to ‘0’, select the
+            //                      active 128 Mbit segment by setting the appropriate
+            //                      A[30:24] bits and use 3-Byte addressing.
+            //           xxx1_xxxxb A 16-bit nonvolatile configuration register controls
+            //                      3-Byte/4-Byte address mode. Read instruction is B5h.
+            //                      Bit[0] controls address mode [0=3-Byte; 1=4-Byte]. Write
+            //                      configuration register instruction is B1h, data length
+            //                      is 2 bytes.
+            //           xx1x_xxxxb Supports dedicated 4-Byte address instruction set.
+            //                      Consult vendor data sheet for the instruction set
+            //                      definition.
+            //           x1xx_xxxxb Always operates in 4-Byte address mode
+            if startup_address_mode == AddressMode::FourByte {
+                    1 << 6 // Always operates in 4-Byte address mode
+            } else {
+                    if support_address_mode_switch {
+                        1  // issue instruction B7h
+                    } else {
+                        0
+                    }
+            },
+        ];
+
+        Ok(table)
+    }
+}
+
+/// Instructions listed in the 4-byte address instruction table.
+/// These take a 4-byte address regardless of the address mode.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct FourByteAddressInstructions {
+    /// Read Data (0x13).
+    pub read: bool,
+
+    /// Fast Read (0x0c).
+    pub fast_read: bool,
+
+    /// 1-1-2 Fast Read (0x3c).
+    pub fast_read_dual_output: bool,
+
+    /// 1-1-4 Fast Read (0x6c).
+    pub fast_read_quad_output: bool,
+
+    /// Page Program (0x12).
+    pub page_program: bool,
+
+    /// 4-byte address erase op code for each of the four erase types of
+    /// the basic flash parameter table, or 0xff if unsupported.
+    pub erase_op_codes: [u8; 4],
+}
+
+impl FourByteAddressInstructions {
+    /// Encodes the 4-byte address instruction table.
+    pub fn to_table(&self) -> [u8; FOUR_BYTE_ADDRESS_TABLE_LEN] {
+        // 1st DWORD: one support bit per instruction, bits 31:20 reserved (1).
+        let mut support: u32 = 0xfff << 20;
+        support |= self.read as u32;
+        support |= (self.fast_read as u32) << 1;
+        support |= (self.fast_read_dual_output as u32) << 2;
+        support |= (self.fast_read_quad_output as u32) << 4;
+        support |= (self.page_program as u32) << 6;
+        for (idx, op_code) in self.erase_op_codes.iter().enumerate() {
+            if *op_code != 0xff {
+                support |= 1 << (9 + idx);
+            }
+        }
+
+        let mut table = [0u8; FOUR_BYTE_ADDRESS_TABLE_LEN];
+        table[..DWORD_LEN].copy_from_slice(&support.to_le_bytes());
+        // 2nd DWORD: erase op codes for erase types 1 to 4.
+        table[DWORD_LEN..].copy_from_slice(&self.erase_op_codes);
+        table
+    }
+}
+
+/// Composes an SFDP table in a buffer.
+///
+/// Parameter headers are kept right after the SFDP header and the parameter
+/// tables follow them in the order they were added. Adding a table moves
+/// the tables added before it to make room for its parameter header.
+pub struct SfdpBuilder<'a> {
+    buf: &'a mut [u8],
+
+    // Number of parameter headers written.
+    num_parameter_headers: usize,
+
+    // Length of the SFDP table so far, in bytes.
+    len: usize,
+}
+
+impl<'a> SfdpBuilder<'a> {
+    /// Starts an SFDP table without parameter tables in `buf`.
+    pub fn new(buf: &'a mut [u8]) -> Result<Self, SfdpError> {
+        if buf.len() < SFDP_HEADER_LEN {
+            return Err(SfdpError::BufferTooSmall);
+        }
+        buf[..SFDP_HEADER_LEN].copy_from_slice(&[
+            0x53, // S
+            0x46, // F
+            0x44, // D
+            0x50, // P
+            0x05, // Minor (=JESD216A)
+            0x01, // Major (=JESD216A)
+            0x00, // # parameter headers - 1, set by add_table
+            0xff, // unused
+        ]);
+        Ok(Self {
+            buf,
+            num_parameter_headers: 0,
+            len: SFDP_HEADER_LEN,
+        })
+    }
+
+    /// Adds the basic flash parameter table. This must be the first table.
+    pub fn add_basic_flash_parameters(&mut self, params: &BasicFlashParameters)
+        -> Result<(), SfdpError> {
+        if self.num_parameter_headers != 0 {
+            return Err(SfdpError::InvalidTableOrder);
+        }
+        // Table v1.5 of JESD216A.
+        self.add_table(BASIC_FLASH_PARAMETERS_ID, 0x01, 0x05, &params.to_table()?)
+    }
+
+    /// Adds the 4-byte address instruction table.
+    pub fn add_4byte_address_table(&mut self, instructions: &FourByteAddressInstructions)
+        -> Result<(), SfdpError> {
+        if self.num_parameter_headers == 0 {
+            return Err(SfdpError::InvalidTableOrder);
+        }
+        self.add_table(FOUR_BYTE_ADDRESS_TABLE_ID, 0x01, 0x00, &instructions.to_table())
+    }
+
+    /// Adds a vendor parameter table. The MSB of `id` is the JEP106 bank
+    /// number of the vendor, the LSB its manufacturer ID. `table` must be a
+    /// whole number of DWORDs.
+    pub fn add_vendor_table(&mut self, id: u16, major: u8, minor: u8, table: &[u8])
+        -> Result<(), SfdpError> {
+        if self.num_parameter_headers == 0 || id >> 8 == 0xff {
+            return Err(SfdpError::InvalidTableOrder);
+        }
+        self.add_table(id, major, minor, table)
+    }
+
+    fn add_table(&mut self, id: u16, major: u8, minor: u8, table: &[u8])
+        -> Result<(), SfdpError> {
+        if table.is_empty() || !table.len().is_multiple_of(DWORD_LEN) || table.len() > MAX_PARAMETER_TABLE_LEN {
+            return Err(SfdpError::InvalidTableLength);
+        }
+        if self.num_parameter_headers == MAX_PARAMETER_HEADERS {
+            return Err(SfdpError::TooManyParameterHeaders);
+        }
+        let new_len = self.len + PARAMETER_HEADER_LEN + table.len();
+        if new_len > self.buf.len() {
+            return Err(SfdpError::BufferTooSmall);
+        }
+        let table_pointer = self.len + PARAMETER_HEADER_LEN;
+        if table_pointer > MAX_TABLE_POINTER {
+            return Err(SfdpError::TablePointerOutOfRange);
+        }
+
+        // Make room for the new parameter header.
+        let headers_end = SFDP_HEADER_LEN + self.num_parameter_headers * PARAMETER_HEADER_LEN;
+        self.buf.copy_within(headers_end..self.len, headers_end + PARAMETER_HEADER_LEN);
+        for idx in 0..self.num_parameter_headers {
+            let header = SFDP_HEADER_LEN + idx * PARAMETER_HEADER_LEN;
+            let pointer = read_table_pointer(&self.buf[header..]) + PARAMETER_HEADER_LEN;
+            write_table_pointer(&mut self.buf[header..], pointer);
+        }
+
+        let header = &mut self.buf[headers_end..headers_end + PARAMETER_HEADER_LEN];
+        header[0] = id as u8;
+        header[1] = minor;
+        header[2] = major;
+        header[3] = (table.len() / DWORD_LEN) as u8;
+        write_table_pointer(header, table_pointer);
+        header[7] = (id >> 8) as u8;
+        self.buf[table_pointer..new_len].copy_from_slice(table);
+
+        self.num_parameter_headers += 1;
+        self.buf[6] = (self.num_parameter_headers - 1) as u8;
+        self.len = new_len;
+        Ok(())
+    }
+
+    /// Fills the rest of the buffer with 0xff and returns the length of the
+    /// SFDP table, in bytes.
+    pub fn finish(self) -> usize {
+        for byte in self.buf[self.len..].iter_mut() {
+            *byte = 0xff;
+        }
+        self.len
+    }
+}
+
+// Reads the table pointer of the parameter header at the start of `header`.
+fn read_table_pointer(header: &[u8]) -> usize {
+    header[4] as usize | (header[5] as usize) << 8 | (header[6] as usize) << 16
+}
+
+// Writes the table pointer of the parameter header at the start of `header`.
+fn write_table_pointer(header: &mut [u8], pointer: usize) {
+    header[4] = pointer as u8;
+    header[5] = (pointer >> 8) as u8;
+    header[6] = (pointer >> 16) as u8;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params() -> BasicFlashParameters {
+        BasicFlashParameters {
+            image_size_bytes: 0x2000000,
+            startup_address_mode: AddressMode::ThreeByte,
+            support_address_mode_switch: true,
+            support_dual_output_read: true,
+            support_quad_output_read: false,
+        }
+    }
+
+    #[test]
+    fn density_dword_encodes_both_ranges() {
+        assert_eq!(density_dword(0x2000000), Ok(0x0fffffff));
+        // 2 gibibits is the largest density stored as N+1 bits.
+        assert_eq!(density_dword(0x10000000), Ok(0x7fffffff));
+        assert_eq!(density_dword(0x20000000), Ok(0x80000020));
+        assert_eq!(density_dword(0x80000000), Ok(0x80000022));
+        assert_eq!(density_dword(0x30000000), Err(SfdpError::InvalidImageSize));
+        assert_eq!(density_dword(0), Err(SfdpError::InvalidImageSize));
+    }
+
+    #[test]
+    fn basic_and_vendor_tables() {
+        let mut buf = [0u8; 128];
+        let mut builder = SfdpBuilder::new(&mut buf).unwrap();
+        builder.add_basic_flash_parameters(&params()).unwrap();
+        builder.add_vendor_table(0x0926, 0x01, 0x00, &[0x47, 0x4f, 0x4f, 0x47]).unwrap();
+        assert_eq!(builder.finish(), 0x5c);
+
+        assert_eq!(buf[..8], [0x53, 0x46, 0x44, 0x50, 0x05, 0x01, 0x01, 0xff]);
+        assert_eq!(buf[8..16], [0x00, 0x05, 0x01, 0x10, 0x18, 0x00, 0x00, 0xff]);
+        assert_eq!(buf[16..24], [0x26, 0x00, 0x01, 0x01, 0x58, 0x00, 0x00, 0x09]);
+        assert_eq!(buf[0x18..0x58], params().to_table().unwrap());
+        assert_eq!(buf[0x58..0x5c], [0x47, 0x4f, 0x4f, 0x47]);
+        assert!(buf[0x5c..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn adding_a_table_moves_earlier_tables() {
+        let mut buf = [0u8; 256];
+        let instructions = FourByteAddressInstructions {
+            fast_read: true,
+            erase_op_codes: [0x21, 0xff, 0xff, 0xff],
+            ..Default::default()
+        };
+        let mut builder = SfdpBuilder::new(&mut buf).unwrap();
+        builder.add_basic_flash_parameters(&params()).unwrap();
+        builder.add_4byte_address_table(&instructions).unwrap();
+        builder.add_vendor_table(0x0926, 0x01, 0x00, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(builder.finish(), 0x70);
+
+        assert_eq!(buf[6], 2);
+        assert_eq!(buf[8..16], [0x00, 0x05, 0x01, 0x10, 0x20, 0x00, 0x00, 0xff]);
+        assert_eq!(buf[16..24], [0x84, 0x00, 0x01, 0x02, 0x60, 0x00, 0x00, 0xff]);
+        assert_eq!(buf[24..32], [0x26, 0x00, 0x01, 0x02, 0x68, 0x00, 0x00, 0x09]);
+        assert_eq!(buf[0x20..0x60], params().to_table().unwrap());
+        assert_eq!(buf[0x60..0x68], [0x02, 0x02, 0xf0, 0xff, 0x21, 0xff, 0xff, 0xff]);
+        assert_eq!(buf[0x68..0x70], [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn basic_flash_parameters_must_come_first() {
+        let mut buf = [0u8; 256];
+        let mut builder = SfdpBuilder::new(&mut buf).unwrap();
+        assert_eq!(builder.add_vendor_table(0x0926, 1, 0, &[0; 4]),
+                   Err(SfdpError::InvalidTableOrder));
+        assert_eq!(builder.add_4byte_address_table(&Default::default()),
+                   Err(SfdpError::InvalidTableOrder));
+        builder.add_basic_flash_parameters(&params()).unwrap();
+        assert_eq!(builder.add_basic_flash_parameters(&params()),
+                   Err(SfdpError::InvalidTableOrder));
+        assert_eq!(builder.add_vendor_table(0xff00, 1, 0, &[0; 4]),
+                   Err(SfdpError::InvalidTableOrder));
+    }
+
+    #[test]
+    fn vendor_tables_must_be_whole_dwords() {
+        let mut buf = [0u8; 2048];
+        let mut builder = SfdpBuilder::new(&mut buf).unwrap();
+        builder.add_basic_flash_parameters(&params()).unwrap();
+        assert_eq!(builder.add_vendor_table(0x0926, 1, 0, &[]),
+                   Err(SfdpError::InvalidTableLength));
+        assert_eq!(builder.add_vendor_table(0x0926, 1, 0, &[0; 6]),
+                   Err(SfdpError::InvalidTableLength));
+        assert_eq!(builder.add_vendor_table(0x0926, 1, 0, &[0; MAX_PARAMETER_TABLE_LEN + 4]),
+                   Err(SfdpError::InvalidTableLength));
+        assert_eq!(builder.add_vendor_table(0x0926, 1, 0, &[0; MAX_PARAMETER_TABLE_LEN]), Ok(()));
+    }
+
+    #[test]
+    fn rejects_tables_that_do_not_fit() {
+        assert!(SfdpBuilder::new(&mut [0u8; SFDP_HEADER_LEN - 1]).is_err());
+
+        let mut buf = [0u8; 0x5b];
+        let mut builder = SfdpBuilder::new(&mut buf).unwrap();
+        builder.add_basic_flash_parameters(&params()).unwrap();
+        assert_eq!(builder.add_vendor_table(0x0926, 1, 0, &[0; 4]),
+                   Err(SfdpError::BufferTooSmall));
+        // A failed add leaves the table as it was.
+        assert_eq!(builder.finish(), 0x50);
+        assert_eq!(buf[6], 0);
+    }
+}
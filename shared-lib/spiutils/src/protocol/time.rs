@@ -0,0 +1,207 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Time protocol payload.
+//!
+//! The chip does not have a real-time clock. The BMC periodically sends its
+//! current UNIX time so that the chip can maintain an approximate wall clock.
+
+use crate::io::Read;
+use crate::io::Write;
+use crate::protocol::wire::FromWireError;
+use crate::protocol::wire::FromWire;
+use crate::protocol::wire::ToWireError;
+use crate::protocol::wire::ToWire;
+use crate::protocol::wire::WireEnum;
+
+wire_enum! {
+    /// The content type.
+    pub enum ContentType: u8 {
+        /// Request to set the current time
+        SetTimeRequest = 0x01,
+
+        /// Response to SetTimeRequest
+        SetTimeResponse = 0x02,
+    }
+}
+
+/// A parsed header.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Header {
+    /// The content type following the header.
+    pub content: ContentType,
+}
+
+/// The length of a time header on the wire, in bytes.
+pub const HEADER_LEN: usize = 1;
+
+impl<'a> FromWire<'a> for Header {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let content_u8 = r.read_be::<u8>()?;
+        let content = ContentType::from_wire_value(content_u8).ok_or(FromWireError::OutOfRange)?;
+        Ok(Self {
+            content,
+        })
+    }
+}
+
+impl ToWire for Header {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.content.to_wire_value())?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A message.
+///
+/// A message is identified by a [`ContentType`]:
+///
+/// [`ContentType`]: enum.ContentType.html
+pub trait Message<'req>: FromWire<'req> + ToWire {
+    /// The unique [`ContentType`] for this `Message`.
+    ///
+    /// [`ContentType`]: enum.ContentType.html
+    const TYPE: ContentType;
+}
+
+// ----------------------------------------------------------------------------
+
+/// A parsed set time request.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SetTimeRequest {
+    /// The current UNIX time in milliseconds.
+    pub unix_time_ms: u64,
+}
+
+/// The length of a set time request on the wire, in bytes.
+pub const SET_TIME_REQUEST_LEN: usize = 8;
+
+impl Message<'_> for SetTimeRequest {
+    const TYPE: ContentType = ContentType::SetTimeRequest;
+}
+
+impl<'a> FromWire<'a> for SetTimeRequest {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let unix_time_ms = r.read_be::<u64>()?;
+        Ok(Self {
+            unix_time_ms,
+        })
+    }
+}
+
+impl ToWire for SetTimeRequest {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.unix_time_ms)?;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+wire_enum! {
+    /// The result of a set time request.
+    pub enum SetTimeResult: u8 {
+        /// Success
+        Success = 0x00,
+
+        /// Unspecified error
+        Error = 0x01,
+    }
+}
+
+/// A parsed set time response.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SetTimeResponse {
+    /// The UNIX time in milliseconds from the request.
+    pub unix_time_ms: u64,
+
+    /// The result of the set time request.
+    pub result: SetTimeResult,
+}
+
+/// The length of a set time response on the wire, in bytes.
+pub const SET_TIME_RESPONSE_LEN: usize = 9;
+
+impl Message<'_> for SetTimeResponse {
+    const TYPE: ContentType = ContentType::SetTimeResponse;
+}
+
+impl<'a> FromWire<'a> for SetTimeResponse {
+    fn from_wire<R: Read<'a>>(mut r: R) -> Result<Self, FromWireError> {
+        let unix_time_ms = r.read_be::<u64>()?;
+        let result_u8 = r.read_be::<u8>()?;
+        let result = SetTimeResult::from_wire_value(result_u8).ok_or(FromWireError::OutOfRange)?;
+        Ok(Self {
+            unix_time_ms,
+            result,
+        })
+    }
+}
+
+impl ToWire for SetTimeResponse {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_be(self.unix_time_ms)?;
+        w.write_be(self.result.to_wire_value())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::Cursor;
+
+    #[test]
+    fn header_round_trip() {
+        let header = Header { content: ContentType::SetTimeResponse };
+        let mut buf = [0u8; HEADER_LEN];
+        header.to_wire(Cursor::new(&mut buf)).expect("to_wire failed");
+        assert_eq!(buf, [0x02]);
+        assert_eq!(Header::from_wire(&buf[..]).expect("from_wire failed"), header);
+    }
+
+    #[test]
+    fn set_time_request_round_trip() {
+        let request = SetTimeRequest { unix_time_ms: 0x0102030405060708 };
+        let mut buf = [0u8; SET_TIME_REQUEST_LEN];
+        request.to_wire(Cursor::new(&mut buf)).expect("to_wire failed");
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(SetTimeRequest::from_wire(&buf[..]).expect("from_wire failed"), request);
+    }
+
+    #[test]
+    fn set_time_response_round_trip() {
+        let response = SetTimeResponse {
+            unix_time_ms: 1_600_000_000_000,
+            result: SetTimeResult::Error,
+        };
+        let mut buf = [0u8; SET_TIME_RESPONSE_LEN];
+        response.to_wire(Cursor::new(&mut buf)).expect("to_wire failed");
+        assert_eq!(buf[SET_TIME_RESPONSE_LEN - 1], 0x01);
+        assert_eq!(SetTimeResponse::from_wire(&buf[..]).expect("from_wire failed"), response);
+    }
+
+    #[test]
+    fn rejects_short_and_unknown() {
+        assert!(SetTimeRequest::from_wire(&[0u8; SET_TIME_REQUEST_LEN - 1][..]).is_err());
+        assert!(Header::from_wire(&[0x7fu8][..]).is_err());
+        let mut buf = [0u8; SET_TIME_RESPONSE_LEN];
+        buf[SET_TIME_RESPONSE_LEN - 1] = 0x7f;
+        assert!(SetTimeResponse::from_wire(&buf[..]).is_err());
+    }
+}
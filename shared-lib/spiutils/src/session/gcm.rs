@@ -0,0 +1,149 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! AES-128-GCM (NIST SP 800-38D) on top of the software AES.
+//!
+//! GHASH uses a simple bit-by-bit multiplication, which is slow but
+//! constant-time.
+
+use crate::protocol::session::TAG_LEN;
+use crate::session::aes::Aes128;
+use crate::session::aes::BLOCK_LEN;
+use crate::session::IV_LEN;
+
+// Multiplies two elements of GF(2^128) in GCM's bit order.
+fn gf_mul(x: u128, y: u128) -> u128 {
+    let mut product = 0u128;
+    let mut v = y;
+    for i in 0..128 {
+        let bit = (x >> (127 - i)) & 1;
+        product ^= v & 0u128.wrapping_sub(bit);
+        let carry = v & 1;
+        v = (v >> 1) ^ ((0xe1u128 << 120) & 0u128.wrapping_sub(carry));
+    }
+    product
+}
+
+struct Ghash {
+    key: u128,
+    accumulator: u128,
+}
+
+impl Ghash {
+    fn new(cipher: &Aes128) -> Ghash {
+        let mut key = [0u8; BLOCK_LEN];
+        cipher.encrypt_block(&mut key);
+        Ghash { key: u128::from_be_bytes(key), accumulator: 0 }
+    }
+
+    // Absorbs `data`, zero-padded to a whole number of blocks.
+    fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(BLOCK_LEN) {
+            let mut block = [0u8; BLOCK_LEN];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.accumulator = gf_mul(self.accumulator ^ u128::from_be_bytes(block), self.key);
+        }
+    }
+
+    fn finalize(mut self, aad_len: usize, data_len: usize) -> u128 {
+        let lengths = ((aad_len as u128 * 8) << 64) | (data_len as u128 * 8);
+        self.accumulator = gf_mul(self.accumulator ^ lengths, self.key);
+        self.accumulator
+    }
+}
+
+fn counter_block(iv: &[u8; IV_LEN], counter: u32) -> [u8; BLOCK_LEN] {
+    let mut block = [0u8; BLOCK_LEN];
+    block[..IV_LEN].copy_from_slice(iv);
+    block[IV_LEN..].copy_from_slice(&counter.to_be_bytes());
+    block
+}
+
+// Encrypts or decrypts `data` in place with the counter starting at 2.
+fn apply_keystream(cipher: &Aes128, iv: &[u8; IV_LEN], data: &mut [u8]) {
+    for (index, chunk) in data.chunks_mut(BLOCK_LEN).enumerate() {
+        let mut keystream = counter_block(iv, 2 + index as u32);
+        cipher.encrypt_block(&mut keystream);
+        for (byte, mask) in chunk.iter_mut().zip(keystream.iter()) {
+            *byte ^= mask;
+        }
+    }
+}
+
+fn compute_tag(cipher: &Aes128, iv: &[u8; IV_LEN], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LEN] {
+    let mut ghash = Ghash::new(cipher);
+    ghash.update(aad);
+    ghash.update(ciphertext);
+    let mut mask = counter_block(iv, 1);
+    cipher.encrypt_block(&mut mask);
+    (ghash.finalize(aad.len(), ciphertext.len()) ^ u128::from_be_bytes(mask)).to_be_bytes()
+}
+
+/// Encrypts `data` in place and returns the authentication tag over `aad`
+/// and the ciphertext. `iv` must never be reused with the same key.
+pub fn seal(cipher: &Aes128, iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8]) -> [u8; TAG_LEN] {
+    apply_keystream(cipher, iv, data);
+    compute_tag(cipher, iv, aad, data)
+}
+
+/// Checks `tag` and, if it is valid, decrypts `data` in place. Returns
+/// false and leaves `data` untouched if the tag does not match.
+pub fn open(cipher: &Aes128, iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8],
+            tag: &[u8; TAG_LEN]) -> bool {
+    let expected = compute_tag(cipher, iv, aad, data);
+    let difference = expected.iter().zip(tag.iter()).fold(0, |acc, (a, b)| acc | (a ^ b));
+    if difference != 0 {
+        return false;
+    }
+    apply_keystream(cipher, iv, data);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_and_known_answer() {
+        // Test case 4 from the GCM specification.
+        let key = [0xfe, 0xff, 0xe9, 0x92, 0x86, 0x65, 0x73, 0x1c,
+                   0x6d, 0x6a, 0x8f, 0x94, 0x67, 0x30, 0x83, 0x08];
+        let iv = [0xca, 0xfe, 0xba, 0xbe, 0xfa, 0xce, 0xdb, 0xad, 0xde, 0xca, 0xf8, 0x88];
+        let aad = [0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef,
+                   0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef,
+                   0xab, 0xad, 0xda, 0xd2];
+        let plaintext = [
+            0xd9, 0x31, 0x32, 0x25, 0xf8, 0x84, 0x06, 0xe5, 0xa5, 0x59, 0x09, 0xc5,
+            0xaf, 0xf5, 0x26, 0x9a, 0x86, 0xa7, 0xa9, 0x53, 0x15, 0x34, 0xf7, 0xda,
+            0x2e, 0x4c, 0x30, 0x3d, 0x8a, 0x31, 0x8a, 0x72, 0x1c, 0x3c, 0x0c, 0x95,
+            0x95, 0x68, 0x09, 0x53, 0x2f, 0xcf, 0x0e, 0x24, 0x49, 0xa6, 0xb5, 0x25,
+            0xb1, 0x6a, 0xed, 0xf5, 0xaa, 0x0d, 0xe6, 0x57, 0xba, 0x63, 0x7b, 0x39,
+        ];
+        let cipher = Aes128::new(&key);
+        let mut data = plaintext;
+        let tag = seal(&cipher, &iv, &aad, &mut data);
+        assert_eq!(data[..4], [0x42, 0x83, 0x1e, 0xc2]);
+        assert_eq!(tag, [0x5b, 0xc9, 0x4f, 0xbc, 0x32, 0x21, 0xa5, 0xdb,
+                         0x94, 0xfa, 0xe9, 0x5a, 0xe7, 0x12, 0x1a, 0x47]);
+
+        let mut tampered = data;
+        tampered[0] ^= 1;
+        assert!(!open(&cipher, &iv, &aad, &mut tampered, &tag));
+
+        assert!(open(&cipher, &iv, &aad, &mut data, &tag));
+        assert_eq!(data[..], plaintext[..]);
+    }
+}
@@ -0,0 +1,608 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Session establishment and record protection for mailbox traffic.
+//!
+//! The host (initiator) and the chip (responder) each pick an ephemeral
+//! X25519 key pair and a random nonce and exchange them in a handshake
+//! (see [`crate::protocol::session`]). Both sides then derive the session
+//! keys with HKDF-SHA256:
+//!
+//! ```text
+//! PRK = HKDF-Extract(salt = host nonce | chip nonce, IKM = X25519 shared secret)
+//! OKM = HKDF-Expand(PRK, info = LABEL | host public key | chip public key, 56)
+//! ```
+//!
+//! The OKM is split into the AES-128 keys for host-to-chip and chip-to-host
+//! records, followed by a 12-byte IV base for each direction. Records are
+//! sealed with AES-128-GCM. The IV of a record is its IV base XORed with its
+//! sequence number, and the sequence number is also the additional
+//! authenticated data. Each direction starts at sequence number 0 and a
+//! record is only accepted with the next expected sequence number, which
+//! rejects replayed and reordered records.
+//!
+//! The chip authenticates the handshake with its identity key, a P-256 key
+//! derived from the attestation branch of its key ladder. The response
+//! carries the identity public key and an ECDSA signature over the
+//! transcript:
+//!
+//! ```text
+//! SHA-256(TRANSCRIPT_LABEL | host public key | host nonce |
+//!         chip public key | chip nonce | identity public key)
+//! ```
+//!
+//! Hosts record the identity public key when the chip is provisioned and
+//! reject handshakes signed by any other key, so an active attacker on the
+//! bus cannot stand in for the chip. The host is not authenticated.
+//!
+//! Whether the chip also accepts plaintext payloads is set by a [`Policy`].
+//! Before provisioning, hosts need plaintext to set the chip up and to learn
+//! its identity key; afterwards, the chip can insist on sessions.
+//!
+//! The HKDF and AES-GCM computations go through the [`Crypto`] trait, so
+//! that the chip can use the kernel's drivers for them. Hosts use
+//! [`software::SoftwareCrypto`].
+
+#[cfg(feature = "software-crypto")]
+pub mod aes;
+#[cfg(feature = "software-crypto")]
+pub mod gcm;
+#[cfg(feature = "software-crypto")]
+pub mod software;
+
+use crate::protocol::session::HandshakeRequest;
+use crate::protocol::session::HandshakeResponse;
+use crate::protocol::session::HandshakeResult;
+use crate::protocol::session::Record;
+use crate::protocol::session::IDENTITY_KEY_LEN;
+use crate::protocol::session::NONCE_LEN;
+use crate::protocol::session::PUBLIC_KEY_LEN;
+use crate::protocol::session::SIGNATURE_LEN;
+use crate::protocol::session::TAG_LEN;
+
+use ecc::curve25519;
+use ecc::p256;
+use ecc::sha256;
+use ecc::wipe;
+
+/// The length of an ephemeral X25519 private key, in bytes.
+pub const PRIVATE_KEY_LEN: usize = curve25519::KEY_LEN;
+
+/// The length of an AES-128 record key, in bytes.
+pub const KEY_LEN: usize = 16;
+
+/// The length of an AES-GCM IV, in bytes.
+pub const IV_LEN: usize = 12;
+
+/// The length of a handshake transcript digest, in bytes.
+pub const DIGEST_LEN: usize = sha256::DIGEST_LEN;
+
+/// The label mixed into the key derivation.
+const LABEL: &[u8] = b"spiutils session v1";
+
+/// The label at the start of the signed transcript.
+const TRANSCRIPT_LABEL: &[u8] = b"spiutils session v1 transcript";
+
+const INFO_LEN: usize = LABEL.len() + 2 * PUBLIC_KEY_LEN;
+
+const OKM_LEN: usize = 2 * KEY_LEN + 2 * IV_LEN;
+
+/// The length of the serialized session state, in bytes.
+pub const SESSION_STATE_LEN: usize = OKM_LEN + 2 * 4;
+
+/// Errors raised by the session layer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SessionError {
+    /// The peer's public key is invalid or of low order.
+    InvalidPublicKey,
+
+    /// The peer rejected the handshake.
+    HandshakeFailed(HandshakeResult),
+
+    /// The record's sequence number is not the expected one.
+    UnexpectedSequence,
+
+    /// The record failed authentication.
+    AuthenticationFailed,
+
+    /// The output buffer is too small.
+    BufferTooSmall,
+
+    /// All sequence numbers of the session have been used.
+    SequenceExhausted,
+
+    /// The HKDF or AES-GCM implementation failed.
+    Crypto,
+
+    /// The chip's identity key is not the one the host expects.
+    UnknownIdentity,
+
+    /// The handshake signature does not verify under the identity key.
+    BadSignature,
+}
+
+/// When the chip requires mailbox traffic to go through a session.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Policy {
+    /// Plaintext payloads are always accepted.
+    Optional,
+
+    /// Plaintext payloads are accepted until the chip has been provisioned.
+    RequiredAfterProvisioning,
+}
+
+impl Policy {
+    /// Returns whether plaintext payloads must be rejected.
+    pub fn is_required(&self, provisioned: bool) -> bool {
+        match self {
+            Policy::Optional => false,
+            Policy::RequiredAfterProvisioning => provisioned,
+        }
+    }
+}
+
+/// The primitives a session is built on.
+pub trait Crypto {
+    /// Fills `okm` with HKDF-SHA256 (RFC 5869) output.
+    fn hkdf(&self, salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8])
+            -> Result<(), SessionError>;
+
+    /// Encrypts `data` in place with AES-128-GCM and returns the tag.
+    fn seal(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8])
+            -> Result<[u8; TAG_LEN], SessionError>;
+
+    /// Decrypts `data` in place with AES-128-GCM. Fails with
+    /// `AuthenticationFailed` if `tag` does not match, in which case the
+    /// contents of `data` must be discarded.
+    fn open(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8],
+            tag: &[u8; TAG_LEN]) -> Result<(), SessionError>;
+}
+
+/// The chip's identity key, which signs handshake responses.
+pub trait Identity {
+    /// Returns the identity public key, big-endian x and y.
+    fn public_key(&self) -> Result<[u8; IDENTITY_KEY_LEN], SessionError>;
+
+    /// Signs `digest` with ECDSA P-256 and returns big-endian r and s.
+    fn sign(&self, digest: &[u8; DIGEST_LEN]) -> Result<[u8; SIGNATURE_LEN], SessionError>;
+}
+
+/// Which end of the session this is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Role {
+    Initiator,
+    Responder,
+}
+
+/// The keys and sequence number for one direction of a session.
+struct Direction {
+    key: [u8; KEY_LEN],
+    iv_base: [u8; IV_LEN],
+    sequence: u32,
+}
+
+impl Direction {
+    fn new(key: &[u8], iv_base: &[u8], sequence: u32) -> Direction {
+        let mut key_bytes = [0u8; KEY_LEN];
+        key_bytes.copy_from_slice(key);
+        let mut iv_bytes = [0u8; IV_LEN];
+        iv_bytes.copy_from_slice(iv_base);
+        Direction {
+            key: key_bytes,
+            iv_base: iv_bytes,
+            sequence,
+        }
+    }
+
+    fn iv(&self) -> [u8; IV_LEN] {
+        let mut iv = self.iv_base;
+        for (byte, sequence) in iv[IV_LEN - 4..].iter_mut().zip(self.sequence.to_be_bytes().iter()) {
+            *byte ^= sequence;
+        }
+        iv
+    }
+
+    fn advance(&mut self) -> Result<(), SessionError> {
+        self.sequence = self.sequence.checked_add(1).ok_or(SessionError::SequenceExhausted)?;
+        Ok(())
+    }
+}
+
+impl Drop for Direction {
+    fn drop(&mut self) {
+        wipe(&mut self.key);
+        wipe(&mut self.iv_base);
+    }
+}
+
+/// An established session.
+pub struct Session {
+    send: Direction,
+    receive: Direction,
+}
+
+impl Session {
+    fn derive(crypto: &dyn Crypto, role: Role, shared_secret: &[u8; curve25519::KEY_LEN],
+              request: &HandshakeRequest, response: &HandshakeResponse)
+              -> Result<Session, SessionError> {
+        let mut salt = [0u8; 2 * NONCE_LEN];
+        salt[..NONCE_LEN].copy_from_slice(&request.nonce);
+        salt[NONCE_LEN..].copy_from_slice(&response.nonce);
+        let mut info = [0u8; INFO_LEN];
+        let (label, public_keys) = info.split_at_mut(LABEL.len());
+        label.copy_from_slice(LABEL);
+        public_keys[..PUBLIC_KEY_LEN].copy_from_slice(&request.public_key);
+        public_keys[PUBLIC_KEY_LEN..].copy_from_slice(&response.public_key);
+        let mut okm = [0u8; OKM_LEN];
+        if let Err(err) = crypto.hkdf(&salt, shared_secret, &info, &mut okm) {
+            wipe(&mut okm);
+            return Err(err);
+        }
+
+        let (keys, ivs) = okm.split_at(2 * KEY_LEN);
+        let to_responder = Direction::new(&keys[..KEY_LEN], &ivs[..IV_LEN], 0);
+        let to_initiator = Direction::new(&keys[KEY_LEN..], &ivs[IV_LEN..], 0);
+        wipe(&mut okm);
+        match role {
+            Role::Initiator => Ok(Session { send: to_responder, receive: to_initiator }),
+            Role::Responder => Ok(Session { send: to_initiator, receive: to_responder }),
+        }
+    }
+
+    /// Returns the sequence number the next sealed record will use.
+    pub fn next_send_sequence(&self) -> u32 {
+        self.send.sequence
+    }
+
+    /// Encrypts `plaintext` into `output`, followed by the tag, and returns
+    /// the record's sequence number and the number of bytes written.
+    pub fn seal(&mut self, crypto: &dyn Crypto, plaintext: &[u8], output: &mut [u8])
+                -> Result<(u32, usize), SessionError> {
+        let len = plaintext.len() + TAG_LEN;
+        if output.len() < len {
+            return Err(SessionError::BufferTooSmall);
+        }
+        let sequence = self.send.sequence;
+        let (data, tag) = output[..len].split_at_mut(plaintext.len());
+        data.copy_from_slice(plaintext);
+        tag.copy_from_slice(&crypto.seal(&self.send.key, &self.send.iv(),
+                                         &sequence.to_be_bytes(), data)?);
+        self.send.advance()?;
+        Ok((sequence, len))
+    }
+
+    /// Authenticates and decrypts `record` into `output` and returns the
+    /// length of the plaintext.
+    pub fn open(&mut self, crypto: &dyn Crypto, record: &Record, output: &mut [u8])
+                -> Result<usize, SessionError> {
+        if record.data.len() < TAG_LEN {
+            return Err(SessionError::AuthenticationFailed);
+        }
+        if record.sequence != self.receive.sequence {
+            return Err(SessionError::UnexpectedSequence);
+        }
+        let (ciphertext, tag) = record.data.split_at(record.data.len() - TAG_LEN);
+        if output.len() < ciphertext.len() {
+            return Err(SessionError::BufferTooSmall);
+        }
+        let data = &mut output[..ciphertext.len()];
+        data.copy_from_slice(ciphertext);
+        let mut expected_tag = [0u8; TAG_LEN];
+        expected_tag.copy_from_slice(tag);
+        if let Err(err) = crypto.open(&self.receive.key, &self.receive.iv(),
+                                      &record.sequence.to_be_bytes(), data, &expected_tag) {
+            wipe(data);
+            return Err(err);
+        }
+        self.receive.advance()?;
+        Ok(ciphertext.len())
+    }
+
+    /// Serializes the session, for hosts that keep it between processes.
+    /// The result contains the session keys and must be protected.
+    pub fn to_bytes(&self) -> [u8; SESSION_STATE_LEN] {
+        let mut state = [0u8; SESSION_STATE_LEN];
+        let mut offset = 0;
+        for direction in &[&self.send, &self.receive] {
+            state[offset..offset + KEY_LEN].copy_from_slice(&direction.key);
+            offset += KEY_LEN;
+            state[offset..offset + IV_LEN].copy_from_slice(&direction.iv_base);
+            offset += IV_LEN;
+            state[offset..offset + 4].copy_from_slice(&direction.sequence.to_be_bytes());
+            offset += 4;
+        }
+        state
+    }
+
+    /// Restores a session serialized by `to_bytes`.
+    pub fn from_bytes(state: &[u8; SESSION_STATE_LEN]) -> Session {
+        let direction = |offset: usize| {
+            let iv_offset = offset + KEY_LEN;
+            let sequence_offset = iv_offset + IV_LEN;
+            let mut sequence = [0u8; 4];
+            sequence.copy_from_slice(&state[sequence_offset..sequence_offset + 4]);
+            Direction::new(&state[offset..iv_offset], &state[iv_offset..sequence_offset],
+                           u32::from_be_bytes(sequence))
+        };
+        Session {
+            send: direction(0),
+            receive: direction(SESSION_STATE_LEN / 2),
+        }
+    }
+}
+
+fn shared_secret(private_key: &[u8; PRIVATE_KEY_LEN], public_key: &[u8; PUBLIC_KEY_LEN])
+                 -> Result<[u8; curve25519::KEY_LEN], SessionError> {
+    let shared = curve25519::x25519(private_key, public_key);
+    // A low-order public key yields an all-zero secret.
+    if shared.iter().fold(0, |acc, byte| acc | byte) == 0 {
+        return Err(SessionError::InvalidPublicKey);
+    }
+    Ok(shared)
+}
+
+// Hashes the handshake transcript the chip signs. The transcript is public,
+// so it is hashed in software: on the chip, the hash engine may be holding
+// a digest session for the host.
+fn transcript_digest(request: &HandshakeRequest, response: &HandshakeResponse)
+                     -> [u8; DIGEST_LEN] {
+    let mut sha = sha256::Sha256::new();
+    sha.update(TRANSCRIPT_LABEL);
+    sha.update(&request.public_key);
+    sha.update(&request.nonce);
+    sha.update(&response.public_key);
+    sha.update(&response.nonce);
+    sha.update(&response.identity_key);
+    sha.finalize()
+}
+
+fn verify_signature(request: &HandshakeRequest, response: &HandshakeResponse)
+                    -> Result<(), SessionError> {
+    let (x, y) = response.identity_key.split_at(p256::SCALAR_LEN);
+    let (r, s) = response.signature.split_at(p256::SCALAR_LEN);
+    let mut public_key = p256::PublicKey { x: [0; p256::SCALAR_LEN], y: [0; p256::SCALAR_LEN] };
+    public_key.x.copy_from_slice(x);
+    public_key.y.copy_from_slice(y);
+    let mut signature = p256::Signature { r: [0; p256::SCALAR_LEN], s: [0; p256::SCALAR_LEN] };
+    signature.r.copy_from_slice(r);
+    signature.s.copy_from_slice(s);
+    if !public_key.verify(&transcript_digest(request, response), &signature) {
+        return Err(SessionError::BadSignature);
+    }
+    Ok(())
+}
+
+/// The host side of a handshake in progress.
+pub struct Initiator {
+    private_key: [u8; PRIVATE_KEY_LEN],
+    request: HandshakeRequest,
+}
+
+impl Initiator {
+    /// Starts a handshake. `private_key` and `nonce` must be fresh random
+    /// values.
+    pub fn new(private_key: &[u8; PRIVATE_KEY_LEN], nonce: &[u8; NONCE_LEN]) -> Initiator {
+        Initiator {
+            private_key: *private_key,
+            request: HandshakeRequest {
+                public_key: curve25519::x25519_public_key(private_key),
+                nonce: *nonce,
+            },
+        }
+    }
+
+    /// Returns the request to send to the chip.
+    pub fn request(&self) -> HandshakeRequest {
+        self.request
+    }
+
+    /// Completes the handshake with the chip's response. The response must
+    /// be signed by `identity_key`, the chip's identity public key recorded
+    /// at provisioning.
+    ///
+    /// Without an expected key, any correctly signed response is accepted.
+    /// That only suits hosts learning the key of an unprovisioned chip,
+    /// which they can read from the response.
+    pub fn finish(self, crypto: &dyn Crypto, response: &HandshakeResponse,
+                  identity_key: Option<&[u8; IDENTITY_KEY_LEN]>)
+                  -> Result<Session, SessionError> {
+        if response.result != HandshakeResult::Success {
+            return Err(SessionError::HandshakeFailed(response.result));
+        }
+        match identity_key {
+            Some(key) if *key != response.identity_key => return Err(SessionError::UnknownIdentity),
+            _ => (),
+        }
+        verify_signature(&self.request, response)?;
+        let mut shared = shared_secret(&self.private_key, &response.public_key)?;
+        let session = Session::derive(crypto, Role::Initiator, &shared, &self.request, response);
+        wipe(&mut shared);
+        session
+    }
+}
+
+impl Drop for Initiator {
+    fn drop(&mut self) {
+        wipe(&mut self.private_key);
+    }
+}
+
+/// Answers a handshake request on the chip side. `private_key` and `nonce`
+/// must be fresh random values.
+///
+/// On failure, the returned response reports the error to the host.
+pub fn respond(crypto: &dyn Crypto, identity: &dyn Identity, request: &HandshakeRequest,
+               private_key: &[u8; PRIVATE_KEY_LEN], nonce: &[u8; NONCE_LEN])
+               -> (HandshakeResponse, Option<Session>) {
+    let mut response = HandshakeResponse {
+        result: HandshakeResult::Success,
+        public_key: curve25519::x25519_public_key(private_key),
+        nonce: *nonce,
+        identity_key: [0; IDENTITY_KEY_LEN],
+        signature: [0; SIGNATURE_LEN],
+    };
+    let mut shared = match shared_secret(private_key, &request.public_key) {
+        Ok(shared) => shared,
+        Err(_) => {
+            response.result = HandshakeResult::InvalidPublicKey;
+            return (response, None);
+        }
+    };
+    let session = Session::derive(crypto, Role::Responder, &shared, request, &response);
+    wipe(&mut shared);
+    let signed = identity.public_key().and_then(|identity_key| {
+        response.identity_key = identity_key;
+        identity.sign(&transcript_digest(request, &response))
+    });
+    match (session, signed) {
+        (Ok(session), Ok(signature)) => {
+            response.signature = signature;
+            (response, Some(session))
+        }
+        _ => {
+            response.result = HandshakeResult::Error;
+            response.identity_key = [0; IDENTITY_KEY_LEN];
+            (response, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::software::SoftwareCrypto;
+
+    // Signs with a fixed nonce, which is only acceptable in tests.
+    struct TestIdentity(p256::PrivateKey);
+
+    impl TestIdentity {
+        fn new() -> TestIdentity {
+            TestIdentity(p256::PrivateKey::from_bytes(&[0x55; p256::SCALAR_LEN]).unwrap())
+        }
+    }
+
+    impl Identity for TestIdentity {
+        fn public_key(&self) -> Result<[u8; IDENTITY_KEY_LEN], SessionError> {
+            let public_key = self.0.public_key();
+            let mut bytes = [0u8; IDENTITY_KEY_LEN];
+            bytes[..p256::SCALAR_LEN].copy_from_slice(&public_key.x);
+            bytes[p256::SCALAR_LEN..].copy_from_slice(&public_key.y);
+            Ok(bytes)
+        }
+
+        fn sign(&self, digest: &[u8; DIGEST_LEN]) -> Result<[u8; SIGNATURE_LEN], SessionError> {
+            let signature = self.0.sign(digest, &[0x66; p256::SCALAR_LEN])
+                .map_err(|_| SessionError::Crypto)?;
+            let mut bytes = [0u8; SIGNATURE_LEN];
+            bytes[..p256::SCALAR_LEN].copy_from_slice(&signature.r);
+            bytes[p256::SCALAR_LEN..].copy_from_slice(&signature.s);
+            Ok(bytes)
+        }
+    }
+
+    fn start() -> (Initiator, HandshakeResponse, Option<Session>) {
+        let initiator = Initiator::new(&[0x11; PRIVATE_KEY_LEN], &[0x22; NONCE_LEN]);
+        let (response, responder) = respond(&SoftwareCrypto, &TestIdentity::new(),
+                                            &initiator.request(), &[0x33; PRIVATE_KEY_LEN],
+                                            &[0x44; NONCE_LEN]);
+        (initiator, response, responder)
+    }
+
+    fn handshake() -> (Session, Session) {
+        let (initiator, response, responder) = start();
+        let identity_key = TestIdentity::new().public_key().unwrap();
+        (initiator.finish(&SoftwareCrypto, &response, Some(&identity_key)).unwrap(),
+         responder.unwrap())
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let (mut host, mut chip) = handshake();
+        let mut sealed = [0u8; 64];
+        let mut opened = [0u8; 64];
+
+        for message in &[&b"first"[..], &b"second message"[..]] {
+            let (sequence, len) = host.seal(&SoftwareCrypto, message, &mut sealed).unwrap();
+            let record = Record { sequence, data: &sealed[..len] };
+            let opened_len = chip.open(&SoftwareCrypto, &record, &mut opened).unwrap();
+            assert_eq!(&opened[..opened_len], *message);
+        }
+
+        let (sequence, len) = chip.seal(&SoftwareCrypto, b"reply", &mut sealed).unwrap();
+        assert_eq!(sequence, 0);
+        let opened_len = host.open(&SoftwareCrypto, &Record { sequence, data: &sealed[..len] }, &mut opened).unwrap();
+        assert_eq!(&opened[..opened_len], b"reply");
+    }
+
+    #[test]
+    fn rejects_tampered_and_replayed_records() {
+        let (mut host, mut chip) = handshake();
+        let mut sealed = [0u8; 32];
+        let mut opened = [0u8; 32];
+        let (sequence, len) = host.seal(&SoftwareCrypto, b"payload", &mut sealed).unwrap();
+
+        sealed[0] ^= 1;
+        assert_eq!(chip.open(&SoftwareCrypto, &Record { sequence, data: &sealed[..len] }, &mut opened),
+                   Err(SessionError::AuthenticationFailed));
+        sealed[0] ^= 1;
+        assert!(chip.open(&SoftwareCrypto, &Record { sequence, data: &sealed[..len] }, &mut opened).is_ok());
+        assert_eq!(chip.open(&SoftwareCrypto, &Record { sequence, data: &sealed[..len] }, &mut opened),
+                   Err(SessionError::UnexpectedSequence));
+    }
+
+    #[test]
+    fn state_survives_serialization() {
+        let (host, mut chip) = handshake();
+        let mut host = Session::from_bytes(&host.to_bytes());
+        let mut sealed = [0u8; 32];
+        let mut opened = [0u8; 32];
+        let (sequence, len) = host.seal(&SoftwareCrypto, b"payload", &mut sealed).unwrap();
+        assert!(chip.open(&SoftwareCrypto, &Record { sequence, data: &sealed[..len] }, &mut opened).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_identity() {
+        let (initiator, response, _) = start();
+        assert_eq!(initiator.finish(&SoftwareCrypto, &response, Some(&[0x77; IDENTITY_KEY_LEN]))
+                       .err(),
+                   Some(SessionError::UnknownIdentity));
+    }
+
+    #[test]
+    fn rejects_tampered_handshake() {
+        let (initiator, mut response, _) = start();
+        response.nonce[0] ^= 1;
+        let identity_key = response.identity_key;
+        assert_eq!(initiator.finish(&SoftwareCrypto, &response, Some(&identity_key)).err(),
+                   Some(SessionError::BadSignature));
+    }
+
+    #[test]
+    fn session_required_after_provisioning() {
+        assert!(!Policy::Optional.is_required(true));
+        assert!(!Policy::RequiredAfterProvisioning.is_required(false));
+        assert!(Policy::RequiredAfterProvisioning.is_required(true));
+    }
+
+    #[test]
+    fn rejects_low_order_public_key() {
+        let request = HandshakeRequest { public_key: [0; PUBLIC_KEY_LEN], nonce: [0; NONCE_LEN] };
+        let (response, session) = respond(&SoftwareCrypto, &TestIdentity::new(), &request,
+                                          &[0x33; PRIVATE_KEY_LEN], &[0x44; NONCE_LEN]);
+        assert_eq!(response.result, HandshakeResult::InvalidPublicKey);
+        assert!(session.is_none());
+    }
+}
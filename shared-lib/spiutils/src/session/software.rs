@@ -0,0 +1,65 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The software implementation of [`Crypto`], for hosts.
+//!
+//! The chip does not use it: it has hardware AES and a kernel HKDF.
+
+use crate::protocol::session::TAG_LEN;
+use crate::session::aes::Aes128;
+use crate::session::gcm;
+use crate::session::Crypto;
+use crate::session::SessionError;
+use crate::session::IV_LEN;
+use crate::session::KEY_LEN;
+
+use ecc::sha256;
+use ecc::wipe;
+
+/// HKDF and AES-GCM computed in software.
+pub struct SoftwareCrypto;
+
+impl Crypto for SoftwareCrypto {
+    fn hkdf(&self, salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8])
+            -> Result<(), SessionError> {
+        let mut prk = sha256::hmac(salt, &[ikm]);
+        let mut block = [0u8; sha256::DIGEST_LEN];
+        for (index, chunk) in okm.chunks_mut(sha256::DIGEST_LEN).enumerate() {
+            // T(0) is empty; T(n) = HMAC(PRK, T(n-1) | info | n).
+            let previous: &[u8] = if index == 0 { &[] } else { &block };
+            let counter = [index as u8 + 1];
+            let next = sha256::hmac(&prk, &[previous, info, &counter]);
+            block = next;
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        wipe(&mut block);
+        wipe(&mut prk);
+        Ok(())
+    }
+
+    fn seal(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8])
+            -> Result<[u8; TAG_LEN], SessionError> {
+        Ok(gcm::seal(&Aes128::new(key), iv, aad, data))
+    }
+
+    fn open(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8],
+            tag: &[u8; TAG_LEN]) -> Result<(), SessionError> {
+        if !gcm::open(&Aes128::new(key), iv, aad, data, tag) {
+            return Err(SessionError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+}
@@ -23,14 +23,249 @@ use core::convert::TryFrom;
 
 use spiutils::io::StdWrite;
 use spiutils::io::Write;
+use spiutils::protocol::digest;
 use spiutils::protocol::payload;
+use spiutils::protocol::session;
 use spiutils::protocol::wire::FromWire;
 use spiutils::protocol::wire::ToWire;
+use spiutils::session::software::SoftwareCrypto;
+use spiutils::session::Initiator;
+use spiutils::session::Session;
+use spiutils::session::PRIVATE_KEY_LEN;
+use spiutils::session::SESSION_STATE_LEN;
 
+use std::convert::TryInto;
+use std::fs;
 use std::fs::OpenOptions;
 use std::io::Read as _;
 
-fn wrap(input_file: &str, output_file: &str) {
+// Length of the state file between session-start and session-finish.
+const HANDSHAKE_STATE_LEN: usize = PRIVATE_KEY_LEN + session::NONCE_LEN;
+
+// Returns a payload with a valid checksum wrapping `content`.
+fn to_payload(content: payload::ContentType, data: &[u8]) -> Vec<u8> {
+    let mut header = payload::Header {
+        content,
+        content_len: u16::try_from(data.len()).expect("payload too large"),
+        checksum: 0,
+        header_crc: None,
+    };
+    header.checksum = payload::compute_checksum(&header, data);
+
+    let mut buf = Vec::new();
+    let mut stdwrite = StdWrite(&mut buf);
+    header
+        .to_wire(&mut stdwrite)
+        .expect("failed to write header");
+    stdwrite
+        .write_bytes(data)
+        .expect("failed to write payload");
+    buf
+}
+
+// Parses a payload and returns its header and content.
+fn from_payload(data: &[u8]) -> (payload::Header, &[u8]) {
+    match payload::parse_payload(data) {
+        Ok(parsed) => parsed,
+        Err(payload::PayloadError::BadChecksum) => panic!("Bad payload checksum"),
+        Err(err) => panic!("failed to read payload: {:?}", err),
+    }
+}
+
+fn read_session(state_file: &str) -> Session {
+    let bytes = fs::read(state_file).expect("failed to read session state");
+    if bytes.len() != SESSION_STATE_LEN {
+        panic!("Invalid session state; run session-finish first");
+    }
+    let mut state = [0u8; SESSION_STATE_LEN];
+    state.copy_from_slice(&bytes);
+    Session::from_bytes(&state)
+}
+
+fn write_session(state_file: &str, session: &Session) {
+    fs::write(state_file, &session.to_bytes()[..]).expect("failed to write session state");
+}
+
+// Seals `payload` into a record and wraps it in a session payload.
+fn seal_payload(state_file: &str, payload: &[u8]) -> Vec<u8> {
+    let mut session = read_session(state_file);
+    let mut sealed = vec![0u8; payload.len() + session::TAG_LEN];
+    let (sequence, len) = session.seal(&SoftwareCrypto, payload, &mut sealed).expect("failed to seal record");
+    write_session(state_file, &session);
+
+    let mut buf = Vec::new();
+    let mut stdwrite = StdWrite(&mut buf);
+    session::Header { content: session::ContentType::Record }
+        .to_wire(&mut stdwrite)
+        .expect("failed to write session header");
+    session::Record { sequence, data: &sealed[..len] }
+        .to_wire(&mut stdwrite)
+        .expect("failed to write record");
+    to_payload(payload::ContentType::Session, &buf)
+}
+
+// Opens the record in the session payload `data` and returns the inner payload.
+fn open_payload(state_file: &str, mut data: &[u8]) -> Vec<u8> {
+    let header = session::Header::from_wire(&mut data).expect("failed to read session header");
+    if header.content != session::ContentType::Record {
+        panic!("Unexpected session content type {:?}", header.content);
+    }
+    let record = session::Record::from_wire(&mut data).expect("failed to read record");
+
+    let mut session = read_session(state_file);
+    let mut opened = vec![0u8; record.data.len()];
+    let len = session.open(&SoftwareCrypto, &record, &mut opened).expect("failed to open record");
+    write_session(state_file, &session);
+    opened.truncate(len);
+    opened
+}
+
+fn session_start(state_file: &str, output_file: &str) {
+    let mut state = [0u8; HANDSHAKE_STATE_LEN];
+    OpenOptions::new()
+        .read(true)
+        .open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut state))
+        .expect("failed to read random data");
+    let (private_key, nonce) = state.split_at(PRIVATE_KEY_LEN);
+    let initiator = Initiator::new(private_key.try_into().unwrap(), nonce.try_into().unwrap());
+
+    let mut buf = Vec::new();
+    let mut stdwrite = StdWrite(&mut buf);
+    session::Header { content: session::ContentType::HandshakeRequest }
+        .to_wire(&mut stdwrite)
+        .expect("failed to write session header");
+    initiator.request()
+        .to_wire(&mut stdwrite)
+        .expect("failed to write handshake request");
+
+    fs::write(state_file, &state[..]).expect("failed to write handshake state");
+    fs::write(output_file, to_payload(payload::ContentType::Session, &buf))
+        .expect("failed to write output file");
+}
+
+// Reads the chip's identity public key, as written by session-finish with
+// --learn-identity-key.
+fn read_identity_key(identity_file: &str) -> [u8; session::IDENTITY_KEY_LEN] {
+    fs::read(identity_file)
+        .expect("failed to read identity key")
+        .as_slice()
+        .try_into()
+        .expect("Invalid identity key file")
+}
+
+// Finishes the handshake. The chip must sign with the key in
+// `identity_file`, or, when `learn_identity_file` is given instead, with any
+// key, which is then written there. Only learn the key of a chip that has
+// not been provisioned yet, over a bus that can be trusted.
+fn session_finish(state_file: &str, input_file: &str, identity_file: Option<&str>,
+                  learn_identity_file: Option<&str>) {
+    let state = fs::read(state_file).expect("failed to read handshake state");
+    if state.len() != HANDSHAKE_STATE_LEN {
+        panic!("Invalid handshake state; run session-start first");
+    }
+    let (private_key, nonce) = state.split_at(PRIVATE_KEY_LEN);
+    let initiator = Initiator::new(private_key.try_into().unwrap(), nonce.try_into().unwrap());
+
+    let read_buf = fs::read(input_file).expect("failed to read input file");
+    let (header, mut data) = from_payload(&read_buf);
+    if header.content != payload::ContentType::Session {
+        panic!("Unexpected content type {:?}", header.content);
+    }
+    let session_header = session::Header::from_wire(&mut data).expect("failed to read session header");
+    if session_header.content != session::ContentType::HandshakeResponse {
+        panic!("Unexpected session content type {:?}", session_header.content);
+    }
+    let response = session::HandshakeResponse::from_wire(&mut data)
+        .expect("failed to read handshake response");
+
+    let identity_key = identity_file.map(read_identity_key);
+    let session = initiator.finish(&SoftwareCrypto, &response, identity_key.as_ref())
+        .expect("handshake failed");
+    if let Some(learn_identity_file) = learn_identity_file {
+        fs::write(learn_identity_file, &response.identity_key[..])
+            .expect("failed to write identity key");
+    }
+    write_session(state_file, &session);
+}
+
+// Writes the digest request `request` to `output_file`, sealed in a record if
+// there is a session.
+fn write_digest_request<'m, M: digest::Message<'m>>(request: M, output_file: &str, session_file: Option<&str>) {
+    let mut buf = Vec::new();
+    let mut stdwrite = StdWrite(&mut buf);
+    digest::Header { content: M::TYPE }
+        .to_wire(&mut stdwrite)
+        .expect("failed to write digest header");
+    request
+        .to_wire(&mut stdwrite)
+        .expect("failed to write digest request");
+
+    let mut payload = to_payload(payload::ContentType::Digest, &buf);
+    if let Some(session_file) = session_file {
+        payload = seal_payload(session_file, &payload);
+    }
+    fs::write(output_file, payload).expect("failed to write output file");
+}
+
+fn digest_init(algorithm: &str, output_file: &str, session_file: Option<&str>) {
+    let algorithm = match algorithm {
+        "sha1" => digest::Algorithm::Sha1,
+        "sha256" => digest::Algorithm::Sha256,
+        _ => panic!("Unsupported digest algorithm {}", algorithm),
+    };
+    write_digest_request(digest::InitRequest { algorithm }, output_file, session_file);
+}
+
+fn digest_update(session_id: u32, offset: u32, input_file: &str, output_file: &str, session_file: Option<&str>) {
+    let data = fs::read(input_file).expect("failed to read input file");
+    let request = digest::UpdateRequest { session_id, offset, data: &data };
+    write_digest_request(request, output_file, session_file);
+}
+
+fn digest_final(session_id: u32, output_file: &str, session_file: Option<&str>) {
+    write_digest_request(digest::FinalRequest { session_id }, output_file, session_file);
+}
+
+// Prints the digest response in `data`. The digest of a final response is
+// written to `output`.
+fn unwrap_digest(mut data: &[u8], output: &mut fs::File) {
+    let header = digest::Header::from_wire(&mut data).expect("failed to read digest header");
+    match header.content {
+        digest::ContentType::InitResponse => {
+            let response = digest::InitResponse::from_wire(&mut data)
+                .expect("failed to read init response");
+            println!("{:?}", response);
+        }
+        digest::ContentType::UpdateResponse => {
+            let response = digest::UpdateResponse::from_wire(&mut data)
+                .expect("failed to read update response");
+            println!("{:?}", response);
+        }
+        digest::ContentType::FinalResponse => {
+            let response = digest::FinalResponse::from_wire(&mut data)
+                .expect("failed to read final response");
+            println!("session_id={} result={:?}", response.session_id, response.result);
+            StdWrite(output)
+                .write_bytes(response.digest)
+                .expect("failed to write digest");
+        }
+        _ => {
+            panic!("Unexpected digest content type {:?}", header.content);
+        }
+    }
+}
+
+fn parse_u32(value: &str) -> u32 {
+    let parsed = if value.starts_with("0x") {
+        u32::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse()
+    };
+    parsed.expect("invalid number")
+}
+
+fn wrap(input_file: &str, output_file: &str, session_file: Option<&str>) {
     let mut input = OpenOptions::new()
         .read(true)
         .open(&input_file)
@@ -47,22 +282,18 @@ fn wrap(input_file: &str, output_file: &str) {
         .read_to_end(&mut read_buf)
         .expect("couldn't read from file");
 
-    let header = payload::Header {
-        content: payload::ContentType::Manticore,
-        content_len: u16::try_from(read_buf.len()).unwrap(),
-        checksum: 0,
-    };
+    let mut payload = to_payload(payload::ContentType::Manticore, &read_buf);
+    if let Some(session_file) = session_file {
+        payload = seal_payload(session_file, &payload);
+    }
 
     let mut stdwrite = StdWrite(&mut output);
-    header
-        .to_wire(&mut stdwrite)
-        .expect("failed to write header");
     stdwrite
-        .write_bytes(&read_buf.as_slice())
+        .write_bytes(&payload)
         .expect("failed to write payload");
 }
 
-fn unwrap(input_file: &str, output_file: &str) {
+fn unwrap(input_file: &str, output_file: &str, session_file: Option<&str>) {
     let mut input = OpenOptions::new()
         .read(true)
         .open(&input_file)
@@ -79,17 +310,27 @@ fn unwrap(input_file: &str, output_file: &str) {
         .read_to_end(&mut read_buf)
         .expect("couldn't read from file");
 
-    let mut read_buf_slice = read_buf.as_slice();
-    println!("read_buf_slice.len={}", read_buf_slice.len());
-    let header = payload::Header::from_wire(&mut read_buf_slice).expect("failed to read header");
+    println!("read_buf.len={}", read_buf.len());
+    if let Some(session_file) = session_file {
+        let (header, data) = from_payload(&read_buf);
+        if header.content != payload::ContentType::Session {
+            panic!("Expected a session record, got content type {:?}", header.content);
+        }
+        read_buf = open_payload(session_file, data);
+    }
+
+    let (header, content) = from_payload(&read_buf);
 
     match header.content {
         payload::ContentType::Manticore => {
             let mut stdwrite = StdWrite(&mut output);
             stdwrite
-                .write_bytes(&mut &read_buf_slice[..header.content_len as usize])
+                .write_bytes(content)
                 .expect("failed to write payload");
         }
+        payload::ContentType::Digest => {
+            unwrap_digest(content, &mut output);
+        }
         _ => {
             panic!("Unsupported content type {:?}", header.content);
         }
@@ -127,6 +368,13 @@ fn main() {
                         .help("output file for wrapped message")
                         .required(true)
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("session")
+                        .short("s")
+                        .long("session")
+                        .help("session state file; seals or opens a session record")
+                        .takes_value(true),
                 ),
         )
         .subcommand(
@@ -147,6 +395,158 @@ fn main() {
                         .help("output file for unwrapped message")
                         .required(true)
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("session")
+                        .short("s")
+                        .long("session")
+                        .help("session state file; seals or opens a session record")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("session-start")
+                .about("Start a session handshake")
+                .arg(
+                    Arg::with_name("state")
+                        .long("state")
+                        .help("file to keep the handshake state in")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("output file for wrapped handshake request")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("session-finish")
+                .about("Finish a session handshake")
+                .arg(
+                    Arg::with_name("state")
+                        .long("state")
+                        .help("file containing the handshake state; replaced by the session state")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .help("input file containing wrapped handshake response")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("identity-key")
+                        .long("identity-key")
+                        .help("file containing the chip's identity public key; the handshake must be signed with it")
+                        .required_unless("learn-identity-key")
+                        .conflicts_with("learn-identity-key")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("learn-identity-key")
+                        .long("learn-identity-key")
+                        .help("file to write the chip's identity public key to; only for chips that are not provisioned yet")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("digest-init")
+                .about("Start a digest session; unwrap prints the response")
+                .arg(
+                    Arg::with_name("algorithm")
+                        .long("algorithm")
+                        .help("digest algorithm")
+                        .possible_values(&["sha1", "sha256"])
+                        .default_value("sha256"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("output file for wrapped digest request")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("session")
+                        .short("s")
+                        .long("session")
+                        .help("session state file; seals the request in a session record")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("digest-update")
+                .about("Hash a chunk of data in a digest session")
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .help("digest session id from the init response")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("offset")
+                        .long("offset")
+                        .help("offset of the chunk within the message")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .help("input file containing the chunk; must fit in the mailbox")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("output file for wrapped digest request")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("session")
+                        .short("s")
+                        .long("session")
+                        .help("session state file; seals the request in a session record")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("digest-final")
+                .about("Finish a digest session; unwrap writes the digest")
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .help("digest session id from the init response")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("output file for wrapped digest request")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("session")
+                        .short("s")
+                        .long("session")
+                        .help("session state file; seals the request in a session record")
+                        .takes_value(true),
                 ),
         );
     let matches = app.get_matches();
@@ -155,11 +555,45 @@ fn main() {
         wrap(
             matches.value_of("input").unwrap(),
             matches.value_of("output").unwrap(),
+            matches.value_of("session"),
         );
     } else if let Some(matches) = matches.subcommand_matches("unwrap") {
         unwrap(
             matches.value_of("input").unwrap(),
             matches.value_of("output").unwrap(),
+            matches.value_of("session"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("session-start") {
+        session_start(
+            matches.value_of("state").unwrap(),
+            matches.value_of("output").unwrap(),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("session-finish") {
+        session_finish(
+            matches.value_of("state").unwrap(),
+            matches.value_of("input").unwrap(),
+            matches.value_of("identity-key"),
+            matches.value_of("learn-identity-key"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("digest-init") {
+        digest_init(
+            matches.value_of("algorithm").unwrap(),
+            matches.value_of("output").unwrap(),
+            matches.value_of("session"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("digest-update") {
+        digest_update(
+            parse_u32(matches.value_of("id").unwrap()),
+            parse_u32(matches.value_of("offset").unwrap()),
+            matches.value_of("input").unwrap(),
+            matches.value_of("output").unwrap(),
+            matches.value_of("session"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("digest-final") {
+        digest_final(
+            parse_u32(matches.value_of("id").unwrap()),
+            matches.value_of("output").unwrap(),
+            matches.value_of("session"),
         );
     }
 }
@@ -0,0 +1,146 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The alarm driver (capsules::alarm), over `std::time`.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::fake::FakeDriver;
+use crate::result::EALREADY;
+use crate::result::ENOSUPPORT;
+
+const DRIVER_NUMBER: usize = 0;
+
+/// The clock frequency reported to the app, the same as the H1 alarm's.
+pub const FREQUENCY_HZ: u32 = 256_000;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_CLOCK_FREQUENCY: usize = 1;
+    pub const GET_NOW: usize = 2;
+    pub const STOP_ALARM: usize = 3;
+    pub const SET_ABSOLUTE_ALARM: usize = 4;
+    pub const SET_RELATIVE_ALARM: usize = 5;
+}
+
+mod subscribe_nr {
+    pub const ALARM_EXPIRED: usize = 0;
+}
+
+/// An alarm that counts real time since it was installed. The clock is 32
+/// bits wide and wraps, as on the device.
+pub struct Alarm {
+    start: Instant,
+    // The alarm time in ticks, and when it expires.
+    armed: Cell<Option<(u32, Instant)>>,
+}
+
+impl Alarm {
+    /// Installs an alarm as driver 0.
+    pub fn install() -> Rc<Alarm> {
+        let alarm = Rc::new(Alarm {
+            start: Instant::now(),
+            armed: Cell::new(None),
+        });
+        crate::install(DRIVER_NUMBER, alarm.clone());
+        alarm
+    }
+
+    /// Returns the current clock value in ticks.
+    pub fn now(&self) -> u32 {
+        let elapsed = self.start.elapsed();
+        (elapsed.as_nanos() * FREQUENCY_HZ as u128 / 1_000_000_000) as u32
+    }
+
+    fn arm(&self, dt: u32) -> usize {
+        let alarm_time = self.now().wrapping_add(dt);
+        let delay = Duration::from_nanos(dt as u64 * 1_000_000_000 / FREQUENCY_HZ as u64);
+        self.armed.set(Some((alarm_time, Instant::now() + delay)));
+        alarm_time as usize
+    }
+}
+
+impl FakeDriver for Alarm {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize) -> Result<usize, isize> {
+        match command_num {
+            command_nr::CHECK_IF_PRESENT => Ok(0),
+            command_nr::GET_CLOCK_FREQUENCY => Ok(FREQUENCY_HZ as usize),
+            command_nr::GET_NOW => Ok(self.now() as usize),
+            command_nr::STOP_ALARM => match self.armed.take() {
+                Some(_) => Ok(0),
+                None => Err(EALREADY),
+            },
+            command_nr::SET_ABSOLUTE_ALARM => Ok(self.arm((arg1 as u32).wrapping_sub(self.now()))),
+            command_nr::SET_RELATIVE_ALARM => Ok(self.arm(arg1 as u32)),
+            _ => Err(ENOSUPPORT),
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.armed.get().map(|(_, deadline)| deadline)
+    }
+
+    fn expire(&self) {
+        if let Some((alarm_time, _)) = self.armed.take() {
+            crate::schedule_upcall(DRIVER_NUMBER, subscribe_nr::ALARM_EXPIRED,
+                                   self.now() as usize, alarm_time as usize, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syscalls;
+    use std::cell::Cell;
+
+    thread_local! {
+        static EXPIRED: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    extern "C" fn alarm_expired(_now: usize, alarm_time: usize, _: usize, _data: usize) {
+        EXPIRED.with(|expired| expired.set(Some(alarm_time)));
+    }
+
+    #[test]
+    fn relative_alarm_fires_after_its_delay() {
+        let alarm = Alarm::install();
+        syscalls::subscribe_fn(DRIVER_NUMBER, subscribe_nr::ALARM_EXPIRED, alarm_expired, 0)
+            .unwrap();
+        let start = Instant::now();
+        let alarm_time = syscalls::command(DRIVER_NUMBER, command_nr::SET_RELATIVE_ALARM,
+                                           FREQUENCY_HZ as usize / 100, 0).unwrap();
+        unsafe { syscalls::raw::yieldk(); }
+        assert_eq!(EXPIRED.with(|expired| expired.get()), Some(alarm_time));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(alarm.now().wrapping_sub(alarm_time as u32) < FREQUENCY_HZ);
+    }
+
+    #[test]
+    #[should_panic(expected = "sleep forever")]
+    fn yield_without_alarm_panics() {
+        Alarm::install();
+        syscalls::subscribe_fn(DRIVER_NUMBER, subscribe_nr::ALARM_EXPIRED, alarm_expired, 0)
+            .unwrap();
+        let alarm_time = syscalls::command(DRIVER_NUMBER, command_nr::SET_RELATIVE_ALARM,
+                                           FREQUENCY_HZ as usize, 0).unwrap();
+        syscalls::command(DRIVER_NUMBER, command_nr::STOP_ALARM, alarm_time, 0).unwrap();
+        unsafe { syscalls::raw::yieldk(); }
+    }
+}
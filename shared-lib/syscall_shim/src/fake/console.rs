@@ -0,0 +1,209 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The console driver (capsules::console), over stdout and a byte queue.
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::rc::Weak;
+
+use crate::fake::FakeDriver;
+use crate::result::ECANCEL;
+use crate::result::ERESERVE;
+use crate::result::SUCCESS;
+
+const DRIVER_NUMBER: usize = 1;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const WRITE: usize = 1;
+    pub const READ: usize = 2;
+    pub const ABORT_READ: usize = 3;
+}
+
+mod subscribe_nr {
+    pub const WRITE_DONE: usize = 1;
+    pub const READ_DONE: usize = 2;
+}
+
+mod allow_nr {
+    pub const WRITE_BUFFER: usize = 1;
+    pub const READ_BUFFER: usize = 2;
+}
+
+thread_local! {
+    // The console `println!` writes to.
+    static INSTALLED: RefCell<Weak<Console>> = const { RefCell::new(Weak::new()) };
+}
+
+/// Writes `text` to the installed console, or to stdout if there is none.
+pub(crate) fn write(text: &str) {
+    match INSTALLED.with(|installed| installed.borrow().upgrade()) {
+        Some(console) => console.write(text.as_bytes()),
+        None => print!("{}", text),
+    }
+}
+
+/// A console that prints what the app writes and reads what the test types.
+#[derive(Default)]
+pub struct Console {
+    output: RefCell<Vec<u8>>,
+    input: RefCell<VecDeque<u8>>,
+    // The length of the pending read, if any.
+    read_len: Cell<Option<usize>>,
+}
+
+impl Console {
+    /// Installs a console as driver 1.
+    pub fn install() -> Rc<Console> {
+        let console = Rc::new(Console::default());
+        crate::install(DRIVER_NUMBER, console.clone());
+        INSTALLED.with(|installed| *installed.borrow_mut() = Rc::downgrade(&console));
+        console
+    }
+
+    /// Returns everything the app wrote so far.
+    pub fn output(&self) -> Vec<u8> {
+        self.output.borrow().clone()
+    }
+
+    /// Makes `data` available to the app's reads.
+    pub fn type_input(&self, data: &[u8]) {
+        self.input.borrow_mut().extend(data);
+        self.complete_read();
+    }
+
+    fn write(&self, data: &[u8]) {
+        self.output.borrow_mut().extend_from_slice(data);
+        print!("{}", String::from_utf8_lossy(data));
+    }
+
+    fn complete_read(&self) {
+        let len = match self.read_len.get() {
+            Some(len) if !self.input.borrow().is_empty() => len,
+            _ => return,
+        };
+        let count = crate::with_allowed(DRIVER_NUMBER, allow_nr::READ_BUFFER, |buffer| {
+            let mut input = self.input.borrow_mut();
+            let count = len.min(buffer.len()).min(input.len());
+            for (dst, src) in buffer.iter_mut().zip(input.drain(..count)) {
+                *dst = src;
+            }
+            count
+        });
+        self.read_len.set(None);
+        match count {
+            Some(count) => crate::schedule_upcall(DRIVER_NUMBER, subscribe_nr::READ_DONE,
+                                                  SUCCESS as usize, count, 0),
+            None => crate::schedule_upcall(DRIVER_NUMBER, subscribe_nr::READ_DONE,
+                                           ERESERVE as usize, 0, 0),
+        }
+    }
+}
+
+impl FakeDriver for Console {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize) -> Result<usize, isize> {
+        match command_num {
+            command_nr::CHECK_IF_PRESENT => Ok(0),
+            command_nr::WRITE => {
+                let written = crate::with_allowed(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, |buffer| {
+                    let data = &buffer[..arg1.min(buffer.len())];
+                    self.write(data);
+                    data.len()
+                }).ok_or(ERESERVE)?;
+                crate::schedule_upcall(DRIVER_NUMBER, subscribe_nr::WRITE_DONE, written, 0, 0);
+                Ok(0)
+            },
+            command_nr::READ => {
+                self.read_len.set(Some(arg1));
+                self.complete_read();
+                Ok(0)
+            },
+            command_nr::ABORT_READ => {
+                if self.read_len.take().is_some() {
+                    crate::schedule_upcall(DRIVER_NUMBER, subscribe_nr::READ_DONE,
+                                           ECANCEL as usize, 0, 0);
+                }
+                Ok(0)
+            },
+            _ => Err(crate::result::ENOSUPPORT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::result::ENODEVICE;
+    use crate::syscalls;
+
+    thread_local! {
+        static DONE: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    }
+
+    extern "C" fn done(arg1: usize, arg2: usize, _: usize, _data: usize) {
+        DONE.with(|done| done.set(Some((arg1, arg2))));
+    }
+
+    fn wait() -> (usize, usize) {
+        DONE.with(|done| done.set(None));
+        unsafe { syscalls::raw::yieldk(); }
+        DONE.with(|done| done.get()).expect("callback did not run")
+    }
+
+    #[test]
+    fn write_goes_to_output() {
+        let console = Console::install();
+        let mut buffer = *b"hello\n";
+        syscalls::subscribe_fn(DRIVER_NUMBER, subscribe_nr::WRITE_DONE, done, 0).unwrap();
+        let _share = syscalls::allow(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, &mut buffer).unwrap();
+        syscalls::command(DRIVER_NUMBER, command_nr::WRITE, 5, 0).unwrap();
+        assert_eq!(wait(), (5, 0));
+        assert_eq!(console.output(), b"hello");
+    }
+
+    #[test]
+    fn read_waits_for_input() {
+        let console = Console::install();
+        let mut buffer = [0u8; 4];
+        {
+            syscalls::subscribe_fn(DRIVER_NUMBER, subscribe_nr::READ_DONE, done, 0).unwrap();
+            let _share = syscalls::allow(DRIVER_NUMBER, allow_nr::READ_BUFFER, &mut buffer)
+                .unwrap();
+            syscalls::command(DRIVER_NUMBER, command_nr::READ, 4, 0).unwrap();
+            console.type_input(b"ok");
+            assert_eq!(wait(), (SUCCESS as usize, 2));
+        }
+        assert_eq!(&buffer[..2], b"ok");
+    }
+
+    #[test]
+    fn println_goes_to_output() {
+        let console = Console::install();
+        crate::println!("answer={}", 42);
+        assert_eq!(console.output(), b"answer=42\n");
+    }
+
+    #[test]
+    fn missing_driver_fails() {
+        crate::reset();
+        let error = syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)
+            .unwrap_err();
+        assert_eq!(error.return_code, ENODEVICE);
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The GPIO driver (capsules::gpio), over levels set by the test.
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::fake::FakeDriver;
+use crate::result::EINVAL;
+use crate::result::ENOSUPPORT;
+
+const DRIVER_NUMBER: usize = 4;
+
+mod command_nr {
+    pub const COUNT: usize = 0;
+    pub const ENABLE_OUTPUT: usize = 1;
+    pub const SET: usize = 2;
+    pub const CLEAR: usize = 3;
+    pub const TOGGLE: usize = 4;
+    pub const ENABLE_INPUT: usize = 5;
+    pub const READ: usize = 6;
+    pub const INTERRUPT_ENABLE: usize = 7;
+    pub const INTERRUPT_DISABLE: usize = 8;
+    pub const DISABLE: usize = 9;
+}
+
+mod subscribe_nr {
+    pub const SUBSCRIBE_CALLBACK: usize = 0;
+}
+
+// The edges passed to INTERRUPT_ENABLE.
+const EITHER_EDGE: usize = 0;
+const RISING_EDGE: usize = 1;
+const FALLING_EDGE: usize = 2;
+
+#[derive(Default)]
+struct Pin {
+    level: Cell<bool>,
+    // The edge the app is interested in, if any.
+    interrupt_edge: Cell<Option<usize>>,
+    // The levels the app wrote since the test last looked.
+    writes: RefCell<Vec<bool>>,
+}
+
+/// GPIO pins whose inputs the test drives and whose outputs it inspects.
+pub struct Gpio {
+    pins: Vec<Pin>,
+}
+
+impl Gpio {
+    /// Installs `count` pins, all low, as driver 4.
+    pub fn install(count: usize) -> Rc<Gpio> {
+        let gpio = Rc::new(Gpio {
+            pins: (0..count).map(|_| Pin::default()).collect(),
+        });
+        crate::install(DRIVER_NUMBER, gpio.clone());
+        gpio
+    }
+
+    /// Returns whether `pin` is high.
+    pub fn level(&self, pin: usize) -> bool {
+        self.pins[pin].level.get()
+    }
+
+    /// Sets the level of `pin` as an external device would, calling back
+    /// the app if it enabled interrupts for the resulting edge.
+    pub fn drive(&self, pin: usize, high: bool) {
+        let pin_state = &self.pins[pin];
+        if pin_state.level.replace(high) == high {
+            return;
+        }
+        let interrupt = match pin_state.interrupt_edge.get() {
+            Some(EITHER_EDGE) => true,
+            Some(RISING_EDGE) => high,
+            Some(FALLING_EDGE) => !high,
+            _ => false,
+        };
+        if interrupt {
+            crate::schedule_upcall(DRIVER_NUMBER, subscribe_nr::SUBSCRIBE_CALLBACK,
+                                   pin, high as usize, 0);
+        }
+    }
+
+    /// Returns the levels the app wrote to `pin` since the last call, in
+    /// order.
+    pub fn take_writes(&self, pin: usize) -> Vec<bool> {
+        self.pins[pin].writes.take()
+    }
+
+    fn write(&self, pin: &Pin, high: bool) {
+        pin.level.set(high);
+        pin.writes.borrow_mut().push(high);
+    }
+}
+
+impl FakeDriver for Gpio {
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize) -> Result<usize, isize> {
+        if command_num == command_nr::COUNT {
+            return Ok(self.pins.len());
+        }
+        let pin = self.pins.get(arg1).ok_or(EINVAL)?;
+        match command_num {
+            command_nr::ENABLE_OUTPUT | command_nr::ENABLE_INPUT => Ok(0),
+            command_nr::SET | command_nr::CLEAR | command_nr::TOGGLE => {
+                self.write(pin, match command_num {
+                    command_nr::SET => true,
+                    command_nr::CLEAR => false,
+                    _ => !pin.level.get(),
+                });
+                Ok(0)
+            },
+            command_nr::READ => Ok(pin.level.get() as usize),
+            command_nr::INTERRUPT_ENABLE => match arg2 {
+                EITHER_EDGE | RISING_EDGE | FALLING_EDGE => {
+                    pin.interrupt_edge.set(Some(arg2));
+                    Ok(0)
+                },
+                _ => Err(EINVAL),
+            },
+            command_nr::INTERRUPT_DISABLE | command_nr::DISABLE => {
+                pin.interrupt_edge.set(None);
+                Ok(0)
+            },
+            _ => Err(ENOSUPPORT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syscalls;
+
+    thread_local! {
+        static FIRED: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    }
+
+    extern "C" fn fired(pin: usize, pin_state: usize, _: usize, _data: usize) {
+        FIRED.with(|fired| fired.set(Some((pin, pin_state))));
+    }
+
+    #[test]
+    fn rising_edge_calls_back() {
+        let gpio = Gpio::install(4);
+        syscalls::subscribe_fn(DRIVER_NUMBER, subscribe_nr::SUBSCRIBE_CALLBACK, fired, 0).unwrap();
+        syscalls::command(DRIVER_NUMBER, command_nr::INTERRUPT_ENABLE, 3, RISING_EDGE).unwrap();
+        gpio.drive(3, true);
+        unsafe { syscalls::raw::yieldk(); }
+        assert_eq!(FIRED.with(|fired| fired.get()), Some((3, 1)));
+        assert_eq!(syscalls::command(DRIVER_NUMBER, command_nr::READ, 3, 0), Ok(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "sleep forever")]
+    fn falling_edge_is_filtered() {
+        let gpio = Gpio::install(4);
+        gpio.drive(2, true);
+        syscalls::subscribe_fn(DRIVER_NUMBER, subscribe_nr::SUBSCRIBE_CALLBACK, fired, 0).unwrap();
+        syscalls::command(DRIVER_NUMBER, command_nr::INTERRUPT_ENABLE, 2, RISING_EDGE).unwrap();
+        gpio.drive(2, false);
+        unsafe { syscalls::raw::yieldk(); }
+    }
+
+    #[test]
+    fn writes_are_recorded() {
+        let gpio = Gpio::install(4);
+        syscalls::command(DRIVER_NUMBER, command_nr::ENABLE_OUTPUT, 1, 0).unwrap();
+        syscalls::command(DRIVER_NUMBER, command_nr::CLEAR, 1, 0).unwrap();
+        syscalls::command(DRIVER_NUMBER, command_nr::SET, 1, 0).unwrap();
+        assert!(gpio.level(1));
+        assert_eq!(gpio.take_writes(1), [false, true]);
+        assert_eq!(gpio.take_writes(1), []);
+        assert!(syscalls::command(DRIVER_NUMBER, command_nr::SET, 4, 0).is_err());
+    }
+}
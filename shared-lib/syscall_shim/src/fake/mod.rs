@@ -0,0 +1,68 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fake drivers.
+
+use std::time::Instant;
+
+mod alarm;
+pub(crate) mod console;
+#[cfg(feature = "fake-hw")]
+mod gpio;
+#[cfg(feature = "fake-hw")]
+mod passthrough_guard;
+#[cfg(feature = "fake-hw")]
+mod spi_device;
+#[cfg(feature = "fake-hw")]
+mod spi_flash;
+#[cfg(feature = "fake-hw")]
+mod spi_host;
+#[cfg(feature = "fake-hw")]
+mod spi_host_h1;
+
+pub use self::alarm::Alarm;
+pub use self::console::Console;
+#[cfg(feature = "fake-hw")]
+pub use self::gpio::Gpio;
+#[cfg(feature = "fake-hw")]
+pub use self::passthrough_guard::PassthroughGuard;
+#[cfg(feature = "fake-hw")]
+pub use self::spi_device::SpiDevice;
+#[cfg(feature = "fake-hw")]
+pub use self::spi_flash::SpiFlash;
+#[cfg(feature = "fake-hw")]
+pub use self::spi_host::SpiHost;
+#[cfg(feature = "fake-hw")]
+pub use self::spi_host_h1::SpiHostH1;
+
+/// A driver answering syscalls in place of the kernel.
+///
+/// Fakes reach the app through `crate::schedule_upcall` and
+/// `crate::with_allowed`. Subscriptions are handled by the shim.
+pub trait FakeDriver {
+    /// Runs a command. Returns the value to pass to the app, or a negative
+    /// return code from `crate::result`.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize) -> Result<usize, isize>;
+
+    /// Returns when the fake will next have a callback to deliver on its
+    /// own, if it will.
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Called by `yieldk` once `deadline` has passed.
+    fn expire(&self) {}
+}
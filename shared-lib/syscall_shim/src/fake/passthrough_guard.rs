@@ -0,0 +1,113 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The passthrough guard driver (h1_syscalls::passthrough_guard).
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::fake::FakeDriver;
+use crate::fake::SpiHostH1;
+use crate::result::ENOSUPPORT;
+
+const DRIVER_NUMBER: usize = 0x40180;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const ARM: usize = 1;
+    pub const IS_ARMED: usize = 2;
+    pub const GET_TRIPS: usize = 3;
+}
+
+mod subscribe_nr {
+    pub const PASSTHROUGH_DISABLED: usize = 0;
+}
+
+/// A guard that disables passthrough on `spi_host_h1` when the board's
+/// wiring calls `fire`.
+pub struct PassthroughGuard {
+    spi_host_h1: Rc<SpiHostH1>,
+    armed: Cell<bool>,
+    trips: Cell<u32>,
+}
+
+impl PassthroughGuard {
+    /// Installs a disarmed guard as driver 0x40180.
+    pub fn install(spi_host_h1: Rc<SpiHostH1>) -> Rc<PassthroughGuard> {
+        let guard = Rc::new(PassthroughGuard {
+            spi_host_h1,
+            armed: Cell::new(false),
+            trips: Cell::new(0),
+        });
+        crate::install(DRIVER_NUMBER, guard.clone());
+        guard
+    }
+
+    /// Starts watching, as the board does at boot.
+    pub fn arm(&self) {
+        self.armed.set(true);
+    }
+
+    /// Returns the number of times the guard disabled passthrough.
+    pub fn trips(&self) -> u32 {
+        self.trips.get()
+    }
+
+    /// Called on the guarded edge. Disables passthrough if armed.
+    pub fn fire(&self) {
+        if !self.armed.get() {
+            return;
+        }
+        self.spi_host_h1.set_passthrough(false);
+        let trips = self.trips.get().wrapping_add(1);
+        self.trips.set(trips);
+        crate::schedule_upcall(DRIVER_NUMBER, subscribe_nr::PASSTHROUGH_DISABLED,
+                               trips as usize, 0, 0);
+    }
+}
+
+impl FakeDriver for PassthroughGuard {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize) -> Result<usize, isize> {
+        match command_num {
+            command_nr::CHECK_IF_PRESENT => Ok(0),
+            command_nr::ARM => {
+                self.armed.set(arg1 != 0);
+                Ok(0)
+            },
+            command_nr::IS_ARMED => Ok(self.armed.get() as usize),
+            command_nr::GET_TRIPS => Ok(self.trips.get() as usize),
+            _ => Err(ENOSUPPORT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syscalls;
+
+    #[test]
+    fn fire_disables_passthrough_when_armed() {
+        let spi_host_h1 = SpiHostH1::install(true);
+        let guard = PassthroughGuard::install(spi_host_h1.clone());
+        guard.fire();
+        assert!(spi_host_h1.passthrough());
+        guard.arm();
+        guard.fire();
+        assert!(!spi_host_h1.passthrough());
+        assert_eq!(syscalls::command(DRIVER_NUMBER, command_nr::GET_TRIPS, 0, 0), Ok(1));
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The SPI device driver (h1_syscalls::spi_device), with the SPI host on the
+//! other end of the bus played by the test.
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use crate::fake::FakeDriver;
+use crate::fake::SpiFlash;
+use crate::fake::SpiHostH1;
+use crate::result::EINVAL;
+use crate::result::ENOSUPPORT;
+use crate::result::ESIZE;
+
+const DRIVER_NUMBER: usize = 0x40030;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const SET_ADDRESS_MODE: usize = 3;
+    pub const GET_ADDRESS_MODE: usize = 4;
+    pub const SET_ADDRESS_MODE_HANDLING: usize = 5;
+    pub const SET_JEDEC_ID: usize = 6;
+    pub const SET_SFDP: usize = 7;
+    pub const CONFIGURE_ADDRESSES: usize = 8;
+    pub const KICK: usize = 12;
+    pub const SET_RX_BUFFER_MODE: usize = 13;
+    pub const RELEASE_RX_BUFFER: usize = 14;
+    pub const GET_STATUS: usize = 17;
+    pub const SET_STATUS: usize = 18;
+    pub const GET_ADDRESS_MODE_SWITCHES: usize = 21;
+    pub const GET_READ_CAPABILITIES: usize = 22;
+}
+
+mod subscribe_nr {
+    pub const ADDRESS_MODE_CHANGED: usize = 1;
+}
+
+mod allow_nr {
+    pub const WRITE_BUFFER: usize = 0;
+}
+
+const READ_STATUS_REGISTER: u8 = 0x05;
+const READ_JEDEC: u8 = 0x9f;
+const READ_SFDP: u8 = 0x5a;
+const ENTER_4_BYTE_ADDRESS_MODE: u8 = 0xb7;
+const EXIT_4_BYTE_ADDRESS_MODE: u8 = 0xe9;
+
+// HandlerMode::KernelSpace in spiutils.
+const HANDLER_MODE_KERNEL_SPACE: usize = 2;
+
+// The length of an AddressConfig on the wire.
+const ADDRESS_CONFIG_LEN: usize = 20;
+
+/// The SPI device, answering the SPI host the way the H1 kernel does for
+/// the commands around resets: the JEDEC ID, SFDP and status register come
+/// from the device, address mode switches are tracked in the kernel, and
+/// everything is passed through to `flash` while passthrough is enabled.
+///
+/// Transactions for userspace (mailbox, program and erase) are not modelled,
+/// so the data received callback never runs, and the commands that only
+/// matter for them return ENOSUPPORT.
+pub struct SpiDevice {
+    spi_host_h1: Rc<SpiHostH1>,
+    flash: Rc<SpiFlash>,
+    four_byte: Cell<bool>,
+    address_mode_handling: Cell<usize>,
+    address_mode_switches: Cell<usize>,
+    jedec_id: RefCell<Vec<u8>>,
+    sfdp: RefCell<Vec<u8>>,
+    status: Cell<u8>,
+}
+
+impl SpiDevice {
+    /// Installs the SPI device as driver 0x40030, in 3-byte address mode.
+    pub fn install(spi_host_h1: Rc<SpiHostH1>, flash: Rc<SpiFlash>) -> Rc<SpiDevice> {
+        let spi_device = Rc::new(SpiDevice {
+            spi_host_h1,
+            flash,
+            four_byte: Cell::new(false),
+            address_mode_handling: Cell::new(0),
+            address_mode_switches: Cell::new(0),
+            jedec_id: RefCell::new(Vec::new()),
+            sfdp: RefCell::new(Vec::new()),
+            status: Cell::new(0),
+        });
+        crate::install(DRIVER_NUMBER, spi_device.clone());
+        spi_device
+    }
+
+    /// Returns whether the device is in 4-byte address mode.
+    pub fn is_four_byte(&self) -> bool {
+        self.four_byte.get()
+    }
+
+    /// Runs one transaction from the SPI host, sending `tx` and returning
+    /// the bytes received while it was sent.
+    pub fn transfer(&self, tx: &[u8]) -> Vec<u8> {
+        let mut rx = vec![0xff; tx.len()];
+        match tx.first() {
+            Some(&READ_STATUS_REGISTER) => fill(&mut rx[1..], &[self.status.get()]),
+            Some(&READ_JEDEC) => fill(&mut rx[1..], &self.jedec_id.borrow()),
+            Some(&READ_SFDP) if tx.len() > 5 => {
+                // A 3-byte address and a dummy byte.
+                let address = tx[1..4].iter()
+                    .fold(0usize, |address, &byte| address << 8 | byte as usize);
+                fill(&mut rx[5..], self.sfdp.borrow().get(address..).unwrap_or(&[]));
+            },
+            Some(&op_code) => {
+                if op_code == ENTER_4_BYTE_ADDRESS_MODE || op_code == EXIT_4_BYTE_ADDRESS_MODE {
+                    self.handle_address_mode_switch(op_code == ENTER_4_BYTE_ADDRESS_MODE);
+                }
+                if self.spi_host_h1.passthrough() {
+                    rx = self.flash.transfer(tx);
+                }
+            },
+            None => (),
+        }
+        rx
+    }
+
+    fn handle_address_mode_switch(&self, four_byte: bool) {
+        if self.address_mode_handling.get() != HANDLER_MODE_KERNEL_SPACE {
+            return;
+        }
+        if self.switch_address_mode(four_byte) {
+            crate::schedule_upcall(DRIVER_NUMBER, subscribe_nr::ADDRESS_MODE_CHANGED,
+                                   four_byte as usize, self.address_mode_switches.get(), 0);
+        }
+    }
+
+    // Returns true if the address mode changed.
+    fn switch_address_mode(&self, four_byte: bool) -> bool {
+        if self.four_byte.replace(four_byte) == four_byte {
+            return false;
+        }
+        self.address_mode_switches.set(self.address_mode_switches.get().wrapping_add(1));
+        true
+    }
+
+    fn copy_write_buffer(&self) -> Result<Vec<u8>, isize> {
+        crate::with_allowed(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, |buffer| buffer.to_vec())
+            .ok_or(ESIZE)
+    }
+}
+
+fn fill(rx: &mut [u8], data: &[u8]) {
+    for (dst, src) in rx.iter_mut().zip(data) {
+        *dst = *src;
+    }
+}
+
+impl FakeDriver for SpiDevice {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize) -> Result<usize, isize> {
+        match command_num {
+            command_nr::CHECK_IF_PRESENT => Ok(0),
+            command_nr::SET_ADDRESS_MODE => match arg1 {
+                0 | 1 => {
+                    self.switch_address_mode(arg1 == 1);
+                    Ok(0)
+                },
+                _ => Err(EINVAL),
+            },
+            command_nr::GET_ADDRESS_MODE => Ok(self.four_byte.get() as usize),
+            command_nr::SET_ADDRESS_MODE_HANDLING => match arg1 {
+                0..=HANDLER_MODE_KERNEL_SPACE => {
+                    self.address_mode_handling.set(arg1);
+                    Ok(0)
+                },
+                _ => Err(EINVAL),
+            },
+            command_nr::SET_JEDEC_ID => {
+                *self.jedec_id.borrow_mut() = self.copy_write_buffer()?;
+                Ok(0)
+            },
+            command_nr::SET_SFDP => {
+                *self.sfdp.borrow_mut() = self.copy_write_buffer()?;
+                Ok(0)
+            },
+            command_nr::CONFIGURE_ADDRESSES => match self.copy_write_buffer()?.len() {
+                ADDRESS_CONFIG_LEN => Ok(0),
+                _ => Err(EINVAL),
+            },
+            // There are never transactions for userspace.
+            command_nr::KICK | command_nr::RELEASE_RX_BUFFER => Ok(0),
+            command_nr::SET_RX_BUFFER_MODE => match arg1 {
+                0 | 1 => Ok(0),
+                _ => Err(EINVAL),
+            },
+            command_nr::GET_STATUS => Ok(self.status.get() as usize),
+            command_nr::SET_STATUS => {
+                self.status.set(u8::try_from(arg1).map_err(|_| EINVAL)?);
+                Ok(0)
+            },
+            command_nr::GET_ADDRESS_MODE_SWITCHES => Ok(self.address_mode_switches.get()),
+            // No dual or quad output reads.
+            command_nr::GET_READ_CAPABILITIES => Ok(0),
+            _ => Err(ENOSUPPORT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syscalls;
+
+    thread_local! {
+        static CHANGED: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    }
+
+    extern "C" fn changed(address_mode: usize, switches: usize, _: usize, _data: usize) {
+        CHANGED.with(|changed| changed.set(Some((address_mode, switches))));
+    }
+
+    fn install(passthrough: bool) -> (Rc<SpiDevice>, Rc<SpiFlash>) {
+        let flash = Rc::new(SpiFlash::new(vec![0x42]));
+        let spi_device = SpiDevice::install(SpiHostH1::install(passthrough), flash.clone());
+        (spi_device, flash)
+    }
+
+    #[test]
+    fn kernel_handles_address_mode_switch() {
+        let (spi_device, flash) = install(true);
+        syscalls::subscribe_fn(DRIVER_NUMBER, subscribe_nr::ADDRESS_MODE_CHANGED, changed, 0)
+            .unwrap();
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_ADDRESS_MODE_HANDLING,
+                          HANDLER_MODE_KERNEL_SPACE, 0).unwrap();
+        spi_device.transfer(&[ENTER_4_BYTE_ADDRESS_MODE]);
+        unsafe { syscalls::raw::yieldk(); }
+        assert_eq!(CHANGED.with(|changed| changed.get()), Some((1, 1)));
+        assert!(spi_device.is_four_byte());
+        assert!(flash.is_four_byte());
+    }
+
+    #[test]
+    fn device_answers_jedec_id_without_passthrough() {
+        let (spi_device, _) = install(false);
+        let mut jedec_id = [0x26, 0x31, 0x19];
+        {
+            let _share = syscalls::allow(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, &mut jedec_id)
+                .unwrap();
+            syscalls::command(DRIVER_NUMBER, command_nr::SET_JEDEC_ID, 0, 0).unwrap();
+        }
+        assert_eq!(spi_device.transfer(&[READ_JEDEC, 0, 0, 0]), [0xff, 0x26, 0x31, 0x19]);
+        assert_eq!(spi_device.transfer(&[0x03, 0, 0, 0, 0]), [0xff; 5]);
+    }
+}
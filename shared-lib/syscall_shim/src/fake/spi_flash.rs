@@ -0,0 +1,103 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! An external SPI NOR flash, for the SPI fakes to talk to.
+
+use std::cell::Cell;
+
+const READ_STATUS_REGISTER: u8 = 0x05;
+const READ_DATA: u8 = 0x03;
+const READ_JEDEC: u8 = 0x9f;
+const ENTER_4_BYTE_ADDRESS_MODE: u8 = 0xb7;
+const EXIT_4_BYTE_ADDRESS_MODE: u8 = 0xe9;
+
+/// The JEDEC ID the flash reports: a 256 Mb Winbond W25Q256.
+pub const JEDEC_ID: [u8; 3] = [0xef, 0x40, 0x19];
+
+/// A flash that supports the commands otpilot and the BMC use around resets:
+/// reading data, reading the status and JEDEC ID, and switching the address
+/// mode. It powers up in 3-byte address mode. Other commands are ignored.
+pub struct SpiFlash {
+    image: Vec<u8>,
+    four_byte: Cell<bool>,
+}
+
+impl SpiFlash {
+    /// Creates a flash holding `image`, repeated to fill the address space.
+    pub fn new(image: Vec<u8>) -> SpiFlash {
+        assert!(!image.is_empty(), "the flash image must not be empty");
+        SpiFlash {
+            image,
+            four_byte: Cell::new(false),
+        }
+    }
+
+    /// Returns whether the flash is in 4-byte address mode.
+    pub fn is_four_byte(&self) -> bool {
+        self.four_byte.get()
+    }
+
+    /// Runs one transaction, sending `tx` and returning the bytes received
+    /// while it was sent.
+    pub fn transfer(&self, tx: &[u8]) -> Vec<u8> {
+        let mut rx = vec![0xff; tx.len()];
+        match tx.first() {
+            Some(&READ_STATUS_REGISTER) => fill(&mut rx[1..], [0].iter().copied()),
+            Some(&READ_JEDEC) => fill(&mut rx[1..], JEDEC_ID.iter().copied()),
+            Some(&ENTER_4_BYTE_ADDRESS_MODE) => self.four_byte.set(true),
+            Some(&EXIT_4_BYTE_ADDRESS_MODE) => self.four_byte.set(false),
+            Some(&READ_DATA) => {
+                let address_len = if self.four_byte.get() { 4 } else { 3 };
+                if tx.len() > address_len {
+                    let address = tx[1..=address_len].iter()
+                        .fold(0usize, |address, &byte| address << 8 | byte as usize);
+                    let data = self.image.iter().cycle().skip(address % self.image.len());
+                    fill(&mut rx[1 + address_len..], data.copied());
+                }
+            },
+            _ => (),
+        }
+        rx
+    }
+}
+
+fn fill<I: Iterator<Item = u8>>(rx: &mut [u8], data: I) {
+    for (dst, src) in rx.iter_mut().zip(data) {
+        *dst = src;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_follows_address_mode() {
+        let flash = SpiFlash::new((0..=255).collect());
+        assert_eq!(flash.transfer(&[0x03, 0, 0, 0x10, 0xff, 0xff]), [0xff, 0xff, 0xff, 0xff, 0x10, 0x11]);
+        flash.transfer(&[0xb7]);
+        assert!(flash.is_four_byte());
+        assert_eq!(flash.transfer(&[0x03, 0, 0, 1, 0x10, 0xff]), [0xff, 0xff, 0xff, 0xff, 0xff, 0x10]);
+        flash.transfer(&[0xe9]);
+        assert!(!flash.is_four_byte());
+    }
+
+    #[test]
+    fn jedec_id() {
+        let flash = SpiFlash::new(vec![0]);
+        assert_eq!(flash.transfer(&[0x9f, 0, 0, 0]), [0xff, 0xef, 0x40, 0x19]);
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The SPI controller driver (capsules::spi_controller), over a `SpiFlash`.
+
+use std::rc::Rc;
+
+use crate::fake::FakeDriver;
+use crate::fake::SpiFlash;
+use crate::result::EINVAL;
+use crate::result::ENOSUPPORT;
+use crate::result::ERESERVE;
+
+const DRIVER_NUMBER: usize = 0x20001;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const READ_WRITE_BYTES: usize = 2;
+}
+
+mod subscribe_nr {
+    pub const READ_WRITE_COMPLETE: usize = 0;
+}
+
+mod allow_nr {
+    pub const READ_BUFFER: usize = 0;
+    pub const WRITE_BUFFER: usize = 1;
+}
+
+/// A SPI controller with `flash` on its bus. Transactions complete at once.
+pub struct SpiHost {
+    flash: Rc<SpiFlash>,
+}
+
+impl SpiHost {
+    /// Installs the controller as driver 0x20001.
+    pub fn install(flash: Rc<SpiFlash>) -> Rc<SpiHost> {
+        let spi_host = Rc::new(SpiHost { flash });
+        crate::install(DRIVER_NUMBER, spi_host.clone());
+        spi_host
+    }
+}
+
+impl FakeDriver for SpiHost {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize) -> Result<usize, isize> {
+        match command_num {
+            command_nr::CHECK_IF_PRESENT => Ok(0),
+            command_nr::READ_WRITE_BYTES => {
+                let rx = crate::with_allowed(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, |tx| {
+                    match tx.get(..arg1) {
+                        Some(tx) if !tx.is_empty() => Ok(self.flash.transfer(tx)),
+                        _ => Err(EINVAL),
+                    }
+                }).ok_or(ERESERVE)??;
+                crate::with_allowed(DRIVER_NUMBER, allow_nr::READ_BUFFER, |buffer| {
+                    for (dst, src) in buffer.iter_mut().zip(rx) {
+                        *dst = src;
+                    }
+                });
+                crate::schedule_upcall(DRIVER_NUMBER, subscribe_nr::READ_WRITE_COMPLETE,
+                                       arg1, 0, 0);
+                Ok(0)
+            },
+            _ => Err(ENOSUPPORT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syscalls;
+
+    extern "C" fn done(_len: usize, _: usize, _: usize, _data: usize) {}
+
+    #[test]
+    fn transaction_reaches_flash() {
+        SpiHost::install(Rc::new(SpiFlash::new(vec![0x5a])));
+        let mut tx = [0x03, 0, 0, 0, 0xff, 0xff];
+        let mut rx = [0u8; 6];
+        {
+            let _rx_share = syscalls::allow(DRIVER_NUMBER, allow_nr::READ_BUFFER, &mut rx).unwrap();
+            let _tx_share = syscalls::allow(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, &mut tx).unwrap();
+            syscalls::subscribe_fn(DRIVER_NUMBER, subscribe_nr::READ_WRITE_COMPLETE, done, 0)
+                .unwrap();
+            syscalls::command(DRIVER_NUMBER, command_nr::READ_WRITE_BYTES, 6, 0).unwrap();
+            unsafe { syscalls::raw::yieldk(); }
+        }
+        assert_eq!(rx, [0xff, 0xff, 0xff, 0xff, 0x5a, 0x5a]);
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The H1 SPI host driver (h1_syscalls::spi_host), which controls passthrough.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::fake::FakeDriver;
+use crate::result::ENOSUPPORT;
+
+const DRIVER_NUMBER: usize = 0x40020;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const ENABLE_DISABLE_PASSTHROUGH: usize = 1;
+    pub const ENABLE_DISABLE_WAIT_BUSY_CLEAR_IN_TRANSACTIONS: usize = 2;
+}
+
+/// The passthrough switch between the SPI device and the SPI host.
+pub struct SpiHostH1 {
+    passthrough: Cell<bool>,
+}
+
+impl SpiHostH1 {
+    /// Installs the driver as driver 0x40020, with passthrough set as the
+    /// board sets it at boot.
+    pub fn install(passthrough: bool) -> Rc<SpiHostH1> {
+        let spi_host_h1 = Rc::new(SpiHostH1 {
+            passthrough: Cell::new(passthrough),
+        });
+        crate::install(DRIVER_NUMBER, spi_host_h1.clone());
+        spi_host_h1
+    }
+
+    /// Returns whether passthrough is enabled.
+    pub fn passthrough(&self) -> bool {
+        self.passthrough.get()
+    }
+
+    /// Enables or disables passthrough from the kernel side.
+    pub fn set_passthrough(&self, enabled: bool) {
+        self.passthrough.set(enabled);
+    }
+}
+
+impl FakeDriver for SpiHostH1 {
+    fn command(&self, command_num: usize, arg1: usize, _arg2: usize) -> Result<usize, isize> {
+        match command_num {
+            command_nr::CHECK_IF_PRESENT => Ok(0),
+            command_nr::ENABLE_DISABLE_PASSTHROUGH => {
+                self.set_passthrough(arg1 != 0);
+                Ok(0)
+            },
+            // Transactions complete at once, so there is no BUSY to wait for.
+            command_nr::ENABLE_DISABLE_WAIT_BUSY_CLEAR_IN_TRANSACTIONS => Ok(0),
+            _ => Err(ENOSUPPORT),
+        }
+    }
+}
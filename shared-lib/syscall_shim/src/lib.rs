@@ -0,0 +1,132 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#![warn(missing_docs)]
+
+//! Host stand-ins for the libtock syscalls.
+//!
+//! Userspace code talks to the kernel through `libtock::syscalls`, which only
+//! exists on the device. This crate provides the same functions (`command`,
+//! `subscribe_fn`, `allow` and `raw::yieldk`) and result types, backed by fake
+//! drivers that run in the test process. A module switches to them in its
+//! tests by importing them under the same names:
+//!
+//! ```ignore
+//! #[cfg(not(test))]
+//! use libtock::{result::TockResult, syscalls};
+//! #[cfg(test)]
+//! use syscall_shim::{result::TockResult, syscalls};
+//! ```
+//!
+//! A test installs the fakes the code needs (see `fake`) before calling into
+//! it. Callbacks run from `yieldk`, as on the device; `yieldk` panics if no
+//! installed fake could ever deliver one, since the app would sleep forever.
+//!
+//! The state is per thread, so tests running in parallel do not see each
+//! other's drivers.
+//!
+//! `println!`, `print!` and `shared_memory::SharedMemory` are provided under
+//! their libtock paths too, so that a host binary can compile userspace
+//! modules unchanged with `extern crate syscall_shim as libtock;`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+pub mod fake;
+pub mod result;
+pub mod syscalls;
+
+/// `libtock::shared_memory`, where libtock keeps `SharedMemory`.
+pub mod shared_memory {
+    pub use crate::syscalls::SharedMemory;
+}
+
+/// Prints to the fake console if one is installed, else to stdout.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
+}
+
+/// Prints a line to the fake console if one is installed, else to stdout.
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::_print(format_args!("{}\n", format_args!($($arg)*))));
+}
+
+#[doc(hidden)]
+pub fn _print(args: std::fmt::Arguments) {
+    // Written directly rather than through the console's syscalls, so that
+    // printing does not yield and run callbacks the app is not waiting for.
+    fake::console::write(&args.to_string());
+}
+
+use crate::fake::FakeDriver;
+
+/// The signature of a callback passed to `syscalls::subscribe_fn`.
+pub type Callback = extern "C" fn(usize, usize, usize, usize);
+
+#[derive(Default)]
+struct Kernel {
+    drivers: HashMap<usize, Rc<dyn FakeDriver>>,
+    callbacks: HashMap<(usize, usize), (Callback, usize)>,
+    // Raw parts of the buffers the app shares, as the kernel sees them.
+    buffers: HashMap<(usize, usize), (*mut u8, usize)>,
+    upcalls: VecDeque<(usize, usize, [usize; 3])>,
+}
+
+thread_local! {
+    static KERNEL: RefCell<Kernel> = RefCell::new(Kernel::default());
+}
+
+/// Makes `driver` answer the syscalls to `driver_num`, replacing any driver
+/// installed there before.
+pub fn install(driver_num: usize, driver: Rc<dyn FakeDriver>) {
+    KERNEL.with(|kernel| kernel.borrow_mut().drivers.insert(driver_num, driver));
+}
+
+/// Removes all drivers, subscriptions and pending callbacks of this thread.
+pub fn reset() {
+    KERNEL.with(|kernel| *kernel.borrow_mut() = Kernel::default());
+}
+
+/// Queues a callback on `subscribe_num` of `driver_num`. It runs from the
+/// next `yieldk`, unless the app has not subscribed by then, in which case it
+/// is dropped as the kernel would.
+pub fn schedule_upcall(driver_num: usize, subscribe_num: usize, arg1: usize, arg2: usize, arg3: usize) {
+    KERNEL.with(|kernel| {
+        kernel.borrow_mut().upcalls.push_back((driver_num, subscribe_num, [arg1, arg2, arg3]))
+    });
+}
+
+/// Calls `f` with the buffer the app shares on `allow_num` of `driver_num`.
+/// Returns None if the app shares no buffer there.
+pub fn with_allowed<R, F: FnOnce(&mut [u8]) -> R>(driver_num: usize, allow_num: usize, f: F) -> Option<R> {
+    let buffer = KERNEL.with(|kernel| kernel.borrow().buffers.get(&(driver_num, allow_num)).copied());
+    // The buffer stays borrowed by its `SharedMemory` until it is unshared,
+    // so it is valid here just like it is for the kernel on the device.
+    buffer.map(|(ptr, len)| f(unsafe { std::slice::from_raw_parts_mut(ptr, len) }))
+}
+
+fn driver(driver_num: usize) -> Option<Rc<dyn FakeDriver>> {
+    KERNEL.with(|kernel| kernel.borrow().drivers.get(&driver_num).cloned())
+}
+
+fn drivers() -> Vec<Rc<dyn FakeDriver>> {
+    KERNEL.with(|kernel| kernel.borrow().drivers.values().cloned().collect())
+}
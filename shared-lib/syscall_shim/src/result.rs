@@ -0,0 +1,132 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The syscall result types of `libtock::result`.
+
+/// The result of a libtock operation.
+pub type TockResult<T> = Result<T, TockError>;
+
+/// Why a libtock operation failed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TockError {
+    /// A subscribe syscall failed.
+    Subscribe(SubscribeError),
+    /// A command syscall failed.
+    Command(CommandError),
+    /// An allow syscall failed.
+    Allow(AllowError),
+    /// Formatting failed.
+    Format,
+}
+
+/// A failed subscribe syscall.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SubscribeError {
+    /// The driver that was called.
+    pub driver_number: usize,
+    /// The subscription number that was passed.
+    pub subscribe_number: usize,
+    /// The return code from the kernel.
+    pub return_code: isize,
+}
+
+/// A failed command syscall.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CommandError {
+    /// The driver that was called.
+    pub driver_number: usize,
+    /// The command number that was passed.
+    pub command_number: usize,
+    /// The first argument that was passed.
+    pub arg1: usize,
+    /// The second argument that was passed.
+    pub arg2: usize,
+    /// The return code from the kernel.
+    pub return_code: isize,
+}
+
+/// A failed allow syscall.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct AllowError {
+    /// The driver that was called.
+    pub driver_number: usize,
+    /// The allow number that was passed.
+    pub allow_number: usize,
+    /// The return code from the kernel.
+    pub return_code: isize,
+}
+
+impl From<SubscribeError> for TockError {
+    fn from(error: SubscribeError) -> Self {
+        TockError::Subscribe(error)
+    }
+}
+
+impl From<CommandError> for TockError {
+    fn from(error: CommandError) -> Self {
+        TockError::Command(error)
+    }
+}
+
+impl From<AllowError> for TockError {
+    fn from(error: AllowError) -> Self {
+        TockError::Allow(error)
+    }
+}
+
+impl From<core::fmt::Error> for TockError {
+    fn from(_: core::fmt::Error) -> Self {
+        TockError::Format
+    }
+}
+
+// Kernel return codes.
+
+/// The operation succeeded.
+pub const SUCCESS: isize = 0;
+
+/// Generic failure.
+pub const FAIL: isize = -1;
+
+/// The driver is busy.
+pub const EBUSY: isize = -2;
+
+/// The requested state is already set.
+pub const EALREADY: isize = -3;
+
+/// The component is off.
+pub const EOFF: isize = -4;
+
+/// A resource needed for the operation is missing.
+pub const ERESERVE: isize = -5;
+
+/// An argument is invalid.
+pub const EINVAL: isize = -6;
+
+/// A size is out of range.
+pub const ESIZE: isize = -7;
+
+/// The operation was cancelled.
+pub const ECANCEL: isize = -8;
+
+/// Out of memory.
+pub const ENOMEM: isize = -9;
+
+/// The operation is not supported.
+pub const ENOSUPPORT: isize = -10;
+
+/// No driver with that number.
+pub const ENODEVICE: isize = -11;
@@ -0,0 +1,147 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The syscalls of `libtock::syscalls`, answered by the installed fakes.
+
+use std::marker::PhantomData;
+use std::time::Instant;
+
+use crate::result::AllowError;
+use crate::result::CommandError;
+use crate::result::SubscribeError;
+use crate::result::ENODEVICE;
+use crate::Callback;
+use crate::KERNEL;
+
+/// Runs command `command_number` of `driver_number`.
+pub fn command(driver_number: usize, command_number: usize, arg1: usize, arg2: usize)
+    -> Result<usize, CommandError> {
+    let result = match crate::driver(driver_number) {
+        Some(driver) => driver.command(command_number, arg1, arg2),
+        None => Err(ENODEVICE),
+    };
+    result.map_err(|return_code| CommandError {
+        driver_number,
+        command_number,
+        arg1,
+        arg2,
+        return_code,
+    })
+}
+
+/// Makes `callback` the callback for `subscribe_number` of `driver_number`.
+/// `data` is passed to it as the last argument.
+pub fn subscribe_fn(driver_number: usize, subscribe_number: usize, callback: Callback, data: usize)
+    -> Result<(), SubscribeError> {
+    if crate::driver(driver_number).is_none() {
+        return Err(SubscribeError {
+            driver_number,
+            subscribe_number,
+            return_code: ENODEVICE,
+        });
+    }
+    KERNEL.with(|kernel| {
+        kernel.borrow_mut().callbacks.insert((driver_number, subscribe_number), (callback, data))
+    });
+    Ok(())
+}
+
+/// A buffer shared with a driver. Dropping it unshares the buffer.
+pub struct SharedMemory<'a> {
+    driver_number: usize,
+    allow_number: usize,
+    _buffer: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> Drop for SharedMemory<'a> {
+    fn drop(&mut self) {
+        KERNEL.with(|kernel| {
+            kernel.borrow_mut().buffers.remove(&(self.driver_number, self.allow_number))
+        });
+    }
+}
+
+/// Shares `buffer` with `driver_number` on `allow_number`, until the returned
+/// `SharedMemory` is dropped.
+pub fn allow(driver_number: usize, allow_number: usize, buffer: &mut [u8])
+    -> Result<SharedMemory<'_>, AllowError> {
+    if crate::driver(driver_number).is_none() {
+        return Err(AllowError {
+            driver_number,
+            allow_number,
+            return_code: ENODEVICE,
+        });
+    }
+    KERNEL.with(|kernel| {
+        kernel.borrow_mut().buffers.insert((driver_number, allow_number),
+                                           (buffer.as_mut_ptr(), buffer.len()))
+    });
+    Ok(SharedMemory {
+        driver_number,
+        allow_number,
+        _buffer: PhantomData,
+    })
+}
+
+/// The raw syscalls.
+pub mod raw {
+    use super::*;
+
+    /// Waits for the next callback and runs it.
+    ///
+    /// # Safety
+    ///
+    /// Unsafe like the syscall it stands in for; it is safe on the host.
+    ///
+    /// # Panics
+    ///
+    /// If no callback is pending and no fake has a deadline that could
+    /// produce one.
+    pub unsafe fn yieldk() {
+        loop {
+            let upcall = KERNEL.with(|kernel| {
+                let mut kernel = kernel.borrow_mut();
+                let (driver, subscribe, args) = kernel.upcalls.pop_front()?;
+                Some((kernel.callbacks.get(&(driver, subscribe)).copied(), args))
+            });
+            match upcall {
+                Some((Some((callback, data)), [arg1, arg2, arg3])) => {
+                    callback(arg1, arg2, arg3, data);
+                    return;
+                },
+                // Nobody is subscribed, so the kernel drops the callback.
+                Some((None, _)) => continue,
+                None => wait_for_deadline(),
+            }
+        }
+    }
+
+    fn wait_for_deadline() {
+        let drivers = crate::drivers();
+        let deadline = drivers.iter().filter_map(|driver| driver.deadline()).min()
+            .expect("yieldk: no callback is pending or can arrive, so the app would sleep forever");
+        let now = Instant::now();
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+        }
+        for driver in drivers {
+            match driver.deadline() {
+                Some(expiry) if expiry <= deadline => driver.expire(),
+                _ => (),
+            }
+        }
+    }
+}
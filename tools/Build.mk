@@ -37,3 +37,9 @@ tools/doc: cargo_version_check sandbox_setup
 .PHONY: tools/localtests
 tools/localtests: cargo_version_check sandbox_setup
 	cd tools && $(BWRAP) cargo test --offline --release
+
+# Checks the toolchain, devices and board, and prints how to fix problems.
+# Runs outside the sandbox since it needs rustup and the console devices.
+.PHONY: doctor
+doctor:
+	cd tools && cargo run --offline --release --bin doctor -- --root ..
@@ -0,0 +1,456 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Assembler for dcrypto programs.
+//!
+//! The kernel embeds dcrypto programs as word arrays with the instructions
+//! in comments, in the format of the cr50 listings (see
+//! kernel/h1/src/crypto/curve25519.rs). This crate assembles such an array
+//! from a `.dasm` source, so that the source, not the array, is what gets
+//! edited and reviewed.
+//!
+//! Source syntax, one item per line:
+//!
+//! * `; text` is a comment. A block of comments right before a function is
+//!   copied to the listing.
+//! * `define NAME 0x...` defines a 256-bit constant.
+//! * `function NAME {` ... `}` is a function. Its name is a label that
+//!   `call &NAME` can refer to.
+//! * `NAME:` labels the next instruction as a branch target.
+//! * `loop #N (` ... `)` runs the enclosed instructions N times.
+//! * `const rD, NAME` loads a defined constant into rD. It expands to 17
+//!   instructions and needs r31 to be zero.
+//! * Anything else is one instruction, written as in the cr50 listings.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// One line of the listing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item {
+    Function { name: String, address: u32, size: u32, comments: Vec<String> },
+    EndFunction,
+    Label(String),
+    Instruction { address: u32, word: u32, text: String },
+    /// The loop instruction; `text` is the source form, `loop #N`.
+    Loop { address: u32, word: u32, text: String },
+    EndLoop,
+}
+
+/// An assembled program.
+pub struct Program {
+    pub items: Vec<Item>,
+    /// Addresses of the functions and labels.
+    pub labels: BTreeMap<String, u32>,
+}
+
+impl Program {
+    /// The instruction words, from address 0.
+    pub fn words(&self) -> Vec<u32> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Instruction { word, .. } | Item::Loop { word, .. } => Some(*word),
+            _ => None,
+        }).collect()
+    }
+
+    /// The body of the Rust array holding the program, one line per word
+    /// with the instruction in a comment.
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+        let mut depth = 0;
+        for item in &self.items {
+            let indent = "    ".repeat(depth + 1);
+            let _ = match item {
+                Item::Function { name, address, size, comments } => {
+                    for comment in comments {
+                        let _ = writeln!(listing, "    //{}{}",
+                                         if comment.is_empty() { "" } else { " " }, comment);
+                    }
+                    writeln!(listing, "    // @{:#x}: function {}[{}] {{", address, name, size)
+                },
+                Item::EndFunction => writeln!(listing, "    // }}"),
+                Item::Label(name) => writeln!(listing, "    // {}:", name),
+                Item::Instruction { word, text, .. } => {
+                    writeln!(listing, "{}{:#010x}, // {}", indent, word, text)
+                },
+                Item::Loop { word, text, .. } => {
+                    depth += 1;
+                    writeln!(listing, "{}{:#010x}, // {} (", indent, word, text)
+                },
+                Item::EndLoop => {
+                    depth -= 1;
+                    writeln!(listing, "{}// )", "    ".repeat(depth + 1))
+                },
+            };
+        }
+        listing
+    }
+}
+
+/// Assembles `source`. Errors name the offending line.
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let mut constants = BTreeMap::new();
+    let mut items = Vec::new();
+    let mut labels = BTreeMap::new();
+    let mut comments = Vec::new();
+    // Index in `items` of the open function and of the open loops.
+    let mut function = None;
+    let mut loops = Vec::new();
+    let mut address = 0u32;
+
+    // First pass: lay out the instructions and collect the labels. The
+    // instructions are encoded once all labels are known.
+    let mut pending = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix(';') {
+            comments.push(comment.trim().to_string());
+            continue;
+        }
+        if line.is_empty() {
+            // A blank line ends a comment block, e.g. a file header.
+            comments.clear();
+            continue;
+        }
+        let line = match line.find(';') {
+            Some(index) => line[..index].trim(),
+            None => line,
+        };
+
+        if let Some(definition) = line.strip_prefix("define ") {
+            let mut fields = definition.split_whitespace();
+            let (name, value) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(name), Some(value), None) => (name, value),
+                _ => return Err(error(format!("malformed define: {}", line))),
+            };
+            constants.insert(name.to_string(), parse_u256(value).map_err(error)?);
+            comments.clear();
+        } else if let Some(name) = line.strip_prefix("function ") {
+            let name = name.strip_suffix('{').map(str::trim)
+                .ok_or_else(|| error(format!("expected {{ after {}", line)))?;
+            if function.is_some() {
+                return Err(error(format!("function {} inside another function", name)));
+            }
+            define_label(&mut labels, name, address).map_err(error)?;
+            function = Some(items.len());
+            items.push(Item::Function {
+                name: name.to_string(),
+                address,
+                size: 0,
+                comments: std::mem::take(&mut comments),
+            });
+        } else if line == "}" {
+            if !loops.is_empty() {
+                return Err(error("function ends inside a loop".to_string()));
+            }
+            match function.take().map(|index| &mut items[index]) {
+                Some(Item::Function { address: start, size, .. }) => *size = address - *start,
+                _ => return Err(error("} outside a function".to_string())),
+            }
+            items.push(Item::EndFunction);
+        } else if let Some(name) = line.strip_suffix(':') {
+            define_label(&mut labels, name, address).map_err(error)?;
+            items.push(Item::Label(name.to_string()));
+        } else if let Some(count) = line.strip_prefix("loop ").and_then(|l| l.strip_suffix('(')) {
+            let count = parse_immediate(count.trim()).map_err(error)?;
+            if count == 0 || count > 0xfff {
+                return Err(error(format!("bad loop count {}", count)));
+            }
+            loops.push((items.len(), count));
+            items.push(Item::Loop { address, word: 0, text: format!("loop #{}", count) });
+            address += 1;
+        } else if line == ")" {
+            let (index, count) = loops.pop().ok_or_else(|| error(") outside a loop".to_string()))?;
+            if let Item::Loop { address: start, word, .. } = &mut items[index] {
+                let body = address - *start - 1;
+                if body == 0 || body > 0xfff {
+                    return Err(error(format!("bad loop body length {}", body)));
+                }
+                *word = 0x0500_0000 | count << 12 | body;
+            }
+            items.push(Item::EndLoop);
+        } else if let Some(operands) = line.strip_prefix("const ") {
+            let (register, name) = split2(operands).map_err(error)?;
+            let d = reg(register).map_err(error)?;
+            let value = constants.get(name)
+                .ok_or_else(|| error(format!("undefined constant {}", name)))?;
+            pending.push((number, items.len()));
+            items.push(Item::Instruction { address, word: 0, text: format!("mov r{}, r31", d) });
+            address += 1;
+            for (half, value) in value.iter().enumerate() {
+                pending.push((number, items.len()));
+                items.push(Item::Instruction {
+                    address,
+                    word: 0,
+                    text: format!("movi r{}.{}{}, #{}", d, half / 2,
+                                  if half % 2 == 0 { 'l' } else { 'h' }, value),
+                });
+                address += 1;
+            }
+        } else {
+            if function.is_none() {
+                return Err(error(format!("instruction outside a function: {}", line)));
+            }
+            pending.push((number, items.len()));
+            items.push(Item::Instruction { address, word: 0, text: line.to_string() });
+            address += 1;
+        }
+        if !line.starts_with("function ") {
+            comments.clear();
+        }
+    }
+    if function.is_some() || !loops.is_empty() {
+        return Err("unterminated function or loop at end of source".to_string());
+    }
+
+    for (number, index) in pending {
+        if let Item::Instruction { word, text, .. } = &mut items[index] {
+            *word = encode(text, &labels).map_err(|message| format!("line {}: {}", number + 1, message))?;
+        }
+    }
+    Ok(Program { items, labels })
+}
+
+fn define_label(labels: &mut BTreeMap<String, u32>, name: &str, address: u32)
+    -> Result<(), String> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ',' || c == '&') {
+        return Err(format!("bad label {:?}", name));
+    }
+    if labels.insert(name.to_string(), address).is_some() {
+        return Err(format!("duplicate label {}", name));
+    }
+    Ok(())
+}
+
+/// Parses a hexadecimal 256-bit value into its 16-bit halves, least
+/// significant first.
+fn parse_u256(value: &str) -> Result<[u32; 16], String> {
+    let digits = value.strip_prefix("0x").ok_or_else(|| format!("{} is not hexadecimal", value))?;
+    if digits.is_empty() || digits.len() > 64 {
+        return Err(format!("{} does not fit in 256 bits", value));
+    }
+    let mut halves = [0; 16];
+    for (index, chunk) in digits.as_bytes().rchunks(4).enumerate() {
+        let chunk = std::str::from_utf8(chunk).unwrap();
+        halves[index] = u32::from_str_radix(chunk, 16)
+            .map_err(|_| format!("{} is not hexadecimal", value))?;
+    }
+    Ok(halves)
+}
+
+fn split2(operands: &str) -> Result<(&str, &str), String> {
+    let fields: Vec<&str> = operands.split(',').map(str::trim).collect();
+    match fields[..] {
+        [a, b] => Ok((a, b)),
+        _ => Err(format!("expected 2 operands: {}", operands)),
+    }
+}
+
+fn split3(operands: &str) -> Result<(&str, &str, &str), String> {
+    let fields: Vec<&str> = operands.split(',').map(str::trim).collect();
+    match fields[..] {
+        [a, b, c] => Ok((a, b, c)),
+        _ => Err(format!("expected 3 operands: {}", operands)),
+    }
+}
+
+fn reg(operand: &str) -> Result<u32, String> {
+    operand.strip_prefix('r')
+        .and_then(|number| number.parse().ok())
+        .filter(|number| *number < 32)
+        .ok_or_else(|| format!("bad register {}", operand))
+}
+
+fn parse_immediate(operand: &str) -> Result<u32, String> {
+    operand.strip_prefix('#')
+        .and_then(|number| number.parse().ok())
+        .ok_or_else(|| format!("bad immediate {}", operand))
+}
+
+// A register with an optional byte-multiple shift: "rB", "rB << N" or
+// "rB >> N". Returns the register and the shift field.
+fn shifted_reg(operand: &str) -> Result<(u32, u32), String> {
+    let (register, shift) = match (operand.find("<<"), operand.find(">>")) {
+        (Some(index), None) => (&operand[..index], Some((&operand[index + 2..], 0))),
+        (None, Some(index)) => (&operand[..index], Some((&operand[index + 2..], 0x80))),
+        _ => (operand, None),
+    };
+    let register = reg(register.trim())?;
+    let shift = match shift {
+        None => 0,
+        Some((bits, right)) => {
+            let bits: u32 = bits.trim().parse().map_err(|_| format!("bad shift in {}", operand))?;
+            if bits & 7 != 0 || bits >= 1024 {
+                return Err(format!("shift in {} is not a byte multiple", operand));
+            }
+            (bits / 8) | right
+        },
+    };
+    Ok((register, shift))
+}
+
+// A 128-bit half of a register for mul128: "rAl" or "rAu".
+fn half_reg(operand: &str) -> Result<(u32, bool), String> {
+    match operand.as_bytes().last() {
+        Some(b'l') => Ok((reg(&operand[..operand.len() - 1])?, false)),
+        Some(b'u') => Ok((reg(&operand[..operand.len() - 1])?, true)),
+        _ => Err(format!("bad register half {}", operand)),
+    }
+}
+
+// A data memory pointer operand for ld and st: "*N" or "*N++".
+fn pointer(operand: &str) -> Result<u32, String> {
+    let operand = operand.strip_prefix('*').ok_or_else(|| format!("bad pointer {}", operand))?;
+    let (number, increment) = match operand.strip_suffix("++") {
+        Some(number) => (number, 8),
+        None => (operand, 0),
+    };
+    number.parse::<u32>().ok()
+        .filter(|number| *number < 8)
+        .map(|number| number | increment)
+        .ok_or_else(|| format!("bad pointer *{}", operand))
+}
+
+fn address_of(name: &str, labels: &BTreeMap<String, u32>) -> Result<u32, String> {
+    labels.get(name.strip_prefix('&').unwrap_or(name)).copied()
+        .ok_or_else(|| format!("undefined label {}", name))
+}
+
+/// Encodes one instruction, given the addresses of the labels it may use.
+pub fn encode(text: &str, labels: &BTreeMap<String, u32>) -> Result<u32, String> {
+    let (mnemonic, operands) = match text.find(' ') {
+        Some(index) => (&text[..index], text[index + 1..].trim()),
+        None => (text, ""),
+    };
+    let alu = |op: u32, carry: bool| -> Result<u32, String> {
+        let (d, a, b) = split3(operands)?;
+        let (b, shift) = shifted_reg(b)?;
+        Ok(op << 26 | (carry as u32) << 23 | reg(d)? << 18 | reg(a)? << 8 | b << 13 | shift)
+    };
+    let three = |op: u32, flags: u32| -> Result<u32, String> {
+        let (d, a, b) = split3(operands)?;
+        Ok(op << 26 | reg(d)? << 18 | reg(a)? << 8 | reg(b)? << 13 | flags)
+    };
+    let branch = |condition: u32| -> Result<u32, String> {
+        Ok(0x1000_0000 | condition << 12 | address_of(operands, labels)?)
+    };
+    Ok(match mnemonic {
+        "nop" => 0xfc00_0000,
+        "ret" => 0x0c00_0000,
+        "sigini" => 0xf800_0000 | parse_immediate(operands)?,
+        "call" => 0x0800_0000 | address_of(operands, labels)?,
+        "b" => branch(0x80)?,
+        "bl" => branch(0x01)?,
+        "bnl" => branch(0x81)?,
+        "bm" => branch(0x02)?,
+        "bnm" => branch(0x82)?,
+        "bz" => branch(0x04)?,
+        "bnz" => branch(0x84)?,
+        "bc" => branch(0x08)?,
+        "bnc" => branch(0x88)?,
+        "and" => alu(0x10, false)?,
+        "or" => alu(0x11, false)?,
+        "xor" => alu(0x13, false)?,
+        "add" => alu(0x14, false)?,
+        "addc" => alu(0x14, true)?,
+        "sub" => alu(0x15, false)?,
+        "subb" => alu(0x15, true)?,
+        "addi" | "subi" => {
+            let (d, a, immediate) = split3(operands)?;
+            let immediate = parse_immediate(immediate)?;
+            if immediate > 0xff {
+                return Err(format!("immediate too large: {}", text));
+            }
+            let op = if mnemonic == "addi" { 0x14 } else { 0x15 };
+            op << 26 | 1 << 24 | reg(d)? << 18 | reg(a)? << 8 | immediate
+        },
+        "notx" => {
+            let (d, b) = split2(operands)?;
+            0x4a00_0000 | reg(d)? << 18 | reg(b)? << 13
+        },
+        "mul128" => {
+            let (d, a, b) = split3(operands)?;
+            let ((a, a_upper), (b, b_upper)) = (half_reg(a)?, half_reg(b)?);
+            0x16 << 26 | reg(d)? << 18 | a << 8 | b << 13 |
+                (a_upper as u32) << 23 | (b_upper as u32) << 24
+        },
+        "cmp" => {
+            let (a, b) = split2(operands)?;
+            0x17 << 26 | reg(a)? << 8 | reg(b)? << 13
+        },
+        "sell" => three(0x19, 0x01)?,
+        "selm" => three(0x19, 0x02)?,
+        "selz" => three(0x19, 0x04)?,
+        "selc" => three(0x19, 0x08)?,
+        "rshi" => {
+            let (d, a, b) = split3(operands)?;
+            let index = b.find(">>").ok_or_else(|| format!("rshi needs >>: {}", text))?;
+            let bits: u32 = b[index + 2..].trim().parse()
+                .ok().filter(|bits| *bits < 256)
+                .ok_or_else(|| format!("bad shift: {}", text))?;
+            0x1a << 26 | reg(d)? << 18 | reg(a)? << 8 | reg(b[..index].trim())? << 13 | bits
+        },
+        "mov" => {
+            let (d, a) = split2(operands)?;
+            0x7c00_0000 | reg(d)? << 18 | reg(a)? << 8
+        },
+        "movi" => {
+            let (target, immediate) = split2(operands)?;
+            let bytes = target.as_bytes();
+            let bad = || format!("bad movi target {}", target);
+            if bytes.len() < 5 || bytes[bytes.len() - 3] != b'.' {
+                return Err(bad());
+            }
+            let d = reg(&target[..target.len() - 3])?;
+            let word = (bytes[bytes.len() - 2] as char).to_digit(8).ok_or_else(bad)?;
+            let upper = match bytes[bytes.len() - 1] {
+                b'l' => 0,
+                b'h' => 1,
+                _ => return Err(bad()),
+            };
+            let immediate = parse_immediate(immediate)?;
+            if immediate > 0xffff {
+                return Err(format!("immediate too large: {}", text));
+            }
+            0x20 << 26 | word << 23 | d << 18 | upper << 16 | immediate
+        },
+        "ldi" => {
+            let (d, cell) = split2(operands)?;
+            let cell = cell.strip_prefix('[').and_then(|cell| cell.strip_suffix(']'))
+                .ok_or_else(|| format!("bad ldi address: {}", text))?;
+            0x21 << 26 | reg(d)? << 18 | 0x4000 | parse_immediate(cell)?
+        },
+        "ld" | "st" => {
+            let (a, b) = split2(operands)?;
+            let (a, b) = (pointer(a)?, pointer(b)?);
+            if mnemonic == "ld" {
+                0x23 << 26 | a << 18 | (0x10 | b) << 8
+            } else {
+                0x24 << 26 | (0x10 | b) << 18 | a << 8
+            }
+        },
+        "lddmp" => 0x9580_0000 | reg(operands)? << 8,
+        "ldrfp" => 0x9780_0000 | reg(operands)? << 8,
+        "ldmod" => 0x9880_0000 | reg(operands)? << 8,
+        "stmod" => 0x9800_0000 | reg(operands)? << 18,
+        "strnd" => 0x9900_0000 | reg(operands)? << 18,
+        "ldrnd" => 0x9980_0000 | reg(operands)? << 8,
+        "addm" => three(0x27, 0)?,
+        "subm" => three(0x28, 0)?,
+        _ => return Err(format!("unknown instruction: {}", text)),
+    })
+}
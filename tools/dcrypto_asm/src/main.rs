@@ -0,0 +1,40 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! dcrypto_asm assembles a dcrypto program source and prints the body of the
+//! Rust array that embeds it, followed by the addresses of its functions.
+//!
+//! Usage: `dcrypto_asm <source.dasm>`
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: {} <source.dasm>", args[0]);
+        std::process::exit(2);
+    }
+    let source = std::fs::read_to_string(&args[1]).unwrap_or_else(|err| {
+        eprintln!("cannot read {}: {}", args[1], err);
+        std::process::exit(1);
+    });
+    let program = dcrypto_asm::assemble(&source).unwrap_or_else(|err| {
+        eprintln!("{}: {}", args[1], err);
+        std::process::exit(1);
+    });
+    print!("static PROGRAM: [u32; {}] = [\n{}];\n", program.words().len(), program.listing());
+    for (name, address) in &program.labels {
+        eprintln!("{} = {}", name, address);
+    }
+}
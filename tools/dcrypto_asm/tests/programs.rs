@@ -0,0 +1,138 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks the dcrypto programs embedded in the tree against their sources.
+
+use dcrypto_asm::assemble;
+use std::path::PathBuf;
+
+fn read(path: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../..").join(path);
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("cannot read {}: {}", path.display(), err))
+}
+
+/// Returns the lines between `start` (exclusive) and `end`.
+fn between<'a>(text: &'a str, start: &str, end: &str) -> &'a str {
+    let start = text.find(start).expect("start marker");
+    let body = &text[text[start..].find('\n').unwrap() + start + 1..];
+    &body[..body.find(end).expect("end marker")]
+}
+
+/// Returns the value of `pub const NAME: u32 = VALUE;` in `text`.
+fn constant(text: &str, name: &str) -> u32 {
+    let prefix = format!("pub const {}: u32 = ", name);
+    let line = text.lines().find(|line| line.starts_with(&prefix)).expect("constant");
+    line[prefix.len()..].trim_end_matches(';').parse().unwrap()
+}
+
+/// Checks that the program embedded in `embedded` is `source` assembled,
+/// and that its entry point constants match the labels.
+fn check_embedded(source: &str, embedded: &str, entry_points: &[(&str, &str)]) {
+    let program = assemble(&read(source)).unwrap();
+    let embedded = read(embedded);
+    let words = program.words();
+    assert!(embedded.contains(&format!("static PROGRAM: [u32; {}] = [", words.len())));
+    assert_eq!(between(&embedded, "static PROGRAM", "];"), program.listing());
+    for (name, label) in entry_points {
+        assert_eq!(constant(&embedded, name), program.labels[*label], "{}", name);
+    }
+}
+
+#[test]
+fn curve25519_matches_source() {
+    check_embedded("kernel/h1/src/crypto/curve25519.dasm", "kernel/h1/src/crypto/curve25519.rs",
+                   &[("X25519", "x25519"), ("ED25519_MUL", "ed25519mul"),
+                     ("ED25519_VERIFY", "ed25519verify")]);
+}
+
+#[test]
+fn rsa_matches_source() {
+    check_embedded("kernel/h1/src/crypto/rsa.dasm", "kernel/h1/src/crypto/rsa.rs",
+                   &[("MODEXP", "modexp")]);
+}
+
+/// Turns the listing of the cr50 P-256 program in the u2f app back into
+/// source. Returns the source and the words of the listing.
+fn cr50_p256() -> (String, Vec<u32>) {
+    let listing = read("userspace/u2f_app/p256_ecdsa.c");
+    let listing = between(&listing, "IMEM_dcrypto_p256[] = {", "};");
+    let mut source = String::new();
+    let mut words = Vec::new();
+    for line in listing.lines() {
+        let line = line.trim();
+        let comment = match (line.find("/*"), line.rfind("*/")) {
+            (Some(start), Some(end)) => line[start + 2..end].trim(),
+            _ => continue,
+        };
+        if line.starts_with("0x") {
+            words.push(u32::from_str_radix(&line[2..10], 16).unwrap());
+        }
+        if let Some(function) = comment.strip_prefix('@') {
+            let name = &function[function.find("function ").unwrap() + 9..function.find('[').unwrap()];
+            source.push_str(&format!("function {} {{\n", name));
+        } else {
+            source.push_str(comment);
+            source.push('\n');
+        }
+    }
+    (source, words)
+}
+
+/// The cr50 P-256 program was assembled by the cr50 tools. Turning its
+/// listing back into source and assembling it must give the same words.
+#[test]
+fn reassembles_cr50_p256() {
+    let (source, expected) = cr50_p256();
+    let program = assemble(&source).unwrap();
+    assert_eq!(program.words().len(), expected.len());
+    for (address, (word, expected)) in program.words().iter().zip(&expected).enumerate() {
+        assert_eq!(word, expected, "word {:#x}", address);
+    }
+}
+
+#[test]
+fn p256_matches_cr50() {
+    let program = assemble(&cr50_p256().0).unwrap();
+    let embedded = read("kernel/h1/src/crypto/p256.rs");
+    let words = program.words();
+    assert!(embedded.contains(&format!("static PROGRAM: [u32; {}] = [", words.len())));
+    assert_eq!(between(&embedded, "static PROGRAM", "];"), program.listing());
+    for (name, label) in &[("INIT", "p256init"), ("SIGN", "p256sign"),
+                           ("BASE_MUL", "p256scalarbasemult"), ("VERIFY", "p256verify")] {
+        assert_eq!(constant(&embedded, name), program.labels[*label], "{}", name);
+    }
+}
+
+#[test]
+fn reports_errors_with_line_numbers() {
+    let error = |source: &str| assemble(source).err().unwrap();
+    assert_eq!(error("function f {\n  frob r1\n}"), "line 2: unknown instruction: frob r1");
+    assert_eq!(error("function f {\n  call &g\n}"), "line 2: undefined label &g");
+    assert_eq!(error("function f {\n  loop #2 (\n  )\n}"), "line 3: bad loop body length 0");
+    assert_eq!(error("function f {\n  const r1, K\n}"), "line 2: undefined constant K");
+    assert!(error("function f {\n  ret").starts_with("unterminated"));
+}
+
+#[test]
+fn expands_constants() {
+    let program = assemble("define K 0x10002\nfunction f {\n  const r3, K\n}\n").unwrap();
+    let words = program.words();
+    assert_eq!(words.len(), 17);
+    assert_eq!(words[0], 0x7c0c1f00); // mov r3, r31
+    assert_eq!(words[1], 0x800c0002); // movi r3.0l, #2
+    assert_eq!(words[2], 0x800d0001); // movi r3.0h, #1
+}
@@ -0,0 +1,317 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! doctor checks that the host is set up to build tock-on-titan and to run it
+//! on a board, and prints how to fix whatever is missing.
+//!
+//! With --probe, it also resets the board using the runner and reports the
+//! firmware banners printed on the console. This restarts the firmware.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Minimum stable cargo version, matching `cargo_version_check` in the
+/// top-level Makefile.
+const MIN_CARGO_VERSION: (u32, u32, u32) = (1, 37, 0);
+
+const TARGET: &str = "thumbv7m-none-eabi";
+
+/// Console devices used by runner and `make run`.
+const DEBUG_CONSOLE: &str = "/dev/ttyUltraConsole3";
+const TARGET_CONSOLE: &str = "/dev/ttyUltraTarget2";
+const CONSOLE_BAUDRATE: &str = "115200";
+
+/// Seconds of console output collected by --probe.
+const PROBE_SECONDS: &str = "5";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Default)]
+struct Report {
+    statuses: Vec<Status>,
+}
+
+impl Report {
+    fn ok<M: Into<String>>(&mut self, message: M) {
+        self.add(Status::Ok, message.into(), None);
+    }
+
+    fn warning<M: Into<String>, F: Into<String>>(&mut self, message: M, fix: F) {
+        self.add(Status::Warning, message.into(), Some(fix.into()));
+    }
+
+    fn error<M: Into<String>, F: Into<String>>(&mut self, message: M, fix: F) {
+        self.add(Status::Error, message.into(), Some(fix.into()));
+    }
+
+    // Prints findings as they come in, since some checks are slow.
+    fn add(&mut self, status: Status, message: String, fix: Option<String>) {
+        let tag = match status {
+            Status::Ok => "[ OK ]",
+            Status::Warning => "[WARN]",
+            Status::Error => "[FAIL]",
+        };
+        println!("{} {}", tag, message);
+        if let Some(fix) = &fix {
+            for line in fix.lines() {
+                println!("       {}", line);
+            }
+        }
+        self.statuses.push(status);
+    }
+
+    fn count(&self, status: Status) -> usize {
+        self.statuses.iter().filter(|found| **found == status).count()
+    }
+}
+
+/// Runs `program` and returns its stdout if it exited successfully.
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses the first "x.y.z" version in `text`.
+fn parse_version(text: &str) -> Option<(u32, u32, u32)> {
+    text.split_whitespace().find_map(|word| {
+        let mut parts = word.trim_matches('"').split(&['.', '-'][..]);
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next()?.parse().ok()?;
+        Some((major, minor, patch))
+    })
+}
+
+/// Finds the repository root by walking up from the current directory.
+fn find_root() -> Option<PathBuf> {
+    let mut dir = std::env::current_dir().ok()?;
+    loop {
+        if dir.join("DirShim.mk").is_file() && dir.join("kernel").is_dir() {
+            return Some(dir);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+fn check_submodules(report: &mut Report, root: &Path) {
+    let missing: Vec<&str> = ["third_party/tock", "third_party/libtock-rs", "third_party/elf2tab"]
+        .iter()
+        .copied()
+        .filter(|dir| !root.join(dir).join("Cargo.toml").is_file()
+                && !root.join(dir).join("kernel/Cargo.toml").is_file())
+        .collect();
+    if missing.is_empty() {
+        report.ok("git submodules are checked out");
+    } else {
+        report.error(format!("git submodules are missing: {}", missing.join(", ")),
+                     "git submodule update --init --recursive");
+    }
+}
+
+fn check_cargo(report: &mut Report) {
+    let version = match run("cargo", &["-V"]) {
+        Some(version) => version,
+        None => {
+            report.error("cargo not found",
+                         "curl https://sh.rustup.rs -sSf | sh, then run `make setup`");
+            return;
+        }
+    };
+    match parse_version(&version) {
+        Some(found) if found >= MIN_CARGO_VERSION => report.ok(version.trim()),
+        _ => report.error(
+            format!("{} is older than {}.{}.{}", version.trim(),
+                    MIN_CARGO_VERSION.0, MIN_CARGO_VERSION.1, MIN_CARGO_VERSION.2),
+            "rustup update stable"),
+    }
+}
+
+/// Checks the nightly toolchain pinned by `<dir>/rust-toolchain`.
+fn check_toolchain(report: &mut Report, root: &Path, dir: &str) {
+    let toolchain = match std::fs::read_to_string(root.join(dir).join("rust-toolchain")) {
+        Ok(toolchain) => toolchain.trim().to_string(),
+        Err(_) => {
+            report.error(format!("{}/rust-toolchain is unreadable", dir),
+                         "git submodule update --init --recursive");
+            return;
+        }
+    };
+
+    let installed = run("rustup", &["toolchain", "list"]).unwrap_or_default();
+    if !installed.lines().any(|line| line.starts_with(&toolchain)) {
+        report.error(format!("{} toolchain {} is not installed", dir, toolchain),
+                     format!("rustup toolchain install {}\nmake setup", toolchain));
+        return;
+    }
+
+    let targets = run("rustup", &["target", "list", "--installed", "--toolchain", &toolchain])
+        .unwrap_or_default();
+    if targets.lines().any(|line| line.trim() == TARGET) {
+        report.ok(format!("{} toolchain {} with {}", dir, toolchain, TARGET));
+    } else {
+        report.error(format!("{} toolchain {} lacks the {} target", dir, toolchain, TARGET),
+                     "make setup");
+    }
+}
+
+fn check_bwrap(report: &mut Report) {
+    match run("bwrap", &["--version"]) {
+        Some(version) => report.ok(version.trim()),
+        None => report.error("bwrap not found; the build runs inside a bubblewrap sandbox",
+                             "sudo apt-get install bubblewrap"),
+    }
+}
+
+/// Compares the built elf2tab against the version in third_party.
+fn check_elf2tab(report: &mut Report, root: &Path) {
+    let elf2tab = root.join("build/cargo-host/release/elf2tab");
+    if !elf2tab.is_file() {
+        report.warning("elf2tab has not been built yet", "make build (builds it on first use)");
+        return;
+    }
+
+    let expected = std::fs::read_to_string(root.join("third_party/elf2tab/Cargo.toml"))
+        .ok()
+        .and_then(|manifest| manifest.lines()
+            .find(|line| line.starts_with("version"))
+            .and_then(parse_version));
+    let found = run(&elf2tab.to_string_lossy(), &["--version"]).and_then(|out| parse_version(&out));
+    match (expected, found) {
+        (Some(expected), Some(found)) if expected == found => {
+            report.ok(format!("elf2tab {}.{}.{}", found.0, found.1, found.2))
+        }
+        (Some(expected), _) => report.error(
+            format!("elf2tab in build/ does not match third_party/elf2tab {}.{}.{}",
+                    expected.0, expected.1, expected.2),
+            "rm build/cargo-host/release/elf2tab && make build"),
+        (None, _) => report.warning("could not determine the elf2tab version",
+                                    "git submodule update --init --recursive"),
+    }
+}
+
+fn check_env(report: &mut Report, variable: &str, purpose: &str) {
+    match std::env::var_os(variable) {
+        Some(_) => report.ok(format!("{} is set", variable)),
+        None => report.warning(format!("{} is not set; needed for {}", variable, purpose),
+                               format!("export {}=<path>", variable)),
+    }
+}
+
+fn check_device(report: &mut Report, device: &str) {
+    if !Path::new(device).exists() {
+        report.error(format!("{} does not exist", device),
+                     "Connect the Ultra debug board and check that its udev rules\n\
+                      create the /dev/ttyUltra* symlinks.");
+        return;
+    }
+    if let Err(err) = std::fs::OpenOptions::new().read(true).write(true).open(device) {
+        report.error(format!("cannot open {}: {}", device, err),
+                     "Add a udev rule with MODE=\"0666\" for the Ultra devices, or add\n\
+                      yourself to the group owning them (usually dialout) and log in again.");
+        return;
+    }
+    match run("stty", &["-F", device, "speed"]) {
+        Some(speed) if speed.trim() == CONSOLE_BAUDRATE => report.ok(format!("{} is usable", device)),
+        _ => report.warning(format!("{} is not configured for {} baud", device, CONSOLE_BAUDRATE),
+                            format!("stty -F {} {} -echo -icrnl", device, CONSOLE_BAUDRATE)),
+    }
+}
+
+/// Resets the board with the runner and reports what the firmware printed.
+fn probe_board(report: &mut Report, root: &Path) {
+    let runner = root.join("build/cargo-host/release/runner");
+    if !runner.is_file() {
+        report.error("runner has not been built", "make runner/build");
+        return;
+    }
+    let output = match run(&runner.to_string_lossy(), &["--timeout", PROBE_SECONDS]) {
+        Some(output) => output,
+        None => {
+            report.error("runner failed to reset the board",
+                         "Check the console device findings above.");
+            return;
+        }
+    };
+
+    let banners: Vec<&str> = output.lines()
+        .map(|line| line.trim())
+        .filter(|line| line.starts_with("Starting ") || line.starts_with("Tock: starting"))
+        .collect();
+    if banners.is_empty() {
+        report.error(format!("no firmware banner within {} seconds of reset", PROBE_SECONDS),
+                     "Program a full image, e.g. make -C userspace/otpilot program");
+    } else {
+        for banner in banners {
+            report.ok(format!("board: {}", banner));
+        }
+    }
+}
+
+fn main() {
+    let cmdline_matches = clap::App::new("doctor")
+        .about("Checks the tock-on-titan development setup")
+        .arg(clap::Arg::with_name("root")
+            .help("Path to the tock-on-titan checkout (default: search upwards)")
+            .long("root")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("probe")
+            .help("Reset the board and report the firmware it runs")
+            .long("probe"))
+        .get_matches();
+
+    let root = match cmdline_matches.value_of("root") {
+        Some(root) => PathBuf::from(root),
+        None => find_root().expect("Run doctor inside the tock-on-titan checkout or pass --root"),
+    };
+
+    let mut report = Report::default();
+
+    println!("Toolchain:");
+    check_submodules(&mut report, &root);
+    check_cargo(&mut report);
+    check_toolchain(&mut report, &root, "kernel");
+    check_toolchain(&mut report, &root, "userspace");
+    check_bwrap(&mut report);
+    check_elf2tab(&mut report, &root);
+    check_env(&mut report, "TANGO_SPIFLASH", "`make program` and `make run`");
+    check_env(&mut report, "TANGO_CODESIGNER", "`make build-signed`");
+
+    println!("Devices:");
+    check_device(&mut report, DEBUG_CONSOLE);
+    check_device(&mut report, TARGET_CONSOLE);
+
+    if cmdline_matches.is_present("probe") {
+        println!("Board:");
+        probe_board(&mut report, &root);
+    }
+
+    let errors = report.count(Status::Error);
+    println!("{} problem(s), {} warning(s)", errors, report.count(Status::Warning));
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
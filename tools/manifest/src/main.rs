@@ -0,0 +1,306 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! manifest creates and checks signed firmware manifests (see
+//! `spiutils::protocol::manifest`) for built images.
+//!
+//! `manifest sign` hashes the loadable segments of an image ELF and signs the
+//! manifest, either with a private key file or with a key held in a PKCS#11
+//! token through OpenSC's pkcs11-tool. `manifest verify` checks a manifest
+//! against an image and a public key, and `manifest public-key` prints the
+//! public key of a private key file.
+//!
+//! Key files hold hex: the 32 byte big-endian private scalar, or the 64 byte
+//! public key x || y (optionally prefixed with the 04 uncompressed point tag).
+
+use ecc::p256::PrivateKey;
+use ecc::p256::PublicKey;
+use ecc::p256::Signature;
+use ecc::p256::SCALAR_LEN;
+use ecc::rfc6979::HmacSha256;
+use ecc::rfc6979::HMAC_LEN;
+use spiutils::io::StdWrite;
+use spiutils::protocol::manifest;
+use spiutils::protocol::manifest::Manifest;
+use spiutils::protocol::manifest::ManifestSegment;
+use spiutils::protocol::wire::FromWire;
+use spiutils::protocol::wire::ToWire;
+use std::path::Path;
+use std::process::Command;
+
+type Result<T> = std::result::Result<T, String>;
+
+/// Environment variable holding the PKCS#11 user PIN. Without it,
+/// pkcs11-tool prompts for the PIN.
+const PIN_VARIABLE: &str = "PKCS11_PIN";
+
+struct SoftwareHmac;
+
+impl HmacSha256 for SoftwareHmac {
+    fn hmac_sha256(&self, key: &[u8; HMAC_LEN], data: &[&[u8]])
+        -> std::result::Result<[u8; HMAC_LEN], ecc::p256::Error> {
+        Ok(ecc::sha256::hmac(key, data))
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|err| format!("cannot read {}: {}", path.display(), err))
+}
+
+fn parse_hex(path: &Path) -> Result<Vec<u8>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    let digits: Vec<u8> = text.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    if digits.len() & 1 != 0 {
+        return Err(format!("{} has an odd number of hex digits", path.display()));
+    }
+    digits.chunks(2)
+        .map(|pair| std::str::from_utf8(pair).ok()
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or_else(|| format!("{} is not hex", path.display())))
+        .collect()
+}
+
+fn read_private_key(path: &Path) -> Result<PrivateKey> {
+    let bytes = parse_hex(path)?;
+    if bytes.len() != SCALAR_LEN {
+        return Err(format!("{} holds {} bytes, expected a {} byte private key",
+                           path.display(), bytes.len(), SCALAR_LEN));
+    }
+    let mut scalar = [0; SCALAR_LEN];
+    scalar.copy_from_slice(&bytes);
+    let key = PrivateKey::from_bytes(&scalar)
+        .map_err(|_| format!("{} is not a valid P-256 private key", path.display()));
+    ecc::wipe(&mut scalar);
+    key
+}
+
+fn read_public_key(path: &Path) -> Result<PublicKey> {
+    let bytes = parse_hex(path)?;
+    let point = match bytes.len() {
+        64 => &bytes[..],
+        65 if bytes[0] == 0x04 => &bytes[1..],
+        len => return Err(format!("{} holds {} bytes, expected a 64 byte public key",
+                                  path.display(), len)),
+    };
+    let mut key = PublicKey { x: [0; SCALAR_LEN], y: [0; SCALAR_LEN] };
+    key.x.copy_from_slice(&point[..SCALAR_LEN]);
+    key.y.copy_from_slice(&point[SCALAR_LEN..]);
+    if !key.is_valid() {
+        return Err(format!("{} is not a point on P-256", path.display()));
+    }
+    Ok(key)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Returns the loadable segments of the ELF at `path`, at their load
+/// addresses.
+fn image_segments(path: &Path) -> Result<Vec<ManifestSegment>> {
+    let data = read(path)?;
+    let elf = elf::File::open_path(path)
+        .map_err(|err| format!("cannot parse {}: {:?}", path.display(), err))?;
+    elf.phdrs.iter()
+        .filter(|phdr| phdr.progtype == elf::types::PT_LOAD && phdr.filesz > 0)
+        .map(|phdr| {
+            let start = phdr.offset as usize;
+            let contents = start.checked_add(phdr.filesz as usize)
+                .and_then(|end| data.get(start..end))
+                .ok_or_else(|| format!("{}: segment at {:#010x} is outside the file",
+                                       path.display(), phdr.paddr))?;
+            Ok(ManifestSegment::new(phdr.paddr as u32, contents))
+        })
+        .collect()
+}
+
+/// Signs `digest` with the key `id` on a PKCS#11 token.
+fn pkcs11_sign(module: &str, id: &str, digest: &[u8]) -> Result<Signature> {
+    let dir = std::env::temp_dir();
+    let input = dir.join(format!("manifest-digest-{}", std::process::id()));
+    let output = dir.join(format!("manifest-signature-{}", std::process::id()));
+    std::fs::write(&input, digest)
+        .map_err(|err| format!("cannot write {}: {}", input.display(), err))?;
+
+    let mut command = Command::new("pkcs11-tool");
+    command.args(["--module", module, "--id", id, "--login", "--sign", "--mechanism", "ECDSA"])
+        .arg("--input-file").arg(&input)
+        .arg("--output-file").arg(&output);
+    if let Ok(pin) = std::env::var(PIN_VARIABLE) {
+        command.arg("--pin").arg(pin);
+    }
+    let status = command.status().map_err(|err| format!("cannot run pkcs11-tool: {}", err));
+    let _ = std::fs::remove_file(&input);
+    let status = status?;
+    let raw = read(&output);
+    let _ = std::fs::remove_file(&output);
+    if !status.success() {
+        return Err(format!("pkcs11-tool failed ({})", status));
+    }
+
+    // pkcs11-tool returns the raw r || s for ECDSA.
+    let raw = raw?;
+    if raw.len() != 2 * SCALAR_LEN {
+        return Err(format!("pkcs11-tool returned a {} byte signature, expected {}",
+                           raw.len(), 2 * SCALAR_LEN));
+    }
+    let mut signature = Signature { r: [0; SCALAR_LEN], s: [0; SCALAR_LEN] };
+    signature.r.copy_from_slice(&raw[..SCALAR_LEN]);
+    signature.s.copy_from_slice(&raw[SCALAR_LEN..]);
+    Ok(signature)
+}
+
+fn sign(matches: &clap::ArgMatches) -> Result<()> {
+    let image = Path::new(matches.value_of("image").unwrap());
+    let output = Path::new(matches.value_of("output").unwrap());
+    let segments = image_segments(image)?;
+
+    let private_key = match matches.value_of("key") {
+        Some(path) => Some(read_private_key(Path::new(path))?),
+        None => None,
+    };
+    let public_key = match (matches.value_of("public-key"), &private_key) {
+        (Some(path), _) => read_public_key(Path::new(path))?,
+        (None, Some(private_key)) => private_key.public_key(),
+        (None, None) => return Err("either --key or --pkcs11-module is required".to_string()),
+    };
+
+    let mut manifest = Manifest::new(manifest::key_id(&public_key), &segments)
+        .ok_or_else(|| format!("{} has {} loadable segments; a manifest holds at most {}",
+                               image.display(), segments.len(),
+                               manifest::MAX_MANIFEST_SEGMENTS))?;
+    let digest = manifest.signed_digest();
+    manifest.signature = match &private_key {
+        Some(private_key) => private_key.sign_deterministic(&digest, &SoftwareHmac)
+            .map_err(|err| format!("signing failed: {:?}", err))?,
+        None => pkcs11_sign(matches.value_of("pkcs11-module").unwrap(),
+                            matches.value_of("pkcs11-id").unwrap(), &digest)?,
+    };
+    // Catches a public key that does not belong to the token's key.
+    if !manifest.verify(&public_key) {
+        return Err("the signature does not verify with the public key".to_string());
+    }
+
+    let mut encoded = Vec::new();
+    manifest.to_wire(StdWrite(&mut encoded))
+        .map_err(|err| format!("cannot encode the manifest: {:?}", err))?;
+    std::fs::write(output, &encoded)
+        .map_err(|err| format!("cannot write {}: {}", output.display(), err))?;
+    print_manifest(&manifest);
+    Ok(())
+}
+
+fn verify(matches: &clap::ArgMatches) -> Result<()> {
+    let image = Path::new(matches.value_of("image").unwrap());
+    let manifest_path = Path::new(matches.value_of("manifest").unwrap());
+    let public_key = read_public_key(Path::new(matches.value_of("public-key").unwrap()))?;
+
+    let encoded = read(manifest_path)?;
+    let manifest = Manifest::from_wire(&encoded[..])
+        .map_err(|err| format!("{} is not a manifest: {:?}", manifest_path.display(), err))?;
+    print_manifest(&manifest);
+    if manifest.key_id != manifest::key_id(&public_key) {
+        return Err(format!("the manifest is signed by key {:08x}, not {:08x}",
+                           manifest.key_id, manifest::key_id(&public_key)));
+    }
+    if !manifest.verify(&public_key) {
+        return Err("the manifest signature is invalid".to_string());
+    }
+    if image_segments(image)?.as_slice() != manifest.segments() {
+        return Err(format!("{} does not match the manifest", image.display()));
+    }
+    println!("OK");
+    Ok(())
+}
+
+fn print_manifest(manifest: &Manifest) {
+    println!("key {:08x}", manifest.key_id);
+    for segment in manifest.segments() {
+        println!("{:#010x} {:#010x} {}", segment.address, segment.length, hex(&segment.digest));
+    }
+}
+
+fn main() {
+    let cmdline_matches = clap::App::new("manifest")
+        .about("Creates and verifies signed firmware manifests")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("sign")
+            .about("Creates a signed manifest for an image")
+            .arg(clap::Arg::with_name("image")
+                .help("The image ELF, e.g. build/userspace/otpilot/papa/unsigned_image_a")
+                .required(true))
+            .arg(clap::Arg::with_name("output")
+                .help("Where to write the manifest")
+                .long("output")
+                .takes_value(true)
+                .required(true))
+            .arg(clap::Arg::with_name("key")
+                .help("Private key file (hex)")
+                .long("key")
+                .takes_value(true)
+                .conflicts_with("pkcs11-module"))
+            .arg(clap::Arg::with_name("pkcs11-module")
+                .help("PKCS#11 module holding the signing key")
+                .long("pkcs11-module")
+                .takes_value(true)
+                .requires_all(&["pkcs11-id", "public-key"]))
+            .arg(clap::Arg::with_name("pkcs11-id")
+                .help("ID of the signing key on the PKCS#11 token (hex)")
+                .long("pkcs11-id")
+                .takes_value(true))
+            .arg(clap::Arg::with_name("public-key")
+                .help("Public key file (hex) of the signing key")
+                .long("public-key")
+                .takes_value(true)))
+        .subcommand(clap::SubCommand::with_name("verify")
+            .about("Checks a manifest against an image and a public key")
+            .arg(clap::Arg::with_name("image")
+                .help("The image ELF")
+                .required(true))
+            .arg(clap::Arg::with_name("manifest")
+                .help("The manifest to check")
+                .long("manifest")
+                .takes_value(true)
+                .required(true))
+            .arg(clap::Arg::with_name("public-key")
+                .help("Public key file (hex)")
+                .long("public-key")
+                .takes_value(true)
+                .required(true)))
+        .subcommand(clap::SubCommand::with_name("public-key")
+            .about("Prints the public key of a private key file")
+            .arg(clap::Arg::with_name("key")
+                .help("Private key file (hex)")
+                .required(true)))
+        .get_matches();
+
+    let result = match cmdline_matches.subcommand() {
+        ("sign", Some(matches)) => sign(matches),
+        ("verify", Some(matches)) => verify(matches),
+        ("public-key", Some(matches)) => {
+            read_private_key(Path::new(matches.value_of("key").unwrap())).map(|key| {
+                let public_key = key.public_key();
+                println!("04{}{}", hex(&public_key.x), hex(&public_key.y));
+            })
+        }
+        _ => unreachable!(),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! otpilot's startup and main loop (userspace/otpilot/src/main.rs), reduced
+//! to what the reset sequencing needs. Keep them in step.
+
+use crate::alarm;
+use crate::gpio_control;
+use crate::gpio_processor::GpioProcessor;
+use crate::passthrough_guard;
+use crate::sfdp;
+use crate::spi_device;
+use crate::spi_host_h1;
+use crate::spi_host_helper::SpiHostHelper;
+
+use libtock::println;
+use libtock::result::TockError;
+use libtock::result::TockResult;
+use libtock::syscalls::raw::yieldk;
+
+use spiutils::driver::spi_device::AddressConfig;
+use spiutils::driver::spi_device::HandlerMode;
+use spiutils::driver::spi_device::RxBufferMode;
+use spiutils::driver::spi_device::READ_CAPABILITY_DUAL_OUTPUT;
+use spiutils::driver::spi_device::READ_CAPABILITY_QUAD_OUTPUT;
+use spiutils::protocol::flash::AddressMode;
+use spiutils::protocol::payload;
+
+// The same as in otpilot's spi_processor.
+const SPI_FLASH_SIZE: u32 = 0x4000000;
+const SPI_MAILBOX_ADDRESS: u32 = 0x80000;
+
+fn run_host_helper_demo() -> TockResult<()> {
+    // We cannot use the SPI host if passthrough is enabled.
+    spi_host_h1::get().set_passthrough(false)?;
+
+    let host_helper = SpiHostHelper {};
+    host_helper.enter_4b()?;
+
+    host_helper.read_and_print_data(0x0)?;
+
+    if spi_device::get().get_address_mode() == AddressMode::ThreeByte {
+        host_helper.exit_4b()?;
+    }
+
+    Ok(())
+}
+
+/// Runs otpilot until an error. Returns only on error.
+pub fn run() -> TockResult<()> {
+    println!("clock_frequency: {}", alarm::get().get_clock_frequency());
+
+    run_host_helper_demo()?;
+
+    let gpio_processor = GpioProcessor::new();
+
+    spi_device::get().set_address_mode_handling(HandlerMode::KernelSpace)?;
+    spi_device::get().set_rx_buffer_mode(RxBufferMode::Handoff)?;
+    spi_device::get().configure_addresses(AddressConfig {
+        flash_virtual_base: 0x0,
+        flash_physical_base: 0x0,
+        flash_physical_size: SPI_FLASH_SIZE,
+        ram_virtual_base: SPI_MAILBOX_ADDRESS,
+        virtual_size: SPI_FLASH_SIZE,
+    })?;
+
+    // OpenTitan JEDEC ID
+    spi_device::get().set_jedec_id(&mut [0x26, 0x31, 0x19])?;
+
+    {
+        let mut sfdp = [0xff; 128];
+        let read_capabilities = spi_device::get().get_read_capabilities()?;
+        sfdp::get_table(
+            &mut sfdp,
+            SPI_FLASH_SIZE, // image_size_bytes
+            spi_device::get().get_address_mode(), // startup_address_mode
+            spi_device::get().get_address_mode() == AddressMode::ThreeByte, // support_address_mode_switch
+            read_capabilities & READ_CAPABILITY_DUAL_OUTPUT != 0, // support_dual_output_read
+            read_capabilities & READ_CAPABILITY_QUAD_OUTPUT != 0, // support_quad_output_read
+            SPI_MAILBOX_ADDRESS, // mailbox_offset
+            spi_device::MAX_READ_BUFFER_SIZE as u32, // mailbox_size
+            payload::CAPABILITY_HEADER_CRC | payload::CAPABILITY_FRAGMENTS // google_capabilities
+            ).map_err(|_| TockError::Format)?;
+        spi_device::get().set_sfdp(&mut sfdp)?;
+    }
+
+    // We need SPI passthrough to be fully operational.
+    spi_host_h1::get().set_passthrough(true)?;
+
+    // Deassert BMC resets.
+    let _ = gpio_processor.set_bmc_cpu_rst(false);
+    let _ = gpio_processor.set_bmc_srst(false);
+
+    loop {
+        // There are no SPI transactions or console input to wait for.
+        while !gpio_control::get().have_events()
+            && !passthrough_guard::get().is_tripped()
+            && !alarm::get().is_expired() {
+            unsafe { yieldk(); }
+        }
+
+        if gpio_control::get().have_events() {
+            if let Err(err) = gpio_processor.process_gpio_events() {
+                println!("GPIO processor (event): Error {:?}", err);
+            }
+        }
+
+        if passthrough_guard::get().consume_trip() {
+            if let Err(err) = gpio_processor.passthrough_disabled() {
+                println!("GPIO processor (passthrough guard): Error {:?}", err);
+            }
+        }
+
+        if alarm::get().is_expired() {
+            if let Err(err) = gpio_processor.alarm_expired() {
+                println!("GPIO processor (alarm): Error {:?}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The papa board, wired from the syscall_shim fakes as kernel/papa wires the
+//! drivers.
+
+use std::rc::Rc;
+
+use gpioutils::pin::GpioPin;
+
+use syscall_shim::fake::Alarm;
+use syscall_shim::fake::Console;
+use syscall_shim::fake::Gpio;
+use syscall_shim::fake::PassthroughGuard;
+use syscall_shim::fake::SpiDevice;
+use syscall_shim::fake::SpiFlash;
+use syscall_shim::fake::SpiHost;
+use syscall_shim::fake::SpiHostH1;
+
+// The pins otpilot uses, see gpioutils::pin.
+const GPIO_COUNT: usize = 4;
+
+/// The board's peripherals, as the script sees them.
+pub struct Board {
+    pub console: Rc<Console>,
+    pub gpio: Rc<Gpio>,
+    pub flash: Rc<SpiFlash>,
+    pub spi_host_h1: Rc<SpiHostH1>,
+    pub spi_device: Rc<SpiDevice>,
+    pub guard: Rc<PassthroughGuard>,
+}
+
+impl Board {
+    /// Installs the drivers in their state at boot.
+    pub fn install() -> Board {
+        Alarm::install();
+        let console = Console::install();
+
+        let gpio = Gpio::install(GPIO_COUNT);
+        // The reset monitors are active low, and the BMC is running.
+        gpio.drive(GpioPin::SYS_RSTMON_N as usize, true);
+        gpio.drive(GpioPin::BMC_RSTMON_N as usize, true);
+
+        // The flash holds 00 01 .. ff repeated, so that reads show which
+        // address the flash saw.
+        let flash = Rc::new(SpiFlash::new((0..=255).collect()));
+        SpiHost::install(flash.clone());
+        // Passthrough is off at boot unless the board config says otherwise,
+        // and the default config does not.
+        let spi_host_h1 = SpiHostH1::install(false);
+        let spi_device = SpiDevice::install(spi_host_h1.clone(), flash.clone());
+
+        // PASSTHROUGH_GUARD in kernel/papa: armed at boot.
+        let guard = PassthroughGuard::install(spi_host_h1.clone());
+        guard.arm();
+
+        Board {
+            console,
+            gpio,
+            flash,
+            spi_host_h1,
+            spi_device,
+            guard,
+        }
+    }
+
+    /// Drives one of the pins otpilot monitors.
+    pub fn drive(&self, pin: GpioPin, high: bool) {
+        let falling = self.gpio.level(pin as usize) && !high;
+        self.gpio.drive(pin as usize, high);
+        // PASSTHROUGH_GUARD in kernel/papa watches SYS_RSTMON#, active low.
+        if pin == GpioPin::SYS_RSTMON_N && falling {
+            self.guard.fire();
+        }
+    }
+}
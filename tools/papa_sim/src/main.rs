@@ -0,0 +1,118 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! papa_sim runs otpilot's reset sequencing on the host, against fakes of
+//! the papa board's GPIO, SPI and passthrough guard drivers, and checks its
+//! behaviour with a script:
+//!
+//!     papa_sim SCRIPT
+//!
+//! otpilot's own GPIO, alarm and SPI modules are compiled unchanged on top of
+//! `syscall_shim`, which stands in for libtock. `board` wires the fakes the
+//! way kernel/papa wires the drivers, `app` is otpilot's startup and main
+//! loop reduced to the reset sequencing, and `script` drives the BMC side of
+//! the board and checks the results. See `script` for the commands.
+//!
+//! Manticore, the mailbox, firmware updates and console commands are left
+//! out, as the fakes do not model SPI transactions for userspace.
+//!
+//! This is a userspace simulator: the papa kernel itself is not run, since
+//! Tock and the cortexm3 crate only build for Cortex-M. The fakes stand in
+//! for its drivers at the syscall boundary.
+//!
+//! Exits with 0 once the script has run, 1 if an expectation failed and 2 if
+//! the script could not be read.
+
+// otpilot's modules use `libtock::...` paths.
+extern crate syscall_shim as libtock;
+
+mod app;
+mod board;
+mod script;
+
+// otpilot's modules, built as otpilot builds them rather than held to the
+// tools' lints.
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/alarm.rs"]
+mod alarm;
+
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/gpio.rs"]
+mod gpio;
+
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/gpio_control.rs"]
+mod gpio_control;
+
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/gpio_processor.rs"]
+mod gpio_processor;
+
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/passthrough_guard.rs"]
+mod passthrough_guard;
+
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/sfdp.rs"]
+mod sfdp;
+
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/spi_device.rs"]
+mod spi_device;
+
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/spi_host.rs"]
+mod spi_host;
+
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/spi_host_h1.rs"]
+mod spi_host_h1;
+
+#[allow(dead_code, static_mut_refs, unknown_lints, clippy::all)]
+#[path = "../../../userspace/otpilot/src/spi_host_helper.rs"]
+mod spi_host_helper;
+
+use std::process::exit;
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: papa_sim SCRIPT");
+            exit(2);
+        },
+    };
+    let steps = match std::fs::read_to_string(&path) {
+        Ok(text) => script::parse(&text),
+        Err(err) => Err(err.to_string()),
+    };
+    let steps = match steps {
+        Ok(steps) => steps,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            exit(2);
+        },
+    };
+
+    let board = board::Board::install();
+    script::Script::install(path, steps, board);
+
+    // The script ends the process once it has run.
+    if let Err(err) = app::run() {
+        eprintln!("papa_sim: otpilot failed: {:?}", err);
+        exit(1);
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Scripts that play the BMC side of the board and check what otpilot does.
+//!
+//! A script has one command per line. `#` starts a comment. Each command runs
+//! once otpilot has handled everything the previous ones caused and is
+//! waiting for an event, so expectations see the settled state.
+//!
+//!     wait MSECS                  let time pass, e.g. for the reset guard
+//!     drive PIN high|low          drive a reset monitor pin
+//!     bmc HEX.. [read N]          send a SPI transaction from the BMC,
+//!                                 clocking N more bytes in after HEX
+//!     expect PIN high|low         the level of a pin
+//!     expect driven PIN high|low..
+//!                                 the levels otpilot wrote to PIN since the
+//!                                 last `expect driven` for it
+//!     expect passthrough on|off
+//!     expect device-address-mode 3|4
+//!     expect flash-address-mode 3|4
+//!     expect trips N              how often the passthrough guard tripped
+//!     expect log TEXT             TEXT follows the last matched log text
+//!     expect bmc-read HEX..       the bytes read by the last `bmc ... read`
+//!
+//! PIN is a `gpioutils::pin::GpioPin` name, e.g. BMC_RSTMON_N.
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::process::exit;
+use std::rc::Rc;
+use std::time::Duration;
+use std::time::Instant;
+
+use gpioutils::pin::GpioPin;
+
+use syscall_shim::fake::FakeDriver;
+use syscall_shim::result::ENOSUPPORT;
+
+use crate::board::Board;
+
+// A driver number no app uses. The script is installed as a driver only so
+// that `yieldk` runs it when it is due.
+const DRIVER_NUMBER: usize = 0xfffff;
+
+/// A script command.
+pub enum Step {
+    Wait(Duration),
+    Drive(GpioPin, bool),
+    Bmc(Vec<u8>, usize),
+    Expect(Expectation),
+}
+
+/// What an `expect` command checks.
+pub enum Expectation {
+    Level(GpioPin, bool),
+    Driven(GpioPin, Vec<bool>),
+    Passthrough(bool),
+    DeviceFourByte(bool),
+    FlashFourByte(bool),
+    Trips(u32),
+    Log(String),
+    BmcRead(Vec<u8>),
+}
+
+/// Parses a script into its steps and their line numbers.
+pub fn parse(text: &str) -> Result<Vec<(usize, Step)>, String> {
+    let mut steps = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let step = parse_step(line).map_err(|err| format!("line {}: {}", index + 1, err))?;
+        steps.push((index + 1, step));
+    }
+    Ok(steps)
+}
+
+fn parse_step(line: &str) -> Result<Step, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["wait", msecs] => Ok(Step::Wait(Duration::from_millis(parse_number(msecs)?))),
+        ["drive", pin, level] => {
+            let pin = parse_pin(pin)?;
+            if pin.is_output() {
+                return Err(format!("{:?} is driven by otpilot", pin));
+            }
+            Ok(Step::Drive(pin, parse_level(level)?))
+        },
+        ["bmc", rest @ ..] => match rest {
+            [hex @ .., "read", len] => Ok(Step::Bmc(parse_hex(hex)?, parse_number(len)? as usize)),
+            hex => Ok(Step::Bmc(parse_hex(hex)?, 0)),
+        },
+        ["expect", "driven", pin, levels @ ..] => {
+            let levels = levels.iter().map(|level| parse_level(level))
+                .collect::<Result<_, _>>()?;
+            Ok(Step::Expect(Expectation::Driven(parse_pin(pin)?, levels)))
+        },
+        ["expect", "passthrough", state] => Ok(Step::Expect(Expectation::Passthrough(match *state {
+            "on" => true,
+            "off" => false,
+            _ => return Err(format!("expected on or off, got {}", state)),
+        }))),
+        ["expect", "device-address-mode", mode] =>
+            Ok(Step::Expect(Expectation::DeviceFourByte(parse_address_mode(mode)?))),
+        ["expect", "flash-address-mode", mode] =>
+            Ok(Step::Expect(Expectation::FlashFourByte(parse_address_mode(mode)?))),
+        ["expect", "trips", trips] => Ok(Step::Expect(Expectation::Trips(parse_number(trips)? as u32))),
+        ["expect", "log", _, ..] => {
+            let text = line["expect".len()..].trim_start()["log".len()..].trim_start();
+            Ok(Step::Expect(Expectation::Log(text.to_string())))
+        },
+        ["expect", "bmc-read", hex @ ..] => Ok(Step::Expect(Expectation::BmcRead(parse_hex(hex)?))),
+        ["expect", pin, level] => Ok(Step::Expect(Expectation::Level(parse_pin(pin)?, parse_level(level)?))),
+        _ => Err(format!("unknown command: {}", line)),
+    }
+}
+
+fn parse_number(word: &str) -> Result<u64, String> {
+    word.parse().map_err(|_| format!("expected a number, got {}", word))
+}
+
+fn parse_pin(name: &str) -> Result<GpioPin, String> {
+    match name {
+        "BMC_SRST_N" => Ok(GpioPin::BMC_SRST_N),
+        "BMC_CPU_RST_N" => Ok(GpioPin::BMC_CPU_RST_N),
+        "SYS_RSTMON_N" => Ok(GpioPin::SYS_RSTMON_N),
+        "BMC_RSTMON_N" => Ok(GpioPin::BMC_RSTMON_N),
+        _ => Err(format!("unknown pin: {}", name)),
+    }
+}
+
+fn parse_level(word: &str) -> Result<bool, String> {
+    match word {
+        "high" => Ok(true),
+        "low" => Ok(false),
+        _ => Err(format!("expected high or low, got {}", word)),
+    }
+}
+
+fn parse_address_mode(word: &str) -> Result<bool, String> {
+    match word {
+        "3" => Ok(false),
+        "4" => Ok(true),
+        _ => Err(format!("expected an address mode of 3 or 4, got {}", word)),
+    }
+}
+
+// Parses hex words, each of one or more bytes, e.g. "03 000010".
+fn parse_hex(words: &[&str]) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for word in words {
+        if word.is_empty() || word.len() % 2 != 0 {
+            return Err(format!("expected hex bytes, got {}", word));
+        }
+        for index in (0..word.len()).step_by(2) {
+            let byte = word.get(index..index + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("expected hex bytes, got {}", word))?;
+            bytes.push(byte);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Runs the steps of a script against the board while otpilot waits.
+pub struct Script {
+    path: String,
+    steps: Vec<(usize, Step)>,
+    board: Board,
+    next: Cell<usize>,
+    due: Cell<Instant>,
+    // Where in the console output the next `expect log` starts looking.
+    log_position: Cell<usize>,
+    bmc_read: RefCell<Vec<u8>>,
+}
+
+impl Script {
+    /// Installs the script so that its first step runs once otpilot waits.
+    pub fn install(path: String, steps: Vec<(usize, Step)>, board: Board) {
+        let script = Rc::new(Script {
+            path,
+            steps,
+            board,
+            next: Cell::new(0),
+            due: Cell::new(Instant::now()),
+            log_position: Cell::new(0),
+            bmc_read: RefCell::new(Vec::new()),
+        });
+        syscall_shim::install(DRIVER_NUMBER, script);
+    }
+
+    fn run(&self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::Wait(duration) => self.due.set(Instant::now() + *duration),
+            Step::Drive(pin, high) => self.board.drive(*pin, *high),
+            Step::Bmc(tx, read_len) => {
+                let mut tx = tx.clone();
+                let write_len = tx.len();
+                tx.resize(write_len + read_len, 0xff);
+                let rx = self.board.spi_device.transfer(&tx);
+                *self.bmc_read.borrow_mut() = rx[write_len..].to_vec();
+            },
+            Step::Expect(expectation) => self.check(expectation)?,
+        }
+        Ok(())
+    }
+
+    fn check(&self, expectation: &Expectation) -> Result<(), String> {
+        let board = &self.board;
+        match expectation {
+            Expectation::Level(pin, high) =>
+                compare(&format!("{:?}", pin), level_name(*high),
+                        level_name(board.gpio.level(*pin as usize))),
+            Expectation::Driven(pin, levels) => {
+                let driven = board.gpio.take_writes(*pin as usize);
+                compare(&format!("levels driven on {:?}", pin), levels_name(levels),
+                        levels_name(&driven))
+            },
+            Expectation::Passthrough(on) =>
+                compare("passthrough", on, &board.spi_host_h1.passthrough()),
+            Expectation::DeviceFourByte(four_byte) =>
+                compare("device four-byte address mode", four_byte,
+                        &board.spi_device.is_four_byte()),
+            Expectation::FlashFourByte(four_byte) =>
+                compare("flash four-byte address mode", four_byte, &board.flash.is_four_byte()),
+            Expectation::Trips(trips) => compare("trips", trips, &board.guard.trips()),
+            Expectation::Log(text) => {
+                let output = board.console.output();
+                let output = String::from_utf8_lossy(&output[self.log_position.get()..]);
+                match output.find(text.as_str()) {
+                    Some(index) => {
+                        self.log_position.set(self.log_position.get() + index + text.len());
+                        Ok(())
+                    },
+                    None => Err(format!("expected log {:?}, the log since the last match is {:?}",
+                                        text, output)),
+                }
+            },
+            Expectation::BmcRead(bytes) =>
+                compare("bytes read by the BMC", bytes, &*self.bmc_read.borrow()),
+        }
+    }
+}
+
+fn compare<T: PartialEq + std::fmt::Debug>(what: &str, expected: T, actual: T)
+    -> Result<(), String> {
+    if expected == actual {
+        return Ok(());
+    }
+    Err(format!("expected {} to be {:?}, got {:?}", what, expected, actual))
+}
+
+fn level_name(high: bool) -> &'static str {
+    if high { "high" } else { "low" }
+}
+
+fn levels_name(levels: &[bool]) -> Vec<&'static str> {
+    levels.iter().map(|&high| level_name(high)).collect()
+}
+
+impl FakeDriver for Script {
+    fn command(&self, _command_num: usize, _arg1: usize, _arg2: usize) -> Result<usize, isize> {
+        Err(ENOSUPPORT)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        Some(self.due.get())
+    }
+
+    fn expire(&self) {
+        let (line, step) = match self.steps.get(self.next.get()) {
+            Some(step) => step,
+            None => {
+                println!("papa_sim: {} passed", self.path);
+                exit(0);
+            },
+        };
+        self.next.set(self.next.get() + 1);
+        self.due.set(Instant::now());
+        if let Err(err) = self.run(step) {
+            eprintln!("{}:{}: {}", self.path, line, err);
+            exit(1);
+        }
+    }
+}
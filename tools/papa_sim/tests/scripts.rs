@@ -0,0 +1,71 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs papa_sim on the scripts in scripts/.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+
+fn run(script: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_papa_sim")).arg(script).output()
+        .expect("could not run papa_sim")
+}
+
+fn check(name: &str) {
+    let script: PathBuf = [env!("CARGO_MANIFEST_DIR"), "scripts", name].iter().collect();
+    let output = run(&script);
+    assert!(output.status.success(), "{} failed:\n{}{}", name,
+            String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}
+
+fn run_text(name: &str, text: &str) -> Output {
+    let script = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&script, text).unwrap();
+    run(&script)
+}
+
+#[test]
+fn startup() {
+    check("startup.script");
+}
+
+#[test]
+fn bmc_reset() {
+    check("bmc_reset.script");
+}
+
+#[test]
+fn passthrough_guard() {
+    check("passthrough_guard.script");
+}
+
+#[test]
+fn failed_expectation_names_its_line() {
+    let output = run_text("failed_expectation.script", "expect trips 0\nexpect passthrough off\n");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed_expectation.script:2: expected passthrough to be false, got true"),
+            "{}", stderr);
+}
+
+#[test]
+fn bad_script_is_rejected() {
+    let output = run_text("bad.script", "drive BMC_CPU_RST_N low\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line 1: BMC_CPU_RST_N is driven by otpilot"));
+}
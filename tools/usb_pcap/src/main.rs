@@ -0,0 +1,202 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! usb_pcap converts the `usbcap:` lines printed by the H1 USB packet capture
+//! (see kernel/h1/src/usb/capture.rs) into a pcap file using the Linux usbmon
+//! link-layer type, so that it can be opened in Wireshark.
+
+use std::fs::OpenOptions;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+
+/// LINKTYPE_USB_LINUX: 48-byte usbmon header followed by the payload.
+const LINKTYPE_USB_LINUX: u32 = 189;
+
+const USBMON_HEADER_LEN: u32 = 48;
+
+// Values of the usbmon `xfer_type` field.
+const XFER_TYPE_INTERRUPT: u8 = 1;
+const XFER_TYPE_CONTROL: u8 = 2;
+
+// Packet kinds, matching h1::usb::capture::CaptureKind.
+const KIND_SETUP: u8 = 0;
+const KIND_DATA_IN: u8 = 1;
+const KIND_DATA_OUT: u8 = 2;
+
+/// A packet parsed from a `usbcap:` line.
+struct Packet {
+    timestamp_us: u32,
+    endpoint: u8,
+    kind: u8,
+    length: u32,
+    data: Vec<u8>,
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parses one console line. Returns None for lines that are not capture
+/// records, including the begin/end markers.
+fn parse_line(line: &str) -> Option<Packet> {
+    let record = &line[line.find("usbcap: ")? + "usbcap: ".len()..];
+    if record.starts_with("begin") {
+        if let Some(dropped) = record.split("dropped=").nth(1) {
+            if dropped.trim() != "0" {
+                eprintln!("warning: {} packets were dropped by the device", dropped.trim());
+            }
+        }
+        return None;
+    }
+
+    let mut fields = record.split_whitespace();
+    let timestamp_us = fields.next()?.parse().ok()?;
+    let endpoint = fields.next()?.parse().ok()?;
+    let kind = fields.next()?.parse().ok()?;
+    let length = fields.next()?.parse().ok()?;
+    let data = parse_hex(fields.next().unwrap_or(""))?;
+    Some(Packet { timestamp_us, endpoint, kind, length, data })
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) { out.extend_from_slice(&value.to_le_bytes()); }
+fn write_u32(out: &mut Vec<u8>, value: u32) { out.extend_from_slice(&value.to_le_bytes()); }
+fn write_u64(out: &mut Vec<u8>, value: u64) { out.extend_from_slice(&value.to_le_bytes()); }
+
+fn pcap_file_header() -> Vec<u8> {
+    let mut out = Vec::new();
+    write_u32(&mut out, 0xa1b2c3d4);  // magic
+    write_u16(&mut out, 2);           // version_major
+    write_u16(&mut out, 4);           // version_minor
+    write_u32(&mut out, 0);           // thiszone
+    write_u32(&mut out, 0);           // sigfigs
+    write_u32(&mut out, 65535);       // snaplen
+    write_u32(&mut out, LINKTYPE_USB_LINUX);
+    out
+}
+
+/// Builds a pcap record for `packet`. `time_us` is the capture timestamp
+/// with counter wraps already accounted for.
+fn pcap_record(id: u64, time_us: u64, packet: &Packet) -> Vec<u8> {
+    let is_setup = packet.kind == KIND_SETUP;
+    let xfer_type = if packet.endpoint == 0 { XFER_TYPE_CONTROL } else { XFER_TYPE_INTERRUPT };
+    let direction_in = match packet.kind {
+        KIND_SETUP => packet.data.first().map_or(false, |request_type| request_type & 0x80 != 0),
+        KIND_DATA_IN => true,
+        _ => false,
+    };
+    // Data flowing from the host is a submission, data from the device
+    // is a completion.
+    let event_type = if is_setup || packet.kind == KIND_DATA_OUT { b'S' } else { b'C' };
+
+    let (setup, payload): (&[u8], &[u8]) = if is_setup {
+        (&packet.data, &[])
+    } else {
+        (&[], &packet.data)
+    };
+    let length = if is_setup { 0 } else { packet.length };
+    let ts_sec = time_us / 1_000_000;
+    let ts_usec = (time_us % 1_000_000) as u32;
+
+    let mut out = Vec::new();
+    // pcap record header.
+    write_u32(&mut out, ts_sec as u32);
+    write_u32(&mut out, ts_usec);
+    write_u32(&mut out, USBMON_HEADER_LEN + payload.len() as u32);
+    write_u32(&mut out, USBMON_HEADER_LEN + length);
+
+    // usbmon header.
+    write_u64(&mut out, id);
+    out.push(event_type);
+    out.push(xfer_type);
+    out.push(packet.endpoint | if direction_in { 0x80 } else { 0 });
+    out.push(1);                                   // devnum
+    write_u16(&mut out, 1);                        // busnum
+    out.push(if is_setup { 0 } else { b'-' });     // flag_setup
+    out.push(if payload.is_empty() { b'<' } else { 0 });  // flag_data
+    write_u64(&mut out, ts_sec);
+    write_u32(&mut out, ts_usec);
+    write_u32(&mut out, 0);                        // status
+    write_u32(&mut out, length);
+    write_u32(&mut out, payload.len() as u32);     // len_cap
+    let mut setup_bytes = [0u8; 8];
+    for (i, byte) in setup.iter().take(8).enumerate() {
+        setup_bytes[i] = *byte;
+    }
+    out.extend_from_slice(&setup_bytes);
+
+    out.extend_from_slice(payload);
+    out
+}
+
+fn main() {
+    let cmdline_matches = clap::App::new("usb_pcap")
+        .about("Converts an H1 USB packet capture dump into a pcap file")
+        .arg(clap::Arg::with_name("input")
+            .help("Console log containing `usbcap:` lines")
+            .required(true))
+        .arg(clap::Arg::with_name("output")
+            .help("pcap file to write")
+            .required(true))
+        .get_matches();
+
+    let input_file = cmdline_matches.value_of("input")
+        .expect("`input` not specified");
+    let output_file = cmdline_matches.value_of("output")
+        .expect("`output` not specified");
+
+    let input = OpenOptions::new()
+        .read(true)
+        .open(&input_file)
+        .expect("failed to open input file");
+    let mut output = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(&output_file)
+        .expect("failed to open output file");
+
+    output.write_all(&pcap_file_header()).expect("failed to write pcap header");
+
+    // The device timestamps are a free-running 32-bit microsecond counter,
+    // so track wraps to keep the pcap timestamps monotonic.
+    let mut last_timestamp_us: Option<u32> = None;
+    let mut wrap_offset_us: u64 = 0;
+    let mut count: u64 = 0;
+    for line in BufReader::new(input).lines() {
+        let line = line.expect("failed to read input file");
+        let packet = match parse_line(&line) {
+            Some(packet) => packet,
+            None => continue,
+        };
+        if let Some(last) = last_timestamp_us {
+            if packet.timestamp_us < last {
+                wrap_offset_us += 1 << 32;
+            }
+        }
+        last_timestamp_us = Some(packet.timestamp_us);
+
+        output.write_all(&pcap_record(count, wrap_offset_us + packet.timestamp_us as u64, &packet))
+            .expect("failed to write pcap record");
+        count += 1;
+    }
+    println!("Wrote {} packets to {}", count, output_file);
+}
@@ -0,0 +1,224 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Lays out several TBF apps in the kernel's app flash region.
+//!
+//! The kernel walks the apps from `_sapps`, using each TBF header's total
+//! size to find the next one, and stops at the first invalid header. Apps are
+//! placed in order, each starting on an `APP_ALIGN` boundary, and the gaps
+//! are filled with padding apps: bare TBF headers with the enable flag clear,
+//! which the kernel skips.
+
+use crate::steps::Result;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Start alignment of each app. Matches `MPU_MIN_ALIGN` in the layout
+/// scripts.
+pub const APP_ALIGN: u32 = 8 * 1024;
+
+/// The last pages of flash, which hold kernel data (see `H1_RESERVED_PAGES`
+/// in kernel/h1/src/hil/flash/h1_hw.rs). No app region may reach into them.
+const KERNEL_DATA: std::ops::Range<u32> = 0xbc000..0xc0000;
+
+/// The TBF header version written by elf2tab and understood by the kernel.
+const TBF_VERSION: u16 = 2;
+
+/// The length of the TBF base header, which is all a padding app has.
+const TBF_BASE_HEADER_LEN: usize = 16;
+
+/// An app to place in the image.
+pub struct AppTbf {
+    pub name: String,
+
+    /// Whether the app is linked for the first app slot (libtock-rs apps
+    /// are) rather than being position independent (libtock-c apps).
+    pub fixed_address: bool,
+
+    pub tbf: Vec<u8>,
+}
+
+/// The app flash region of a kernel, from its `_sapps` and `_eapps` symbols.
+#[derive(Clone, Copy)]
+pub struct AppRegion {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// One entry of the assembled app region.
+pub struct Placement {
+    pub address: u32,
+    pub size: u32,
+
+    /// The app's name, or None for padding.
+    pub name: Option<String>,
+}
+
+/// The assembled contents of the .apps section.
+pub struct Layout {
+    pub region: AppRegion,
+    pub placements: Vec<Placement>,
+    pub data: Vec<u8>,
+}
+
+impl AppRegion {
+    /// Reads `_sapps` and `_eapps` from a kernel ELF.
+    pub fn from_kernel(kernel: &Path) -> Result<AppRegion> {
+        let elf = elf::File::open_path(kernel)
+            .map_err(|err| format!("cannot parse {}: {:?}", kernel.display(), err))?;
+        let symtab = elf.get_section(".symtab")
+            .ok_or_else(|| format!("{} has no symbol table", kernel.display()))?;
+        let symbols = elf.get_symbols(symtab)
+            .map_err(|err| format!("cannot read symbols of {}: {:?}", kernel.display(), err))?;
+        let find = |name: &str| symbols.iter()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| symbol.value as u32)
+            .ok_or_else(|| format!("{} does not define {}", kernel.display(), name));
+        let region = AppRegion { start: find("_sapps")?, end: find("_eapps")? };
+        if region.end < region.start {
+            return Err(format!("{} has _eapps before _sapps", kernel.display()));
+        }
+        if region.start < KERNEL_DATA.end && region.end > KERNEL_DATA.start {
+            return Err(format!(
+                "{}: app region {:#010x}..{:#010x} overlaps the kernel data pages at \
+                 {:#010x}..{:#010x}", kernel.display(), region.start, region.end,
+                KERNEL_DATA.start, KERNEL_DATA.end));
+        }
+        Ok(region)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.end - self.start
+    }
+}
+
+/// Returns the total size of a TBF from its header, checking that the header
+/// is one the kernel will accept and that the size matches the file.
+fn validate_tbf(app: &AppTbf) -> Result<u32> {
+    let tbf = &app.tbf;
+    let word = |offset: usize| u32::from_le_bytes([tbf[offset], tbf[offset + 1],
+                                                   tbf[offset + 2], tbf[offset + 3]]);
+    if tbf.len() < TBF_BASE_HEADER_LEN {
+        return Err(format!("{}: TBF is only {} bytes", app.name, tbf.len()));
+    }
+    let version = u16::from_le_bytes([tbf[0], tbf[1]]);
+    let header_len = u16::from_le_bytes([tbf[2], tbf[3]]) as usize;
+    let total_size = word(4);
+    if version != TBF_VERSION {
+        return Err(format!("{}: TBF version {} is not {}", app.name, version, TBF_VERSION));
+    }
+    if header_len < TBF_BASE_HEADER_LEN || header_len > tbf.len() || header_len & 3 != 0 {
+        return Err(format!("{}: bad TBF header length {}", app.name, header_len));
+    }
+    let checksum = (0..header_len).step_by(4)
+        .filter(|offset| *offset != 12)
+        .fold(0, |checksum, offset| checksum ^ word(offset));
+    if checksum != word(12) {
+        return Err(format!("{}: TBF header checksum mismatch", app.name));
+    }
+    if total_size as usize != tbf.len() {
+        return Err(format!("{}: TBF header says {} bytes but the file has {}",
+                           app.name, total_size, tbf.len()));
+    }
+    if total_size & 3 != 0 {
+        return Err(format!("{}: TBF size {} is not a multiple of 4", app.name, total_size));
+    }
+    Ok(total_size)
+}
+
+/// Returns a padding app of `len` bytes.
+fn padding(len: u32) -> Vec<u8> {
+    let first_word = TBF_VERSION as u32 | (TBF_BASE_HEADER_LEN as u32) << 16;
+    let flags = 0u32;
+    let mut data = Vec::with_capacity(len as usize);
+    data.extend(&first_word.to_le_bytes());
+    data.extend(&len.to_le_bytes());
+    data.extend(&flags.to_le_bytes());
+    data.extend(&(first_word ^ len ^ flags).to_le_bytes());
+    data.resize(len as usize, 0xff);
+    data
+}
+
+// `align` must be a power of two.
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
+
+/// Places `apps` in `region`, in order.
+pub fn layout(region: AppRegion, apps: &[AppTbf]) -> Result<Layout> {
+    if apps.is_empty() {
+        return Err("no apps to place".to_string());
+    }
+    if region.start & 3 != 0 {
+        return Err(format!("_sapps {:#010x} is not word aligned", region.start));
+    }
+    if let Some(app) = apps.iter().skip(1).find(|app| app.fixed_address) {
+        return Err(format!("{} is linked for the first app slot and must be the first app",
+                           app.name));
+    }
+
+    let mut placements = Vec::new();
+    let mut data = Vec::new();
+    // Kept in u64 so that a region ending at the top of the address space
+    // cannot overflow.
+    let mut address = region.start as u64;
+    for (index, app) in apps.iter().enumerate() {
+        let size = validate_tbf(app)?;
+        // The first app goes at _sapps, where the kernel starts looking.
+        let start = if index == 0 { address } else { align_up(address, APP_ALIGN as u64) };
+        let end = start + size as u64;
+        if end > region.end as u64 {
+            return Err(format!(
+                "apps do not fit in {:#010x}..{:#010x} ({} bytes): {} would end at {:#010x}, \
+                 {} bytes over", region.start, region.end, region.size(), app.name, end,
+                end - region.end as u64));
+        }
+        if start > address {
+            let gap = (start - address) as u32;
+            if (gap as usize) < TBF_BASE_HEADER_LEN {
+                return Err(format!("{}: {} byte gap before it is too small for padding",
+                                   app.name, gap));
+            }
+            placements.push(Placement { address: address as u32, size: gap, name: None });
+            data.extend(padding(gap));
+        }
+        placements.push(Placement {
+            address: start as u32,
+            size,
+            name: Some(app.name.clone()),
+        });
+        data.extend(&app.tbf);
+        address = end;
+    }
+    Ok(Layout { region, placements, data })
+}
+
+impl Layout {
+    /// Describes the layout, one line per app or padding.
+    pub fn map(&self) -> String {
+        let mut map = String::new();
+        let _ = writeln!(map, "# app region {:#010x}..{:#010x} ({} bytes)",
+                         self.region.start, self.region.end, self.region.size());
+        let _ = writeln!(map, "# address    size        app");
+        for placement in &self.placements {
+            let _ = writeln!(map, "{:#010x}   {:#010x}  {}", placement.address, placement.size,
+                             placement.name.as_ref().map_or("(padding)", |name| name.as_str()));
+        }
+        let used = self.data.len() as u32;
+        let _ = writeln!(map, "# used {} bytes, {} bytes free", used, self.region.size() - used);
+        map
+    }
+}
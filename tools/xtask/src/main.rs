@@ -0,0 +1,169 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! xtask builds flashable images without going through the Makefiles.
+//!
+//! `cargo xtask build papa` builds the papa kernel, every app in the papa
+//! column of the matrix, and one composite image per app (and per A/B image
+//! for apps that have them). The outputs land in the same places under
+//! build/ as with `make`, each with a .map file describing the app layout.
+//! With --combine, the apps share one image per kernel image instead.
+//! `cargo xtask list` prints the matrix.
+
+mod image;
+mod matrix;
+mod steps;
+
+use matrix::Image;
+use std::path::PathBuf;
+use steps::Builder;
+
+/// Finds the repository root by walking up from the current directory.
+fn find_root() -> Option<PathBuf> {
+    let mut dir = std::env::current_dir().ok()?;
+    loop {
+        if dir.join("DirShim.mk").is_file() && dir.join("kernel").is_dir() {
+            return Some(dir);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+fn list() {
+    for board in matrix::BOARDS {
+        println!("{}:", board);
+        for app in matrix::APPS.iter().filter(|app| app.supports(board)) {
+            let images: Vec<&str> = app.images.iter()
+                .filter(|image| **image != Image::Single)
+                .map(|image| image.suffix())
+                .collect();
+            println!("  {:<20} {:?} {}", app.name, app.kind, images.join(" "));
+        }
+    }
+}
+
+fn build(builder: &Builder, board: &str, apps: &[&'static matrix::App], combine: bool,
+         signed: bool) -> steps::Result<Vec<PathBuf>> {
+    builder.setup()?;
+    builder.gitlongtag()?;
+    let elf2tab = builder.elf2tab()?;
+
+    let mut outputs = Vec::new();
+    for image in &[Image::Single, Image::A, Image::B] {
+        let image_apps: Vec<_> = apps.iter().filter(|app| app.images.contains(image)).collect();
+        if image_apps.is_empty() {
+            continue;
+        }
+        let kernel = builder.kernel(*image)?.join(board);
+        let mut tbfs = Vec::new();
+        for app in &image_apps {
+            tbfs.push(builder.app(app, board, *image, &elf2tab)?);
+        }
+
+        // Either one image holding all the apps, or one image per app.
+        let groups: Vec<(PathBuf, &[_])> = if combine {
+            vec![(builder.path(format!("build/xtask/{}", board)), &tbfs[..])]
+        } else {
+            image_apps.iter().zip(tbfs.chunks(1))
+                .map(|(app, tbf)| (builder.path(format!("build/userspace/{}/{}", app.name, board)),
+                                   tbf))
+                .collect()
+        };
+        for (dir, group) in groups {
+            steps::create_dir(&dir)?;
+            let unsigned = builder.unsigned_image(&kernel, group, &dir, *image)?;
+            outputs.push(if signed {
+                builder.full_image(&unsigned, &dir, *image)?
+            } else {
+                unsigned
+            });
+        }
+    }
+    Ok(outputs)
+}
+
+fn main() {
+    let cmdline_matches = clap::App::new("xtask")
+        .about("Builds tock-on-titan images")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .arg(clap::Arg::with_name("root")
+            .help("Path to the tock-on-titan checkout (default: search upwards)")
+            .long("root")
+            .takes_value(true)
+            .global(true))
+        .subcommand(clap::SubCommand::with_name("build")
+            .about("Builds the kernel and apps for a board and combines them into images")
+            .arg(clap::Arg::with_name("board")
+                .help("The board to build for")
+                .required(true)
+                .possible_values(matrix::BOARDS))
+            .arg(clap::Arg::with_name("app")
+                .help("Only build this app (may be repeated; default: all apps for the board)")
+                .long("app")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1))
+            .arg(clap::Arg::with_name("combine")
+                .help("Put all the apps into one image under build/xtask/<board>/ instead of \
+                       one image per app")
+                .long("combine"))
+            .arg(clap::Arg::with_name("signed")
+                .help("Sign the images and prepend the bootloader, like `make build-signed`")
+                .long("signed"))
+            .arg(clap::Arg::with_name("no-sandbox")
+                .help("Run the build steps outside the bubblewrap sandbox")
+                .long("no-sandbox")))
+        .subcommand(clap::SubCommand::with_name("list")
+            .about("Lists the boards and the apps built for each"))
+        .get_matches();
+
+    let root = match cmdline_matches.value_of("root") {
+        Some(root) => PathBuf::from(root),
+        None => find_root().expect("Run xtask inside the tock-on-titan checkout or pass --root"),
+    };
+
+    match cmdline_matches.subcommand() {
+        ("build", Some(build_matches)) => {
+            let board = build_matches.value_of("board").unwrap();
+            let apps: Vec<_> = match build_matches.values_of("app") {
+                Some(names) => names.map(|name| match matrix::find_app(name) {
+                    Some(app) if app.supports(board) => app,
+                    Some(_) => exit_with(&format!("{} is not built for {}", name, board)),
+                    None => exit_with(&format!("unknown app {}; see `cargo xtask list`", name)),
+                }).collect(),
+                None => matrix::APPS.iter().filter(|app| app.supports(board)).collect(),
+            };
+            let builder = Builder::new(root, !build_matches.is_present("no-sandbox"));
+            match build(&builder, board, &apps, build_matches.is_present("combine"),
+                        build_matches.is_present("signed")) {
+                Ok(outputs) => {
+                    for output in outputs {
+                        println!("{}", output.display());
+                    }
+                }
+                Err(err) => exit_with(&err),
+            }
+        }
+        _ => list(),
+    }
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1);
+}
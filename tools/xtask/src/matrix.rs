@@ -0,0 +1,120 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The board and app matrix, mirroring the Build.mk files under userspace/.
+//!
+//! When adding an app, add its Build.mk line and an entry in `APPS`.
+
+/// All boards that apps are built for (`BOARDS` in userspace/Build.mk).
+pub const BOARDS: &[&str] = &["golf2", "papa"];
+
+/// How an app is built.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    /// A libtock-c app built by its TockMakefile (`C_APPS`).
+    C,
+
+    /// A libtock-rs app built by `cargo build` (`RUST_APPS`).
+    Rust,
+
+    /// A libtock-rs test built by `cargo test --no-run` (`RUST_TESTS`).
+    RustTest,
+}
+
+/// The kernel image an app is linked for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Image {
+    /// The image linked by layout.ld.
+    Single,
+
+    /// The A image of an A/B pair, linked by layout_a.ld.
+    A,
+
+    /// The B image of an A/B pair, linked by layout_b.ld.
+    B,
+}
+
+impl Image {
+    /// The suffix used in file names and layout scripts (`IMAGE` in the
+    /// Build.mk files).
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Image::Single => "",
+            Image::A => "_a",
+            Image::B => "_b",
+        }
+    }
+}
+
+pub struct App {
+    pub name: &'static str,
+    pub kind: Kind,
+
+    /// The boards the app is built for; empty means all of `BOARDS`.
+    pub boards: &'static [&'static str],
+
+    pub images: &'static [Image],
+}
+
+impl App {
+    pub fn supports(&self, board: &str) -> bool {
+        self.boards.is_empty() || self.boards.contains(&board)
+    }
+}
+
+const SINGLE: &[Image] = &[Image::Single];
+const ALL_BOARDS: &[&str] = &[];
+const PAPA: &[&str] = &["papa"];
+
+macro_rules! apps {
+    ($($name:ident: $kind:ident, $boards:ident, $images:expr;)*) => {
+        pub const APPS: &[App] = &[$(
+            App {
+                name: stringify!($name),
+                kind: Kind::$kind,
+                boards: $boards,
+                images: $images,
+            },
+        )*];
+    };
+}
+
+apps! {
+    aes_test:          C,        ALL_BOARDS, SINGLE;
+    blink:             C,        ALL_BOARDS, SINGLE;
+    crypto_capture:    C,        ALL_BOARDS, SINGLE;
+    dcrypto_test:      C,        ALL_BOARDS, SINGLE;
+    flash_test:        RustTest, ALL_BOARDS, SINGLE;
+    gpio_test:         C,        ALL_BOARDS, SINGLE;
+    irq_latency_test:  RustTest, PAPA,       SINGLE;
+    low_level_debug:   Rust,     ALL_BOARDS, SINGLE;
+    nvcounter_chaos:   C,        ALL_BOARDS, SINGLE;
+    nvcounter_ctest:   C,        ALL_BOARDS, SINGLE;
+    nvcounter_test:    RustTest, ALL_BOARDS, SINGLE;
+    otpilot:           Rust,     PAPA,       &[Image::A, Image::B];
+    papa_e2e_test:     RustTest, PAPA,       SINGLE;
+    personality_clear: C,        ALL_BOARDS, SINGLE;
+    personality_test:  C,        ALL_BOARDS, SINGLE;
+    rng:               C,        ALL_BOARDS, SINGLE;
+    sha_test:          C,        ALL_BOARDS, SINGLE;
+    spin:              C,        ALL_BOARDS, SINGLE;
+    u2f_app:           C,        ALL_BOARDS, SINGLE;
+    u2f_test:          C,        ALL_BOARDS, SINGLE;
+}
+
+pub fn find_app(name: &str) -> Option<&'static App> {
+    APPS.iter().find(|app| app.name == name)
+}
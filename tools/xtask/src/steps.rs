@@ -0,0 +1,342 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Build steps, each matching a rule in the Build.mk files.
+//!
+//! Outputs go to the same paths under build/ as the Makefile rules, so that
+//! `make program` and `make run` pick up images built here and vice versa.
+
+use crate::image;
+use crate::image::AppRegion;
+use crate::image::AppTbf;
+use crate::matrix::App;
+use crate::matrix::Image;
+use crate::matrix::Kind;
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+pub type Result<T> = std::result::Result<T, String>;
+
+const TARGET: &str = "thumbv7m-none-eabi";
+
+/// Lock files bound writable into the sandbox (see `BWRAP` in the
+/// top-level Makefile).
+const LOCK_FILES: &[&str] = &[
+    "kernel/Cargo.lock",
+    "runner/Cargo.lock",
+    "third_party/libtock-rs/Cargo.lock",
+    "third_party/rustc-demangle/Cargo.lock",
+    "tools/Cargo.lock",
+    "userspace/Cargo.lock",
+];
+
+/// Apps whose TBF exceeds this are rejected, as in userspace/Build.mk.
+const MAX_TBF_LEN: u64 = 64 * 1024;
+
+/// elf2tab arguments shared by all Rust apps.
+const ELF2TAB_ARGS: &[&str] = &[
+    "--stack=2048", "--app-heap=4096", "--kernel-heap=1024", "--protected-region-size=64",
+];
+
+pub struct Builder {
+    root: PathBuf,
+    sandbox: bool,
+}
+
+impl Builder {
+    /// Runs the build steps in the checkout at `root`. With `sandbox`, every
+    /// step runs inside the same bubblewrap sandbox as the Makefile build.
+    pub fn new(root: PathBuf, sandbox: bool) -> Builder {
+        Builder { root, sandbox }
+    }
+
+    pub fn path<P: AsRef<Path>>(&self, relative: P) -> PathBuf {
+        self.root.join(relative)
+    }
+
+    /// Creates build/ and the files the sandbox binds, like `sandbox_setup`.
+    pub fn setup(&self) -> Result<()> {
+        create_dir(&self.path("build"))?;
+        if !self.sandbox {
+            return Ok(());
+        }
+        for lock in LOCK_FILES {
+            OpenOptions::new().create(true).append(true).open(self.path(lock))
+                .map_err(|err| format!("cannot create {}: {}", lock, err))?;
+        }
+        Ok(())
+    }
+
+    /// Writes build/gitlongtag, which the Rust apps embed as their version.
+    pub fn gitlongtag(&self) -> Result<()> {
+        let output = Command::new("git")
+            .args(["describe", "--always", "--dirty", "--long"])
+            .current_dir(&self.root)
+            .output()
+            .map_err(|err| format!("cannot run git: {}", err))?;
+        if !output.status.success() {
+            return Err("git describe failed".to_string());
+        }
+        let tag = String::from_utf8_lossy(&output.stdout);
+        write(&self.path("build/gitlongtag"), tag.trim().as_bytes())
+    }
+
+    /// Builds elf2tab from a copy of third_party/elf2tab and returns its path.
+    pub fn elf2tab(&self) -> Result<PathBuf> {
+        let copy = self.path("build/elf2tab");
+        if copy.exists() {
+            std::fs::remove_dir_all(&copy)
+                .map_err(|err| format!("cannot remove {}: {}", copy.display(), err))?;
+        }
+        let mut cp = Command::new("cp");
+        cp.arg("-rp").arg("-t").arg(self.path("build")).arg(self.path("third_party/elf2tab"));
+        self.run("copying elf2tab", cp)?;
+        let _ = std::fs::remove_file(copy.join("Cargo.lock"));
+
+        let mut cargo = self.cargo("build/elf2tab");
+        cargo.env("CARGO_TARGET_DIR", self.path("build/cargo-host")).args(["build", "--release"]);
+        self.run("building elf2tab", cargo)?;
+        Ok(self.path("build/cargo-host/release/elf2tab"))
+    }
+
+    /// Builds the kernels for `image` and returns the directory holding one
+    /// ELF per board.
+    pub fn kernel(&self, image: Image) -> Result<PathBuf> {
+        let target_dir = self.path(format!("build/kernel/cargo{}", image.suffix()));
+        let mut cargo = self.cargo("kernel");
+        cargo.env("CARGO_TARGET_DIR", &target_dir)
+            .env("RUSTFLAGS", format!("-C link-arg=-T./layout{}.ld", image.suffix()))
+            .args(["build", "--release"]);
+        self.run(&format!("building kernel{}", image.suffix()), cargo)?;
+        Ok(target_dir.join(TARGET).join("release"))
+    }
+
+    /// Builds `app` for `board` and `image` and returns its TBF.
+    pub fn app(&self, app: &App, board: &str, image: Image, elf2tab: &Path) -> Result<AppTbf> {
+        let out_dir = self.path(format!("build/userspace/{}/{}", app.name, board));
+        create_dir(&out_dir)?;
+        let tbf = match app.kind {
+            Kind::C => self.c_app(app)?,
+            Kind::Rust => {
+                let elf = out_dir.join(format!("app{}", image.suffix()));
+                self.rust_app(app, image, &elf)?;
+                self.tab(app, &elf, &out_dir.join(format!("app_tab{}", image.suffix())), elf2tab)?
+            }
+            Kind::RustTest => {
+                let elf = out_dir.join("app");
+                self.rust_test(app, &elf)?;
+                self.tab(app, &elf, &out_dir.join("app_tab"), elf2tab)?
+            }
+        };
+
+        let data = read(&tbf)?;
+        if data.len() as u64 > MAX_TBF_LEN {
+            return Err(format!("application {} for board {} is too large: {} is {} bytes, \
+                                the limit is {}", app.name, board, tbf.display(), data.len(),
+                               MAX_TBF_LEN));
+        }
+        Ok(AppTbf {
+            name: app.name.to_string(),
+            // libtock-rs apps are linked for the first app slot; libtock-c
+            // apps are position independent.
+            fixed_address: app.kind != Kind::C,
+            tbf: data,
+        })
+    }
+
+    fn c_app(&self, app: &App) -> Result<PathBuf> {
+        let mut make = self.command(&format!("userspace/{}", app.name), "make");
+        make.args(["-f", "TockMakefile"]);
+        self.run(&format!("building {}", app.name), make)?;
+        Ok(self.path(format!("build/userspace/{}/cortex-m3/cortex-m3.tbf", app.name)))
+    }
+
+    fn rust_app(&self, app: &App, image: Image, elf: &Path) -> Result<()> {
+        let target_dir = self.path(format!("build/userspace/cargo{}", image.suffix()));
+        let mut cargo = self.cargo(&format!("userspace/{}", app.name));
+        cargo.env("CARGO_TARGET_DIR", &target_dir)
+            .env("RUSTFLAGS", format!("-C link-arg=-T./layout{}.ld -C relocation-model=static \
+                                       -C linker-flavor=ld.lld", image.suffix()))
+            .env("TOCK_KERNEL_VERSION", app.name)
+            .args(["build", "--offline", "--release"]);
+        self.run(&format!("building {}{}", app.name, image.suffix()), cargo)?;
+        copy(&target_dir.join(TARGET).join("release").join(app.name), elf)
+    }
+
+    fn rust_test(&self, app: &App, elf: &Path) -> Result<()> {
+        let deps = self.path(format!("build/userspace/cargo/{}/release/deps", TARGET));
+        // Remove stale test binaries so that the one found below is current.
+        for stale in test_binaries(&deps, app.name) {
+            let _ = std::fs::remove_file(stale);
+        }
+        let mut cargo = self.cargo(&format!("userspace/{}", app.name));
+        cargo.env("TOCK_KERNEL_VERSION", app.name)
+            .args(["test", "--no-run", "--offline", "--release"]);
+        self.run(&format!("building {}", app.name), cargo)?;
+        match test_binaries(&deps, app.name).as_slice() {
+            [binary] => copy(binary, elf),
+            found => Err(format!("expected one test binary for {} in {}, found {}",
+                                 app.name, deps.display(), found.len())),
+        }
+    }
+
+    /// Runs elf2tab on `elf`. It writes the TBF next to the ELF.
+    fn tab(&self, app: &App, elf: &Path, tab: &Path, elf2tab: &Path) -> Result<PathBuf> {
+        let mut command = Command::new(elf2tab);
+        command.arg("-n").arg(app.name).arg("-o").arg(tab).arg(elf).args(ELF2TAB_ARGS);
+        self.run(&format!("packaging {}", app.name), command)?;
+        Ok(elf.with_extension("tbf"))
+    }
+
+    /// Lays out `apps` in the .apps section of `kernel` and writes the result
+    /// to `dir`/unsigned_image, next to a map of the layout. Returns the path
+    /// of the unsigned image.
+    pub fn unsigned_image(&self, kernel: &Path, apps: &[AppTbf], dir: &Path, image: Image)
+        -> Result<PathBuf> {
+        let layout = image::layout(AppRegion::from_kernel(kernel)?, apps)?;
+        let output = dir.join(format!("unsigned_image{}", image.suffix()));
+        let apps_bin = output.with_extension("apps");
+        write(&apps_bin, &layout.data)?;
+        write(&output.with_extension("map"), layout.map().as_bytes())?;
+        copy(kernel, &output)?;
+
+        let mut flags = Command::new("arm-none-eabi-objcopy");
+        flags.args(["--set-section-flags", ".apps=alloc,code,contents"]).arg(&output);
+        self.run("marking the .apps section loadable", flags)?;
+
+        let mut section = OsString::from(".apps=");
+        section.push(&apps_bin);
+        let mut update = Command::new("arm-none-eabi-objcopy");
+        update.arg("--update-section").arg(section).arg(&output);
+        self.run(&format!("adding the apps to {}", output.display()), update)?;
+        Ok(output)
+    }
+
+    /// Signs `unsigned` with $TANGO_CODESIGNER and prepends the bootloader,
+    /// returning the path of the flashable image in `dir`.
+    pub fn full_image(&self, unsigned: &Path, dir: &Path, image: Image) -> Result<PathBuf> {
+        let codesigner = env("TANGO_CODESIGNER")?;
+        let key = env("TANGO_CODESIGNER_KEY")?;
+        let bootloader = env(&format!("TANGO_BOOTLOADER{}", image.suffix()))?;
+        let signed = dir.join(format!("signed_image{}", image.suffix()));
+
+        let mut key_arg = OsString::from("--key=");
+        key_arg.push(&key);
+        let mut output_arg = OsString::from("--output=");
+        output_arg.push(&signed);
+        let mut bin_arg = OsString::from("--bin_output=");
+        bin_arg.push(signed.with_extension("bin"));
+        let mut sign = Command::new(&codesigner);
+        sign.arg("--b").arg("--input").arg(unsigned).arg(key_arg).arg(output_arg).arg(bin_arg);
+        self.run(&format!("signing {}", unsigned.display()), sign)?;
+
+        let mut full = read(Path::new(&bootloader))?;
+        full.extend(read(&signed)?);
+        let output = dir.join(format!("full_image{}", image.suffix()));
+        write(&output, &full)?;
+        Ok(output)
+    }
+
+    /// Returns a command running `program` in `dir`, inside the sandbox if
+    /// enabled.
+    fn command(&self, dir: &str, program: &str) -> Command {
+        let mut command = if self.sandbox {
+            let mut bwrap = Command::new("bwrap");
+            bwrap.args(self.bwrap_args()).arg(program);
+            bwrap
+        } else {
+            Command::new(program)
+        };
+        command.current_dir(self.path(dir));
+        command
+    }
+
+    fn cargo(&self, dir: &str) -> Command {
+        let mut cargo = self.command(dir, "cargo");
+        // `cargo xtask` runs under the tools toolchain; let rustup pick the
+        // toolchain pinned in `dir` instead.
+        cargo.env_remove("RUSTUP_TOOLCHAIN").env_remove("CARGO_TARGET_DIR");
+        cargo
+    }
+
+    fn bwrap_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["--ro-bind".into(), "/".into(), "/".into(),
+                                           "--tmpfs".into(), "/tmp".into()];
+        args.push("--ro-bind".into());
+        args.push(self.root.clone().into());
+        args.push(self.root.clone().into());
+        for writable in std::iter::once("build").chain(LOCK_FILES.iter().copied()) {
+            args.push("--bind".into());
+            args.push(self.path(writable).into());
+            args.push(self.path(writable).into());
+        }
+        args.extend(["--dev", "/dev", "--unshare-all"].iter().map(OsString::from));
+        args
+    }
+
+    fn run(&self, what: &str, mut command: Command) -> Result<()> {
+        println!("==> {}", what);
+        let status = command.status()
+            .map_err(|err| format!("{}: cannot run {:?}: {}", what, command, err))?;
+        if !status.success() {
+            return Err(format!("{} failed ({})", what, status));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the test binaries cargo built for `app` in `deps`, which are
+/// named `<app>-<hash>` without an extension.
+fn test_binaries(deps: &Path, app: &str) -> Vec<PathBuf> {
+    let prefix = format!("{}-", app);
+    let entries = match std::fs::read_dir(deps) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.starts_with(&prefix) && !name.contains('.'),
+            None => false,
+        })
+        .collect()
+}
+
+fn env(variable: &str) -> Result<OsString> {
+    std::env::var_os(variable)
+        .ok_or_else(|| format!("{} must be set to build signed images", variable))
+}
+
+pub fn create_dir(path: &Path) -> Result<()> {
+    std::fs::create_dir_all(path).map_err(|err| format!("cannot create {}: {}", path.display(), err))
+}
+
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    std::fs::copy(from, to)
+        .map(|_| ())
+        .map_err(|err| format!("cannot copy {} to {}: {}", from.display(), to.display(), err))
+}
+
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|err| format!("cannot read {}: {}", path.display(), err))
+}
+
+pub fn write(path: &Path, data: &[u8]) -> Result<()> {
+    std::fs::write(path, data).map_err(|err| format!("cannot write {}: {}", path.display(), err))
+}
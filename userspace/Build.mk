@@ -15,13 +15,17 @@
 BUILD_SUBDIRS := $(addprefix userspace/,                   \
                                          aes_test          \
                                          blink             \
+                                         crypto_capture    \
                                          dcrypto_test      \
                                          flash_test        \
                                          gpio_test         \
+                                         irq_latency_test  \
                                          low_level_debug   \
+                                         nvcounter_chaos   \
                                          nvcounter_ctest   \
                                          nvcounter_test    \
                                          otpilot           \
+                                         papa_e2e_test     \
                                          personality_clear \
                                          personality_test  \
                                          rng               \
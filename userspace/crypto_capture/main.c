@@ -0,0 +1,153 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Runs the SHA and AES engines over a fixed corpus and prints every result as
+// a "golden:" line. kernel/h1/src/crypto/golden_vectors.txt holds the lines
+// of a capture, and the h1 host tests check the drivers against them:
+//
+//   golden: <op> <key> <iv> <input> <output>
+//
+// All fields are hex, and "-" stands for a field the operation does not use.
+
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "digest_syscalls.h"
+#include "h1_aes_syscalls.h"
+
+#define AES_BLOCK_LEN 16
+#define MAX_INPUT_LEN 255
+
+// Lengths around the SHA block (64 bytes) and padding (56 bytes) boundaries.
+static const size_t sha_lengths[] = {
+  1, 3, 4, 5, 55, 56, 57, 63, 64, 65, 119, 120, 128, 255,
+};
+
+// Seeds of the key, IV and input of each AES case.
+static const uint8_t aes_seeds[][3] = {
+  { 0x00, 0x11, 0x22 },
+  { 0x5a, 0xa5, 0x3c },
+  { 0xff, 0x80, 0x01 },
+};
+
+static uint8_t input[MAX_INPUT_LEN];
+static uint8_t output[32];
+static uint8_t key[AES_BLOCK_LEN];
+static uint8_t iv[AES_BLOCK_LEN];
+static uint8_t ctr[AES_BLOCK_LEN];
+static uint8_t block[AES_BLOCK_LEN];
+
+// The corpus is generated rather than stored, so that the host tests can
+// generate it too.
+static void fill(uint8_t* buf, size_t len, uint8_t seed) {
+  for (size_t i = 0; i < len; ++i) {
+    buf[i] = (uint8_t)(seed + i * 29);
+  }
+}
+
+static void print_hex(const uint8_t* buf, size_t len) {
+  printf(" ");
+  if (buf == NULL) {
+    printf("-");
+    return;
+  }
+  for (size_t i = 0; i < len; ++i) {
+    printf("%02x", buf[i]);
+  }
+}
+
+static void print_golden(const char* op, const uint8_t* k, const uint8_t* v,
+                         const uint8_t* in, size_t in_len,
+                         const uint8_t* out, size_t out_len) {
+  printf("golden: %s", op);
+  print_hex(k, AES_BLOCK_LEN);
+  print_hex(v, AES_BLOCK_LEN);
+  print_hex(in, in_len);
+  print_hex(out, out_len);
+  printf("\n");
+}
+
+static int capture_sha(const char* op, TockDigestMode mode, size_t digest_len) {
+  for (size_t i = 0; i < sizeof(sha_lengths) / sizeof(sha_lengths[0]); ++i) {
+    size_t len = sha_lengths[i];
+    fill(input, len, (uint8_t)len);
+    memset(output, 0, sizeof(output));
+    int ret = tock_digest_hash_easy(input, len, output, digest_len, mode);
+    if (ret < 0) {
+      printf("%s of %u bytes failed: %d\n", op, (unsigned)len, ret);
+      return ret;
+    }
+    print_golden(op, NULL, NULL, input, len, output, digest_len);
+  }
+  return 0;
+}
+
+static int capture_aes(void) {
+  for (size_t i = 0; i < sizeof(aes_seeds) / sizeof(aes_seeds[0]); ++i) {
+    fill(key, AES_BLOCK_LEN, aes_seeds[i][0]);
+    fill(iv, AES_BLOCK_LEN, aes_seeds[i][1]);
+    fill(input, AES_BLOCK_LEN, aes_seeds[i][2]);
+
+    int ret = tock_aes_set_key(key, AES_BLOCK_LEN);
+    if (ret < 0) {
+      printf("Setting AES key failed: %d\n", ret);
+      return ret;
+    }
+
+    memcpy(block, input, AES_BLOCK_LEN);
+    ret = tock_aes_encrypt_ecb_sync(AES_BLOCK_LEN, block, AES_BLOCK_LEN);
+    if (ret < 0) {
+      printf("ECB encryption failed: %d\n", ret);
+      return ret;
+    }
+    print_golden("aes128-ecb-encrypt", key, NULL, input, AES_BLOCK_LEN,
+                 block, AES_BLOCK_LEN);
+
+    memcpy(block, input, AES_BLOCK_LEN);
+    ret = tock_aes_decrypt_ecb_sync(AES_BLOCK_LEN, block, AES_BLOCK_LEN);
+    if (ret < 0) {
+      printf("ECB decryption failed: %d\n", ret);
+      return ret;
+    }
+    print_golden("aes128-ecb-decrypt", key, NULL, input, AES_BLOCK_LEN,
+                 block, AES_BLOCK_LEN);
+
+    // The library advances the counter, so work on a copy.
+    memcpy(block, input, AES_BLOCK_LEN);
+    memcpy(ctr, iv, AES_BLOCK_LEN);
+    ret = tock_aes_encrypt_ctr_sync(block, AES_BLOCK_LEN, ctr, AES_BLOCK_LEN);
+    if (ret < 0) {
+      printf("CTR encryption failed: %d\n", ret);
+      return ret;
+    }
+    print_golden("aes128-ctr", key, iv, input, AES_BLOCK_LEN,
+                 block, AES_BLOCK_LEN);
+  }
+  return 0;
+}
+
+int main(void) {
+  printf("==== Capturing golden vectors ====\n");
+  if (capture_sha("sha1", DIGEST_MODE_SHA1, 160 / 8) < 0 ||
+      capture_sha("sha256", DIGEST_MODE_SHA256, 256 / 8) < 0 ||
+      capture_aes() < 0) {
+    printf("==== Capture failed ====\n");
+    return -1;
+  }
+  printf("==== Capture done ====\n");
+  return 0;
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#include <string.h>
 #include <tock.h>
 #include "dcrypto.h"
 
@@ -101,3 +102,82 @@ int tock_dcrypto_run(void* data, size_t datalen,
   }
 }
 
+
+// Data buffer shared with the kernel by the Curve25519 commands; large
+// enough for a public key, a signature and the longest message.
+static uint8_t curve25519_buffer[TOCK_DCRYPTO_25519_KEY_LEN +
+				 TOCK_DCRYPTO_25519_SIGNATURE_LEN +
+				 TOCK_DCRYPTO_25519_MAX_MESSAGE];
+
+// Runs a Curve25519 command on curve25519_buffer and waits for the engine
+// to finish. Returns the result of the command.
+static int tock_dcrypto_25519_command(int command_num, size_t message_len) {
+  bool run_done = false;
+  int ret = subscribe(HOTEL_DRIVER_DCRYPTO, TOCK_DCRYPTO_RUN_DONE,
+		      tock_dcrypto_run_done, &run_done);
+  if (ret < 0) {
+    return ret;
+  }
+  ret = allow(HOTEL_DRIVER_DCRYPTO, TOCK_DCRYPTO_ALLOW_DATA,
+	      curve25519_buffer, sizeof(curve25519_buffer));
+  if (ret < 0) {
+    return ret;
+  }
+  ret = command(HOTEL_DRIVER_DCRYPTO, command_num, message_len, 0);
+  if (ret >= 0) {
+    yield_for(&run_done);
+    ret = last_error;
+  }
+  allow(HOTEL_DRIVER_DCRYPTO, TOCK_DCRYPTO_ALLOW_DATA, NULL, 0);
+  return ret;
+}
+
+int tock_dcrypto_x25519(uint8_t* out, const uint8_t* scalar, const uint8_t* u) {
+  memcpy(curve25519_buffer, scalar, TOCK_DCRYPTO_25519_KEY_LEN);
+  memcpy(curve25519_buffer + TOCK_DCRYPTO_25519_KEY_LEN, u, TOCK_DCRYPTO_25519_KEY_LEN);
+  int ret = tock_dcrypto_25519_command(TOCK_DCRYPTO_CMD_X25519, 0);
+  if (ret >= 0) {
+    memcpy(out, curve25519_buffer, TOCK_DCRYPTO_25519_KEY_LEN);
+  }
+  memset(curve25519_buffer, 0, sizeof(curve25519_buffer));
+  return ret;
+}
+
+int tock_dcrypto_ed25519_public_key(uint8_t* public_key, const uint8_t* secret) {
+  memcpy(curve25519_buffer, secret, TOCK_DCRYPTO_25519_KEY_LEN);
+  int ret = tock_dcrypto_25519_command(TOCK_DCRYPTO_CMD_ED25519_PUBKEY, 0);
+  if (ret >= 0) {
+    memcpy(public_key, curve25519_buffer + TOCK_DCRYPTO_25519_KEY_LEN,
+	   TOCK_DCRYPTO_25519_KEY_LEN);
+  }
+  memset(curve25519_buffer, 0, sizeof(curve25519_buffer));
+  return ret;
+}
+
+int tock_dcrypto_ed25519_sign(uint8_t* signature, const uint8_t* secret,
+			      const uint8_t* message, size_t message_len) {
+  if (message_len > TOCK_DCRYPTO_25519_MAX_MESSAGE) {
+    return TOCK_ESIZE;
+  }
+  memcpy(curve25519_buffer, secret, TOCK_DCRYPTO_25519_KEY_LEN);
+  memcpy(curve25519_buffer + TOCK_DCRYPTO_25519_SIGNATURE_LEN, message, message_len);
+  int ret = tock_dcrypto_25519_command(TOCK_DCRYPTO_CMD_ED25519_SIGN, message_len);
+  if (ret >= 0) {
+    memcpy(signature, curve25519_buffer, TOCK_DCRYPTO_25519_SIGNATURE_LEN);
+  }
+  memset(curve25519_buffer, 0, sizeof(curve25519_buffer));
+  return ret;
+}
+
+int tock_dcrypto_ed25519_verify(const uint8_t* public_key, const uint8_t* signature,
+				const uint8_t* message, size_t message_len) {
+  if (message_len > TOCK_DCRYPTO_25519_MAX_MESSAGE) {
+    return TOCK_ESIZE;
+  }
+  memcpy(curve25519_buffer, public_key, TOCK_DCRYPTO_25519_KEY_LEN);
+  memcpy(curve25519_buffer + TOCK_DCRYPTO_25519_KEY_LEN, signature,
+	 TOCK_DCRYPTO_25519_SIGNATURE_LEN);
+  memcpy(curve25519_buffer + TOCK_DCRYPTO_25519_KEY_LEN + TOCK_DCRYPTO_25519_SIGNATURE_LEN,
+	 message, message_len);
+  return tock_dcrypto_25519_command(TOCK_DCRYPTO_CMD_ED25519_VERIFY, message_len);
+}
@@ -15,12 +15,22 @@
 #ifndef TOCK_DCRYPTO_H
 #define TOCK_DCRYPTO_H
 
+#include <stdint.h>
 #include <stdlib.h>
 
 #define HOTEL_DRIVER_DCRYPTO 0x40004
 
-#define TOCK_DCRYPTO_CMD_CHECK 0
-#define TOCK_DCRYPTO_CMD_RUN   1
+#define TOCK_DCRYPTO_CMD_CHECK          0
+#define TOCK_DCRYPTO_CMD_RUN            1
+#define TOCK_DCRYPTO_CMD_X25519         2
+#define TOCK_DCRYPTO_CMD_ED25519_PUBKEY 3
+#define TOCK_DCRYPTO_CMD_ED25519_SIGN   4
+#define TOCK_DCRYPTO_CMD_ED25519_VERIFY 5
+
+#define TOCK_DCRYPTO_25519_KEY_LEN       32
+#define TOCK_DCRYPTO_25519_SIGNATURE_LEN 64
+// Longest message accepted by the Ed25519 wrappers below.
+#define TOCK_DCRYPTO_25519_MAX_MESSAGE   256
 
 #define TOCK_DCRYPTO_ALLOW_DATA 0
 #define TOCK_DCRYPTO_ALLOW_PROG 1
@@ -50,4 +60,18 @@
 int tock_dcrypto_run(void* data, size_t datalen,
 		     void* program, size_t programlen);
 
+// Computes X25519(scalar, u) as described in RFC 7748.
+int tock_dcrypto_x25519(uint8_t* out, const uint8_t* scalar, const uint8_t* u);
+
+// Computes the Ed25519 public key for a 32-byte secret key.
+int tock_dcrypto_ed25519_public_key(uint8_t* public_key, const uint8_t* secret);
+
+// Signs a message of up to TOCK_DCRYPTO_25519_MAX_MESSAGE bytes with Ed25519.
+int tock_dcrypto_ed25519_sign(uint8_t* signature, const uint8_t* secret,
+			      const uint8_t* message, size_t message_len);
+
+// Returns 0 if the Ed25519 signature is valid and a negative value otherwise.
+int tock_dcrypto_ed25519_verify(const uint8_t* public_key, const uint8_t* signature,
+				const uint8_t* message, size_t message_len);
+
 #endif
@@ -23,6 +23,44 @@ static char program_recursion[] = {0x00, 0x00, 0x00, 0x08,  // CALL 0
                                    0x00, 0x00, 0x00, 0x00}; // BREAK
  
 static char data[] = "Data to encrypt. We shall see if this works.";
+
+// X25519 test vector from RFC 7748 section 5.2.
+static const uint8_t x25519_scalar[] = {
+  0xa5, 0x46, 0xe3, 0x6b, 0xf0, 0x52, 0x7c, 0x9d, 0x3b, 0x16, 0x15, 0x4b,
+  0x82, 0x46, 0x5e, 0xdd, 0x62, 0x14, 0x4c, 0x0a, 0xc1, 0xfc, 0x5a, 0x18,
+  0x50, 0x6a, 0x22, 0x44, 0xba, 0x44, 0x9a, 0xc4,
+};
+static const uint8_t x25519_u[] = {
+  0xe6, 0xdb, 0x68, 0x67, 0x58, 0x30, 0x30, 0xdb, 0x35, 0x94, 0xc1, 0xa4,
+  0x24, 0xb1, 0x5f, 0x7c, 0x72, 0x66, 0x24, 0xec, 0x26, 0xb3, 0x35, 0x3b,
+  0x10, 0xa9, 0x03, 0xa6, 0xd0, 0xab, 0x1c, 0x4c,
+};
+static const uint8_t x25519_expected[] = {
+  0xc3, 0xda, 0x55, 0x37, 0x9d, 0xe9, 0xc6, 0x90, 0x8e, 0x94, 0xea, 0x4d,
+  0xf2, 0x8d, 0x08, 0x4f, 0x32, 0xec, 0xcf, 0x03, 0x49, 0x1c, 0x71, 0xf7,
+  0x54, 0xb4, 0x07, 0x55, 0x77, 0xa2, 0x85, 0x52,
+};
+
+// Ed25519 test vector 2 from RFC 8032 section 7.1.
+static const uint8_t ed25519_secret[] = {
+  0x4c, 0xcd, 0x08, 0x9b, 0x28, 0xff, 0x96, 0xda, 0x9d, 0xb6, 0xc3, 0x46,
+  0xec, 0x11, 0x4e, 0x0f, 0x5b, 0x8a, 0x31, 0x9f, 0x35, 0xab, 0xa6, 0x24,
+  0xda, 0x8c, 0xf6, 0xed, 0x4f, 0xb8, 0xa6, 0xfb,
+};
+static const uint8_t ed25519_public[] = {
+  0x3d, 0x40, 0x17, 0xc3, 0xe8, 0x43, 0x89, 0x5a, 0x92, 0xb7, 0x0a, 0xa7,
+  0x4d, 0x1b, 0x7e, 0xbc, 0x9c, 0x98, 0x2c, 0xcf, 0x2e, 0xc4, 0x96, 0x8c,
+  0xc0, 0xcd, 0x55, 0xf1, 0x2a, 0xf4, 0x66, 0x0c,
+};
+static const uint8_t ed25519_signature[] = {
+  0x92, 0xa0, 0x09, 0xa9, 0xf0, 0xd4, 0xca, 0xb8, 0x72, 0x0e, 0x82, 0x0b,
+  0x5f, 0x64, 0x25, 0x40, 0xa2, 0xb2, 0x7b, 0x54, 0x16, 0x50, 0x3f, 0x8f,
+  0xb3, 0x76, 0x22, 0x23, 0xeb, 0xdb, 0x69, 0xda, 0x08, 0x5a, 0xc1, 0xe4,
+  0x3e, 0x15, 0x99, 0x6e, 0x45, 0x8f, 0x36, 0x13, 0xd0, 0xf1, 0x1d, 0x8c,
+  0x38, 0x7b, 0x2e, 0xae, 0xb4, 0x30, 0x2a, 0xee, 0xb0, 0x0d, 0x29, 0x16,
+  0x12, 0xbb, 0x0c, 0x00,
+};
+static const uint8_t ed25519_message[] = {0x72};
 /* static char key[] = "1234567890123456";
 static char expected[] = {
     0x25, 0x97, 0xea, 0xce, 0x3a, 0x51, 0xce, 0x0d, 0xd8, 0x97, 0xae, 0x00,
@@ -45,6 +83,43 @@ void print_buffer(char *buffer, size_t length, const char *format) {
   printf("\n");
 }
 
+static void check_result(const char* name, int ret, const uint8_t* actual,
+                         const uint8_t* expected, size_t length) {
+  if (ret < 0) {
+    printf("%s: FAIL (error %d)\n", name, ret);
+  } else if (actual != NULL && memcmp(actual, expected, length) != 0) {
+    printf("%s: FAIL (mismatch)\n", name);
+  } else {
+    printf("%s: PASS\n", name);
+  }
+}
+
+static void test_curve25519(void) {
+  uint8_t output[TOCK_DCRYPTO_25519_SIGNATURE_LEN];
+  uint8_t bad_signature[TOCK_DCRYPTO_25519_SIGNATURE_LEN];
+  int ret;
+
+  ret = tock_dcrypto_x25519(output, x25519_scalar, x25519_u);
+  check_result("X25519", ret, output, x25519_expected, sizeof(x25519_expected));
+
+  ret = tock_dcrypto_ed25519_public_key(output, ed25519_secret);
+  check_result("Ed25519 public key", ret, output, ed25519_public, sizeof(ed25519_public));
+
+  ret = tock_dcrypto_ed25519_sign(output, ed25519_secret,
+                                  ed25519_message, sizeof(ed25519_message));
+  check_result("Ed25519 sign", ret, output, ed25519_signature, sizeof(ed25519_signature));
+
+  ret = tock_dcrypto_ed25519_verify(ed25519_public, ed25519_signature,
+                                    ed25519_message, sizeof(ed25519_message));
+  check_result("Ed25519 verify", ret, NULL, NULL, 0);
+
+  memcpy(bad_signature, ed25519_signature, sizeof(bad_signature));
+  bad_signature[0] ^= 1;
+  ret = tock_dcrypto_ed25519_verify(ed25519_public, bad_signature,
+                                    ed25519_message, sizeof(ed25519_message));
+  printf("Ed25519 verify bad signature: %s\n", ret < 0 ? "PASS" : "FAIL");
+}
+
 int main(void) {
   int ret = 0;
   
@@ -61,6 +136,9 @@ int main(void) {
   //printf("Return value: %i.\n", ret);
   //printf("\n");
 
+  printf("3. Testing X25519 and Ed25519 against RFC test vectors.\n");
+  test_curve25519();
+
   /*
   printf("Expecting [%d]: 0x", sizeof(expected));
   print_buffer(expected, sizeof(expected), "%02x");
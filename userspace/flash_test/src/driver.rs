@@ -361,3 +361,40 @@ fn write_max_retries() -> bool {
 
     true
 }
+
+#[test]
+fn read_while_write() -> bool {
+    use h1::hil::flash::Bank;
+    use kernel::hil::time::{AlarmClient,Time};
+    let alarm = crate::mock_alarm::MockAlarm::new();
+    let client = MockClient::new();
+    let hw = h1::hil::flash::fake::FakeHw::new();
+    let driver = unsafe { h1::hil::flash::FlashImpl::new(&alarm, &hw) };
+    driver.set_client(&client);
+
+    // Start a write in bank 1.
+    unsafe {
+        WRITE_BUF[0] = 0xFFFFABCD;
+        require!(driver.write(WORDS_PER_BANK + 1300, &mut WRITE_BUF) == (kernel::ReturnCode::SUCCESS, None));
+    }
+    require!(driver.busy_bank() == Some(Bank::One));
+    require!(hw.is_bank_programming(Bank::One) == true);
+    require!(hw.is_bank_programming(Bank::Zero) == false);
+
+    // Bank 1 cannot be read while the write is ongoing, bank 0 can.
+    require!(driver.read(WORDS_PER_BANK + 1300) == ReturnCode::EBUSY);
+    require!(driver.read(1300) == ReturnCode::SuccessWithValue { value: 0xFFFFFFFF });
+
+    // Finish the write (including the final pulse).
+    alarm.set_time(WRITE_WORD_TIME.into());
+    hw.inject_result(0);
+    driver.alarm();
+    require!(driver.read(WORDS_PER_BANK + 1300) == ReturnCode::EBUSY);
+    hw.finish_operation();
+    driver.alarm();
+    require!(client.state() == Some(MockClientState::WriteDone(kernel::ReturnCode::SUCCESS)));
+    require!(driver.busy_bank() == None);
+    require!(driver.read(WORDS_PER_BANK + 1300) == ReturnCode::SuccessWithValue { value: 0xFFFFABCD });
+
+    true
+}
@@ -0,0 +1,80 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::flash;
+use crate::globalsec;
+use crate::probe;
+use libtock::println;
+use spiutils::protocol::firmware::SegmentAndLocation;
+use test::require;
+
+/// The longest the kernel may take to service the SPI device interrupt.
+const LATENCY_BOUND_NS: u32 = 50_000;
+
+/// Probes per measurement. A probe takes a syscall round trip, so this keeps
+/// probing for a few milliseconds, well into a page erase.
+const PROBES: usize = 500;
+
+/// Runs `PROBES` probes, calling `load` before each, and checks the
+/// latency against the bound.
+fn measure<F: FnMut()>(name: &str, mut load: F) -> bool {
+    require!(probe::is_present());
+    require!(probe::reset().is_ok());
+    for _ in 0..PROBES {
+        load();
+        require!(probe::pend().is_ok());
+    }
+    let stats = match probe::stats() {
+        Ok(stats) => stats,
+        Err(_) => return false,
+    };
+    println!("{}: {} samples, max {} ns, mean {} ns",
+             name, stats.samples, stats.max_ns, stats.mean_ns);
+    require!(stats.samples > 0);
+    require!(stats.max_ns <= LATENCY_BOUND_NS);
+    true
+}
+
+#[test]
+fn idle() -> bool {
+    measure("idle", || ())
+}
+
+/// Erases the first page of the inactive RW segment while probing.
+#[test]
+fn during_flash_erase() -> bool {
+    let segment = globalsec::get().get_inactive_rw();
+    require!(segment.identifier == SegmentAndLocation::RwA ||
+             segment.identifier == SegmentAndLocation::RwB);
+
+    require!(flash::get().erase(segment.start_page as usize).is_ok());
+    let within_bound = measure("flash erase", || ());
+    flash::get().wait_operation_done();
+    require!(flash::get().get_operation_result() == 0);
+    within_bound
+}
+
+/// Keeps the console UART transmitting while probing. LowLevelDebug queues
+/// its messages in the kernel, so they are sent by UART interrupts in the
+/// background.
+#[test]
+fn during_uart_traffic() -> bool {
+    let mut count = 0;
+    measure("uart traffic", || {
+        libtock::debug::low_level_print1(count);
+        count += 1;
+    })
+}
@@ -0,0 +1,44 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! On-target test of the SPI device interrupt service latency.
+//!
+//! The SPI device must answer BUSY polls within a fixed window. The tests
+//! probe the SPI device interrupt through the kernel's latency probe while
+//! other peripherals keep the kernel busy, and fail if servicing it ever
+//! takes longer than `latency::LATENCY_BOUND_NS`.
+
+#![no_std]
+
+// Rust complains that things are unused if they are only used when cfg(test) is
+// true. If we include modules when cfg(test) is false, then declarations in the
+// modules need to be marked #[cfg(test)]. Instead, we simply do not include the
+// code in other configs.
+
+// The drivers are shared with otpilot.
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../otpilot/src/flash.rs"]
+mod flash;
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../otpilot/src/globalsec.rs"]
+mod globalsec;
+
+#[cfg(test)]
+mod latency;
+#[cfg(test)]
+mod probe;
@@ -0,0 +1,70 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The kernel's SPI device interrupt latency probe.
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+const DRIVER_NUMBER: usize = 0x400e0;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const PROBE: usize = 1;
+    pub const GET_STAT: usize = 2;
+    pub const RESET: usize = 3;
+}
+
+mod stat_nr {
+    pub const SAMPLES: usize = 0;
+    pub const MAX_NS: usize = 1;
+    pub const MEAN_NS: usize = 2;
+}
+
+pub struct Stats {
+    pub samples: u32,
+    pub max_ns: u32,
+    pub mean_ns: u32,
+}
+
+pub fn is_present() -> bool {
+    syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0).is_ok()
+}
+
+/// Sets the SPI device interrupt pending. The kernel services it before the
+/// next syscall returns, so one probe is outstanding at a time.
+pub fn pend() -> TockResult<()> {
+    syscalls::command(DRIVER_NUMBER, command_nr::PROBE, 0, 0)?;
+    Ok(())
+}
+
+pub fn reset() -> TockResult<()> {
+    syscalls::command(DRIVER_NUMBER, command_nr::RESET, 0, 0)?;
+    Ok(())
+}
+
+fn get_stat(stat: usize) -> TockResult<u32> {
+    let value = syscalls::command(DRIVER_NUMBER, command_nr::GET_STAT, stat, 0)?;
+    Ok(value as u32)
+}
+
+pub fn stats() -> TockResult<Stats> {
+    Ok(Stats {
+        samples: get_stat(stat_nr::SAMPLES)?,
+        max_ns: get_stat(stat_nr::MAX_NS)?,
+        mean_ns: get_stat(stat_nr::MEAN_NS)?,
+    })
+}
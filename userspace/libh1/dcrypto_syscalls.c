@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#include <string.h>
 #include <tock.h>
 #include "dcrypto_syscalls.h"
 
@@ -19,6 +20,9 @@
 
 #define TOCK_DCRYPTO_CMD_CHECK 0
 #define TOCK_DCRYPTO_CMD_RUN   1
+#define TOCK_DCRYPTO_CMD_P256_PUBLIC_KEY 6
+#define TOCK_DCRYPTO_CMD_P256_SIGN       7
+#define TOCK_DCRYPTO_CMD_P256_VERIFY     8
 
 #define TOCK_DCRYPTO_ALLOW_DATA 0
 #define TOCK_DCRYPTO_ALLOW_PROG 1
@@ -122,3 +126,53 @@ int tock_dcrypto_run(void* data, size_t datalen,
     return 0;
   }
 }
+
+// Runs a P-256 command on buf, then clears the data allow.
+static int tock_dcrypto_p256_command(int cmd, uint8_t* buf, size_t len) {
+  int ret = allow(H1_DRIVER_DCRYPTO, TOCK_DCRYPTO_ALLOW_DATA, buf, len);
+  if (ret < 0) {
+    return ret;
+  }
+  ret = command(H1_DRIVER_DCRYPTO, cmd, 0, 0);
+  allow(H1_DRIVER_DCRYPTO, TOCK_DCRYPTO_ALLOW_DATA, NULL, 0);
+  return ret;
+}
+
+int tock_dcrypto_p256_public_key(const uint8_t private_key[32],
+                                 uint8_t public_key[64]) {
+  uint8_t buf[96];
+  memcpy(buf, private_key, 32);
+  int ret = tock_dcrypto_p256_command(TOCK_DCRYPTO_CMD_P256_PUBLIC_KEY,
+                                      buf, sizeof(buf));
+  if (ret == TOCK_SUCCESS) {
+    memcpy(public_key, buf + 32, 64);
+  }
+  memset(buf, 0, sizeof(buf));
+  return ret;
+}
+
+int tock_dcrypto_p256_sign(const uint8_t private_key[32],
+                           const uint8_t digest[32],
+                           uint8_t signature[64]) {
+  uint8_t buf[64];
+  memcpy(buf, private_key, 32);
+  memcpy(buf + 32, digest, 32);
+  int ret = tock_dcrypto_p256_command(TOCK_DCRYPTO_CMD_P256_SIGN,
+                                      buf, sizeof(buf));
+  if (ret == TOCK_SUCCESS) {
+    memcpy(signature, buf, 64);
+  }
+  memset(buf, 0, sizeof(buf));
+  return ret;
+}
+
+int tock_dcrypto_p256_verify(const uint8_t public_key[64],
+                             const uint8_t digest[32],
+                             const uint8_t signature[64]) {
+  uint8_t buf[160];
+  memcpy(buf, public_key, 64);
+  memcpy(buf + 64, digest, 32);
+  memcpy(buf + 96, signature, 64);
+  return tock_dcrypto_p256_command(TOCK_DCRYPTO_CMD_P256_VERIFY,
+                                   buf, sizeof(buf));
+}
@@ -15,6 +15,7 @@
 #ifndef TOCK_DCRYPTO_H
 #define TOCK_DCRYPTO_H
 
+#include <stdint.h>
 #include <stdlib.h>
 
 int tock_dcrypto_check(void);
@@ -33,4 +34,17 @@ int tock_dcrypto_run(void* data, size_t datalen,
                      void* program, size_t programlen,
                      size_t instruction);
 
+// ECDSA P-256 computed by the kernel. Keys, digests and signatures are
+// big-endian; public keys are x followed by y and signatures r followed by
+// s. Signing derives its nonce as in RFC 6979. verify returns TOCK_SUCCESS
+// for a valid signature and TOCK_FAIL otherwise.
+int tock_dcrypto_p256_public_key(const uint8_t private_key[32],
+                                 uint8_t public_key[64]);
+int tock_dcrypto_p256_sign(const uint8_t private_key[32],
+                           const uint8_t digest[32],
+                           uint8_t signature[64]);
+int tock_dcrypto_p256_verify(const uint8_t public_key[64],
+                             const uint8_t digest[32],
+                             const uint8_t signature[64]);
+
 #endif
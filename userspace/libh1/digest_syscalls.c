@@ -25,10 +25,14 @@
 #define TOCK_DIGEST_CMD_FINALIZE   3
 #define TOCK_DIGEST_CMD_BUSY       4
 #define TOCK_DIGEST_CMD_CERT_INIT  5
+#define TOCK_DIGEST_CMD_HASHED_LEN 6
+#define TOCK_DIGEST_CMD_HMAC_INIT  7
+#define TOCK_DIGEST_CMD_HMAC_FINAL 8
 
 // allow() type ids
 #define TOCK_DIGEST_ALLOW_INPUT    0
 #define TOCK_DIGEST_ALLOW_OUTPUT   1
+#define TOCK_DIGEST_ALLOW_HMAC_KEY 2
 
 int tock_digest_set_input(void* buf, size_t len) {
   int rval = allow(H1_DRIVER_DIGEST, TOCK_DIGEST_ALLOW_INPUT, buf, len);
@@ -46,6 +50,14 @@ int tock_digest_set_output(void* buf, size_t len) {
   return rval;
 }
 
+int tock_digest_set_hmac_key(void* buf, size_t len) {
+  int rval = allow(H1_DRIVER_DIGEST, TOCK_DIGEST_ALLOW_HMAC_KEY, buf, len);
+  if (rval != TOCK_SUCCESS){
+    printf("Digest set HMAC key returned error %i\n", rval);
+  }
+  return rval;
+}
+
 int tock_digest_check(void) {
   return command(H1_DRIVER_DIGEST, TOCK_DIGEST_CMD_CHECK, 0, 0);
 }
@@ -62,10 +74,26 @@ int tock_digest_hash_update(size_t n) {
   return command(H1_DRIVER_DIGEST, TOCK_DIGEST_CMD_UPDATE, n, 0);
 }
 
+int tock_digest_hash_update_at(size_t offset, size_t n) {
+  return command(H1_DRIVER_DIGEST, TOCK_DIGEST_CMD_UPDATE, n, offset);
+}
+
+int tock_digest_hashed_length(void) {
+  return command(H1_DRIVER_DIGEST, TOCK_DIGEST_CMD_HASHED_LEN, 0, 0);
+}
+
 int tock_digest_hash_finalize(void) {
   return command(H1_DRIVER_DIGEST, TOCK_DIGEST_CMD_FINALIZE, 0, 0);
 }
 
+int tock_digest_hmac_initialize(void) {
+  return command(H1_DRIVER_DIGEST, TOCK_DIGEST_CMD_HMAC_INIT, 0, 0);
+}
+
+int tock_digest_hmac_finalize(void) {
+  return command(H1_DRIVER_DIGEST, TOCK_DIGEST_CMD_HMAC_FINAL, 0, 0);
+}
+
 int tock_digest_busy(void) {
   return (command(H1_DRIVER_DIGEST, TOCK_DIGEST_CMD_BUSY, 0, 0) == TOCK_EBUSY);
 }
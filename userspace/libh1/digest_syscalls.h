@@ -27,6 +27,8 @@ typedef enum TockDigestMode {
 // TODO: Should be const, but currently not allowed by kernel
 int tock_digest_set_input(void* buf, size_t len);
 int tock_digest_set_output(void* buf, size_t len);
+// The HMAC key must be 32 bytes.
+int tock_digest_set_hmac_key(void* buf, size_t len);
 
 int tock_digest_check(void);
 
@@ -34,8 +36,19 @@ int tock_digest_hash_initialize(TockDigestMode mode);
 int tock_digest_cert_initialize(uint32_t cert);
 
 int tock_digest_hash_update(size_t n);
+// Feeds n bytes starting at offset in the input buffer. The input buffer may
+// be changed with tock_digest_set_input between updates, so a large message
+// can be hashed in chunks.
+int tock_digest_hash_update_at(size_t offset, size_t n);
+// Returns the number of bytes fed into the current hash, or an error.
+int tock_digest_hashed_length(void);
 int tock_digest_hash_finalize(void);
 
+// HMAC-SHA256 with the key from tock_digest_set_hmac_key. The key is cleared
+// from the engine when the HMAC is finalized.
+int tock_digest_hmac_initialize(void);
+int tock_digest_hmac_finalize(void);
+
 // Return if the hash engine is busy
 int tock_digest_busy(void);
 
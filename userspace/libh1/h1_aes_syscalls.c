@@ -24,6 +24,10 @@
 #define TOCK_AES_CMD_CTR_DEC 4
 #define TOCK_AES_CMD_CBC_ENC 5
 #define TOCK_AES_CMD_CBC_DEC 6
+#define TOCK_AES_CMD_INSTALL_SLOT 13
+#define TOCK_AES_CMD_CLEAR_SLOT   14
+#define TOCK_AES_CMD_USE_SLOT     15
+#define TOCK_AES_CMD_USE_KEY      16
 
 #define TOCK_AES_ALLOW_KEY    0
 #define TOCK_AES_ALLOW_INPUT  1
@@ -98,6 +102,28 @@ int tock_aes_set_key(const unsigned char* data, unsigned char len) {
   return allow(H1_AES_DRIVER, TOCK_AES_ALLOW_KEY, (void*)data, len);
 }
 
+int tock_aes_install_key_slot(unsigned int slot,
+                              const unsigned char* key, unsigned char len) {
+  int err = tock_aes_set_key(key, len);
+  if (err < TOCK_SUCCESS) return err;
+  err = command(H1_AES_DRIVER, TOCK_AES_CMD_INSTALL_SLOT, slot, 0);
+  // The kernel keeps its own copy of the key.
+  allow(H1_AES_DRIVER, TOCK_AES_ALLOW_KEY, NULL, 0);
+  return err;
+}
+
+int tock_aes_clear_key_slot(unsigned int slot) {
+  return command(H1_AES_DRIVER, TOCK_AES_CMD_CLEAR_SLOT, slot, 0);
+}
+
+int tock_aes_use_key_slot(unsigned int slot) {
+  return command(H1_AES_DRIVER, TOCK_AES_CMD_USE_SLOT, slot, 0);
+}
+
+int tock_aes_use_key_buffer(void) {
+  return command(H1_AES_DRIVER, TOCK_AES_CMD_USE_KEY, 0, 0);
+}
+
 // Operates on a single 16-byte block.
 // buf and ctr are assumed to be >= 16 bytes.
 static int aes_encrypt_ctr_block(unsigned char* buf,
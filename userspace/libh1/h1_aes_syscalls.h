@@ -31,6 +31,21 @@ int tock_aes_check(void);
 // len - length of the buffer (must be 16 for AES128 or 32 for AES256)
 int tock_aes_set_key(const unsigned char* key, unsigned char len);
 
+// Copies a 16-byte key into kernel key slot `slot`, which must be free or
+// already hold a key of this app. The kernel never hands the key back.
+int tock_aes_install_key_slot(unsigned int slot,
+                              const unsigned char* key, unsigned char len);
+
+// Wipes and frees key slot `slot`, which must hold a key of this app.
+int tock_aes_clear_key_slot(unsigned int slot);
+
+// Uses the key in slot `slot` instead of the key set with tock_aes_set_key.
+// Besides its own slots, an app can use slots the kernel installed keys in.
+int tock_aes_use_key_slot(unsigned int slot);
+
+// Goes back to the key set with tock_aes_set_key.
+int tock_aes_use_key_buffer(void);
+
 
 // Encrypts a payload according to AES counter-mode. The counter
 // stored in ctr is incremented for each block encrypted in a single
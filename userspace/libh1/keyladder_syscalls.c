@@ -0,0 +1,50 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#include "keyladder_syscalls.h"
+#include "tock.h"
+
+#define H1_DRIVER_KEYLADDER 0x401A0
+
+#define TOCK_KEYLADDER_CMD_CHECK  0
+#define TOCK_KEYLADDER_CMD_DERIVE 1
+
+#define TOCK_KEYLADDER_ALLOW_CONTEXT 0
+#define TOCK_KEYLADDER_ALLOW_OUTPUT  1
+
+int tock_keyladder_check(void) {
+  return command(H1_DRIVER_KEYLADDER, TOCK_KEYLADDER_CMD_CHECK, 0, 0);
+}
+
+int tock_keyladder_derive(TockKeyUsage usage,
+                          void* context, size_t context_len,
+                          unsigned char key[TOCK_KEYLADDER_KEY_LEN]) {
+  int rval = allow(H1_DRIVER_KEYLADDER, TOCK_KEYLADDER_ALLOW_CONTEXT,
+                   context_len > 0 ? context : NULL, context_len);
+  if (rval != TOCK_SUCCESS) {
+    return rval;
+  }
+  rval = allow(H1_DRIVER_KEYLADDER, TOCK_KEYLADDER_ALLOW_OUTPUT,
+               key, TOCK_KEYLADDER_KEY_LEN);
+  if (rval != TOCK_SUCCESS) {
+    return rval;
+  }
+  rval = command(H1_DRIVER_KEYLADDER, TOCK_KEYLADDER_CMD_DERIVE, usage, 0);
+  // Take the buffers back so the kernel no longer holds the key.
+  allow(H1_DRIVER_KEYLADDER, TOCK_KEYLADDER_ALLOW_CONTEXT, NULL, 0);
+  allow(H1_DRIVER_KEYLADDER, TOCK_KEYLADDER_ALLOW_OUTPUT, NULL, 0);
+  return rval;
+}
@@ -0,0 +1,39 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#ifndef TOCK_KEYLADDER_H
+#define TOCK_KEYLADDER_H
+
+#include <stdlib.h>
+
+#define TOCK_KEYLADDER_KEY_LEN 32
+
+typedef enum TockKeyUsage {
+  KEY_USAGE_ATTESTATION = 0,
+  KEY_USAGE_ORIGIN = 1,
+  KEY_USAGE_STORAGE = 2,
+} TockKeyUsage;
+
+int tock_keyladder_check(void);
+
+// Derives the calling app's key for usage and context into key. The same
+// app, usage and context always give the same key on the same chip.
+// context may be NULL if context_len is 0.
+int tock_keyladder_derive(TockKeyUsage usage,
+                          void* context, size_t context_len,
+                          unsigned char key[TOCK_KEYLADDER_KEY_LEN]);
+
+#endif
@@ -22,18 +22,26 @@
 
 #define TOCK_NVCOUNTER_INCREMENT_DONE    0
 
+// Callback codes for TOCK_NVCOUNTER_INCREMENT_DONE. On failure, the counter
+// argument is the value read before the failed increment.
+#define TOCK_NVCOUNTER_NOT_STARTED       0
+#define TOCK_NVCOUNTER_FAILED            1
+#define TOCK_NVCOUNTER_SUCCEEDED         2
+
 // We store the pointer to where we should store
 // the updated counter as a global. This is protected
 // by a successful call to the command, so it's only
 // overwritten if the command is successful. Furthermore,
 // after a callback it's reset to NULL.
 static unsigned int* counter_global = NULL;
+static int increment_code = TOCK_NVCOUNTER_NOT_STARTED;
 
-static void tock_nvcounter_increment_done(int code __attribute__ ((unused)),
+static void tock_nvcounter_increment_done(int code,
                                           int counter,
                                           int unused2 __attribute__((unused)),
                                           void *callback_args) {
   *(bool*)callback_args = true;
+  increment_code = code;
   if (counter_global != NULL) {
 
     *counter_global = (unsigned int)counter;
@@ -66,5 +74,8 @@ int tock_nvcounter_increment(unsigned int* counter) {
   counter_global = counter;
   yield_for(&increment_done);
 
+  if (increment_code != TOCK_NVCOUNTER_SUCCEEDED) {
+    return TOCK_FAIL;
+  }
   return TOCK_SUCCESS;
 }
@@ -18,7 +18,9 @@
 int tock_nvcounter_check(void);
 
 // Returns whether the increment was successful; if so, the
-// incremented value is stored in counter.
+// incremented value is stored in counter. If the increment was
+// started but failed, returns TOCK_FAIL and stores the value read
+// before the increment, which may or may not have been committed.
 int tock_nvcounter_increment(unsigned int* counter);
 
 #endif
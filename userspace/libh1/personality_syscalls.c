@@ -20,6 +20,10 @@
 #define TOCK_PERSONALITY_CMD_CHECK   0
 #define TOCK_PERSONALITY_CMD_GET     1
 #define TOCK_PERSONALITY_CMD_SET     2
+#define TOCK_PERSONALITY_CMD_BEGIN   3
+#define TOCK_PERSONALITY_CMD_COMMIT  4
+#define TOCK_PERSONALITY_CMD_ABORT   5
+#define TOCK_PERSONALITY_CMD_STATUS  6
 
 #define TOCK_PERSONALITY_ALLOW       0
 
@@ -83,3 +87,48 @@ int tock_set_personality(const perso_st* personality) {
 
   return TOCK_SUCCESS;
 }
+
+int tock_personality_begin(const perso_st* personality) {
+  int ret = allow(H1_DRIVER_PERSONALITY, TOCK_PERSONALITY_ALLOW,
+                  (perso_st*)personality, sizeof(perso_st));
+  if (ret < 0) {
+    printf("Could not give kernel access to personality buffer.\n");
+    return ret;
+  }
+
+  ret = command(H1_DRIVER_PERSONALITY, TOCK_PERSONALITY_CMD_BEGIN, 0, 0);
+  if (ret < 0) {
+    printf("Could not stage H1 personality update.\n");
+    return ret;
+  }
+
+  return TOCK_SUCCESS;
+}
+
+int tock_personality_commit(void) {
+  int ret = 0;
+  bool commit_done = false;
+  ret = subscribe(H1_DRIVER_PERSONALITY, TOCK_PERSONALITY_SET_DONE,
+                  tock_personality_set_done, &commit_done);
+  if (ret < 0) {
+    printf("Could not register for personality commit done callback.\n");
+    return ret;
+  }
+
+  ret = command(H1_DRIVER_PERSONALITY, TOCK_PERSONALITY_CMD_COMMIT, 0, 0);
+  if (ret < 0) {
+    printf("Could not commit H1 personality update.\n");
+    return ret;
+  }
+  yield_for(&commit_done);
+
+  return TOCK_SUCCESS;
+}
+
+int tock_personality_abort(void) {
+  return command(H1_DRIVER_PERSONALITY, TOCK_PERSONALITY_CMD_ABORT, 0, 0);
+}
+
+int tock_personality_status(void) {
+  return command(H1_DRIVER_PERSONALITY, TOCK_PERSONALITY_CMD_STATUS, 0, 0);
+}
@@ -21,4 +21,17 @@ int tock_personality_check(void);
 int tock_get_personality(perso_st* personality);
 int tock_set_personality(const perso_st* personality);
 
+/* Two-phase update: stage with begin, then commit or abort. A commit
+ * replaces the stored data entirely or not at all. */
+int tock_personality_begin(const perso_st* personality);
+int tock_personality_commit(void);
+int tock_personality_abort(void);
+
+/* Returns TOCK_PERSONALITY_STATUS_* or a negative error code. */
+int tock_personality_status(void);
+
+#define TOCK_PERSONALITY_STATUS_COMMITTED 0
+#define TOCK_PERSONALITY_STATUS_LEGACY    1
+#define TOCK_PERSONALITY_STATUS_CORRUPTED 2
+
 #endif
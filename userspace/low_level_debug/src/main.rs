@@ -18,9 +18,21 @@ use libtock_core::stack_size;
 
 stack_size!{2048}
 
-// Note: this currently calls into UintPrinter, not LowLevelDebug. When Tock 1.5
-// is released, we should replace UintPrinter with LowLevelDebug in golf2, at
-// which point this app will work correctly.
+const LOW_LEVEL_DEBUG_DRIVER: usize = 0x8;
+const PRINT_TAG: usize = 4;
+
+// Prints an ASCII tag of up to 8 bytes. This is an H1 extension of
+// LowLevelDebug; shorter tags are padded with NUL bytes.
+fn low_level_print_tag(tag: &[u8]) {
+    let mut bytes = [0u8; 8];
+    let len = tag.len().min(bytes.len());
+    bytes[..len].copy_from_slice(&tag[..len]);
+    let low = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let high = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let _ = libtock::syscalls::command(LOW_LEVEL_DEBUG_DRIVER, PRINT_TAG,
+                                       low as usize, high as usize);
+}
+
 fn main() {
     use libtock::timer::Duration;
 
@@ -30,6 +42,9 @@ fn main() {
     // LowLevelDebug: App 0x0 prints 0x456 0x789
     libtock::debug::low_level_print2(0x456, 0x789);
 
+    // LowLevelDebug: App 0x0 tag START
+    low_level_print_tag(b"START");
+
     // Print a series of messages quickly to overfill the queue and demonstrate
     // the message drop behavior.
     for _ in 0..10 {
@@ -0,0 +1,34 @@
+# Copyright 2021 lowRISC contributors.
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     https://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+#
+# SPDX-License-Identifier: Apache-2.0
+
+
+C_APPS += nvcounter_chaos
+
+CHAOS_CYCLES ?= 50
+
+# Power-cycles the board CHAOS_CYCLES times at random points while
+# nvcounter_chaos increments the counter, and checks that the counter never
+# goes backwards. This is slow, so it is not part of devicetests.
+.PHONY: userspace/nvcounter_chaos/chaos
+userspace/nvcounter_chaos/chaos: \
+		build/cargo-host/release/runner \
+		build/userspace/nvcounter_chaos/$(TANGO_BOARD_FOR_TEST)/full_image
+	flock build/device_lock -c ' \
+		$(TANGO_SPIFLASH) --verbose \
+			--input=build/userspace/nvcounter_chaos/$(TANGO_BOARD_FOR_TEST)/full_image ; \
+		stty -F /dev/ttyUltraConsole3 115200 -echo ; \
+		stty -F /dev/ttyUltraTarget2 115200 -icrnl ; \
+		build/cargo-host/release/runner --chaos $(CHAOS_CYCLES)'
@@ -0,0 +1,73 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+// Power-loss test for the nonvolatile counter. It increments the counter as
+// fast as it can while `runner --chaos` power-cycles the board at random
+// points, and reports what it sees so that the runner can check that the
+// counter never goes backwards across a power loss.
+//
+// Output lines (parsed by the runner):
+//   nvcounter_chaos: boot <value>       First value read after this boot.
+//   nvcounter_chaos: committed <value>  Every REPORT_INTERVAL successful
+//                                       increments, the counter's new value.
+//   FAILED: ...                         A violation seen within this boot.
+
+#include "nvcounter_syscalls.h"
+#include "tock.h"
+
+#include <stdbool.h>
+#include <stdio.h>
+
+// Reporting every increment would keep the app waiting on the console most of
+// the time, making power loss during a flash operation unlikely.
+#define REPORT_INTERVAL 16
+
+int main(void) {
+  printf("= NvCounter chaos test =\n");
+  if (tock_nvcounter_check() != TOCK_SUCCESS) {
+    printf("FAILED: no Nonvolatile Counter syscall driver installed.\n");
+    printf("TEST_FINISHED: FAIL\n");
+    return 0;
+  }
+
+  bool booted = false;
+  unsigned int last = 0;
+  unsigned int increments = 0;
+  for (;;) {
+    unsigned int value = 0;
+    int rval = tock_nvcounter_increment(&value);
+    if (rval != TOCK_SUCCESS) {
+      // The increment may or may not have been committed, so the next value
+      // may skip one; the checks below allow for that.
+      printf("nvcounter_chaos: increment failed: %s (%i)\n", tock_strerror(rval), rval);
+      continue;
+    }
+
+    if (!booted) {
+      booted = true;
+      printf("nvcounter_chaos: boot %u\n", value - 1);
+    } else if (value <= last) {
+      printf("FAILED: counter went from %u to %u\n", last, value);
+    }
+    last = value;
+
+    increments++;
+    if (increments % REPORT_INTERVAL == 0) {
+      printf("nvcounter_chaos: committed %u\n", value);
+    }
+  }
+}
@@ -278,3 +278,125 @@ fn test_capsule() -> bool {
 
     true
 }
+
+#[test]
+fn test_capsule_recovery() -> bool {
+    use crate::fake_flash::{ErrorTime,FakeFlash,Fault,Operation};
+    use h1::hil::flash::flash::{Client,Flash};
+    use h1::nvcounter::{FlashCounter,NvCounter};
+    use h1::nvcounter::internal::{COUNTS_PER_PAGE,Page,WORDS_PER_PAGE};
+    use Operation::{Erase,Write};
+    use ReturnCode::{FAIL,SUCCESS,SuccessWithValue};
+    use test::{require,require_eq};
+
+    const HIGH_PAGE_START: usize = Page::High as usize * WORDS_PER_PAGE;
+    const LOW_PAGE_START: usize = Page::Low as usize * WORDS_PER_PAGE;
+
+    // Setup. Each FlashCounter represents one boot of the device.
+    let mut first_boot_buffer = [0];
+    let mut second_boot_buffer = [0];
+    let flash = FakeFlash::new();
+    let client = MockClient::new();
+    let nvcounter = FlashCounter::new(&mut first_boot_buffer, &flash);
+    nvcounter.set_client(&client);
+    // Max out the low page so the next increment rolls over.
+    let mut buffer = [0];
+    flash.write(LOW_PAGE_START + WORDS_PER_PAGE - 1, &mut buffer);
+    flash.take_log();
+
+    // Roll over, losing power partway through the low page erase (Rollover2).
+    flash.inject(Some(Fault::PowerLossDuringErase { nth: 0, words: 100 }));
+    require_eq!("rollover", nvcounter.read_and_increment(),
+                SuccessWithValue { value: COUNTS_PER_PAGE as usize });
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, SUCCESS);
+    require!(client.take_last() == IncrementDone(SUCCESS));
+    require!(!flash.is_powered());
+    require!(flash.take_log().operations() ==
+             [Write(HIGH_PAGE_START, 0x3CFFFFFF), Erase(Page::Low as usize)]);
+
+    // Reboot. The committed increment must not be lost: the counter redoes
+    // Rollover2 and Rollover3 before incrementing.
+    flash.power_cycle();
+    let nvcounter = FlashCounter::new(&mut second_boot_buffer, &flash);
+    nvcounter.set_client(&client);
+    require_eq!("post-power-loss", nvcounter.read_and_increment(),
+                SuccessWithValue { value: COUNTS_PER_PAGE as usize + 1 });
+    nvcounter.erase_done(SUCCESS);
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, SUCCESS);
+    require!(client.take_last() == Uncalled);
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, SUCCESS);
+    require!(client.take_last() == IncrementDone(SUCCESS));
+    require!(flash.take_log().operations() ==
+             [Erase(Page::Low as usize), Write(HIGH_PAGE_START, 0x00FFFFFF),
+              Write(LOW_PAGE_START, 0x3CFFFFFF)]);
+
+    // Roll over again, failing the background high page write (Rollover3).
+    // The increment was already reported, so the next one must redo
+    // Rollover3.
+    let mut buffer = [0];
+    flash.write(LOW_PAGE_START + WORDS_PER_PAGE - 1, &mut buffer);
+    flash.take_log();
+    flash.inject(Some(Fault::FailWrite { nth: 1, time: ErrorTime::Callback, code: FAIL }));
+    require_eq!("rollover with Rollover3 failure", nvcounter.read_and_increment(),
+                SuccessWithValue { value: 2 * COUNTS_PER_PAGE as usize + 1 });
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, SUCCESS);
+    require!(client.take_last() == IncrementDone(SUCCESS));
+    nvcounter.erase_done(SUCCESS);
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, FAIL);
+    require!(client.take_last() == Uncalled);
+    flash.inject(None);
+    require_eq!("post-Rollover3 failure", nvcounter.read_and_increment(),
+                SuccessWithValue { value: 2 * COUNTS_PER_PAGE as usize + 2 });
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, SUCCESS);
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, SUCCESS);
+    require!(client.take_last() == IncrementDone(SUCCESS));
+    require!(flash.take_log().operations() ==
+             [Write(HIGH_PAGE_START, 0x003CFFFF), Erase(Page::Low as usize),
+              Write(HIGH_PAGE_START, 0x0000FFFF), Write(HIGH_PAGE_START, 0x0000FFFF),
+              Write(LOW_PAGE_START, 0x3CFFFFFF)]);
+
+    // A bit flip away from the current position may only move the counter
+    // forward.
+    flash.inject(Some(Fault::BitFlip { offset: LOW_PAGE_START + 300, mask: 0xC0000000 }));
+    require_eq!("bit flip", nvcounter.read_and_increment(),
+                SuccessWithValue { value: 2 * COUNTS_PER_PAGE as usize + 2 + 300 * 8 + 1 });
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, SUCCESS);
+    require!(client.take_last() == IncrementDone(SUCCESS));
+    require!(flash.take_log().operations() == [Write(LOW_PAGE_START + 300, 0x00FFFFFF)]);
+    flash.inject(None);
+
+    // Request initialization while Rollover3 runs in the background. The
+    // initialization starts once the write completes.
+    let mut buffer = [0];
+    flash.write(LOW_PAGE_START + WORDS_PER_PAGE - 1, &mut buffer);
+    flash.take_log();
+    require!(nvcounter.read_and_increment() != FAIL);
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, SUCCESS);
+    require!(client.take_last() == IncrementDone(SUCCESS));
+    nvcounter.erase_done(SUCCESS);
+    flash.set_busy(true);
+    require!(nvcounter.initialize() == SUCCESS);
+    flash.set_busy(false);
+    let mut buffer = [0];
+    nvcounter.write_done(&mut buffer, SUCCESS);
+    require!(client.take_last() == Uncalled);
+    nvcounter.erase_done(SUCCESS);
+    require!(client.take_last() == Uncalled);
+    nvcounter.erase_done(SUCCESS);
+    require!(client.take_last() == InitializeDone(SUCCESS));
+    require!(flash.take_log().operations() ==
+             [Write(HIGH_PAGE_START, 0x00003CFF), Erase(Page::Low as usize),
+              Write(HIGH_PAGE_START, 0x000000FF), Erase(Page::Low as usize),
+              Erase(Page::High as usize)]);
+
+    true
+}
@@ -17,6 +17,10 @@
 /// implements the Flash HIL (rather than the Hardware trait), only supports the
 /// NvCounter pages, and uses run-length encoding so it can support the
 /// NvCounter's write patterns using a reasonable amount of stack space.
+///
+/// Besides failing every operation (configure_error), FakeFlash can run a
+/// scripted Fault (inject) and records the erases and writes it was asked to
+/// start, so tests can check the exact sequence (take_log).
 
 pub struct FakeFlash<'c> {
     buffer: core::cell::Cell<Option<&'c mut [u32]>>,
@@ -24,6 +28,13 @@ pub struct FakeFlash<'c> {
     high_page: FakePage,
     low_page: FakePage,
     error_time: core::cell::Cell<Option<ErrorTime>>,
+    fault: core::cell::Cell<Option<Fault>>,
+    // Number of erases and writes started since the fault was injected.
+    erase_count: core::cell::Cell<usize>,
+    write_count: core::cell::Cell<usize>,
+    // False after a simulated power loss, until power_cycle is called.
+    powered: core::cell::Cell<bool>,
+    log: core::cell::Cell<OperationLog>,
 }
 
 impl<'c> FakeFlash<'c> {
@@ -34,6 +45,11 @@ impl<'c> FakeFlash<'c> {
             high_page: FakePage::new(),
             low_page: FakePage::new(),
             error_time: Default::default(),
+            fault: Default::default(),
+            erase_count: Default::default(),
+            write_count: Default::default(),
+            powered: core::cell::Cell::new(true),
+            log: Default::default(),
         }
     }
 
@@ -41,6 +57,32 @@ impl<'c> FakeFlash<'c> {
         self.error_time.set(error_config);
     }
 
+    // Arms `fault` (or disarms faults with None) and restarts the counts its
+    // `nth` fields refer to.
+    pub fn inject(&self, fault: Option<Fault>) {
+        self.fault.set(fault);
+        self.erase_count.set(0);
+        self.write_count.set(0);
+    }
+
+    // Restores power after a PowerLossDuringErase fault, as a reboot would.
+    // The flash contents are kept; the fault and any buffer are dropped.
+    pub fn power_cycle(&self) {
+        self.powered.set(true);
+        self.busy.set(false);
+        self.buffer.set(None);
+        self.inject(None);
+    }
+
+    pub fn is_powered(&self) -> bool {
+        self.powered.get()
+    }
+
+    // Returns the operations started since the last call and clears the log.
+    pub fn take_log(&self) -> OperationLog {
+        self.log.take()
+    }
+
     pub fn retrieve_buffer(&self) -> Option<&'c mut [u32]> {
         self.buffer.take()
     }
@@ -53,10 +95,32 @@ impl<'c> FakeFlash<'c> {
 
 impl<'c> h1::hil::flash::Flash<'c> for FakeFlash<'c> {
     fn erase(&self, page: usize) -> ReturnCode {
+        if !self.powered.get() { return ReturnCode::FAIL; }
         if let Some(error_time) = self.error_time.get() {
             return start_return_code(error_time);
         }
         if self.busy.get() { return ReturnCode::EBUSY; }
+        self.record(Operation::Erase(page));
+        let nth = self.erase_count.get();
+        self.erase_count.set(nth + 1);
+        match self.fault.get() {
+            Some(Fault::FailErase { nth: fault_nth, time, code }) if fault_nth == nth => {
+                return match time {
+                    ErrorTime::Fast => code,
+                    ErrorTime::Callback => ReturnCode::SUCCESS,
+                };
+            },
+            Some(Fault::PowerLossDuringErase { nth: fault_nth, words }) if fault_nth == nth => {
+                match page {
+                    254 => self.high_page.erase_prefix(words),
+                    255 => self.low_page.erase_prefix(words),
+                    _ => return ReturnCode::FAIL,
+                }
+                self.powered.set(false);
+                return ReturnCode::SUCCESS;
+            },
+            _ => {},
+        }
         match page {
             254 => self.high_page.erase(),
             255 => self.low_page.erase(),
@@ -68,18 +132,23 @@ impl<'c> h1::hil::flash::Flash<'c> for FakeFlash<'c> {
         // We ignore error_time here because Flash::read() only fails if offset
         // is out of range. This makes it easier for tests to simulate write()
         // errors realistically.
+        let flip = match self.fault.get() {
+            Some(Fault::BitFlip { offset: flip_offset, mask }) if flip_offset == offset => mask,
+            _ => 0,
+        };
         match offset_to_page(offset) {
             None => ReturnCode::ESIZE,
             Some(Page::High) => ReturnCode::SuccessWithValue {
-                value: self.high_page.read(offset - HIGH_PAGE_START) as usize,
+                value: (self.high_page.read(offset - HIGH_PAGE_START) ^ flip) as usize,
             },
             Some(Page::Low) => ReturnCode::SuccessWithValue {
-                value: self.low_page.read(offset - LOW_PAGE_START) as usize,
+                value: (self.low_page.read(offset - LOW_PAGE_START) ^ flip) as usize,
             },
         }
     }
 
     fn write(&self, target: usize, data: &'c mut [u32]) -> (ReturnCode, Option<&'c mut [u32]>) {
+        if !self.powered.get() { return (ReturnCode::FAIL, Some(data)); }
         if let Some(error_time) = self.error_time.get() {
             return match error_time {
                 ErrorTime::Fast => (kernel::ReturnCode::FAIL, Some(data)),
@@ -90,6 +159,20 @@ impl<'c> h1::hil::flash::Flash<'c> for FakeFlash<'c> {
             };
         }
         if self.busy.get() { return (ReturnCode::EBUSY, Some(data)); }
+        self.record(Operation::Write(target, data.first().copied().unwrap_or(0xFFFFFFFF)));
+        let nth = self.write_count.get();
+        self.write_count.set(nth + 1);
+        if let Some(Fault::FailWrite { nth: fault_nth, time, code }) = self.fault.get() {
+            if fault_nth == nth {
+                return match time {
+                    ErrorTime::Fast => (code, Some(data)),
+                    ErrorTime::Callback => {
+                        self.buffer.set(Some(data));
+                        (ReturnCode::SUCCESS, None)
+                    },
+                };
+            }
+        }
         // Note: this will fail if the write crosses pages, which is fine for
         // this use case. That may be true of the real flash anyway.
         match offset_to_page(target) {
@@ -105,6 +188,14 @@ impl<'c> h1::hil::flash::Flash<'c> for FakeFlash<'c> {
     fn set_client(&self, _client: &'c dyn h1::hil::flash::Client<'c>) {}
 }
 
+impl<'c> FakeFlash<'c> {
+    fn record(&self, operation: Operation) {
+        let mut log = self.log.get();
+        log.push(operation);
+        self.log.set(log);
+    }
+}
+
 #[test]
 fn test_fake_flash() -> bool {
     use h1::hil::flash::Flash;
@@ -142,6 +233,63 @@ fn test_fake_flash() -> bool {
     true
 }
 
+#[test]
+fn test_fault_injection() -> bool {
+    use h1::hil::flash::Flash;
+    use kernel::ReturnCode::{ESIZE,FAIL,SUCCESS,SuccessWithValue};
+    let flash = FakeFlash::new();
+
+    // Fail only the second write, with a structured error code.
+    flash.inject(Some(Fault::FailWrite { nth: 1, time: ErrorTime::Fast, code: ESIZE }));
+    let mut buffer = [0x3CFFFFFF];
+    require!(flash.write(HIGH_PAGE_START, &mut buffer) == (SUCCESS, None));
+    let mut buffer = [0x00FFFFFF];
+    require!(flash.write(HIGH_PAGE_START, &mut buffer).0 == ESIZE);
+    require!(flash.read(HIGH_PAGE_START) == SuccessWithValue { value: 0x3CFFFFFF });
+    let mut buffer = [0x00FFFFFF];
+    require!(flash.write(HIGH_PAGE_START, &mut buffer) == (SUCCESS, None));
+    require!(flash.read(HIGH_PAGE_START) == SuccessWithValue { value: 0x00FFFFFF });
+
+    // Every operation that was started is logged, including the failed one.
+    let log = flash.take_log();
+    require!(log.operations() == [Operation::Write(HIGH_PAGE_START, 0x3CFFFFFF),
+                                  Operation::Write(HIGH_PAGE_START, 0x00FFFFFF),
+                                  Operation::Write(HIGH_PAGE_START, 0x00FFFFFF)]);
+    require!(!log.overflowed());
+    require!(flash.take_log().operations().is_empty());
+
+    // Fail the first erase asynchronously: the contents must be untouched.
+    flash.inject(Some(Fault::FailErase { nth: 0, time: ErrorTime::Callback, code: FAIL }));
+    require!(flash.erase(254) == SUCCESS);
+    require!(flash.read(HIGH_PAGE_START) == SuccessWithValue { value: 0x00FFFFFF });
+
+    // Bit flips only affect reads of the given word.
+    flash.inject(Some(Fault::BitFlip { offset: HIGH_PAGE_START + 1, mask: 0x00800000 }));
+    require!(flash.read(HIGH_PAGE_START + 1) == SuccessWithValue { value: 0xFF7FFFFF });
+    require!(flash.read(HIGH_PAGE_START + 2) == SuccessWithValue { value: 0xFFFFFFFF });
+    flash.inject(None);
+    require!(flash.read(HIGH_PAGE_START + 1) == SuccessWithValue { value: 0xFFFFFFFF });
+
+    // Lose power halfway through an erase.
+    let mut buffer = [0, 0, 0, 0];
+    require!(flash.write(LOW_PAGE_START + 254, &mut buffer) == (SUCCESS, None));
+    flash.inject(Some(Fault::PowerLossDuringErase { nth: 0, words: 256 }));
+    require!(flash.erase(255) == SUCCESS);
+    require!(!flash.is_powered());
+    require!(flash.read(LOW_PAGE_START + 255) == SuccessWithValue { value: 0xFFFFFFFF });
+    require!(flash.read(LOW_PAGE_START + 256) == SuccessWithValue { value: 0 });
+    require!(flash.read(LOW_PAGE_START + 257) == SuccessWithValue { value: 0 });
+    require!(flash.erase(254) == FAIL);
+    let mut buffer = [0];
+    require!(flash.write(LOW_PAGE_START, &mut buffer).0 == FAIL);
+    flash.power_cycle();
+    require!(flash.is_powered());
+    require!(flash.erase(255) == SUCCESS);
+    require!(flash.read(LOW_PAGE_START + 256) == SuccessWithValue { value: 0xFFFFFFFF });
+
+    true
+}
+
 // -----------------------------------------------------------------------------
 // Implementation details below
 // -----------------------------------------------------------------------------
@@ -188,6 +336,71 @@ pub enum ErrorTime {
     Callback,  // Writes and erases fail asynchronously.
 }
 
+/// A scripted fault. `nth` counts erases or writes from the `inject` call,
+/// starting at 0.
+#[derive(Clone,Copy,PartialEq)]
+pub enum Fault {
+    // The nth write fails with `code`. With ErrorTime::Callback, it starts
+    // successfully without changing flash; the test then calls write_done.
+    FailWrite { nth: usize, time: ErrorTime, code: ReturnCode },
+    // The nth erase fails, as above.
+    FailErase { nth: usize, time: ErrorTime, code: ReturnCode },
+    // Power is lost during the nth erase after the first `words` words of the
+    // page were erased. Every later operation fails until power_cycle.
+    PowerLossDuringErase { nth: usize, words: usize },
+    // Reads of the word at `offset` return its value XOR `mask`.
+    BitFlip { offset: usize, mask: u32 },
+}
+
+/// An erase (of a page number) or write (of the first word to an offset)
+/// started by FakeFlash.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Operation {
+    Erase(usize),
+    Write(usize, u32),
+}
+
+const LOG_LEN: usize = 8;
+
+/// The operations started since the log was last taken. Keeps the first
+/// LOG_LEN operations.
+#[derive(Clone,Copy)]
+pub struct OperationLog {
+    operations: [Operation; LOG_LEN],
+    len: usize,
+    overflowed: bool,
+}
+
+impl Default for OperationLog {
+    fn default() -> OperationLog {
+        OperationLog {
+            operations: [Operation::Erase(0); LOG_LEN],
+            len: 0,
+            overflowed: false,
+        }
+    }
+}
+
+impl OperationLog {
+    fn push(&mut self, operation: Operation) {
+        if self.len == LOG_LEN {
+            self.overflowed = true;
+            return;
+        }
+        self.operations[self.len] = operation;
+        self.len += 1;
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations[..self.len]
+    }
+
+    // True if operations were dropped because the log was full.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
 // Returns the return code for attempting to start an action.
 fn start_return_code(error_time: ErrorTime) -> kernel::ReturnCode {
     match error_time {
@@ -233,20 +446,29 @@ impl FakePage {
     }
 
     fn write(&self, offset: usize, data: &[u32]) {
+        self.rewrite(|i, value| {
+            if i >= offset && i < offset + data.len() { data[i - offset] } else { value }
+        });
+    }
+
+    // Erases the first `words` words of this page, as an interrupted erase
+    // might.
+    fn erase_prefix(&self, words: usize) {
+        self.rewrite(|i, value| if i < words { 0xFFFFFFFF } else { value });
+    }
+
+    // Replaces the value of every word i with new_value(i, old value).
+    fn rewrite<F: Fn(usize, u32) -> u32>(&self, new_value: F) {
         let mut cur_run = 0;
         let mut start = 0;
         let mut builder = RleBuilder::new();
         for i in 0..WORDS_PER_PAGE {
-            if i >= offset && i < offset + data.len() {
-                builder.append(data[i - offset]);
-                continue;
-            }
             // Advance the run until we see a run containing index i.
             while start + self.lens.get()[cur_run] as usize <= i {
                 start += self.lens.get()[cur_run] as usize;
                 cur_run += 1;
             }
-            builder.append(self.values.get()[cur_run]);
+            builder.append(new_value(i, self.values.get()[cur_run]));
         }
         let (lens, values) = builder.build();
         self.lens.set(lens);
@@ -0,0 +1,250 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use core::cell::Cell;
+
+use crate::syscall_error;
+
+use errorcode::ErrorCode;
+
+use libtock::result::TockError;
+use libtock::result::TockResult;
+use libtock::syscalls;
+use libtock::syscalls::raw::yieldk;
+
+// The length of an AES-128 key, in bytes.
+pub const KEY_LEN: usize = 16;
+
+// The length of a GCM IV, in bytes.
+pub const IV_LEN: usize = 12;
+
+// The length of a GCM tag, in bytes.
+pub const TAG_LEN: usize = 16;
+
+pub trait AesGcm {
+    // Encrypt `data` in place with AES-128-GCM and return the tag over `aad`
+    // and the ciphertext. Blocks (yieldk) until done.
+    fn seal(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8])
+            -> TockResult<[u8; TAG_LEN]>;
+
+    // Decrypt `data` in place with AES-128-GCM and check `tag`. Returns false
+    // if the tag does not match, in which case `data` must be discarded.
+    // Blocks (yieldk) until done.
+    fn open(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8],
+            tag: &[u8; TAG_LEN]) -> TockResult<bool>;
+}
+
+// Get the static AesGcm object.
+pub fn get() -> &'static dyn AesGcm {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40010;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const BEGIN_SESSION: usize = 8;
+    pub const UPDATE_SESSION: usize = 9;
+    pub const END_SESSION: usize = 10;
+    pub const GCM_ADD_AAD: usize = 11;
+    pub const GCM_FINISH: usize = 12;
+}
+
+mod subscribe_nr {
+    pub const CRYPT_DONE: usize = 0;
+}
+
+mod allow_nr {
+    pub const KEY: usize = 0;
+    pub const INPUT_BUFFER: usize = 1;
+    pub const OUTPUT_BUFFER: usize = 2;
+    pub const IV: usize = 3;
+}
+
+mod session_mode {
+    pub const GCM_ENCRYPT: usize = 3;
+    pub const GCM_DECRYPT: usize = 4;
+}
+
+// The driver works on whole blocks.
+const BLOCK_LEN: usize = 16;
+
+// Data is copied into these buffers before it is shared with the kernel, as
+// allow needs a mutable buffer. DATA_BUFFER_LENGTH must be a multiple of
+// BLOCK_LEN.
+const DATA_BUFFER_LENGTH: usize = 128;
+
+static mut KEY_BUFFER: [u8; KEY_LEN] = [0; KEY_LEN];
+static mut IV_BUFFER: [u8; BLOCK_LEN] = [0; BLOCK_LEN];
+static mut BLOCK_BUFFER: [u8; BLOCK_LEN] = [0; BLOCK_LEN];
+static mut DATA_BUFFER: [u8; DATA_BUFFER_LENGTH] = [0; DATA_BUFFER_LENGTH];
+
+struct AesGcmImpl {
+    // Whether the last update is complete.
+    update_done: Cell<bool>,
+
+    // The return code of the last update.
+    update_result: Cell<isize>,
+}
+
+static mut AES_GCM: AesGcmImpl = AesGcmImpl {
+    update_done: Cell::new(false),
+    update_result: Cell::new(0),
+};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static AesGcmImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if AES_GCM.initialize().is_err() {
+                panic!("Could not initialize AesGcm");
+            }
+            IS_INITIALIZED = true;
+        }
+        &AES_GCM
+    }
+}
+
+impl AesGcmImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        syscalls::subscribe_fn(
+            DRIVER_NUMBER,
+            subscribe_nr::CRYPT_DONE,
+            AesGcmImpl::crypt_done_trampoline,
+            0)?;
+
+        Ok(())
+    }
+
+    extern "C"
+    fn crypt_done_trampoline(rcode: usize, _processed: usize, _arg3: usize, _data: usize) {
+        let aes_gcm = get_impl();
+        aes_gcm.update_result.set(rcode as isize);
+        aes_gcm.update_done.set(true);
+    }
+
+    // Run a GCM session over `aad` and `data`, which is processed in place.
+    // Returns the tag when encrypting. When decrypting, returns whether
+    // `tag` matched. Ends the session on failure, which wipes its state.
+    fn run(&self, mode: usize, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8],
+           data: &mut [u8], tag: &mut [u8; TAG_LEN]) -> TockResult<bool> {
+        let result = unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing KEY_BUFFER as &mut.
+            KEY_BUFFER.copy_from_slice(key);
+            let result = self.run_session(mode, iv, aad, data, tag);
+            ecc::wipe(&mut KEY_BUFFER);
+            ecc::wipe(&mut DATA_BUFFER);
+            result
+        };
+        if result.is_err() {
+            let _ = syscalls::command(DRIVER_NUMBER, command_nr::END_SESSION, 0, 0);
+        }
+        result
+    }
+
+    fn run_session(&self, mode: usize, iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8],
+                   tag: &mut [u8; TAG_LEN]) -> TockResult<bool> {
+        // The key is loaded again for every block, so it stays shared until
+        // the session has ended.
+        let _key_share = unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing KEY_BUFFER as &mut.
+            syscalls::allow(DRIVER_NUMBER, allow_nr::KEY, &mut KEY_BUFFER)?
+        };
+
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing IV_BUFFER as &mut.
+            IV_BUFFER[..IV_LEN].copy_from_slice(iv);
+            // We want this to go out of scope after executing the command
+            let _iv_share = syscalls::allow(DRIVER_NUMBER, allow_nr::IV, &mut IV_BUFFER)?;
+            syscalls::command(DRIVER_NUMBER, command_nr::BEGIN_SESSION, mode, 0)?;
+        }
+
+        for chunk in aad.chunks(BLOCK_LEN) {
+            unsafe {
+                // TODO(osk): We need the unsafe block since we're accessing BLOCK_BUFFER as &mut.
+                BLOCK_BUFFER[..chunk.len()].copy_from_slice(chunk);
+                // We want this to go out of scope after executing the command
+                let _input_share = syscalls::allow(DRIVER_NUMBER, allow_nr::INPUT_BUFFER,
+                    &mut BLOCK_BUFFER)?;
+                syscalls::command(DRIVER_NUMBER, command_nr::GCM_ADD_AAD, chunk.len(), 0)?;
+            }
+        }
+
+        for chunk in data.chunks_mut(DATA_BUFFER_LENGTH) {
+            unsafe {
+                // TODO(osk): We need the unsafe block since we're accessing DATA_BUFFER as &mut.
+                let blocks_len = (chunk.len() + BLOCK_LEN - 1) / BLOCK_LEN * BLOCK_LEN;
+                DATA_BUFFER[..chunk.len()].copy_from_slice(chunk);
+                {
+                    // We want this to go out of scope after the update completes
+                    let _input_share = syscalls::allow(DRIVER_NUMBER, allow_nr::INPUT_BUFFER,
+                        &mut DATA_BUFFER[..blocks_len])?;
+                    self.update_done.set(false);
+                    syscalls::command(DRIVER_NUMBER, command_nr::UPDATE_SESSION, chunk.len(), 0)?;
+                    while !self.update_done.get() { yieldk(); }
+                }
+                if self.update_result.get() != 0 {
+                    return Err(TockError::Format);
+                }
+                chunk.copy_from_slice(&DATA_BUFFER[..chunk.len()]);
+            }
+        }
+
+        if mode == session_mode::GCM_ENCRYPT {
+            // We want this to go out of scope after executing the command
+            let _output_share = syscalls::allow(DRIVER_NUMBER, allow_nr::OUTPUT_BUFFER, tag)?;
+            syscalls::command(DRIVER_NUMBER, command_nr::GCM_FINISH, 0, 0)?;
+            return Ok(true);
+        }
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing BLOCK_BUFFER as &mut.
+            BLOCK_BUFFER.copy_from_slice(tag);
+            // We want this to go out of scope after executing the command
+            let _input_share = syscalls::allow(DRIVER_NUMBER, allow_nr::INPUT_BUFFER,
+                &mut BLOCK_BUFFER)?;
+            match syscalls::command(DRIVER_NUMBER, command_nr::GCM_FINISH, TAG_LEN, 0) {
+                Ok(_) => Ok(true),
+                Err(err) => {
+                    let err = TockError::from(err);
+                    if syscall_error::error_code(&err) == Some(ErrorCode::Fail) {
+                        // The session has ended, so there is nothing to clean up.
+                        Ok(false)
+                    } else {
+                        Err(err)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl AesGcm for AesGcmImpl {
+    fn seal(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8])
+            -> TockResult<[u8; TAG_LEN]> {
+        let mut tag = [0u8; TAG_LEN];
+        self.run(session_mode::GCM_ENCRYPT, key, iv, aad, data, &mut tag)?;
+        Ok(tag)
+    }
+
+    fn open(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8],
+            tag: &[u8; TAG_LEN]) -> TockResult<bool> {
+        let mut expected = *tag;
+        self.run(session_mode::GCM_DECRYPT, key, iv, aad, data, &mut expected)
+    }
+}
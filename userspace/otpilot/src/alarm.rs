@@ -23,6 +23,9 @@ pub trait Alarm {
     // Get clock frequency in Hz.
     fn get_clock_frequency(&self) -> usize;
 
+    // Get the current clock value in ticks.
+    fn now(&self) -> usize;
+
     // Set alarm to occur after `ticks`.
     fn set(&self, ticks: usize) -> TockResult<()>;
 
@@ -43,6 +46,7 @@ const DRIVER_NUMBER: usize = 0x00000;
 mod command_nr {
     pub const CHECK_IF_PRESENT: usize = 0;
     pub const GET_CLOCK_FREQUENCY: usize = 1;
+    pub const GET_NOW: usize = 2;
     pub const STOP_ALARM: usize = 3;
     pub const SET_RELATIVE_ALARM: usize = 5;
 }
@@ -117,6 +121,10 @@ impl Alarm for AlarmImpl {
         self.clock_frequency
     }
 
+    fn now(&self) -> usize {
+        syscalls::command(DRIVER_NUMBER, command_nr::GET_NOW, 0, 0).unwrap_or(0)
+    }
+
     fn set(&self, ticks: usize) -> TockResult<()> {
         self.alarm_expired.set(false);
         self.alarm_id.set(None);
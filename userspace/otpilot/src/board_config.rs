@@ -0,0 +1,152 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use core::cell::Cell;
+
+use libtock::result::TockError;
+use libtock::result::TockResult;
+use libtock::syscalls;
+use libtock::syscalls::raw::yieldk;
+
+use spiutils::protocol::config::ConfigKey;
+use spiutils::protocol::wire::WireEnum;
+
+pub trait BoardConfig {
+    // Get the staged value of `key`.
+    fn get(&self, key: ConfigKey) -> TockResult<u32>;
+
+    // Get the value of `key` the board booted with.
+    fn get_active(&self, key: ConfigKey) -> TockResult<u32>;
+
+    // Stage a new value for `key`. Fails if the value is out of range.
+    fn set(&self, key: ConfigKey, value: u32) -> TockResult<()>;
+
+    // Write the staged configuration to flash and wait (yieldk) until done.
+    // The new values take effect on the next boot.
+    fn commit(&self) -> TockResult<()>;
+
+    // Discard the staged changes.
+    fn rollback(&self) -> TockResult<()>;
+}
+
+// Get the static BoardConfig object.
+pub fn get() -> &'static dyn BoardConfig {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x400c0;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_STAGED: usize = 1;
+    pub const GET_ACTIVE: usize = 2;
+    pub const SET: usize = 3;
+    pub const COMMIT: usize = 4;
+    pub const ROLLBACK: usize = 5;
+}
+
+mod subscribe_nr {
+    pub const COMMIT_DONE: usize = 0;
+}
+
+struct BoardConfigImpl {
+    // The result of the last commit.
+    commit_result: Cell<isize>,
+
+    // Whether the last commit is complete.
+    commit_done: Cell<bool>,
+}
+
+static mut BOARD_CONFIG: BoardConfigImpl = BoardConfigImpl {
+    commit_result: Cell::new(-1),
+    commit_done: Cell::new(false),
+};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static BoardConfigImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if BOARD_CONFIG.initialize().is_err() {
+                panic!("Could not initialize BoardConfig");
+            }
+            IS_INITIALIZED = true;
+        }
+        &BOARD_CONFIG
+    }
+}
+
+impl BoardConfigImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        syscalls::subscribe_fn(
+            DRIVER_NUMBER,
+            subscribe_nr::COMMIT_DONE,
+            BoardConfigImpl::commit_done_trampoline,
+            0)?;
+
+        Ok(())
+    }
+
+    extern "C"
+    fn commit_done_trampoline(arg1: usize, arg2: usize, arg3: usize, _data: usize) {
+        get_impl().commit_done(arg1, arg2, arg3);
+    }
+
+    fn commit_done(&self, result: usize, _: usize, _: usize) {
+        self.commit_result.set(result as isize);
+        self.commit_done.set(true);
+    }
+}
+
+impl BoardConfig for BoardConfigImpl {
+    fn get(&self, key: ConfigKey) -> TockResult<u32> {
+        let value = syscalls::command(DRIVER_NUMBER, command_nr::GET_STAGED,
+            key.to_wire_value() as usize, 0)?;
+        Ok(value as u32)
+    }
+
+    fn get_active(&self, key: ConfigKey) -> TockResult<u32> {
+        let value = syscalls::command(DRIVER_NUMBER, command_nr::GET_ACTIVE,
+            key.to_wire_value() as usize, 0)?;
+        Ok(value as u32)
+    }
+
+    fn set(&self, key: ConfigKey, value: u32) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET,
+            key.to_wire_value() as usize, value as usize)?;
+        Ok(())
+    }
+
+    fn commit(&self) -> TockResult<()> {
+        self.commit_result.set(-1);
+        self.commit_done.set(false);
+        syscalls::command(DRIVER_NUMBER, command_nr::COMMIT, 0, 0)?;
+
+        while !self.commit_done.get() { unsafe { yieldk(); } }
+
+        if self.commit_result.get() != 0 {
+            return Err(TockError::Format);
+        }
+        Ok(())
+    }
+
+    fn rollback(&self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::ROLLBACK, 0, 0)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+pub trait BootAttempts {
+    /// Get the number of failed boots in a row before this one.
+    fn get_failed_boots(&self) -> TockResult<usize>;
+
+    /// Report that startup completed, which clears the failed boot count.
+    /// Fails if this boot was already reported healthy, e.g. by an earlier
+    /// instance of the app.
+    fn mark_healthy(&self) -> TockResult<()>;
+}
+
+// Get the static BootAttempts object.
+pub fn get() -> &'static dyn BootAttempts {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x400f0;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_FAILED_BOOTS: usize = 1;
+    pub const MARK_HEALTHY: usize = 2;
+}
+
+struct BootAttemptsImpl {}
+
+static mut BOOT_ATTEMPTS: BootAttemptsImpl = BootAttemptsImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static BootAttemptsImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if BOOT_ATTEMPTS.initialize().is_err() {
+                panic!("Could not initialize BootAttempts");
+            }
+            IS_INITIALIZED = true;
+        }
+        &BOOT_ATTEMPTS
+    }
+}
+
+impl BootAttemptsImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+impl BootAttempts for BootAttemptsImpl {
+    fn get_failed_boots(&self) -> TockResult<usize> {
+        Ok(syscalls::command(DRIVER_NUMBER, command_nr::GET_FAILED_BOOTS, 0, 0)?)
+    }
+
+    fn mark_healthy(&self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::MARK_HEALTHY, 0, 0)?;
+        Ok(())
+    }
+}
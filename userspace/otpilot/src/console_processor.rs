@@ -14,76 +14,312 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::board_config;
 use crate::console_reader;
+use crate::console_timestamps;
+use crate::console_writer;
+use crate::crypto_stats;
+use crate::fault_stats;
 use crate::firmware_controller;
 use crate::globalsec;
+use crate::gpio_control;
 use crate::gpio_processor::GpioProcessor;
+use crate::irq_stats;
+use crate::line_editor::LineEditor;
+use crate::line_editor::MAX_LINE_LEN;
+use crate::passthrough_guard;
 use crate::reset;
+use crate::stack_usage;
+use crate::usb_debug;
 
+use consoleutils::cobs::FrameReader;
+use consoleutils::protocol;
+use consoleutils::protocol::FirmwareInfo;
+use consoleutils::protocol::Request;
+use consoleutils::protocol::Response;
+use consoleutils::protocol::Version;
+use consoleutils::protocol::BINARY_MODE_SEQUENCE;
+use consoleutils::protocol::MAX_FRAME_LEN;
+use consoleutils::protocol::MAX_WIRE_LEN;
+
+use core::fmt;
+
+use libtock::print;
 use libtock::println;
+use libtock::result::TockError;
 use libtock::result::TockResult;
 
+use spiutils::driver::firmware::SegmentInfo;
+use spiutils::protocol::config::ConfigKey;
+use spiutils::protocol::wire::WireEnum;
+
+/// Echoes edited input back to the console.
+struct ConsoleEcho;
+
+impl fmt::Write for ConsoleEcho {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+        Ok(())
+    }
+}
+
+/// Reads the version of a segment for a binary response.
+fn get_version(segment: SegmentInfo) -> Option<Version> {
+    firmware_controller::get_build_info(segment).ok().map(|build_info| Version {
+        epoch: build_info.epoch,
+        major: build_info.major,
+        minor: build_info.minor,
+        timestamp: build_info.timestamp,
+    })
+}
+
 pub struct ConsoleProcessor<'a> {
     gpio_processor: &'a GpioProcessor,
+    line_editor: LineEditor,
+
+    /// Whether the console carries COBS frames instead of text.
+    binary_mode: bool,
+
+    /// Number of bytes of BINARY_MODE_SEQUENCE received so far.
+    binary_mode_matched: usize,
+
+    frame_reader: FrameReader<MAX_FRAME_LEN>,
 }
 
 impl<'a> ConsoleProcessor<'a> {
     pub fn new(gpio_processor: &'a GpioProcessor) -> ConsoleProcessor<'a> {
         ConsoleProcessor {
             gpio_processor: gpio_processor,
+            line_editor: LineEditor::new(),
+            binary_mode: false,
+            binary_mode_matched: 0,
+            frame_reader: FrameReader::new(),
         }
     }
 
     fn print_help(&self) -> TockResult<()> {
 
-        println!("Available commands:");
+        println!("Available commands (end with Enter, Up/Down recall earlier ones):");
         println!("? : This help screen.");
         println!("1 : Assert BMC_CPU_RST.");
         println!("! : Deassert BMC_CPU_RST.");
         println!("2 : Assert BMC_SRST.");
         println!("@ : Deassert BMC_SRST.");
         println!("i : Read firmware info.");
+        println!("f : Show CPU fault statistics.");
+        println!("s : Show stack high-water marks.");
+        println!("t : Print and clear the GPIO trace.");
+        println!("n : Show interrupt counts per NVIC line.");
+        println!("c : Show crypto engine usage.");
+        println!("p : Show how often the kernel isolated the SPI flash on host reset.");
+        println!("u : Print and clear the USB event trace.");
+        println!("U : Reconnect USB so the host enumerates the device again.");
+        println!("T : Toggle console line timestamps.");
         println!("R : Reset chip.");
 
         Ok(())
     }
 
-    pub fn process_input(&self) -> TockResult<()> {
+    pub fn process_input(&mut self) -> TockResult<()> {
 
-        let data = console_reader::get().get_data();
-        if data.len() < 1 {
-            return Ok(());
+        for &byte in console_reader::get().get_data() {
+            if self.binary_mode {
+                self.process_binary_byte(byte)?;
+                continue;
+            }
+            if self.match_binary_mode_sequence(byte) {
+                // The start of the sequence went to the line editor.
+                self.line_editor.reset();
+                self.binary_mode = true;
+                continue;
+            }
+            if let Some(line) = self.line_editor.process_byte(byte, &mut ConsoleEcho) {
+                // Copy the line out of the editor so that `self` is free
+                // while the command runs.
+                let mut command = [0u8; MAX_LINE_LEN];
+                let len = line.len();
+                command[..len].copy_from_slice(line);
+                self.run_command(&command[..len])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true once the last byte of BINARY_MODE_SEQUENCE is received.
+    fn match_binary_mode_sequence(&mut self, byte: u8) -> bool {
+        if byte == BINARY_MODE_SEQUENCE[self.binary_mode_matched] {
+            self.binary_mode_matched += 1;
+        } else if byte == BINARY_MODE_SEQUENCE[0] {
+            self.binary_mode_matched = 1;
+        } else {
+            self.binary_mode_matched = 0;
+        }
+
+        if self.binary_mode_matched == BINARY_MODE_SEQUENCE.len() {
+            self.binary_mode_matched = 0;
+            return true;
         }
+        false
+    }
+
+    fn process_binary_byte(&mut self, byte: u8) -> TockResult<()> {
+        let request = match self.frame_reader.push(byte) {
+            Some(frame) => protocol::decode::<Request>(frame),
+            None => return Ok(()),
+        };
 
-        match data[0] as char {
-            '?' => self.print_help()?,
-            '1' => {
+        let response = match request {
+            Ok(Request::Reset) => {
+                reset::get().reset()?;
+                return Ok(());
+            },
+            Ok(request) => self.run_request(request),
+            Err(_) => Response::BadRequest,
+        };
+
+        let mut wire = [0u8; MAX_WIRE_LEN];
+        let len = protocol::encode(&response, &mut wire).map_err(|_| TockError::Format)?;
+        console_writer::get().write(&wire[..len])?;
+
+        if request == Ok(Request::TextMode) {
+            self.binary_mode = false;
+        }
+        Ok(())
+    }
+
+    fn run_request(&self, request: Request) -> Response {
+        let result = match request {
+            Request::Ping | Request::Reset | Request::TextMode => Ok(()),
+            Request::SetBmcCpuRst(asserted) => self.gpio_processor.set_bmc_cpu_rst(asserted),
+            Request::SetBmcSrst(asserted) => self.gpio_processor.set_bmc_srst(asserted),
+            Request::GetFirmwareInfo => {
+                return Response::FirmwareInfo(FirmwareInfo {
+                    active_ro: get_version(globalsec::get().get_active_ro()),
+                    active_rw: get_version(globalsec::get().get_active_rw()),
+                    inactive_ro: get_version(globalsec::get().get_inactive_ro()),
+                    inactive_rw: get_version(globalsec::get().get_inactive_rw()),
+                });
+            },
+            Request::GetConfig(key) => {
+                return match ConfigKey::from_wire_value(key) {
+                    None => Response::BadRequest,
+                    Some(key) => match board_config::get().get(key) {
+                        Ok(value) => Response::ConfigValue(value),
+                        Err(_) => Response::Failed,
+                    },
+                };
+            },
+            Request::SetConfig(key, value) => {
+                match ConfigKey::from_wire_value(key) {
+                    None => return Response::BadRequest,
+                    Some(key) => board_config::get().set(key, value),
+                }
+            },
+            Request::CommitConfig => board_config::get().commit(),
+            Request::RollbackConfig => board_config::get().rollback(),
+            Request::SetUsbDebug(mask) => usb_debug::get().set_verbosity(mask),
+            Request::SetConsoleTimestamps(enabled) => console_timestamps::get().set_enabled(enabled),
+        };
+
+        match result {
+            Ok(()) => Response::Ok,
+            Err(_) => Response::Failed,
+        }
+    }
+
+    fn run_command(&self, line: &[u8]) -> TockResult<()> {
+
+        match line {
+            b"" => (),
+            b"?" => self.print_help()?,
+            b"1" => {
                 println!("Asserting BMC_CPU_RST");
                 self.gpio_processor.set_bmc_cpu_rst(true)?;
             },
-            '!' => {
+            b"!" => {
                 println!("Deasserting BMC_CPU_RST");
                 self.gpio_processor.set_bmc_cpu_rst(false)?;
             },
-            '2' => {
+            b"2" => {
                 println!("Asserting BMC_SRST");
                 self.gpio_processor.set_bmc_srst(true)?;
             },
-            '@' => {
+            b"@" => {
                 println!("Deasserting BMC_SRST");
                 self.gpio_processor.set_bmc_srst(false)?;
             },
-            'i' => {
+            b"i" => {
                 println!("active RO: {:?}, {:?}", globalsec::get().get_active_ro(), firmware_controller::get_build_info(globalsec::get().get_active_ro())?);
                 println!("active RW: {:?}, {:?}", globalsec::get().get_active_rw(), firmware_controller::get_build_info(globalsec::get().get_active_rw())?);
                 println!("inactive RO: {:?}, {:?}", globalsec::get().get_inactive_ro(), firmware_controller::get_build_info(globalsec::get().get_inactive_ro())?);
                 println!("inactive RW: {:?}, {:?}", globalsec::get().get_inactive_rw(), firmware_controller::get_build_info(globalsec::get().get_inactive_rw())?);
             },
-            'R' => {
+            b"f" => {
+                for &fault_type in fault_stats::FAULT_TYPES.iter() {
+                    let count = fault_stats::get().get_count(fault_type)?;
+                    match fault_stats::get().get_last(fault_type)? {
+                        Some(frame) => println!("{:?}: {}, last {:x?}", fault_type, count, frame),
+                        None => println!("{:?}: {}", fault_type, count),
+                    }
+                }
+            },
+            b"s" => {
+                let stack_usage = stack_usage::get();
+                println!("kernel: {} of {} bytes used",
+                    stack_usage.get_kernel_used()?, stack_usage.get_kernel_size()?);
+                for index in 0..stack_usage.get_process_count()? {
+                    println!("process {}: {} of {} bytes never used", index,
+                        stack_usage.get_process_unused(index)?, stack_usage.get_process_size(index)?);
+                }
+            },
+            b"t" => gpio_control::get().print_trace(),
+            b"n" => {
+                let irq_stats = irq_stats::get();
+                let window_ms = irq_stats.get_window_ms()?;
+                let mut line = 0;
+                while let Some(active) = irq_stats.next_active(line)? {
+                    println!("NVIC {}: {} total, {} in last {} ms", active,
+                        irq_stats.get_total(active)?, irq_stats.get_last_window(active)?, window_ms);
+                    line = active + 1;
+                }
+            },
+            b"c" => {
+                let crypto_stats = crypto_stats::get();
+                for &engine in crypto_stats::ENGINES.iter() {
+                    println!("{:?}: {} ops, {} bytes, {} us busy", engine,
+                        crypto_stats.get_ops(engine)?, crypto_stats.get_bytes(engine)?,
+                        crypto_stats.get_busy_us(engine)?);
+                }
+            },
+            b"p" => {
+                if passthrough_guard::get().is_present() {
+                    println!("Passthrough guard tripped {} times", passthrough_guard::get().get_trips()?);
+                } else {
+                    println!("No passthrough guard on this board");
+                }
+            },
+            b"u" => {
+                if usb_debug::get().dump_trace().is_err() {
+                    println!("No USB event trace on this board");
+                }
+            },
+            b"U" => {
+                if usb_debug::get().reconnect().is_err() {
+                    println!("No USB on this board");
+                }
+            },
+            b"T" => {
+                let timestamps = console_timestamps::get();
+                let enabled = !timestamps.is_enabled()?;
+                timestamps.set_enabled(enabled)?;
+                println!("Console timestamps {}", if enabled { "on" } else { "off" });
+            },
+            b"R" => {
                 println!("resetting ...");
                 reset::get().reset()?;
             }
-            _ => (),
+            _ => println!("Unknown command. Enter ? for help."),
         }
 
         Ok(())
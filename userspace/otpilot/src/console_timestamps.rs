@@ -0,0 +1,77 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+pub trait ConsoleTimestamps {
+    /// Check whether the kernel prefixes console lines with timestamps.
+    fn is_enabled(&self) -> TockResult<bool>;
+
+    /// Turn the console line timestamps on or off. Fails if the board has no
+    /// timestamp clock.
+    fn set_enabled(&self, enabled: bool) -> TockResult<()>;
+}
+
+// Get the static ConsoleTimestamps object.
+pub fn get() -> &'static dyn ConsoleTimestamps {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40150;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_ENABLED: usize = 1;
+    pub const SET_ENABLED: usize = 2;
+}
+
+struct ConsoleTimestampsImpl {}
+
+static mut CONSOLE_TIMESTAMPS: ConsoleTimestampsImpl = ConsoleTimestampsImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static ConsoleTimestampsImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if CONSOLE_TIMESTAMPS.initialize().is_err() {
+                panic!("Could not initialize ConsoleTimestamps");
+            }
+            IS_INITIALIZED = true;
+        }
+        &CONSOLE_TIMESTAMPS
+    }
+}
+
+impl ConsoleTimestampsImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+impl ConsoleTimestamps for ConsoleTimestampsImpl {
+    fn is_enabled(&self) -> TockResult<bool> {
+        Ok(syscalls::command(DRIVER_NUMBER, command_nr::GET_ENABLED, 0, 0)? != 0)
+    }
+
+    fn set_enabled(&self, enabled: bool) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_ENABLED, enabled as usize, 0)?;
+        Ok(())
+    }
+}
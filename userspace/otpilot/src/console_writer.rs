@@ -0,0 +1,99 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use core::cell::Cell;
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+use libtock::syscalls::raw::yieldk;
+
+pub const MAX_WRITE_BUFFER_SIZE: usize = 512;
+
+pub trait ConsoleWriter {
+    // Write raw bytes to the console and wait (yieldk) until they are sent.
+    // data: Bytes to write. Must be data.len() <= MAX_WRITE_BUFFER_SIZE.
+    fn write(&'static mut self, data: &[u8]) -> TockResult<()>;
+}
+
+// Get the static ConsoleWriter object.
+pub fn get() -> &'static mut dyn ConsoleWriter {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 1;
+
+mod command_nr {
+    pub const WRITE: usize = 1;
+}
+
+mod subscribe_nr {
+    pub const WRITE_DONE: usize = 1;
+}
+
+mod allow_nr {
+    pub const WRITE_BUFFER: usize = 1;
+}
+
+pub struct ConsoleWriterImpl {
+    /// The transmit buffer.
+    write_buffer: [u8; MAX_WRITE_BUFFER_SIZE],
+
+    /// Whether the last write is complete.
+    write_done: Cell<bool>,
+}
+
+static mut CONSOLE_WRITER: ConsoleWriterImpl = ConsoleWriterImpl {
+    write_buffer: [0; MAX_WRITE_BUFFER_SIZE],
+    write_done: Cell::new(false),
+};
+
+fn get_impl() -> &'static mut ConsoleWriterImpl {
+    unsafe { &mut CONSOLE_WRITER }
+}
+
+impl ConsoleWriterImpl {
+    extern "C"
+    fn write_done_trampoline(arg1: usize, arg2: usize, arg3: usize, _data: usize) {
+        get_impl().write_done(arg1, arg2, arg3);
+    }
+
+    fn write_done(&self, _: usize, _: usize, _: usize) {
+        self.write_done.set(true);
+    }
+}
+
+impl ConsoleWriter for ConsoleWriterImpl {
+    fn write(&'static mut self, data: &[u8]) -> TockResult<()> {
+        let len = data.len();
+        self.write_buffer[..len].copy_from_slice(data);
+        self.write_done.set(false);
+
+        // libtock's console (println!) uses the same subscription and buffer,
+        // so take them over for the duration of this write only.
+        syscalls::subscribe_fn(
+            DRIVER_NUMBER,
+            subscribe_nr::WRITE_DONE,
+            ConsoleWriterImpl::write_done_trampoline,
+            0)?;
+        let _buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::WRITE_BUFFER,
+            &mut self.write_buffer)?;
+        syscalls::command(DRIVER_NUMBER, command_nr::WRITE, len, 0)?;
+
+        while !self.write_done.get() { unsafe { yieldk(); } }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+/// A crypto engine, as counted by the kernel.
+#[derive(Clone, Copy, Debug)]
+pub enum Engine {
+    Sha = 0,
+    Aes = 1,
+    Dcrypto = 2,
+}
+
+pub const ENGINES: [Engine; 3] = [
+    Engine::Sha,
+    Engine::Aes,
+    Engine::Dcrypto,
+];
+
+pub trait CryptoStats {
+    // Get the number of operations `engine` completed since boot.
+    fn get_ops(&self, engine: Engine) -> TockResult<u32>;
+
+    // Get the number of bytes `engine` processed since boot.
+    fn get_bytes(&self, engine: Engine) -> TockResult<u32>;
+
+    // Get the time `engine` was busy since boot, in microseconds.
+    fn get_busy_us(&self, engine: Engine) -> TockResult<u32>;
+}
+
+// Get the static CryptoStats object.
+pub fn get() -> &'static dyn CryptoStats {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40160;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_OPS: usize = 1;
+    pub const GET_BYTES: usize = 2;
+    pub const GET_BUSY: usize = 3;
+}
+
+struct CryptoStatsImpl {}
+
+static mut CRYPTO_STATS: CryptoStatsImpl = CryptoStatsImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static CryptoStatsImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if CRYPTO_STATS.initialize().is_err() {
+                panic!("Could not initialize CryptoStats");
+            }
+            IS_INITIALIZED = true;
+        }
+        &CRYPTO_STATS
+    }
+}
+
+impl CryptoStatsImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+
+    fn get_counter(&self, command: usize, engine: Engine) -> TockResult<u32> {
+        let value = syscalls::command(DRIVER_NUMBER, command, engine as usize, 0)?;
+        Ok(value as u32)
+    }
+}
+
+impl CryptoStats for CryptoStatsImpl {
+    fn get_ops(&self, engine: Engine) -> TockResult<u32> {
+        self.get_counter(command_nr::GET_OPS, engine)
+    }
+
+    fn get_bytes(&self, engine: Engine) -> TockResult<u32> {
+        self.get_counter(command_nr::GET_BYTES, engine)
+    }
+
+    fn get_busy_us(&self, engine: Engine) -> TockResult<u32> {
+        self.get_counter(command_nr::GET_BUSY, engine)
+    }
+}
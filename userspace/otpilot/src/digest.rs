@@ -0,0 +1,131 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+// The longest digest the driver produces, in bytes.
+pub const MAX_DIGEST_LENGTH: usize = 32;
+
+/// A digest algorithm supported by the hash engine.
+#[derive(Clone, Copy, Debug)]
+pub enum DigestMode {
+    Sha1 = 0,
+    Sha256 = 1,
+}
+
+pub trait Digest {
+    // Returns true if another app owns the hash engine.
+    fn is_busy(&self) -> bool;
+
+    // Start a new hash. Fails with EBUSY while another app owns the engine.
+    fn initialize(&self, mode: DigestMode) -> TockResult<()>;
+
+    // Feed data into the hash started with initialize.
+    fn update(&self, data: &[u8]) -> TockResult<()>;
+
+    // Finish the hash and write the digest to `output`, which must hold the
+    // digest of the mode the hash was started with.
+    fn finalize(&self, output: &mut [u8]) -> TockResult<()>;
+}
+
+// Get the static Digest object.
+pub fn get() -> &'static dyn Digest {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40003;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const INITIALIZE: usize = 1;
+    pub const UPDATE: usize = 2;
+    pub const FINALIZE: usize = 3;
+    pub const BUSY: usize = 4;
+}
+
+mod allow_nr {
+    pub const INPUT_BUFFER: usize = 0;
+    pub const OUTPUT_BUFFER: usize = 1;
+}
+
+// Data is copied into this buffer before it is shared with the kernel, as
+// allow needs a mutable buffer.
+const INPUT_BUFFER_LENGTH: usize = 128;
+
+static mut INPUT_BUFFER: [u8; INPUT_BUFFER_LENGTH] = [0; INPUT_BUFFER_LENGTH];
+
+struct DigestImpl {}
+
+static mut DIGEST: DigestImpl = DigestImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static DigestImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if DIGEST.initialize().is_err() {
+                panic!("Could not initialize Digest");
+            }
+            IS_INITIALIZED = true;
+        }
+        &DIGEST
+    }
+}
+
+impl DigestImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+impl Digest for DigestImpl {
+    fn is_busy(&self) -> bool {
+        syscalls::command(DRIVER_NUMBER, command_nr::BUSY, 0, 0).is_err()
+    }
+
+    fn initialize(&self, mode: DigestMode) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::INITIALIZE, mode as usize, 0)?;
+
+        Ok(())
+    }
+
+    fn update(&self, data: &[u8]) -> TockResult<()> {
+        for chunk in data.chunks(INPUT_BUFFER_LENGTH) {
+            unsafe {
+                // TODO(osk): We need the unsafe block since we're accessing INPUT_BUFFER as &mut.
+                INPUT_BUFFER[..chunk.len()].copy_from_slice(chunk);
+                // We want this to go out of scope after executing the command
+                let _buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::INPUT_BUFFER,
+                    &mut INPUT_BUFFER)?;
+                syscalls::command(DRIVER_NUMBER, command_nr::UPDATE, chunk.len(), 0)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finalize(&self, output: &mut [u8]) -> TockResult<()> {
+        // We want this to go out of scope after executing the command
+        let _buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::OUTPUT_BUFFER, output)?;
+
+        syscalls::command(DRIVER_NUMBER, command_nr::FINALIZE, 0, 0)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+/// A CPU exception type, as recorded by the kernel.
+#[derive(Clone, Copy, Debug)]
+pub enum FaultType {
+    HardFault = 0,
+    MemManage = 1,
+    BusFault = 2,
+    UsageFault = 3,
+}
+
+pub const FAULT_TYPES: [FaultType; 4] = [
+    FaultType::HardFault,
+    FaultType::MemManage,
+    FaultType::BusFault,
+    FaultType::UsageFault,
+];
+
+/// The CPU state when a fault was taken.
+#[derive(Clone, Copy, Debug)]
+pub struct FaultFrame {
+    pub pc: u32,
+    pub lr: u32,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    pub in_app: bool,
+}
+
+pub trait FaultStats {
+    // Get the number of faults of `fault_type` since boot.
+    fn get_count(&self, fault_type: FaultType) -> TockResult<u32>;
+
+    // Get the most recent fault of `fault_type`, if any.
+    fn get_last(&self, fault_type: FaultType) -> TockResult<Option<FaultFrame>>;
+}
+
+// Get the static FaultStats object.
+pub fn get() -> &'static dyn FaultStats {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x400d0;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_COUNT: usize = 1;
+    pub const GET_LAST: usize = 2;
+}
+
+mod field_nr {
+    pub const PC: usize = 0;
+    pub const LR: usize = 1;
+    pub const CFSR: usize = 2;
+    pub const HFSR: usize = 3;
+    pub const MMFAR: usize = 4;
+    pub const BFAR: usize = 5;
+    pub const IN_APP: usize = 6;
+}
+
+struct FaultStatsImpl {}
+
+static mut FAULT_STATS: FaultStatsImpl = FaultStatsImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static FaultStatsImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if FAULT_STATS.initialize().is_err() {
+                panic!("Could not initialize FaultStats");
+            }
+            IS_INITIALIZED = true;
+        }
+        &FAULT_STATS
+    }
+}
+
+impl FaultStatsImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+
+    fn get_field(&self, fault_type: FaultType, field: usize) -> TockResult<u32> {
+        let value = syscalls::command(DRIVER_NUMBER, command_nr::GET_LAST,
+            fault_type as usize, field)?;
+        Ok(value as u32)
+    }
+}
+
+impl FaultStats for FaultStatsImpl {
+    fn get_count(&self, fault_type: FaultType) -> TockResult<u32> {
+        let value = syscalls::command(DRIVER_NUMBER, command_nr::GET_COUNT,
+            fault_type as usize, 0)?;
+        Ok(value as u32)
+    }
+
+    fn get_last(&self, fault_type: FaultType) -> TockResult<Option<FaultFrame>> {
+        if self.get_count(fault_type)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(FaultFrame {
+            pc: self.get_field(fault_type, field_nr::PC)?,
+            lr: self.get_field(fault_type, field_nr::LR)?,
+            cfsr: self.get_field(fault_type, field_nr::CFSR)?,
+            hfsr: self.get_field(fault_type, field_nr::HFSR)?,
+            mmfar: self.get_field(fault_type, field_nr::MMFAR)?,
+            bfar: self.get_field(fault_type, field_nr::BFAR)?,
+            in_app: self.get_field(fault_type, field_nr::IN_APP)? != 0,
+        }))
+    }
+}
@@ -14,24 +14,23 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::alarm;
 use crate::gpio;
 use crate::gpio::FloatingState;
 use crate::gpio::GpioValue;
 use crate::gpio::InterruptEdge;
 
-use core::convert::TryFrom;
+use core::cell::RefCell;
 
+use gpioutils::trace::Edge;
+use gpioutils::trace::GpioTrace;
+use gpioutils::trace::TraceEvent;
+use gpioutils::trace::TRACE_PREFIX;
+
+use libtock::println;
 use libtock::result::TockResult;
 
-/// GPIO pins and mapping to kernel number.
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
-#[allow(non_camel_case_types)]
-pub enum GpioPin {
-    BMC_SRST_N = 0,
-    BMC_CPU_RST_N = 1,
-    SYS_RSTMON_N = 2,
-    BMC_RSTMON_N = 3,
-}
+pub use gpioutils::pin::GpioPin;
 
 pub trait GpioControl {
     /// Check if there are any events to be consumed.
@@ -47,6 +46,10 @@ pub trait GpioControl {
 
     /// Set GpioPin value.
     fn set(&self, pin: GpioPin, val: GpioValue) -> TockResult<()>;
+
+    /// Print and clear the trace of consumed events and driven edges (see
+    /// `gpioutils::trace`).
+    fn print_trace(&self);
 }
 
 // Get the static GpioControl object.
@@ -54,34 +57,12 @@ pub fn get() -> &'static dyn GpioControl {
     get_impl()
 }
 
-/// Error for invalid GpioPin conversion.
-pub struct InvalidGpioPin;
-
-impl TryFrom<usize> for GpioPin {
-    type Error = InvalidGpioPin;
-
-    fn try_from(item: usize) -> Result<GpioPin, Self::Error> {
-        match item {
-            0 => Ok(GpioPin::BMC_SRST_N),
-            1 => Ok(GpioPin::BMC_CPU_RST_N),
-            2 => Ok(GpioPin::SYS_RSTMON_N),
-            3 => Ok(GpioPin::BMC_RSTMON_N),
-            _ => Err(InvalidGpioPin),
-        }
-    }
-}
-
-impl From<GpioPin> for usize {
-    fn from(item: GpioPin) -> usize {
-        item as usize
-    }
-}
-
-
 struct GpioControlImpl {
+    trace: RefCell<GpioTrace>,
 }
 
 static mut GPIO_CTRL: GpioControlImpl = GpioControlImpl {
+    trace: RefCell::new(GpioTrace::new()),
 };
 
 static mut IS_INITIALIZED: bool = false;
@@ -109,6 +90,14 @@ impl GpioControlImpl {
         gpio::get().enable_events(GpioPin::BMC_RSTMON_N as usize, InterruptEdge::RisingEdge)?;
         Ok(())
     }
+
+    fn record(&self, pin: GpioPin, edge: Edge) {
+        self.trace.borrow_mut().record(TraceEvent {
+            timestamp: alarm::get().now() as u32,
+            pin: pin,
+            edge: edge,
+        });
+    }
 }
 
 impl GpioControl for GpioControlImpl {
@@ -119,7 +108,12 @@ impl GpioControl for GpioControlImpl {
     }
 
     fn consume_event(&self, pin: GpioPin) -> bool {
-        gpio::get().consume_event(pin as usize)
+        let consumed = gpio::get().consume_event(pin as usize);
+        if consumed {
+            // Events are only enabled on rising edges.
+            self.record(pin, Edge::Rising);
+        }
+        consumed
     }
 
     fn clear_event(&self, pin: GpioPin) -> bool {
@@ -127,7 +121,23 @@ impl GpioControl for GpioControlImpl {
     }
 
     fn set(&self, pin: GpioPin, val: GpioValue) -> TockResult<()> {
-        gpio::get().write(pin as usize, val)
+        gpio::get().write(pin as usize, val)?;
+        self.record(pin, match val {
+            GpioValue::Low => Edge::Falling,
+            GpioValue::High => Edge::Rising,
+        });
+        Ok(())
+    }
+
+    fn print_trace(&self) {
+        let mut trace = self.trace.borrow_mut();
+        println!("{} begin hz={} dropped={}",
+                 TRACE_PREFIX, alarm::get().get_clock_frequency(), trace.dropped());
+        for event in trace.events() {
+            println!("{}", event);
+        }
+        println!("{} end", TRACE_PREFIX);
+        trace.clear();
     }
 }
 
@@ -22,16 +22,19 @@ use crate::spi_device;
 use crate::spi_host_h1;
 use crate::spi_host_helper::SpiHostHelper;
 
-use core::cell::Cell;
+use gpioutils::sequencer::Action;
+use gpioutils::sequencer::Input;
+use gpioutils::sequencer::Sequencer;
+use gpioutils::sequencer::GUARD_MSECS;
 
 use libtock::println;
 use libtock::result::TockResult;
 
 use spiutils::protocol::flash::AddressMode;
 
+/// Carries out the reset sequencing decided by `gpioutils::sequencer`.
 pub struct GpioProcessor {
-    /// Whether to ignore bmc_rstmon_n events
-    ignore_bmc_rstmon_n_events: Cell<bool>,
+    sequencer: Sequencer,
 
     /// The initial address mode after resetting the BMC.
     initial_address_mode: AddressMode,
@@ -40,52 +43,48 @@ pub struct GpioProcessor {
     alarm_ticks: usize,
 }
 
-const ALARM_MSECS: u64 = 62;
 const MSECS_IN_SEC: u64 = 1000;
 
 impl GpioProcessor {
     pub fn new() -> GpioProcessor {
         let alarm_ticks: u64 =
-            ((alarm::get().get_clock_frequency() as u64) * ALARM_MSECS) / MSECS_IN_SEC;
+            ((alarm::get().get_clock_frequency() as u64) * GUARD_MSECS) / MSECS_IN_SEC;
 
         GpioProcessor {
-            ignore_bmc_rstmon_n_events: Cell::new(false),
+            sequencer: Sequencer::new(),
             initial_address_mode: spi_device::get().get_address_mode(),
             alarm_ticks: alarm_ticks as usize,
         }
     }
 
-    fn set_alarm(&self) -> TockResult<()> {
-        self.ignore_bmc_rstmon_n_events.set(true);
-        alarm::get().set(self.alarm_ticks)
+    fn process(&self, input: Input) -> TockResult<()> {
+        self.sequencer.process(input, |action| self.perform(action))
     }
 
-    pub fn set_bmc_cpu_rst(&self, asserted: bool) -> TockResult<()> {
-        if asserted {
-            gpio_control::get().set(GpioPin::BMC_CPU_RST_N, GpioValue::Low)?;
-        } else  {
-            gpio_control::get().set(GpioPin::BMC_CPU_RST_N, GpioValue::High)?;
-            self.set_alarm()?;
+    fn perform(&self, action: Action) -> TockResult<()> {
+        match action {
+            Action::Drive(pin, high) => {
+                gpio_control::get().set(pin, if high { GpioValue::High } else { GpioValue::Low })?;
+            },
+            Action::StartGuard => alarm::get().set(self.alarm_ticks)?,
+            Action::ResetFlash => self.reset_flash()?,
+            Action::ClearEvents(pin) => {
+                gpio_control::get().clear_event(pin);
+            },
+            Action::Log(message) => println!("{}", message),
         }
-
         Ok(())
     }
 
-    pub fn set_bmc_srst(&self, asserted: bool) -> TockResult<()> {
-        if asserted {
-            gpio_control::get().set(GpioPin::BMC_SRST_N, GpioValue::Low)?;
-        } else  {
-            gpio_control::get().set(GpioPin::BMC_SRST_N, GpioValue::High)?;
-            self.set_alarm()?;
-        }
-
-        Ok(())
+    pub fn set_bmc_cpu_rst(&self, asserted: bool) -> TockResult<()> {
+        self.process(Input::SetBmcCpuRst(asserted))
     }
 
-    fn handle_bmc_rstmon(&self) -> TockResult<()> {
-        // Put BMC into reset
-        self.set_bmc_cpu_rst(true)?;
+    pub fn set_bmc_srst(&self, asserted: bool) -> TockResult<()> {
+        self.process(Input::SetBmcSrst(asserted))
+    }
 
+    fn reset_flash(&self) -> TockResult<()> {
         // Disable SPI passthrough
         spi_host_h1::get().set_passthrough(false)?;
 
@@ -106,37 +105,26 @@ impl GpioProcessor {
         // Enable SPI passthrough
         spi_host_h1::get().set_passthrough(true)?;
 
-        // We don't care about any events that may have happened during reset.
-        gpio_control::get().clear_event(GpioPin::BMC_RSTMON_N);
-
-        // Let BMC out of reset
-        self.set_bmc_cpu_rst(false)?;
-
         Ok(())
     }
 
     pub fn process_gpio_events(&self) -> TockResult<()> {
-        let bmc_rstmon_n = gpio_control::get().consume_event(GpioPin::BMC_RSTMON_N);
-        if bmc_rstmon_n {
-            if self.ignore_bmc_rstmon_n_events.get() {
-                println!("Ignored bmc_rstmon_n");
-            } else {
-                println!("Handling bmc_rstmon_n");
-                self.handle_bmc_rstmon()?;
+        for &pin in [GpioPin::BMC_RSTMON_N, GpioPin::SYS_RSTMON_N].iter() {
+            if gpio_control::get().consume_event(pin) {
+                self.process(Input::Event(pin))?;
             }
         }
 
-        let sys_rstmon_n = gpio_control::get().consume_event(GpioPin::SYS_RSTMON_N);
-        if sys_rstmon_n {
-            println!("Ignored sys_rstmon_n");
-        }
-
         Ok(())
     }
 
+    /// Called when the kernel disabled SPI passthrough on SYS_RSTMON_N.
+    pub fn passthrough_disabled(&self) -> TockResult<()> {
+        self.process(Input::PassthroughDisabled)
+    }
+
     pub fn alarm_expired(&self) -> TockResult<()> {
-        println!("GPIO: alarm expired");
-        self.ignore_bmc_rstmon_n_events.set(false);
+        self.process(Input::GuardExpired)?;
         alarm::get().clear()
     }
 }
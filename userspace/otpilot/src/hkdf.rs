@@ -0,0 +1,118 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use libtock::result::TockError;
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+pub trait Hkdf {
+    // Fill `okm` with HKDF-SHA256 output for the given salt, input key
+    // material and info. Fails with EBUSY while another app has a digest in
+    // progress on the SHA engine.
+    fn derive(&self, salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8]) -> TockResult<()>;
+}
+
+// Get the static Hkdf object.
+pub fn get() -> &'static dyn Hkdf {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x400b0;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const DERIVE: usize = 1;
+}
+
+mod allow_nr {
+    pub const SALT: usize = 0;
+    pub const IKM: usize = 1;
+    pub const INFO: usize = 2;
+    pub const OUTPUT: usize = 3;
+}
+
+// Selects the IKM buffer (rather than a kernel key source) for DERIVE.
+const IKM_SOURCE_APP: usize = 0;
+
+// Inputs are copied into these buffers before they are shared with the
+// kernel, as allow needs a mutable buffer.
+const INPUT_BUFFER_LENGTH: usize = 128;
+
+static mut SALT_BUFFER: [u8; INPUT_BUFFER_LENGTH] = [0; INPUT_BUFFER_LENGTH];
+static mut IKM_BUFFER: [u8; INPUT_BUFFER_LENGTH] = [0; INPUT_BUFFER_LENGTH];
+static mut INFO_BUFFER: [u8; INPUT_BUFFER_LENGTH] = [0; INPUT_BUFFER_LENGTH];
+
+struct HkdfImpl {}
+
+static mut HKDF: HkdfImpl = HkdfImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static HkdfImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if HKDF.initialize().is_err() {
+                panic!("Could not initialize Hkdf");
+            }
+            IS_INITIALIZED = true;
+        }
+        &HKDF
+    }
+}
+
+impl HkdfImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+
+    // Run a derivation on buffers that can be shared with the kernel.
+    fn run(salt: &mut [u8], ikm: &mut [u8], info: &mut [u8], okm: &mut [u8]) -> TockResult<()> {
+        // We want these to go out of scope after executing the command
+        let _salt_share = syscalls::allow(DRIVER_NUMBER, allow_nr::SALT, salt)?;
+        let _ikm_share = syscalls::allow(DRIVER_NUMBER, allow_nr::IKM, ikm)?;
+        let _info_share = syscalls::allow(DRIVER_NUMBER, allow_nr::INFO, info)?;
+        let len = okm.len();
+        let _output_share = syscalls::allow(DRIVER_NUMBER, allow_nr::OUTPUT, okm)?;
+
+        syscalls::command(DRIVER_NUMBER, command_nr::DERIVE, IKM_SOURCE_APP, len)?;
+
+        Ok(())
+    }
+}
+
+impl Hkdf for HkdfImpl {
+    fn derive(&self, salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8]) -> TockResult<()> {
+        if salt.len() > INPUT_BUFFER_LENGTH || ikm.len() > INPUT_BUFFER_LENGTH ||
+            info.len() > INPUT_BUFFER_LENGTH {
+            return Err(TockError::Format);
+        }
+        let result = unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing the input buffers as &mut.
+            SALT_BUFFER[..salt.len()].copy_from_slice(salt);
+            IKM_BUFFER[..ikm.len()].copy_from_slice(ikm);
+            INFO_BUFFER[..info.len()].copy_from_slice(info);
+            Self::run(&mut SALT_BUFFER[..salt.len()], &mut IKM_BUFFER[..ikm.len()],
+                      &mut INFO_BUFFER[..info.len()], okm)
+        };
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing IKM_BUFFER as &mut.
+            ecc::wipe(&mut IKM_BUFFER);
+        }
+        result
+    }
+}
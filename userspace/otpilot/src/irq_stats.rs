@@ -0,0 +1,99 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+/// Number of NVIC lines counted by the kernel.
+pub const NUM_IRQS: usize = 256;
+
+pub trait IrqStats {
+    // Get the number of interrupts on NVIC line `line` since boot.
+    fn get_total(&self, line: usize) -> TockResult<u32>;
+
+    // Get the number of interrupts on NVIC line `line` in the last window.
+    fn get_last_window(&self, line: usize) -> TockResult<u32>;
+
+    // Get the window length, in milliseconds.
+    fn get_window_ms(&self) -> TockResult<u32>;
+
+    // Get the first line at or after `line` that has had an interrupt.
+    fn next_active(&self, line: usize) -> TockResult<Option<usize>>;
+}
+
+// Get the static IrqStats object.
+pub fn get() -> &'static dyn IrqStats {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40130;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_TOTAL: usize = 1;
+    pub const GET_LAST_WINDOW: usize = 2;
+    pub const GET_WINDOW_MS: usize = 3;
+    pub const NEXT_ACTIVE: usize = 6;
+}
+
+struct IrqStatsImpl {}
+
+static mut IRQ_STATS: IrqStatsImpl = IrqStatsImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static IrqStatsImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if IRQ_STATS.initialize().is_err() {
+                panic!("Could not initialize IrqStats");
+            }
+            IS_INITIALIZED = true;
+        }
+        &IRQ_STATS
+    }
+}
+
+impl IrqStatsImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+impl IrqStats for IrqStatsImpl {
+    fn get_total(&self, line: usize) -> TockResult<u32> {
+        let value = syscalls::command(DRIVER_NUMBER, command_nr::GET_TOTAL, line, 0)?;
+        Ok(value as u32)
+    }
+
+    fn get_last_window(&self, line: usize) -> TockResult<u32> {
+        let value = syscalls::command(DRIVER_NUMBER, command_nr::GET_LAST_WINDOW, line, 0)?;
+        Ok(value as u32)
+    }
+
+    fn get_window_ms(&self) -> TockResult<u32> {
+        let value = syscalls::command(DRIVER_NUMBER, command_nr::GET_WINDOW_MS, 0, 0)?;
+        Ok(value as u32)
+    }
+
+    fn next_active(&self, line: usize) -> TockResult<Option<usize>> {
+        let value = syscalls::command(DRIVER_NUMBER, command_nr::NEXT_ACTIVE, line, 0)?;
+        Ok(if value < NUM_IRQS { Some(value) } else { None })
+    }
+}
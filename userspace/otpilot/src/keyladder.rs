@@ -0,0 +1,106 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+use libtock::result::TockError;
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+// The length of a derived key, in bytes.
+pub const KEY_LEN: usize = 32;
+
+// What a derived key is used for (see h1::hil::keyladder::KeyUsage).
+#[derive(Clone, Copy, Debug)]
+pub enum KeyUsage {
+    Attestation = 0,
+}
+
+pub trait KeyLadder {
+    // Derive the key for `usage` and `context` into `key`. The kernel mixes
+    // in the app's package name, so other apps cannot derive the same key.
+    // Fails with EBUSY while another app has a digest in progress on the SHA
+    // engine.
+    fn derive(&self, usage: KeyUsage, context: &[u8], key: &mut [u8; KEY_LEN]) -> TockResult<()>;
+}
+
+// Get the static KeyLadder object.
+pub fn get() -> &'static dyn KeyLadder {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x401A0;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const DERIVE: usize = 1;
+}
+
+mod allow_nr {
+    pub const CONTEXT: usize = 0;
+    pub const OUTPUT: usize = 1;
+}
+
+// The context is copied into this buffer before it is shared with the
+// kernel, as allow needs a mutable buffer.
+const CONTEXT_BUFFER_LENGTH: usize = 64;
+
+static mut CONTEXT_BUFFER: [u8; CONTEXT_BUFFER_LENGTH] = [0; CONTEXT_BUFFER_LENGTH];
+
+struct KeyLadderImpl {}
+
+static mut KEY_LADDER: KeyLadderImpl = KeyLadderImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static KeyLadderImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if KEY_LADDER.initialize().is_err() {
+                panic!("Could not initialize KeyLadder");
+            }
+            IS_INITIALIZED = true;
+        }
+        &KEY_LADDER
+    }
+}
+
+impl KeyLadderImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+impl KeyLadder for KeyLadderImpl {
+    fn derive(&self, usage: KeyUsage, context: &[u8], key: &mut [u8; KEY_LEN]) -> TockResult<()> {
+        if context.len() > CONTEXT_BUFFER_LENGTH {
+            return Err(TockError::Format);
+        }
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing CONTEXT_BUFFER as &mut.
+            CONTEXT_BUFFER[..context.len()].copy_from_slice(context);
+            // We want these to go out of scope after executing the command
+            let _context_share = syscalls::allow(DRIVER_NUMBER, allow_nr::CONTEXT,
+                &mut CONTEXT_BUFFER[..context.len()])?;
+            let _output_share = syscalls::allow(DRIVER_NUMBER, allow_nr::OUTPUT, key)?;
+
+            syscalls::command(DRIVER_NUMBER, command_nr::DERIVE, usage as usize, 0)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Line editing for the console.
+//!
+//! Collects console input into a line, echoing it back, and supports
+//! backspace, Ctrl-U to clear the line and the up/down arrow keys to recall
+//! earlier lines. All buffers are fixed-size.
+
+use core::fmt::Write;
+
+/// Maximum length of a line. Further input is ignored.
+pub const MAX_LINE_LEN: usize = 64;
+
+/// Number of earlier lines kept for recall.
+const HISTORY_LEN: usize = 4;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1b;
+
+#[derive(Clone, Copy)]
+struct Line {
+    bytes: [u8; MAX_LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    const EMPTY: Line = Line {
+        bytes: [0; MAX_LINE_LEN],
+        len: 0,
+    };
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Where we are in an ANSI escape sequence.
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    /// Received ESC.
+    Start,
+    /// Received ESC [, waiting for the final byte.
+    Csi,
+}
+
+pub struct LineEditor {
+    /// The line being edited.
+    line: Line,
+
+    /// Earlier lines, as a ring buffer.
+    history: [Line; HISTORY_LEN],
+
+    /// Number of valid entries in `history`.
+    history_len: usize,
+
+    /// Index in `history` of the most recent entry.
+    history_head: usize,
+
+    /// How far back in `history` the line was recalled from. 0 if the line
+    /// was not recalled.
+    recall: usize,
+
+    escape: Escape,
+
+    /// Whether the previous byte was a CR, so that the LF of a CR LF pair does
+    /// not end another line.
+    after_cr: bool,
+}
+
+impl LineEditor {
+    pub const fn new() -> LineEditor {
+        LineEditor {
+            line: Line::EMPTY,
+            history: [Line::EMPTY; HISTORY_LEN],
+            history_len: 0,
+            history_head: 0,
+            recall: 0,
+            escape: Escape::None,
+            after_cr: false,
+        }
+    }
+
+    /// Discards the line being edited, without echo. Keeps the history.
+    pub fn reset(&mut self) {
+        self.line = Line::EMPTY;
+        self.recall = 0;
+        self.escape = Escape::None;
+        self.after_cr = false;
+    }
+
+    /// Processes one byte of input, writing the echo to `echo`. Returns the
+    /// line once it is ended by CR or LF. Empty lines are returned too.
+    pub fn process_byte<W: Write>(&mut self, byte: u8, echo: &mut W) -> Option<&[u8]> {
+        let after_cr = self.after_cr;
+        self.after_cr = byte == b'\r';
+
+        match self.escape {
+            Escape::Start => {
+                self.escape = if byte == b'[' { Escape::Csi } else { Escape::None };
+                return None;
+            },
+            Escape::Csi => {
+                // Parameter and intermediate bytes precede the final byte.
+                if (0x40..=0x7e).contains(&byte) {
+                    self.escape = Escape::None;
+                    match byte {
+                        b'A' => self.recall_older(echo),
+                        b'B' => self.recall_newer(echo),
+                        _ => (),
+                    }
+                }
+                return None;
+            },
+            Escape::None => (),
+        }
+
+        match byte {
+            b'\n' if after_cr => (),
+            b'\r' | b'\n' => {
+                let _ = echo.write_str("\r\n");
+                return Some(self.finish_line());
+            },
+            BACKSPACE | DELETE if self.line.len > 0 => {
+                self.line.len -= 1;
+                let _ = echo.write_str("\x08 \x08");
+            },
+            CTRL_U => self.set_line(Line::EMPTY, echo),
+            ESC => self.escape = Escape::Start,
+            0x20..=0x7e if self.line.len < MAX_LINE_LEN => {
+                self.line.bytes[self.line.len] = byte;
+                self.line.len += 1;
+                let _ = echo.write_char(byte as char);
+            },
+            _ => (),
+        }
+        None
+    }
+
+    /// Moves the current line to the history and returns it.
+    fn finish_line(&mut self) -> &[u8] {
+        let line = self.line;
+        self.line = Line::EMPTY;
+        self.recall = 0;
+
+        if line.len == 0 {
+            return &[];
+        }
+        let is_repeat = self.history_len > 0 &&
+            self.history[self.history_head].as_bytes() == line.as_bytes();
+        if !is_repeat {
+            self.history_head = (self.history_head + 1) % HISTORY_LEN;
+            self.history[self.history_head] = line;
+            if self.history_len < HISTORY_LEN {
+                self.history_len += 1;
+            }
+        }
+        self.history[self.history_head].as_bytes()
+    }
+
+    /// Returns the entry `age` lines back, where 1 is the most recent.
+    fn history_entry(&self, age: usize) -> Line {
+        self.history[(self.history_head + HISTORY_LEN + 1 - age) % HISTORY_LEN]
+    }
+
+    fn recall_older<W: Write>(&mut self, echo: &mut W) {
+        if self.recall < self.history_len {
+            self.recall += 1;
+            self.set_line(self.history_entry(self.recall), echo);
+        }
+    }
+
+    fn recall_newer<W: Write>(&mut self, echo: &mut W) {
+        if self.recall > 0 {
+            self.recall -= 1;
+            let line = match self.recall {
+                0 => Line::EMPTY,
+                age => self.history_entry(age),
+            };
+            self.set_line(line, echo);
+        }
+    }
+
+    /// Replaces the current line, erasing the old one from the terminal.
+    fn set_line<W: Write>(&mut self, line: Line, echo: &mut W) {
+        for _ in 0..self.line.len {
+            let _ = echo.write_str("\x08 \x08");
+        }
+        self.line = line;
+        for &byte in self.line.as_bytes() {
+            let _ = echo.write_char(byte as char);
+        }
+    }
+}
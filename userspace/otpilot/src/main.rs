@@ -16,9 +16,17 @@
 
 #![no_std]
 
+mod aes_gcm;
 mod alarm;
+mod board_config;
+mod boot_attempts;
 mod console_processor;
+mod console_timestamps;
 mod console_reader;
+mod console_writer;
+mod crypto_stats;
+mod digest;
+mod fault_stats;
 mod firmware_controller;
 mod flash;
 mod fuse;
@@ -26,19 +34,34 @@ mod globalsec;
 mod gpio;
 mod gpio_control;
 mod gpio_processor;
+mod hkdf;
+mod irq_stats;
+mod keyladder;
+mod line_editor;
 mod manticore_support;
+mod p256;
+mod passthrough_guard;
+mod personality;
 mod reset;
+mod rng;
+mod session_crypto;
 mod sfdp;
 mod spi_host;
 mod spi_host_h1;
 mod spi_host_helper;
 mod spi_device;
 mod spi_processor;
+mod stack_usage;
+mod syscall_error;
+mod timebase;
+mod usb_debug;
 
 use crate::console_processor::ConsoleProcessor;
 use crate::gpio_processor::GpioProcessor;
 use crate::spi_host_helper::SpiHostHelper;
 use crate::spi_processor::SpiProcessor;
+use crate::spi_processor::SpiProcessorError;
+use crate::syscall_error::Decoded;
 
 use libtock::println;
 use libtock::result::TockError;
@@ -48,10 +71,15 @@ use libtock::syscalls::raw::yieldk;
 use spiutils::driver::firmware::SegmentInfo;
 use spiutils::driver::spi_device::AddressConfig;
 use spiutils::driver::spi_device::HandlerMode;
+use spiutils::driver::spi_device::READ_CAPABILITY_DUAL_OUTPUT;
+use spiutils::driver::spi_device::READ_CAPABILITY_QUAD_OUTPUT;
+use spiutils::driver::spi_device::RxBufferMode;
 use spiutils::io::Cursor;
 use spiutils::protocol::firmware::SegmentAndLocation;
 use spiutils::protocol::flash::AddressMode;
+use spiutils::protocol::payload;
 use spiutils::protocol::wire::ToWire;
+use spiutils::session::Policy as SessionPolicy;
 
 libtock_core::stack_size! {2048}
 
@@ -142,14 +170,24 @@ fn run() -> TockResult<()> {
         manticore_handler: manticore_support::Handler::new(&identity),
         print_flash_headers: false,  // Enable to print incoming SPI flash headers
         firmware: firmware_controller::FirmwareController::new(),
+        session_policy: SessionPolicy::RequiredAfterProvisioning,
+        provisioned: personality::get().is_provisioned()?,
+        session: None,
+        in_session_record: false,
+        digest_session: None,
+        next_digest_session_id: 1,
+        header_crc: false,
+        fragmented_response_len: None,
+        in_reassembled_message: false,
     };
 
     let gpio_processor = GpioProcessor::new();
-    let console_processor = ConsoleProcessor::new(&gpio_processor);
+    let mut console_processor = ConsoleProcessor::new(&gpio_processor);
 
     //////////////////////////////////////////////////////////////////////////////
 
     spi_device::get().set_address_mode_handling(HandlerMode::KernelSpace)?;
+    spi_device::get().set_rx_buffer_mode(RxBufferMode::Handoff)?;
     spi_device::get().configure_addresses(AddressConfig {
         flash_virtual_base: 0x0,
         flash_physical_base: 0x0,
@@ -172,14 +210,17 @@ fn run() -> TockResult<()> {
 
     {
         let mut sfdp = [0xff; 128];
+        let read_capabilities = spi_device::get().get_read_capabilities()?;
         sfdp::get_table(
             &mut sfdp,
-            spi_processor::SPI_FLASH_SIZE * 8, // image_size_bits
+            spi_processor::SPI_FLASH_SIZE, // image_size_bytes
             spi_device::get().get_address_mode(), // startup_address_mode
             spi_device::get().get_address_mode() == AddressMode::ThreeByte, // support_address_mode_switch
+            read_capabilities & READ_CAPABILITY_DUAL_OUTPUT != 0, // support_dual_output_read
+            read_capabilities & READ_CAPABILITY_QUAD_OUTPUT != 0, // support_quad_output_read
             spi_processor::SPI_MAILBOX_ADDRESS, // mailbox_offset
             spi_device::MAX_READ_BUFFER_SIZE as u32, // mailbox_size
-            0 // google_capabilities
+            payload::CAPABILITY_HEADER_CRC | payload::CAPABILITY_FRAGMENTS // google_capabilities
             ).map_err(|_| TockError::Format)?;
         spi_device::get().set_sfdp(&mut sfdp)?;
     }
@@ -198,6 +239,11 @@ fn run() -> TockResult<()> {
     let _ = gpio_processor.set_bmc_cpu_rst(false);
     let _ = gpio_processor.set_bmc_srst(false);
 
+    // Startup is complete, so this boot no longer counts as failed. This
+    // fails harmlessly if the app was restarted after an earlier instance
+    // got here.
+    let _ = boot_attempts::get().mark_healthy();
+
     //////////////////////////////////////////////////////////////////////////////
 
     console_reader::get().allow_read(1)?;
@@ -206,6 +252,7 @@ fn run() -> TockResult<()> {
         while !spi_device::get().have_transaction()
             && !console_reader::get().have_data()
             && !gpio_control::get().have_events()
+            && !passthrough_guard::get().is_tripped()
             && !alarm::get().is_expired() {
 
             // Note: Do NOT use the console here, as that results in a "hidden"
@@ -219,7 +266,10 @@ fn run() -> TockResult<()> {
                 Ok(()) => {}
                 Err(why) => {
                     // Ignore error from writeln. There's nothing we can do here anyway.
-                    println!("SPI processor: Error {:?}", why);
+                    match why {
+                        SpiProcessorError::Tock(err) => println!("SPI processor: Error {}", Decoded(&err)),
+                        _ => println!("SPI processor: Error {:?}", why),
+                    }
                     if spi_device::get().is_busy_set() {
                         if let Err(_) = spi_device::get().end_transaction_with_status(true, false) {
                             // Ignore error from writeln. There's nothing we can do here anyway.
@@ -230,14 +280,24 @@ fn run() -> TockResult<()> {
                     }
                 }
             }
+
+            // The SPI host may have sent the next transaction while we were
+            // busy. Pick it up right away rather than waiting for the next
+            // interrupt.
+            if !spi_device::get().have_transaction() {
+                if let Err(_) = spi_device::get().kick() {
+                    // Ignore error from writeln. There's nothing we can do here anyway.
+                    println!("SPI device: kick error.");
+                }
+            }
         }
 
         if console_reader::get().have_data() {
             match console_processor.process_input() {
                 Ok(()) => {}
-                Err(_) => {
+                Err(err) => {
                     // Ignore error from writeln. There's nothing we can do here anyway.
-                    println!("Console processor: Error.");
+                    println!("Console processor: Error {}", Decoded(&err));
                 }
             }
             console_reader::get().allow_read(1)?;
@@ -246,9 +306,19 @@ fn run() -> TockResult<()> {
         if gpio_control::get().have_events() {
             match gpio_processor.process_gpio_events() {
                 Ok(()) => {}
-                Err(_) => {
+                Err(err) => {
+                    // Ignore error from writeln. There's nothing we can do here anyway.
+                    println!("GPIO processor (event): Error {}", Decoded(&err));
+                }
+            }
+        }
+
+        if passthrough_guard::get().consume_trip() {
+            match gpio_processor.passthrough_disabled() {
+                Ok(()) => {}
+                Err(err) => {
                     // Ignore error from writeln. There's nothing we can do here anyway.
-                    println!("GPIO processor (event): Error.");
+                    println!("GPIO processor (passthrough guard): Error {}", Decoded(&err));
                 }
             }
         }
@@ -256,9 +326,9 @@ fn run() -> TockResult<()> {
         if alarm::get().is_expired() {
             match gpio_processor.alarm_expired() {
                 Ok(()) => {}
-                Err(_) => {
+                Err(err) => {
                     // Ignore error from writeln. There's nothing we can do here anyway.
-                    println!("GPIO processor (alarm): Error.");
+                    println!("GPIO processor (alarm): Error {}", Decoded(&err));
                 }
             }
         }
@@ -278,6 +348,7 @@ async fn main() -> TockResult<()> {
 
     println!("Starting {}", BANNER);
     println!("Reset source: {:?}", reset::get().get_reset_source()?);
+    println!("Failed boots before this one: {}", boot_attempts::get().get_failed_boots()?);
     println!("active RO: {:?}, {:?}", globalsec::get().get_active_ro(), firmware_controller::get_build_info(globalsec::get().get_active_ro())?);
     println!("active RW: {:?}, {:?}", globalsec::get().get_active_rw(), firmware_controller::get_build_info(globalsec::get().get_active_rw())?);
     println!("inactive RO: {:?}, {:?}", globalsec::get().get_inactive_ro(), firmware_controller::get_build_info(globalsec::get().get_inactive_ro())?);
@@ -0,0 +1,169 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+use core::cell::Cell;
+
+use libtock::result::TockError;
+use libtock::result::TockResult;
+use libtock::syscalls;
+use libtock::syscalls::raw::yieldk;
+
+// The length of a P-256 scalar or coordinate, in bytes.
+pub const SCALAR_LEN: usize = 32;
+
+// The length of a public key (x, y) or a signature (r, s), in bytes.
+pub const PAIR_LEN: usize = 2 * SCALAR_LEN;
+
+// P-256 on the dcrypto engine. Scalars and coordinates are big-endian.
+pub trait P256 {
+    // Compute the public key (x, y) of `private_key`. Fails with EINVAL if
+    // the key is not in [1, n). Blocks (yieldk) until done.
+    fn public_key(&self, private_key: &[u8; SCALAR_LEN]) -> TockResult<[u8; PAIR_LEN]>;
+
+    // Sign the 32-byte `digest` with ECDSA and return (r, s). The nonce is
+    // derived as in RFC 6979 on the SHA engine, so this fails with EBUSY
+    // while a digest is in progress. Blocks (yieldk) until done.
+    fn sign(&self, private_key: &[u8; SCALAR_LEN], digest: &[u8; SCALAR_LEN])
+            -> TockResult<[u8; PAIR_LEN]>;
+}
+
+// Get the static P256 object.
+pub fn get() -> &'static dyn P256 {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40004;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const P256_PUBLIC_KEY: usize = 6;
+    pub const P256_SIGN: usize = 7;
+}
+
+mod subscribe_nr {
+    pub const DONE: usize = 0;
+}
+
+mod allow_nr {
+    pub const DATA: usize = 0;
+}
+
+// The data buffer of the driver: the private key, followed by the digest
+// when signing. The public key is written after the private key, and the
+// signature replaces the private key and the digest.
+const DATA_BUFFER_LENGTH: usize = SCALAR_LEN + PAIR_LEN;
+
+static mut DATA_BUFFER: [u8; DATA_BUFFER_LENGTH] = [0; DATA_BUFFER_LENGTH];
+
+struct P256Impl {
+    // Whether the last command is complete.
+    done: Cell<bool>,
+
+    // The return code of the last command.
+    result: Cell<isize>,
+}
+
+static mut P256_ENGINE: P256Impl = P256Impl {
+    done: Cell::new(false),
+    result: Cell::new(0),
+};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static P256Impl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if P256_ENGINE.initialize().is_err() {
+                panic!("Could not initialize P256");
+            }
+            IS_INITIALIZED = true;
+        }
+        &P256_ENGINE
+    }
+}
+
+impl P256Impl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        syscalls::subscribe_fn(
+            DRIVER_NUMBER,
+            subscribe_nr::DONE,
+            P256Impl::done_trampoline,
+            0)?;
+
+        Ok(())
+    }
+
+    extern "C"
+    fn done_trampoline(rcode: usize, _arg2: usize, _arg3: usize, _data: usize) {
+        let p256 = get_impl();
+        p256.result.set(rcode as isize);
+        p256.done.set(true);
+    }
+
+    // Run `command` on DATA_BUFFER, which must hold the command's input, and
+    // return the pair at `output_offset`. Wipes DATA_BUFFER.
+    fn run(&self, command: usize, output_offset: usize) -> TockResult<[u8; PAIR_LEN]> {
+        let result = self.run_command(command, output_offset);
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing DATA_BUFFER as &mut.
+            ecc::wipe(&mut DATA_BUFFER);
+        }
+        result
+    }
+
+    fn run_command(&self, command: usize, output_offset: usize) -> TockResult<[u8; PAIR_LEN]> {
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing DATA_BUFFER as &mut.
+            // We want this to go out of scope after the command completes
+            let _data_share = syscalls::allow(DRIVER_NUMBER, allow_nr::DATA, &mut DATA_BUFFER)?;
+            self.done.set(false);
+            syscalls::command(DRIVER_NUMBER, command, 0, 0)?;
+            while !self.done.get() { yieldk(); }
+        }
+        if self.result.get() != 0 {
+            return Err(TockError::Format);
+        }
+        let mut output = [0u8; PAIR_LEN];
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing DATA_BUFFER.
+            output.copy_from_slice(&DATA_BUFFER[output_offset..output_offset + PAIR_LEN]);
+        }
+        Ok(output)
+    }
+}
+
+impl P256 for P256Impl {
+    fn public_key(&self, private_key: &[u8; SCALAR_LEN]) -> TockResult<[u8; PAIR_LEN]> {
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing DATA_BUFFER as &mut.
+            DATA_BUFFER[..SCALAR_LEN].copy_from_slice(private_key);
+        }
+        self.run(command_nr::P256_PUBLIC_KEY, SCALAR_LEN)
+    }
+
+    fn sign(&self, private_key: &[u8; SCALAR_LEN], digest: &[u8; SCALAR_LEN])
+            -> TockResult<[u8; PAIR_LEN]> {
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing DATA_BUFFER as &mut.
+            DATA_BUFFER[..SCALAR_LEN].copy_from_slice(private_key);
+            DATA_BUFFER[SCALAR_LEN..2 * SCALAR_LEN].copy_from_slice(digest);
+        }
+        self.run(command_nr::P256_SIGN, 0)
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use core::cell::Cell;
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+pub trait PassthroughGuard {
+    // Whether the board has a passthrough guard.
+    fn is_present(&self) -> bool;
+
+    // Check whether the guard disabled SPI passthrough since the last
+    // consume_trip().
+    fn is_tripped(&self) -> bool;
+
+    // Like is_tripped(), but also clears the flag.
+    fn consume_trip(&self) -> bool;
+
+    // Get the number of times the guard disabled SPI passthrough since boot.
+    fn get_trips(&self) -> TockResult<u32>;
+}
+
+// Get the static PassthroughGuard object.
+pub fn get() -> &'static dyn PassthroughGuard {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40180;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_TRIPS: usize = 3;
+}
+
+mod subscribe_nr {
+    pub const PASSTHROUGH_DISABLED: usize = 0;
+}
+
+struct PassthroughGuardImpl {
+    present: bool,
+
+    // Whether the guard tripped and this has not been consumed yet.
+    tripped: Cell<bool>,
+}
+
+static mut PASSTHROUGH_GUARD: PassthroughGuardImpl = PassthroughGuardImpl {
+    present: false,
+    tripped: Cell::new(false),
+};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static PassthroughGuardImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if PASSTHROUGH_GUARD.initialize().is_err() {
+                panic!("Could not initialize PassthroughGuard");
+            }
+            IS_INITIALIZED = true;
+        }
+        &PASSTHROUGH_GUARD
+    }
+}
+
+impl PassthroughGuardImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        // Boards without a guard leave isolation to us.
+        self.present = syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0).is_ok();
+        if !self.present {
+            return Ok(());
+        }
+
+        syscalls::subscribe_fn(
+            DRIVER_NUMBER,
+            subscribe_nr::PASSTHROUGH_DISABLED,
+            PassthroughGuardImpl::passthrough_disabled_trampoline,
+            0)?;
+
+        Ok(())
+    }
+
+    extern "C"
+    fn passthrough_disabled_trampoline(_trips: usize, _: usize, _: usize, _data: usize) {
+        get_impl().tripped.set(true);
+    }
+}
+
+impl PassthroughGuard for PassthroughGuardImpl {
+    fn is_present(&self) -> bool {
+        self.present
+    }
+
+    fn is_tripped(&self) -> bool {
+        self.tripped.get()
+    }
+
+    fn consume_trip(&self) -> bool {
+        self.tripped.replace(false)
+    }
+
+    fn get_trips(&self) -> TockResult<u32> {
+        let trips = syscalls::command(DRIVER_NUMBER, command_nr::GET_TRIPS, 0, 0)?;
+        Ok(trips as u32)
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+pub trait Personality {
+    /// Returns true if attestation data has been written, i.e. the chip
+    /// has been provisioned.
+    fn is_provisioned(&self) -> TockResult<bool>;
+}
+
+// Get the static Personality object.
+pub fn get() -> &'static dyn Personality {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x5000b;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const READ: usize = 1;
+}
+
+mod allow_nr {
+    pub const BUFFER: usize = 0;
+}
+
+// Size of the personality data. The driver always reads all of it.
+const PERSONALITY_SIZE: usize = 2048;
+
+// Offset of the certificate length, after the checksum, salt, public key
+// and certificate hash (see h1::hil::personality::PersonalityData).
+const CERTIFICATE_LEN_OFFSET: usize = 5 * 32;
+
+// Erased flash reads as all ones.
+const ERASED_WORD: u32 = 0xffffffff;
+
+struct PersonalityImpl {}
+
+static mut PERSONALITY: PersonalityImpl = PersonalityImpl {};
+
+// Too large for the stack, which is currently limited to 2048 bytes.
+static mut PERSONALITY_BUFFER: [u8; PERSONALITY_SIZE] = [0; PERSONALITY_SIZE];
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static PersonalityImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if PERSONALITY.initialize().is_err() {
+                panic!("Could not initialize Personality");
+            }
+            IS_INITIALIZED = true;
+        }
+        &PERSONALITY
+    }
+}
+
+impl PersonalityImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+impl Personality for PersonalityImpl {
+    fn is_provisioned(&self) -> TockResult<bool> {
+        let certificate_len: u32;
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing PERSONALITY_BUFFER as &mut.
+            {
+                // We want this to go out of scope after executing the command
+                let _buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::BUFFER, &mut PERSONALITY_BUFFER)?;
+
+                syscalls::command(DRIVER_NUMBER, command_nr::READ, 0, 0)?;
+            }
+            let mut word = [0u8; 4];
+            word.copy_from_slice(&PERSONALITY_BUFFER[CERTIFICATE_LEN_OFFSET..CERTIFICATE_LEN_OFFSET + 4]);
+            certificate_len = u32::from_le_bytes(word);
+        }
+        Ok(certificate_len != 0 && certificate_len != ERASED_WORD)
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use core::cell::Cell;
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+use libtock::syscalls::raw::yieldk;
+
+pub trait Rng {
+    /// Fill `buffer` with random bytes. Blocks (yieldk) until done.
+    fn fill(&self, buffer: &mut [u8]) -> TockResult<()>;
+}
+
+// Get the static Rng object.
+pub fn get() -> &'static dyn Rng {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40001;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const REQUEST_BYTES: usize = 1;
+}
+
+mod subscribe_nr {
+    pub const BYTES_AVAILABLE: usize = 0;
+}
+
+mod allow_nr {
+    pub const BUFFER: usize = 0;
+}
+
+struct RngImpl {
+    // Whether the last request is complete.
+    request_done: Cell<bool>,
+}
+
+static mut RNG: RngImpl = RngImpl {
+    request_done: Cell::new(false),
+};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static RngImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if RNG.initialize().is_err() {
+                panic!("Could not initialize Rng");
+            }
+            IS_INITIALIZED = true;
+        }
+        &RNG
+    }
+}
+
+impl RngImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        syscalls::subscribe_fn(
+            DRIVER_NUMBER,
+            subscribe_nr::BYTES_AVAILABLE,
+            RngImpl::bytes_available_trampoline,
+            0)?;
+
+        Ok(())
+    }
+
+    extern "C"
+    fn bytes_available_trampoline(_arg1: usize, _arg2: usize, _arg3: usize, _data: usize) {
+        get_impl().request_done.set(true);
+    }
+}
+
+impl Rng for RngImpl {
+    fn fill(&self, buffer: &mut [u8]) -> TockResult<()> {
+        let len = buffer.len();
+
+        // We want this to go out of scope after the request completes
+        let _buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::BUFFER, buffer)?;
+
+        self.request_done.set(false);
+        syscalls::command(DRIVER_NUMBER, command_nr::REQUEST_BYTES, len, 0)?;
+        while !self.request_done.get() { unsafe { yieldk(); } }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::aes_gcm;
+use crate::hkdf;
+use crate::keyladder;
+use crate::keyladder::KeyUsage;
+use crate::p256;
+use crate::syscall_error;
+
+use errorcode::ErrorCode;
+
+use libtock::result::TockResult;
+
+use spiutils::protocol::session::IDENTITY_KEY_LEN;
+use spiutils::protocol::session::SIGNATURE_LEN;
+use spiutils::protocol::session::TAG_LEN;
+use spiutils::session::Crypto;
+use spiutils::session::Identity;
+use spiutils::session::SessionError;
+use spiutils::session::DIGEST_LEN;
+use spiutils::session::IV_LEN;
+use spiutils::session::KEY_LEN;
+
+// Session crypto on top of the kernel's HKDF and AES drivers.
+pub struct KernelCrypto;
+
+impl Crypto for KernelCrypto {
+    fn hkdf(&self, salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8])
+            -> Result<(), SessionError> {
+        hkdf::get().derive(salt, ikm, info, okm).map_err(|_| SessionError::Crypto)
+    }
+
+    fn seal(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8])
+            -> Result<[u8; TAG_LEN], SessionError> {
+        aes_gcm::get().seal(key, iv, aad, data).map_err(|_| SessionError::Crypto)
+    }
+
+    fn open(&self, key: &[u8; KEY_LEN], iv: &[u8; IV_LEN], aad: &[u8], data: &mut [u8],
+            tag: &[u8; TAG_LEN]) -> Result<(), SessionError> {
+        match aes_gcm::get().open(key, iv, aad, data, tag) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(SessionError::AuthenticationFailed),
+            Err(_) => Err(SessionError::Crypto),
+        }
+    }
+}
+
+// The key ladder context of the identity key. A counter byte follows it.
+const IDENTITY_CONTEXT: &[u8] = b"otpilot session identity";
+
+// The session identity key, derived from the attestation branch of the key
+// ladder. It is the same across boots and updates of the same RW, and is
+// derived again whenever it is used rather than kept in memory. Like the
+// nonce of the signature, the derivation needs the SHA engine, so
+// handshakes fail while the host has a digest session open.
+pub struct KernelIdentity;
+
+impl KernelIdentity {
+    // Run `f` with the identity private key. Key ladder outputs that are not
+    // valid P-256 keys (EINVAL from the dcrypto driver) are skipped by
+    // counting up in the context.
+    fn with_private_key<T>(f: impl Fn(&[u8; p256::SCALAR_LEN]) -> TockResult<T>)
+                           -> Result<T, SessionError> {
+        let mut context = [0u8; IDENTITY_CONTEXT.len() + 1];
+        context[..IDENTITY_CONTEXT.len()].copy_from_slice(IDENTITY_CONTEXT);
+        for counter in 0..=u8::MAX {
+            context[IDENTITY_CONTEXT.len()] = counter;
+            let mut key = [0u8; keyladder::KEY_LEN];
+            let result = keyladder::get().derive(KeyUsage::Attestation, &context, &mut key)
+                .and_then(|_| f(&key));
+            ecc::wipe(&mut key);
+            match result {
+                Err(ref err) if syscall_error::error_code(err) == Some(ErrorCode::Invalid) => {}
+                result => return result.map_err(|_| SessionError::Crypto),
+            }
+        }
+        Err(SessionError::Crypto)
+    }
+}
+
+impl Identity for KernelIdentity {
+    fn public_key(&self) -> Result<[u8; IDENTITY_KEY_LEN], SessionError> {
+        Self::with_private_key(|key| p256::get().public_key(key))
+    }
+
+    fn sign(&self, digest: &[u8; DIGEST_LEN]) -> Result<[u8; SIGNATURE_LEN], SessionError> {
+        Self::with_private_key(|key| p256::get().sign(key, digest))
+    }
+}
@@ -22,9 +22,17 @@ use libtock::result::TockResult;
 use libtock::shared_memory::SharedMemory;
 use libtock::syscalls;
 
+use spiutils::driver::spi_device::AccessMetrics;
+use spiutils::driver::spi_device::AccessRegion;
+use spiutils::driver::spi_device::ACCESS_REGION_LEN;
+use spiutils::driver::spi_device::MAX_ACCESS_REGIONS;
 use spiutils::driver::spi_device::AddressConfig;
 use spiutils::driver::spi_device::ADDRESS_CONFIG_LEN;
+use spiutils::driver::spi_device::DeniedAccessResponse;
+use spiutils::driver::spi_device::EmulatedOperation;
 use spiutils::driver::spi_device::HandlerMode;
+use spiutils::driver::spi_device::PassthroughFilterAction;
+use spiutils::driver::spi_device::RxBufferMode;
 use spiutils::io::Cursor;
 use spiutils::protocol::flash::AddressMode;
 use spiutils::protocol::wire::ToWire;
@@ -58,15 +66,32 @@ pub trait SpiDevice {
     fn end_transaction_with_data(&self, write_buffer: &mut[u8], clear_busy: bool, clear_write_enable: bool)
     -> TockResult<()>;
 
+    /// End the transaction, put a payload into the mailbox for the SPI host and clear the BUSY
+    /// and/or WRITE_ENABLE bits. `take_mailbox_read` tells when the SPI host has read it.
+    fn end_transaction_with_mailbox_data(&self, mailbox_buffer: &mut[u8], clear_busy: bool, clear_write_enable: bool)
+    -> TockResult<()>;
+
+    /// Check whether the SPI host has read the last mailbox payload since the last call.
+    fn take_mailbox_read(&self) -> bool;
+
     /// Configure the engine's address mode.
     fn set_address_mode(&self, address_mode: AddressMode) -> TockResult<()>;
 
     /// Get the engine's address mode.
     fn get_address_mode(&self) -> AddressMode;
 
+    /// Get the multi-line fast reads passed through to the external flash,
+    /// as `READ_CAPABILITY_*` bits.
+    fn get_read_capabilities(&self) -> TockResult<u32>;
+
     /// Set handling mode for address mode changes.
     fn set_address_mode_handling(&self, address_mode_handling: HandlerMode) -> TockResult<()>;
 
+    /// Configure who owns the read buffer between transactions. With
+    /// RxBufferMode::Handoff, the kernel does not touch the buffer until the
+    /// transaction is ended.
+    fn set_rx_buffer_mode(&self, rx_buffer_mode: RxBufferMode) -> TockResult<()>;
+
     /// Set the JEDEC ID data.
     fn set_jedec_id(&self, data: &mut[u8]) -> TockResult<()>;
 
@@ -75,6 +100,49 @@ pub trait SpiDevice {
 
     /// Configure SPI addresses.
     fn configure_addresses(&self, address_config: AddressConfig) -> TockResult<()>;
+
+    /// Configure the access map for the SPI address space.
+    fn set_access_regions(&self, regions: &[AccessRegion]) -> TockResult<()>;
+
+    /// Configure how accesses denied by the access map are answered.
+    fn set_denied_access_response(&self, response: DeniedAccessResponse) -> TockResult<()>;
+
+    /// Get the counters for accesses denied by the access map.
+    fn get_access_metrics(&self) -> TockResult<AccessMetrics>;
+
+    /// Ask the kernel to check for a transaction that is already latched
+    /// instead of waiting for the next interrupt. If there is one, its
+    /// data received callback is delivered on the next yield.
+    ///
+    /// Returns whether a transaction was pending.
+    fn kick(&self) -> TockResult<bool>;
+
+    /// Keep the BUSY bit set for at least `latency_us` after the SPI host
+    /// sends a command performing `operation`. A latency of 0 turns the
+    /// emulation off.
+    fn set_emulated_latency(&self, operation: EmulatedOperation, latency_us: u32) -> TockResult<()>;
+
+    /// Configure who handles program and erase commands. With
+    /// HandlerMode::KernelSpace, the kernel applies those for its flash
+    /// region, which starts at `spi_base` on the SPI bus, and the app never
+    /// sees them.
+    fn set_program_erase_handling(&self, handler_mode: HandlerMode, spi_base: u32) -> TockResult<()>;
+
+    /// Get the status register as the SPI host reads it, including BUSY and
+    /// WEL.
+    fn get_status(&self) -> TockResult<u8>;
+
+    /// Set the status register, e.g. the block protect bits. BUSY and WEL
+    /// are left alone; use `end_transaction_with_status` to clear them.
+    fn set_status(&self, status: u8) -> TockResult<()>;
+
+    /// Configure what SPI passthrough does with commands with `op_code`.
+    /// Commands are still delivered to the app as before.
+    fn set_passthrough_filter(&self, op_code: u8, action: PassthroughFilterAction) -> TockResult<()>;
+
+    /// Get the number of address mode switches since boot, whether made by
+    /// the SPI host (EN4B/EX4B) or with `set_address_mode`.
+    fn get_address_mode_switches(&self) -> TockResult<usize>;
 }
 
 // Get the static SpiDevice object.
@@ -94,16 +162,32 @@ mod command_nr {
     pub const SET_JEDEC_ID: usize = 6;
     pub const SET_SFDP: usize = 7;
     pub const CONFIGURE_ADDRESSES: usize = 8;
+    pub const SET_ACCESS_REGIONS: usize = 9;
+    pub const SET_DENIED_ACCESS_RESPONSE: usize = 10;
+    pub const GET_ACCESS_METRICS: usize = 11;
+    pub const KICK: usize = 12;
+    pub const SET_RX_BUFFER_MODE: usize = 13;
+    pub const RELEASE_RX_BUFFER: usize = 14;
+    pub const SET_EMULATED_LATENCY: usize = 15;
+    pub const SET_PROGRAM_ERASE_HANDLING: usize = 16;
+    pub const GET_STATUS: usize = 17;
+    pub const SET_STATUS: usize = 18;
+    pub const SET_PASSTHROUGH_FILTER: usize = 19;
+    pub const SEND_MAILBOX_DATA: usize = 20;
+    pub const GET_ADDRESS_MODE_SWITCHES: usize = 21;
+    pub const GET_READ_CAPABILITIES: usize = 22;
 }
 
 mod subscribe_nr {
     pub const DATA_RECEIVED: usize = 0;
     pub const ADDRESS_MODE_CHANGED: usize = 1;
+    pub const MAILBOX_READ: usize = 2;
 }
 
 mod allow_nr {
     pub const WRITE_BUFFER: usize = 0;
     pub const READ_BUFFER: usize = 1;
+    pub const MAILBOX_BUFFER: usize = 2;
 }
 
 struct SpiDeviceImpl {
@@ -124,6 +208,12 @@ struct SpiDeviceImpl {
 
     /// The current address mode
     address_mode: Cell<AddressMode>,
+
+    /// Who owns read_buffer between transactions.
+    rx_buffer_mode: Cell<RxBufferMode>,
+
+    /// Whether the SPI host has read the last mailbox payload.
+    mailbox_read: Cell<bool>,
 }
 
 static mut SPI_DEVICE: SpiDeviceImpl = SpiDeviceImpl {
@@ -133,6 +223,8 @@ static mut SPI_DEVICE: SpiDeviceImpl = SpiDeviceImpl {
     is_busy_set: Cell::new(false),
     is_write_enable_set: Cell::new(false),
     address_mode: Cell::new(AddressMode::ThreeByte),
+    rx_buffer_mode: Cell::new(RxBufferMode::Copy),
+    mailbox_read: Cell::new(false),
 };
 
 static mut IS_INITIALIZED: bool = false;
@@ -176,6 +268,12 @@ impl SpiDeviceImpl {
             SpiDeviceImpl::data_received_trampoline,
             0)?;
 
+        syscalls::subscribe_fn(
+            DRIVER_NUMBER,
+            subscribe_nr::MAILBOX_READ,
+            SpiDeviceImpl::mailbox_read_trampoline,
+            0)?;
+
         Ok(())
     }
 
@@ -206,10 +304,23 @@ impl SpiDeviceImpl {
         }
     }
 
+    extern "C"
+    fn mailbox_read_trampoline(_arg1: usize, _arg2: usize, _arg3: usize, _data: usize) {
+        get_impl().mailbox_read.set(true);
+    }
+
     /// Clear the current received transaction.
     fn clear_transaction(&self) {
         self.received_len.set(0);
     }
+
+    /// Hand read_buffer back to the kernel once the transaction has ended.
+    fn release_read_buffer(&self) -> TockResult<()> {
+        if self.rx_buffer_mode.get() == RxBufferMode::Handoff {
+            syscalls::command(DRIVER_NUMBER, command_nr::RELEASE_RX_BUFFER, 0, 0)?;
+        }
+        Ok(())
+    }
 }
 
 impl SpiDevice for SpiDeviceImpl {
@@ -231,6 +342,9 @@ impl SpiDevice for SpiDeviceImpl {
 
     fn end_transaction(&self) {
         self.clear_transaction();
+
+        // Ignore the error. There's nothing we can do here anyway.
+        let _ = self.release_read_buffer();
     }
 
     fn end_transaction_with_status(&self, clear_busy: bool, clear_write_enable: bool) -> TockResult<()> {
@@ -239,20 +353,43 @@ impl SpiDevice for SpiDeviceImpl {
         syscalls::command(DRIVER_NUMBER, command_nr::CLEAR_STATUS,
             if clear_busy { 1 } else { 0 },
             if clear_write_enable { 1 } else { 0 })?;
-        Ok(())
+
+        self.release_read_buffer()
     }
 
     fn end_transaction_with_data(&self, write_buffer: &mut[u8], clear_busy: bool, clear_write_enable: bool) -> TockResult<()> {
         self.clear_transaction();
 
-        // We want this to go out of scope after executing the command
-        let _write_buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, write_buffer)?;
+        {
+            // We want this to go out of scope after executing the command
+            let _write_buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, write_buffer)?;
 
-        syscalls::command(DRIVER_NUMBER, command_nr::SEND_DATA,
-            if clear_busy { 1 } else { 0 },
-            if clear_write_enable { 1 } else { 0 })?;
+            syscalls::command(DRIVER_NUMBER, command_nr::SEND_DATA,
+                if clear_busy { 1 } else { 0 },
+                if clear_write_enable { 1 } else { 0 })?;
+        }
 
-        Ok(())
+        self.release_read_buffer()
+    }
+
+    fn end_transaction_with_mailbox_data(&self, mailbox_buffer: &mut[u8], clear_busy: bool, clear_write_enable: bool) -> TockResult<()> {
+        self.clear_transaction();
+        self.mailbox_read.set(false);
+
+        {
+            // We want this to go out of scope after executing the command
+            let _mailbox_buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::MAILBOX_BUFFER, mailbox_buffer)?;
+
+            syscalls::command(DRIVER_NUMBER, command_nr::SEND_MAILBOX_DATA,
+                if clear_busy { 1 } else { 0 },
+                if clear_write_enable { 1 } else { 0 })?;
+        }
+
+        self.release_read_buffer()
+    }
+
+    fn take_mailbox_read(&self) -> bool {
+        self.mailbox_read.replace(false)
     }
 
     fn set_address_mode(&self, address_mode: AddressMode) -> TockResult<()> {
@@ -271,6 +408,13 @@ impl SpiDevice for SpiDeviceImpl {
         Ok(())
     }
 
+    fn set_rx_buffer_mode(&self, rx_buffer_mode: RxBufferMode) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_RX_BUFFER_MODE, rx_buffer_mode as usize, 0)?;
+        self.rx_buffer_mode.set(rx_buffer_mode);
+
+        Ok(())
+    }
+
     fn set_jedec_id(&self, data: &mut[u8]) -> TockResult<()> {
         // We want this to go out of scope after executing the command
         let _write_buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, data)?;
@@ -309,4 +453,98 @@ impl SpiDevice for SpiDeviceImpl {
 
         Ok(())
     }
+
+    fn set_access_regions(&self, regions: &[AccessRegion]) -> TockResult<()> {
+        if regions.len() > MAX_ACCESS_REGIONS {
+            return Err(TockError::Format);
+        }
+
+        let mut buf = [0u8; MAX_ACCESS_REGIONS * ACCESS_REGION_LEN];
+
+        {
+            // Scope for cursor (which doesn't implement Drop).
+            // We need cursor to go out of scope so that we can use buf further down.
+            let mut cursor = Cursor::new(&mut buf);
+            for region in regions {
+                if region.to_wire(&mut cursor).is_err() {
+                    return Err(TockError::Format);
+                }
+            }
+        }
+
+        // We want this to go out of scope only AFTER executing the command,
+        // so assign it to an unused variable to keep the result object around.
+        let _write_buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::WRITE_BUFFER, &mut buf)?;
+
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_ACCESS_REGIONS, regions.len(), 0)?;
+
+        Ok(())
+    }
+
+    fn set_denied_access_response(&self, response: DeniedAccessResponse) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_DENIED_ACCESS_RESPONSE, response as usize, 0)?;
+
+        Ok(())
+    }
+
+    fn get_access_metrics(&self) -> TockResult<AccessMetrics> {
+        let denied_reads = syscalls::command(DRIVER_NUMBER, command_nr::GET_ACCESS_METRICS, 0, 0)?;
+        let denied_writes = syscalls::command(DRIVER_NUMBER, command_nr::GET_ACCESS_METRICS, 1, 0)?;
+
+        Ok(AccessMetrics {
+            denied_reads: denied_reads as u32,
+            denied_writes: denied_writes as u32,
+        })
+    }
+
+    fn kick(&self) -> TockResult<bool> {
+        let pending = syscalls::command(DRIVER_NUMBER, command_nr::KICK, 0, 0)?;
+
+        Ok(pending != 0)
+    }
+
+    fn set_emulated_latency(&self, operation: EmulatedOperation, latency_us: u32) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_EMULATED_LATENCY,
+                          operation as usize, latency_us as usize)?;
+
+        Ok(())
+    }
+
+    fn set_program_erase_handling(&self, handler_mode: HandlerMode, spi_base: u32) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_PROGRAM_ERASE_HANDLING,
+                          handler_mode as usize, spi_base as usize)?;
+
+        Ok(())
+    }
+
+    fn get_status(&self) -> TockResult<u8> {
+        let status = syscalls::command(DRIVER_NUMBER, command_nr::GET_STATUS, 0, 0)?;
+
+        Ok(status as u8)
+    }
+
+    fn set_status(&self, status: u8) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_STATUS, status as usize, 0)?;
+
+        Ok(())
+    }
+
+    fn set_passthrough_filter(&self, op_code: u8, action: PassthroughFilterAction) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_PASSTHROUGH_FILTER,
+                          op_code as usize, action as usize)?;
+
+        Ok(())
+    }
+
+    fn get_address_mode_switches(&self) -> TockResult<usize> {
+        let switches = syscalls::command(DRIVER_NUMBER, command_nr::GET_ADDRESS_MODE_SWITCHES, 0, 0)?;
+
+        Ok(switches)
+    }
+
+    fn get_read_capabilities(&self) -> TockResult<u32> {
+        let capabilities = syscalls::command(DRIVER_NUMBER, command_nr::GET_READ_CAPABILITIES, 0, 0)?;
+
+        Ok(capabilities as u32)
+    }
 }
@@ -14,23 +14,32 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::digest;
+use crate::digest::DigestMode;
 use crate::firmware_controller::FirmwareController;
 use crate::globalsec;
 use crate::manticore_support;
 use crate::reset;
+use crate::rng;
+use crate::session_crypto::KernelCrypto;
+use crate::session_crypto::KernelIdentity;
 use crate::spi_host;
 use crate::spi_host_h1;
 use crate::spi_device;
+use crate::timebase;
 
 use core::cmp::min;
 use core::convert::TryFrom;
 
 use libtock::println;
 use libtock::result::TockError;
+use libtock::result::TockResult;
 
 use spiutils::io::Cursor as SpiutilsCursor;
 use spiutils::io::Write as SpiutilsWrite;
 use spiutils::driver::firmware::SegmentInfo;
+use spiutils::protocol::digest as spi_digest;
+use spiutils::protocol::digest::Message as DigestMessage;
 use spiutils::protocol::error;
 use spiutils::protocol::error::Message as ErrorMessage;
 use spiutils::protocol::firmware;
@@ -40,10 +49,17 @@ use spiutils::protocol::flash::Address;
 use spiutils::protocol::flash::AddressMode;
 use spiutils::protocol::flash::OpCode;
 use spiutils::protocol::payload;
+use spiutils::protocol::session;
+use spiutils::protocol::session::Message as SessionMessage;
+use spiutils::protocol::time;
 use spiutils::protocol::wire::FromWire;
 use spiutils::protocol::wire::FromWireError;
 use spiutils::protocol::wire::ToWire;
 use spiutils::protocol::wire::ToWireError;
+use spiutils::session::Policy as SessionPolicy;
+use spiutils::session::Session;
+use spiutils::session::SessionError;
+use spiutils::session::PRIVATE_KEY_LEN;
 
 // Size of the SPI flash chip.
 // Hard-coded to 64 MiB for now.
@@ -61,9 +77,14 @@ const SPI_MAILBOX_SIZE: u32 = spi_device::MAX_READ_BUFFER_SIZE as u32;
 pub enum SpiProcessorError {
     FromWire(FromWireError),
     ToWire(ToWireError),
-    Tock,
+    Tock(TockError),
     Manticore(manticore_support::HandlerError),
     UnsupportedFirmwareOperation(firmware::ContentType),
+    UnsupportedTimeOperation(time::ContentType),
+    UnsupportedSessionOperation(session::ContentType),
+    UnsupportedDigestOperation(spi_digest::ContentType),
+    Session(SessionError),
+    NoSession,
     UnsupportedOpCode(OpCode),
     InvalidAddress(Option<u32>),
     Format(core::fmt::Error),
@@ -82,8 +103,8 @@ impl From<ToWireError> for SpiProcessorError {
 }
 
 impl From<TockError> for SpiProcessorError {
-    fn from(_err: TockError) -> Self {
-        SpiProcessorError::Tock
+    fn from(err: TockError) -> Self {
+        SpiProcessorError::Tock(err)
     }
 }
 
@@ -93,6 +114,12 @@ impl From<manticore_support::HandlerError> for SpiProcessorError {
     }
 }
 
+impl From<SessionError> for SpiProcessorError {
+    fn from(err: SessionError) -> Self {
+        SpiProcessorError::Session(err)
+    }
+}
+
 impl From<core::fmt::Error> for SpiProcessorError {
     fn from(err: core::fmt::Error) -> Self {
         SpiProcessorError::Format(err)
@@ -101,6 +128,16 @@ impl From<core::fmt::Error> for SpiProcessorError {
 
 //////////////////////////////////////////////////////////////////////////////
 
+// A digest session the BMC started over the mailbox.
+pub struct DigestSession {
+    pub id: u32,
+
+    pub algorithm: spi_digest::Algorithm,
+
+    // The number of bytes hashed so far. The next chunk must start here.
+    pub hashed_len: u32,
+}
+
 pub struct SpiProcessor<'a> {
     pub manticore_handler: manticore_support::Handler<'a>,
 
@@ -108,43 +145,184 @@ pub struct SpiProcessor<'a> {
     pub print_flash_headers: bool,
 
     pub firmware: FirmwareController,
+
+    // When plaintext mailbox payloads are rejected.
+    pub session_policy: SessionPolicy,
+
+    // Whether the chip has been provisioned (see SessionPolicy). Read at
+    // boot, so provisioning takes effect with the next reset.
+    pub provisioned: bool,
+
+    // The current session, if the host has completed a handshake.
+    pub session: Option<Session>,
+
+    // Whether the payload being processed came in a session record. If so,
+    // responses are sealed, too.
+    pub in_session_record: bool,
+
+    // The digest session in progress, if any. The hash engine cannot save
+    // its state, so there is only one.
+    pub digest_session: Option<DigestSession>,
+
+    // The id the next digest session gets.
+    pub next_digest_session_id: u32,
+
+    // Whether the payload being answered had a header CRC. If so, the
+    // response has one, too.
+    pub header_crc: bool,
+
+    // The length of the response in FRAGMENT_TX_BUF, if it was too large
+    // for the mailbox. The host asks for its fragments one by one.
+    pub fragmented_response_len: Option<usize>,
+
+    // Whether the payload being processed was reassembled from fragments.
+    // Fragments cannot be nested.
+    pub in_reassembled_message: bool,
 }
 
-const SPI_TX_BUF_SIZE : usize = 512;
+// The longest request or response. Longer than the mailbox, since messages
+// can be sent in fragments.
+const MAX_MESSAGE_LEN : usize = 1024;
+
+const SPI_TX_BUF_SIZE : usize = MAX_MESSAGE_LEN;
 
 // TODO(osk): We need to have this tx_buf somewhere, but putting it on the stack
 // doesn't work, since that's currently limited to 2048 bytes. Declaring it
 // static here for now until we have a better place for it to live.
 static mut SPI_TX_BUF : [u8; SPI_TX_BUF_SIZE] = [0xff; SPI_TX_BUF_SIZE];
 
+// Buffers for the sealed response and the opened request of a session record.
+// Static for the same reason as SPI_TX_BUF.
+static mut SESSION_TX_BUF : [u8; SPI_TX_BUF_SIZE] = [0xff; SPI_TX_BUF_SIZE];
+static mut SESSION_RX_BUF : [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+
+// The response that is sent in fragments and the request being reassembled
+// from them. Static for the same reason as SPI_TX_BUF.
+static mut FRAGMENT_TX_BUF : [u8; MAX_MESSAGE_LEN] = [0xff; MAX_MESSAGE_LEN];
+static mut REASSEMBLER : payload::Reassembler<MAX_MESSAGE_LEN> = payload::Reassembler::new();
+
 pub type SpiProcessorResult<T> = Result<T, SpiProcessorError>;
 
 impl<'a> SpiProcessor<'a> {
 
-    fn send_data(&mut self, content_type: payload::ContentType, content_len: u16, tx_buf: &mut[u8]) -> SpiProcessorResult<()> {
+    // The length of the payload header of responses. Content starts here.
+    fn payload_header_len(&self) -> usize {
+        if self.header_crc {
+            payload::HEADER_LEN + payload::HEADER_CRC_LEN
+        } else {
+            payload::HEADER_LEN
+        }
+    }
+
+    fn write_payload_header(&self, content_type: payload::ContentType, content_len: u16, tx_buf: &mut[u8]) -> SpiProcessorResult<()> {
         let mut header = payload::Header {
             content: content_type,
             content_len: content_len,
             checksum: 0,
+            header_crc: None,
         };
-        header.checksum = payload::compute_checksum(&header, &tx_buf[payload::HEADER_LEN..]);
-        {
-            // Scope for tx_cursor.
-            // We need tx_cursor to go out of scope so that we can use tx_buf further down.
-            let tx_cursor = SpiutilsCursor::new(tx_buf);
-            header.to_wire(tx_cursor)?;
+        header.checksum = payload::compute_checksum(&header, &tx_buf[self.payload_header_len()..]);
+        if self.header_crc {
+            header.header_crc = Some(payload::compute_header_crc(&header));
         }
-        spi_device::get().end_transaction_with_data(
-            &mut tx_buf[..payload::HEADER_LEN + content_len as usize], true, true)?;
+        let tx_cursor = SpiutilsCursor::new(tx_buf);
+        header.to_wire(tx_cursor)?;
+        Ok(())
+    }
 
+    fn send_data(&mut self, content_type: payload::ContentType, content_len: u16, tx_buf: &mut[u8]) -> SpiProcessorResult<()> {
+        self.write_payload_header(content_type, content_len, tx_buf)?;
+        let payload_len = self.payload_header_len() + content_len as usize;
+        if self.in_session_record {
+            // The request came in a session record, so the response goes
+            // back in one, too.
+            return self.send_record(&tx_buf[..payload_len]);
+        }
+        self.send_payload(&mut tx_buf[..payload_len])
+    }
+
+    // Put a complete payload into the mailbox. A payload too large for the
+    // mailbox is kept, and its first fragment is sent instead.
+    fn send_payload(&mut self, payload: &mut [u8]) -> SpiProcessorResult<()> {
+        if payload.len() <= SPI_MAILBOX_SIZE as usize {
+            self.fragmented_response_len = None;
+            spi_device::get().end_transaction_with_mailbox_data(payload, true, true)?;
+            return Ok(());
+        }
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing FRAGMENT_TX_BUF as &mut.
+            FRAGMENT_TX_BUF.get_mut(..payload.len())
+                .ok_or(SpiProcessorError::FromWire(FromWireError::OutOfRange))?
+                .copy_from_slice(payload);
+        }
+        self.fragmented_response_len = Some(payload.len());
+        self.send_response_fragment(0)
+    }
+
+    // Send fragment `sequence` of the response kept by send_payload.
+    fn send_response_fragment(&mut self, sequence: u8) -> SpiProcessorResult<()> {
+        let header_len = self.payload_header_len();
+        let max_data_len = SPI_MAILBOX_SIZE as usize - header_len - payload::FRAGMENT_HEADER_LEN;
+        let payload_len : u16;
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing FRAGMENT_TX_BUF and SPI_TX_BUF as &mut.
+            let fragment = self.fragmented_response_len.and_then(
+                |len| payload::fragment(&FRAGMENT_TX_BUF[..len], max_data_len, sequence));
+            let (fragment_header, data) = match fragment {
+                Some(fragment) => fragment,
+                None => return self.send_error(error::BadFragment {}),
+            };
+            let mut tx_cursor = SpiutilsCursor::new(&mut SPI_TX_BUF[header_len..]);
+            fragment_header.to_wire(&mut tx_cursor)?;
+            tx_cursor.write_bytes(data)
+                .map_err(|err| SpiProcessorError::ToWire(ToWireError::Io(err)))?;
+            payload_len = u16::try_from(tx_cursor.consumed_len())
+                .map_err(|_| SpiProcessorError::FromWire(FromWireError::OutOfRange))?;
+        }
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
+            self.write_payload_header(payload::ContentType::Fragment, payload_len, &mut SPI_TX_BUF)?;
+            spi_device::get().end_transaction_with_mailbox_data(
+                &mut SPI_TX_BUF[..header_len + payload_len as usize], true, true)?;
+        }
         Ok(())
     }
 
+    // Seal a complete payload (header and content) into a session record and send it.
+    fn send_record(&mut self, inner_payload: &[u8]) -> SpiProcessorResult<()> {
+        let header_len = self.payload_header_len();
+        let session = self.session.as_mut().ok_or(SpiProcessorError::NoSession)?;
+        let payload_len : u16;
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SESSION_TX_BUF as &mut.
+            let record_offset = header_len + session::HEADER_LEN;
+            let data_offset = record_offset + session::RECORD_LEN;
+            let (sequence, sealed_len) = session.seal(&KernelCrypto, inner_payload, &mut SESSION_TX_BUF[data_offset..])?;
+            {
+                let mut tx_cursor = SpiutilsCursor::new(&mut SESSION_TX_BUF[header_len..data_offset]);
+                let header = session::Header {
+                    content: session::ContentType::Record,
+                };
+                header.to_wire(&mut tx_cursor)?;
+                tx_cursor.write_be(sequence)
+                    .map_err(|err| SpiProcessorError::ToWire(ToWireError::Io(err)))?;
+            }
+            payload_len = u16::try_from(session::HEADER_LEN + session::RECORD_LEN + sealed_len)
+                .map_err(|_| SpiProcessorError::FromWire(FromWireError::OutOfRange))?;
+        }
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SESSION_TX_BUF as &mut.
+            self.write_payload_header(payload::ContentType::Session, payload_len, &mut SESSION_TX_BUF)?;
+            self.send_payload(&mut SESSION_TX_BUF[..header_len + payload_len as usize])
+        }
+    }
+
     fn send_error<'m, M: ErrorMessage<'m>>(&mut self, msg: M) -> SpiProcessorResult<()> {
         let payload_len : u16;
         unsafe {
             // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
-            let mut tx_cursor = SpiutilsCursor::new(&mut SPI_TX_BUF[payload::HEADER_LEN..]);
+            let header_len = self.payload_header_len();
+            let mut tx_cursor = SpiutilsCursor::new(&mut SPI_TX_BUF[header_len..]);
 
             let header = error::Header {
                 content: M::TYPE
@@ -166,7 +344,8 @@ impl<'a> SpiProcessor<'a> {
         {
             unsafe {
                 // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
-                let manticore_len = self.manticore_handler.process_request(&data, &mut SPI_TX_BUF[payload::HEADER_LEN..])?;
+                let header_len = self.payload_header_len();
+                let manticore_len = self.manticore_handler.process_request(&data, &mut SPI_TX_BUF[header_len..])?;
                 payload_len = u16::try_from(manticore_len)
                     .map_err(|_| SpiProcessorError::FromWire(FromWireError::OutOfRange))?;
             }
@@ -182,7 +361,8 @@ impl<'a> SpiProcessor<'a> {
         let payload_len : u16;
         unsafe {
             // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
-            let mut tx_cursor = SpiutilsCursor::new(&mut SPI_TX_BUF[payload::HEADER_LEN..]);
+            let header_len = self.payload_header_len();
+            let mut tx_cursor = SpiutilsCursor::new(&mut SPI_TX_BUF[header_len..]);
 
             let fw_header = firmware::Header {
                 content: M::TYPE
@@ -343,19 +523,335 @@ impl<'a> SpiProcessor<'a> {
         result
     }
 
-    fn process_spi_payload(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
-        let header = payload::Header::from_wire(&mut data)?;
-        if header.checksum != payload::compute_checksum(&header, data) {
-            let error = error::BadChecksum {};
+    fn send_time_response<'m, M: time::Message<'m>>(&mut self, response: M) -> SpiProcessorResult<()> {
+        let payload_len : u16;
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
+            let header_len = self.payload_header_len();
+            let mut tx_cursor = SpiutilsCursor::new(&mut SPI_TX_BUF[header_len..]);
+
+            let time_header = time::Header {
+                content: M::TYPE
+            };
+            time_header.to_wire(&mut tx_cursor)?;
+            response.to_wire(&mut tx_cursor)?;
+            payload_len = u16::try_from(tx_cursor.consumed_len())
+                .map_err(|_| SpiProcessorError::FromWire(FromWireError::OutOfRange))?;
+        }
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
+            self.send_data(payload::ContentType::Time, payload_len, &mut SPI_TX_BUF)?;
+        }
+        Ok(())
+    }
+
+    fn process_time_set(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let req = time::SetTimeRequest::from_wire(&mut data)?;
+
+        let result = match timebase::get().set_unix_time_ms(req.unix_time_ms) {
+            Ok(()) => time::SetTimeResult::Success,
+            Err(_) => time::SetTimeResult::Error,
+        };
+
+        let response = time::SetTimeResponse {
+            unix_time_ms: req.unix_time_ms,
+            result: result,
+        };
+        self.send_time_response(response)
+    }
+
+    fn process_time(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let header = time::Header::from_wire(&mut data)?;
+
+        match header.content {
+            time::ContentType::SetTimeRequest => {
+                self.process_time_set(&mut data)
+            },
+            _ => {
+                Err(SpiProcessorError::UnsupportedTimeOperation(header.content))
+            }
+        }
+    }
+
+    fn send_digest_response<'m, M: DigestMessage<'m>>(&mut self, response: M) -> SpiProcessorResult<()> {
+        let payload_len : u16;
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
+            let header_len = self.payload_header_len();
+            let mut tx_cursor = SpiutilsCursor::new(&mut SPI_TX_BUF[header_len..]);
+
+            let digest_header = spi_digest::Header {
+                content: M::TYPE
+            };
+            digest_header.to_wire(&mut tx_cursor)?;
+            response.to_wire(&mut tx_cursor)?;
+            payload_len = u16::try_from(tx_cursor.consumed_len())
+                .map_err(|_| SpiProcessorError::FromWire(FromWireError::OutOfRange))?;
+        }
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
+            self.send_data(payload::ContentType::Digest, payload_len, &mut SPI_TX_BUF)?;
+        }
+        Ok(())
+    }
+
+    // Finish the digest session in progress, if any, and return its digest.
+    fn finish_digest_session(&mut self, output: &mut [u8]) -> Option<(DigestSession, TockResult<()>)> {
+        let session = self.digest_session.take()?;
+        let len = session.algorithm.digest_len();
+        let result = digest::get().finalize(&mut output[..len]);
+        Some((session, result))
+    }
+
+    fn process_digest_init(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let req = spi_digest::InitRequest::from_wire(&mut data)?;
+
+        // Abandon the session in progress to free the engine.
+        let mut discarded = [0u8; spi_digest::MAX_DIGEST_LEN];
+        let _ = self.finish_digest_session(&mut discarded);
+
+        let mode = match req.algorithm {
+            spi_digest::Algorithm::Sha1 => DigestMode::Sha1,
+            spi_digest::Algorithm::Sha256 => DigestMode::Sha256,
+        };
+        let result = if digest::get().is_busy() {
+            spi_digest::DigestResult::Busy
+        } else if digest::get().initialize(mode).is_err() {
+            spi_digest::DigestResult::Error
+        } else {
+            spi_digest::DigestResult::Success
+        };
+
+        let mut session_id = 0;
+        if result == spi_digest::DigestResult::Success {
+            session_id = self.next_digest_session_id;
+            self.next_digest_session_id = self.next_digest_session_id.wrapping_add(1);
+            self.digest_session = Some(DigestSession {
+                id: session_id,
+                algorithm: req.algorithm,
+                hashed_len: 0,
+            });
+        }
+
+        let response = spi_digest::InitResponse {
+            session_id: session_id,
+            result: result,
+        };
+        self.send_digest_response(response)
+    }
+
+    fn process_digest_update(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let req = spi_digest::UpdateRequest::from_wire(&mut data)?;
+
+        let (result, hashed_len) = match self.digest_session.as_mut() {
+            Some(session) if session.id == req.session_id => {
+                if req.offset != session.hashed_len {
+                    (spi_digest::DigestResult::InvalidOffset, session.hashed_len)
+                } else if digest::get().update(req.data).is_err() {
+                    (spi_digest::DigestResult::Error, session.hashed_len)
+                } else {
+                    session.hashed_len += req.data.len() as u32;
+                    (spi_digest::DigestResult::Success, session.hashed_len)
+                }
+            },
+            _ => (spi_digest::DigestResult::InvalidSession, 0),
+        };
+
+        let response = spi_digest::UpdateResponse {
+            session_id: req.session_id,
+            hashed_len: hashed_len,
+            result: result,
+        };
+        self.send_digest_response(response)
+    }
+
+    fn process_digest_final(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let req = spi_digest::FinalRequest::from_wire(&mut data)?;
+
+        let mut output = [0u8; spi_digest::MAX_DIGEST_LEN];
+        let mut digest_len = 0;
+        let result = if self.digest_session.as_ref().map(|session| session.id) != Some(req.session_id) {
+            spi_digest::DigestResult::InvalidSession
+        } else {
+            match self.finish_digest_session(&mut output) {
+                Some((session, Ok(()))) => {
+                    digest_len = session.algorithm.digest_len();
+                    spi_digest::DigestResult::Success
+                },
+                _ => spi_digest::DigestResult::Error,
+            }
+        };
+
+        let response = spi_digest::FinalResponse {
+            session_id: req.session_id,
+            result: result,
+            digest: &output[..digest_len],
+        };
+        self.send_digest_response(response)
+    }
+
+    fn process_digest(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let header = spi_digest::Header::from_wire(&mut data)?;
+
+        match header.content {
+            spi_digest::ContentType::InitRequest => {
+                self.process_digest_init(&mut data)
+            },
+            spi_digest::ContentType::UpdateRequest => {
+                self.process_digest_update(&mut data)
+            },
+            spi_digest::ContentType::FinalRequest => {
+                self.process_digest_final(&mut data)
+            },
+            _ => {
+                Err(SpiProcessorError::UnsupportedDigestOperation(header.content))
+            }
+        }
+    }
+
+    fn send_session_response<'m, M: SessionMessage<'m>>(&mut self, response: M) -> SpiProcessorResult<()> {
+        let payload_len : u16;
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
+            let header_len = self.payload_header_len();
+            let mut tx_cursor = SpiutilsCursor::new(&mut SPI_TX_BUF[header_len..]);
+
+            let session_header = session::Header {
+                content: M::TYPE
+            };
+            session_header.to_wire(&mut tx_cursor)?;
+            response.to_wire(&mut tx_cursor)?;
+            payload_len = u16::try_from(tx_cursor.consumed_len())
+                .map_err(|_| SpiProcessorError::FromWire(FromWireError::OutOfRange))?;
+        }
+        unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SPI_TX_BUF as &mut.
+            self.send_data(payload::ContentType::Session, payload_len, &mut SPI_TX_BUF)?;
+        }
+        Ok(())
+    }
+
+    fn process_session_handshake(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let req = session::HandshakeRequest::from_wire(&mut data)?;
+
+        let mut private_key = [0u8; PRIVATE_KEY_LEN];
+        let mut nonce = [0u8; session::NONCE_LEN];
+        rng::get().fill(&mut private_key)?;
+        rng::get().fill(&mut nonce)?;
+        let (response, new_session) = spiutils::session::respond(&KernelCrypto, &KernelIdentity, &req,
+                                                                      &private_key, &nonce);
+        ecc::wipe(&mut private_key);
+
+        // A new handshake always replaces the current session.
+        self.session = new_session;
+        self.send_session_response(response)
+    }
+
+    fn process_session_record(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let record = session::Record::from_wire(&mut data)?;
+        let inner_len = match self.session.as_mut() {
+            Some(current) => unsafe {
+                // TODO(osk): We need the unsafe block since we're accessing SESSION_RX_BUF as &mut.
+                current.open(&KernelCrypto, &record, &mut SESSION_RX_BUF)?
+            },
+            None => return self.send_error(error::SessionRequired {}),
+        };
+
+        self.in_session_record = true;
+        let result = unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing SESSION_RX_BUF.
+            self.process_spi_payload(&SESSION_RX_BUF[..inner_len])
+        };
+        self.in_session_record = false;
+        result
+    }
+
+    fn process_session(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let header = session::Header::from_wire(&mut data)?;
+
+        match header.content {
+            session::ContentType::HandshakeRequest => {
+                self.process_session_handshake(data)
+            },
+            session::ContentType::Record => {
+                self.process_session_record(data)
+            },
+            _ => {
+                Err(SpiProcessorError::UnsupportedSessionOperation(header.content))
+            }
+        }
+    }
+
+    fn process_fragment(&mut self, mut data: &[u8]) -> SpiProcessorResult<()> {
+        let header = payload::FragmentHeader::from_wire(&mut data)?;
+
+        // A fragment without data asks for a fragment of the response.
+        if data.is_empty() {
+            return self.send_response_fragment(header.sequence);
+        }
+
+        let message = unsafe {
+            // TODO(osk): We need the unsafe block since we're accessing REASSEMBLER as &mut.
+            match REASSEMBLER.add(&header, data) {
+                Ok(Some(message)) => message,
+                // Wait for the next fragment.
+                Ok(None) => return Ok(()),
+                Err(_) => return self.send_error(error::BadFragment {}),
+            }
+        };
+
+        self.in_reassembled_message = true;
+        let result = self.process_spi_payload(message);
+        self.in_reassembled_message = false;
+        result
+    }
+
+    fn process_spi_payload(&mut self, data: &[u8]) -> SpiProcessorResult<()> {
+        // Records answer in the format of the payload they came in.
+        if !self.in_session_record {
+            self.header_crc = payload::has_header_crc(data);
+        }
+        let (header, content) = match payload::parse_payload(data) {
+            Ok(parsed) => parsed,
+            Err(payload::PayloadError::Header(err)) => return Err(err.into()),
+            // A payload cut short cannot match the checksum it was sent with.
+            // With a bad header CRC, the host resends the whole payload.
+            Err(payload::PayloadError::Truncated) | Err(payload::PayloadError::BadChecksum)
+                | Err(payload::PayloadError::BadHeaderCrc) => {
+                let error = error::BadChecksum {};
+                return self.send_error(error);
+            }
+        };
+
+        if header.content == payload::ContentType::Fragment {
+            if self.in_session_record || self.in_reassembled_message {
+                let error = error::ContentTypeNotSupported {};
+                return self.send_error(error);
+            }
+            return self.process_fragment(content);
+        }
+
+        // Sessions cannot be nested.
+        if header.content == payload::ContentType::Session && !self.in_session_record {
+            return self.process_session(content);
+        }
+        if !self.in_session_record && self.session_policy.is_required(self.provisioned) {
+            let error = error::SessionRequired {};
             return self.send_error(error);
         }
 
         match header.content {
             payload::ContentType::Manticore => {
-                self.process_manticore(&data[..header.content_len as usize])
+                self.process_manticore(content)
             }
             payload::ContentType::Firmware => {
-                self.process_firmware(&data[..header.content_len as usize])
+                self.process_firmware(content)
+            }
+            payload::ContentType::Time => {
+                self.process_time(content)
+            }
+            payload::ContentType::Digest => {
+                self.process_digest(content)
             }
             _ => {
                 let error = error::ContentTypeNotSupported {};
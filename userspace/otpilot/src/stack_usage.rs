@@ -0,0 +1,101 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+pub trait StackUsage {
+    // Get the size of the kernel stack, in bytes.
+    fn get_kernel_size(&self) -> TockResult<usize>;
+
+    // Get the deepest the kernel stack has been since boot, in bytes.
+    fn get_kernel_used(&self) -> TockResult<usize>;
+
+    // Get the number of processes.
+    fn get_process_count(&self) -> TockResult<usize>;
+
+    // Get the number of bytes at the bottom of the memory of process `index`
+    // that have never been used.
+    fn get_process_unused(&self, index: usize) -> TockResult<usize>;
+
+    // Get the memory size of process `index`, in bytes.
+    fn get_process_size(&self, index: usize) -> TockResult<usize>;
+}
+
+// Get the static StackUsage object.
+pub fn get() -> &'static dyn StackUsage {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40110;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_KERNEL_SIZE: usize = 1;
+    pub const GET_KERNEL_USED: usize = 2;
+    pub const GET_PROCESS_COUNT: usize = 3;
+    pub const GET_PROCESS_UNUSED: usize = 4;
+    pub const GET_PROCESS_SIZE: usize = 5;
+}
+
+struct StackUsageImpl {}
+
+static mut STACK_USAGE: StackUsageImpl = StackUsageImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static StackUsageImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if STACK_USAGE.initialize().is_err() {
+                panic!("Could not initialize StackUsage");
+            }
+            IS_INITIALIZED = true;
+        }
+        &STACK_USAGE
+    }
+}
+
+impl StackUsageImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+impl StackUsage for StackUsageImpl {
+    fn get_kernel_size(&self) -> TockResult<usize> {
+        Ok(syscalls::command(DRIVER_NUMBER, command_nr::GET_KERNEL_SIZE, 0, 0)?)
+    }
+
+    fn get_kernel_used(&self) -> TockResult<usize> {
+        Ok(syscalls::command(DRIVER_NUMBER, command_nr::GET_KERNEL_USED, 0, 0)?)
+    }
+
+    fn get_process_count(&self) -> TockResult<usize> {
+        Ok(syscalls::command(DRIVER_NUMBER, command_nr::GET_PROCESS_COUNT, 0, 0)?)
+    }
+
+    fn get_process_unused(&self, index: usize) -> TockResult<usize> {
+        Ok(syscalls::command(DRIVER_NUMBER, command_nr::GET_PROCESS_UNUSED, index, 0)?)
+    }
+
+    fn get_process_size(&self, index: usize) -> TockResult<usize> {
+        Ok(syscalls::command(DRIVER_NUMBER, command_nr::GET_PROCESS_SIZE, index, 0)?)
+    }
+}
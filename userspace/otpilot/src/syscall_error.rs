@@ -0,0 +1,58 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use core::fmt;
+
+use errorcode::ErrorCode;
+
+use libtock::result::TockError;
+
+// Get the driver error behind a failed syscall, if the kernel returned one.
+// Apps can check `ErrorCode::is_retryable` on it.
+pub fn error_code(error: &TockError) -> Option<ErrorCode> {
+    ErrorCode::from_return_code(parts(error)?.3)
+}
+
+// Split a failed syscall into its kind, number, driver and return code.
+fn parts(error: &TockError) -> Option<(&'static str, usize, usize, isize)> {
+    match error {
+        TockError::Command(err) =>
+            Some(("command", err.command_number, err.driver_number, err.return_code)),
+        TockError::Subscribe(err) =>
+            Some(("subscribe", err.subscribe_number, err.driver_number, err.return_code)),
+        TockError::Allow(err) =>
+            Some(("allow", err.allow_number, err.driver_number, err.return_code)),
+        _ => None,
+    }
+}
+
+/// Prints a failed syscall along with what its error code means, e.g.
+/// "command 1 on driver 0x40170: EBUSY (-2): resource in use, retry later".
+pub struct Decoded<'a>(pub &'a TockError);
+
+impl fmt::Display for Decoded<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, number, driver, return_code) = match parts(self.0) {
+            Some(parts) => parts,
+            None => return write!(f, "{:?}", self.0),
+        };
+        write!(f, "{} {} on driver {:#x}: ", kind, number, driver)?;
+        match error_code(self.0) {
+            Some(code) => write!(f, "{}", code),
+            None => write!(f, "return code {}", return_code),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use core::mem;
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+pub trait Timebase {
+    /// Set the current UNIX time in milliseconds.
+    fn set_unix_time_ms(&self, unix_time_ms: u64) -> TockResult<()>;
+
+    /// Get the current approximate UNIX time in milliseconds.
+    /// Fails if the time was never set.
+    fn get_unix_time_ms(&self) -> TockResult<u64>;
+}
+
+// Get the static Timebase object.
+pub fn get() -> &'static dyn Timebase {
+    get_impl()
+}
+
+const DRIVER_NUMBER: usize = 0x40080;
+
+mod command_nr {
+    pub const CHECK_IF_PRESENT: usize = 0;
+    pub const GET_TIME: usize = 1;
+    pub const SET_TIME: usize = 2;
+}
+
+mod allow_nr {
+    pub const TIME_BUFFER: usize = 0;
+}
+
+struct TimebaseImpl {}
+
+static mut TIMEBASE: TimebaseImpl = TimebaseImpl {};
+
+static mut IS_INITIALIZED: bool = false;
+
+fn get_impl() -> &'static TimebaseImpl {
+    unsafe {
+        if !IS_INITIALIZED {
+            if TIMEBASE.initialize().is_err() {
+                panic!("Could not initialize Timebase");
+            }
+            IS_INITIALIZED = true;
+        }
+        &TIMEBASE
+    }
+}
+
+impl TimebaseImpl {
+    fn initialize(&'static mut self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CHECK_IF_PRESENT, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+impl Timebase for TimebaseImpl {
+    fn set_unix_time_ms(&self, unix_time_ms: u64) -> TockResult<()> {
+        let mut time_buffer = unix_time_ms.to_be_bytes();
+
+        // We want this to go out of scope after executing the command
+        let _time_buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::TIME_BUFFER, &mut time_buffer)?;
+
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_TIME, 0, 0)?;
+
+        Ok(())
+    }
+
+    fn get_unix_time_ms(&self) -> TockResult<u64> {
+        let mut time_buffer = [0u8; mem::size_of::<u64>()];
+
+        {
+            // We want this to go out of scope after executing the command
+            let _time_buffer_share = syscalls::allow(DRIVER_NUMBER, allow_nr::TIME_BUFFER, &mut time_buffer)?;
+
+            syscalls::command(DRIVER_NUMBER, command_nr::GET_TIME, 0, 0)?;
+        }
+
+        Ok(u64::from_be_bytes(time_buffer))
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use libtock::result::TockResult;
+use libtock::syscalls;
+
+pub trait UsbDebug {
+    /// Set which categories of USB driver debug messages the kernel prints.
+    /// Fails if the board has no USB driver or the kernel is a release build.
+    fn set_verbosity(&self, mask: u32) -> TockResult<()>;
+
+    /// Have the kernel print and clear its trace of USB driver events.
+    /// Fails if the board has no USB driver or does not trace it.
+    fn dump_trace(&self) -> TockResult<()>;
+
+    /// Have the kernel drop off the bus and connect again, so that the host
+    /// enumerates the device anew.
+    fn reconnect(&self) -> TockResult<()>;
+}
+
+// Get the static UsbDebug object.
+pub fn get() -> &'static dyn UsbDebug {
+    unsafe { &USB_DEBUG }
+}
+
+const DRIVER_NUMBER: usize = 0x20008;
+
+mod command_nr {
+    pub const SET_DEBUG_VERBOSITY: usize = 4;
+    pub const DUMP_TRACE: usize = 8;
+    pub const RECONNECT: usize = 9;
+}
+
+struct UsbDebugImpl {}
+
+// Not every board has USB, so unlike other drivers this one does not check
+// for the driver up front.
+static mut USB_DEBUG: UsbDebugImpl = UsbDebugImpl {};
+
+impl UsbDebug for UsbDebugImpl {
+    fn set_verbosity(&self, mask: u32) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_DEBUG_VERBOSITY, mask as usize, 0)?;
+
+        Ok(())
+    }
+
+    fn dump_trace(&self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::DUMP_TRACE, 0, 0)?;
+
+        Ok(())
+    }
+
+    fn reconnect(&self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::RECONNECT, 0, 0)?;
+
+        Ok(())
+    }
+}
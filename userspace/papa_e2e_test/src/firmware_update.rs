@@ -0,0 +1,63 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::firmware_controller::FirmwareController;
+use crate::flash;
+use crate::globalsec;
+use spiutils::protocol::firmware::SegmentAndLocation;
+use test::require;
+
+// Number of chunks in the staged image.
+const CHUNK_COUNT: usize = 4;
+
+fn pattern(chunk: usize, index: usize) -> u8 {
+    (chunk * 0x40 + index) as u8
+}
+
+/// Stages a small image in the inactive RW segment the way a firmware update
+/// does, then erases it again so the segment is left blank.
+#[test]
+fn stage_inactive_rw() -> bool {
+    let segment = globalsec::get().get_inactive_rw();
+    require!(segment.identifier == SegmentAndLocation::RwA ||
+             segment.identifier == SegmentAndLocation::RwB);
+
+    let mut controller = FirmwareController::new();
+    require!(controller.erase_segment(segment).is_ok());
+
+    let chunk_len = controller.get_max_write_chunk_length();
+    let mut chunk = [0u8; flash::MAX_BUFFER_LENGTH];
+    for index in 0..CHUNK_COUNT {
+        for (offset, byte) in chunk.iter_mut().enumerate() {
+            *byte = pattern(index, offset);
+        }
+        let verified = controller.write_and_verify_segment_chunk(
+            segment, index * chunk_len, &chunk[..chunk_len]);
+        require!(verified.unwrap_or(false));
+    }
+
+    // The rest of the segment must be untouched.
+    let mut buf = [0u8; flash::MAX_BUFFER_LENGTH];
+    let address = segment.address as usize + CHUNK_COUNT * chunk_len;
+    require!(flash::get().read(address, &mut buf, chunk_len).is_ok());
+    require!(buf[..chunk_len].iter().all(|byte| *byte == 0xff));
+
+    require!(controller.erase_segment(segment).is_ok());
+    let address = segment.address as usize;
+    require!(flash::get().read(address, &mut buf, chunk_len).is_ok());
+    require!(buf[..chunk_len].iter().all(|byte| *byte == 0xff));
+    true
+}
@@ -0,0 +1,62 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! End-to-end test of the papa platform.
+//!
+//! Exercises SPI passthrough, the mailbox and a firmware update against the
+//! real drivers. The bench has no SPI master attached to the SPI device, so
+//! the tests play the host's part in the app: they frame requests the way
+//! `spiutils` hosts do and feed them to the same handlers `otpilot` uses.
+
+#![no_std]
+
+// Rust complains that things are unused if they are only used when cfg(test) is
+// true. If we include modules when cfg(test) is false, then declarations in the
+// modules need to be marked #[cfg(test)]. Instead, we simply do not include the
+// code in other configs.
+
+// The drivers are shared with otpilot.
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../otpilot/src/firmware_controller.rs"]
+mod firmware_controller;
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../otpilot/src/flash.rs"]
+mod flash;
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../otpilot/src/globalsec.rs"]
+mod globalsec;
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../otpilot/src/manticore_support.rs"]
+mod manticore_support;
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../otpilot/src/spi_host.rs"]
+mod spi_host;
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../otpilot/src/spi_host_h1.rs"]
+mod spi_host_h1;
+
+#[cfg(test)]
+mod firmware_update;
+#[cfg(test)]
+mod mailbox;
+#[cfg(test)]
+mod passthrough;
@@ -0,0 +1,124 @@
+// Copyright 2020 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::manticore_support::Handler;
+use crate::manticore_support::Identity;
+use manticore::io::Cursor as ManticoreCursor;
+use manticore::mem::BumpArena;
+use manticore::protocol::firmware_version::FirmwareVersionRequest;
+use manticore::protocol::wire::FromWire as _;
+use manticore::protocol::wire::ToWire as _;
+use manticore::protocol::CommandType;
+use manticore::protocol::Header as ManticoreHeader;
+use manticore::protocol::HEADER_LEN as MANTICORE_HEADER_LEN;
+use spiutils::io::Cursor;
+use spiutils::io::Write as _;
+use spiutils::protocol::payload;
+use spiutils::protocol::wire::ToWire;
+use test::require;
+
+const BUF_LEN: usize = 256;
+
+const IDENTITY: Identity = Identity {
+    version: *b"papa_e2e_test version           ",
+    ro_version: [0x11; 32],
+    rw_version: [0x22; 32],
+    device_id: [0x33; 64],
+};
+
+/// Wraps `data` into a payload with a valid checksum, as the `spiutils` tool
+/// does. Returns the payload length.
+fn to_payload(content: payload::ContentType, data: &[u8], buf: &mut [u8]) -> Option<usize> {
+    let mut header = payload::Header {
+        content,
+        content_len: data.len() as u16,
+        checksum: 0,
+        header_crc: None,
+    };
+    header.checksum = payload::compute_checksum(&header, data);
+
+    let mut cursor = Cursor::new(buf);
+    header.to_wire(&mut cursor).ok()?;
+    cursor.write_bytes(data).ok()?;
+    Some(cursor.consumed_len())
+}
+
+/// Parses a payload, checking its checksum, and returns its header and content.
+fn from_payload(data: &[u8]) -> Option<(payload::Header, &[u8])> {
+    payload::parse_payload(data).ok()
+}
+
+/// Sends a firmware version request through the mailbox framing and checks
+/// the response, taking both the host's and otpilot's parts.
+#[test]
+fn firmware_version() -> bool {
+    // Host: encode the request.
+    let mut request = [0u8; BUF_LEN];
+    let request_len = {
+        let mut cursor = ManticoreCursor::new(&mut request);
+        let header = ManticoreHeader {
+            command: CommandType::FirmwareVersion,
+            is_request: true,
+        };
+        require!(header.to_wire(&mut cursor).is_ok());
+        require!(FirmwareVersionRequest { index: 0 }.to_wire(&mut cursor).is_ok());
+        cursor.consumed_len()
+    };
+    let mut mailbox = [0u8; BUF_LEN];
+    let mailbox_len = match to_payload(
+        payload::ContentType::Manticore, &request[..request_len], &mut mailbox) {
+        Some(len) => len,
+        None => return false,
+    };
+
+    // Device: unwrap the payload and answer it, as otpilot's SPI processor does.
+    let mut response = [0u8; BUF_LEN];
+    let response_len = {
+        let (header, content) = match from_payload(&mailbox[..mailbox_len]) {
+            Some(payload) => payload,
+            None => return false,
+        };
+        require!(header.content == payload::ContentType::Manticore);
+        let mut handler = Handler::new(&IDENTITY);
+        match handler.process_request(content, &mut response) {
+            Ok(len) => len,
+            Err(_) => return false,
+        }
+    };
+    let mailbox_len = match to_payload(
+        payload::ContentType::Manticore, &response[..response_len], &mut mailbox) {
+        Some(len) => len,
+        None => return false,
+    };
+
+    // Host: decode the response.
+    let (header, mut content) = match from_payload(&mailbox[..mailbox_len]) {
+        Some(payload) => payload,
+        None => return false,
+    };
+    require!(header.content == payload::ContentType::Manticore);
+    let mut arena_buf = [0u8; 64];
+    let arena = BumpArena::new(&mut arena_buf[..]);
+    let response_header = match ManticoreHeader::from_wire(&mut content, &arena) {
+        Ok(header) => header,
+        Err(_) => return false,
+    };
+    require!(response_header.command == CommandType::FirmwareVersion);
+    require!(!response_header.is_request);
+    require!(response_len == MANTICORE_HEADER_LEN + IDENTITY.version.len());
+    require!(content == &IDENTITY.version[..]);
+    true
+}
@@ -72,7 +72,7 @@ pub enum TestType { UnitTest }
 // The test harness's equivalent of main() (it is called by a compiler-generated
 // shim).
 pub fn test_main_static(tests: &[&TestDescAndFn]) {
-    use libtock::println;
+    use crate::framing::{Line, Output};
 
     let maybe_drivers = libtock::retrieve_drivers();
     if maybe_drivers.is_err() {
@@ -80,22 +80,24 @@ pub fn test_main_static(tests: &[&TestDescAndFn]) {
     }
     maybe_drivers.ok().unwrap().console.create_console();
 
-    println!("Starting tests.");
+    let mut output = Output::negotiate();
+    output.line(Line::Starting);
     let mut overall_success = true;
     for test_case in tests {
         // Skip ignored test cases.
         let desc = &test_case.desc;
         let name = desc.name.0;
         if desc.ignore {
-            println!("Skipping ignored test {}", name);
+            output.line(Line::Skipping(name));
             continue;
         }
 
         // Run the test.
-        println!("Running test {}", name);
+        output.line(Line::Running(name));
         let succeeded = test_case.testfn.0();
-        println!("Finished test {}. Result: {}", name, if succeeded { "succeeded" } else { "failed" });
+        output.line(Line::Finished(name, succeeded));
         overall_success &= succeeded;
     }
-    println!("TEST_FINISHED: {}", if overall_success { "SUCCESS" } else { "FAIL" });
+    output.line(Line::Done(overall_success));
+    output.finish();
 }
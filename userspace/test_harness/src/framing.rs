@@ -0,0 +1,257 @@
+// Copyright 2021 lowRISC contributors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! CRC-protected test result lines (see `consoleutils::framing`).
+//!
+//! At startup the harness offers framing and waits briefly for the runner to
+//! accept. If it does, the result lines are framed, and after the last one
+//! the harness stays around to resend any frame the runner could not verify.
+//! Otherwise the lines are printed as plain text, as before.
+
+use core::fmt;
+use core::fmt::Write;
+use consoleutils::framing;
+use libtock::println;
+use libtock::syscalls;
+use libtock::syscalls::raw::yieldk;
+
+/// How long to wait for the runner to accept framing.
+const ACCEPT_TIMEOUT_MS: usize = 500;
+
+/// How long to serve retransmit requests after the last line.
+const RETRANSMIT_TIMEOUT_MS: usize = 5000;
+
+/// Interval at which the last line is resent while the runner is quiet.
+const RESEND_INTERVAL_MS: usize = 500;
+
+/// The number of lines that can be retransmitted.
+const MAX_LINES: usize = 256;
+
+/// The longest payload; longer test names are truncated.
+const MAX_PAYLOAD: usize = 96;
+
+mod console {
+    pub const DRIVER_NUMBER: usize = 1;
+    pub const COMMAND_READ: usize = 2;
+    pub const COMMAND_ABORT_READ: usize = 3;
+    pub const SUBSCRIBE_READ_DONE: usize = 2;
+    pub const ALLOW_READ_BUFFER: usize = 2;
+}
+
+mod alarm {
+    pub const DRIVER_NUMBER: usize = 0;
+    pub const COMMAND_GET_CLOCK_FREQUENCY: usize = 1;
+    pub const COMMAND_STOP_ALARM: usize = 3;
+    pub const COMMAND_SET_RELATIVE_ALARM: usize = 5;
+    pub const SUBSCRIBE_ALARM_EXPIRED: usize = 0;
+}
+
+/// A result line.
+#[derive(Clone, Copy)]
+pub enum Line {
+    Starting,
+    Skipping(&'static str),
+    Running(&'static str),
+    Finished(&'static str, bool),
+    Done(bool),
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let result = |succeeded| if succeeded { "succeeded" } else { "failed" };
+        match *self {
+            Line::Starting => write!(f, "Starting tests."),
+            Line::Skipping(name) => write!(f, "Skipping ignored test {}", name),
+            Line::Running(name) => write!(f, "Running test {}", name),
+            Line::Finished(name, succeeded) =>
+                write!(f, "Finished test {}. Result: {}", name, result(succeeded)),
+            Line::Done(succeeded) =>
+                write!(f, "TEST_FINISHED: {}", if succeeded { "SUCCESS" } else { "FAIL" }),
+        }
+    }
+}
+
+/// Formats into a fixed buffer, dropping what does not fit.
+struct Payload {
+    buffer: [u8; MAX_PAYLOAD],
+    len: usize,
+}
+
+impl Write for Payload {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = core::cmp::min(s.len(), MAX_PAYLOAD - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+// Set by the callbacks.
+static mut READ_DONE: bool = false;
+static mut ALARM_EXPIRED: bool = false;
+
+static mut READ_BUFFER: [u8; 1] = [0];
+static mut HISTORY: [Line; MAX_LINES] = [Line::Starting; MAX_LINES];
+
+extern "C" fn read_done(_: usize, _: usize, _: usize, _: usize) {
+    unsafe { READ_DONE = true; }
+}
+
+extern "C" fn alarm_expired(_: usize, _: usize, _: usize, _: usize) {
+    unsafe { ALARM_EXPIRED = true; }
+}
+
+/// Prints the result lines, framed if the runner accepted framing.
+pub struct Output {
+    framed: bool,
+    clock_frequency: usize,
+    sent: usize,
+}
+
+impl Output {
+    /// Offers framing to the runner and waits for it to accept.
+    pub fn negotiate() -> Output {
+        let mut output = Output { framed: false, clock_frequency: 0, sent: 0 };
+        let setup = syscalls::subscribe_fn(console::DRIVER_NUMBER, console::SUBSCRIBE_READ_DONE,
+                                           read_done, 0)
+            .and_then(|_| syscalls::subscribe_fn(alarm::DRIVER_NUMBER,
+                                                 alarm::SUBSCRIBE_ALARM_EXPIRED,
+                                                 alarm_expired, 0))
+            .and_then(|_| syscalls::command(alarm::DRIVER_NUMBER,
+                                            alarm::COMMAND_GET_CLOCK_FREQUENCY, 0, 0));
+        output.clock_frequency = match setup {
+            Ok(frequency) => frequency,
+            Err(_) => return output,
+        };
+
+        println!("{}", core::str::from_utf8(framing::FRAMING_OFFER).unwrap_or(""));
+        let deadline = output.start_alarm(ACCEPT_TIMEOUT_MS);
+        while let Some(byte) = output.read_byte(deadline) {
+            if byte == framing::FRAMING_ACCEPT {
+                output.framed = true;
+                break;
+            }
+        }
+        output.stop_alarm(deadline);
+        output
+    }
+
+    /// Prints `line`.
+    pub fn line(&mut self, line: Line) {
+        if !self.framed {
+            println!("{}", line);
+            return;
+        }
+        if self.sent < MAX_LINES {
+            unsafe { HISTORY[self.sent] = line; }
+        }
+        self.send(self.sent as u16, line);
+        self.sent += 1;
+    }
+
+    /// Serves retransmit requests after the last line, until the runner is
+    /// done or stops responding.
+    pub fn finish(&mut self) {
+        if !self.framed || self.sent == 0 {
+            return;
+        }
+        let last = (self.sent - 1) as u16;
+        let mut request = [0u8; framing::RETRANSMIT_LEN];
+        let mut request_len = 0;
+        let mut waited_ms = 0;
+        while waited_ms < RETRANSMIT_TIMEOUT_MS {
+            let deadline = self.start_alarm(RESEND_INTERVAL_MS);
+            while let Some(byte) = self.read_byte(deadline) {
+                if byte == framing::FRAMING_DONE {
+                    self.stop_alarm(deadline);
+                    return;
+                }
+                if byte == framing::RETRANSMIT {
+                    request_len = 0;
+                }
+                if request_len < request.len() {
+                    request[request_len] = byte;
+                    request_len += 1;
+                }
+                if byte != b'\n' {
+                    continue;
+                }
+                if let Some(seq) = framing::parse_retransmit(&request[..request_len]) {
+                    if (seq as usize) < core::cmp::min(self.sent, MAX_LINES) {
+                        self.send(seq, unsafe { HISTORY[seq as usize] });
+                    }
+                    // A request shows the runner is still there.
+                    waited_ms = 0;
+                }
+                request_len = 0;
+            }
+            self.stop_alarm(deadline);
+            waited_ms += RESEND_INTERVAL_MS;
+            self.send(last, unsafe { HISTORY[last as usize] });
+        }
+    }
+
+    fn send(&self, seq: u16, line: Line) {
+        let mut payload = Payload { buffer: [0; MAX_PAYLOAD], len: 0 };
+        let _ = write!(payload, "{}", line);
+        let mut encoded = [0u8; framing::max_line_len(MAX_PAYLOAD)];
+        if let Some(len) = framing::encode_line(seq, &payload.buffer[..payload.len], &mut encoded) {
+            // Payloads are escaped, so the frame is ASCII. println! adds the
+            // newline back.
+            println!("{}", core::str::from_utf8(&encoded[..len - 1]).unwrap_or(""));
+        }
+    }
+
+    /// Starts an alarm `ms` from now and returns its ID.
+    fn start_alarm(&self, ms: usize) -> Option<usize> {
+        unsafe { ALARM_EXPIRED = false; }
+        let ticks = (self.clock_frequency as u64 * ms as u64 / 1000) as usize;
+        syscalls::command(alarm::DRIVER_NUMBER, alarm::COMMAND_SET_RELATIVE_ALARM, ticks, 0).ok()
+    }
+
+    fn stop_alarm(&self, alarm_id: Option<usize>) {
+        if let Some(alarm_id) = alarm_id {
+            if unsafe { !ALARM_EXPIRED } {
+                let _ = syscalls::command(alarm::DRIVER_NUMBER, alarm::COMMAND_STOP_ALARM,
+                                          alarm_id, 0);
+            }
+        }
+    }
+
+    /// Reads one byte from the console. Returns None once the alarm started
+    /// by `start_alarm` expires.
+    fn read_byte(&self, alarm_id: Option<usize>) -> Option<u8> {
+        alarm_id?;
+        unsafe {
+            READ_DONE = false;
+            // Keep the buffer shared until the read is over.
+            let _share = syscalls::allow(console::DRIVER_NUMBER, console::ALLOW_READ_BUFFER,
+                                         &mut READ_BUFFER).ok()?;
+            syscalls::command(console::DRIVER_NUMBER, console::COMMAND_READ, 1, 0).ok()?;
+            while !READ_DONE && !ALARM_EXPIRED {
+                yieldk();
+            }
+            if !READ_DONE {
+                let _ = syscalls::command(console::DRIVER_NUMBER, console::COMMAND_ABORT_READ,
+                                          0, 0);
+                // The aborted read still calls back.
+                while !READ_DONE { yieldk(); }
+                return None;
+            }
+            Some(READ_BUFFER[0])
+        }
+    }
+}
@@ -21,6 +21,7 @@
 
 mod assertions;
 mod compiler_required;
+mod framing;
 
 pub use self::assertions::*;
 pub use self::compiler_required::*;